@@ -59,6 +59,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         path: Some("/tmp/example_jail".into()),
         ip: Some("192.168.1.100".into()),
         bootstrap: None,
+        devfs_ruleset: None,
+        cleanup_path: None,
+        mount_permissions: Default::default(),
+        description: None,
     };
     let request = Request::post(Endpoint::Jails, create_req)?;
     let response = send_request(request).await?;
@@ -126,6 +130,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         path: Some("/jails/webserver".into()),
         ip: None,
         bootstrap: None,
+        devfs_ruleset: None,
+        cleanup_path: None,
+        mount_permissions: Default::default(),
+        description: None,
     };
     let request = Request::post(Endpoint::Jails, create_req)?;
     let response = send_request(request).await?;
@@ -154,6 +162,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         path: None,
         ip: None,
         bootstrap: None,
+        devfs_ruleset: None,
+        cleanup_path: None,
+        mount_permissions: Default::default(),
+        description: None,
     };
     let request = Request::post(Endpoint::Jails, create_req)?;
     let response = send_request(request).await?;
@@ -209,6 +221,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         path: None,
         ip: None,
         bootstrap: None,
+        devfs_ruleset: None,
+        cleanup_path: None,
+        mount_permissions: Default::default(),
+        description: None,
     };
     let request = Request::post(Endpoint::Jails, create_req)?;
     let response = send_request(request).await?;
@@ -3,9 +3,22 @@
 //! This module defines the REST-like JSON-over-Unix-socket protocol used for
 //! communicating with the Kawakaze jail manager backend.
 
-use crate::jail::{JailError, JailState};
+use crate::api_schema::{ApiDescription, OneOf, SchemaFn, schema_of};
+use crate::cmdtrace::CommandMetricsInfo;
+use crate::doctor::DoctorReport;
+use crate::error_codes;
+use crate::exec_sessions::{ExecKind, ExecSession, Termination};
+use crate::image_builder::ImageBuildProgress;
+use crate::jail::{JailError, JailState, MountPermissions, StopMode};
+use crate::logs::{LogEntry, LogSource};
+use crate::operation::OperationProgress;
+use crate::snapshot_gc::SnapshotGcReport;
+use crate::top::ProcessInfo;
+use crate::upgrade::UpgradeProgress;
+use crate::upload::{UploadPurpose, UploadStatus};
+use crate::timestamp::Timestamp;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 /// HTTP-like methods for API requests
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
@@ -35,6 +48,14 @@ pub enum Endpoint {
     BootstrapJail(String),
     /// Get bootstrap status: GET /jails/{name}/bootstrap/status
     BootstrapStatus(String),
+    /// Get the log of a jail's last bootstrap: GET /jails/{name}/bootstrap/log
+    BootstrapLog(String),
+    /// Apply freebsd-update to a stopped jail: POST /jails/{name}/upgrade
+    JailUpgrade(String),
+    /// Get jail upgrade status: GET /jails/{name}/upgrade/status
+    JailUpgradeStatus(String),
+    /// Update jail settings: POST /jails/{name}/update
+    JailUpdate(String),
 
     // Image endpoints
 
@@ -44,10 +65,26 @@ pub enum Endpoint {
     Image(String),
     /// Build image from Dockerfile: POST /images/build
     ImageBuild,
+    /// Get image build progress: GET /images/build/{id}
+    ImageBuildStatus(String),
     /// Delete an image: DELETE /images/{id}
     DeleteImage(String),
     /// Get image history: GET /images/{id}/history
     ImageHistory(String),
+    /// Containers created from an image: GET /images/{id}/containers
+    ImageContainers(String),
+    /// Get the log of an image's build: GET /images/{id}/build-log
+    ImageBuildLog(String),
+    /// Stream a build log until the build ends: GET /images/{id}/build-log/follow
+    ImageBuildLogFollow(String),
+    /// Update image settings: POST /images/{id}/update
+    UpdateImage(String),
+    /// Remove dangling images: POST /images/prune
+    PruneImages,
+    /// Apply freebsd-update to an image: POST /images/{id}/upgrade
+    ImageUpgrade(String),
+    /// Get image upgrade status: GET /images/{id}/upgrade/status
+    ImageUpgradeStatus(String),
 
     // Container endpoints
 
@@ -57,6 +94,8 @@ pub enum Endpoint {
     Container(String),
     /// Create container: POST /containers/create
     ContainerCreate,
+    /// Create and start containers in `depends_on` order: POST /containers/batch
+    ContainerBatchCreate,
     /// Start container: POST /containers/{id}/start
     StartContainer(String),
     /// Stop container: POST /containers/{id}/stop
@@ -65,8 +104,79 @@ pub enum Endpoint {
     RemoveContainer(String),
     /// Get container logs: GET /containers/{id}/logs
     ContainerLogs(String),
+    /// Stream container logs as they are written: GET /containers/{id}/logs/follow
+    ContainerLogsFollow(String),
     /// Execute command in container: POST /containers/{id}/exec
     ContainerExec(String),
+    /// List a running container's processes: GET /containers/{id}/top
+    ContainerTop(String),
+    /// List the commands running in a container through exec: GET /containers/{id}/exec-sessions
+    ContainerExecSessions(String),
+    /// Update container settings: POST /containers/{id}/update
+    UpdateContainer(String),
+    /// Rename a container: POST /containers/{id}/rename
+    RenameContainer(String),
+    /// Add an `ip4.addr` alias to a container's jail: POST /containers/{id}/ips
+    AddContainerIp(String),
+    /// Remove an `ip4.addr` alias: DELETE /containers/{id}/ips
+    RemoveContainerIp(String),
+
+    // System endpoints
+
+    /// Get the running configuration: GET /system/config
+    SystemConfig,
+    /// List datasets no record points at: GET /system/orphans
+    SystemOrphans,
+    /// Re-register an orphaned dataset: POST /system/orphans/adopt
+    OrphanAdopt,
+    /// Destroy an orphaned dataset: POST /system/orphans/destroy
+    OrphanDestroy,
+    /// Destroy old snapshots of a dataset: POST /system/snapshots/prune
+    PruneSnapshots,
+    /// Destroy snapshots nothing references: POST /system/snapshots/gc
+    SystemSnapshotGc,
+    /// Change the daemon's log filter: POST /system/log-level
+    SetLogLevel,
+    /// Timing of external commands: GET /system/commands
+    CommandMetrics,
+    /// Host and daemon state: GET /system/info
+    SystemInfo,
+    /// Check host prerequisites: GET /system/doctor
+    SystemDoctor,
+    /// Containers started at boot and the last boot's results: GET /system/boot
+    SystemBootList,
+    /// Queued and running builds and bootstraps: GET /system/tasks
+    SystemTasks,
+    /// Container or image names and IDs for shell completion: GET /system/names
+    SystemNames,
+    /// Cancel a queued build or bootstrap: POST /system/tasks/{id}/cancel
+    CancelTask(String),
+    /// Progress of an async container create or remove: GET /operations/{id}
+    Operation(String),
+
+    // Exec session endpoints
+
+    /// List exec sessions, or register the CLI's own: GET/POST /exec-sessions
+    ExecSessions,
+    /// End a session the CLI registered: DELETE /exec-sessions/{id}
+    EndExecSession(String),
+    /// Kill an exec session's processes: POST /exec-sessions/{id}/kill
+    ExecSessionKill(String),
+
+    // Upload endpoints
+
+    /// Announce a chunked upload: POST /uploads
+    UploadCreate,
+    /// Where an upload is: GET /uploads/{id}
+    UploadStatus(String),
+    /// Send an upload's next chunk: POST /uploads/{id}/chunk
+    UploadChunk(String),
+    /// Verify a complete upload: POST /uploads/{id}/commit
+    UploadCommit(String),
+    /// Abort an upload: DELETE /uploads/{id}
+    UploadAbort(String),
+    /// JSON Schema of every route: GET /system/api-schema
+    ApiSchema,
 }
 
 impl Endpoint {
@@ -79,25 +189,191 @@ impl Endpoint {
             Endpoint::StopJail(name) => format!("jails/{}/stop", name),
             Endpoint::BootstrapJail(name) => format!("jails/{}/bootstrap", name),
             Endpoint::BootstrapStatus(name) => format!("jails/{}/bootstrap/status", name),
+            Endpoint::BootstrapLog(name) => format!("jails/{}/bootstrap/log", name),
+            Endpoint::JailUpgrade(name) => format!("jails/{}/upgrade", name),
+            Endpoint::JailUpgradeStatus(name) => format!("jails/{}/upgrade/status", name),
+            Endpoint::JailUpdate(name) => format!("jails/{}/update", name),
 
             Endpoint::Images => "images".to_string(),
             Endpoint::Image(id) => format!("images/{}", id),
             Endpoint::ImageBuild => "images/build".to_string(),
+            Endpoint::ImageBuildStatus(id) => format!("images/build/{}", id),
             Endpoint::DeleteImage(id) => format!("images/{}", id),
             Endpoint::ImageHistory(id) => format!("images/{}/history", id),
+            Endpoint::ImageContainers(id) => format!("images/{}/containers", id),
+            Endpoint::ImageBuildLog(id) => format!("images/{}/build-log", id),
+            Endpoint::ImageBuildLogFollow(id) => format!("images/{}/build-log/follow", id),
+            Endpoint::UpdateImage(id) => format!("images/{}/update", id),
+            Endpoint::PruneImages => "images/prune".to_string(),
+            Endpoint::ImageUpgrade(id) => format!("images/{}/upgrade", id),
+            Endpoint::ImageUpgradeStatus(id) => format!("images/{}/upgrade/status", id),
 
             Endpoint::Containers => "containers".to_string(),
             Endpoint::Container(id) => format!("containers/{}", id),
             Endpoint::ContainerCreate => "containers/create".to_string(),
+            Endpoint::ContainerBatchCreate => "containers/batch".to_string(),
             Endpoint::StartContainer(id) => format!("containers/{}/start", id),
             Endpoint::StopContainer(id) => format!("containers/{}/stop", id),
             Endpoint::RemoveContainer(id) => format!("containers/{}", id),
             Endpoint::ContainerLogs(id) => format!("containers/{}/logs", id),
+            Endpoint::ContainerLogsFollow(id) => format!("containers/{}/logs/follow", id),
             Endpoint::ContainerExec(id) => format!("containers/{}/exec", id),
+            Endpoint::ContainerTop(id) => format!("containers/{}/top", id),
+            Endpoint::ContainerExecSessions(id) => format!("containers/{}/exec-sessions", id),
+            Endpoint::UpdateContainer(id) => format!("containers/{}/update", id),
+            Endpoint::RenameContainer(id) => format!("containers/{}/rename", id),
+            Endpoint::AddContainerIp(id) | Endpoint::RemoveContainerIp(id) => format!("containers/{}/ips", id),
+
+            Endpoint::SystemConfig => "system/config".to_string(),
+            Endpoint::SystemOrphans => "system/orphans".to_string(),
+            Endpoint::OrphanAdopt => "system/orphans/adopt".to_string(),
+            Endpoint::OrphanDestroy => "system/orphans/destroy".to_string(),
+            Endpoint::PruneSnapshots => "system/snapshots/prune".to_string(),
+            Endpoint::SystemSnapshotGc => "system/snapshots/gc".to_string(),
+            Endpoint::SetLogLevel => "system/log-level".to_string(),
+            Endpoint::CommandMetrics => "system/commands".to_string(),
+            Endpoint::SystemInfo => "system/info".to_string(),
+            Endpoint::SystemDoctor => "system/doctor".to_string(),
+            Endpoint::SystemBootList => "system/boot".to_string(),
+            Endpoint::SystemTasks => "system/tasks".to_string(),
+            Endpoint::SystemNames => "system/names".to_string(),
+            Endpoint::CancelTask(id) => format!("system/tasks/{}/cancel", id),
+            Endpoint::Operation(id) => format!("operations/{}", id),
+
+            Endpoint::ExecSessions => "exec-sessions".to_string(),
+            Endpoint::EndExecSession(id) => format!("exec-sessions/{}", id),
+            Endpoint::ExecSessionKill(id) => format!("exec-sessions/{}/kill", id),
+
+            Endpoint::UploadCreate => "uploads".to_string(),
+            Endpoint::UploadStatus(id) | Endpoint::UploadAbort(id) => format!("uploads/{}", id),
+            Endpoint::UploadChunk(id) => format!("uploads/{}/chunk", id),
+            Endpoint::UploadCommit(id) => format!("uploads/{}/commit", id),
+            Endpoint::ApiSchema => "system/api-schema".to_string(),
         }
     }
+
+    /// Whether the endpoint answers with a stream of frames rather than
+    /// one response (see [`crate::stream`]). This is the one place such
+    /// endpoints are tagged.
+    pub fn is_streaming(&self) -> bool {
+        matches!(self, Endpoint::ContainerLogsFollow(_) | Endpoint::ImageBuildLogFollow(_))
+    }
+}
+
+/// One method on one endpoint, as `GET /system/api-schema` describes it
+#[derive(Debug)]
+pub struct Route {
+    pub method: Method,
+    /// The endpoint, its parameters left empty
+    pub endpoint: Endpoint,
+    /// Path with the parameters as `{placeholders}`
+    pub path: &'static str,
+    /// Schema of the request body, if the route reads one
+    pub request: Option<SchemaFn>,
+    /// Schema of the response data (of each frame's, when streaming)
+    pub response: SchemaFn,
+}
+
+const fn route(method: Method, endpoint: Endpoint, path: &'static str, request: Option<SchemaFn>, response: SchemaFn) -> Route {
+    Route { method, endpoint, path, request, response }
 }
 
+const P: String = String::new();
+
+/// Every route the handler serves. An [`Endpoint`] without a row here fails
+/// the tests in [`crate::api_schema`].
+pub static ROUTES: &[Route] = &[
+    route(Method::Get, Endpoint::Jails, "jails", Some(schema_of::<ListJailsRequest>), schema_of::<Vec<JailListItem>>),
+    route(Method::Post, Endpoint::Jails, "jails", Some(schema_of::<CreateJailRequest>), schema_of::<JailInfo>),
+    route(Method::Get, Endpoint::Jail(P), "jails/{name}", None, schema_of::<JailInfo>),
+    route(Method::Delete, Endpoint::Jail(P), "jails/{name}", None, schema_of::<MessageResult>),
+    route(Method::Post, Endpoint::StartJail(P), "jails/{name}/start", None, schema_of::<JailInfo>),
+    route(Method::Post, Endpoint::StopJail(P), "jails/{name}/stop", Some(schema_of::<StopRequest>), schema_of::<JailInfo>),
+    route(Method::Post, Endpoint::BootstrapJail(P), "jails/{name}/bootstrap", Some(schema_of::<BootstrapRequest>), schema_of::<()>),
+    route(Method::Get, Endpoint::BootstrapStatus(P), "jails/{name}/bootstrap/status", None, schema_of::<BootstrapProgress>),
+    route(Method::Get, Endpoint::BootstrapLog(P), "jails/{name}/bootstrap/log", Some(schema_of::<LogsRequest>), schema_of::<Vec<LogEntry>>),
+    route(Method::Post, Endpoint::JailUpgrade(P), "jails/{name}/upgrade", Some(schema_of::<UpgradeRequest>), schema_of::<UpgradeStarted>),
+    route(Method::Get, Endpoint::JailUpgradeStatus(P), "jails/{name}/upgrade/status", None, schema_of::<UpgradeProgress>),
+    route(Method::Post, Endpoint::JailUpdate(P), "jails/{name}/update", Some(schema_of::<JailUpdateRequest>), schema_of::<JailInfo>),
+
+    route(Method::Get, Endpoint::Images, "images", None, schema_of::<Vec<ImageListItem>>),
+    route(Method::Get, Endpoint::Image(P), "images/{id}", None, schema_of::<ImageInfo>),
+    route(Method::Post, Endpoint::ImageBuild, "images/build", Some(schema_of::<BuildImageRequest>), schema_of::<BuildStarted>),
+    route(Method::Get, Endpoint::ImageBuildStatus(P), "images/build/{id}", None, schema_of::<ImageBuildProgress>),
+    route(Method::Delete, Endpoint::DeleteImage(P), "images/{id}", Some(schema_of::<RemoveRequest>), schema_of::<MessageResult>),
+    route(Method::Get, Endpoint::ImageHistory(P), "images/{id}/history", None, schema_of::<Vec<ImageHistoryItem>>),
+    route(Method::Get, Endpoint::ImageContainers(P), "images/{id}/containers", None, schema_of::<Vec<ContainerListItem>>),
+    route(Method::Get, Endpoint::ImageBuildLog(P), "images/{id}/build-log", Some(schema_of::<LogsRequest>), schema_of::<Vec<LogEntry>>),
+    route(Method::Get, Endpoint::ImageBuildLogFollow(P), "images/{id}/build-log/follow", Some(schema_of::<LogsRequest>), schema_of::<LogEntry>),
+    route(Method::Post, Endpoint::UpdateImage(P), "images/{id}/update", Some(schema_of::<UpdateRequest>), schema_of::<ImageInfo>),
+    route(Method::Post, Endpoint::PruneImages, "images/prune", Some(schema_of::<PruneImagesRequest>), schema_of::<PruneImagesResult>),
+    route(Method::Post, Endpoint::ImageUpgrade(P), "images/{id}/upgrade", Some(schema_of::<UpgradeRequest>), schema_of::<UpgradeStarted>),
+    route(Method::Get, Endpoint::ImageUpgradeStatus(P), "images/{id}/upgrade/status", None, schema_of::<UpgradeProgress>),
+
+    route(
+        Method::Get,
+        Endpoint::Containers,
+        "containers",
+        Some(schema_of::<ListContainersRequest>),
+        schema_of::<OneOf<Vec<ContainerListItem>, ContainerListWithSummary>>,
+    ),
+    route(Method::Get, Endpoint::Container(P), "containers/{id}", None, schema_of::<ContainerInfo>),
+    route(
+        Method::Post,
+        Endpoint::ContainerCreate,
+        "containers/create",
+        Some(schema_of::<CreateContainerRequest>),
+        schema_of::<OneOf<CreatedContainer, OperationStarted>>,
+    ),
+    route(Method::Post, Endpoint::ContainerBatchCreate, "containers/batch", Some(schema_of::<BatchCreateRequest>), schema_of::<BatchCreated>),
+    route(Method::Post, Endpoint::StartContainer(P), "containers/{id}/start", None, schema_of::<ContainerInfo>),
+    route(Method::Post, Endpoint::StopContainer(P), "containers/{id}/stop", Some(schema_of::<StopRequest>), schema_of::<ContainerInfo>),
+    route(
+        Method::Delete,
+        Endpoint::RemoveContainer(P),
+        "containers/{id}",
+        Some(schema_of::<RemoveRequest>),
+        schema_of::<OneOf<MessageResult, OperationStarted>>,
+    ),
+    route(Method::Get, Endpoint::ContainerLogs(P), "containers/{id}/logs", Some(schema_of::<LogsRequest>), schema_of::<Vec<LogEntry>>),
+    route(Method::Get, Endpoint::ContainerLogsFollow(P), "containers/{id}/logs/follow", Some(schema_of::<LogsRequest>), schema_of::<LogEntry>),
+    route(Method::Post, Endpoint::ContainerExec(P), "containers/{id}/exec", Some(schema_of::<ExecRequest>), schema_of::<ExecResult>),
+    route(Method::Get, Endpoint::ContainerTop(P), "containers/{id}/top", None, schema_of::<Vec<ProcessInfo>>),
+    route(Method::Get, Endpoint::ContainerExecSessions(P), "containers/{id}/exec-sessions", None, schema_of::<Vec<ExecSession>>),
+    route(Method::Post, Endpoint::UpdateContainer(P), "containers/{id}/update", Some(schema_of::<UpdateRequest>), schema_of::<ContainerInfo>),
+    route(Method::Post, Endpoint::RenameContainer(P), "containers/{id}/rename", Some(schema_of::<RenameRequest>), schema_of::<ContainerInfo>),
+    route(Method::Post, Endpoint::AddContainerIp(P), "containers/{id}/ips", Some(schema_of::<ContainerIpRequest>), schema_of::<ContainerInfo>),
+    route(Method::Delete, Endpoint::RemoveContainerIp(P), "containers/{id}/ips", Some(schema_of::<ContainerIpRequest>), schema_of::<ContainerInfo>),
+
+    route(Method::Get, Endpoint::SystemConfig, "system/config", None, schema_of::<SystemConfigInfo>),
+    route(Method::Get, Endpoint::SystemOrphans, "system/orphans", None, schema_of::<Vec<OrphanInfo>>),
+    route(Method::Post, Endpoint::OrphanAdopt, "system/orphans/adopt", Some(schema_of::<AdoptOrphanRequest>), schema_of::<AdoptedOrphan>),
+    route(Method::Post, Endpoint::OrphanDestroy, "system/orphans/destroy", Some(schema_of::<DestroyOrphanRequest>), schema_of::<MessageResult>),
+    route(Method::Post, Endpoint::PruneSnapshots, "system/snapshots/prune", Some(schema_of::<PruneSnapshotsRequest>), schema_of::<PruneSnapshotsResult>),
+    route(Method::Post, Endpoint::SystemSnapshotGc, "system/snapshots/gc", Some(schema_of::<SnapshotGcRequest>), schema_of::<SnapshotGcReport>),
+    route(Method::Post, Endpoint::SetLogLevel, "system/log-level", Some(schema_of::<LogLevelRequest>), schema_of::<LogLevelResult>),
+    route(Method::Get, Endpoint::CommandMetrics, "system/commands", None, schema_of::<CommandMetricsInfo>),
+    route(Method::Get, Endpoint::SystemInfo, "system/info", None, schema_of::<SystemInfo>),
+    route(Method::Get, Endpoint::SystemDoctor, "system/doctor", None, schema_of::<DoctorReport>),
+    route(Method::Get, Endpoint::SystemBootList, "system/boot", None, schema_of::<BootList>),
+    route(Method::Get, Endpoint::SystemTasks, "system/tasks", None, schema_of::<Vec<TaskInfo>>),
+    route(Method::Get, Endpoint::SystemNames, "system/names", Some(schema_of::<NamesRequest>), schema_of::<Vec<String>>),
+    route(Method::Post, Endpoint::CancelTask(P), "system/tasks/{id}/cancel", None, schema_of::<MessageResult>),
+    route(Method::Get, Endpoint::Operation(P), "operations/{id}", None, schema_of::<OperationProgress>),
+
+    route(Method::Get, Endpoint::ExecSessions, "exec-sessions", None, schema_of::<Vec<ExecSession>>),
+    route(Method::Post, Endpoint::ExecSessions, "exec-sessions", Some(schema_of::<ExecSessionRequest>), schema_of::<ExecSession>),
+    route(Method::Delete, Endpoint::EndExecSession(P), "exec-sessions/{id}", None, schema_of::<MessageResult>),
+    route(Method::Post, Endpoint::ExecSessionKill(P), "exec-sessions/{id}/kill", None, schema_of::<ExecSessionKilled>),
+
+    route(Method::Post, Endpoint::UploadCreate, "uploads", Some(schema_of::<UploadRequest>), schema_of::<UploadStatus>),
+    route(Method::Get, Endpoint::UploadStatus(P), "uploads/{id}", None, schema_of::<UploadStatus>),
+    route(Method::Post, Endpoint::UploadChunk(P), "uploads/{id}/chunk", Some(schema_of::<UploadChunk>), schema_of::<UploadStatus>),
+    route(Method::Post, Endpoint::UploadCommit(P), "uploads/{id}/commit", None, schema_of::<UploadStatus>),
+    route(Method::Delete, Endpoint::UploadAbort(P), "uploads/{id}", None, schema_of::<MessageResult>),
+    route(Method::Get, Endpoint::ApiSchema, "system/api-schema", None, schema_of::<ApiDescription>),
+];
+
 /// HTTP-like status codes for API responses
 pub type StatusCode = u16;
 
@@ -105,10 +381,18 @@ pub type StatusCode = u16;
 pub mod status {
     pub const OK: u16 = 200;
     pub const CREATED: u16 = 201;
+    /// Work started in the background (see `error_codes::OPERATION_STARTED`)
+    pub const ACCEPTED: u16 = 202;
     pub const BAD_REQUEST: u16 = 400;
     pub const NOT_FOUND: u16 = 404;
+    pub const FORBIDDEN: u16 = 403;
+    /// An HTTP method the API has no counterpart for (see `http_gateway`)
+    pub const METHOD_NOT_ALLOWED: u16 = 405;
     pub const CONFLICT: u16 = 409;
+    /// The client closed the connection before the response
+    pub const CLIENT_CLOSED_REQUEST: u16 = 499;
     pub const INTERNAL_SERVER_ERROR: u16 = 500;
+    pub const SERVICE_UNAVAILABLE: u16 = 503;
 }
 
 /// API request with REST-like method and endpoint
@@ -123,6 +407,11 @@ pub struct Request {
     /// Optional request body (as JSON value)
     #[serde(default)]
     pub body: serde_json::Value,
+
+    /// uid of the client, from the socket's peer credentials; set by the
+    /// server for each connection, never sent
+    #[serde(skip)]
+    pub peer_uid: Option<u32>,
 }
 
 impl Request {
@@ -132,6 +421,7 @@ impl Request {
             method,
             endpoint: endpoint.path(),
             body,
+            peer_uid: None,
         }
     }
 
@@ -140,6 +430,15 @@ impl Request {
         Self::new(Method::Get, endpoint, serde_json::Value::Null)
     }
 
+    /// Create a GET request with a body of options
+    pub fn get_with(endpoint: Endpoint, body: impl Serialize) -> Result<Self, serde_json::Error> {
+        Ok(Self::new(
+            Method::Get,
+            endpoint,
+            serde_json::to_value(&body)?,
+        ))
+    }
+
     /// Create a POST request with a body
     pub fn post(endpoint: Endpoint, body: impl Serialize) -> Result<Self, serde_json::Error> {
         Ok(Self::new(
@@ -154,6 +453,15 @@ impl Request {
         Self::new(Method::Delete, endpoint, serde_json::Value::Null)
     }
 
+    /// Create a DELETE request with a body
+    pub fn delete_with(endpoint: Endpoint, body: impl Serialize) -> Result<Self, serde_json::Error> {
+        Ok(Self::new(
+            Method::Delete,
+            endpoint,
+            serde_json::to_value(&body)?,
+        ))
+    }
+
     /// Parse the endpoint string into an Endpoint enum
     pub fn parse_endpoint(&self) -> Result<Endpoint, ApiError> {
         // Parse endpoint based on method and path
@@ -168,25 +476,73 @@ impl Request {
             ["jails", name, "stop"] => Ok(Endpoint::StopJail(name.to_string())),
             ["jails", name, "bootstrap"] => Ok(Endpoint::BootstrapJail(name.to_string())),
             ["jails", name, "bootstrap", "status"] => Ok(Endpoint::BootstrapStatus(name.to_string())),
+            ["jails", name, "bootstrap", "log"] => Ok(Endpoint::BootstrapLog(name.to_string())),
+            ["jails", name, "upgrade"] => Ok(Endpoint::JailUpgrade(name.to_string())),
+            ["jails", name, "upgrade", "status"] => Ok(Endpoint::JailUpgradeStatus(name.to_string())),
+            ["jails", name, "update"] => Ok(Endpoint::JailUpdate(name.to_string())),
 
             ["images"] => Ok(Endpoint::Images),
             ["images", "build"] => Ok(Endpoint::ImageBuild),
-            ["images", id] if self.method == Method::Get || self.method == Method::Delete => {
-                Ok(Endpoint::Image(id.to_string()))
-            }
+            ["images", "build", id] => Ok(Endpoint::ImageBuildStatus(id.to_string())),
+            ["images", "prune"] if self.method == Method::Post => Ok(Endpoint::PruneImages),
+            ["images", id] if self.method == Method::Get => Ok(Endpoint::Image(id.to_string())),
+            ["images", id] if self.method == Method::Delete => Ok(Endpoint::DeleteImage(id.to_string())),
             ["images", id, "history"] => Ok(Endpoint::ImageHistory(id.to_string())),
+            ["images", id, "containers"] => Ok(Endpoint::ImageContainers(id.to_string())),
+            ["images", id, "build-log"] => Ok(Endpoint::ImageBuildLog(id.to_string())),
+            ["images", id, "build-log", "follow"] => Ok(Endpoint::ImageBuildLogFollow(id.to_string())),
+            ["images", id, "update"] => Ok(Endpoint::UpdateImage(id.to_string())),
+            ["images", id, "upgrade"] => Ok(Endpoint::ImageUpgrade(id.to_string())),
+            ["images", id, "upgrade", "status"] => Ok(Endpoint::ImageUpgradeStatus(id.to_string())),
 
             ["containers"] => Ok(Endpoint::Containers),
             ["containers", "create"] => Ok(Endpoint::ContainerCreate),
-            ["containers", id] if self.method == Method::Get || self.method == Method::Delete => {
-                Ok(Endpoint::Container(id.to_string()))
+            ["containers", "batch"] if self.method == Method::Post => Ok(Endpoint::ContainerBatchCreate),
+            ["containers", id] if self.method == Method::Get => Ok(Endpoint::Container(id.to_string())),
+            ["containers", id] if self.method == Method::Delete => {
+                Ok(Endpoint::RemoveContainer(id.to_string()))
             }
             ["containers", id, "start"] => Ok(Endpoint::StartContainer(id.to_string())),
             ["containers", id, "stop"] => Ok(Endpoint::StopContainer(id.to_string())),
             ["containers", id, "logs"] => Ok(Endpoint::ContainerLogs(id.to_string())),
+            ["containers", id, "logs", "follow"] => Ok(Endpoint::ContainerLogsFollow(id.to_string())),
             ["containers", id, "exec"] => Ok(Endpoint::ContainerExec(id.to_string())),
-
-            _ => Err(ApiError::BadRequest(format!("Unknown endpoint: {}", self.endpoint))),
+            ["containers", id, "top"] => Ok(Endpoint::ContainerTop(id.to_string())),
+            ["containers", id, "exec-sessions"] if self.method == Method::Get => Ok(Endpoint::ContainerExecSessions(id.to_string())),
+            ["containers", id, "update"] => Ok(Endpoint::UpdateContainer(id.to_string())),
+            ["containers", id, "rename"] if self.method == Method::Post => Ok(Endpoint::RenameContainer(id.to_string())),
+            ["containers", id, "ips"] if self.method == Method::Post => Ok(Endpoint::AddContainerIp(id.to_string())),
+            ["containers", id, "ips"] if self.method == Method::Delete => Ok(Endpoint::RemoveContainerIp(id.to_string())),
+
+            ["system", "config"] => Ok(Endpoint::SystemConfig),
+            ["system", "orphans"] => Ok(Endpoint::SystemOrphans),
+            ["system", "commands"] => Ok(Endpoint::CommandMetrics),
+            ["system", "info"] => Ok(Endpoint::SystemInfo),
+            ["system", "doctor"] => Ok(Endpoint::SystemDoctor),
+            ["system", "boot"] => Ok(Endpoint::SystemBootList),
+            ["system", "tasks"] => Ok(Endpoint::SystemTasks),
+            ["system", "names"] => Ok(Endpoint::SystemNames),
+            ["system", "api-schema"] => Ok(Endpoint::ApiSchema),
+            ["system", "tasks", id, "cancel"] => Ok(Endpoint::CancelTask(id.to_string())),
+            ["system", "orphans", "adopt"] => Ok(Endpoint::OrphanAdopt),
+            ["system", "orphans", "destroy"] => Ok(Endpoint::OrphanDestroy),
+            ["system", "snapshots", "prune"] if self.method == Method::Post => Ok(Endpoint::PruneSnapshots),
+            ["system", "snapshots", "gc"] if self.method == Method::Post => Ok(Endpoint::SystemSnapshotGc),
+            ["system", "log-level"] if self.method == Method::Post => Ok(Endpoint::SetLogLevel),
+
+            ["operations", id] => Ok(Endpoint::Operation(id.to_string())),
+
+            ["exec-sessions"] => Ok(Endpoint::ExecSessions),
+            ["exec-sessions", id] if self.method == Method::Delete => Ok(Endpoint::EndExecSession(id.to_string())),
+            ["exec-sessions", id, "kill"] if self.method == Method::Post => Ok(Endpoint::ExecSessionKill(id.to_string())),
+
+            ["uploads"] if self.method == Method::Post => Ok(Endpoint::UploadCreate),
+            ["uploads", id] if self.method == Method::Get => Ok(Endpoint::UploadStatus(id.to_string())),
+            ["uploads", id] if self.method == Method::Delete => Ok(Endpoint::UploadAbort(id.to_string())),
+            ["uploads", id, "chunk"] if self.method == Method::Post => Ok(Endpoint::UploadChunk(id.to_string())),
+            ["uploads", id, "commit"] if self.method == Method::Post => Ok(Endpoint::UploadCommit(id.to_string())),
+
+            _ => Err(ApiError::new(error_codes::UNKNOWN_ENDPOINT, format!("Unknown endpoint: {}", self.endpoint))),
         }
     }
 }
@@ -204,6 +560,15 @@ pub struct Response {
     /// Error information (on failure)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error: Option<ApiError>,
+
+    /// Set on every frame of a streaming response
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub stream: bool,
+
+    /// Set on the terminal frame of a stream, whose status tells how the
+    /// stream ended
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub end: bool,
 }
 
 impl Response {
@@ -213,9 +578,29 @@ impl Response {
             status,
             data: Some(serde_json::to_value(data)?),
             error: None,
+            stream: false,
+            end: false,
         })
     }
 
+    /// Create a data frame of a streaming response
+    pub fn stream_frame(data: impl Serialize) -> Result<Self, serde_json::Error> {
+        Ok(Self { stream: true, ..Self::success(data)? })
+    }
+
+    /// Create the terminal frame of a stream: 200 when it ended normally,
+    /// otherwise the error it ended with
+    pub fn stream_end(outcome: Result<(), Response>) -> Self {
+        let response = outcome.err().unwrap_or(Self {
+            status: status::OK,
+            data: None,
+            error: None,
+            stream: false,
+            end: false,
+        });
+        Self { stream: true, end: true, ..response }
+    }
+
     /// Create a 200 OK response with data
     pub fn success(data: impl Serialize) -> Result<Self, serde_json::Error> {
         Self::ok(status::OK, data)
@@ -232,6 +617,8 @@ impl Response {
             status,
             data: None,
             error: Some(error),
+            stream: false,
+            end: false,
         }
     }
 
@@ -240,6 +627,11 @@ impl Response {
         Self::error(status::BAD_REQUEST, ApiError::BadRequest(message.into()))
     }
 
+    /// Create a 403 Forbidden error response
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        Self::error(status::FORBIDDEN, ApiError::Forbidden(message.into()))
+    }
+
     /// Create a 404 Not Found error response
     pub fn not_found(resource: impl Into<String>) -> Self {
         Self::error(
@@ -261,6 +653,32 @@ impl Response {
         )
     }
 
+    /// Create an error response for a registered code, with the status it
+    /// is registered with (see `error_codes`)
+    pub fn coded(code: &'static str, message: impl Into<String>) -> Self {
+        Self::from(ApiError::new(code, message))
+    }
+
+    /// Create a 400 response for a body that doesn't match the endpoint
+    pub fn invalid_body(err: impl std::fmt::Display) -> Self {
+        Self::coded(error_codes::INVALID_BODY, format!("Invalid request body: {}", err))
+    }
+
+    /// Create a 404 response for a jail name
+    pub fn jail_not_found(name: &str) -> Self {
+        Self::from(ApiError::JailNotFound(name.to_string()))
+    }
+
+    /// Create a 404 response for an image ID, name or prefix
+    pub fn image_not_found(id_or_name: &str) -> Self {
+        Self::from(ApiError::ImageNotFound(id_or_name.to_string()))
+    }
+
+    /// Create a 404 response for a container ID, name or prefix
+    pub fn container_not_found(id_or_name: &str) -> Self {
+        Self::from(ApiError::ContainerNotFound(id_or_name.to_string()))
+    }
+
     /// Check if the response indicates success
     pub fn is_success(&self) -> bool {
         self.status >= 200 && self.status < 300
@@ -270,7 +688,7 @@ impl Response {
 /// API error information
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ApiError {
-    /// Error code (e.g., "JAIL_NOT_FOUND")
+    /// Error code, one of `error_codes::CODES` (e.g., "JAIL_NOT_FOUND")
     pub code: String,
 
     /// Human-readable error message
@@ -289,37 +707,112 @@ impl ApiError {
     /// Bad request error (400)
     #[allow(non_snake_case)]
     pub fn BadRequest(message: String) -> Self {
-        Self::new("BAD_REQUEST", message)
+        Self::new(error_codes::BAD_REQUEST, message)
+    }
+
+    /// Refused by the daemon's policy (403)
+    #[allow(non_snake_case)]
+    pub fn Forbidden(message: String) -> Self {
+        Self::new(error_codes::FORBIDDEN, message)
     }
 
     /// Not found error (404)
     #[allow(non_snake_case)]
     pub fn NotFound(resource: String) -> Self {
-        Self::new("NOT_FOUND", format!("Resource not found: {}", resource))
+        Self::new(error_codes::NOT_FOUND, format!("Resource not found: {}", resource))
+    }
+
+    /// HTTP method other than GET, POST or DELETE (405)
+    #[allow(non_snake_case)]
+    pub fn MethodNotAllowed(method: String) -> Self {
+        Self::new(error_codes::METHOD_NOT_ALLOWED, format!("Method not allowed: {}", method))
     }
 
     /// Conflict error (409)
     #[allow(non_snake_case)]
     pub fn Conflict(message: String) -> Self {
-        Self::new("CONFLICT", message)
+        Self::new(error_codes::CONFLICT, message)
     }
 
     /// Internal server error (500)
     #[allow(non_snake_case)]
     pub fn Internal(message: String) -> Self {
-        Self::new("INTERNAL_ERROR", message)
+        Self::new(error_codes::INTERNAL_ERROR, message)
+    }
+
+    /// Request on a connection whose stream is still active (409)
+    #[allow(non_snake_case)]
+    pub fn ConnectionBusyStreaming() -> Self {
+        Self::new(
+            error_codes::CONNECTION_BUSY_STREAMING,
+            "A stream is active on this connection; open another connection for other requests",
+        )
+    }
+
+    /// The client went away before the request finished (499)
+    #[allow(non_snake_case)]
+    pub fn RequestCancelled() -> Self {
+        Self::new(error_codes::REQUEST_CANCELLED, "The client closed the connection")
+    }
+
+    /// The daemon is shutting down (503)
+    #[allow(non_snake_case)]
+    pub fn ShuttingDown() -> Self {
+        Self::new(error_codes::SHUTTING_DOWN, "The daemon is shutting down")
+    }
+
+    /// Resource is protected against removal (409)
+    #[allow(non_snake_case)]
+    pub fn ResourceProtected(message: String) -> Self {
+        Self::new(error_codes::RESOURCE_PROTECTED, message)
+    }
+
+    /// Resource was last written by a newer kawakaze and is read-only (409)
+    #[allow(non_snake_case)]
+    pub fn VersionSkew(message: String) -> Self {
+        Self::new(error_codes::VERSION_SKEW, message)
+    }
+
+    /// The container's dataset was destroyed outside kawakaze (409)
+    #[allow(non_snake_case)]
+    pub fn DatasetMissing(message: String) -> Self {
+        Self::new(error_codes::DATASET_MISSING, message)
+    }
+
+    /// The daemon runs inside a jail that doesn't allow the operation (503)
+    #[allow(non_snake_case)]
+    pub fn Unavailable(message: String) -> Self {
+        Self::new(error_codes::UNAVAILABLE, message)
+    }
+
+    /// The pool isn't healthy enough to write to (503; see `pool_health`)
+    #[allow(non_snake_case)]
+    pub fn PoolUnhealthy(message: String) -> Self {
+        Self::new(error_codes::POOL_UNHEALTHY, message)
     }
 
     /// Jail already exists error (409)
     #[allow(non_snake_case)]
     pub fn JailAlreadyExists(name: String) -> Self {
-        Self::Conflict(format!("Jail '{}' already exists", name))
+        Self::new(error_codes::NAME_IN_USE, format!("Jail '{}' already exists", name))
     }
 
     /// Jail not found error (404)
     #[allow(non_snake_case)]
     pub fn JailNotFound(name: String) -> Self {
-        Self::NotFound(format!("Jail '{}'", name))
+        Self::new(error_codes::JAIL_NOT_FOUND, format!("Resource not found: Jail '{}'", name))
+    }
+
+    /// Image not found error (404)
+    #[allow(non_snake_case)]
+    pub fn ImageNotFound(id_or_name: String) -> Self {
+        Self::new(error_codes::IMAGE_NOT_FOUND, format!("Resource not found: Image '{}'", id_or_name))
+    }
+
+    /// Container not found error (404)
+    #[allow(non_snake_case)]
+    pub fn ContainerNotFound(id_or_name: String) -> Self {
+        Self::new(error_codes::CONTAINER_NOT_FOUND, format!("Resource not found: Container '{}'", id_or_name))
     }
 }
 
@@ -331,6 +824,15 @@ impl std::fmt::Display for ApiError {
 
 impl std::error::Error for ApiError {}
 
+/// An error response with the status its code is registered with, 500 for
+/// a code that isn't
+impl From<ApiError> for Response {
+    fn from(error: ApiError) -> Self {
+        let status = error_codes::status(&error.code).unwrap_or(status::INTERNAL_SERVER_ERROR);
+        Self::error(status, error)
+    }
+}
+
 /// Convert JailError to ApiError with appropriate status code
 impl From<JailError> for ApiError {
     fn from(err: JailError) -> Self {
@@ -352,7 +854,7 @@ impl From<JailError> for ApiError {
                         .to_string(),
                 )
             }
-            JailError::StartFailed(msg) => Self::new("START_FAILED", msg),
+            JailError::StartFailed(msg) => Self::new(error_codes::START_FAILED, msg),
             JailError::StopFailed(msg) if msg.contains("not found") => {
                 Self::JailNotFound(
                     msg.split('\'')
@@ -361,7 +863,7 @@ impl From<JailError> for ApiError {
                         .to_string(),
                 )
             }
-            JailError::StopFailed(msg) => Self::new("STOP_FAILED", msg),
+            JailError::StopFailed(msg) => Self::new(error_codes::STOP_FAILED, msg),
             JailError::DestroyFailed(msg) if msg.contains("not found") => {
                 Self::JailNotFound(
                     msg.split('\'')
@@ -370,9 +872,12 @@ impl From<JailError> for ApiError {
                         .to_string(),
                 )
             }
-            JailError::DestroyFailed(msg) => Self::new("DESTROY_FAILED", msg),
+            JailError::DestroyFailed(msg) => Self::new(error_codes::DESTROY_FAILED, msg),
             JailError::InvalidState(msg) => Self::BadRequest(msg),
             JailError::InvalidPath(msg) => Self::BadRequest(msg),
+            JailError::UpdateFailed(msg) => Self::new(error_codes::UPDATE_FAILED, msg),
+            JailError::VersionSkew(msg) => Self::VersionSkew(msg),
+            JailError::Unavailable(msg) => Self::Unavailable(msg),
         }
     }
 }
@@ -394,6 +899,23 @@ pub struct CreateJailRequest {
     /// Optional bootstrap configuration
     #[serde(skip_serializing_if = "Option::is_none")]
     pub bootstrap: Option<BootstrapConfig>,
+
+    /// Optional devfs ruleset (must be allowed by the daemon config)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub devfs_ruleset: Option<u16>,
+
+    /// Delete the jail path on removal (defaults to the daemon's `cleanup_jail_paths`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cleanup_path: Option<bool>,
+
+    /// File systems the jail may mount itself; anything but `none` needs
+    /// `allow_in_jail_mounts` in the daemon config (403 otherwise)
+    #[serde(default, skip_serializing_if = "MountPermissions::is_none")]
+    pub mount_permissions: MountPermissions,
+
+    /// Free-form notes, at most [`MAX_DESCRIPTION_LEN`] bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
 }
 
 impl CreateJailRequest {
@@ -405,15 +927,56 @@ impl CreateJailRequest {
 
         // Validate jail name (alphanumeric, underscore, and hyphen only)
         if !self.name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-') {
-            return Err(ApiError::BadRequest(
+            return Err(ApiError::new(
+                error_codes::INVALID_NAME,
                 format!("Invalid jail name '{}': only alphanumeric, underscore, and hyphen characters allowed", self.name)
             ));
         }
 
+        if let Some(ref description) = self.description {
+            check_description(description)?;
+        }
+
         Ok(())
     }
 }
 
+/// Longest description a jail or container may have, in bytes
+pub const MAX_DESCRIPTION_LEN: usize = 4096;
+
+/// Check a jail or container description: at most [`MAX_DESCRIPTION_LEN`]
+/// bytes, and no control characters but newlines and tabs
+pub fn check_description(description: &str) -> Result<(), ApiError> {
+    if description.len() > MAX_DESCRIPTION_LEN {
+        return Err(ApiError::BadRequest(format!(
+            "Description is {} bytes long; at most {} are allowed",
+            description.len(),
+            MAX_DESCRIPTION_LEN
+        )));
+    }
+    if description.chars().any(|c| c.is_control() && c != '\n' && c != '\t') {
+        return Err(ApiError::BadRequest("Description may not contain control characters other than newlines and tabs".into()));
+    }
+    Ok(())
+}
+
+/// Request body for POST /jails/{name}/update; fields left out are kept
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct JailUpdateRequest {
+    /// Set the description; an empty one clears it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+/// Options for GET /jails
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ListJailsRequest {
+    /// Only jails matching every predicate, `key=value` or `key~=value`
+    /// (see `filter`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub filter: Vec<String>,
+}
+
 /// Jail information in API response format
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct JailInfo {
@@ -429,6 +992,22 @@ pub struct JailInfo {
     /// Root directory path (if set)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub path: Option<String>,
+
+    /// Userland version recorded by the last upgrade
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub os_version: Option<String>,
+
+    /// Why the jail can't be changed ("written by newer version")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub readonly_reason: Option<String>,
+
+    /// File systems the jail may mount itself
+    #[serde(default, skip_serializing_if = "MountPermissions::is_none")]
+    pub mount_permissions: MountPermissions,
+
+    /// Free-form notes from the operator
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
 }
 
 impl From<crate::jail::JailInfo> for JailInfo {
@@ -438,6 +1017,10 @@ impl From<crate::jail::JailInfo> for JailInfo {
             jid: info.jid,
             state: state_to_string(info.state),
             path: info.path,
+            os_version: info.os_version,
+            readonly_reason: info.readonly_reason,
+            mount_permissions: info.mount_permissions,
+            description: info.description,
         }
     }
 }
@@ -462,6 +1045,10 @@ pub struct JailListItem {
 
     /// Whether the jail is running
     pub running: bool,
+
+    /// Why the jail can't be changed ("written by newer version")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub readonly_reason: Option<String>,
 }
 
 /// Bootstrap configuration (re-exported from bootstrap module)
@@ -479,6 +1066,7 @@ impl From<(String, JailState)> for JailListItem {
             name,
             state: state_to_string(state),
             running: state == JailState::Running,
+            readonly_reason: None,
         }
     }
 }
@@ -488,11 +1076,27 @@ impl From<(String, JailState)> for JailListItem {
 // ============================================================================
 
 /// Placeholder for port mapping (to be implemented in container.rs)
+///
+/// A `host_port` of 0 asks the daemon to pick a free one.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PortMapping {
     pub host_port: u16,
     pub container_port: u16,
     pub protocol: String, // "tcp" or "udp"
+    /// Host address to publish on; every address when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host_ip: Option<String>,
+}
+
+impl From<&crate::container::PortMapping> for PortMapping {
+    fn from(mapping: &crate::container::PortMapping) -> Self {
+        Self {
+            host_port: mapping.host_port,
+            container_port: mapping.container_port,
+            protocol: mapping.protocol.as_str().to_string(),
+            host_ip: mapping.host_ip.map(|ip| ip.to_string()),
+        }
+    }
 }
 
 /// Placeholder for volume mount (to be implemented in container.rs)
@@ -501,6 +1105,34 @@ pub struct Mount {
     pub source: String,
     pub destination: String,
     pub mount_type: String, // "zfs" or "nullfs"
+    /// Mount read-only (`-v src:dst:ro`)
+    #[serde(default)]
+    pub read_only: bool,
+    /// Hand the ZFS dataset to the jail with `zfs jail` (`-v
+    /// dataset:dst:delegate`); needs mount permissions zfs or all
+    #[serde(default)]
+    pub delegate: bool,
+}
+
+/// A container's volume as inspect shows it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VolumeInfo {
+    pub source: String,
+    pub destination: String,
+    pub mode: crate::container::VolumeMode,
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+impl From<&crate::container::Mount> for VolumeInfo {
+    fn from(mount: &crate::container::Mount) -> Self {
+        Self {
+            source: mount.source.clone(),
+            destination: mount.destination.clone(),
+            mode: mount.mode(),
+            read_only: mount.read_only,
+        }
+    }
 }
 
 // ----------------------------------------------------------------------------
@@ -517,6 +1149,27 @@ pub struct BuildImageRequest {
     /// Build arguments for Dockerfile ARG instructions
     #[serde(default)]
     pub build_args: HashMap<String, String>,
+    /// Mark the built image as protected against removal
+    #[serde(default)]
+    pub protected: bool,
+    /// Queue behind the base image's build if it is still in progress,
+    /// instead of failing with a conflict
+    #[serde(default)]
+    pub wait_for_base: bool,
+    /// Fail the build when an instruction references an undefined variable
+    #[serde(default)]
+    pub strict_vars: bool,
+    /// Secrets available to RUN steps, by name. Unlike build args they are
+    /// not substituted into instructions and never recorded with the image.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub secrets: HashMap<String, String>,
+    /// Build even if an image of this name was built from identical content
+    #[serde(default)]
+    pub no_cache: bool,
+    /// Network for RUN steps: "default" (the host's) or "none" (a
+    /// temporary jail without addresses)
+    #[serde(default)]
+    pub network: crate::build_jail::BuildNetwork,
 }
 
 // ----------------------------------------------------------------------------
@@ -536,6 +1189,10 @@ pub struct CreateContainerRequest {
     /// Volume mounts (ZFS datasets or nullfs filepaths)
     #[serde(default)]
     pub volumes: Vec<Mount>,
+    /// tmpfs mounted while the container runs (`--tmpfs`); a destination
+    /// may not also be a volume's
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tmpfs: Vec<crate::container::TmpfsMount>,
     /// Environment variables
     #[serde(default)]
     pub env: HashMap<String, String>,
@@ -544,6 +1201,196 @@ pub struct CreateContainerRequest {
     pub restart_policy: String,
     /// Optional command to run (overrides image default)
     pub command: Option<Vec<String>>,
+    /// Mark the container as protected against removal
+    #[serde(default)]
+    pub protected: bool,
+    /// devfs ruleset for devices beyond the default (must be allowed by the daemon config)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub devfs_ruleset: Option<u16>,
+    /// Don't run the image's HEALTHCHECK for this container
+    #[serde(default)]
+    pub disable_healthcheck: bool,
+    /// Start the container when the daemon boots with `--boot`
+    #[serde(default)]
+    pub boot: bool,
+    /// Put the container on an encrypted dataset (always the case when the
+    /// daemon config enables encryption)
+    #[serde(default)]
+    pub encrypted: bool,
+    /// Keep the root filesystem read-only while the container runs; only
+    /// volumes and the configured tmpfs mounts are writable
+    #[serde(default)]
+    pub read_only_root: bool,
+    /// Extra names other containers on the network can reach this one by;
+    /// taken names are refused with 409
+    #[serde(default)]
+    pub network_aliases: Vec<String>,
+    /// Host name inside the jail; by default it is the container's name
+    /// and follows it through renames
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    /// File systems the container may mount itself; anything but `none`
+    /// needs `allow_in_jail_mounts` in the daemon config (403 otherwise)
+    #[serde(default, skip_serializing_if = "MountPermissions::is_none")]
+    pub mount_permissions: MountPermissions,
+    /// Answer 202 with an operation to poll instead of waiting for the
+    /// datasets to be made
+    #[serde(rename = "async", default, skip_serializing_if = "std::ops::Not::not")]
+    pub run_async: bool,
+    /// Names of containers that must be running before this one starts
+    /// (batch create only)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub depends_on: Vec<String>,
+    /// Placement constraints and preferences (see `placement`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub placement: Option<crate::placement::Placement>,
+    /// sysctls set in the jail at every start, e.g.
+    /// `net.inet.ip.forwarding` = `1` (see `sysctl`)
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub sysctls: HashMap<String, String>,
+    /// Free-form notes, at most [`MAX_DESCRIPTION_LEN`] bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+/// Request body for creating and starting several containers
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BatchCreateRequest {
+    /// Started so that each comes after its `depends_on`
+    pub containers: Vec<CreateContainerRequest>,
+}
+
+/// Request body for renaming a container
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RenameRequest {
+    pub name: String,
+}
+
+/// Request body for adding or removing a container's IP alias
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ContainerIpRequest {
+    pub ip: String,
+}
+
+// ----------------------------------------------------------------------------
+// Shared Request Types
+// ----------------------------------------------------------------------------
+
+/// Request body for removing a container or image
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RemoveRequest {
+    /// Remove even if the resource is in use
+    #[serde(default)]
+    pub force: bool,
+    /// Remove even if the resource is protected (requires `force`)
+    #[serde(default)]
+    pub override_protection: bool,
+    /// Answer 202 with an operation to poll instead of waiting for the
+    /// datasets to be destroyed (containers only)
+    #[serde(rename = "async", default, skip_serializing_if = "std::ops::Not::not")]
+    pub run_async: bool,
+}
+
+/// Optional body of POST /jails/{name}/stop and /containers/{id}/stop
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct StopRequest {
+    /// "remove" (default) destroys the jail; "freeze" kills its processes
+    /// and keeps the jail and its JID for the next start
+    #[serde(default)]
+    pub stop_mode: StopMode,
+}
+
+/// Options for GET /containers
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ListContainersRequest {
+    /// Wrap the list in an object with per-state counts
+    #[serde(default)]
+    pub include_summary: bool,
+    /// Only containers matching every predicate, `key=value` or
+    /// `key~=value` (see `filter`); the summary still counts all of them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub filter: Vec<String>,
+}
+
+/// What GET /system/names lists
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NameKind {
+    /// Every container
+    Containers,
+    /// Running and paused containers
+    Running,
+    /// Created and stopped containers
+    Stopped,
+    Images,
+}
+
+impl std::str::FromStr for NameKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "containers" => Ok(NameKind::Containers),
+            "running" => Ok(NameKind::Running),
+            "stopped" => Ok(NameKind::Stopped),
+            "images" => Ok(NameKind::Images),
+            _ => Err(format!("unknown kind '{}' (containers, running, stopped or images)", s)),
+        }
+    }
+}
+
+/// Request body for GET /system/names
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NamesRequest {
+    pub kind: NameKind,
+}
+
+/// Options for GET /containers/{id}/logs and the build and bootstrap logs
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LogsRequest {
+    /// Prefix each line with its RFC 3339 timestamp
+    #[serde(default)]
+    pub timestamps: bool,
+    /// Only the last this many lines
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tail: Option<usize>,
+    /// Which logs to read; containers only, other logs have just one
+    #[serde(default, skip_serializing_if = "LogSource::is_stdio")]
+    pub source: LogSource,
+}
+
+/// Request body for upgrading a jail or image
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct UpgradeRequest {
+    /// Release to upgrade to (e.g. "14.2-RELEASE"); without it, patches
+    /// for the current release are applied
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+}
+
+/// Request body for pruning images
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PruneImagesRequest {
+    /// Also remove user images that no container uses
+    #[serde(default)]
+    pub all: bool,
+}
+
+/// Request body for updating a container or image
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct UpdateRequest {
+    /// Set or clear the protected flag
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protected: Option<bool>,
+    /// Set or clear the start-at-boot flag (containers only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub boot: Option<bool>,
+    /// Set or clear the read-only root flag (stopped containers only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_only: Option<bool>,
+    /// Set the description; an empty one clears it (containers only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
 }
 
 /// Request body for executing a command in a container
@@ -559,6 +1406,24 @@ pub struct ExecRequest {
     pub workdir: Option<String>,
 }
 
+/// Request body registering a session the CLI runs itself
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExecSessionRequest {
+    /// Container ID, name or prefix
+    pub container: String,
+    pub command: Vec<String>,
+    pub kind: ExecKind,
+    /// Host pid of the session's `jexec`
+    pub pid: u32,
+}
+
+/// Response to killing an exec session
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExecSessionKilled {
+    pub session: ExecSession,
+    pub termination: Termination,
+}
+
 // ----------------------------------------------------------------------------
 // Image Response Types
 // ----------------------------------------------------------------------------
@@ -576,8 +1441,27 @@ pub struct ImageInfo {
     pub size_bytes: u64,
     /// Image state
     pub state: String,
-    /// Unix timestamp of creation
-    pub created_at: i64,
+    /// When the image was created
+    pub created_at: Timestamp,
+    /// Whether the image is protected against removal
+    #[serde(default)]
+    pub protected: bool,
+    /// "user" or "intermediate"
+    #[serde(default = "default_image_kind")]
+    pub kind: String,
+    /// Userland version recorded by the last upgrade
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub os_version: Option<String>,
+    /// Verified manifest digest of a loaded, pulled or imported image
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+    /// Why the image can't be changed ("written by newer version")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub readonly_reason: Option<String>,
+    /// ZFS properties of its dataset (`zfs::INSPECT_PROPERTIES`), when the
+    /// daemon could read them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zfs_properties: Option<BTreeMap<String, String>>,
 }
 
 /// Item in image list response
@@ -589,8 +1473,30 @@ pub struct ImageListItem {
     pub name: String,
     /// Size in bytes
     pub size_bytes: u64,
-    /// Unix timestamp of creation
-    pub created_at: i64,
+    /// When the image was created
+    pub created_at: Timestamp,
+    /// Whether the image is protected against removal
+    #[serde(default)]
+    pub protected: bool,
+    /// "user" or "intermediate"
+    #[serde(default = "default_image_kind")]
+    pub kind: String,
+    /// Why the image can't be changed ("written by newer version")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub readonly_reason: Option<String>,
+}
+
+fn default_image_kind() -> String {
+    "user".to_string()
+}
+
+/// Result of pruning images
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PruneImagesResult {
+    /// Images that were removed
+    pub removed: Vec<ImageListItem>,
+    /// Total size of the removed images in bytes
+    pub reclaimed_bytes: u64,
 }
 
 /// Historical layer information for an image
@@ -598,18 +1504,99 @@ pub struct ImageListItem {
 pub struct ImageHistoryItem {
     /// Layer ID
     pub id: String,
-    /// Created timestamp
-    pub created_at: i64,
+    /// When the layer was created
+    pub created_at: Timestamp,
     /// Size in bytes
     pub size_bytes: u64,
     /// Description/command that created this layer
     pub created_by: String,
 }
 
+/// Response body for starting a build (202), or for a request whose image
+/// is already up to date (200, `cached`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildStarted {
+    /// Build ID to poll, or the existing image's ID when cached
+    pub id: String,
+    /// Image name
+    pub name: String,
+    /// Nothing was built: the image matches the request
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub cached: bool,
+}
+
+/// Response body for starting an upgrade (202)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpgradeStarted {
+    /// Snapshot the upgrade rolls back to
+    pub snapshot: String,
+}
+
+/// Response body for starting an async container create or remove (202)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OperationStarted {
+    /// Operation ID to poll at GET /operations/{id}
+    pub id: String,
+    /// Container being created or removed
+    pub container_id: String,
+}
+
 // ----------------------------------------------------------------------------
 // Container Response Types
 // ----------------------------------------------------------------------------
 
+/// A container's addresses on its network
+///
+/// Flattened into [`ContainerInfo`] as `ip4` and `ip6`, next to the `ip`
+/// clients from before IPv6 read: the IPv4 address, or the IPv6 one when
+/// there is no IPv4. An older daemon sends only `ip`; its family decides
+/// which address it is.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "AddressesWire", into = "AddressesWire")]
+pub struct ContainerAddresses {
+    pub v4: Option<String>,
+    pub v6: Option<String>,
+}
+
+impl ContainerAddresses {
+    /// The address to show where there is room for one: IPv4 first
+    pub fn primary(&self) -> Option<&str> {
+        self.v4.as_deref().or(self.v6.as_deref())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.v4.is_none() && self.v6.is_none()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct AddressesWire {
+    #[serde(default)]
+    ip: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ip4: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ip6: Option<String>,
+}
+
+impl From<ContainerAddresses> for AddressesWire {
+    fn from(addresses: ContainerAddresses) -> Self {
+        Self { ip: addresses.primary().map(str::to_string), ip4: addresses.v4, ip6: addresses.v6 }
+    }
+}
+
+impl From<AddressesWire> for ContainerAddresses {
+    fn from(wire: AddressesWire) -> Self {
+        if wire.ip4.is_some() || wire.ip6.is_some() {
+            return Self { v4: wire.ip4, v6: wire.ip6 };
+        }
+        match wire.ip {
+            Some(ip) if ip.parse::<std::net::Ipv6Addr>().is_ok() => Self { v4: None, v6: Some(ip) },
+            ip => Self { v4: ip, v6: None },
+        }
+    }
+}
+
 /// Detailed information about a container
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ContainerInfo {
@@ -623,14 +1610,183 @@ pub struct ContainerInfo {
     pub jail_name: String,
     /// Container state
     pub state: String,
-    /// Container IP address (if running)
-    pub ip: Option<String>,
+    /// Container addresses; `ip` on the wire (see [`ContainerAddresses`])
+    #[serde(flatten)]
+    pub ip: ContainerAddresses,
     /// Restart policy
     pub restart_policy: String,
-    /// Unix timestamp of creation
-    pub created_at: i64,
-    /// Unix timestamp when last started
-    pub started_at: Option<i64>,
+    /// When the container was created
+    pub created_at: Timestamp,
+    /// When the container was last started
+    pub started_at: Option<Timestamp>,
+    /// When the container last stopped (not sent by older daemons)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stopped_at: Option<Timestamp>,
+    /// Why it last stopped, when the daemon recorded it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_stop_cause: Option<crate::container::StopCause>,
+    /// Exec sessions the last stop killed, since the daemon started
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_stop_exec_sessions: Option<usize>,
+    /// Environment of its processes; inspect includes the image's ENV,
+    /// other responses only what was given at create
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// Where it may run, when given at create
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub placement: Option<crate::placement::Placement>,
+    /// sysctls requested at create
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sysctls: BTreeMap<String, String>,
+    /// Values the jail reported for them at the last start, while running
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub applied_sysctls: BTreeMap<String, String>,
+    /// Free-form notes from the operator
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Whether the container is protected against removal
+    #[serde(default)]
+    pub protected: bool,
+    /// devfs ruleset override (`None` means the daemon default)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub devfs_ruleset: Option<u16>,
+    /// Health check status; "none" when disabled at create time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health: Option<String>,
+    /// Whether the container is started at boot
+    #[serde(default)]
+    pub boot: bool,
+    /// Whether the container's dataset is encrypted
+    #[serde(default)]
+    pub encrypted: bool,
+    /// Whether the dataset is a full copy of the image instead of a clone
+    #[serde(default)]
+    pub full_copy: bool,
+    /// Whether the root filesystem is read-only while running
+    #[serde(default)]
+    pub read_only_root: bool,
+    /// tmpfs mounted while the container runs, apart from its volumes
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tmpfs: Vec<crate::container::TmpfsMount>,
+    /// Volumes, and how each reaches the container
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub volumes: Vec<VolumeInfo>,
+    /// Names besides its own the container answers to on its network
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub network_aliases: Vec<String>,
+    /// Host name inside the jail (not sent by older daemons)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    /// Extra addresses of a jail without VNET (`network connect`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ip_aliases: Vec<String>,
+    /// File systems the container may mount itself
+    #[serde(default, skip_serializing_if = "MountPermissions::is_none")]
+    pub mount_permissions: MountPermissions,
+    /// Why the container can't be changed ("written by newer version")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub readonly_reason: Option<String>,
+    /// Space its dataset uses, when the daemon could find out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size_bytes: Option<u64>,
+    /// ZFS properties of its dataset (`zfs::INSPECT_PROPERTIES`), when the
+    /// daemon could read them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zfs_properties: Option<BTreeMap<String, String>>,
+    /// The dataset was destroyed outside kawakaze (see `dataset_health`)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub dataset_missing: bool,
+}
+
+impl From<&crate::container::Container> for ContainerInfo {
+    fn from(container: &crate::container::Container) -> Self {
+        Self {
+            id: container.id.clone(),
+            name: container.name.clone(),
+            image_id: container.image_id.clone(),
+            jail_name: container.jail_name.clone(),
+            state: container.state.as_str().to_string(),
+            ip: ContainerAddresses { v4: container.ip.clone(), v6: container.ip6.clone() },
+            restart_policy: container.restart_policy.as_str().to_string(),
+            created_at: container.created_at,
+            started_at: container.started_at,
+            stopped_at: container.stopped_at,
+            last_stop_cause: container.last_stop_cause,
+            last_stop_exec_sessions: Some(container.last_stop_exec_sessions).filter(|n| *n > 0),
+            env: container.env.clone(),
+            placement: container.placement.clone(),
+            sysctls: container.sysctls.clone(),
+            applied_sysctls: container.applied_sysctls.clone(),
+            description: container.description.clone(),
+            protected: container.protected,
+            devfs_ruleset: container.devfs_ruleset,
+            health: container.healthcheck_disabled.then(|| "none".to_string()),
+            boot: container.boot,
+            encrypted: container.encrypted,
+            full_copy: container.full_copy,
+            read_only_root: container.read_only_root,
+            tmpfs: container.tmpfs.clone(),
+            volumes: container.mounts.iter().map(VolumeInfo::from).collect(),
+            network_aliases: container.network_aliases.clone(),
+            hostname: Some(container.hostname().to_string()),
+            ip_aliases: container.ip_aliases.clone(),
+            mount_permissions: container.mount_permissions,
+            readonly_reason: container.readonly_reason().map(str::to_string),
+            size_bytes: None,
+            zfs_properties: None,
+            dataset_missing: false,
+        }
+    }
+}
+
+/// Response body for creating a container: its info plus everything
+/// allocated for it, so callers don't need a follow-up inspect
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreatedContainer {
+    #[serde(flatten)]
+    pub container: ContainerInfo,
+    /// Port mappings with the host ports actually assigned
+    pub ports: Vec<PortMapping>,
+    /// Command run when the container starts (`None` runs nothing)
+    pub command: Option<Vec<String>>,
+    /// Tradeoffs the daemon made to honor the request
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+impl From<&crate::container::Container> for CreatedContainer {
+    fn from(container: &crate::container::Container) -> Self {
+        Self {
+            container: ContainerInfo::from(container),
+            ports: container.port_mappings.iter().map(PortMapping::from).collect(),
+            command: container.command.clone(),
+            warnings: container
+                .full_copy
+                .then(|| {
+                    format!(
+                        "image {} is not encrypted, so the encrypted dataset was made with zfs send/receive; \
+                         it is a full copy that shares no space with the image",
+                        container.image_id
+                    )
+                })
+                .into_iter()
+                .collect(),
+        }
+    }
+}
+
+/// Response body for a batch create: the containers in the order they
+/// were started
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BatchCreated {
+    pub containers: Vec<CreatedContainer>,
+}
+
+/// Container list response when a summary was asked for
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ContainerListWithSummary {
+    pub containers: Vec<ContainerListItem>,
+    pub summary: crate::container::ContainerSummary,
 }
 
 /// Item in container list response
@@ -644,15 +1800,51 @@ pub struct ContainerListItem {
     pub image_id: String,
     /// Container state
     pub state: String,
-    /// Container IP address (if running)
-    pub ip: Option<String>,
+    /// Container addresses; `ip` on the wire (see [`ContainerAddresses`])
+    #[serde(flatten)]
+    pub ip: ContainerAddresses,
+    /// When the container was created (not sent by older daemons)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<Timestamp>,
+    /// When the container last stopped (not sent by older daemons)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stopped_at: Option<Timestamp>,
+    /// Why it last stopped, when the daemon recorded it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_stop_cause: Option<crate::container::StopCause>,
+    /// Whether the container is protected against removal
+    #[serde(default)]
+    pub protected: bool,
+    /// Why the container can't be changed ("written by newer version")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub readonly_reason: Option<String>,
+    /// The dataset was destroyed outside kawakaze
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub dataset_missing: bool,
+}
+
+impl From<&crate::container::Container> for ContainerListItem {
+    fn from(container: &crate::container::Container) -> Self {
+        Self {
+            id: container.id.clone(),
+            name: container.name.clone(),
+            image_id: container.image_id.clone(),
+            state: container.state.as_str().to_string(),
+            ip: ContainerAddresses { v4: container.ip.clone(), v6: container.ip6.clone() },
+            created_at: Some(container.created_at),
+            stopped_at: container.stopped_at,
+            last_stop_cause: container.last_stop_cause,
+            protected: container.protected,
+            readonly_reason: container.readonly_reason().map(str::to_string),
+            dataset_missing: false,
+        }
+    }
 }
 
 /// Container log entry
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ContainerLogEntry {
-    /// Unix timestamp
-    pub timestamp: i64,
+    pub timestamp: Timestamp,
     /// Log level ("info", "warn", "error", "debug")
     pub level: String,
     /// Log message
@@ -670,6 +1862,206 @@ pub struct ExecResult {
     pub stderr: String,
 }
 
+// ----------------------------------------------------------------------------
+// System Response Types
+// ----------------------------------------------------------------------------
+
+/// The daemon's running configuration and whether its file has since changed
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SystemConfigInfo {
+    /// Effective configuration with secrets redacted
+    pub config: serde_json::Value,
+    /// Config file it was loaded from (`None` when running on defaults)
+    pub path: Option<String>,
+    /// The file's modification time at load
+    pub loaded_mtime: Option<Timestamp>,
+    /// Whether the file on disk no longer matches the running configuration
+    pub drift: bool,
+    /// Paths of fields that differ between the running config and the file
+    #[serde(default)]
+    pub drifted_fields: Vec<String>,
+    /// Why the file could not be re-read, if it couldn't
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_error: Option<String>,
+}
+
+/// Response body for GET /system/boot
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BootList {
+    /// Containers flagged to start at boot, in start order
+    pub containers: Vec<ContainerListItem>,
+    /// Results of the last `--boot` start (`None` if the daemon wasn't
+    /// launched with it)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_boot: Option<crate::boot::BootReport>,
+}
+
+/// Host and daemon state, like `docker info`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SystemInfo {
+    /// Backend version
+    pub version: String,
+    /// FreeBSD release, kernel, CPU and memory of the host
+    #[serde(default)]
+    pub host: crate::system::HostInfo,
+    /// Pool health and capacity (`None` when ZFS isn't available)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pool: Option<crate::zfs::PoolStatus>,
+    /// Why the pool status could not be read, if it couldn't
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pool_error: Option<String>,
+    /// Builds, container creates, bootstraps and upgrades are refused
+    /// because of the pool's state (see `pool_health`)
+    #[serde(default)]
+    pub pool_writes_refused: bool,
+    /// Jails per state
+    #[serde(default)]
+    pub jails: std::collections::BTreeMap<String, usize>,
+    /// Images per state
+    #[serde(default)]
+    pub images: std::collections::BTreeMap<String, usize>,
+    /// Containers per state
+    #[serde(default)]
+    pub containers: crate::container::ContainerSummary,
+    /// Seconds since the daemon started
+    #[serde(default)]
+    pub uptime_secs: u64,
+    /// Slowest external commands of the last hour, slowest first
+    #[serde(default)]
+    pub slowest_commands: Vec<crate::cmdtrace::CommandRecord>,
+    /// Failed database writes waiting to be retried
+    #[serde(default)]
+    pub pending_writes: crate::persist_queue::QueueInfo,
+    /// Labels placement constraints are checked against (see `placement`)
+    #[serde(default)]
+    pub node_labels: std::collections::BTreeMap<String, String>,
+}
+
+/// A build or bootstrap holding or waiting for a slot (see `task_queue`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TaskInfo {
+    /// "build", "bootstrap", or "create"/"remove" for a container operation
+    pub kind: String,
+    /// Build ID, the jail name of a bootstrap, or an operation ID
+    pub id: String,
+    /// Image or jail name
+    pub name: String,
+    /// Place in line (1 is next), or `None` once running
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<usize>,
+}
+
+/// A dataset under the containers/images roots that no record points at
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OrphanInfo {
+    /// Full dataset name, to be passed back verbatim to adopt or destroy it
+    pub dataset: String,
+    /// "container" or "image", from the root it was found under
+    pub kind: String,
+    /// Space used in bytes
+    pub size_bytes: u64,
+    /// When the dataset was created
+    pub created_at: Timestamp,
+    /// Snapshot an adopted image would use
+    pub snapshot: Option<String>,
+}
+
+/// Request body for adopting an orphaned dataset
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct AdoptOrphanRequest {
+    /// Dataset name exactly as listed by GET /system/orphans
+    pub dataset: String,
+    /// Container name (optional) or image name (required)
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Image ID or name a container was created from (required for containers)
+    #[serde(default)]
+    pub image: Option<String>,
+}
+
+/// Request body for destroying an orphaned dataset
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DestroyOrphanRequest {
+    /// Dataset name exactly as listed by GET /system/orphans
+    pub dataset: String,
+}
+
+/// Request body for pruning a dataset's snapshots
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PruneSnapshotsRequest {
+    /// Dataset below the configured pool root
+    pub dataset: String,
+    /// Number of newest snapshots to keep
+    pub keep_last: usize,
+}
+
+/// Result of pruning a dataset's snapshots
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PruneSnapshotsResult {
+    pub dataset: String,
+    /// Destroyed snapshot names, without the dataset prefix
+    pub destroyed: Vec<String>,
+}
+
+/// Request body for collecting unreferenced snapshots
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SnapshotGcRequest {
+    /// List the candidates without destroying them
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Request body for changing the daemon's log filter
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LogLevelRequest {
+    /// A level (`debug`) or `EnvFilter` directives
+    /// (`info,kawakaze_backend::zfs=trace`)
+    pub level: String,
+}
+
+/// Result of changing the daemon's log filter
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogLevelResult {
+    /// The filter now in effect
+    pub level: String,
+    /// The filter it replaced
+    pub previous: String,
+}
+
+/// Request body for announcing a chunked upload
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadRequest {
+    pub purpose: UploadPurpose,
+    /// Size of the whole file in bytes
+    pub size: u64,
+    /// SHA-256 of the whole file, hex
+    pub sha256: String,
+}
+
+/// Request body for one chunk of an upload
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadChunk {
+    /// Where in the file the chunk starts: the upload's `received`
+    pub offset: u64,
+    /// The chunk's bytes, base64; at most
+    /// [`MAX_CHUNK_BYTES`](crate::upload::MAX_CHUNK_BYTES) once decoded
+    pub data: String,
+}
+
+/// Response body for adopting an orphaned dataset
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdoptedOrphan {
+    /// ID of the new container or image record
+    pub id: String,
+    pub dataset: String,
+}
+
+/// Response body of deletes and other requests with nothing else to return
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MessageResult {
+    pub message: String,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -680,23 +2072,61 @@ mod tests {
         assert_eq!(Endpoint::Jail("test".into()).path(), "jails/test");
         assert_eq!(Endpoint::StartJail("test".into()).path(), "jails/test/start");
         assert_eq!(Endpoint::StopJail("test".into()).path(), "jails/test/stop");
+        assert_eq!(Endpoint::JailUpgrade("test".into()).path(), "jails/test/upgrade");
+        assert_eq!(Endpoint::JailUpgradeStatus("test".into()).path(), "jails/test/upgrade/status");
+        assert_eq!(Endpoint::JailUpdate("test".into()).path(), "jails/test/update");
+        assert_eq!(Endpoint::BootstrapLog("test".into()).path(), "jails/test/bootstrap/log");
 
         // Image endpoints
         assert_eq!(Endpoint::Images.path(), "images");
         assert_eq!(Endpoint::Image("abc123".into()).path(), "images/abc123");
         assert_eq!(Endpoint::ImageBuild.path(), "images/build");
+        assert_eq!(Endpoint::ImageBuildStatus("abc123".into()).path(), "images/build/abc123");
         assert_eq!(Endpoint::DeleteImage("abc123".into()).path(), "images/abc123");
         assert_eq!(Endpoint::ImageHistory("abc123".into()).path(), "images/abc123/history");
+        assert_eq!(Endpoint::ImageContainers("abc123".into()).path(), "images/abc123/containers");
+        assert_eq!(Endpoint::ImageBuildLog("abc123".into()).path(), "images/abc123/build-log");
+        assert_eq!(Endpoint::ImageBuildLogFollow("abc123".into()).path(), "images/abc123/build-log/follow");
+        assert_eq!(Endpoint::UpdateImage("abc123".into()).path(), "images/abc123/update");
+        assert_eq!(Endpoint::ImageUpgrade("abc123".into()).path(), "images/abc123/upgrade");
+        assert_eq!(Endpoint::ImageUpgradeStatus("abc123".into()).path(), "images/abc123/upgrade/status");
 
         // Container endpoints
         assert_eq!(Endpoint::Containers.path(), "containers");
         assert_eq!(Endpoint::Container("def456".into()).path(), "containers/def456");
         assert_eq!(Endpoint::ContainerCreate.path(), "containers/create");
+        assert_eq!(Endpoint::ContainerBatchCreate.path(), "containers/batch");
         assert_eq!(Endpoint::StartContainer("def456".into()).path(), "containers/def456/start");
         assert_eq!(Endpoint::StopContainer("def456".into()).path(), "containers/def456/stop");
         assert_eq!(Endpoint::RemoveContainer("def456".into()).path(), "containers/def456");
         assert_eq!(Endpoint::ContainerLogs("def456".into()).path(), "containers/def456/logs");
+        assert_eq!(Endpoint::ContainerLogsFollow("def456".into()).path(), "containers/def456/logs/follow");
         assert_eq!(Endpoint::ContainerExec("def456".into()).path(), "containers/def456/exec");
+        assert_eq!(Endpoint::ContainerTop("def456".into()).path(), "containers/def456/top");
+        assert_eq!(Endpoint::UpdateContainer("def456".into()).path(), "containers/def456/update");
+        assert_eq!(Endpoint::RenameContainer("def456".into()).path(), "containers/def456/rename");
+        assert_eq!(Endpoint::AddContainerIp("def456".into()).path(), "containers/def456/ips");
+        assert_eq!(Endpoint::RemoveContainerIp("def456".into()).path(), "containers/def456/ips");
+
+        // System endpoints
+        assert_eq!(Endpoint::SystemConfig.path(), "system/config");
+        assert_eq!(Endpoint::SystemOrphans.path(), "system/orphans");
+        assert_eq!(Endpoint::CommandMetrics.path(), "system/commands");
+        assert_eq!(Endpoint::SystemInfo.path(), "system/info");
+        assert_eq!(Endpoint::SystemDoctor.path(), "system/doctor");
+        assert_eq!(Endpoint::SystemBootList.path(), "system/boot");
+        assert_eq!(Endpoint::SystemTasks.path(), "system/tasks");
+        assert_eq!(Endpoint::CancelTask("web".into()).path(), "system/tasks/web/cancel");
+        assert_eq!(Endpoint::Operation("op1".into()).path(), "operations/op1");
+        assert_eq!(Endpoint::OrphanAdopt.path(), "system/orphans/adopt");
+        assert_eq!(Endpoint::OrphanDestroy.path(), "system/orphans/destroy");
+        assert_eq!(Endpoint::SystemSnapshotGc.path(), "system/snapshots/gc");
+        assert_eq!(Endpoint::SetLogLevel.path(), "system/log-level");
+        assert_eq!(Endpoint::UploadCreate.path(), "uploads");
+        assert_eq!(Endpoint::UploadStatus("u1".into()).path(), "uploads/u1");
+        assert_eq!(Endpoint::UploadChunk("u1".into()).path(), "uploads/u1/chunk");
+        assert_eq!(Endpoint::UploadCommit("u1".into()).path(), "uploads/u1/commit");
+        assert_eq!(Endpoint::UploadAbort("u1".into()).path(), "uploads/u1");
     }
 
     #[test]
@@ -713,6 +2143,10 @@ mod tests {
             path: Some("/tmp/test".into()),
             ip: None,
             bootstrap: None,
+            devfs_ruleset: None,
+            cleanup_path: None,
+            mount_permissions: MountPermissions::None,
+            description: None,
         };
         let req = Request::post(Endpoint::Jails, body).unwrap();
         assert_eq!(req.method, Method::Post);
@@ -733,6 +2167,7 @@ mod tests {
             method: Method::Get,
             endpoint: "jails".to_string(),
             body: serde_json::Value::Null,
+            peer_uid: None,
         };
         assert_eq!(req.parse_endpoint().unwrap(), Endpoint::Jails);
 
@@ -740,6 +2175,7 @@ mod tests {
             method: Method::Get,
             endpoint: "jails/test".to_string(),
             body: serde_json::Value::Null,
+            peer_uid: None,
         };
         assert_eq!(req.parse_endpoint().unwrap(), Endpoint::Jail("test".into()));
 
@@ -747,24 +2183,40 @@ mod tests {
             method: Method::Post,
             endpoint: "jails/test/start".to_string(),
             body: serde_json::Value::Null,
+            peer_uid: None,
         };
         assert_eq!(
             req.parse_endpoint().unwrap(),
             Endpoint::StartJail("test".into())
         );
 
+        let req = Request::post(Endpoint::JailUpdate("test".into()), JailUpdateRequest { description: Some("notes".into()) }).unwrap();
+        assert_eq!(req.endpoint, "jails/test/update");
+        assert_eq!(req.parse_endpoint().unwrap(), Endpoint::JailUpdate("test".into()));
+        assert_eq!(req.body, serde_json::json!({ "description": "notes" }));
+
         // Image endpoints
         let req = Request {
             method: Method::Get,
             endpoint: "images".to_string(),
             body: serde_json::Value::Null,
+            peer_uid: None,
         };
         assert_eq!(req.parse_endpoint().unwrap(), Endpoint::Images);
 
+        let req = Request {
+            method: Method::Post,
+            endpoint: "images/prune".to_string(),
+            body: serde_json::Value::Null,
+            peer_uid: None,
+        };
+        assert_eq!(req.parse_endpoint().unwrap(), Endpoint::PruneImages);
+
         let req = Request {
             method: Method::Get,
             endpoint: "images/abc123".to_string(),
             body: serde_json::Value::Null,
+            peer_uid: None,
         };
         assert_eq!(
             req.parse_endpoint().unwrap(),
@@ -775,6 +2227,7 @@ mod tests {
             method: Method::Post,
             endpoint: "images/build".to_string(),
             body: serde_json::Value::Null,
+            peer_uid: None,
         };
         assert_eq!(req.parse_endpoint().unwrap(), Endpoint::ImageBuild);
 
@@ -783,6 +2236,7 @@ mod tests {
             method: Method::Get,
             endpoint: "containers".to_string(),
             body: serde_json::Value::Null,
+            peer_uid: None,
         };
         assert_eq!(req.parse_endpoint().unwrap(), Endpoint::Containers);
 
@@ -790,13 +2244,31 @@ mod tests {
             method: Method::Post,
             endpoint: "containers/create".to_string(),
             body: serde_json::Value::Null,
+            peer_uid: None,
         };
         assert_eq!(req.parse_endpoint().unwrap(), Endpoint::ContainerCreate);
 
+        let req = Request::post(Endpoint::ContainerBatchCreate, ()).unwrap();
+        assert_eq!(req.parse_endpoint().unwrap(), Endpoint::ContainerBatchCreate);
+        // A container can still be called "batch"
+        let req = Request::get(Endpoint::Container("batch".into()));
+        assert_eq!(req.parse_endpoint().unwrap(), Endpoint::Container("batch".into()));
+
+        let req = Request::post(Endpoint::RenameContainer("web".into()), RenameRequest { name: "edge".into() }).unwrap();
+        assert_eq!(req.parse_endpoint().unwrap(), Endpoint::RenameContainer("web".into()));
+
+        let body = || ContainerIpRequest { ip: "192.0.2.10".into() };
+        let req = Request::post(Endpoint::AddContainerIp("web".into()), body()).unwrap();
+        assert_eq!(req.parse_endpoint().unwrap(), Endpoint::AddContainerIp("web".into()));
+        let req = Request::delete_with(Endpoint::RemoveContainerIp("web".into()), body()).unwrap();
+        assert_eq!(req.parse_endpoint().unwrap(), Endpoint::RemoveContainerIp("web".into()));
+        assert!(Request::get(Endpoint::AddContainerIp("web".into())).parse_endpoint().is_err());
+
         let req = Request {
             method: Method::Post,
             endpoint: "containers/def456/start".to_string(),
             body: serde_json::Value::Null,
+            peer_uid: None,
         };
         assert_eq!(
             req.parse_endpoint().unwrap(),
@@ -804,6 +2276,28 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_request_parse_delete_endpoints() {
+        let req = Request::delete(Endpoint::DeleteImage("abc123".into()));
+        assert_eq!(
+            req.parse_endpoint().unwrap(),
+            Endpoint::DeleteImage("abc123".into())
+        );
+
+        let req = Request::delete(Endpoint::RemoveContainer("def456".into()));
+        assert_eq!(
+            req.parse_endpoint().unwrap(),
+            Endpoint::RemoveContainer("def456".into())
+        );
+
+        let req = Request::delete_with(
+            Endpoint::RemoveContainer("def456".into()),
+            RemoveRequest { force: true, override_protection: true, ..Default::default() },
+        )
+        .unwrap();
+        assert_eq!(req.body["override_protection"], true);
+    }
+
     #[test]
     fn test_response_success() {
         let resp = Response::success(serde_json::json!({"test": "data"})).unwrap();
@@ -829,6 +2323,25 @@ mod tests {
         assert!(!resp.is_success());
     }
 
+    #[test]
+    fn test_stream_frames() {
+        let frame = serde_json::to_value(Response::stream_frame("line").unwrap()).unwrap();
+        assert_eq!(frame, serde_json::json!({"status": 200, "data": "line", "stream": true}));
+
+        let end = serde_json::to_value(Response::stream_end(Ok(()))).unwrap();
+        assert_eq!(end, serde_json::json!({"status": 200, "stream": true, "end": true}));
+
+        let failed = Response::stream_end(Err(Response::not_found("x")));
+        assert_eq!((failed.status, failed.stream, failed.end), (status::NOT_FOUND, true, true));
+
+        // Plain responses don't carry the markers
+        let plain = serde_json::to_value(Response::conflict("busy")).unwrap();
+        assert!(plain.get("stream").is_none() && plain.get("end").is_none());
+        assert!(Endpoint::ContainerLogsFollow("web".into()).is_streaming());
+        assert!(Endpoint::ImageBuildLogFollow("web".into()).is_streaming());
+        assert!(!Endpoint::ContainerLogs("web".into()).is_streaming());
+    }
+
     #[test]
     fn test_create_jail_request_validate() {
         let req = CreateJailRequest {
@@ -836,6 +2349,10 @@ mod tests {
             path: Some("/tmp/test".into()),
             ip: None,
             bootstrap: None,
+            devfs_ruleset: None,
+            cleanup_path: None,
+            mount_permissions: MountPermissions::None,
+            description: None,
         };
         assert!(req.validate().is_ok());
 
@@ -844,6 +2361,10 @@ mod tests {
             path: None,
             ip: None,
             bootstrap: None,
+            devfs_ruleset: None,
+            cleanup_path: None,
+            mount_permissions: MountPermissions::None,
+            description: None,
         };
         assert!(req.validate().is_err());
 
@@ -852,8 +2373,42 @@ mod tests {
             path: None,
             ip: None,
             bootstrap: None,
+            devfs_ruleset: None,
+            cleanup_path: None,
+            mount_permissions: MountPermissions::None,
+            description: None,
         };
         assert!(req.validate().is_err());
+
+        let req = CreateJailRequest {
+            name: "db".into(),
+            path: None,
+            ip: None,
+            bootstrap: None,
+            devfs_ruleset: None,
+            cleanup_path: None,
+            mount_permissions: MountPermissions::None,
+            description: Some("x".repeat(MAX_DESCRIPTION_LEN + 1)),
+        };
+        assert!(req.validate().is_err());
+    }
+
+    #[test]
+    fn test_check_description() {
+        assert!(check_description("").is_ok());
+        assert!(check_description("owned by team-payments\n\tdecommission after Q3").is_ok());
+        assert!(check_description(&"x".repeat(MAX_DESCRIPTION_LEN)).is_ok());
+
+        // The cap is in bytes, not characters
+        let err = check_description(&"x".repeat(MAX_DESCRIPTION_LEN + 1)).unwrap_err();
+        assert_eq!(err.code, error_codes::BAD_REQUEST);
+        assert_eq!(err.message, "Description is 4097 bytes long; at most 4096 are allowed");
+        assert!(check_description(&"é".repeat(MAX_DESCRIPTION_LEN / 2)).is_ok());
+        assert!(check_description(&"é".repeat(MAX_DESCRIPTION_LEN / 2 + 1)).is_err());
+
+        assert!(check_description("a\0b").is_err());
+        assert!(check_description("bell\u{7}").is_err());
+        assert!(check_description("line\r\n").is_err());
     }
 
     #[test]
@@ -863,6 +2418,10 @@ mod tests {
             jid: 123,
             state: JailState::Running,
             path: Some("/tmp/test".into()),
+            os_version: None,
+            readonly_reason: None,
+            mount_permissions: MountPermissions::None,
+            description: None,
         };
         let api_info = JailInfo::from(jail_info);
         assert_eq!(api_info.name, "test");
@@ -875,15 +2434,20 @@ mod tests {
     fn test_api_error_from_jail_error() {
         let err = JailError::CreationFailed("Jail 'test' already exists".into());
         let api_err = ApiError::from(err);
-        assert_eq!(api_err.code, "CONFLICT");
+        assert_eq!(api_err.code, "NAME_IN_USE");
 
         let err = JailError::StartFailed("Jail 'test' not found".into());
         let api_err = ApiError::from(err);
-        assert_eq!(api_err.code, "NOT_FOUND");
+        assert_eq!(api_err.code, "JAIL_NOT_FOUND");
 
         let err = JailError::StopFailed("Jail 'missing' not found".into());
         let api_err = ApiError::from(err);
-        assert_eq!(api_err.code, "NOT_FOUND");
+        assert_eq!(api_err.code, "JAIL_NOT_FOUND");
+        assert_eq!(Response::from(api_err).status, status::NOT_FOUND);
+
+        let failed = Response::from(ApiError::from(JailError::StartFailed("jail: exec.start failed".into())));
+        assert_eq!((failed.status, failed.error.unwrap().code.as_str()), (status::INTERNAL_SERVER_ERROR, "START_FAILED"));
+        assert_eq!(Response::from(ApiError::new("NO_SUCH_CODE", "?")).status, status::INTERNAL_SERVER_ERROR);
     }
 
     #[test]
@@ -895,9 +2459,18 @@ mod tests {
             name: "test-image".to_string(),
             dockerfile: "FROM freebsd:15.0\nRUN pkg install -y nginx".to_string(),
             build_args,
+            protected: false,
+            wait_for_base: false,
+            strict_vars: false,
+            secrets: HashMap::new(),
+            no_cache: false,
+            network: crate::build_jail::BuildNetwork::None,
         };
 
         assert_eq!(req.name, "test-image");
+        assert_eq!(serde_json::to_value(&req).unwrap()["network"], "none");
+        let parsed: BuildImageRequest = serde_json::from_str(r#"{"name": "a", "dockerfile": "FROM scratch"}"#).unwrap();
+        assert_eq!(parsed.network, crate::build_jail::BuildNetwork::Default);
         assert_eq!(req.build_args.len(), 1);
         assert_eq!(req.build_args.get("VERSION"), Some(&"1.0".to_string()));
     }
@@ -911,12 +2484,16 @@ mod tests {
                 host_port: 8080,
                 container_port: 80,
                 protocol: "tcp".to_string(),
+                host_ip: None,
             }],
             volumes: vec![Mount {
                 source: "/data".to_string(),
                 destination: "/mnt/data".to_string(),
                 mount_type: "nullfs".to_string(),
+                read_only: false,
+                delegate: false,
             }],
+            tmpfs: vec![crate::container::TmpfsMount { destination: "/run".to_string(), size_bytes: Some(64 << 20), mode: Some(0o1777) }],
             env: {
                 let mut map = HashMap::new();
                 map.insert("DEBUG".to_string(), "true".to_string());
@@ -924,6 +2501,20 @@ mod tests {
             },
             restart_policy: "on-fail".to_string(),
             command: Some(vec!["nginx".to_string(), "-g".to_string(), "daemon off;".to_string()]),
+            protected: true,
+            devfs_ruleset: None,
+            disable_healthcheck: false,
+            boot: true,
+            encrypted: false,
+            read_only_root: false,
+            network_aliases: vec!["www".to_string()],
+            hostname: None,
+            mount_permissions: MountPermissions::None,
+            run_async: false,
+            depends_on: Vec::new(),
+            placement: None,
+            sysctls: HashMap::new(),
+            description: None,
         };
 
         assert_eq!(req.image_id, "abc123");
@@ -932,6 +2523,43 @@ mod tests {
         assert_eq!(req.volumes.len(), 1);
         assert_eq!(req.restart_policy, "on-fail");
         assert!(req.command.is_some());
+        assert!(req.protected);
+
+        let json = serde_json::to_value(&req).unwrap();
+        assert_eq!(json["tmpfs"], serde_json::json!([{"destination": "/run", "size_bytes": 67108864, "mode": 1023}]));
+        let parsed: CreateContainerRequest = serde_json::from_str(r#"{"image_id": "abc123", "tmpfs": [{"destination": "/tmp"}]}"#).unwrap();
+        assert_eq!(parsed.tmpfs, [crate::container::TmpfsMount::new("/tmp".to_string())]);
+
+        // `async` is only sent when set
+        assert!(serde_json::to_value(&req).unwrap().get("async").is_none());
+        let req: CreateContainerRequest = serde_json::from_value(serde_json::json!({ "image_id": "abc123", "async": true })).unwrap();
+        assert!(req.run_async);
+        let remove: RemoveRequest = serde_json::from_value(serde_json::json!({ "async": true })).unwrap();
+        assert!(remove.run_async && !remove.force);
+    }
+
+    #[test]
+    fn test_operation_endpoint() {
+        let req = Request { method: Method::Get, endpoint: "operations/op1".to_string(), body: serde_json::Value::Null, peer_uid: None };
+        assert_eq!(req.parse_endpoint().unwrap(), Endpoint::Operation("op1".into()));
+    }
+
+    #[test]
+    fn test_exec_session_endpoints() {
+        let parse = |method: Method, endpoint: &str| {
+            Request { method, endpoint: endpoint.to_string(), body: serde_json::Value::Null, peer_uid: None }.parse_endpoint()
+        };
+        assert_eq!(parse(Method::Get, "exec-sessions").unwrap(), Endpoint::ExecSessions);
+        assert_eq!(parse(Method::Post, "exec-sessions").unwrap(), Endpoint::ExecSessions);
+        assert_eq!(parse(Method::Delete, "exec-sessions/ab12").unwrap(), Endpoint::EndExecSession("ab12".into()));
+        assert_eq!(parse(Method::Post, "exec-sessions/ab12/kill").unwrap(), Endpoint::ExecSessionKill("ab12".into()));
+        assert_eq!(parse(Method::Get, "containers/web/exec-sessions").unwrap(), Endpoint::ContainerExecSessions("web".into()));
+        assert!(parse(Method::Get, "exec-sessions/ab12/kill").is_err());
+
+        for endpoint in [Endpoint::ExecSessions, Endpoint::EndExecSession("ab12".into()), Endpoint::ExecSessionKill("ab12".into())] {
+            let method = if matches!(endpoint, Endpoint::EndExecSession(_)) { Method::Delete } else { Method::Post };
+            assert_eq!(parse(method, &endpoint.path()).unwrap(), endpoint);
+        }
     }
 
     #[test]
@@ -954,7 +2582,13 @@ mod tests {
             parent_id: Some("def456".to_string()),
             size_bytes: 500_000_000,
             state: "ready".to_string(),
-            created_at: 1640000000,
+            created_at: Timestamp::from_secs(1640000000),
+            protected: false,
+            kind: "user".to_string(),
+            os_version: None,
+            digest: None,
+            readonly_reason: None,
+            zfs_properties: None,
         };
 
         assert_eq!(info.id, "abc123");
@@ -970,15 +2604,95 @@ mod tests {
             image_id: "abc123".to_string(),
             jail_name: "kawakaze-container-1".to_string(),
             state: "running".to_string(),
-            ip: Some("10.11.0.2".to_string()),
+            ip: ContainerAddresses { v4: Some("10.11.0.2".to_string()), v6: None },
             restart_policy: "on-restart".to_string(),
-            created_at: 1640000000,
-            started_at: Some(1640000100),
+            created_at: Timestamp::from_secs(1640000000),
+            started_at: Some(Timestamp::from_secs(1640000100)),
+            stopped_at: None,
+            last_stop_cause: None,
+            last_stop_exec_sessions: None,
+            env: BTreeMap::new(),
+            placement: None,
+            sysctls: BTreeMap::new(),
+            applied_sysctls: BTreeMap::new(),
+            protected: false,
+            devfs_ruleset: None,
+            health: None,
+            boot: false,
+            encrypted: true,
+            full_copy: false,
+            read_only_root: false,
+            volumes: Vec::new(),
+            tmpfs: Vec::new(),
+            network_aliases: Vec::new(),
+            hostname: None,
+            ip_aliases: Vec::new(),
+            mount_permissions: MountPermissions::None,
+            readonly_reason: None,
+            size_bytes: None,
+            zfs_properties: None,
+            dataset_missing: false,
+            description: None,
         };
 
         assert_eq!(info.id, "container-1");
         assert_eq!(info.state, "running");
-        assert_eq!(info.ip, Some("10.11.0.2".to_string()));
+        assert_eq!(info.ip.primary(), Some("10.11.0.2"));
         assert!(info.started_at.is_some());
+
+        // RFC3339 on the wire; older daemons' Unix seconds still read back
+        let mut json = serde_json::to_value(&info).unwrap();
+        assert_eq!(json["created_at"], "2021-12-20T11:33:20Z");
+        assert_eq!(json["started_at"], "2021-12-20T11:35:00Z");
+        json["created_at"] = serde_json::json!(1640000000);
+        let decoded: ContainerInfo = serde_json::from_value(json).unwrap();
+        assert_eq!(decoded.created_at, info.created_at);
+    }
+
+    #[test]
+    fn test_container_addresses_wire_format() {
+        let addresses = |v4: Option<&str>, v6: Option<&str>| ContainerAddresses { v4: v4.map(String::from), v6: v6.map(String::from) };
+        let wire = |addresses: &ContainerAddresses| serde_json::to_value(AddressesWire::from(addresses.clone())).unwrap();
+
+        // `ip` stays a string: the IPv4 address, else the IPv6 one
+        assert_eq!(wire(&addresses(Some("10.11.0.2"), None)), serde_json::json!({ "ip": "10.11.0.2", "ip4": "10.11.0.2" }));
+        assert_eq!(
+            wire(&addresses(Some("10.11.0.2"), Some("fd00::2"))),
+            serde_json::json!({ "ip": "10.11.0.2", "ip4": "10.11.0.2", "ip6": "fd00::2" })
+        );
+        assert_eq!(wire(&addresses(None, Some("fd00::2"))), serde_json::json!({ "ip": "fd00::2", "ip6": "fd00::2" }));
+        assert_eq!(wire(&addresses(None, None)), serde_json::json!({ "ip": null }));
+
+        // What older daemons send: only `ip`, of either family
+        let read = |json: serde_json::Value| ContainerAddresses::from(serde_json::from_value::<AddressesWire>(json).unwrap());
+        assert_eq!(read(serde_json::json!({ "ip": "10.11.0.2" })), addresses(Some("10.11.0.2"), None));
+        assert_eq!(read(serde_json::json!({ "ip": "fd00::2" })), addresses(None, Some("fd00::2")));
+        assert_eq!(read(serde_json::json!({ "ip": null })), addresses(None, None));
+        assert_eq!(read(serde_json::json!({})), addresses(None, None));
+        assert_eq!(
+            read(serde_json::json!({ "ip": "10.11.0.2", "ip4": "10.11.0.2", "ip6": "fd00::2" })),
+            addresses(Some("10.11.0.2"), Some("fd00::2"))
+        );
+    }
+
+    #[test]
+    fn test_container_info_keeps_string_ip() {
+        let container = crate::container::Container::new("img".into(), "kawakaze-web".into(), "tank/web".into())
+            .with_ip6("fd00:6b7a::2".into());
+        let json = serde_json::to_value(ContainerInfo::from(&container)).unwrap();
+        assert_eq!(json["ip"], "fd00:6b7a::2");
+        assert_eq!(json["ip6"], "fd00:6b7a::2");
+        assert!(json.get("ip4").is_none());
+
+        // A client from before IPv6 only knows `ip: Option<String>`
+        #[derive(Deserialize)]
+        struct OldContainerInfo {
+            ip: Option<String>,
+        }
+        let old: OldContainerInfo = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(old.ip.as_deref(), Some("fd00:6b7a::2"));
+
+        let decoded: ContainerInfo = serde_json::from_value(json).unwrap();
+        assert_eq!(decoded.ip, ContainerAddresses { v4: None, v6: Some("fd00:6b7a::2".into()) });
     }
 }
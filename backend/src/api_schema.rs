@@ -0,0 +1,974 @@
+//! Machine-readable description of the socket API
+//!
+//! `GET /system/api-schema` answers an [`ApiDescription`]: every route of
+//! [`ROUTES`] with the JSON Schema of its request body and response data,
+//! and the definitions those schemas refer to with `$ref`. Clients in other
+//! languages generate their types from it and pin [`API_VERSION`].
+//!
+//! Schemas come from [`ApiSchema`], implemented below for every type that
+//! crosses the socket. `object_schema!` and `enum_schema!` list a type's
+//! fields or variants and also expand to a destructuring or `match`
+//! without `..`, so a field added, removed or retyped without updating its
+//! schema doesn't compile. Serde attributes are invisible to them: a
+//! renamed field is written `field as "wire"`, a non-`Option` field with
+//! `#[serde(default)]` is marked `#[default]`, and a `#[serde(flatten)]`ed
+//! one `#[flatten]`. `Option` fields are never required. Enum variant names
+//! are taken from serde itself.
+
+use std::collections::{BTreeMap, HashMap};
+use std::marker::PhantomData;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+
+use crate::api::{
+    AdoptOrphanRequest, AdoptedOrphan, ApiError, BatchCreateRequest, BatchCreated, BootList, BuildImageRequest, BuildStarted,
+    ContainerAddresses, ContainerInfo, ContainerIpRequest, ContainerListItem, ContainerListWithSummary, CreateContainerRequest,
+    CreateJailRequest, CreatedContainer, DestroyOrphanRequest, ExecRequest, ExecResult, ExecSessionKilled, ExecSessionRequest, ImageHistoryItem, ImageInfo, ImageListItem,
+    JailInfo, JailListItem, JailUpdateRequest, ListContainersRequest, ListJailsRequest, LogsRequest, MessageResult, Method, Mount, NameKind, NamesRequest, OperationStarted, OrphanInfo,
+    PortMapping, PruneImagesRequest, PruneImagesResult, PruneSnapshotsRequest, PruneSnapshotsResult, LogLevelRequest, LogLevelResult, ROUTES, RemoveRequest,
+    RenameRequest, Request, Response, SnapshotGcRequest, StopRequest, SystemConfigInfo, SystemInfo, TaskInfo, UpdateRequest, UpgradeRequest,
+    UpgradeStarted, UploadChunk, UploadRequest, VolumeInfo,
+};
+use crate::boot::{BootOutcome, BootReport, BootResult};
+use crate::bootstrap::{BootstrapConfig, BootstrapProgress, BootstrapStatus};
+use crate::build_jail::BuildNetwork;
+use crate::cmdtrace::{CommandMetricsInfo, CommandRecord, CommandStats};
+use crate::container::{ContainerSummary, StopCause, TmpfsMount, VolumeMode};
+use crate::doctor::{CheckResult, CheckStatus, DoctorReport};
+use crate::exec_sessions::{ExecKind, ExecSession, Termination};
+use crate::image_builder::{BuildStatus, CopyProgress, ImageBuildProgress};
+use crate::jail::{MountPermissions, StopMode};
+use crate::logs::{LogEntry, LogSource};
+use crate::operation::{OperationKind, OperationProgress, Phase};
+use crate::persist_queue::QueueInfo;
+use crate::placement::Placement;
+use crate::snapshot_gc::{Candidate, SnapshotGcReport};
+use crate::system::HostInfo;
+use crate::timestamp::Timestamp;
+use crate::top::ProcessInfo;
+use crate::upgrade::{UpgradeProgress, UpgradeStatus};
+use crate::upload::{UploadPurpose, UploadState, UploadStatus};
+use crate::zfs::{PoolState, PoolStatus};
+
+/// Version of the wire format. Bumped when existing clients would break (a
+/// field removed or retyped, a route dropped), not for additions.
+pub const API_VERSION: u32 = 1;
+
+/// Named schemas, referred to as `#/definitions/<name>`
+pub type Definitions = BTreeMap<String, Value>;
+
+/// Builds a schema, adding the definitions it refers to
+pub type SchemaFn = fn(&mut Definitions) -> Value;
+
+/// A type with a JSON Schema
+pub trait ApiSchema {
+    /// Whether a struct field of this type must be present
+    const REQUIRED: bool = true;
+
+    /// The type's schema; a named type is added to `defs` and referred to
+    fn schema(defs: &mut Definitions) -> Value;
+}
+
+/// [`ApiSchema::schema`] of `T` as a [`SchemaFn`]
+pub fn schema_of<T: ApiSchema>(defs: &mut Definitions) -> Value {
+    T::schema(defs)
+}
+
+/// Either of two bodies, for routes whose answer depends on the request
+pub struct OneOf<A, B>(PhantomData<(A, B)>);
+
+impl<A: ApiSchema, B: ApiSchema> ApiSchema for OneOf<A, B> {
+    fn schema(defs: &mut Definitions) -> Value {
+        json!({ "oneOf": [A::schema(defs), B::schema(defs)] })
+    }
+}
+
+/// Response body of `GET /system/api-schema`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApiDescription {
+    /// See [`API_VERSION`]
+    pub api_version: u32,
+    /// Version of the daemon that described itself
+    pub daemon_version: String,
+    pub endpoints: Vec<EndpointDescription>,
+    /// Schemas referred to with `$ref`, by name; `Request` and `Response`
+    /// describe the envelope every body travels in
+    pub definitions: BTreeMap<String, Value>,
+}
+
+/// One route in an [`ApiDescription`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EndpointDescription {
+    pub method: Method,
+    /// Path with its parameters as placeholders, e.g. `containers/{id}/start`
+    pub path: String,
+    /// Schema of the request body; `None` when the route reads none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request: Option<Value>,
+    /// Schema of the response's `data`, or of each frame's when streaming
+    pub response: Value,
+    pub streaming: bool,
+}
+
+impl ApiDescription {
+    /// Describe every route of [`ROUTES`]
+    pub fn generate() -> Self {
+        let mut definitions = Definitions::new();
+        Request::schema(&mut definitions);
+        Response::schema(&mut definitions);
+
+        let endpoints = ROUTES
+            .iter()
+            .map(|route| EndpointDescription {
+                method: route.method.clone(),
+                path: route.path.to_string(),
+                request: route.request.map(|schema| schema(&mut definitions)),
+                response: (route.response)(&mut definitions),
+                streaming: route.endpoint.is_streaming(),
+            })
+            .collect();
+
+        Self { api_version: API_VERSION, daemon_version: crate::version::CURRENT.to_string(), endpoints, definitions }
+    }
+}
+
+/// A reference to the definition `name`, built by `build` the first time
+fn named(defs: &mut Definitions, name: &str, build: impl FnOnce(&mut Definitions) -> Value) -> Value {
+    if !defs.contains_key(name) {
+        // Claimed before building so a type containing itself refers to itself
+        defs.insert(name.to_string(), Value::Null);
+        let schema = build(defs);
+        defs.insert(name.to_string(), schema);
+    }
+    json!({ "$ref": format!("#/definitions/{}", name) })
+}
+
+/// Properties of an object schema as they are collected
+#[derive(Default)]
+struct ObjectSchema {
+    properties: Map<String, Value>,
+    required: Vec<String>,
+}
+
+impl ObjectSchema {
+    fn field(&mut self, name: &str, schema: Value, required: bool) {
+        self.properties.insert(name.to_string(), schema);
+        if required {
+            self.required.push(name.to_string());
+        }
+    }
+
+    /// Take over the properties of a `#[serde(flatten)]`ed struct
+    fn flatten(&mut self, defs: &Definitions, schema: &Value) {
+        let definition = schema["$ref"]
+            .as_str()
+            .and_then(|reference| reference.strip_prefix("#/definitions/"))
+            .and_then(|name| defs.get(name))
+            .unwrap_or(schema);
+        if let Some(properties) = definition["properties"].as_object() {
+            self.properties.extend(properties.clone());
+        }
+        if let Some(required) = definition["required"].as_array() {
+            self.required.extend(required.iter().filter_map(Value::as_str).map(str::to_string));
+        }
+    }
+
+    fn build(self) -> Value {
+        let mut schema = json!({ "type": "object", "properties": self.properties });
+        if !self.required.is_empty() {
+            schema["required"] = json!(self.required);
+        }
+        schema
+    }
+}
+
+/// Variants of an enum schema as they are collected
+#[derive(Default)]
+struct EnumSchema {
+    names: Vec<Value>,
+    tagged: Vec<Value>,
+}
+
+impl EnumSchema {
+    /// A unit variant, serialized as its name
+    fn unit(&mut self, wire: serde_json::Result<Value>) {
+        self.names.push(wire.expect("a unit variant serializes to its name"));
+    }
+
+    /// A newtype variant, serialized as `{"<name>": payload}`
+    fn newtype(&mut self, wire: serde_json::Result<Value>, payload: Value) {
+        let wire = wire.expect("a newtype variant serializes to an object");
+        let name = wire.as_object().and_then(|object| object.keys().next()).expect("externally tagged variant");
+        self.tagged.push(json!({
+            "type": "object",
+            "properties": { name: payload },
+            "required": [name],
+            "additionalProperties": false,
+        }));
+    }
+
+    fn build(self) -> Value {
+        let names = json!({ "type": "string", "enum": self.names });
+        match (self.names.is_empty(), self.tagged.is_empty()) {
+            (_, true) => names,
+            (true, false) => json!({ "oneOf": self.tagged }),
+            (false, false) => json!({ "oneOf": std::iter::once(names).chain(self.tagged).collect::<Vec<_>>() }),
+        }
+    }
+}
+
+macro_rules! primitive_schema {
+    ($($ty:ty => $schema:tt),* $(,)?) => {$(
+        impl ApiSchema for $ty {
+            fn schema(_: &mut Definitions) -> Value {
+                json!($schema)
+            }
+        }
+    )*};
+}
+
+primitive_schema! {
+    String => { "type": "string" },
+    bool => { "type": "boolean" },
+    u8 => { "type": "integer", "minimum": 0 },
+    u16 => { "type": "integer", "minimum": 0 },
+    u32 => { "type": "integer", "minimum": 0 },
+    u64 => { "type": "integer", "minimum": 0 },
+    usize => { "type": "integer", "minimum": 0 },
+    i32 => { "type": "integer" },
+    i64 => { "type": "integer" },
+    f64 => { "type": "number" },
+    () => { "type": "null" },
+    Value => {},
+    Timestamp => { "type": "string", "format": "date-time" },
+}
+
+impl<T: ApiSchema> ApiSchema for Option<T> {
+    const REQUIRED: bool = false;
+
+    fn schema(defs: &mut Definitions) -> Value {
+        match T::schema(defs) {
+            Value::Object(mut schema) if schema.get("type").is_some_and(Value::is_string) => {
+                let ty = schema["type"].take();
+                schema.insert("type".to_string(), json!([ty, "null"]));
+                Value::Object(schema)
+            }
+            schema => json!({ "anyOf": [schema, { "type": "null" }] }),
+        }
+    }
+}
+
+impl<T: ApiSchema> ApiSchema for Vec<T> {
+    fn schema(defs: &mut Definitions) -> Value {
+        json!({ "type": "array", "items": T::schema(defs) })
+    }
+}
+
+impl<T: ApiSchema> ApiSchema for HashMap<String, T> {
+    fn schema(defs: &mut Definitions) -> Value {
+        json!({ "type": "object", "additionalProperties": T::schema(defs) })
+    }
+}
+
+impl<T: ApiSchema> ApiSchema for BTreeMap<String, T> {
+    fn schema(defs: &mut Definitions) -> Value {
+        HashMap::<String, T>::schema(defs)
+    }
+}
+
+/// `ip`, `ip4` and `ip6`, as `ContainerAddresses` goes over the wire
+impl ApiSchema for ContainerAddresses {
+    fn schema(defs: &mut Definitions) -> Value {
+        named(defs, "ContainerAddresses", |defs| {
+            let mut object = ObjectSchema::default();
+            for name in ["ip", "ip4", "ip6"] {
+                object.field(name, Option::<String>::schema(defs), false);
+            }
+            object.build()
+        })
+    }
+}
+
+macro_rules! wire_name {
+    ($field:ident) => {
+        stringify!($field)
+    };
+    ($field:ident as $wire:literal) => {
+        $wire
+    };
+}
+
+macro_rules! object_field {
+    ($object:ident, $defs:ident, [], $name:expr, $ty:ty) => {
+        $object.field($name, <$ty as ApiSchema>::schema($defs), <$ty as ApiSchema>::REQUIRED)
+    };
+    ($object:ident, $defs:ident, [default], $name:expr, $ty:ty) => {
+        $object.field($name, <$ty as ApiSchema>::schema($defs), false)
+    };
+    ($object:ident, $defs:ident, [flatten], $name:expr, $ty:ty) => {{
+        let schema = <$ty as ApiSchema>::schema($defs);
+        $object.flatten($defs, &schema)
+    }};
+    // `#[serde(skip)]`: not on the wire
+    ($object:ident, $defs:ident, [skip], $name:expr, $ty:ty) => {};
+}
+
+macro_rules! object_schema {
+    ($($ty:ident {
+        $($(#[$marker:ident])? $field:ident $(as $wire:literal)? : $field_ty:ty),* $(,)?
+    })*) => {$(
+        impl ApiSchema for $ty {
+            fn schema(defs: &mut Definitions) -> Value {
+                named(defs, stringify!($ty), |defs| {
+                    let mut object = ObjectSchema::default();
+                    $(object_field!(object, defs, [$($marker)?], wire_name!($field $(as $wire)?), $field_ty);)*
+                    object.build()
+                })
+            }
+        }
+
+        const _: fn(&$ty) = |value| {
+            let $ty { $($field),* } = value;
+            $(let _: &$field_ty = $field;)*
+        };
+    )*};
+}
+
+macro_rules! ignore {
+    ($ty:ty) => {
+        _
+    };
+}
+
+macro_rules! enum_variant {
+    ($variants:ident, $defs:ident, $ty:ident, $variant:ident) => {
+        $variants.unit(serde_json::to_value($ty::$variant))
+    };
+    ($variants:ident, $defs:ident, $ty:ident, $variant:ident ($payload:ty)) => {
+        $variants.newtype(
+            serde_json::to_value($ty::$variant(<$payload>::default())),
+            <$payload as ApiSchema>::schema($defs),
+        )
+    };
+}
+
+macro_rules! enum_schema {
+    ($($ty:ident { $($variant:ident $(($payload:ty))?),* $(,)? })*) => {$(
+        impl ApiSchema for $ty {
+            fn schema(defs: &mut Definitions) -> Value {
+                named(defs, stringify!($ty), |defs| {
+                    let mut variants = EnumSchema::default();
+                    $(enum_variant!(variants, defs, $ty, $variant $(($payload))?);)*
+                    // Only variants with a payload need the definitions
+                    let _ = defs;
+                    variants.build()
+                })
+            }
+        }
+
+        const _: fn(&$ty) = |value| match value {
+            $($ty::$variant $((ignore!($payload)))? => {})*
+        };
+    )*};
+}
+
+// The envelope
+
+object_schema! {
+    Request { method: Method, endpoint: String, #[default] body: Value, #[skip] peer_uid: Option<u32> }
+    Response { status: u16, data: Option<Value>, error: Option<ApiError>, #[default] stream: bool, #[default] end: bool }
+    ApiError { code: String, message: String }
+}
+
+// Jails
+
+object_schema! {
+    CreateJailRequest {
+        name: String,
+        path: Option<String>,
+        ip: Option<String>,
+        bootstrap: Option<BootstrapConfig>,
+        devfs_ruleset: Option<u16>,
+        cleanup_path: Option<bool>,
+        #[default] mount_permissions: MountPermissions,
+        description: Option<String>,
+    }
+    JailUpdateRequest { description: Option<String> }
+    ListJailsRequest { #[default] filter: Vec<String> }
+    JailInfo {
+        name: String,
+        jid: i32,
+        state: String,
+        path: Option<String>,
+        os_version: Option<String>,
+        readonly_reason: Option<String>,
+        #[default] mount_permissions: MountPermissions,
+        description: Option<String>,
+    }
+    JailListItem { name: String, state: String, running: bool, readonly_reason: Option<String> }
+    BootstrapConfig {
+        version: Option<String>,
+        architecture: Option<String>,
+        mirror: Option<String>,
+        #[default] no_cache: bool,
+        config_overrides: Option<HashMap<String, String>>,
+        #[default] sets: Vec<String>,
+    }
+    BootstrapProgress { status: BootstrapStatus, progress: u8, current_step: String, version: String, architecture: String }
+    StopRequest { #[default] stop_mode: StopMode }
+    LogsRequest { #[default] timestamps: bool, tail: Option<usize>, #[default] source: LogSource }
+    LogEntry { timestamp: Option<Timestamp>, stream: String, message: String }
+    UpgradeRequest { to: Option<String> }
+    UpgradeStarted { snapshot: String }
+    UpgradeProgress {
+        status: UpgradeStatus,
+        current_step: String,
+        previous_version: Option<String>,
+        version: Option<String>,
+        snapshot: Option<String>,
+        #[default] output: Vec<String>,
+    }
+}
+
+// Images
+
+object_schema! {
+    BuildImageRequest {
+        name: String,
+        dockerfile: String,
+        #[default] build_args: HashMap<String, String>,
+        #[default] protected: bool,
+        #[default] wait_for_base: bool,
+        #[default] strict_vars: bool,
+        #[default] secrets: HashMap<String, String>,
+        #[default] no_cache: bool,
+        #[default] network: BuildNetwork,
+    }
+    BuildStarted { id: String, name: String, #[default] cached: bool }
+    ImageBuildProgress {
+        image_id: String,
+        step: usize,
+        total_steps: usize,
+        current_instruction: String,
+        status: BuildStatus,
+        copy: Option<CopyProgress>,
+    }
+    CopyProgress { files_copied: u64, files_total: u64, bytes_copied: u64, bytes_total: u64 }
+    ImageInfo {
+        id: String,
+        name: String,
+        parent_id: Option<String>,
+        size_bytes: u64,
+        state: String,
+        created_at: Timestamp,
+        #[default] protected: bool,
+        #[default] kind: String,
+        os_version: Option<String>,
+        digest: Option<String>,
+        readonly_reason: Option<String>,
+        zfs_properties: Option<BTreeMap<String, String>>,
+    }
+    ImageListItem {
+        id: String,
+        name: String,
+        size_bytes: u64,
+        created_at: Timestamp,
+        #[default] protected: bool,
+        #[default] kind: String,
+        readonly_reason: Option<String>,
+    }
+    ImageHistoryItem { id: String, created_at: Timestamp, size_bytes: u64, created_by: String }
+    PruneImagesRequest { #[default] all: bool }
+    PruneImagesResult { removed: Vec<ImageListItem>, reclaimed_bytes: u64 }
+    UpdateRequest { protected: Option<bool>, boot: Option<bool>, read_only: Option<bool>, description: Option<String> }
+    RemoveRequest { #[default] force: bool, #[default] override_protection: bool, #[default] run_async as "async": bool }
+}
+
+// Containers
+
+object_schema! {
+    CreateContainerRequest {
+        image_id: String,
+        name: Option<String>,
+        #[default] ports: Vec<PortMapping>,
+        #[default] volumes: Vec<Mount>,
+        #[default] tmpfs: Vec<TmpfsMount>,
+        #[default] env: HashMap<String, String>,
+        #[default] restart_policy: String,
+        command: Option<Vec<String>>,
+        #[default] protected: bool,
+        devfs_ruleset: Option<u16>,
+        #[default] disable_healthcheck: bool,
+        #[default] boot: bool,
+        #[default] encrypted: bool,
+        #[default] read_only_root: bool,
+        #[default] network_aliases: Vec<String>,
+        hostname: Option<String>,
+        #[default] mount_permissions: MountPermissions,
+        #[default] run_async as "async": bool,
+        #[default] depends_on: Vec<String>,
+        placement: Option<Placement>,
+        #[default] sysctls: HashMap<String, String>,
+        description: Option<String>,
+    }
+    Placement { #[default] constraints: Vec<String>, #[default] preferences: Vec<String> }
+    BatchCreateRequest { containers: Vec<CreateContainerRequest> }
+    PortMapping { host_port: u16, container_port: u16, protocol: String, host_ip: Option<String> }
+    Mount { source: String, destination: String, mount_type: String, #[default] read_only: bool, #[default] delegate: bool }
+    VolumeInfo { source: String, destination: String, mode: VolumeMode, #[default] read_only: bool }
+    TmpfsMount { destination: String, size_bytes: Option<u64>, mode: Option<u32> }
+    ContainerInfo {
+        id: String,
+        name: Option<String>,
+        image_id: String,
+        jail_name: String,
+        state: String,
+        #[flatten] ip: ContainerAddresses,
+        restart_policy: String,
+        created_at: Timestamp,
+        started_at: Option<Timestamp>,
+        stopped_at: Option<Timestamp>,
+        last_stop_cause: Option<StopCause>,
+        last_stop_exec_sessions: Option<usize>,
+        #[default] env: BTreeMap<String, String>,
+        placement: Option<Placement>,
+        #[default] sysctls: BTreeMap<String, String>,
+        #[default] applied_sysctls: BTreeMap<String, String>,
+        description: Option<String>,
+        #[default] protected: bool,
+        devfs_ruleset: Option<u16>,
+        health: Option<String>,
+        #[default] boot: bool,
+        #[default] encrypted: bool,
+        #[default] full_copy: bool,
+        #[default] read_only_root: bool,
+        #[default] tmpfs: Vec<TmpfsMount>,
+        #[default] volumes: Vec<VolumeInfo>,
+        #[default] network_aliases: Vec<String>,
+        hostname: Option<String>,
+        #[default] ip_aliases: Vec<String>,
+        #[default] mount_permissions: MountPermissions,
+        readonly_reason: Option<String>,
+        size_bytes: Option<u64>,
+        zfs_properties: Option<BTreeMap<String, String>>,
+        #[default] dataset_missing: bool,
+    }
+    CreatedContainer {
+        #[flatten] container: ContainerInfo,
+        ports: Vec<PortMapping>,
+        command: Option<Vec<String>>,
+        #[default] warnings: Vec<String>,
+    }
+    BatchCreated { containers: Vec<CreatedContainer> }
+    ContainerListItem {
+        id: String,
+        name: Option<String>,
+        image_id: String,
+        state: String,
+        #[flatten] ip: ContainerAddresses,
+        created_at: Option<Timestamp>,
+        stopped_at: Option<Timestamp>,
+        last_stop_cause: Option<StopCause>,
+        #[default] protected: bool,
+        readonly_reason: Option<String>,
+        #[default] dataset_missing: bool,
+    }
+    ContainerListWithSummary { containers: Vec<ContainerListItem>, summary: ContainerSummary }
+    ContainerSummary {
+        total: usize,
+        running: usize,
+        stopped: usize,
+        created: usize,
+        paused: usize,
+        #[default] locked: usize,
+        unhealthy: usize,
+    }
+    ListContainersRequest { #[default] include_summary: bool, #[default] filter: Vec<String> }
+    NamesRequest { kind: NameKind }
+    RenameRequest { name: String }
+    ContainerIpRequest { ip: String }
+    ExecRequest { command: Vec<String>, #[default] env: HashMap<String, String>, workdir: Option<String> }
+    ExecResult { exit_code: i32, stdout: String, stderr: String }
+    ExecSession {
+        id: String,
+        container_id: String,
+        container: String,
+        command: Vec<String>,
+        kind: ExecKind,
+        user: Option<String>,
+        peer_uid: Option<u32>,
+        started_at: Timestamp,
+        pid: Option<u32>,
+    }
+    ExecSessionRequest { container: String, command: Vec<String>, kind: ExecKind, pid: u32 }
+    ExecSessionKilled { session: ExecSession, termination: Termination }
+    ProcessInfo {
+        pid: u32,
+        user: String,
+        cpu_percent: f64,
+        mem_percent: f64,
+        rss_kb: u64,
+        elapsed: String,
+        command: String,
+    }
+    OperationStarted { id: String, container_id: String }
+    OperationProgress {
+        id: String,
+        kind: OperationKind,
+        container_id: String,
+        name: String,
+        phase: Phase,
+        progress: u8,
+        current_step: String,
+        bytes_total: Option<u64>,
+        bytes_remaining: Option<u64>,
+        error: Option<String>,
+    }
+}
+
+// System
+
+object_schema! {
+    SystemConfigInfo {
+        config: Value,
+        path: Option<String>,
+        loaded_mtime: Option<Timestamp>,
+        drift: bool,
+        #[default] drifted_fields: Vec<String>,
+        file_error: Option<String>,
+    }
+    SystemInfo {
+        version: String,
+        #[default] host: HostInfo,
+        pool: Option<PoolStatus>,
+        pool_error: Option<String>,
+        #[default] pool_writes_refused: bool,
+        #[default] jails: BTreeMap<String, usize>,
+        #[default] images: BTreeMap<String, usize>,
+        #[default] containers: ContainerSummary,
+        #[default] uptime_secs: u64,
+        #[default] slowest_commands: Vec<CommandRecord>,
+        #[default] pending_writes: QueueInfo,
+        #[default] node_labels: BTreeMap<String, String>,
+    }
+    HostInfo {
+        hostname: Option<String>,
+        os_version: Option<String>,
+        kernel: Option<String>,
+        arch: Option<String>,
+        cpu_model: Option<String>,
+        cpus: Option<u32>,
+        memory_bytes: Option<u64>,
+    }
+    PoolStatus { name: String, state: PoolState, free_bytes: u64, size_bytes: u64, status: Option<String> }
+    QueueInfo { pending: usize, oldest_secs: Option<u64>, #[default] escalated: usize }
+    DoctorReport { checks: Vec<CheckResult> }
+    CheckResult { name: String, status: CheckStatus, detail: String, hint: Option<String> }
+    BootList { containers: Vec<ContainerListItem>, last_boot: Option<BootReport> }
+    BootReport { finished_at: Timestamp, results: Vec<BootResult> }
+    BootResult { id: String, name: Option<String>, outcome: BootOutcome, error: Option<String> }
+    TaskInfo { kind: String, id: String, name: String, position: Option<usize> }
+    CommandMetricsInfo {
+        commands: BTreeMap<String, CommandStats>,
+        slowest_recent: Vec<CommandRecord>,
+        slow_threshold_ms: u64,
+    }
+    CommandStats { count: u64, total_ms: u64, failures: u64 }
+    CommandRecord {
+        program: String,
+        args: Vec<String>,
+        resource: Option<String>,
+        duration_ms: u64,
+        success: bool,
+        finished_at: Timestamp,
+    }
+    OrphanInfo { dataset: String, kind: String, size_bytes: u64, created_at: Timestamp, snapshot: Option<String> }
+    AdoptOrphanRequest { dataset: String, name: Option<String>, image: Option<String> }
+    AdoptedOrphan { id: String, dataset: String }
+    DestroyOrphanRequest { dataset: String }
+    PruneSnapshotsRequest { dataset: String, keep_last: usize }
+    PruneSnapshotsResult { dataset: String, destroyed: Vec<String> }
+    SnapshotGcRequest { #[default] dry_run: bool }
+    Candidate { name: String, used_bytes: u64, created_at: Timestamp }
+    SnapshotGcReport { dry_run: bool, candidates: Vec<Candidate>, destroyed: usize, reclaimed_bytes: u64, #[default] errors: Vec<String> }
+    LogLevelRequest { level: String }
+    LogLevelResult { level: String, previous: String }
+    UploadRequest { purpose: UploadPurpose, size: u64, sha256: String }
+    UploadChunk { offset: u64, data: String }
+    UploadStatus { id: String, purpose: UploadPurpose, size: u64, received: u64, sha256: String, state: UploadState }
+    MessageResult { message: String }
+    ApiDescription {
+        api_version: u32,
+        daemon_version: String,
+        endpoints: Vec<EndpointDescription>,
+        definitions: BTreeMap<String, Value>,
+    }
+    EndpointDescription { method: Method, path: String, request: Option<Value>, response: Value, streaming: bool }
+}
+
+enum_schema! {
+    Method { Post, Get, Delete }
+    StopMode { Remove, Freeze }
+    LogSource { Stdio, Syslog, All }
+    NameKind { Containers, Running, Stopped, Images }
+    StopCause { UserRequest(Option<u32>), ProcessExit, RestartPolicyGaveUp, DaemonShutdown, HealthcheckKill, Oom }
+    MountPermissions { None, Nullfs, Tmpfs, Zfs, All }
+    VolumeMode { Nullfs, Anonymous, Attached, Delegated }
+    BuildNetwork { Default, None }
+    BuildStatus { Queued, Building, Failed, Complete }
+    BootstrapStatus { Queued, Initializing, Downloading, Verifying, Extracting, Configuring, Complete, Failed(String) }
+    UpgradeStatus { Snapshotting, Updating, Verifying, Complete, RolledBack(String), Failed(String) }
+    OperationKind { Create, Remove }
+    ExecKind { Api, Pty, Interactive }
+    Termination { Terminated, Killed, Gone }
+    Phase { Pending, Cloning, Mounting, Volumes, Configuring, Stopping, Unmounting, Destroying, Recording, Complete, Failed }
+    CheckStatus { Ok, Warn, Fail, Skipped }
+    BootOutcome { Started, AlreadyRunning, Failed }
+    PoolState { Online, Degraded, Faulted, Offline, Removed, Unavail, Suspended, Unknown }
+    UploadPurpose { BuildContext, ImageLoad, Copy }
+    UploadState { Open, Committed }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::Endpoint;
+    use serde::de::{self, Deserializer, Visitor};
+
+    /// Names of a serde enum's variants, as `#[derive(Deserialize)]` lists them
+    fn variant_names<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+        #[derive(Debug)]
+        struct Found(Option<&'static [&'static str]>);
+
+        impl std::fmt::Display for Found {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str("not an enum")
+            }
+        }
+
+        impl std::error::Error for Found {}
+
+        impl de::Error for Found {
+            fn custom<M: std::fmt::Display>(_: M) -> Self {
+                Found(None)
+            }
+        }
+
+        struct Probe;
+
+        impl<'de> Deserializer<'de> for Probe {
+            type Error = Found;
+
+            fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Found> {
+                Err(Found(None))
+            }
+
+            fn deserialize_enum<V: Visitor<'de>>(
+                self,
+                _: &'static str,
+                variants: &'static [&'static str],
+                _: V,
+            ) -> Result<V::Value, Found> {
+                Err(Found(Some(variants)))
+            }
+
+            serde::forward_to_deserialize_any! {
+                bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+                bytes byte_buf option unit unit_struct newtype_struct seq tuple
+                tuple_struct map struct identifier ignored_any
+            }
+        }
+
+        match T::deserialize(Probe) {
+            Err(Found(Some(variants))) => variants,
+            _ => panic!("not a serde enum"),
+        }
+    }
+
+    /// The serde name of an endpoint's variant
+    fn variant_of(endpoint: &Endpoint) -> String {
+        match serde_json::to_value(endpoint).unwrap() {
+            Value::String(name) => name,
+            Value::Object(object) => object.keys().next().unwrap().clone(),
+            other => panic!("unexpected endpoint encoding {}", other),
+        }
+    }
+
+    #[test]
+    fn test_routes_cover_every_endpoint() {
+        let mut methods: BTreeMap<String, Vec<Method>> = BTreeMap::new();
+        for route in ROUTES {
+            let methods = methods.entry(variant_of(&route.endpoint)).or_default();
+            assert!(!methods.contains(&route.method), "{:?} {} is listed twice", route.method, route.path);
+            methods.push(route.method.clone());
+        }
+
+        // A new endpoint must be described before it ships
+        for variant in variant_names::<Endpoint>() {
+            assert!(methods.contains_key(*variant), "Endpoint variant '{}' has no row in api::ROUTES", variant);
+        }
+        assert_eq!(methods.len(), variant_names::<Endpoint>().len());
+    }
+
+    #[test]
+    fn test_route_paths_parse_to_their_endpoint() {
+        for route in ROUTES {
+            // Every `{placeholder}` filled in with a value
+            let path: String = route
+                .path
+                .split('/')
+                .map(|segment| if segment.starts_with('{') && segment.ends_with('}') { "x" } else { segment })
+                .collect::<Vec<_>>()
+                .join("/");
+            let request = Request { method: route.method.clone(), endpoint: path.clone(), body: Value::Null, peer_uid: None };
+            let endpoint = request.parse_endpoint().unwrap_or_else(|e| panic!("{}: {}", route.path, e));
+
+            assert_eq!(std::mem::discriminant(&endpoint), std::mem::discriminant(&route.endpoint), "{}", route.path);
+            assert_eq!(endpoint.path(), path, "{}", route.path);
+        }
+    }
+
+    #[test]
+    fn test_description_is_self_contained() {
+        let description = ApiDescription::generate();
+        assert_eq!(description.api_version, API_VERSION);
+        assert_eq!(description.endpoints.len(), ROUTES.len());
+
+        // Every reference resolves to a finished definition
+        fn references(value: &Value, found: &mut Vec<String>) {
+            match value {
+                Value::Object(object) => {
+                    if let Some(Value::String(reference)) = object.get("$ref") {
+                        found.push(reference.trim_start_matches("#/definitions/").to_string());
+                    }
+                    object.values().for_each(|value| references(value, found));
+                }
+                Value::Array(values) => values.iter().for_each(|value| references(value, found)),
+                _ => {}
+            }
+        }
+        let mut found = Vec::new();
+        references(&serde_json::to_value(&description).unwrap(), &mut found);
+        assert!(!found.is_empty());
+        for name in found {
+            let definition = description.definitions.get(&name).unwrap_or_else(|| panic!("no definition {}", name));
+            assert!(definition.is_object(), "{} is unfinished", name);
+        }
+
+        let logs = description.endpoints.iter().find(|e| e.path == "containers/{id}/logs/follow").unwrap();
+        assert!(logs.streaming);
+        assert_eq!(logs.method, Method::Get);
+        assert_eq!(logs.response, json!({ "$ref": "#/definitions/LogEntry" }));
+        assert!(description.definitions.contains_key("Response"));
+    }
+
+    #[test]
+    fn test_struct_snapshots() {
+        let mut defs = Definitions::new();
+        assert_eq!(RemoveRequest::schema(&mut defs), json!({ "$ref": "#/definitions/RemoveRequest" }));
+        assert_eq!(
+            defs["RemoveRequest"],
+            json!({
+                "type": "object",
+                "properties": {
+                    "force": { "type": "boolean" },
+                    "override_protection": { "type": "boolean" },
+                    "async": { "type": "boolean" },
+                },
+            })
+        );
+
+        CreateJailRequest::schema(&mut defs);
+        assert_eq!(
+            defs["CreateJailRequest"],
+            json!({
+                "type": "object",
+                "properties": {
+                    "name": { "type": "string" },
+                    "path": { "type": ["string", "null"] },
+                    "ip": { "type": ["string", "null"] },
+                    "bootstrap": { "anyOf": [{ "$ref": "#/definitions/BootstrapConfig" }, { "type": "null" }] },
+                    "devfs_ruleset": { "type": ["integer", "null"], "minimum": 0 },
+                    "cleanup_path": { "type": ["boolean", "null"] },
+                    "mount_permissions": { "$ref": "#/definitions/MountPermissions" },
+                    "description": { "type": ["string", "null"] },
+                },
+                "required": ["name"],
+            })
+        );
+        assert_eq!(
+            defs["MountPermissions"],
+            json!({ "type": "string", "enum": ["none", "nullfs", "tmpfs", "zfs", "all"] })
+        );
+
+        // Flattened addresses sit next to the item's own fields
+        ContainerListItem::schema(&mut defs);
+        assert_eq!(
+            defs["ContainerListItem"],
+            json!({
+                "type": "object",
+                "properties": {
+                    "id": { "type": "string" },
+                    "name": { "type": ["string", "null"] },
+                    "image_id": { "type": "string" },
+                    "state": { "type": "string" },
+                    "ip": { "type": ["string", "null"] },
+                    "ip4": { "type": ["string", "null"] },
+                    "ip6": { "type": ["string", "null"] },
+                    "created_at": { "type": ["string", "null"], "format": "date-time" },
+                    "stopped_at": { "type": ["string", "null"], "format": "date-time" },
+                    "last_stop_cause": { "anyOf": [{ "$ref": "#/definitions/StopCause" }, { "type": "null" }] },
+                    "protected": { "type": "boolean" },
+                    "readonly_reason": { "type": ["string", "null"] },
+                    "dataset_missing": { "type": "boolean" },
+                },
+                "required": ["id", "image_id", "state"],
+            })
+        );
+
+        TaskInfo::schema(&mut defs);
+        assert_eq!(
+            defs["TaskInfo"],
+            json!({
+                "type": "object",
+                "properties": {
+                    "kind": { "type": "string" },
+                    "id": { "type": "string" },
+                    "name": { "type": "string" },
+                    "position": { "type": ["integer", "null"], "minimum": 0 },
+                },
+                "required": ["kind", "id", "name"],
+            })
+        );
+    }
+
+    #[test]
+    fn test_enum_snapshots() {
+        let mut defs = Definitions::new();
+        BootstrapStatus::schema(&mut defs);
+        assert_eq!(
+            defs["BootstrapStatus"],
+            json!({
+                "oneOf": [
+                    {
+                        "type": "string",
+                        "enum": ["queued", "initializing", "downloading", "verifying", "extracting", "configuring", "complete"],
+                    },
+                    {
+                        "type": "object",
+                        "properties": { "failed": { "type": "string" } },
+                        "required": ["failed"],
+                        "additionalProperties": false,
+                    },
+                ],
+            })
+        );
+
+        // Names come from serde's renaming, whatever the case
+        BootOutcome::schema(&mut defs);
+        PoolState::schema(&mut defs);
+        assert_eq!(defs["BootOutcome"]["enum"], json!(["started", "already_running", "failed"]));
+        assert_eq!(defs["PoolState"]["enum"][7], "UNKNOWN");
+    }
+}
@@ -0,0 +1,381 @@
+//! Image artifact manifests: digests and signatures
+//!
+//! An image leaving the host as a file or registry artifact carries a
+//! manifest with the sha256 digests of its config and of its filesystem
+//! tarball. Before such an artifact is registered as an image, [`verify`]
+//! checks that both parts match their digests and then applies the
+//! `verify_signatures` policy to the manifest's signatures:
+//!
+//! - each signature is Ed25519 over the manifest digest, which is the sha256
+//!   of the manifest's canonical JSON without the `signatures` section
+//!   ([`ArtifactManifest::digest`]);
+//! - a signature counts only if its `key_id` names a key in `trusted_keys`;
+//! - `off` skips signatures, `warn` reports what's wrong, `enforce` refuses
+//!   artifacts without a valid signature from a trusted key.
+//!
+//! Digest mismatches are refused under every policy. The verified manifest
+//! digest is what gets recorded as the image's `digest`.
+
+use std::io::Read;
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::config::{SignaturePolicy, TrustedKey};
+
+/// Reasons an artifact is refused
+#[derive(Debug, thiserror::Error)]
+pub enum ArtifactError {
+    #[error("Failed to read the artifact: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid manifest: {0}")]
+    InvalidManifest(String),
+    #[error("The {part} digest doesn't match the manifest: expected {expected}, got {actual}")]
+    DigestMismatch { part: &'static str, expected: String, actual: String },
+    #[error("The artifact is unsigned and signatures are enforced")]
+    Unsigned,
+    #[error("The artifact is signed with key '{0}', which is not a trusted key")]
+    UntrustedKey(String),
+    #[error("The signature of key '{0}' doesn't verify")]
+    BadSignature(String),
+    #[error("Invalid Ed25519 public key for '{key_id}': {reason}")]
+    InvalidKey { key_id: String, reason: String },
+}
+
+/// Manifest of an exported image
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ArtifactManifest {
+    /// Image name at export
+    pub name: String,
+    /// `sha256:<hex>` of the serialized image config
+    pub config_digest: String,
+    /// `sha256:<hex>` of the filesystem tarball
+    pub rootfs_digest: String,
+    /// Detached signatures over [`ArtifactManifest::digest`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub signatures: Vec<ManifestSignature>,
+}
+
+/// An Ed25519 signature of a manifest
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ManifestSignature {
+    /// ID of the signing key, as listed in `trusted_keys`
+    pub key_id: String,
+    /// Hex-encoded 64-byte signature
+    pub signature: String,
+}
+
+impl ArtifactManifest {
+    /// Manifest of an image's config and filesystem tarball, for export
+    pub fn new(name: &str, config: &[u8], rootfs: impl Read) -> std::io::Result<Self> {
+        Ok(Self {
+            name: name.to_string(),
+            config_digest: sha256(config),
+            rootfs_digest: sha256_reader(rootfs)?,
+            signatures: Vec::new(),
+        })
+    }
+
+    /// Digest the signatures cover: the canonical JSON of everything but
+    /// the signatures themselves
+    pub fn digest(&self) -> String {
+        let mut value = serde_json::to_value(self).expect("manifest serializes");
+        if let Value::Object(map) = &mut value {
+            map.remove("signatures");
+        }
+        sha256(canonical_json(&value).as_bytes())
+    }
+
+    /// Add a signature with `key`
+    pub fn sign(&mut self, key_id: &str, key: &SigningKey) {
+        let signature = key.sign(self.digest().as_bytes());
+        self.signatures.retain(|s| s.key_id != key_id);
+        self.signatures.push(ManifestSignature {
+            key_id: key_id.to_string(),
+            signature: hex::encode(signature.to_bytes()),
+        });
+    }
+}
+
+/// A verified artifact
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verified {
+    /// Manifest digest, to record on the image
+    pub digest: String,
+    /// Trusted key whose signature verified, if any
+    pub signer: Option<String>,
+    /// Policy violations let through under `warn`
+    pub warnings: Vec<String>,
+}
+
+/// Check an artifact's parts against its manifest, then its signatures
+/// against `policy`
+pub fn verify(
+    manifest: &ArtifactManifest,
+    config: &[u8],
+    rootfs: impl Read,
+    trusted_keys: &[TrustedKey],
+    policy: SignaturePolicy,
+) -> Result<Verified, ArtifactError> {
+    verify_digests(manifest, config, rootfs)?;
+    check_signatures(manifest, trusted_keys, policy)
+}
+
+/// Compare the config and tarball digests with the manifest's
+pub fn verify_digests(manifest: &ArtifactManifest, config: &[u8], rootfs: impl Read) -> Result<(), ArtifactError> {
+    for (part, expected) in [("config", &manifest.config_digest), ("rootfs", &manifest.rootfs_digest)] {
+        if !is_sha256(expected) {
+            return Err(ArtifactError::InvalidManifest(format!("{} digest '{}' is not sha256:<hex>", part, expected)));
+        }
+    }
+
+    let actual = sha256(config);
+    if actual != manifest.config_digest {
+        return Err(ArtifactError::DigestMismatch { part: "config", expected: manifest.config_digest.clone(), actual });
+    }
+    let actual = sha256_reader(rootfs)?;
+    if actual != manifest.rootfs_digest {
+        return Err(ArtifactError::DigestMismatch { part: "rootfs", expected: manifest.rootfs_digest.clone(), actual });
+    }
+    Ok(())
+}
+
+/// Apply the signature policy to a manifest whose digests already verified
+pub fn check_signatures(
+    manifest: &ArtifactManifest,
+    trusted_keys: &[TrustedKey],
+    policy: SignaturePolicy,
+) -> Result<Verified, ArtifactError> {
+    let digest = manifest.digest();
+    let mut verified = Verified { digest: digest.clone(), signer: None, warnings: Vec::new() };
+    if policy == SignaturePolicy::Off {
+        return Ok(verified);
+    }
+
+    // The first problem is what gets reported if no signature is good
+    let mut problem = None;
+    for signature in &manifest.signatures {
+        let Some(key) = trusted_keys.iter().find(|key| key.id == signature.key_id) else {
+            problem.get_or_insert(ArtifactError::UntrustedKey(signature.key_id.clone()));
+            continue;
+        };
+        match verify_signature(&digest, signature, key) {
+            Ok(()) => {
+                verified.signer = Some(key.id.clone());
+                return Ok(verified);
+            }
+            Err(e) => {
+                problem.get_or_insert(e);
+            }
+        }
+    }
+
+    let problem = problem.unwrap_or(ArtifactError::Unsigned);
+    match policy {
+        SignaturePolicy::Enforce => Err(problem),
+        _ => {
+            verified.warnings.push(problem.to_string());
+            Ok(verified)
+        }
+    }
+}
+
+fn verify_signature(digest: &str, signature: &ManifestSignature, key: &TrustedKey) -> Result<(), ArtifactError> {
+    let public_key = parse_public_key(key)?;
+    let bad = || ArtifactError::BadSignature(key.id.clone());
+    let bytes: [u8; 64] = hex::decode(&signature.signature)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(bad)?;
+    public_key
+        .verify(digest.as_bytes(), &Signature::from_bytes(&bytes))
+        .map_err(|_| bad())
+}
+
+/// Decode a trusted key's hex-encoded 32-byte public key
+pub fn parse_public_key(key: &TrustedKey) -> Result<VerifyingKey, ArtifactError> {
+    let invalid = |reason: String| ArtifactError::InvalidKey { key_id: key.id.clone(), reason };
+    let bytes: [u8; 32] = hex::decode(&key.public_key)
+        .map_err(|e| invalid(e.to_string()))?
+        .try_into()
+        .map_err(|bytes: Vec<u8>| invalid(format!("expected 32 bytes, got {}", bytes.len())))?;
+    VerifyingKey::from_bytes(&bytes).map_err(|e| invalid(e.to_string()))
+}
+
+/// Serialize with object keys sorted and no whitespace, so the same
+/// manifest always hashes the same
+pub fn canonical_json(value: &Value) -> String {
+    match value {
+        Value::Object(map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            let fields: Vec<String> = entries
+                .into_iter()
+                .map(|(key, value)| format!("{}:{}", Value::String(key.clone()), canonical_json(value)))
+                .collect();
+            format!("{{{}}}", fields.join(","))
+        }
+        Value::Array(items) => format!("[{}]", items.iter().map(canonical_json).collect::<Vec<_>>().join(",")),
+        other => other.to_string(),
+    }
+}
+
+/// `sha256:<hex>` of `data`
+pub fn sha256(data: &[u8]) -> String {
+    format!("sha256:{}", hex::encode(Sha256::digest(data)))
+}
+
+/// `sha256:<hex>` of everything `reader` yields
+pub fn sha256_reader(mut reader: impl Read) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(format!("sha256:{}", hex::encode(hasher.finalize())))
+}
+
+fn is_sha256(digest: &str) -> bool {
+    digest
+        .strip_prefix("sha256:")
+        .is_some_and(|hex| hex.len() == 64 && hex.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &[u8] = br#"{"cmd":["nginx"],"env":{"A":"1"}}"#;
+    const ROOTFS: &[u8] = b"fake tarball contents";
+
+    /// Fixture key pair; the seed is fixed so the tests are repeatable
+    fn fixture_key(seed: u8) -> (SigningKey, TrustedKey) {
+        let signing = SigningKey::from_bytes(&[seed; 32]);
+        let trusted = TrustedKey {
+            id: format!("ci-{}", seed),
+            public_key: hex::encode(signing.verifying_key().to_bytes()),
+        };
+        (signing, trusted)
+    }
+
+    fn signed_manifest() -> (ArtifactManifest, TrustedKey) {
+        let (signing, trusted) = fixture_key(1);
+        let mut manifest = ArtifactManifest::new("web", CONFIG, ROOTFS).unwrap();
+        manifest.sign(&trusted.id, &signing);
+        (manifest, trusted)
+    }
+
+    #[test]
+    fn test_canonical_json_is_stable() {
+        let a: Value = serde_json::from_str(r#"{"b": 1, "a": {"y": [1, {"d": 2, "c": 3}], "x": "s"}}"#).unwrap();
+        let b: Value = serde_json::from_str(r#"{"a":{"x":"s","y":[1,{"c":3,"d":2}]},"b":1}"#).unwrap();
+        assert_eq!(canonical_json(&a), r#"{"a":{"x":"s","y":[1,{"c":3,"d":2}]},"b":1}"#);
+        assert_eq!(canonical_json(&a), canonical_json(&b));
+    }
+
+    #[test]
+    fn test_manifest_digests_and_signature_scope() {
+        let (manifest, _) = signed_manifest();
+        assert_eq!(manifest.config_digest, sha256(CONFIG));
+        assert_eq!(manifest.rootfs_digest, sha256_reader(ROOTFS).unwrap());
+
+        // Signatures aren't part of what they sign
+        let mut unsigned = manifest.clone();
+        unsigned.signatures.clear();
+        assert_eq!(unsigned.digest(), manifest.digest());
+
+        // A round trip through JSON keeps the digest
+        let reparsed: ArtifactManifest = serde_json::from_str(&serde_json::to_string_pretty(&manifest).unwrap()).unwrap();
+        assert_eq!(reparsed.digest(), manifest.digest());
+    }
+
+    #[test]
+    fn test_verify_signed_artifact() {
+        let (manifest, trusted) = signed_manifest();
+        let verified = verify(&manifest, CONFIG, ROOTFS, &[trusted], SignaturePolicy::Enforce).unwrap();
+        assert_eq!(verified.digest, manifest.digest());
+        assert_eq!(verified.signer.as_deref(), Some("ci-1"));
+        assert!(verified.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_tampered_parts_refused_under_every_policy() {
+        let (manifest, trusted) = signed_manifest();
+        for policy in [SignaturePolicy::Off, SignaturePolicy::Warn, SignaturePolicy::Enforce] {
+            let err = verify(&manifest, b"{}", ROOTFS, std::slice::from_ref(&trusted), policy).unwrap_err();
+            assert!(matches!(err, ArtifactError::DigestMismatch { part: "config", .. }), "{}", err);
+            let err = verify(&manifest, CONFIG, &b"fake tarball content!"[..], std::slice::from_ref(&trusted), policy)
+                .unwrap_err();
+            assert!(matches!(err, ArtifactError::DigestMismatch { part: "rootfs", .. }), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_tampered_manifest_breaks_signature() {
+        let (mut manifest, trusted) = signed_manifest();
+        manifest.name = "evil".to_string();
+
+        let err = check_signatures(&manifest, std::slice::from_ref(&trusted), SignaturePolicy::Enforce).unwrap_err();
+        assert!(matches!(&err, ArtifactError::BadSignature(id) if id == "ci-1"), "{}", err);
+
+        let verified = check_signatures(&manifest, &[trusted], SignaturePolicy::Warn).unwrap();
+        assert_eq!(verified.signer, None);
+        assert_eq!(verified.warnings, ["The signature of key 'ci-1' doesn't verify"]);
+    }
+
+    #[test]
+    fn test_policy_on_unsigned_and_untrusted() {
+        let (signed, _) = signed_manifest();
+        let (_, other) = fixture_key(2);
+        let unsigned = ArtifactManifest::new("web", CONFIG, ROOTFS).unwrap();
+
+        assert!(matches!(
+            check_signatures(&unsigned, std::slice::from_ref(&other), SignaturePolicy::Enforce),
+            Err(ArtifactError::Unsigned)
+        ));
+        let err = check_signatures(&signed, std::slice::from_ref(&other), SignaturePolicy::Enforce).unwrap_err();
+        assert!(err.to_string().contains("'ci-1'"), "{}", err);
+
+        // A trusted key ID with someone else's public key
+        let impostor = TrustedKey { id: "ci-1".to_string(), public_key: other.public_key.clone() };
+        assert!(matches!(
+            check_signatures(&signed, &[impostor], SignaturePolicy::Enforce),
+            Err(ArtifactError::BadSignature(_))
+        ));
+
+        let verified = check_signatures(&unsigned, &[], SignaturePolicy::Warn).unwrap();
+        assert_eq!(verified.warnings.len(), 1);
+        let verified = check_signatures(&signed, &[], SignaturePolicy::Off).unwrap();
+        assert!(verified.warnings.is_empty() && verified.signer.is_none());
+        assert_eq!(verified.digest, signed.digest());
+    }
+
+    #[test]
+    fn test_any_trusted_signature_is_enough() {
+        let (mut manifest, trusted) = signed_manifest();
+        let (second, second_trusted) = fixture_key(3);
+        manifest.sign(&second_trusted.id, &second);
+        manifest.signatures.insert(0, ManifestSignature { key_id: "unknown".to_string(), signature: "00".to_string() });
+
+        let verified = check_signatures(&manifest, &[second_trusted], SignaturePolicy::Enforce).unwrap();
+        assert_eq!(verified.signer.as_deref(), Some("ci-3"));
+        assert!(check_signatures(&manifest, &[trusted], SignaturePolicy::Enforce).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_manifest_and_keys() {
+        let (mut manifest, trusted) = signed_manifest();
+        manifest.rootfs_digest = "md5:abc".to_string();
+        assert!(matches!(verify_digests(&manifest, CONFIG, ROOTFS), Err(ArtifactError::InvalidManifest(_))));
+
+        let short = TrustedKey { id: "short".to_string(), public_key: "abcd".to_string() };
+        assert!(matches!(parse_public_key(&short), Err(ArtifactError::InvalidKey { .. })));
+        assert!(parse_public_key(&trusted).is_ok());
+    }
+}
@@ -2,23 +2,31 @@
 //!
 //! This is the main entry point for running the Kawakaze jail manager backend.
 
-use kawakaze_backend::{JailManager, config::KawakazeConfig};
+use kawakaze_backend::{JailManager, config::{ConfigSource, KawakazeConfig}};
 use std::sync::Arc;
 use tokio::sync::Mutex;
-use tracing_subscriber::{EnvFilter, fmt, prelude::*};
+use kawakaze_backend::log_level::{self, LogLevel};
+use tracing_subscriber::{fmt, prelude::*};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Initialize tracing subscriber
-    // Set RUST_LOG environment variable to control logging (e.g., RUST_LOG=debug)
-    let env_filter = EnvFilter::from_default_env()
-        .add_directive(tracing::Level::INFO.into());
+    // RUST_LOG, if set, wins over the config's log_level for this run. The
+    // filter sits behind a reload layer so POST /system/log-level can
+    // change it; until the config is loaded it is RUST_LOG or info.
+    let rust_log = std::env::var("RUST_LOG").ok().filter(|level| log_level::parse(level).is_ok());
+    let startup_level = rust_log.clone().unwrap_or_else(|| log_level::DEFAULT_LEVEL.to_string());
+    let (filter, filter_handle) = log_level::layer(&startup_level)?;
 
     tracing_subscriber::registry()
-        .with(env_filter)
+        .with(filter)
         .with(fmt::layer().with_writer(std::io::stderr))
         .init();
 
+    // Launched from rc.d: start the boot containers before serving
+    let boot = std::env::args().skip(1).any(|arg| arg == "--boot");
+    // After a rollback: take over records the newer version wrote
+    let force_downgrade_writes = std::env::args().skip(1).any(|arg| arg == "--force-downgrade-writes");
+
     tracing::info!("Kawakaze Backend - FreeBSD Jail Manager");
     tracing::info!("=======================================");
 
@@ -29,23 +37,38 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
 
     // Load configuration from default locations, or use defaults
-    let config = match KawakazeConfig::load_defaults() {
-        Ok(cfg) => {
+    let (config, config_source) = match KawakazeConfig::load_defaults_with_source() {
+        Ok((cfg, source)) => {
             tracing::info!("Loaded configuration");
+            if let Some(path) = &source.path {
+                tracing::info!("Config file: {}", path.display());
+            }
             tracing::info!("ZFS pool: {}", cfg.zfs_pool);
-            tracing::info!("Database: {}", cfg.storage.database_path);
-            cfg
+            tracing::info!("Data root: {}", cfg.data_root);
+            tracing::info!("Database: {}", kawakaze_backend::paths::Paths::from_config(&cfg).database.display());
+            (cfg, source)
         }
         Err(e) => {
             tracing::warn!("Failed to load configuration ({}), using defaults", e);
-            KawakazeConfig::default()
+            (KawakazeConfig::default(), ConfigSource::default())
         }
     };
 
+    let mut level = LogLevel::new(filter_handle, &startup_level);
+    if rust_log.is_none()
+        && config.log_level.trim() != startup_level
+        && let Err(e) = level.set(&config.log_level)
+    {
+        tracing::warn!("Keeping log level {}: {}", startup_level, e);
+    }
+
     // Create jail manager with configuration (includes ZFS initialization)
     let manager = match JailManager::with_config(config) {
-        Ok(m) => {
+        Ok(mut m) => {
             tracing::info!("JailManager initialized with ZFS support");
+            m.set_config_source(config_source);
+            m.set_log_level_handle(level);
+            m.set_force_downgrade_writes(force_downgrade_writes);
             Arc::new(Mutex::new(m))
         }
         Err(e) => {
@@ -61,11 +84,55 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     manager.lock().await.start().await?;
 
     // Create and run the socket server
-    let socket_path = Arc::new("/var/run/kawakaze.sock".to_string());
-    let server = kawakaze_backend::server::SocketServer::new(socket_path, manager);
+    let socket_path = Arc::new(manager.lock().await.socket_path().display().to_string());
+    let server = kawakaze_backend::server::SocketServer::new(socket_path, manager.clone());
+    server.spawn_write_retries();
+    server.spawn_upload_sweeper();
+    server.spawn_pool_checks();
+    server.spawn_exec_session_sweeper();
+    server.spawn_snapshot_gc().await;
+
+    let http = manager.lock().await.config().http.clone();
+    if let Some(listen) = http.listen {
+        #[cfg(feature = "http-gateway")]
+        {
+            let listener = tokio::net::TcpListener::bind(&listen).await?;
+            tokio::spawn(kawakaze_backend::http_gateway::serve(listener, manager.clone()));
+        }
+        #[cfg(not(feature = "http-gateway"))]
+        tracing::warn!("Not serving http.listen {}: built without the http-gateway feature", listen);
+    }
+
+    let serve = async {
+        if boot {
+            kawakaze_backend::boot::boot_then_serve(&manager, &kawakaze_backend::boot::ManagerStarter, &server).await
+        } else {
+            tracing::info!("Starting Kawakaze API server...");
+            server.run().await
+        }
+    };
 
-    tracing::info!("Starting Kawakaze API server...");
-    server.run().await?;
+    // On SIGINT/SIGTERM, end open streams with their terminal frame
+    tokio::select! {
+        result = serve => result?,
+        _ = shutdown_signal() => server.shutdown().await,
+    }
 
     Ok(())
 }
+
+/// Resolves on SIGINT or SIGTERM
+async fn shutdown_signal() {
+    let mut terminate = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+        Ok(signal) => signal,
+        Err(e) => {
+            tracing::warn!("Failed to listen for SIGTERM: {}", e);
+            let _ = tokio::signal::ctrl_c().await;
+            return;
+        }
+    };
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+}
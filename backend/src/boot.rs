@@ -0,0 +1,329 @@
+//! Starting containers at boot (`kawakaze-backend --boot`)
+//!
+//! An rc.d script launches the daemon with `--boot`. Before the API socket
+//! is bound, every container flagged `boot` is started in [`boot_order`].
+//! Failures are logged and recorded in a [`BootReport`] but never keep the
+//! daemon from serving; the report stays on the manager and is returned by
+//! `GET /system/boot`.
+//!
+//! Separately, with `container.restart_on_boot` set, `JailManager::start`
+//! starts again the containers [`needs_restart_on_boot`] picks: those whose
+//! restart policy asks for it and that were running when the daemon or the
+//! host went down.
+
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::{error, info};
+
+use crate::JailManager;
+use crate::container::{Container, ContainerId, RestartPolicy};
+use crate::server::SocketServer;
+use crate::timestamp::Timestamp;
+
+/// The containers among `containers` that start at boot, in start order
+///
+/// Containers don't declare dependencies on each other, so creation order
+/// (then ID, for a stable order) stands in for one.
+pub fn boot_order<'a>(containers: impl IntoIterator<Item = &'a Container>) -> Vec<&'a Container> {
+    start_order(containers.into_iter().filter(|c| c.boot).collect())
+}
+
+/// `containers` by creation, then ID
+pub(crate) fn start_order(mut containers: Vec<&Container>) -> Vec<&Container> {
+    containers.sort_by(|a, b| a.created_at.cmp(&b.created_at).then_with(|| a.id.cmp(&b.id)));
+    containers
+}
+
+/// Whether a loaded container should be started again at daemon start
+///
+/// Its policy must be `always` or `on-restart`. It must also still be
+/// recorded as running while its jail (`jail_running`) is gone: a user
+/// stop records it as stopped, a crash or reboot leaves it running.
+pub fn needs_restart_on_boot(container: &Container, jail_running: bool) -> bool {
+    matches!(container.restart_policy, RestartPolicy::Always | RestartPolicy::OnRestart)
+        && container.is_running()
+        && !jail_running
+}
+
+/// What happened to one boot container
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BootOutcome {
+    Started,
+    AlreadyRunning,
+    Failed,
+}
+
+/// Result of starting one boot container
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BootResult {
+    pub id: ContainerId,
+    pub name: Option<String>,
+    pub outcome: BootOutcome,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Outcome of the boot start, one result per boot container in start order
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BootReport {
+    /// When the last container was attempted
+    pub finished_at: Timestamp,
+    pub results: Vec<BootResult>,
+}
+
+impl BootReport {
+    /// Number of containers that failed to start
+    pub fn failed(&self) -> usize {
+        self.results.iter().filter(|r| r.outcome == BootOutcome::Failed).count()
+    }
+}
+
+/// Starts one container; the manager's own start in the daemon
+pub trait ContainerStarter {
+    fn start(&self, manager: &mut JailManager, id: &ContainerId) -> Result<(), String>;
+}
+
+/// Starts containers through [`JailManager::start_container`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ManagerStarter;
+
+impl ContainerStarter for ManagerStarter {
+    fn start(&self, manager: &mut JailManager, id: &ContainerId) -> Result<(), String> {
+        manager.start_container(id).map_err(|e| e.to_string())
+    }
+}
+
+/// Start every boot container, one after another, and record the report
+/// on the manager
+pub async fn start_boot_containers(manager: &Arc<Mutex<JailManager>>, starter: &impl ContainerStarter) -> BootReport {
+    let mut mgr = manager.lock().await;
+    let boot: Vec<(ContainerId, Option<String>, bool)> = mgr
+        .boot_containers()
+        .into_iter()
+        .map(|c| (c.id.clone(), c.name.clone(), c.is_running()))
+        .collect();
+    info!("Starting {} boot container(s)", boot.len());
+
+    let mut report = BootReport::default();
+    for (id, name, running) in boot {
+        let label = name.clone().unwrap_or_else(|| id.clone());
+        let (outcome, error) = if running {
+            info!("Boot container {} is already running", label);
+            (BootOutcome::AlreadyRunning, None)
+        } else {
+            match starter.start(&mut mgr, &id) {
+                Ok(()) => {
+                    info!("Started boot container {}", label);
+                    (BootOutcome::Started, None)
+                }
+                Err(e) => {
+                    error!("Failed to start boot container {}: {}", label, e);
+                    (BootOutcome::Failed, Some(e))
+                }
+            }
+        };
+        report.results.push(BootResult { id, name, outcome, error });
+    }
+
+    report.finished_at = Timestamp::now();
+    if report.failed() > 0 {
+        error!("{} of {} boot container(s) failed to start", report.failed(), report.results.len());
+    }
+    mgr.boot_report = Some(report.clone());
+    report
+}
+
+/// Start the boot containers, then serve the API. The socket is only bound
+/// once every boot container has been attempted.
+pub async fn boot_then_serve(
+    manager: &Arc<Mutex<JailManager>>,
+    starter: &impl ContainerStarter,
+    server: &SocketServer,
+) -> Result<(), Box<dyn std::error::Error>> {
+    start_boot_containers(manager, starter).await;
+    server.run().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{Endpoint, Request, Response};
+    use futures::{SinkExt, StreamExt};
+    use std::path::PathBuf;
+    use std::time::Duration;
+    use tokio_util::codec::{Framed, LinesCodec};
+
+    /// Starts containers without jails, failing the ones named in `fail`,
+    /// and notes whether the API socket existed at any start
+    struct FakeStarter {
+        socket: PathBuf,
+        fail: Vec<&'static str>,
+        started: std::sync::Mutex<Vec<String>>,
+        saw_socket: std::sync::atomic::AtomicBool,
+    }
+
+    impl ContainerStarter for FakeStarter {
+        fn start(&self, manager: &mut JailManager, id: &ContainerId) -> Result<(), String> {
+            if self.socket.exists() {
+                self.saw_socket.store(true, std::sync::atomic::Ordering::SeqCst);
+            }
+            let container = manager.containers.get_mut(id).unwrap();
+            let name = container.name.clone().unwrap();
+            self.started.lock().unwrap().push(name.clone());
+            if self.fail.contains(&name.as_str()) {
+                return Err(format!("jail {} failed to start", name));
+            }
+            container.set_state(crate::container::ContainerState::Running);
+            Ok(())
+        }
+    }
+
+    fn container(name: &str, created_at: i64, boot: bool) -> Container {
+        let mut container = Container::new("img".to_string(), format!("kawakaze-{}", name), format!("tank/containers/{}", name))
+            .with_name(name.to_string())
+            .with_boot(boot);
+        container.created_at = Timestamp::from_secs(created_at);
+        container
+    }
+
+    fn manager_with(socket: &std::path::Path, containers: Vec<Container>) -> JailManager {
+        let mut manager = JailManager::new(socket);
+        for c in containers {
+            manager.containers.insert(c.id.clone(), c);
+        }
+        manager
+    }
+
+    #[test]
+    fn test_boot_order_selects_flagged_by_creation() {
+        let containers = [container("db", 20, true), container("web", 30, true), container("scratch", 10, false), container("cache", 5, true)];
+        let order: Vec<_> = boot_order(&containers).into_iter().map(|c| c.name.clone().unwrap()).collect();
+        assert_eq!(order, ["cache", "db", "web"]);
+    }
+
+    #[tokio::test]
+    async fn test_boot_reports_mixed_results() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("kawakaze.sock");
+        let mut running = container("proxy", 4, true);
+        running.set_state(crate::container::ContainerState::Running);
+        let manager = Arc::new(Mutex::new(manager_with(&socket, vec![
+            container("db", 1, true),
+            container("web", 2, true),
+            container("idle", 3, false),
+            running,
+        ])));
+        let starter = FakeStarter { socket, fail: vec!["web"], started: Default::default(), saw_socket: Default::default() };
+
+        let report = start_boot_containers(&manager, &starter).await;
+
+        assert_eq!(*starter.started.lock().unwrap(), ["db", "web"]);
+        let outcomes: Vec<_> = report.results.iter().map(|r| (r.name.as_deref().unwrap(), r.outcome)).collect();
+        assert_eq!(outcomes, [
+            ("db", BootOutcome::Started),
+            ("web", BootOutcome::Failed),
+            ("proxy", BootOutcome::AlreadyRunning),
+        ]);
+        assert_eq!(report.failed(), 1);
+        assert!(report.results[1].error.as_deref().unwrap().contains("failed to start"));
+        assert_eq!(manager.lock().await.boot_report(), Some(&report));
+    }
+
+    #[test]
+    fn test_restart_on_boot_by_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let loaded = |name: &str, policy: RestartPolicy, state: crate::container::ContainerState| {
+            let mut container = container(name, 0, false).with_restart_policy(policy);
+            container.state = state;
+            container
+        };
+        use crate::container::ContainerState::{Running, Stopped};
+        let mut survivor = loaded("survivor", RestartPolicy::Always, Running);
+        survivor.jail_name = "kawakaze-survivor".to_string();
+        let mut manager = manager_with(&dir.path().join("kawakaze.sock"), vec![
+            loaded("always", RestartPolicy::Always, Running),
+            loaded("on-restart", RestartPolicy::OnRestart, Running),
+            loaded("on-failure", RestartPolicy::OnFailure, Running),
+            loaded("no", RestartPolicy::No, Running),
+            loaded("stopped", RestartPolicy::Always, Stopped),
+            loaded("broken", RestartPolicy::OnRestart, Running),
+            survivor,
+        ]);
+        // This one's jail outlived the daemon, so it's still up
+        let mut jail = crate::jail::Jail::create("kawakaze-survivor").unwrap();
+        jail.set_state(crate::jail::JailState::Running);
+        manager.jails.insert("kawakaze-survivor".to_string(), jail);
+
+        let starter = FakeStarter {
+            socket: dir.path().join("unused.sock"),
+            fail: vec!["broken"],
+            started: Default::default(),
+            saw_socket: Default::default(),
+        };
+        let started = manager.restart_on_boot(&starter);
+
+        let mut attempted = starter.started.lock().unwrap().clone();
+        attempted.sort();
+        assert_eq!(attempted, ["always", "broken", "on-restart"]);
+        let mut started: Vec<_> = started.iter().map(|id| manager.containers[id].name.clone().unwrap()).collect();
+        started.sort();
+        assert_eq!(started, ["always", "on-restart"]);
+
+        // A failed restart leaves the container stopped, not running
+        let state = |name: &str| manager.containers.values().find(|c| c.name.as_deref() == Some(name)).unwrap().state;
+        assert_eq!(state("broken"), Stopped);
+        assert_eq!(state("survivor"), Running);
+        assert_eq!(state("stopped"), Stopped);
+
+        // ...and says why
+        let cause = |name: &str| manager.containers.values().find(|c| c.name.as_deref() == Some(name)).unwrap().last_stop_cause;
+        assert_eq!(cause("broken"), Some(crate::container::StopCause::RestartPolicyGaveUp));
+        assert_eq!(cause("always"), None);
+    }
+
+    #[tokio::test]
+    async fn test_socket_is_bound_only_after_boot_set() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("kawakaze.sock");
+        let manager = Arc::new(Mutex::new(manager_with(&socket, vec![container("db", 1, true), container("web", 2, true)])));
+        let starter = Arc::new(FakeStarter {
+            socket: socket.clone(),
+            fail: vec!["db"],
+            started: Default::default(),
+            saw_socket: Default::default(),
+        });
+
+        let server = SocketServer::new(Arc::new(socket.to_string_lossy().into_owned()), manager.clone());
+        let task = {
+            let (manager, starter) = (manager.clone(), starter.clone());
+            tokio::spawn(async move {
+                let _ = boot_then_serve(&manager, &*starter, &server).await.map_err(|e| e.to_string());
+            })
+        };
+
+        let mut waited = Duration::ZERO;
+        while !socket.exists() {
+            assert!(waited < Duration::from_secs(5), "socket was never bound");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            waited += Duration::from_millis(10);
+        }
+
+        // Both containers were attempted, neither while the socket existed
+        assert_eq!(*starter.started.lock().unwrap(), ["db", "web"]);
+        assert!(!starter.saw_socket.load(std::sync::atomic::Ordering::SeqCst));
+
+        // The report is served once the socket is up
+        let stream = tokio::net::UnixStream::connect(&socket).await.unwrap();
+        let mut framed = Framed::new(stream, LinesCodec::new());
+        framed.send(serde_json::to_string(&Request::get(Endpoint::SystemBootList)).unwrap()).await.unwrap();
+        let response: Response = serde_json::from_str(&framed.next().await.unwrap().unwrap()).unwrap();
+        let data = response.data.unwrap();
+        assert_eq!(data["last_boot"]["results"][0]["outcome"], "failed");
+        assert_eq!(data["last_boot"]["results"][1]["outcome"], "started");
+
+        task.abort();
+    }
+}
@@ -4,6 +4,7 @@
 //! to bootstrap jails with a complete FreeBSD installation.
 
 use std::collections::HashMap;
+use std::future::Future;
 use std::path::{Path, PathBuf};
 use std::time::Duration;
 
@@ -13,7 +14,48 @@ use sha2::{Digest, Sha256};
 use tokio::fs::{self, File};
 use tokio::io::AsyncWriteExt;
 use tokio::sync::mpsc;
-use tracing::info;
+use tracing::{info, warn};
+
+use crate::zfs::{DatasetOptions, Zfs};
+
+/// Snapshot name used for cached base system datasets
+pub const BASE_SNAPSHOT: &str = "base";
+
+/// Mirror base.txz is fetched from when no other is given
+pub const DEFAULT_MIRROR: &str = "https://download.freebsd.org/releases";
+
+/// Files every FreeBSD base system has. A root counts as bootstrapped
+/// only with all of them, so an extraction that stopped halfway or a stray
+/// shell doesn't pass for one.
+const BASE_MARKERS: &[&str] = &["bin/sh", "etc/freebsd-update.conf"];
+
+/// Distribution sets every release publishes
+const COMMON_SETS: &[&str] = &["base", "base-dbg", "kernel", "kernel-dbg", "ports", "src", "tests"];
+
+/// Distribution sets a release publishes for an architecture
+///
+/// 32-bit compatibility libraries exist for amd64 (and arm64 from 14.0);
+/// the documentation set was dropped in 13.0.
+pub fn known_sets(version: &str, architecture: &str) -> Vec<&'static str> {
+    let major = version.split(['.', '-']).next().and_then(|major| major.parse::<u32>().ok());
+    let mut sets = COMMON_SETS.to_vec();
+    let lib32 = match architecture {
+        "amd64" => true,
+        "aarch64" | "arm64" => major.is_some_and(|major| major >= 14),
+        _ => false,
+    };
+    if lib32 {
+        sets.extend(["lib32", "lib32-dbg"]);
+    }
+    if major.is_some_and(|major| major < 13) {
+        sets.push("doc");
+    }
+    sets
+}
+
+fn default_sets() -> Vec<String> {
+    vec!["base".to_string()]
+}
 
 /// Bootstrap configuration options
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -37,6 +79,10 @@ pub struct BootstrapConfig {
     /// Custom configuration file overrides (path -> content)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub config_overrides: Option<HashMap<String, String>>,
+
+    /// Distribution sets to extract, e.g. `["base", "lib32"]`
+    #[serde(default = "default_sets")]
+    pub sets: Vec<String>,
 }
 
 impl Default for BootstrapConfig {
@@ -47,7 +93,108 @@ impl Default for BootstrapConfig {
             mirror: None,
             no_cache: false,
             config_overrides: None,
+            sets: default_sets(),
+        }
+    }
+}
+
+impl BootstrapConfig {
+    /// Resolve the FreeBSD version, falling back to the host's release
+    pub fn resolve_version(&self) -> Result<String, BootstrapError> {
+        if let Some(ref version) = self.version {
+            return Ok(version.clone());
+        }
+
+        // Try to detect from host system
+        #[cfg(target_os = "freebsd")]
+        {
+            use std::ffi::CStr;
+            let mut utsname: libc::utsname = unsafe { std::mem::zeroed() };
+
+            if unsafe { libc::uname(&mut utsname) } == 0 {
+                let release = unsafe { CStr::from_ptr(utsname.release.as_ptr()) };
+                if let Ok(s) = release.to_str() {
+                    // Check if it already ends with -RELEASE
+                    if s.ends_with("-RELEASE") {
+                        return Ok(s.to_string());
+                    }
+                    // Convert "15.0" to "15.0-RELEASE"
+                    return Ok(format!("{}-RELEASE", s));
+                }
+            }
+        }
+
+        // Fallback to default
+        Ok("15.0-RELEASE".to_string())
+    }
+
+    /// Resolve the architecture, falling back to the host's machine type
+    pub fn resolve_architecture(&self) -> Result<String, BootstrapError> {
+        if let Some(ref arch) = self.architecture {
+            return Ok(arch.clone());
+        }
+
+        // Try to detect from host system
+        #[cfg(target_os = "freebsd")]
+        {
+            use std::ffi::CStr;
+            let mut utsname: libc::utsname = unsafe { std::mem::zeroed() };
+
+            if unsafe { libc::uname(&mut utsname) } == 0 {
+                let machine = unsafe { CStr::from_ptr(utsname.machine.as_ptr()) };
+                if let Ok(s) = machine.to_str() {
+                    // Map FreeBSD machine names to architecture names
+                    return match s {
+                        "amd64" => Ok("amd64".to_string()),
+                        "i386" => Ok("i386".to_string()),
+                        "aarch64" => Ok("arm64".to_string()),
+                        "arm64" => Ok("aarch64".to_string()),
+                        _ => Ok(s.to_string()),
+                    };
+                }
+            }
+        }
+
+        // Fallback to common architectures
+        #[cfg(target_arch = "x86_64")]
+        return Ok("amd64".to_string());
+
+        #[cfg(target_arch = "aarch64")]
+        return Ok("aarch64".to_string());
+
+        #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+        Err(BootstrapError::InvalidArchitecture(
+            "Could not detect architecture".to_string(),
+        ))
+    }
+
+    /// The requested sets, checked against what `version` publishes for
+    /// `architecture`, without duplicates and with `base` first
+    pub fn selected_sets(&self, version: &str, architecture: &str) -> Result<Vec<String>, BootstrapError> {
+        let known = known_sets(version, architecture);
+        if let Some(unknown) = self.sets.iter().find(|set| !known.contains(&set.as_str())) {
+            return Err(BootstrapError::InvalidSet(format!(
+                "'{}' is not a distribution set of {} {} (known: {})",
+                unknown, version, architecture, known.join(", ")
+            )));
+        }
+        // A jail can't run without the base system
+        if !self.sets.iter().any(|set| set == "base") {
+            return Err(BootstrapError::InvalidSet("the sets must include 'base'".to_string()));
         }
+
+        let mut sets = default_sets();
+        for set in &self.sets {
+            if !sets.contains(set) {
+                sets.push(set.clone());
+            }
+        }
+        Ok(sets)
+    }
+
+    /// Check the requested sets before queueing a bootstrap
+    pub fn validate_sets(&self) -> Result<(), BootstrapError> {
+        self.selected_sets(&self.resolve_version()?, &self.resolve_architecture()?).map(|_| ())
     }
 }
 
@@ -74,9 +221,11 @@ pub struct BootstrapProgress {
 #[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum BootstrapStatus {
+    /// Waiting for a bootstrap slot (see `task_queue`)
+    Queued,
     /// Bootstrap is initializing
     Initializing,
-    /// Downloading base.txz
+    /// Downloading a distribution set
     Downloading,
     /// Verifying checksum
     Verifying,
@@ -114,6 +263,9 @@ pub enum BootstrapError {
     #[error("Invalid architecture: {0}")]
     InvalidArchitecture(String),
 
+    #[error("Invalid distribution set: {0}")]
+    InvalidSet(String),
+
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 
@@ -122,6 +274,9 @@ pub enum BootstrapError {
 
     #[error("Database error: {0}")]
     Database(String),
+
+    #[error("ZFS error: {0}")]
+    Zfs(String),
 }
 
 /// Bootstrap cache for storing downloaded tarballs
@@ -153,8 +308,8 @@ impl BootstrapCache {
     }
 
     /// Get a cached tarball path
-    pub fn get(&self, key: &str) -> Option<PathBuf> {
-        let path = self.cache_dir.join(key).join("base.txz");
+    pub fn get(&self, key: &str, file: &str) -> Option<PathBuf> {
+        let path = self.cache_dir.join(key).join(file);
         if path.exists() {
             Some(path)
         } else {
@@ -163,11 +318,11 @@ impl BootstrapCache {
     }
 
     /// Store a tarball in the cache
-    pub async fn put(&self, key: &str, tarball_path: &Path) -> Result<(), BootstrapError> {
+    pub async fn put(&self, key: &str, file: &str, tarball_path: &Path) -> Result<(), BootstrapError> {
         let cache_entry = self.cache_dir.join(key);
         fs::create_dir_all(&cache_entry).await?;
 
-        let cached_path = cache_entry.join("base.txz");
+        let cached_path = cache_entry.join(file);
         fs::copy(tarball_path, &cached_path).await?;
 
         // Evict if needed
@@ -196,10 +351,11 @@ impl BootstrapCache {
         let mut entries = fs::read_dir(&self.cache_dir).await?;
         while let Some(entry) = entries.next_entry().await? {
             if entry.path().is_dir() {
-                let tarball_path = entry.path().join("base.txz");
-                if tarball_path.exists() {
-                    let metadata = fs::metadata(&tarball_path).await?;
-                    total += metadata.len();
+                let mut tarballs = fs::read_dir(entry.path()).await?;
+                while let Some(tarball) = tarballs.next_entry().await? {
+                    if tarball.path().extension().is_some_and(|ext| ext == "txz") {
+                        total += tarball.metadata().await?.len();
+                    }
                 }
             }
         }
@@ -207,11 +363,11 @@ impl BootstrapCache {
         Ok(total)
     }
 
-    /// Invalidate a cache entry
-    pub async fn invalidate(&self, key: &str) -> Result<(), BootstrapError> {
-        let cache_entry = self.cache_dir.join(key);
-        if cache_entry.exists() {
-            fs::remove_dir_all(cache_entry).await?;
+    /// Invalidate a cached tarball
+    pub async fn invalidate(&self, key: &str, file: &str) -> Result<(), BootstrapError> {
+        let cached_path = self.cache_dir.join(key).join(file);
+        if cached_path.exists() {
+            fs::remove_file(cached_path).await?;
         }
         Ok(())
     }
@@ -241,8 +397,14 @@ impl Bootstrap {
     }
 
     /// Check if a jail is already bootstrapped
+    ///
+    /// Each of [`BASE_MARKERS`] must be a regular file with content; a
+    /// symlink doesn't count, since it might lead out of the root.
     pub fn is_bootstrapped(jail_path: impl AsRef<Path>) -> bool {
-        jail_path.as_ref().join("bin/sh").exists()
+        let root = jail_path.as_ref();
+        BASE_MARKERS.iter().all(|marker| {
+            std::fs::symlink_metadata(root.join(marker)).is_ok_and(|meta| meta.is_file() && meta.len() > 0)
+        })
     }
 
     /// Run the bootstrap process
@@ -257,150 +419,118 @@ impl Bootstrap {
         // Detect version and architecture
         let version = self.detect_version()?;
         let architecture = self.detect_architecture()?;
+        let sets = self.config.selected_sets(&version, &architecture)?;
 
         self.report_progress(
             BootstrapStatus::Initializing,
             0,
-            "Initializing bootstrap...",
+            &format!("Initializing bootstrap of {}...", sets.join(", ")),
         );
 
         // Build cache key
         let cache_key = format!("{}-{}", version, architecture);
 
+        // Fetched on the first download and shared by every set
+        let mut manifest = None;
+
+        for (index, set) in sets.iter().enumerate() {
+            let part = SetPart { set, index, count: sets.len() };
+            self.install_set(&version, &architecture, &cache_key, part, &mut manifest).await?;
+        }
+
+        // Create configuration files
+        self.create_config_files().await?;
+
+        // Report completion
+        self.report_progress(
+            BootstrapStatus::Complete,
+            100,
+            "Bootstrap completed successfully",
+        );
+
+        Ok(())
+    }
+
+    /// Download (or take from the cache), verify and extract one set
+    async fn install_set(
+        &mut self,
+        version: &str,
+        architecture: &str,
+        cache_key: &str,
+        part: SetPart<'_>,
+        manifest: &mut Option<HashMap<String, String>>,
+    ) -> Result<(), BootstrapError> {
+        let file = set_file(part.set);
+
         // Try to use cached tarball, but fall back to download if extraction fails
-        let mut tarball_path = if !self.config.no_cache {
-            if let Some(ref cache) = self.cache {
-                if let Some(cached) = cache.get(&cache_key) {
-                    info!("Using cached tarball: {:?}", cached);
-                    cached
-                } else {
-                    self.download_and_verify(&version, &architecture).await?
-                }
-            } else {
-                self.download_and_verify(&version, &architecture).await?
-            }
+        let cached = if self.config.no_cache {
+            None
         } else {
-            self.download_and_verify(&version, &architecture).await?
+            self.cache.as_ref().and_then(|cache| cache.get(cache_key, &file))
+        };
+        let using_cached = cached.is_some();
+        let tarball_path = match cached {
+            Some(cached) => {
+                info!("Using cached tarball: {:?}", cached);
+                cached
+            }
+            None => self.download_and_verify(version, architecture, part, manifest).await?,
         };
 
         // Try extracting, but invalidate cache and retry on failure
-        let using_cached = self.cache.as_ref().is_some()
-            && self.cache.as_ref().and_then(|c| c.get(&cache_key)).is_some();
-
-        match self.extract_tarball(&tarball_path).await {
+        match self.extract_tarball(&tarball_path, part).await {
             Ok(_) => {
                 // Store in cache if we just downloaded it
-                if !self.config.no_cache {
-                    if let Some(ref cache) = self.cache {
-                        let _ = cache.put(&cache_key, &tarball_path).await;
-                    }
+                if !using_cached {
+                    self.keep_download(cache_key, &file, &tarball_path).await;
                 }
             }
             Err(e) => {
                 // If we were using a cached file, invalidate it and retry
-                if using_cached && !self.config.no_cache {
-                    info!("Cached tarball appears corrupted, invalidating cache and retrying...");
+                if using_cached {
+                    info!("Cached {} appears corrupted, invalidating cache and retrying...", file);
                     if let Some(ref cache) = self.cache {
-                        cache.invalidate(&cache_key).await?;
+                        cache.invalidate(cache_key, &file).await?;
                     }
-                    tarball_path = self.download_and_verify(&version, &architecture).await?;
-                    self.extract_tarball(&tarball_path).await?;
+                    let tarball_path = self.download_and_verify(version, architecture, part, manifest).await?;
+                    self.extract_tarball(&tarball_path, part).await?;
                     // Store the fresh download in cache
-                    if let Some(ref cache) = self.cache {
-                        let _ = cache.put(&cache_key, &tarball_path).await;
-                    }
+                    self.keep_download(cache_key, &file, &tarball_path).await;
                 } else {
+                    let _ = fs::remove_file(&tarball_path).await;
                     return Err(e);
                 }
             }
         }
 
-        // Create configuration files
-        self.create_config_files().await?;
-
-        // Report completion
-        self.report_progress(
-            BootstrapStatus::Complete,
-            100,
-            "Bootstrap completed successfully",
-        );
-
         Ok(())
     }
 
-    /// Detect FreeBSD version
-    fn detect_version(&self) -> Result<String, BootstrapError> {
-        if let Some(ref version) = self.config.version {
-            return Ok(version.clone());
-        }
-
-        // Try to detect from host system
-        #[cfg(target_os = "freebsd")]
+    /// Cache a downloaded tarball (unless caching is off) and remove it from
+    /// the jail
+    async fn keep_download(&self, cache_key: &str, file: &str, tarball_path: &Path) {
+        if !self.config.no_cache
+            && let Some(ref cache) = self.cache
         {
-            use std::ffi::CStr;
-            let mut utsname: libc::utsname = unsafe { std::mem::zeroed() };
-
-            if unsafe { libc::uname(&mut utsname) } == 0 {
-                let release = unsafe { CStr::from_ptr(utsname.release.as_ptr()) };
-                if let Ok(s) = release.to_str() {
-                    // Check if it already ends with -RELEASE
-                    if s.ends_with("-RELEASE") {
-                        return Ok(s.to_string());
-                    }
-                    // Convert "15.0" to "15.0-RELEASE"
-                    return Ok(format!("{}-RELEASE", s));
-                }
-            }
+            let _ = cache.put(cache_key, file, tarball_path).await;
         }
+        let _ = fs::remove_file(tarball_path).await;
+    }
 
-        // Fallback to default
-        Ok("15.0-RELEASE".to_string())
+    /// Detect FreeBSD version
+    fn detect_version(&self) -> Result<String, BootstrapError> {
+        self.config.resolve_version()
     }
 
     /// Detect system architecture
     fn detect_architecture(&self) -> Result<String, BootstrapError> {
-        if let Some(ref arch) = self.config.architecture {
-            return Ok(arch.clone());
-        }
-
-        // Try to detect from host system
-        #[cfg(target_os = "freebsd")]
-        {
-            use std::ffi::CStr;
-            let mut utsname: libc::utsname = unsafe { std::mem::zeroed() };
-
-            if unsafe { libc::uname(&mut utsname) } == 0 {
-                let machine = unsafe { CStr::from_ptr(utsname.machine.as_ptr()) };
-                if let Ok(s) = machine.to_str() {
-                    // Map FreeBSD machine names to architecture names
-                    return match s {
-                        "amd64" => Ok("amd64".to_string()),
-                        "i386" => Ok("i386".to_string()),
-                        "aarch64" => Ok("arm64".to_string()),
-                        "arm64" => Ok("aarch64".to_string()),
-                        _ => Ok(s.to_string()),
-                    };
-                }
-            }
-        }
-
-        // Fallback to common architectures
-        #[cfg(target_arch = "x86_64")]
-        return Ok("amd64".to_string());
-
-        #[cfg(target_arch = "aarch64")]
-        return Ok("aarch64".to_string());
-
-        #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
-        Err(BootstrapError::InvalidArchitecture(
-            "Could not detect architecture".to_string(),
-        ))
+        self.config.resolve_architecture()
     }
 
     /// Build the mirror URL for downloading
     fn build_mirror_url(&self, version: &str, architecture: &str, file: &str) -> String {
         let mirror = self.config.mirror.as_deref()
-            .unwrap_or("https://download.freebsd.org/releases");
+            .unwrap_or(DEFAULT_MIRROR);
 
         // Map architecture for URL (amd64 -> amd64/amd64, aarch64 -> arm64/aarch64)
         let arch_path = match architecture {
@@ -414,49 +544,60 @@ impl Bootstrap {
         format!("{}/{}/{}/{}", mirror, arch_path, version, file)
     }
 
-    /// Download and verify the base.txz tarball
+    /// Download and verify a set's tarball
     async fn download_and_verify(
         &mut self,
         version: &str,
         architecture: &str,
+        part: SetPart<'_>,
+        manifest: &mut Option<HashMap<String, String>>,
     ) -> Result<PathBuf, BootstrapError> {
-        let tarball_url = self.build_mirror_url(version, architecture, "base.txz");
-        let manifest_url = self.build_mirror_url(version, architecture, "MANIFEST");
+        let file = set_file(part.set);
+        let tarball_url = self.build_mirror_url(version, architecture, &file);
 
         info!("Downloading from: {}", tarball_url);
 
         self.report_progress(
             BootstrapStatus::Downloading,
-            10,
-            &format!("Downloading FreeBSD base system for {} ({})", version, architecture),
+            part.progress(0),
+            &format!("Downloading {} {} for {} ({})", file, part.counter(), version, architecture),
         );
 
         // Download tarball
-        let tarball_path = self.download_file(&tarball_url).await?;
+        let tarball_path = self.download_file(&tarball_url, part).await?;
 
         self.report_progress(
             BootstrapStatus::Verifying,
-            50,
-            "Verifying checksum...",
+            part.progress(50),
+            &format!("Verifying checksum of {}...", file),
         );
 
-        // Download checksum from MANIFEST
-        let expected_checksum = self.download_checksum_from_manifest(&manifest_url).await?;
+        // Download checksums from MANIFEST
+        if manifest.is_none() {
+            let manifest_url = self.build_mirror_url(version, architecture, "MANIFEST");
+            *manifest = Some(self.download_manifest(&manifest_url).await?);
+        }
+        let expected_checksum = manifest.as_ref().and_then(|manifest| manifest.get(&file)).ok_or_else(|| {
+            BootstrapError::DownloadFailed(format!("{} not found in MANIFEST", file))
+        })?;
 
         // Verify checksum
-        self.verify_checksum(&tarball_path, &expected_checksum).await?;
+        if let Err(e) = self.verify_checksum(&tarball_path, expected_checksum).await {
+            let _ = fs::remove_file(&tarball_path).await;
+            return Err(e);
+        }
 
         self.report_progress(
             BootstrapStatus::Verifying,
-            60,
-            "Checksum verified",
+            part.progress(60),
+            &format!("Checksum of {} verified", file),
         );
 
         Ok(tarball_path)
     }
 
     /// Download a file with progress tracking
-    async fn download_file(&self, url: &str) -> Result<PathBuf, BootstrapError> {
+    async fn download_file(&self, url: &str, part: SetPart<'_>) -> Result<PathBuf, BootstrapError> {
         let client = Client::builder()
             .timeout(Duration::from_secs(600))
             .build()?;
@@ -475,7 +616,7 @@ impl Bootstrap {
         let mut downloaded = 0u64;
 
         // Create temp file
-        let temp_path = self.jail_path.join(".bootstrap_temp");
+        let temp_path = self.jail_path.join(format!(".bootstrap_temp_{}", set_file(part.set)));
         let mut file = File::create(&temp_path).await?;
 
         // Download with streaming
@@ -488,11 +629,13 @@ impl Bootstrap {
 
             // Update progress
             if total_size > 0 {
-                let progress = 10 + (downloaded * 35 / total_size) as u8; // 10-45%
+                let progress = part.progress(downloaded * 45 / total_size); // 0-45% of the set
                 self.report_progress(
                     BootstrapStatus::Downloading,
                     progress,
-                    &format!("Downloading... ({}/{})",
+                    &format!("Downloading {} {}... ({}/{})",
+                             part.set,
+                             part.counter(),
                              bytes_to_mb(downloaded),
                              bytes_to_mb(total_size)),
                 );
@@ -504,8 +647,8 @@ impl Bootstrap {
         Ok(temp_path)
     }
 
-    /// Download checksum from MANIFEST file
-    async fn download_checksum_from_manifest(&self, url: &str) -> Result<String, BootstrapError> {
+    /// Download the MANIFEST file of a release
+    async fn download_manifest(&self, url: &str) -> Result<HashMap<String, String>, BootstrapError> {
         let client = Client::builder()
             .timeout(Duration::from_secs(30))
             .build()?;
@@ -519,20 +662,7 @@ impl Bootstrap {
             )));
         }
 
-        let content = response.text().await?;
-
-        // Parse MANIFEST file (tab-separated format: filename\thash\tsize\t...)
-        // We're looking for the line that starts with "base.txz"
-        for line in content.lines() {
-            let parts: Vec<&str> = line.split('\t').collect();
-            if parts.len() >= 2 && parts[0] == "base.txz" {
-                return Ok(parts[1].to_string());
-            }
-        }
-
-        Err(BootstrapError::DownloadFailed(
-            "base.txz not found in MANIFEST".to_string(),
-        ))
+        Ok(parse_manifest(&response.text().await?))
     }
 
     /// Verify SHA256 checksum
@@ -557,14 +687,14 @@ impl Bootstrap {
         }
     }
 
-    /// Extract the base.txz tarball
-    async fn extract_tarball(&mut self, tarball_path: &Path) -> Result<(), BootstrapError> {
-        info!("Extracting tarball to: {:?}", self.jail_path);
+    /// Extract a set's tarball
+    async fn extract_tarball(&mut self, tarball_path: &Path, part: SetPart<'_>) -> Result<(), BootstrapError> {
+        info!("Extracting {:?} to: {:?}", tarball_path, self.jail_path);
 
         self.report_progress(
             BootstrapStatus::Extracting,
-            65,
-            "Extracting FreeBSD base system...",
+            part.progress(65),
+            &format!("Extracting {} {}...", part.set, part.counter()),
         );
 
         // Use blocking task for tar extraction since we need sync xz2 + tar
@@ -589,8 +719,8 @@ impl Bootstrap {
 
         self.report_progress(
             BootstrapStatus::Extracting,
-            90,
-            "Extraction complete",
+            part.progress(100),
+            &format!("Extracted {}", part.set),
         );
 
         Ok(())
@@ -775,6 +905,182 @@ set prompt = '%n@%m:%~%# '
     }
 }
 
+/// Tarball of a distribution set
+fn set_file(set: &str) -> String {
+    format!("{}.txz", set)
+}
+
+/// Checksums by file name from a release's MANIFEST (tab-separated:
+/// file, SHA256, file count, set, description, default)
+pub fn parse_manifest(content: &str) -> HashMap<String, String> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split('\t');
+            Some((parts.next()?.to_string(), parts.next()?.to_string()))
+        })
+        .collect()
+}
+
+/// One set of a bootstrap, for progress reporting
+#[derive(Debug, Clone, Copy)]
+struct SetPart<'a> {
+    set: &'a str,
+    index: usize,
+    count: usize,
+}
+
+impl SetPart<'_> {
+    /// Overall progress once this set is `percent` done: the sets share
+    /// 5-90%, the rest is for setup and configuration
+    fn progress(&self, percent: u64) -> u8 {
+        let done = self.index as u64 * 100 + percent.min(100);
+        (5 + 85 * done / (self.count.max(1) as u64 * 100)) as u8
+    }
+
+    /// "(2/3)"
+    fn counter(&self) -> String {
+        format!("({}/{})", self.index + 1, self.count)
+    }
+}
+
+/// Populates a directory with a FreeBSD base system
+///
+/// The base image cache only needs "put a base system here"; keeping that behind
+/// a trait lets the cache logic be exercised without network access.
+pub trait BaseBootstrapper {
+    /// Bootstrap `version`/`architecture` into `root`
+    fn bootstrap(
+        &self,
+        root: &Path,
+        version: &str,
+        architecture: &str,
+    ) -> impl Future<Output = Result<(), BootstrapError>> + Send;
+}
+
+/// Bootstrapper that downloads base.txz from a FreeBSD mirror
+#[derive(Debug, Clone, Default)]
+pub struct MirrorBootstrapper {
+    /// Custom mirror URL. If None, uses official FreeBSD mirrors.
+    pub mirror: Option<String>,
+}
+
+impl BaseBootstrapper for MirrorBootstrapper {
+    async fn bootstrap(
+        &self,
+        root: &Path,
+        version: &str,
+        architecture: &str,
+    ) -> Result<(), BootstrapError> {
+        let config = BootstrapConfig {
+            version: Some(version.to_string()),
+            architecture: Some(architecture.to_string()),
+            mirror: self.mirror.clone(),
+            ..Default::default()
+        };
+
+        let (progress_tx, _progress_rx) = mpsc::channel(100);
+        Bootstrap::new(root, config, progress_tx)?.run().await
+    }
+}
+
+/// Image namespace that resolves to a bootstrapped FreeBSD base system
+pub const FREEBSD_IMAGE: &str = "freebsd";
+
+/// Serialises base system creation so concurrent builds never bootstrap
+/// (or clean up) the same base dataset at once
+static BASE_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// Recognise a `FROM freebsd:<version>` reference and return the base system it names
+///
+/// A bare version such as `14.1` means `14.1-RELEASE`. `freebsd` without a tag
+/// (or `freebsd:latest`) follows the host's release.
+pub fn parse_base_reference(from: &str) -> Option<BootstrapConfig> {
+    let (name, tag) = match from.split_once(':') {
+        Some((name, tag)) => (name, Some(tag)),
+        None => (from, None),
+    };
+
+    if !name.eq_ignore_ascii_case(FREEBSD_IMAGE) {
+        return None;
+    }
+
+    let version = match tag {
+        None | Some("latest") => None,
+        Some(tag) if tag.contains('-') => Some(tag.to_uppercase()),
+        Some(tag) => Some(format!("{}-RELEASE", tag)),
+    };
+
+    Some(BootstrapConfig {
+        version,
+        ..Default::default()
+    })
+}
+
+/// Dataset holding the cached base system for a version/architecture pair
+///
+/// `root_dataset` is the configured Kawakaze dataset (e.g., "tank" or "tank/kawakaze").
+pub fn base_dataset(root_dataset: &str, version: &str, architecture: &str) -> String {
+    format!("{}/base/{}-{}", root_dataset, version, architecture)
+}
+
+/// Ensure a cached base system snapshot exists, bootstrapping it on first use
+///
+/// The base system is extracted once into `<root>/base/<version>-<arch>`
+/// (mounted at `mountpoint`, see `Paths::base_root`) and snapshotted as
+/// `@base`. Jails and images are then created with
+/// `clone_snapshot` instead of re-extracting base.txz every time.
+///
+/// # Returns
+///
+/// Returns the full snapshot name (e.g., "tank/base/14.1-RELEASE-amd64@base")
+pub async fn ensure_base_snapshot<B: BaseBootstrapper>(
+    zfs: &Zfs,
+    root_dataset: &str,
+    version: &str,
+    architecture: &str,
+    mountpoint: &Path,
+    bootstrapper: &B,
+) -> Result<String, BootstrapError> {
+    let dataset = base_dataset(root_dataset, version, architecture);
+    let snapshot = format!("{}@{}", dataset, BASE_SNAPSHOT);
+
+    let _guard = BASE_LOCK.lock().await;
+
+    if zfs.snapshot_exists(&snapshot) {
+        return Ok(snapshot);
+    }
+
+    // A dataset without the snapshot is left over from an interrupted bootstrap
+    if zfs.dataset_exists(&dataset) {
+        warn!("Base dataset {} has no snapshot, recreating it", dataset);
+        zfs.destroy(&dataset)
+            .map_err(|e| BootstrapError::Zfs(e.to_string()))?;
+    }
+
+    info!("Creating base image {} ({} {})", dataset, version, architecture);
+
+    zfs.create_dataset_with_options(&dataset, &DatasetOptions::default().mounted_at(mountpoint))
+        .and_then(|()| zfs.mount_at_mountpoint(&dataset))
+        .map_err(|e| BootstrapError::Zfs(e.to_string()))?;
+
+    let result = match bootstrapper.bootstrap(mountpoint, version, architecture).await {
+        Ok(()) => zfs.create_snapshot(&dataset, BASE_SNAPSHOT)
+            .map_err(|e| BootstrapError::Zfs(e.to_string())),
+        Err(e) => Err(e),
+    };
+
+    let _ = zfs.unmount_dataset(&dataset);
+
+    if let Err(e) = result {
+        let _ = zfs.destroy(&dataset);
+        return Err(e);
+    }
+
+    info!("Base image snapshot {} is ready", snapshot);
+    Ok(snapshot)
+}
+
 /// Convert bytes to megabytes
 fn bytes_to_mb(bytes: u64) -> String {
     format!("{:.1} MB", bytes as f64 / 1024.0 / 1024.0)
@@ -784,6 +1090,42 @@ fn bytes_to_mb(bytes: u64) -> String {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_is_bootstrapped() {
+        fn root(files: &[(&str, &str)]) -> tempfile::TempDir {
+            let dir = tempfile::tempdir().unwrap();
+            for (path, content) in files {
+                let path = dir.path().join(path);
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(path, content).unwrap();
+            }
+            dir
+        }
+        let sh = ("bin/sh", "\x7fELF");
+        let update_conf = ("etc/freebsd-update.conf", "KeyPrint ...\n");
+
+        assert!(Bootstrap::is_bootstrapped(root(&[sh, update_conf]).path()));
+
+        // Empty, or only part of a base system
+        assert!(!Bootstrap::is_bootstrapped(root(&[]).path()));
+        assert!(!Bootstrap::is_bootstrapped(root(&[sh]).path()));
+        assert!(!Bootstrap::is_bootstrapped(root(&[update_conf]).path()));
+        assert!(!Bootstrap::is_bootstrapped(Path::new("/nonexistent/kawakaze/root")));
+
+        // An extraction cut short leaves empty files
+        assert!(!Bootstrap::is_bootstrapped(root(&[("bin/sh", ""), update_conf]).path()));
+
+        // A directory or symlink in place of a marker
+        let dir = root(&[update_conf]);
+        std::fs::create_dir_all(dir.path().join("bin/sh")).unwrap();
+        assert!(!Bootstrap::is_bootstrapped(dir.path()));
+        let dir = root(&[update_conf]);
+        let host = root(&[sh]);
+        std::fs::create_dir_all(dir.path().join("bin")).unwrap();
+        std::os::unix::fs::symlink(host.path().join("bin/sh"), dir.path().join("bin/sh")).unwrap();
+        assert!(!Bootstrap::is_bootstrapped(dir.path()));
+    }
+
     #[test]
     fn test_bootstrap_config_default() {
         let config = BootstrapConfig::default();
@@ -792,6 +1134,77 @@ mod tests {
         assert!(config.mirror.is_none());
         assert!(!config.no_cache);
         assert!(config.config_overrides.is_none());
+        assert_eq!(config.sets, ["base"]);
+
+        // Requests from before sets existed get the base system only
+        let config: BootstrapConfig = serde_json::from_str(r#"{"version": "14.1-RELEASE"}"#).unwrap();
+        assert_eq!(config.sets, ["base"]);
+    }
+
+    #[test]
+    fn test_known_sets() {
+        let amd64 = known_sets("14.1-RELEASE", "amd64");
+        assert!(amd64.contains(&"lib32") && amd64.contains(&"src") && !amd64.contains(&"doc"));
+        assert!(known_sets("12.4-RELEASE", "amd64").contains(&"doc"));
+        assert!(!known_sets("13.4-RELEASE", "aarch64").contains(&"lib32"));
+        assert!(known_sets("15.0-RELEASE", "aarch64").contains(&"lib32"));
+        assert!(!known_sets("14.1-RELEASE", "i386").contains(&"lib32"));
+    }
+
+    #[test]
+    fn test_selected_sets() {
+        let config = |sets: &[&str]| BootstrapConfig { sets: sets.iter().map(|s| s.to_string()).collect(), ..Default::default() };
+
+        assert_eq!(config(&["base"]).selected_sets("14.1-RELEASE", "amd64").unwrap(), ["base"]);
+        // Base goes first; repeats are dropped
+        assert_eq!(
+            config(&["lib32", "base", "lib32", "src"]).selected_sets("14.1-RELEASE", "amd64").unwrap(),
+            ["base", "lib32", "src"]
+        );
+
+        let err = config(&["base", "lib32"]).selected_sets("14.1-RELEASE", "i386").unwrap_err();
+        assert!(matches!(err, BootstrapError::InvalidSet(_)));
+        assert!(err.to_string().contains("'lib32'"));
+        assert!(config(&["base", "doc"]).selected_sets("14.1-RELEASE", "amd64").is_err());
+        assert!(config(&["base", "doc"]).selected_sets("12.4-RELEASE", "amd64").is_ok());
+        assert!(config(&["lib32"]).selected_sets("14.1-RELEASE", "amd64").is_err());
+        assert!(config(&[]).selected_sets("14.1-RELEASE", "amd64").is_err());
+    }
+
+    #[test]
+    fn test_set_urls() {
+        let bootstrap = create_test_bootstrap();
+        let urls: Vec<String> = ["base", "lib32"]
+            .iter()
+            .map(|set| bootstrap.build_mirror_url("14.1-RELEASE", "amd64", &set_file(set)))
+            .collect();
+        assert_eq!(urls, [
+            "https://download.freebsd.org/releases/amd64/amd64/14.1-RELEASE/base.txz",
+            "https://download.freebsd.org/releases/amd64/amd64/14.1-RELEASE/lib32.txz",
+        ]);
+    }
+
+    #[test]
+    fn test_parse_manifest() {
+        let manifest = parse_manifest(
+            "base.txz\tabc123\t27000\tbase\t\"Base system\"\ton\n\
+             lib32.txz\tdef456\t1100\tlib32\t\"32-bit compatibility libraries\"\ton\n\
+             \n",
+        );
+        assert_eq!(manifest.get("base.txz").map(String::as_str), Some("abc123"));
+        assert_eq!(manifest.get("lib32.txz").map(String::as_str), Some("def456"));
+        assert_eq!(manifest.len(), 2);
+    }
+
+    #[test]
+    fn test_set_progress() {
+        let part = |index, count| SetPart { set: "base", index, count };
+        assert_eq!(part(0, 1).progress(0), 5);
+        assert_eq!(part(0, 1).progress(100), 90);
+        assert_eq!(part(0, 2).progress(100), part(1, 2).progress(0));
+        assert_eq!(part(1, 2).progress(100), 90);
+        assert_eq!(part(0, 1).progress(500), 90);
+        assert_eq!(part(1, 3).counter(), "(2/3)");
     }
 
     #[test]
@@ -845,6 +1258,32 @@ mod tests {
         assert_eq!(arch, "i386");
     }
 
+    #[test]
+    fn test_parse_base_reference() {
+        let config = parse_base_reference("freebsd:14.1").unwrap();
+        assert_eq!(config.version.as_deref(), Some("14.1-RELEASE"));
+
+        let config = parse_base_reference("FreeBSD:15.0-current").unwrap();
+        assert_eq!(config.version.as_deref(), Some("15.0-CURRENT"));
+
+        assert!(parse_base_reference("freebsd").unwrap().version.is_none());
+        assert!(parse_base_reference("freebsd:latest").unwrap().version.is_none());
+        assert!(parse_base_reference("nginx:1.25").is_none());
+        assert!(parse_base_reference("freebsd-ports").is_none());
+    }
+
+    #[test]
+    fn test_base_dataset() {
+        assert_eq!(
+            base_dataset("tank", "14.1-RELEASE", "amd64"),
+            "tank/base/14.1-RELEASE-amd64"
+        );
+        assert_eq!(
+            base_dataset("tank/kawakaze", "15.0-RELEASE", "aarch64"),
+            "tank/kawakaze/base/15.0-RELEASE-aarch64"
+        );
+    }
+
     #[test]
     fn test_bootstrap_status_serialization() {
         let status = BootstrapStatus::Downloading;
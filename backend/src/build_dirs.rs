@@ -0,0 +1,312 @@
+//! Build mount points under `builds/`
+//!
+//! Each build mounts its dataset at `builds/<name>-<build id>`
+//! ([`Paths::build_root`](crate::paths::Paths::build_root)), so two builds of
+//! one name never share a directory. [`BuildDir`] creates that directory
+//! fresh and, when dropped, unmounts whatever is mounted there and removes
+//! it, whichever way the build ended.
+//!
+//! Directories a crashed daemon left behind are removed by [`sweep`] at
+//! start, except those with something mounted at or below them or whose
+//! build is still running.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+
+use tracing::warn;
+
+/// Unmounts a dataset; errors are only logged
+pub type Unmount = Box<dyn Fn(&str) -> Result<(), String> + Send>;
+
+/// A build's mount point, unmounted and removed on drop
+pub struct BuildDir {
+    path: PathBuf,
+    /// Dataset mounted at `path`, if any
+    dataset: Option<String>,
+    unmount: Unmount,
+}
+
+impl BuildDir {
+    /// Create `path`; a directory already there is an error, never reused
+    ///
+    /// Missing parents (the `builds/` directory) are created `0700`: build
+    /// roots hold whole base systems, setuid binaries included, that only
+    /// root has any business reaching. The mount point itself is `0755`,
+    /// as a jail's `/` should be.
+    pub fn create(path: impl Into<PathBuf>, unmount: Unmount) -> io::Result<Self> {
+        let path = path.into();
+        if let Some(parent) = path.parent() {
+            fs::DirBuilder::new().recursive(true).mode(0o700).create(parent)?;
+        }
+        fs::DirBuilder::new().mode(0o755).create(&path).map_err(|e| match e.kind() {
+            io::ErrorKind::AlreadyExists => {
+                io::Error::new(e.kind(), format!("build directory {} already exists", path.display()))
+            }
+            _ => e,
+        })?;
+        Ok(Self { path, dataset: None, unmount })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Record the dataset now mounted here; called again when the dataset
+    /// is renamed, since the mount moves with it
+    pub fn mounted(&mut self, dataset: impl Into<String>) {
+        self.dataset = Some(dataset.into());
+    }
+}
+
+impl Drop for BuildDir {
+    fn drop(&mut self) {
+        if let Some(dataset) = self.dataset.take()
+            && let Err(e) = (self.unmount)(&dataset)
+        {
+            warn!("Failed to unmount {} from {}: {}", dataset, self.path.display(), e);
+        }
+        match fs::remove_dir(&self.path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            // Still mounted or holding files; the next daemon start sweeps it
+            Err(e) => warn!("Leaving build directory {} in place: {}", self.path.display(), e),
+        }
+    }
+}
+
+/// Mount points listed in fstab(5) format: `mount -p` output on FreeBSD,
+/// `/proc/self/mounts` elsewhere. Spaces and tabs in paths are octal
+/// escapes (`\040`).
+pub fn parse_mount_points(table: &str) -> HashSet<PathBuf> {
+    table
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .map(|field| PathBuf::from(unescape(field)))
+        .collect()
+}
+
+fn unescape(field: &str) -> String {
+    let mut out = String::with_capacity(field.len());
+    let mut rest = field;
+    while let Some(i) = rest.find('\\') {
+        out.push_str(&rest[..i]);
+        let code = rest.get(i + 1..i + 4).and_then(|digits| u8::from_str_radix(digits, 8).ok());
+        match code {
+            Some(byte) => {
+                out.push(byte as char);
+                rest = &rest[i + 4..];
+            }
+            None => {
+                out.push('\\');
+                rest = &rest[i + 1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// The host's current mount points
+pub fn mount_points() -> io::Result<HashSet<PathBuf>> {
+    #[cfg(target_os = "freebsd")]
+    let table = {
+        use crate::cmdtrace::TracedCommand;
+        let output = std::process::Command::new("mount").arg("-p").traced_output()?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!("mount -p: {}", String::from_utf8_lossy(&output.stderr).trim())));
+        }
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    #[cfg(not(target_os = "freebsd"))]
+    let table = fs::read_to_string("/proc/self/mounts")?;
+
+    Ok(parse_mount_points(&table))
+}
+
+/// Why a leftover build directory is kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Keep {
+    /// Something is mounted at or below it
+    Mounted,
+    /// It belongs to a build that is still queued or running
+    Active,
+}
+
+/// Whether the build directory `dir` may be removed, given the mount
+/// points and the IDs of active builds
+pub fn keep(dir: &Path, mounts: &HashSet<PathBuf>, active_builds: &[String]) -> Option<Keep> {
+    if mounts.iter().any(|mount| mount.starts_with(dir)) {
+        return Some(Keep::Mounted);
+    }
+    let name = dir.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    if active_builds.iter().any(|id| name.ends_with(&format!("-{}", id))) {
+        return Some(Keep::Active);
+    }
+    None
+}
+
+/// Remove leftover directories under `builds` that nothing is mounted on
+/// and no active build owns; returns what was removed
+pub fn sweep(builds: &Path, mounts: &HashSet<PathBuf>, active_builds: &[String]) -> io::Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(builds) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut removed = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        if !entry.file_type()?.is_dir() || keep(&path, mounts, active_builds).is_some() {
+            continue;
+        }
+        match fs::remove_dir_all(&path) {
+            Ok(()) => removed.push(path),
+            Err(e) => warn!("Failed to remove leftover build directory {}: {}", path.display(), e),
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use std::sync::{Arc, Mutex};
+
+    /// An unmount that records its calls and then fails or succeeds
+    fn recording(fail: bool) -> (Arc<Mutex<Vec<String>>>, Unmount) {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&calls);
+        let unmount: Unmount = Box::new(move |dataset: &str| {
+            log.lock().unwrap().push(dataset.to_string());
+            if fail { Err("busy".to_string()) } else { Ok(()) }
+        });
+        (calls, unmount)
+    }
+
+    #[test]
+    fn test_parse_mount_points() {
+        let table = "\
+zroot/ROOT/default\t/\tzfs\trw\t0 0
+devfs\t/dev\tdevfs\trw\t0 0
+zroot/kawakaze/images/build-web\t/var/lib/kawakaze/builds/web-1234\tzfs\trw\t0 0
+devfs /var/lib/kawakaze/builds/web-1234/dev devfs rw 0 0
+# comment
+tank/data /mnt/with\\040space zfs rw 0 0
+";
+        let mounts = parse_mount_points(table);
+        assert_eq!(mounts.len(), 5);
+        assert!(mounts.contains(Path::new("/var/lib/kawakaze/builds/web-1234")));
+        assert!(mounts.contains(Path::new("/var/lib/kawakaze/builds/web-1234/dev")));
+        assert!(mounts.contains(Path::new("/mnt/with space")));
+        assert_eq!(unescape("a\\tb\\0"), "a\\tb\\0");
+    }
+
+    #[test]
+    fn test_build_dir_cleans_up_on_early_return() {
+        let builds = tempfile::tempdir().unwrap();
+        let path = builds.path().join("builds/web-1234");
+        let (calls, unmount) = recording(false);
+
+        // A build that fails before mounting anything
+        let fail_before_mount = || -> io::Result<()> {
+            let dir = BuildDir::create(&path, unmount)?;
+            assert!(dir.path().is_dir());
+            // builds/ is root's alone
+            assert_eq!(fs::metadata(builds.path().join("builds"))?.permissions().mode() & 0o777, 0o700);
+            Err(io::Error::other("parse error"))
+        };
+        assert!(fail_before_mount().is_err());
+        assert!(!path.exists());
+        assert!(calls.lock().unwrap().is_empty());
+
+        // A build that fails after mounting: unmounted, then removed
+        let (calls, unmount) = recording(false);
+        let fail_after_mount = || -> io::Result<()> {
+            let mut dir = BuildDir::create(&path, unmount)?;
+            dir.mounted("tank/images/build-web");
+            Err(io::Error::other("step failed"))
+        };
+        assert!(fail_after_mount().is_err());
+        assert!(!path.exists());
+        assert_eq!(*calls.lock().unwrap(), ["tank/images/build-web"]);
+
+        // A successful build renamed its dataset; the new name is unmounted
+        let (calls, unmount) = recording(false);
+        {
+            let mut dir = BuildDir::create(&path, unmount).unwrap();
+            dir.mounted("tank/images/build-web");
+            dir.mounted("tank/images/web");
+        }
+        assert!(!path.exists());
+        assert_eq!(*calls.lock().unwrap(), ["tank/images/web"]);
+    }
+
+    #[test]
+    fn test_build_dir_is_fresh_and_tolerates_leftovers() {
+        let builds = tempfile::tempdir().unwrap();
+        let path = builds.path().join("web-1234");
+        fs::create_dir(&path).unwrap();
+        let (_, unmount) = recording(false);
+        let err = BuildDir::create(&path, unmount).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        fs::remove_dir(&path).unwrap();
+
+        // A failed unmount leaves files behind; the directory stays, no panic
+        let (calls, unmount) = recording(true);
+        {
+            let mut dir = BuildDir::create(&path, unmount).unwrap();
+            dir.mounted("tank/images/build-web");
+            fs::write(path.join("etc"), "").unwrap();
+        }
+        assert_eq!(calls.lock().unwrap().len(), 1);
+        assert!(path.join("etc").exists());
+    }
+
+    #[test]
+    fn test_keep_decision() {
+        let builds = Path::new("/var/lib/kawakaze/builds");
+        let mounts: HashSet<PathBuf> =
+            [PathBuf::from("/var/lib/kawakaze/builds/web-aaaa"), PathBuf::from("/var/lib/kawakaze/builds/db-bbbb/dev")]
+                .into_iter()
+                .collect();
+        let active = vec!["cccc".to_string()];
+
+        assert_eq!(keep(&builds.join("web-aaaa"), &mounts, &active), Some(Keep::Mounted));
+        // A mount below the directory counts too
+        assert_eq!(keep(&builds.join("db-bbbb"), &mounts, &active), Some(Keep::Mounted));
+        assert_eq!(keep(&builds.join("app-cccc"), &mounts, &active), Some(Keep::Active));
+        assert_eq!(keep(&builds.join("app-dddd"), &mounts, &active), None);
+        // Directories from before build IDs were part of the name
+        assert_eq!(keep(&builds.join("web"), &mounts, &active), None);
+        // A prefix of a mounted path isn't a mount below it
+        assert_eq!(keep(&builds.join("web-aa"), &mounts, &active), None);
+    }
+
+    #[test]
+    fn test_sweep() {
+        let builds = tempfile::tempdir().unwrap();
+        for dir in ["web-aaaa", "app-cccc", "old/etc", "mounted-bbbb"] {
+            fs::create_dir_all(builds.path().join(dir)).unwrap();
+        }
+        fs::write(builds.path().join("old/etc/rc.conf"), "").unwrap();
+        fs::write(builds.path().join("notes.txt"), "").unwrap();
+        let mounts: HashSet<PathBuf> = [builds.path().join("mounted-bbbb")].into_iter().collect();
+
+        let mut removed = sweep(builds.path(), &mounts, &["cccc".to_string()]).unwrap();
+        removed.sort();
+        assert_eq!(removed, [builds.path().join("old"), builds.path().join("web-aaaa")]);
+        assert!(builds.path().join("app-cccc").exists());
+        assert!(builds.path().join("mounted-bbbb").exists());
+        assert!(builds.path().join("notes.txt").exists());
+
+        assert!(sweep(&builds.path().join("missing"), &mounts, &[]).unwrap().is_empty());
+    }
+}
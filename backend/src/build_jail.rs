@@ -0,0 +1,333 @@
+//! Network isolation for RUN steps during image builds
+//!
+//! With [`BuildNetwork::Default`] RUN steps execute in a chroot of the build
+//! root and share the host's network. Before each step the builder checks
+//! that the API socket isn't reachable from the build root
+//! ([`socket_reachable`]), so a Dockerfile can't drive the daemon building it.
+//!
+//! With [`BuildNetwork::None`] each RUN step executes in a temporary jail
+//! whose path is the build root and which has no addresses and no raw
+//! sockets, so the kernel enforces the isolation. The jail is removed after
+//! the step whether it succeeded, failed or timed out.
+//!
+//! Jails are driven through the [`JailBackend`] trait so the lifecycle can
+//! be tested without FreeBSD.
+
+use std::collections::HashMap;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::cmdtrace::{self, TracedCommand};
+
+/// Network available to RUN steps
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BuildNetwork {
+    /// The host's network, from a chroot
+    #[default]
+    Default,
+    /// No network, from a temporary jail
+    None,
+}
+
+impl BuildNetwork {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BuildNetwork::Default => "default",
+            BuildNetwork::None => "none",
+        }
+    }
+}
+
+impl std::str::FromStr for BuildNetwork {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" => Ok(BuildNetwork::Default),
+            "none" => Ok(BuildNetwork::None),
+            other => Err(format!("Unknown build network '{}' (expected \"default\" or \"none\")", other)),
+        }
+    }
+}
+
+/// A temporary jail for one RUN step
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TempJail {
+    pub name: String,
+    /// Root of the jail: the build mountpoint
+    pub path: PathBuf,
+    /// Kill the step after this long
+    pub timeout: Option<Duration>,
+    /// Append the step's output to this file (the build log)
+    pub log: Option<PathBuf>,
+}
+
+impl TempJail {
+    /// A jail with a fresh name rooted at `path`
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        Self {
+            name: format!("kawakaze-build-{}", crate::names::short_id(&id)),
+            path: path.into(),
+            timeout: None,
+            log: None,
+        }
+    }
+
+    /// Parameters for `jail -c`: no addresses, no raw sockets, nothing
+    /// kept running once the step's command exits
+    pub fn create_args(&self) -> Vec<String> {
+        vec![
+            "-c".to_string(),
+            format!("name={}", self.name),
+            format!("path={}", self.path.display()),
+            format!("host.hostname={}", self.name),
+            "ip4=disable".to_string(),
+            "ip6=disable".to_string(),
+            "allow.raw_sockets=0".to_string(),
+            "persist".to_string(),
+        ]
+    }
+}
+
+/// Creates, runs commands in and removes jails
+pub trait JailBackend {
+    /// Create the jail, without running anything in it
+    fn create(&self, jail: &TempJail) -> Result<(), String>;
+
+    /// Run `sh -c cmd` in the jail; `Ok(false)` if it exited unsuccessfully,
+    /// `Err` if it couldn't run or timed out
+    fn exec(&self, jail: &TempJail, cmd: &str, env: &HashMap<String, String>) -> Result<bool, String>;
+
+    /// Remove the jail and everything still running in it
+    fn remove(&self, jail: &TempJail) -> Result<(), String>;
+}
+
+/// Drives jails with `jail(8)` and `jexec(8)`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CommandJailBackend;
+
+impl JailBackend for CommandJailBackend {
+    fn create(&self, jail: &TempJail) -> Result<(), String> {
+        let output = Command::new("jail")
+            .args(jail.create_args())
+            .traced_output()
+            .map_err(|e| format!("Failed to run jail: {}", e))?;
+        if !output.status.success() {
+            return Err(format!("jail -c failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+        }
+        Ok(())
+    }
+
+    fn exec(&self, jail: &TempJail, cmd: &str, env: &HashMap<String, String>) -> Result<bool, String> {
+        let args = vec![jail.name.clone(), "/bin/sh".to_string(), "-c".to_string(), cmd.to_string()];
+        let started = Instant::now();
+        let mut command = Command::new("jexec");
+        command.args(&args).envs(env).stdin(Stdio::null());
+        if let Some(log) = &jail.log {
+            let file = crate::build_log::open_for_output(log).map_err(|e| format!("Failed to open build log: {}", e))?;
+            let stderr = file.try_clone().map_err(|e| format!("Failed to open build log: {}", e))?;
+            command.stdout(file).stderr(stderr);
+        }
+        let mut child = command.spawn().map_err(|e| format!("Failed to run jexec: {}", e))?;
+
+        let status = loop {
+            match child.try_wait().map_err(|e| format!("Failed to wait for jexec: {}", e))? {
+                Some(status) => break status,
+                None if jail.timeout.is_some_and(|timeout| started.elapsed() >= timeout) => {
+                    // Removing the jail kills whatever the step left behind
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    cmdtrace::record("jexec", &args, started, false);
+                    return Err(format!("RUN timed out after {}s", started.elapsed().as_secs()));
+                }
+                None => std::thread::sleep(Duration::from_millis(50)),
+            }
+        };
+        cmdtrace::record("jexec", &args, started, status.success());
+        Ok(status.success())
+    }
+
+    fn remove(&self, jail: &TempJail) -> Result<(), String> {
+        let output = Command::new("jail")
+            .args(["-r", &jail.name])
+            .traced_output()
+            .map_err(|e| format!("Failed to run jail: {}", e))?;
+        if !output.status.success() {
+            return Err(format!("jail -r failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+        }
+        Ok(())
+    }
+}
+
+/// Run one RUN step in a temporary jail, removing the jail afterwards no
+/// matter how the step ended
+pub fn run_in_temp_jail(
+    backend: &impl JailBackend,
+    jail: &TempJail,
+    cmd: &str,
+    env: &HashMap<String, String>,
+) -> Result<(), String> {
+    backend.create(jail)?;
+    debug!("Created build jail {} at {}", jail.name, jail.path.display());
+
+    let result = backend.exec(jail, cmd, env);
+
+    if let Err(e) = backend.remove(jail) {
+        warn!("Failed to remove build jail {}: {}", jail.name, e);
+        if result.is_ok() {
+            return Err(format!("Failed to remove build jail {}: {}", jail.name, e));
+        }
+    }
+
+    match result {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(format!("RUN failed: {}", cmd)),
+        Err(e) => Err(e),
+    }
+}
+
+/// Whether the API socket at `socket` can be reached from a chroot of
+/// `root`: the root is the host's own, or the socket's path inside the
+/// root is the socket itself
+pub fn socket_reachable(root: &Path, socket: &Path) -> io::Result<bool> {
+    if root.canonicalize()? == Path::new("/") {
+        return Ok(true);
+    }
+    let host = match std::fs::symlink_metadata(socket) {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    let inside = root.join(socket.strip_prefix("/").unwrap_or(socket));
+    match std::fs::symlink_metadata(&inside) {
+        Ok(meta) => Ok(meta.dev() == host.dev() && meta.ino() == host.ino()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Records the lifecycle calls; `exec` answers with `outcome`
+    struct FakeBackend {
+        calls: Mutex<Vec<String>>,
+        outcome: Result<bool, String>,
+        fail_create: bool,
+    }
+
+    impl FakeBackend {
+        fn new(outcome: Result<bool, String>) -> Self {
+            Self { calls: Mutex::new(Vec::new()), outcome, fail_create: false }
+        }
+
+        fn calls(&self) -> Vec<String> {
+            self.calls.lock().unwrap().clone()
+        }
+    }
+
+    impl JailBackend for FakeBackend {
+        fn create(&self, jail: &TempJail) -> Result<(), String> {
+            self.calls.lock().unwrap().push(format!("create {}", jail.name));
+            if self.fail_create { Err("jail -c failed".to_string()) } else { Ok(()) }
+        }
+
+        fn exec(&self, _jail: &TempJail, cmd: &str, env: &HashMap<String, String>) -> Result<bool, String> {
+            self.calls.lock().unwrap().push(format!("exec {} env={}", cmd, env.len()));
+            self.outcome.clone()
+        }
+
+        fn remove(&self, jail: &TempJail) -> Result<(), String> {
+            self.calls.lock().unwrap().push(format!("remove {}", jail.name));
+            Ok(())
+        }
+    }
+
+    fn jail() -> TempJail {
+        TempJail { name: "kawakaze-build-test".to_string(), path: PathBuf::from("/tank/images/web"), timeout: None, log: None }
+    }
+
+    #[test]
+    fn test_temp_jail_lifecycle() {
+        let backend = FakeBackend::new(Ok(true));
+        let env = HashMap::from([("TOKEN".to_string(), "t".to_string())]);
+
+        run_in_temp_jail(&backend, &jail(), "make install", &env).unwrap();
+        assert_eq!(backend.calls(), [
+            "create kawakaze-build-test",
+            "exec make install env=1",
+            "remove kawakaze-build-test",
+        ]);
+    }
+
+    #[test]
+    fn test_temp_jail_removed_on_failure_and_timeout() {
+        let failed = FakeBackend::new(Ok(false));
+        let err = run_in_temp_jail(&failed, &jail(), "false", &HashMap::new()).unwrap_err();
+        assert!(err.contains("RUN failed"));
+        assert_eq!(failed.calls().last().unwrap(), "remove kawakaze-build-test");
+
+        let timed_out = FakeBackend::new(Err("RUN timed out after 600s".to_string()));
+        let err = run_in_temp_jail(&timed_out, &jail(), "sleep 1000", &HashMap::new()).unwrap_err();
+        assert!(err.contains("timed out"));
+        assert_eq!(timed_out.calls().last().unwrap(), "remove kawakaze-build-test");
+
+        // Nothing to remove if the jail was never created
+        let uncreated = FakeBackend { fail_create: true, ..FakeBackend::new(Ok(true)) };
+        assert!(run_in_temp_jail(&uncreated, &jail(), "true", &HashMap::new()).is_err());
+        assert_eq!(uncreated.calls(), ["create kawakaze-build-test"]);
+    }
+
+    #[test]
+    fn test_create_args_isolate_network() {
+        let args = jail().create_args();
+        assert_eq!(args[..3], ["-c", "name=kawakaze-build-test", "path=/tank/images/web"]);
+        for param in ["ip4=disable", "ip6=disable", "allow.raw_sockets=0"] {
+            assert!(args.contains(&param.to_string()), "missing {}", param);
+        }
+        assert!(!args.iter().any(|a| a.starts_with("ip4.addr") || a == "vnet"));
+
+        let first = TempJail::new("/tank/images/web");
+        assert!(first.name.starts_with("kawakaze-build-"));
+        assert_ne!(first.name, TempJail::new("/tank/images/web").name);
+    }
+
+    #[test]
+    fn test_socket_reachable() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("run/kawakaze.sock");
+        std::fs::create_dir_all(socket.parent().unwrap()).unwrap();
+        let _listener = std::os::unix::net::UnixListener::bind(&socket).unwrap();
+
+        let root = dir.path().join("root");
+        std::fs::create_dir_all(&root).unwrap();
+        assert!(!socket_reachable(&root, &socket).unwrap());
+        assert!(socket_reachable(Path::new("/"), &socket).unwrap());
+
+        // The socket linked into the build root at its host path
+        let inside = root.join(socket.strip_prefix("/").unwrap());
+        std::fs::create_dir_all(inside.parent().unwrap()).unwrap();
+        std::fs::hard_link(&socket, &inside).unwrap();
+        assert!(socket_reachable(&root, &socket).unwrap());
+
+        assert!(!socket_reachable(&root, &dir.path().join("missing.sock")).unwrap());
+    }
+
+    #[test]
+    fn test_build_network_parse() {
+        assert_eq!("none".parse::<BuildNetwork>(), Ok(BuildNetwork::None));
+        assert_eq!("default".parse::<BuildNetwork>(), Ok(BuildNetwork::Default));
+        assert!("host".parse::<BuildNetwork>().is_err());
+        assert_eq!(serde_json::to_value(BuildNetwork::None).unwrap(), "none");
+    }
+}
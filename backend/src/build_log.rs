@@ -0,0 +1,173 @@
+//! Persistent image build and bootstrap logs
+//!
+//! Every build writes a log under `storage.build_log_path` named after the
+//! image ID the build produces, and every bootstrap one under
+//! `storage.bootstrap_log_path` named after its jail. Progress lines are
+//! JSON log lines on the [`PROGRESS_STREAM`] stream; RUN output is written
+//! to the same file as it comes, as plain text, which `logs::parse_log`
+//! reads as such. Reading a finished log and following a running build both
+//! read this file, so they show the same lines.
+//!
+//! Logs are removed with their image or jail, and [`sweep`] keeps each
+//! directory within the configured age and size caps.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::config::StorageConfig;
+
+/// Stream name of progress lines
+pub const PROGRESS_STREAM: &str = "build";
+
+/// Log file of the build producing `image_id`
+pub fn build_log_file(dir: &Path, image_id: &str) -> PathBuf {
+    dir.join(format!("{}.log", image_id))
+}
+
+/// Log file of the bootstrap of `jail`
+pub fn bootstrap_log_file(dir: &Path, jail: &str) -> PathBuf {
+    dir.join(format!("{}.log", jail))
+}
+
+/// Append a progress line, stamped with the current time
+pub fn append_progress(path: &Path, line: &str) -> io::Result<()> {
+    crate::logs::append_output(path, PROGRESS_STREAM, line, chrono::Utc::now().timestamp())
+}
+
+/// Open a log for RUN output to be appended to, creating it if needed
+pub fn open_for_output(path: &Path) -> io::Result<fs::File> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Remove a log if it exists
+pub fn remove(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Age and size caps for a log directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retention {
+    pub max_age: Duration,
+    pub max_bytes: u64,
+}
+
+impl Retention {
+    pub fn from_config(storage: &StorageConfig) -> Self {
+        Self {
+            max_age: Duration::from_secs(storage.log_retention_days * 24 * 60 * 60),
+            max_bytes: storage.log_retention_bytes,
+        }
+    }
+}
+
+/// Remove the `.log` files in `dir` older than `max_age`, then the oldest
+/// remaining ones while the directory holds more than `max_bytes`. Files in
+/// `keep` (logs still being written) are never removed. Returns what was
+/// removed.
+pub fn sweep(dir: &Path, retention: Retention, keep: &HashSet<PathBuf>, now: SystemTime) -> io::Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut logs = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        let meta = entry.metadata()?;
+        if meta.is_file() && path.extension().is_some_and(|ext| ext == "log") {
+            logs.push((meta.modified()?, meta.len(), path));
+        }
+    }
+    logs.sort();
+
+    let mut removed = Vec::new();
+    let mut total: u64 = logs.iter().map(|(_, len, _)| len).sum();
+    for (modified, len, path) in logs {
+        if keep.contains(&path) {
+            continue;
+        }
+        let expired = now.duration_since(modified).is_ok_and(|age| age > retention.max_age);
+        if expired || total > retention.max_bytes {
+            remove(&path)?;
+            total -= len;
+            removed.push(path);
+        }
+    }
+
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_log(dir: &Path, name: &str, len: usize, age: Duration) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, "x".repeat(len)).unwrap();
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() - age).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_sweep_by_age() {
+        let dir = tempfile::tempdir().unwrap();
+        let day = Duration::from_secs(24 * 60 * 60);
+        let old = write_log(dir.path(), "old.log", 10, day * 40);
+        let recent = write_log(dir.path(), "recent.log", 10, day);
+        let other = write_log(dir.path(), "notes.txt", 10, day * 40);
+        let retention = Retention { max_age: day * 30, max_bytes: u64::MAX };
+
+        let removed = sweep(dir.path(), retention, &HashSet::new(), SystemTime::now()).unwrap();
+        assert_eq!(removed, [old]);
+        assert!(recent.exists() && other.exists());
+    }
+
+    #[test]
+    fn test_sweep_by_size_removes_oldest_first_and_keeps_active() {
+        let dir = tempfile::tempdir().unwrap();
+        let hour = Duration::from_secs(60 * 60);
+        let active = write_log(dir.path(), "active.log", 100, hour * 4);
+        let oldest = write_log(dir.path(), "a.log", 100, hour * 3);
+        let middle = write_log(dir.path(), "b.log", 100, hour * 2);
+        let newest = write_log(dir.path(), "c.log", 100, hour);
+        let retention = Retention { max_age: hour * 24, max_bytes: 250 };
+
+        let keep = HashSet::from([active.clone()]);
+        let removed = sweep(dir.path(), retention, &keep, SystemTime::now()).unwrap();
+        assert_eq!(removed, [oldest, middle]);
+        assert!(active.exists() && newest.exists());
+
+        // Nothing to do for a missing directory
+        assert!(sweep(&dir.path().join("missing"), retention, &keep, SystemTime::now()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_progress_and_output_read_back_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = build_log_file(&dir.path().join("builds"), "img-1");
+
+        append_progress(&path, "Step 1/2: RUN make").unwrap();
+        io::Write::write_all(&mut open_for_output(&path).unwrap(), b"cc -o app app.c\n").unwrap();
+        append_progress(&path, "Build complete").unwrap();
+
+        let entries = crate::logs::read_log(&path).unwrap();
+        let lines: Vec<_> = entries.iter().map(|e| (e.stream.as_str(), e.message.as_str())).collect();
+        assert_eq!(lines, [("build", "Step 1/2: RUN make"), ("stdout", "cc -o app app.c"), ("build", "Build complete")]);
+
+        remove(&path).unwrap();
+        remove(&path).unwrap();
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,436 @@
+//! External command tracing
+//!
+//! Every `zfs`, `jail`, `jexec`, `mount`, `ifconfig`, `pfctl`, ... call goes
+//! through [`TracedCommand::traced_output`], which returns exactly what
+//! `Command::output` would and additionally records the program, its
+//! (sanitized) arguments, how long it took and whether it succeeded.
+//!
+//! Records feed process-wide per-program counters and a list of the
+//! slowest commands of the last hour. Commands slower than the configured
+//! threshold are logged at warn level together with the resource they
+//! worked on (the last non-flag argument, e.g. the dataset or jail name).
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::process::{Command, Output, Stdio};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+use crate::timestamp::Timestamp;
+
+/// Default duration above which a command is logged as slow
+pub const DEFAULT_SLOW_THRESHOLD: Duration = Duration::from_secs(5);
+
+/// Number of slow commands kept for `slowest_recent`
+const SLOWEST_KEEP: usize = 20;
+
+/// How far back `slowest_recent` looks, in seconds
+const SLOWEST_WINDOW_SECS: i64 = 60 * 60;
+
+/// Longest argument kept verbatim; longer ones are cut
+const MAX_ARG_LEN: usize = 128;
+
+/// Aggregates for one program
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommandStats {
+    pub count: u64,
+    pub total_ms: u64,
+    pub failures: u64,
+}
+
+/// One finished external command
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommandRecord {
+    pub program: String,
+    pub args: Vec<String>,
+    /// What the command operated on, if it could be told from the arguments
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resource: Option<String>,
+    pub duration_ms: u64,
+    pub success: bool,
+    pub finished_at: Timestamp,
+}
+
+impl CommandRecord {
+    /// Record for `program args...`, sanitizing the arguments
+    pub fn new(program: &str, args: &[String], duration: Duration, success: bool, finished_at: Timestamp) -> Self {
+        Self {
+            program: program.to_string(),
+            args: args.iter().map(|arg| sanitize_arg(arg)).collect(),
+            resource: resource_of(args),
+            duration_ms: duration.as_millis() as u64,
+            success,
+            finished_at,
+        }
+    }
+}
+
+/// Snapshot of the collected metrics
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommandMetricsInfo {
+    /// Aggregates per program name
+    pub commands: BTreeMap<String, CommandStats>,
+    /// Slowest commands of the last hour, slowest first
+    pub slowest_recent: Vec<CommandRecord>,
+    pub slow_threshold_ms: u64,
+}
+
+/// Per-program aggregates plus the slowest recent commands
+#[derive(Debug, Clone)]
+pub struct CommandMetrics {
+    commands: BTreeMap<String, CommandStats>,
+    slowest: Vec<CommandRecord>,
+    slow_threshold: Duration,
+}
+
+impl Default for CommandMetrics {
+    fn default() -> Self {
+        Self {
+            commands: BTreeMap::new(),
+            slowest: Vec::new(),
+            slow_threshold: DEFAULT_SLOW_THRESHOLD,
+        }
+    }
+}
+
+impl CommandMetrics {
+    /// Add a finished command; returns whether it counts as slow
+    pub fn record(&mut self, record: CommandRecord) -> bool {
+        let stats = self.commands.entry(record.program.clone()).or_default();
+        stats.count += 1;
+        stats.total_ms += record.duration_ms;
+        if !record.success {
+            stats.failures += 1;
+        }
+
+        let slow = record.duration_ms >= self.slow_threshold.as_millis() as u64;
+
+        let cutoff = record.finished_at.as_secs() - SLOWEST_WINDOW_SECS;
+        self.slowest.retain(|r| r.finished_at.as_secs() > cutoff);
+        let fastest_kept = self.slowest.last().map(|r| r.duration_ms).unwrap_or(0);
+        if self.slowest.len() < SLOWEST_KEEP || record.duration_ms > fastest_kept {
+            self.slowest.push(record);
+            self.slowest.sort_by_key(|r| std::cmp::Reverse(r.duration_ms));
+            self.slowest.truncate(SLOWEST_KEEP);
+        }
+
+        slow
+    }
+
+    /// Slowest commands that finished within the last hour before `now`
+    pub fn slowest_recent(&self, now: Timestamp) -> Vec<CommandRecord> {
+        self.slowest
+            .iter()
+            .filter(|r| r.finished_at.as_secs() > now.as_secs() - SLOWEST_WINDOW_SECS)
+            .cloned()
+            .collect()
+    }
+
+    pub fn set_slow_threshold(&mut self, threshold: Duration) {
+        self.slow_threshold = threshold;
+    }
+
+    /// Snapshot for the API
+    pub fn info(&self, now: Timestamp) -> CommandMetricsInfo {
+        CommandMetricsInfo {
+            commands: self.commands.clone(),
+            slowest_recent: self.slowest_recent(now),
+            slow_threshold_ms: self.slow_threshold.as_millis() as u64,
+        }
+    }
+}
+
+static METRICS: LazyLock<Mutex<CommandMetrics>> = LazyLock::new(Default::default);
+
+/// Set the duration above which commands are logged as slow
+pub fn set_slow_threshold(threshold: Duration) {
+    if let Ok(mut metrics) = METRICS.lock() {
+        metrics.set_slow_threshold(threshold);
+    }
+}
+
+/// Current metrics for every traced command
+pub fn metrics() -> CommandMetricsInfo {
+    let now = Timestamp::now();
+    METRICS.lock().map(|m| m.info(now)).unwrap_or_default()
+}
+
+/// Record a command that was run without `traced_output` (e.g. spawned
+/// to stream its output)
+pub fn record(program: &str, args: &[String], started: Instant, success: bool) {
+    let record = CommandRecord::new(program, args, started.elapsed(), success, Timestamp::now());
+    debug!(
+        program = %record.program,
+        args = ?record.args,
+        duration_ms = record.duration_ms,
+        success = record.success,
+        "external command finished"
+    );
+
+    let slow = METRICS.lock().map(|mut m| m.record(record.clone())).unwrap_or(false);
+    if slow {
+        warn!(
+            "slow external command: {} {} took {}ms (resource: {})",
+            record.program,
+            record.args.join(" "),
+            record.duration_ms,
+            record.resource.as_deref().unwrap_or("-")
+        );
+    }
+}
+
+/// `Command::output` with tracing
+pub trait TracedCommand {
+    /// Run the command like `output()`, recording its duration and result
+    fn traced_output(&mut self) -> io::Result<Output>;
+
+    /// Like `traced_output`, writing `input` to the command's stdin first
+    fn traced_output_with_input(&mut self, input: &[u8]) -> io::Result<Output>;
+}
+
+impl TracedCommand for Command {
+    fn traced_output(&mut self) -> io::Result<Output> {
+        traced(self, |cmd| cmd.output())
+    }
+
+    fn traced_output_with_input(&mut self, input: &[u8]) -> io::Result<Output> {
+        traced(self, |cmd| {
+            cmd.stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .and_then(|mut child| {
+                    if let Some(mut stdin) = child.stdin.take() {
+                        stdin.write_all(input)?;
+                    }
+                    child.wait_with_output()
+                })
+        })
+    }
+}
+
+/// Like [`TracedCommand::traced_output`], but kills the command once
+/// `cancel` fires; `None` means it was killed. `on_spawn` gets the pid of
+/// the command as soon as it runs.
+pub async fn cancellable_output(mut cmd: Command, cancel: &CancellationToken, on_spawn: impl FnOnce(u32)) -> io::Result<Option<Output>> {
+    let program = cmd.get_program().to_string_lossy().into_owned();
+    let args: Vec<String> = cmd.get_args().map(|a| a.to_string_lossy().into_owned()).collect();
+
+    let started = Instant::now();
+    let child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    let pid = child.id();
+    on_spawn(pid);
+    let mut waiter = tokio::task::spawn_blocking(move || child.wait_with_output());
+
+    let (output, killed) = tokio::select! {
+        output = &mut waiter => (output, false),
+        _ = cancel.cancelled() => {
+            // The child isn't reaped until the waiter returns, so the pid is still ours
+            if !waiter.is_finished() {
+                unsafe { libc::kill(pid as libc::pid_t, libc::SIGKILL) };
+            }
+            ((&mut waiter).await, true)
+        }
+    };
+    let output = output.map_err(io::Error::other)?;
+    record(&program, &args, started, !killed && output.as_ref().is_ok_and(|o| o.status.success()));
+
+    if killed { output.map(|_| None) } else { output.map(Some) }
+}
+
+fn traced(cmd: &mut Command, run: impl FnOnce(&mut Command) -> io::Result<Output>) -> io::Result<Output> {
+    let program = cmd.get_program().to_string_lossy().into_owned();
+    let args: Vec<String> = cmd.get_args().map(|a| a.to_string_lossy().into_owned()).collect();
+
+    #[cfg(test)]
+    if let Some(output) = recorder::intercept(&program, &args) {
+        return Ok(output);
+    }
+
+    let started = Instant::now();
+    let output = run(cmd);
+    let success = output.as_ref().is_ok_and(|o| o.status.success());
+    record(&program, &args, started, success);
+
+    output
+}
+
+/// Commands a test would run, answered without running them
+///
+/// While a [`Recorder`](recorder::Recorder) is installed, every command
+/// `traced_output` runs on the same thread is logged and answered by it,
+/// so a test can check which `zfs` invocations a wrapper makes on a host
+/// without ZFS.
+#[cfg(test)]
+pub(crate) mod recorder {
+    use std::cell::RefCell;
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::{ExitStatus, Output};
+
+    type Respond = Box<dyn Fn(&[String]) -> Result<String, String>>;
+
+    thread_local! {
+        static ACTIVE: RefCell<Option<(Respond, Vec<Vec<String>>)>> = const { RefCell::new(None) };
+    }
+
+    /// Installed until dropped
+    pub struct Recorder(());
+
+    impl Recorder {
+        /// `respond` gets the program and its arguments, and returns the
+        /// stdout of a success or the stderr of a failure
+        pub fn install(respond: impl Fn(&[String]) -> Result<String, String> + 'static) -> Self {
+            ACTIVE.with(|active| *active.borrow_mut() = Some((Box::new(respond), Vec::new())));
+            Self(())
+        }
+
+        /// Every command run so far, program first
+        pub fn calls(&self) -> Vec<Vec<String>> {
+            ACTIVE.with(|active| active.borrow().as_ref().map(|(_, calls)| calls.clone()).unwrap_or_default())
+        }
+    }
+
+    impl Drop for Recorder {
+        fn drop(&mut self) {
+            ACTIVE.with(|active| *active.borrow_mut() = None);
+        }
+    }
+
+    pub(super) fn intercept(program: &str, args: &[String]) -> Option<Output> {
+        ACTIVE.with(|active| {
+            let mut active = active.borrow_mut();
+            let (respond, calls) = active.as_mut()?;
+            let command: Vec<String> = std::iter::once(program.to_string()).chain(args.iter().cloned()).collect();
+            let answer = respond(&command);
+            calls.push(command);
+            Some(match answer {
+                Ok(stdout) => Output { status: ExitStatus::from_raw(0), stdout: stdout.into_bytes(), stderr: Vec::new() },
+                Err(stderr) => Output { status: ExitStatus::from_raw(1 << 8), stdout: Vec::new(), stderr: stderr.into_bytes() },
+            })
+        })
+    }
+}
+
+/// Hide secret-looking `key=value` values and cut very long arguments
+fn sanitize_arg(arg: &str) -> String {
+    if let Some((key, _)) = arg.split_once('=')
+        && crate::config::is_secret_key(key)
+    {
+        return format!("{}=<redacted>", key);
+    }
+    if arg.chars().count() > MAX_ARG_LEN {
+        let cut: String = arg.chars().take(MAX_ARG_LEN).collect();
+        return format!("{}...", cut);
+    }
+    arg.to_string()
+}
+
+/// Last argument that isn't a flag or a `key=value` option
+fn resource_of(args: &[String]) -> Option<String> {
+    args.iter()
+        .rev()
+        .find(|arg| !arg.starts_with('-') && !arg.contains('='))
+        .map(|arg| sanitize_arg(arg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rec(program: &str, duration_ms: u64, success: bool, finished_at: i64) -> CommandRecord {
+        CommandRecord::new(program, &["destroy".to_string(), "tank/a".to_string()], Duration::from_millis(duration_ms), success, Timestamp::from_secs(finished_at))
+    }
+
+    #[test]
+    fn test_record_aggregates_per_program() {
+        let mut metrics = CommandMetrics::default();
+        metrics.record(rec("zfs", 100, true, 1000));
+        metrics.record(rec("zfs", 250, false, 1001));
+        metrics.record(rec("jexec", 10, true, 1002));
+
+        let info = metrics.info(Timestamp::from_secs(1003));
+        assert_eq!(info.commands["zfs"], CommandStats { count: 2, total_ms: 350, failures: 1 });
+        assert_eq!(info.commands["jexec"], CommandStats { count: 1, total_ms: 10, failures: 0 });
+    }
+
+    #[test]
+    fn test_slow_threshold() {
+        let mut metrics = CommandMetrics::default();
+        assert!(!metrics.record(rec("zfs", 4_999, true, 1000)));
+        assert!(metrics.record(rec("zfs", 5_000, true, 1000)));
+
+        metrics.set_slow_threshold(Duration::from_millis(100));
+        assert!(metrics.record(rec("zfs", 150, true, 1000)));
+    }
+
+    #[test]
+    fn test_slowest_keeps_twenty_slowest() {
+        let mut metrics = CommandMetrics::default();
+        for ms in 1..=30 {
+            metrics.record(rec("zfs", ms * 10, true, 1000 + ms as i64));
+        }
+
+        let slowest = metrics.slowest_recent(Timestamp::from_secs(1100));
+        assert_eq!(slowest.len(), SLOWEST_KEEP);
+        assert_eq!(slowest[0].duration_ms, 300);
+        assert_eq!(slowest[19].duration_ms, 110);
+
+        // A fast command doesn't displace anything
+        metrics.record(rec("zfs", 5, true, 1200));
+        assert_eq!(metrics.slowest_recent(Timestamp::from_secs(1200)).last().unwrap().duration_ms, 110);
+    }
+
+    #[test]
+    fn test_slowest_forgets_after_an_hour() {
+        let mut metrics = CommandMetrics::default();
+        metrics.record(rec("zfs", 9_000, true, 1000));
+        metrics.record(rec("zfs", 10, true, 1000 + SLOWEST_WINDOW_SECS - 1));
+        assert_eq!(metrics.slowest_recent(Timestamp::from_secs(1000 + SLOWEST_WINDOW_SECS - 1)).len(), 2);
+
+        // Old entries drop out of the view, and out of the buffer on the next record
+        assert_eq!(metrics.slowest_recent(Timestamp::from_secs(1000 + SLOWEST_WINDOW_SECS)).len(), 1);
+        metrics.record(rec("zfs", 20, true, 1000 + SLOWEST_WINDOW_SECS + 1));
+        assert!(metrics.slowest.iter().all(|r| r.duration_ms != 9_000));
+    }
+
+    #[test]
+    fn test_sanitize_and_resource() {
+        let args: Vec<String> = ["create", "-o", "encryption=on", "-o", "keylocation=file:///k", "-o", "password=hunter2", "tank/secret"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let record = CommandRecord::new("zfs", &args, Duration::from_millis(1), true, Timestamp::EPOCH);
+
+        assert!(record.args.contains(&"password=<redacted>".to_string()));
+        assert!(record.args.contains(&"encryption=on".to_string()));
+        assert_eq!(record.resource.as_deref(), Some("tank/secret"));
+        assert_eq!(resource_of(&["-H".to_string()]), None);
+        assert!(sanitize_arg(&"x".repeat(500)).ends_with("..."));
+    }
+
+    #[tokio::test]
+    async fn test_cancellable_output() {
+        let cancel = CancellationToken::new();
+        let mut echo = Command::new("echo");
+        echo.arg("hi");
+        let mut spawned = None;
+        let output = cancellable_output(echo, &cancel, |pid| spawned = Some(pid)).await.unwrap().unwrap();
+        assert_eq!(output.stdout, b"hi\n");
+        assert!(spawned.is_some());
+
+        let mut sleep = Command::new("sleep");
+        sleep.arg("30");
+        let canceller = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            canceller.cancel();
+        });
+        let started = Instant::now();
+        assert!(cancellable_output(sleep, &cancel, |_| ()).await.unwrap().is_none());
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+}
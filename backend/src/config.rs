@@ -3,6 +3,7 @@
 //! This module handles loading and saving configuration from TOML files.
 //! It provides default values for all configuration options.
 
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 use std::fs;
 use serde::{Deserialize, Serialize};
@@ -27,6 +28,32 @@ pub enum ConfigError {
 pub struct KawakazeConfig {
     /// ZFS pool name for jail storage
     pub zfs_pool: String,
+    /// Directory the database, socket, build and jail directories derive
+    /// from unless set individually (see `paths`)
+    #[serde(default = "default_data_root")]
+    pub data_root: String,
+    /// Directory that client-supplied jail root paths must stay within
+    /// (default `<data_root>/jails`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jail_base_path: Option<String>,
+    /// Delete jail root directories under `jail_base_path` when jails are
+    /// removed, unless a create request says otherwise
+    #[serde(default)]
+    pub cleanup_jail_paths: bool,
+    /// Host directories containers may bind-mount from (see `mount_policy`)
+    #[serde(default = "default_allowed_mount_roots")]
+    pub allowed_mount_roots: Vec<PathBuf>,
+    /// Accept bind-mount sources anywhere on the host; kawakaze's own state
+    /// stays off limits
+    #[serde(default)]
+    pub allow_any_mount_source: bool,
+    /// Require `:ro` for bind mounts from outside the home roots
+    #[serde(default)]
+    pub strict_mounts: bool,
+    /// Let jails and containers be created with `mount_permissions`, so
+    /// they can mount file systems themselves
+    #[serde(default)]
+    pub allow_in_jail_mounts: bool,
     /// Network configuration
     #[serde(default)]
     pub network: NetworkConfig,
@@ -36,6 +63,60 @@ pub struct KawakazeConfig {
     /// API configuration
     #[serde(default)]
     pub api: ApiConfig,
+    /// devfs ruleset configuration
+    #[serde(default)]
+    pub devfs: DevfsConfig,
+    /// Diagnostics settings
+    #[serde(default)]
+    pub diagnostics: DiagnosticsConfig,
+    /// ZFS native encryption for container datasets
+    #[serde(default)]
+    pub encryption: EncryptionConfig,
+    /// Rules for container and image names
+    #[serde(default)]
+    pub names: NamesConfig,
+    /// Writable mounts of containers with a read-only root
+    #[serde(default)]
+    pub read_only: ReadOnlyConfig,
+    /// Container lifecycle settings
+    #[serde(default)]
+    pub container: ContainerRuntimeConfig,
+    /// Spooling of chunked uploads (see `upload`)
+    #[serde(default)]
+    pub uploads: UploadConfig,
+    /// HTTP gateway onto the API (see `http_gateway`)
+    #[serde(default)]
+    pub http: HttpConfig,
+    /// Collection of snapshots nothing references (see `snapshot_gc`)
+    #[serde(default)]
+    pub snapshot_gc: SnapshotGcConfig,
+    /// sysctls containers may set in their jail (see `sysctl`)
+    #[serde(default)]
+    pub sysctl: SysctlConfig,
+    /// Keys whose signatures on image artifacts are trusted (see `artifact`)
+    #[serde(default)]
+    pub trusted_keys: Vec<TrustedKey>,
+    /// What to do with unsigned or untrusted image artifacts
+    #[serde(default)]
+    pub verify_signatures: SignaturePolicy,
+    /// Image builds running at once; more are queued (see `task_queue`)
+    #[serde(default = "default_max_concurrent_builds")]
+    pub max_concurrent_builds: usize,
+    /// Jail bootstraps running at once; more are queued
+    #[serde(default = "default_max_concurrent_bootstraps")]
+    pub max_concurrent_bootstraps: usize,
+    /// Seconds the final status of a finished build or bootstrap stays
+    /// available from the progress endpoints
+    #[serde(default = "default_progress_retention_secs")]
+    pub progress_retention_secs: u64,
+    /// The daemon's log filter at startup, unless `RUST_LOG` is set: a
+    /// level (`debug`) or `EnvFilter` directives (see `log_level`)
+    #[serde(default = "default_log_level")]
+    pub log_level: String,
+    /// Labels of this node for placement constraints, besides the ones
+    /// derived from the host (see `placement`)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub node_labels: BTreeMap<String, String>,
 }
 
 /// Network configuration settings
@@ -50,20 +131,61 @@ pub struct NetworkConfig {
     /// Whether NAT is enabled
     #[serde(default = "default_nat_enabled")]
     pub nat_enabled: bool,
+    /// Whether containers get IPv4 addresses; off with `container_cidr6`
+    /// set makes the network IPv6-only
+    #[serde(default = "default_ipv4_enabled")]
+    pub ipv4_enabled: bool,
+    /// IPv6 subnet for container addresses (e.g. `fd00:6b7a::/64`); unset
+    /// means containers get no IPv6 address
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container_cidr6: Option<String>,
 }
 
 /// Storage configuration settings
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StorageConfig {
-    /// Path to SQLite database
-    #[serde(default = "default_database_path")]
-    pub database_path: String,
-    /// Path to Unix socket
-    #[serde(default = "default_socket_path")]
-    pub socket_path: String,
+    /// Path to SQLite database (default `<data_root>/kawakaze.db`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub database_path: Option<String>,
+    /// Path to Unix socket (default `<data_root>/kawakaze.sock`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub socket_path: Option<String>,
+    /// Directory build datasets are mounted under (default
+    /// `<data_root>/builds`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub builds_path: Option<String>,
     /// Path to cache directory
     #[serde(default = "default_cache_path")]
     pub cache_path: String,
+    /// Directory holding per-container log files
+    #[serde(default = "default_log_path")]
+    pub log_path: String,
+    /// Snapshots kept on an image dataset after a build; older ones are
+    /// destroyed unless something was cloned from them
+    #[serde(default = "default_keep_snapshots")]
+    pub keep_snapshots: usize,
+    /// Snapshot build datasets after every step so that a failed build of
+    /// the same name resumes after its last good step
+    #[serde(default = "default_layer_snapshots")]
+    pub layer_snapshots: bool,
+    /// Directory holding image build logs, one per build
+    #[serde(default = "default_build_log_path")]
+    pub build_log_path: String,
+    /// Directory holding bootstrap logs, one per jail
+    #[serde(default = "default_bootstrap_log_path")]
+    pub bootstrap_log_path: String,
+    /// Build and bootstrap logs older than this many days are removed
+    #[serde(default = "default_log_retention_days")]
+    pub log_retention_days: u64,
+    /// Oldest build and bootstrap logs are removed while a directory holds
+    /// more than this many bytes
+    #[serde(default = "default_log_retention_bytes")]
+    pub log_retention_bytes: u64,
+    /// Keep accepting builds and container creates while the pool is
+    /// DEGRADED (with a warning); any other state but ONLINE still refuses
+    /// them (see `pool_health`)
+    #[serde(default)]
+    pub allow_degraded_pool: bool,
 }
 
 /// API configuration settings
@@ -74,8 +196,225 @@ pub struct ApiConfig {
     pub timeout: u64,
 }
 
+/// Diagnostics settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiagnosticsConfig {
+    /// External commands (zfs, jail, ...) running at least this many
+    /// seconds are logged as slow
+    #[serde(default = "default_slow_command_secs")]
+    pub slow_command_secs: u64,
+}
+
+/// ZFS native encryption settings
+///
+/// Keys are only ever read from files (`keylocation=file://...`); the
+/// daemon never prompts for a passphrase.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptionConfig {
+    /// Encrypt every new container dataset, not only ones created with
+    /// `--encrypted`
+    #[serde(default)]
+    pub enabled: bool,
+    /// `keyformat` for new encrypted datasets: `raw`, `hex` or `passphrase`
+    #[serde(default = "default_keyformat")]
+    pub keyformat: String,
+    /// `keylocation` for new encrypted datasets; must be a `file://` URI
+    #[serde(default = "default_keylocation")]
+    pub keylocation: String,
+}
+
+/// Rules for container and image names
+///
+/// Names are checked against IDs in `names::check_name`; this only sets
+/// which names count as looking like an ID.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NamesConfig {
+    /// Refuse names made only of hex digits, like a short or full ID
+    #[serde(default = "default_reject_hex")]
+    pub reject_hex: bool,
+    /// Shortest hex name refused
+    #[serde(default = "default_hex_min_len")]
+    pub hex_min_len: usize,
+    /// Longest hex name refused
+    #[serde(default = "default_hex_max_len")]
+    pub hex_max_len: usize,
+}
+
+/// Settings for containers created with `read_only_root`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReadOnlyConfig {
+    /// tmpfs mounted over the read-only root at start
+    #[serde(default = "default_read_only_tmpfs")]
+    pub tmpfs: Vec<TmpfsConfig>,
+}
+
+/// Container lifecycle settings
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ContainerRuntimeConfig {
+    /// At daemon start, start again the containers with an `always` or
+    /// `on-restart` policy that were running when it (or the host) went down
+    #[serde(default)]
+    pub restart_on_boot: bool,
+}
+
+/// Where chunked uploads are spooled, and how much
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadConfig {
+    /// Directory holding uploads until they are committed and taken; its
+    /// files are removed when the daemon starts
+    #[serde(default = "default_upload_spool_path")]
+    pub spool_path: String,
+    /// Largest file one upload may announce
+    #[serde(default = "default_max_upload_bytes")]
+    pub max_upload_bytes: u64,
+    /// Space all uploads held at once may reserve
+    #[serde(default = "default_max_spool_bytes")]
+    pub max_spool_bytes: u64,
+    /// An upload with no request for this long is dropped
+    #[serde(default = "default_upload_idle_timeout_secs")]
+    pub idle_timeout_secs: u64,
+}
+
+/// When unreferenced snapshots are collected, and what counts as referenced
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotGcConfig {
+    /// Seconds between background runs; 0 runs it only on request
+    #[serde(default = "default_snapshot_gc_interval_secs")]
+    pub interval_secs: u64,
+    /// Pre-upgrade snapshots younger than this many days are kept for a
+    /// manual rollback
+    #[serde(default = "default_pre_upgrade_retention_days")]
+    pub pre_upgrade_retention_days: u64,
+}
+
+/// sysctls containers may set besides `sysctl::DEFAULT_PREFIXES`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SysctlConfig {
+    /// More prefixes, matched on whole components (`net.pf`)
+    #[serde(default)]
+    pub allowed_prefixes: Vec<String>,
+    /// Exact keys allowed outside the prefixes
+    #[serde(default)]
+    pub allowed: Vec<String>,
+    /// Exact keys refused even under a prefix
+    #[serde(default)]
+    pub denied: Vec<String>,
+}
+
+/// HTTP gateway onto the API, served when the daemon is built with the
+/// `http-gateway` feature
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HttpConfig {
+    /// Address to listen on (e.g. "127.0.0.1:7080"); no gateway when unset
+    #[serde(default)]
+    pub listen: Option<String>,
+    /// Allow a listen address other than loopback. TCP carries no peer
+    /// credentials, so whoever reaches the gateway controls the daemon
+    #[serde(default)]
+    pub allow_remote: bool,
+}
+
+/// A tmpfs mount inside a container
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TmpfsConfig {
+    /// Path inside the container
+    pub path: String,
+    /// Size limit as mount_tmpfs takes it (e.g. "64m")
+    pub size: String,
+}
+
+/// An Ed25519 public key trusted to sign image artifacts
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TrustedKey {
+    /// Key ID that manifest signatures name
+    pub id: String,
+    /// Hex-encoded 32-byte public key
+    pub public_key: String,
+}
+
+/// Signature policy for loaded, pulled and imported images
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SignaturePolicy {
+    /// Don't look at signatures
+    #[default]
+    Off,
+    /// Accept, but warn about unsigned, untrusted or bad signatures
+    Warn,
+    /// Refuse artifacts without a valid signature from a trusted key
+    Enforce,
+}
+
+/// devfs ruleset settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DevfsConfig {
+    /// Ruleset applied to jails that don't request one
+    #[serde(default = "default_devfs_ruleset")]
+    pub ruleset: u16,
+    /// Create or repair the rulesets below at daemon start
+    #[serde(default = "default_manage_rules")]
+    pub manage_rules: bool,
+    /// Additional rulesets jails may request, each unhiding extra devices
+    #[serde(default)]
+    pub rulesets: Vec<DevfsRulesetConfig>,
+}
+
+/// An extra devfs ruleset: the standard jail rules plus `unhide`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DevfsRulesetConfig {
+    /// Ruleset number
+    pub number: u16,
+    /// Device path patterns to unhide (e.g. "pf", "bpf*")
+    #[serde(default)]
+    pub unhide: Vec<String>,
+}
+
+impl EncryptionConfig {
+    /// Options for creating or receiving an encrypted dataset
+    pub fn dataset_options(&self) -> crate::zfs::DatasetOptions {
+        crate::zfs::DatasetOptions::default().with_encryption(&self.keyformat, &self.keylocation)
+    }
+}
+
+impl DevfsConfig {
+    /// Pick the ruleset for a jail, rejecting numbers the config doesn't allow
+    pub fn resolve(&self, requested: Option<u16>) -> Result<u16> {
+        match requested {
+            None => Ok(self.ruleset),
+            Some(n) if n == self.ruleset || self.rulesets.iter().any(|r| r.number == n) => Ok(n),
+            Some(n) => {
+                let allowed: Vec<String> = std::iter::once(self.ruleset)
+                    .chain(self.rulesets.iter().map(|r| r.number))
+                    .map(|n| n.to_string())
+                    .collect();
+                Err(ConfigError::InvalidValue(format!(
+                    "devfs ruleset {} is not allowed (allowed: {})",
+                    n,
+                    allowed.join(", ")
+                )))
+            }
+        }
+    }
+}
+
 // Default value functions
 
+fn default_devfs_ruleset() -> u16 {
+    10
+}
+
+fn default_manage_rules() -> bool {
+    true
+}
+
+fn default_data_root() -> String {
+    crate::paths::DEFAULT_DATA_ROOT.to_string()
+}
+
+fn default_allowed_mount_roots() -> Vec<PathBuf> {
+    vec![PathBuf::from("/usr/home"), PathBuf::from("/srv")]
+}
+
 fn default_container_cidr() -> String {
     "10.11.0.0/16".to_string()
 }
@@ -88,22 +427,117 @@ fn default_nat_enabled() -> bool {
     true
 }
 
-fn default_database_path() -> String {
-    "/var/db/kawakaze/kawakaze.db".to_string()
-}
-
-fn default_socket_path() -> String {
-    "/var/run/kawakaze.sock".to_string()
+fn default_ipv4_enabled() -> bool {
+    true
 }
 
 fn default_cache_path() -> String {
     "/var/cache/kawakaze".to_string()
 }
 
+fn default_log_path() -> String {
+    "/var/log/kawakaze".to_string()
+}
+
+fn default_build_log_path() -> String {
+    "/var/log/kawakaze/builds".to_string()
+}
+
+fn default_bootstrap_log_path() -> String {
+    "/var/log/kawakaze/bootstrap".to_string()
+}
+
+fn default_log_retention_days() -> u64 {
+    30
+}
+
+fn default_log_retention_bytes() -> u64 {
+    512 * 1024 * 1024
+}
+
+fn default_upload_spool_path() -> String {
+    "/var/spool/kawakaze/uploads".to_string()
+}
+
+fn default_max_upload_bytes() -> u64 {
+    4 * 1024 * 1024 * 1024
+}
+
+fn default_max_spool_bytes() -> u64 {
+    16 * 1024 * 1024 * 1024
+}
+
+fn default_upload_idle_timeout_secs() -> u64 {
+    15 * 60
+}
+
+fn default_keep_snapshots() -> usize {
+    1
+}
+
+fn default_layer_snapshots() -> bool {
+    true
+}
+
 fn default_timeout() -> u64 {
     30
 }
 
+fn default_keyformat() -> String {
+    "raw".to_string()
+}
+
+fn default_keylocation() -> String {
+    "file:///var/db/kawakaze/keys/kawakaze.key".to_string()
+}
+
+fn default_reject_hex() -> bool {
+    true
+}
+
+fn default_hex_min_len() -> usize {
+    6
+}
+
+fn default_hex_max_len() -> usize {
+    64
+}
+
+fn default_max_concurrent_builds() -> usize {
+    2
+}
+
+fn default_max_concurrent_bootstraps() -> usize {
+    1
+}
+
+fn default_progress_retention_secs() -> u64 {
+    600
+}
+
+fn default_snapshot_gc_interval_secs() -> u64 {
+    24 * 60 * 60
+}
+
+fn default_pre_upgrade_retention_days() -> u64 {
+    7
+}
+
+fn default_log_level() -> String {
+    crate::log_level::DEFAULT_LEVEL.to_string()
+}
+
+fn default_read_only_tmpfs() -> Vec<TmpfsConfig> {
+    vec![
+        TmpfsConfig { path: "/tmp".to_string(), size: "64m".to_string() },
+        TmpfsConfig { path: "/var/run".to_string(), size: "16m".to_string() },
+    ]
+}
+
+fn default_slow_command_secs() -> u64 {
+    crate::cmdtrace::DEFAULT_SLOW_THRESHOLD.as_secs()
+}
+
 // Default implementations
 
 impl Default for NetworkConfig {
@@ -112,6 +546,8 @@ impl Default for NetworkConfig {
             container_cidr: default_container_cidr(),
             bridge_name: default_bridge_name(),
             nat_enabled: default_nat_enabled(),
+            ipv4_enabled: default_ipv4_enabled(),
+            container_cidr6: None,
         }
     }
 }
@@ -119,9 +555,82 @@ impl Default for NetworkConfig {
 impl Default for StorageConfig {
     fn default() -> Self {
         Self {
-            database_path: default_database_path(),
-            socket_path: default_socket_path(),
+            database_path: None,
+            socket_path: None,
+            builds_path: None,
             cache_path: default_cache_path(),
+            log_path: default_log_path(),
+            keep_snapshots: default_keep_snapshots(),
+            layer_snapshots: default_layer_snapshots(),
+            build_log_path: default_build_log_path(),
+            bootstrap_log_path: default_bootstrap_log_path(),
+            log_retention_days: default_log_retention_days(),
+            log_retention_bytes: default_log_retention_bytes(),
+            allow_degraded_pool: false,
+        }
+    }
+}
+
+impl Default for DevfsConfig {
+    fn default() -> Self {
+        Self {
+            ruleset: default_devfs_ruleset(),
+            manage_rules: default_manage_rules(),
+            rulesets: Vec::new(),
+        }
+    }
+}
+
+impl Default for EncryptionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            keyformat: default_keyformat(),
+            keylocation: default_keylocation(),
+        }
+    }
+}
+
+impl Default for ReadOnlyConfig {
+    fn default() -> Self {
+        Self { tmpfs: default_read_only_tmpfs() }
+    }
+}
+
+impl Default for SnapshotGcConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: default_snapshot_gc_interval_secs(),
+            pre_upgrade_retention_days: default_pre_upgrade_retention_days(),
+        }
+    }
+}
+
+impl Default for UploadConfig {
+    fn default() -> Self {
+        Self {
+            spool_path: default_upload_spool_path(),
+            max_upload_bytes: default_max_upload_bytes(),
+            max_spool_bytes: default_max_spool_bytes(),
+            idle_timeout_secs: default_upload_idle_timeout_secs(),
+        }
+    }
+}
+
+impl Default for NamesConfig {
+    fn default() -> Self {
+        Self {
+            reject_hex: default_reject_hex(),
+            hex_min_len: default_hex_min_len(),
+            hex_max_len: default_hex_max_len(),
+        }
+    }
+}
+
+impl Default for DiagnosticsConfig {
+    fn default() -> Self {
+        Self {
+            slow_command_secs: default_slow_command_secs(),
         }
     }
 }
@@ -164,23 +673,54 @@ impl KawakazeConfig {
     ///
     /// If neither exists, returns default configuration.
     pub fn load_defaults() -> Result<Self> {
-        let system_config = Path::new("/etc/kawakaze/config.toml");
-        let user_config = std::env::var("HOME")
-            .map(|home| PathBuf::from(home).join(".config/kawakaze/config.toml"))
-            .unwrap_or_else(|_| PathBuf::from("~/.config/kawakaze/config.toml"));
+        Self::load_defaults_with_source().map(|(config, _)| config)
+    }
 
-        // Try system config first
-        if system_config.exists() {
-            return Self::load(system_config);
+    /// Like [`load_defaults`](Self::load_defaults), also reporting which file
+    /// (if any) the configuration came from
+    pub fn load_defaults_with_source() -> Result<(Self, ConfigSource)> {
+        match default_config_path() {
+            Some(path) => {
+                let config = Self::load(&path)?;
+                Ok((config, ConfigSource::from_path(path)))
+            }
+            None => Ok((Self::default(), ConfigSource::default())),
         }
+    }
 
-        // Then try user config
-        if user_config.exists() {
-            return Self::load(user_config);
+    /// Refuse to let a jail mount file systems itself unless the operator
+    /// allowed it with `allow_in_jail_mounts`
+    pub fn check_mount_permissions(&self, permissions: crate::jail::MountPermissions) -> std::result::Result<(), String> {
+        if permissions == crate::jail::MountPermissions::None || self.allow_in_jail_mounts {
+            return Ok(());
         }
+        Err(format!(
+            "Mount permissions '{}' are refused: jails may not mount file systems themselves unless the daemon config sets allow_in_jail_mounts",
+            permissions.as_str()
+        ))
+    }
+
+    /// Compare against the file the configuration was loaded from
+    ///
+    /// Returns the paths of fields whose value in the file differs from this
+    /// configuration. A configuration built from defaults never drifts.
+    pub fn drift(&self, source: &ConfigSource) -> Result<Vec<String>> {
+        let Some(path) = &source.path else {
+            return Ok(Vec::new());
+        };
+
+        let on_disk = Self::load(path)?;
+        let running = serde_json::to_value(self).map_err(|e| ConfigError::InvalidValue(e.to_string()))?;
+        let current = serde_json::to_value(&on_disk).map_err(|e| ConfigError::InvalidValue(e.to_string()))?;
+
+        Ok(diff_values(&running, &current))
+    }
 
-        // Return default config if none found
-        Ok(Self::default())
+    /// Serialize to JSON with secret-looking fields redacted
+    pub fn to_sanitized_json(&self) -> serde_json::Value {
+        let mut value = serde_json::to_value(self).unwrap_or(serde_json::Value::Null);
+        redact_secrets(&mut value);
+        value
     }
 
     /// Save configuration to a specific path
@@ -209,6 +749,29 @@ impl KawakazeConfig {
             return Err(ConfigError::InvalidValue("ZFS pool name cannot be empty".to_string()));
         }
 
+        // Validate data root and the paths set instead of derived from it
+        if !Path::new(&self.data_root).is_absolute() {
+            return Err(ConfigError::InvalidValue(format!(
+                "Data root must be absolute: {}",
+                self.data_root
+            )));
+        }
+        if let Some(ref jail_base_path) = self.jail_base_path
+            && !Path::new(jail_base_path).is_absolute()
+        {
+            return Err(ConfigError::InvalidValue(format!(
+                "Jail base path must be absolute: {}",
+                jail_base_path
+            )));
+        }
+
+        if let Some(root) = self.allowed_mount_roots.iter().find(|root| !root.is_absolute()) {
+            return Err(ConfigError::InvalidValue(format!(
+                "Allowed mount root must be absolute: {}",
+                root.display()
+            )));
+        }
+
         // Validate CIDR format
         if !is_valid_cidr(&self.network.container_cidr) {
             return Err(ConfigError::InvalidValue(format!(
@@ -216,17 +779,57 @@ impl KawakazeConfig {
                 self.network.container_cidr
             )));
         }
+        if let Some(cidr6) = &self.network.container_cidr6 {
+            crate::networking::Subnet6::parse(cidr6).map_err(ConfigError::InvalidValue)?;
+        }
+        if !self.network.ipv4_enabled && self.network.container_cidr6.is_none() {
+            return Err(ConfigError::InvalidValue(
+                "network.ipv4_enabled = false needs network.container_cidr6".to_string(),
+            ));
+        }
 
         // Validate paths are not empty
-        if self.storage.database_path.is_empty() {
+        if self.storage.database_path.as_deref() == Some("") {
             return Err(ConfigError::InvalidValue("Database path cannot be empty".to_string()));
         }
-        if self.storage.socket_path.is_empty() {
+        if self.storage.socket_path.as_deref() == Some("") {
             return Err(ConfigError::InvalidValue("Socket path cannot be empty".to_string()));
         }
+        if self.storage.builds_path.as_deref() == Some("") {
+            return Err(ConfigError::InvalidValue("Builds path cannot be empty".to_string()));
+        }
         if self.storage.cache_path.is_empty() {
             return Err(ConfigError::InvalidValue("Cache path cannot be empty".to_string()));
         }
+        if self.storage.log_path.is_empty() {
+            return Err(ConfigError::InvalidValue("Log path cannot be empty".to_string()));
+        }
+        if self.storage.build_log_path.is_empty() || self.storage.bootstrap_log_path.is_empty() {
+            return Err(ConfigError::InvalidValue("Build and bootstrap log paths cannot be empty".to_string()));
+        }
+        if !Path::new(&self.uploads.spool_path).is_absolute() {
+            return Err(ConfigError::InvalidValue(format!("Upload spool path must be absolute: {}", self.uploads.spool_path)));
+        }
+        if self.uploads.max_upload_bytes > self.uploads.max_spool_bytes {
+            return Err(ConfigError::InvalidValue(format!(
+                "uploads.max_upload_bytes ({}) cannot exceed uploads.max_spool_bytes ({})",
+                self.uploads.max_upload_bytes, self.uploads.max_spool_bytes
+            )));
+        }
+        if self.uploads.idle_timeout_secs == 0 {
+            return Err(ConfigError::InvalidValue("uploads.idle_timeout_secs cannot be zero".to_string()));
+        }
+        if let Some(listen) = &self.http.listen {
+            let addr: std::net::SocketAddr = listen
+                .parse()
+                .map_err(|_| ConfigError::InvalidValue(format!("Invalid http.listen address: {}", listen)))?;
+            if !addr.ip().is_loopback() && !self.http.allow_remote {
+                return Err(ConfigError::InvalidValue(format!(
+                    "http.listen {} is not a loopback address; set http.allow_remote to serve it",
+                    listen
+                )));
+            }
+        }
 
         // Validate timeout is reasonable
         if self.api.timeout == 0 {
@@ -236,6 +839,81 @@ impl KawakazeConfig {
             return Err(ConfigError::InvalidValue("API timeout cannot exceed 3600 seconds".to_string()));
         }
 
+        // Validate devfs rulesets: 0 means "no rules", and numbers must be unique
+        let mut numbers = vec![self.devfs.ruleset];
+        for ruleset in &self.devfs.rulesets {
+            if numbers.contains(&ruleset.number) {
+                return Err(ConfigError::InvalidValue(format!(
+                    "Duplicate devfs ruleset number: {}",
+                    ruleset.number
+                )));
+            }
+            numbers.push(ruleset.number);
+
+            for pattern in &ruleset.unhide {
+                crate::devfs::validate_pattern(pattern).map_err(|e| ConfigError::InvalidValue(e.to_string()))?;
+            }
+        }
+        if numbers.contains(&0) {
+            return Err(ConfigError::InvalidValue("devfs ruleset 0 exposes all devices".to_string()));
+        }
+
+        if self.names.hex_min_len == 0 || self.names.hex_min_len > self.names.hex_max_len {
+            return Err(ConfigError::InvalidValue(format!(
+                "Invalid hex name lengths: {}-{}",
+                self.names.hex_min_len, self.names.hex_max_len
+            )));
+        }
+
+        for tmpfs in &self.read_only.tmpfs {
+            if !tmpfs.path.starts_with('/') || tmpfs.path.split('/').any(|part| part == "..") {
+                return Err(ConfigError::InvalidValue(format!(
+                    "read_only tmpfs path must be absolute without '..': {}",
+                    tmpfs.path
+                )));
+            }
+            if tmpfs.size.is_empty() {
+                return Err(ConfigError::InvalidValue(format!("read_only tmpfs {} needs a size", tmpfs.path)));
+            }
+        }
+
+        for (i, key) in self.trusted_keys.iter().enumerate() {
+            if key.id.is_empty() || self.trusted_keys[..i].iter().any(|other| other.id == key.id) {
+                return Err(ConfigError::InvalidValue(format!("Trusted key IDs must be unique and non-empty: '{}'", key.id)));
+            }
+            crate::artifact::parse_public_key(key).map_err(|e| ConfigError::InvalidValue(e.to_string()))?;
+        }
+
+        if self.max_concurrent_builds == 0 || self.max_concurrent_bootstraps == 0 {
+            return Err(ConfigError::InvalidValue(
+                "max_concurrent_builds and max_concurrent_bootstraps must be at least 1".to_string(),
+            ));
+        }
+
+        crate::log_level::parse(&self.log_level).map_err(ConfigError::InvalidValue)?;
+        crate::placement::check_node_labels(&self.node_labels).map_err(ConfigError::InvalidValue)?;
+        let sysctl = &self.sysctl;
+        if let Some(key) = sysctl.allowed_prefixes.iter().map(|p| p.trim_end_matches('.'))
+            .chain(sysctl.allowed.iter().chain(&sysctl.denied).map(String::as_str))
+            .find(|key| !crate::sysctl::is_valid_key(key))
+        {
+            return Err(ConfigError::InvalidValue(format!("Invalid sysctl name in [sysctl]: '{}'", key)));
+        }
+
+        // Keys come from files only; the daemon has nobody to prompt
+        if !["raw", "hex", "passphrase"].contains(&self.encryption.keyformat.as_str()) {
+            return Err(ConfigError::InvalidValue(format!(
+                "Invalid encryption keyformat: {}",
+                self.encryption.keyformat
+            )));
+        }
+        if !self.encryption.keylocation.starts_with("file:///") {
+            return Err(ConfigError::InvalidValue(format!(
+                "Encryption keylocation must be an absolute file:// URI: {}",
+                self.encryption.keylocation
+            )));
+        }
+
         Ok(())
     }
 }
@@ -244,10 +922,133 @@ impl Default for KawakazeConfig {
     fn default() -> Self {
         Self {
             zfs_pool: "zroot/kawakaze".to_string(),
+            data_root: default_data_root(),
+            jail_base_path: None,
+            cleanup_jail_paths: false,
+            allowed_mount_roots: default_allowed_mount_roots(),
+            allow_any_mount_source: false,
+            strict_mounts: false,
+            allow_in_jail_mounts: false,
             network: NetworkConfig::default(),
             storage: StorageConfig::default(),
             api: ApiConfig::default(),
+            devfs: DevfsConfig::default(),
+            diagnostics: DiagnosticsConfig::default(),
+            encryption: EncryptionConfig::default(),
+            names: NamesConfig::default(),
+            read_only: ReadOnlyConfig::default(),
+            container: ContainerRuntimeConfig::default(),
+            uploads: UploadConfig::default(),
+            http: HttpConfig::default(),
+            snapshot_gc: SnapshotGcConfig::default(),
+            sysctl: SysctlConfig::default(),
+            trusted_keys: Vec::new(),
+            verify_signatures: SignaturePolicy::default(),
+            max_concurrent_builds: default_max_concurrent_builds(),
+            max_concurrent_bootstraps: default_max_concurrent_bootstraps(),
+            progress_retention_secs: default_progress_retention_secs(),
+            log_level: default_log_level(),
+            node_labels: BTreeMap::new(),
+        }
+    }
+}
+
+/// Where the running configuration was loaded from
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConfigSource {
+    /// Config file path, or `None` when running on defaults
+    pub path: Option<PathBuf>,
+    /// Unix timestamp of the file's modification time when it was loaded
+    pub mtime: Option<i64>,
+}
+
+impl ConfigSource {
+    /// Record a config file and its current modification time
+    pub fn from_path(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let mtime = fs::metadata(&path)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs() as i64);
+
+        Self { path: Some(path), mtime }
+    }
+}
+
+/// First existing config file in the default search order
+fn default_config_path() -> Option<PathBuf> {
+    let system_config = PathBuf::from("/etc/kawakaze/config.toml");
+    let user_config = std::env::var("HOME")
+        .map(|home| PathBuf::from(home).join(".config/kawakaze/config.toml"))
+        .unwrap_or_else(|_| PathBuf::from("~/.config/kawakaze/config.toml"));
+
+    [system_config, user_config].into_iter().find(|p| p.exists())
+}
+
+/// Replacement value for redacted fields
+pub const REDACTED: &str = "<redacted>";
+
+/// Substrings that mark a field name as holding a secret
+const SECRET_KEY_PATTERNS: &[&str] = &["token", "secret", "password", "passwd", "credential", "private_key", "api_key"];
+
+/// Whether a field name looks like it holds a secret
+pub fn is_secret_key(name: &str) -> bool {
+    let name = name.to_ascii_lowercase().replace('-', "_");
+    SECRET_KEY_PATTERNS.iter().any(|pattern| name.contains(pattern))
+}
+
+/// Replace the values of secret-looking fields, at any depth, with [`REDACTED`]
+pub fn redact_secrets(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, field) in map.iter_mut() {
+                if is_secret_key(key) && !field.is_null() {
+                    *field = serde_json::Value::String(REDACTED.to_string());
+                } else {
+                    redact_secrets(field);
+                }
+            }
         }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(redact_secrets),
+        _ => {}
+    }
+}
+
+/// Collect the dotted paths at which two JSON values differ
+///
+/// Objects are compared key by key (a key missing on one side counts as a
+/// difference); array elements are addressed as `path[index]`.
+pub fn diff_values(a: &serde_json::Value, b: &serde_json::Value) -> Vec<String> {
+    let mut paths = Vec::new();
+    diff_into(a, b, String::new(), &mut paths);
+    paths
+}
+
+fn diff_into(a: &serde_json::Value, b: &serde_json::Value, path: String, out: &mut Vec<String>) {
+    use serde_json::Value;
+
+    match (a, b) {
+        (Value::Object(left), Value::Object(right)) => {
+            let mut keys: Vec<&String> = left.keys().chain(right.keys()).collect();
+            keys.sort();
+            keys.dedup();
+
+            for key in keys {
+                let child = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                match (left.get(key), right.get(key)) {
+                    (Some(l), Some(r)) => diff_into(l, r, child, out),
+                    _ => out.push(child),
+                }
+            }
+        }
+        (Value::Array(left), Value::Array(right)) if left.len() == right.len() => {
+            for (i, (l, r)) in left.iter().zip(right).enumerate() {
+                diff_into(l, r, format!("{}[{}]", path, i), out);
+            }
+        }
+        _ if a != b => out.push(path),
+        _ => {}
     }
 }
 
@@ -276,11 +1077,17 @@ mod tests {
         let config = KawakazeConfig::default();
 
         assert_eq!(config.zfs_pool, "zroot/kawakaze");
+        assert_eq!(config.data_root, "/var/lib/kawakaze");
+        assert_eq!(config.jail_base_path, None);
+        assert!(!config.cleanup_jail_paths);
+        assert_eq!(config.allowed_mount_roots, [PathBuf::from("/usr/home"), PathBuf::from("/srv")]);
+        assert!(!config.allow_any_mount_source && !config.strict_mounts);
+        assert!(!config.allow_in_jail_mounts);
         assert_eq!(config.network.container_cidr, "10.11.0.0/16");
         assert_eq!(config.network.bridge_name, "kawakaze-bridge");
         assert_eq!(config.network.nat_enabled, true);
-        assert_eq!(config.storage.database_path, "/var/db/kawakaze/kawakaze.db");
-        assert_eq!(config.storage.socket_path, "/var/run/kawakaze.sock");
+        assert_eq!(config.storage.database_path, None);
+        assert_eq!(config.storage.socket_path, None);
         assert_eq!(config.storage.cache_path, "/var/cache/kawakaze");
         assert_eq!(config.api.timeout, 30);
     }
@@ -298,8 +1105,9 @@ mod tests {
     fn test_storage_config_default() {
         let config = StorageConfig::default();
 
-        assert_eq!(config.database_path, "/var/db/kawakaze/kawakaze.db");
-        assert_eq!(config.socket_path, "/var/run/kawakaze.sock");
+        assert_eq!(config.database_path, None);
+        assert_eq!(config.socket_path, None);
+        assert_eq!(config.builds_path, None);
         assert_eq!(config.cache_path, "/var/cache/kawakaze");
     }
 
@@ -314,19 +1122,69 @@ mod tests {
     fn test_load_and_save_config() {
         let config = KawakazeConfig {
             zfs_pool: "myPool/jails".to_string(),
+            data_root: "/srv/kawakaze".to_string(),
+            jail_base_path: Some("/jails".to_string()),
+            cleanup_jail_paths: true,
+            allowed_mount_roots: vec![PathBuf::from("/data")],
+            allow_any_mount_source: false,
+            strict_mounts: true,
+            allow_in_jail_mounts: true,
             network: NetworkConfig {
                 container_cidr: "192.168.1.0/24".to_string(),
                 bridge_name: "my-bridge".to_string(),
                 nat_enabled: false,
+                ipv4_enabled: true,
+                container_cidr6: Some("2001:db8:1::/64".to_string()),
             },
             storage: StorageConfig {
-                database_path: "/tmp/kawakaze.db".to_string(),
-                socket_path: "/tmp/kawakaze.sock".to_string(),
+                database_path: Some("/tmp/kawakaze.db".to_string()),
+                socket_path: Some("/tmp/kawakaze.sock".to_string()),
+                builds_path: None,
                 cache_path: "/tmp/cache".to_string(),
+                log_path: "/tmp/logs".to_string(),
+                keep_snapshots: 3,
+                layer_snapshots: false,
+                build_log_path: "/tmp/logs/builds".to_string(),
+                bootstrap_log_path: "/tmp/logs/bootstrap".to_string(),
+                log_retention_days: 7,
+                log_retention_bytes: 1024 * 1024,
+                allow_degraded_pool: true,
             },
             api: ApiConfig {
                 timeout: 60,
             },
+            devfs: DevfsConfig {
+                ruleset: 20,
+                manage_rules: false,
+                rulesets: vec![DevfsRulesetConfig {
+                    number: 21,
+                    unhide: vec!["pf".to_string()],
+                }],
+            },
+            diagnostics: DiagnosticsConfig {
+                slow_command_secs: 2,
+            },
+            encryption: EncryptionConfig {
+                enabled: true,
+                keyformat: "hex".to_string(),
+                keylocation: "file:///root/kawakaze.key".to_string(),
+            },
+            names: NamesConfig::default(),
+            read_only: ReadOnlyConfig {
+                tmpfs: vec![TmpfsConfig { path: "/tmp".to_string(), size: "8m".to_string() }],
+            },
+            container: ContainerRuntimeConfig { restart_on_boot: true },
+            trusted_keys: vec![TrustedKey { id: "ci".to_string(), public_key: "58".to_string() + &"66".repeat(31) }],
+            verify_signatures: SignaturePolicy::Enforce,
+            max_concurrent_builds: 4,
+            max_concurrent_bootstraps: 2,
+            progress_retention_secs: 60,
+            log_level: "info,kawakaze_backend::zfs=debug".to_string(),
+            node_labels: BTreeMap::from([("zone".to_string(), "eu-west".to_string())]),
+            uploads: UploadConfig { max_upload_bytes: 1 << 30, ..UploadConfig::default() },
+            http: HttpConfig { listen: Some("127.0.0.1:7080".to_string()), allow_remote: false },
+            snapshot_gc: SnapshotGcConfig { interval_secs: 0, pre_upgrade_retention_days: 30 },
+            sysctl: SysctlConfig { allowed_prefixes: vec!["net.pf".to_string()], ..SysctlConfig::default() },
         };
 
         // Save to temp file
@@ -337,13 +1195,40 @@ mod tests {
         let loaded = KawakazeConfig::load(temp_file.path()).unwrap();
 
         assert_eq!(loaded.zfs_pool, "myPool/jails");
+        assert_eq!(loaded.data_root, "/srv/kawakaze");
+        assert_eq!(loaded.jail_base_path.as_deref(), Some("/jails"));
+        assert!(loaded.cleanup_jail_paths);
+        assert_eq!(loaded.allowed_mount_roots, [PathBuf::from("/data")]);
+        assert_eq!(loaded.read_only.tmpfs, config.read_only.tmpfs);
+        assert_eq!(loaded.trusted_keys, config.trusted_keys);
+        assert_eq!(loaded.verify_signatures, SignaturePolicy::Enforce);
+        assert_eq!((loaded.max_concurrent_builds, loaded.max_concurrent_bootstraps), (4, 2));
+        assert_eq!(loaded.progress_retention_secs, 60);
+        assert_eq!(loaded.log_level, "info,kawakaze_backend::zfs=debug");
+        assert_eq!(loaded.node_labels["zone"], "eu-west");
+        assert_eq!((loaded.snapshot_gc.interval_secs, loaded.snapshot_gc.pre_upgrade_retention_days), (0, 30));
+        assert_eq!(loaded.sysctl.allowed_prefixes, ["net.pf"]);
+        assert_eq!(loaded.uploads.max_upload_bytes, 1 << 30);
+        assert_eq!(loaded.http.listen.as_deref(), Some("127.0.0.1:7080"));
+        assert!(loaded.container.restart_on_boot);
+        assert!(loaded.strict_mounts);
+        assert!(loaded.allow_in_jail_mounts);
         assert_eq!(loaded.network.container_cidr, "192.168.1.0/24");
         assert_eq!(loaded.network.bridge_name, "my-bridge");
+        assert_eq!(loaded.network.container_cidr6.as_deref(), Some("2001:db8:1::/64"));
         assert_eq!(loaded.network.nat_enabled, false);
-        assert_eq!(loaded.storage.database_path, "/tmp/kawakaze.db");
-        assert_eq!(loaded.storage.socket_path, "/tmp/kawakaze.sock");
+        assert_eq!(loaded.storage.database_path.as_deref(), Some("/tmp/kawakaze.db"));
+        assert_eq!(loaded.storage.socket_path.as_deref(), Some("/tmp/kawakaze.sock"));
+        assert_eq!(loaded.storage.builds_path, None);
         assert_eq!(loaded.storage.cache_path, "/tmp/cache");
         assert_eq!(loaded.api.timeout, 60);
+        assert_eq!(loaded.devfs.ruleset, 20);
+        assert!(!loaded.devfs.manage_rules);
+        assert_eq!(loaded.devfs.rulesets[0].unhide, vec!["pf"]);
+        assert_eq!(loaded.diagnostics.slow_command_secs, 2);
+        assert!(loaded.encryption.enabled);
+        assert_eq!(loaded.encryption.keyformat, "hex");
+        assert_eq!(loaded.encryption.keylocation, "file:///root/kawakaze.key");
     }
 
     #[test]
@@ -362,7 +1247,8 @@ mod tests {
         // Rest should be defaults
         assert_eq!(config.network.container_cidr, "10.11.0.0/16");
         assert_eq!(config.network.bridge_name, "kawakaze-bridge");
-        assert_eq!(config.storage.database_path, "/var/db/kawakaze/kawakaze.db");
+        assert_eq!(config.data_root, "/var/lib/kawakaze");
+        assert_eq!(config.storage.database_path, None);
         assert_eq!(config.api.timeout, 30);
     }
 
@@ -395,6 +1281,204 @@ mod tests {
         assert!(matches!(result, Err(ConfigError::InvalidValue(_))));
     }
 
+    #[test]
+    fn test_validate_relative_jail_base_path() {
+        let config = KawakazeConfig {
+            jail_base_path: Some("jails".to_string()),
+            ..Default::default()
+        };
+
+        let result = config.validate();
+        assert!(matches!(result, Err(ConfigError::InvalidValue(_))));
+
+        let config = KawakazeConfig {
+            data_root: "var/lib/kawakaze".to_string(),
+            ..Default::default()
+        };
+        assert!(matches!(config.validate(), Err(ConfigError::InvalidValue(_))));
+    }
+
+    #[test]
+    fn test_validate_hex_name_lengths() {
+        let mut config = KawakazeConfig::default();
+        config.names.hex_min_len = 12;
+        config.names.hex_max_len = 8;
+        assert!(matches!(config.validate(), Err(ConfigError::InvalidValue(_))));
+
+        config.names.hex_min_len = 0;
+        assert!(matches!(config.validate(), Err(ConfigError::InvalidValue(_))));
+
+        config.names.hex_min_len = 8;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_uploads() {
+        let mut config = KawakazeConfig::default();
+        assert_eq!(config.uploads.spool_path, "/var/spool/kawakaze/uploads");
+        assert!(config.validate().is_ok());
+
+        config.uploads.spool_path = "spool".to_string();
+        assert!(config.validate().is_err());
+        config.uploads.spool_path = "/var/spool/kawakaze/uploads".to_string();
+        config.uploads.max_upload_bytes = config.uploads.max_spool_bytes + 1;
+        assert!(config.validate().is_err());
+        config.uploads.max_upload_bytes = config.uploads.max_spool_bytes;
+        config.uploads.idle_timeout_secs = 0;
+        assert!(config.validate().is_err());
+
+        let parsed: KawakazeConfig = toml::from_str("zfs_pool = \"tank\"\n[uploads]\nmax_upload_bytes = 1024\n").unwrap();
+        assert_eq!(parsed.uploads.max_upload_bytes, 1024);
+        assert_eq!(parsed.uploads.idle_timeout_secs, 900);
+    }
+
+    #[test]
+    fn test_validate_http_listen() {
+        let mut config = KawakazeConfig::default();
+        assert!(config.http.listen.is_none());
+
+        config.http.listen = Some("127.0.0.1:7080".to_string());
+        assert!(config.validate().is_ok());
+        config.http.listen = Some("[::1]:7080".to_string());
+        assert!(config.validate().is_ok());
+        config.http.listen = Some("localhost".to_string());
+        assert!(config.validate().is_err());
+
+        // Anywhere but loopback only when asked for
+        config.http.listen = Some("0.0.0.0:7080".to_string());
+        assert!(config.validate().is_err());
+        config.http.allow_remote = true;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_read_only_tmpfs() {
+        let mut config = KawakazeConfig::default();
+        assert_eq!(config.read_only.tmpfs.len(), 2);
+        assert!(config.validate().is_ok());
+
+        config.read_only.tmpfs[0].path = "tmp".to_string();
+        assert!(config.validate().is_err());
+        config.read_only.tmpfs[0].path = "/tmp/../etc".to_string();
+        assert!(config.validate().is_err());
+        config.read_only.tmpfs[0].path = "/tmp".to_string();
+        config.read_only.tmpfs[0].size.clear();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_trusted_keys() {
+        let mut config = KawakazeConfig::default();
+        assert_eq!(config.verify_signatures, SignaturePolicy::Off);
+
+        // The Ed25519 base point, a valid public key
+        let key = "5866666666666666666666666666666666666666666666666666666666666666".to_string();
+        config.trusted_keys.push(TrustedKey { id: "ci".to_string(), public_key: key.clone() });
+        assert!(config.validate().is_ok());
+
+        config.trusted_keys.push(TrustedKey { id: "ci".to_string(), public_key: key });
+        assert!(config.validate().is_err());
+        config.trusted_keys[1].id = "release".to_string();
+        config.trusted_keys[1].public_key = "abcd".to_string();
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("release"), "{}", err);
+
+        let parsed: KawakazeConfig = toml::from_str("zfs_pool = \"tank\"\nverify_signatures = \"warn\"").unwrap();
+        assert_eq!(parsed.verify_signatures, SignaturePolicy::Warn);
+    }
+
+    #[test]
+    fn test_validate_concurrency_limits() {
+        let mut config = KawakazeConfig::default();
+        assert_eq!((config.max_concurrent_builds, config.max_concurrent_bootstraps), (2, 1));
+
+        config.max_concurrent_bootstraps = 0;
+        assert!(config.validate().is_err());
+        config.max_concurrent_bootstraps = 3;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_node_labels() {
+        let mut config = KawakazeConfig::default();
+        config.node_labels.insert("rack".to_string(), "r12".to_string());
+        assert!(config.validate().is_ok());
+
+        config.node_labels.insert("arch".to_string(), "amd64".to_string());
+        assert!(matches!(config.validate(), Err(ConfigError::InvalidValue(_))));
+    }
+
+    #[test]
+    fn test_validate_sysctl() {
+        let mut config = KawakazeConfig::default();
+        config.sysctl.allowed_prefixes.push("net.pf.".to_string());
+        config.sysctl.denied.push("net.inet.ip.forwarding".to_string());
+        assert!(config.validate().is_ok());
+
+        config.sysctl.allowed.push("kern ipc".to_string());
+        assert!(matches!(config.validate(), Err(ConfigError::InvalidValue(_))));
+    }
+
+    #[test]
+    fn test_validate_log_level() {
+        let mut config = KawakazeConfig::default();
+        assert_eq!(config.log_level, "info");
+
+        config.log_level = "kawakaze_backend=loud".to_string();
+        assert!(matches!(config.validate(), Err(ConfigError::InvalidValue(_))));
+        config.log_level = "warn,kawakaze_backend::zfs=trace".to_string();
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_devfs_rulesets() {
+        let mut config = KawakazeConfig::default();
+        config.devfs.rulesets.push(DevfsRulesetConfig {
+            number: config.devfs.ruleset,
+            unhide: vec!["pf".to_string()],
+        });
+        assert!(matches!(config.validate(), Err(ConfigError::InvalidValue(_))));
+
+        config.devfs.rulesets[0].number = 11;
+        assert!(config.validate().is_ok());
+
+        config.devfs.rulesets[0].unhide.push("/dev/ada0".to_string());
+        assert!(matches!(config.validate(), Err(ConfigError::InvalidValue(_))));
+
+        config.devfs.rulesets.clear();
+        config.devfs.ruleset = 0;
+        assert!(matches!(config.validate(), Err(ConfigError::InvalidValue(_))));
+    }
+
+    #[test]
+    fn test_validate_encryption() {
+        let mut config = KawakazeConfig::default();
+        assert!(!config.encryption.enabled);
+        assert!(config.validate().is_ok());
+
+        config.encryption.keylocation = "prompt".to_string();
+        assert!(matches!(config.validate(), Err(ConfigError::InvalidValue(_))));
+
+        config.encryption.keylocation = "file:///var/db/kawakaze/keys/tenant.key".to_string();
+        config.encryption.keyformat = "pkcs11".to_string();
+        assert!(matches!(config.validate(), Err(ConfigError::InvalidValue(_))));
+    }
+
+    #[test]
+    fn test_devfs_resolve() {
+        let mut devfs = DevfsConfig::default();
+        devfs.rulesets.push(DevfsRulesetConfig {
+            number: 11,
+            unhide: vec!["pf".to_string()],
+        });
+
+        assert_eq!(devfs.resolve(None).unwrap(), 10);
+        assert_eq!(devfs.resolve(Some(10)).unwrap(), 10);
+        assert_eq!(devfs.resolve(Some(11)).unwrap(), 11);
+        assert!(devfs.resolve(Some(0)).is_err());
+        assert!(devfs.resolve(Some(4)).is_err());
+    }
+
     #[test]
     fn test_validate_invalid_cidr() {
         let config = KawakazeConfig {
@@ -410,12 +1494,28 @@ mod tests {
         assert!(matches!(result, Err(ConfigError::InvalidValue(_))));
     }
 
+    #[test]
+    fn test_validate_ipv6_network() {
+        let network = |ipv4_enabled: bool, cidr6: Option<&str>| KawakazeConfig {
+            zfs_pool: "zroot/kawakaze".to_string(),
+            network: NetworkConfig { ipv4_enabled, container_cidr6: cidr6.map(String::from), ..Default::default() },
+            ..Default::default()
+        };
+
+        assert!(network(true, Some("fd00:6b7a::/64")).validate().is_ok());
+        assert!(network(false, Some("fd00:6b7a::/64")).validate().is_ok());
+        // IPv6-only needs a subnet, and the subnet must be IPv6
+        assert!(matches!(network(false, None).validate(), Err(ConfigError::InvalidValue(_))));
+        assert!(matches!(network(true, Some("10.12.0.0/16")).validate(), Err(ConfigError::InvalidValue(_))));
+        assert!(matches!(network(true, Some("fd00::/128")).validate(), Err(ConfigError::InvalidValue(_))));
+    }
+
     #[test]
     fn test_validate_empty_database_path() {
         let config = KawakazeConfig {
             zfs_pool: "zroot/kawakaze".to_string(),
             storage: StorageConfig {
-                database_path: "".to_string(),
+                database_path: Some("".to_string()),
                 ..Default::default()
             },
             ..Default::default()
@@ -470,6 +1570,74 @@ mod tests {
         assert!(!is_valid_cidr("10.11.0.0/invalid"));
     }
 
+    #[test]
+    fn test_redact_secrets() {
+        let mut value = serde_json::json!({
+            "zfs_pool": "zroot/kawakaze",
+            "registry": {
+                "auth_token": "abc",
+                "Password": "hunter2",
+                "tls-private-key": "/etc/ssl/key.pem",
+                "mirrors": [{"api_key": "k", "url": "https://example"}],
+                "secret": null,
+            },
+        });
+
+        redact_secrets(&mut value);
+
+        assert_eq!(value["zfs_pool"], "zroot/kawakaze");
+        assert_eq!(value["registry"]["auth_token"], REDACTED);
+        assert_eq!(value["registry"]["Password"], REDACTED);
+        assert_eq!(value["registry"]["tls-private-key"], REDACTED);
+        assert_eq!(value["registry"]["mirrors"][0]["api_key"], REDACTED);
+        assert_eq!(value["registry"]["mirrors"][0]["url"], "https://example");
+        assert!(value["registry"]["secret"].is_null());
+    }
+
+    #[test]
+    fn test_diff_values() {
+        let a = serde_json::json!({
+            "zfs_pool": "zroot/a",
+            "network": {"bridge_name": "br0", "nat_enabled": true},
+            "ports": [1, 2],
+            "removed": 1,
+        });
+        let b = serde_json::json!({
+            "zfs_pool": "zroot/a",
+            "network": {"bridge_name": "br1", "nat_enabled": true},
+            "ports": [1, 3],
+            "added": 1,
+        });
+
+        assert_eq!(diff_values(&a, &b), vec!["added", "network.bridge_name", "ports[1]", "removed"]);
+        assert!(diff_values(&a, &a).is_empty());
+    }
+
+    #[test]
+    fn test_drift_against_file() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("config.toml");
+
+        let running = KawakazeConfig::default();
+        running.save(&path).unwrap();
+        let source = ConfigSource::from_path(&path);
+        assert_eq!(source.path.as_deref(), Some(path.as_path()));
+        assert!(source.mtime.is_some());
+        assert!(running.drift(&source).unwrap().is_empty());
+
+        let mut edited = running.clone();
+        edited.zfs_pool = "tank/kawakaze".to_string();
+        edited.api.timeout = 90;
+        edited.save(&path).unwrap();
+        assert_eq!(running.drift(&source).unwrap(), vec!["api.timeout", "zfs_pool"]);
+    }
+
+    #[test]
+    fn test_drift_without_file() {
+        let config = KawakazeConfig::default();
+        assert!(config.drift(&ConfigSource::default()).unwrap().is_empty());
+    }
+
     #[test]
     fn test_load_defaults_creates_directories() {
         let temp_dir = tempfile::tempdir().unwrap();
@@ -1,6 +1,11 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Component, Path};
+
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::timestamp::Timestamp;
+
 pub type ContainerId = String;
 
 /// Represents the current state of a container
@@ -16,6 +21,9 @@ pub enum ContainerState {
     Paused,
     /// Container is being removed
     Removing,
+    /// The encryption key for the container's dataset couldn't be loaded;
+    /// never stored, re-checked at every daemon start
+    Locked,
 }
 
 impl ContainerState {
@@ -26,6 +34,7 @@ impl ContainerState {
             ContainerState::Stopped => "stopped",
             ContainerState::Paused => "paused",
             ContainerState::Removing => "removing",
+            ContainerState::Locked => "locked",
         }
     }
 }
@@ -46,11 +55,43 @@ impl std::str::FromStr for ContainerState {
             "stopped" => Ok(ContainerState::Stopped),
             "paused" => Ok(ContainerState::Paused),
             "removing" => Ok(ContainerState::Removing),
+            "locked" => Ok(ContainerState::Locked),
             _ => Err(format!("Invalid container state: {}", s)),
         }
     }
 }
 
+/// Container counts by state
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContainerSummary {
+    /// Every container, including ones being removed
+    pub total: usize,
+    pub running: usize,
+    pub stopped: usize,
+    pub created: usize,
+    pub paused: usize,
+    /// Containers whose dataset's encryption key isn't loaded
+    #[serde(default)]
+    pub locked: usize,
+    /// Containers whose health check is failing (always 0 without health checks)
+    pub unhealthy: usize,
+}
+
+impl ContainerSummary {
+    /// Count one container in `state`
+    pub fn add(&mut self, state: ContainerState) {
+        self.total += 1;
+        match state {
+            ContainerState::Running => self.running += 1,
+            ContainerState::Stopped => self.stopped += 1,
+            ContainerState::Created => self.created += 1,
+            ContainerState::Paused => self.paused += 1,
+            ContainerState::Locked => self.locked += 1,
+            ContainerState::Removing => {}
+        }
+    }
+}
+
 /// Defines when a container should be restarted
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum RestartPolicy {
@@ -101,8 +142,46 @@ impl Default for RestartPolicy {
     }
 }
 
-/// Protocol for port mappings
+/// Why a container last stopped, for post-incident review
+///
+/// Each stop path records its own cause where it stops the container; none
+/// is inferred afterwards. Nothing records `ProcessExit`, `HealthcheckKill`
+/// or `Oom` yet: kawakaze has no process supervisor, health monitor or
+/// memory limit, and these are the causes they will record.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StopCause {
+    /// A client asked for the stop; the uid is the socket peer's, when the
+    /// connection reported it
+    UserRequest(Option<u32>),
+    /// The container's process exited
+    ProcessExit,
+    /// The restart policy tried to start the container again and failed
+    RestartPolicyGaveUp,
+    /// The daemon stopped the container as it shut down
+    DaemonShutdown,
+    /// The health monitor killed an unhealthy container
+    HealthcheckKill,
+    /// The container ran out of memory
+    Oom,
+}
+
+impl std::fmt::Display for StopCause {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StopCause::UserRequest(Some(uid)) => write!(f, "stopped by uid {}", uid),
+            StopCause::UserRequest(None) => write!(f, "stopped by request"),
+            StopCause::ProcessExit => write!(f, "process exited"),
+            StopCause::RestartPolicyGaveUp => write!(f, "restart policy gave up"),
+            StopCause::DaemonShutdown => write!(f, "daemon shutdown"),
+            StopCause::HealthcheckKill => write!(f, "killed by healthcheck"),
+            StopCause::Oom => write!(f, "out of memory"),
+        }
+    }
+}
+
+/// Protocol for port mappings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum PortProtocol {
     Tcp,
     Udp,
@@ -148,6 +227,10 @@ pub struct PortMapping {
     pub container_port: u16,
     #[serde(default)]
     pub protocol: PortProtocol,
+    /// Host address the port is published on (`None`: every address);
+    /// only the container address of the same family is forwarded to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host_ip: Option<std::net::IpAddr>,
 }
 
 impl PortMapping {
@@ -156,8 +239,91 @@ impl PortMapping {
             host_port,
             container_port,
             protocol,
+            host_ip: None,
+        }
+    }
+
+    /// Publish on `host_ip` only
+    pub fn with_host_ip(mut self, host_ip: std::net::IpAddr) -> Self {
+        self.host_ip = Some(host_ip);
+        self
+    }
+}
+
+/// Host ports handed out to mappings that ask for host port 0
+pub const DYNAMIC_HOST_PORTS: std::ops::RangeInclusive<u16> = 49152..=65535;
+
+/// Give each mapping with host port 0 the lowest port in
+/// `DYNAMIC_HOST_PORTS` not already published for the same protocol, by
+/// `in_use` or by an earlier mapping in `ports`
+pub fn assign_host_ports(ports: &mut [PortMapping], in_use: &[PortMapping]) -> Result<(), String> {
+    let mut taken: std::collections::HashSet<(u16, PortProtocol)> = in_use
+        .iter()
+        .chain(ports.iter())
+        .filter(|p| p.host_port != 0)
+        .map(|p| (p.host_port, p.protocol))
+        .collect();
+
+    for mapping in ports.iter_mut().filter(|p| p.host_port == 0) {
+        let port = DYNAMIC_HOST_PORTS
+            .clone()
+            .find(|port| !taken.contains(&(*port, mapping.protocol)))
+            .ok_or_else(|| format!("No free {} host port for container port {}", mapping.protocol, mapping.container_port))?;
+        taken.insert((port, mapping.protocol));
+        mapping.host_port = port;
+    }
+
+    Ok(())
+}
+
+/// Whether a host service already listens on `port`
+///
+/// Only `AddrInUse` counts; a port we may not bind (e.g. below 1024 when
+/// not root) isn't known to be taken. Published ports are forwarded by pf
+/// and never bound, so container ports are tracked in [`PublishedPorts`].
+pub fn host_port_bound(port: u16, protocol: PortProtocol) -> bool {
+    let addr = (std::net::Ipv4Addr::UNSPECIFIED, port);
+    let bound = match protocol {
+        PortProtocol::Tcp => std::net::TcpListener::bind(addr).map(drop),
+        PortProtocol::Udp => std::net::UdpSocket::bind(addr).map(drop),
+    };
+    matches!(bound, Err(e) if e.kind() == std::io::ErrorKind::AddrInUse)
+}
+
+/// Host ports published by containers (port and protocol -> container)
+#[derive(Debug, Clone, Default)]
+pub struct PublishedPorts {
+    owners: std::collections::HashMap<(u16, PortProtocol), ContainerId>,
+}
+
+impl PublishedPorts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Container publishing `port`, if any
+    pub fn owner(&self, port: u16, protocol: PortProtocol) -> Option<&ContainerId> {
+        self.owners.get(&(port, protocol))
+    }
+
+    /// Record `ports` as published by `id`; the caller checked them first
+    pub fn claim(&mut self, id: &ContainerId, ports: &[PortMapping]) {
+        for mapping in ports {
+            self.owners.insert((mapping.host_port, mapping.protocol), id.clone());
         }
     }
+
+    /// Forget every port published by `id`
+    pub fn release(&mut self, id: &ContainerId) {
+        self.owners.retain(|_, owner| owner != id);
+    }
+
+    /// Ports published by `id`
+    pub fn ports_of(&self, id: &ContainerId) -> Vec<(u16, PortProtocol)> {
+        let mut ports: Vec<_> = self.owners.iter().filter(|(_, owner)| *owner == id).map(|(port, _)| *port).collect();
+        ports.sort_by_key(|(port, protocol)| (*port, protocol.as_str()));
+        ports
+    }
 }
 
 /// Type of mount for a container
@@ -211,6 +377,28 @@ pub struct Mount {
     pub mount_type: MountType,
     #[serde(default)]
     pub read_only: bool,
+    /// Dataset created for an image VOLUME nothing else was mounted on; it
+    /// belongs to the container and is destroyed with it
+    #[serde(default)]
+    pub anonymous: bool,
+    /// Hand the (external) ZFS dataset to the jail with `zfs jail` instead
+    /// of mounting it for the container (see `dataset_attach`)
+    #[serde(default)]
+    pub delegate: bool,
+}
+
+/// How a volume reaches the container
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum VolumeMode {
+    /// A host directory, null-mounted
+    Nullfs,
+    /// A dataset kawakaze created for an image VOLUME
+    Anonymous,
+    /// An existing dataset mounted under the container's root
+    Attached,
+    /// An existing dataset handed to the jail with `zfs jail`
+    Delegated,
 }
 
 impl Mount {
@@ -220,8 +408,213 @@ impl Mount {
             destination,
             mount_type,
             read_only,
+            anonymous: false,
+            delegate: false,
+        }
+    }
+
+    /// Delegate the dataset to the jail rather than mount it
+    pub fn with_delegate(mut self, delegate: bool) -> Self {
+        self.delegate = delegate;
+        self
+    }
+
+    pub fn mode(&self) -> VolumeMode {
+        match (self.mount_type, self.anonymous, self.delegate) {
+            (MountType::Nullfs, _, _) => VolumeMode::Nullfs,
+            (MountType::Zfs, true, _) => VolumeMode::Anonymous,
+            (MountType::Zfs, false, false) => VolumeMode::Attached,
+            (MountType::Zfs, false, true) => VolumeMode::Delegated,
         }
     }
+
+    /// A dataset kawakaze didn't create, passed through to the container
+    pub fn is_external_dataset(&self) -> bool {
+        matches!(self.mode(), VolumeMode::Attached | VolumeMode::Delegated)
+    }
+
+    /// Anonymous volume on `dataset`
+    pub fn anonymous(dataset: String, destination: String) -> Self {
+        Mount { anonymous: true, ..Self::new(dataset, destination, MountType::Zfs, false) }
+    }
+}
+
+/// A tmpfs mounted in the container while it runs (`--tmpfs /run:size=64m`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TmpfsMount {
+    /// Path inside the container
+    pub destination: String,
+    /// Size limit; without one mount_tmpfs allows all free memory and swap
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size_bytes: Option<u64>,
+    /// Permissions of the mount's root directory (e.g. 0o1777)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+}
+
+impl TmpfsMount {
+    pub fn new(destination: String) -> Self {
+        Self { destination, size_bytes: None, mode: None }
+    }
+
+    /// Options for `mount -t tmpfs -o`, empty without size and mode
+    pub fn options(&self) -> String {
+        let size = self.size_bytes.map(|size| format!("size={}", size));
+        let mode = self.mode.map(|mode| format!("mode={:o}", mode));
+        size.into_iter().chain(mode).collect::<Vec<_>>().join(",")
+    }
+}
+
+/// `path` with exactly one leading and no trailing slash, for comparing
+/// mount destinations
+fn normalize_destination(path: &str) -> String {
+    format!("/{}", path.trim_matches('/'))
+}
+
+/// Check the tmpfs mounts of a new container: absolute destinations below
+/// `/` without `..`, a non-zero size, a mode of at most 7777, and no
+/// destination used twice or by one of `mounts`
+pub fn check_tmpfs(tmpfs: &[TmpfsMount], mounts: &[Mount]) -> Result<(), String> {
+    let mut taken: Vec<String> = mounts.iter().map(|m| normalize_destination(&m.destination)).collect();
+    for t in tmpfs {
+        let destination = normalize_destination(&t.destination);
+        if !t.destination.starts_with('/') || destination == "/" || Path::new(&t.destination).components().any(|c| c == Component::ParentDir) {
+            return Err(format!("Invalid tmpfs destination '{}': must be an absolute path below /", t.destination));
+        }
+        if t.size_bytes == Some(0) {
+            return Err(format!("Invalid tmpfs size for {}: must be more than 0 bytes", t.destination));
+        }
+        if let Some(mode) = t.mode
+            && mode > 0o7777
+        {
+            return Err(format!("Invalid tmpfs mode {:o} for {}: at most 7777", mode, t.destination));
+        }
+        if taken.contains(&destination) {
+            return Err(format!("tmpfs destination {} is already used by another mount", destination));
+        }
+        taken.push(destination);
+    }
+    Ok(())
+}
+
+/// The environment a container's processes get: the image's ENV, then the
+/// container's own variables, which win
+///
+/// The client has already merged the container's own: `--env-file` first,
+/// then `-e`, so a flag overrides the file and both override the image.
+pub fn effective_env(image: &HashMap<String, String>, container: &BTreeMap<String, String>) -> BTreeMap<String, String> {
+    let mut env: BTreeMap<String, String> = image.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+    env.extend(container.iter().map(|(k, v)| (k.clone(), v.clone())));
+    env
+}
+
+/// Check the environment of a new container: names of letters, digits and
+/// `_` not starting with a digit, and no NUL in the values
+pub fn check_env(env: &BTreeMap<String, String>) -> Result<(), String> {
+    for (name, value) in env {
+        let valid = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err(format!("Invalid environment variable name '{}'", name));
+        }
+        if value.contains('\0') {
+            return Err(format!("Environment variable {} contains a NUL byte", name));
+        }
+    }
+    Ok(())
+}
+
+/// Anonymous volumes for the image's VOLUME paths that none of `mounts` or
+/// `tmpfs` covers, one dataset `<volumes_dataset>/<short id>-<n>` each
+pub fn plan_anonymous_volumes(
+    image_volumes: &[String],
+    mounts: &[Mount],
+    tmpfs: &[TmpfsMount],
+    volumes_dataset: &str,
+    container_id: &str,
+) -> Vec<Mount> {
+    let mut taken: Vec<String> = mounts
+        .iter()
+        .map(|m| normalize_destination(&m.destination))
+        .chain(tmpfs.iter().map(|t| normalize_destination(&t.destination)))
+        .collect();
+
+    let mut volumes = Vec::new();
+    for path in image_volumes {
+        let path = normalize_destination(path);
+        if taken.contains(&path) {
+            continue;
+        }
+        let dataset = format!(
+            "{}/{}-{}",
+            volumes_dataset,
+            &container_id[..container_id.len().min(8)],
+            volumes.len()
+        );
+        volumes.push(Mount::anonymous(dataset, path.clone()));
+        taken.push(path);
+    }
+    volumes
+}
+
+/// Order in which a batch of containers is started: every member after the
+/// members named in its `depends_on`, ties in request order. `members` are
+/// each member's name and `depends_on`; names not in the batch don't affect
+/// the order and are left to the caller to check.
+pub fn start_order(members: &[(Option<&str>, &[String])]) -> Result<Vec<usize>, String> {
+    let mut by_name = HashMap::new();
+    for (idx, (name, _)) in members.iter().enumerate() {
+        if let Some(name) = name
+            && by_name.insert(*name, idx).is_some()
+        {
+            return Err(format!("Container name '{}' is used twice in the batch", name));
+        }
+    }
+
+    let mut deps: Vec<Vec<usize>> = Vec::with_capacity(members.len());
+    for (name, depends_on) in members {
+        let mut member_deps = Vec::new();
+        for dependency in depends_on.iter() {
+            if Some(dependency.as_str()) == *name {
+                return Err(format!("Container '{}' depends on itself", dependency));
+            }
+            if let Some(&idx) = by_name.get(dependency.as_str()) {
+                member_deps.push(idx);
+            }
+        }
+        deps.push(member_deps);
+    }
+
+    let mut order = Vec::with_capacity(members.len());
+    let mut placed = vec![false; members.len()];
+    while order.len() < members.len() {
+        let next = (0..members.len()).find(|&i| !placed[i] && deps[i].iter().all(|&dep| placed[dep]));
+        let Some(next) = next else {
+            return Err(format!("depends_on forms a cycle: {}", describe_cycle(members, &deps, &placed)));
+        };
+        placed[next] = true;
+        order.push(next);
+    }
+    Ok(order)
+}
+
+/// `a -> b -> a` for a cycle among the members not yet placed
+fn describe_cycle(members: &[(Option<&str>, &[String])], deps: &[Vec<usize>], placed: &[bool]) -> String {
+    // Every unplaced member waits on an unplaced member, so following those
+    // edges from any of them runs into a cycle
+    let mut path: Vec<usize> = Vec::new();
+    let mut current = (0..members.len()).find(|&i| !placed[i]).unwrap_or(0);
+    while !path.contains(&current) {
+        path.push(current);
+        current = deps[current].iter().copied().find(|&dep| !placed[dep]).unwrap_or(current);
+    }
+    let start = path.iter().position(|&i| i == current).unwrap_or(0);
+    path[start..]
+        .iter()
+        .chain(std::iter::once(&current))
+        .map(|&i| members[i].0.unwrap_or("?"))
+        .collect::<Vec<_>>()
+        .join(" -> ")
 }
 
 /// Configuration for creating a container
@@ -237,12 +630,56 @@ pub struct ContainerConfig {
     /// Volume mounts (ZFS datasets or nullfs filepaths)
     #[serde(default)]
     pub volumes: Vec<Mount>,
+    /// tmpfs mounted while the container runs
+    #[serde(default)]
+    pub tmpfs: Vec<TmpfsMount>,
     /// Restart policy
     #[serde(default)]
     pub restart_policy: RestartPolicy,
     /// Command to run (overrides image's CMD/ENTRYPOINT)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub command: Option<Vec<String>>,
+    /// Refuse removal unless explicitly overridden
+    #[serde(default)]
+    pub protected: bool,
+    /// devfs ruleset override (`None` uses the configured default)
+    #[serde(default)]
+    pub devfs_ruleset: Option<u16>,
+    /// Skip the image's HEALTHCHECK for this container
+    #[serde(default)]
+    pub healthcheck_disabled: bool,
+    /// Start the container when the daemon is launched with `--boot`
+    #[serde(default)]
+    pub boot: bool,
+    /// Put the container on an encrypted dataset
+    #[serde(default)]
+    pub encrypted: bool,
+    /// Run with the dataset `readonly=on`, writable only through volumes
+    /// and tmpfs
+    #[serde(default)]
+    pub read_only_root: bool,
+    /// Extra names other containers on the network reach this one by
+    #[serde(default)]
+    pub network_aliases: Vec<String>,
+    /// Host name inside the jail (`None` follows the container's name)
+    #[serde(default)]
+    pub hostname: Option<String>,
+    /// File systems the container may mount itself
+    #[serde(default)]
+    pub mount_permissions: crate::jail::MountPermissions,
+    /// The container's own environment, over the image's ENV
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Where it may run; checked against this node's labels at create
+    #[serde(default)]
+    pub placement: Option<crate::placement::Placement>,
+    /// sysctls set in the jail at start, already checked against the
+    /// allowlist (see [`crate::sysctl`])
+    #[serde(default)]
+    pub sysctls: BTreeMap<String, String>,
+    /// Free-form notes from the operator
+    #[serde(default)]
+    pub description: Option<String>,
 }
 
 /// Represents a container (running jail instance)
@@ -256,12 +693,84 @@ pub struct Container {
     pub state: ContainerState,
     pub restart_policy: RestartPolicy,
     pub mounts: Vec<Mount>,
+    /// tmpfs mounted at start, after the volumes, and unmounted at stop
+    #[serde(default)]
+    pub tmpfs: Vec<TmpfsMount>,
     pub port_mappings: Vec<PortMapping>,
     pub ip: Option<String>,
+    /// IPv6 address on the network, when it has an IPv6 subnet
+    #[serde(default)]
+    pub ip6: Option<String>,
     /// Command to run (overrides image's CMD/ENTRYPOINT)
     pub command: Option<Vec<String>>,
-    pub created_at: i64,
-    pub started_at: Option<i64>,
+    /// Environment given at create (`--env-file`, then `-e`); the image's
+    /// ENV is under it (see [`effective_env`])
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Placement constraints and preferences given at create (see
+    /// [`crate::placement`])
+    #[serde(default)]
+    pub placement: Option<crate::placement::Placement>,
+    /// sysctls set in the jail at every start (see [`crate::sysctl`])
+    #[serde(default)]
+    pub sysctls: BTreeMap<String, String>,
+    /// Values the jail reported for `sysctls` at the last start; cleared
+    /// when it stops (not persisted)
+    #[serde(default)]
+    pub applied_sysctls: BTreeMap<String, String>,
+    /// Free-form notes from the operator, set at create or by update
+    #[serde(default)]
+    pub description: Option<String>,
+    pub created_at: Timestamp,
+    pub started_at: Option<Timestamp>,
+    /// When the container last stopped, and why (see [`StopCause`])
+    #[serde(default)]
+    pub stopped_at: Option<Timestamp>,
+    #[serde(default)]
+    pub last_stop_cause: Option<StopCause>,
+    /// Exec sessions killed by the last stop (not persisted)
+    #[serde(default)]
+    pub last_stop_exec_sessions: usize,
+    /// Refuse removal unless explicitly overridden
+    #[serde(default)]
+    pub protected: bool,
+    /// devfs ruleset override (`None` uses the configured default)
+    #[serde(default)]
+    pub devfs_ruleset: Option<u16>,
+    /// Skip the image's HEALTHCHECK for this container
+    #[serde(default)]
+    pub healthcheck_disabled: bool,
+    /// Start the container when the daemon is launched with `--boot`
+    #[serde(default)]
+    pub boot: bool,
+    /// The container's dataset uses ZFS native encryption
+    #[serde(default)]
+    pub encrypted: bool,
+    /// The dataset is a full copy of the image (`zfs send | zfs receive`)
+    /// rather than a clone, so it shares no space with the image
+    #[serde(default)]
+    pub full_copy: bool,
+    /// The dataset is `readonly=on` while the container runs
+    #[serde(default)]
+    pub read_only_root: bool,
+    /// Extra names other containers on the network reach this one by
+    /// (see `hosts`)
+    #[serde(default)]
+    pub network_aliases: Vec<String>,
+    /// Host name set at create; `None` follows the container's name
+    #[serde(default)]
+    pub hostname: Option<String>,
+    /// Extra IPv4 and IPv6 addresses of a jail without VNET (`ip4.addr`
+    /// and `ip6.addr`), added and removed with `network connect`/`disconnect`
+    #[serde(default)]
+    pub ip_aliases: Vec<String>,
+    /// File systems the container may mount itself (`allow.mount.*`)
+    #[serde(default)]
+    pub mount_permissions: crate::jail::MountPermissions,
+    /// Newer kawakaze version that last wrote the record; the container
+    /// is read-only while set (see `version`)
+    #[serde(default)]
+    pub written_by_newer: Option<String>,
 }
 
 impl Container {
@@ -276,11 +785,33 @@ impl Container {
             state: ContainerState::Created,
             restart_policy: RestartPolicy::default(),
             mounts: Vec::new(),
+            tmpfs: Vec::new(),
             port_mappings: Vec::new(),
             ip: None,
+            ip6: None,
             command: None,
-            created_at: chrono::Utc::now().timestamp(),
+            created_at: Timestamp::now(),
             started_at: None,
+            stopped_at: None,
+            last_stop_cause: None,
+            last_stop_exec_sessions: 0,
+            env: BTreeMap::new(),
+            placement: None,
+            sysctls: BTreeMap::new(),
+            applied_sysctls: BTreeMap::new(),
+            description: None,
+            protected: false,
+            devfs_ruleset: None,
+            healthcheck_disabled: false,
+            boot: false,
+            encrypted: false,
+            full_copy: false,
+            read_only_root: false,
+            network_aliases: Vec::new(),
+            hostname: None,
+            ip_aliases: Vec::new(),
+            mount_permissions: crate::jail::MountPermissions::None,
+            written_by_newer: None,
         }
     }
 
@@ -296,11 +827,33 @@ impl Container {
             state: ContainerState::Created,
             restart_policy: RestartPolicy::default(),
             mounts: Vec::new(),
+            tmpfs: Vec::new(),
             port_mappings: Vec::new(),
             ip: None,
+            ip6: None,
             command: None,
-            created_at: chrono::Utc::now().timestamp(),
+            created_at: Timestamp::now(),
             started_at: None,
+            stopped_at: None,
+            last_stop_cause: None,
+            last_stop_exec_sessions: 0,
+            env: BTreeMap::new(),
+            placement: None,
+            sysctls: BTreeMap::new(),
+            applied_sysctls: BTreeMap::new(),
+            description: None,
+            protected: false,
+            devfs_ruleset: None,
+            healthcheck_disabled: false,
+            boot: false,
+            encrypted: false,
+            full_copy: false,
+            read_only_root: false,
+            network_aliases: Vec::new(),
+            hostname: None,
+            ip_aliases: Vec::new(),
+            mount_permissions: crate::jail::MountPermissions::None,
+            written_by_newer: None,
         }
     }
 
@@ -317,8 +870,8 @@ impl Container {
         port_mappings: Vec<PortMapping>,
         ip: Option<String>,
         command: Option<Vec<String>>,
-        created_at: i64,
-        started_at: Option<i64>,
+        created_at: Timestamp,
+        started_at: Option<Timestamp>,
     ) -> Self {
         Container {
             id,
@@ -329,11 +882,33 @@ impl Container {
             state,
             restart_policy,
             mounts,
+            tmpfs: Vec::new(),
             port_mappings,
             ip,
+            ip6: None,
             command,
             created_at,
             started_at,
+            stopped_at: None,
+            last_stop_cause: None,
+            last_stop_exec_sessions: 0,
+            env: BTreeMap::new(),
+            placement: None,
+            sysctls: BTreeMap::new(),
+            applied_sysctls: BTreeMap::new(),
+            description: None,
+            protected: false,
+            devfs_ruleset: None,
+            healthcheck_disabled: false,
+            boot: false,
+            encrypted: false,
+            full_copy: false,
+            read_only_root: false,
+            network_aliases: Vec::new(),
+            hostname: None,
+            ip_aliases: Vec::new(),
+            mount_permissions: crate::jail::MountPermissions::None,
+            written_by_newer: None,
         }
     }
 
@@ -354,6 +929,17 @@ impl Container {
         self
     }
 
+    /// Sets the IPv6 address for the container
+    pub fn with_ip6(mut self, ip6: String) -> Self {
+        self.ip6 = Some(ip6);
+        self
+    }
+
+    /// Addresses on the network, IPv4 first
+    pub fn addresses(&self) -> Vec<String> {
+        self.ip.iter().chain(self.ip6.iter()).cloned().collect()
+    }
+
     /// Sets the restart policy
     pub fn with_restart_policy(mut self, policy: RestartPolicy) -> Self {
         self.restart_policy = policy;
@@ -366,6 +952,36 @@ impl Container {
         self
     }
 
+    /// Sets the container's own environment
+    pub fn with_env(mut self, env: BTreeMap<String, String>) -> Self {
+        self.env = env;
+        self
+    }
+
+    /// Sets the sysctls set in the jail at start
+    pub fn with_sysctls(mut self, sysctls: BTreeMap<String, String>) -> Self {
+        self.sysctls = sysctls;
+        self
+    }
+
+    /// Sets the operator's notes on the container
+    pub fn with_description(mut self, description: Option<String>) -> Self {
+        self.description = description;
+        self
+    }
+
+    /// Sets where the container may run
+    pub fn with_placement(mut self, placement: Option<crate::placement::Placement>) -> Self {
+        self.placement = placement;
+        self
+    }
+
+    /// Sets the tmpfs mounted while the container runs
+    pub fn with_tmpfs(mut self, tmpfs: Vec<TmpfsMount>) -> Self {
+        self.tmpfs = tmpfs;
+        self
+    }
+
     /// Adds a port mapping to the container
     pub fn with_port_mapping(mut self, mapping: PortMapping) -> Self {
         self.port_mappings.push(mapping);
@@ -378,13 +994,89 @@ impl Container {
         self
     }
 
+    /// Marks the container as protected against removal
+    pub fn with_protected(mut self, protected: bool) -> Self {
+        self.protected = protected;
+        self
+    }
+
+    /// Sets the devfs ruleset override
+    pub fn with_devfs_ruleset(mut self, devfs_ruleset: Option<u16>) -> Self {
+        self.devfs_ruleset = devfs_ruleset;
+        self
+    }
+
+    /// Skips the image's HEALTHCHECK for this container
+    pub fn with_healthcheck_disabled(mut self, disabled: bool) -> Self {
+        self.healthcheck_disabled = disabled;
+        self
+    }
+
+    /// Marks the container to be started at boot
+    pub fn with_boot(mut self, boot: bool) -> Self {
+        self.boot = boot;
+        self
+    }
+
+    /// Makes the root read-only while the container runs
+    pub fn with_read_only_root(mut self, read_only_root: bool) -> Self {
+        self.read_only_root = read_only_root;
+        self
+    }
+
+    /// Sets what the container may mount itself
+    pub fn with_mount_permissions(mut self, mount_permissions: crate::jail::MountPermissions) -> Self {
+        self.mount_permissions = mount_permissions;
+        self
+    }
+
+    /// Sets the extra `ip4.addr` and `ip6.addr` addresses of a jail without VNET
+    pub fn with_ip_aliases(mut self, ip_aliases: Vec<String>) -> Self {
+        self.ip_aliases = ip_aliases;
+        self
+    }
+
+    /// Sets the names the container answers to on its network besides its own
+    pub fn with_network_aliases(mut self, network_aliases: Vec<String>) -> Self {
+        self.network_aliases = network_aliases;
+        self
+    }
+
+    /// Sets an explicit host name, which a rename leaves alone
+    pub fn with_hostname(mut self, hostname: Option<String>) -> Self {
+        self.hostname = hostname;
+        self
+    }
+
+    /// Records how the container's dataset was made from the image
+    pub fn with_dataset_copy(mut self, encrypted: bool, full_copy: bool) -> Self {
+        self.encrypted = encrypted;
+        self.full_copy = full_copy;
+        self
+    }
+
+    /// Restores the last stop loaded from the database
+    pub fn with_last_stop(mut self, stopped_at: Option<Timestamp>, cause: Option<StopCause>) -> Self {
+        self.stopped_at = stopped_at;
+        self.last_stop_cause = cause;
+        self
+    }
+
+    /// Marks the container stopped now, for `cause`
+    pub fn record_stop(&mut self, cause: StopCause) {
+        self.set_state(ContainerState::Stopped);
+        self.stopped_at = Some(Timestamp::now());
+        self.last_stop_cause = Some(cause);
+        self.last_stop_exec_sessions = 0;
+    }
+
     /// Updates the container state
     pub fn set_state(&mut self, state: ContainerState) {
         self.state = state;
         match state {
             ContainerState::Running => {
                 if self.started_at.is_none() {
-                    self.started_at = Some(chrono::Utc::now().timestamp());
+                    self.started_at = Some(Timestamp::now());
                 }
             }
             ContainerState::Stopped => {
@@ -394,6 +1086,11 @@ impl Container {
         }
     }
 
+    /// Why the container can't be changed, if it can't
+    pub fn readonly_reason(&self) -> Option<&'static str> {
+        self.written_by_newer.as_ref().map(|_| crate::version::READONLY_REASON)
+    }
+
     /// Returns whether the container is running
     pub fn is_running(&self) -> bool {
         self.state == ContainerState::Running
@@ -408,12 +1105,246 @@ impl Container {
     pub fn display_name(&self) -> &str {
         self.name.as_deref().unwrap_or(self.id.as_str())
     }
+
+    /// Host name inside the jail: the explicit one, otherwise the name
+    pub fn hostname(&self) -> &str {
+        self.hostname.as_deref().unwrap_or(self.display_name())
+    }
+
+    /// Renames the container. Returns the new host name when the host name
+    /// followed the old name, `None` when it was set explicitly.
+    pub fn rename(&mut self, name: String) -> Option<String> {
+        self.name = Some(name);
+        self.hostname.is_none().then(|| self.hostname().to_string())
+    }
+}
+
+/// Jail parameters that take a jail from the `before` IP aliases to
+/// `after`: `ip4.addr` and `ip6.addr` lists, each only when that family
+/// has aliases before or after, so a jail never using IPv6 is left alone
+pub fn ip_alias_params(before: &[String], after: &[String]) -> Vec<(&'static str, String)> {
+    let family = |aliases: &[String], v6: bool| -> Vec<String> {
+        aliases
+            .iter()
+            .filter(|alias| alias.parse::<std::net::IpAddr>().is_ok_and(|ip| ip.is_ipv6() == v6))
+            .cloned()
+            .collect()
+    };
+
+    [("ip4.addr", false), ("ip6.addr", true)]
+        .into_iter()
+        .filter(|(_, v6)| !family(before, *v6).is_empty() || !family(after, *v6).is_empty())
+        .map(|(param, v6)| (param, family(after, v6).join(",")))
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_container_summary_serialization() {
+        let mut summary = ContainerSummary::default();
+        for state in [ContainerState::Running, ContainerState::Running, ContainerState::Stopped, ContainerState::Removing] {
+            summary.add(state);
+        }
+
+        assert_eq!(
+            serde_json::to_value(summary).unwrap(),
+            serde_json::json!({
+                "total": 4,
+                "running": 2,
+                "stopped": 1,
+                "created": 0,
+                "paused": 0,
+                "locked": 0,
+                "unhealthy": 0,
+            })
+        );
+    }
+
+    #[test]
+    fn test_ip_alias_params() {
+        let aliases = |list: &[&str]| list.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        let params = |before: &[&str], after: &[&str]| ip_alias_params(&aliases(before), &aliases(after));
+
+        assert_eq!(params(&[], &["192.0.2.10"]), vec![("ip4.addr", "192.0.2.10".to_string())]);
+        assert_eq!(params(&[], &["2001:db8::10"]), vec![("ip6.addr", "2001:db8::10".to_string())]);
+        assert_eq!(
+            params(&["192.0.2.10"], &["192.0.2.10", "2001:db8::10", "2001:db8::11"]),
+            vec![("ip4.addr", "192.0.2.10".to_string()), ("ip6.addr", "2001:db8::10,2001:db8::11".to_string())]
+        );
+        // Removing the last address of a family clears its list
+        assert_eq!(
+            params(&["192.0.2.10", "2001:db8::10"], &["192.0.2.10"]),
+            vec![("ip4.addr", "192.0.2.10".to_string()), ("ip6.addr", String::new())]
+        );
+        assert!(params(&[], &[]).is_empty());
+    }
+
+    #[test]
+    fn test_port_mapping_host_ip_serde() {
+        let mapping = PortMapping::new(8080, 80, PortProtocol::Tcp);
+        assert!(!serde_json::to_string(&mapping).unwrap().contains("host_ip"));
+
+        let bound = mapping.with_host_ip("::1".parse().unwrap());
+        let json = serde_json::to_string(&bound).unwrap();
+        assert!(json.contains(r#""host_ip":"::1""#), "{}", json);
+        let parsed: PortMapping = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.host_ip, Some("::1".parse().unwrap()));
+
+        // Records written before host addresses existed
+        let old: PortMapping = serde_json::from_str(r#"{"host_port":8080,"container_port":80,"protocol":"Tcp"}"#).unwrap();
+        assert_eq!(old.host_ip, None);
+    }
+
+    #[test]
+    fn test_assign_host_ports() {
+        let in_use = vec![PortMapping::new(49152, 80, PortProtocol::Tcp)];
+        let mut ports = vec![
+            PortMapping::new(0, 80, PortProtocol::Tcp),
+            PortMapping::new(49153, 443, PortProtocol::Tcp),
+            PortMapping::new(0, 8080, PortProtocol::Tcp),
+            PortMapping::new(0, 53, PortProtocol::Udp),
+            PortMapping::new(8443, 8443, PortProtocol::Tcp),
+        ];
+
+        assign_host_ports(&mut ports, &in_use).unwrap();
+
+        let hosts: Vec<u16> = ports.iter().map(|p| p.host_port).collect();
+        assert_eq!(hosts, vec![49154, 49153, 49155, 49152, 8443]);
+    }
+
+    #[test]
+    fn test_plan_anonymous_volumes() {
+        let image_volumes = ["/data".to_string(), "/var/log/".to_string(), "/cache".to_string(), "/data".to_string()];
+        let mounts = [Mount::new("/srv/logs".to_string(), "/var/log".to_string(), MountType::Nullfs, false)];
+
+        let volumes = plan_anonymous_volumes(&image_volumes, &mounts, &[], "tank/kawakaze/volumes", "a1b2c3d4-e5f6");
+        let planned: Vec<_> = volumes.iter().map(|m| (m.source.as_str(), m.destination.as_str())).collect();
+        // The user's mount wins, and each path gets one volume
+        assert_eq!(planned, [
+            ("tank/kawakaze/volumes/a1b2c3d4-0", "/data"),
+            ("tank/kawakaze/volumes/a1b2c3d4-1", "/cache"),
+        ]);
+        assert!(volumes.iter().all(|m| m.anonymous && m.mount_type == MountType::Zfs && !m.read_only));
+
+        assert!(plan_anonymous_volumes(&[], &mounts, &[], "tank/kawakaze/volumes", "a1b2c3d4").is_empty());
+
+        // A tmpfs on a VOLUME path takes the place of its dataset
+        let tmpfs = [TmpfsMount::new("/cache/".to_string())];
+        let volumes = plan_anonymous_volumes(&image_volumes, &mounts, &tmpfs, "tank/kawakaze/volumes", "a1b2c3d4");
+        assert_eq!(volumes.iter().map(|m| m.destination.as_str()).collect::<Vec<_>>(), ["/data"]);
+    }
+
+    #[test]
+    fn test_effective_env() {
+        let image = HashMap::from([
+            ("PATH".to_string(), "/usr/local/bin:/usr/bin:/bin".to_string()),
+            ("APP_ENV".to_string(), "production".to_string()),
+        ]);
+        // What the client sends: the env file's, with -e over it
+        let mut own = BTreeMap::from([("APP_ENV".to_string(), "staging".to_string()), ("DEBUG".to_string(), "0".to_string())]);
+        own.extend([("DEBUG".to_string(), "1".to_string())]);
+
+        let env = effective_env(&image, &own);
+        assert_eq!(env["APP_ENV"], "staging");
+        assert_eq!(env["DEBUG"], "1");
+        assert_eq!(env["PATH"], "/usr/local/bin:/usr/bin:/bin");
+        assert_eq!(env.len(), 3);
+
+        assert_eq!(effective_env(&image, &BTreeMap::new())["APP_ENV"], "production");
+        assert!(effective_env(&HashMap::new(), &BTreeMap::new()).is_empty());
+    }
+
+    #[test]
+    fn test_check_env() {
+        let env = |name: &str, value: &str| BTreeMap::from([(name.to_string(), value.to_string())]);
+        assert!(check_env(&env("APP_ENV", "a=b c")).is_ok());
+        assert!(check_env(&env("_x1", "")).is_ok());
+        for name in ["", "1X", "A-B", "A=B", "A B"] {
+            assert!(check_env(&env(name, "v")).is_err(), "{}", name);
+        }
+        assert!(check_env(&env("A", "a\0b")).is_err());
+    }
+
+    #[test]
+    fn test_check_tmpfs() {
+        let tmpfs = |destination: &str, size_bytes: Option<u64>, mode: Option<u32>| TmpfsMount {
+            destination: destination.to_string(),
+            size_bytes,
+            mode,
+        };
+        let mounts = [Mount::new("/srv/www".to_string(), "/var/www/".to_string(), MountType::Nullfs, true)];
+
+        assert!(check_tmpfs(&[], &mounts).is_ok());
+        assert!(check_tmpfs(&[tmpfs("/run", Some(64 << 20), Some(0o1777)), tmpfs("/var/www/cache", None, None)], &mounts).is_ok());
+
+        // Same destination as a volume, or another tmpfs, however it is spelled
+        let err = check_tmpfs(&[tmpfs("/var/www", None, None)], &mounts).unwrap_err();
+        assert!(err.contains("/var/www"), "{}", err);
+        assert!(check_tmpfs(&[tmpfs("/run", None, None), tmpfs("/run/", None, None)], &mounts).is_err());
+
+        for bad in ["run", "/", "//", "/run/../etc", ""] {
+            assert!(check_tmpfs(&[tmpfs(bad, None, None)], &[]).is_err(), "{}", bad);
+        }
+        assert!(check_tmpfs(&[tmpfs("/run", Some(0), None)], &[]).unwrap_err().contains("size"));
+        assert!(check_tmpfs(&[tmpfs("/run", None, Some(0o10000))], &[]).unwrap_err().contains("mode"));
+    }
+
+    #[test]
+    fn test_tmpfs_options() {
+        let mut mount = TmpfsMount::new("/run".to_string());
+        assert_eq!(mount.options(), "");
+        mount.size_bytes = Some(65536);
+        assert_eq!(mount.options(), "size=65536");
+        mount.mode = Some(0o1777);
+        assert_eq!(mount.options(), "size=65536,mode=1777");
+    }
+
+    #[test]
+    fn test_start_order() {
+        let deps = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        let (none, db, cache, app) = (deps(&[]), deps(&["db"]), deps(&["db", "cache"]), deps(&["app", "proxy-host"]));
+        // web <- app <- (db, cache); db depends on nothing
+        let members: Vec<(Option<&str>, &[String])> = vec![
+            (Some("web"), &app),
+            (Some("app"), &cache),
+            (Some("cache"), &db),
+            (Some("db"), &none),
+            (None, &none),
+        ];
+        let order = start_order(&members).unwrap();
+        let names: Vec<_> = order.iter().map(|&i| members[i].0.unwrap_or("-")).collect();
+        // "proxy-host" isn't in the batch, so it doesn't hold web back
+        assert_eq!(names, ["db", "cache", "app", "web", "-"]);
+
+        assert!(start_order(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_start_order_rejects_cycles() {
+        let on = |name: &str| vec![name.to_string()];
+        let (a, b, c, none) = (on("c"), on("a"), on("b"), Vec::new());
+        let members: Vec<(Option<&str>, &[String])> = vec![(Some("x"), &none), (Some("a"), &a), (Some("b"), &b), (Some("c"), &c)];
+        assert_eq!(start_order(&members).unwrap_err(), "depends_on forms a cycle: a -> c -> b -> a");
+
+        let own = on("a");
+        assert_eq!(start_order(&[(Some("a"), &own)]).unwrap_err(), "Container 'a' depends on itself");
+        let twice: Vec<(Option<&str>, &[String])> = vec![(Some("a"), &none), (Some("a"), &none)];
+        assert!(start_order(&twice).unwrap_err().contains("used twice"));
+    }
+
+    #[test]
+    fn test_host_port_bound() {
+        let listener = std::net::TcpListener::bind((std::net::Ipv4Addr::UNSPECIFIED, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert!(host_port_bound(port, PortProtocol::Tcp));
+
+        drop(listener);
+        assert!(!host_port_bound(port, PortProtocol::Tcp));
+    }
+
     #[test]
     fn test_container_state_display() {
         assert_eq!(ContainerState::Created.as_str(), "created");
@@ -421,6 +1352,7 @@ mod tests {
         assert_eq!(ContainerState::Stopped.as_str(), "stopped");
         assert_eq!(ContainerState::Paused.as_str(), "paused");
         assert_eq!(ContainerState::Removing.as_str(), "removing");
+        assert_eq!(ContainerState::Locked.as_str(), "locked");
     }
 
     #[test]
@@ -663,6 +1595,36 @@ mod tests {
         assert!(container.is_stopped());
     }
 
+    #[test]
+    fn test_record_stop() {
+        let mut container = Container::new("image1".to_string(), "kawakaze-web".to_string(), "tank/c/web".to_string());
+        container.set_state(ContainerState::Running);
+        assert!(container.last_stop_cause.is_none());
+
+        container.record_stop(StopCause::UserRequest(Some(1001)));
+        assert!(container.is_stopped());
+        assert!(container.stopped_at.is_some());
+        assert_eq!(container.last_stop_cause, Some(StopCause::UserRequest(Some(1001))));
+    }
+
+    #[test]
+    fn test_stop_cause_wire_format() {
+        let cases = [
+            (StopCause::UserRequest(Some(1001)), serde_json::json!({ "user_request": 1001 }), "stopped by uid 1001"),
+            (StopCause::UserRequest(None), serde_json::json!({ "user_request": null }), "stopped by request"),
+            (StopCause::ProcessExit, serde_json::json!("process_exit"), "process exited"),
+            (StopCause::RestartPolicyGaveUp, serde_json::json!("restart_policy_gave_up"), "restart policy gave up"),
+            (StopCause::DaemonShutdown, serde_json::json!("daemon_shutdown"), "daemon shutdown"),
+            (StopCause::HealthcheckKill, serde_json::json!("healthcheck_kill"), "killed by healthcheck"),
+            (StopCause::Oom, serde_json::json!("oom"), "out of memory"),
+        ];
+        for (cause, wire, text) in cases {
+            assert_eq!(serde_json::to_value(cause).unwrap(), wire);
+            assert_eq!(serde_json::from_value::<StopCause>(wire).unwrap(), cause);
+            assert_eq!(cause.to_string(), text);
+        }
+    }
+
     #[test]
     fn test_container_display_name() {
         let container = Container::new(
@@ -680,6 +1642,23 @@ mod tests {
         assert_eq!(container.display_name(), "my-container");
     }
 
+    #[test]
+    fn test_rename_updates_derived_hostname() {
+        let mut container = Container::new("image-123".to_string(), "jail-test".to_string(), "zroot/jails/test".to_string())
+            .with_name("web".to_string());
+        assert_eq!(container.hostname(), "web");
+
+        // The host name followed the name, so it moves with it
+        assert_eq!(container.rename("frontend".to_string()).as_deref(), Some("frontend"));
+        assert_eq!(container.hostname(), "frontend");
+
+        // An explicit host name stays put
+        let mut container = container.with_hostname(Some("www.example.org".to_string()));
+        assert_eq!(container.rename("edge".to_string()), None);
+        assert_eq!(container.display_name(), "edge");
+        assert_eq!(container.hostname(), "www.example.org");
+    }
+
     #[test]
     fn test_container_id_unique() {
         let id1 = Container::generate_id();
@@ -712,6 +1691,23 @@ mod tests {
         assert!(mount.read_only);
     }
 
+    #[test]
+    fn test_volume_modes() {
+        let mount = |mount_type| Mount::new("tank/data/pg".to_string(), "/var/db/postgres".to_string(), mount_type, false);
+        assert_eq!(mount(MountType::Nullfs).mode(), VolumeMode::Nullfs);
+        assert_eq!(mount(MountType::Nullfs).with_delegate(true).mode(), VolumeMode::Nullfs);
+        assert_eq!(mount(MountType::Zfs).mode(), VolumeMode::Attached);
+        assert_eq!(mount(MountType::Zfs).with_delegate(true).mode(), VolumeMode::Delegated);
+        let anonymous = Mount::anonymous("tank/kawakaze/volumes/a1-data".to_string(), "/data".to_string());
+        assert_eq!(anonymous.mode(), VolumeMode::Anonymous);
+        assert!(!anonymous.is_external_dataset());
+        assert!(mount(MountType::Zfs).with_delegate(true).is_external_dataset());
+
+        // Containers stored before delegation existed
+        let stored: Mount = serde_json::from_str(r#"{"source":"tank/data/pg","destination":"/data","mount_type":"Zfs","read_only":false}"#).unwrap();
+        assert_eq!(stored.mode(), VolumeMode::Attached);
+    }
+
     #[test]
     fn test_container_serialization() {
         let container = Container::new(
@@ -0,0 +1,103 @@
+//! Per-container operation locks
+//!
+//! Handlers hold the manager's lock only while they use it, and an async
+//! remove gives it up for the whole ZFS teardown, so requests on one
+//! container can interleave: a stop can land between a remove's start and
+//! its end, or two removes can both find the container. Start, stop and
+//! remove therefore take the container's lock from [`ContainerLocks`]
+//! before the manager's and keep it until they are done, the background
+//! part of an async remove included. Requests on different containers
+//! don't wait for each other.
+//!
+//! A waiting request must look the container up again once it holds the
+//! lock: the operation before it may have removed it.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::OwnedMutexGuard;
+
+type Lock = Arc<tokio::sync::Mutex<()>>;
+
+/// The operation locks of all containers, created on first use and dropped
+/// once nobody holds or waits for them
+#[derive(Debug, Clone, Default)]
+pub struct ContainerLocks {
+    locks: Arc<Mutex<HashMap<String, Lock>>>,
+}
+
+impl ContainerLocks {
+    /// Wait for and take the lock of container `id`
+    pub async fn lock(&self, id: &str) -> ContainerGuard {
+        let lock = self.locks.lock().unwrap().entry(id.to_string()).or_default().clone();
+        let guard = lock.lock_owned().await;
+        ContainerGuard { id: id.to_string(), locks: self.clone(), guard: Some(guard) }
+    }
+
+    /// Whether an operation on container `id` holds its lock or waits for it
+    pub fn is_busy(&self, id: &str) -> bool {
+        self.locks.lock().unwrap().contains_key(id)
+    }
+}
+
+/// The held lock of one container, released on drop
+#[derive(Debug)]
+pub struct ContainerGuard {
+    id: String,
+    locks: ContainerLocks,
+    guard: Option<OwnedMutexGuard<()>>,
+}
+
+impl ContainerGuard {
+    /// The container this lock is for
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+impl Drop for ContainerGuard {
+    fn drop(&mut self) {
+        drop(self.guard.take());
+        // Waiters hold a reference of their own, so only an idle lock goes
+        let mut locks = self.locks.locks.lock().unwrap();
+        if locks.get(&self.id).is_some_and(|lock| Arc::strong_count(lock) == 1) {
+            locks.remove(&self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_same_container_serializes() {
+        let locks = ContainerLocks::default();
+        let guard = locks.lock("web").await;
+        assert_eq!(guard.id(), "web");
+
+        let waiter = tokio::spawn({
+            let locks = locks.clone();
+            async move { locks.lock("web").await.id().to_string() }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiter.is_finished());
+
+        drop(guard);
+        assert_eq!(waiter.await.unwrap(), "web");
+        assert!(!locks.is_busy("web"));
+    }
+
+    #[tokio::test]
+    async fn test_other_containers_proceed() {
+        let locks = ContainerLocks::default();
+        let _web = locks.lock("web").await;
+        let db = tokio::time::timeout(Duration::from_secs(1), locks.lock("db")).await.unwrap();
+        assert!(locks.is_busy("web") && locks.is_busy("db"));
+
+        drop(db);
+        assert!(!locks.is_busy("db"));
+        assert!(locks.is_busy("web"));
+    }
+}
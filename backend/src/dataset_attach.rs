@@ -0,0 +1,616 @@
+//! Existing ZFS datasets passed through to containers
+//!
+//! A ZFS volume whose source is a dataset kawakaze didn't create
+//! (`tank/data/postgres`, with its own recordsize and snapshot schedule) is
+//! handed to the container as it is rather than copied:
+//!
+//! - attached (the default): before the jail starts, its `mountpoint` is
+//!   pointed at the destination under the container's root and it is
+//!   mounted there (read-only with `:ro`);
+//! - delegated (`delegate`, which needs mount permissions `zfs` or `all`
+//!   for `allow.mount.zfs`): it is unmounted on the host, set `jailed=on`
+//!   with the destination as its mountpoint, and handed to the running jail
+//!   with `zfs jail`, so tools in the jail can mount it, snapshot it and
+//!   create children. `zfs unjail` takes it back before the jail stops.
+//!
+//! When the container stops, each dataset gets back its own mountpoint (a
+//! local one is kept meanwhile in `org.kawakaze:mountpoint`), `jailed` is
+//! cleared, and it is mounted on the host again if it can be. The
+//! `org.kawakaze:attached` property names the container holding a dataset;
+//! release goes by it rather than by what the daemon remembers, so what a
+//! crash left attached is released by the next stop, start or removal, and
+//! a dataset held by another container is never touched.
+//!
+//! Sources are checked at create: the dataset must exist, and it can't be a
+//! pool, inside kawakaze's own datasets (`zfs_pool`) or above them.
+//! kawakaze never destroys one; only anonymous volumes, which it created,
+//! go with a removed container.
+
+use std::path::{Path, PathBuf};
+
+use tracing::warn;
+
+use crate::container::{Mount, VolumeMode};
+use crate::zfs::Zfs;
+
+/// User property naming the container a dataset is attached to
+pub const ATTACHED_PROPERTY: &str = "org.kawakaze:attached";
+
+/// User property keeping a dataset's local `mountpoint` while it is attached
+pub const MOUNTPOINT_PROPERTY: &str = "org.kawakaze:mountpoint";
+
+/// An external dataset of a container
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attachment {
+    pub dataset: String,
+    /// `mountpoint` while attached: under the container's root on the host,
+    /// or the destination itself for a delegated dataset, whose mountpoint
+    /// the jail reads relative to its root
+    pub mountpoint: PathBuf,
+    pub read_only: bool,
+    pub delegate: bool,
+}
+
+/// The external datasets among a container's `mounts`, `root` being its
+/// root on the host
+pub fn attachments(root: &Path, mounts: &[Mount]) -> Vec<Attachment> {
+    mounts
+        .iter()
+        .filter(|mount| mount.is_external_dataset())
+        .map(|mount| {
+            let delegate = mount.mode() == VolumeMode::Delegated;
+            let destination = format!("/{}", mount.destination.trim_matches('/'));
+            Attachment {
+                dataset: mount.source.clone(),
+                mountpoint: if delegate { PathBuf::from(destination) } else { root.join(&destination[1..]) },
+                read_only: mount.read_only,
+                delegate,
+            }
+        })
+        .collect()
+}
+
+/// Check an external dataset's name; `own` is kawakaze's dataset
+/// (`zfs_pool`), which the source can be neither inside nor above
+pub fn check_source(dataset: &str, own: &str) -> Result<(), String> {
+    let valid_part = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric() || "-_.:".contains(c));
+    if !dataset.split('/').all(valid_part) {
+        return Err(format!("Invalid dataset name '{}'", dataset));
+    }
+    if !dataset.contains('/') {
+        return Err(format!("'{}' is a pool; attach a dataset inside it", dataset));
+    }
+    let inside = |outer: &str, inner: &str| inner == outer || inner.starts_with(&format!("{}/", outer));
+    if inside(own, dataset) {
+        return Err(format!("'{}' is one of kawakaze's own datasets ({})", dataset, own));
+    }
+    if inside(dataset, own) {
+        return Err(format!("'{}' holds kawakaze's own datasets ({})", dataset, own));
+    }
+    Ok(())
+}
+
+/// ZFS operations on external datasets, so start and stop can be tested
+pub trait AttachOps {
+    /// A property's value and source (see [`Zfs::property_with_source`])
+    fn get(&mut self, dataset: &str, property: &str) -> Result<(String, String), String>;
+    fn set(&mut self, dataset: &str, property: &str, value: &str) -> Result<(), String>;
+    fn inherit(&mut self, dataset: &str, property: &str) -> Result<(), String>;
+    fn mounted(&mut self, dataset: &str) -> bool;
+    fn mount(&mut self, dataset: &str, read_only: bool) -> Result<(), String>;
+    fn unmount(&mut self, dataset: &str) -> Result<(), String>;
+    fn create_mount_point(&mut self, root: &Path, target: &Path) -> Result<(), String>;
+    fn jail(&mut self, jid: i32, dataset: &str) -> Result<(), String>;
+    fn unjail(&mut self, jid: i32, dataset: &str) -> Result<(), String>;
+}
+
+/// [`AttachOps`] on the host's ZFS
+pub struct HostDatasets {
+    pub zfs: Option<Zfs>,
+}
+
+impl HostDatasets {
+    fn zfs(&self) -> Result<&Zfs, String> {
+        self.zfs.as_ref().ok_or_else(|| "ZFS is not available".to_string())
+    }
+}
+
+impl AttachOps for HostDatasets {
+    fn get(&mut self, dataset: &str, property: &str) -> Result<(String, String), String> {
+        self.zfs()?.property_with_source(dataset, property).map_err(|e| e.to_string())
+    }
+
+    fn set(&mut self, dataset: &str, property: &str, value: &str) -> Result<(), String> {
+        self.zfs()?.set_property(dataset, property, value).map_err(|e| e.to_string())
+    }
+
+    fn inherit(&mut self, dataset: &str, property: &str) -> Result<(), String> {
+        self.zfs()?.inherit_property(dataset, property).map_err(|e| e.to_string())
+    }
+
+    fn mounted(&mut self, dataset: &str) -> bool {
+        self.zfs().is_ok_and(|zfs| zfs.is_dataset_mounted(dataset))
+    }
+
+    fn mount(&mut self, dataset: &str, read_only: bool) -> Result<(), String> {
+        let zfs = self.zfs()?;
+        if read_only { zfs.mount_read_only(dataset) } else { zfs.mount_at_mountpoint(dataset) }.map_err(|e| e.to_string())
+    }
+
+    fn unmount(&mut self, dataset: &str) -> Result<(), String> {
+        self.zfs()?.force_unmount(dataset).map_err(|e| e.to_string())
+    }
+
+    fn create_mount_point(&mut self, root: &Path, target: &Path) -> Result<(), String> {
+        crate::read_only::create_mount_point(root, target).map_err(|e| e.to_string())
+    }
+
+    fn jail(&mut self, jid: i32, dataset: &str) -> Result<(), String> {
+        self.zfs()?.jail_dataset(jid, dataset).map_err(|e| e.to_string())
+    }
+
+    fn unjail(&mut self, jid: i32, dataset: &str) -> Result<(), String> {
+        self.zfs()?.unjail_dataset(jid, dataset).map_err(|e| e.to_string())
+    }
+}
+
+/// Claim every dataset for `container` before its jail starts, mounting
+/// the attached ones under `root` and preparing the delegated ones; a
+/// failure releases what was done
+pub fn attach(ops: &mut impl AttachOps, container: &str, root: &Path, attachments: &[Attachment]) -> Result<(), String> {
+    for (i, attachment) in attachments.iter().enumerate() {
+        if let Err(e) = attach_one(ops, container, root, attachment) {
+            release(ops, container, &attachments[..=i]);
+            return Err(format!("Failed to attach dataset {}: {}", attachment.dataset, e));
+        }
+    }
+    Ok(())
+}
+
+fn attach_one(ops: &mut impl AttachOps, container: &str, root: &Path, attachment: &Attachment) -> Result<(), String> {
+    let dataset = attachment.dataset.as_str();
+    match owner(ops, dataset)? {
+        None => {}
+        // Left from a run that didn't stop cleanly
+        Some(owner) if owner == container => release_one(ops, container, attachment)?,
+        Some(owner) => return Err(format!("it is attached to container {}", owner)),
+    }
+    if ops.get(dataset, "jailed")?.0 == "on" {
+        return Err("it is already jailed (jailed=on)".to_string());
+    }
+
+    if !attachment.delegate {
+        ops.create_mount_point(root, &attachment.mountpoint)?;
+    }
+    let (mountpoint, source) = ops.get(dataset, "mountpoint")?;
+    ops.set(dataset, ATTACHED_PROPERTY, container)?;
+    if source == "local" {
+        ops.set(dataset, MOUNTPOINT_PROPERTY, &mountpoint)?;
+    }
+
+    let target = attachment.mountpoint.display().to_string();
+    if attachment.delegate {
+        // Jailed before it gets a mountpoint the host must not mount
+        if ops.mounted(dataset) {
+            ops.unmount(dataset)?;
+        }
+        ops.set(dataset, "jailed", "on")?;
+        ops.set(dataset, "mountpoint", &target)?;
+    } else {
+        // A mounted dataset moves to its new mountpoint, read-write
+        ops.set(dataset, "mountpoint", &target)?;
+        if attachment.read_only && ops.mounted(dataset) {
+            ops.unmount(dataset)?;
+        }
+        if !ops.mounted(dataset) {
+            ops.mount(dataset, attachment.read_only)?;
+        }
+    }
+    Ok(())
+}
+
+/// Hand the delegated datasets to the started jail `jid`; a failure takes
+/// back those already handed over
+pub fn delegate(ops: &mut impl AttachOps, jid: i32, attachments: &[Attachment]) -> Result<(), String> {
+    let delegated: Vec<&Attachment> = attachments.iter().filter(|a| a.delegate).collect();
+    for (i, attachment) in delegated.iter().enumerate() {
+        if let Err(e) = ops.jail(jid, &attachment.dataset) {
+            for done in delegated[..i].iter().rev() {
+                if let Err(e) = ops.unjail(jid, &done.dataset) {
+                    warn!("Failed to take dataset {} back from jail {}: {}", done.dataset, jid, e);
+                }
+            }
+            return Err(format!("Failed to delegate dataset {} to jail {}: {}", attachment.dataset, jid, e));
+        }
+    }
+    Ok(())
+}
+
+/// Take the delegated datasets back from jail `jid` while it still exists
+pub fn take_back(ops: &mut impl AttachOps, jid: i32, attachments: &[Attachment]) {
+    for attachment in attachments.iter().rev().filter(|a| a.delegate) {
+        if let Err(e) = ops.unjail(jid, &attachment.dataset) {
+            warn!("Failed to take dataset {} back from jail {}: {}", attachment.dataset, jid, e);
+        }
+    }
+}
+
+/// Give back what `container` holds of `attachments`, in reverse order,
+/// once its jail is gone; failures are logged
+pub fn release(ops: &mut impl AttachOps, container: &str, attachments: &[Attachment]) {
+    for attachment in attachments.iter().rev() {
+        if let Err(e) = release_one(ops, container, attachment) {
+            warn!("Failed to release dataset {} from container {}: {}", attachment.dataset, container, e);
+        }
+    }
+}
+
+/// Unmount a dataset `container` holds, restore its mountpoint and
+/// `jailed`, and mount it on the host again if it mounts itself
+fn release_one(ops: &mut impl AttachOps, container: &str, attachment: &Attachment) -> Result<(), String> {
+    let dataset = attachment.dataset.as_str();
+    if owner(ops, dataset)?.as_deref() != Some(container) {
+        return Ok(());
+    }
+
+    if ops.mounted(dataset) {
+        ops.unmount(dataset)?;
+    }
+    if attachment.delegate {
+        ops.inherit(dataset, "jailed")?;
+    }
+    match ops.get(dataset, MOUNTPOINT_PROPERTY)?.0.as_str() {
+        "-" | "" => ops.inherit(dataset, "mountpoint")?,
+        saved => {
+            let saved = saved.to_string();
+            ops.set(dataset, "mountpoint", &saved)?;
+            ops.inherit(dataset, MOUNTPOINT_PROPERTY)?;
+        }
+    }
+    ops.inherit(dataset, ATTACHED_PROPERTY)?;
+
+    let mountpoint = ops.get(dataset, "mountpoint")?.0;
+    if ops.get(dataset, "canmount")?.0 == "on"
+        && mountpoint.starts_with('/')
+        && let Err(e) = ops.mount(dataset, false)
+    {
+        warn!("Released dataset {} but couldn't mount it at {}: {}", dataset, mountpoint, e);
+    }
+    Ok(())
+}
+
+/// Container holding `dataset`, if any
+fn owner(ops: &mut impl AttachOps, dataset: &str) -> Result<Option<String>, String> {
+    let (value, _) = ops.get(dataset, ATTACHED_PROPERTY)?;
+    Ok((value != "-" && !value.is_empty()).then_some(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::MountType;
+    use std::collections::{HashMap, HashSet};
+
+    /// Datasets as far as their properties go: `set` makes a property local,
+    /// `inherit` drops it back to a default; every call is recorded
+    #[derive(Default)]
+    struct MockZfs {
+        properties: HashMap<(String, String), String>,
+        mounted: HashSet<String>,
+        /// Jail each delegated dataset is in
+        jailed_in: HashMap<String, i32>,
+        ops: Vec<String>,
+        fail: Vec<String>,
+    }
+
+    impl MockZfs {
+        /// `tank/data/pg` mounted at its local mountpoint, and
+        /// `tank/data/cache` inheriting one
+        fn new() -> Self {
+            let mut zfs = Self::default();
+            zfs.properties.insert(("tank/data/pg".into(), "mountpoint".into()), "/srv/pg".into());
+            zfs.mounted.insert("tank/data/pg".to_string());
+            zfs.mounted.insert("tank/data/cache".to_string());
+            zfs
+        }
+
+        fn record(&mut self, op: String) -> Result<(), String> {
+            let failed = self.fail.contains(&op);
+            self.ops.push(op);
+            if failed { Err("mock failure".to_string()) } else { Ok(()) }
+        }
+
+        fn value(&self, dataset: &str, property: &str) -> Option<&String> {
+            self.properties.get(&(dataset.to_string(), property.to_string()))
+        }
+
+        /// Calls that change something, in order
+        fn changes(&self) -> Vec<&str> {
+            self.ops.iter().map(String::as_str).filter(|op| !op.starts_with("get ")).collect()
+        }
+    }
+
+    impl AttachOps for MockZfs {
+        fn get(&mut self, dataset: &str, property: &str) -> Result<(String, String), String> {
+            self.ops.push(format!("get {} {}", property, dataset));
+            Ok(match self.value(dataset, property) {
+                Some(value) => (value.clone(), "local".to_string()),
+                None => match property {
+                    "mountpoint" => (format!("/{}", dataset), "inherited from tank".to_string()),
+                    "jailed" => ("off".to_string(), "default".to_string()),
+                    "canmount" => ("on".to_string(), "default".to_string()),
+                    _ => ("-".to_string(), "-".to_string()),
+                },
+            })
+        }
+
+        fn set(&mut self, dataset: &str, property: &str, value: &str) -> Result<(), String> {
+            self.record(format!("set {}={} {}", property, value, dataset))?;
+            self.properties.insert((dataset.to_string(), property.to_string()), value.to_string());
+            Ok(())
+        }
+
+        fn inherit(&mut self, dataset: &str, property: &str) -> Result<(), String> {
+            self.record(format!("inherit {} {}", property, dataset))?;
+            self.properties.remove(&(dataset.to_string(), property.to_string()));
+            Ok(())
+        }
+
+        fn mounted(&mut self, dataset: &str) -> bool {
+            self.mounted.contains(dataset)
+        }
+
+        fn mount(&mut self, dataset: &str, read_only: bool) -> Result<(), String> {
+            self.record(format!("mount{} {}", if read_only { " -o ro" } else { "" }, dataset))?;
+            self.mounted.insert(dataset.to_string());
+            Ok(())
+        }
+
+        fn unmount(&mut self, dataset: &str) -> Result<(), String> {
+            self.record(format!("unmount {}", dataset))?;
+            self.mounted.remove(dataset);
+            Ok(())
+        }
+
+        fn create_mount_point(&mut self, _root: &Path, target: &Path) -> Result<(), String> {
+            self.record(format!("mkdir {}", target.display()))
+        }
+
+        fn jail(&mut self, jid: i32, dataset: &str) -> Result<(), String> {
+            self.record(format!("jail {} {}", jid, dataset))?;
+            self.jailed_in.insert(dataset.to_string(), jid);
+            Ok(())
+        }
+
+        fn unjail(&mut self, jid: i32, dataset: &str) -> Result<(), String> {
+            if self.jailed_in.get(dataset) != Some(&jid) {
+                self.ops.push(format!("unjail {} {}", jid, dataset));
+                return Err("no such jail".to_string());
+            }
+            self.record(format!("unjail {} {}", jid, dataset))?;
+            self.jailed_in.remove(dataset);
+            Ok(())
+        }
+    }
+
+    fn root() -> PathBuf {
+        PathBuf::from("/var/kawakaze/containers/c1")
+    }
+
+    fn external(source: &str, destination: &str, read_only: bool, delegate: bool) -> Mount {
+        Mount::new(source.to_string(), destination.to_string(), MountType::Zfs, read_only).with_delegate(delegate)
+    }
+
+    fn pg_attached() -> Vec<Attachment> {
+        attachments(&root(), &[external("tank/data/pg", "/var/db/postgres/", false, false)])
+    }
+
+    fn cache_delegated() -> Vec<Attachment> {
+        attachments(&root(), &[external("tank/data/cache", "/cache", false, true)])
+    }
+
+    #[test]
+    fn test_check_source() {
+        assert!(check_source("tank/data/postgres", "zroot/kawakaze").is_ok());
+        assert!(check_source("zroot/data", "zroot/kawakaze").is_ok());
+        // A sibling sharing a prefix isn't inside
+        assert!(check_source("zroot/kawakaze-data/pg", "zroot/kawakaze").is_ok());
+
+        for (dataset, why) in [
+            ("", "Invalid"),
+            ("tank//pg", "Invalid"),
+            ("tank/pg@snap", "Invalid"),
+            ("/tank/pg", "Invalid"),
+            ("tank/pg/", "Invalid"),
+            ("tank", "is a pool"),
+            ("zroot/kawakaze", "own datasets"),
+            ("zroot/kawakaze/volumes/a1-data", "own datasets"),
+            ("zroot/kawakaze/containers/c1", "own datasets"),
+        ] {
+            let err = check_source(dataset, "zroot/kawakaze").unwrap_err();
+            assert!(err.contains(why), "{}: {}", dataset, err);
+        }
+        assert!(check_source("tank/apps", "tank/apps/kawakaze").unwrap_err().contains("holds"));
+    }
+
+    #[test]
+    fn test_attachments_pick_external_datasets() {
+        let mounts = [
+            Mount::new("/srv/www".to_string(), "/var/www".to_string(), MountType::Nullfs, true),
+            Mount::anonymous("tank/kawakaze/volumes/c1-data".to_string(), "/data".to_string()),
+            external("tank/data/pg", "/var/db/postgres/", true, false),
+            external("tank/data/cache", "cache", false, true),
+        ];
+        assert_eq!(attachments(&root(), &mounts), [
+            Attachment {
+                dataset: "tank/data/pg".to_string(),
+                mountpoint: root().join("var/db/postgres"),
+                read_only: true,
+                delegate: false,
+            },
+            Attachment {
+                dataset: "tank/data/cache".to_string(),
+                mountpoint: PathBuf::from("/cache"),
+                read_only: false,
+                delegate: true,
+            },
+        ]);
+    }
+
+    #[test]
+    fn test_attach_mounts_under_root_and_stop_restores() {
+        let mut zfs = MockZfs::new();
+        attach(&mut zfs, "c1", &root(), &pg_attached()).unwrap();
+        assert_eq!(zfs.changes(), [
+            "mkdir /var/kawakaze/containers/c1/var/db/postgres",
+            "set org.kawakaze:attached=c1 tank/data/pg",
+            "set org.kawakaze:mountpoint=/srv/pg tank/data/pg",
+            "set mountpoint=/var/kawakaze/containers/c1/var/db/postgres tank/data/pg",
+        ]);
+        assert!(zfs.mounted.contains("tank/data/pg"));
+
+        zfs.ops.clear();
+        release(&mut zfs, "c1", &pg_attached());
+        assert_eq!(zfs.changes(), [
+            "unmount tank/data/pg",
+            "set mountpoint=/srv/pg tank/data/pg",
+            "inherit org.kawakaze:mountpoint tank/data/pg",
+            "inherit org.kawakaze:attached tank/data/pg",
+            "mount tank/data/pg",
+        ]);
+        assert_eq!(zfs.value("tank/data/pg", "mountpoint").unwrap(), "/srv/pg");
+        assert!(zfs.value("tank/data/pg", ATTACHED_PROPERTY).is_none());
+    }
+
+    #[test]
+    fn test_attach_read_only_remounts() {
+        let mut zfs = MockZfs::new();
+        let read_only = attachments(&root(), &[external("tank/data/pg", "/var/db/postgres", true, false)]);
+        attach(&mut zfs, "c1", &root(), &read_only).unwrap();
+        assert_eq!(&zfs.changes()[3..], [
+            "set mountpoint=/var/kawakaze/containers/c1/var/db/postgres tank/data/pg",
+            "unmount tank/data/pg",
+            "mount -o ro tank/data/pg",
+        ]);
+    }
+
+    #[test]
+    fn test_delegate_lifecycle() {
+        let mut zfs = MockZfs::new();
+        let delegated = cache_delegated();
+        attach(&mut zfs, "c1", &root(), &delegated).unwrap();
+        // Inherited mountpoint: nothing to keep; no mount point on the host
+        assert_eq!(zfs.changes(), [
+            "set org.kawakaze:attached=c1 tank/data/cache",
+            "unmount tank/data/cache",
+            "set jailed=on tank/data/cache",
+            "set mountpoint=/cache tank/data/cache",
+        ]);
+
+        delegate(&mut zfs, 42, &delegated).unwrap();
+        assert_eq!(zfs.jailed_in["tank/data/cache"], 42);
+
+        // Stop: back from the jail while it exists, then released
+        zfs.ops.clear();
+        take_back(&mut zfs, 42, &delegated);
+        release(&mut zfs, "c1", &delegated);
+        assert_eq!(zfs.changes(), [
+            "unjail 42 tank/data/cache",
+            "inherit jailed tank/data/cache",
+            "inherit mountpoint tank/data/cache",
+            "inherit org.kawakaze:attached tank/data/cache",
+            "mount tank/data/cache",
+        ]);
+        assert!(zfs.jailed_in.is_empty());
+        assert!(zfs.value("tank/data/cache", "jailed").is_none());
+    }
+
+    #[test]
+    fn test_failed_delegation_takes_back_the_others() {
+        let mut zfs = MockZfs::new();
+        let delegated = attachments(&root(), &[
+            external("tank/data/cache", "/cache", false, true),
+            external("tank/data/pg", "/var/db/postgres", false, true),
+        ]);
+        attach(&mut zfs, "c1", &root(), &delegated).unwrap();
+        zfs.fail.push("jail 42 tank/data/pg".to_string());
+
+        let err = delegate(&mut zfs, 42, &delegated).unwrap_err();
+        assert!(err.contains("tank/data/pg"), "{}", err);
+        assert!(zfs.jailed_in.is_empty());
+        assert!(zfs.ops.contains(&"unjail 42 tank/data/cache".to_string()));
+    }
+
+    #[test]
+    fn test_failed_attach_releases_earlier_ones() {
+        let mut zfs = MockZfs::new();
+        let both = attachments(&root(), &[
+            external("tank/data/pg", "/var/db/postgres", false, false),
+            external("tank/data/cache", "/cache", false, true),
+        ]);
+        zfs.fail.push("set jailed=on tank/data/cache".to_string());
+
+        let err = attach(&mut zfs, "c1", &root(), &both).unwrap_err();
+        assert!(err.contains("tank/data/cache"), "{}", err);
+        // Both are back as they were
+        assert_eq!(zfs.value("tank/data/pg", "mountpoint").unwrap(), "/srv/pg");
+        assert!(zfs.value("tank/data/pg", ATTACHED_PROPERTY).is_none());
+        assert!(zfs.value("tank/data/cache", ATTACHED_PROPERTY).is_none());
+        assert!(zfs.value("tank/data/cache", "mountpoint").is_none());
+        assert!(zfs.mounted.contains("tank/data/pg") && zfs.mounted.contains("tank/data/cache"));
+    }
+
+    #[test]
+    fn test_release_after_crash() {
+        let mut zfs = MockZfs::new();
+        let both = attachments(&root(), &[
+            external("tank/data/pg", "/var/db/postgres", false, false),
+            external("tank/data/cache", "/cache", false, true),
+        ]);
+        attach(&mut zfs, "c1", &root(), &both).unwrap();
+        delegate(&mut zfs, 42, &both).unwrap();
+
+        // The jail died with the daemon: nothing to take back from it
+        zfs.jailed_in.clear();
+        take_back(&mut zfs, 42, &both);
+        release(&mut zfs, "c1", &both);
+        for dataset in ["tank/data/pg", "tank/data/cache"] {
+            assert!(zfs.value(dataset, ATTACHED_PROPERTY).is_none(), "{}", dataset);
+            assert!(zfs.value(dataset, "jailed").is_none(), "{}", dataset);
+            assert!(zfs.mounted.contains(dataset), "{}", dataset);
+        }
+        assert_eq!(zfs.value("tank/data/pg", "mountpoint").unwrap(), "/srv/pg");
+    }
+
+    #[test]
+    fn test_start_after_crash_releases_leftovers_first() {
+        let mut zfs = MockZfs::new();
+        attach(&mut zfs, "c1", &root(), &pg_attached()).unwrap();
+
+        // Never stopped; the next start takes it over again
+        zfs.ops.clear();
+        attach(&mut zfs, "c1", &root(), &pg_attached()).unwrap();
+        let changes = zfs.changes();
+        assert_eq!(changes[0], "unmount tank/data/pg");
+        assert!(changes.contains(&"set org.kawakaze:mountpoint=/srv/pg tank/data/pg"));
+        assert_eq!(zfs.value("tank/data/pg", MOUNTPOINT_PROPERTY).unwrap(), "/srv/pg");
+    }
+
+    #[test]
+    fn test_never_touches_another_containers_dataset() {
+        let mut zfs = MockZfs::new();
+        attach(&mut zfs, "c1", &root(), &pg_attached()).unwrap();
+        zfs.ops.clear();
+
+        let err = attach(&mut zfs, "c2", Path::new("/var/kawakaze/containers/c2"), &pg_attached()).unwrap_err();
+        assert!(err.contains("attached to container c1"), "{}", err);
+        release(&mut zfs, "c2", &pg_attached());
+        assert!(zfs.changes().is_empty(), "{:?}", zfs.changes());
+        assert_eq!(zfs.value("tank/data/pg", ATTACHED_PROPERTY).unwrap(), "c1");
+
+        // Jailed outside kawakaze
+        zfs.properties.insert(("tank/data/cache".into(), "jailed".into()), "on".into());
+        assert!(attach(&mut zfs, "c2", &root(), &cache_delegated()).unwrap_err().contains("jailed"));
+        assert!(zfs.changes().is_empty());
+    }
+}
@@ -0,0 +1,138 @@
+//! Containers whose dataset was destroyed behind the daemon's back
+//!
+//! A `zfs destroy -r` run by hand leaves the container record in place.
+//! Such a container is degraded rather than broken: listings and inspect
+//! still show it, with `dataset_missing`. Operations that need the dataset
+//! answer 409 `DATASET_MISSING`, and remove only skips the ZFS destroy.
+//!
+//! [`DatasetView`] checks whether a dataset exists when first asked, and
+//! keeps the answer for a short TTL so a listing doesn't run `zfs list`
+//! per container on every call. A check that fails (as opposed to finding
+//! nothing) is neither cached nor taken to mean the dataset is gone. The
+//! source is injectable, which is how the tests delete datasets.
+
+use crate::zfs::Zfs;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long an answer is kept before the dataset is checked again
+pub const DEFAULT_TTL: Duration = Duration::from_secs(2);
+
+/// Where a [`DatasetView`] finds out whether a dataset exists
+pub trait DatasetSource: Send + Sync {
+    /// `Ok(false)` only when the dataset is known not to exist
+    fn exists(&self, dataset: &str) -> Result<bool, String>;
+}
+
+impl<F> DatasetSource for F
+where
+    F: Fn(&str) -> Result<bool, String> + Send + Sync,
+{
+    fn exists(&self, dataset: &str) -> Result<bool, String> {
+        self(dataset)
+    }
+}
+
+impl DatasetSource for Zfs {
+    fn exists(&self, dataset: &str) -> Result<bool, String> {
+        self.dataset_presence(dataset).map_err(|e| e.to_string())
+    }
+}
+
+/// Dataset existence, cached per dataset for a short TTL
+pub struct DatasetView {
+    /// `None` without ZFS: there is nothing to check, so nothing is missing
+    source: Option<Box<dyn DatasetSource>>,
+    ttl: Duration,
+    cached: Mutex<HashMap<String, (Instant, bool)>>,
+}
+
+impl DatasetView {
+    pub fn new(source: impl DatasetSource + 'static, ttl: Duration) -> Self {
+        Self { source: Some(Box::new(source)), ttl, cached: Mutex::new(HashMap::new()) }
+    }
+
+    /// Whether `dataset` is known to be gone
+    pub fn is_missing(&self, dataset: &str) -> bool {
+        let Some(source) = &self.source else { return false };
+
+        let mut cached = self.cached.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((checked, exists)) = cached.get(dataset)
+            && checked.elapsed() < self.ttl
+        {
+            return !exists;
+        }
+
+        match source.exists(dataset) {
+            Ok(exists) => {
+                cached.insert(dataset.to_string(), (Instant::now(), exists));
+                !exists
+            }
+            Err(e) => {
+                tracing::warn!("Could not check dataset {}: {}", dataset, e);
+                cached.remove(dataset);
+                false
+            }
+        }
+    }
+
+    /// Drop what is known about `dataset`, after creating or destroying it
+    pub fn forget(&self, dataset: &str) {
+        self.cached.lock().unwrap_or_else(|e| e.into_inner()).remove(dataset);
+    }
+}
+
+impl Default for DatasetView {
+    fn default() -> Self {
+        Self { source: None, ttl: DEFAULT_TTL, cached: Mutex::new(HashMap::new()) }
+    }
+}
+
+impl std::fmt::Debug for DatasetView {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DatasetView").field("ttl", &self.ttl).finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_view_caches_answers() {
+        let datasets = Arc::new(Mutex::new(HashSet::from(["tank/containers/web".to_string()])));
+        let checks = Arc::new(AtomicUsize::new(0));
+        let (present, counter) = (Arc::clone(&datasets), Arc::clone(&checks));
+        let view = DatasetView::new(
+            move |dataset: &str| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Ok(present.lock().unwrap().contains(dataset))
+            },
+            Duration::from_secs(3600),
+        );
+
+        assert!(!view.is_missing("tank/containers/web"));
+        assert!(view.is_missing("tank/containers/db"));
+        // Within the TTL the answer stands, even once the dataset is gone
+        datasets.lock().unwrap().clear();
+        assert!(!view.is_missing("tank/containers/web"));
+        assert_eq!(checks.load(Ordering::SeqCst), 2);
+
+        view.forget("tank/containers/web");
+        assert!(view.is_missing("tank/containers/web"));
+        assert_eq!(checks.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_failed_checks_are_not_missing() {
+        let view = DatasetView::new(|_: &str| Err("zfs: command not found".to_string()), Duration::from_secs(3600));
+        assert!(!view.is_missing("tank/containers/web"));
+
+        // Without ZFS nothing is checked
+        assert!(!DatasetView::default().is_missing("tank/containers/web"));
+    }
+}
@@ -0,0 +1,287 @@
+//! devfs ruleset management
+//!
+//! Jails get `/dev` mounted with a devfs ruleset so they only see the device
+//! nodes they need. At startup the manager makes sure the configured rulesets
+//! exist with the expected rules, (re)creating them with `devfs rule` when
+//! they are missing or have been changed.
+
+use crate::cmdtrace::TracedCommand;
+use crate::config::DevfsConfig;
+
+/// The stock `devfsrules_jail` ruleset from `/etc/defaults/devfs.rules`,
+/// used for jails that weren't given a ruleset by the manager
+pub const SYSTEM_JAIL_RULESET: u16 = 4;
+
+/// Rules equivalent to `devfsrules_jail`: hide everything, then unhide the
+/// basic and login devices a jail needs
+pub const JAIL_RULES: &[&str] = &[
+    "hide",
+    "path log unhide",
+    "path null unhide",
+    "path zero unhide",
+    "path crypto unhide",
+    "path random unhide",
+    "path urandom unhide",
+    "path pts unhide",
+    "path pts/* unhide",
+    "path fd unhide",
+    "path fd/* unhide",
+    "path stdin unhide",
+    "path stdout unhide",
+    "path stderr unhide",
+    "path fuse unhide",
+    "path zfs unhide",
+];
+
+/// devfs ruleset errors
+#[derive(Debug, thiserror::Error)]
+pub enum DevfsError {
+    #[error("devfs command failed: {0}")]
+    Command(String),
+    #[error("Invalid devfs path pattern: {0}")]
+    InvalidPattern(String),
+}
+
+/// What needs to happen to bring a ruleset in line with the config
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RulesetAction {
+    /// The ruleset doesn't exist yet
+    Create,
+    /// The ruleset exists with exactly the wanted rules
+    Keep,
+    /// The ruleset exists with different rules
+    Replace,
+}
+
+/// Check a device path pattern before it is passed to `devfs rule add path`
+pub fn validate_pattern(pattern: &str) -> Result<(), DevfsError> {
+    let valid = !pattern.is_empty()
+        && !pattern.starts_with('/')
+        && !pattern.split('/').any(|part| part == "..")
+        && pattern
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '/' | '*' | '?' | '.' | '_' | '-' | '[' | ']'));
+
+    if valid {
+        Ok(())
+    } else {
+        Err(DevfsError::InvalidPattern(pattern.to_string()))
+    }
+}
+
+/// Rules for a ruleset: the standard jail rules plus extra unhidden paths
+pub fn ruleset_rules(unhide: &[String]) -> Vec<String> {
+    JAIL_RULES
+        .iter()
+        .map(|rule| rule.to_string())
+        .chain(unhide.iter().map(|path| format!("path {} unhide", path)))
+        .collect()
+}
+
+/// Every ruleset the config asks for, with its rules
+pub fn desired_rulesets(config: &DevfsConfig) -> Vec<(u16, Vec<String>)> {
+    std::iter::once((config.ruleset, ruleset_rules(&[])))
+        .chain(config.rulesets.iter().map(|r| (r.number, ruleset_rules(&r.unhide))))
+        .collect()
+}
+
+/// Parse `devfs rule showsets` output (one ruleset number per line)
+pub fn parse_showsets(output: &str) -> Vec<u16> {
+    output.lines().filter_map(|line| line.trim().parse().ok()).collect()
+}
+
+/// Parse `devfs rule -s N show` output, dropping the rule numbers
+pub fn parse_show(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (number, rule) = line.trim().split_once(char::is_whitespace)?;
+            number.parse::<u32>().ok()?;
+            Some(rule.split_whitespace().collect::<Vec<_>>().join(" "))
+        })
+        .collect()
+}
+
+/// Decide what to do with a ruleset given its current state
+pub fn plan_ruleset(existing_sets: &[u16], number: u16, current: &[String], desired: &[String]) -> RulesetAction {
+    if !existing_sets.contains(&number) {
+        RulesetAction::Create
+    } else if current == desired {
+        RulesetAction::Keep
+    } else {
+        RulesetAction::Replace
+    }
+}
+
+/// Make sure every configured ruleset exists with the expected rules
+///
+/// `devfs` runs `devfs` with the given arguments and returns its stdout.
+/// Rulesets that already match are left alone, so this is safe to call on
+/// every start.
+pub fn ensure_rulesets_with<F>(config: &DevfsConfig, mut devfs: F) -> Result<Vec<(u16, RulesetAction)>, DevfsError>
+where
+    F: FnMut(&[String]) -> Result<String, DevfsError>,
+{
+    let args = |parts: &[&str]| parts.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+
+    let existing = parse_showsets(&devfs(&args(&["rule", "showsets"]))?);
+    let mut actions = Vec::new();
+
+    for (number, desired) in desired_rulesets(config) {
+        let set = number.to_string();
+        let current = if existing.contains(&number) {
+            parse_show(&devfs(&args(&["rule", "-s", &set, "show"]))?)
+        } else {
+            Vec::new()
+        };
+
+        let action = plan_ruleset(&existing, number, &current, &desired);
+        if action == RulesetAction::Replace {
+            devfs(&args(&["rule", "-s", &set, "delset"]))?;
+        }
+        if action != RulesetAction::Keep {
+            for rule in &desired {
+                let mut add = args(&["rule", "-s", &set, "add"]);
+                add.extend(rule.split_whitespace().map(str::to_string));
+                devfs(&add)?;
+            }
+        }
+
+        actions.push((number, action));
+    }
+
+    Ok(actions)
+}
+
+/// Ensure the configured rulesets using the system `devfs` command
+pub fn ensure_rulesets(config: &DevfsConfig) -> Result<Vec<(u16, RulesetAction)>, DevfsError> {
+    ensure_rulesets_with(config, |args| {
+        let output = std::process::Command::new("devfs")
+            .args(args)
+            .traced_output()
+            .map_err(|e| DevfsError::Command(format!("devfs {}: {}", args.join(" "), e)))?;
+
+        if !output.status.success() {
+            return Err(DevfsError::Command(format!(
+                "devfs {}: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DevfsRulesetConfig;
+
+    const SHOWSETS: &str = "1\n2\n3\n4\n10\n";
+
+    fn show_output(rules: &[String]) -> String {
+        rules
+            .iter()
+            .enumerate()
+            .map(|(i, rule)| format!("{} {}\n", (i + 1) * 100, rule))
+            .collect()
+    }
+
+    fn test_config() -> DevfsConfig {
+        DevfsConfig {
+            ruleset: 10,
+            manage_rules: true,
+            rulesets: vec![DevfsRulesetConfig {
+                number: 11,
+                unhide: vec!["pf".to_string(), "bpf*".to_string()],
+            }],
+        }
+    }
+
+    #[test]
+    fn test_parse_showsets() {
+        assert_eq!(parse_showsets(SHOWSETS), vec![1, 2, 3, 4, 10]);
+        assert!(parse_showsets("").is_empty());
+    }
+
+    #[test]
+    fn test_parse_show() {
+        let output = "100 hide\n200 path  log unhide\n300 path pts/* unhide\n";
+        assert_eq!(parse_show(output), vec!["hide", "path log unhide", "path pts/* unhide"]);
+    }
+
+    #[test]
+    fn test_ruleset_rules_appends_unhides() {
+        let rules = ruleset_rules(&["pf".to_string()]);
+        assert_eq!(rules.first().map(String::as_str), Some("hide"));
+        assert_eq!(rules.last().map(String::as_str), Some("path pf unhide"));
+        assert_eq!(rules.len(), JAIL_RULES.len() + 1);
+    }
+
+    #[test]
+    fn test_plan_ruleset() {
+        let desired = ruleset_rules(&[]);
+        assert_eq!(plan_ruleset(&[1, 4], 10, &[], &desired), RulesetAction::Create);
+        assert_eq!(plan_ruleset(&[10], 10, &desired, &desired), RulesetAction::Keep);
+        assert_eq!(plan_ruleset(&[10], 10, &["hide".to_string()], &desired), RulesetAction::Replace);
+    }
+
+    #[test]
+    fn test_ensure_rulesets_is_idempotent() {
+        let config = test_config();
+        let default_rules = ruleset_rules(&[]);
+        let mut calls = Vec::new();
+
+        // Ruleset 10 already matches; 11 is missing
+        let actions = ensure_rulesets_with(&config, |args| {
+            calls.push(args.join(" "));
+            Ok(match args.join(" ").as_str() {
+                "rule showsets" => SHOWSETS.to_string(),
+                "rule -s 10 show" => show_output(&default_rules),
+                _ => String::new(),
+            })
+        })
+        .unwrap();
+
+        assert_eq!(actions, vec![(10, RulesetAction::Keep), (11, RulesetAction::Create)]);
+        assert!(!calls.iter().any(|c| c.starts_with("rule -s 10 add")));
+        assert!(calls.contains(&"rule -s 11 add hide".to_string()));
+        assert!(calls.contains(&"rule -s 11 add path bpf* unhide".to_string()));
+    }
+
+    #[test]
+    fn test_ensure_rulesets_replaces_changed_set() {
+        let config = DevfsConfig {
+            rulesets: Vec::new(),
+            ..test_config()
+        };
+        let mut calls = Vec::new();
+
+        let actions = ensure_rulesets_with(&config, |args| {
+            calls.push(args.join(" "));
+            Ok(match args.join(" ").as_str() {
+                "rule showsets" => SHOWSETS.to_string(),
+                "rule -s 10 show" => "100 hide\n".to_string(),
+                _ => String::new(),
+            })
+        })
+        .unwrap();
+
+        assert_eq!(actions, vec![(10, RulesetAction::Replace)]);
+        let delset = calls.iter().position(|c| c == "rule -s 10 delset").unwrap();
+        let first_add = calls.iter().position(|c| c == "rule -s 10 add hide").unwrap();
+        assert!(delset < first_add);
+    }
+
+    #[test]
+    fn test_validate_pattern() {
+        assert!(validate_pattern("pf").is_ok());
+        assert!(validate_pattern("bpf*").is_ok());
+        assert!(validate_pattern("usb/0.1.0").is_ok());
+        assert!(validate_pattern("").is_err());
+        assert!(validate_pattern("/dev/pf").is_err());
+        assert!(validate_pattern("pts/../ada0").is_err());
+        assert!(validate_pattern("pf unhide; rm").is_err());
+    }
+}
@@ -0,0 +1,821 @@
+//! Host prerequisite checks for `GET /system/doctor`
+//!
+//! Each check is a small type implementing [`Check`]; it looks at the host
+//! through a [`HostProbe`] so it can be tested with canned command output.
+//! Checks that only make sense on FreeBSD report `skipped` elsewhere.
+//! Adding a check means one `impl Check` and an entry in [`default_checks`].
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::KawakazeConfig;
+use crate::system::HostProbe;
+
+/// Free pool space below which the pool check warns
+pub const MIN_POOL_FREE_BYTES: u64 = 5 * 1024 * 1024 * 1024;
+
+/// Outcome of a single check
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Ok,
+    Warn,
+    Fail,
+    Skipped,
+}
+
+impl CheckStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CheckStatus::Ok => "ok",
+            CheckStatus::Warn => "warn",
+            CheckStatus::Fail => "fail",
+            CheckStatus::Skipped => "skipped",
+        }
+    }
+}
+
+/// Result of a single check
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckResult {
+    pub name: String,
+    pub status: CheckStatus,
+    pub detail: String,
+    /// How to fix a warning or failure
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+}
+
+impl CheckResult {
+    fn new(name: &str, status: CheckStatus, detail: impl Into<String>, hint: Option<&str>) -> Self {
+        Self { name: name.to_string(), status, detail: detail.into(), hint: hint.map(str::to_string) }
+    }
+}
+
+/// Every check's result
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DoctorReport {
+    pub checks: Vec<CheckResult>,
+}
+
+impl DoctorReport {
+    /// Whether any check failed
+    pub fn failed(&self) -> bool {
+        self.count(CheckStatus::Fail) > 0
+    }
+
+    /// How many checks ended with `status`
+    pub fn count(&self, status: CheckStatus) -> usize {
+        self.checks.iter().filter(|check| check.status == status).count()
+    }
+}
+
+/// What the checks get to look at
+pub struct DoctorContext<'a> {
+    pub config: &'a KawakazeConfig,
+    pub probe: &'a dyn HostProbe,
+    /// Whether FreeBSD-only checks should run
+    pub freebsd: bool,
+}
+
+/// A single host check
+pub trait Check {
+    fn name(&self) -> &'static str;
+
+    /// Whether the check needs a FreeBSD host; otherwise it is skipped
+    fn freebsd_only(&self) -> bool {
+        true
+    }
+
+    fn run(&self, ctx: &DoctorContext) -> CheckResult;
+}
+
+/// Every check `GET /system/doctor` runs, in display order
+pub fn default_checks() -> Vec<Box<dyn Check + Send + Sync>> {
+    vec![
+        Box::new(RootCheck),
+        Box::new(PoolCheck),
+        Box::new(DatasetsCheck),
+        Box::new(JailCheck),
+        Box::new(VimageCheck),
+        Box::new(PfCheck),
+        Box::new(RctlCheck),
+        Box::new(DevfsCheck),
+        Box::new(MirrorCheck),
+        Box::new(DataRootCheck),
+        Box::new(SocketDirCheck),
+        Box::new(DatabaseCheck),
+    ]
+}
+
+/// Run `checks`, skipping FreeBSD-only ones on other hosts
+pub fn run_checks(ctx: &DoctorContext, checks: &[Box<dyn Check + Send + Sync>]) -> DoctorReport {
+    let checks = checks
+        .iter()
+        .map(|check| {
+            if check.freebsd_only() && !ctx.freebsd {
+                CheckResult::new(check.name(), CheckStatus::Skipped, "not a FreeBSD host", None)
+            } else {
+                check.run(ctx)
+            }
+        })
+        .collect();
+    DoctorReport { checks }
+}
+
+/// The daemon runs as root, which jail, ZFS, pf and devfs all need
+pub struct RootCheck;
+
+impl Check for RootCheck {
+    fn name(&self) -> &'static str {
+        "root"
+    }
+
+    fn freebsd_only(&self) -> bool {
+        false
+    }
+
+    fn run(&self, ctx: &DoctorContext) -> CheckResult {
+        match ctx.probe.command("id", &["-u"]) {
+            Ok(uid) if uid.trim() == "0" => CheckResult::new(self.name(), CheckStatus::Ok, "running as root", None),
+            Ok(uid) => CheckResult::new(
+                self.name(),
+                CheckStatus::Fail,
+                format!("running as uid {}", uid.trim()),
+                Some("run the daemon as root (e.g. through its rc.d script)"),
+            ),
+            Err(err) => CheckResult::new(self.name(), CheckStatus::Fail, format!("id -u failed: {}", err), None),
+        }
+    }
+}
+
+/// The configured pool exists, is healthy and has room
+pub struct PoolCheck;
+
+impl Check for PoolCheck {
+    fn name(&self) -> &'static str {
+        "zfs pool"
+    }
+
+    fn run(&self, ctx: &DoctorContext) -> CheckResult {
+        let pool = ctx.config.zfs_pool.split('/').next().unwrap_or_default();
+        let output = match ctx.probe.command("zpool", &["list", "-H", "-p", "-o", "health,free,size", pool]) {
+            Ok(output) => output,
+            Err(err) => {
+                return CheckResult::new(
+                    self.name(),
+                    CheckStatus::Fail,
+                    format!("pool '{}' not found: {}", pool, err),
+                    Some("create the pool or point zfs_pool in the config at an existing one"),
+                );
+            }
+        };
+
+        match crate::zfs::parse_pool_list(&output) {
+            Some((state, _, _)) if !state.is_healthy() => CheckResult::new(
+                self.name(),
+                CheckStatus::Warn,
+                format!("pool '{}' is {}", pool, state),
+                Some(if crate::pool_health::refuses(state, ctx.config.storage.allow_degraded_pool) {
+                    "see `zpool status -x`; builds, container creation, bootstraps and upgrades are refused until it is ONLINE"
+                } else {
+                    "see `zpool status -x`; builds and container creation go on because storage.allow_degraded_pool is set"
+                }),
+            ),
+            Some((_, free, _)) if free < MIN_POOL_FREE_BYTES => CheckResult::new(
+                self.name(),
+                CheckStatus::Warn,
+                format!("pool '{}' has only {} MB free", pool, free / (1024 * 1024)),
+                Some("free up space (e.g. `kawakaze image prune`) or grow the pool"),
+            ),
+            Some((state, free, _)) => CheckResult::new(
+                self.name(),
+                CheckStatus::Ok,
+                format!("pool '{}' is {}, {} GB free", pool, state, free / (1024 * 1024 * 1024)),
+                None,
+            ),
+            None => CheckResult::new(
+                self.name(),
+                CheckStatus::Fail,
+                format!("unexpected zpool output: {}", output.trim()),
+                None,
+            ),
+        }
+    }
+}
+
+/// The images/containers parent datasets exist, or the pool dataset they
+/// would be created under does
+pub struct DatasetsCheck;
+
+impl Check for DatasetsCheck {
+    fn name(&self) -> &'static str {
+        "datasets"
+    }
+
+    fn run(&self, ctx: &DoctorContext) -> CheckResult {
+        let exists = |dataset: &str| ctx.probe.command("zfs", &["list", "-H", "-o", "name", dataset]).is_ok();
+        let root = &ctx.config.zfs_pool;
+
+        let missing: Vec<String> = ["images", "containers"]
+            .iter()
+            .map(|child| format!("{}/{}", root, child))
+            .filter(|dataset| !exists(dataset))
+            .collect();
+
+        if missing.is_empty() {
+            CheckResult::new(self.name(), CheckStatus::Ok, format!("{}/images and {}/containers exist", root, root), None)
+        } else if exists(root) {
+            CheckResult::new(
+                self.name(),
+                CheckStatus::Ok,
+                format!("{} will be created on first use", missing.join(", ")),
+                None,
+            )
+        } else {
+            CheckResult::new(
+                self.name(),
+                CheckStatus::Fail,
+                format!("{} does not exist, so {} cannot be created", root, missing.join(", ")),
+                Some("create it with `zfs create -p <zfs_pool>`"),
+            )
+        }
+    }
+}
+
+/// The jail subsystem answers queries (`jls` goes through jail_get)
+pub struct JailCheck;
+
+impl Check for JailCheck {
+    fn name(&self) -> &'static str {
+        "jail subsystem"
+    }
+
+    fn run(&self, ctx: &DoctorContext) -> CheckResult {
+        if ctx.probe.sysctl("security.jail.jailed").as_deref() == Some("1") {
+            return CheckResult::new(
+                self.name(),
+                CheckStatus::Warn,
+                "running inside a jail",
+                Some("the parent jail needs children.max > 0 and allow.mount for kawakaze to work"),
+            );
+        }
+
+        match ctx.probe.command("jls", &["-n", "jid"]) {
+            Ok(_) => CheckResult::new(self.name(), CheckStatus::Ok, "jail_get works", None),
+            Err(err) => CheckResult::new(
+                self.name(),
+                CheckStatus::Fail,
+                format!("jls failed: {}", err),
+                Some("make sure the daemon runs as root on a kernel with jail support"),
+            ),
+        }
+    }
+}
+
+/// The kernel has VIMAGE, needed for per-container network stacks
+pub struct VimageCheck;
+
+impl Check for VimageCheck {
+    fn name(&self) -> &'static str {
+        "vimage"
+    }
+
+    fn run(&self, ctx: &DoctorContext) -> CheckResult {
+        match ctx.probe.sysctl("kern.features.vimage").as_deref() {
+            Some("1") => CheckResult::new(self.name(), CheckStatus::Ok, "kernel has VIMAGE", None),
+            _ => CheckResult::new(
+                self.name(),
+                CheckStatus::Fail,
+                "kernel lacks VIMAGE (kern.features.vimage is not 1)",
+                Some("use the GENERIC kernel (VIMAGE is on by default since 12.0) or add `options VIMAGE`"),
+            ),
+        }
+    }
+}
+
+/// pf is loaded and enabled, and the kawakaze anchor can be queried
+pub struct PfCheck;
+
+impl Check for PfCheck {
+    fn name(&self) -> &'static str {
+        "pf"
+    }
+
+    fn run(&self, ctx: &DoctorContext) -> CheckResult {
+        let info = match ctx.probe.command("pfctl", &["-s", "info"]) {
+            Ok(info) => info,
+            Err(err) => {
+                return CheckResult::new(
+                    self.name(),
+                    CheckStatus::Fail,
+                    format!("pf is not loaded: {}", err),
+                    Some("`kldload pf` and add pf_enable=\"YES\" to /etc/rc.conf"),
+                );
+            }
+        };
+
+        if !info.contains("Status: Enabled") {
+            return CheckResult::new(
+                self.name(),
+                CheckStatus::Warn,
+                "pf is loaded but disabled, so port forwarding and NAT won't work",
+                Some("`pfctl -e` and add pf_enable=\"YES\" to /etc/rc.conf"),
+            );
+        }
+
+        let anchor = crate::networking::PF_ANCHOR;
+        match ctx.probe.command("pfctl", &["-a", anchor, "-s", "rules"]) {
+            Ok(_) => CheckResult::new(self.name(), CheckStatus::Ok, format!("pf enabled, anchor '{}' usable", anchor), None),
+            Err(err) => CheckResult::new(
+                self.name(),
+                CheckStatus::Warn,
+                format!("anchor '{}' is not usable: {}", anchor, err),
+                Some("add `anchor \"kawakaze\"` and `rdr-anchor \"kawakaze\"` to /etc/pf.conf, then `pfctl -f /etc/pf.conf`"),
+            ),
+        }
+    }
+}
+
+/// Resource accounting is on, so rctl limits can be applied
+pub struct RctlCheck;
+
+impl Check for RctlCheck {
+    fn name(&self) -> &'static str {
+        "rctl"
+    }
+
+    fn run(&self, ctx: &DoctorContext) -> CheckResult {
+        match ctx.probe.sysctl("kern.racct.enable").as_deref() {
+            Some("1") => CheckResult::new(self.name(), CheckStatus::Ok, "kern.racct.enable=1", None),
+            other => CheckResult::new(
+                self.name(),
+                CheckStatus::Warn,
+                format!("kern.racct.enable is {}; resource limits can't be applied", other.unwrap_or("unset")),
+                Some("add kern.racct.enable=1 to /boot/loader.conf and reboot"),
+            ),
+        }
+    }
+}
+
+/// The configured devfs rulesets exist
+pub struct DevfsCheck;
+
+impl Check for DevfsCheck {
+    fn name(&self) -> &'static str {
+        "devfs rulesets"
+    }
+
+    fn run(&self, ctx: &DoctorContext) -> CheckResult {
+        let existing = match ctx.probe.command("devfs", &["rule", "showsets"]) {
+            Ok(output) => crate::devfs::parse_showsets(&output),
+            Err(err) => {
+                return CheckResult::new(self.name(), CheckStatus::Fail, format!("devfs rule showsets failed: {}", err), None);
+            }
+        };
+
+        let missing: Vec<String> = crate::devfs::desired_rulesets(&ctx.config.devfs)
+            .into_iter()
+            .map(|(number, _)| number)
+            .filter(|number| !existing.contains(number))
+            .map(|number| number.to_string())
+            .collect();
+
+        if missing.is_empty() {
+            CheckResult::new(self.name(), CheckStatus::Ok, "all configured rulesets exist", None)
+        } else if ctx.config.devfs.manage_rules {
+            CheckResult::new(
+                self.name(),
+                CheckStatus::Ok,
+                format!("ruleset(s) {} will be created at daemon start", missing.join(", ")),
+                None,
+            )
+        } else {
+            CheckResult::new(
+                self.name(),
+                CheckStatus::Fail,
+                format!("ruleset(s) {} missing", missing.join(", ")),
+                Some("define them in /etc/devfs.rules or set devfs.manage_rules = true"),
+            )
+        }
+    }
+}
+
+/// The FreeBSD mirror base systems are bootstrapped from answers
+pub struct MirrorCheck;
+
+impl Check for MirrorCheck {
+    fn name(&self) -> &'static str {
+        "mirror"
+    }
+
+    fn run(&self, ctx: &DoctorContext) -> CheckResult {
+        let url = format!("{}/", crate::bootstrap::DEFAULT_MIRROR);
+        match ctx.probe.command("fetch", &["-q", "-T", "10", "-o", "/dev/null", &url]) {
+            Ok(_) => CheckResult::new(self.name(), CheckStatus::Ok, format!("{} is reachable", url), None),
+            Err(err) => CheckResult::new(
+                self.name(),
+                CheckStatus::Warn,
+                format!("cannot reach {}: {}", url, err.trim()),
+                Some("check DNS and outbound HTTPS; cached base images still work, new bootstraps will fail"),
+            ),
+        }
+    }
+}
+
+/// The data root, or the nearest existing directory it would be created
+/// under, is writable
+pub struct DataRootCheck;
+
+impl Check for DataRootCheck {
+    fn name(&self) -> &'static str {
+        "data root"
+    }
+
+    fn freebsd_only(&self) -> bool {
+        false
+    }
+
+    fn run(&self, ctx: &DoctorContext) -> CheckResult {
+        let root = crate::paths::Paths::from_config(ctx.config).data_root;
+        let dir = root.ancestors().find(|dir| dir.is_dir()).unwrap_or(Path::new("/"));
+        let probe = dir.join(format!(".kawakaze-doctor-{}", std::process::id()));
+
+        match std::fs::write(&probe, b"") {
+            Ok(()) => {
+                let _ = std::fs::remove_file(&probe);
+                let detail = if dir == root {
+                    format!("{} is writable", root.display())
+                } else {
+                    format!("{} will be created under {}", root.display(), dir.display())
+                };
+                CheckResult::new(self.name(), CheckStatus::Ok, detail, None)
+            }
+            Err(err) => CheckResult::new(
+                self.name(),
+                CheckStatus::Fail,
+                format!("cannot write to {}: {}", dir.display(), err),
+                Some("run the daemon as root, or point data_root in the config at a writable directory"),
+            ),
+        }
+    }
+}
+
+/// The socket's directory exists and is writable
+pub struct SocketDirCheck;
+
+impl Check for SocketDirCheck {
+    fn name(&self) -> &'static str {
+        "socket directory"
+    }
+
+    fn freebsd_only(&self) -> bool {
+        false
+    }
+
+    fn run(&self, ctx: &DoctorContext) -> CheckResult {
+        let socket = crate::paths::Paths::from_config(ctx.config).socket;
+        let dir = socket.parent().unwrap_or(Path::new("/"));
+        let probe = dir.join(format!(".kawakaze-doctor-{}", std::process::id()));
+
+        match std::fs::write(&probe, b"") {
+            Ok(()) => {
+                let _ = std::fs::remove_file(&probe);
+                CheckResult::new(self.name(), CheckStatus::Ok, format!("{} is writable", dir.display()), None)
+            }
+            Err(err) => CheckResult::new(
+                self.name(),
+                CheckStatus::Fail,
+                format!("cannot write to {}: {}", dir.display(), err),
+                Some("create the directory and run the daemon as root"),
+            ),
+        }
+    }
+}
+
+/// The database opens and has every migrated column
+pub struct DatabaseCheck;
+
+impl Check for DatabaseCheck {
+    fn name(&self) -> &'static str {
+        "database"
+    }
+
+    fn freebsd_only(&self) -> bool {
+        false
+    }
+
+    fn run(&self, ctx: &DoctorContext) -> CheckResult {
+        let database = crate::paths::Paths::from_config(ctx.config).database;
+        let path = database.display();
+        if !database.exists() {
+            return CheckResult::new(self.name(), CheckStatus::Ok, format!("{} will be created at daemon start", path), None);
+        }
+
+        match crate::store::JailStore::missing_columns(&database) {
+            Ok(missing) if missing.is_empty() => {
+                CheckResult::new(self.name(), CheckStatus::Ok, format!("{} is current", path), None)
+            }
+            Ok(missing) => CheckResult::new(
+                self.name(),
+                CheckStatus::Warn,
+                format!("{} lacks {}", path, missing.join(", ")),
+                Some("restart the daemon to migrate it"),
+            ),
+            Err(err) => CheckResult::new(
+                self.name(),
+                CheckStatus::Fail,
+                format!("cannot open {}: {}", path, err),
+                Some("check the file's permissions, or move it aside to start fresh"),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[derive(Default)]
+    struct MockProbe {
+        sysctls: HashMap<&'static str, &'static str>,
+        /// "program arg arg" -> stdout, or Err(stderr)
+        commands: HashMap<String, Result<&'static str, &'static str>>,
+    }
+
+    impl MockProbe {
+        fn with_command(mut self, cmd: &str, result: Result<&'static str, &'static str>) -> Self {
+            self.commands.insert(cmd.to_string(), result);
+            self
+        }
+
+        fn with_sysctl(mut self, name: &'static str, value: &'static str) -> Self {
+            self.sysctls.insert(name, value);
+            self
+        }
+    }
+
+    impl HostProbe for MockProbe {
+        fn sysctl(&self, name: &str) -> Option<String> {
+            self.sysctls.get(name).map(|v| v.to_string())
+        }
+
+        fn uname(&self, _flag: &str) -> Option<String> {
+            None
+        }
+
+        fn command(&self, program: &str, args: &[&str]) -> Result<String, String> {
+            let key = std::iter::once(program).chain(args.iter().copied()).collect::<Vec<_>>().join(" ");
+            match self.commands.get(&key) {
+                Some(Ok(stdout)) => Ok(stdout.to_string()),
+                Some(Err(stderr)) => Err(stderr.to_string()),
+                None => Err(format!("{}: not found", program)),
+            }
+        }
+    }
+
+    fn check(check: &dyn Check, probe: &MockProbe, config: &KawakazeConfig) -> CheckResult {
+        check.run(&DoctorContext { config, probe, freebsd: true })
+    }
+
+    fn config() -> KawakazeConfig {
+        KawakazeConfig { zfs_pool: "tank/kawakaze".to_string(), ..Default::default() }
+    }
+
+    #[test]
+    fn test_pool_check() {
+        let cmd = "zpool list -H -p -o health,free,size tank";
+        let config = config();
+
+        let probe = MockProbe::default().with_command(cmd, Ok("ONLINE\t107374182400\t214748364800\n"));
+        assert_eq!(check(&PoolCheck, &probe, &config).status, CheckStatus::Ok);
+
+        let probe = MockProbe::default().with_command(cmd, Ok("ONLINE\t1048576\t214748364800\n"));
+        assert_eq!(check(&PoolCheck, &probe, &config).status, CheckStatus::Warn);
+
+        let probe = MockProbe::default().with_command(cmd, Ok("DEGRADED\t107374182400\t214748364800\n"));
+        let result = check(&PoolCheck, &probe, &config);
+        assert_eq!(result.status, CheckStatus::Warn);
+        assert!(result.detail.contains("DEGRADED"));
+        assert!(result.hint.as_deref().unwrap().contains("refused"));
+        let mut lenient = config.clone();
+        lenient.storage.allow_degraded_pool = true;
+        let result = check(&PoolCheck, &probe, &lenient);
+        assert!(result.hint.as_deref().unwrap().contains("allow_degraded_pool"));
+
+        let probe = MockProbe::default().with_command(cmd, Err("cannot open 'tank': no such pool"));
+        let result = check(&PoolCheck, &probe, &config);
+        assert_eq!(result.status, CheckStatus::Fail);
+        assert!(result.hint.is_some());
+    }
+
+    #[test]
+    fn test_datasets_check() {
+        let config = config();
+        let images = "zfs list -H -o name tank/kawakaze/images";
+        let containers = "zfs list -H -o name tank/kawakaze/containers";
+        let root = "zfs list -H -o name tank/kawakaze";
+
+        let probe = MockProbe::default().with_command(images, Ok("")).with_command(containers, Ok(""));
+        assert_eq!(check(&DatasetsCheck, &probe, &config).status, CheckStatus::Ok);
+
+        // Missing children are fine while the root exists
+        let probe = MockProbe::default().with_command(root, Ok(""));
+        let result = check(&DatasetsCheck, &probe, &config);
+        assert_eq!(result.status, CheckStatus::Ok);
+        assert!(result.detail.contains("created on first use"));
+
+        assert_eq!(check(&DatasetsCheck, &MockProbe::default(), &config).status, CheckStatus::Fail);
+    }
+
+    #[test]
+    fn test_jail_vimage_rctl_checks() {
+        let config = config();
+
+        let probe = MockProbe::default()
+            .with_command("jls -n jid", Ok(""))
+            .with_sysctl("kern.features.vimage", "1")
+            .with_sysctl("kern.racct.enable", "1");
+        assert_eq!(check(&JailCheck, &probe, &config).status, CheckStatus::Ok);
+        assert_eq!(check(&VimageCheck, &probe, &config).status, CheckStatus::Ok);
+        assert_eq!(check(&RctlCheck, &probe, &config).status, CheckStatus::Ok);
+
+        let probe = MockProbe::default().with_sysctl("kern.racct.enable", "0");
+        assert_eq!(check(&JailCheck, &probe, &config).status, CheckStatus::Fail);
+        assert_eq!(check(&VimageCheck, &probe, &config).status, CheckStatus::Fail);
+        let result = check(&RctlCheck, &probe, &config);
+        assert_eq!(result.status, CheckStatus::Warn);
+        assert!(result.hint.unwrap().contains("loader.conf"));
+
+        let probe = MockProbe::default().with_sysctl("security.jail.jailed", "1");
+        assert_eq!(check(&JailCheck, &probe, &config).status, CheckStatus::Warn);
+    }
+
+    #[test]
+    fn test_pf_check() {
+        let config = config();
+        let enabled = "Status: Enabled for 0 days 01:02:03           Debug: Urgent\n";
+
+        let probe = MockProbe::default()
+            .with_command("pfctl -s info", Ok(enabled))
+            .with_command("pfctl -a kawakaze -s rules", Ok(""));
+        assert_eq!(check(&PfCheck, &probe, &config).status, CheckStatus::Ok);
+
+        let probe = MockProbe::default().with_command("pfctl -s info", Ok(enabled));
+        assert_eq!(check(&PfCheck, &probe, &config).status, CheckStatus::Warn);
+
+        let probe = MockProbe::default().with_command("pfctl -s info", Ok("Status: Disabled\n"));
+        assert_eq!(check(&PfCheck, &probe, &config).status, CheckStatus::Warn);
+
+        let probe = MockProbe::default().with_command("pfctl -s info", Err("pfctl: /dev/pf: No such file or directory"));
+        assert_eq!(check(&PfCheck, &probe, &config).status, CheckStatus::Fail);
+    }
+
+    #[test]
+    fn test_devfs_check() {
+        let mut config = config();
+        config.devfs.manage_rules = false;
+
+        let probe = MockProbe::default().with_command("devfs rule showsets", Ok("1\n2\n3\n4\n10\n"));
+        assert_eq!(check(&DevfsCheck, &probe, &config).status, CheckStatus::Ok);
+
+        let probe = MockProbe::default().with_command("devfs rule showsets", Ok("1\n2\n3\n4\n"));
+        let result = check(&DevfsCheck, &probe, &config);
+        assert_eq!(result.status, CheckStatus::Fail);
+        assert!(result.detail.contains("10"));
+
+        config.devfs.manage_rules = true;
+        assert_eq!(check(&DevfsCheck, &probe, &config).status, CheckStatus::Ok);
+    }
+
+    #[test]
+    fn test_socket_and_database_checks() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = config();
+        config.data_root = dir.path().display().to_string();
+        let database = dir.path().join("kawakaze.db");
+        let probe = MockProbe::default();
+
+        assert_eq!(check(&SocketDirCheck, &probe, &config).status, CheckStatus::Ok);
+        assert_eq!(check(&DatabaseCheck, &probe, &config).status, CheckStatus::Ok);
+
+        crate::store::JailStore::new(&database).unwrap();
+        let result = check(&DatabaseCheck, &probe, &config);
+        assert_eq!(result.status, CheckStatus::Ok);
+        assert!(result.detail.contains("current"));
+
+        std::fs::write(&database, b"not a database").unwrap();
+        assert_eq!(check(&DatabaseCheck, &probe, &config).status, CheckStatus::Fail);
+
+        config.storage.socket_path = Some(dir.path().join("missing/kawakaze.sock").display().to_string());
+        assert_eq!(check(&SocketDirCheck, &probe, &config).status, CheckStatus::Fail);
+    }
+
+    #[test]
+    fn test_root_and_mirror_checks() {
+        let config = config();
+        let fetch = "fetch -q -T 10 -o /dev/null https://download.freebsd.org/releases/";
+
+        let probe = MockProbe::default().with_command("id -u", Ok("0\n")).with_command(fetch, Ok(""));
+        assert_eq!(check(&RootCheck, &probe, &config).status, CheckStatus::Ok);
+        assert_eq!(check(&MirrorCheck, &probe, &config).status, CheckStatus::Ok);
+
+        let probe = MockProbe::default()
+            .with_command("id -u", Ok("1001\n"))
+            .with_command(fetch, Err("fetch: transfer timed out"));
+        let result = check(&RootCheck, &probe, &config);
+        assert_eq!(result.status, CheckStatus::Fail);
+        assert!(result.detail.contains("1001") && result.hint.is_some());
+        let result = check(&MirrorCheck, &probe, &config);
+        assert_eq!(result.status, CheckStatus::Warn);
+        assert!(result.detail.contains("timed out"));
+    }
+
+    #[test]
+    fn test_data_root_check() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = config();
+        let probe = MockProbe::default();
+
+        config.data_root = dir.path().display().to_string();
+        let result = check(&DataRootCheck, &probe, &config);
+        assert_eq!(result.status, CheckStatus::Ok);
+        assert!(result.detail.ends_with("is writable"));
+
+        config.data_root = dir.path().join("a/b").display().to_string();
+        let result = check(&DataRootCheck, &probe, &config);
+        assert_eq!(result.status, CheckStatus::Ok);
+        assert!(result.detail.contains("will be created"));
+    }
+
+    /// A check with a fixed outcome
+    struct Fixed(&'static str, CheckStatus, bool);
+
+    impl Check for Fixed {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+
+        fn freebsd_only(&self) -> bool {
+            self.2
+        }
+
+        fn run(&self, _ctx: &DoctorContext) -> CheckResult {
+            CheckResult::new(self.0, self.1, "fixed", None)
+        }
+    }
+
+    #[test]
+    fn test_run_checks_aggregates_in_order() {
+        let config = config();
+        let probe = MockProbe::default();
+        let checks: Vec<Box<dyn Check + Send + Sync>> = vec![
+            Box::new(Fixed("a", CheckStatus::Ok, false)),
+            Box::new(Fixed("b", CheckStatus::Warn, false)),
+            Box::new(Fixed("c", CheckStatus::Fail, true)),
+            Box::new(Fixed("d", CheckStatus::Ok, true)),
+        ];
+
+        let report = run_checks(&DoctorContext { config: &config, probe: &probe, freebsd: true }, &checks);
+        let names: Vec<_> = report.checks.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["a", "b", "c", "d"]);
+        assert!(report.failed());
+        assert_eq!((report.count(CheckStatus::Ok), report.count(CheckStatus::Warn), report.count(CheckStatus::Fail)), (2, 1, 1));
+
+        // The failing check is FreeBSD-only, so elsewhere it is skipped and nothing fails
+        let report = run_checks(&DoctorContext { config: &config, probe: &probe, freebsd: false }, &checks);
+        assert!(!report.failed());
+        assert_eq!(report.count(CheckStatus::Skipped), 2);
+        assert_eq!(report.checks[2].detail, "not a FreeBSD host");
+
+        // A warning alone doesn't fail the report
+        let report = run_checks(&DoctorContext { config: &config, probe: &probe, freebsd: true }, &checks[..2]);
+        assert!(!report.failed());
+    }
+
+    #[test]
+    fn test_run_checks_skips_freebsd_only_checks_elsewhere() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = config();
+        config.data_root = dir.path().display().to_string();
+        let probe = MockProbe::default().with_command("id -u", Ok("0\n"));
+
+        let report = run_checks(&DoctorContext { config: &config, probe: &probe, freebsd: false }, &default_checks());
+        assert_eq!(report.checks.len(), default_checks().len());
+        assert!(!report.failed());
+        for result in &report.checks {
+            let expected = if ["root", "data root", "socket directory", "database"].contains(&result.name.as_str()) {
+                CheckStatus::Ok
+            } else {
+                CheckStatus::Skipped
+            };
+            assert_eq!(result.status, expected, "{}", result.name);
+        }
+
+        // On FreeBSD the same probe fails the pool check
+        let report = run_checks(&DoctorContext { config: &config, probe: &probe, freebsd: true }, &default_checks());
+        assert!(report.failed());
+    }
+}
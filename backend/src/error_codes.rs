@@ -0,0 +1,228 @@
+//! Machine-readable codes in [`ApiError::code`](crate::api::ApiError)
+//!
+//! The message of an error is for people and may change between releases;
+//! the code is what scripts and the CLI's exit status go by, so every code
+//! the daemon answers with is registered in [`CODES`] together with the
+//! status it is sent with. A code is never reused for something else.
+//!
+//! Codes fall into a few [`Family`]s a caller can act on without knowing
+//! every code: the request was invalid, the resource doesn't exist, it is
+//! in the wrong state, the daemon can't do it right now, or it failed.
+
+use crate::api::{StatusCode, status};
+
+/// 400: the request is invalid; the generic code for a failed validation
+pub const BAD_REQUEST: &str = "BAD_REQUEST";
+/// 400: the request line isn't a request at all
+pub const INVALID_REQUEST: &str = "INVALID_REQUEST";
+/// 400: the body doesn't match the endpoint's request type
+pub const INVALID_BODY: &str = "INVALID_BODY";
+/// 400: no such endpoint, or not for this method
+pub const UNKNOWN_ENDPOINT: &str = "UNKNOWN_ENDPOINT";
+/// 400: a streaming endpoint was requested without streaming, or the reverse
+pub const WRONG_ENDPOINT_KIND: &str = "WRONG_ENDPOINT_KIND";
+/// 400: a jail, host, secret or alias name that isn't allowed
+pub const INVALID_NAME: &str = "INVALID_NAME";
+/// 400: a container reference matches more than one container, such as one's
+/// name and a prefix of another's ID
+pub const AMBIGUOUS_REFERENCE: &str = "AMBIGUOUS_REFERENCE";
+/// 403: refused by the daemon's configuration
+pub const FORBIDDEN: &str = "FORBIDDEN";
+/// 405: an HTTP method the API has no counterpart for
+pub const METHOD_NOT_ALLOWED: &str = "METHOD_NOT_ALLOWED";
+
+/// 404: a build, task, upload, operation, log or dataset doesn't exist
+pub const NOT_FOUND: &str = "NOT_FOUND";
+/// 404: no jail by that name
+pub const JAIL_NOT_FOUND: &str = "JAIL_NOT_FOUND";
+/// 404: no image by that ID, name or prefix
+pub const IMAGE_NOT_FOUND: &str = "IMAGE_NOT_FOUND";
+/// 404: no container by that ID, name or prefix
+pub const CONTAINER_NOT_FOUND: &str = "CONTAINER_NOT_FOUND";
+
+/// 409: the resource is in the wrong state for the request
+pub const CONFLICT: &str = "CONFLICT";
+/// 409: a jail, container, tag or alias by that name already exists
+pub const NAME_IN_USE: &str = "NAME_IN_USE";
+/// 409: a published port is taken by another container
+pub const PORT_IN_USE: &str = "PORT_IN_USE";
+/// 409: an address is taken by another container
+pub const ADDRESS_IN_USE: &str = "ADDRESS_IN_USE";
+/// 409: the same build, bootstrap or task is already running
+pub const IN_PROGRESS: &str = "IN_PROGRESS";
+/// 409: the container must be stopped first
+pub const CONTAINER_RUNNING: &str = "CONTAINER_RUNNING";
+/// 409: the container must be running
+pub const CONTAINER_NOT_RUNNING: &str = "CONTAINER_NOT_RUNNING";
+/// 409: the resource is protected against removal
+pub const RESOURCE_PROTECTED: &str = "RESOURCE_PROTECTED";
+/// 409: the resource was last written by a newer kawakaze
+pub const VERSION_SKEW: &str = "VERSION_SKEW";
+/// 409: the container's dataset was destroyed outside kawakaze
+pub const DATASET_MISSING: &str = "DATASET_MISSING";
+/// 409: a request on a connection whose stream is still active
+pub const CONNECTION_BUSY_STREAMING: &str = "CONNECTION_BUSY_STREAMING";
+/// 409: this node doesn't satisfy a container's placement constraints
+pub const PLACEMENT_UNSATISFIABLE: &str = "PLACEMENT_UNSATISFIABLE";
+
+/// 499: the client went away before the request finished
+pub const REQUEST_CANCELLED: &str = "REQUEST_CANCELLED";
+
+/// 500: a failure with no more specific code, such as serializing a response
+pub const INTERNAL_ERROR: &str = "INTERNAL_ERROR";
+/// 500: the response couldn't be serialized for the wire
+pub const SERIALIZATION_ERROR: &str = "SERIALIZATION_ERROR";
+/// 500: creating a container or jail failed
+pub const CREATE_FAILED: &str = "CREATE_FAILED";
+/// 500: starting a container or jail failed
+pub const START_FAILED: &str = "START_FAILED";
+/// 500: stopping a container or jail failed
+pub const STOP_FAILED: &str = "STOP_FAILED";
+/// 500: removing a container or image failed
+pub const REMOVE_FAILED: &str = "REMOVE_FAILED";
+/// 500: destroying a jail failed
+pub const DESTROY_FAILED: &str = "DESTROY_FAILED";
+/// 500: changing a resource's settings failed
+pub const UPDATE_FAILED: &str = "UPDATE_FAILED";
+/// 500: an upgrade failed and was rolled back, or the rollback failed too
+pub const UPGRADE_FAILED: &str = "UPGRADE_FAILED";
+/// 500: running a command in a container failed
+pub const EXEC_FAILED: &str = "EXEC_FAILED";
+/// 500: a log couldn't be read
+pub const LOG_READ_FAILED: &str = "LOG_READ_FAILED";
+/// 500: a `zfs` command failed
+pub const ZFS_ERROR: &str = "ZFS_ERROR";
+/// 500: a stream ended because the daemon cancelled it
+pub const STREAM_CANCELLED: &str = "STREAM_CANCELLED";
+
+/// 503: the daemon is shutting down
+pub const SHUTTING_DOWN: &str = "SHUTTING_DOWN";
+/// 503: the daemon's host (or ZFS) doesn't allow the operation
+pub const UNAVAILABLE: &str = "UNAVAILABLE";
+/// 503: the pool isn't healthy enough to write to
+pub const POOL_UNHEALTHY: &str = "POOL_UNHEALTHY";
+
+/// 202: a bootstrap runs in the background (not a failure)
+pub const BOOTSTRAP_STARTED: &str = "BOOTSTRAP_STARTED";
+/// 202: an upgrade runs in the background (not a failure)
+pub const UPGRADE_STARTED: &str = "UPGRADE_STARTED";
+/// 202: a create or remove runs in the background (not a failure)
+pub const OPERATION_STARTED: &str = "OPERATION_STARTED";
+
+/// Every code the daemon answers with, and the status it is sent with
+pub const CODES: &[(&str, StatusCode)] = &[
+    (BAD_REQUEST, status::BAD_REQUEST),
+    (INVALID_REQUEST, status::BAD_REQUEST),
+    (INVALID_BODY, status::BAD_REQUEST),
+    (UNKNOWN_ENDPOINT, status::BAD_REQUEST),
+    (WRONG_ENDPOINT_KIND, status::BAD_REQUEST),
+    (INVALID_NAME, status::BAD_REQUEST),
+    (AMBIGUOUS_REFERENCE, status::BAD_REQUEST),
+    (FORBIDDEN, status::FORBIDDEN),
+    (METHOD_NOT_ALLOWED, status::METHOD_NOT_ALLOWED),
+    (NOT_FOUND, status::NOT_FOUND),
+    (JAIL_NOT_FOUND, status::NOT_FOUND),
+    (IMAGE_NOT_FOUND, status::NOT_FOUND),
+    (CONTAINER_NOT_FOUND, status::NOT_FOUND),
+    (CONFLICT, status::CONFLICT),
+    (NAME_IN_USE, status::CONFLICT),
+    (PORT_IN_USE, status::CONFLICT),
+    (ADDRESS_IN_USE, status::CONFLICT),
+    (IN_PROGRESS, status::CONFLICT),
+    (CONTAINER_RUNNING, status::CONFLICT),
+    (CONTAINER_NOT_RUNNING, status::CONFLICT),
+    (RESOURCE_PROTECTED, status::CONFLICT),
+    (VERSION_SKEW, status::CONFLICT),
+    (DATASET_MISSING, status::CONFLICT),
+    (CONNECTION_BUSY_STREAMING, status::CONFLICT),
+    (PLACEMENT_UNSATISFIABLE, status::CONFLICT),
+    (REQUEST_CANCELLED, status::CLIENT_CLOSED_REQUEST),
+    (INTERNAL_ERROR, status::INTERNAL_SERVER_ERROR),
+    (SERIALIZATION_ERROR, status::INTERNAL_SERVER_ERROR),
+    (CREATE_FAILED, status::INTERNAL_SERVER_ERROR),
+    (START_FAILED, status::INTERNAL_SERVER_ERROR),
+    (STOP_FAILED, status::INTERNAL_SERVER_ERROR),
+    (REMOVE_FAILED, status::INTERNAL_SERVER_ERROR),
+    (DESTROY_FAILED, status::INTERNAL_SERVER_ERROR),
+    (UPDATE_FAILED, status::INTERNAL_SERVER_ERROR),
+    (UPGRADE_FAILED, status::INTERNAL_SERVER_ERROR),
+    (EXEC_FAILED, status::INTERNAL_SERVER_ERROR),
+    (LOG_READ_FAILED, status::INTERNAL_SERVER_ERROR),
+    (ZFS_ERROR, status::INTERNAL_SERVER_ERROR),
+    (STREAM_CANCELLED, status::INTERNAL_SERVER_ERROR),
+    (SHUTTING_DOWN, status::SERVICE_UNAVAILABLE),
+    (UNAVAILABLE, status::SERVICE_UNAVAILABLE),
+    (POOL_UNHEALTHY, status::SERVICE_UNAVAILABLE),
+    (BOOTSTRAP_STARTED, status::ACCEPTED),
+    (UPGRADE_STARTED, status::ACCEPTED),
+    (OPERATION_STARTED, status::ACCEPTED),
+];
+
+/// What a caller can do about an error, by its status
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Family {
+    /// 400, 403, 405: fix the request
+    Invalid,
+    /// 404: the resource doesn't exist
+    NotFound,
+    /// 409: the resource is in the wrong state or the name is taken
+    Conflict,
+    /// 503: the daemon can't do it right now
+    Unavailable,
+    /// 499, 500: the daemon failed
+    Internal,
+}
+
+impl Family {
+    /// The family of an error sent with `status`; `None` for a success
+    pub fn of_status(status: StatusCode) -> Option<Self> {
+        match status {
+            0..=399 => None,
+            status::NOT_FOUND => Some(Self::NotFound),
+            status::CONFLICT => Some(Self::Conflict),
+            status::SERVICE_UNAVAILABLE => Some(Self::Unavailable),
+            400..=498 => Some(Self::Invalid),
+            _ => Some(Self::Internal),
+        }
+    }
+}
+
+/// The status `code` is sent with, if it is registered
+pub fn status(code: &str) -> Option<StatusCode> {
+    CODES.iter().find(|(known, _)| *known == code).map(|(_, status)| *status)
+}
+
+/// The family of a registered `code`
+pub fn family(code: &str) -> Option<Family> {
+    status(code).and_then(Family::of_status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_codes_registered_once() {
+        let mut seen = HashSet::new();
+        for (code, status) in CODES {
+            assert!(seen.insert(*code), "{} is registered twice", code);
+            assert!(code.chars().all(|c| c.is_ascii_uppercase() || c == '_'), "{}", code);
+            assert!((200..600).contains(status), "{}", code);
+        }
+    }
+
+    #[test]
+    fn test_family() {
+        assert_eq!(family(INVALID_BODY), Some(Family::Invalid));
+        assert_eq!(family(FORBIDDEN), Some(Family::Invalid));
+        assert_eq!(family(CONTAINER_NOT_FOUND), Some(Family::NotFound));
+        assert_eq!(family(NAME_IN_USE), Some(Family::Conflict));
+        assert_eq!(family(RESOURCE_PROTECTED), Some(Family::Conflict));
+        assert_eq!(family(POOL_UNHEALTHY), Some(Family::Unavailable));
+        assert_eq!(family(START_FAILED), Some(Family::Internal));
+        assert_eq!(family(REQUEST_CANCELLED), Some(Family::Internal));
+        assert_eq!(family(OPERATION_STARTED), None);
+        assert_eq!(family("NO_SUCH_CODE"), None);
+    }
+}
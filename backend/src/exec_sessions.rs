@@ -0,0 +1,457 @@
+//! Commands running in containers through `exec`
+//!
+//! A shell left open in a container, or a stuck process started by `exec`,
+//! is otherwise invisible, and keeps the jail busy when the container is
+//! stopped. Every exec registers an [`ExecSession`] for as long as its
+//! command runs: API execs through the handler, and the CLI's interactive
+//! sessions, which run `jexec` themselves, through `POST /exec-sessions`.
+//!
+//! Killing a session signals its process group, SIGTERM and then SIGKILL
+//! once the grace period is over (see [`terminate`]); a process that doesn't
+//! lead a group (the CLI's `exec -i`, which shares the terminal's group) is
+//! signalled alone. Stopping a container kills its sessions before the jail
+//! is stopped. A session whose process is gone without it being ended, such
+//! as one of a CLI that was killed, is dropped by [`ExecSessions::sweep`],
+//! which the daemon runs every [`SWEEP_INTERVAL`].
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::timestamp::Timestamp;
+
+/// How long a session has to exit after SIGTERM before it gets SIGKILL
+pub const KILL_GRACE: Duration = Duration::from_secs(5);
+
+/// How often the daemon drops sessions whose process is gone
+pub const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often [`terminate`] checks whether the group has exited
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How an exec session was started
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExecKind {
+    /// `POST /containers/{id}/exec`, run by the daemon
+    Api,
+    /// The CLI's `exec -t`, on a pseudo-terminal
+    Pty,
+    /// The CLI's `exec -i`, on the CLI's own terminal
+    Interactive,
+}
+
+impl ExecKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ExecKind::Api => "api",
+            ExecKind::Pty => "pty",
+            ExecKind::Interactive => "interactive",
+        }
+    }
+}
+
+/// A command running in a container
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExecSession {
+    pub id: String,
+    pub container_id: String,
+    /// The container's name, or its short ID
+    pub container: String,
+    pub command: Vec<String>,
+    pub kind: ExecKind,
+    /// Login name of the client on the host, when its uid has one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// uid of the client, from the socket's peer credentials
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peer_uid: Option<u32>,
+    pub started_at: Timestamp,
+    /// Host pid of the command, which leads its process group unless the
+    /// session is `interactive`; `None` until it is spawned
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>,
+}
+
+/// How a killed session ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Termination {
+    /// Exited within the grace period after SIGTERM
+    Terminated,
+    /// Still there after the grace period, and sent SIGKILL
+    Killed,
+    /// The process was already gone, or had never been spawned
+    Gone,
+}
+
+/// Signals sent to a session's processes
+pub trait ProcessGroups: Send + Sync {
+    /// Send `signal` to the group `pid` leads, or to `pid` alone if it
+    /// leads none
+    fn signal(&self, pid: u32, signal: i32) -> io::Result<()>;
+
+    /// Whether anything `signal` would reach is still there
+    fn exists(&self, pid: u32) -> bool;
+}
+
+/// Processes on this host
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HostProcessGroups;
+
+impl HostProcessGroups {
+    /// `-pid` when `pid` leads its process group, or led it and is gone, so
+    /// the group is signalled
+    fn target(pid: u32) -> libc::pid_t {
+        let pid = pid as libc::pid_t;
+        match unsafe { libc::getpgid(pid) } {
+            pgid if pgid == pid || pgid == -1 => -pid,
+            _ => pid,
+        }
+    }
+}
+
+impl ProcessGroups for HostProcessGroups {
+    fn signal(&self, pid: u32, signal: i32) -> io::Result<()> {
+        if unsafe { libc::kill(Self::target(pid), signal) } == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    fn exists(&self, pid: u32) -> bool {
+        // EPERM means it is there, only not ours to signal
+        let found = unsafe { libc::kill(Self::target(pid), 0) } == 0;
+        found || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+    }
+}
+
+/// End the processes of `pid`: SIGTERM, and SIGKILL if anything is left
+/// after `grace`
+pub fn terminate(groups: &dyn ProcessGroups, pid: u32, grace: Duration) -> Termination {
+    if !groups.exists(pid) || groups.signal(pid, libc::SIGTERM).is_err() {
+        return Termination::Gone;
+    }
+
+    let deadline = Instant::now() + grace;
+    while Instant::now() < deadline {
+        if !groups.exists(pid) {
+            return Termination::Terminated;
+        }
+        std::thread::sleep(POLL_INTERVAL.min(deadline.saturating_duration_since(Instant::now())));
+    }
+    if !groups.exists(pid) {
+        return Termination::Terminated;
+    }
+
+    let _ = groups.signal(pid, libc::SIGKILL);
+    Termination::Killed
+}
+
+/// The exec sessions of all containers
+pub struct ExecSessions {
+    sessions: Mutex<HashMap<String, ExecSession>>,
+    groups: Box<dyn ProcessGroups>,
+    grace: Duration,
+}
+
+impl ExecSessions {
+    pub fn new(groups: impl ProcessGroups + 'static, grace: Duration) -> Self {
+        Self { sessions: Mutex::new(HashMap::new()), groups: Box::new(groups), grace }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, ExecSession>> {
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Register a session; its `id` and `started_at` are filled in
+    pub fn register(&self, mut session: ExecSession) -> ExecSession {
+        session.id = uuid::Uuid::new_v4().simple().to_string()[..12].to_string();
+        session.started_at = Timestamp::now();
+        tracing::info!(
+            "Exec session {} started in container {}: {}",
+            session.id,
+            session.container,
+            shell_words::join(&session.command)
+        );
+        self.lock().insert(session.id.clone(), session.clone());
+        session
+    }
+
+    /// Register a session that ends when the returned guard is dropped, on
+    /// whichever path the command finishes
+    pub fn guard(self: &Arc<Self>, session: ExecSession) -> SessionGuard {
+        let session = self.register(session);
+        SessionGuard { sessions: Arc::clone(self), id: session.id }
+    }
+
+    /// Record the pid of a session's command once it is spawned
+    pub fn set_pid(&self, id: &str, pid: u32) {
+        if let Some(session) = self.lock().get_mut(id) {
+            session.pid = Some(pid);
+        }
+    }
+
+    /// Forget a session whose command finished
+    pub fn end(&self, id: &str) -> Option<ExecSession> {
+        let session = self.lock().remove(id);
+        if let Some(session) = &session {
+            tracing::debug!("Exec session {} in container {} ended", session.id, session.container);
+        }
+        session
+    }
+
+    pub fn get(&self, id: &str) -> Option<ExecSession> {
+        self.lock().get(id).cloned()
+    }
+
+    /// All sessions, or those of one container, oldest first
+    pub fn list(&self, container_id: Option<&str>) -> Vec<ExecSession> {
+        let mut sessions: Vec<ExecSession> = self
+            .lock()
+            .values()
+            .filter(|s| container_id.is_none_or(|id| s.container_id == id))
+            .cloned()
+            .collect();
+        sessions.sort_by(|a, b| (a.started_at, &a.id).cmp(&(b.started_at, &b.id)));
+        sessions
+    }
+
+    /// Kill a session (see [`terminate`]) and forget it; blocks for up to
+    /// the grace period
+    pub fn kill(&self, id: &str) -> Option<(ExecSession, Termination)> {
+        let session = self.get(id)?;
+        let termination = match session.pid {
+            Some(pid) => terminate(self.groups.as_ref(), pid, self.grace),
+            None => Termination::Gone,
+        };
+        tracing::info!(target: "kawakaze::audit", "Exec session {} in container {} killed ({:?})", session.id, session.container, termination);
+        self.end(id);
+        Some((session, termination))
+    }
+
+    /// Kill every session of a container, as it is about to stop; returns
+    /// the sessions that were killed
+    pub fn kill_container(&self, container_id: &str) -> Vec<ExecSession> {
+        self.list(Some(container_id))
+            .into_iter()
+            .filter_map(|session| self.kill(&session.id))
+            .map(|(session, _)| session)
+            .collect()
+    }
+
+    /// Drop the sessions whose process is gone without the session being
+    /// ended; returns how many were dropped
+    pub fn sweep(&self) -> usize {
+        let mut sessions = self.lock();
+        let before = sessions.len();
+        sessions.retain(|_, session| {
+            let alive = session.pid.is_none_or(|pid| self.groups.exists(pid));
+            if !alive {
+                tracing::warn!("Exec session {} in container {} ended without being closed; dropped", session.id, session.container);
+            }
+            alive
+        });
+        before - sessions.len()
+    }
+}
+
+impl Default for ExecSessions {
+    fn default() -> Self {
+        Self::new(HostProcessGroups, KILL_GRACE)
+    }
+}
+
+impl std::fmt::Debug for ExecSessions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ExecSessions").field("sessions", &self.lock().len()).finish_non_exhaustive()
+    }
+}
+
+/// Ends its session when dropped (see [`ExecSessions::guard`])
+pub struct SessionGuard {
+    sessions: Arc<ExecSessions>,
+    id: String,
+}
+
+impl SessionGuard {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Record the pid of the session's command
+    pub fn set_pid(&self, pid: u32) {
+        self.sessions.set_pid(&self.id, pid);
+    }
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        self.sessions.end(&self.id);
+    }
+}
+
+/// Login name of `uid` on this host
+pub fn user_name(uid: u32) -> Option<String> {
+    let mut buf = vec![0 as libc::c_char; 1024];
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let rc = unsafe { libc::getpwuid_r(uid, &mut pwd, buf.as_mut_ptr(), buf.len(), &mut result) };
+    if rc != 0 || result.is_null() {
+        return None;
+    }
+    let name = unsafe { std::ffi::CStr::from_ptr(pwd.pw_name) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    /// Process groups that exit after a number of signals, recording them
+    #[derive(Default)]
+    struct FakeGroups {
+        /// pid -> signals it takes to exit (0: ignores SIGTERM)
+        live: Mutex<HashMap<u32, usize>>,
+        sent: Mutex<Vec<(u32, i32)>>,
+        stubborn: HashSet<u32>,
+    }
+
+    impl FakeGroups {
+        fn with(pids: &[u32], stubborn: &[u32]) -> Arc<Self> {
+            Arc::new(Self {
+                live: Mutex::new(pids.iter().map(|pid| (*pid, 1)).collect()),
+                sent: Mutex::default(),
+                stubborn: stubborn.iter().copied().collect(),
+            })
+        }
+    }
+
+    impl ProcessGroups for Arc<FakeGroups> {
+        fn signal(&self, pid: u32, signal: i32) -> io::Result<()> {
+            self.sent.lock().unwrap().push((pid, signal));
+            let mut live = self.live.lock().unwrap();
+            if !live.contains_key(&pid) {
+                return Err(io::Error::from_raw_os_error(libc::ESRCH));
+            }
+            if signal == libc::SIGKILL || !self.stubborn.contains(&pid) {
+                live.remove(&pid);
+            }
+            Ok(())
+        }
+
+        fn exists(&self, pid: u32) -> bool {
+            self.live.lock().unwrap().contains_key(&pid)
+        }
+    }
+
+    fn session(container_id: &str, pid: Option<u32>) -> ExecSession {
+        ExecSession {
+            id: String::new(),
+            container_id: container_id.to_string(),
+            container: container_id.to_string(),
+            command: vec!["/bin/sh".to_string()],
+            kind: ExecKind::Api,
+            user: None,
+            peer_uid: Some(1001),
+            started_at: Timestamp::from_secs(0),
+            pid,
+        }
+    }
+
+    #[test]
+    fn test_guard_ends_session() {
+        let sessions = Arc::new(ExecSessions::new(FakeGroups::with(&[], &[]), Duration::ZERO));
+        let id = {
+            let guard = sessions.guard(session("web", None));
+            guard.set_pid(42);
+            assert_eq!(sessions.get(guard.id()).unwrap().pid, Some(42));
+            guard.id().to_string()
+        };
+        assert!(sessions.get(&id).is_none());
+
+        // A panicking exec still ends its session
+        let inner = Arc::clone(&sessions);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(move || {
+            let _guard = inner.guard(session("web", None));
+            panic!("exec failed");
+        }));
+        assert!(result.is_err());
+        assert!(sessions.list(None).is_empty());
+    }
+
+    #[test]
+    fn test_kill() {
+        let groups = FakeGroups::with(&[10, 20], &[20]);
+        let sessions = ExecSessions::new(Arc::clone(&groups), Duration::from_millis(20));
+        let polite = sessions.register(session("web", Some(10)));
+        let stubborn = sessions.register(session("web", Some(20)));
+        let unspawned = sessions.register(session("web", None));
+
+        assert_eq!(sessions.kill(&polite.id).unwrap().1, Termination::Terminated);
+        assert_eq!(sessions.kill(&stubborn.id).unwrap().1, Termination::Killed);
+        assert_eq!(sessions.kill(&unspawned.id).unwrap().1, Termination::Gone);
+        assert!(sessions.kill("nope").is_none());
+        assert!(sessions.list(None).is_empty());
+        assert_eq!(*groups.sent.lock().unwrap(), [(10, libc::SIGTERM), (20, libc::SIGTERM), (20, libc::SIGKILL)]);
+    }
+
+    #[test]
+    fn test_kill_container_and_list() {
+        let groups = FakeGroups::with(&[10, 20, 30], &[]);
+        let sessions = ExecSessions::new(Arc::clone(&groups), Duration::ZERO);
+        sessions.register(session("web", Some(10)));
+        sessions.register(session("db", Some(20)));
+        sessions.register(session("web", Some(30)));
+        assert_eq!(sessions.list(Some("web")).len(), 2);
+        assert_eq!(sessions.list(None).len(), 3);
+
+        let killed = sessions.kill_container("web");
+        assert_eq!(killed.len(), 2);
+        assert!(killed.iter().all(|s| s.container_id == "web"));
+        assert_eq!(sessions.list(None).iter().map(|s| s.container_id.as_str()).collect::<Vec<_>>(), ["db"]);
+        assert!(sessions.kill_container("web").is_empty());
+    }
+
+    #[test]
+    fn test_sweep_drops_leaked_sessions() {
+        let groups = FakeGroups::with(&[10], &[]);
+        let sessions = ExecSessions::new(Arc::clone(&groups), Duration::ZERO);
+        let live = sessions.register(session("web", Some(10)));
+        sessions.register(session("web", Some(99)));
+        let unspawned = sessions.register(session("web", None));
+
+        assert_eq!(sessions.sweep(), 1);
+        let left: HashSet<String> = sessions.list(None).into_iter().map(|s| s.id).collect();
+        assert_eq!(left, HashSet::from([live.id, unspawned.id]));
+        assert_eq!(sessions.sweep(), 0);
+    }
+
+    /// A shell leading its own group, reaped as soon as it exits
+    fn spawn_group(script: &str) -> (u32, std::thread::JoinHandle<std::process::ExitStatus>) {
+        use std::os::unix::process::CommandExt;
+        let mut child = std::process::Command::new("/bin/sh").args(["-c", script]).process_group(0).spawn().unwrap();
+        (child.id(), std::thread::spawn(move || child.wait().unwrap()))
+    }
+
+    #[test]
+    fn test_terminate_host_process_group() {
+        let (pid, reaper) = spawn_group("sleep 30 & wait");
+        assert!(HostProcessGroups.exists(pid));
+        assert_eq!(terminate(&HostProcessGroups, pid, Duration::from_secs(5)), Termination::Terminated);
+        assert!(!reaper.join().unwrap().success());
+        assert!(!HostProcessGroups.exists(pid));
+        assert_eq!(terminate(&HostProcessGroups, pid, Duration::ZERO), Termination::Gone);
+
+        // One that ignores SIGTERM is killed after the grace period
+        let (pid, reaper) = spawn_group("trap '' TERM; sleep 30 & wait");
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(terminate(&HostProcessGroups, pid, Duration::from_millis(200)), Termination::Killed);
+        assert!(!reaper.join().unwrap().success());
+    }
+}
@@ -0,0 +1,137 @@
+//! `--filter` predicates on the list endpoints
+//!
+//! GET /containers and GET /jails take a `filter` list in their bodies; an
+//! item is listed only when it matches every predicate. A predicate is
+//! `key=value`, matching the field exactly, or `key~=value`, matching when
+//! the field contains the value, ignoring case. A field that isn't set
+//! matches `key=` and nothing else. The keys are [`KEYS`]: `name`, `state`
+//! and `description`.
+//!
+//! Filters are parsed before anything is listed, so a typo in a key fails
+//! the request instead of silently matching nothing.
+
+/// Fields a predicate can test
+pub const KEYS: &[&str] = &["name", "state", "description"];
+
+/// How a predicate compares its field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    /// `key=value`: the field is the value
+    Equals,
+    /// `key~=value`: the field contains the value, ignoring case
+    Contains,
+}
+
+/// One parsed predicate
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filter {
+    key: String,
+    op: Op,
+    value: String,
+}
+
+impl Filter {
+    /// The field the predicate tests
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Whether a field of value `field` matches
+    pub fn matches(&self, field: Option<&str>) -> bool {
+        match (self.op, field) {
+            (Op::Equals, field) => field.unwrap_or("") == self.value,
+            (Op::Contains, Some(field)) => field.to_lowercase().contains(&self.value.to_lowercase()),
+            (Op::Contains, None) => false,
+        }
+    }
+}
+
+impl std::str::FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Invalid filter '{}': expected key=value or key~=value", s))?;
+        let (key, op) = match key.strip_suffix('~') {
+            Some(key) => (key, Op::Contains),
+            None => (key, Op::Equals),
+        };
+        if !KEYS.contains(&key) {
+            return Err(format!("Unknown filter key '{}' (known: {})", key, KEYS.join(", ")));
+        }
+        Ok(Self { key: key.to_string(), op, value: value.to_string() })
+    }
+}
+
+/// Parse every predicate of a request
+pub fn parse(filters: &[String]) -> Result<Vec<Filter>, String> {
+    filters.iter().map(|f| f.parse()).collect()
+}
+
+/// Whether an item matches every predicate, with `field` giving its value
+/// of a key
+pub fn matches_all<'a>(filters: &[Filter], field: impl Fn(&str) -> Option<&'a str>) -> bool {
+    filters.iter().all(|filter| filter.matches(field(filter.key())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(s: &str) -> Filter {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(filter("state=running"), Filter { key: "state".into(), op: Op::Equals, value: "running".into() });
+        assert_eq!(filter("description~=team-payments"), Filter { key: "description".into(), op: Op::Contains, value: "team-payments".into() });
+        // Only the first '=' separates
+        assert_eq!(filter("description~=a=b").value, "a=b");
+        assert_eq!(filter("name=").value, "");
+
+        assert!("description".parse::<Filter>().unwrap_err().contains("expected key=value"));
+        assert!("image=base".parse::<Filter>().unwrap_err().starts_with("Unknown filter key 'image'"));
+        assert!("~=x".parse::<Filter>().is_err());
+        assert!(parse(&["name=web".into(), "label=x".into()]).is_err());
+    }
+
+    #[test]
+    fn test_substring_match() {
+        let payments = filter("description~=Payments");
+        assert!(payments.matches(Some("owned by team-payments, decommission after Q3")));
+        assert!(payments.matches(Some("PAYMENTS")));
+        assert!(!payments.matches(Some("owned by team-search")));
+        assert!(!payments.matches(None));
+
+        // An empty substring matches any set field
+        assert!(filter("description~=").matches(Some("x")));
+        assert!(!filter("description~=").matches(None));
+    }
+
+    #[test]
+    fn test_exact_match() {
+        assert!(filter("state=running").matches(Some("running")));
+        assert!(!filter("state=running").matches(Some("Running")));
+        assert!(!filter("name=web").matches(Some("web-1")));
+        assert!(filter("description=").matches(None));
+        assert!(!filter("description=").matches(Some("notes")));
+    }
+
+    #[test]
+    fn test_matches_all() {
+        let filters = parse(&["state=running".into(), "description~=payments".into()]).unwrap();
+        let item = |state: &'static str, description: Option<&'static str>| {
+            move |key: &str| match key {
+                "state" => Some(state),
+                "description" => description,
+                _ => None,
+            }
+        };
+        assert!(matches_all(&filters, item("running", Some("team-payments"))));
+        assert!(!matches_all(&filters, item("stopped", Some("team-payments"))));
+        assert!(!matches_all(&filters, item("running", None)));
+        assert!(matches_all(&[], item("stopped", None)));
+    }
+}
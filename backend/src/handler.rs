@@ -6,48 +6,104 @@
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use crate::api::{
-    ApiError, BootstrapRequest, BuildImageRequest, ContainerInfo, ContainerListItem, CreateContainerRequest,
-    CreateJailRequest, Endpoint, ExecRequest, ExecResult, ImageHistoryItem, ImageInfo, ImageListItem,
-    JailInfo, JailListItem, Request, Response,
+    AdoptOrphanRequest, AdoptedOrphan, ApiError, BatchCreateRequest, BatchCreated, BootstrapRequest, BuildImageRequest, BuildStarted, ContainerInfo, ContainerListItem, CreateContainerRequest,
+    CreatedContainer,
+    CreateJailRequest, DestroyOrphanRequest, Endpoint, ExecRequest, ExecResult, ExecSessionKilled, ExecSessionRequest, ImageHistoryItem, ImageInfo, ImageListItem,
+    JailInfo, JailListItem, JailUpdateRequest, ListContainersRequest, ListJailsRequest, LogsRequest, MessageResult, OrphanInfo, PruneImagesRequest, PruneImagesResult, PruneSnapshotsRequest, PruneSnapshotsResult, SnapshotGcRequest, LogLevelRequest, LogLevelResult, RemoveRequest, RenameRequest, ContainerIpRequest, Request, Response, StopRequest, SystemConfigInfo, UpdateRequest, UpgradeRequest,
+    OperationStarted, UpgradeStarted, UploadChunk, UploadRequest,
+    status,
 };
-use crate::bootstrap::{Bootstrap, BootstrapConfig};
+use crate::bootstrap::{Bootstrap, BootstrapConfig, BootstrapStatus, MirrorBootstrapper};
 use crate::container::RestartPolicy;
+use crate::error_codes;
+use crate::exec_sessions::{ExecKind, ExecSession};
 use crate::image::Image;
+use crate::jail::StopMode;
 use crate::image_builder::ImageBuildProgress;
-use crate::JailManager;
+use crate::operation::{OperationEvent, OperationKind, Phase};
+use crate::orphans::OrphanError;
+use crate::timestamp::Timestamp;
+use crate::store::StoreError;
+use crate::stream::{ActiveStream, StreamRegistry, StreamSender};
+use tokio_util::sync::CancellationToken;
+use crate::upgrade::{UpgradeError, UpgradeTarget, ZfsUpgrader};
+use crate::upload::{UploadError, UploadStatus};
+use crate::{JailManager, ProgressKey, RemovalOptions};
 
 /// Handle an API request and return a response
+///
+/// `cancel` fires when the client that sent the request went away; work
+/// done only for that client (such as an exec) is stopped. Work the client
+/// only started, like a build, carries on.
 pub async fn handle_request(
     request: Request,
     manager: Arc<Mutex<JailManager>>,
+    cancel: CancellationToken,
 ) -> Response {
     // Parse the endpoint
     let endpoint = match request.parse_endpoint() {
         Ok(ep) => ep,
-        Err(err) => return Response::bad_request(err.message),
+        Err(err) => return Response::from(err),
     };
 
+    // Writes to an unhealthy pool would hang in zfs, so refuse them up front
+    if let Some(action) = crate::pool_health::gated_action(&request.method, &endpoint)
+        && let Some(reason) = manager.lock().await.pool_write_refusal(action)
+    {
+        return Response::error(status::SERVICE_UNAVAILABLE, ApiError::PoolUnhealthy(reason));
+    }
+
     // Route to appropriate handler based on endpoint and method
     match (&request.method, &endpoint) {
         // Jail endpoints
-        (crate::api::Method::Get, Endpoint::Jails) => list_jails(manager).await,
+        (crate::api::Method::Get, Endpoint::Jails) => {
+            let list_req = if request.body.is_null() {
+                Ok(ListJailsRequest::default())
+            } else {
+                serde_json::from_value::<ListJailsRequest>(request.body)
+            };
+            match list_req {
+                Ok(list_req) => list_jails(manager, list_req).await,
+                Err(err) => Response::invalid_body(err),
+            }
+        }
         (crate::api::Method::Get, Endpoint::Jail(name)) => get_jail(manager, name).await,
         (crate::api::Method::Get, Endpoint::BootstrapStatus(name)) => get_bootstrap_progress(manager, name).await,
+        (crate::api::Method::Get, Endpoint::BootstrapLog(name)) => match logs_request(request.body) {
+            Ok(logs_req) => bootstrap_log(manager, name, logs_req).await,
+            Err(resp) => resp,
+        },
         (crate::api::Method::Post, Endpoint::Jails) => {
             match serde_json::from_value::<CreateJailRequest>(request.body) {
                 Ok(create_req) => create_jail(manager, create_req).await,
-                Err(err) => Response::bad_request(format!("Invalid request body: {}", err)),
+                Err(err) => Response::invalid_body(err),
             }
         }
         (crate::api::Method::Post, Endpoint::StartJail(name)) => start_jail(manager, name).await,
-        (crate::api::Method::Post, Endpoint::StopJail(name)) => stop_jail(manager, name).await,
+        (crate::api::Method::Post, Endpoint::StopJail(name)) => match stop_request(request.body) {
+            Ok(stop_req) => stop_jail(manager, name, stop_req.stop_mode).await,
+            Err(resp) => resp,
+        },
         (crate::api::Method::Post, Endpoint::BootstrapJail(name)) => {
             match serde_json::from_value::<BootstrapRequest>(request.body) {
                 Ok(config) => bootstrap_jail(manager, name, config).await,
-                Err(err) => Response::bad_request(format!("Invalid request body: {}", err)),
+                Err(err) => Response::invalid_body(err),
             }
         }
         (crate::api::Method::Delete, Endpoint::Jail(name)) => delete_jail(manager, name).await,
+        (crate::api::Method::Post, Endpoint::JailUpgrade(name)) => match parse_upgrade_request(request.body) {
+            Ok(upgrade_req) => upgrade(manager, UpgradeTarget::Jail(name.clone()), upgrade_req).await,
+            Err(resp) => resp,
+        },
+        (crate::api::Method::Get, Endpoint::JailUpgradeStatus(name)) => {
+            get_upgrade_status(manager, UpgradeTarget::Jail(name.clone())).await
+        }
+        (crate::api::Method::Post, Endpoint::JailUpdate(name)) => {
+            match serde_json::from_value::<JailUpdateRequest>(request.body) {
+                Ok(update_req) => update_jail(manager, name, update_req).await,
+                Err(err) => Response::invalid_body(err),
+            }
+        }
 
         // Image endpoints
         (crate::api::Method::Get, Endpoint::Images) => list_images(manager).await,
@@ -55,48 +111,341 @@ pub async fn handle_request(
         (crate::api::Method::Post, Endpoint::ImageBuild) => {
             match serde_json::from_value::<BuildImageRequest>(request.body) {
                 Ok(build_req) => build_image(manager, build_req).await,
-                Err(err) => Response::bad_request(format!("Invalid request body: {}", err)),
+                Err(err) => Response::invalid_body(err),
+            }
+        }
+        (crate::api::Method::Get, Endpoint::ImageBuildStatus(build_id)) => get_build_status(manager, build_id).await,
+        (crate::api::Method::Delete, Endpoint::DeleteImage(id_or_name)) => {
+            match parse_remove_request(request.body) {
+                Ok((_, true)) => Response::bad_request("async removal is only supported for containers"),
+                Ok((remove_req, false)) => delete_image(manager, id_or_name, remove_req).await,
+                Err(resp) => resp,
             }
         }
-        (crate::api::Method::Delete, Endpoint::DeleteImage(id_or_name)) => delete_image(manager, id_or_name).await,
         (crate::api::Method::Get, Endpoint::ImageHistory(id_or_name)) => get_image_history(manager, id_or_name).await,
+        (crate::api::Method::Get, Endpoint::ImageContainers(id_or_name)) => image_containers(manager, id_or_name).await,
+        (crate::api::Method::Get, Endpoint::ImageBuildLog(id_or_name)) => match logs_request(request.body) {
+            Ok(logs_req) => image_build_log(manager, id_or_name, logs_req).await,
+            Err(resp) => resp,
+        },
+        (crate::api::Method::Post, Endpoint::PruneImages) => {
+            let prune_req = if request.body.is_null() {
+                Ok(PruneImagesRequest::default())
+            } else {
+                serde_json::from_value::<PruneImagesRequest>(request.body)
+            };
+            match prune_req {
+                Ok(prune_req) => prune_images(manager, prune_req).await,
+                Err(err) => Response::invalid_body(err),
+            }
+        }
+        (crate::api::Method::Post, Endpoint::ImageUpgrade(id_or_name)) => {
+            match (parse_upgrade_request(request.body), image_target(&manager, id_or_name).await) {
+                (Ok(upgrade_req), Ok(target)) => upgrade(manager, target, upgrade_req).await,
+                (Err(resp), _) | (_, Err(resp)) => resp,
+            }
+        }
+        (crate::api::Method::Get, Endpoint::ImageUpgradeStatus(id_or_name)) => {
+            match image_target(&manager, id_or_name).await {
+                Ok(target) => get_upgrade_status(manager, target).await,
+                Err(resp) => resp,
+            }
+        }
+        (crate::api::Method::Post, Endpoint::UpdateImage(id_or_name)) => {
+            match serde_json::from_value::<UpdateRequest>(request.body) {
+                Ok(update_req) => update_image(manager, id_or_name, update_req).await,
+                Err(err) => Response::invalid_body(err),
+            }
+        }
 
         // Container endpoints
-        (crate::api::Method::Get, Endpoint::Containers) => list_containers(manager).await,
+        (crate::api::Method::Get, Endpoint::Containers) => {
+            let list_req = if request.body.is_null() {
+                Ok(ListContainersRequest::default())
+            } else {
+                serde_json::from_value::<ListContainersRequest>(request.body)
+            };
+            match list_req {
+                Ok(list_req) => list_containers(manager, list_req).await,
+                Err(err) => Response::invalid_body(err),
+            }
+        }
         (crate::api::Method::Get, Endpoint::Container(id_or_name)) => get_container(manager, id_or_name).await,
         (crate::api::Method::Post, Endpoint::ContainerCreate) => {
             match serde_json::from_value::<CreateContainerRequest>(request.body) {
                 Ok(create_req) => create_container(manager, create_req).await,
-                Err(err) => Response::bad_request(format!("Invalid request body: {}", err)),
+                Err(err) => Response::invalid_body(err),
+            }
+        }
+        (crate::api::Method::Post, Endpoint::ContainerBatchCreate) => {
+            match serde_json::from_value::<BatchCreateRequest>(request.body) {
+                Ok(batch_req) => batch_create_containers(manager, batch_req).await,
+                Err(err) => Response::invalid_body(err),
             }
         }
         (crate::api::Method::Post, Endpoint::StartContainer(id_or_name)) => start_container(manager, id_or_name).await,
-        (crate::api::Method::Post, Endpoint::StopContainer(id_or_name)) => stop_container(manager, id_or_name).await,
+        (crate::api::Method::Post, Endpoint::StopContainer(id_or_name)) => match stop_request(request.body) {
+            Ok(stop_req) => {
+                let cause = crate::container::StopCause::UserRequest(request.peer_uid);
+                stop_container(manager, id_or_name, stop_req.stop_mode, cause).await
+            }
+            Err(resp) => resp,
+        },
+        (crate::api::Method::Get, Endpoint::ContainerLogs(id_or_name)) => match logs_request(request.body) {
+            Ok(logs_req) => container_logs(manager, id_or_name, logs_req).await,
+            Err(resp) => resp,
+        },
+        (crate::api::Method::Get, Endpoint::ContainerTop(id_or_name)) => container_top(manager, id_or_name).await,
+        (crate::api::Method::Get, Endpoint::ContainerExecSessions(id_or_name)) => container_exec_sessions(manager, id_or_name).await,
         (crate::api::Method::Post, Endpoint::ContainerExec(id_or_name)) => {
             match serde_json::from_value::<ExecRequest>(request.body) {
-                Ok(exec_req) => exec_container(manager, id_or_name, exec_req).await,
-                Err(err) => Response::bad_request(format!("Invalid request body: {}", err)),
+                Ok(exec_req) => exec_container(manager, id_or_name, exec_req, request.peer_uid, &cancel).await,
+                Err(err) => Response::invalid_body(err),
+            }
+        }
+        (crate::api::Method::Delete, Endpoint::RemoveContainer(id_or_name)) => {
+            match parse_remove_request(request.body) {
+                Ok((remove_req, run_async)) => remove_container(manager, id_or_name, remove_req, run_async).await,
+                Err(resp) => resp,
+            }
+        }
+        (crate::api::Method::Post, Endpoint::RenameContainer(id_or_name)) => {
+            match serde_json::from_value::<RenameRequest>(request.body) {
+                Ok(rename_req) => rename_container(manager, id_or_name, rename_req).await,
+                Err(err) => Response::invalid_body(err),
+            }
+        }
+        (crate::api::Method::Post, Endpoint::AddContainerIp(id_or_name)) => {
+            match serde_json::from_value::<ContainerIpRequest>(request.body) {
+                Ok(ip_req) => container_ip(manager, id_or_name, ip_req, true).await,
+                Err(err) => Response::invalid_body(err),
+            }
+        }
+        (crate::api::Method::Delete, Endpoint::RemoveContainerIp(id_or_name)) => {
+            match serde_json::from_value::<ContainerIpRequest>(request.body) {
+                Ok(ip_req) => container_ip(manager, id_or_name, ip_req, false).await,
+                Err(err) => Response::invalid_body(err),
+            }
+        }
+        (crate::api::Method::Post, Endpoint::UpdateContainer(id_or_name)) => {
+            match serde_json::from_value::<UpdateRequest>(request.body) {
+                Ok(update_req) => update_container(manager, id_or_name, update_req).await,
+                Err(err) => Response::invalid_body(err),
+            }
+        }
+
+        // System endpoints
+        (crate::api::Method::Get, Endpoint::SystemConfig) => get_system_config(manager).await,
+        (crate::api::Method::Get, Endpoint::SystemInfo) => get_system_info(manager).await,
+        (crate::api::Method::Get, Endpoint::SystemDoctor) => run_doctor(manager).await,
+        (crate::api::Method::Get, Endpoint::SystemBootList) => boot_list(manager).await,
+        (crate::api::Method::Get, Endpoint::SystemTasks) => list_tasks(manager).await,
+        (crate::api::Method::Get, Endpoint::SystemNames) => match serde_json::from_value::<crate::api::NamesRequest>(request.body) {
+            Ok(names_req) => list_names(manager, names_req.kind).await,
+            Err(err) => Response::invalid_body(err),
+        },
+        (crate::api::Method::Get, Endpoint::ApiSchema) => {
+            match Response::success(crate::api_schema::ApiDescription::generate()) {
+                Ok(resp) => resp,
+                Err(_) => Response::internal_error("Failed to serialize the API schema"),
+            }
+        }
+        (crate::api::Method::Post, Endpoint::CancelTask(id)) => cancel_task(manager, id).await,
+        (crate::api::Method::Get, Endpoint::Operation(id)) => get_operation(manager, id).await,
+        (crate::api::Method::Get, Endpoint::ExecSessions) => list_exec_sessions(manager).await,
+        (crate::api::Method::Post, Endpoint::ExecSessions) => {
+            match serde_json::from_value::<ExecSessionRequest>(request.body) {
+                Ok(session_req) => register_exec_session(manager, session_req, request.peer_uid).await,
+                Err(err) => Response::invalid_body(err),
+            }
+        }
+        (crate::api::Method::Delete, Endpoint::EndExecSession(id)) => end_exec_session(manager, id).await,
+        (crate::api::Method::Post, Endpoint::ExecSessionKill(id)) => kill_exec_session(manager, id).await,
+
+        // Upload endpoints
+        (crate::api::Method::Post, Endpoint::UploadCreate) => {
+            match serde_json::from_value::<UploadRequest>(request.body) {
+                Ok(upload_req) => create_upload(manager, upload_req).await,
+                Err(err) => Response::invalid_body(err),
+            }
+        }
+        (crate::api::Method::Get, Endpoint::UploadStatus(id)) => {
+            upload_response(manager.lock().await.uploads.status(id, std::time::Instant::now()))
+        }
+        (crate::api::Method::Post, Endpoint::UploadChunk(id)) => {
+            match serde_json::from_value::<UploadChunk>(request.body) {
+                Ok(chunk) => append_upload_chunk(manager, id, chunk).await,
+                Err(err) => Response::invalid_body(err),
+            }
+        }
+        (crate::api::Method::Post, Endpoint::UploadCommit(id)) => {
+            upload_response(manager.lock().await.uploads.commit(id, std::time::Instant::now()))
+        }
+        (crate::api::Method::Delete, Endpoint::UploadAbort(id)) => abort_upload(manager, id).await,
+        (crate::api::Method::Get, Endpoint::SystemOrphans) => list_orphans(manager).await,
+        (crate::api::Method::Get, Endpoint::CommandMetrics) => match Response::success(crate::cmdtrace::metrics()) {
+            Ok(resp) => resp,
+            Err(_) => Response::internal_error("Failed to serialize command metrics"),
+        },
+        (crate::api::Method::Post, Endpoint::OrphanAdopt) => {
+            match serde_json::from_value::<AdoptOrphanRequest>(request.body) {
+                Ok(adopt_req) => adopt_orphan(manager, adopt_req).await,
+                Err(err) => Response::invalid_body(err),
+            }
+        }
+        (crate::api::Method::Post, Endpoint::OrphanDestroy) => {
+            match serde_json::from_value::<DestroyOrphanRequest>(request.body) {
+                Ok(destroy_req) => destroy_orphan(manager, destroy_req).await,
+                Err(err) => Response::invalid_body(err),
+            }
+        }
+        (crate::api::Method::Post, Endpoint::PruneSnapshots) => {
+            match serde_json::from_value::<PruneSnapshotsRequest>(request.body) {
+                Ok(prune_req) => prune_snapshots(manager, prune_req).await,
+                Err(err) => Response::invalid_body(err),
+            }
+        }
+        (crate::api::Method::Post, Endpoint::SystemSnapshotGc) => {
+            match serde_json::from_value::<SnapshotGcRequest>(request.body) {
+                Ok(gc_req) => snapshot_gc(manager, gc_req).await,
+                Err(err) => Response::invalid_body(err),
+            }
+        }
+        (crate::api::Method::Post, Endpoint::SetLogLevel) => {
+            match serde_json::from_value::<LogLevelRequest>(request.body) {
+                Ok(level_req) => set_log_level(manager, level_req).await,
+                Err(err) => Response::invalid_body(err),
             }
         }
-        (crate::api::Method::Delete, Endpoint::RemoveContainer(id_or_name)) => remove_container(manager, id_or_name, false).await,
 
-        _ => Response::bad_request(format!(
+        // Streams are started by the server through `start_stream`
+        (_, endpoint) if endpoint.is_streaming() => {
+            Response::coded(error_codes::WRONG_ENDPOINT_KIND, format!("{} is a streaming endpoint", request.endpoint))
+        }
+
+        _ => Response::coded(error_codes::UNKNOWN_ENDPOINT, format!(
             "Method {:?} not supported for endpoint {}",
             request.method, request.endpoint
         )),
     }
 }
 
-/// List all jails
-async fn list_jails(manager: Arc<Mutex<JailManager>>) -> Response {
+/// Parse the optional body of a DELETE request into removal options
+fn parse_remove_request(body: serde_json::Value) -> Result<(RemovalOptions, bool), Response> {
+    let request = if body.is_null() {
+        RemoveRequest::default()
+    } else {
+        serde_json::from_value::<RemoveRequest>(body)
+            .map_err(Response::invalid_body)?
+    };
+
+    if request.override_protection && !request.force {
+        return Err(Response::bad_request("override_protection requires force"));
+    }
+
+    let options = RemovalOptions {
+        force: request.force,
+        override_protection: request.override_protection,
+    };
+    Ok((options, request.run_async))
+}
+
+/// Map a failed removal to a response, surfacing protection as 409
+fn removal_error(what: &str, err: StoreError) -> Response {
+    match err {
+        StoreError::ResourceProtected(msg) => {
+            Response::error(status::CONFLICT, ApiError::ResourceProtected(msg))
+        }
+        StoreError::VersionSkew(msg) => Response::error(status::CONFLICT, ApiError::VersionSkew(msg)),
+        e => Response::coded(error_codes::REMOVE_FAILED, format!("Failed to remove {}: {}", what, e)),
+    }
+}
+
+/// Map a failed settings update to a response, surfacing version skew as 409
+fn update_error(what: &str, err: StoreError) -> Response {
+    match err {
+        StoreError::VersionSkew(msg) => Response::error(status::CONFLICT, ApiError::VersionSkew(msg)),
+        StoreError::Unavailable(msg) => Response::error(status::SERVICE_UNAVAILABLE, ApiError::Unavailable(msg)),
+        e => Response::coded(error_codes::UPDATE_FAILED, format!("Failed to update {}: {}", what, e)),
+    }
+}
+
+/// Map an orphan handling error to a response
+fn orphan_error(err: OrphanError) -> Response {
+    match err {
+        OrphanError::NotListed(_) => Response::not_found(err.to_string()),
+        OrphanError::OutOfScope(_) | OrphanError::Invalid(..) => Response::bad_request(err.to_string()),
+        OrphanError::Conflict(..) => Response::conflict(err.to_string()),
+        OrphanError::Zfs(_) => Response::coded(error_codes::ZFS_ERROR, err.to_string()),
+        OrphanError::Store(_) => Response::internal_error(err.to_string()),
+    }
+}
+
+/// Map an upgrade error to a response
+fn upgrade_error(err: UpgradeError) -> Response {
+    match err {
+        UpgradeError::NotFound(resource) => Response::not_found(resource),
+        UpgradeError::Conflict(msg) => Response::conflict(msg),
+        UpgradeError::NotStarted(_) => Response::bad_request(err.to_string()),
+        UpgradeError::RolledBack { .. } | UpgradeError::RollbackFailed { .. } => {
+            Response::coded(error_codes::UPGRADE_FAILED, err.to_string())
+        }
+    }
+}
+
+/// Parse the optional body of an upgrade request
+fn stop_request(body: serde_json::Value) -> Result<StopRequest, Response> {
+    if body.is_null() {
+        return Ok(StopRequest::default());
+    }
+    serde_json::from_value(body).map_err(Response::invalid_body)
+}
+
+fn parse_upgrade_request(body: serde_json::Value) -> Result<UpgradeRequest, Response> {
+    if body.is_null() {
+        return Ok(UpgradeRequest::default());
+    }
+    serde_json::from_value::<UpgradeRequest>(body)
+        .map_err(Response::invalid_body)
+}
+
+/// Resolve an image ID, name or prefix to an upgrade target
+async fn image_target(manager: &Arc<Mutex<JailManager>>, id_or_name: &str) -> Result<UpgradeTarget, Response> {
+    let mgr = manager.lock().await;
+    mgr.get_image(&id_or_name.to_string())
+        .or_else(|| mgr.get_image_by_name(id_or_name))
+        .or_else(|| mgr.get_image_by_prefix(id_or_name))
+        .map(|image| UpgradeTarget::Image(image.id.clone()))
+        .ok_or_else(|| Response::image_not_found(id_or_name))
+}
+
+/// List the jails matching the request's filters
+async fn list_jails(manager: Arc<Mutex<JailManager>>, request: ListJailsRequest) -> Response {
+    let filters = match crate::filter::parse(&request.filter) {
+        Ok(filters) => filters,
+        Err(e) => return Response::bad_request(e),
+    };
+
     let mgr: tokio::sync::MutexGuard<'_, JailManager> = manager.lock().await;
     let jail_names = mgr.jail_names();
 
     let items: Vec<JailListItem> = jail_names
         .into_iter()
+        .filter(|name| {
+            mgr.get_jail(name).is_some_and(|jail| {
+                crate::filter::matches_all(&filters, |key| match key {
+                    "name" => Some(jail.name()),
+                    "state" => Some(jail.state().as_str()),
+                    "description" => jail.description(),
+                    _ => None,
+                })
+            })
+        })
         .map(|name| {
             if let Some(jail) = mgr.get_jail(&name) {
-                JailListItem::from((name, jail.state()))
+                let mut item = JailListItem::from((name, jail.state()));
+                item.readonly_reason = jail.readonly_reason().map(str::to_string);
+                item
             } else {
                 // This shouldn't happen, but handle it gracefully
                 JailListItem::from((name, crate::jail::JailState::Created))
@@ -122,7 +471,7 @@ async fn get_jail(manager: Arc<Mutex<JailManager>>, name: &str) -> Response {
                 Err(_) => Response::internal_error("Failed to serialize jail info"),
             }
         }
-        None => Response::not_found(format!("Jail '{}'", name)),
+        None => Response::jail_not_found(name),
     }
 }
 
@@ -130,43 +479,58 @@ async fn get_jail(manager: Arc<Mutex<JailManager>>, name: &str) -> Response {
 async fn create_jail(manager: Arc<Mutex<JailManager>>, request: CreateJailRequest) -> Response {
     // Validate the request
     if let Err(err) = request.validate() {
-        return Response::bad_request(err.message);
+        return Response::from(err);
     }
 
     let mut mgr: tokio::sync::MutexGuard<'_, JailManager> = manager.lock().await;
 
     // Check if jail already exists
     if mgr.get_jail(&request.name).is_some() {
-        return Response::conflict(format!("Jail '{}' already exists", request.name));
+        return Response::from(ApiError::JailAlreadyExists(request.name));
     }
 
     // Create the jail
     let jail = match crate::jail::Jail::create(&request.name) {
         Ok(jail) => jail,
-        Err(err) => {
-            let api_err: ApiError = err.into();
-            return match api_err.code.as_str() {
-                "BAD_REQUEST" => Response::bad_request(api_err.message),
-                "CONFLICT" => Response::conflict(api_err.message),
-                _ => Response::internal_error(api_err.message),
-            };
-        }
+        Err(err) => return Response::from(ApiError::from(err)),
     };
 
-    // Apply optional parameters
-    let mut jail = if let Some(ref path) = request.path {
-        match jail.with_path(path) {
-            Ok(j) => j,
-            Err(err) => {
-                let api_err: ApiError = err.into();
-                return Response::bad_request(api_err.message);
-            }
-        }
+    // Only rulesets the operator configured may be requested
+    let jail = match mgr.config.devfs.resolve(request.devfs_ruleset) {
+        Ok(ruleset) => jail.with_devfs_ruleset(ruleset),
+        Err(err) => return Response::bad_request(err.to_string()),
+    };
+
+    // Jails mount file systems themselves only where the operator allows it
+    if let Err(msg) = mgr.config.check_mount_permissions(request.mount_permissions) {
+        return Response::forbidden(msg);
+    }
+    let description = request.description.filter(|d| !d.is_empty());
+    let jail = jail.with_mount_permissions(request.mount_permissions).with_description(description.clone());
+
+    // Apply optional parameters; jails without a path get one under the
+    // jail base
+    let paths = mgr.paths();
+    let jail = if let Some(ref path) = request.path {
+        let cleanup_path = request.cleanup_path.unwrap_or(mgr.config.cleanup_jail_paths);
+        jail.with_path_within(path, &paths.jails)
+            .map(|j| j.with_cleanup_path(cleanup_path))
     } else {
-        jail
+        jail.with_path(paths.jail_root(&request.name))
+    };
+    let mut jail = match jail {
+        Ok(j) => j,
+        Err(err) => {
+            let api_err: ApiError = err.into();
+            return Response::bad_request(api_err.message);
+        }
     };
 
     if let Some(ref ip) = request.ip {
+        // An IP gives the jail its own network stack
+        if let Err(msg) = mgr.confinement().require_vnet() {
+            return Response::error(status::SERVICE_UNAVAILABLE, ApiError::Unavailable(msg));
+        }
         jail = match jail.with_ip(ip) {
             Ok(j) => j,
             Err(err) => {
@@ -188,6 +552,10 @@ async fn create_jail(manager: Arc<Mutex<JailManager>>, request: CreateJailReques
         jid: -1,
         state: "created".to_string(),
         path: request.path,
+        os_version: None,
+        readonly_reason: None,
+        mount_permissions: request.mount_permissions,
+        description,
     };
 
     match Response::created(jail_info) {
@@ -209,22 +577,15 @@ async fn start_jail(manager: Arc<Mutex<JailManager>>, name: &str) -> Response {
                 Err(_) => Response::internal_error("Failed to serialize jail info"),
             }
         }
-        Err(err) => {
-            let api_err: ApiError = err.into();
-            match api_err.code.as_str() {
-                "NOT_FOUND" => Response::not_found(api_err.message),
-                "BAD_REQUEST" => Response::bad_request(api_err.message),
-                _ => Response::internal_error(api_err.message),
-            }
-        }
+        Err(err) => Response::from(ApiError::from(err)),
     }
 }
 
 /// Stop a jail
-async fn stop_jail(manager: Arc<Mutex<JailManager>>, name: &str) -> Response {
+async fn stop_jail(manager: Arc<Mutex<JailManager>>, name: &str, mode: StopMode) -> Response {
     let mut mgr: tokio::sync::MutexGuard<'_, JailManager> = manager.lock().await;
 
-    match mgr.stop_jail(name) {
+    match mgr.stop_jail_with(name, mode) {
         Ok(()) => {
             let jail = mgr.get_jail(name).unwrap();
             let jail_info = JailInfo::from(jail.info());
@@ -233,14 +594,7 @@ async fn stop_jail(manager: Arc<Mutex<JailManager>>, name: &str) -> Response {
                 Err(_) => Response::internal_error("Failed to serialize jail info"),
             }
         }
-        Err(err) => {
-            let api_err: ApiError = err.into();
-            match api_err.code.as_str() {
-                "NOT_FOUND" => Response::not_found(api_err.message),
-                "BAD_REQUEST" => Response::bad_request(api_err.message),
-                _ => Response::internal_error(api_err.message),
-            }
-        }
+        Err(err) => Response::from(ApiError::from(err)),
     }
 }
 
@@ -250,19 +604,38 @@ async fn delete_jail(manager: Arc<Mutex<JailManager>>, name: &str) -> Response {
 
     match mgr.remove_jail(name) {
         Ok(()) => {
-            match Response::success(serde_json::json!({"message": format!("Jail '{}' deleted", name)})) {
+            match Response::success(MessageResult { message: format!("Jail '{}' deleted", name) }) {
                 Ok(resp) => resp,
                 Err(_) => Response::internal_error("Failed to serialize response"),
             }
         }
-        Err(err) => {
-            let api_err: ApiError = err.into();
-            match api_err.code.as_str() {
-                "NOT_FOUND" => Response::not_found(api_err.message),
-                "BAD_REQUEST" => Response::bad_request(api_err.message),
-                _ => Response::internal_error(api_err.message),
-            }
-        }
+        Err(err) => Response::from(ApiError::from(err)),
+    }
+}
+
+/// Change a jail's settings; fields the request leaves out are kept
+async fn update_jail(manager: Arc<Mutex<JailManager>>, name: &str, request: JailUpdateRequest) -> Response {
+    if let Some(ref description) = request.description
+        && let Err(e) = crate::api::check_description(description)
+    {
+        return Response::from(e);
+    }
+
+    let mut mgr: tokio::sync::MutexGuard<'_, JailManager> = manager.lock().await;
+    if mgr.get_jail(name).is_none() {
+        return Response::jail_not_found(name);
+    }
+
+    if let Some(description) = request.description
+        && let Err(err) = mgr.set_jail_description(name, Some(description).filter(|d| !d.is_empty()))
+    {
+        return Response::from(ApiError::from(err));
+    }
+
+    let jail_info = JailInfo::from(mgr.get_jail(name).unwrap().info());
+    match Response::success(jail_info) {
+        Ok(resp) => resp,
+        Err(_) => Response::internal_error("Failed to serialize jail info"),
     }
 }
 
@@ -273,20 +646,21 @@ async fn bootstrap_jail(
     config: BootstrapConfig,
 ) -> Response {
     // First check if jail exists
-    let jail_path = {
+    let (jail_path, bootstrap_log) = {
         let mgr = manager.lock().await;
+        let log = crate::build_log::bootstrap_log_file(&mgr.bootstrap_log_dir(), name);
         match mgr.get_jail(name) {
             Some(jail) => {
                 // Get the jail path
                 match jail.info().path {
-                    Some(ref p) => p.clone(),
+                    Some(ref p) => (p.clone(), log),
                     None => {
                         // Use default path
-                        format!("/tmp/{}", name)
+                        (mgr.paths().jail_root(name).display().to_string(), log)
                     }
                 }
             }
-            None => return Response::not_found(format!("Jail '{}'", name)),
+            None => return Response::jail_not_found(name),
         }
     };
 
@@ -297,51 +671,88 @@ async fn bootstrap_jail(
             name
         ));
     }
+    if let Err(e) = config.validate_sets() {
+        return Response::bad_request(e.to_string());
+    }
+    if manager.lock().await.bootstrap_queue.contains(name) {
+        return Response::coded(error_codes::IN_PROGRESS, format!("Jail '{}' is already being bootstrapped", name));
+    }
 
     // Start bootstrap in background
     let jail_name = name.to_string();
     let manager_clone = manager.clone();
     let jail_path_clone = jail_path.clone();
 
-    tokio::spawn(async move {
-        // Create progress channel
-        let (progress_tx, mut progress_rx) = tokio::sync::mpsc::channel(100);
+    // A new bootstrap starts a new log
+    if let Err(e) = crate::build_log::remove(&bootstrap_log) {
+        tracing::warn!("Failed to remove bootstrap log {}: {}", bootstrap_log.display(), e);
+    }
+    log_progress(&bootstrap_log, &format!("Bootstrapping jail '{}' at {}", name, jail_path));
 
-        // Store the progress sender in the manager
-        {
-            let mut mgr = manager_clone.lock().await;
-            mgr.register_bootstrap_tracker(jail_name.clone(), progress_tx.clone()).await;
-        }
+    // Create progress channel
+    let (progress_tx, mut progress_rx) = tokio::sync::mpsc::channel(100);
 
-        // Spawn a task to forward progress updates to the manager
-        let manager_for_progress = manager_clone.clone();
-        let jail_name_for_progress = jail_name.clone();
-        tokio::spawn(async move {
-            while let Some(progress) = progress_rx.recv().await {
-                let mut mgr = manager_for_progress.lock().await;
-                // Update the stored progress
-                if let Some(stored) = mgr.bootstrap_progress.get_mut(&jail_name_for_progress) {
-                    *stored = progress.clone();
+    // Store the progress sender in the manager and take a place in line, so
+    // bootstraps start in the order they were requested
+    let ticket = {
+        let mut mgr = manager.lock().await;
+        mgr.register_bootstrap_tracker(jail_name.clone(), progress_tx.clone()).await;
+        mgr.queue_bootstrap(&jail_name)
+    };
+
+    tokio::spawn(async move {
+        let result = async {
+            // Wait for a bootstrap slot; released when this block ends, panics included
+            let _slot = ticket.wait().await.map_err(|e| e.to_string())?;
+            manager_clone.lock().await.bootstrap_admitted(&jail_name);
+
+            // Spawn a task to forward progress updates to the manager
+            let manager_for_progress = manager_clone.clone();
+            let jail_name_for_progress = jail_name.clone();
+            let log_for_progress = bootstrap_log.clone();
+            tokio::spawn(async move {
+                let mut last_step = String::new();
+                while let Some(progress) = progress_rx.recv().await {
+                    // Download percentages repeat the step; log each step once
+                    if progress.current_step != last_step {
+                        log_progress(&log_for_progress, &progress.current_step);
+                        last_step = progress.current_step.clone();
+                    }
+                    let mut mgr = manager_for_progress.lock().await;
+                    // Update the stored progress
+                    if let Some(stored) = mgr.bootstrap_progress.get_mut(&jail_name_for_progress) {
+                        *stored = progress.clone();
+                    }
                 }
-            }
-        });
+            });
+
+            // Create and run bootstrap
+            let bootstrap = Bootstrap::new(&jail_path_clone, config, progress_tx)
+                .map_err(|e| format!("Failed to create bootstrap instance: {}", e))?;
+            bootstrap.run().await.map_err(|e| e.to_string())
+        }
+        .await;
 
-        // Create and run bootstrap
-        let bootstrap = match Bootstrap::new(&jail_path_clone, config, progress_tx) {
-            Ok(b) => b,
+        let mut mgr = manager_clone.lock().await;
+        match result {
+            Ok(()) => log_progress(&bootstrap_log, "Bootstrap complete"),
             Err(e) => {
-                tracing::error!("Failed to create bootstrap instance: {}", e);
-                return;
+                tracing::error!("Bootstrap failed for jail '{}': {}", jail_name, e);
+                log_progress(&bootstrap_log, &format!("Bootstrap failed: {}", e));
+                // Also covers failures before the bootstrap reported any progress
+                let _ = mgr.send_bootstrap_progress(&jail_name, BootstrapStatus::Failed(e)).await;
             }
-        };
-
-        if let Err(e) = bootstrap.run().await {
-            tracing::error!("Bootstrap failed for jail '{}': {}", jail_name, e);
         }
+        mgr.sweep_logs();
+
+        // Keep the final status around for a while, then forget it
+        let retention = mgr.finish_progress(ProgressKey::Bootstrap(jail_name.clone()));
+        drop(mgr);
+        JailManager::schedule_progress_prune(&manager_clone, retention);
     });
 
     // Return immediately with 202 Accepted
-    Response::error(202, ApiError::new("BOOTSTRAP_STARTED", format!("Bootstrap started for jail '{}'", name)))
+    Response::coded(error_codes::BOOTSTRAP_STARTED, format!("Bootstrap started for jail '{}'", name))
 }
 
 /// Get bootstrap progress for a jail
@@ -359,6 +770,80 @@ async fn get_bootstrap_progress(manager: Arc<Mutex<JailManager>>, name: &str) ->
     }
 }
 
+/// Start upgrading a stopped jail or an image in the background
+///
+/// Progress is polled through the matching upgrade status endpoint; a
+/// failed upgrade is rolled back to the pre-upgrade snapshot.
+async fn upgrade(manager: Arc<Mutex<JailManager>>, target: UpgradeTarget, request: UpgradeRequest) -> Response {
+    let (plan, zfs) = {
+        let mut mgr = manager.lock().await;
+        let plan = match &target {
+            UpgradeTarget::Jail(name) => mgr.plan_jail_upgrade(name, request.to),
+            UpgradeTarget::Image(id) => mgr.plan_image_upgrade(id, request.to),
+        };
+        let plan = match plan.and_then(|plan| mgr.begin_upgrade(&target).map(|()| plan)) {
+            Ok(plan) => plan,
+            Err(e) => return upgrade_error(e),
+        };
+        // Planning fails without ZFS, so it is always there
+        let Some(zfs) = mgr.zfs.clone() else {
+            return Response::coded(error_codes::UNAVAILABLE, "ZFS is not available");
+        };
+        (plan, zfs)
+    };
+
+    let snapshot = plan.snapshot();
+    let manager_clone = manager.clone();
+    let target_clone = target.clone();
+
+    tokio::spawn(async move {
+        let (progress_tx, mut progress_rx) = tokio::sync::mpsc::channel(16);
+
+        // Forward progress updates to the manager
+        let manager_for_progress = manager_clone.clone();
+        let target_for_progress = target_clone.clone();
+        let forwarder = tokio::spawn(async move {
+            while let Some(progress) = progress_rx.recv().await {
+                manager_for_progress.lock().await.set_upgrade_progress(&target_for_progress, progress);
+            }
+        });
+
+        let result = tokio::task::spawn_blocking(move || {
+            let upgrader = ZfsUpgrader { zfs };
+            crate::upgrade::run_upgrade(&upgrader, &plan, &mut |progress| {
+                let _ = progress_tx.blocking_send(progress.clone());
+            })
+        })
+        .await
+        .unwrap_or_else(|e| Err(UpgradeError::NotStarted(format!("upgrade task failed: {}", e))));
+
+        // Apply the final state only after every progress update has landed
+        let _ = forwarder.await;
+        manager_clone.lock().await.finish_upgrade(&target_clone, result);
+    });
+
+    let (kind, name) = match &target {
+        UpgradeTarget::Jail(name) => ("jail", name),
+        UpgradeTarget::Image(id) => ("image", id),
+    };
+    let mut response = Response::coded(error_codes::UPGRADE_STARTED, format!("Upgrade started for {} '{}'", kind, name));
+    response.data = serde_json::to_value(UpgradeStarted { snapshot }).ok();
+    response
+}
+
+/// Get the progress of a jail or image upgrade
+async fn get_upgrade_status(manager: Arc<Mutex<JailManager>>, target: UpgradeTarget) -> Response {
+    let mgr = manager.lock().await;
+
+    match mgr.upgrade_progress(&target) {
+        Some(progress) => match Response::success(progress) {
+            Ok(resp) => resp,
+            Err(_) => Response::internal_error("Failed to serialize upgrade progress"),
+        },
+        None => Response::not_found("Upgrade"),
+    }
+}
+
 // ============================================================================
 // Image Handlers
 // ============================================================================
@@ -372,9 +857,12 @@ async fn list_images(manager: Arc<Mutex<JailManager>>) -> Response {
         .into_iter()
         .map(|image| ImageListItem {
             id: image.id.clone(),
-            name: image.name.clone(),
+            name: image.display_name().to_string(),
             size_bytes: image.size_bytes,
             created_at: image.created_at,
+            protected: image.protected,
+            kind: image.kind.as_str().to_string(),
+            readonly_reason: image.readonly_reason().map(str::to_string),
         })
         .collect();
 
@@ -398,64 +886,93 @@ async fn get_image(manager: Arc<Mutex<JailManager>>, id_or_name: &str) -> Respon
         Some(image) => {
             let image_info = ImageInfo {
                 id: image.id.clone(),
-                name: image.name.clone(),
+                name: image.display_name().to_string(),
                 parent_id: image.parent_id.clone(),
                 size_bytes: image.size_bytes,
                 state: image.state.as_str().to_string(),
                 created_at: image.created_at,
+                protected: image.protected,
+                kind: image.kind.as_str().to_string(),
+                os_version: image.os_version.clone(),
+                digest: image.digest.clone(),
+                readonly_reason: image.readonly_reason().map(str::to_string),
+                zfs_properties: image
+                    .snapshot
+                    .split_once('@')
+                    .and_then(|(dataset, _)| mgr.inspect_properties(dataset)),
             };
             match Response::success(image_info) {
                 Ok(resp) => resp,
                 Err(_) => Response::internal_error("Failed to serialize image info"),
             }
         }
-        None => Response::not_found(format!("Image '{}'", id_or_name)),
+        None => Response::image_not_found(id_or_name),
     }
 }
 
 /// Build an image from a Dockerfile
 async fn build_image(manager: Arc<Mutex<JailManager>>, request: BuildImageRequest) -> Response {
-    // Validate request
-    if request.name.is_empty() {
-        return Response::bad_request("Image name cannot be empty");
-    }
-
+    // Validate request; the name is checked with the manager's images
     if request.dockerfile.is_empty() {
         return Response::bad_request("Dockerfile cannot be empty");
     }
 
-    let mut mgr = manager.lock().await;
-
-    // Check if image with this name already exists
-    if let Some(existing) = mgr.get_image_by_name(&request.name) {
-        if existing.is_available() {
-            return Response::conflict(format!("Image '{}' already exists", request.name));
+    for name in request.secrets.keys() {
+        if !crate::image_builder::is_valid_secret_name(name) {
+            return Response::coded(error_codes::INVALID_NAME, format!("Invalid secret name '{}'", name));
+        }
+        // A build arg of the same name would be substituted into the
+        // recorded instructions
+        if request.build_args.contains_key(name) {
+            return Response::bad_request(format!("'{}' is passed both as a secret and as a build arg", name));
         }
     }
 
-    // Check if ZFS is available
-    let zfs_pool_name = if mgr.zfs.is_some() {
-        mgr.config.zfs_pool.clone()
-    } else {
-        return Response::internal_error("ZFS not configured");
-    };
-
-    let base_dataset = format!("{}/images", zfs_pool_name);
+    let mut mgr = manager.lock().await;
 
-    // Store build args for background task
-    let build_args = request.build_args.clone();
+    match mgr.check_new_image_name(&request.name) {
+        Ok(()) => {}
+        Err(e @ crate::names::NameError::TagTaken { .. }) => return Response::coded(error_codes::NAME_IN_USE, e.to_string()),
+        Err(e) => return Response::coded(error_codes::INVALID_NAME, e.to_string()),
+    }
 
-    // Create ImageBuilder - note: we need to recreate Zfs in the background task
-    let _base_dataset_clone = base_dataset.clone();
+    // An existing image with this name is untagged once the build succeeds;
+    // only a concurrent build of the same name conflicts
+    if mgr.is_building(&request.name) {
+        return Response::coded(error_codes::IN_PROGRESS, format!("Image '{}' is already being built", request.name));
+    }
 
-    // Parse dockerfile to get FROM image
+    // Parse dockerfile to get FROM image. `freebsd:<version>` names a base
+    // system that is bootstrapped on demand. A base that is itself still
+    // building is either waited on (wait_for_base) or reported as a conflict.
+    let mut base_waiter = None;
+    let mut base_system = None;
     let from_image = match parse_from_instruction(&request.dockerfile) {
         Ok(from_name) => {
             // Handle "scratch" as a special case - no base image
             if from_name == "scratch" {
                 None
+            } else if let Some(config) = crate::bootstrap::parse_base_reference(&from_name) {
+                let resolved = config.resolve_version()
+                    .and_then(|version| Ok((version, config.resolve_architecture()?)));
+                match resolved {
+                    Ok(system) => base_system = Some(system),
+                    Err(e) => {
+                        return Response::bad_request(format!("Cannot resolve base image '{}': {}", from_name, e));
+                    }
+                }
+                None
             } else if let Some(img) = mgr.get_image_by_name(&from_name) {
                 Some(img.clone())
+            } else if let Some(waiter) = mgr.build_waiter(&from_name) {
+                if !request.wait_for_base {
+                    return Response::conflict(format!(
+                        "Base image '{}' is still building. Retry once it completes or set wait_for_base.",
+                        from_name
+                    ));
+                }
+                base_waiter = Some((from_name, waiter));
+                None
             } else {
                 return Response::bad_request(format!(
                     "Base image '{}' not found. Ensure the base image exists or build it first.",
@@ -463,21 +980,79 @@ async fn build_image(manager: Arc<Mutex<JailManager>>, request: BuildImageReques
                 ));
             }
         }
-        Err(_) => None, // No FROM instruction
+        // Comments only, or instructions before FROM: the build would fail
+        Err(_) => {
+            return Response::bad_request("Dockerfile must start with FROM (FROM scratch for an empty image)");
+        }
+    };
+
+    // Content address of this build. A base that is still building can't
+    // be addressed yet, and a Dockerfile that doesn't parse fails in the build.
+    let content_digest = match base_waiter {
+        Some(_) => None,
+        None => {
+            let base = match (&from_image, &base_system) {
+                (Some(image), _) => image.id.clone(),
+                (None, Some((version, architecture))) => format!("freebsd:{}-{}", version, architecture),
+                (None, None) => "scratch".to_string(),
+            };
+            let base_env = from_image.as_ref().map(|i| i.config.env.clone()).unwrap_or_default();
+            crate::image_builder::parse_dockerfile(&request.dockerfile, &request.build_args, &base_env, request.strict_vars)
+                .and_then(|instructions| {
+                    crate::image_builder::content_digest(
+                        &instructions,
+                        &base,
+                        std::path::Path::new(crate::image_builder::DEFAULT_BUILD_CONTEXT),
+                    )
+                })
+                .ok()
+        }
     };
 
-    // Generate image ID
-    let image_id = Image::generate_id();
+    // The image of this name was built from identical content: nothing to do
+    if !request.no_cache
+        && let Some(digest) = &content_digest
+        && let Some(existing) = mgr
+            .get_image_by_name(&request.name)
+            .filter(|image| image.is_available() && image.content_digest.as_ref() == Some(digest))
+    {
+        tracing::info!("Image '{}' is up to date ({})", request.name, digest);
+        return match Response::success(BuildStarted { id: existing.id.clone(), name: request.name.clone(), cached: true }) {
+            Ok(resp) => resp,
+            Err(_) => Response::internal_error("Failed to serialize build result"),
+        };
+    }
+
+    // Check if ZFS is available
+    if mgr.zfs.is_none() {
+        return Response::coded(error_codes::UNAVAILABLE, "ZFS not configured");
+    }
+
+    // Store build args for background task
+    let build_args = request.build_args.clone();
+
+    // Generate image ID; the builder gives it to the finished image
+    let image_id = match mgr.unique_id(Image::generate_id) {
+        Ok(id) => id,
+        Err(e) => return Response::internal_error(e.to_string()),
+    };
     let image_id_clone = image_id.clone();
     let name_clone = request.name.clone();
     let dockerfile_clone = request.dockerfile.clone();
-    let from_image_clone = from_image.clone();
     let build_args_clone = build_args.clone();
+    let protected = request.protected;
+    let strict_vars = request.strict_vars;
+    let secrets = request.secrets;
+    let network = request.network;
+    let no_cache = request.no_cache;
+    let api_socket = mgr.socket_path().clone();
+    let build_log = crate::build_log::build_log_file(&mgr.build_log_dir(), &image_id);
 
     // Create progress channel
     let (progress_tx, mut progress_rx) = tokio::sync::mpsc::channel(100);
 
     // Register progress tracker
+    mgr.begin_build(&request.name);
     mgr.image_build_tracker.insert(image_id.clone(), progress_tx.clone());
     mgr.image_build_progress.insert(
         image_id.clone(),
@@ -485,71 +1060,122 @@ async fn build_image(manager: Arc<Mutex<JailManager>>, request: BuildImageReques
             image_id: image_id.clone(),
             step: 0,
             total_steps: 0,
-            current_instruction: "Initializing...".to_string(),
+            current_instruction: match base_waiter {
+                Some((ref base, _)) => format!("Waiting for base image '{}'", base),
+                None => "Initializing...".to_string(),
+            },
             status: crate::image_builder::BuildStatus::Building,
+            copy: None,
         },
     );
+    // A build waiting for its base image queues once that is built
+    let ticket = base_waiter.is_none().then(|| mgr.queue_build(&image_id, &request.name));
+    drop(mgr);
+
+    log_progress(&build_log, &format!("Building image '{}' ({})", request.name, image_id));
+    if let Some((ref base, _)) = base_waiter {
+        log_progress(&build_log, &format!("Waiting for base image '{}'", base));
+    }
 
     // Clone manager for background task
     let manager_clone = manager.clone();
 
     // Spawn background build task
     tokio::spawn(async move {
-        // Create a new builder for the background task
-        let mgr_inner = manager_clone.lock().await;
-        let zfs_inner = match mgr_inner.zfs.as_ref() {
-            Some(_z) => {
-                // Create a new Zfs instance with the same pool
-                match crate::zfs::Zfs::new(&mgr_inner.config.zfs_pool) {
-                    Ok(z) => z,
-                    Err(_) => {
-                        let _ = progress_tx
-                            .send(ImageBuildProgress {
-                                image_id: image_id_clone.clone(),
-                                step: 0,
-                                total_steps: 0,
-                                current_instruction: "Failed to create ZFS instance".to_string(),
-                                status: crate::image_builder::BuildStatus::Failed,
-                            })
-                            .await;
-                        return;
+        let result = async {
+            // Queued behind the base image's build: wait for it to finish
+            // (successfully or not) and resolve it by name afterwards
+            let from_image = match base_waiter {
+                Some((base_name, mut waiter)) => {
+                    let _ = waiter.changed().await;
+                    let mgr_inner = manager_clone.lock().await;
+                    match mgr_inner.get_image_by_name(&base_name) {
+                        Some(img) => Some(img.clone()),
+                        None => return Err(format!("Base image '{}' failed to build", base_name)),
                     }
                 }
-            }
-            None => {
-                let _ = progress_tx
-                    .send(ImageBuildProgress {
-                        image_id: image_id_clone.clone(),
-                        step: 0,
-                        total_steps: 0,
-                        current_instruction: "ZFS not configured".to_string(),
-                        status: crate::image_builder::BuildStatus::Failed,
-                    })
-                    .await;
-                return;
-            }
-        };
+                None => from_image,
+            };
 
-        let base_dataset_inner = format!("{}/images", mgr_inner.config.zfs_pool);
-        drop(mgr_inner);
+            // Wait for a build slot; released when this block ends, panics included
+            let ticket = match ticket {
+                Some(ticket) => ticket,
+                None => manager_clone.lock().await.queue_build(&image_id_clone, &name_clone),
+            };
+            let _slot = ticket.wait().await.map_err(|e| e.to_string())?;
+            manager_clone.lock().await.build_admitted(&image_id_clone);
+
+            // Bootstrap (or reuse) the FreeBSD base system named by FROM
+            let base_snapshot = match base_system {
+                Some((version, architecture)) => Some(
+                    JailManager::ensure_base_image_shared(
+                        &manager_clone,
+                        &version,
+                        &architecture,
+                        &MirrorBootstrapper::default(),
+                    )
+                    .await
+                    .map_err(|e| e.to_string())?,
+                ),
+                None => None,
+            };
 
-        let (mut builder_inner, _rx) =
-            crate::image_builder::ImageBuilder::new(zfs_inner, base_dataset_inner);
+            // Create a new builder for the background task
+            let mgr_inner = manager_clone.lock().await;
+            let zfs_inner = match mgr_inner.zfs.as_ref() {
+                // Create a new Zfs instance with the same pool
+                Some(_z) => crate::zfs::Zfs::new(&mgr_inner.config.zfs_pool)
+                    .map_err(|_| "Failed to create ZFS instance".to_string())?,
+                None => return Err("ZFS not configured".to_string()),
+            };
 
-        // Set build args if provided
-        if !build_args_clone.is_empty() {
-            builder_inner = builder_inner.with_build_args(build_args_clone);
-        }
+            let base_dataset_inner = format!("{}/images", mgr_inner.config.zfs_pool);
+            let keep_snapshots = mgr_inner.config.storage.keep_snapshots;
+            let layer_snapshots = mgr_inner.config.storage.layer_snapshots;
+            let paths = mgr_inner.paths();
+            drop(mgr_inner);
+
+            let (builder_inner, _rx) =
+                crate::image_builder::ImageBuilder::new(zfs_inner, base_dataset_inner);
+            let mut builder_inner = builder_inner
+                .with_snapshot_retention(keep_snapshots)
+                .with_layer_snapshots(layer_snapshots)
+                .with_no_cache(no_cache)
+                .with_strict_vars(strict_vars)
+                .with_secrets(secrets)
+                .with_network(network)
+                .with_api_socket(api_socket)
+                .with_image_id(image_id_clone.clone())
+                .with_build_log(&build_log)
+                .with_paths(paths);
+
+            // Set build args if provided
+            if !build_args_clone.is_empty() {
+                builder_inner = builder_inner.with_build_args(build_args_clone);
+            }
+
+            if let Some(snapshot) = base_snapshot {
+                builder_inner = builder_inner.with_base_snapshot(snapshot);
+            }
 
-        let result = builder_inner
-            .build(name_clone.clone(), &dockerfile_clone, from_image_clone.as_ref())
-            .await;
+            builder_inner
+                .build(name_clone.clone(), &dockerfile_clone, from_image.as_ref())
+                .await
+                .map_err(|e| e.to_string())
+        }
+        .await;
 
+        let mut mgr_inner = manager_clone.lock().await;
         match result {
             Ok(image) => {
-                // Store image in manager
-                let mut mgr_inner = manager_clone.lock().await;
-                if let Err(e) = mgr_inner.add_image(image.clone()) {
+                log_progress(&build_log, "Build complete");
+                let mut image = image.with_protected(protected).with_build_log(build_log.display().to_string());
+                if let Some(digest) = content_digest {
+                    image = image.with_content_digest(digest);
+                }
+
+                // Store image in manager, moving the name onto it
+                if let Err(e) = mgr_inner.add_built_image(image.clone()) {
                     tracing::error!("Failed to store image in manager: {}", e);
                 }
 
@@ -562,14 +1188,15 @@ async fn build_image(manager: Arc<Mutex<JailManager>>, request: BuildImageReques
                         total_steps: image.dockerfile.len(),
                         current_instruction: "Build complete".to_string(),
                         status: crate::image_builder::BuildStatus::Complete,
+                        copy: None,
                     },
                 );
             }
             Err(e) => {
                 tracing::error!("Image build failed: {}", e);
+                log_progress(&build_log, &format!("Build failed: {}", e));
 
                 // Update progress to failed
-                let mut mgr_inner = manager_clone.lock().await;
                 mgr_inner.image_build_progress.insert(
                     image_id_clone.clone(),
                     ImageBuildProgress {
@@ -578,10 +1205,20 @@ async fn build_image(manager: Arc<Mutex<JailManager>>, request: BuildImageReques
                         total_steps: 0,
                         current_instruction: format!("Build failed: {}", e),
                         status: crate::image_builder::BuildStatus::Failed,
+                        copy: None,
                     },
                 );
             }
         }
+
+        // Release any builds queued behind this one
+        mgr_inner.finish_build(&name_clone);
+        mgr_inner.sweep_logs();
+
+        // Keep the final status around for a while, then forget it
+        let retention = mgr_inner.finish_progress(ProgressKey::Build(image_id_clone.clone()));
+        drop(mgr_inner);
+        JailManager::schedule_progress_prune(&manager_clone, retention);
     });
 
     // Spawn a task to forward progress updates to the manager
@@ -597,928 +1234,4539 @@ async fn build_image(manager: Arc<Mutex<JailManager>>, request: BuildImageReques
         }
     });
 
-    // Return immediately with 202 Accepted
-    Response::error(
+    // Return immediately with 202 Accepted; the build ID is also returned as
+    // data so clients can poll GET /images/build/{id}
+    let mut response = Response::error(
         202,
         ApiError::new(
             "BUILD_STARTED",
             format!(
-                "Image build started for '{}'. ID: {}. Use GET /images/{}/history to track progress.",
+                "Image build started for '{}'. ID: {}. Use GET /images/build/{} to track progress.",
                 request.name, image_id, image_id
             ),
         ),
-    )
+    );
+    response.data = serde_json::to_value(BuildStarted { id: image_id, name: request.name, cached: false }).ok();
+    response
 }
 
-/// Delete an image
-async fn delete_image(manager: Arc<Mutex<JailManager>>, id_or_name: &str) -> Response {
-    let mut mgr = manager.lock().await;
+/// Append a progress line to a build or bootstrap log
+fn log_progress(log: &std::path::Path, line: &str) {
+    if let Err(e) = crate::build_log::append_progress(log, line) {
+        tracing::warn!("Failed to write log {}: {}", log.display(), e);
+    }
+}
 
-    // Try to find the image (exact ID, name, or prefix)
-    let id_or_name_string = id_or_name.to_string();
-    let image_id = if let Some(image) = mgr.get_image(&id_or_name_string) {
-        image.id.clone()
-    } else if let Some(image) = mgr.get_image_by_name(id_or_name) {
-        image.id.clone()
-    } else if let Some(image) = mgr.get_image_by_prefix(id_or_name) {
-        image.id.clone()
-    } else {
-        return Response::not_found(format!("Image '{}'", id_or_name));
+/// Get the progress of an image build
+async fn get_build_status(manager: Arc<Mutex<JailManager>>, build_id: &str) -> Response {
+    let mgr = manager.lock().await;
+
+    match mgr.build_progress(build_id) {
+        Some(progress) => match Response::success(progress) {
+            Ok(resp) => resp,
+            Err(_) => Response::internal_error("Failed to serialize build progress"),
+        },
+        None => Response::not_found(format!("Build '{}'", build_id)),
+    }
+}
+
+/// List datasets under the containers/images roots with no record
+async fn list_orphans(manager: Arc<Mutex<JailManager>>) -> Response {
+    let orphans = match manager.lock().await.find_orphans() {
+        Ok(orphans) => orphans,
+        Err(e) => return orphan_error(e),
     };
 
-    match mgr.remove_image(&image_id) {
-        Ok(()) => {
-            match Response::success(serde_json::json!({"message": format!("Image '{}' deleted", id_or_name)})) {
-                Ok(resp) => resp,
-                Err(_) => Response::internal_error("Failed to serialize response"),
-            }
+    let items: Vec<OrphanInfo> = orphans
+        .into_iter()
+        .map(|orphan| OrphanInfo {
+            kind: orphan.kind.as_str().to_string(),
+            snapshot: orphan.latest_snapshot(),
+            dataset: orphan.dataset,
+            size_bytes: orphan.size_bytes,
+            created_at: orphan.created_at,
+        })
+        .collect();
+
+    match Response::success(items) {
+        Ok(resp) => resp,
+        Err(_) => Response::internal_error("Failed to serialize orphan list"),
+    }
+}
+
+/// Read a container's log, its syslog or both, optionally prefixing lines
+/// with timestamps
+async fn container_logs(manager: Arc<Mutex<JailManager>>, id_or_name: &str, request: LogsRequest) -> Response {
+    let (log_file, messages_file) = {
+        let mgr = manager.lock().await;
+        match find_container(&mgr, id_or_name) {
+            Ok(c) => (
+                crate::logs::container_log_file(std::path::Path::new(&mgr.config.storage.log_path), &c.id),
+                mgr.container_messages_file(c),
+            ),
+            Err(resp) => return resp,
         }
-        Err(e) => Response::internal_error(format!("Failed to delete image: {}", e)),
+    };
+
+    let read = |path: &std::path::Path, parse: crate::logs::Parser| match request.tail {
+        Some(lines) => crate::logs::read_tail_with(path, lines, parse),
+        None => crate::logs::read_with(path, parse),
+    };
+    let stdio = match request.source.includes_stdio() {
+        true => read(&log_file, crate::logs::parse_log),
+        false => Ok(Vec::new()),
+    };
+    let syslog = match request.source.includes_syslog() {
+        true => read(&messages_file, crate::syslog::parse_messages),
+        false => Ok(Vec::new()),
+    };
+    let entries = match (stdio, syslog) {
+        (Ok(stdio), Ok(syslog)) => crate::logs::merge(stdio, syslog),
+        (Err(e), _) | (_, Err(e)) => return Response::coded(error_codes::LOG_READ_FAILED, format!("Failed to read logs: {}", e)),
+    };
+
+    // Each source gave its last lines; of those, the last overall
+    let skip = request.tail.map_or(0, |lines| entries.len().saturating_sub(lines));
+    log_response(entries.into_iter().skip(skip).collect(), request.timestamps)
+}
+
+/// Body of a logs request; none means the defaults
+fn logs_request(body: serde_json::Value) -> Result<LogsRequest, Response> {
+    if body.is_null() {
+        return Ok(LogsRequest::default());
     }
+    serde_json::from_value(body).map_err(Response::invalid_body)
 }
 
-/// Get image history
-async fn get_image_history(manager: Arc<Mutex<JailManager>>, id_or_name: &str) -> Response {
-    let mgr = manager.lock().await;
+/// Answer with the lines of a log file, rendered as `request` asks
+fn read_log_response(log_file: &std::path::Path, request: &LogsRequest) -> Response {
+    let entries = match request.tail {
+        Some(lines) => crate::logs::read_log_tail(log_file, lines),
+        None => crate::logs::read_log(log_file),
+    };
+    match entries {
+        Ok(entries) => log_response(entries, request.timestamps),
+        Err(e) => Response::coded(error_codes::LOG_READ_FAILED, format!("Failed to read logs: {}", e)),
+    }
+}
 
-    // Try to find the image (exact ID, name, or prefix)
-    let id_or_name_string = id_or_name.to_string();
-    let image = mgr.get_image(&id_or_name_string)
+/// Answer with log lines, prefixed with their timestamps if asked
+fn log_response(entries: Vec<crate::logs::LogEntry>, timestamps: bool) -> Response {
+    let lines: Vec<crate::logs::LogEntry> = entries
+        .into_iter()
+        .map(|entry| crate::logs::LogEntry {
+            message: crate::logs::render_line(&entry, timestamps),
+            ..entry
+        })
+        .collect();
+
+    match Response::success(lines) {
+        Ok(resp) => resp,
+        Err(_) => Response::internal_error("Failed to serialize logs"),
+    }
+}
+
+/// Build ID and log file of an image's build. Builds that are running or
+/// failed have no image yet and are found by their build ID.
+fn find_build_log(mgr: &JailManager, id_or_name: &str) -> Result<(String, std::path::PathBuf), Response> {
+    let image = mgr.get_image(&id_or_name.to_string())
         .or_else(|| mgr.get_image_by_name(id_or_name))
         .or_else(|| mgr.get_image_by_prefix(id_or_name));
 
     match image {
-        Some(img) => {
-            // Convert Dockerfile instructions to history items
-            let history: Vec<ImageHistoryItem> = img
-                .dockerfile
-                .iter()
-                .enumerate()
-                .map(|(idx, instr)| {
-                    let created_by = match instr {
-                        crate::image::DockerfileInstruction::From(img) => format!("FROM {}", img),
-                        crate::image::DockerfileInstruction::Bootstrap { version, architecture, .. } => {
-                            format!("BOOTSTRAP {} {}", version.as_deref().unwrap_or("auto"), architecture.as_deref().unwrap_or("auto"))
-                        }
-                        crate::image::DockerfileInstruction::Run(cmd) => format!("RUN {}", cmd),
-                        crate::image::DockerfileInstruction::Copy { src, dest, .. } => {
-                            format!("COPY {} {}", src, dest)
-                        }
-                        crate::image::DockerfileInstruction::Add { src, dest } => {
-                            format!("ADD {} {}", src, dest)
-                        }
-                        crate::image::DockerfileInstruction::WorkDir(path) => {
-                            format!("WORKDIR {}", path)
-                        }
-                        crate::image::DockerfileInstruction::Env(env) => {
-                            format!("ENV {} vars", env.len())
-                        }
-                        crate::image::DockerfileInstruction::Expose(ports) => {
-                            format!("EXPOSE {:?}", ports)
-                        }
-                        crate::image::DockerfileInstruction::User(user) => format!("USER {}", user),
-                        crate::image::DockerfileInstruction::Volume(vols) => {
-                            format!("VOLUME {:?}", vols)
-                        }
-                        crate::image::DockerfileInstruction::Cmd(cmd) => format!("CMD {:?}", cmd),
-                        crate::image::DockerfileInstruction::Entrypoint(ep) => {
-                            format!("ENTRYPOINT {:?}", ep)
-                        }
-                        crate::image::DockerfileInstruction::Label(labels) => {
-                            format!("LABEL {} entries", labels.len())
-                        }
-                    };
-
-                    // Estimate layer size (in reality, each layer would have its own size)
-                    let layer_size = if !img.dockerfile.is_empty() {
-                        img.size_bytes / img.dockerfile.len() as u64
-                    } else {
-                        0
-                    };
+        Some(image) => match image.build_log {
+            Some(ref log) => Ok((image.id.clone(), std::path::PathBuf::from(log))),
+            None => Err(Response::not_found(format!("Build log of image '{}'", id_or_name))),
+        },
+        None if mgr.image_build_progress.contains_key(id_or_name) => {
+            Ok((id_or_name.to_string(), crate::build_log::build_log_file(&mgr.build_log_dir(), id_or_name)))
+        }
+        None => Err(Response::image_not_found(id_or_name)),
+    }
+}
 
-                    ImageHistoryItem {
-                        id: format!("{}-layer-{}", img.id, idx),
-                        created_at: img.created_at, // In reality, each layer would have its own timestamp
-                        size_bytes: layer_size,
-                        created_by,
-                    }
-                })
-                .collect();
+/// Get the log of an image's build
+async fn image_build_log(manager: Arc<Mutex<JailManager>>, id_or_name: &str, request: LogsRequest) -> Response {
+    let log_file = match find_build_log(&*manager.lock().await, id_or_name) {
+        Ok((_, log_file)) => log_file,
+        Err(resp) => return resp,
+    };
+    read_log_response(&log_file, &request)
+}
 
-            match Response::success(history) {
-                Ok(resp) => resp,
-                Err(_) => Response::internal_error("Failed to serialize image history"),
-            }
+/// Get the log of a jail's last bootstrap
+async fn bootstrap_log(manager: Arc<Mutex<JailManager>>, name: &str, request: LogsRequest) -> Response {
+    let log_file = {
+        let mgr = manager.lock().await;
+        if mgr.get_jail(name).is_none() {
+            return Response::jail_not_found(name);
         }
-        None => Response::not_found(format!("Image '{}'", id_or_name)),
+        crate::build_log::bootstrap_log_file(&mgr.bootstrap_log_dir(), name)
+    };
+    if !log_file.exists() {
+        return Response::not_found(format!("Bootstrap log of jail '{}'", name));
     }
+    read_log_response(&log_file, &request)
 }
 
-/// Helper: Parse the FROM instruction from a Dockerfile to get the base image name
-fn parse_from_instruction(dockerfile: &str) -> Result<String, &'static str> {
-    for line in dockerfile.lines() {
-        let line = line.trim();
-        if line.is_empty() || line.starts_with('#') {
-            continue;
+/// How often a followed log is checked for new lines
+const LOG_FOLLOW_INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
+
+/// Start the stream for a request to a streaming endpoint, or return the
+/// response refusing it
+pub async fn start_stream(
+    request: Request,
+    manager: Arc<Mutex<JailManager>>,
+    registry: &Arc<StreamRegistry>,
+    connection_id: u64,
+) -> Result<ActiveStream, Response> {
+    let endpoint = request.parse_endpoint().map_err(Response::from)?;
+
+    match (&request.method, &endpoint) {
+        (crate::api::Method::Get, Endpoint::ContainerLogsFollow(id_or_name)) => {
+            let logs_req = logs_request(request.body)?;
+
+            let (container_id, tails) = {
+                let mgr = manager.lock().await;
+                let container = find_container(&mgr, id_or_name)?;
+
+                let mut tails = Vec::new();
+                if logs_req.source.includes_stdio() {
+                    let log_file = crate::logs::container_log_file(std::path::Path::new(&mgr.config.storage.log_path), &container.id);
+                    tails.push(crate::logs::Tail::new(log_file, crate::logs::parse_log));
+                }
+                if logs_req.source.includes_syslog() {
+                    tails.push(crate::logs::Tail::new(mgr.container_messages_file(container), crate::syslog::parse_messages));
+                }
+                (container.id.clone(), tails)
+            };
+
+            let running = move |mgr: &JailManager| mgr.get_container(&container_id).is_some_and(|c| c.is_running());
+            Ok(registry.spawn(connection_id, endpoint.path(), move |sender| {
+                follow_logs(manager, tails, logs_req.timestamps, running, sender)
+            }))
+        }
+        (crate::api::Method::Get, Endpoint::ImageBuildLogFollow(id_or_name)) => {
+            let logs_req = logs_request(request.body)?;
+            let (build_id, log_file) = find_build_log(&*manager.lock().await, id_or_name)?;
+            let building = move |mgr: &JailManager| mgr.is_build_active(&build_id);
+            let tails = vec![crate::logs::Tail::new(log_file, crate::logs::parse_log)];
+            Ok(registry.spawn(connection_id, endpoint.path(), move |sender| {
+                follow_logs(manager, tails, logs_req.timestamps, building, sender)
+            }))
         }
+        _ => Err(Response::coded(error_codes::WRONG_ENDPOINT_KIND, format!("{} is not a streaming endpoint", request.endpoint))),
+    }
+}
 
-        let line_upper = line.to_uppercase();
-        if line_upper.starts_with("FROM ") {
-            let parts = line[5..].trim().split_whitespace().collect::<Vec<_>>();
-            if !parts.is_empty() {
-                return Ok(parts[0].to_string());
+/// Send logs, then each new line until `active` (a running container, a
+/// running build) no longer holds. Lines from several logs are merged by
+/// time within each poll.
+async fn follow_logs(
+    manager: Arc<Mutex<JailManager>>,
+    mut tails: Vec<crate::logs::Tail>,
+    timestamps: bool,
+    active: impl Fn(&JailManager) -> bool + Send + 'static,
+    sender: StreamSender,
+) -> Result<(), Response> {
+    loop {
+        // Checked before reading so the last lines written before the end
+        // are still sent
+        let running = active(&*manager.lock().await);
+
+        let mut entries = Vec::new();
+        for tail in &mut tails {
+            let new = tail.poll().map_err(|e| Response::coded(error_codes::LOG_READ_FAILED, format!("Failed to read logs: {}", e)))?;
+            entries = crate::logs::merge(entries, new);
+        }
+        for entry in entries {
+            let line = crate::logs::LogEntry { message: crate::logs::render_line(&entry, timestamps), ..entry };
+            if sender.send(line).await.is_err() {
+                return Ok(());
             }
         }
 
-        // Stop after first non-FROM instruction
-        if !line_upper.starts_with("FROM") && !line_upper.starts_with('#') {
-            break;
+        if !running {
+            return Ok(());
         }
+        tokio::time::sleep(LOG_FOLLOW_INTERVAL).await;
     }
-
-    Err("No FROM instruction found")
 }
 
-// ============================================================================
-// Container Handlers
-// ============================================================================
+/// List the processes running in a container
+async fn container_top(manager: Arc<Mutex<JailManager>>, id_or_name: &str) -> Response {
+    let mgr = manager.lock().await;
+    let container_id = match find_container(&mgr, id_or_name) {
+        Ok(c) if !c.is_running() => {
+            return Response::coded(error_codes::CONTAINER_NOT_RUNNING, format!("Container '{}' is not running", id_or_name));
+        }
+        Ok(c) => c.id.clone(),
+        Err(resp) => return resp,
+    };
 
-/// List all containers
-async fn list_containers(manager: Arc<Mutex<JailManager>>) -> Response {
+    match mgr.container_top(&container_id) {
+        Ok(processes) => match Response::success(processes) {
+            Ok(resp) => resp,
+            Err(_) => Response::internal_error("Failed to serialize process list"),
+        },
+        Err(e) => Response::coded(error_codes::EXEC_FAILED, format!("Failed to list processes: {}", e)),
+    }
+}
+
+/// List the exec sessions of a container
+async fn container_exec_sessions(manager: Arc<Mutex<JailManager>>, id_or_name: &str) -> Response {
     let mgr = manager.lock().await;
-    let containers = mgr.list_containers();
+    let container = match find_container(&mgr, id_or_name) {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
 
-    let items: Vec<ContainerListItem> = containers
-        .iter()
-        .map(|c| ContainerListItem {
-            id: c.id.clone(),
-            name: c.name.clone(),
-            image_id: c.image_id.clone(),
-            state: c.state.as_str().to_string(),
-            ip: c.ip.clone(),
-        })
-        .collect();
+    match Response::success(mgr.exec_sessions.list(Some(&container.id))) {
+        Ok(resp) => resp,
+        Err(_) => Response::internal_error("Failed to serialize exec sessions"),
+    }
+}
 
-    match Response::success(items) {
+/// List the exec sessions of every container
+async fn list_exec_sessions(manager: Arc<Mutex<JailManager>>) -> Response {
+    match Response::success(manager.lock().await.exec_sessions.list(None)) {
         Ok(resp) => resp,
-        Err(_) => Response::internal_error("Failed to serialize container list"),
+        Err(_) => Response::internal_error("Failed to serialize exec sessions"),
     }
 }
 
-/// Get container by ID, name, or prefix
-async fn get_container(manager: Arc<Mutex<JailManager>>, id_or_name: &str) -> Response {
+/// Register a session the CLI runs itself, on its own terminal
+async fn register_exec_session(manager: Arc<Mutex<JailManager>>, request: ExecSessionRequest, peer_uid: Option<u32>) -> Response {
     let mgr = manager.lock().await;
+    let container = match find_container(&mgr, &request.container) {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+    if !container.is_running() {
+        return Response::coded(error_codes::CONTAINER_NOT_RUNNING, format!("Container '{}' is not running", request.container));
+    }
+    if request.pid == 0 || !crate::exec_sessions::ProcessGroups::exists(&crate::exec_sessions::HostProcessGroups, request.pid) {
+        return Response::bad_request(format!("No process {} to register", request.pid));
+    }
 
-    // Try ID first, then prefix, then search by name
-    let id_or_name_string = id_or_name.to_string();
-    let container = mgr.get_container(&id_or_name_string)
-        .or_else(|| mgr.get_container_by_prefix(id_or_name))
-        .or_else(|| {
-            mgr.list_containers()
-                .into_iter()
-                .find(|c| c.name.as_deref() == Some(id_or_name))
-        });
+    let session = mgr.exec_sessions.register(ExecSession {
+        id: String::new(),
+        container_id: container.id.clone(),
+        container: container.display_name().to_string(),
+        command: request.command,
+        kind: request.kind,
+        user: peer_uid.and_then(crate::exec_sessions::user_name),
+        peer_uid,
+        started_at: Timestamp::now(),
+        pid: Some(request.pid),
+    });
+    match Response::created(session) {
+        Ok(resp) => resp,
+        Err(_) => Response::internal_error("Failed to serialize exec session"),
+    }
+}
 
-    match container {
-        Some(container) => {
-            let container_info = ContainerInfo {
-                id: container.id.clone(),
-                name: container.name.clone(),
-                image_id: container.image_id.clone(),
-                jail_name: container.jail_name.clone(),
-                state: container.state.as_str().to_string(),
-                ip: container.ip.clone(),
-                restart_policy: container.restart_policy.as_str().to_string(),
-                created_at: container.created_at,
-                started_at: container.started_at,
-            };
-            match Response::success(container_info) {
-                Ok(resp) => resp,
-                Err(_) => Response::internal_error("Failed to serialize container info"),
-            }
-        }
-        None => Response::not_found(format!("Container '{}'", id_or_name)),
+/// End a session the CLI registered, once its command exited
+async fn end_exec_session(manager: Arc<Mutex<JailManager>>, id: &str) -> Response {
+    match manager.lock().await.exec_sessions.end(id) {
+        Some(_) => match Response::success(MessageResult { message: format!("Exec session '{}' ended", id) }) {
+            Ok(resp) => resp,
+            Err(_) => Response::internal_error("Failed to serialize end result"),
+        },
+        None => Response::not_found(format!("Exec session '{}'", id)),
     }
 }
 
-/// Create container from image
-async fn create_container(manager: Arc<Mutex<JailManager>>, request: CreateContainerRequest) -> Response {
+/// Kill an exec session's processes; waits out the grace period without
+/// holding the manager
+async fn kill_exec_session(manager: Arc<Mutex<JailManager>>, id: &str) -> Response {
+    let sessions = manager.lock().await.exec_sessions();
+    let session_id = id.to_string();
+    match tokio::task::spawn_blocking(move || sessions.kill(&session_id)).await {
+        Ok(Some((session, termination))) => match Response::success(ExecSessionKilled { session, termination }) {
+            Ok(resp) => resp,
+            Err(_) => Response::internal_error("Failed to serialize kill result"),
+        },
+        Ok(None) => Response::not_found(format!("Exec session '{}'", id)),
+        Err(e) => Response::internal_error(format!("Killing exec session '{}' failed: {}", id, e)),
+    }
+}
+
+/// Re-register an orphaned dataset as a container or image
+async fn adopt_orphan(manager: Arc<Mutex<JailManager>>, request: AdoptOrphanRequest) -> Response {
     let mut mgr = manager.lock().await;
 
-    // Validate image exists (try exact ID, then name, then prefix)
-    let image = mgr.get_image(&request.image_id)
-        .or_else(|| mgr.get_image_by_name(&request.image_id))
-        .or_else(|| mgr.get_image_by_prefix(&request.image_id));
+    match mgr.adopt_orphan(&request.dataset, request.name, request.image.as_deref()) {
+        Ok(id) => match Response::created(AdoptedOrphan { id, dataset: request.dataset }) {
+            Ok(resp) => resp,
+            Err(_) => Response::internal_error("Failed to serialize response"),
+        },
+        Err(e) => orphan_error(e),
+    }
+}
 
-    if image.is_none() {
-        return Response::not_found(format!("Image '{}'", request.image_id));
+/// Destroy an orphaned dataset
+async fn destroy_orphan(manager: Arc<Mutex<JailManager>>, request: DestroyOrphanRequest) -> Response {
+    let mut mgr = manager.lock().await;
+
+    match mgr.destroy_orphan(&request.dataset) {
+        Ok(()) => match Response::success(MessageResult { message: format!("Dataset '{}' destroyed", request.dataset) }) {
+            Ok(resp) => resp,
+            Err(_) => Response::internal_error("Failed to serialize response"),
+        },
+        Err(e) => orphan_error(e),
     }
+}
 
-    // Parse restart policy
-    let restart_policy = match request.restart_policy.parse::<RestartPolicy>() {
-        Ok(policy) => policy,
-        Err(_) => {
-            return Response::bad_request(format!("Invalid restart policy: {}", request.restart_policy));
-        }
-    };
+/// Change the daemon's log filter until it restarts
+async fn set_log_level(manager: Arc<Mutex<JailManager>>, request: LogLevelRequest) -> Response {
+    use crate::log_level::LogLevelError;
 
-    // Convert API port mappings to internal format
-    let port_mappings: Vec<crate::container::PortMapping> = request.ports
-        .into_iter()
-        .map(|p| {
-            let protocol = match p.protocol.as_str() {
-                "tcp" => crate::container::PortProtocol::Tcp,
-                "udp" => crate::container::PortProtocol::Udp,
-                _ => crate::container::PortProtocol::Tcp,
-            };
-            crate::container::PortMapping::new(p.host_port, p.container_port, protocol)
-        })
-        .collect();
+    let mut mgr = manager.lock().await;
+    match mgr.set_log_level(&request.level) {
+        Ok(previous) => match Response::success(LogLevelResult { level: request.level.trim().to_string(), previous }) {
+            Ok(resp) => resp,
+            Err(_) => Response::internal_error("Failed to serialize response"),
+        },
+        Err(e @ LogLevelError::Invalid(_)) => Response::bad_request(e.to_string()),
+        Err(e @ LogLevelError::NotReloadable) => Response::conflict(e.to_string()),
+        Err(e @ LogLevelError::Reload(_)) => Response::coded(error_codes::UPDATE_FAILED, e.to_string()),
+    }
+}
 
-    // Convert API mounts to internal format
-    let mounts: Vec<crate::container::Mount> = request.volumes
-        .into_iter()
-        .map(|v| {
-            let mount_type = match v.mount_type.as_str() {
-                "zfs" => crate::container::MountType::Zfs,
-                "nullfs" => crate::container::MountType::Nullfs,
-                _ => crate::container::MountType::Nullfs,
-            };
-            crate::container::Mount::new(v.source, v.destination, mount_type, false)
-        })
-        .collect();
+/// Destroy all but the newest snapshots of a dataset below the pool root
+async fn prune_snapshots(manager: Arc<Mutex<JailManager>>, request: PruneSnapshotsRequest) -> Response {
+    let mgr = manager.lock().await;
 
-    // Create container config - use the resolved full image ID
-    let config = crate::container::ContainerConfig {
-        image_id: image.unwrap().id.clone(),
-        name: request.name.clone(),
-        ports: port_mappings,
-        volumes: mounts,
-        restart_policy,
-        command: request.command.clone(),
+    let root = format!("{}/", mgr.config.zfs_pool);
+    let in_scope = request.dataset.strip_prefix(&root).is_some_and(|rest| !rest.is_empty())
+        && !request.dataset.contains('@');
+    if !in_scope {
+        return Response::bad_request(format!(
+            "Dataset '{}' is not below {}",
+            request.dataset, mgr.config.zfs_pool
+        ));
+    }
+
+    let Some(zfs) = mgr.zfs.as_ref() else {
+        return Response::internal_error("ZFS is not available");
     };
 
-    match mgr.create_container(config) {
-        Ok(container) => {
-            let container_info = ContainerInfo {
-                id: container.id.clone(),
-                name: container.name.clone(),
-                image_id: container.image_id.clone(),
-                jail_name: container.jail_name.clone(),
-                state: container.state.as_str().to_string(),
-                ip: container.ip.clone(),
-                restart_policy: container.restart_policy.as_str().to_string(),
-                created_at: container.created_at,
-                started_at: container.started_at,
-            };
-            match Response::created(container_info) {
+    match zfs.prune_snapshots(&request.dataset, request.keep_last) {
+        Ok(destroyed) => {
+            if !destroyed.is_empty() {
+                tracing::warn!(target: "kawakaze::audit", "Pruned {} snapshot(s) of {}", destroyed.len(), request.dataset);
+            }
+            match Response::success(PruneSnapshotsResult { dataset: request.dataset, destroyed }) {
                 Ok(resp) => resp,
-                Err(_) => Response::internal_error("Failed to serialize container info"),
+                Err(_) => Response::internal_error("Failed to serialize prune result"),
             }
         }
-        Err(e) => Response::internal_error(format!("Failed to create container: {}", e)),
+        Err(crate::zfs::ZfsError::DatasetNotFound(_)) => Response::not_found(format!("Dataset '{}'", request.dataset)),
+        Err(e) => Response::coded(error_codes::ZFS_ERROR, format!("Failed to prune snapshots: {}", e)),
     }
 }
 
-/// Start container
-async fn start_container(manager: Arc<Mutex<JailManager>>, id_or_name: &str) -> Response {
-    let mut mgr = manager.lock().await;
+/// Destroy the snapshots under the containers/images roots that nothing
+/// references, or only list them with `dry_run`
+///
+/// The plan is taken under the lock; listing and destroying run without it.
+async fn snapshot_gc(manager: Arc<Mutex<JailManager>>, request: SnapshotGcRequest) -> Response {
+    let gc = {
+        let mgr = manager.lock().await;
+        if !request.dry_run
+            && let Some(reason) = mgr.pool_write_refusal("collect snapshots")
+        {
+            return Response::coded(error_codes::POOL_UNHEALTHY, reason);
+        }
+        match mgr.snapshot_gc() {
+            Some(gc) => gc,
+            None => return Response::coded(error_codes::UNAVAILABLE, "ZFS is not available"),
+        }
+    };
+
+    let now = chrono::Utc::now().timestamp();
+    match tokio::task::spawn_blocking(move || gc.run(request.dry_run, now)).await {
+        Ok(Ok(report)) => match Response::success(report) {
+            Ok(resp) => resp,
+            Err(_) => Response::internal_error("Failed to serialize snapshot collection report"),
+        },
+        Ok(Err(e)) => Response::coded(error_codes::ZFS_ERROR, format!("Failed to list snapshots: {}", e)),
+        Err(e) => Response::internal_error(format!("Snapshot collection task failed: {}", e)),
+    }
+}
+
+/// Report the running configuration and any drift from its file on disk
+async fn get_system_config(manager: Arc<Mutex<JailManager>>) -> Response {
+    let (config, source) = {
+        let mgr = manager.lock().await;
+        (mgr.config().clone(), mgr.config_source().clone())
+    };
+
+    let (drifted_fields, file_error) = match config.drift(&source) {
+        Ok(fields) => (fields, None),
+        Err(err) => (Vec::new(), Some(err.to_string())),
+    };
+
+    let info = SystemConfigInfo {
+        config: config.to_sanitized_json(),
+        path: source.path.as_ref().map(|p| p.display().to_string()),
+        loaded_mtime: source.mtime.map(Timestamp::from_secs),
+        drift: !drifted_fields.is_empty() || file_error.is_some(),
+        drifted_fields,
+        file_error,
+    };
+
+    match Response::success(info) {
+        Ok(resp) => resp,
+        Err(_) => Response::internal_error("Failed to serialize configuration"),
+    }
+}
+
+/// Get host and daemon state
+async fn get_system_info(manager: Arc<Mutex<JailManager>>) -> Response {
+    // Host facts don't need the manager, so read them before locking it
+    let host = crate::system::HostInfo::collect(&crate::system::CommandProbe);
+    let info = crate::system::system_info(&*manager.lock().await, host);
+
+    match Response::success(info) {
+        Ok(resp) => resp,
+        Err(_) => Response::internal_error("Failed to serialize system info"),
+    }
+}
+
+/// Run the host prerequisite checks
+async fn run_doctor(manager: Arc<Mutex<JailManager>>) -> Response {
+    // The checks shell out, so don't hold the manager while they run
+    let config = manager.lock().await.config().clone();
+    let ctx = crate::doctor::DoctorContext {
+        config: &config,
+        probe: &crate::system::CommandProbe,
+        freebsd: cfg!(target_os = "freebsd"),
+    };
+    let report = crate::doctor::run_checks(&ctx, &crate::doctor::default_checks());
+
+    match Response::success(report) {
+        Ok(resp) => resp,
+        Err(_) => Response::internal_error("Failed to serialize doctor report"),
+    }
+}
+
+/// List the builds and bootstraps running or waiting for a slot
+async fn list_tasks(manager: Arc<Mutex<JailManager>>) -> Response {
+    match Response::success(manager.lock().await.tasks()) {
+        Ok(resp) => resp,
+        Err(_) => Response::internal_error("Failed to serialize tasks"),
+    }
+}
+
+/// Names and short IDs for shell completion
+async fn list_names(manager: Arc<Mutex<JailManager>>, kind: crate::api::NameKind) -> Response {
+    match Response::success(manager.lock().await.completion_names(kind)) {
+        Ok(resp) => resp,
+        Err(_) => Response::internal_error("Failed to serialize names"),
+    }
+}
+
+/// Cancel a build or bootstrap that is still waiting for a slot
+async fn cancel_task(manager: Arc<Mutex<JailManager>>, id: &str) -> Response {
+    match manager.lock().await.cancel_task(id) {
+        Ok(()) => match Response::success(MessageResult { message: format!("Task '{}' cancelled", id) }) {
+            Ok(resp) => resp,
+            Err(_) => Response::internal_error("Failed to serialize cancel result"),
+        },
+        Err(e @ crate::task_queue::QueueError::AlreadyRunning(_)) => Response::coded(error_codes::IN_PROGRESS, e.to_string()),
+        Err(_) => Response::not_found(format!("Queued task '{}'", id)),
+    }
+}
+
+/// Announce a chunked upload, reserving its space in the spool
+async fn create_upload(manager: Arc<Mutex<JailManager>>, request: UploadRequest) -> Response {
+    let result = manager.lock().await.uploads.create(request.purpose, request.size, &request.sha256, std::time::Instant::now());
+    match result {
+        Ok(upload) => match Response::created(upload) {
+            Ok(resp) => resp,
+            Err(_) => Response::internal_error("Failed to serialize upload"),
+        },
+        Err(e) => upload_error(e),
+    }
+}
+
+/// Decode a chunk and append it to its upload
+async fn append_upload_chunk(manager: Arc<Mutex<JailManager>>, id: &str, chunk: UploadChunk) -> Response {
+    use base64::Engine;
+
+    let data = match base64::engine::general_purpose::STANDARD.decode(&chunk.data) {
+        Ok(data) => data,
+        Err(e) => return Response::bad_request(format!("Chunk data is not valid base64: {}", e)),
+    };
+    upload_response(manager.lock().await.uploads.append(id, chunk.offset, &data, std::time::Instant::now()))
+}
+
+/// Abort an upload and drop what was received of it
+async fn abort_upload(manager: Arc<Mutex<JailManager>>, id: &str) -> Response {
+    match manager.lock().await.uploads.abort(id) {
+        Ok(()) => match Response::success(MessageResult { message: format!("Upload '{}' aborted", id) }) {
+            Ok(resp) => resp,
+            Err(_) => Response::internal_error("Failed to serialize abort result"),
+        },
+        Err(e) => upload_error(e),
+    }
+}
+
+fn upload_response(result: Result<UploadStatus, UploadError>) -> Response {
+    match result {
+        Ok(upload) => match Response::success(upload) {
+            Ok(resp) => resp,
+            Err(_) => Response::internal_error("Failed to serialize upload"),
+        },
+        Err(e) => upload_error(e),
+    }
+}
+
+/// Map an upload error to a response; a chunk at the wrong offset is 409,
+/// after which the client asks where the upload is
+fn upload_error(err: UploadError) -> Response {
+    match err {
+        UploadError::NotFound(id) => Response::not_found(format!("Upload '{}'", id)),
+        UploadError::InvalidDigest(_)
+        | UploadError::TooLarge { .. }
+        | UploadError::ChunkTooLarge(_)
+        | UploadError::Overflow { .. }
+        | UploadError::DigestMismatch { .. } => Response::bad_request(err.to_string()),
+        UploadError::SpoolFull { .. }
+        | UploadError::OffsetMismatch { .. }
+        | UploadError::Incomplete { .. }
+        | UploadError::Committed(_)
+        | UploadError::NotCommitted(_)
+        | UploadError::WrongPurpose { .. } => Response::conflict(err.to_string()),
+        UploadError::Io(_) => Response::internal_error(err.to_string()),
+    }
+}
+
+/// List the boot containers and how the last boot went
+async fn boot_list(manager: Arc<Mutex<JailManager>>) -> Response {
+    let mgr = manager.lock().await;
+    let list = crate::api::BootList {
+        containers: mgr.boot_containers().into_iter().map(|c| mgr.container_list_item(c)).collect(),
+        last_boot: mgr.boot_report().cloned(),
+    };
+
+    match Response::success(list) {
+        Ok(resp) => resp,
+        Err(_) => Response::internal_error("Failed to serialize boot list"),
+    }
+}
+
+/// Delete an image
+async fn delete_image(manager: Arc<Mutex<JailManager>>, id_or_name: &str, options: RemovalOptions) -> Response {
+    let mut mgr = manager.lock().await;
 
-    // Find container by ID, name, or prefix
+    // Try to find the image (exact ID, name, or prefix)
     let id_or_name_string = id_or_name.to_string();
-    let container_id = if let Some(c) = mgr.get_container(&id_or_name_string) {
-        c.id.clone()
-    } else if let Some(c) = mgr.get_container_by_prefix(id_or_name) {
-        c.id.clone()
+    let image_id = if let Some(image) = mgr.get_image(&id_or_name_string) {
+        image.id.clone()
+    } else if let Some(image) = mgr.get_image_by_name(id_or_name) {
+        image.id.clone()
+    } else if let Some(image) = mgr.get_image_by_prefix(id_or_name) {
+        image.id.clone()
     } else {
-        match mgr.list_containers()
-            .into_iter()
-            .find(|c| c.name.as_deref() == Some(id_or_name))
-        {
-            Some(c) => c.id.clone(),
-            None => return Response::not_found(format!("Container '{}'", id_or_name)),
-        }
+        return Response::image_not_found(id_or_name);
     };
 
-    match mgr.start_container(&container_id) {
+    match mgr.remove_image_with(&image_id, &options) {
         Ok(()) => {
-            let container = mgr.get_container(&container_id).unwrap();
-            let container_info = ContainerInfo {
-                id: container.id.clone(),
-                name: container.name.clone(),
-                image_id: container.image_id.clone(),
-                jail_name: container.jail_name.clone(),
-                state: container.state.as_str().to_string(),
-                ip: container.ip.clone(),
-                restart_policy: container.restart_policy.as_str().to_string(),
-                created_at: container.created_at,
-                started_at: container.started_at,
-            };
-            match Response::success(container_info) {
+            match Response::success(MessageResult { message: format!("Image '{}' deleted", id_or_name) }) {
                 Ok(resp) => resp,
-                Err(_) => Response::internal_error("Failed to serialize container info"),
+                Err(_) => Response::internal_error("Failed to serialize response"),
             }
         }
-        Err(e) => Response::internal_error(format!("Failed to start container: {}", e)),
+        Err(e) => removal_error("image", e),
     }
 }
 
-/// Stop container
-async fn stop_container(manager: Arc<Mutex<JailManager>>, id_or_name: &str) -> Response {
+/// List the containers created from an image
+async fn image_containers(manager: Arc<Mutex<JailManager>>, id_or_name: &str) -> Response {
+    let mgr = manager.lock().await;
+
+    // Try to find the image (exact ID, name, or prefix)
+    let id_or_name_string = id_or_name.to_string();
+    let image = mgr.get_image(&id_or_name_string)
+        .or_else(|| mgr.get_image_by_name(id_or_name))
+        .or_else(|| mgr.get_image_by_prefix(id_or_name));
+
+    let Some(image) = image else {
+        return Response::image_not_found(id_or_name);
+    };
+
+    let items: Vec<ContainerListItem> = mgr.containers_for_image(&image.id).iter().map(|c| mgr.container_list_item(c)).collect();
+    match Response::success(items) {
+        Ok(resp) => resp,
+        Err(_) => Response::internal_error("Failed to serialize container list"),
+    }
+}
+
+/// Remove dangling (or, with `all`, every unused) image
+async fn prune_images(manager: Arc<Mutex<JailManager>>, request: PruneImagesRequest) -> Response {
+    let mut mgr = manager.lock().await;
+
+    let removed = match mgr.prune_images(request.all) {
+        Ok(removed) => removed,
+        Err(e) => return Response::coded(error_codes::REMOVE_FAILED, format!("Failed to prune images: {}", e)),
+    };
+
+    let result = PruneImagesResult {
+        reclaimed_bytes: removed.iter().map(|image| image.size_bytes).sum(),
+        removed: removed
+            .into_iter()
+            .map(|image| ImageListItem {
+                kind: image.kind.as_str().to_string(),
+                name: image.display_name().to_string(),
+                id: image.id,
+                size_bytes: image.size_bytes,
+                created_at: image.created_at,
+                protected: image.protected,
+                readonly_reason: None,
+            })
+            .collect(),
+    };
+
+    match Response::success(result) {
+        Ok(resp) => resp,
+        Err(_) => Response::internal_error("Failed to serialize prune result"),
+    }
+}
+
+/// Update image settings
+async fn update_image(manager: Arc<Mutex<JailManager>>, id_or_name: &str, request: UpdateRequest) -> Response {
+    if request.boot.is_some() {
+        return Response::bad_request("Only containers can be started at boot");
+    }
+    if request.read_only.is_some() {
+        return Response::bad_request("Only containers have a read-only root");
+    }
+    if request.description.is_some() {
+        return Response::bad_request("Only containers and jails have a description");
+    }
+
     let mut mgr = manager.lock().await;
 
-    // Find container by ID, name, or prefix
+    // Try to find the image (exact ID, name, or prefix)
     let id_or_name_string = id_or_name.to_string();
-    let container_id = if let Some(c) = mgr.get_container(&id_or_name_string) {
-        c.id.clone()
-    } else if let Some(c) = mgr.get_container_by_prefix(id_or_name) {
-        c.id.clone()
+    let image_id = if let Some(image) = mgr.get_image(&id_or_name_string) {
+        image.id.clone()
+    } else if let Some(image) = mgr.get_image_by_name(id_or_name) {
+        image.id.clone()
+    } else if let Some(image) = mgr.get_image_by_prefix(id_or_name) {
+        image.id.clone()
     } else {
-        match mgr.list_containers()
-            .into_iter()
-            .find(|c| c.name.as_deref() == Some(id_or_name))
-        {
-            Some(c) => c.id.clone(),
-            None => return Response::not_found(format!("Container '{}'", id_or_name)),
-        }
+        return Response::image_not_found(id_or_name);
     };
 
-    match mgr.stop_container(&container_id) {
-        Ok(()) => {
-            let container = mgr.get_container(&container_id).unwrap();
-            let container_info = ContainerInfo {
-                id: container.id.clone(),
-                name: container.name.clone(),
-                image_id: container.image_id.clone(),
-                jail_name: container.jail_name.clone(),
-                state: container.state.as_str().to_string(),
-                ip: container.ip.clone(),
-                restart_policy: container.restart_policy.as_str().to_string(),
-                created_at: container.created_at,
-                started_at: container.started_at,
-            };
-            match Response::success(container_info) {
+    if let Some(protected) = request.protected
+        && let Err(e) = mgr.set_image_protected(&image_id, protected)
+    {
+        return update_error("image", e);
+    }
+
+    let image = mgr.get_image(&image_id).unwrap();
+    let image_info = ImageInfo {
+        id: image.id.clone(),
+        name: image.display_name().to_string(),
+        parent_id: image.parent_id.clone(),
+        size_bytes: image.size_bytes,
+        state: image.state.as_str().to_string(),
+        created_at: image.created_at,
+        protected: image.protected,
+        kind: image.kind.as_str().to_string(),
+        os_version: image.os_version.clone(),
+        digest: image.digest.clone(),
+        readonly_reason: image.readonly_reason().map(str::to_string),
+        zfs_properties: None,
+    };
+    match Response::success(image_info) {
+        Ok(resp) => resp,
+        Err(_) => Response::internal_error("Failed to serialize image info"),
+    }
+}
+
+/// Get image history
+async fn get_image_history(manager: Arc<Mutex<JailManager>>, id_or_name: &str) -> Response {
+    let mgr = manager.lock().await;
+
+    // Try to find the image (exact ID, name, or prefix)
+    let id_or_name_string = id_or_name.to_string();
+    let image = mgr.get_image(&id_or_name_string)
+        .or_else(|| mgr.get_image_by_name(id_or_name))
+        .or_else(|| mgr.get_image_by_prefix(id_or_name));
+
+    match image {
+        Some(img) => {
+            // Convert Dockerfile instructions to history items
+            let history: Vec<ImageHistoryItem> = img
+                .dockerfile
+                .iter()
+                .enumerate()
+                .map(|(idx, instr)| {
+                    let created_by = match instr {
+                        crate::image::DockerfileInstruction::From(img) => format!("FROM {}", img),
+                        crate::image::DockerfileInstruction::Bootstrap { version, architecture, .. } => {
+                            format!("BOOTSTRAP {} {}", version.as_deref().unwrap_or("auto"), architecture.as_deref().unwrap_or("auto"))
+                        }
+                        crate::image::DockerfileInstruction::Run(cmd) => format!("RUN {}", cmd),
+                        crate::image::DockerfileInstruction::Copy { src, dest, .. } => {
+                            format!("COPY {} {}", src, dest)
+                        }
+                        crate::image::DockerfileInstruction::Add { src, dest, .. } => {
+                            format!("ADD {} {}", src, dest)
+                        }
+                        crate::image::DockerfileInstruction::WorkDir(path) => {
+                            format!("WORKDIR {}", path)
+                        }
+                        crate::image::DockerfileInstruction::Env(env) => {
+                            format!("ENV {} vars", env.len())
+                        }
+                        crate::image::DockerfileInstruction::Expose(ports) => {
+                            format!("EXPOSE {:?}", ports)
+                        }
+                        crate::image::DockerfileInstruction::User(user) => format!("USER {}", user),
+                        crate::image::DockerfileInstruction::Volume(vols) => {
+                            format!("VOLUME {:?}", vols)
+                        }
+                        crate::image::DockerfileInstruction::Cmd(cmd) => format!("CMD {:?}", cmd),
+                        crate::image::DockerfileInstruction::Entrypoint(ep) => {
+                            format!("ENTRYPOINT {:?}", ep)
+                        }
+                        crate::image::DockerfileInstruction::Label(labels) => {
+                            format!("LABEL {} entries", labels.len())
+                        }
+                    };
+
+                    ImageHistoryItem {
+                        id: format!("{}-layer-{}", img.id, idx),
+                        created_at: img.created_at, // In reality, each layer would have its own timestamp
+                        size_bytes: img.layer_size(),
+                        created_by,
+                    }
+                })
+                .collect();
+
+            match Response::success(history) {
                 Ok(resp) => resp,
-                Err(_) => Response::internal_error("Failed to serialize container info"),
+                Err(_) => Response::internal_error("Failed to serialize image history"),
             }
         }
-        Err(e) => Response::internal_error(format!("Failed to stop container: {}", e)),
+        None => Response::image_not_found(id_or_name),
     }
 }
 
-/// Remove container
-async fn remove_container(manager: Arc<Mutex<JailManager>>, id_or_name: &str, force: bool) -> Response {
-    let mut mgr = manager.lock().await;
+/// Helper: Parse the FROM instruction from a Dockerfile to get the base image name
+pub fn parse_from_instruction(dockerfile: &str) -> Result<String, &'static str> {
+    for line in dockerfile.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
 
-    // Find container by ID, name, or prefix
-    let id_or_name_string = id_or_name.to_string();
-    let container_id = if let Some(c) = mgr.get_container(&id_or_name_string) {
-        c.id.clone()
-    } else if let Some(c) = mgr.get_container_by_prefix(id_or_name) {
-        c.id.clone()
-    } else {
-        match mgr.list_containers()
-            .into_iter()
-            .find(|c| c.name.as_deref() == Some(id_or_name))
-        {
-            Some(c) => c.id.clone(),
-            None => return Response::not_found(format!("Container '{}'", id_or_name)),
+        let line_upper = line.to_uppercase();
+        if line_upper.starts_with("FROM ") {
+            let parts = line[5..].trim().split_whitespace().collect::<Vec<_>>();
+            if !parts.is_empty() {
+                return Ok(parts[0].to_string());
+            }
         }
-    };
 
-    // Check if container is running
-    if let Some(container) = mgr.get_container(&container_id) {
-        if container.is_running() && !force {
-            return Response::bad_request(format!(
-                "Container '{}' is running. Stop it first or use force flag.",
-                id_or_name
-            ));
+        // Stop after first non-FROM instruction
+        if !line_upper.starts_with("FROM") && !line_upper.starts_with('#') {
+            break;
         }
     }
 
-    match mgr.remove_container(&container_id) {
-        Ok(()) => {
-            match Response::success(serde_json::json!({"message": format!("Container '{}' removed", id_or_name)})) {
-                Ok(resp) => resp,
-                Err(_) => Response::internal_error("Failed to serialize response"),
+    Err("No FROM instruction found")
+}
+
+// ============================================================================
+// Container Handlers
+// ============================================================================
+
+/// List all containers
+async fn list_containers(manager: Arc<Mutex<JailManager>>, request: ListContainersRequest) -> Response {
+    let filters = match crate::filter::parse(&request.filter) {
+        Ok(filters) => filters,
+        Err(e) => return Response::bad_request(e),
+    };
+
+    let mgr = manager.lock().await;
+    let containers = mgr.list_containers();
+
+    // A container whose dataset is gone is listed, marked, like any other
+    let items: Vec<ContainerListItem> = containers
+        .iter()
+        .filter(|c| {
+            crate::filter::matches_all(&filters, |key| match key {
+                "name" => c.name.as_deref(),
+                "state" => Some(c.state.as_str()),
+                "description" => c.description.as_deref(),
+                _ => None,
+            })
+        })
+        .map(|c| mgr.container_list_item(c))
+        .collect();
+
+    let response = if request.include_summary {
+        Response::success(crate::api::ContainerListWithSummary {
+            containers: items,
+            summary: mgr.summarize_containers(),
+        })
+    } else {
+        Response::success(items)
+    };
+
+    match response {
+        Ok(resp) => resp,
+        Err(_) => Response::internal_error("Failed to serialize container list"),
+    }
+}
+
+/// The container `id_or_name` refers to, by exact ID, exact name, then
+/// unique ID prefix (see [`JailManager::resolve_container`])
+fn find_container<'a>(mgr: &'a JailManager, id_or_name: &str) -> Result<&'a crate::container::Container, Response> {
+    use crate::names::ResolveError;
+    match mgr.resolve_container(id_or_name) {
+        Ok(container) => Ok(container),
+        Err(e @ (ResolveError::NameOrPrefix { .. } | ResolveError::Ambiguous { .. })) => {
+            Err(Response::coded(error_codes::AMBIGUOUS_REFERENCE, e.to_string()))
+        }
+        Err(ResolveError::NotFound(_) | ResolveError::TooShort { .. }) => Err(Response::container_not_found(id_or_name)),
+    }
+}
+
+/// Resolve `id_or_name`, then take the container's operation lock and the
+/// manager (see [`crate::container_lock`])
+///
+/// The container is looked up again under the lock: the operation that
+/// held it before may have removed it.
+async fn lock_container<'a>(
+    manager: &'a Mutex<JailManager>,
+    id_or_name: &str,
+) -> Result<(crate::container_lock::ContainerGuard, tokio::sync::MutexGuard<'a, JailManager>, String), Response> {
+    let (id, locks) = {
+        let mgr = manager.lock().await;
+        (find_container(&mgr, id_or_name)?.id.clone(), mgr.container_locks.clone())
+    };
+    let guard = locks.lock(&id).await;
+    let mgr = manager.lock().await;
+    if mgr.get_container(&id).is_none() {
+        return Err(Response::container_not_found(id_or_name));
+    }
+    Ok((guard, mgr, id))
+}
+
+/// Get container by ID, name, or prefix
+async fn get_container(manager: Arc<Mutex<JailManager>>, id_or_name: &str) -> Response {
+    let mgr = manager.lock().await;
+
+    match find_container(&mgr, id_or_name) {
+        Ok(container) => {
+            let mut container_info = ContainerInfo::from(container);
+            container_info.env = mgr.container_env(container);
+            container_info.dataset_missing = mgr.dataset_missing(container);
+            if !container_info.dataset_missing {
+                container_info.size_bytes = mgr.zfs.as_ref().and_then(|zfs| zfs.get_used_space(&container.dataset).ok());
+                container_info.zfs_properties = mgr.inspect_properties(&container.dataset);
+            }
+            match Response::success(container_info) {
+                Ok(resp) => resp,
+                Err(_) => Response::internal_error("Failed to serialize container info"),
+            }
+        }
+        Err(resp) => resp,
+    }
+}
+
+/// Create container from image
+async fn create_container(manager: Arc<Mutex<JailManager>>, request: CreateContainerRequest) -> Response {
+    let mut mgr = manager.lock().await;
+    let run_async = request.run_async;
+
+    if !request.depends_on.is_empty() {
+        return Response::bad_request("depends_on is only supported by a batch create (POST /containers/batch)");
+    }
+    let config = match container_config(&mgr, request) {
+        Ok(config) => config,
+        Err(resp) => return resp,
+    };
+
+    if run_async {
+        let pending = match mgr.plan_container(config) {
+            Ok(pending) => pending,
+            Err(e) => return Response::coded(error_codes::CREATE_FAILED, format!("Failed to create container: {}", e)),
+        };
+        let name = pending.config.name.clone().unwrap_or_else(|| pending.id.clone());
+        let operation = mgr.begin_operation(OperationKind::Create, &pending.id, &name);
+        let zfs = mgr.zfs.clone();
+        drop(mgr);
+
+        let started = OperationStarted { id: operation.clone(), container_id: pending.id.clone() };
+        tokio::spawn(create_container_in_background(manager, operation, pending, zfs));
+        return operation_started(started, format!("Creating container '{}'", name));
+    }
+
+    match mgr.create_container(config) {
+        Ok(container) => {
+            match Response::created(crate::api::CreatedContainer::from(&container)) {
+                Ok(resp) => resp,
+                Err(_) => Response::internal_error("Failed to serialize container info"),
+            }
+        }
+        Err(e) => Response::coded(error_codes::CREATE_FAILED, format!("Failed to create container: {}", e)),
+    }
+}
+
+/// Create and start several containers, each after the containers it
+/// depends on
+///
+/// The order is worked out before anything is created, so cycles and
+/// unknown dependencies create nothing. Members are then created and
+/// started one at a time; a failure stops the batch and leaves the members
+/// already started in place. A dependency counts as ready once it is
+/// running: health isn't tracked by the daemon yet.
+async fn batch_create_containers(manager: Arc<Mutex<JailManager>>, request: BatchCreateRequest) -> Response {
+    let mut mgr = manager.lock().await;
+
+    if request.containers.iter().any(|c| c.run_async) {
+        return Response::bad_request("async is not supported in a batch create");
+    }
+
+    let members: Vec<(Option<&str>, &[String])> =
+        request.containers.iter().map(|c| (c.name.as_deref(), c.depends_on.as_slice())).collect();
+    let order = match crate::container::start_order(&members) {
+        Ok(order) => order,
+        Err(e) => return Response::bad_request(e),
+    };
+
+    // Dependencies outside the batch must be running already
+    for (name, depends_on) in &members {
+        for dependency in depends_on.iter().filter(|d| !members.iter().any(|(n, _)| *n == Some(d.as_str()))) {
+            if !dependency_running(&mgr, dependency) {
+                return Response::bad_request(format!(
+                    "Container '{}' depends on '{}', which is neither in the batch nor running",
+                    name.unwrap_or("(unnamed)"),
+                    dependency
+                ));
+            }
+        }
+    }
+
+    let mut requests: Vec<Option<CreateContainerRequest>> = request.containers.into_iter().map(Some).collect();
+    let mut started: Vec<CreatedContainer> = Vec::new();
+    for idx in order {
+        let Some(request) = requests[idx].take() else { continue };
+        let member = request.name.clone().unwrap_or_else(|| format!("#{}", idx + 1));
+
+        // Started earlier in the batch, but one may have exited since
+        if let Some(dependency) = request.depends_on.iter().find(|d| !dependency_running(&mgr, d)) {
+            let response = Response::coded(error_codes::CONTAINER_NOT_RUNNING, format!("dependency '{}' is not running", dependency));
+            return batch_failure(response, &member, &started);
+        }
+
+        let config = match container_config(&mgr, request) {
+            Ok(config) => config,
+            Err(response) => return batch_failure(response, &member, &started),
+        };
+        let id = match mgr.create_container(config) {
+            Ok(container) => container.id,
+            Err(e) => return batch_failure(Response::coded(error_codes::CREATE_FAILED, format!("Failed to create container: {}", e)), &member, &started),
+        };
+        match mgr.start_container(&id) {
+            Ok(()) => {}
+            Err(StoreError::PortInUse(msg)) => return batch_failure(Response::coded(error_codes::PORT_IN_USE, msg), &member, &started),
+            Err(StoreError::Unavailable(msg)) => {
+                let response = Response::error(status::SERVICE_UNAVAILABLE, ApiError::Unavailable(msg));
+                return batch_failure(response, &member, &started);
+            }
+            Err(e) => {
+                let response = Response::coded(error_codes::START_FAILED, format!("Created as {} but failed to start: {}", id, e));
+                return batch_failure(response, &member, &started);
+            }
+        }
+        if let Some(container) = mgr.get_container(&id) {
+            started.push(CreatedContainer::from(container));
+        }
+    }
+
+    match Response::created(BatchCreated { containers: started }) {
+        Ok(resp) => resp,
+        Err(_) => Response::internal_error("Failed to serialize batch result"),
+    }
+}
+
+/// Whether the container named `name` is running
+fn dependency_running(mgr: &JailManager, name: &str) -> bool {
+    mgr.list_containers().into_iter().any(|c| c.name.as_deref() == Some(name) && c.is_running())
+}
+
+/// The error of a failed batch member, naming the member and the members
+/// already started
+fn batch_failure(mut response: Response, member: &str, started: &[CreatedContainer]) -> Response {
+    if let Some(error) = response.error.as_mut() {
+        error.message = format!("Container '{}': {}", member, error.message);
+        if !started.is_empty() {
+            let names: Vec<&str> = started.iter().map(|c| c.container.name.as_deref().unwrap_or(&c.container.id)).collect();
+            error.message.push_str(&format!(" (already started: {})", names.join(", ")));
+        }
+    }
+    response
+}
+
+/// Check a create request and turn it into a container config
+fn container_config(mgr: &JailManager, request: CreateContainerRequest) -> Result<crate::container::ContainerConfig, Response> {
+    // Validate image exists (try exact ID, then name, then prefix)
+    let image = mgr.get_image(&request.image_id)
+        .or_else(|| mgr.get_image_by_name(&request.image_id))
+        .or_else(|| mgr.get_image_by_prefix(&request.image_id));
+
+    if image.is_none() {
+        return Err(Response::image_not_found(&request.image_id));
+    }
+
+    if let Some(ref name) = request.name
+        && let Err(e) = mgr.check_new_name(name)
+    {
+        return Err(Response::coded(error_codes::INVALID_NAME, e.to_string()));
+    }
+
+    // Every container shares the bridge, so aliases may not shadow any name on it
+    let others = mgr.list_containers().into_iter().map(|c| (c.display_name(), c.network_aliases.as_slice()));
+    match crate::hosts::check_aliases(request.name.as_deref(), &request.network_aliases, others) {
+        Ok(()) => {}
+        Err(e @ crate::hosts::AliasError::Invalid(_)) => return Err(Response::coded(error_codes::INVALID_NAME, e.to_string())),
+        Err(e @ crate::hosts::AliasError::Conflict { .. }) => return Err(Response::coded(error_codes::NAME_IN_USE, e.to_string())),
+    }
+
+    if let Some(ref hostname) = request.hostname
+        && !crate::hosts::is_valid_hostname(hostname)
+    {
+        return Err(Response::coded(error_codes::INVALID_NAME, format!("Invalid host name '{}': use letters, digits, '-' and '.'", hostname)));
+    }
+
+    // Parse restart policy
+    let restart_policy = match request.restart_policy.parse::<RestartPolicy>() {
+        Ok(policy) => policy,
+        Err(_) => {
+            return Err(Response::bad_request(format!("Invalid restart policy: {}", request.restart_policy)));
+        }
+    };
+
+    // Only rulesets the operator configured may be requested
+    if let Err(err) = mgr.config.devfs.resolve(request.devfs_ruleset) {
+        return Err(Response::bad_request(err.to_string()));
+    }
+    if let Err(msg) = mgr.config.check_mount_permissions(request.mount_permissions) {
+        return Err(Response::forbidden(msg));
+    }
+
+    // Convert API port mappings to internal format
+    let mut port_mappings: Vec<crate::container::PortMapping> = Vec::new();
+    for p in request.ports {
+        let protocol = match p.protocol.as_str() {
+            "tcp" => crate::container::PortProtocol::Tcp,
+            "udp" => crate::container::PortProtocol::Udp,
+            _ => crate::container::PortProtocol::Tcp,
+        };
+        let mut mapping = crate::container::PortMapping::new(p.host_port, p.container_port, protocol);
+        if let Some(host_ip) = p.host_ip {
+            match host_ip.trim_start_matches('[').trim_end_matches(']').parse() {
+                Ok(host_ip) => mapping = mapping.with_host_ip(host_ip),
+                Err(_) => return Err(Response::bad_request(format!("Invalid host address for port {}: '{}'", p.host_port, host_ip))),
+            }
+        }
+        port_mappings.push(mapping);
+    }
+
+    // Convert API mounts to internal format; nullfs sources must pass the
+    // mount policy and are recorded by their canonical path, and ZFS sources
+    // are existing datasets outside kawakaze's own
+    let policy = crate::mount_policy::MountPolicy::from_config(&mgr.config);
+    let mut mounts: Vec<crate::container::Mount> = Vec::new();
+    for v in request.volumes {
+        let mount_type = match v.mount_type.as_str() {
+            "zfs" => crate::container::MountType::Zfs,
+            "nullfs" => crate::container::MountType::Nullfs,
+            _ => crate::container::MountType::Nullfs,
+        };
+        let source = match mount_type {
+            crate::container::MountType::Nullfs if v.delegate => {
+                return Err(Response::bad_request(format!("Only a ZFS dataset can be delegated, not {}", v.source)));
+            }
+            crate::container::MountType::Nullfs => match policy.check_source(&v.source, v.read_only) {
+                Ok(resolved) => resolved.display().to_string(),
+                Err(e) => return Err(Response::bad_request(e.to_string())),
+            },
+            crate::container::MountType::Zfs => {
+                check_external_dataset(&mgr, &v, request.mount_permissions)?;
+                if mounts.iter().any(|m| m.mount_type == mount_type && m.source == v.source) {
+                    return Err(Response::bad_request(format!("Dataset {} is mounted twice", v.source)));
+                }
+                v.source
+            }
+        };
+        mounts.push(crate::container::Mount::new(source, v.destination, mount_type, v.read_only).with_delegate(v.delegate));
+    }
+    if let Err(e) = crate::container::check_tmpfs(&request.tmpfs, &mounts) {
+        return Err(Response::bad_request(e));
+    }
+    let env = request.env.into_iter().collect();
+    if let Err(e) = crate::container::check_env(&env) {
+        return Err(Response::bad_request(e));
+    }
+    if let Some(ref description) = request.description {
+        crate::api::check_description(description).map_err(Response::from)?;
+    }
+    let sysctls = request.sysctls.into_iter().collect();
+    crate::sysctl::Allowlist::from_config(&mgr.config.sysctl).check(&sysctls).map_err(Response::bad_request)?;
+    // Only this node runs containers for now, so it must satisfy them
+    if let Some(ref placement) = request.placement {
+        let constraints = placement.parse().map_err(|e| Response::bad_request(e.to_string()))?;
+        if let Some(reason) = crate::placement::unsatisfied(&constraints, mgr.node_labels()) {
+            return Err(Response::coded(error_codes::PLACEMENT_UNSATISFIABLE, reason));
+        }
+    }
+
+    // Create container config - use the resolved full image ID
+    Ok(crate::container::ContainerConfig {
+        image_id: image.unwrap().id.clone(),
+        name: request.name.clone(),
+        ports: port_mappings,
+        volumes: mounts,
+        tmpfs: request.tmpfs,
+        restart_policy,
+        command: request.command.clone(),
+        protected: request.protected,
+        devfs_ruleset: request.devfs_ruleset,
+        healthcheck_disabled: request.disable_healthcheck,
+        boot: request.boot,
+        encrypted: request.encrypted,
+        read_only_root: request.read_only_root,
+        network_aliases: request.network_aliases,
+        hostname: request.hostname,
+        mount_permissions: request.mount_permissions,
+        env,
+        placement: request.placement,
+        sysctls,
+        description: request.description.filter(|d| !d.is_empty()),
+    })
+}
+
+/// Check a ZFS volume's source: an existing dataset outside kawakaze's own,
+/// delegated only to a container allowed to mount ZFS
+fn check_external_dataset(mgr: &JailManager, volume: &crate::api::Mount, permissions: crate::jail::MountPermissions) -> Result<(), Response> {
+    crate::dataset_attach::check_source(&volume.source, &mgr.config.zfs_pool).map_err(Response::bad_request)?;
+    if volume.delegate && !permissions.allows_zfs() {
+        return Err(Response::bad_request(format!(
+            "Delegating {} needs mount permissions zfs or all, so the jail can mount it",
+            volume.source
+        )));
+    }
+    if volume.delegate && volume.read_only {
+        return Err(Response::bad_request(format!("{} can't be both delegated and read-only", volume.source)));
+    }
+    match mgr.zfs.as_ref().map(|zfs| zfs.dataset_presence(&volume.source)) {
+        None | Some(Ok(true)) => Ok(()),
+        Some(Ok(false)) => Err(Response::bad_request(format!("Dataset {} does not exist", volume.source))),
+        Some(Err(e)) => Err(Response::coded(error_codes::ZFS_ERROR, e.to_string())),
+    }
+}
+
+/// Start container
+async fn start_container(manager: Arc<Mutex<JailManager>>, id_or_name: &str) -> Response {
+    let (_operation, mut mgr, container_id) = match lock_container(&manager, id_or_name).await {
+        Ok(locked) => locked,
+        Err(resp) => return resp,
+    };
+
+    if let Some(container) = mgr.get_container(&container_id)
+        && container.state == crate::container::ContainerState::Locked
+    {
+        return Response::conflict(format!(
+            "Container '{}' is locked: the encryption key for {} is not loaded",
+            id_or_name, container.dataset
+        ));
+    }
+
+    match mgr.start_container(&container_id) {
+        Ok(()) => {
+            let container = mgr.get_container(&container_id).unwrap();
+            let container_info = ContainerInfo::from(container);
+            match Response::success(container_info) {
+                Ok(resp) => resp,
+                Err(_) => Response::internal_error("Failed to serialize container info"),
+            }
+        }
+        Err(StoreError::PortInUse(msg)) => Response::coded(error_codes::PORT_IN_USE, msg),
+        Err(StoreError::VersionSkew(msg)) => Response::error(status::CONFLICT, ApiError::VersionSkew(msg)),
+        Err(StoreError::Unavailable(msg)) => Response::error(status::SERVICE_UNAVAILABLE, ApiError::Unavailable(msg)),
+        Err(StoreError::DatasetMissing(msg)) => Response::error(status::CONFLICT, ApiError::DatasetMissing(msg)),
+        Err(e) => Response::coded(error_codes::START_FAILED, format!("Failed to start container: {}", e)),
+    }
+}
+
+/// Stop container
+async fn stop_container(manager: Arc<Mutex<JailManager>>, id_or_name: &str, mode: StopMode, cause: crate::container::StopCause) -> Response {
+    let (_operation, mut mgr, container_id) = match lock_container(&manager, id_or_name).await {
+        Ok(locked) => locked,
+        Err(resp) => return resp,
+    };
+
+    match mgr.stop_container_with(&container_id, mode, cause) {
+        Ok(()) => {
+            let container = mgr.get_container(&container_id).unwrap();
+            let container_info = ContainerInfo::from(container);
+            match Response::success(container_info) {
+                Ok(resp) => resp,
+                Err(_) => Response::internal_error("Failed to serialize container info"),
+            }
+        }
+        Err(StoreError::VersionSkew(msg)) => Response::error(status::CONFLICT, ApiError::VersionSkew(msg)),
+        Err(e) => Response::coded(error_codes::STOP_FAILED, format!("Failed to stop container: {}", e)),
+    }
+}
+
+/// Remove container
+async fn remove_container(manager: Arc<Mutex<JailManager>>, id_or_name: &str, options: RemovalOptions, run_async: bool) -> Response {
+    let (operation_lock, mut mgr, container_id) = match lock_container(&manager, id_or_name).await {
+        Ok(locked) => locked,
+        Err(resp) => return resp,
+    };
+
+    // Check if container is running
+    if let Some(container) = mgr.get_container(&container_id) {
+        if container.is_running() && !options.force {
+            return Response::coded(error_codes::CONTAINER_RUNNING, format!(
+                "Container '{}' is running. Stop it first or use force flag.",
+                id_or_name
+            ));
+        }
+    }
+
+    if run_async {
+        let name = mgr.get_container(&container_id).map(|c| c.display_name().to_string()).unwrap_or_default();
+        let teardown = match mgr.begin_container_removal(&container_id, &options) {
+            Ok(teardown) => teardown,
+            Err(e) => return removal_error("container", e),
+        };
+        // The jail is stopped and its network released by now
+        let operation = mgr.begin_operation(OperationKind::Remove, &container_id, &name);
+        mgr.update_operation(&operation, OperationEvent::Phase(Phase::Stopping, format!("Stopped {}", teardown.id)));
+        let zfs = mgr.zfs.clone();
+        drop(mgr);
+
+        let started = OperationStarted { id: operation.clone(), container_id };
+        tokio::spawn(remove_container_in_background(manager, operation, teardown, zfs, operation_lock));
+        return operation_started(started, format!("Removing container '{}'", id_or_name));
+    }
+
+    match mgr.remove_container_with(&container_id, &options) {
+        Ok(()) => {
+            match Response::success(MessageResult { message: format!("Container '{}' removed", id_or_name) }) {
+                Ok(resp) => resp,
+                Err(_) => Response::internal_error("Failed to serialize response"),
+            }
+        }
+        Err(e) => removal_error("container", e),
+    }
+}
+
+/// Answer 202 for an operation started in the background
+fn operation_started(started: OperationStarted, message: String) -> Response {
+    let mut response = Response::coded(error_codes::OPERATION_STARTED, message);
+    response.data = serde_json::to_value(started).ok();
+    response
+}
+
+/// Run the ZFS work of an operation on a blocking thread, forwarding what it
+/// reports to the manager as it goes
+async fn run_operation_work<T, F>(manager: &Arc<Mutex<JailManager>>, operation: &str, work: F) -> Result<T, String>
+where
+    T: Send + 'static,
+    F: FnOnce(&mut dyn FnMut(OperationEvent)) -> T + Send + 'static,
+{
+    let (progress_tx, mut progress_rx) = tokio::sync::mpsc::channel(16);
+
+    let manager_for_progress = manager.clone();
+    let operation_for_progress = operation.to_string();
+    let forwarder = tokio::spawn(async move {
+        while let Some(event) = progress_rx.recv().await {
+            manager_for_progress.lock().await.update_operation(&operation_for_progress, event);
+        }
+    });
+
+    let result = tokio::task::spawn_blocking(move || {
+        work(&mut |event| {
+            let _ = progress_tx.blocking_send(event);
+        })
+    })
+    .await
+    .map_err(|e| format!("operation task failed: {}", e));
+
+    // Finish only after every progress update has landed
+    let _ = forwarder.await;
+    result
+}
+
+/// Make a planned container's datasets off the lock, then give it its
+/// network and jail
+async fn create_container_in_background(
+    manager: Arc<Mutex<JailManager>>,
+    operation: String,
+    pending: crate::PendingContainer,
+    zfs: Option<crate::zfs::Zfs>,
+) {
+    let container_id = pending.id.clone();
+    let to_provision = pending.clone();
+    let provisioned = run_operation_work(&manager, &operation, move |report| {
+        JailManager::provision_container(zfs.as_ref(), &to_provision, &mut |phase, step| {
+            report(OperationEvent::Phase(phase, step.to_string()))
+        })
+    })
+    .await;
+
+    let mut mgr = manager.lock().await;
+    let result = match provisioned.and_then(|plan| plan.map_err(|e| e.to_string())) {
+        Ok(plan) => {
+            mgr.update_operation(&operation, OperationEvent::Phase(Phase::Configuring, "Configuring network and jail".to_string()));
+            mgr.finish_container(pending, plan).map(|_| ()).map_err(|e| e.to_string())
+        }
+        Err(e) => {
+            mgr.abandon_container(&container_id);
+            Err(e)
+        }
+    };
+    if let Err(ref e) = result {
+        tracing::error!("Creating container {} failed: {}", container_id, e);
+    }
+
+    let retention = mgr.finish_operation(&operation, result);
+    drop(mgr);
+    JailManager::schedule_progress_prune(&manager, retention);
+}
+
+/// Destroy a removed container's datasets off the lock, sampling the space
+/// they free, then delete its record
+async fn remove_container_in_background(
+    manager: Arc<Mutex<JailManager>>,
+    operation: String,
+    teardown: crate::ContainerTeardown,
+    zfs: Option<crate::zfs::Zfs>,
+    // Held until the record is gone
+    _operation_lock: crate::container_lock::ContainerGuard,
+) {
+    let container_id = teardown.id.clone();
+    let destroyed = run_operation_work(&manager, &operation, move |report| {
+        // Phases and samples both go to the one reporter
+        let report = std::cell::RefCell::new(report);
+        JailManager::destroy_container_datasets(
+            zfs.as_ref(),
+            &teardown,
+            &mut |phase, step| (report.borrow_mut())(OperationEvent::Phase(phase, step.to_string())),
+            &mut |remaining, total| (report.borrow_mut())(OperationEvent::Sample { remaining, total }),
+        );
+        teardown
+    })
+    .await;
+
+    let mut mgr = manager.lock().await;
+    let result = destroyed.and_then(|teardown| {
+        mgr.update_operation(&operation, OperationEvent::Phase(Phase::Recording, "Removing the container record".to_string()));
+        mgr.finish_container_removal(teardown).map_err(|e| e.to_string())
+    });
+    if let Err(ref e) = result {
+        tracing::error!("Removing container {} failed: {}", container_id, e);
+    }
+
+    let retention = mgr.finish_operation(&operation, result);
+    drop(mgr);
+    JailManager::schedule_progress_prune(&manager, retention);
+}
+
+/// Get the progress of an async container create or remove
+async fn get_operation(manager: Arc<Mutex<JailManager>>, id: &str) -> Response {
+    let mgr = manager.lock().await;
+
+    match mgr.operation(id) {
+        Some(progress) => match Response::success(progress) {
+            Ok(resp) => resp,
+            Err(_) => Response::internal_error("Failed to serialize operation progress"),
+        },
+        None => Response::not_found(format!("Operation '{}'", id)),
+    }
+}
+
+/// Update container settings
+async fn update_container(manager: Arc<Mutex<JailManager>>, id_or_name: &str, request: UpdateRequest) -> Response {
+    let mut mgr = manager.lock().await;
+
+    let container_id = match find_container(&mgr, id_or_name) {
+        Ok(c) => c.id.clone(),
+        Err(resp) => return resp,
+    };
+
+    if let Some(ref description) = request.description
+        && let Err(e) = crate::api::check_description(description)
+    {
+        return Response::from(e);
+    }
+
+    // The dataset's readonly property and tmpfs mounts are set up at start,
+    // so refuse before changing anything
+    if request.read_only.is_some() && mgr.get_container(&container_id).is_some_and(|c| c.is_running()) {
+        return Response::coded(error_codes::CONTAINER_RUNNING, format!("Container '{}' is running; stop it to change --read-only", id_or_name));
+    }
+
+    if let Some(protected) = request.protected
+        && let Err(e) = mgr.set_container_protected(&container_id, protected)
+    {
+        return update_error("container", e);
+    }
+
+    if let Some(boot) = request.boot
+        && let Err(e) = mgr.set_container_boot(&container_id, boot)
+    {
+        return update_error("container", e);
+    }
+
+    if let Some(read_only) = request.read_only
+        && let Err(e) = mgr.set_container_read_only_root(&container_id, read_only)
+    {
+        return update_error("container", e);
+    }
+
+    if let Some(description) = request.description
+        && let Err(e) = mgr.set_container_description(&container_id, Some(description).filter(|d| !d.is_empty()))
+    {
+        return update_error("container", e);
+    }
+
+    let container = mgr.get_container(&container_id).unwrap();
+    let container_info = ContainerInfo::from(container);
+    match Response::success(container_info) {
+        Ok(resp) => resp,
+        Err(_) => Response::internal_error("Failed to serialize container info"),
+    }
+}
+
+/// Rename a container; a host name that followed the old name follows the
+/// new one (see `JailManager::rename_container`)
+async fn rename_container(manager: Arc<Mutex<JailManager>>, id_or_name: &str, request: RenameRequest) -> Response {
+    let mut mgr = manager.lock().await;
+
+    let container = match find_container(&mgr, id_or_name) {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+    let container_id = container.id.clone();
+
+    if container.name.as_deref() != Some(request.name.as_str()) {
+        if let Err(e) = mgr.check_new_name(&request.name) {
+            return Response::coded(error_codes::INVALID_NAME, e.to_string());
+        }
+        let others: Vec<&crate::container::Container> = mgr.list_containers().into_iter().filter(|c| c.id != container_id).collect();
+        if others.iter().any(|c| c.name.as_deref() == Some(request.name.as_str())) {
+            return Response::coded(error_codes::NAME_IN_USE, format!("Container name '{}' is already in use", request.name));
+        }
+        // The new name joins the aliases on the network
+        let others = others.iter().map(|c| (c.display_name(), c.network_aliases.as_slice()));
+        if let Err(e) = crate::hosts::check_aliases(Some(&request.name), &container.network_aliases, others) {
+            return Response::coded(error_codes::NAME_IN_USE, e.to_string());
+        }
+
+        if let Err(e) = mgr.rename_container(&container_id, request.name) {
+            return update_error("container", e);
+        }
+    }
+
+    let container = mgr.get_container(&container_id).unwrap();
+    match Response::success(ContainerInfo::from(container)) {
+        Ok(resp) => resp,
+        Err(_) => Response::internal_error("Failed to serialize container info"),
+    }
+}
+
+/// Add (`add`) or remove an `ip4.addr` alias of a container's jail
+async fn container_ip(manager: Arc<Mutex<JailManager>>, id_or_name: &str, request: ContainerIpRequest, add: bool) -> Response {
+    let mut mgr = manager.lock().await;
+
+    let container = match find_container(&mgr, id_or_name) {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+    let container_id = container.id.clone();
+
+    let ip: std::net::IpAddr = match request.ip.parse() {
+        Ok(ip) => ip,
+        Err(_) => return Response::bad_request(format!("Invalid IP address: '{}'", request.ip)),
+    };
+    let ip_string = ip.to_string();
+
+    let result = if add {
+        if container.ip.is_some() || container.ip6.is_some() {
+            return Response::bad_request(format!(
+                "Container '{}' has its own network stack (VNET); aliases are for jails that share the host's",
+                id_or_name
+            ));
+        }
+        let owner = mgr.list_containers().into_iter()
+            .find(|c| c.addresses().contains(&ip_string) || c.ip_aliases.contains(&ip_string));
+        if let Some(owner) = owner {
+            return Response::coded(error_codes::ADDRESS_IN_USE, format!("Address {} is already used by container '{}'", ip, owner.display_name()));
+        }
+        mgr.add_container_ip(&container_id, ip)
+    } else {
+        if !container.ip_aliases.contains(&ip_string) {
+            return Response::not_found(format!("Address {} on container '{}'", ip, id_or_name));
+        }
+        mgr.remove_container_ip(&container_id, ip)
+    };
+    if let Err(e) = result {
+        return update_error("container", e);
+    }
+
+    let container = mgr.get_container(&container_id).unwrap();
+    match Response::success(ContainerInfo::from(container)) {
+        Ok(resp) => resp,
+        Err(_) => Response::internal_error("Failed to serialize container info"),
+    }
+}
+
+/// Execute command in container
+async fn exec_container(
+    manager: Arc<Mutex<JailManager>>,
+    id_or_name: &str,
+    exec_req: ExecRequest,
+    peer_uid: Option<u32>,
+    cancel: &CancellationToken,
+) -> Response {
+    let mgr = manager.lock().await;
+
+    let container = match find_container(&mgr, id_or_name) {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+
+    if let Err(StoreError::DatasetMissing(msg)) = mgr.check_container_dataset(&container.id) {
+        return Response::error(status::CONFLICT, ApiError::DatasetMissing(msg));
+    }
+
+    // Check if container is running
+    if !container.is_running() {
+        return Response::coded(error_codes::CONTAINER_NOT_RUNNING, format!(
+            "Container '{}' is not running. Start it first.",
+            id_or_name
+        ));
+    }
+
+    // The container's environment with the request's over it, including a
+    // default PATH if neither sets one
+    let mut env = mgr.container_env(container);
+    env.extend(exec_req.env.clone());
+    env.entry("PATH".to_string())
+        .or_insert_with(|| "/sbin:/bin:/usr/sbin:/usr/bin:/usr/local/sbin:/usr/local/bin".to_string());
+    let env_vars: Vec<String> = env.iter()
+        .map(|(k, v)| format!("{}={}", k, shell_words::quote(v)))
+        .collect();
+
+    // Build the shell command
+    // We use sh -c to execute the command, which will properly load PATH and handle arguments
+    let shell_command = if let Some(ref workdir) = exec_req.workdir {
+        // Change to workdir first, then execute the command
+        format!("cd {} && exec {}", workdir, shell_words::join(&exec_req.command))
+    } else {
+        format!("exec {}", shell_words::join(&exec_req.command))
+    };
+
+    // Prefix with environment variable exports if needed
+    let final_command = if !env_vars.is_empty() {
+        let env_prefix = env_vars.join(" ");
+        format!("export {}; {}", env_prefix, shell_command)
+    } else {
+        shell_command
+    };
+
+    tracing::debug!("Executing in jail '{}': {}", container.jail_name, final_command);
+    let jail_name = container.jail_name.clone();
+    let session = mgr.exec_sessions.guard(ExecSession {
+        id: String::new(),
+        container_id: container.id.clone(),
+        container: container.display_name().to_string(),
+        command: exec_req.command.clone(),
+        kind: ExecKind::Api,
+        user: peer_uid.and_then(crate::exec_sessions::user_name),
+        peer_uid,
+        started_at: Timestamp::now(),
+        pid: None,
+    });
+    drop(mgr);
+
+    // Execute the command using jexec with a shell wrapper
+    // Note: We don't use -l flag here because it causes command echo issues
+    // We already set up environment variables and PATH explicitly above
+    let mut jexec_cmd = std::process::Command::new("jexec");
+    jexec_cmd.arg(&jail_name);
+    jexec_cmd.arg("/bin/sh");
+    jexec_cmd.arg("-c");
+    jexec_cmd.arg(&final_command);
+    // Its own process group, so killing the session reaches what it started
+    std::os::unix::process::CommandExt::process_group(&mut jexec_cmd, 0);
+
+    // The session ends with `session` on every path out of here
+    let output = match crate::cmdtrace::cancellable_output(jexec_cmd, cancel, |pid| session.set_pid(pid)).await {
+        Ok(Some(output)) => output,
+        Ok(None) => {
+            tracing::info!("Client went away; killed exec in jail '{}'", jail_name);
+            return Response::error(status::CLIENT_CLOSED_REQUEST, ApiError::RequestCancelled());
+        }
+        Err(e) => {
+            return Response::coded(error_codes::EXEC_FAILED, format!("Failed to execute command: {}", e));
+        }
+    };
+
+    let exit_code = output.status.code().unwrap_or(-1);
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+
+    let result = ExecResult {
+        exit_code,
+        stdout,
+        stderr,
+    };
+
+    match Response::success(result) {
+        Ok(resp) => resp,
+        Err(_) => Response::internal_error("Failed to serialize exec result"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{Method, status};
+    use crate::jail::MountPermissions;
+
+    fn create_test_manager() -> JailManager {
+        JailManager::new("/tmp/test-handler.sock")
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_list_jails() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+
+        // Add some jails
+        {
+            let mut mgr = manager.lock().await;
+            mgr.add_jail("jail1").unwrap();
+            mgr.add_jail("jail2").unwrap();
+        }
+
+        let request = Request::get(crate::api::Endpoint::Jails);
+        let response = handle_request(request, manager, CancellationToken::new()).await;
+
+        assert_eq!(response.status, status::OK);
+        assert!(response.is_success());
+        assert!(response.data.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_get_jail() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+
+        {
+            let mut mgr = manager.lock().await;
+            mgr.add_jail("test_jail").unwrap();
+        }
+
+        let request = Request::get(crate::api::Endpoint::Jail("test_jail".into()));
+        let response = handle_request(request, manager, CancellationToken::new()).await;
+
+        assert_eq!(response.status, status::OK);
+        assert!(response.is_success());
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_get_jail_not_found() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+
+        let request = Request::get(crate::api::Endpoint::Jail("nonexistent".into()));
+        let response = handle_request(request, manager, CancellationToken::new()).await;
+
+        assert_eq!(response.status, status::NOT_FOUND);
+        assert!(!response.is_success());
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_create_jail() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+
+        let create_req = CreateJailRequest {
+            name: "new_jail".into(),
+            path: Some("/var/lib/kawakaze/jails/new_jail".into()),
+            ip: Some("192.168.1.100".into()),
+            bootstrap: None,
+            devfs_ruleset: None,
+            cleanup_path: None,
+            mount_permissions: MountPermissions::None,
+            description: None,
+        };
+
+        let request = Request::post(crate::api::Endpoint::Jails, create_req).unwrap();
+        let response = handle_request(request, manager, CancellationToken::new()).await;
+
+        assert_eq!(response.status, status::CREATED);
+        assert!(response.is_success());
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_create_jail_outside_base() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+
+        let create_req = CreateJailRequest {
+            name: "escape".into(),
+            path: Some("/".into()),
+            ip: None,
+            bootstrap: None,
+            devfs_ruleset: None,
+            cleanup_path: None,
+            mount_permissions: MountPermissions::None,
+            description: None,
+        };
+
+        let request = Request::post(crate::api::Endpoint::Jails, create_req).unwrap();
+        let response = handle_request(request, manager.clone(), CancellationToken::new()).await;
+
+        assert_eq!(response.status, status::BAD_REQUEST);
+        assert!(manager.lock().await.get_jail("escape").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_create_jail_without_path_uses_data_root() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+        manager.lock().await.config.data_root = "/srv/kz".to_string();
+
+        let create_req = CreateJailRequest {
+            name: "web".into(),
+            path: None,
+            ip: None,
+            bootstrap: None,
+            devfs_ruleset: None,
+            cleanup_path: None,
+            mount_permissions: MountPermissions::None,
+            description: None,
+        };
+        let request = Request::post(crate::api::Endpoint::Jails, create_req).unwrap();
+        let response = handle_request(request, manager.clone(), CancellationToken::new()).await;
+
+        assert_eq!(response.status, status::CREATED);
+        let path = manager.lock().await.get_jail("web").unwrap().info().path;
+        assert_eq!(path.as_deref(), Some("/srv/kz/jails/web"));
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_create_jail_devfs_ruleset() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+        manager.lock().await.config.devfs.rulesets.push(crate::config::DevfsRulesetConfig {
+            number: 11,
+            unhide: vec!["pf".to_string()],
+        });
+
+        let create_req = |name: &str, devfs_ruleset| CreateJailRequest {
+            name: name.into(),
+            path: None,
+            ip: None,
+            bootstrap: None,
+            devfs_ruleset,
+            cleanup_path: None,
+            mount_permissions: MountPermissions::None,
+            description: None,
+        };
+
+        let request = Request::post(crate::api::Endpoint::Jails, create_req("monitor", Some(11))).unwrap();
+        let response = handle_request(request, manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::CREATED);
+        assert_eq!(manager.lock().await.get_jail("monitor").unwrap().devfs_ruleset(), 11);
+
+        let request = Request::post(crate::api::Endpoint::Jails, create_req("plain", None)).unwrap();
+        handle_request(request, manager.clone(), CancellationToken::new()).await;
+        assert_eq!(manager.lock().await.get_jail("plain").unwrap().devfs_ruleset(), 10);
+
+        // Ruleset 0 (no rules) is not in the allowlist
+        let request = Request::post(crate::api::Endpoint::Jails, create_req("wide-open", Some(0))).unwrap();
+        let response = handle_request(request, manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::BAD_REQUEST);
+        assert!(manager.lock().await.get_jail("wide-open").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_jailed_daemon_refuses_what_its_jail_does_not_allow() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+        manager.lock().await.set_confinement(crate::system::Confinement { jailed: true, allow_vnet: false, allow_devfs: false });
+
+        let create_req = |name: &str, ip: Option<&str>| CreateJailRequest {
+            name: name.into(),
+            path: None,
+            ip: ip.map(str::to_string),
+            bootstrap: None,
+            devfs_ruleset: None,
+            cleanup_path: None,
+            mount_permissions: MountPermissions::None,
+            description: None,
+        };
+
+        // An IP means VNET, which the daemon's jail doesn't allow
+        let request = Request::post(crate::api::Endpoint::Jails, create_req("web", Some("10.0.0.5"))).unwrap();
+        let response = handle_request(request, manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::SERVICE_UNAVAILABLE);
+        let error = response.error.unwrap();
+        assert_eq!(error.code, "UNAVAILABLE");
+        assert!(error.message.starts_with("operation unavailable: daemon is running inside a jail"), "{}", error.message);
+        assert!(manager.lock().await.get_jail("web").is_none());
+
+        // Without an IP it is created, but can't start without devfs
+        let request = Request::post(crate::api::Endpoint::Jails, create_req("plain", None)).unwrap();
+        let response = handle_request(request, manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::CREATED);
+        let request = Request::post(crate::api::Endpoint::StartJail("plain".to_string()), serde_json::json!({})).unwrap();
+        let response = handle_request(request, manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::SERVICE_UNAVAILABLE);
+        assert!(response.error.unwrap().message.contains("allow.mount.devfs"));
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_create_jail_invalid_name() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+
+        let create_req = CreateJailRequest {
+            name: "invalid name!".into(),
+            path: None,
+            ip: None,
+            bootstrap: None,
+            devfs_ruleset: None,
+            cleanup_path: None,
+            mount_permissions: MountPermissions::None,
+            description: None,
+        };
+
+        let request = Request::post(crate::api::Endpoint::Jails, create_req).unwrap();
+        let response = handle_request(request, manager, CancellationToken::new()).await;
+
+        assert_eq!(response.status, status::BAD_REQUEST);
+        assert!(!response.is_success());
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_create_jail_duplicate() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+
+        {
+            let mut mgr = manager.lock().await;
+            mgr.add_jail("existing_jail").unwrap();
+        }
+
+        let create_req = CreateJailRequest {
+            name: "existing_jail".into(),
+            path: None,
+            ip: None,
+            bootstrap: None,
+            devfs_ruleset: None,
+            cleanup_path: None,
+            mount_permissions: MountPermissions::None,
+            description: None,
+        };
+
+        let request = Request::post(crate::api::Endpoint::Jails, create_req).unwrap();
+        let response = handle_request(request, manager, CancellationToken::new()).await;
+
+        assert_eq!(response.status, status::CONFLICT);
+        assert!(!response.is_success());
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_invalid_endpoint() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+
+        let request = Request {
+            method: Method::Get,
+            endpoint: "invalid/endpoint".to_string(),
+            body: serde_json::Value::Null,
+            peer_uid: None,
+        };
+
+        let response = handle_request(request, manager, CancellationToken::new()).await;
+
+        assert_eq!(response.status, status::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_unsupported_method() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+
+        // DELETE on /jails is not supported
+        let request = Request::delete(crate::api::Endpoint::Jails);
+        let response = handle_request(request, manager, CancellationToken::new()).await;
+
+        assert_eq!(response.status, status::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_error_codes_registered() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+        let (image_id, container_id) = {
+            let mut mgr = manager.lock().await;
+            mgr.add_jail("taken").unwrap();
+            let image_id = add_test_image(&mut mgr, "guarded", true);
+            (image_id.clone(), add_test_container(&mut mgr, &image_id, false))
+        };
+        let raw = |method, endpoint: &str, body| Request { method, endpoint: endpoint.to_string(), body, peer_uid: None };
+        let jail = |name: &str| CreateJailRequest {
+            name: name.into(),
+            path: None,
+            ip: None,
+            bootstrap: None,
+            devfs_ruleset: None,
+            cleanup_path: None,
+            mount_permissions: MountPermissions::None,
+            description: None,
+        };
+        let exec = ExecRequest { command: vec!["true".into()], env: Default::default(), workdir: None };
+
+        let cases = [
+            (raw(Method::Get, "no/such/endpoint", serde_json::Value::Null), "UNKNOWN_ENDPOINT"),
+            (Request::delete(Endpoint::Jails), "UNKNOWN_ENDPOINT"),
+            (Request::get(Endpoint::ContainerLogsFollow(container_id.clone())), "WRONG_ENDPOINT_KIND"),
+            (raw(Method::Post, "containers/create", serde_json::json!({"ports": "80"})), "INVALID_BODY"),
+            (Request::post(Endpoint::Jails, jail("bad name!")).unwrap(), "INVALID_NAME"),
+            (Request::get(Endpoint::Jail("missing".into())), "JAIL_NOT_FOUND"),
+            (Request::post(Endpoint::StartJail("missing".into()), serde_json::Value::Null).unwrap(), "JAIL_NOT_FOUND"),
+            (Request::delete(Endpoint::Jail("missing".into())), "JAIL_NOT_FOUND"),
+            (Request::get(Endpoint::Image("missing".into())), "IMAGE_NOT_FOUND"),
+            (Request::get(Endpoint::Container("missing".into())), "CONTAINER_NOT_FOUND"),
+            (Request::delete(Endpoint::RemoveContainer("missing".into())), "CONTAINER_NOT_FOUND"),
+            (Request::get(Endpoint::ImageBuildStatus("missing".into())), "NOT_FOUND"),
+            (Request::get(Endpoint::Operation("missing".into())), "NOT_FOUND"),
+            (Request::post(Endpoint::Jails, jail("taken")).unwrap(), "NAME_IN_USE"),
+            (Request::get(Endpoint::ContainerTop(container_id.clone())), "CONTAINER_NOT_RUNNING"),
+            (Request::post(Endpoint::ContainerExec(container_id.clone()), exec).unwrap(), "CONTAINER_NOT_RUNNING"),
+            (Request::delete(Endpoint::DeleteImage(image_id.clone())), "RESOURCE_PROTECTED"),
+        ];
+        for (request, expected) in cases {
+            let endpoint = request.endpoint.clone();
+            let response = handle_request(request, manager.clone(), CancellationToken::new()).await;
+            let code = response.error.as_ref().map(|e| e.code.clone()).unwrap_or_default();
+            assert_eq!(code, expected, "{}", endpoint);
+            // Every code is registered, and sent with its registered status
+            assert_eq!(error_codes::status(&code), Some(response.status), "{} answered {}", endpoint, code);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_list_jails_empty() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+        let response = list_jails(manager, ListJailsRequest::default()).await;
+
+        assert_eq!(response.status, status::OK);
+        assert!(response.is_success());
+
+        // Verify empty list
+        let data = response.data.unwrap();
+        let items: Vec<JailListItem> = serde_json::from_value(data).unwrap();
+        assert_eq!(items.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_get_jail_found() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+
+        {
+            let mut mgr = manager.lock().await;
+            mgr.add_jail("test_jail").unwrap();
+        }
+
+        let response = get_jail(manager, "test_jail").await;
+
+        assert_eq!(response.status, status::OK);
+        assert!(response.is_success());
+    }
+
+    #[tokio::test]
+    async fn test_get_jail_not_found() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+        let response = get_jail(manager, "nonexistent").await;
+
+        assert_eq!(response.status, status::NOT_FOUND);
+        assert!(!response.is_success());
+    }
+
+    #[tokio::test]
+    async fn test_create_jail_success() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+
+        let request = CreateJailRequest {
+            name: "new_jail".into(),
+            path: None,
+            ip: None,
+            bootstrap: None,
+            devfs_ruleset: None,
+            cleanup_path: None,
+            mount_permissions: MountPermissions::None,
+            description: None,
+        };
+
+        let response = create_jail(manager, request).await;
+
+        assert_eq!(response.status, status::CREATED);
+        assert!(response.is_success());
+    }
+
+    #[tokio::test]
+    async fn test_create_jail_invalid_request() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+
+        let request = CreateJailRequest {
+            name: "".into(),
+            path: None,
+            ip: None,
+            bootstrap: None,
+            devfs_ruleset: None,
+            cleanup_path: None,
+            mount_permissions: MountPermissions::None,
+            description: None,
+        };
+
+        let response = create_jail(manager, request).await;
+
+        assert_eq!(response.status, status::BAD_REQUEST);
+        assert!(!response.is_success());
+    }
+
+    #[tokio::test]
+    async fn test_start_jail_success() {
+        // Skip if not running as root
+        #[cfg(unix)]
+        if unsafe { libc::getuid() } != 0 {
+            return;
+        }
+
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+
+        {
+            let mut mgr = manager.lock().await;
+            mgr.add_jail("test_jail").unwrap();
+        }
+
+        let response = start_jail(manager, "test_jail").await;
+
+        assert!(response.is_success());
+    }
+
+    #[tokio::test]
+    async fn test_start_jail_not_found() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+        let response = start_jail(manager, "nonexistent").await;
+
+        assert_eq!(response.status, status::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_stop_jail_success() {
+        // Skip if not running as root
+        #[cfg(unix)]
+        if unsafe { libc::getuid() } != 0 {
+            return;
+        }
+
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+
+        {
+            let mut mgr = manager.lock().await;
+            mgr.add_jail("test_jail").unwrap();
+            mgr.start_jail("test_jail").unwrap();
+        }
+
+        let response = stop_jail(manager, "test_jail", StopMode::Remove).await;
+
+        assert!(response.is_success());
+    }
+
+    #[tokio::test]
+    async fn test_stop_request_body() {
+        assert_eq!(stop_request(serde_json::Value::Null).unwrap().stop_mode, StopMode::Remove);
+        assert_eq!(stop_request(serde_json::json!({ "stop_mode": "freeze" })).unwrap().stop_mode, StopMode::Freeze);
+
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+        manager.lock().await.add_jail("web").unwrap();
+        let request = Request::post(Endpoint::StopJail("web".to_string()), serde_json::json!({ "stop_mode": "pause" })).unwrap();
+        assert_eq!(handle_request(request, manager.clone(), CancellationToken::new()).await.status, status::BAD_REQUEST);
+
+        // Freezing a jail that isn't running fails like removing it does
+        let request = Request::post(Endpoint::StopJail("web".to_string()), StopRequest { stop_mode: StopMode::Freeze }).unwrap();
+        let response = handle_request(request, manager, CancellationToken::new()).await;
+        assert!(!response.is_success());
+        assert!(response.error.unwrap().message.contains("not running"));
+    }
+
+    #[tokio::test]
+    async fn test_stop_jail_not_found() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+        let response = stop_jail(manager, "nonexistent", StopMode::Remove).await;
+
+        assert_eq!(response.status, status::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_delete_jail_success() {
+        // Skip if not running as root
+        #[cfg(unix)]
+        if unsafe { libc::getuid() } != 0 {
+            return;
+        }
+
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+
+        {
+            let mut mgr = manager.lock().await;
+            mgr.add_jail("test_jail").unwrap();
+        }
+
+        let response = delete_jail(manager, "test_jail").await;
+
+        assert!(response.is_success());
+    }
+
+    #[tokio::test]
+    async fn test_delete_jail_not_found() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+        let response = delete_jail(manager, "nonexistent").await;
+
+        assert_eq!(response.status, status::NOT_FOUND);
+    }
+
+    // ============================================================================
+    // Exec Container Tests
+    // ============================================================================
+
+    #[tokio::test]
+    async fn test_exec_container_not_found() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+
+        let exec_req = ExecRequest {
+            command: vec!["echo".to_string(), "test".to_string()],
+            env: std::collections::HashMap::new(),
+            workdir: None,
+        };
+
+        let response = exec_container(manager, "nonexistent", exec_req, None, &CancellationToken::new()).await;
+
+        assert_eq!(response.status, status::NOT_FOUND);
+        assert!(!response.is_success());
+    }
+
+    #[tokio::test]
+    async fn test_exec_container_invalid_request_body() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+
+        let request = Request::post(
+            crate::api::Endpoint::ContainerExec("some-id".into()),
+            "invalid json",
+        ).unwrap();
+
+        let response = handle_request(request, manager, CancellationToken::new()).await;
+
+        assert_eq!(response.status, status::BAD_REQUEST);
+        assert!(!response.is_success());
+    }
+
+    #[tokio::test]
+    async fn test_exec_request_valid() {
+        let exec_req = ExecRequest {
+            command: vec!["ls".to_string(), "-la".to_string()],
+            env: {
+                let mut map = std::collections::HashMap::new();
+                map.insert("PATH".to_string(), "/usr/bin".to_string());
+                map
+            },
+            workdir: Some("/tmp".to_string()),
+        };
+
+        assert_eq!(exec_req.command.len(), 2);
+        assert_eq!(exec_req.workdir, Some("/tmp".to_string()));
+        assert_eq!(exec_req.env.len(), 1);
+    }
+
+    /// Test that exec request without PATH still includes default PATH
+    #[tokio::test]
+    async fn test_exec_request_without_path_gets_default() {
+        let exec_req = ExecRequest {
+            command: vec!["echo".to_string(), "test".to_string()],
+            env: std::collections::HashMap::new(),
+            workdir: None,
+        };
+
+        // Verify the exec request has no PATH in env
+        assert!(!exec_req.env.contains_key("PATH"));
+        assert_eq!(exec_req.command.len(), 2);
+    }
+
+    /// Test that exec request with custom PATH preserves it
+    #[tokio::test]
+    async fn test_exec_request_with_custom_path() {
+        let mut custom_env = std::collections::HashMap::new();
+        custom_env.insert("CUSTOM_VAR".to_string(), "custom_value".to_string());
+        custom_env.insert("PATH".to_string(), "/custom/path".to_string());
+
+        let exec_req = ExecRequest {
+            command: vec!["ls".to_string()],
+            env: custom_env,
+            workdir: None,
+        };
+
+        // Verify custom PATH is preserved
+        assert_eq!(exec_req.env.get("PATH"), Some(&"/custom/path".to_string()));
+        assert_eq!(exec_req.env.get("CUSTOM_VAR"), Some(&"custom_value".to_string()));
+    }
+
+    /// Test exec command building with workdir
+    #[tokio::test]
+    async fn test_exec_command_with_workdir() {
+        let exec_req = ExecRequest {
+            command: vec!["ls".to_string(), "-la".to_string()],
+            env: std::collections::HashMap::new(),
+            workdir: Some("/root".to_string()),
+        };
+
+        // Simulate the command building logic from exec_container
+        let shell_command = format!("cd {} && exec {}", exec_req.workdir.unwrap(), shell_words::join(&exec_req.command));
+
+        assert!(shell_command.contains("cd /root"));
+        assert!(shell_command.contains("exec ls -la"));
+    }
+
+    /// Test exec command building without workdir
+    #[tokio::test]
+    async fn test_exec_command_without_workdir() {
+        let exec_req = ExecRequest {
+            command: vec!["uname".to_string(), "-a".to_string()],
+            env: std::collections::HashMap::new(),
+            workdir: None,
+        };
+
+        // Simulate the command building logic from exec_container
+        let shell_command = format!("exec {}", shell_words::join(&exec_req.command));
+
+        assert_eq!(shell_command, "exec uname -a");
+    }
+
+    /// Test that shell-words properly quotes arguments
+    #[tokio::test]
+    async fn test_exec_command_with_spaces() {
+        let exec_req = ExecRequest {
+            command: vec!["echo".to_string(), "hello world".to_string(), "test".to_string()],
+            env: std::collections::HashMap::new(),
+            workdir: None,
+        };
+
+        // shell-words::join should properly quote arguments with spaces
+        let joined = shell_words::join(&exec_req.command);
+
+        assert!(joined.contains("'hello world'") || joined.contains("\"hello world\""));
+    }
+
+    fn add_test_image(mgr: &mut JailManager, name: &str, protected: bool) -> String {
+        let image = Image::new(name.to_string(), Vec::new()).with_protected(protected);
+        let id = image.id.clone();
+        mgr.add_image(image).unwrap();
+        id
+    }
+
+    fn add_test_container(mgr: &mut JailManager, image_id: &str, protected: bool) -> String {
+        let config = crate::container::ContainerConfig {
+            image_id: image_id.to_string(),
+            name: Some("protected-test".to_string()),
+            ports: Vec::new(),
+            volumes: Vec::new(),
+            tmpfs: Vec::new(),
+            restart_policy: RestartPolicy::default(),
+            command: None,
+            protected,
+            devfs_ruleset: None,
+            healthcheck_disabled: false,
+            boot: false,
+            encrypted: false,
+            read_only_root: false,
+            network_aliases: Vec::new(),
+            hostname: None,
+            mount_permissions: MountPermissions::None,
+            env: Default::default(),
+            placement: None,
+            sysctls: Default::default(),
+            description: None,
+        };
+        mgr.create_container(config).unwrap().id
+    }
+
+    #[tokio::test]
+    async fn test_prune_images_dangling_and_all() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+        let (tagged, layer, dangling) = {
+            let mut mgr = manager.lock().await;
+            let layer = Image::new("cache-1".to_string(), Vec::new())
+                .with_kind(crate::image::ImageKind::Intermediate)
+                .with_size(100);
+            let tagged = Image::new("app".to_string(), Vec::new()).with_parent(layer.id.clone());
+            let dangling = Image::new("cache-2".to_string(), Vec::new())
+                .with_kind(crate::image::ImageKind::Intermediate)
+                .with_size(50);
+            let ids = (tagged.id.clone(), layer.id.clone(), dangling.id.clone());
+            for image in [layer, tagged, dangling] {
+                mgr.add_image(image).unwrap();
+            }
+            ids
+        };
+
+        // Only the unreferenced intermediate image goes
+        let request = Request::post(Endpoint::PruneImages, PruneImagesRequest::default()).unwrap();
+        let response = handle_request(request, manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::OK);
+        let result: PruneImagesResult = serde_json::from_value(response.data.unwrap()).unwrap();
+        assert_eq!(result.removed.len(), 1);
+        assert_eq!(result.removed[0].id, dangling);
+        assert_eq!(result.reclaimed_bytes, 50);
+        {
+            let mgr = manager.lock().await;
+            assert!(mgr.get_image(&layer).is_some());
+            assert!(mgr.get_image(&tagged).is_some());
+        }
+
+        // --all takes the unused tagged image and then its layer
+        let request = Request::post(Endpoint::PruneImages, PruneImagesRequest { all: true }).unwrap();
+        let response = handle_request(request, manager.clone(), CancellationToken::new()).await;
+        let result: PruneImagesResult = serde_json::from_value(response.data.unwrap()).unwrap();
+        let removed: Vec<String> = result.removed.into_iter().map(|item| item.id).collect();
+        assert_eq!(removed, vec![tagged, layer]);
+    }
+
+    #[tokio::test]
+    async fn test_delete_protected_image_conflict() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+        let image_id = {
+            let mut mgr = manager.lock().await;
+            add_test_image(&mut mgr, "guarded", true)
+        };
+
+        let request = Request::delete(Endpoint::DeleteImage(image_id.clone()));
+        let response = handle_request(request, manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::CONFLICT);
+        assert_eq!(response.error.unwrap().code, "RESOURCE_PROTECTED");
+        assert!(manager.lock().await.get_image(&image_id).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_image_written_by_newer_version() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+        let image_id = {
+            let mut mgr = manager.lock().await;
+            let id = add_test_image(&mut mgr, "future", false);
+            mgr.images.get_mut(&id).unwrap().written_by_newer = Some("99.0.0".to_string());
+            id
+        };
+
+        let response = handle_request(Request::get(Endpoint::Image(image_id.clone())), manager.clone(), CancellationToken::new()).await;
+        let info: ImageInfo = serde_json::from_value(response.data.unwrap()).unwrap();
+        assert_eq!(info.readonly_reason.as_deref(), Some("written by newer version"));
+
+        let request = Request::post(Endpoint::UpdateImage(image_id.clone()), UpdateRequest { protected: Some(true), boot: None, read_only: None, description: None }).unwrap();
+        let response = handle_request(request, manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::CONFLICT);
+        assert_eq!(response.error.unwrap().code, "VERSION_SKEW");
+
+        let response = handle_request(Request::delete(Endpoint::DeleteImage(image_id.clone())), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::CONFLICT);
+        assert_eq!(response.error.unwrap().code, "VERSION_SKEW");
+        assert!(manager.lock().await.get_image(&image_id).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_delete_protected_image_override() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+        let image_id = {
+            let mut mgr = manager.lock().await;
+            add_test_image(&mut mgr, "guarded", true)
+        };
+
+        // Overriding protection without force is rejected outright
+        let request = Request::delete_with(
+            Endpoint::DeleteImage(image_id.clone()),
+            RemoveRequest { force: false, override_protection: true, ..Default::default() },
+        )
+        .unwrap();
+        let response = handle_request(request, manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::BAD_REQUEST);
+
+        let request = Request::delete_with(
+            Endpoint::DeleteImage(image_id.clone()),
+            RemoveRequest { force: true, override_protection: true, ..Default::default() },
+        )
+        .unwrap();
+        let response = handle_request(request, manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::OK);
+        assert!(manager.lock().await.get_image(&image_id).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_create_container_reports_allocations() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+        {
+            let mut mgr = manager.lock().await;
+            add_test_image(&mut mgr, "base", false);
+        }
+
+        let request = Request::post(
+            Endpoint::ContainerCreate,
+            serde_json::json!({
+                "image_id": "base",
+                "name": "web",
+                "ports": [
+                    {"host_port": 0, "container_port": 80, "protocol": "tcp"},
+                    {"host_port": 8443, "container_port": 443, "protocol": "tcp"},
+                ],
+                "restart_policy": "no",
+                "command": ["/usr/local/bin/httpd", "-f"],
+            }),
+        )
+        .unwrap();
+        let response = handle_request(request, manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::CREATED);
+
+        let data = response.data.unwrap();
+        assert_eq!(data["name"], "web");
+        assert!(data.get("ip").is_some());
+        assert_eq!(data["ports"][0]["host_port"], *crate::container::DYNAMIC_HOST_PORTS.start());
+        assert_eq!(data["ports"][0]["container_port"], 80);
+        assert_eq!(data["ports"][1]["host_port"], 8443);
+        assert_eq!(data["command"], serde_json::json!(["/usr/local/bin/httpd", "-f"]));
+
+        // The chosen host port is reported the same way by the stored container
+        let id = data["id"].as_str().unwrap().to_string();
+        let mgr = manager.lock().await;
+        assert_eq!(mgr.get_container(&id).unwrap().port_mappings[0].host_port, 49152);
+    }
+
+    #[tokio::test]
+    async fn test_create_container_does_not_start_it() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+        add_test_image(&mut *manager.lock().await, "base", false);
+
+        let request =
+            Request::post(Endpoint::ContainerCreate, serde_json::json!({ "image_id": "base", "name": "web", "restart_policy": "no" })).unwrap();
+        let response = handle_request(request, manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::CREATED);
+
+        // Everything `kawakaze create` prints comes back with the create
+        let data = response.data.unwrap();
+        assert_eq!(data["state"], "created");
+        assert!(data["jail_name"].as_str().unwrap().starts_with("kawakaze-"));
+        assert!(data.get("created_at").is_some() && data.get("ports").is_some());
+        assert!(data["started_at"].is_null());
+
+        let id = data["id"].as_str().unwrap().to_string();
+        let mgr = manager.lock().await;
+        let container = mgr.get_container(&id).unwrap();
+        assert_eq!(container.state, crate::container::ContainerState::Created);
+        assert!(!mgr.get_jail(&container.jail_name).is_some_and(|jail| jail.is_running()));
+    }
+
+    #[tokio::test]
+    async fn test_create_container_checks_mount_sources() {
+        let host = tempfile::tempdir().unwrap();
+        let www = host.path().join("srv/www");
+        std::fs::create_dir_all(&www).unwrap();
+        std::os::unix::fs::symlink(&www, host.path().join("www")).unwrap();
+
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+        {
+            let mut mgr = manager.lock().await;
+            mgr.config.allowed_mount_roots = vec![host.path().join("srv")];
+            add_test_image(&mut mgr, "base", false);
+        }
+        let create = |name: &str, source: &std::path::Path| {
+            Request::post(
+                Endpoint::ContainerCreate,
+                serde_json::json!({
+                    "image_id": "base",
+                    "name": name,
+                    "volumes": [{"source": source, "destination": "/var/www", "mount_type": "nullfs", "read_only": true}],
+                    "restart_policy": "no",
+                }),
+            )
+            .unwrap()
+        };
+
+        let response = handle_request(create("etc", std::path::Path::new("/etc")), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::BAD_REQUEST);
+        let message = response.error.unwrap().message;
+        assert!(message.contains("'/etc'") && message.contains("srv"), "{}", message);
+        assert!(manager.lock().await.get_container(&"etc".to_string()).is_none());
+
+        // Recorded by the path it resolves to
+        let response = handle_request(create("web", &host.path().join("www")), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::CREATED);
+        let id = response.data.unwrap()["id"].as_str().unwrap().to_string();
+        let mgr = manager.lock().await;
+        let mount = &mgr.get_container(&id).unwrap().mounts[0];
+        assert_eq!(mount.source, www.canonicalize().unwrap().display().to_string());
+        assert!(mount.read_only);
+    }
+
+    #[tokio::test]
+    async fn test_create_container_tmpfs() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = Arc::new(Mutex::new(JailManager::with_database(dir.path().join("kawakaze.db")).unwrap()));
+        add_test_image(&mut *manager.lock().await, "base", false);
+        let create = |name: &str, tmpfs: serde_json::Value| {
+            Request::post(
+                Endpoint::ContainerCreate,
+                serde_json::json!({
+                    "image_id": "base",
+                    "name": name,
+                    "volumes": [{"source": "tank/www", "destination": "/var/www", "mount_type": "zfs"}],
+                    "tmpfs": tmpfs,
+                    "restart_policy": "no",
+                }),
+            )
+            .unwrap()
+        };
+
+        // A volume already mounts there
+        let response = handle_request(create("clash", serde_json::json!([{"destination": "/var/www/"}])), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::BAD_REQUEST);
+        assert!(response.error.unwrap().message.contains("/var/www"));
+        let response = handle_request(create("relative", serde_json::json!([{"destination": "run"}])), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::BAD_REQUEST);
+
+        let tmpfs = serde_json::json!([{"destination": "/run", "size_bytes": 67108864, "mode": 1023}]);
+        let response = handle_request(create("web", tmpfs.clone()), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::CREATED);
+        let data = response.data.unwrap();
+        assert_eq!(data["tmpfs"], tmpfs);
+
+        // Kept with the container
+        let id = data["id"].as_str().unwrap().to_string();
+        let mgr = manager.lock().await;
+        let row = mgr.store.as_ref().unwrap().get_container(&id).unwrap().unwrap();
+        let stored = mgr.load_container_from_store_row(row).unwrap();
+        assert_eq!(stored.tmpfs, [crate::container::TmpfsMount { destination: "/run".to_string(), size_bytes: Some(64 << 20), mode: Some(0o1777) }]);
+    }
+
+    #[tokio::test]
+    async fn test_mount_permissions_need_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = Arc::new(Mutex::new(JailManager::with_database(dir.path().join("kawakaze.db")).unwrap()));
+        add_test_image(&mut *manager.lock().await, "base", false);
+        let jail = |name: &str| {
+            let body = serde_json::json!({ "name": name, "mount_permissions": "tmpfs" });
+            Request::post(Endpoint::Jails, body).unwrap()
+        };
+        let container = |name: &str| {
+            let body = serde_json::json!({ "image_id": "base", "name": name, "restart_policy": "no", "mount_permissions": "all" });
+            Request::post(Endpoint::ContainerCreate, body).unwrap()
+        };
+
+        // Refused by default, naming the setting
+        let response = handle_request(jail("mounter"), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::FORBIDDEN);
+        let error = response.error.unwrap();
+        assert_eq!(error.code, "FORBIDDEN");
+        assert!(error.message.contains("allow_in_jail_mounts"), "{}", error.message);
+        let response = handle_request(container("builder"), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::FORBIDDEN);
+        assert!(manager.lock().await.get_jail("mounter").is_none());
+
+        manager.lock().await.config.allow_in_jail_mounts = true;
+        let response = handle_request(jail("mounter"), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::CREATED);
+        assert_eq!(response.data.unwrap()["mount_permissions"], "tmpfs");
+        assert_eq!(manager.lock().await.get_jail("mounter").unwrap().mount_permissions(), MountPermissions::Tmpfs);
+
+        let response = handle_request(container("builder"), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::CREATED);
+        let id = response.data.unwrap()["id"].as_str().unwrap().to_string();
+        let mgr = manager.lock().await;
+        let jail_name = &mgr.containers[&id].jail_name;
+        assert_eq!(mgr.get_jail(jail_name).unwrap().mount_permissions(), MountPermissions::All);
+        let row = mgr.store.as_ref().unwrap().get_container(&id).unwrap().unwrap();
+        assert_eq!(mgr.load_container_from_store_row(row).unwrap().mount_permissions, MountPermissions::All);
+    }
+
+    #[tokio::test]
+    async fn test_create_container_external_datasets() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = Arc::new(Mutex::new(JailManager::with_database(dir.path().join("kawakaze.db")).unwrap()));
+        {
+            let mut mgr = manager.lock().await;
+            mgr.config.allow_in_jail_mounts = true;
+            add_test_image(&mut mgr, "base", false);
+        }
+        let create = |name: &str, volume: serde_json::Value, permissions: &str| {
+            let body = serde_json::json!({
+                "image_id": "base",
+                "name": name,
+                "volumes": [volume],
+                "mount_permissions": permissions,
+                "restart_policy": "no",
+            });
+            Request::post(Endpoint::ContainerCreate, body).unwrap()
+        };
+        let refused = [
+            ("pool", serde_json::json!({"source": "data", "destination": "/data", "mount_type": "zfs"}), "none"),
+            ("own", serde_json::json!({"source": "zroot/kawakaze/volumes/x", "destination": "/data", "mount_type": "zfs"}), "none"),
+            ("unmountable", serde_json::json!({"source": "data/db", "destination": "/data", "mount_type": "zfs", "delegate": true}), "none"),
+            ("ro", serde_json::json!({"source": "data/db", "destination": "/data", "mount_type": "zfs", "delegate": true, "read_only": true}), "zfs"),
+            ("nullfs", serde_json::json!({"source": "/srv", "destination": "/data", "mount_type": "nullfs", "delegate": true}), "all"),
+        ];
+        for (name, volume, permissions) in refused {
+            let response = handle_request(create(name, volume, permissions), manager.clone(), CancellationToken::new()).await;
+            assert_eq!(response.status, status::BAD_REQUEST, "{}", name);
+        }
+
+        let volume = serde_json::json!({"source": "data/db", "destination": "/data", "mount_type": "zfs", "delegate": true});
+        let response = handle_request(create("db", volume, "zfs"), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::CREATED);
+        let id = response.data.unwrap()["id"].as_str().unwrap().to_string();
+
+        let response = handle_request(Request::get(Endpoint::Container(id)), manager.clone(), CancellationToken::new()).await;
+        let volumes = &response.data.unwrap()["volumes"];
+        assert_eq!(volumes[0]["source"], "data/db");
+        assert_eq!(volumes[0]["mode"], "delegated");
+    }
+
+    #[tokio::test]
+    async fn test_unhealthy_pool_gates_writes() {
+        use crate::zfs::{PoolState, PoolStatus};
+
+        let dir = tempfile::tempdir().unwrap();
+        let manager = Arc::new(Mutex::new(JailManager::with_database(dir.path().join("kawakaze.db")).unwrap()));
+        let state = Arc::new(std::sync::Mutex::new(PoolState::Online));
+        {
+            let mut mgr = manager.lock().await;
+            add_test_image(&mut mgr, "base", false);
+            let state = Arc::clone(&state);
+            let source = move || {
+                let state = *state.lock().unwrap();
+                Ok(PoolStatus { name: "zroot".to_string(), state, free_bytes: 1 << 30, size_bytes: 1 << 31, status: None })
+            };
+            mgr.pool_health = Arc::new(crate::pool_health::PoolHealth::new(source, std::time::Duration::ZERO));
+        }
+        let create = |name: &str| {
+            let body = serde_json::json!({ "image_id": "base", "name": name, "restart_policy": "no" });
+            Request::post(Endpoint::ContainerCreate, body).unwrap()
+        };
+
+        let response = handle_request(create("web"), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::CREATED);
+        let id = response.data.unwrap()["id"].as_str().unwrap().to_string();
+
+        *state.lock().unwrap() = PoolState::Suspended;
+        let response = handle_request(create("db"), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::SERVICE_UNAVAILABLE);
+        let error = response.error.unwrap();
+        assert_eq!(error.code, "POOL_UNHEALTHY");
+        assert!(error.message.contains("SUSPENDED"), "{}", error.message);
+        let response = handle_request(Request::get(Endpoint::SystemInfo), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.data.unwrap()["pool_writes_refused"], true);
+
+        // DEGRADED is refused unless allowed
+        *state.lock().unwrap() = PoolState::Degraded;
+        let response = handle_request(create("db"), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::SERVICE_UNAVAILABLE);
+        manager.lock().await.config.storage.allow_degraded_pool = true;
+        let response = handle_request(create("db"), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::CREATED);
+
+        // Reads and removal go on during the incident
+        *state.lock().unwrap() = PoolState::Faulted;
+        let response = handle_request(Request::get(Endpoint::Containers), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::OK);
+        let response = handle_request(Request::delete(Endpoint::RemoveContainer(id)), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::OK);
+    }
+
+    #[tokio::test]
+    async fn test_create_container_without_healthcheck() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = Arc::new(Mutex::new(JailManager::with_database(dir.path().join("kawakaze.db")).unwrap()));
+        {
+            let mut mgr = manager.lock().await;
+            add_test_image(&mut mgr, "base", false);
+        }
+
+        let create = |disable_healthcheck: bool| {
+            Request::post(
+                Endpoint::ContainerCreate,
+                serde_json::json!({ "image_id": "base", "restart_policy": "no", "disable_healthcheck": disable_healthcheck }),
+            )
+            .unwrap()
+        };
+
+        let data = handle_request(create(false), manager.clone(), CancellationToken::new()).await.data.unwrap();
+        assert!(data.get("health").is_none());
+
+        let data = handle_request(create(true), manager.clone(), CancellationToken::new()).await.data.unwrap();
+        assert_eq!(data["health"], "none");
+
+        // The override survives a reload from the database
+        let id = data["id"].as_str().unwrap().to_string();
+        let mgr = manager.lock().await;
+        let row = mgr.store.as_ref().unwrap().get_container(&id).unwrap().unwrap();
+        assert!(row.healthcheck_disabled);
+        let reloaded = mgr.load_container_from_store_row(row).unwrap();
+        assert_eq!(ContainerInfo::from(&reloaded).health.as_deref(), Some("none"));
+    }
+
+    #[tokio::test]
+    async fn test_create_container_env() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = Arc::new(Mutex::new(JailManager::with_database(dir.path().join("kawakaze.db")).unwrap()));
+        {
+            let mut mgr = manager.lock().await;
+            let mut image = Image::new("base".to_string(), Vec::new());
+            image.config.env.insert("APP_ENV".to_string(), "production".to_string());
+            image.config.env.insert("LANG".to_string(), "C.UTF-8".to_string());
+            mgr.add_image(image).unwrap();
+        }
+
+        let create = |env: serde_json::Value| {
+            Request::post(Endpoint::ContainerCreate, serde_json::json!({ "image_id": "base", "restart_policy": "no", "env": env })).unwrap()
+        };
+
+        let response = handle_request(create(serde_json::json!({ "1BAD": "x" })), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::BAD_REQUEST);
+
+        let data = handle_request(create(serde_json::json!({ "APP_ENV": "staging" })), manager.clone(), CancellationToken::new()).await.data.unwrap();
+        assert_eq!(data["env"], serde_json::json!({ "APP_ENV": "staging" }));
+        let id = data["id"].as_str().unwrap().to_string();
+
+        // Inspect shows the effective environment: the override wins over the image
+        let response = handle_request(Request::get(Endpoint::Container(id.clone())), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.data.unwrap()["env"], serde_json::json!({ "APP_ENV": "staging", "LANG": "C.UTF-8" }));
+
+        // And survives a reload from the database
+        let mgr = manager.lock().await;
+        let row = mgr.store.as_ref().unwrap().get_container(&id).unwrap().unwrap();
+        let reloaded = mgr.load_container_from_store_row(row).unwrap();
+        assert_eq!(mgr.container_env(&reloaded)["APP_ENV"], "staging");
+    }
+
+    #[tokio::test]
+    async fn test_create_container_placement() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = Arc::new(Mutex::new(JailManager::with_database(dir.path().join("kawakaze.db")).unwrap()));
+        {
+            let mut mgr = manager.lock().await;
+            add_test_image(&mut mgr, "base", false);
+            let labels = [("hostname", "web1"), ("zone", "eu-west")].map(|(k, v)| (k.to_string(), v.to_string()));
+            mgr.node_labels.set(labels.into()).unwrap();
+        }
+
+        let create = |constraints: &[&str]| {
+            Request::post(
+                Endpoint::ContainerCreate,
+                serde_json::json!({
+                    "image_id": "base",
+                    "restart_policy": "no",
+                    "placement": { "constraints": constraints, "preferences": ["spread=node.labels.zone"] },
+                }),
+            )
+            .unwrap()
+        };
+
+        let response = handle_request(create(&["node.labels.zone=eu-west"]), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::BAD_REQUEST);
+        assert!(response.error.unwrap().message.contains("at position 16: expected '==' or '!='"));
+
+        let response = handle_request(create(&["node.labels.zone==us-east", "node.hostname!=web2"]), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::CONFLICT);
+        let error = response.error.unwrap();
+        assert_eq!(error.code, error_codes::PLACEMENT_UNSATISFIABLE);
+        assert!(error.message.contains("node.labels.zone==us-east (zone is 'eu-west')"), "{}", error.message);
+        assert!(!error.message.contains("node.hostname"), "{}", error.message);
+
+        let data = handle_request(create(&["node.labels.zone==eu-west", "node.hostname!=web2"]), manager.clone(), CancellationToken::new()).await.data.unwrap();
+        assert_eq!(data["placement"]["constraints"][0], "node.labels.zone==eu-west");
+        assert_eq!(data["placement"]["preferences"][0], "spread=node.labels.zone");
+
+        // The placement survives a reload from the database
+        let id = data["id"].as_str().unwrap().to_string();
+        let mgr = manager.lock().await;
+        let row = mgr.store.as_ref().unwrap().get_container(&id).unwrap().unwrap();
+        let reloaded = mgr.load_container_from_store_row(row).unwrap();
+        assert_eq!(reloaded.placement.unwrap().constraints.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_create_container_sysctls() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = Arc::new(Mutex::new(JailManager::with_database(dir.path().join("kawakaze.db")).unwrap()));
+        {
+            let mut mgr = manager.lock().await;
+            add_test_image(&mut mgr, "base", false);
+            mgr.config.sysctl.denied.push("net.inet.ip.forwarding".to_string());
+        }
+
+        let create = |sysctls: serde_json::Value| {
+            let body = serde_json::json!({ "image_id": "base", "restart_policy": "no", "sysctls": sysctls });
+            Request::post(Endpoint::ContainerCreate, body).unwrap()
+        };
+
+        for sysctls in [serde_json::json!({ "kern.securelevel": "-1" }), serde_json::json!({ "net.inet.ip.forwarding": "1" })] {
+            let response = handle_request(create(sysctls), manager.clone(), CancellationToken::new()).await;
+            assert_eq!(response.status, status::BAD_REQUEST);
+            let message = response.error.unwrap().message;
+            assert!(message.contains("can't be set in a container; permitted: net.inet.ip.*"), "{}", message);
+        }
+
+        let sysctls = serde_json::json!({ "net.inet.tcp.keepidle": "30000", "net.inet6.ip6.forwarding": "1" });
+        let data = handle_request(create(sysctls), manager.clone(), CancellationToken::new()).await.data.unwrap();
+        assert_eq!(data["sysctls"]["net.inet.tcp.keepidle"], "30000");
+        // Nothing is applied until it starts
+        assert!(data.get("applied_sysctls").is_none());
+
+        let id = data["id"].as_str().unwrap().to_string();
+        let mgr = manager.lock().await;
+        let row = mgr.store.as_ref().unwrap().get_container(&id).unwrap().unwrap();
+        let reloaded = mgr.load_container_from_store_row(row).unwrap();
+        assert_eq!(reloaded.sysctls["net.inet6.ip6.forwarding"], "1");
+    }
+
+    #[tokio::test]
+    async fn test_container_description() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = Arc::new(Mutex::new(JailManager::with_database(dir.path().join("kawakaze.db")).unwrap()));
+        add_test_image(&mut *manager.lock().await, "base", false);
+
+        let create = |description: String| {
+            let body = serde_json::json!({ "image_id": "base", "restart_policy": "no", "description": description });
+            Request::post(Endpoint::ContainerCreate, body).unwrap()
+        };
+        let response = handle_request(create("x".repeat(crate::api::MAX_DESCRIPTION_LEN + 1)), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::BAD_REQUEST);
+        assert!(manager.lock().await.list_containers().is_empty());
+
+        let data = handle_request(create("owned by team-payments".into()), manager.clone(), CancellationToken::new()).await.data.unwrap();
+        assert_eq!(data["description"], "owned by team-payments");
+        let id = data["id"].as_str().unwrap().to_string();
+
+        let update = |description: String| {
+            let body = UpdateRequest { description: Some(description), ..Default::default() };
+            Request::post(Endpoint::UpdateContainer(id.clone()), body).unwrap()
+        };
+        let data = handle_request(update("decommission after Q3".into()), manager.clone(), CancellationToken::new()).await.data.unwrap();
+        assert_eq!(data["description"], "decommission after Q3");
+        {
+            let mgr = manager.lock().await;
+            let row = mgr.store.as_ref().unwrap().get_container(&id).unwrap().unwrap();
+            assert_eq!(mgr.load_container_from_store_row(row).unwrap().description.as_deref(), Some("decommission after Q3"));
+        }
+
+        // A description that is too long changes nothing
+        let response = handle_request(update("x".repeat(crate::api::MAX_DESCRIPTION_LEN + 1)), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::BAD_REQUEST);
+        assert_eq!(manager.lock().await.get_container(&id).unwrap().description.as_deref(), Some("decommission after Q3"));
+
+        // An empty one clears it
+        let data = handle_request(update(String::new()), manager.clone(), CancellationToken::new()).await.data.unwrap();
+        assert!(data.get("description").is_none());
+        assert_eq!(manager.lock().await.store.as_ref().unwrap().get_container(&id).unwrap().unwrap().description, None);
+
+        // Images have none
+        let body = UpdateRequest { description: Some("x".into()), ..Default::default() };
+        let request = Request::post(Endpoint::UpdateImage("base".into()), body).unwrap();
+        assert_eq!(handle_request(request, manager.clone(), CancellationToken::new()).await.status, status::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_jail_update() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+        manager.lock().await.add_jail("db").unwrap();
+
+        let update = |name: &str, body: serde_json::Value| Request::post(Endpoint::JailUpdate(name.to_string()), body).unwrap();
+        let response = handle_request(update("db", serde_json::json!({ "description": "owned by team-payments" })), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::OK);
+        assert_eq!(response.data.unwrap()["description"], "owned by team-payments");
+        assert_eq!(manager.lock().await.get_jail("db").unwrap().description(), Some("owned by team-payments"));
+
+        // Left out, it is kept; empty, it is cleared
+        let response = handle_request(update("db", serde_json::json!({})), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.data.unwrap()["description"], "owned by team-payments");
+        let response = handle_request(update("db", serde_json::json!({ "description": "" })), manager.clone(), CancellationToken::new()).await;
+        assert!(response.data.unwrap().get("description").is_none());
+        assert_eq!(manager.lock().await.get_jail("db").unwrap().description(), None);
+
+        let too_long = "x".repeat(crate::api::MAX_DESCRIPTION_LEN + 1);
+        let response = handle_request(update("db", serde_json::json!({ "description": too_long })), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::BAD_REQUEST);
+        let response = handle_request(update("db", serde_json::json!({ "description": 5 })), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.error.unwrap().code, error_codes::INVALID_BODY);
+        let response = handle_request(update("nope", serde_json::json!({ "description": "x" })), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.error.unwrap().code, error_codes::JAIL_NOT_FOUND);
+
+        // Set at create too
+        let create = serde_json::json!({ "name": "cache", "description": "team-search" });
+        let response = handle_request(Request::post(Endpoint::Jails, create).unwrap(), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.data.unwrap()["description"], "team-search");
+        let response = handle_request(Request::get(Endpoint::Jail("cache".into())), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.data.unwrap()["description"], "team-search");
+    }
+
+    #[tokio::test]
+    async fn test_list_filters() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+        add_test_image(&mut *manager.lock().await, "base", false);
+        for (name, description) in [("web", Some("owned by Team-Payments")), ("db", Some("payments ledger")), ("cache", None)] {
+            let body = serde_json::json!({ "image_id": "base", "name": name, "restart_policy": "no", "description": description });
+            let response = handle_request(Request::post(Endpoint::ContainerCreate, body).unwrap(), manager.clone(), CancellationToken::new()).await;
+            assert_eq!(response.status, status::CREATED);
+        }
+
+        let list = |filter: &[&str]| {
+            let filter = filter.iter().map(|f| f.to_string()).collect();
+            Request::get_with(Endpoint::Containers, ListContainersRequest { include_summary: false, filter }).unwrap()
+        };
+        let names = |response: Response| {
+            let mut names: Vec<String> = response.data.unwrap().as_array().unwrap().iter().map(|c| c["name"].as_str().unwrap().to_string()).collect();
+            names.sort();
+            names
+        };
+        assert_eq!(names(handle_request(list(&["description~=payments"]), manager.clone(), CancellationToken::new()).await), ["db", "web"]);
+        assert_eq!(names(handle_request(list(&["description~=payments", "name=web"]), manager.clone(), CancellationToken::new()).await), ["web"]);
+        assert_eq!(names(handle_request(list(&["state=created", "description="]), manager.clone(), CancellationToken::new()).await), ["cache"]);
+        assert!(names(handle_request(list(&["description~=search"]), manager.clone(), CancellationToken::new()).await).is_empty());
+
+        let response = handle_request(list(&["label=x"]), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::BAD_REQUEST);
+        assert!(response.error.unwrap().message.starts_with("Unknown filter key 'label'"));
+
+        // Jails take the same filters
+        {
+            let mut mgr = manager.lock().await;
+            mgr.add_jail("ledger").unwrap();
+            mgr.add_jail("search").unwrap();
+            mgr.set_jail_description("ledger", Some("payments".to_string())).unwrap();
+        }
+        let request = Request::get_with(Endpoint::Jails, ListJailsRequest { filter: vec!["description~=PAY".to_string()] }).unwrap();
+        assert_eq!(names(handle_request(request, manager.clone(), CancellationToken::new()).await), ["ledger"]);
+        let request = Request::get_with(Endpoint::Jails, ListJailsRequest { filter: vec!["description".to_string()] }).unwrap();
+        assert_eq!(handle_request(request, manager.clone(), CancellationToken::new()).await.status, status::BAD_REQUEST);
+        // The containers' jails are listed too, unless filtered out
+        let all = names(handle_request(Request::get(Endpoint::Jails), manager.clone(), CancellationToken::new()).await);
+        assert!(all.len() > 2 && all.contains(&"search".to_string()));
+        let request = Request::get_with(Endpoint::Jails, ListJailsRequest { filter: vec!["name=search".to_string()] }).unwrap();
+        assert_eq!(names(handle_request(request, manager, CancellationToken::new()).await), ["search"]);
+    }
+
+    #[tokio::test]
+    async fn test_create_container_rejects_taken_alias() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = Arc::new(Mutex::new(JailManager::with_database(dir.path().join("kawakaze.db")).unwrap()));
+        {
+            let mut mgr = manager.lock().await;
+            add_test_image(&mut mgr, "base", false);
+        }
+
+        let create = |name: &str, aliases: &[&str]| {
+            Request::post(
+                Endpoint::ContainerCreate,
+                serde_json::json!({ "image_id": "base", "name": name, "restart_policy": "no", "network_aliases": aliases }),
+            )
+            .unwrap()
+        };
+
+        let response = handle_request(create("db", &["postgres"]), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.data.unwrap()["network_aliases"][0], "postgres");
+
+        // Another container's name or alias is taken
+        let response = handle_request(create("web", &["postgres"]), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, 409);
+        let response = handle_request(create("postgres", &[]), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, 409);
+
+        let response = handle_request(create("web", &["web_1"]), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, 400);
+    }
+
+    #[tokio::test]
+    async fn test_list_containers_with_summary() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+        {
+            let mut mgr = manager.lock().await;
+            let image_id = add_test_image(&mut mgr, "base", false);
+            add_test_container(&mut mgr, &image_id, false);
+        }
+
+        // Without the option the response stays a plain array
+        let response = handle_request(Request::get(Endpoint::Containers), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.data.unwrap().as_array().unwrap().len(), 1);
+
+        let request = Request::get_with(Endpoint::Containers, ListContainersRequest { include_summary: true, filter: Vec::new() }).unwrap();
+        let response = handle_request(request, manager, CancellationToken::new()).await;
+        let data = response.data.unwrap();
+        assert_eq!(data["containers"].as_array().unwrap().len(), 1);
+        assert_eq!(data["summary"]["total"], 1);
+        assert_eq!(data["summary"]["created"], 1);
+        assert_eq!(data["summary"]["unhealthy"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_remove_protected_container_conflict() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+        let container_id = {
+            let mut mgr = manager.lock().await;
+            let image_id = add_test_image(&mut mgr, "base", false);
+            add_test_container(&mut mgr, &image_id, true)
+        };
+
+        // force alone does not bypass protection
+        let request = Request::delete_with(
+            Endpoint::RemoveContainer(container_id.clone()),
+            RemoveRequest { force: true, override_protection: false, ..Default::default() },
+        )
+        .unwrap();
+        let response = handle_request(request, manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::CONFLICT);
+        assert_eq!(response.error.unwrap().code, "RESOURCE_PROTECTED");
+        assert!(manager.lock().await.get_container(&container_id).is_some());
+
+        let request = Request::delete_with(
+            Endpoint::RemoveContainer(container_id.clone()),
+            RemoveRequest { force: true, override_protection: true, ..Default::default() },
+        )
+        .unwrap();
+        let response = handle_request(request, manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::OK);
+        assert!(manager.lock().await.get_container(&container_id).is_none());
+    }
+
+    fn batch_request(members: serde_json::Value) -> Request {
+        Request::post(Endpoint::ContainerBatchCreate, serde_json::json!({ "containers": members })).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_batch_create_checks_order_first() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+        add_test_image(&mut *manager.lock().await, "base", false);
+
+        let cycle = serde_json::json!([
+            { "image_id": "base", "name": "app", "depends_on": ["db"] },
+            { "image_id": "base", "name": "db", "depends_on": ["app"] },
+        ]);
+        let response = handle_request(batch_request(cycle), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::BAD_REQUEST);
+        assert!(response.error.unwrap().message.contains("cycle: app -> db -> app"));
+
+        let missing = serde_json::json!([{ "image_id": "base", "name": "app", "depends_on": ["db"] }]);
+        let response = handle_request(batch_request(missing), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::BAD_REQUEST);
+        assert!(response.error.unwrap().message.contains("neither in the batch nor running"));
+
+        // Nothing was created for either
+        assert!(manager.lock().await.list_containers().is_empty());
+
+        // depends_on needs a batch
+        let single = Request::post(Endpoint::ContainerCreate, serde_json::json!({ "image_id": "base", "depends_on": ["db"] })).unwrap();
+        let response = handle_request(single, manager, CancellationToken::new()).await;
+        assert_eq!(response.status, status::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_batch_create_starts_dependencies_first() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+        add_test_image(&mut *manager.lock().await, "base", false);
+
+        // Jails can't be started here, so the batch stops at the first start:
+        // db's, though app comes first in the request
+        let members = serde_json::json!([
+            { "image_id": "base", "name": "app", "restart_policy": "no", "depends_on": ["db"] },
+            { "image_id": "base", "name": "db", "restart_policy": "no" },
+        ]);
+        let response = handle_request(batch_request(members), manager.clone(), CancellationToken::new()).await;
+        assert!(!response.is_success());
+        let message = response.error.unwrap().message;
+        assert!(message.starts_with("Container 'db': "), "{}", message);
+
+        let mgr = manager.lock().await;
+        let names: Vec<_> = mgr.list_containers().into_iter().filter_map(|c| c.name.clone()).collect();
+        assert_eq!(names, ["db"]);
+    }
+
+    /// Poll an operation until it finishes
+    async fn wait_for_operation(manager: &Arc<Mutex<JailManager>>, id: &str) -> serde_json::Value {
+        for _ in 0..200 {
+            let request = Request::get(Endpoint::Operation(id.to_string()));
+            let response = handle_request(request, manager.clone(), CancellationToken::new()).await;
+            assert_eq!(response.status, status::OK);
+            let progress = response.data.unwrap();
+            if progress["phase"] == "complete" || progress["phase"] == "failed" {
+                return progress;
             }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
         }
-        Err(e) => Response::internal_error(format!("Failed to remove container: {}", e)),
+        panic!("operation {} didn't finish", id);
     }
-}
 
-/// Execute command in container
-async fn exec_container(manager: Arc<Mutex<JailManager>>, id_or_name: &str, exec_req: ExecRequest) -> Response {
-    let mgr = manager.lock().await;
+    #[tokio::test]
+    async fn test_async_create_and_remove_container() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+        add_test_image(&mut *manager.lock().await, "base", false);
 
-    // Find container by ID, name, or prefix
-    let id_or_name_string = id_or_name.to_string();
-    let container = mgr.get_container(&id_or_name_string)
-        .or_else(|| mgr.get_container_by_prefix(id_or_name))
-        .or_else(|| {
-            mgr.list_containers()
-                .into_iter()
-                .find(|c| c.name.as_deref() == Some(id_or_name))
-        });
+        let request = Request::post(
+            Endpoint::ContainerCreate,
+            serde_json::json!({ "image_id": "base", "name": "web", "restart_policy": "no", "async": true }),
+        )
+        .unwrap();
+        let response = handle_request(request, manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, 202);
+        assert_eq!(response.error.as_ref().unwrap().code, "OPERATION_STARTED");
+        let started: OperationStarted = serde_json::from_value(response.data.unwrap()).unwrap();
+
+        let progress = wait_for_operation(&manager, &started.id).await;
+        assert_eq!(progress["phase"], "complete", "{}", progress);
+        assert_eq!(progress["kind"], "create");
+        assert_eq!(progress["progress"], 100);
+        assert!(manager.lock().await.get_container(&started.container_id).is_some());
+
+        let request = Request::delete_with(
+            Endpoint::RemoveContainer("web".to_string()),
+            RemoveRequest { run_async: true, ..Default::default() },
+        )
+        .unwrap();
+        let response = handle_request(request, manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, 202);
+        let removing: OperationStarted = serde_json::from_value(response.data.unwrap()).unwrap();
+        assert_eq!(removing.container_id, started.container_id);
+
+        let progress = wait_for_operation(&manager, &removing.id).await;
+        assert_eq!(progress["phase"], "complete", "{}", progress);
+        assert!(manager.lock().await.get_container(&started.container_id).is_none());
+        // Finished operations no longer count as tasks
+        assert!(manager.lock().await.tasks().is_empty());
+    }
 
-    let container = match container {
-        Some(c) => c,
-        None => return Response::not_found(format!("Container '{}'", id_or_name)),
-    };
+    #[tokio::test]
+    async fn test_async_remove_refusals_stay_synchronous() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+        let (image_id, container_id) = {
+            let mut mgr = manager.lock().await;
+            let image_id = add_test_image(&mut mgr, "base", false);
+            let container_id = add_test_container(&mut mgr, &image_id, true);
+            (image_id, container_id)
+        };
 
-    // Check if container is running
-    if !container.is_running() {
-        return Response::bad_request(format!(
-            "Container '{}' is not running. Start it first.",
-            id_or_name
-        ));
+        // Protection is checked before any operation starts
+        let request = Request::delete_with(
+            Endpoint::RemoveContainer(container_id.clone()),
+            RemoveRequest { run_async: true, ..Default::default() },
+        )
+        .unwrap();
+        let response = handle_request(request, manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::CONFLICT);
+        assert!(manager.lock().await.operations.is_empty());
+
+        // Images are removed synchronously only
+        let request = Request::delete_with(Endpoint::DeleteImage(image_id), RemoveRequest { run_async: true, ..Default::default() }).unwrap();
+        let response = handle_request(request, manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::BAD_REQUEST);
+
+        let response = handle_request(Request::get(Endpoint::Operation("nope".into())), manager, CancellationToken::new()).await;
+        assert_eq!(response.status, status::NOT_FOUND);
     }
 
-    // Build environment variables, including a default PATH if not provided
-    let mut env_vars: Vec<String> = exec_req.env.iter()
-        .map(|(k, v)| format!("{}={}", k, v))
-        .collect();
+    #[tokio::test]
+    async fn test_concurrent_stop_and_remove_serialize() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+        let (container_id, locks) = {
+            let mut mgr = manager.lock().await;
+            let image_id = add_test_image(&mut mgr, "base", false);
+            let container_id = add_test_container(&mut mgr, &image_id, false);
+            mgr.containers.get_mut(&container_id).unwrap().set_state(crate::container::ContainerState::Running);
+            (container_id, mgr.container_locks.clone())
+        };
 
-    // Ensure PATH is set (critical for commands to work)
-    if !exec_req.env.contains_key("PATH") {
-        env_vars.push("PATH=/sbin:/bin:/usr/sbin:/usr/bin:/usr/local/sbin:/usr/local/bin".to_string());
+        // An operation in flight holds the container's lock
+        let in_flight = locks.lock(&container_id).await;
+        let send = |request: Request| tokio::spawn(handle_request(request, manager.clone(), CancellationToken::new()));
+        let remove = || Request::delete_with(Endpoint::RemoveContainer(container_id.clone()), RemoveRequest { force: true, ..Default::default() }).unwrap();
+        let stop = send(Request::post(Endpoint::StopContainer(container_id.clone()), ()).unwrap());
+        let removes = [send(remove()), send(remove())];
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(!stop.is_finished() && removes.iter().all(|r| !r.is_finished()));
+        // Other requests don't wait for it
+        assert!(handle_request(Request::get(Endpoint::Container(container_id.clone())), manager.clone(), CancellationToken::new()).await.is_success());
+
+        drop(in_flight);
+        let mut statuses = Vec::new();
+        for remove in removes {
+            statuses.push(remove.await.unwrap().status);
+        }
+        // One remove tears the container down; the other finds it gone
+        statuses.sort();
+        assert_eq!(statuses, [status::OK, status::NOT_FOUND]);
+        let stopped = stop.await.unwrap();
+        assert!(stopped.status == status::NOT_FOUND || stopped.error.as_ref().unwrap().code == "STOP_FAILED", "{:?}", stopped.error);
+        assert!(manager.lock().await.get_container(&container_id).is_none());
+        assert!(!locks.is_busy(&container_id));
     }
 
-    // Build the shell command
-    // We use sh -c to execute the command, which will properly load PATH and handle arguments
-    let shell_command = if let Some(ref workdir) = exec_req.workdir {
-        // Change to workdir first, then execute the command
-        format!("cd {} && exec {}", workdir, shell_words::join(&exec_req.command))
-    } else {
-        format!("exec {}", shell_words::join(&exec_req.command))
-    };
+    #[tokio::test]
+    async fn test_update_container_protection() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+        let container_id = {
+            let mut mgr = manager.lock().await;
+            let image_id = add_test_image(&mut mgr, "base", false);
+            add_test_container(&mut mgr, &image_id, false)
+        };
 
-    // Prefix with environment variable exports if needed
-    let final_command = if !env_vars.is_empty() {
-        let env_prefix = env_vars.join(" ");
-        format!("export {}; {}", env_prefix, shell_command)
-    } else {
-        shell_command
-    };
+        let request = Request::post(
+            Endpoint::UpdateContainer(container_id.clone()),
+            UpdateRequest { protected: Some(true), boot: None, read_only: None, description: None },
+        )
+        .unwrap();
+        let response = handle_request(request, manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::OK);
+        assert_eq!(response.data.unwrap()["protected"], true);
 
-    tracing::debug!("Executing in jail '{}': {}", container.jail_name, final_command);
+        let request = Request::delete(Endpoint::RemoveContainer(container_id.clone()));
+        let response = handle_request(request, manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::CONFLICT);
 
-    // Execute the command using jexec with a shell wrapper
-    // Note: We don't use -l flag here because it causes command echo issues
-    // We already set up environment variables and PATH explicitly above
-    let mut jexec_cmd = std::process::Command::new("jexec");
-    jexec_cmd.arg(&container.jail_name);
-    jexec_cmd.arg("/bin/sh");
-    jexec_cmd.arg("-c");
-    jexec_cmd.arg(&final_command);
+        let request = Request::post(
+            Endpoint::UpdateContainer(container_id.clone()),
+            UpdateRequest { protected: Some(false), boot: None, read_only: None, description: None },
+        )
+        .unwrap();
+        handle_request(request, manager.clone(), CancellationToken::new()).await;
+
+        let request = Request::delete(Endpoint::RemoveContainer(container_id));
+        let response = handle_request(request, manager, CancellationToken::new()).await;
+        assert_eq!(response.status, status::OK);
+    }
 
-    let output = match jexec_cmd.output() {
-        Ok(output) => output,
-        Err(e) => {
-            return Response::internal_error(format!("Failed to execute command: {}", e));
-        }
-    };
+    #[tokio::test]
+    async fn test_rename_container() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+        add_test_image(&mut *manager.lock().await, "base", false);
+        let create = |body: serde_json::Value| Request::post(Endpoint::ContainerCreate, body).unwrap();
+        let rename = |container: &str, name: &str| {
+            Request::post(Endpoint::RenameContainer(container.to_string()), RenameRequest { name: name.to_string() }).unwrap()
+        };
 
-    let exit_code = output.status.code().unwrap_or(-1);
-    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+        let response = handle_request(create(serde_json::json!({ "image_id": "base", "name": "web", "restart_policy": "no" })), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::CREATED, "{:?}", response.error);
+        assert_eq!(response.data.unwrap()["hostname"], "web");
+        let explicit = serde_json::json!({ "image_id": "base", "name": "db", "hostname": "db.internal", "restart_policy": "no" });
+        let response = handle_request(create(explicit), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::CREATED, "{:?}", response.error);
 
-    let result = ExecResult {
-        exit_code,
-        stdout,
-        stderr,
-    };
+        // The host name followed the name
+        let response = handle_request(rename("web", "frontend"), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::OK);
+        let data = response.data.unwrap();
+        assert_eq!((data["name"].as_str(), data["hostname"].as_str()), (Some("frontend"), Some("frontend")));
 
-    match Response::success(result) {
-        Ok(resp) => resp,
-        Err(_) => Response::internal_error("Failed to serialize exec result"),
-    }
-}
+        // An explicit one doesn't
+        let response = handle_request(rename("db", "postgres"), manager.clone(), CancellationToken::new()).await;
+        let data = response.data.unwrap();
+        assert_eq!((data["name"].as_str(), data["hostname"].as_str()), (Some("postgres"), Some("db.internal")));
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::api::{Method, status};
+        let response = handle_request(rename("postgres", "frontend"), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::CONFLICT);
+        let response = handle_request(rename("missing", "web"), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::NOT_FOUND);
 
-    fn create_test_manager() -> JailManager {
-        JailManager::new("/tmp/test-handler.sock")
+        let bad = serde_json::json!({ "image_id": "base", "name": "cache", "hostname": "not a host", "restart_policy": "no" });
+        let response = handle_request(create(bad), manager, CancellationToken::new()).await;
+        assert_eq!(response.status, status::BAD_REQUEST);
     }
 
     #[tokio::test]
-    async fn test_handle_request_list_jails() {
+    async fn test_container_ips() {
         let manager = Arc::new(Mutex::new(create_test_manager()));
-
-        // Add some jails
+        add_test_image(&mut *manager.lock().await, "base", false);
+        for name in ["web", "db", "vnet"] {
+            let create = Request::post(Endpoint::ContainerCreate, serde_json::json!({ "image_id": "base", "name": name, "restart_policy": "no" })).unwrap();
+            let response = handle_request(create, manager.clone(), CancellationToken::new()).await;
+            assert_eq!(response.status, status::CREATED, "{:?}", response.error);
+        }
         {
             let mut mgr = manager.lock().await;
-            mgr.add_jail("jail1").unwrap();
-            mgr.add_jail("jail2").unwrap();
+            let vnet = mgr.containers.values_mut().find(|c| c.name.as_deref() == Some("vnet")).unwrap();
+            vnet.ip = Some("10.11.0.5".to_string());
         }
+        let body = |ip: &str| ContainerIpRequest { ip: ip.to_string() };
+        let add = |container: &str, ip: &str| Request::post(Endpoint::AddContainerIp(container.to_string()), body(ip)).unwrap();
+        let remove = |container: &str, ip: &str| Request::delete_with(Endpoint::RemoveContainerIp(container.to_string()), body(ip)).unwrap();
+        let send = |request: Request| handle_request(request, manager.clone(), CancellationToken::new());
+
+        let response = send(add("web", "192.0.2.10")).await;
+        assert_eq!(response.status, status::OK, "{:?}", response.error);
+        assert_eq!(response.data.unwrap()["ip_aliases"], serde_json::json!(["192.0.2.10"]));
+        let response = send(add("web", "192.0.2.11")).await;
+        assert_eq!(response.data.unwrap()["ip_aliases"], serde_json::json!(["192.0.2.10", "192.0.2.11"]));
+
+        // An address belongs to one container, VNET ones included
+        assert_eq!(send(add("web", "192.0.2.10")).await.status, status::CONFLICT);
+        assert_eq!(send(add("db", "192.0.2.10")).await.status, status::CONFLICT);
+        assert_eq!(send(add("db", "10.11.0.5")).await.status, status::CONFLICT);
+        assert_eq!(send(add("vnet", "192.0.2.20")).await.status, status::BAD_REQUEST);
+        assert_eq!(send(add("web", "192.0.2")).await.status, status::BAD_REQUEST);
+        assert_eq!(send(add("missing", "192.0.2.20")).await.status, status::NOT_FOUND);
+
+        assert_eq!(send(remove("db", "192.0.2.10")).await.status, status::NOT_FOUND);
+        let response = send(remove("web", "192.0.2.10")).await;
+        assert_eq!(response.status, status::OK);
+        assert_eq!(response.data.unwrap()["ip_aliases"], serde_json::json!(["192.0.2.11"]));
+        let response = send(remove("web", "192.0.2.11")).await;
+        assert!(response.data.unwrap().get("ip_aliases").is_none());
+
+        // IPv6 aliases go to ip6.addr and are kept in canonical form
+        let response = send(add("web", "2001:DB8:0::10")).await;
+        assert_eq!(response.status, status::OK, "{:?}", response.error);
+        assert_eq!(response.data.unwrap()["ip_aliases"], serde_json::json!(["2001:db8::10"]));
+        assert_eq!(send(add("db", "2001:db8::10")).await.status, status::CONFLICT);
+        {
+            let mut mgr = manager.lock().await;
+            let vnet = mgr.containers.values_mut().find(|c| c.name.as_deref() == Some("vnet")).unwrap();
+            vnet.ip6 = Some("fd00:6b7a::5".to_string());
+        }
+        assert_eq!(send(add("db", "fd00:6b7a::5")).await.status, status::CONFLICT);
+        assert_eq!(send(remove("web", "2001:db8::10")).await.status, status::OK);
+    }
 
-        let request = Request::get(crate::api::Endpoint::Jails);
-        let response = handle_request(request, manager).await;
+    #[tokio::test]
+    async fn test_update_read_only_root() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+        let container_id = {
+            let mut mgr = manager.lock().await;
+            let image_id = add_test_image(&mut mgr, "base", false);
+            add_test_container(&mut mgr, &image_id, false)
+        };
+        let update = |read_only: bool| {
+            Request::post(
+                Endpoint::UpdateContainer(container_id.clone()),
+                UpdateRequest { protected: Some(true), boot: None, read_only: Some(read_only), description: None },
+            )
+            .unwrap()
+        };
 
+        let response = handle_request(update(true), manager.clone(), CancellationToken::new()).await;
         assert_eq!(response.status, status::OK);
-        assert!(response.is_success());
-        assert!(response.data.is_some());
+        assert_eq!(response.data.unwrap()["read_only_root"], true);
+
+        // Refused while running, before any other field is applied
+        manager.lock().await.containers.get_mut(&container_id).unwrap().set_state(crate::container::ContainerState::Running);
+        manager.lock().await.set_container_protected(&container_id, false).unwrap();
+        let response = handle_request(update(false), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::CONFLICT);
+        let container = manager.lock().await.get_container(&container_id).cloned().unwrap();
+        assert!(container.read_only_root && !container.protected);
     }
 
     #[tokio::test]
-    async fn test_handle_request_get_jail() {
-        let manager = Arc::new(Mutex::new(create_test_manager()));
+    async fn test_boot_flag_create_update_and_list() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = Arc::new(Mutex::new(JailManager::with_database(dir.path().join("kawakaze.db")).unwrap()));
+        add_test_image(&mut *manager.lock().await, "base", false);
+
+        let create = |name: &str, boot: bool| {
+            Request::post(
+                Endpoint::ContainerCreate,
+                serde_json::json!({ "image_id": "base", "name": name, "restart_policy": "no", "boot": boot }),
+            )
+            .unwrap()
+        };
+        let pg = handle_request(create("pg", true), manager.clone(), CancellationToken::new()).await.data.unwrap();
+        assert_eq!(pg["boot"], true);
+        let web = handle_request(create("web", false), manager.clone(), CancellationToken::new()).await.data.unwrap();
+        let web_id = web["id"].as_str().unwrap().to_string();
 
+        let request = Request::post(Endpoint::UpdateContainer(web_id.clone()), UpdateRequest { protected: None, boot: Some(true), read_only: None, description: None }).unwrap();
+        let response = handle_request(request, manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.data.unwrap()["boot"], true);
+
+        // The flag is persisted
         {
-            let mut mgr = manager.lock().await;
-            mgr.add_jail("test_jail").unwrap();
+            let mgr = manager.lock().await;
+            assert!(mgr.store.as_ref().unwrap().get_container(&web_id).unwrap().unwrap().boot);
         }
 
-        let request = Request::get(crate::api::Endpoint::Jail("test_jail".into()));
-        let response = handle_request(request, manager).await;
-
+        let response = handle_request(Request::get(Endpoint::SystemBootList), manager.clone(), CancellationToken::new()).await;
         assert_eq!(response.status, status::OK);
-        assert!(response.is_success());
+        let list: crate::api::BootList = serde_json::from_value(response.data.unwrap()).unwrap();
+        let names: Vec<_> = list.containers.iter().map(|c| c.name.as_deref().unwrap()).collect();
+        assert_eq!(names.len(), 2);
+        assert!(names.contains(&"pg") && names.contains(&"web"));
+        assert!(list.last_boot.is_none());
+
+        // Images can't be started at boot
+        let request = Request::post(Endpoint::UpdateImage("base".to_string()), UpdateRequest { protected: None, boot: Some(true), read_only: None, description: None }).unwrap();
+        assert_eq!(handle_request(request, manager, CancellationToken::new()).await.status, status::BAD_REQUEST);
     }
 
     #[tokio::test]
-    async fn test_handle_request_get_jail_not_found() {
-        let manager = Arc::new(Mutex::new(create_test_manager()));
+    async fn test_top_requires_running_container() {
+        let manager = Arc::new(Mutex::new(JailManager::new("/tmp/kawakaze-top-test.sock")));
+        let image_id = add_test_image(&mut *manager.lock().await, "base", false);
+        let id = add_test_container(&mut *manager.lock().await, &image_id, false);
 
-        let request = Request::get(crate::api::Endpoint::Jail("nonexistent".into()));
-        let response = handle_request(request, manager).await;
+        let response = handle_request(Request::get(Endpoint::ContainerTop(id)), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::CONFLICT);
+        assert!(response.error.unwrap().message.contains("not running"));
 
+        let response = handle_request(Request::get(Endpoint::ContainerTop("missing".to_string())), manager, CancellationToken::new()).await;
         assert_eq!(response.status, status::NOT_FOUND);
-        assert!(!response.is_success());
     }
 
     #[tokio::test]
-    async fn test_handle_request_create_jail() {
-        let manager = Arc::new(Mutex::new(create_test_manager()));
+    async fn test_encrypted_container_and_locked_start() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = Arc::new(Mutex::new(JailManager::with_database(dir.path().join("kawakaze.db")).unwrap()));
+        add_test_image(&mut *manager.lock().await, "base", false);
 
-        let create_req = CreateJailRequest {
-            name: "new_jail".into(),
-            path: Some("/tmp/new_jail".into()),
-            ip: Some("192.168.1.100".into()),
-            bootstrap: None,
+        let request = Request::post(
+            Endpoint::ContainerCreate,
+            serde_json::json!({ "image_id": "base", "name": "vault", "restart_policy": "no", "encrypted": true }),
+        )
+        .unwrap();
+        let response = handle_request(request, manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::CREATED);
+        let data = response.data.unwrap();
+        assert_eq!(data["encrypted"], true);
+        assert_eq!(data["full_copy"], true);
+        assert!(data["warnings"][0].as_str().unwrap().contains("send/receive"));
+        let id = data["id"].as_str().unwrap().to_string();
+
+        // A missing key locks the container rather than failing the daemon
+        {
+            let mut mgr = manager.lock().await;
+            let stored = mgr.store.as_ref().unwrap().get_container(&id).unwrap().unwrap();
+            assert!(stored.encrypted && stored.full_copy);
+
+            let dataset = mgr.get_container(&id).unwrap().dataset.clone();
+            let statuses = [crate::zfs::KeyStatus { dataset: dataset.clone(), encryption_root: Some(dataset), available: false }];
+            let locked = crate::zfs::load_missing_keys(&statuses, |root| {
+                Err(crate::zfs::ZfsError::CommandFailed(format!("no key for {}", root)))
+            });
+            assert_eq!(mgr.lock_containers(&locked), [id.clone()]);
+        }
+
+        let response = handle_request(Request::post(Endpoint::StartContainer(id.clone()), ()).unwrap(), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::CONFLICT);
+        assert!(response.error.unwrap().message.contains("locked"));
+
+        let response = handle_request(Request::get(Endpoint::Container(id)), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.data.unwrap()["state"], "locked");
+    }
+
+    /// A process leading its own group, as a session's `jexec` does, reaped
+    /// as soon as it exits
+    fn session_process() -> (u32, std::thread::JoinHandle<std::process::ExitStatus>) {
+        use std::os::unix::process::CommandExt;
+        let mut child = std::process::Command::new("sleep").arg("30").process_group(0).spawn().unwrap();
+        (child.id(), std::thread::spawn(move || child.wait().unwrap()))
+    }
+
+    #[tokio::test]
+    async fn test_container_name_colliding_with_id_prefix() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = Arc::new(Mutex::new(JailManager::with_database(dir.path().join("kawakaze.db")).unwrap()));
+        add_test_image(&mut *manager.lock().await, "base", false);
+        let mut ids = Vec::new();
+        for name in ["web", "db"] {
+            let create = Request::post(Endpoint::ContainerCreate, serde_json::json!({ "image_id": "base", "name": name, "restart_policy": "no" })).unwrap();
+            let response = handle_request(create, manager.clone(), CancellationToken::new()).await;
+            ids.push(response.data.unwrap()["id"].as_str().unwrap().to_string());
+        }
+        // New names can't look like an ID prefix, but an older record may
+        let prefix = ids[0][..8].to_string();
+        manager.lock().await.containers.get_mut(&ids[1]).unwrap().name = Some(prefix.clone());
+        let get = |reference: &str| handle_request(Request::get(Endpoint::Container(reference.to_string())), manager.clone(), CancellationToken::new());
+
+        let response = get(&prefix).await;
+        assert_eq!(response.status, status::BAD_REQUEST);
+        let error = response.error.unwrap();
+        assert_eq!(error.code, "AMBIGUOUS_REFERENCE");
+        assert!(error.message.contains(&ids[0]), "{}", error.message);
+
+        // The full ID, and names and prefixes that match one container, resolve
+        assert_eq!(get(&ids[0]).await.data.unwrap()["name"], "web");
+        assert_eq!(get(&ids[1]).await.data.unwrap()["name"], prefix.as_str());
+        assert_eq!(get("web").await.data.unwrap()["id"], ids[0].as_str());
+        assert_eq!(get(&ids[1][..8]).await.data.unwrap()["id"], ids[1].as_str());
+        assert_eq!(get("nope").await.status, status::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_exec_sessions() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = Arc::new(Mutex::new(JailManager::with_database(dir.path().join("kawakaze.db")).unwrap()));
+        add_test_image(&mut *manager.lock().await, "base", false);
+        let mut ids = Vec::new();
+        for name in ["web", "db"] {
+            let create = Request::post(Endpoint::ContainerCreate, serde_json::json!({ "image_id": "base", "name": name, "restart_policy": "no" })).unwrap();
+            let response = handle_request(create, manager.clone(), CancellationToken::new()).await;
+            ids.push(response.data.unwrap()["id"].as_str().unwrap().to_string());
+        }
+        let send = |request: Request| handle_request(request, manager.clone(), CancellationToken::new());
+        let register = |container: &str, pid: u32| {
+            let body = serde_json::json!({ "container": container, "command": ["/bin/sh"], "kind": "pty", "pid": pid });
+            Request::post(Endpoint::ExecSessions, body).unwrap()
         };
+        let listed = |response: Response| response.data.unwrap().as_array().unwrap().len();
 
-        let request = Request::post(crate::api::Endpoint::Jails, create_req).unwrap();
-        let response = handle_request(request, manager).await;
+        let (shell, shell_reaper) = session_process();
+        let response = send(register("web", shell)).await;
+        assert_eq!(response.error.unwrap().code, "CONTAINER_NOT_RUNNING");
+        for id in &ids {
+            manager.lock().await.containers.get_mut(id).unwrap().set_state(crate::container::ContainerState::Running);
+        }
+        assert_eq!(send(register("web", 0)).await.error.unwrap().code, "BAD_REQUEST");
+        assert_eq!(send(register("nope", shell)).await.error.unwrap().code, "CONTAINER_NOT_FOUND");
+
+        // The API's own exec ends its session however it finishes
+        let exec = Request::post(Endpoint::ContainerExec("web".to_string()), serde_json::json!({ "command": ["true"] })).unwrap();
+        send(exec).await;
+        assert_eq!(listed(send(Request::get(Endpoint::ExecSessions)).await), 0);
 
+        // Registered, listed, and ended by the CLI
+        let response = send(register("web", shell)).await;
         assert_eq!(response.status, status::CREATED);
-        assert!(response.is_success());
+        let session = response.data.unwrap();
+        assert_eq!((session["container_id"].as_str(), session["pid"].as_u64()), (Some(ids[0].as_str()), Some(shell as u64)));
+        let id = session["id"].as_str().unwrap().to_string();
+        assert_eq!(listed(send(Request::get(Endpoint::ExecSessions)).await), 1);
+        assert_eq!(listed(send(Request::get(Endpoint::ContainerExecSessions("web".into()))).await), 1);
+        assert_eq!(listed(send(Request::get(Endpoint::ContainerExecSessions("db".into()))).await), 0);
+        assert_eq!(send(Request::delete(Endpoint::EndExecSession(id.clone()))).await.status, status::OK);
+        assert_eq!(send(Request::delete(Endpoint::EndExecSession(id))).await.error.unwrap().code, "NOT_FOUND");
+
+        // Killed on request
+        let id = send(register("web", shell)).await.data.unwrap()["id"].as_str().unwrap().to_string();
+        let response = send(Request::post(Endpoint::ExecSessionKill(id.clone()), ()).unwrap()).await;
+        assert_eq!(response.status, status::OK, "{:?}", response.error);
+        assert_eq!(response.data.unwrap()["termination"], "terminated");
+        assert!(!shell_reaper.join().unwrap().success());
+        assert_eq!(send(Request::post(Endpoint::ExecSessionKill(id), ()).unwrap()).await.error.unwrap().code, "NOT_FOUND");
+        assert_eq!(listed(send(Request::get(Endpoint::ExecSessions)).await), 0);
+
+        // Stopping a container kills its sessions first, and only its own
+        let ((web, web_reaper), (db, db_reaper)) = (session_process(), session_process());
+        send(register("web", web)).await;
+        send(register("db", db)).await;
+        send(Request::post(Endpoint::StopContainer("web".into()), ()).unwrap()).await;
+        assert!(!web_reaper.join().unwrap().success());
+        let left = send(Request::get(Endpoint::ExecSessions)).await.data.unwrap();
+        assert_eq!(left.as_array().unwrap().iter().map(|s| s["container"].as_str().unwrap()).collect::<Vec<_>>(), ["db"]);
+
+        // A session whose process is gone is swept
+        unsafe { libc::kill(db as libc::pid_t, libc::SIGKILL) };
+        db_reaper.join().unwrap();
+        assert_eq!(manager.lock().await.exec_sessions().sweep(), 1);
+        assert_eq!(listed(send(Request::get(Endpoint::ExecSessions)).await), 0);
     }
 
     #[tokio::test]
-    async fn test_handle_request_create_jail_invalid_name() {
+    async fn test_container_with_missing_dataset() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = Arc::new(Mutex::new(JailManager::with_database(dir.path().join("kawakaze.db")).unwrap()));
+        add_test_image(&mut *manager.lock().await, "base", false);
+
+        // Every dataset exists but the ones in `destroyed`
+        let destroyed = Arc::new(std::sync::Mutex::new(std::collections::HashSet::new()));
+        let gone = Arc::clone(&destroyed);
+        manager.lock().await.datasets = crate::dataset_health::DatasetView::new(
+            move |dataset: &str| Ok(!gone.lock().unwrap().contains(dataset)),
+            std::time::Duration::ZERO,
+        );
+
+        let mut ids = Vec::new();
+        for name in ["web", "db"] {
+            let create = Request::post(Endpoint::ContainerCreate, serde_json::json!({ "image_id": "base", "name": name, "restart_policy": "no" })).unwrap();
+            let response = handle_request(create, manager.clone(), CancellationToken::new()).await;
+            assert_eq!(response.status, status::CREATED);
+            ids.push(response.data.unwrap()["id"].as_str().unwrap().to_string());
+        }
+        let dataset = manager.lock().await.get_container(&ids[0]).unwrap().dataset.clone();
+        // zfs destroy -r, behind the daemon's back
+        destroyed.lock().unwrap().insert(dataset);
+
+        let request = || Request::get_with(Endpoint::Containers, ListContainersRequest { include_summary: true, filter: Vec::new() }).unwrap();
+        let response = handle_request(request(), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::OK);
+        let listed = response.data.unwrap()["containers"].as_array().unwrap().clone();
+        let missing: Vec<(&str, bool)> = listed
+            .iter()
+            .map(|c| (c["name"].as_str().unwrap(), c["dataset_missing"] == true))
+            .collect();
+        assert_eq!(missing.len(), 2);
+        assert!(missing.contains(&("web", true)) && missing.contains(&("db", false)));
+
+        let response = handle_request(Request::get(Endpoint::Container("web".to_string())), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::OK);
+        assert_eq!(response.data.unwrap()["dataset_missing"], true);
+
+        // What needs the dataset is refused with a specific conflict
+        let response = handle_request(Request::post(Endpoint::StartContainer("web".to_string()), ()).unwrap(), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::CONFLICT);
+        let error = response.error.unwrap();
+        assert_eq!(error.code, "DATASET_MISSING");
+        assert!(error.message.contains("destroyed outside kawakaze"), "{}", error.message);
+        let exec = Request::post(Endpoint::ContainerExec("web".to_string()), serde_json::json!({ "command": ["true"] })).unwrap();
+        let response = handle_request(exec, manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.error.unwrap().code, "DATASET_MISSING");
+
+        // Remove still works, and the other container is untouched
+        let remove = Request::delete(Endpoint::RemoveContainer("web".to_string()));
+        let response = handle_request(remove, manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::OK, "{:?}", response.error);
+        let response = handle_request(request(), manager.clone(), CancellationToken::new()).await;
+        let listed = response.data.unwrap()["containers"].as_array().unwrap().clone();
+        assert_eq!(listed.len(), 1);
+        // Only sent when true
+        assert!(listed[0].get("dataset_missing").is_none());
+    }
+
+    fn build_request(name: &str, dockerfile: &str, wait_for_base: bool) -> Request {
+        Request::post(
+            Endpoint::ImageBuild,
+            BuildImageRequest {
+                name: name.to_string(),
+                dockerfile: dockerfile.to_string(),
+                build_args: std::collections::HashMap::new(),
+                protected: false,
+                wait_for_base,
+                strict_vars: false,
+                secrets: std::collections::HashMap::new(),
+                no_cache: false,
+                network: Default::default(),
+            },
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_build_image_already_building_conflict() {
         let manager = Arc::new(Mutex::new(create_test_manager()));
+        manager.lock().await.begin_build("api");
 
-        let create_req = CreateJailRequest {
-            name: "invalid name!".into(),
-            path: None,
-            ip: None,
-            bootstrap: None,
+        let response = handle_request(build_request("api", "FROM scratch\n", false), manager, CancellationToken::new()).await;
+        assert_eq!(response.status, status::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn test_identical_build_reuses_image() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = Arc::new(Mutex::new(JailManager::with_database(dir.path().join("kawakaze.db")).unwrap()));
+        let dockerfile = "FROM scratch\nRUN echo built\n";
+
+        // What a completed first build of the Dockerfile leaves behind
+        let instructions = crate::image_builder::parse_dockerfile(dockerfile, &Default::default(), &Default::default(), false).unwrap();
+        let digest = crate::image_builder::content_digest(
+            &instructions,
+            "scratch",
+            std::path::Path::new(crate::image_builder::DEFAULT_BUILD_CONTEXT),
+        )
+        .unwrap();
+        let first = Image::new("api".to_string(), instructions)
+            .with_state(crate::image::ImageState::Available)
+            .with_content_digest(digest.clone());
+        let first_id = first.id.clone();
+        manager.lock().await.add_image(first).unwrap();
+
+        // The second build returns it without building: no build is
+        // registered and no dataset is needed (there's no ZFS here)
+        let response = handle_request(build_request("api", dockerfile, false), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::OK);
+        let data = response.data.unwrap();
+        assert_eq!(data["id"], first_id.as_str());
+        assert_eq!(data["cached"], true);
+        {
+            let mgr = manager.lock().await;
+            assert_eq!(mgr.images.len(), 1);
+            assert!(mgr.image_build_progress.is_empty());
+            assert!(!mgr.is_building("api"));
+            let row = mgr.store.as_ref().unwrap().get_image(&first_id).unwrap().unwrap();
+            assert_eq!(row.content_digest.as_deref(), Some(digest.as_str()));
+        }
+
+        // A changed Dockerfile or --no-cache builds again
+        let response = handle_request(build_request("api", "FROM scratch\nRUN echo changed\n", false), manager.clone(), CancellationToken::new()).await;
+        assert!(response.error.unwrap().message.contains("ZFS"));
+        let mut request = build_request("api", dockerfile, false);
+        request.body["no_cache"] = serde_json::json!(true);
+        let response = handle_request(request, manager, CancellationToken::new()).await;
+        assert!(response.error.unwrap().message.contains("ZFS"));
+    }
+
+    #[tokio::test]
+    async fn test_scratch_only_and_from_only_images() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = Arc::new(Mutex::new(JailManager::with_database(dir.path().join("kawakaze.db")).unwrap()));
+        let context = std::path::Path::new(crate::image_builder::DEFAULT_BUILD_CONTEXT);
+        let parse = |dockerfile: &str| crate::image_builder::parse_dockerfile(dockerfile, &Default::default(), &Default::default(), false).unwrap();
+
+        let mut config = crate::image::ImageConfig::default();
+        config.cmd = Some(vec!["/bin/sh".to_string()]);
+        let base = Image::new("base".to_string(), parse("FROM scratch\nRUN echo base\n"))
+            .with_state(crate::image::ImageState::Available)
+            .with_config(config.clone())
+            .with_size(8192);
+        let base_id = base.id.clone();
+        manager.lock().await.add_image(base).unwrap();
+
+        // What completed builds of `FROM scratch` and `FROM base` leave
+        // behind: no steps at all, and the one FROM step
+        for (name, dockerfile, base) in [("empty", "FROM scratch\n", "scratch"), ("retag", "FROM base\n", base_id.as_str())] {
+            let instructions = parse(dockerfile);
+            let digest = crate::image_builder::content_digest(&instructions, base, context).unwrap();
+            let mut image = Image::new(name.to_string(), instructions)
+                .with_state(crate::image::ImageState::Available)
+                .with_size(4096)
+                .with_content_digest(digest);
+            if name == "retag" {
+                image = image.with_parent(base_id.clone()).with_config(config.clone());
+            }
+            manager.lock().await.add_image(image).unwrap();
+
+            // Building them again finds them up to date
+            let response = handle_request(build_request(name, dockerfile, false), manager.clone(), CancellationToken::new()).await;
+            assert_eq!(response.status, status::OK, "{}", name);
+            assert_eq!(response.data.unwrap()["cached"], true);
+        }
+
+        // Their history lists their steps without dividing by zero
+        let history = |name: &str| {
+            let manager = manager.clone();
+            let name = name.to_string();
+            async move {
+                let response = handle_request(Request::get(Endpoint::ImageHistory(name)), manager, CancellationToken::new()).await;
+                assert_eq!(response.status, status::OK);
+                response.data.unwrap().as_array().unwrap().clone()
+            }
         };
+        assert!(history("empty").await.is_empty());
+        let retag = history("retag").await;
+        assert_eq!(retag.len(), 1);
+        assert_eq!(retag[0]["created_by"], "FROM base");
+        assert_eq!(retag[0]["size_bytes"], 4096);
 
-        let request = Request::post(crate::api::Endpoint::Jails, create_req).unwrap();
-        let response = handle_request(request, manager).await;
+        // The copy runs what its base runs
+        let mgr = manager.lock().await;
+        let retag = mgr.get_image_by_name("retag").unwrap();
+        assert_eq!(retag.parent_id.as_deref(), Some(base_id.as_str()));
+        assert_eq!(retag.config.cmd, config.cmd);
 
+        // A Dockerfile of only comments names no base at all
+        drop(mgr);
+        let response = handle_request(build_request("nothing", "# TODO\n\n", false), manager, CancellationToken::new()).await;
         assert_eq!(response.status, status::BAD_REQUEST);
-        assert!(!response.is_success());
+        assert!(response.error.unwrap().message.contains("FROM scratch"));
     }
 
     #[tokio::test]
-    async fn test_handle_request_create_jail_duplicate() {
+    async fn test_build_image_rejects_bad_secrets() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+
+        let mut request = build_request("api", "FROM scratch\n", false);
+        request.body["secrets"] = serde_json::json!({ "../token": "x" });
+        let response = handle_request(request, manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::BAD_REQUEST);
+
+        let mut request = build_request("api", "FROM scratch\n", false);
+        request.body["secrets"] = serde_json::json!({ "TOKEN": "x" });
+        request.body["build_args"] = serde_json::json!({ "TOKEN": "y" });
+        let response = handle_request(request, manager, CancellationToken::new()).await;
+        assert_eq!(response.status, status::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_image_containers_lists_dependents() {
         let manager = Arc::new(Mutex::new(create_test_manager()));
+        let (image_id, other_id) = {
+            let mut mgr = manager.lock().await;
+            let image = Image::new("web".to_string(), Vec::new()).with_state(crate::image::ImageState::Available);
+            let other = Image::new("db".to_string(), Vec::new()).with_state(crate::image::ImageState::Available);
+            let ids = (image.id.clone(), other.id.clone());
+            mgr.add_image(image).unwrap();
+            mgr.add_image(other).unwrap();
+
+            for (name, image_id, created_at, running) in [("web-1", &ids.0, 1, true), ("web-2", &ids.0, 2, false), ("db-1", &ids.1, 3, true)] {
+                let mut container = crate::container::Container::new(image_id.clone(), format!("kawakaze-{}", name), format!("tank/containers/{}", name))
+                    .with_name(name.to_string());
+                container.created_at = Timestamp::from_secs(created_at);
+                if running {
+                    container.set_state(crate::container::ContainerState::Running);
+                }
+                mgr.containers.insert(container.id.clone(), container);
+            }
+            ids
+        };
 
+        let response = handle_request(Request::get(Endpoint::ImageContainers("web".to_string())), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::OK);
+        let items: Vec<ContainerListItem> = serde_json::from_value(response.data.unwrap()).unwrap();
+        let listed: Vec<_> = items.iter().map(|c| (c.name.as_deref().unwrap(), c.state.as_str())).collect();
+        assert_eq!(listed, [("web-1", "running"), ("web-2", "created")]);
+        assert!(items.iter().all(|c| c.image_id == image_id));
+
+        // Also by ID, and an image nothing uses
+        let response = handle_request(Request::get(Endpoint::ImageContainers(other_id)), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.data.unwrap().as_array().unwrap().len(), 1);
+        let response = handle_request(Request::get(Endpoint::ImageContainers("missing".to_string())), manager, CancellationToken::new()).await;
+        assert_eq!(response.status, status::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_start_container_published_port_conflict() {
+        use crate::container::{PortMapping, PortProtocol};
+
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+        let port = std::net::TcpListener::bind((std::net::Ipv4Addr::UNSPECIFIED, 0)).unwrap().local_addr().unwrap().port();
         {
             let mut mgr = manager.lock().await;
-            mgr.add_jail("existing_jail").unwrap();
+            for name in ["web", "web-copy"] {
+                let container = crate::container::Container::new("img".to_string(), format!("kawakaze-{}", name), format!("tank/containers/{}", name))
+                    .with_name(name.to_string())
+                    .with_port_mapping(PortMapping::new(port, 80, PortProtocol::Tcp));
+                mgr.containers.insert(container.id.clone(), container);
+            }
+            let web = mgr.list_containers().into_iter().find(|c| c.name.as_deref() == Some("web")).unwrap().clone();
+            mgr.reserve_ports(&web.id, &web.port_mappings).unwrap();
         }
 
-        let create_req = CreateJailRequest {
-            name: "existing_jail".into(),
-            path: None,
-            ip: None,
-            bootstrap: None,
+        let response = handle_request(Request::post(Endpoint::StartContainer("web-copy".to_string()), ()).unwrap(), manager, CancellationToken::new()).await;
+        assert_eq!(response.status, status::CONFLICT);
+        assert_eq!(response.error.unwrap().message, format!("host port {}/tcp already in use by container web", port));
+    }
+
+    #[tokio::test]
+    async fn test_names_cannot_take_id_forms() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+        let (image_id, container_id) = {
+            let mut mgr = manager.lock().await;
+            let image = Image::new("web".to_string(), Vec::new()).with_state(crate::image::ImageState::Available);
+            let image_id = image.id.clone();
+            mgr.add_image(image).unwrap();
+            let container = crate::container::Container::new(image_id.clone(), "kawakaze-web".to_string(), "tank/containers/web".to_string());
+            let container_id = container.id.clone();
+            mgr.containers.insert(container_id.clone(), container);
+            (image_id, container_id)
+        };
+        let create = |name: &str| {
+            Request::post(Endpoint::ContainerCreate, serde_json::json!({ "image_id": "web", "name": name })).unwrap()
         };
 
-        let request = Request::post(crate::api::Endpoint::Jails, create_req).unwrap();
-        let response = handle_request(request, manager).await;
+        // A name equal to an existing ID prefix, dashes included
+        for name in [&container_id[..11], &image_id[..3], "deadbeef"] {
+            let response = handle_request(create(name), manager.clone(), CancellationToken::new()).await;
+            assert_eq!(response.status, status::BAD_REQUEST, "{}", name);
+        }
+        let response = handle_request(build_request(&container_id[..8], "FROM scratch\n", false), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::BAD_REQUEST);
+        assert!(response.error.unwrap().message.contains("hex digits"));
+    }
 
-        assert_eq!(response.status, status::CONFLICT);
-        assert!(!response.is_success());
+    #[tokio::test]
+    async fn test_image_history_never_shows_secrets() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+        let secrets = std::collections::HashMap::from([("NPM_TOKEN".to_string(), "npm_s3cr3t".to_string())]);
+
+        // What the builder records for a build run with --secret id=NPM_TOKEN
+        let instructions = crate::image_builder::scrub_secrets(
+            vec![
+                crate::image::DockerfileInstruction::From("scratch".to_string()),
+                crate::image::DockerfileInstruction::Run("npm install --token=$NPM_TOKEN".to_string()),
+                crate::image::DockerfileInstruction::Run("echo npm_s3cr3t".to_string()),
+            ],
+            &secrets,
+        );
+        let image = Image::new("app".to_string(), instructions).with_state(crate::image::ImageState::Available);
+        manager.lock().await.add_image(image).unwrap();
+
+        let stored = serde_json::to_string(&manager.lock().await.get_image_by_name("app").unwrap().dockerfile).unwrap();
+        assert!(!stored.contains("npm_s3cr3t"));
+
+        let response = handle_request(Request::get(Endpoint::ImageHistory("app".to_string())), manager, CancellationToken::new()).await;
+        assert_eq!(response.status, status::OK);
+        let history = serde_json::to_string(&response.data).unwrap();
+        assert!(!history.contains("npm_s3cr3t"));
+        assert!(history.contains("$NPM_TOKEN"));
     }
 
     #[tokio::test]
-    async fn test_handle_request_invalid_endpoint() {
+    async fn test_build_image_existing_name_is_not_a_conflict() {
         let manager = Arc::new(Mutex::new(create_test_manager()));
+        let existing = Image::new("api".to_string(), Vec::new()).with_state(crate::image::ImageState::Available);
+        manager.lock().await.add_image(existing).unwrap();
 
-        let request = Request {
-            method: Method::Get,
-            endpoint: "invalid/endpoint".to_string(),
-            body: serde_json::Value::Null,
-        };
+        // The rebuild goes ahead (and only fails here for lack of ZFS)
+        let response = handle_request(build_request("api", "FROM scratch\n", false), manager, CancellationToken::new()).await;
+        assert_ne!(response.status, status::CONFLICT);
+        assert!(response.error.unwrap().message.contains("ZFS"));
+    }
+
+    #[tokio::test]
+    async fn test_build_image_checks_name() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+        let existing = Image::new("web".to_string(), Vec::new()).with_state(crate::image::ImageState::Available);
+        manager.lock().await.add_image(existing).unwrap();
+        let build = |name: &str| build_request(name, "FROM scratch\n", false);
 
-        let response = handle_request(request, manager).await;
+        for name in ["", "  ", "Web", "web app", "web:", "freebsd:14.1", &"a".repeat(300)] {
+            let response = handle_request(build(name), manager.clone(), CancellationToken::new()).await;
+            assert_eq!(response.status, status::BAD_REQUEST, "{:?}", name);
+        }
 
-        assert_eq!(response.status, status::BAD_REQUEST);
+        // `web:latest` is another spelling of the existing `web`
+        let response = handle_request(build("web:latest"), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::CONFLICT);
+        assert!(response.error.unwrap().message.contains("'web'"));
+
+        // Other tags and repositories go ahead (and only fail here for lack of ZFS)
+        for name in ["repo/web:1.0", "web:2"] {
+            let response = handle_request(build(name), manager.clone(), CancellationToken::new()).await;
+            assert!(response.error.unwrap().message.contains("ZFS"), "{}", name);
+        }
     }
 
     #[tokio::test]
-    async fn test_handle_request_unsupported_method() {
+    async fn test_build_image_base_still_building() {
         let manager = Arc::new(Mutex::new(create_test_manager()));
+        manager.lock().await.begin_build("base");
 
-        // DELETE on /jails is not supported
-        let request = Request::delete(crate::api::Endpoint::Jails);
-        let response = handle_request(request, manager).await;
+        // Without wait_for_base a dependent build is rejected
+        let response = handle_request(build_request("web", "FROM base\n", false), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::CONFLICT);
 
-        assert_eq!(response.status, status::BAD_REQUEST);
+        // With it, the build is queued and only fails later for lack of ZFS here
+        let response = handle_request(build_request("web", "FROM base\n", true), manager, CancellationToken::new()).await;
+        assert_ne!(response.status, status::CONFLICT);
+        assert!(response.error.unwrap().message.contains("ZFS"));
     }
 
     #[tokio::test]
-    async fn test_list_jails_empty() {
+    async fn test_get_build_status() {
         let manager = Arc::new(Mutex::new(create_test_manager()));
-        let response = list_jails(manager).await;
+        manager.lock().await.image_build_progress.insert(
+            "build-1".to_string(),
+            ImageBuildProgress {
+                image_id: "build-1".to_string(),
+                step: 1,
+                total_steps: 3,
+                current_instruction: "RUN make".to_string(),
+                status: crate::image_builder::BuildStatus::Building,
+                copy: None,
+            },
+        );
 
+        let request = Request::get(Endpoint::ImageBuildStatus("build-1".to_string()));
+        let response = handle_request(request, manager.clone(), CancellationToken::new()).await;
         assert_eq!(response.status, status::OK);
-        assert!(response.is_success());
+        assert_eq!(response.data.unwrap()["current_instruction"], "RUN make");
 
-        // Verify empty list
-        let data = response.data.unwrap();
-        let items: Vec<JailListItem> = serde_json::from_value(data).unwrap();
-        assert_eq!(items.len(), 0);
+        let request = Request::get(Endpoint::ImageBuildStatus("missing".to_string()));
+        let response = handle_request(request, manager, CancellationToken::new()).await;
+        assert_eq!(response.status, status::NOT_FOUND);
     }
 
     #[tokio::test]
-    async fn test_get_jail_found() {
+    async fn test_queued_builds_report_position_and_cancel() {
         let manager = Arc::new(Mutex::new(create_test_manager()));
-
+        let mut tickets = Vec::new();
         {
             let mut mgr = manager.lock().await;
-            mgr.add_jail("test_jail").unwrap();
+            for (id, name) in [("build-1", "web"), ("build-2", "db"), ("build-3", "api"), ("build-4", "cache")] {
+                mgr.image_build_progress.insert(id.to_string(), ImageBuildProgress {
+                    image_id: id.to_string(),
+                    step: 0,
+                    total_steps: 0,
+                    current_instruction: "Initializing...".to_string(),
+                    status: crate::image_builder::BuildStatus::Building,
+                    copy: None,
+                });
+                tickets.push(mgr.queue_build(id, name));
+            }
         }
 
-        let response = get_jail(manager, "test_jail").await;
+        // Two run (the default limit), the rest wait their turn
+        let status_of = |id: &str| {
+            let manager = manager.clone();
+            let id = id.to_string();
+            async move {
+                let response = handle_request(Request::get(Endpoint::ImageBuildStatus(id)), manager, CancellationToken::new()).await;
+                let data = response.data.unwrap();
+                (data["status"].as_str().unwrap().to_string(), data["current_instruction"].as_str().unwrap().to_string())
+            }
+        };
+        assert_eq!(status_of("build-2").await, ("Building".to_string(), "Initializing...".to_string()));
+        assert_eq!(status_of("build-4").await, ("Queued".to_string(), "queued, position 2".to_string()));
+
+        let response = handle_request(Request::get(Endpoint::SystemTasks), manager.clone(), CancellationToken::new()).await;
+        let tasks: Vec<crate::api::TaskInfo> = serde_json::from_value(response.data.unwrap()).unwrap();
+        let positions: Vec<_> = tasks.iter().map(|t| (t.id.as_str(), t.position)).collect();
+        assert_eq!(positions, [("build-1", None), ("build-2", None), ("build-3", Some(1)), ("build-4", Some(2))]);
 
+        let cancel = |id: &str| Request::post(Endpoint::CancelTask(id.to_string()), ()).unwrap();
+        let response = handle_request(cancel("build-3"), manager.clone(), CancellationToken::new()).await;
         assert_eq!(response.status, status::OK);
-        assert!(response.is_success());
+        assert_eq!(status_of("build-3").await, ("Failed".to_string(), "Build failed: cancelled while queued".to_string()));
+        assert_eq!(status_of("build-4").await.1, "queued, position 1");
+
+        let response = handle_request(cancel("build-1"), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::CONFLICT);
+        let response = handle_request(cancel("build-9"), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::NOT_FOUND);
+
+        // Shutdown fails what is still queued
+        manager.lock().await.drain_task_queues();
+        assert_eq!(status_of("build-4").await, ("Failed".to_string(), "Build failed: daemon shutdown".to_string()));
+        assert_eq!(status_of("build-1").await.0, "Building");
     }
 
     #[tokio::test]
-    async fn test_get_jail_not_found() {
+    async fn test_set_log_level() {
         let manager = Arc::new(Mutex::new(create_test_manager()));
-        let response = get_jail(manager, "nonexistent").await;
+        let set = |level: &str| {
+            let request = Request::post(Endpoint::SetLogLevel, LogLevelRequest { level: level.to_string() }).unwrap();
+            handle_request(request, manager.clone(), CancellationToken::new())
+        };
 
-        assert_eq!(response.status, status::NOT_FOUND);
-        assert!(!response.is_success());
+        // Without the daemon's subscriber there is nothing to change
+        assert_eq!(set("debug").await.status, status::CONFLICT);
+
+        let (_filter, handle) = crate::log_level::layer("info").unwrap();
+        manager.lock().await.set_log_level_handle(crate::log_level::LogLevel::new(handle, "info"));
+        assert_eq!(set("kawakaze_backend=loud").await.status, status::BAD_REQUEST);
+
+        let response = set("debug").await;
+        assert_eq!(response.status, status::OK);
+        let result: LogLevelResult = serde_json::from_value(response.data.unwrap()).unwrap();
+        assert_eq!(result, LogLevelResult { level: "debug".to_string(), previous: "info".to_string() });
     }
 
     #[tokio::test]
-    async fn test_create_jail_success() {
-        let manager = Arc::new(Mutex::new(create_test_manager()));
-
-        let request = CreateJailRequest {
-            name: "new_jail".into(),
-            path: None,
-            ip: None,
-            bootstrap: None,
+    async fn test_upload_resumes_after_offset_mismatch() {
+        use base64::Engine;
+        use sha2::{Digest, Sha256};
+
+        let spool = tempfile::tempdir().unwrap();
+        let mut mgr = create_test_manager();
+        mgr.uploads = crate::upload::Uploads::new(spool.path(), crate::upload::UploadLimits {
+            max_upload_bytes: 1024,
+            max_spool_bytes: 2048,
+            idle_timeout: std::time::Duration::from_secs(60),
+        });
+        let manager = Arc::new(Mutex::new(mgr));
+        let send = |request: Request| handle_request(request, manager.clone(), CancellationToken::new());
+        let upload = |response: Response| serde_json::from_value::<UploadStatus>(response.data.unwrap()).unwrap();
+        let chunk = |offset: u64, bytes: &[u8]| UploadChunk {
+            offset,
+            data: base64::engine::general_purpose::STANDARD.encode(bytes),
         };
 
-        let response = create_jail(manager, request).await;
+        let file = b"FROM freebsd:14.3\nRUN pkg install -y nginx\n";
+        let sha256 = hex::encode(Sha256::digest(file));
+        let announce = |size: u64| UploadRequest { purpose: crate::upload::UploadPurpose::BuildContext, size, sha256: sha256.clone() };
 
+        assert_eq!(send(Request::post(Endpoint::UploadCreate, announce(4096)).unwrap()).await.status, status::BAD_REQUEST);
+        let response = send(Request::post(Endpoint::UploadCreate, announce(file.len() as u64)).unwrap()).await;
         assert_eq!(response.status, status::CREATED);
-        assert!(response.is_success());
-    }
+        let id = upload(response).id;
 
-    #[tokio::test]
-    async fn test_create_jail_invalid_request() {
-        let manager = Arc::new(Mutex::new(create_test_manager()));
+        let response = send(Request::post(Endpoint::UploadChunk(id.clone()), chunk(0, &file[..20])).unwrap()).await;
+        assert_eq!(upload(response).received, 20);
 
-        let request = CreateJailRequest {
-            name: "".into(),
-            path: None,
-            ip: None,
-            bootstrap: None,
-        };
+        // A retried chunk the daemon already has is refused; the client
+        // asks where the upload is and carries on from there
+        let response = send(Request::post(Endpoint::UploadChunk(id.clone()), chunk(0, &file[..20])).unwrap()).await;
+        assert_eq!(response.status, status::CONFLICT);
+        let received = upload(send(Request::get(Endpoint::UploadStatus(id.clone()))).await).received;
+        assert_eq!(received, 20);
 
-        let response = create_jail(manager, request).await;
+        let bad = UploadChunk { offset: received, data: "not base64!".to_string() };
+        assert_eq!(send(Request::post(Endpoint::UploadChunk(id.clone()), bad).unwrap()).await.status, status::BAD_REQUEST);
 
-        assert_eq!(response.status, status::BAD_REQUEST);
-        assert!(!response.is_success());
+        // Committing early is refused, and leaves the upload open
+        assert_eq!(send(Request::post(Endpoint::UploadCommit(id.clone()), ()).unwrap()).await.status, status::CONFLICT);
+
+        send(Request::post(Endpoint::UploadChunk(id.clone()), chunk(received, &file[20..])).unwrap()).await;
+        let response = send(Request::post(Endpoint::UploadCommit(id.clone()), ()).unwrap()).await;
+        assert_eq!(response.status, status::OK);
+        assert_eq!(upload(response).state, crate::upload::UploadState::Committed);
+        assert_eq!(std::fs::read(spool.path().join(&id)).unwrap(), file);
+
+        assert_eq!(send(Request::delete(Endpoint::UploadAbort(id.clone()))).await.status, status::OK);
+        assert!(!spool.path().join(&id).exists());
+        assert_eq!(send(Request::get(Endpoint::UploadStatus(id))).await.status, status::NOT_FOUND);
     }
 
     #[tokio::test]
-    async fn test_start_jail_success() {
-        // Skip if not running as root
-        #[cfg(unix)]
-        if unsafe { libc::getuid() } != 0 {
-            return;
+    async fn test_prune_snapshots_out_of_scope() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+
+        for dataset in ["zroot", "zroot/kawakaze", "tank/other", "zroot/kawakaze/images/web@snap"] {
+            let body = PruneSnapshotsRequest { dataset: dataset.to_string(), keep_last: 1 };
+            let request = Request::post(Endpoint::PruneSnapshots, body).unwrap();
+            let response = handle_request(request, manager.clone(), CancellationToken::new()).await;
+            assert_eq!(response.status, status::BAD_REQUEST, "{}", dataset);
         }
+    }
 
+    #[tokio::test]
+    async fn test_snapshot_gc_without_zfs() {
         let manager = Arc::new(Mutex::new(create_test_manager()));
 
-        {
-            let mut mgr = manager.lock().await;
-            mgr.add_jail("test_jail").unwrap();
+        for body in [serde_json::json!({}), serde_json::json!({ "dry_run": true })] {
+            let request = Request::post(Endpoint::SystemSnapshotGc, body).unwrap();
+            let response = handle_request(request, manager.clone(), CancellationToken::new()).await;
+            assert_eq!(response.status, status::SERVICE_UNAVAILABLE);
+            assert_eq!(response.error.unwrap().code, error_codes::UNAVAILABLE);
         }
+    }
 
-        let response = start_jail(manager, "test_jail").await;
+    #[tokio::test]
+    async fn test_upgrade_jail_running_conflict() {
+        let mut mgr = create_test_manager();
+        mgr.add_jail("web").unwrap();
+        mgr.get_jail_mut("web").unwrap().set_state(crate::jail::JailState::Running);
+        let manager = Arc::new(Mutex::new(mgr));
+
+        let request = Request::post(Endpoint::JailUpgrade("web".to_string()), UpgradeRequest::default()).unwrap();
+        let response = handle_request(request, manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::CONFLICT);
 
-        assert!(response.is_success());
+        let request = Request::post(Endpoint::JailUpgrade("missing".to_string()), UpgradeRequest::default()).unwrap();
+        let response = handle_request(request, manager, CancellationToken::new()).await;
+        assert_eq!(response.status, status::NOT_FOUND);
     }
 
     #[tokio::test]
-    async fn test_start_jail_not_found() {
-        let manager = Arc::new(Mutex::new(create_test_manager()));
-        let response = start_jail(manager, "nonexistent").await;
+    async fn test_get_upgrade_status() {
+        let mut mgr = create_test_manager();
+        mgr.add_jail("web").unwrap();
+        let target = UpgradeTarget::Jail("web".to_string());
+        mgr.begin_upgrade(&target).unwrap();
+        mgr.finish_upgrade(&target, Err(UpgradeError::RolledBack {
+            reason: "freebsd-update exited with 1".to_string(),
+            output: Vec::new(),
+        }));
+        let manager = Arc::new(Mutex::new(mgr));
+
+        let request = Request::get(Endpoint::JailUpgradeStatus("web".to_string()));
+        let response = handle_request(request, manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::OK);
+        assert_eq!(response.data.unwrap()["status"]["rolledback"], "freebsd-update exited with 1");
 
+        let request = Request::get(Endpoint::ImageUpgradeStatus("missing".to_string()));
+        let response = handle_request(request, manager, CancellationToken::new()).await;
         assert_eq!(response.status, status::NOT_FOUND);
     }
 
     #[tokio::test]
-    async fn test_stop_jail_success() {
-        // Skip if not running as root
-        #[cfg(unix)]
-        if unsafe { libc::getuid() } != 0 {
-            return;
-        }
-
+    async fn test_build_image_from_freebsd_base() {
         let manager = Arc::new(Mutex::new(create_test_manager()));
 
-        {
+        // No local image is needed; resolution gets as far as requiring ZFS
+        let response = handle_request(build_request("web", "FROM freebsd:14.1\n", false), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::SERVICE_UNAVAILABLE);
+        assert!(response.error.unwrap().message.contains("ZFS"));
+
+        let response = handle_request(build_request("web", "FROM nginx:1.25\n", false), manager, CancellationToken::new()).await;
+        assert_eq!(response.status, status::BAD_REQUEST);
+    }
+    #[tokio::test]
+    async fn test_container_logs_timestamps() {
+        let log_dir = tempfile::tempdir().unwrap();
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+        let container_id = {
             let mut mgr = manager.lock().await;
-            mgr.add_jail("test_jail").unwrap();
-            mgr.start_jail("test_jail").unwrap();
-        }
+            mgr.config.storage.log_path = log_dir.path().display().to_string();
+            let container = crate::container::Container::new("img".to_string(), "kawakaze-test".to_string(), "tank/c".to_string());
+            let id = container.id.clone();
+            mgr.containers.insert(id.clone(), container);
+            id
+        };
+        let log_file = crate::logs::container_log_file(log_dir.path(), &container_id);
+        crate::logs::append_output(&log_file, "stdout", "started\nready", 1700000000).unwrap();
 
-        let response = stop_jail(manager, "test_jail").await;
+        // Off by default: lines come back unchanged
+        let response = handle_request(Request::get(Endpoint::ContainerLogs(container_id.clone())), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::OK);
+        let data = response.data.unwrap();
+        assert_eq!(data[0]["message"], "started");
+        assert_eq!(data[0]["timestamp"], "2023-11-14T22:13:20Z");
 
-        assert!(response.is_success());
+        let request = Request::get_with(Endpoint::ContainerLogs(container_id), LogsRequest { timestamps: true, ..Default::default() }).unwrap();
+        let response = handle_request(request, manager, CancellationToken::new()).await;
+        let data = response.data.unwrap();
+        assert_eq!(data[0]["message"], "2023-11-14T22:13:20Z started");
+        assert_eq!(data[1]["message"], "2023-11-14T22:13:20Z ready");
     }
 
     #[tokio::test]
-    async fn test_stop_jail_not_found() {
+    async fn test_container_logs_sources() {
+        let dir = tempfile::tempdir().unwrap();
         let manager = Arc::new(Mutex::new(create_test_manager()));
-        let response = stop_jail(manager, "nonexistent").await;
+        let (container_id, messages) = {
+            let mut mgr = manager.lock().await;
+            mgr.config.data_root = dir.path().display().to_string();
+            mgr.config.storage.log_path = dir.path().join("logs").display().to_string();
+            let container = crate::container::Container::new("img".to_string(), "kawakaze-test".to_string(), "tank/c".to_string());
+            let id = container.id.clone();
+            let messages = mgr.container_messages_file(&container);
+            mgr.containers.insert(id.clone(), container);
+            (id, messages)
+        };
+        let log_file = crate::logs::container_log_file(&dir.path().join("logs"), &container_id);
+        crate::logs::append_output(&log_file, "stdout", "first", 1700000000).unwrap();
+        crate::logs::append_output(&log_file, "stdout", "third", 1700000020).unwrap();
+        std::fs::create_dir_all(messages.parent().unwrap()).unwrap();
+        std::fs::write(&messages, "2023-11-14T22:13:30Z web nginx[1]: second\n2023-11-14T22:13:50Z web nginx[1]: fourth\n").unwrap();
+
+        let logs = |source: crate::logs::LogSource, tail: Option<usize>| {
+            let request = Request::get_with(Endpoint::ContainerLogs(container_id.clone()), LogsRequest { source, tail, ..Default::default() });
+            let manager = manager.clone();
+            async move {
+                let response = handle_request(request.unwrap(), manager, CancellationToken::new()).await;
+                assert_eq!(response.status, status::OK);
+                let data = response.data.unwrap();
+                data.as_array().unwrap().iter().map(|e| e["message"].as_str().unwrap().to_string()).collect::<Vec<_>>()
+            }
+        };
 
-        assert_eq!(response.status, status::NOT_FOUND);
+        assert_eq!(logs(crate::logs::LogSource::Stdio, None).await, ["first", "third"]);
+        assert_eq!(logs(crate::logs::LogSource::Syslog, None).await, ["nginx[1]: second", "nginx[1]: fourth"]);
+        assert_eq!(logs(crate::logs::LogSource::All, None).await, ["first", "nginx[1]: second", "third", "nginx[1]: fourth"]);
+        // The last lines of both together
+        assert_eq!(logs(crate::logs::LogSource::All, Some(2)).await, ["third", "nginx[1]: fourth"]);
     }
 
     #[tokio::test]
-    async fn test_delete_jail_success() {
-        // Skip if not running as root
-        #[cfg(unix)]
-        if unsafe { libc::getuid() } != 0 {
-            return;
-        }
-
+    async fn test_image_build_log() {
+        let log_dir = tempfile::tempdir().unwrap();
         let manager = Arc::new(Mutex::new(create_test_manager()));
+        let image_id = {
+            let mut mgr = manager.lock().await;
+            mgr.config.storage.build_log_path = log_dir.path().display().to_string();
+            let log = crate::build_log::build_log_file(log_dir.path(), "built");
+            crate::build_log::append_progress(&log, "Step 1/1: RUN make").unwrap();
+            crate::build_log::append_progress(&log, "Build complete").unwrap();
+            let image = Image::new("web".to_string(), Vec::new())
+                .with_state(crate::image::ImageState::Available)
+                .with_build_log(log.display().to_string());
+            let id = image.id.clone();
+            mgr.add_image(image).unwrap();
+
+            // A failed build has no image, only its progress entry
+            let failed = crate::build_log::build_log_file(log_dir.path(), "failed-build");
+            crate::build_log::append_progress(&failed, "Build failed: exit 1").unwrap();
+            mgr.image_build_progress.insert(
+                "failed-build".to_string(),
+                ImageBuildProgress {
+                    image_id: "failed-build".to_string(),
+                    step: 1,
+                    total_steps: 1,
+                    current_instruction: "RUN make".to_string(),
+                    status: crate::image_builder::BuildStatus::Failed,
+                    copy: None,
+                },
+            );
+            id
+        };
+
+        let request = Request::get_with(Endpoint::ImageBuildLog("web".to_string()), LogsRequest { tail: Some(1), ..Default::default() }).unwrap();
+        let response = handle_request(request, manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::OK);
+        let data = response.data.unwrap();
+        assert_eq!(data.as_array().unwrap().len(), 1);
+        assert_eq!(data[0]["message"], "Build complete");
 
+        let response = handle_request(Request::get(Endpoint::ImageBuildLog(image_id)), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.data.unwrap().as_array().unwrap().len(), 2);
+
+        let response = handle_request(Request::get(Endpoint::ImageBuildLog("failed-build".to_string())), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::OK);
+        assert_eq!(response.data.unwrap()[0]["message"], "Build failed: exit 1");
+
+        let response = handle_request(Request::get(Endpoint::ImageBuildLog("missing".to_string())), manager, CancellationToken::new()).await;
+        assert_eq!(response.status, status::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_bootstrap_log() {
+        let log_dir = tempfile::tempdir().unwrap();
+        let manager = Arc::new(Mutex::new(create_test_manager()));
         {
             let mut mgr = manager.lock().await;
-            mgr.add_jail("test_jail").unwrap();
+            mgr.config.storage.bootstrap_log_path = log_dir.path().display().to_string();
+            mgr.add_jail("base").unwrap();
+            mgr.add_jail("fresh").unwrap();
         }
+        let log = crate::build_log::bootstrap_log_file(log_dir.path(), "base");
+        crate::build_log::append_progress(&log, "Fetching base.txz").unwrap();
 
-        let response = delete_jail(manager, "test_jail").await;
+        let response = handle_request(Request::get(Endpoint::BootstrapLog("base".to_string())), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::OK);
+        assert_eq!(response.data.unwrap()[0]["message"], "Fetching base.txz");
 
-        assert!(response.is_success());
+        // A jail never bootstrapped, and an unknown one
+        let response = handle_request(Request::get(Endpoint::BootstrapLog("fresh".to_string())), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::NOT_FOUND);
+        let response = handle_request(Request::get(Endpoint::BootstrapLog("missing".to_string())), manager, CancellationToken::new()).await;
+        assert_eq!(response.status, status::NOT_FOUND);
     }
 
     #[tokio::test]
-    async fn test_delete_jail_not_found() {
+    async fn test_bootstrap_rejects_unknown_set() {
         let manager = Arc::new(Mutex::new(create_test_manager()));
-        let response = delete_jail(manager, "nonexistent").await;
+        manager.lock().await.add_jail("slim").unwrap();
 
-        assert_eq!(response.status, status::NOT_FOUND);
+        let config = serde_json::json!({ "version": "14.1-RELEASE", "architecture": "i386", "sets": ["base", "lib32"] });
+        let request = Request::post(Endpoint::BootstrapJail("slim".to_string()), config).unwrap();
+        let response = handle_request(request, manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::BAD_REQUEST);
+        assert!(response.error.unwrap().message.contains("'lib32'"));
+        assert!(!manager.lock().await.bootstrap_queue.contains("slim"));
     }
 
-    // ============================================================================
-    // Exec Container Tests
-    // ============================================================================
-
     #[tokio::test]
-    async fn test_exec_container_not_found() {
+    async fn test_orphan_endpoints_require_dataset_and_zfs() {
         let manager = Arc::new(Mutex::new(create_test_manager()));
 
-        let exec_req = ExecRequest {
-            command: vec!["echo".to_string(), "test".to_string()],
-            env: std::collections::HashMap::new(),
-            workdir: None,
-        };
+        let request = Request::post(Endpoint::OrphanDestroy, serde_json::json!({})).unwrap();
+        let response = handle_request(request, manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::BAD_REQUEST);
 
-        let response = exec_container(manager, "nonexistent", exec_req).await;
+        // Without ZFS there is nothing to list, and nothing is ever destroyed
+        let response = handle_request(Request::get(Endpoint::SystemOrphans), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::INTERNAL_SERVER_ERROR);
 
-        assert_eq!(response.status, status::NOT_FOUND);
-        assert!(!response.is_success());
+        let request = Request::post(
+            Endpoint::OrphanDestroy,
+            DestroyOrphanRequest { dataset: "zroot/kawakaze/containers/bbbb2222".to_string() },
+        )
+        .unwrap();
+        let response = handle_request(request, manager, CancellationToken::new()).await;
+        assert_eq!(response.status, status::INTERNAL_SERVER_ERROR);
+    }
+
+    #[test]
+    fn test_orphan_error_status() {
+        assert_eq!(orphan_error(OrphanError::NotListed("x".into())).status, status::NOT_FOUND);
+        assert_eq!(orphan_error(OrphanError::OutOfScope("x".into())).status, status::BAD_REQUEST);
+        assert_eq!(orphan_error(OrphanError::Conflict("x".into(), "taken".into())).status, status::CONFLICT);
     }
 
     #[tokio::test]
-    async fn test_exec_container_invalid_request_body() {
+    async fn test_get_system_config_defaults() {
         let manager = Arc::new(Mutex::new(create_test_manager()));
 
-        let request = Request::post(
-            crate::api::Endpoint::ContainerExec("some-id".into()),
-            "invalid json",
-        ).unwrap();
-
-        let response = handle_request(request, manager).await;
+        let response = handle_request(Request::get(Endpoint::SystemConfig), manager, CancellationToken::new()).await;
+        assert_eq!(response.status, status::OK);
 
-        assert_eq!(response.status, status::BAD_REQUEST);
-        assert!(!response.is_success());
+        let data = response.data.unwrap();
+        assert!(data["path"].is_null());
+        assert_eq!(data["drift"], false);
+        assert_eq!(data["config"]["zfs_pool"], "zroot/kawakaze");
     }
 
     #[tokio::test]
-    async fn test_exec_request_valid() {
-        let exec_req = ExecRequest {
-            command: vec!["ls".to_string(), "-la".to_string()],
-            env: {
-                let mut map = std::collections::HashMap::new();
-                map.insert("PATH".to_string(), "/usr/bin".to_string());
-                map
-            },
-            workdir: Some("/tmp".to_string()),
-        };
+    async fn test_get_system_config_reports_drift() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("config.toml");
+        let manager = Arc::new(Mutex::new(create_test_manager()));
 
-        assert_eq!(exec_req.command.len(), 2);
-        assert_eq!(exec_req.workdir, Some("/tmp".to_string()));
-        assert_eq!(exec_req.env.len(), 1);
-    }
+        let mut edited = crate::config::KawakazeConfig::default();
+        edited.network.bridge_name = "bridge1".to_string();
+        edited.save(&path).unwrap();
+        manager.lock().await.set_config_source(crate::config::ConfigSource::from_path(&path));
 
-    /// Test that exec request without PATH still includes default PATH
-    #[tokio::test]
-    async fn test_exec_request_without_path_gets_default() {
-        let exec_req = ExecRequest {
-            command: vec!["echo".to_string(), "test".to_string()],
-            env: std::collections::HashMap::new(),
-            workdir: None,
-        };
+        let response = handle_request(Request::get(Endpoint::SystemConfig), manager.clone(), CancellationToken::new()).await;
+        let data = response.data.unwrap();
+        assert_eq!(data["path"], path.display().to_string());
+        assert_eq!(data["drift"], true);
+        assert_eq!(data["drifted_fields"], serde_json::json!(["network.bridge_name"]));
 
-        // Verify the exec request has no PATH in env
-        assert!(!exec_req.env.contains_key("PATH"));
-        assert_eq!(exec_req.command.len(), 2);
+        std::fs::remove_file(&path).unwrap();
+        let response = handle_request(Request::get(Endpoint::SystemConfig), manager, CancellationToken::new()).await;
+        let data = response.data.unwrap();
+        assert_eq!(data["drift"], true);
+        assert!(data["file_error"].is_string());
     }
 
-    /// Test that exec request with custom PATH preserves it
     #[tokio::test]
-    async fn test_exec_request_with_custom_path() {
-        let mut custom_env = std::collections::HashMap::new();
-        custom_env.insert("CUSTOM_VAR".to_string(), "custom_value".to_string());
-        custom_env.insert("PATH".to_string(), "/custom/path".to_string());
+    async fn test_get_system_info_without_zfs() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
 
-        let exec_req = ExecRequest {
-            command: vec!["ls".to_string()],
-            env: custom_env,
-            workdir: None,
-        };
+        let response = handle_request(Request::get(Endpoint::SystemInfo), manager, CancellationToken::new()).await;
+        assert_eq!(response.status, status::OK);
 
-        // Verify custom PATH is preserved
-        assert_eq!(exec_req.env.get("PATH"), Some(&"/custom/path".to_string()));
-        assert_eq!(exec_req.env.get("CUSTOM_VAR"), Some(&"custom_value".to_string()));
+        let data = response.data.unwrap();
+        assert_eq!(data["version"], env!("CARGO_PKG_VERSION"));
+        assert!(data.get("pool").is_none());
+        assert!(data.get("pool_error").is_none());
+        assert_eq!(data["containers"]["total"], 0);
+        assert!(data["uptime_secs"].is_u64());
+        assert!(data["host"].is_object());
     }
 
-    /// Test exec command building with workdir
     #[tokio::test]
-    async fn test_exec_command_with_workdir() {
-        let exec_req = ExecRequest {
-            command: vec!["ls".to_string(), "-la".to_string()],
-            env: std::collections::HashMap::new(),
-            workdir: Some("/root".to_string()),
-        };
+    async fn test_doctor_reports_every_check() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
 
-        // Simulate the command building logic from exec_container
-        let shell_command = format!("cd {} && exec {}", exec_req.workdir.unwrap(), shell_words::join(&exec_req.command));
+        let response = handle_request(Request::get(Endpoint::SystemDoctor), manager, CancellationToken::new()).await;
+        assert_eq!(response.status, status::OK);
 
-        assert!(shell_command.contains("cd /root"));
-        assert!(shell_command.contains("exec ls -la"));
+        let report: crate::doctor::DoctorReport = serde_json::from_value(response.data.unwrap()).unwrap();
+        assert_eq!(report.checks.len(), crate::doctor::default_checks().len());
+        #[cfg(not(target_os = "freebsd"))]
+        assert!(report.checks.iter().any(|c| c.name == "vimage" && c.status == crate::doctor::CheckStatus::Skipped));
     }
 
-    /// Test exec command building without workdir
     #[tokio::test]
-    async fn test_exec_command_without_workdir() {
-        let exec_req = ExecRequest {
-            command: vec!["uname".to_string(), "-a".to_string()],
-            env: std::collections::HashMap::new(),
-            workdir: None,
-        };
+    async fn test_get_command_metrics() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
+        crate::cmdtrace::record("zfs", &["list".to_string(), "tank/metrics-test".to_string()], std::time::Instant::now(), true);
 
-        // Simulate the command building logic from exec_container
-        let shell_command = format!("exec {}", shell_words::join(&exec_req.command));
+        let response = handle_request(Request::get(Endpoint::CommandMetrics), manager, CancellationToken::new()).await;
+        assert_eq!(response.status, status::OK);
 
-        assert_eq!(shell_command, "exec uname -a");
+        let data = response.data.unwrap();
+        assert!(data["commands"]["zfs"]["count"].as_u64().unwrap() >= 1);
+        assert!(data["slow_threshold_ms"].is_u64());
     }
 
-    /// Test that shell-words properly quotes arguments
     #[tokio::test]
-    async fn test_exec_command_with_spaces() {
-        let exec_req = ExecRequest {
-            command: vec!["echo".to_string(), "hello world".to_string(), "test".to_string()],
-            env: std::collections::HashMap::new(),
-            workdir: None,
-        };
-
-        // shell-words::join should properly quote arguments with spaces
-        let joined = shell_words::join(&exec_req.command);
+    async fn test_api_schema_describes_handled_routes() {
+        let manager = Arc::new(Mutex::new(create_test_manager()));
 
-        assert!(joined.contains("'hello world'") || joined.contains("\"hello world\""));
+        let response = handle_request(Request::get(Endpoint::ApiSchema), manager.clone(), CancellationToken::new()).await;
+        assert_eq!(response.status, status::OK);
+        let description: crate::api_schema::ApiDescription = serde_json::from_value(response.data.unwrap()).unwrap();
+        assert_eq!(description.api_version, crate::api_schema::API_VERSION);
+
+        // Whatever else goes wrong with an empty body, no described route
+        // is one the handler doesn't serve
+        for endpoint in description.endpoints.iter().filter(|e| !e.streaming) {
+            let path = endpoint.path.split('/').map(|s| if s.starts_with('{') { "missing" } else { s }).collect::<Vec<_>>().join("/");
+            let request = Request { method: endpoint.method.clone(), endpoint: path, body: serde_json::Value::Null, peer_uid: None };
+            let response = handle_request(request, manager.clone(), CancellationToken::new()).await;
+            let message = response.error.map(|e| e.message).unwrap_or_default();
+            assert!(!message.contains("not supported"), "{:?} {}: {}", endpoint.method, endpoint.path, message);
+        }
     }
 }
@@ -0,0 +1,309 @@
+//! Container name resolution on a kawakaze network
+//!
+//! Running containers with an address on the same network can reach each
+//! other by name and by their `network_aliases`. The manager keeps a
+//! [`Registry`] of the network's running members (name and aliases → addresses)
+//! and, after a container starts, stops or is removed, hands the new
+//! registry to a [`NameResolver`]. The only backend is [`HostsFile`], which
+//! rewrites a managed block in each member's `/etc/hosts`; a DNS server
+//! (dnsmasq, unbound) can take its place behind the same trait.
+//!
+//! There is a single network today, the bridge (`network.bridge_name`), so
+//! every container with an IP is a member of it.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::container::ContainerId;
+
+/// First line of the block kawakaze manages in `/etc/hosts`
+pub const BEGIN_MARKER: &str = "# BEGIN kawakaze network hosts (managed, do not edit)";
+/// Last line of the managed block
+pub const END_MARKER: &str = "# END kawakaze network hosts";
+
+/// Longest host name, and longest label within it
+const MAX_NAME_LEN: usize = 253;
+const MAX_LABEL_LEN: usize = 63;
+
+/// Names a running member answers to, and its addresses (IPv4 first)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostEntry {
+    pub ips: Vec<String>,
+    pub name: String,
+    pub aliases: Vec<String>,
+}
+
+/// A network's running members (container ID → entry)
+pub type Registry = BTreeMap<ContainerId, HostEntry>;
+
+/// Why a container's names can't be used on its network
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum AliasError {
+    #[error("Invalid network alias '{0}': use letters, digits, '-' and '.'")]
+    Invalid(String),
+    #[error("Network name '{name}' is already used by container '{container}'")]
+    Conflict { name: String, container: String },
+}
+
+/// Check a new container's aliases, and its name against the aliases of
+/// the network's other containers (`others`: name and aliases of each)
+pub fn check_aliases<'a>(
+    name: Option<&str>,
+    aliases: &[String],
+    others: impl IntoIterator<Item = (&'a str, &'a [String])>,
+) -> Result<(), AliasError> {
+    if let Some(alias) = aliases.iter().find(|alias| !is_valid_hostname(alias)) {
+        return Err(AliasError::Invalid(alias.clone()));
+    }
+
+    let wanted: Vec<&str> = name.into_iter().chain(aliases.iter().map(String::as_str)).collect();
+    for (other, other_aliases) in others {
+        let taken = std::iter::once(other).chain(other_aliases.iter().map(String::as_str));
+        for used in taken {
+            // Names themselves are kept unique elsewhere; only aliases can clash with them here
+            let is_own_name = Some(used) == name && used == other;
+            if !is_own_name && wanted.iter().any(|want| want.eq_ignore_ascii_case(used)) {
+                return Err(AliasError::Conflict { name: used.to_string(), container: other.to_string() });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Whether `name` may appear in `/etc/hosts`: dot-separated labels of
+/// letters, digits and inner hyphens
+pub fn is_valid_hostname(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= MAX_LABEL_LEN
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/// Members whose hosts file must be rewritten when the registry goes from
+/// `before` to `after`: every member holds the whole registry, so any
+/// change reaches all of them, and no change reaches none
+pub fn rewrites(before: &Registry, after: &Registry) -> Vec<ContainerId> {
+    if before == after {
+        return Vec::new();
+    }
+    after.keys().cloned().collect()
+}
+
+/// The managed block for `registry`, one line per member address sorted
+/// by name, so a dual-stack member has an IPv4 and an IPv6 line
+pub fn render_block(registry: &Registry) -> String {
+    let mut entries: Vec<&HostEntry> = registry.values().collect();
+    entries.sort_by(|a, b| a.name.cmp(&b.name).then(a.ips.cmp(&b.ips)));
+
+    let mut block = format!("{}\n", BEGIN_MARKER);
+    for entry in entries {
+        let names: Vec<&str> = std::iter::once(entry.name.as_str()).chain(entry.aliases.iter().map(String::as_str)).collect();
+        for ip in &entry.ips {
+            block.push_str(&format!("{}\t{}\n", ip, names.join(" ")));
+        }
+    }
+    block.push_str(END_MARKER);
+    block.push('\n');
+    block
+}
+
+/// `existing` with its managed block replaced by `block`, or `block`
+/// appended if there was none. Everything outside the markers is kept.
+pub fn splice_block(existing: &str, block: &str) -> String {
+    let mut out = String::with_capacity(existing.len() + block.len());
+    let mut lines = existing.lines();
+    let mut replaced = false;
+    while let Some(line) = lines.next() {
+        if line == BEGIN_MARKER && !replaced {
+            out.push_str(block);
+            replaced = true;
+            // Skip the old block; an unterminated one runs to the end
+            for line in lines.by_ref() {
+                if line == END_MARKER {
+                    break;
+                }
+            }
+            continue;
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+    if !replaced {
+        if !out.is_empty() && !out.ends_with("\n\n") {
+            out.push('\n');
+        }
+        out.push_str(block);
+    }
+    out
+}
+
+/// Replace a file the daemon manages inside a container root: written to a
+/// temporary file next to it and renamed over it, so readers in the jail
+/// see the old or the new contents, never half of one
+pub fn write_managed_file(path: &Path, contents: &str) -> std::io::Result<()> {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("managed");
+    let temp = path.with_file_name(format!(".{}.kawakaze.tmp", name));
+    let result = (|| {
+        let mut file = std::fs::File::create(&temp)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+        // Keep the mode of the file being replaced (0644 for a new one)
+        if let Ok(metadata) = std::fs::metadata(path) {
+            std::fs::set_permissions(&temp, metadata.permissions())?;
+        }
+        std::fs::rename(&temp, path)
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    result
+}
+
+/// Publishes a network's registry so its members can resolve each other
+pub trait NameResolver {
+    /// Make `registry` visible on `network`; `roots` are the root
+    /// directories of the members whose view needs updating
+    fn publish(&mut self, network: &str, registry: &Registry, roots: &[PathBuf]) -> Result<(), String>;
+}
+
+/// [`NameResolver`] writing the managed block of each member's `/etc/hosts`
+pub struct HostsFile;
+
+impl NameResolver for HostsFile {
+    fn publish(&mut self, _network: &str, registry: &Registry, roots: &[PathBuf]) -> Result<(), String> {
+        let block = render_block(registry);
+        let mut failures = Vec::new();
+        for root in roots {
+            let path = root.join("etc/hosts");
+            let existing = match std::fs::read_to_string(&path) {
+                Ok(existing) => existing,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+                Err(e) => {
+                    failures.push(format!("{}: {}", path.display(), e));
+                    continue;
+                }
+            };
+            if let Err(e) = write_managed_file(&path, &splice_block(&existing, &block)) {
+                failures.push(format!("{}: {}", path.display(), e));
+            }
+        }
+        if failures.is_empty() { Ok(()) } else { Err(failures.join("; ")) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(ip: &str, name: &str, aliases: &[&str]) -> HostEntry {
+        HostEntry { ips: vec![ip.to_string()], name: name.to_string(), aliases: aliases.iter().map(|a| a.to_string()).collect() }
+    }
+
+    fn registry(entries: &[(&str, HostEntry)]) -> Registry {
+        entries.iter().map(|(id, entry)| (id.to_string(), entry.clone())).collect()
+    }
+
+    #[test]
+    fn test_rewrites_on_membership_change() {
+        let db = ("c1", entry("10.11.0.2", "db", &["postgres"]));
+        let web = ("c2", entry("10.11.0.3", "web", &[]));
+        let before = registry(&[db.clone()]);
+
+        // web starts: both need the new block
+        let after = registry(&[db.clone(), web.clone()]);
+        assert_eq!(rewrites(&before, &after), ["c1", "c2"]);
+
+        // web stops: only db is left to update
+        assert_eq!(rewrites(&after, &before), ["c1"]);
+
+        // A container without an address came and went: nothing changes
+        assert!(rewrites(&before, &before.clone()).is_empty());
+
+        // db comes back on a new address
+        let moved = registry(&[("c1", entry("10.11.0.9", "db", &["postgres"])), web.clone()]);
+        assert_eq!(rewrites(&after, &moved), ["c1", "c2"]);
+    }
+
+    #[test]
+    fn test_check_aliases() {
+        let cache_aliases = ["redis".to_string()];
+        let others = || [("db", &[][..]), ("cache", &cache_aliases[..])];
+
+        assert!(check_aliases(Some("web"), &["www".to_string(), "app.internal".to_string()], others()).is_ok());
+
+        let err = check_aliases(Some("web"), &["redis".to_string()], others()).unwrap_err();
+        assert_eq!(err, AliasError::Conflict { name: "redis".to_string(), container: "cache".to_string() });
+        assert!(check_aliases(Some("web"), &["DB".to_string()], others()).is_err());
+        // A new container may not take a name another one answers to
+        assert!(check_aliases(Some("redis"), &[], others()).is_err());
+
+        for bad in ["", "-web", "web_1", "a..b", "web:80"] {
+            assert_eq!(check_aliases(None, &[bad.to_string()], others()), Err(AliasError::Invalid(bad.to_string())), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_render_block() {
+        let registry = registry(&[
+            ("c2", entry("10.11.0.3", "web", &[])),
+            ("c1", entry("10.11.0.2", "db", &["postgres", "pg"])),
+        ]);
+        assert_eq!(
+            render_block(&registry),
+            format!("{}\n10.11.0.2\tdb postgres pg\n10.11.0.3\tweb\n{}\n", BEGIN_MARKER, END_MARKER)
+        );
+        assert_eq!(render_block(&Registry::new()), format!("{}\n{}\n", BEGIN_MARKER, END_MARKER));
+    }
+
+    #[test]
+    fn test_render_block_dual_stack() {
+        let dual = HostEntry { ips: vec!["10.11.0.2".to_string(), "fd00:6b7a::2".to_string()], ..entry("", "db", &["pg"]) };
+        let registry = registry(&[("c1", dual), ("c2", entry("fd00:6b7a::3", "web", &[]))]);
+        assert_eq!(
+            render_block(&registry),
+            format!("{}\n10.11.0.2\tdb pg\nfd00:6b7a::2\tdb pg\nfd00:6b7a::3\tweb\n{}\n", BEGIN_MARKER, END_MARKER)
+        );
+    }
+
+    #[test]
+    fn test_splice_block_keeps_surrounding_lines() {
+        let block = format!("{}\n10.11.0.2\tdb\n{}\n", BEGIN_MARKER, END_MARKER);
+        let base = "127.0.0.1\tlocalhost\n";
+
+        let first = splice_block(base, &block);
+        assert_eq!(first, format!("{}\n{}", base, block));
+
+        // Replacing the block again leaves one block and the user's lines alone
+        let edited = format!("{}10.0.0.1\tnas\n", first);
+        let newer = format!("{}\n10.11.0.2\tdb\n10.11.0.3\tweb\n{}\n", BEGIN_MARKER, END_MARKER);
+        let second = splice_block(&edited, &newer);
+        assert_eq!(second, format!("{}\n{}10.0.0.1\tnas\n", base, newer));
+        assert_eq!(second.matches(BEGIN_MARKER).count(), 1);
+
+        assert_eq!(splice_block("", &block), block);
+    }
+
+    #[test]
+    fn test_hosts_file_publish() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(root.path().join("etc")).unwrap();
+        std::fs::write(root.path().join("etc/hosts"), "127.0.0.1\tlocalhost\n").unwrap();
+        let registry = registry(&[("c1", entry("10.11.0.2", "db", &[]))]);
+
+        HostsFile.publish("kawakaze-bridge", &registry, &[root.path().to_path_buf()]).unwrap();
+        let hosts = std::fs::read_to_string(root.path().join("etc/hosts")).unwrap();
+        assert!(hosts.starts_with("127.0.0.1\tlocalhost\n"));
+        assert!(hosts.contains("10.11.0.2\tdb\n"));
+        // No temporary file is left behind
+        assert_eq!(std::fs::read_dir(root.path().join("etc")).unwrap().count(), 1);
+
+        let missing = root.path().join("gone");
+        assert!(HostsFile.publish("kawakaze-bridge", &registry, &[missing]).is_err());
+    }
+}
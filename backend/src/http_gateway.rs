@@ -0,0 +1,359 @@
+//! HTTP gateway onto the API
+//!
+//! For tools that speak HTTP (`curl`, probes, scripts) rather than the
+//! socket's line protocol. An HTTP request maps directly onto a
+//! [`Request`]: its method is the API method, its path without the
+//! surrounding slashes is the endpoint, and its JSON body is the body:
+//!
+//! ```text
+//! curl http://127.0.0.1:7080/containers
+//! curl -X POST -d '{"name":"web","image":"base"}' http://127.0.0.1:7080/containers/create
+//! curl -X DELETE 'http://127.0.0.1:7080/containers/web?force=true'
+//! ```
+//!
+//! A request without a body may carry its fields in the query string
+//! instead. A value that parses as JSON is taken as such (`tail=100`,
+//! `force=true`), anything else as a string. The response's status is the
+//! API status, and its body the data, or the error (`code` and `message`)
+//! on failure.
+//!
+//! Each connection serves one request and is then closed. Streaming
+//! endpoints are refused as they are over the socket without a stream;
+//! follow logs and progress over the socket. TCP carries no peer
+//! credentials, so anyone who can reach the gateway controls the daemon,
+//! and `http.listen` must be a loopback address unless `http.allow_remote`
+//! is set.
+//!
+//! The mapping is always built; the listener only with the `http-gateway`
+//! feature, so a daemon that doesn't serve HTTP doesn't carry a parser.
+
+use percent_encoding::percent_decode_str;
+use serde_json::Value;
+
+use crate::api::{ApiError, Method, Request, Response, StatusCode, status};
+
+/// Largest request head (request line and headers)
+pub const MAX_HEADER_BYTES: usize = 64 * 1024;
+
+/// Largest request body; uploads go in chunks well below it
+pub const MAX_BODY_BYTES: usize = 8 * 1024 * 1024;
+
+/// The API request an HTTP request stands for, or the response refusing it
+pub fn to_request(method: &str, target: &str, body: &[u8]) -> Result<Request, Response> {
+    let method = match method {
+        "GET" => Method::Get,
+        "POST" => Method::Post,
+        "DELETE" => Method::Delete,
+        other => {
+            return Err(Response::error(status::METHOD_NOT_ALLOWED, ApiError::MethodNotAllowed(other.to_string())));
+        }
+    };
+
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let endpoint = decode(path)?.trim_matches('/').to_string();
+
+    let has_body = !body.iter().all(u8::is_ascii_whitespace);
+    let body = match (has_body, query.is_empty()) {
+        (true, true) => serde_json::from_slice(body)
+            .map_err(|e| Response::bad_request(format!("Invalid JSON body: {}", e)))?,
+        (true, false) => {
+            return Err(Response::bad_request("Send fields in the body or the query string, not both"));
+        }
+        (false, false) => query_fields(query)?,
+        (false, true) => Value::Null,
+    };
+
+    Ok(Request { method, endpoint, body, peer_uid: None })
+}
+
+/// A query string's fields as a JSON object
+fn query_fields(query: &str) -> Result<Value, Response> {
+    let mut fields = serde_json::Map::new();
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let value = decode(&value.replace('+', " "))?;
+        let value = serde_json::from_str(&value).unwrap_or(Value::String(value));
+        fields.insert(decode(&key.replace('+', " "))?, value);
+    }
+    Ok(Value::Object(fields))
+}
+
+fn decode(s: &str) -> Result<String, Response> {
+    percent_decode_str(s)
+        .decode_utf8()
+        .map(|decoded| decoded.into_owned())
+        .map_err(|_| Response::bad_request(format!("Invalid UTF-8 in '{}'", s)))
+}
+
+/// An API response's HTTP status and JSON body
+pub fn to_http(response: &Response) -> (StatusCode, Vec<u8>) {
+    let body = match (&response.error, &response.data) {
+        (Some(error), _) => serde_json::to_vec(error),
+        (None, Some(data)) => serde_json::to_vec(data),
+        (None, None) => Ok(b"null".to_vec()),
+    };
+    match body {
+        Ok(body) => (response.status, body),
+        Err(e) => {
+            let error = ApiError::Internal(format!("Failed to serialize response: {}", e));
+            (status::INTERNAL_SERVER_ERROR, serde_json::to_vec(&error).unwrap_or_default())
+        }
+    }
+}
+
+/// Reason phrase of a status the API answers with
+pub fn reason(status: StatusCode) -> &'static str {
+    match status {
+        status::OK => "OK",
+        status::CREATED => "Created",
+        status::BAD_REQUEST => "Bad Request",
+        status::FORBIDDEN => "Forbidden",
+        status::NOT_FOUND => "Not Found",
+        status::METHOD_NOT_ALLOWED => "Method Not Allowed",
+        status::CONFLICT => "Conflict",
+        status::CLIENT_CLOSED_REQUEST => "Client Closed Request",
+        status::INTERNAL_SERVER_ERROR => "Internal Server Error",
+        status::SERVICE_UNAVAILABLE => "Service Unavailable",
+        _ => "Unknown",
+    }
+}
+
+#[cfg(feature = "http-gateway")]
+pub use listener::serve;
+
+#[cfg(feature = "http-gateway")]
+mod listener {
+    use std::sync::Arc;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::Mutex;
+    use tokio_util::sync::CancellationToken;
+    use tracing::{debug, info, warn};
+
+    use super::{MAX_BODY_BYTES, MAX_HEADER_BYTES, reason, to_http, to_request};
+    use crate::JailManager;
+    use crate::api::Response;
+    use crate::handler::handle_request;
+
+    /// A request as read off the connection
+    struct HttpRequest {
+        method: String,
+        target: String,
+        body: Vec<u8>,
+    }
+
+    /// Serve HTTP requests from `listener` until the task is dropped
+    pub async fn serve(listener: TcpListener, manager: Arc<Mutex<JailManager>>) {
+        if let Ok(addr) = listener.local_addr() {
+            info!(%addr, "Serving the HTTP gateway");
+        }
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!(error = %e, "Failed to accept HTTP connection");
+                    continue;
+                }
+            };
+            let manager = manager.clone();
+            tokio::spawn(async move {
+                if let Err(e) = handle_connection(stream, manager).await {
+                    debug!(%peer, error = %e, "HTTP connection failed");
+                }
+            });
+        }
+    }
+
+    async fn handle_connection(mut stream: TcpStream, manager: Arc<Mutex<JailManager>>) -> std::io::Result<()> {
+        let response = match read_request(&mut stream).await? {
+            Ok(http) => match to_request(&http.method, &http.target, &http.body) {
+                Ok(request) => {
+                    info!(method = ?request.method, endpoint = %request.endpoint, "Incoming HTTP request");
+                    handle_request(request, manager, CancellationToken::new()).await
+                }
+                Err(response) => response,
+            },
+            Err(response) => response,
+        };
+        if !response.is_success() {
+            warn!(
+                status = response.status,
+                error = response.error.as_ref().map(|e| e.message.as_str()),
+                "HTTP request failed"
+            );
+        }
+
+        let (status, body) = to_http(&response);
+        let head = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            status,
+            reason(status),
+            body.len()
+        );
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(&body).await?;
+        stream.shutdown().await
+    }
+
+    /// Read one request, or the response refusing it
+    async fn read_request(stream: &mut TcpStream) -> std::io::Result<Result<HttpRequest, Response>> {
+        let mut buf = Vec::with_capacity(4096);
+        let mut chunk = [0u8; 4096];
+        loop {
+            let read = stream.read(&mut chunk).await?;
+            if read == 0 {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            buf.extend_from_slice(&chunk[..read]);
+
+            let mut headers = [httparse::EMPTY_HEADER; 64];
+            let mut parsed = httparse::Request::new(&mut headers);
+            let head_len = match parsed.parse(&buf) {
+                Ok(httparse::Status::Complete(len)) => len,
+                Ok(httparse::Status::Partial) if buf.len() < MAX_HEADER_BYTES => continue,
+                Ok(httparse::Status::Partial) => {
+                    return Ok(Err(Response::bad_request("Request headers too large")));
+                }
+                Err(e) => return Ok(Err(Response::bad_request(format!("Invalid HTTP request: {}", e)))),
+            };
+
+            let header = |name: &str| {
+                parsed
+                    .headers
+                    .iter()
+                    .find(|h| h.name.eq_ignore_ascii_case(name))
+                    .map(|h| String::from_utf8_lossy(h.value).trim().to_string())
+            };
+            if header("transfer-encoding").is_some() {
+                return Ok(Err(Response::bad_request("Chunked request bodies are not supported")));
+            }
+            let length = match header("content-length").map(|v| v.parse::<usize>()) {
+                None => 0,
+                Some(Ok(length)) if length <= MAX_BODY_BYTES => length,
+                Some(Ok(length)) => {
+                    return Ok(Err(Response::bad_request(format!(
+                        "Request body of {} bytes is over the limit of {}",
+                        length, MAX_BODY_BYTES
+                    ))));
+                }
+                Some(Err(_)) => return Ok(Err(Response::bad_request("Invalid Content-Length"))),
+            };
+            let method = parsed.method.unwrap_or_default().to_string();
+            let target = parsed.path.unwrap_or_default().to_string();
+
+            let mut body = buf.split_off(head_len);
+            body.truncate(length);
+            if body.len() < length {
+                let start = body.len();
+                body.resize(length, 0);
+                stream.read_exact(&mut body[start..]).await?;
+            }
+            return Ok(Ok(HttpRequest { method, target, body }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::Endpoint;
+
+    fn endpoint(method: &str, target: &str) -> Endpoint {
+        to_request(method, target, b"").unwrap().parse_endpoint().unwrap()
+    }
+
+    #[test]
+    fn test_routes_map_onto_endpoints() {
+        assert!(matches!(endpoint("GET", "/containers"), Endpoint::Containers));
+        assert!(matches!(endpoint("GET", "/containers/"), Endpoint::Containers));
+        assert!(matches!(endpoint("POST", "/containers/create"), Endpoint::ContainerCreate));
+        assert!(matches!(endpoint("GET", "/containers/web"), Endpoint::Container(id) if id == "web"));
+        assert!(matches!(endpoint("POST", "/containers/web/start"), Endpoint::StartContainer(id) if id == "web"));
+        assert!(matches!(endpoint("DELETE", "/containers/web/ips"), Endpoint::RemoveContainerIp(id) if id == "web"));
+        assert!(matches!(endpoint("GET", "/jails/my%20jail"), Endpoint::Jail(name) if name == "my jail"));
+
+        let request = to_request("DELETE", "/containers/web?force=true", b"").unwrap();
+        assert_eq!(request.method, Method::Delete);
+        assert_eq!(request.endpoint, "containers/web");
+        assert!(request.peer_uid.is_none());
+
+        assert!(to_request("GET", "/nothing/here", b"").unwrap().parse_endpoint().is_err());
+    }
+
+    #[test]
+    fn test_bodies_and_query_fields() {
+        let request = to_request("POST", "/containers/create", br#"{"name":"web","image":"base"}"#).unwrap();
+        assert_eq!(request.body, serde_json::json!({"name": "web", "image": "base"}));
+        assert_eq!(to_request("GET", "/containers", b" \r\n").unwrap().body, Value::Null);
+
+        let request = to_request("GET", "/containers/web/logs?tail=100&stream=std%20err&since=&q=a+b", b"").unwrap();
+        assert_eq!(request.endpoint, "containers/web/logs");
+        assert_eq!(
+            request.body,
+            serde_json::json!({"tail": 100, "stream": "std err", "since": "", "q": "a b"})
+        );
+        let request = to_request("DELETE", "/containers/web?force=true&name=%2212%22", b"").unwrap();
+        assert_eq!(request.body, serde_json::json!({"force": true, "name": "12"}));
+
+        let refused = to_request("POST", "/containers/create", b"{not json").unwrap_err();
+        assert_eq!(refused.status, status::BAD_REQUEST);
+        let refused = to_request("POST", "/containers/create?x=1", b"{}").unwrap_err();
+        assert_eq!(refused.status, status::BAD_REQUEST);
+        let refused = to_request("PUT", "/containers/web", b"").unwrap_err();
+        assert_eq!(refused.status, status::METHOD_NOT_ALLOWED);
+        assert_eq!(refused.error.unwrap().code, "METHOD_NOT_ALLOWED");
+    }
+
+    #[test]
+    fn test_responses_to_http() {
+        let ok = Response::success(serde_json::json!({"id": "abc"})).unwrap();
+        assert_eq!(to_http(&ok), (status::OK, br#"{"id":"abc"}"#.to_vec()));
+
+        let (code, body) = to_http(&Response::not_found("container web".to_string()));
+        assert_eq!(code, status::NOT_FOUND);
+        let error: ApiError = serde_json::from_slice(&body).unwrap();
+        assert_eq!(error.code, "NOT_FOUND");
+
+        assert_eq!(reason(status::CONFLICT), "Conflict");
+        assert_eq!(reason(status::METHOD_NOT_ALLOWED), "Method Not Allowed");
+    }
+
+    #[cfg(feature = "http-gateway")]
+    #[tokio::test]
+    async fn test_serve_answers_over_tcp() {
+        use std::sync::Arc;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::sync::Mutex;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let manager = Arc::new(Mutex::new(crate::JailManager::new("/tmp/test-http-gateway.sock")));
+        manager.lock().await.add_jail("web").unwrap();
+        let server = tokio::spawn(serve(listener, manager));
+
+        let exchange = |raw: &'static str| async move {
+            let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            stream.write_all(raw.as_bytes()).await.unwrap();
+            let mut answer = String::new();
+            stream.read_to_string(&mut answer).await.unwrap();
+            answer
+        };
+
+        let answer = exchange("GET /jails HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(answer.starts_with("HTTP/1.1 200 OK\r\n"), "{}", answer);
+        assert!(answer.contains("Connection: close"));
+        assert!(answer.contains("\"web\""));
+
+        let answer = exchange("POST /jails/nope/start HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}").await;
+        assert!(answer.starts_with("HTTP/1.1 404 Not Found\r\n"), "{}", answer);
+
+        let answer = exchange("PATCH /jails HTTP/1.1\r\n\r\n").await;
+        assert!(answer.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"), "{}", answer);
+
+        let answer = exchange("POST /jails HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n").await;
+        assert!(answer.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", answer);
+
+        server.abort();
+    }
+}
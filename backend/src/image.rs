@@ -1,9 +1,11 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 use std::fmt;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::timestamp::Timestamp;
+
 pub type ImageId = String;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -42,13 +44,73 @@ impl std::str::FromStr for ImageState {
     }
 }
 
+/// Whether an image was asked for by a user or left behind by a build
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ImageKind {
+    /// A named image produced as the result of a build
+    #[default]
+    User,
+    /// A cache layer, non-final stage or leftover from a failed build
+    Intermediate,
+    /// A user image whose name moved to a newer build of the same name.
+    /// Containers cloned from it keep working; it is pruned like an
+    /// intermediate image once nothing uses it.
+    Untagged,
+}
+
+impl ImageKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImageKind::User => "user",
+            ImageKind::Intermediate => "intermediate",
+            ImageKind::Untagged => "untagged",
+        }
+    }
+}
+
+impl fmt::Display for ImageKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl std::str::FromStr for ImageKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "user" => Ok(ImageKind::User),
+            "intermediate" => Ok(ImageKind::Intermediate),
+            "untagged" => Ok(ImageKind::Untagged),
+            _ => Err(format!("Invalid ImageKind: {}", s)),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum DockerfileInstruction {
     From(String),
     Bootstrap { version: Option<String>, architecture: Option<String>, mirror: Option<String> },
     Run(String),
-    Copy { from: Option<String>, src: String, dest: String },
-    Add { src: String, dest: String },
+    Copy {
+        from: Option<String>,
+        src: String,
+        dest: String,
+        /// `--chown=user[:group]`, resolved against the image being built
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        chown: Option<String>,
+        /// `--chmod`, permission bits
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        chmod: Option<u32>,
+    },
+    Add {
+        src: String,
+        dest: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        chown: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        chmod: Option<u32>,
+    },
     WorkDir(String),
     Env(HashMap<String, String>),
     Expose(Vec<u16>),
@@ -59,6 +121,37 @@ pub enum DockerfileInstruction {
     Label(HashMap<String, String>),
 }
 
+/// Stands in for a secret value in recorded instructions
+pub const REDACTED: &str = "<redacted>";
+
+impl DockerfileInstruction {
+    /// Replace every occurrence of `secret` in the instruction's arguments
+    pub fn redact(&mut self, secret: &str) {
+        if secret.is_empty() {
+            return;
+        }
+        let scrub = |s: &mut String| {
+            if s.contains(secret) {
+                *s = s.replace(secret, REDACTED);
+            }
+        };
+
+        match self {
+            Self::From(s) | Self::Run(s) | Self::WorkDir(s) | Self::User(s) => scrub(s),
+            Self::Bootstrap { version, architecture, mirror } => {
+                version.iter_mut().chain(architecture).chain(mirror).for_each(scrub)
+            }
+            Self::Copy { from, src, dest, .. } => {
+                from.iter_mut().chain([src, dest]).for_each(scrub)
+            }
+            Self::Add { src, dest, .. } => [src, dest].into_iter().for_each(scrub),
+            Self::Env(map) | Self::Label(map) => map.values_mut().for_each(scrub),
+            Self::Volume(list) | Self::Cmd(list) | Self::Entrypoint(list) => list.iter_mut().for_each(scrub),
+            Self::Expose(_) => {}
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct ImageConfig {
     pub env: HashMap<String, String>,
@@ -81,7 +174,32 @@ pub struct Image {
     pub config: ImageConfig,
     pub size_bytes: u64,
     pub state: ImageState,
-    pub created_at: i64,
+    pub created_at: Timestamp,
+    /// Refuse removal unless explicitly overridden
+    #[serde(default)]
+    pub protected: bool,
+    /// User images are listed by default; intermediate ones are dangling
+    /// unless something still depends on them
+    #[serde(default)]
+    pub kind: ImageKind,
+    /// Userland version recorded by the last upgrade
+    #[serde(default)]
+    pub os_version: Option<String>,
+    /// Digest of what the image was built from (see
+    /// `image_builder::content_digest`); `None` for images not built here
+    #[serde(default)]
+    pub content_digest: Option<String>,
+    /// Log file of the build that produced the image (see `build_log`)
+    #[serde(default)]
+    pub build_log: Option<String>,
+    /// Verified manifest digest of the artifact the image was loaded,
+    /// pulled or imported from (see `artifact`); `None` for local builds
+    #[serde(default)]
+    pub digest: Option<String>,
+    /// Newer kawakaze version that last wrote the record; the image is
+    /// read-only while set (see `version`)
+    #[serde(default)]
+    pub written_by_newer: Option<String>,
 }
 
 impl Image {
@@ -95,7 +213,14 @@ impl Image {
             config: ImageConfig::default(),
             size_bytes: 0,
             state: ImageState::Building,
-            created_at: chrono::Utc::now().timestamp(),
+            created_at: Timestamp::now(),
+            protected: false,
+            kind: ImageKind::User,
+            os_version: None,
+            content_digest: None,
+            build_log: None,
+            digest: None,
+            written_by_newer: None,
         }
     }
 
@@ -128,6 +253,64 @@ impl Image {
         self
     }
 
+    pub fn with_protected(mut self, protected: bool) -> Self {
+        self.protected = protected;
+        self
+    }
+
+    pub fn with_content_digest(mut self, digest: String) -> Self {
+        self.content_digest = Some(digest);
+        self
+    }
+
+    pub fn with_digest(mut self, digest: String) -> Self {
+        self.digest = Some(digest);
+        self
+    }
+
+    pub fn with_build_log(mut self, path: String) -> Self {
+        self.build_log = Some(path);
+        self
+    }
+
+    pub fn with_kind(mut self, kind: ImageKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    pub fn is_intermediate(&self) -> bool {
+        self.kind == ImageKind::Intermediate
+    }
+
+    pub fn is_untagged(&self) -> bool {
+        self.kind == ImageKind::Untagged
+    }
+
+    /// Give up the image's name. The name column is unique, so the image
+    /// keeps its ID there instead.
+    pub fn untag(&mut self) {
+        self.name = self.id.clone();
+        self.kind = ImageKind::Untagged;
+    }
+
+    /// Name to show for the image: `<none>` once untagged
+    pub fn display_name(&self) -> &str {
+        if self.is_untagged() { "<none>" } else { &self.name }
+    }
+
+    /// Size shown for each step in the image's history: the image's size
+    /// spread evenly over its instructions. An image without any (built
+    /// from a Dockerfile that is only `FROM scratch`) has no steps to spread
+    /// it over, so 0.
+    pub fn layer_size(&self) -> u64 {
+        self.size_bytes.checked_div(self.dockerfile.len() as u64).unwrap_or(0)
+    }
+
+    /// Why the image can't be changed, if it can't
+    pub fn readonly_reason(&self) -> Option<&'static str> {
+        self.written_by_newer.as_ref().map(|_| crate::version::READONLY_REASON)
+    }
+
     pub fn is_available(&self) -> bool {
         self.state == ImageState::Available
     }
@@ -141,6 +324,61 @@ impl Image {
     }
 }
 
+/// Images an `image prune` should remove, children before their parents
+///
+/// Intermediate and untagged images are always candidates; user images only
+/// when `all` is set. Anything in `in_use` (e.g. the image of an existing container) or
+/// protected is kept, and so is every ancestor of a kept image, so a dangling
+/// cache layer that a tagged image is built on survives the prune.
+pub fn prune_candidates<'a, I>(images: I, in_use: &HashSet<ImageId>, all: bool) -> Vec<ImageId>
+where
+    I: IntoIterator<Item = &'a Image>,
+{
+    let by_id: HashMap<&str, &Image> = images.into_iter().map(|image| (image.id.as_str(), image)).collect();
+
+    // Walk up from every image that has to stay, keeping its whole chain
+    let mut keep: HashSet<&str> = HashSet::new();
+    for image in by_id.values() {
+        let is_root = in_use.contains(&image.id)
+            || image.protected
+            || (!all && image.kind == ImageKind::User);
+        if !is_root {
+            continue;
+        }
+
+        let mut current = Some(image.id.as_str());
+        while let Some(id) = current {
+            if !keep.insert(id) {
+                break;
+            }
+            current = by_id.get(id).and_then(|image| image.parent_id.as_deref());
+        }
+    }
+
+    // Order so that an image is always removed before its parent
+    let depth = |id: &str| {
+        let mut depth = 0;
+        let mut seen = HashSet::new();
+        let mut current = by_id.get(id).and_then(|image| image.parent_id.as_deref());
+        while let Some(parent) = current {
+            if !seen.insert(parent) {
+                break;
+            }
+            depth += 1;
+            current = by_id.get(parent).and_then(|image| image.parent_id.as_deref());
+        }
+        depth
+    };
+
+    let mut candidates: Vec<(usize, &str)> = by_id
+        .keys()
+        .filter(|id| !keep.contains(*id))
+        .map(|id| (depth(id), *id))
+        .collect();
+    candidates.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(b.1)));
+    candidates.into_iter().map(|(_, id)| id.to_string()).collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -189,7 +427,7 @@ mod tests {
         assert_eq!(image.parent_id, None);
         assert_eq!(image.snapshot, "");
         assert_eq!(image.size_bytes, 0);
-        assert!(image.created_at > 0);
+        assert!(image.created_at > Timestamp::EPOCH);
         assert!(!image.id.is_empty());
     }
 
@@ -312,12 +550,21 @@ mod tests {
             from: Some("stage1".to_string()),
             src: "/app/src".to_string(),
             dest: "/usr/src".to_string(),
+            chown: Some("www:www".to_string()),
+            chmod: Some(0o755),
         };
 
         let json = serde_json::to_string(&instruction).unwrap();
         let deserialized: DockerfileInstruction = serde_json::from_str(&json).unwrap();
 
         assert_eq!(instruction, deserialized);
+
+        // Without flags the fields are left out, so records and build cache
+        // keys from before them are unchanged
+        let json = r#"{"Add":{"src":"a","dest":"/b"}}"#;
+        let add: DockerfileInstruction = serde_json::from_str(json).unwrap();
+        assert_eq!(add, DockerfileInstruction::Add { src: "a".into(), dest: "/b".into(), chown: None, chmod: None });
+        assert_eq!(serde_json::to_string(&add).unwrap(), json);
     }
 
     #[test]
@@ -368,4 +615,110 @@ mod tests {
         );
         assert_eq!(image.config.labels.get("version"), Some(&"1.0.0".to_string()));
     }
+
+    fn graph_image(id: &str, parent: Option<&str>, kind: ImageKind) -> Image {
+        let mut image = Image::new(id.to_string(), vec![]).with_kind(kind);
+        image.id = id.to_string();
+        image.parent_id = parent.map(str::to_string);
+        image
+    }
+
+    /// base <- layer1 <- layer2 <- app (tagged), plus an orphaned chain
+    /// failed1 <- failed2 from a build that never finished
+    fn graph_fixture() -> Vec<Image> {
+        vec![
+            graph_image("base", None, ImageKind::User),
+            graph_image("layer1", Some("base"), ImageKind::Intermediate),
+            graph_image("layer2", Some("layer1"), ImageKind::Intermediate),
+            graph_image("app", Some("layer2"), ImageKind::User),
+            graph_image("failed1", Some("base"), ImageKind::Intermediate),
+            graph_image("failed2", Some("failed1"), ImageKind::Intermediate),
+        ]
+    }
+
+    #[test]
+    fn test_image_kind_round_trip() {
+        assert_eq!(ImageKind::default(), ImageKind::User);
+        assert_eq!("intermediate".parse::<ImageKind>().unwrap(), ImageKind::Intermediate);
+        assert_eq!(ImageKind::Intermediate.to_string(), "intermediate");
+        assert!("layer".parse::<ImageKind>().is_err());
+        assert_eq!("untagged".parse::<ImageKind>().unwrap(), ImageKind::Untagged);
+    }
+
+    #[test]
+    fn test_untag() {
+        let mut image = Image::new("web".to_string(), vec![]);
+        assert_eq!(image.display_name(), "web");
+
+        image.untag();
+        assert!(image.is_untagged());
+        assert_eq!(image.name, image.id);
+        assert_eq!(image.display_name(), "<none>");
+    }
+
+    #[test]
+    fn test_layer_size() {
+        let run = |cmd: &str| DockerfileInstruction::Run(cmd.to_string());
+        let image = Image::new("app".to_string(), vec![run("a"), run("b"), run("c")]).with_size(3000);
+        assert_eq!(image.layer_size(), 1000);
+
+        // `FROM scratch` alone leaves no instructions
+        assert_eq!(Image::new("empty".to_string(), vec![]).with_size(4096).layer_size(), 0);
+    }
+
+    #[test]
+    fn test_redact_instruction() {
+        let mut run = DockerfileInstruction::Run("curl -H 'Authorization: tok123' https://x".to_string());
+        run.redact("tok123");
+        assert_eq!(run, DockerfileInstruction::Run("curl -H 'Authorization: <redacted>' https://x".to_string()));
+
+        let mut env = DockerfileInstruction::Env(HashMap::from([("TOKEN".to_string(), "tok123".to_string())]));
+        env.redact("tok123");
+        assert_eq!(env, DockerfileInstruction::Env(HashMap::from([("TOKEN".to_string(), REDACTED.to_string())])));
+
+        let mut untouched = DockerfileInstruction::Run("echo $TOKEN".to_string());
+        untouched.redact("");
+        assert_eq!(untouched, DockerfileInstruction::Run("echo $TOKEN".to_string()));
+    }
+
+    #[test]
+    fn test_prune_dangling_includes_untagged_unless_in_use() {
+        let mut images = graph_fixture();
+        images.push(graph_image("app-old", Some("base"), ImageKind::Untagged));
+        images.push(graph_image("app-older", Some("base"), ImageKind::Untagged));
+        let in_use: HashSet<ImageId> = ["app-older".to_string()].into_iter().collect();
+
+        let pruned = prune_candidates(&images, &in_use, false);
+        assert!(pruned.contains(&"app-old".to_string()));
+        assert!(!pruned.contains(&"app-older".to_string()));
+    }
+
+    #[test]
+    fn test_prune_dangling_keeps_ancestors_of_tagged_images() {
+        let images = graph_fixture();
+        let pruned = prune_candidates(&images, &HashSet::new(), false);
+
+        // layer1/layer2 are intermediate but app depends on them
+        assert_eq!(pruned, vec!["failed2".to_string(), "failed1".to_string()]);
+    }
+
+    #[test]
+    fn test_prune_dangling_keeps_in_use_and_protected() {
+        let mut images = graph_fixture();
+        images.iter_mut().find(|i| i.id == "failed1").unwrap().protected = true;
+        let in_use: HashSet<ImageId> = ["failed2".to_string()].into_iter().collect();
+
+        assert!(prune_candidates(&images, &in_use, false).is_empty());
+    }
+
+    #[test]
+    fn test_prune_all_removes_unreferenced_user_images() {
+        let images = graph_fixture();
+        let in_use: HashSet<ImageId> = ["layer1".to_string()].into_iter().collect();
+        let pruned = prune_candidates(&images, &in_use, true);
+
+        // Only layer1 and its parent stay; children come before parents
+        assert_eq!(pruned, vec!["app", "failed2", "layer2", "failed1"]);
+        assert!(!pruned.contains(&"base".to_string()));
+    }
 }
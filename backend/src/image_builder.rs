@@ -1,12 +1,20 @@
 //! Image building from Dockerfiles
 //!
 //! This module provides functionality for building images from Dockerfile-like specifications.
-//! It supports parsing Dockerfiles, executing instructions in a chroot environment,
+//! It supports parsing Dockerfiles, executing instructions in a chroot environment
+//! (or, for RUN with `network: none`, a temporary jail; see [`crate::build_jail`]),
 //! and managing ZFS snapshots for layer management.
 
 use crate::image::{Image, ImageConfig, DockerfileInstruction, ImageId};
 use crate::zfs::Zfs;
-use crate::bootstrap::{Bootstrap, BootstrapConfig};
+use crate::vars::{self, BuildVars};
+use crate::bootstrap::{Bootstrap, BootstrapConfig, MirrorBootstrapper};
+use crate::build_jail::{self, BuildNetwork};
+use crate::build_log;
+use crate::build_dirs::BuildDir;
+use crate::layer_cache::{self, Layers};
+use crate::ownership::{self, Owner};
+use crate::paths::Paths;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
@@ -38,11 +46,30 @@ pub struct ImageBuildProgress {
     pub total_steps: usize,
     pub current_instruction: String,
     pub status: BuildStatus,
+    /// File counts for a COPY/ADD of a large directory, while it runs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub copy: Option<CopyProgress>,
 }
 
+/// Progress of copying a directory tree into the build root
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CopyProgress {
+    pub files_copied: u64,
+    pub files_total: u64,
+    pub bytes_copied: u64,
+    pub bytes_total: u64,
+}
+
+/// Report COPY progress after this many files...
+const COPY_PROGRESS_FILES: u64 = 500;
+/// ...or this many bytes since the last update, whichever comes first
+const COPY_PROGRESS_BYTES: u64 = 64 * 1024 * 1024;
+
 /// Status of an image build operation
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum BuildStatus {
+    /// Waiting for a build slot (see `task_queue`)
+    Queued,
     Building,
     Failed,
     Complete,
@@ -55,8 +82,26 @@ pub struct ImageBuilder {
     progress_tx: mpsc::Sender<ImageBuildProgress>,
     build_args: HashMap<String, String>,
     build_context: PathBuf,
+    base_snapshot: Option<String>,
+    keep_snapshots: Option<usize>,
+    strict_vars: bool,
+    secrets: HashMap<String, String>,
+    network: BuildNetwork,
+    api_socket: Option<PathBuf>,
+    image_id: Option<ImageId>,
+    build_log: Option<PathBuf>,
+    paths: Paths,
+    layer_snapshots: bool,
+    no_cache: bool,
 }
 
+/// Directory COPY/ADD sources are resolved against unless
+/// `with_build_context` says otherwise
+pub const DEFAULT_BUILD_CONTEXT: &str = ".";
+
+/// Directory inside the build root that RUN steps find secrets in
+pub const SECRETS_DIR: &str = "run/secrets";
+
 impl ImageBuilder {
     /// Create a new image builder
     ///
@@ -75,7 +120,18 @@ impl ImageBuilder {
             base_dataset,
             progress_tx,
             build_args: HashMap::new(),
-            build_context: PathBuf::from("."),
+            build_context: PathBuf::from(DEFAULT_BUILD_CONTEXT),
+            base_snapshot: None,
+            keep_snapshots: None,
+            strict_vars: false,
+            secrets: HashMap::new(),
+            network: BuildNetwork::Default,
+            api_socket: None,
+            image_id: None,
+            build_log: None,
+            paths: Paths::default(),
+            layer_snapshots: false,
+            no_cache: false,
         };
         (builder, progress_rx)
     }
@@ -92,6 +148,82 @@ impl ImageBuilder {
         self
     }
 
+    /// Start from a base system snapshot (e.g. resolved from `FROM freebsd:<version>`)
+    pub fn with_base_snapshot(mut self, snapshot: String) -> Self {
+        self.base_snapshot = Some(snapshot);
+        self
+    }
+
+    /// Prune the image dataset down to `keep` snapshots after a build
+    pub fn with_snapshot_retention(mut self, keep: usize) -> Self {
+        self.keep_snapshots = Some(keep);
+        self
+    }
+
+    /// Fail the build on references to undefined variables instead of
+    /// leaving them as written (`RUN` still leaves them to the shell)
+    pub fn with_strict_vars(mut self, strict: bool) -> Self {
+        self.strict_vars = strict;
+        self
+    }
+
+    /// Secrets for RUN steps, by name. Each one is exported as an
+    /// environment variable and written to `/run/secrets/<name>` while a RUN
+    /// executes. They never take part in variable substitution and are
+    /// scrubbed from the recorded instructions.
+    pub fn with_secrets(mut self, secrets: HashMap<String, String>) -> Self {
+        self.secrets = secrets;
+        self
+    }
+
+    /// Network available to RUN steps
+    pub fn with_network(mut self, network: BuildNetwork) -> Self {
+        self.network = network;
+        self
+    }
+
+    /// The daemon's API socket, which RUN steps sharing the host's network
+    /// must not be able to reach
+    pub fn with_api_socket(mut self, socket: impl Into<PathBuf>) -> Self {
+        self.api_socket = Some(socket.into());
+        self
+    }
+
+    /// ID for the resulting image, drawn by the caller so it can avoid
+    /// existing names; a fresh one is generated otherwise
+    pub fn with_image_id(mut self, id: ImageId) -> Self {
+        self.image_id = Some(id);
+        self
+    }
+
+    /// Mount build and base datasets under these paths instead of the
+    /// default data root
+    pub fn with_paths(mut self, paths: Paths) -> Self {
+        self.paths = paths;
+        self
+    }
+
+    /// Append step progress and RUN output to this log file
+    pub fn with_build_log(mut self, path: impl Into<PathBuf>) -> Self {
+        self.build_log = Some(path.into());
+        self
+    }
+
+    /// Snapshot the build dataset after every step, and resume from the
+    /// last matching snapshot of a failed build of the same name (see
+    /// [`crate::layer_cache`])
+    pub fn with_layer_snapshots(mut self, enabled: bool) -> Self {
+        self.layer_snapshots = enabled;
+        self
+    }
+
+    /// Run every step even if a failed build of the same name left layers
+    /// that match
+    pub fn with_no_cache(mut self, no_cache: bool) -> Self {
+        self.no_cache = no_cache;
+        self
+    }
+
     /// Build an image from a Dockerfile
     ///
     /// # Arguments
@@ -112,45 +244,109 @@ impl ImageBuilder {
         info!("Starting image build for '{}'", name);
 
         // Parse dockerfile
-        let instructions = self.parse_dockerfile(dockerfile)?;
+        let base_env = from_image.map(|i| i.config.env.clone()).unwrap_or_default();
+        let instructions = self.parse_dockerfile(dockerfile, &base_env)?;
         let total_steps = instructions.len();
 
-        info!("Parsed {} instructions from Dockerfile", total_steps);
+        match instructions.as_slice() {
+            [] => info!("Dockerfile of '{}' is only FROM scratch: building an empty image", name),
+            [DockerfileInstruction::From(base)] => {
+                info!("Dockerfile of '{}' is only FROM {}: building a copy of it under the new name", name, base)
+            }
+            _ => info!("Parsed {} instructions from Dockerfile", total_steps),
+        }
 
         // Initialize config from base image or default
         let mut config = from_image.map(|i| i.config.clone()).unwrap_or_default();
         let mut parent_id = from_image.map(|i| i.id.clone());
 
-        // Create build dataset
-        let build_dataset = format!("{}/build-{}", self.base_dataset, name);
-        self.create_build_dataset(&build_dataset, from_image)?;
+        // A leading BOOTSTRAP on an empty image clones the cached base system
+        // instead of extracting base.txz into every build
+        let base_snapshot = match (from_image, &self.base_snapshot, instructions.first()) {
+            (None, Some(snapshot), _) => Some(snapshot.clone()),
+            (None, None, Some(DockerfileInstruction::Bootstrap { version, architecture, mirror })) => {
+                Some(self.base_snapshot_for(version, architecture, mirror).await?)
+            }
+            _ => None,
+        };
 
-        // Mount the build dataset to a temporary location for building
-        let build_mountpoint = PathBuf::from(format!("/var/db/kawakaze/builds/{}", name.replace('/', "-")));
+        // Resume from the layers a failed build of this name left, or
+        // start over on a fresh build dataset
+        let build_dataset = format!("{}/build-{}", self.base_dataset, name);
+        let layers = if self.layer_snapshots {
+            let base = match (from_image, &base_snapshot) {
+                (Some(image), _) => image.snapshot.as_str(),
+                (None, Some(snapshot)) => snapshot.as_str(),
+                (None, None) => "scratch",
+            };
+            Some(Layers::new(&instructions, base, &self.build_context)?)
+        } else {
+            None
+        };
+        let resumed = self.resume_build_dataset(&build_dataset, layers.as_ref(), from_image, base_snapshot.as_deref())?;
+
+        // Mount the build dataset at a directory of this build's own; the
+        // guard unmounts and removes it however the build ends
+        let image_id = self.image_id.clone().unwrap_or_else(Image::generate_id);
+        let build_mountpoint = self.paths.build_root(&name, &image_id);
+        let zfs = self.zfs.clone();
+        let mut build_dir = BuildDir::create(&build_mountpoint, Box::new(move |dataset: &str| zfs.unmount_dataset(dataset).map_err(|e| e.to_string())))
+            .map_err(|e| ImageError::BuildFailed(format!("Failed to create build directory: {}", e)))?;
         self.zfs.mount_dataset(&build_dataset, &build_mountpoint)
             .map_err(|e| ImageError::Zfs(e.to_string()))?;
+        build_dir.mounted(&build_dataset);
 
         // Execute instructions
         let build_result = (|| async {
             let mut config = from_image.map(|i| i.config.clone()).unwrap_or_default();
             let parent_id = from_image.map(|i| i.id.clone());
 
+            // Steps a layer snapshot already covers only replay their
+            // effect on the config
+            for (step, instruction) in instructions.iter().enumerate().take(resumed) {
+                let description = format!("{} (cached)", describe_instruction(instruction));
+                self.log_progress(&format!("Step {}/{}: {}", step + 1, total_steps, description));
+                self.report_progress(&name, step, total_steps, description, BuildStatus::Building).await;
+                apply_config(instruction, &mut config);
+            }
+            if resumed > 0 {
+                info!("Resumed build of '{}' after step {}/{} from its layer snapshot", name, resumed, total_steps);
+            }
+
             // Execute instructions
-            for (step, instruction) in instructions.iter().enumerate() {
+            for (step, instruction) in instructions.iter().enumerate().skip(resumed) {
+                // A BOOTSTRAP onto a root that already holds a base system (the
+                // cached base snapshot, or a base image) is a cache hit
+                let cached = is_cached_bootstrap(instruction, &build_mountpoint);
+                let description = describe_step(instruction, cached);
+                self.log_progress(&format!("Step {}/{}: {}", step + 1, total_steps, description));
                 self.report_progress(
                     &name,
                     step,
                     total_steps,
-                    instruction,
+                    description,
                     BuildStatus::Building
                 ).await;
+                if cached {
+                    info!("Step {}/{}: base system already in place, not bootstrapping again", step + 1, total_steps);
+                } else {
+                    let mut on_copy = self.copy_progress_reporter(&name, step, total_steps, instruction);
+                    if let Err(e) = self.execute_instruction(&build_mountpoint, instruction, &mut config, &mut on_copy).await {
+                        error!("Build failed at step {}: {}", step, e);
+                        return Err(e);
+                    }
+                }
 
-                if let Err(e) = self.execute_instruction(&build_mountpoint, instruction, &mut config).await {
-                    error!("Build failed at step {}: {}", step, e);
-                    return Err(e);
+                if let Some(layers) = &layers {
+                    layers.commit(&self.zfs, &build_dataset, step + 1)?;
                 }
             }
 
+            // Layer snapshots only serve retries; the image doesn't keep them
+            if layers.is_some() {
+                layer_cache::clear(&self.zfs, &build_dataset)?;
+            }
+
             // Create snapshot of the final image
             let snapshot_name = format!("{}-{}", name.replace('/', "-"), uuid::Uuid::new_v4());
             self.zfs.create_snapshot(&build_dataset, &snapshot_name)
@@ -164,19 +360,38 @@ impl ImageBuilder {
                 Err(_) => 0,
             };
 
-            // Rename build dataset to final image dataset
-            let final_dataset = format!("{}/{}", self.base_dataset, name.replace('/', "-"));
+            // Rename build dataset to final image dataset. A previous image
+            // of this name keeps its dataset (containers may be cloned from
+            // it), so a rebuild gets a dataset of its own.
+            let mut final_dataset = format!("{}/{}", self.base_dataset, name.replace('/', "-"));
+            if self.zfs.dataset_exists(&final_dataset) {
+                final_dataset = format!("{}-{}", final_dataset, &image_id[..8]);
+            }
             self.zfs.rename(&build_dataset, &final_dataset)
                 .map_err(|e| ImageError::Zfs(e.to_string()))?;
+            build_dir.mounted(&final_dataset);
 
             let final_snapshot = format!("{}@{}", final_dataset, snapshot_name);
 
+            // The new snapshot is the newest, so it is always kept
+            if let Some(keep) = self.keep_snapshots {
+                match self.zfs.prune_snapshots(&final_dataset, keep.max(1)) {
+                    Ok(pruned) if !pruned.is_empty() => {
+                        info!("Pruned {} old snapshot(s) of {}", pruned.len(), final_dataset)
+                    }
+                    Ok(_) => {}
+                    Err(e) => warn!("Failed to prune snapshots of {}: {}", final_dataset, e),
+                }
+            }
+
             // Create image - only set parent_id if we have a base image
-            let mut image = Image::new(name, instructions)
+            let mut image = Image::new(name, scrub_secrets(instructions, &self.secrets))
                 .with_snapshot(final_snapshot)
                 .with_config(config)
                 .with_size(size_bytes)
-                .with_state(crate::image::ImageState::Available);
+                .with_state(crate::image::ImageState::Available)
+                .with_kind(crate::image::ImageKind::User);
+            image.id = image_id.clone();
 
             // Set parent_id only if building from a base image
             if let Some(pid) = parent_id {
@@ -187,7 +402,7 @@ impl ImageBuilder {
                 &image.id,
                 total_steps,
                 total_steps,
-                &DockerfileInstruction::Run("complete".to_string()),
+                describe_instruction(&DockerfileInstruction::Run("complete".to_string())),
                 BuildStatus::Complete
             ).await;
 
@@ -195,233 +410,84 @@ impl ImageBuilder {
             Ok::<Image, ImageError>(image)
         })().await;
 
-        // Unmount the build dataset
-        let _ = self.zfs.unmount_dataset(&build_dataset);
-
+        drop(build_dir);
         build_result
     }
 
-    /// Parse a Dockerfile into instructions
-    fn parse_dockerfile(&self, dockerfile: &str) -> Result<Vec<DockerfileInstruction>> {
-        let mut instructions = Vec::new();
-
-        for (line_num, line) in dockerfile.lines().enumerate() {
-            let line = line.trim();
-
-            // Skip empty lines and comments
-            if line.is_empty() || line.starts_with('#') {
-                continue;
-            }
-
-            // Handle line continuation
-            let mut full_line = line.to_string();
-            if line.ends_with('\\') {
-                // Remove the backslash
-                full_line.pop();
-                full_line.push(' ');
-                continue; // Would need multi-line handling in a more complete implementation
-            }
-
-            // Parse instruction
-            match self.parse_instruction(&full_line) {
-                Ok(instr) => instructions.push(instr),
-                Err(e) => {
-                    error!("Failed to parse line {}: {}", line_num + 1, e);
-                    return Err(ImageError::ParseError(
-                        format!("Line {}: {}", line_num + 1, e)
-                    ));
-                }
-            }
-        }
-
-        // Validate Dockerfile has FROM as first instruction
-        // Allow "scratch" as a special no-op base image
-        if !instructions.is_empty() {
-            if !matches!(&instructions[0], DockerfileInstruction::From(_)) {
-                return Err(ImageError::ParseError(
-                    "Dockerfile must start with FROM instruction".into()
-                ));
-            }
-            // If FROM scratch, remove it from instructions since it's a no-op
-            if matches!(&instructions[0], DockerfileInstruction::From(name) if name == "scratch") {
-                instructions.remove(0);
-            }
-        }
-
-        Ok(instructions)
+    /// Parse a Dockerfile into instructions with this builder's build args
+    fn parse_dockerfile(&self, dockerfile: &str, base_env: &HashMap<String, String>) -> Result<Vec<DockerfileInstruction>> {
+        parse_dockerfile(dockerfile, &self.build_args, base_env, self.strict_vars)
     }
 
-    /// Parse a single Dockerfile instruction
-    fn parse_instruction(&self, line: &str) -> Result<DockerfileInstruction> {
-        let line = self.substitute_build_args(line);
-
-        let parts: Vec<&str> = line.splitn(2, ' ').collect();
-        if parts.is_empty() {
-            return Err(ImageError::ParseError("Empty instruction".into()));
-        }
-
-        let instruction = parts[0].to_uppercase();
-        let args = parts.get(1).unwrap_or(&"").trim();
-
-        match instruction.as_str() {
-            "FROM" => Ok(DockerfileInstruction::From(args.to_string())),
-
-            "BOOTSTRAP" => {
-                // Parse BOOTSTRAP [VERSION] [ARCHITECTURE] [MIRROR]
-                let parts: Vec<&str> = args.split_whitespace().collect();
-                let version = if parts.len() > 0 && !parts[0].is_empty() {
-                    Some(parts[0].to_string())
-                } else {
-                    None
-                };
-                let architecture = if parts.len() > 1 && !parts[1].is_empty() {
-                    Some(parts[1].to_string())
-                } else {
-                    None
-                };
-                let mirror = if parts.len() > 2 && !parts[2].is_empty() {
-                    Some(parts[2].to_string())
-                } else {
-                    None
-                };
-                Ok(DockerfileInstruction::Bootstrap { version, architecture, mirror })
-            }
-
-            "RUN" => Ok(DockerfileInstruction::Run(args.to_string())),
-
-            "COPY" => {
-                let parts: Vec<&str> = args.split_whitespace().collect();
-                if parts.len() < 2 {
-                    return Err(ImageError::ParseError("COPY requires source and destination".into()));
-                }
-                let dst = parts.last().unwrap().to_string();
-                let src = parts[0].to_string();
-                Ok(DockerfileInstruction::Copy {
-                    from: None,
-                    src,
-                    dest: dst,
-                })
-            }
-
-            "ADD" => {
-                let parts: Vec<&str> = args.split_whitespace().collect();
-                if parts.len() < 2 {
-                    return Err(ImageError::ParseError("ADD requires source and destination".into()));
-                }
-                let dst = parts.last().unwrap().to_string();
-                let src = parts[0].to_string();
-                Ok(DockerfileInstruction::Add { src, dest: dst })
-            }
-
-            "WORKDIR" => Ok(DockerfileInstruction::WorkDir(args.to_string())),
-
-            "ENV" => {
-                let mut env_map = HashMap::new();
-                // Parse multiple ENV vars
-                for part in args.split_whitespace().collect::<Vec<_>>().chunks(2) {
-                    if part.len() == 2 {
-                        env_map.insert(part[0].to_string(), part[1].to_string());
-                    }
-                }
-                Ok(DockerfileInstruction::Env(env_map))
-            }
-
-            "EXPOSE" => {
-                let ports: std::result::Result<Vec<u16>, _> = args
-                    .split_whitespace()
-                    .map(|p| p.parse::<u16>().map_err(|_| ImageError::ParseError(format!("Invalid port: {}", p))))
-                    .collect();
-                Ok(DockerfileInstruction::Expose(ports?))
-            }
-
-            "USER" => Ok(DockerfileInstruction::User(args.to_string())),
-
-            "VOLUME" => {
-                let volumes: Vec<String> = args
-                    .split_whitespace()
-                    .map(|v| v.to_string())
-                    .collect();
-                Ok(DockerfileInstruction::Volume(volumes))
-            }
-
-            "CMD" => {
-                let cmd = if args.starts_with('[') {
-                    // Exec form
-                    serde_json::from_str::<Vec<String>>(args)
-                        .map_err(|_| ImageError::ParseError("Invalid CMD syntax".into()))?
-                } else {
-                    // Shell form
-                    vec![args.to_string()]
-                };
-                Ok(DockerfileInstruction::Cmd(cmd))
-            }
-
-            "ENTRYPOINT" => {
-                let entrypoint = if args.starts_with('[') {
-                    serde_json::from_str::<Vec<String>>(args)
-                        .map_err(|_| ImageError::ParseError("Invalid ENTRYPOINT syntax".into()))?
-                } else {
-                    vec![args.to_string()]
-                };
-                Ok(DockerfileInstruction::Entrypoint(entrypoint))
-            }
+    /// Root Kawakaze dataset (the parent of the images dataset)
+    fn root_dataset(&self) -> &str {
+        self.base_dataset
+            .rsplit_once('/')
+            .map(|(root, _)| root)
+            .unwrap_or_else(|| self.zfs.pool())
+    }
 
-            "LABEL" => {
-                let mut label_map = HashMap::new();
-                for part in args.split_whitespace().collect::<Vec<_>>().chunks(2) {
-                    if part.len() == 2 {
-                        label_map.insert(part[0].to_string(), part[1].to_string());
-                    }
-                }
-                Ok(DockerfileInstruction::Label(label_map))
-            }
+    /// Get the cached base system snapshot for a BOOTSTRAP instruction, creating it if needed
+    async fn base_snapshot_for(
+        &self,
+        version: &Option<String>,
+        architecture: &Option<String>,
+        mirror: &Option<String>,
+    ) -> Result<String> {
+        let config = BootstrapConfig {
+            version: version.clone(),
+            architecture: architecture.clone(),
+            mirror: mirror.clone(),
+            ..Default::default()
+        };
+        let version = config.resolve_version()
+            .map_err(|e| ImageError::BuildFailed(e.to_string()))?;
+        let architecture = config.resolve_architecture()
+            .map_err(|e| ImageError::BuildFailed(e.to_string()))?;
+
+        let bootstrapper = MirrorBootstrapper { mirror: mirror.clone() };
+        let mountpoint = self.paths.base_root(&version, &architecture);
+        crate::bootstrap::ensure_base_snapshot(&self.zfs, self.root_dataset(), &version, &architecture, &mountpoint, &bootstrapper)
+            .await
+            .map_err(|e| ImageError::BuildFailed(format!("Bootstrap failed: {}", e)))
+    }
 
-            "ARG" => {
-                // ARG is handled during build, not stored
-                let parts: Vec<&str> = args.split('=').collect();
-                if !parts.is_empty() {
-                    debug!("Build ARG: {}", parts[0]);
+    /// Prepare the build dataset and return how many steps it already has
+    ///
+    /// With `layers`, a dataset left by a failed build is rolled back to its
+    /// deepest matching layer. Otherwise, or if nothing matches, it is
+    /// destroyed and created afresh.
+    fn resume_build_dataset(
+        &self,
+        dataset: &str,
+        layers: Option<&Layers>,
+        from_image: Option<&Image>,
+        base_snapshot: Option<&str>,
+    ) -> Result<usize> {
+        if self.zfs.dataset_exists(dataset) {
+            if let Some(layers) = layers
+                && !self.no_cache
+            {
+                let resumed = layers.resume(&self.zfs, dataset)?;
+                if resumed > 0 {
+                    return Ok(resumed);
                 }
-                Ok(DockerfileInstruction::Run(format!("# ARG {}", args)))
             }
-
-            "STOPSIGNAL" => {
-                // Store as label for now
-                let mut labels = HashMap::new();
-                labels.insert("stop_signal".to_string(), args.to_string());
-                Ok(DockerfileInstruction::Label(labels))
-            }
-
-            "SHELL" => {
-                let shell = if args.starts_with('[') {
-                    serde_json::from_str::<Vec<String>>(args)
-                        .map_err(|_| ImageError::ParseError("Invalid SHELL syntax".into()))?
-                } else {
-                    vec![args.to_string()]
-                };
-                // Store as label for now
-                let mut labels = HashMap::new();
-                labels.insert("shell".to_string(), shell.join(" "));
-                Ok(DockerfileInstruction::Label(labels))
-            }
-
-            _ => Err(ImageError::ParseError(format!("Unknown instruction: {}", instruction))),
+            info!("Destroying {} left by an earlier build", dataset);
+            self.zfs.destroy(dataset)
+                .map_err(|e| ImageError::Zfs(e.to_string()))?;
         }
-    }
 
-    /// Substitute build arguments in a line
-    fn substitute_build_args(&self, line: &str) -> String {
-        let mut result = line.to_string();
-        for (key, value) in &self.build_args {
-            result = result.replace(&format!("${{{}}}", key), value);
-            result = result.replace(&format!("${}", key), value);
-        }
-        result
+        self.create_build_dataset(dataset, from_image, base_snapshot)?;
+        Ok(0)
     }
 
-    /// Create a build dataset, cloning from base image if provided
-    fn create_build_dataset(&self, dataset: &str, from_image: Option<&Image>) -> Result<()> {
-        if let Some(base) = from_image {
+    /// Create a build dataset, cloning from base image or base system snapshot if provided
+    fn create_build_dataset(&self, dataset: &str, from_image: Option<&Image>, base_snapshot: Option<&str>) -> Result<()> {
+        if let Some(snapshot) = base_snapshot {
+            self.zfs.clone_snapshot(snapshot, dataset)
+                .map_err(|e| ImageError::Zfs(e.to_string()))?;
+        } else if let Some(base) = from_image {
             // Clone from base image snapshot
             let snapshot_parts: Vec<&str> = base.snapshot.split('@').collect();
             if snapshot_parts.len() != 2 {
@@ -445,6 +511,7 @@ impl ImageBuilder {
         root: &Path,
         instruction: &DockerfileInstruction,
         config: &mut ImageConfig,
+        on_copy: &mut (dyn FnMut(&CopyProgress) + Send),
     ) -> Result<()> {
         match instruction {
             DockerfileInstruction::From(_) => {
@@ -462,41 +529,38 @@ impl ImageBuilder {
                 self.execute_run(root, cmd).await?;
             }
 
-            DockerfileInstruction::Copy { from: _, src, dest } => {
+            DockerfileInstruction::Copy { from: _, src, dest, chown, chmod } => {
                 info!("Executing COPY: {} -> {}", src, dest);
-                self.execute_copy(root, src, dest)?;
+                let owner = resolve_copy_owner(root, chown.as_deref())?;
+                self.execute_copy(root, src, dest, owner, *chmod, on_copy)?;
             }
 
-            DockerfileInstruction::Add { src, dest } => {
+            DockerfileInstruction::Add { src, dest, chown, chmod } => {
                 info!("Executing ADD: {} -> {}", src, dest);
-                self.execute_add(root, src, dest)?;
+                let owner = resolve_copy_owner(root, chown.as_deref())?;
+                self.execute_add(root, src, dest, owner, *chmod, on_copy)?;
             }
 
             DockerfileInstruction::WorkDir(path) => {
                 info!("Setting WORKDIR: {}", path);
-                config.workdir = Some(PathBuf::from(path));
                 self.create_directory(root, path)?;
             }
 
             DockerfileInstruction::Env(env_map) => {
                 info!("Setting ENV: {} variables", env_map.len());
-                config.env.extend(env_map.clone());
                 self.write_environment(root, env_map)?;
             }
 
             DockerfileInstruction::Expose(ports) => {
                 info!("Exposing ports: {:?}", ports);
-                config.exposed_ports.extend(ports);
             }
 
             DockerfileInstruction::User(user) => {
                 info!("Setting USER: {}", user);
-                config.user = Some(user.clone());
             }
 
             DockerfileInstruction::Volume(volumes) => {
                 info!("Adding volumes: {:?}", volumes);
-                config.volumes.extend(volumes.clone());
                 for vol in volumes {
                     self.create_directory(root, vol)?;
                 }
@@ -504,35 +568,57 @@ impl ImageBuilder {
 
             DockerfileInstruction::Cmd(cmd) => {
                 info!("Setting CMD: {:?}", cmd);
-                config.cmd = Some(cmd.clone());
             }
 
             DockerfileInstruction::Entrypoint(ep) => {
                 info!("Setting ENTRYPOINT: {:?}", ep);
-                config.entrypoint = Some(ep.clone());
             }
 
             DockerfileInstruction::Label(labels) => {
                 info!("Adding labels: {} entries", labels.len());
-                config.labels.extend(labels.clone());
-            }
-
-            _ => {
-                debug!("Skipping instruction: {:?}", instruction);
             }
         }
 
+        apply_config(instruction, config);
         Ok(())
     }
 
-    /// Execute a RUN instruction in the chroot
+    /// Execute a RUN instruction in the chroot, or in a temporary jail
+    /// without network
     async fn execute_run(&mut self, root: &Path, cmd: &str) -> Result<()> {
-        debug!("Running command in chroot: {}", cmd);
+        debug!("Running command ({} network): {}", self.network.as_str(), cmd);
+
+        let _mounted = MountedSecrets::write(root, &self.secrets)?;
+
+        if self.network == BuildNetwork::Default
+            && let Some(socket) = &self.api_socket
+            && build_jail::socket_reachable(root, socket)?
+        {
+            return Err(ImageError::BuildFailed(format!(
+                "The API socket {} is reachable from the build root {}; refusing to RUN",
+                socket.display(),
+                root.display()
+            )));
+        }
 
         // Check if we're on FreeBSD and if chroot is available
         #[cfg(target_os = "freebsd")]
         {
-            let result = self.chroot_command(root, cmd);
+            if self.network == BuildNetwork::None {
+                let mut jail = build_jail::TempJail::new(root);
+                jail.log = self.build_log.clone();
+                let (cmd, env) = (cmd.to_string(), self.secrets.clone());
+                return tokio::task::spawn_blocking(move || {
+                    build_jail::run_in_temp_jail(&build_jail::CommandJailBackend, &jail, &cmd, &env)
+                })
+                .await
+                .map_err(|e| ImageError::BuildFailed(format!("RUN task failed: {}", e)))?
+                .map_err(ImageError::BuildFailed);
+            }
+
+            let env: Vec<(&String, &String)> = self.secrets.iter().collect();
+            let log = self.build_log.as_deref().and_then(|path| build_log::open_for_output(path).ok());
+            let result = self.chroot_command(root, cmd, &env, log.as_ref());
             if result {
                 return Ok(());
             }
@@ -562,14 +648,27 @@ impl ImageBuilder {
 
     /// Execute a command in a chroot environment
     #[cfg(target_os = "freebsd")]
-    fn chroot_command(&self, root: &Path, cmd: &str) -> bool {
+    fn chroot_command(&self, root: &Path, cmd: &str, env: &[(&String, &String)], log: Option<&fs::File>) -> bool {
         use std::ffi::CString;
+        use std::os::fd::AsRawFd;
+
+        let log_fd = log.map(|file| file.as_raw_fd());
 
         let root_c = match CString::new(root.to_string_lossy().as_ref()) {
             Ok(r) => r,
             Err(_) => return false,
         };
 
+        // Prepared before forking; the child only calls setenv
+        let env_c: Vec<(CString, CString)> = match env
+            .iter()
+            .map(|(k, v)| Ok((CString::new(k.as_str())?, CString::new(v.as_str())?)))
+            .collect::<std::result::Result<_, std::ffi::NulError>>()
+        {
+            Ok(env) => env,
+            Err(_) => return false,
+        };
+
         // Fork and exec in chroot
         unsafe {
             match libc::fork() {
@@ -582,6 +681,14 @@ impl ImageBuilder {
                     if libc::chroot(root_c.as_ptr()) != 0 {
                         libc::_exit(1);
                     }
+                    for (key, value) in &env_c {
+                        libc::setenv(key.as_ptr(), value.as_ptr(), 1);
+                    }
+                    // Output goes to the build log
+                    if let Some(fd) = log_fd {
+                        libc::dup2(fd, 1);
+                        libc::dup2(fd, 2);
+                    }
 
                     // Execute the command
                     let shell = CString::new("/bin/sh").unwrap();
@@ -633,8 +740,7 @@ impl ImageBuilder {
             version,
             architecture,
             mirror,
-            no_cache: false,
-            config_overrides: None,
+            ..Default::default()
         };
 
         // Create progress channel
@@ -651,8 +757,17 @@ impl ImageBuilder {
         Ok(())
     }
 
-    /// Execute a COPY instruction
-    fn execute_copy(&self, root: &Path, src: &str, dest: &str) -> Result<()> {
+    /// Execute a COPY instruction, then give what it copied `owner` and
+    /// `mode` if set
+    fn execute_copy(
+        &self,
+        root: &Path,
+        src: &str,
+        dest: &str,
+        owner: Option<Owner>,
+        mode: Option<u32>,
+        on_progress: &mut dyn FnMut(&CopyProgress),
+    ) -> Result<()> {
         let src_path = self.build_context.join(src);
         let dst_path = root.join(dest.trim_start_matches('/'));
 
@@ -663,35 +778,27 @@ impl ImageBuilder {
 
         // Copy file or directory
         if src_path.is_dir() {
-            self.copy_directory(&src_path, &dst_path)?;
+            copy_directory(&src_path, &dst_path, on_progress)?;
         } else {
             fs::copy(&src_path, &dst_path)?;
         }
 
-        Ok(())
-    }
-
-    /// Copy a directory recursively
-    fn copy_directory(&self, src: &Path, dst: &Path) -> Result<()> {
-        fs::create_dir_all(dst)?;
-
-        for entry in fs::read_dir(src)? {
-            let entry = entry?;
-            let src_path = entry.path();
-            let dst_path = dst.join(entry.file_name());
-
-            if src_path.is_dir() {
-                self.copy_directory(&src_path, &dst_path)?;
-            } else {
-                fs::copy(&src_path, &dst_path)?;
-            }
-        }
+        ownership::apply(root, &src_path, &dst_path, owner, mode)
+            .map_err(|e| ImageError::BuildFailed(format!("Failed to set ownership of {}: {}", dest, e)))?;
 
         Ok(())
     }
 
     /// Execute an ADD instruction (supports URLs and auto-extraction)
-    fn execute_add(&self, root: &Path, src: &str, dest: &str) -> Result<()> {
+    fn execute_add(
+        &self,
+        root: &Path,
+        src: &str,
+        dest: &str,
+        owner: Option<Owner>,
+        mode: Option<u32>,
+        on_progress: &mut dyn FnMut(&CopyProgress),
+    ) -> Result<()> {
         // Check if src is a URL
         if src.starts_with("http://") || src.starts_with("https://") {
             // Download to destination
@@ -705,7 +812,7 @@ impl ImageBuilder {
             warn!("URL download not yet implemented: {}", src);
         } else {
             // Copy like COPY instruction
-            self.execute_copy(root, src, dest)?;
+            self.execute_copy(root, src, dest, owner, mode, on_progress)?;
         }
 
         Ok(())
@@ -739,77 +846,724 @@ impl ImageBuilder {
         Ok(())
     }
 
-    /// Report build progress
-    async fn report_progress(&self, image_id: &str, step: usize, total: usize, instruction: &DockerfileInstruction, status: BuildStatus) {
-        let current_instruction = match instruction {
-            DockerfileInstruction::From(img) => format!("FROM {}", img),
-            DockerfileInstruction::Bootstrap { version, architecture, .. } => {
-                format!("BOOTSTRAP {} {}", version.as_deref().unwrap_or("auto"), architecture.as_deref().unwrap_or("auto"))
-            }
-            DockerfileInstruction::Run(cmd) => format!("RUN {}", cmd),
-            DockerfileInstruction::Copy { src, dest, .. } => format!("COPY {} {}", src, dest),
-            DockerfileInstruction::Add { src, dest } => format!("ADD {} {}", src, dest),
-            DockerfileInstruction::WorkDir(path) => format!("WORKDIR {}", path),
-            DockerfileInstruction::Env(env) => format!("ENV {} vars", env.len()),
-            DockerfileInstruction::Expose(ports) => format!("EXPOSE {:?}", ports),
-            DockerfileInstruction::User(user) => format!("USER {}", user),
-            DockerfileInstruction::Volume(vols) => format!("VOLUME {:?}", vols),
-            DockerfileInstruction::Cmd(cmd) => format!("CMD {:?}", cmd),
-            DockerfileInstruction::Entrypoint(ep) => format!("ENTRYPOINT {:?}", ep),
-            DockerfileInstruction::Label(labels) => format!("LABEL {} entries", labels.len()),
-        };
+    /// Append a progress line to the build log, if there is one
+    fn log_progress(&self, line: &str) {
+        if let Some(path) = &self.build_log
+            && let Err(e) = build_log::append_progress(path, line)
+        {
+            warn!("Failed to write build log {}: {}", path.display(), e);
+        }
+    }
 
+    /// Report build progress
+    async fn report_progress(&self, image_id: &str, step: usize, total: usize, current_instruction: String, status: BuildStatus) {
         let progress = ImageBuildProgress {
             image_id: image_id.to_string(),
             step,
             total_steps: total,
             current_instruction,
             status,
+            copy: None,
         };
 
         let _ = self.progress_tx.send(progress).await;
     }
+
+    /// Callback that turns copy progress for a build step into progress
+    /// updates. Copies run synchronously, so updates are dropped rather than
+    /// waited on if the channel is full.
+    fn copy_progress_reporter(
+        &self,
+        image_id: &str,
+        step: usize,
+        total: usize,
+        instruction: &DockerfileInstruction,
+    ) -> impl FnMut(&CopyProgress) + use<> {
+        let progress_tx = self.progress_tx.clone();
+        let image_id = image_id.to_string();
+        let description = describe_instruction(instruction);
+
+        move |copy: &CopyProgress| {
+            let _ = progress_tx.try_send(ImageBuildProgress {
+                image_id: image_id.clone(),
+                step,
+                total_steps: total,
+                current_instruction: format!(
+                    "{}: Copying {}/{} files",
+                    description, copy.files_copied, copy.files_total
+                ),
+                status: BuildStatus::Building,
+                copy: Some(*copy),
+            });
+        }
+    }
+}
+
+/// Resolve a COPY or ADD `--chown` against the build root
+fn resolve_copy_owner(root: &Path, chown: Option<&str>) -> Result<Option<Owner>> {
+    chown
+        .map(|spec| ownership::resolve_owner(root, spec).map_err(|e| ImageError::BuildFailed(format!("--chown={}: {}", spec, e))))
+        .transpose()
+}
+
+/// `--chown`/`--chmod` as written, each followed by a space
+fn copy_flags(chown: &Option<String>, chmod: &Option<u32>) -> String {
+    let chown = chown.as_ref().map(|spec| format!("--chown={} ", spec));
+    let chmod = chmod.map(|mode| format!("--chmod={:o} ", mode));
+    chown.into_iter().chain(chmod).collect()
+}
+
+/// What `instruction` changes in the image config; steps resumed from a
+/// layer snapshot only do this
+fn apply_config(instruction: &DockerfileInstruction, config: &mut ImageConfig) {
+    match instruction {
+        DockerfileInstruction::WorkDir(path) => config.workdir = Some(PathBuf::from(path)),
+        DockerfileInstruction::Env(env_map) => config.env.extend(env_map.clone()),
+        DockerfileInstruction::Expose(ports) => config.exposed_ports.extend(ports),
+        DockerfileInstruction::User(user) => config.user = Some(user.clone()),
+        DockerfileInstruction::Volume(volumes) => config.volumes.extend(volumes.clone()),
+        DockerfileInstruction::Cmd(cmd) => config.cmd = Some(cmd.clone()),
+        DockerfileInstruction::Entrypoint(ep) => config.entrypoint = Some(ep.clone()),
+        DockerfileInstruction::Label(labels) => config.labels.extend(labels.clone()),
+        DockerfileInstruction::From(_)
+        | DockerfileInstruction::Bootstrap { .. }
+        | DockerfileInstruction::Run(_)
+        | DockerfileInstruction::Copy { .. }
+        | DockerfileInstruction::Add { .. } => {}
+    }
+}
+
+/// Whether `instruction` is a BOOTSTRAP that `root` already satisfies
+fn is_cached_bootstrap(instruction: &DockerfileInstruction, root: &Path) -> bool {
+    matches!(instruction, DockerfileInstruction::Bootstrap { .. }) && Bootstrap::is_bootstrapped(root)
+}
+
+/// Progress description of a build step; a BOOTSTRAP that was a cache hit
+/// reads `BOOTSTRAP (cached)`
+fn describe_step(instruction: &DockerfileInstruction, cached: bool) -> String {
+    match instruction {
+        DockerfileInstruction::Bootstrap { .. } if cached => "BOOTSTRAP (cached)".to_string(),
+        _ => describe_instruction(instruction),
+    }
+}
+
+/// Human-readable form of an instruction for progress reports
+fn describe_instruction(instruction: &DockerfileInstruction) -> String {
+    match instruction {
+        DockerfileInstruction::From(img) => format!("FROM {}", img),
+        DockerfileInstruction::Bootstrap { version, architecture, .. } => {
+            format!("BOOTSTRAP {} {}", version.as_deref().unwrap_or("auto"), architecture.as_deref().unwrap_or("auto"))
+        }
+        DockerfileInstruction::Run(cmd) => format!("RUN {}", cmd),
+        DockerfileInstruction::Copy { src, dest, chown, chmod, .. } => {
+            format!("COPY {}{} {}", copy_flags(chown, chmod), src, dest)
+        }
+        DockerfileInstruction::Add { src, dest, chown, chmod } => format!("ADD {}{} {}", copy_flags(chown, chmod), src, dest),
+        DockerfileInstruction::WorkDir(path) => format!("WORKDIR {}", path),
+        DockerfileInstruction::Env(env) => format!("ENV {} vars", env.len()),
+        DockerfileInstruction::Expose(ports) => format!("EXPOSE {:?}", ports),
+        DockerfileInstruction::User(user) => format!("USER {}", user),
+        DockerfileInstruction::Volume(vols) => format!("VOLUME {:?}", vols),
+        DockerfileInstruction::Cmd(cmd) => format!("CMD {:?}", cmd),
+        DockerfileInstruction::Entrypoint(ep) => format!("ENTRYPOINT {:?}", ep),
+        DockerfileInstruction::Label(labels) => format!("LABEL {} entries", labels.len()),
+    }
+}
+
+/// Parse a Dockerfile into instructions
+///
+/// Variables are expanded against `base_env` (the ENV of the image being
+/// built on), `build_args`, and the ARG and ENV instructions seen so far.
+pub fn parse_dockerfile(
+    dockerfile: &str,
+    build_args: &HashMap<String, String>,
+    base_env: &HashMap<String, String>,
+    strict_vars: bool,
+) -> Result<Vec<DockerfileInstruction>> {
+    let mut instructions = Vec::new();
+    let mut vars = BuildVars::new(build_args.clone()).with_env(base_env);
+
+    for (line_num, line) in dockerfile.lines().enumerate() {
+        let line = line.trim();
+
+        // Skip empty lines and comments
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        // Handle line continuation
+        let mut full_line = line.to_string();
+        if line.ends_with('\\') {
+            // Remove the backslash
+            full_line.pop();
+            full_line.push(' ');
+            continue; // Would need multi-line handling in a more complete implementation
+        }
+
+        let full_line = vars::expand_instruction(&full_line, &vars, strict_vars)
+            .map_err(|e| ImageError::ParseError(format!("Line {}: {}", line_num + 1, e)))?;
+
+        // Parse instruction
+        match parse_instruction(&full_line) {
+            Ok(instr) => {
+                match &instr {
+                    DockerfileInstruction::Env(env) => vars.set_env(env),
+                    DockerfileInstruction::Run(cmd) if cmd.starts_with("# ARG ") => {
+                        if let Some((name, default)) = vars::parse_arg(&cmd["# ARG ".len()..]) {
+                            vars.declare_arg(&name, default);
+                        }
+                    }
+                    _ => {}
+                }
+                instructions.push(instr);
+            }
+            Err(e) => {
+                error!("Failed to parse line {}: {}", line_num + 1, e);
+                return Err(ImageError::ParseError(
+                    format!("Line {}: {}", line_num + 1, e)
+                ));
+            }
+        }
+    }
+
+    // Validate Dockerfile has FROM as first instruction. One of only
+    // comments would otherwise build an empty image by accident; that takes
+    // an explicit `FROM scratch`.
+    match instructions.first() {
+        None => {
+            return Err(ImageError::ParseError(
+                "Dockerfile has no instructions; an empty image needs FROM scratch".into()
+            ));
+        }
+        Some(DockerfileInstruction::From(_)) => {}
+        Some(_) => {
+            return Err(ImageError::ParseError(
+                "Dockerfile must start with FROM instruction".into()
+            ));
+        }
+    }
+    // If FROM scratch, remove it from instructions since it's a no-op. A
+    // Dockerfile of only `FROM scratch` leaves none: an empty image.
+    if matches!(&instructions[0], DockerfileInstruction::From(name) if name == "scratch") {
+        instructions.remove(0);
+    }
+
+    Ok(instructions)
+}
+
+/// `--chown` and `--chmod` of a COPY or ADD
+#[derive(Debug, Default)]
+struct CopyFlags {
+    chown: Option<String>,
+    chmod: Option<u32>,
+}
+
+/// Split the leading `--flag=value` arguments of a COPY or ADD from its
+/// sources and destination
+fn parse_copy_flags<'a>(instruction: &str, args: &'a str) -> Result<(CopyFlags, Vec<&'a str>)> {
+    let mut flags = CopyFlags::default();
+    let mut parts = args.split_whitespace().peekable();
+
+    while let Some(flag) = parts.next_if(|part| part.starts_with("--")) {
+        match flag.split_once('=') {
+            Some(("--chown", spec)) => {
+                ownership::check_chown(spec).map_err(ImageError::ParseError)?;
+                flags.chown = Some(spec.to_string());
+            }
+            Some(("--chmod", spec)) => flags.chmod = Some(ownership::parse_chmod(spec).map_err(ImageError::ParseError)?),
+            _ => return Err(ImageError::ParseError(format!("Unsupported {} flag: {}", instruction, flag))),
+        }
+    }
+
+    Ok((flags, parts.collect()))
+}
+
+/// Parse a single Dockerfile instruction
+pub fn parse_instruction(line: &str) -> Result<DockerfileInstruction> {
+    let parts: Vec<&str> = line.splitn(2, ' ').collect();
+    if parts.is_empty() {
+        return Err(ImageError::ParseError("Empty instruction".into()));
+    }
+
+    let instruction = parts[0].to_uppercase();
+    let args = parts.get(1).unwrap_or(&"").trim();
+
+    match instruction.as_str() {
+        "FROM" => Ok(DockerfileInstruction::From(args.to_string())),
+
+        "BOOTSTRAP" => {
+            // Parse BOOTSTRAP [VERSION] [ARCHITECTURE] [MIRROR]
+            let parts: Vec<&str> = args.split_whitespace().collect();
+            let version = if parts.len() > 0 && !parts[0].is_empty() {
+                Some(parts[0].to_string())
+            } else {
+                None
+            };
+            let architecture = if parts.len() > 1 && !parts[1].is_empty() {
+                Some(parts[1].to_string())
+            } else {
+                None
+            };
+            let mirror = if parts.len() > 2 && !parts[2].is_empty() {
+                Some(parts[2].to_string())
+            } else {
+                None
+            };
+            Ok(DockerfileInstruction::Bootstrap { version, architecture, mirror })
+        }
+
+        "RUN" => Ok(DockerfileInstruction::Run(args.to_string())),
+
+        "COPY" => {
+            let (flags, parts) = parse_copy_flags("COPY", args)?;
+            if parts.len() < 2 {
+                return Err(ImageError::ParseError("COPY requires source and destination".into()));
+            }
+            let dst = parts.last().unwrap().to_string();
+            let src = parts[0].to_string();
+            Ok(DockerfileInstruction::Copy {
+                from: None,
+                src,
+                dest: dst,
+                chown: flags.chown,
+                chmod: flags.chmod,
+            })
+        }
+
+        "ADD" => {
+            let (flags, parts) = parse_copy_flags("ADD", args)?;
+            if parts.len() < 2 {
+                return Err(ImageError::ParseError("ADD requires source and destination".into()));
+            }
+            let dst = parts.last().unwrap().to_string();
+            let src = parts[0].to_string();
+            Ok(DockerfileInstruction::Add { src, dest: dst, chown: flags.chown, chmod: flags.chmod })
+        }
+
+        "WORKDIR" => Ok(DockerfileInstruction::WorkDir(args.to_string())),
+
+        "ENV" => vars::parse_env(args).map(DockerfileInstruction::Env).map_err(ImageError::ParseError),
+
+        "EXPOSE" => {
+            let ports: std::result::Result<Vec<u16>, _> = args
+                .split_whitespace()
+                .map(|p| p.parse::<u16>().map_err(|_| ImageError::ParseError(format!("Invalid port: {}", p))))
+                .collect();
+            Ok(DockerfileInstruction::Expose(ports?))
+        }
+
+        "USER" => Ok(DockerfileInstruction::User(args.to_string())),
+
+        "VOLUME" => {
+            let volumes: Vec<String> = args
+                .split_whitespace()
+                .map(|v| v.to_string())
+                .collect();
+            Ok(DockerfileInstruction::Volume(volumes))
+        }
+
+        "CMD" => {
+            let cmd = if args.starts_with('[') {
+                // Exec form
+                serde_json::from_str::<Vec<String>>(args)
+                    .map_err(|_| ImageError::ParseError("Invalid CMD syntax".into()))?
+            } else {
+                // Shell form
+                vec![args.to_string()]
+            };
+            Ok(DockerfileInstruction::Cmd(cmd))
+        }
+
+        "ENTRYPOINT" => {
+            let entrypoint = if args.starts_with('[') {
+                serde_json::from_str::<Vec<String>>(args)
+                    .map_err(|_| ImageError::ParseError("Invalid ENTRYPOINT syntax".into()))?
+            } else {
+                vec![args.to_string()]
+            };
+            Ok(DockerfileInstruction::Entrypoint(entrypoint))
+        }
+
+        "LABEL" => {
+            let mut label_map = HashMap::new();
+            for part in args.split_whitespace().collect::<Vec<_>>().chunks(2) {
+                if part.len() == 2 {
+                    label_map.insert(part[0].to_string(), part[1].to_string());
+                }
+            }
+            Ok(DockerfileInstruction::Label(label_map))
+        }
+
+        "ARG" => {
+            // ARG is handled during build, not stored
+            let parts: Vec<&str> = args.split('=').collect();
+            if !parts.is_empty() {
+                debug!("Build ARG: {}", parts[0]);
+            }
+            Ok(DockerfileInstruction::Run(format!("# ARG {}", args)))
+        }
+
+        "STOPSIGNAL" => {
+            // Store as label for now
+            let mut labels = HashMap::new();
+            labels.insert("stop_signal".to_string(), args.to_string());
+            Ok(DockerfileInstruction::Label(labels))
+        }
+
+        "SHELL" => {
+            let shell = if args.starts_with('[') {
+                serde_json::from_str::<Vec<String>>(args)
+                    .map_err(|_| ImageError::ParseError("Invalid SHELL syntax".into()))?
+            } else {
+                vec![args.to_string()]
+            };
+            // Store as label for now
+            let mut labels = HashMap::new();
+            labels.insert("shell".to_string(), shell.join(" "));
+            Ok(DockerfileInstruction::Label(labels))
+        }
+
+        _ => Err(ImageError::ParseError(format!("Unknown instruction: {}", instruction))),
+    }
+}
+
+/// Content address of a build
+///
+/// Covers what the build is based on (`base`: the parent image's ID, a base
+/// system reference, or `scratch`), the parsed instructions with variables
+/// already expanded, and the contents of every local COPY/ADD source under
+/// `context`. Two builds with the same digest produce the same image.
+pub fn content_digest(instructions: &[DockerfileInstruction], base: &str, context: &Path) -> Result<String> {
+    use sha2::{Digest, Sha256};
+
+    let mut hasher = Sha256::new();
+    hasher.update(b"kawakaze-build-v1\0");
+    hasher.update(base.as_bytes());
+    hasher.update(b"\0");
+
+    for instruction in instructions {
+        // Through a Value so that ENV/LABEL maps serialize with sorted keys
+        let value = serde_json::to_value(instruction)
+            .map_err(|e| ImageError::ParseError(format!("Failed to serialize instruction: {}", e)))?;
+        hasher.update(value.to_string().as_bytes());
+        hasher.update(b"\0");
+
+        let src = match instruction {
+            DockerfileInstruction::Copy { src, .. } => src,
+            DockerfileInstruction::Add { src, .. } if !src.starts_with("http://") && !src.starts_with("https://") => src,
+            _ => continue,
+        };
+        hash_tree(&mut hasher, &context.join(src), Path::new(""))?;
+    }
+
+    Ok(format!("sha256:{}", hex::encode(hasher.finalize())))
+}
+
+/// Feed the names and contents of everything under `path` to `hasher`,
+/// in a stable order
+pub(crate) fn hash_tree(hasher: &mut sha2::Sha256, path: &Path, rel: &Path) -> Result<()> {
+    use sha2::Digest;
+
+    if path.is_dir() {
+        hasher.update(format!("d {}\0", rel.display()).as_bytes());
+        let mut entries: Vec<_> = fs::read_dir(path)?.collect::<std::io::Result<_>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            hash_tree(hasher, &entry.path(), &rel.join(entry.file_name()))?;
+        }
+    } else if path.exists() {
+        hasher.update(format!("f {} {}\0", rel.display(), fs::metadata(path)?.len()).as_bytes());
+        hasher.update(fs::read(path)?);
+    } else {
+        // The build fails on it anyway; keep the digest well-defined
+        hasher.update(format!("missing {}\0", rel.display()).as_bytes());
+    }
+    Ok(())
+}
+
+/// Whether `name` can be used as a secret: it doubles as an environment
+/// variable and a file name, so `[A-Za-z_][A-Za-z0-9_]*`
+pub fn is_valid_secret_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Replace every secret value in `instructions` before they are recorded
+/// with the image
+pub fn scrub_secrets(
+    mut instructions: Vec<DockerfileInstruction>,
+    secrets: &HashMap<String, String>,
+) -> Vec<DockerfileInstruction> {
+    for secret in secrets.values() {
+        for instruction in &mut instructions {
+            instruction.redact(secret);
+        }
+    }
+    instructions
+}
+
+/// Secret files written into a build root for the duration of one RUN;
+/// removed again when dropped, so they never end up in a snapshot
+struct MountedSecrets {
+    dir: PathBuf,
+    files: Vec<PathBuf>,
+}
+
+impl MountedSecrets {
+    fn write(root: &Path, secrets: &HashMap<String, String>) -> Result<Self> {
+        let dir = root.join(SECRETS_DIR);
+        let mut mounted = Self { dir, files: Vec::new() };
+        if secrets.is_empty() {
+            return Ok(mounted);
+        }
+
+        fs::create_dir_all(&mounted.dir)?;
+        for (name, value) in secrets {
+            let path = mounted.dir.join(name);
+            let _ = fs::remove_file(&path);
+
+            let mut options = fs::OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::OpenOptionsExt;
+                options.mode(0o400);
+            }
+            let mut file = options.open(&path)?;
+            mounted.files.push(path);
+            file.write_all(value.as_bytes())?;
+        }
+        Ok(mounted)
+    }
+}
+
+impl Drop for MountedSecrets {
+    fn drop(&mut self) {
+        for file in &self.files {
+            if let Err(e) = fs::remove_file(file) {
+                warn!("Failed to remove build secret {:?}: {}", file, e);
+            }
+        }
+        if !self.files.is_empty() {
+            let _ = fs::remove_dir(&self.dir);
+        }
+    }
+}
+
+/// Count the files and bytes under a directory
+fn count_tree(dir: &Path) -> Result<(u64, u64)> {
+    let mut files = 0;
+    let mut bytes = 0;
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            let (sub_files, sub_bytes) = count_tree(&path)?;
+            files += sub_files;
+            bytes += sub_bytes;
+        } else {
+            files += 1;
+            bytes += fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+        }
+    }
+
+    Ok((files, bytes))
+}
+
+/// Copy a directory recursively, calling `on_progress` every
+/// `COPY_PROGRESS_FILES` files or `COPY_PROGRESS_BYTES` bytes and once more
+/// at the end. Copies smaller than both thresholds never call it.
+fn copy_directory(src: &Path, dst: &Path, on_progress: &mut dyn FnMut(&CopyProgress)) -> Result<()> {
+    let (files_total, bytes_total) = count_tree(src)?;
+    let mut tracker = CopyTracker {
+        progress: CopyProgress { files_total, bytes_total, ..Default::default() },
+        reported: CopyProgress::default(),
+        on_progress,
+    };
+
+    tracker.copy_tree(src, dst)?;
+    tracker.finish();
+    Ok(())
+}
+
+struct CopyTracker<'a> {
+    progress: CopyProgress,
+    reported: CopyProgress,
+    on_progress: &'a mut dyn FnMut(&CopyProgress),
+}
+
+impl CopyTracker<'_> {
+    fn copy_tree(&mut self, src: &Path, dst: &Path) -> Result<()> {
+        fs::create_dir_all(dst)?;
+
+        for entry in fs::read_dir(src)? {
+            let entry = entry?;
+            let src_path = entry.path();
+            let dst_path = dst.join(entry.file_name());
+
+            if src_path.is_dir() {
+                self.copy_tree(&src_path, &dst_path)?;
+            } else {
+                let bytes = fs::copy(&src_path, &dst_path)?;
+                self.file_copied(bytes);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn file_copied(&mut self, bytes: u64) {
+        self.progress.files_copied += 1;
+        self.progress.bytes_copied += bytes;
+
+        if self.progress.files_copied - self.reported.files_copied >= COPY_PROGRESS_FILES
+            || self.progress.bytes_copied - self.reported.bytes_copied >= COPY_PROGRESS_BYTES
+        {
+            self.report();
+        }
+    }
+
+    /// Send a final update if any were sent, so the count ends at the total
+    fn finish(&mut self) {
+        if self.reported != CopyProgress::default() && self.reported != self.progress {
+            self.report();
+        }
+    }
+
+    fn report(&mut self) {
+        // Files may appear while copying; never report more than the total
+        self.progress.files_total = self.progress.files_total.max(self.progress.files_copied);
+        self.progress.bytes_total = self.progress.bytes_total.max(self.progress.bytes_copied);
+        (self.on_progress)(&self.progress);
+        self.reported = self.progress;
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_content_digest() {
+        let context = tempfile::tempdir().unwrap();
+        fs::create_dir_all(context.path().join("app/bin")).unwrap();
+        fs::write(context.path().join("app/bin/server"), "v1").unwrap();
+
+        let parse = |dockerfile: &str| parse_dockerfile(dockerfile, &HashMap::new(), &HashMap::new(), false).unwrap();
+        let dockerfile = "FROM scratch\nENV A=1 B=2 C=3\nCOPY app /opt/app\nRUN echo hi";
+        let digest = content_digest(&parse(dockerfile), "scratch", context.path()).unwrap();
+        assert!(digest.starts_with("sha256:"));
+
+        // Stable across parses (ENV is a map, so its order isn't)
+        for _ in 0..5 {
+            assert_eq!(content_digest(&parse(dockerfile), "scratch", context.path()).unwrap(), digest);
+        }
+
+        // Instructions, base and copied files all count
+        let changed = parse("FROM scratch\nENV A=1 B=2 C=3\nCOPY app /opt/app\nRUN echo bye");
+        assert_ne!(content_digest(&changed, "scratch", context.path()).unwrap(), digest);
+        assert_ne!(content_digest(&parse(dockerfile), "other-base", context.path()).unwrap(), digest);
+        fs::write(context.path().join("app/bin/server"), "v2").unwrap();
+        assert_ne!(content_digest(&parse(dockerfile), "scratch", context.path()).unwrap(), digest);
+    }
+
+    #[test]
+    fn test_secret_names() {
+        assert!(is_valid_secret_name("NPM_TOKEN"));
+        assert!(is_valid_secret_name("_key2"));
+        assert!(!is_valid_secret_name(""));
+        assert!(!is_valid_secret_name("2FA"));
+        assert!(!is_valid_secret_name("../etc/passwd"));
+        assert!(!is_valid_secret_name("A-B"));
+    }
+
+    #[test]
+    fn test_scrub_secrets_from_recorded_instructions() {
+        let secrets = HashMap::from([("NPM_TOKEN".to_string(), "npm_s3cr3t".to_string())]);
+        let instructions = vec![
+            DockerfileInstruction::From("scratch".to_string()),
+            DockerfileInstruction::Run("npm config set //registry/:_authToken=$NPM_TOKEN".to_string()),
+            DockerfileInstruction::Run("echo npm_s3cr3t > /dev/null".to_string()),
+            DockerfileInstruction::Label(HashMap::from([("token".to_string(), "npm_s3cr3t".to_string())])),
+        ];
+
+        let scrubbed = scrub_secrets(instructions, &secrets);
+        let recorded = serde_json::to_string(&scrubbed).unwrap();
+        assert!(!recorded.contains("npm_s3cr3t"));
+        assert!(recorded.contains("$NPM_TOKEN"));
+        assert_eq!(scrubbed[2], DockerfileInstruction::Run("echo <redacted> > /dev/null".to_string()));
+    }
+
+    #[test]
+    fn test_mounted_secrets_are_removed() {
+        let root = tempfile::tempdir().unwrap();
+        let secrets = HashMap::from([("API_KEY".to_string(), "k-123".to_string())]);
+        let path = root.path().join(SECRETS_DIR).join("API_KEY");
+
+        {
+            let _mounted = MountedSecrets::write(root.path(), &secrets).unwrap();
+            assert_eq!(fs::read_to_string(&path).unwrap(), "k-123");
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o400);
+            }
+        }
+
+        assert!(!path.exists());
+        assert!(!root.path().join(SECRETS_DIR).exists());
+    }
+
     #[test]
     fn test_substitute_build_args() {
         let mut build_args = HashMap::new();
         build_args.insert("VERSION".to_string(), "1.0.0".to_string());
         build_args.insert("DEBUG".to_string(), "true".to_string());
 
-        let builder = create_test_builder();
+        let vars = BuildVars::new(build_args);
 
         let line = "RUN apt-get install myapp-${VERSION}";
-        let result = builder.substitute_build_args(line);
+        let result = vars::expand_instruction(line, &vars, false).unwrap();
         assert_eq!(result, "RUN apt-get install myapp-1.0.0");
 
         let line2 = "ENV DEBUG=${DEBUG}";
-        let result2 = builder.substitute_build_args(line2);
+        let result2 = vars::expand_instruction(line2, &vars, false).unwrap();
         assert_eq!(result2, "ENV DEBUG=true");
     }
 
+    #[test]
+    #[ignore] // Requires actual ZFS pool
+    fn test_parse_dockerfile_threads_env() {
+        let (builder, _rx) = ImageBuilder::new(Zfs::new("tank").unwrap(), "tank/test".to_string());
+        let builder = builder
+            .with_build_args(HashMap::from([("VERSION".to_string(), "2.0".to_string())]))
+            .with_strict_vars(true);
+
+        let dockerfile = "FROM scratch\nARG FLAVOR=lite\nENV PREFIX=/opt\nWORKDIR $PREFIX/app-${VERSION}-$FLAVOR\nRUN echo $HOME";
+        let base_env = HashMap::from([("PREFIX".to_string(), "/usr/local".to_string())]);
+        let instructions = builder.parse_dockerfile(dockerfile, &base_env).unwrap();
+
+        assert!(matches!(&instructions[2], DockerfileInstruction::WorkDir(dir) if dir == "/opt/app-2.0-lite"));
+        assert!(matches!(&instructions[3], DockerfileInstruction::Run(cmd) if cmd == "echo $HOME"));
+        assert!(builder.parse_dockerfile("FROM scratch\nWORKDIR $NOPE", &HashMap::new()).is_err());
+    }
+
     #[test]
     fn test_parse_from() {
-        let builder = create_test_builder();
-        let instr = builder.parse_instruction("FROM ubuntu:22.04").unwrap();
+        let instr = parse_instruction("FROM ubuntu:22.04").unwrap();
         assert!(matches!(instr, DockerfileInstruction::From(_)));
     }
 
     #[test]
     fn test_parse_run() {
-        let builder = create_test_builder();
-        let instr = builder.parse_instruction("RUN apt-get update").unwrap();
+        let instr = parse_instruction("RUN apt-get update").unwrap();
         assert!(matches!(instr, DockerfileInstruction::Run(_)));
     }
 
     #[test]
     fn test_parse_copy() {
-        let builder = create_test_builder();
-        let instr = builder.parse_instruction("COPY . /app").unwrap();
+        let instr = parse_instruction("COPY . /app").unwrap();
         match instr {
             DockerfileInstruction::Copy { src, dest, .. } => {
                 assert_eq!(src, ".");
@@ -819,10 +1573,73 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_copy_flags() {
+        match parse_instruction("COPY --chown=www:www --chmod=750 htdocs /usr/local/www").unwrap() {
+            DockerfileInstruction::Copy { src, dest, chown, chmod, .. } => {
+                assert_eq!((src.as_str(), dest.as_str()), ("htdocs", "/usr/local/www"));
+                assert_eq!(chown.as_deref(), Some("www:www"));
+                assert_eq!(chmod, Some(0o750));
+            }
+            other => panic!("Expected Copy instruction, got {:?}", other),
+        }
+        match parse_instruction("ADD --chmod=0644 app.conf /usr/local/etc/").unwrap() {
+            DockerfileInstruction::Add { src, chown, chmod, .. } => {
+                assert_eq!(src, "app.conf");
+                assert_eq!((chown, chmod), (None, Some(0o644)));
+            }
+            other => panic!("Expected Add instruction, got {:?}", other),
+        }
+        let instruction = parse_instruction("COPY --chmod=755 --chown=1001 bin /usr/local/bin").unwrap();
+        assert_eq!(describe_instruction(&instruction), "COPY --chown=1001 --chmod=755 bin /usr/local/bin");
+
+        for bad in ["COPY --chown= a /b", "COPY --chown=www: a /b", "COPY --chmod=999 a /b", "COPY --from=build a /b", "ADD --chown=www /b"] {
+            assert!(matches!(parse_instruction(bad), Err(ImageError::ParseError(_))), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_cached_bootstrap_step() {
+        let root = tempfile::tempdir().unwrap();
+        let bootstrap = parse_instruction("BOOTSTRAP 14.1-RELEASE amd64").unwrap();
+        let run = parse_instruction("RUN pkg install -y nginx").unwrap();
+
+        assert!(!is_cached_bootstrap(&bootstrap, root.path()));
+        assert_eq!(describe_step(&bootstrap, false), "BOOTSTRAP 14.1-RELEASE amd64");
+
+        // Half a base system still needs the bootstrap
+        fs::create_dir_all(root.path().join("bin")).unwrap();
+        fs::write(root.path().join("bin/sh"), "sh").unwrap();
+        assert!(!is_cached_bootstrap(&bootstrap, root.path()));
+
+        fs::create_dir_all(root.path().join("etc")).unwrap();
+        fs::write(root.path().join("etc/freebsd-update.conf"), "KeyPrint ...").unwrap();
+        assert!(is_cached_bootstrap(&bootstrap, root.path()));
+        assert_eq!(describe_step(&bootstrap, true), "BOOTSTRAP (cached)");
+
+        // Only BOOTSTRAP is ever skipped
+        assert!(!is_cached_bootstrap(&run, root.path()));
+        assert_eq!(describe_step(&run, true), "RUN pkg install -y nginx");
+    }
+
+    #[test]
+    fn test_copy_unknown_user_fails() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir_all(root.path().join("etc")).unwrap();
+        fs::write(root.path().join("etc/passwd"), "www:*:80:80::/nonexistent:/usr/sbin/nologin
+").unwrap();
+
+        assert_eq!(resolve_copy_owner(root.path(), Some("www")).unwrap(), Some(Owner { uid: 80, gid: 80 }));
+        assert_eq!(resolve_copy_owner(root.path(), None).unwrap(), None);
+        match resolve_copy_owner(root.path(), Some("nginx:www")) {
+            Err(ImageError::BuildFailed(msg)) => assert!(msg.contains("Unknown user 'nginx'"), "{}", msg),
+            other => panic!("Expected BuildFailed, got {:?}", other),
+        }
+    }
+
     #[test]
     fn test_parse_env() {
-        let builder = create_test_builder();
-        let instr = builder.parse_instruction("ENV PATH=/usr/bin DEBUG=true").unwrap();
+        let instr = parse_instruction("ENV PATH=/usr/bin DEBUG=true").unwrap();
         match instr {
             DockerfileInstruction::Env(map) => {
                 assert_eq!(map.get("PATH"), Some(&"/usr/bin".to_string()));
@@ -834,8 +1651,7 @@ mod tests {
 
     #[test]
     fn test_parse_expose() {
-        let builder = create_test_builder();
-        let instr = builder.parse_instruction("EXPOSE 8080 8443").unwrap();
+        let instr = parse_instruction("EXPOSE 8080 8443").unwrap();
         match instr {
             DockerfileInstruction::Expose(ports) => {
                 assert_eq!(ports, vec![8080, 8443]);
@@ -846,8 +1662,7 @@ mod tests {
 
     #[test]
     fn test_parse_workdir() {
-        let builder = create_test_builder();
-        let instr = builder.parse_instruction("WORKDIR /app").unwrap();
+        let instr = parse_instruction("WORKDIR /app").unwrap();
         match instr {
             DockerfileInstruction::WorkDir(path) => {
                 assert_eq!(path, "/app");
@@ -858,8 +1673,7 @@ mod tests {
 
     #[test]
     fn test_parse_user() {
-        let builder = create_test_builder();
-        let instr = builder.parse_instruction("USER appuser").unwrap();
+        let instr = parse_instruction("USER appuser").unwrap();
         match instr {
             DockerfileInstruction::User(user) => {
                 assert_eq!(user, "appuser");
@@ -870,8 +1684,7 @@ mod tests {
 
     #[test]
     fn test_parse_volume() {
-        let builder = create_test_builder();
-        let instr = builder.parse_instruction("VOLUME /data /logs").unwrap();
+        let instr = parse_instruction("VOLUME /data /logs").unwrap();
         match instr {
             DockerfileInstruction::Volume(vols) => {
                 assert_eq!(vols, vec!["/data".to_string(), "/logs".to_string()]);
@@ -882,8 +1695,7 @@ mod tests {
 
     #[test]
     fn test_parse_cmd_exec() {
-        let builder = create_test_builder();
-        let instr = builder.parse_instruction("CMD [\"/bin/app\", \"--help\"]").unwrap();
+        let instr = parse_instruction("CMD [\"/bin/app\", \"--help\"]").unwrap();
         match instr {
             DockerfileInstruction::Cmd(cmd) => {
                 assert_eq!(cmd, vec!["/bin/app".to_string(), "--help".to_string()]);
@@ -894,8 +1706,7 @@ mod tests {
 
     #[test]
     fn test_parse_cmd_shell() {
-        let builder = create_test_builder();
-        let instr = builder.parse_instruction("CMD /bin/app --help").unwrap();
+        let instr = parse_instruction("CMD /bin/app --help").unwrap();
         match instr {
             DockerfileInstruction::Cmd(cmd) => {
                 assert_eq!(cmd, vec!["/bin/app --help".to_string()]);
@@ -906,8 +1717,7 @@ mod tests {
 
     #[test]
     fn test_parse_entrypoint() {
-        let builder = create_test_builder();
-        let instr = builder.parse_instruction("ENTRYPOINT [\"/bin/sh\"]").unwrap();
+        let instr = parse_instruction("ENTRYPOINT [\"/bin/sh\"]").unwrap();
         match instr {
             DockerfileInstruction::Entrypoint(ep) => {
                 assert_eq!(ep, vec!["/bin/sh".to_string()]);
@@ -918,8 +1728,7 @@ mod tests {
 
     #[test]
     fn test_parse_label() {
-        let builder = create_test_builder();
-        let instr = builder.parse_instruction("LABEL version=1.0 maintainer=test@example.com").unwrap();
+        let instr = parse_instruction("LABEL version=1.0 maintainer=test@example.com").unwrap();
         match instr {
             DockerfileInstruction::Label(labels) => {
                 assert_eq!(labels.get("version"), Some(&"1.0".to_string()));
@@ -940,7 +1749,7 @@ mod tests {
             EXPOSE 8080
         "#;
 
-        let instructions = builder.parse_dockerfile(dockerfile).unwrap();
+        let instructions = builder.parse_dockerfile(dockerfile, &HashMap::new()).unwrap();
         assert_eq!(instructions.len(), 5);
         assert!(matches!(instructions[0], DockerfileInstruction::From(_)));
         assert!(matches!(instructions[1], DockerfileInstruction::Run(_)));
@@ -957,10 +1766,34 @@ mod tests {
             COPY . /app
         "#;
 
-        let result = builder.parse_dockerfile(dockerfile);
+        let result = builder.parse_dockerfile(dockerfile, &HashMap::new());
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_parse_from_only_dockerfiles() {
+        let parse = |dockerfile: &str| parse_dockerfile(dockerfile, &HashMap::new(), &HashMap::new(), false);
+
+        // FROM scratch alone is an empty image: no steps at all
+        assert!(parse("FROM scratch\n").unwrap().is_empty());
+        assert!(parse("# nothing else\n\nFROM scratch\n# really\n").unwrap().is_empty());
+
+        // FROM <image> alone keeps its one (no-op) step
+        let instructions = parse("FROM base\n").unwrap();
+        assert!(matches!(instructions.as_slice(), [DockerfileInstruction::From(base)] if base == "base"));
+
+        // Without FROM there is nothing to build from
+        let err = parse("# only a comment\n\n").unwrap_err().to_string();
+        assert!(err.contains("no instructions"), "{}", err);
+        assert!(parse("").is_err());
+
+        // Both have a content address, distinct from each other
+        let context = Path::new(DEFAULT_BUILD_CONTEXT);
+        let empty = content_digest(&parse("FROM scratch").unwrap(), "scratch", context).unwrap();
+        let retag = content_digest(&parse("FROM base").unwrap(), "base-id", context).unwrap();
+        assert_ne!(empty, retag);
+    }
+
     #[test]
     fn test_parse_dockerfile_with_comments() {
         let builder = create_test_builder();
@@ -972,10 +1805,47 @@ mod tests {
             RUN apt-get update
         "#;
 
-        let instructions = builder.parse_dockerfile(dockerfile).unwrap();
+        let instructions = builder.parse_dockerfile(dockerfile, &HashMap::new()).unwrap();
         assert_eq!(instructions.len(), 2);
     }
 
+    fn make_tree(root: &Path, files: usize) {
+        for i in 0..files {
+            let dir = root.join(format!("dir{}", i % 7));
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join(format!("file{}", i)), b"x").unwrap();
+        }
+    }
+
+    #[test]
+    fn test_copy_directory_reports_progress_for_large_trees() {
+        let src = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        make_tree(src.path(), 1200);
+
+        let mut updates = Vec::new();
+        copy_directory(src.path(), &dst.path().join("app"), &mut |p: &CopyProgress| updates.push(*p)).unwrap();
+
+        let copied: Vec<u64> = updates.iter().map(|p| p.files_copied).collect();
+        assert_eq!(copied, vec![500, 1000, 1200]);
+        assert!(updates.iter().all(|p| p.files_total == 1200));
+        assert_eq!(updates.last().unwrap().bytes_copied, 1200);
+        assert!(dst.path().join("app/dir0/file0").exists());
+    }
+
+    #[test]
+    fn test_copy_directory_small_tree_is_quiet() {
+        let src = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        make_tree(src.path(), 20);
+
+        let mut updates = 0;
+        copy_directory(src.path(), dst.path(), &mut |_: &CopyProgress| updates += 1).unwrap();
+
+        assert_eq!(updates, 0);
+        assert_eq!(count_tree(dst.path()).unwrap(), (20, 20));
+    }
+
     fn create_test_builder() -> ImageBuilder {
         // This would need a mock ZFS for proper testing
         // For now, just create a builder that won't actually be used for ZFS operations
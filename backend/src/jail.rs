@@ -2,11 +2,16 @@
 //!
 //! Interfaces with FreeBSD's jail system using libc.
 
+use std::collections::HashSet;
 use std::ffi::{CString, NulError};
 use std::fs;
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
 use std::process::Command;
 
+use crate::kernel_jails::KernelJails;
+#[cfg(target_os = "freebsd")]
+use crate::cmdtrace::TracedCommand;
+
 /// Represents a FreeBSD jail
 pub struct Jail {
     name: String,
@@ -15,6 +20,26 @@ pub struct Jail {
     path: Option<String>,
     ip: Option<String>,
     vnet_interface: Option<String>,
+    devfs_ruleset: u16,
+    /// `host.hostname` inside the jail; the jail name when unset
+    hostname: Option<String>,
+    /// Base directory `path` was validated against by `with_path_within`
+    managed_base: Option<PathBuf>,
+    /// Delete the jail root on destroy (only honoured for managed paths)
+    cleanup_path: bool,
+    /// Userland version recorded by the last upgrade
+    os_version: Option<String>,
+    /// Newer kawakaze version that last wrote the record; the jail is
+    /// read-only while set (see `version`)
+    written_by_newer: Option<String>,
+    /// File systems the jail may mount itself
+    mount_permissions: MountPermissions,
+    /// Free-form notes from the operator
+    description: Option<String>,
+    /// Mount points below the root when the jail was created, before
+    /// anything ran in it; teardown leaves them to whoever mounted them
+    #[cfg_attr(not(target_os = "freebsd"), allow(dead_code))]
+    host_mounts: HashSet<PathBuf>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -24,6 +49,126 @@ pub enum JailState {
     Stopped,
 }
 
+/// How [`Jail::stop_with`] leaves the jail
+///
+/// `Remove` destroys the jail with `jail_remove`, as `jail -r` does; the
+/// next start creates it anew with a new JID. `Freeze` only kills the
+/// jail's processes: the persistent jail stays defined in the kernel with
+/// its JID, devfs mount and in-kernel state (VNET interfaces, sysctls), and
+/// the next start reuses it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StopMode {
+    #[default]
+    Remove,
+    Freeze,
+}
+
+/// File systems a jail may mount from inside (`allow.mount.*`)
+///
+/// Anything but `None` also sets `allow.mount` and lowers `enforce_statfs`
+/// to 1, without which `mount(8)` refuses to run in a jail. What the jail
+/// mounts is unmounted when it is removed (see [`release_jail_mounts`]).
+/// `zfs` only lets the jail mount datasets delegated to it (`jailed=on`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MountPermissions {
+    #[default]
+    None,
+    Nullfs,
+    Tmpfs,
+    Zfs,
+    All,
+}
+
+impl MountPermissions {
+    pub fn is_none(&self) -> bool {
+        *self == MountPermissions::None
+    }
+
+    /// Whether the jail gets `allow.mount.zfs`
+    pub fn allows_zfs(&self) -> bool {
+        matches!(self, MountPermissions::Zfs | MountPermissions::All)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MountPermissions::None => "none",
+            MountPermissions::Nullfs => "nullfs",
+            MountPermissions::Tmpfs => "tmpfs",
+            MountPermissions::Zfs => "zfs",
+            MountPermissions::All => "all",
+        }
+    }
+
+    /// Jail parameters granting the permissions, as `jail(8)` takes them
+    pub fn jail_params(&self) -> Vec<(&'static str, &'static str)> {
+        let fstypes: &[&'static str] = match self {
+            MountPermissions::None => return Vec::new(),
+            MountPermissions::Nullfs => &["allow.mount.nullfs"],
+            MountPermissions::Tmpfs => &["allow.mount.tmpfs"],
+            MountPermissions::Zfs => &["allow.mount.zfs"],
+            MountPermissions::All => &["allow.mount.nullfs", "allow.mount.tmpfs", "allow.mount.zfs"],
+        };
+        let mut params = vec![("allow.mount", "true"), ("enforce_statfs", "1")];
+        params.extend(fstypes.iter().map(|&param| (param, "true")));
+        params
+    }
+}
+
+impl std::str::FromStr for MountPermissions {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(MountPermissions::None),
+            "nullfs" => Ok(MountPermissions::Nullfs),
+            "tmpfs" => Ok(MountPermissions::Tmpfs),
+            "zfs" => Ok(MountPermissions::Zfs),
+            "all" => Ok(MountPermissions::All),
+            _ => Err(format!("Invalid mount permissions '{}': expected none, nullfs, tmpfs, zfs or all", s)),
+        }
+    }
+}
+
+/// Mount points strictly below `root`, deepest first: the order they can
+/// be unmounted in
+pub fn mounts_below(mounts: &HashSet<PathBuf>, root: &Path) -> Vec<PathBuf> {
+    let mut below: Vec<PathBuf> = mounts.iter().filter(|mount| mount.starts_with(root) && *mount != root).cloned().collect();
+    below.sort_by(|a, b| b.components().count().cmp(&a.components().count()).then_with(|| b.cmp(a)));
+    below
+}
+
+/// Unmount what a jail mounted itself below `root`, deepest first
+///
+/// `mounts` is the current mount table and `host_mounts` what was mounted
+/// below the root before the jail ran anything, which is left alone. A
+/// failed unmount is logged and the rest are still tried; returns what was
+/// unmounted.
+pub fn release_jail_mounts(
+    root: &Path,
+    mounts: &HashSet<PathBuf>,
+    host_mounts: &HashSet<PathBuf>,
+    mut unmount: impl FnMut(&Path) -> Result<(), String>,
+) -> Vec<PathBuf> {
+    let mut released = Vec::new();
+    for mount in mounts_below(mounts, root) {
+        if host_mounts.contains(&mount) {
+            continue;
+        }
+        match unmount(&mount) {
+            Ok(()) => released.push(mount),
+            Err(e) => tracing::warn!("Failed to unmount {}: {}", mount.display(), e),
+        }
+    }
+    released
+}
+
+/// Seconds the jail's processes get to exit after SIGTERM in
+/// [`StopMode::Freeze`] before they are killed
+#[cfg(target_os = "freebsd")]
+const FREEZE_GRACE_SECS: u64 = 5;
+
 impl Jail {
     /// Create a new jail configuration
     pub fn create(name: &str) -> Result<Self, JailError> {
@@ -46,6 +191,15 @@ impl Jail {
             path: None,
             ip: None,
             vnet_interface: None,
+            devfs_ruleset: crate::devfs::SYSTEM_JAIL_RULESET,
+            hostname: None,
+            managed_base: None,
+            cleanup_path: false,
+            os_version: None,
+            written_by_newer: None,
+            mount_permissions: MountPermissions::None,
+            description: None,
+            host_mounts: HashSet::new(),
         })
     }
 
@@ -53,9 +207,29 @@ impl Jail {
     pub fn with_path(mut self, path: impl AsRef<Path>) -> Result<Self, JailError> {
         let path_str = path.as_ref().to_string_lossy().to_string();
         self.path = Some(path_str);
+        self.managed_base = None;
         Ok(self)
     }
 
+    /// Set the jail path, requiring it to resolve inside `base`
+    ///
+    /// Use this for client-supplied paths; see [`resolve_jail_path`].
+    pub fn with_path_within(self, path: impl AsRef<Path>, base: impl AsRef<Path>) -> Result<Self, JailError> {
+        let resolved = resolve_jail_path(path.as_ref(), base.as_ref())?;
+        let mut jail = self.with_path(resolved)?;
+        jail.managed_base = Some(base.as_ref().to_path_buf());
+        Ok(jail)
+    }
+
+    /// Delete the jail root directory when the jail is destroyed
+    ///
+    /// Only takes effect for paths set with [`with_path_within`](Self::with_path_within);
+    /// see [`cleanup_jail_root`] for the checks applied before deleting.
+    pub fn with_cleanup_path(mut self, cleanup_path: bool) -> Self {
+        self.cleanup_path = cleanup_path;
+        self
+    }
+
     /// Set the jail IP address
     pub fn with_ip(mut self, ip: &str) -> Result<Self, JailError> {
         self.ip = Some(ip.to_string());
@@ -69,6 +243,91 @@ impl Jail {
         Ok(self)
     }
 
+    /// Set the devfs ruleset applied to the jail's /dev
+    pub fn with_devfs_ruleset(mut self, ruleset: u16) -> Self {
+        self.devfs_ruleset = ruleset;
+        self
+    }
+
+    /// Get the devfs ruleset applied to the jail's /dev
+    pub fn devfs_ruleset(&self) -> u16 {
+        self.devfs_ruleset
+    }
+
+    /// Set what the jail may mount itself; applies from the next start
+    pub fn with_mount_permissions(mut self, permissions: MountPermissions) -> Self {
+        self.mount_permissions = permissions;
+        self
+    }
+
+    /// Change what the jail may mount itself; applies from the next start
+    pub fn set_mount_permissions(&mut self, permissions: MountPermissions) {
+        self.mount_permissions = permissions;
+    }
+
+    /// File systems the jail may mount itself
+    pub fn mount_permissions(&self) -> MountPermissions {
+        self.mount_permissions
+    }
+
+    /// Set the operator's notes on the jail
+    pub fn with_description(mut self, description: Option<String>) -> Self {
+        self.description = description;
+        self
+    }
+
+    /// Change or clear the operator's notes on the jail
+    pub fn set_description(&mut self, description: Option<String>) {
+        self.description = description;
+    }
+
+    /// The operator's notes on the jail
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    /// Host name the jail runs with (`host.hostname`)
+    pub fn hostname(&self) -> &str {
+        self.hostname.as_deref().unwrap_or(&self.name)
+    }
+
+    /// Change the jail's host name
+    ///
+    /// A jail that is in the kernel (running or frozen) is updated in place
+    /// with `jail_set(JAIL_UPDATE)`, keeping its JID, processes and network;
+    /// otherwise the name is used from the next start.
+    pub fn set_hostname(&mut self, hostname: &str) -> Result<(), JailError> {
+        if self.jid >= 0 {
+            self.update_params(&[("host.hostname", hostname)])?;
+        }
+        self.hostname = Some(hostname.to_string());
+        Ok(())
+    }
+
+    /// Change kernel parameters of a jail in place
+    ///
+    /// Uses `jail_set(JAIL_UPDATE)` on the jail's JID, so a running jail
+    /// keeps its processes: `host.hostname`, `ip4.addr`, `allow.*` and the
+    /// like. Values are given as `jail(8)` would take them and encoded per
+    /// parameter (see [`JailParams`]).
+    pub fn update_params(&self, params: &[(&str, &str)]) -> Result<(), JailError> {
+        if self.jid < 0 {
+            return Err(JailError::UpdateFailed(format!("Jail '{}' is not in the kernel", self.name)));
+        }
+        let params = JailParams::new(params)?;
+
+        #[cfg(target_os = "freebsd")]
+        {
+            update_freebsd_jail(self.jid, &params)
+        }
+
+        #[cfg(not(target_os = "freebsd"))]
+        {
+            let _ = params;
+            Err(JailError::UpdateFailed(format!("Updating jail {} is only supported on FreeBSD", self.jid)))
+        }
+    }
+
     /// Get the jail name
     pub fn name(&self) -> &str {
         &self.name
@@ -84,14 +343,75 @@ impl Jail {
         self.state
     }
 
+    /// Userland version recorded by the last upgrade
+    pub fn os_version(&self) -> Option<&str> {
+        self.os_version.as_deref()
+    }
+
+    /// Record the userland version after an upgrade
+    pub(crate) fn set_os_version(&mut self, version: String) {
+        self.os_version = Some(version);
+    }
+
+    /// Newer kawakaze version that last wrote the record, if any
+    pub fn written_by_newer(&self) -> Option<&str> {
+        self.written_by_newer.as_deref()
+    }
+
+    /// Forget that a newer version wrote the record (see `--force-downgrade-writes`)
+    pub(crate) fn clear_written_by_newer(&mut self) {
+        self.written_by_newer = None;
+    }
+
+    /// Why the jail can't be changed, if it can't
+    pub fn readonly_reason(&self) -> Option<&'static str> {
+        self.written_by_newer.as_ref().map(|_| crate::version::READONLY_REASON)
+    }
+
     /// Check if the jail is running
     pub fn is_running(&self) -> bool {
         self.state == JailState::Running
     }
 
+    /// Whether the jail was stopped with [`StopMode::Freeze`] and still
+    /// holds its JID
+    pub fn is_frozen(&self) -> bool {
+        self.state == JailState::Stopped && self.jid >= 0
+    }
+
+    /// Whether the jail gets its own network stack (it does when it has an IP)
+    pub fn uses_vnet(&self) -> bool {
+        self.ip.is_some()
+    }
+
+    /// Bring the JID and state in line with the kernel
+    ///
+    /// A jail the kernel has under this name takes its JID and, unless it
+    /// was frozen, counts as running. One the kernel doesn't have gives up
+    /// its JID (host reboot, `jail -r`) and a running one becomes stopped.
+    pub fn sync_with_kernel(&mut self, kernel: &KernelJails) {
+        let frozen = self.is_frozen();
+        match kernel.jid(&self.name) {
+            Some(jid) => {
+                self.jid = jid;
+                if !frozen {
+                    self.state = JailState::Running;
+                }
+            }
+            None => {
+                self.jid = -1;
+                if self.state == JailState::Running {
+                    self.state = JailState::Stopped;
+                }
+            }
+        }
+    }
+
     /// Start the jail
     ///
     /// This creates and starts a FreeBSD jail with the given configuration.
+    /// A frozen jail is resumed as is; sync it with the kernel first
+    /// ([`Jail::sync_with_kernel`]) so one that is gone gets created anew.
     pub fn start(&mut self) -> Result<(), JailError> {
         if self.state == JailState::Running {
             return Err(JailError::StartFailed(format!(
@@ -100,6 +420,11 @@ impl Jail {
             )));
         }
 
+        if self.is_frozen() {
+            self.state = JailState::Running;
+            return Ok(());
+        }
+
         // Check if running as root (required for jail creation)
         if !is_root() {
             return Err(JailError::StartFailed(
@@ -110,13 +435,14 @@ impl Jail {
         #[cfg(target_os = "freebsd")]
         {
             // Get the jail path for devfs mounting
-            let jail_path = self.path.clone().unwrap_or_else(|| format!("/tmp/{}", self.name));
+            let jail_path = self.path.clone().unwrap_or_else(|| default_jail_path(&self.name));
 
             // VNET is enabled when an IP is allocated
             let vnet = self.ip.is_some();
 
             self.jid = create_freebsd_jail(
                 &self.name,
+                self.hostname(),
                 self.path.as_deref(),
                 self.ip.as_deref(),
                 self.vnet_interface.as_deref(),
@@ -124,7 +450,14 @@ impl Jail {
             )?;
 
             // Mount devfs inside the jail for device access (needed by commands like top)
-            mount_devfs(&self.name, &jail_path)?;
+            mount_devfs(&self.name, &jail_path, self.devfs_ruleset)?;
+
+            if let Err(e) = self.grant_mount_permissions(&jail_path) {
+                let _ = unmount_devfs(&jail_path);
+                let _ = remove_freebsd_jail(self.jid);
+                self.jid = -1;
+                return Err(e);
+            }
 
             self.state = JailState::Running;
             return Ok(());
@@ -142,7 +475,15 @@ impl Jail {
     ///
     /// This stops a running FreeBSD jail.
     pub fn stop(&mut self) -> Result<(), JailError> {
-        if self.state != JailState::Running {
+        self.stop_with(StopMode::Remove)
+    }
+
+    /// Stop the jail, removing or freezing it
+    ///
+    /// A frozen jail can still be removed this way.
+    pub fn stop_with(&mut self, mode: StopMode) -> Result<(), JailError> {
+        let removing_frozen = mode == StopMode::Remove && self.is_frozen();
+        if self.state != JailState::Running && !removing_frozen {
             return Err(JailError::StopFailed(format!(
                 "Jail '{}' is not running",
                 self.name
@@ -158,14 +499,29 @@ impl Jail {
         #[cfg(target_os = "freebsd")]
         {
             // Get the jail path for devfs unmounting
-            let jail_path = self.path.clone().unwrap_or_else(|| format!("/tmp/{}", self.name));
-
-            // Unmount devfs before removing the jail
-            let _ = unmount_devfs(&jail_path); // Ignore errors, devfs might not be mounted
-
-            remove_freebsd_jail(self.jid)?;
-            self.jid = -1;
-            self.state = JailState::Stopped;
+            let jail_path = self.path.clone().unwrap_or_else(|| default_jail_path(&self.name));
+
+            match mode {
+                StopMode::Remove => {
+                    // What the jail mounted itself would outlive it
+                    if self.mount_permissions != MountPermissions::None {
+                        match crate::build_dirs::mount_points() {
+                            Ok(mounts) => {
+                                release_jail_mounts(Path::new(&jail_path), &mounts, &self.host_mounts, unmount_forced);
+                            }
+                            Err(e) => tracing::warn!("Not unmounting what jail '{}' mounted: {}", self.name, e),
+                        }
+                        self.host_mounts.clear();
+                    }
+
+                    // Unmount devfs before removing the jail
+                    let _ = unmount_devfs(&jail_path); // Ignore errors, devfs might not be mounted
+
+                    remove_freebsd_jail(self.jid)?;
+                }
+                StopMode::Freeze => kill_jail_processes(self.jid, FREEZE_GRACE_SECS)?,
+            }
+            self.record_stop(mode);
             return Ok(());
         }
 
@@ -177,20 +533,49 @@ impl Jail {
         }
     }
 
+    /// Set the jail's `allow.mount*` parameters after it was created
+    ///
+    /// Nothing has run in the jail yet, so whatever is mounted below its
+    /// root now was mounted by the host and is recorded as such.
+    #[cfg(target_os = "freebsd")]
+    fn grant_mount_permissions(&mut self, jail_path: &str) -> Result<(), JailError> {
+        if self.mount_permissions == MountPermissions::None {
+            return Ok(());
+        }
+        let mounts = crate::build_dirs::mount_points()
+            .map_err(|e| JailError::StartFailed(format!("Failed to read the mount table: {}", e)))?;
+        self.host_mounts = mounts_below(&mounts, Path::new(jail_path)).into_iter().collect();
+        self.update_params(&self.mount_permissions.jail_params())
+    }
+
+    /// Record a finished stop: only a removed jail gives up its JID
+    #[cfg_attr(not(target_os = "freebsd"), allow(dead_code))]
+    fn record_stop(&mut self, mode: StopMode) {
+        if mode == StopMode::Remove {
+            self.jid = -1;
+        }
+        self.state = JailState::Stopped;
+    }
+
     /// Destroy the jail
     ///
     /// This destroys the jail and cleans up resources.
     pub fn destroy(mut self) -> Result<(), JailError> {
-        // Stop the jail first if it's running
-        if self.state == JailState::Running {
+        // Stop the jail first if it's running, or remove it if frozen
+        if self.is_running() || self.is_frozen() {
             self.stop()?;
         }
 
-        // Clean up jail path if it was created by us
-        if let Some(ref path) = self.path {
-            // Optionally clean up the jail directory
-            // For safety, we don't auto-delete paths
-            let _ = path;
+        // Only delete the root when asked to and it was validated against
+        // the managed base; a refused or failed cleanup doesn't fail destroy
+        if let (true, Some(path), Some(base)) = (self.cleanup_path, &self.path, &self.managed_base) {
+            #[cfg(target_os = "freebsd")]
+            let _ = unmount_devfs(path);
+
+            match cleanup_jail_root(Path::new(path), base) {
+                Ok(()) => tracing::info!("Removed jail root '{}' for '{}'", path, self.name),
+                Err(e) => tracing::warn!("Left jail root '{}' in place: {}", path, e),
+            }
         }
 
         Ok(())
@@ -203,19 +588,10 @@ impl Jail {
             jid: self.jid,
             state: self.state,
             path: self.path.clone(),
-        }
-    }
-
-    /// Check if a jail with the given JID exists
-    pub fn exists(jid: i32) -> bool {
-        #[cfg(target_os = "freebsd")]
-        {
-            check_jail_exists(jid)
-        }
-
-        #[cfg(not(target_os = "freebsd"))]
-        {
-            false
+            os_version: self.os_version.clone(),
+            readonly_reason: self.readonly_reason().map(str::to_string),
+            mount_permissions: self.mount_permissions,
+            description: self.description.clone(),
         }
     }
 
@@ -223,7 +599,14 @@ impl Jail {
     ///
     /// This runs the specified command with arguments inside the running jail using jexec.
     /// The PATH environment variable is set to ensure commands work correctly.
-    pub fn exec(&self, command: &str, args: &[String]) -> Result<(), JailError> {
+    /// Returns the command's captured stdout and stderr.
+    pub fn exec(&self, command: &str, args: &[String]) -> Result<(String, String), JailError> {
+        self.exec_with_env(command, args, &std::collections::BTreeMap::new())
+    }
+
+    /// Like [`Jail::exec`], with `env` exported to the command; a `PATH`
+    /// in it replaces the default
+    pub fn exec_with_env(&self, command: &str, args: &[String], env: &std::collections::BTreeMap<String, String>) -> Result<(String, String), JailError> {
         if self.state != JailState::Running {
             return Err(JailError::StartFailed(format!(
                 "Jail '{}' is not running", self.name
@@ -239,12 +622,14 @@ impl Jail {
 
             // Set PATH environment variable for command execution
             cmd.env("PATH", "/sbin:/bin:/usr/sbin:/usr/bin:/usr/local/sbin:/usr/local/bin:~/bin");
+            // jexec passes its environment on to the command
+            cmd.envs(env);
 
             cmd.arg(command);
             cmd.args(args);
 
             // Execute the command and wait for it to complete
-            let output = cmd.output()
+            let output = cmd.traced_output()
                 .map_err(|e| JailError::StartFailed(format!(
                     "Failed to execute jexec: {}", e
                 )))?;
@@ -260,11 +645,15 @@ impl Jail {
                 )));
             }
 
-            Ok(())
+            Ok((
+                String::from_utf8_lossy(&output.stdout).into_owned(),
+                String::from_utf8_lossy(&output.stderr).into_owned(),
+            ))
         }
 
         #[cfg(not(target_os = "freebsd"))]
         {
+            let _ = (command, args, env);
             Err(JailError::StartFailed(
                 "jexec is only supported on FreeBSD".into()
             ))
@@ -299,6 +688,10 @@ pub struct JailInfo {
     pub jid: i32,
     pub state: JailState,
     pub path: Option<String>,
+    pub os_version: Option<String>,
+    pub readonly_reason: Option<String>,
+    pub mount_permissions: MountPermissions,
+    pub description: Option<String>,
 }
 
 impl Jail {
@@ -310,6 +703,10 @@ impl Jail {
             ip: self.ip.clone(),
             state: self.state.as_str().to_string(),
             jid: self.jid,
+            os_version: self.os_version.clone(),
+            mount_permissions: self.mount_permissions.as_str().to_string(),
+            description: self.description.clone(),
+            provenance: Default::default(),
         }
     }
 
@@ -324,6 +721,15 @@ impl Jail {
             path: row.path,
             ip: row.ip,
             vnet_interface: None,
+            devfs_ruleset: crate::devfs::SYSTEM_JAIL_RULESET,
+            hostname: None,
+            managed_base: None,
+            cleanup_path: false,
+            os_version: row.os_version,
+            written_by_newer: row.provenance.newer_than(crate::version::CURRENT),
+            mount_permissions: row.mount_permissions.parse().map_err(JailError::CreationFailed)?,
+            description: row.description,
+            host_mounts: HashSet::new(),
         })
     }
 
@@ -338,6 +744,121 @@ impl Jail {
     }
 }
 
+/// Resolve a jail root path and check it is contained in `base`
+///
+/// The path must be absolute, must not contain `..`, and after resolving
+/// symlinks must lie strictly below the (also resolved) base directory.
+/// Components that don't exist yet are kept as given, so a jail root can be
+/// validated before it is created.
+pub fn resolve_jail_path(path: &Path, base: &Path) -> Result<PathBuf, JailError> {
+    if !path.is_absolute() {
+        return Err(JailError::InvalidPath(format!("'{}' is not absolute", path.display())));
+    }
+    if path.components().any(|c| c == Component::ParentDir) {
+        return Err(JailError::InvalidPath(format!("'{}' contains '..'", path.display())));
+    }
+
+    let base = canonicalize_existing(base);
+    let resolved = canonicalize_existing(path);
+
+    if resolved == base || !resolved.starts_with(&base) {
+        return Err(JailError::InvalidPath(format!(
+            "'{}' resolves to '{}', outside the jail base '{}'",
+            path.display(),
+            resolved.display(),
+            base.display()
+        )));
+    }
+
+    Ok(resolved)
+}
+
+/// Root of a jail that was never given a path
+///
+/// Managers give every jail they create a path under the configured jail
+/// base; this only covers jails built directly.
+#[cfg(target_os = "freebsd")]
+fn default_jail_path(name: &str) -> String {
+    crate::paths::Paths::default().jail_root(name).display().to_string()
+}
+
+/// Delete a jail root directory tree, refusing anything that isn't clearly ours
+///
+/// The path must resolve strictly inside `base` (see [`resolve_jail_path`]),
+/// be a real directory rather than a symlink, and contain no mount points:
+/// a root that is itself a mount (e.g. a ZFS dataset) is left for its owner
+/// to destroy, and a mount inside it (devfs, nullfs volumes) would otherwise
+/// have its contents deleted too.
+pub fn cleanup_jail_root(path: &Path, base: &Path) -> Result<(), JailError> {
+    use std::os::unix::fs::MetadataExt;
+
+    let resolved = resolve_jail_path(path, base)?;
+
+    let meta = fs::symlink_metadata(path)
+        .map_err(|e| JailError::DestroyFailed(format!("'{}': {}", path.display(), e)))?;
+    if !meta.is_dir() {
+        return Err(JailError::InvalidPath(format!("'{}' is not a directory", path.display())));
+    }
+
+    let parent = resolved.parent().unwrap_or(&resolved);
+    let parent_dev = fs::metadata(parent)
+        .map_err(|e| JailError::DestroyFailed(format!("'{}': {}", parent.display(), e)))?
+        .dev();
+    if meta.dev() != parent_dev {
+        return Err(JailError::InvalidPath(format!(
+            "'{}' is a mount point; its owner must remove it",
+            path.display()
+        )));
+    }
+    if let Some(mount) = find_mount_below(&resolved, meta.dev()) {
+        return Err(JailError::InvalidPath(format!("'{}' is still mounted", mount.display())));
+    }
+
+    fs::remove_dir_all(&resolved)
+        .map_err(|e| JailError::DestroyFailed(format!("Failed to remove '{}': {}", resolved.display(), e)))
+}
+
+/// Find a directory below `dir` on a different device than `dev`
+fn find_mount_below(dir: &Path, dev: u64) -> Option<PathBuf> {
+    use std::os::unix::fs::MetadataExt;
+
+    for entry in fs::read_dir(dir).ok()?.flatten() {
+        let Ok(meta) = entry.path().symlink_metadata() else {
+            continue;
+        };
+        if !meta.is_dir() {
+            continue;
+        }
+        if meta.dev() != dev {
+            return Some(entry.path());
+        }
+        if let Some(mount) = find_mount_below(&entry.path(), dev) {
+            return Some(mount);
+        }
+    }
+
+    None
+}
+
+/// Canonicalize the longest existing prefix of `path` and re-append the rest
+fn canonicalize_existing(path: &Path) -> PathBuf {
+    let mut existing = path;
+    let mut tail = Vec::new();
+
+    loop {
+        if let Ok(canonical) = existing.canonicalize() {
+            return tail.iter().rev().fold(canonical, |acc, part| acc.join(part));
+        }
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                tail.push(name.to_os_string());
+                existing = parent;
+            }
+            _ => return path.to_path_buf(),
+        }
+    }
+}
+
 /// Jail operation errors
 #[derive(Debug)]
 pub enum JailError {
@@ -347,6 +868,12 @@ pub enum JailError {
     DestroyFailed(String),
     InvalidState(String),
     InvalidPath(String),
+    UpdateFailed(String),
+    /// The jail was last written by a newer kawakaze (see `version`)
+    VersionSkew(String),
+    /// The daemon runs inside a jail that doesn't allow this (see
+    /// `system::Confinement`)
+    Unavailable(String),
 }
 
 impl std::fmt::Display for JailError {
@@ -358,6 +885,9 @@ impl std::fmt::Display for JailError {
             JailError::DestroyFailed(msg) => write!(f, "Failed to destroy jail: {}", msg),
             JailError::InvalidState(msg) => write!(f, "Invalid jail state: {}", msg),
             JailError::InvalidPath(msg) => write!(f, "Invalid path: {}", msg),
+            JailError::UpdateFailed(msg) => write!(f, "Failed to update jail: {}", msg),
+            JailError::VersionSkew(msg) => write!(f, "{}", msg),
+            JailError::Unavailable(msg) => write!(f, "{}", msg),
         }
     }
 }
@@ -370,6 +900,120 @@ impl From<NulError> for JailError {
     }
 }
 
+/// Integer parameters
+const INT_PARAMS: &[&str] = &["securelevel", "devfs_ruleset", "children.max", "enforce_statfs", "osreldate"];
+
+/// Boolean parameters besides `allow.*`, passed as an int
+const BOOL_PARAMS: &[&str] = &["persist"];
+
+/// Jailsys values from sys/jail.h (libc only has them on FreeBSD)
+const JAIL_SYS_DISABLE: libc::c_int = 0;
+const JAIL_SYS_NEW: libc::c_int = 1;
+const JAIL_SYS_INHERIT: libc::c_int = 2;
+
+/// "Jailsys" parameters: `disable`, `new` or `inherit`, passed as an int
+const JAILSYS_PARAMS: &[&str] = &["host", "ip4", "ip6", "vnet", "sysvmsg", "sysvsem", "sysvshm"];
+
+/// A parameter value in the form `jail_set` takes it
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ParamValue {
+    Int(libc::c_int),
+    Str(CString),
+    /// `in_addr`s, already in network byte order
+    Ip4(Vec<[u8; 4]>),
+    Ip6(Vec<[u8; 16]>),
+}
+
+/// Jail parameters encoded for `jail_set`
+///
+/// Names and values are given as `jail(8)` takes them. Booleans (`persist`,
+/// `allow.*`), integers and jailsys parameters become a C int, `ip4.addr`
+/// and `ip6.addr` a packed array of addresses (comma-separated in the
+/// value), and everything else a NUL-terminated string.
+#[derive(Debug)]
+#[cfg_attr(not(target_os = "freebsd"), allow(dead_code))]
+pub(crate) struct JailParams {
+    params: Vec<(CString, ParamValue)>,
+}
+
+impl JailParams {
+    pub(crate) fn new(params: &[(&str, &str)]) -> Result<Self, JailError> {
+        let params = params
+            .iter()
+            .map(|&(name, value)| {
+                let key = CString::new(name)
+                    .map_err(|_| JailError::UpdateFailed(format!("Null byte in parameter name '{}'", name)))?;
+                Ok((key, Self::encode(name, value)?))
+            })
+            .collect::<Result<_, JailError>>()?;
+        Ok(Self { params })
+    }
+
+    fn encode(name: &str, value: &str) -> Result<ParamValue, JailError> {
+        let invalid = |expected: &str| JailError::UpdateFailed(format!("Invalid value '{}' for {}: expected {}", value, name, expected));
+
+        if BOOL_PARAMS.contains(&name) || name.starts_with("allow.") {
+            return match value {
+                "1" | "true" | "yes" => Ok(ParamValue::Int(1)),
+                "0" | "false" | "no" => Ok(ParamValue::Int(0)),
+                _ => Err(invalid("true or false")),
+            };
+        }
+        if INT_PARAMS.contains(&name) {
+            return value.parse().map(ParamValue::Int).map_err(|_| invalid("an integer"));
+        }
+        if JAILSYS_PARAMS.contains(&name) {
+            return match value {
+                "disable" => Ok(ParamValue::Int(JAIL_SYS_DISABLE)),
+                "new" => Ok(ParamValue::Int(JAIL_SYS_NEW)),
+                "inherit" => Ok(ParamValue::Int(JAIL_SYS_INHERIT)),
+                _ => Err(invalid("disable, new or inherit")),
+            };
+        }
+        match name {
+            // An empty list leaves the jail without addresses
+            "ip4.addr" | "ip6.addr" if value.trim().is_empty() => {
+                Ok(if name == "ip4.addr" { ParamValue::Ip4(Vec::new()) } else { ParamValue::Ip6(Vec::new()) })
+            }
+            "ip4.addr" => value
+                .split(',')
+                .map(|addr| addr.trim().parse::<std::net::Ipv4Addr>().map(|ip| ip.octets()))
+                .collect::<Result<_, _>>()
+                .map(ParamValue::Ip4)
+                .map_err(|_| invalid("IPv4 addresses")),
+            "ip6.addr" => value
+                .split(',')
+                .map(|addr| addr.trim().parse::<std::net::Ipv6Addr>().map(|ip| ip.octets()))
+                .collect::<Result<_, _>>()
+                .map(ParamValue::Ip6)
+                .map_err(|_| invalid("IPv6 addresses")),
+            _ => CString::new(value)
+                .map(ParamValue::Str)
+                .map_err(|_| JailError::UpdateFailed(format!("Null byte in value of {}", name))),
+        }
+    }
+
+    /// Name and value iovecs for each parameter, pointing into `self`
+    #[cfg_attr(not(target_os = "freebsd"), allow(dead_code))]
+    pub(crate) fn iovecs(&self) -> Vec<libc::iovec> {
+        fn iov(base: *const libc::c_void, len: usize) -> libc::iovec {
+            libc::iovec { iov_base: base as *mut libc::c_void, iov_len: len }
+        }
+
+        let mut iovs = Vec::with_capacity(self.params.len() * 2);
+        for (name, value) in &self.params {
+            iovs.push(iov(name.as_ptr().cast(), name.as_bytes_with_nul().len()));
+            iovs.push(match value {
+                ParamValue::Int(int) => iov((int as *const libc::c_int).cast(), std::mem::size_of::<libc::c_int>()),
+                ParamValue::Str(s) => iov(s.as_ptr().cast(), s.as_bytes_with_nul().len()),
+                ParamValue::Ip4(addrs) => iov(addrs.as_ptr().cast(), addrs.len() * 4),
+                ParamValue::Ip6(addrs) => iov(addrs.as_ptr().cast(), addrs.len() * 16),
+            });
+        }
+        iovs
+    }
+}
+
 /// Check if running as root
 fn is_root() -> bool {
     #[cfg(unix)]
@@ -396,6 +1040,7 @@ mod freebsd {
     /// For non-VNET jails, we use jail_set() directly for better control.
     pub fn create_freebsd_jail(
         name: &str,
+        hostname: &str,
         path: Option<&str>,
         ip: Option<&str>,
         vnet_interface: Option<&str>,
@@ -403,24 +1048,25 @@ mod freebsd {
     ) -> Result<i32, JailError> {
         // For VNET jails, use the jail command instead of jail_set()
         if vnet {
-            return create_freebsd_jail_with_command(name, path, ip, vnet_interface);
+            return create_freebsd_jail_with_command(name, hostname, path, ip, vnet_interface);
         }
 
         // For non-VNET jails, use jail_set() system call
-        create_freebsd_jail_with_syscall(name, path, ip)
+        create_freebsd_jail_with_syscall(name, hostname, path, ip)
     }
 
     /// Create a VNET jail using the jail command
     fn create_freebsd_jail_with_command(
         name: &str,
+        hostname: &str,
         path: Option<&str>,
         ip: Option<&str>,
         vnet_interface: Option<&str>,
     ) -> Result<i32, JailError> {
         use std::process::Command;
 
-        // Determine path - use /tmp/jailname if not specified
-        let default_path = format!("/tmp/{}", name);
+        // Determine path - use the default jail root if not specified
+        let default_path = default_jail_path(name);
         let jail_path = path.unwrap_or(&default_path);
 
         // Create jail directory if it doesn't exist
@@ -432,14 +1078,14 @@ mod freebsd {
         }
 
         // Build the jail command
-        // jail -c name=<name> path=<path> host.hostname=<name> persist vnet [vnet.interface=<iface>]
+        // jail -c name=<name> path=<path> host.hostname=<hostname> persist vnet [vnet.interface=<iface>]
         // Note: For VNET jails, we do NOT pass ip4.addr to the jail command.
         // The IP will be configured on the epair interface by the networking module.
         let mut cmd = Command::new("jail");
         cmd.arg("-c");
         cmd.arg(format!("name={}", name));
         cmd.arg(format!("path={}", jail_path));
-        cmd.arg(format!("host.hostname={}", name));
+        cmd.arg(format!("host.hostname={}", hostname));
         cmd.arg("persist");
         cmd.arg("vnet");
 
@@ -453,7 +1099,7 @@ mod freebsd {
         tracing::debug!("Creating VNET jail with command: {:?}", cmd);
 
         // Execute the jail command
-        let output = cmd.output()
+        let output = cmd.traced_output()
             .map_err(|e| JailError::CreationFailed(format!(
                 "Failed to execute jail command: {}", e
             )))?;
@@ -506,13 +1152,14 @@ mod freebsd {
     /// Create a non-VNET jail using jail_set system call
     fn create_freebsd_jail_with_syscall(
         name: &str,
+        hostname: &str,
         path: Option<&str>,
         ip: Option<&str>,
     ) -> Result<i32, JailError> {
         use std::mem;
 
-        // Determine path - use /tmp/jailname if not specified
-        let default_path = format!("/tmp/{}", name);
+        // Determine path - use the default jail root if not specified
+        let default_path = default_jail_path(name);
         let jail_path = path.unwrap_or(&default_path);
 
         // Create jail directory if it doesn't exist
@@ -526,7 +1173,7 @@ mod freebsd {
         // Prepare jail parameters as C strings
         let name_cstring = CString::new(name)?;
         let path_cstring = CString::new(jail_path)?;
-        let hostname_cstring = CString::new(name)?;
+        let hostname_cstring = CString::new(hostname)?;
         let ip_cstring = ip.map(|p| CString::new(p)).transpose()?;
 
         // Create static C strings for parameter names
@@ -568,7 +1215,7 @@ mod freebsd {
         });
         iovs.push(libc::iovec {
             iov_base: hostname_cstring.as_ptr() as *mut libc::c_void,
-            iov_len: name.len() + 1,
+            iov_len: hostname.len() + 1,
         });
 
         // persist parameter
@@ -651,49 +1298,116 @@ mod freebsd {
         Ok(jid)
     }
 
-    /// Remove a FreeBSD jail
-    pub fn remove_freebsd_jail(jid: i32) -> Result<(), JailError> {
-        let result = unsafe { libc::jail_remove(jid) };
-
-        if result < 0 {
-            return Err(JailError::StopFailed(format!(
-                "jail_remove failed: {}",
-                std::io::Error::last_os_error()
-            )));
-        }
-
-        Ok(())
-    }
-
-    /// Check if a jail exists
-    pub fn check_jail_exists(jid: i32) -> bool {
-        let _jid_out: libc::c_int = 0;
-
-        let jid_param = CString::new("jid").unwrap();
+    /// Change parameters of an existing jail with `jail_set(JAIL_UPDATE)`
+    pub fn update_freebsd_jail(jid: i32, params: &JailParams) -> Result<(), JailError> {
+        let jid_param = c"jid";
+        let errmsg_param = c"errmsg";
+        let mut errmsg = [0u8; 1024];
 
-        let iovs = [
+        let mut iovs = vec![
             libc::iovec {
                 iov_base: jid_param.as_ptr() as *mut libc::c_void,
-                iov_len: jid_param.as_bytes().len() + 1,
+                iov_len: jid_param.to_bytes_with_nul().len(),
             },
             libc::iovec {
                 iov_base: &jid as *const i32 as *mut libc::c_void,
                 iov_len: std::mem::size_of::<i32>(),
             },
         ];
+        iovs.extend(params.iovecs());
+        iovs.push(libc::iovec {
+            iov_base: errmsg_param.as_ptr() as *mut libc::c_void,
+            iov_len: errmsg_param.to_bytes_with_nul().len(),
+        });
+        iovs.push(libc::iovec {
+            iov_base: errmsg.as_mut_ptr() as *mut libc::c_void,
+            iov_len: errmsg.len(),
+        });
 
+        // Not JAIL_ATTACH: the daemon stays outside the jail
         let result = unsafe {
-            libc::jail_get(iovs.as_ptr() as *mut libc::iovec, iovs.len() as libc::c_uint, 0)
+            libc::jail_set(iovs.as_mut_ptr(), iovs.len() as libc::c_uint, libc::JAIL_UPDATE)
         };
 
-        result >= 0
+        if result < 0 {
+            let err = std::io::Error::last_os_error();
+            let message = std::ffi::CStr::from_bytes_until_nul(&errmsg)
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            return Err(JailError::UpdateFailed(format!(
+                "jail_set on JID {} failed: {}",
+                jid,
+                if message.is_empty() { err.to_string() } else { message }
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Kill every process in a jail without removing it: SIGTERM, then
+    /// SIGKILL for whatever is left after `grace_secs`
+    pub fn kill_jail_processes(jid: i32, grace_secs: u64) -> Result<(), JailError> {
+        let jid = jid.to_string();
+        let signal = |signal: &str| {
+            // pkill exits 1 when nothing matched, which is fine here
+            Command::new("pkill")
+                .args([signal, "-j", &jid])
+                .traced_output()
+                .map(|_| ())
+                .map_err(|e| JailError::StopFailed(format!("pkill {} -j {}: {}", signal, jid, e)))
+        };
+        let has_processes = || {
+            Command::new("pgrep")
+                .args(["-j", &jid])
+                .output()
+                .map(|output| output.status.success())
+                .unwrap_or(false)
+        };
+
+        signal("-TERM")?;
+        for _ in 0..grace_secs * 10 {
+            if !has_processes() {
+                return Ok(());
+            }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+        signal("-KILL")?;
+        Ok(())
+    }
+
+    /// Force-unmount a file system a jail mounted
+    pub fn unmount_forced(path: &Path) -> Result<(), String> {
+        let output = Command::new("umount")
+            .arg("-f")
+            .arg(path)
+            .traced_output()
+            .map_err(|e| format!("umount: {}", e))?;
+        if !output.status.success() {
+            return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+        }
+        Ok(())
+    }
+
+    /// Remove a FreeBSD jail
+    pub fn remove_freebsd_jail(jid: i32) -> Result<(), JailError> {
+        let result = unsafe { libc::jail_remove(jid) };
+
+        if result < 0 {
+            return Err(JailError::StopFailed(format!(
+                "jail_remove failed: {}",
+                std::io::Error::last_os_error()
+            )));
+        }
+
+        Ok(())
     }
 
     /// Mount devfs inside a jail
     ///
     /// This mounts the devfs filesystem at /dev inside the jail path,
     /// which is necessary for commands like `top` to access device nodes.
-    pub fn mount_devfs(jail_name: &str, jail_path: &str) -> Result<(), JailError> {
+    /// `ruleset` limits which device nodes are visible.
+    pub fn mount_devfs(jail_name: &str, jail_path: &str, ruleset: u16) -> Result<(), JailError> {
         // Create /dev directory if it doesn't exist
         let dev_path = format!("{}/dev", jail_path);
         if let Err(e) = fs::create_dir_all(&dev_path) {
@@ -704,14 +1418,15 @@ mod freebsd {
         }
 
         // Mount devfs using the mount command
-        // On FreeBSD, the correct syntax is: mount -t devfs devfs /path
-        // We can also use -o to specify options like ruleset
+        // On FreeBSD, the correct syntax is: mount -t devfs -o ruleset=N devfs /path
         let output = Command::new("mount")
             .arg("-t")
             .arg("devfs")
+            .arg("-o")
+            .arg(format!("ruleset={}", ruleset))
             .arg("devfs")
             .arg(&dev_path)
-            .output();
+            .traced_output();
 
         match output {
             Ok(output) if output.status.success() => Ok(()),
@@ -742,7 +1457,7 @@ mod freebsd {
         let output = Command::new("umount")
             .arg("-f")
             .arg(&dev_path)
-            .output();
+            .traced_output();
 
         match output {
             Ok(output) if output.status.success() => Ok(()),
@@ -768,7 +1483,7 @@ mod freebsd {
 }
 
 #[cfg(target_os = "freebsd")]
-use freebsd::{create_freebsd_jail, remove_freebsd_jail, check_jail_exists, mount_devfs, unmount_devfs};
+use freebsd::{create_freebsd_jail, remove_freebsd_jail, kill_jail_processes, mount_devfs, unmount_devfs, unmount_forced, update_freebsd_jail};
 
 #[cfg(test)]
 mod tests {
@@ -820,6 +1535,179 @@ mod tests {
         assert_eq!(jail.path, Some("/tmp/test_jail".to_string()));
     }
 
+    #[test]
+    fn test_jail_path_within_base() {
+        let base = tempfile::tempdir().unwrap();
+        let root = base.path().join("web");
+        fs::create_dir(&root).unwrap();
+
+        let jail = Jail::create("test_within").unwrap().with_path_within(&root, base.path()).unwrap();
+        assert_eq!(jail.path, Some(root.canonicalize().unwrap().to_string_lossy().to_string()));
+
+        // Not created yet is fine as long as it stays inside the base
+        assert!(resolve_jail_path(&base.path().join("new/root"), base.path()).is_ok());
+    }
+
+    #[test]
+    fn test_jail_path_escaping_base() {
+        let base = tempfile::tempdir().unwrap();
+
+        for path in [Path::new("/"), Path::new("/etc"), base.path()] {
+            assert!(matches!(resolve_jail_path(path, base.path()), Err(JailError::InvalidPath(_))));
+        }
+        assert!(matches!(
+            resolve_jail_path(&base.path().join("web/../../etc"), base.path()),
+            Err(JailError::InvalidPath(_))
+        ));
+        assert!(matches!(resolve_jail_path(Path::new("jails/web"), base.path()), Err(JailError::InvalidPath(_))));
+    }
+
+    #[test]
+    fn test_jail_path_symlink_escaping_base() {
+        let base = tempfile::tempdir().unwrap();
+        let link = base.path().join("web");
+        std::os::unix::fs::symlink("/etc", &link).unwrap();
+
+        let result = Jail::create("test_symlink").unwrap().with_path_within(&link, base.path());
+        assert!(matches!(result, Err(JailError::InvalidPath(_))));
+        assert!(matches!(resolve_jail_path(&link.join("root"), base.path()), Err(JailError::InvalidPath(_))));
+    }
+
+    #[test]
+    fn test_jail_devfs_ruleset() {
+        let jail = Jail::create("test_devfs").unwrap();
+        assert_eq!(jail.devfs_ruleset(), crate::devfs::SYSTEM_JAIL_RULESET);
+
+        let jail = jail.with_devfs_ruleset(11);
+        assert_eq!(jail.devfs_ruleset(), 11);
+    }
+
+    #[test]
+    fn test_mount_permissions_params() {
+        assert!(MountPermissions::None.jail_params().is_empty());
+        assert_eq!(
+            MountPermissions::Nullfs.jail_params(),
+            [("allow.mount", "true"), ("enforce_statfs", "1"), ("allow.mount.nullfs", "true")]
+        );
+        assert_eq!(MountPermissions::Tmpfs.jail_params()[2], ("allow.mount.tmpfs", "true"));
+        assert_eq!(MountPermissions::Zfs.jail_params()[2], ("allow.mount.zfs", "true"));
+        assert_eq!(
+            MountPermissions::All.jail_params(),
+            [
+                ("allow.mount", "true"),
+                ("enforce_statfs", "1"),
+                ("allow.mount.nullfs", "true"),
+                ("allow.mount.tmpfs", "true"),
+                ("allow.mount.zfs", "true"),
+            ]
+        );
+        // Every expansion encodes for jail_set
+        for permissions in [MountPermissions::Nullfs, MountPermissions::Tmpfs, MountPermissions::Zfs, MountPermissions::All] {
+            assert!(JailParams::new(&permissions.jail_params()).is_ok());
+            assert_eq!(permissions.as_str().parse(), Ok(permissions));
+            assert_eq!(serde_json::to_value(permissions).unwrap(), permissions.as_str());
+        }
+        assert!("procfs".parse::<MountPermissions>().unwrap_err().contains("procfs"));
+
+        // Kept with the jail
+        let jail = Jail::create("mounter").unwrap().with_mount_permissions(MountPermissions::Tmpfs);
+        let row = jail.to_db_row();
+        assert_eq!(row.mount_permissions, "tmpfs");
+        assert_eq!(Jail::from_db_row(row).unwrap().mount_permissions(), MountPermissions::Tmpfs);
+    }
+
+    #[test]
+    fn test_release_jail_mounts_deepest_first() {
+        let root = Path::new("/kz/jails/web");
+        let mounts: HashSet<PathBuf> = [
+            "/",
+            "/kz/jails/web",
+            "/kz/jails/web/dev",
+            "/kz/jails/web/mnt/data",
+            "/kz/jails/web/mnt/data/cache",
+            "/kz/jails/web/tmp",
+            "/kz/jails/webby/tmp",
+        ]
+        .into_iter()
+        .map(PathBuf::from)
+        .collect();
+        assert_eq!(
+            mounts_below(&mounts, root),
+            ["/kz/jails/web/mnt/data/cache", "/kz/jails/web/mnt/data", "/kz/jails/web/tmp", "/kz/jails/web/dev"].map(PathBuf::from)
+        );
+
+        // What the host mounted before the jail ran is left alone, and a
+        // failure doesn't stop the rest
+        let host_mounts: HashSet<PathBuf> = [PathBuf::from("/kz/jails/web/dev")].into_iter().collect();
+        let mut tried = Vec::new();
+        let released = release_jail_mounts(root, &mounts, &host_mounts, |mount| {
+            tried.push(mount.to_path_buf());
+            if mount.ends_with("cache") { Err("busy".to_string()) } else { Ok(()) }
+        });
+        assert_eq!(tried, ["/kz/jails/web/mnt/data/cache", "/kz/jails/web/mnt/data", "/kz/jails/web/tmp"].map(PathBuf::from));
+        assert_eq!(released, ["/kz/jails/web/mnt/data", "/kz/jails/web/tmp"].map(PathBuf::from));
+    }
+
+    #[test]
+    fn test_jail_hostname() {
+        let mut jail = Jail::create("kawakaze-0f8e1a2b").unwrap();
+        assert_eq!(jail.hostname(), "kawakaze-0f8e1a2b");
+
+        // Not in the kernel: only recorded for the next start
+        jail.set_hostname("web").unwrap();
+        assert_eq!(jail.hostname(), "web");
+    }
+
+    #[test]
+    fn test_jail_params_iovecs() {
+        let params = JailParams::new(&[
+            ("host.hostname", "web"),
+            ("allow.raw_sockets", "true"),
+            ("children.max", "3"),
+            ("ip4.addr", "10.0.0.2, 10.0.0.3"),
+            ("ip6.addr", "fd00::2"),
+            ("vnet", "inherit"),
+        ])
+        .unwrap();
+        let iovs = params.iovecs();
+        assert_eq!(iovs.len(), 12);
+
+        let bytes = |iov: &libc::iovec| unsafe { std::slice::from_raw_parts(iov.iov_base as *const u8, iov.iov_len) }.to_vec();
+        let int = |iov: &libc::iovec| {
+            assert_eq!(iov.iov_len, std::mem::size_of::<libc::c_int>());
+            unsafe { *(iov.iov_base as *const libc::c_int) }
+        };
+
+        // Names and strings carry their NUL
+        assert_eq!(bytes(&iovs[0]), b"host.hostname\0");
+        assert_eq!(bytes(&iovs[1]), b"web\0");
+        assert_eq!(bytes(&iovs[2]), b"allow.raw_sockets\0");
+        assert_eq!(int(&iovs[3]), 1);
+        assert_eq!(int(&iovs[5]), 3);
+        // Addresses are packed in network byte order
+        assert_eq!(bytes(&iovs[7]), [10, 0, 0, 2, 10, 0, 0, 3]);
+        assert_eq!(iovs[9].iov_len, 16);
+        assert_eq!(bytes(&iovs[9])[..2], [0xfd, 0x00]);
+        assert_eq!(int(&iovs[11]), JAIL_SYS_INHERIT);
+
+        // Removing the last alias sends an empty address list
+        let iovs = JailParams::new(&[("ip4.addr", "")]).unwrap().iovecs();
+        assert_eq!(iovs[1].iov_len, 0);
+
+        for bad in [("allow.mount", "maybe"), ("children.max", "x"), ("ip4.addr", "10.0.0.256"), ("vnet", "yes"), ("host.hostname", "a\0b")] {
+            assert!(matches!(JailParams::new(&[bad]), Err(JailError::UpdateFailed(_))), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn test_update_params_needs_a_jid() {
+        let jail = Jail::create("test_update_params").unwrap();
+        match jail.update_params(&[("allow.raw_sockets", "true")]) {
+            Err(JailError::UpdateFailed(msg)) => assert!(msg.contains("not in the kernel"), "{}", msg),
+            other => panic!("Expected UpdateFailed, got {:?}", other),
+        }
+    }
+
     #[test]
     fn test_jail_with_ip() {
         let jail = Jail::create("test_ip").unwrap().with_ip("192.168.1.100");
@@ -904,6 +1792,90 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_stop_modes_state_transitions() {
+        // Remove gives up the JID; the next start creates a new jail
+        let mut jail = Jail::create("test_stop_remove").unwrap();
+        jail.jid = 123;
+        jail.state = JailState::Running;
+        jail.record_stop(StopMode::Remove);
+        assert_eq!((jail.state(), jail.jid()), (JailState::Stopped, -1));
+        assert!(!jail.is_frozen());
+
+        // Freeze keeps it, and the row written to the database says so
+        let mut jail = Jail::create("test_stop_freeze").unwrap();
+        jail.jid = 123;
+        jail.state = JailState::Running;
+        jail.record_stop(StopMode::Freeze);
+        assert_eq!((jail.state(), jail.jid()), (JailState::Stopped, 123));
+        assert!(jail.is_frozen() && !jail.is_running());
+        let reloaded = Jail::from_db_row(jail.to_db_row()).unwrap();
+        assert!(reloaded.is_frozen());
+
+        // A frozen jail can't be frozen again, only removed
+        match jail.stop_with(StopMode::Freeze) {
+            Err(JailError::StopFailed(msg)) => assert!(msg.contains("not running"), "{}", msg),
+            other => panic!("Expected StopFailed, got {:?}", other.err()),
+        }
+        #[cfg(not(target_os = "freebsd"))]
+        match jail.stop_with(StopMode::Remove) {
+            Err(JailError::StopFailed(msg)) => assert!(!msg.contains("not running"), "{}", msg),
+            other => panic!("Expected StopFailed, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    #[cfg(not(target_os = "freebsd"))]
+    fn test_start_frozen_jail_gone_from_kernel() {
+        // The kernel no longer has it, like after a host reboot: the kept
+        // JID is dropped and the jail is created anew
+        let mut jail = Jail::create("test_start_frozen").unwrap();
+        jail.jid = 123;
+        jail.state = JailState::Stopped;
+
+        jail.sync_with_kernel(&KernelJails::default());
+        assert!(jail.start().is_err());
+        assert_eq!((jail.state(), jail.jid()), (JailState::Stopped, -1));
+    }
+
+    #[test]
+    fn test_sync_with_kernel() {
+        use crate::kernel_jails::KernelJail;
+        let kernel = KernelJails::from_jails([KernelJail { jid: 9, name: "up".into(), path: "/jails/up".into(), vnet: false }]);
+
+        // Found by name: takes the kernel's JID and runs
+        let mut jail = Jail::create("up").unwrap();
+        jail.sync_with_kernel(&kernel);
+        assert_eq!((jail.state(), jail.jid()), (JailState::Running, 9));
+
+        // A frozen jail stays frozen
+        let mut jail = Jail::create("up").unwrap();
+        jail.jid = 4;
+        jail.state = JailState::Stopped;
+        jail.sync_with_kernel(&kernel);
+        assert!(jail.is_frozen());
+        assert_eq!(jail.jid(), 9);
+
+        // Not in the kernel: no JID, and a running jail is stopped
+        let mut jail = Jail::create("down").unwrap();
+        jail.jid = 5;
+        jail.state = JailState::Running;
+        jail.sync_with_kernel(&kernel);
+        assert_eq!((jail.state(), jail.jid()), (JailState::Stopped, -1));
+
+        let mut jail = Jail::create("down").unwrap();
+        jail.sync_with_kernel(&kernel);
+        assert_eq!((jail.state(), jail.jid()), (JailState::Created, -1));
+    }
+
+    #[test]
+    fn test_stop_mode_serde() {
+        assert_eq!(StopMode::default(), StopMode::Remove);
+        assert_eq!(serde_json::from_str::<StopMode>("\"freeze\"").unwrap(), StopMode::Freeze);
+        assert_eq!(serde_json::to_string(&StopMode::Remove).unwrap(), "\"remove\"");
+        assert!(serde_json::from_str::<StopMode>("\"pause\"").is_err());
+    }
+
     #[test]
     fn test_jail_destroy() {
         let mut jail = Jail::create("test_destroy").unwrap();
@@ -921,6 +1893,81 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_jail_destroy_cleans_up_managed_path() {
+        let base = tempfile::tempdir().unwrap();
+        let root = base.path().join("web");
+        fs::create_dir_all(root.join("etc")).unwrap();
+        fs::write(root.join("etc/rc.conf"), "").unwrap();
+
+        // Cleanup is opt-in
+        let jail = Jail::create("test_keep").unwrap().with_path_within(&root, base.path()).unwrap();
+        jail.destroy().unwrap();
+        assert!(root.exists());
+
+        let jail = Jail::create("test_cleanup")
+            .unwrap()
+            .with_path_within(&root, base.path())
+            .unwrap()
+            .with_cleanup_path(true);
+        jail.destroy().unwrap();
+        assert!(!root.exists());
+        assert!(base.path().exists());
+    }
+
+    #[test]
+    fn test_jail_destroy_keeps_unmanaged_path() {
+        let dir = tempfile::tempdir().unwrap();
+
+        // Paths set without a base are never deleted
+        let jail = Jail::create("test_unmanaged")
+            .unwrap()
+            .with_path(dir.path())
+            .unwrap()
+            .with_cleanup_path(true);
+        jail.destroy().unwrap();
+        assert!(dir.path().exists());
+    }
+
+    #[test]
+    fn test_cleanup_jail_root_guards() {
+        let base = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+
+        // The base itself and anything outside it
+        assert!(matches!(cleanup_jail_root(base.path(), base.path()), Err(JailError::InvalidPath(_))));
+        assert!(matches!(cleanup_jail_root(outside.path(), base.path()), Err(JailError::InvalidPath(_))));
+
+        // A symlink inside the base pointing elsewhere
+        let link = base.path().join("link");
+        std::os::unix::fs::symlink(outside.path(), &link).unwrap();
+        assert!(matches!(cleanup_jail_root(&link, base.path()), Err(JailError::InvalidPath(_))));
+        assert!(outside.path().exists());
+
+        // A plain file
+        let file = base.path().join("file");
+        fs::write(&file, "").unwrap();
+        assert!(matches!(cleanup_jail_root(&file, base.path()), Err(JailError::InvalidPath(_))));
+
+        // Missing directory
+        assert!(cleanup_jail_root(&base.path().join("missing"), base.path()).is_err());
+    }
+
+    #[test]
+    fn test_cleanup_jail_root_keeps_symlinked_contents() {
+        let base = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        fs::write(outside.path().join("keep"), "").unwrap();
+
+        let root = base.path().join("web");
+        fs::create_dir(&root).unwrap();
+        std::os::unix::fs::symlink(outside.path(), root.join("data")).unwrap();
+
+        cleanup_jail_root(&root, base.path()).unwrap();
+        assert!(!root.exists());
+        assert!(outside.path().join("keep").exists());
+    }
+
     #[test]
     fn test_jail_destroy_stopped() {
         let jail = Jail::create("test_destroy_stopped").unwrap();
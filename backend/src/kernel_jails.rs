@@ -0,0 +1,298 @@
+//! What the kernel knows about jails, in one pass
+//!
+//! [`KernelJails::snapshot`] walks every jail with `jail_get` and the
+//! `lastjid` iteration protocol: each call returns the first jail with a JID
+//! above `lastjid`, and `ENOENT` without an error message means there are no
+//! more. Startup loading and the frozen-jail checks in `start_jail` and
+//! `stop_jail_with` look names and JIDs up in a snapshot instead of issuing
+//! one syscall per jail.
+//!
+//! [`KernelView`] keeps the last snapshot for a short TTL so a burst of API
+//! calls takes one walk, not one per call. Whatever changes jails in the
+//! kernel calls [`KernelView::invalidate`]. The source is injectable, which
+//! is how the tests run off FreeBSD.
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long a snapshot answers lookups before the kernel is walked again
+pub const DEFAULT_TTL: Duration = Duration::from_secs(1);
+
+/// One jail as the kernel reports it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KernelJail {
+    pub jid: i32,
+    pub name: String,
+    pub path: String,
+    /// Whether the jail has its own network stack
+    pub vnet: bool,
+}
+
+/// Every jail in the kernel at one moment, by name
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KernelJails {
+    by_name: HashMap<String, KernelJail>,
+}
+
+impl KernelJails {
+    pub fn from_jails(jails: impl IntoIterator<Item = KernelJail>) -> Self {
+        Self { by_name: jails.into_iter().map(|jail| (jail.name.clone(), jail)).collect() }
+    }
+
+    /// Walk the kernel's jails; off FreeBSD there are none
+    pub fn snapshot() -> io::Result<Self> {
+        #[cfg(target_os = "freebsd")]
+        {
+            freebsd::snapshot()
+        }
+
+        #[cfg(not(target_os = "freebsd"))]
+        {
+            Ok(Self::default())
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&KernelJail> {
+        self.by_name.get(name)
+    }
+
+    /// JID of the jail called `name`, if the kernel has one
+    pub fn jid(&self, name: &str) -> Option<i32> {
+        self.get(name).map(|jail| jail.jid)
+    }
+
+    /// Whether a jail with this JID exists
+    pub fn has_jid(&self, jid: i32) -> bool {
+        jid >= 0 && self.by_name.values().any(|jail| jail.jid == jid)
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_name.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_name.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &KernelJail> {
+        self.by_name.values()
+    }
+}
+
+/// Where a [`KernelView`] gets its snapshots
+pub trait SnapshotSource: Send + Sync {
+    fn snapshot(&self) -> io::Result<KernelJails>;
+}
+
+impl<F> SnapshotSource for F
+where
+    F: Fn() -> io::Result<KernelJails> + Send + Sync,
+{
+    fn snapshot(&self) -> io::Result<KernelJails> {
+        self()
+    }
+}
+
+/// A snapshot cached for a short TTL
+pub struct KernelView {
+    source: Box<dyn SnapshotSource>,
+    ttl: Duration,
+    cached: Mutex<Option<(Instant, Arc<KernelJails>)>>,
+}
+
+impl KernelView {
+    pub fn new(source: impl SnapshotSource + 'static, ttl: Duration) -> Self {
+        Self { source: Box::new(source), ttl, cached: Mutex::new(None) }
+    }
+
+    /// The cached snapshot, or a fresh one if it is older than the TTL
+    ///
+    /// A failed walk isn't cached; the next call tries again.
+    pub fn current(&self) -> io::Result<Arc<KernelJails>> {
+        let mut cached = self.cached.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((taken, jails)) = cached.as_ref()
+            && taken.elapsed() < self.ttl
+        {
+            return Ok(Arc::clone(jails));
+        }
+
+        let jails = Arc::new(self.source.snapshot()?);
+        *cached = Some((Instant::now(), Arc::clone(&jails)));
+        Ok(jails)
+    }
+
+    /// Drop the cached snapshot after jails were created or removed
+    pub fn invalidate(&self) {
+        *self.cached.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    }
+}
+
+impl Default for KernelView {
+    fn default() -> Self {
+        Self::new(KernelJails::snapshot, DEFAULT_TTL)
+    }
+}
+
+impl std::fmt::Debug for KernelView {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KernelView").field("ttl", &self.ttl).finish_non_exhaustive()
+    }
+}
+
+#[cfg(target_os = "freebsd")]
+mod freebsd {
+    use super::*;
+    use std::ffi::CStr;
+
+    /// `JAIL_ERRMSGLEN` from sys/jail.h
+    const ERRMSG_LEN: usize = 1024;
+
+    pub fn snapshot() -> io::Result<KernelJails> {
+        let mut jails = Vec::new();
+        let mut lastjid = 0;
+        while let Some(jail) = next_jail(lastjid)? {
+            lastjid = jail.jid;
+            jails.push(jail);
+        }
+        Ok(KernelJails::from_jails(jails))
+    }
+
+    /// The first jail with a JID above `lastjid`, `None` past the last one
+    fn next_jail(lastjid: libc::c_int) -> io::Result<Option<KernelJail>> {
+        let mut lastjid = lastjid;
+        let mut name = [0u8; libc::MAXHOSTNAMELEN as usize];
+        let mut path = [0u8; libc::PATH_MAX as usize];
+        let mut vnet: libc::c_int = 0;
+        let mut errmsg = [0u8; ERRMSG_LEN];
+
+        fn iov(base: *mut libc::c_void, len: usize) -> libc::iovec {
+            libc::iovec { iov_base: base, iov_len: len }
+        }
+        fn key(name: &CStr) -> libc::iovec {
+            iov(name.as_ptr() as *mut libc::c_void, name.to_bytes_with_nul().len())
+        }
+
+        let mut iovs = [
+            key(c"lastjid"),
+            iov(&mut lastjid as *mut libc::c_int as *mut libc::c_void, std::mem::size_of::<libc::c_int>()),
+            key(c"name"),
+            iov(name.as_mut_ptr() as *mut libc::c_void, name.len()),
+            key(c"path"),
+            iov(path.as_mut_ptr() as *mut libc::c_void, path.len()),
+            key(c"vnet"),
+            iov(&mut vnet as *mut libc::c_int as *mut libc::c_void, std::mem::size_of::<libc::c_int>()),
+            key(c"errmsg"),
+            iov(errmsg.as_mut_ptr() as *mut libc::c_void, errmsg.len()),
+        ];
+
+        let jid = unsafe { libc::jail_get(iovs.as_mut_ptr(), iovs.len() as libc::c_uint, 0) };
+        if jid < 0 {
+            let err = io::Error::last_os_error();
+            let message = c_string(&errmsg);
+            // An unknown parameter is ENOENT too, but comes with a message
+            if err.raw_os_error() == Some(libc::ENOENT) && message.is_empty() {
+                return Ok(None);
+            }
+            return Err(if message.is_empty() { err } else { io::Error::other(format!("jail_get: {}", message)) });
+        }
+
+        Ok(Some(KernelJail { jid, name: c_string(&name), path: c_string(&path), vnet: vnet == libc::JAIL_SYS_NEW }))
+    }
+
+    fn c_string(buf: &[u8]) -> String {
+        CStr::from_bytes_until_nul(buf).map(|s| s.to_string_lossy().into_owned()).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn jail(name: &str, jid: i32) -> KernelJail {
+        KernelJail { jid, name: name.to_string(), path: format!("/jails/{}", name), vnet: false }
+    }
+
+    /// A source that counts its walks and reports whatever `jails` holds
+    fn counting(jails: Arc<Mutex<Vec<KernelJail>>>) -> (Arc<AtomicUsize>, impl SnapshotSource) {
+        let walks = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&walks);
+        let source = move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok(KernelJails::from_jails(jails.lock().unwrap().clone()))
+        };
+        (walks, source)
+    }
+
+    #[test]
+    fn test_lookups() {
+        let jails = KernelJails::from_jails([jail("web", 3), KernelJail { vnet: true, ..jail("db", 7) }]);
+        assert_eq!(jails.len(), 2);
+        assert_eq!(jails.jid("web"), Some(3));
+        assert_eq!(jails.jid("cache"), None);
+        assert!(jails.get("db").unwrap().vnet);
+        assert_eq!(jails.get("db").unwrap().path, "/jails/db");
+        assert!(jails.has_jid(7) && !jails.has_jid(4));
+        // -1 is "no JID", never a kernel jail
+        assert!(!jails.has_jid(-1));
+        assert!(KernelJails::default().is_empty());
+    }
+
+    #[test]
+    #[cfg(not(target_os = "freebsd"))]
+    fn test_snapshot_off_freebsd_is_empty() {
+        assert!(KernelJails::snapshot().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_view_caches_for_ttl() {
+        let jails = Arc::new(Mutex::new(vec![jail("web", 3)]));
+        let (walks, source) = counting(Arc::clone(&jails));
+        let view = KernelView::new(source, Duration::from_secs(3600));
+
+        assert_eq!(view.current().unwrap().jid("web"), Some(3));
+        // Within the TTL the kernel isn't asked again, absence included
+        jails.lock().unwrap().push(jail("db", 4));
+        assert_eq!(view.current().unwrap().jid("db"), None);
+        assert_eq!(walks.load(Ordering::SeqCst), 1);
+
+        // Invalidating forces the next lookup to walk again
+        view.invalidate();
+        assert_eq!(view.current().unwrap().jid("db"), Some(4));
+        assert_eq!(walks.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_view_refreshes_after_ttl() {
+        let jails = Arc::new(Mutex::new(vec![jail("web", 3)]));
+        let (walks, source) = counting(Arc::clone(&jails));
+        let view = KernelView::new(source, Duration::ZERO);
+
+        assert!(view.current().unwrap().has_jid(3));
+        jails.lock().unwrap().clear();
+        assert!(!view.current().unwrap().has_jid(3));
+        assert_eq!(walks.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_view_does_not_cache_failures() {
+        let fail = Arc::new(std::sync::atomic::AtomicBool::new(true));
+        let failing = Arc::clone(&fail);
+        let view = KernelView::new(
+            move || {
+                if failing.load(Ordering::SeqCst) {
+                    Err(io::Error::other("jail_get: unknown parameter: vnet"))
+                } else {
+                    Ok(KernelJails::from_jails([jail("web", 3)]))
+                }
+            },
+            Duration::from_secs(3600),
+        );
+
+        assert!(view.current().is_err());
+        fail.store(false, Ordering::SeqCst);
+        assert_eq!(view.current().unwrap().jid("web"), Some(3));
+    }
+}
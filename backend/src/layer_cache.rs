@@ -0,0 +1,281 @@
+//! Per-instruction snapshots that let a failed build resume
+//!
+//! After every step that succeeds, the build dataset gets a snapshot
+//! `@layer-<n>-<key>`, `n` being the number of steps done. The key chains a
+//! hash of the base, each instruction so far and the files its COPY/ADD
+//! read, so a snapshot stands for exactly the root those steps produce.
+//!
+//! A failed build leaves its dataset `images/build-<name>` and the layer
+//! snapshots behind. The next build of that name rolls the dataset back to
+//! the deepest snapshot whose key still matches and runs only the steps
+//! after it; with none matching, it starts over on a fresh dataset. Layer
+//! snapshots are destroyed once a build succeeds, so they never become
+//! snapshots of the image.
+//!
+//! Dataset operations go through [`LayerStore`], which is how the tests run
+//! without ZFS.
+
+use crate::image::DockerfileInstruction;
+use crate::image_builder::{ImageError, Result};
+use crate::zfs::Zfs;
+use std::path::Path;
+
+/// Prefix of every layer snapshot name
+pub const SNAPSHOT_PREFIX: &str = "layer-";
+
+/// Hex digits of a layer key that go into its snapshot name
+const KEY_LEN: usize = 16;
+
+/// The snapshot operations layer caching needs on a build dataset
+pub trait LayerStore {
+    /// Snapshot names of `dataset` (without `dataset@`), oldest first
+    fn snapshots(&self, dataset: &str) -> std::result::Result<Vec<String>, String>;
+    fn snapshot(&self, dataset: &str, name: &str) -> std::result::Result<(), String>;
+    /// Roll `dataset` back to `name`, destroying later snapshots
+    fn rollback(&self, dataset: &str, name: &str) -> std::result::Result<(), String>;
+    fn destroy_snapshot(&self, dataset: &str, name: &str) -> std::result::Result<(), String>;
+}
+
+impl LayerStore for Zfs {
+    fn snapshots(&self, dataset: &str) -> std::result::Result<Vec<String>, String> {
+        self.list_snapshots(dataset).map_err(|e| e.to_string())
+    }
+
+    fn snapshot(&self, dataset: &str, name: &str) -> std::result::Result<(), String> {
+        self.create_snapshot(dataset, name).map_err(|e| e.to_string())
+    }
+
+    fn rollback(&self, dataset: &str, name: &str) -> std::result::Result<(), String> {
+        Zfs::rollback(self, dataset, name).map_err(|e| e.to_string())
+    }
+
+    fn destroy_snapshot(&self, dataset: &str, name: &str) -> std::result::Result<(), String> {
+        self.destroy(&format!("{}@{}", dataset, name)).map_err(|e| e.to_string())
+    }
+}
+
+/// Layer keys of one build, one per instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layers {
+    keys: Vec<String>,
+}
+
+impl Layers {
+    /// Chain the keys of `instructions` built on `base` (the parent image's
+    /// snapshot, the base system snapshot, or `scratch`), hashing COPY/ADD
+    /// sources under `context`
+    pub fn new(instructions: &[DockerfileInstruction], base: &str, context: &Path) -> Result<Self> {
+        use sha2::{Digest, Sha256};
+
+        let mut previous = format!("base {}", base);
+        let mut keys = Vec::with_capacity(instructions.len());
+        for instruction in instructions {
+            let mut hasher = Sha256::new();
+            hasher.update(b"kawakaze-layer-v1\0");
+            hasher.update(previous.as_bytes());
+            hasher.update(b"\0");
+            // Through a Value so that ENV/LABEL maps serialize with sorted keys
+            let value = serde_json::to_value(instruction)
+                .map_err(|e| ImageError::ParseError(format!("Failed to serialize instruction: {}", e)))?;
+            hasher.update(value.to_string().as_bytes());
+            hasher.update(b"\0");
+            match instruction {
+                DockerfileInstruction::Copy { src, .. } => crate::image_builder::hash_tree(&mut hasher, &context.join(src), Path::new(""))?,
+                DockerfileInstruction::Add { src, .. } if !src.starts_with("http://") && !src.starts_with("https://") => {
+                    crate::image_builder::hash_tree(&mut hasher, &context.join(src), Path::new(""))?
+                }
+                _ => {}
+            }
+            previous = hex::encode(hasher.finalize());
+            keys.push(previous.clone());
+        }
+        Ok(Self { keys })
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Snapshot name for the root after the first `done` steps (`done` >= 1)
+    pub fn snapshot_name(&self, done: usize) -> String {
+        format!("{}{}-{}", SNAPSHOT_PREFIX, done, &self.keys[done - 1][..KEY_LEN])
+    }
+
+    /// How many steps `existing` snapshots let a build skip: the deepest
+    /// layer whose name matches this build's key
+    pub fn resume_point(&self, existing: &[String]) -> usize {
+        (1..=self.keys.len())
+            .rev()
+            .find(|&done| existing.contains(&self.snapshot_name(done)))
+            .unwrap_or(0)
+    }
+
+    /// Roll `dataset` back to its deepest matching layer and return how
+    /// many steps that covers; 0 leaves the dataset alone
+    pub fn resume(&self, store: &dyn LayerStore, dataset: &str) -> Result<usize> {
+        let existing = store.snapshots(dataset).map_err(ImageError::Zfs)?;
+        let done = self.resume_point(&existing);
+        if done > 0 {
+            store.rollback(dataset, &self.snapshot_name(done)).map_err(ImageError::Zfs)?;
+        }
+        Ok(done)
+    }
+
+    /// Snapshot `dataset` after the first `done` steps succeeded
+    pub fn commit(&self, store: &dyn LayerStore, dataset: &str, done: usize) -> Result<()> {
+        store.snapshot(dataset, &self.snapshot_name(done)).map_err(ImageError::Zfs)
+    }
+}
+
+/// Destroy every layer snapshot of `dataset`, returning how many there were
+pub fn clear(store: &dyn LayerStore, dataset: &str) -> Result<usize> {
+    let layers: Vec<String> = store
+        .snapshots(dataset)
+        .map_err(ImageError::Zfs)?
+        .into_iter()
+        .filter(|name| name.starts_with(SNAPSHOT_PREFIX))
+        .collect();
+    for name in &layers {
+        store.destroy_snapshot(dataset, name).map_err(ImageError::Zfs)?;
+    }
+    Ok(layers.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image_builder::parse_dockerfile;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::sync::Mutex;
+
+    const DATASET: &str = "tank/images/build-web";
+
+    /// A dataset whose contents are the steps applied to it, with
+    /// snapshots of those contents
+    #[derive(Default)]
+    struct FakeStore {
+        root: Mutex<Vec<usize>>,
+        snapshots: Mutex<Vec<(String, Vec<usize>)>>,
+    }
+
+    impl LayerStore for FakeStore {
+        fn snapshots(&self, _dataset: &str) -> std::result::Result<Vec<String>, String> {
+            Ok(self.snapshots.lock().unwrap().iter().map(|(name, _)| name.clone()).collect())
+        }
+
+        fn snapshot(&self, _dataset: &str, name: &str) -> std::result::Result<(), String> {
+            let root = self.root.lock().unwrap().clone();
+            self.snapshots.lock().unwrap().push((name.to_string(), root));
+            Ok(())
+        }
+
+        fn rollback(&self, _dataset: &str, name: &str) -> std::result::Result<(), String> {
+            let mut snapshots = self.snapshots.lock().unwrap();
+            let index = snapshots.iter().position(|(n, _)| n == name).ok_or("no such snapshot")?;
+            snapshots.truncate(index + 1);
+            *self.root.lock().unwrap() = snapshots[index].1.clone();
+            Ok(())
+        }
+
+        fn destroy_snapshot(&self, _dataset: &str, name: &str) -> std::result::Result<(), String> {
+            self.snapshots.lock().unwrap().retain(|(n, _)| n != name);
+            Ok(())
+        }
+    }
+
+    fn layers(dockerfile: &str, context: &Path) -> Layers {
+        let instructions = parse_dockerfile(dockerfile, &HashMap::new(), &HashMap::new(), false).unwrap();
+        Layers::new(&instructions, "tank/base/14.1-RELEASE-amd64@base", context).unwrap()
+    }
+
+    /// The build loop of `ImageBuilder::build`, with steps that record
+    /// themselves in the root; fails at step `fail_at`
+    fn build(store: &FakeStore, layers: &Layers, fail_at: Option<usize>) -> (std::result::Result<(), usize>, Vec<usize>) {
+        let mut executed = Vec::new();
+        let start = layers.resume(store, DATASET).unwrap();
+        for step in start..layers.len() {
+            executed.push(step);
+            if fail_at == Some(step) {
+                return (Err(step), executed);
+            }
+            store.root.lock().unwrap().push(step);
+            layers.commit(store, DATASET, step + 1).unwrap();
+        }
+        (Ok(()), executed)
+    }
+
+    const DOCKERFILE: &str = "FROM freebsd:14.1\nRUN pkg install -y nginx\nENV PORT=80\nCOPY htdocs /usr/local/www\nRUN make\nCMD [\"nginx\"]";
+
+    #[test]
+    fn test_failed_build_resumes_from_last_layer() {
+        let context = tempfile::tempdir().unwrap();
+        fs::create_dir(context.path().join("htdocs")).unwrap();
+        fs::write(context.path().join("htdocs/index.html"), "hello").unwrap();
+        let layers = layers(DOCKERFILE, context.path());
+        let store = FakeStore::default();
+
+        // The first attempt fails at RUN make
+        let (result, executed) = build(&store, &layers, Some(4));
+        assert_eq!(result, Err(4));
+        assert_eq!(executed, [0, 1, 2, 3, 4]);
+        assert_eq!(store.snapshots(DATASET).unwrap().len(), 4);
+
+        // The retry reuses the first four steps and starts at the failed one
+        let (result, executed) = build(&store, &layers, None);
+        assert_eq!(result, Ok(()));
+        assert_eq!(executed, [4, 5]);
+        assert_eq!(*store.root.lock().unwrap(), [0, 1, 2, 3, 4, 5]);
+
+        // Success clears the layers; other snapshots stay
+        store.snapshot(DATASET, "web-final").unwrap();
+        assert_eq!(clear(&store, DATASET).unwrap(), 6);
+        assert_eq!(store.snapshots(DATASET).unwrap(), ["web-final"]);
+    }
+
+    #[test]
+    fn test_changes_invalidate_later_layers() {
+        let context = tempfile::tempdir().unwrap();
+        fs::create_dir(context.path().join("htdocs")).unwrap();
+        fs::write(context.path().join("htdocs/index.html"), "hello").unwrap();
+        let store = FakeStore::default();
+        let (result, _) = build(&store, &layers(DOCKERFILE, context.path()), Some(5));
+        assert_eq!(result, Err(5));
+
+        // A changed file only invalidates from its COPY on, and the
+        // rollback drops the stale layers after it
+        fs::write(context.path().join("htdocs/index.html"), "hello, world").unwrap();
+        let (result, executed) = build(&store, &layers(DOCKERFILE, context.path()), Some(4));
+        assert_eq!((result, executed), (Err(4), vec![3, 4]));
+        assert_eq!(*store.root.lock().unwrap(), [0, 1, 2, 3]);
+
+        // So does a changed instruction
+        let changed = DOCKERFILE.replace("ENV PORT=80", "ENV PORT=8080");
+        let (result, executed) = build(&store, &layers(&changed, context.path()), None);
+        assert_eq!((result, executed), (Ok(()), vec![2, 3, 4, 5]));
+        assert_eq!(*store.root.lock().unwrap(), [0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_keys() {
+        let context = tempfile::tempdir().unwrap();
+        let a = layers(DOCKERFILE, context.path());
+        assert_eq!(a.len(), 6);
+        assert_eq!(a, layers(DOCKERFILE, context.path()));
+        assert!(a.snapshot_name(1).starts_with("layer-1-"));
+        assert_eq!(a.snapshot_name(6).len(), "layer-6-".len() + KEY_LEN);
+
+        // Another base shares no layer
+        let instructions = parse_dockerfile(DOCKERFILE, &HashMap::new(), &HashMap::new(), false).unwrap();
+        let other = Layers::new(&instructions, "scratch", context.path()).unwrap();
+        let names: Vec<String> = (1..=6).map(|done| a.snapshot_name(done)).collect();
+        assert_eq!(a.resume_point(&names), 6);
+        assert_eq!(other.resume_point(&names), 0);
+        assert_eq!(a.resume_point(&names[..2]), 2);
+        assert_eq!(a.resume_point(&[]), 0);
+    }
+}
@@ -5,6 +5,7 @@
 
 pub mod jail;
 pub mod api;
+pub mod api_schema;
 pub mod handler;
 pub mod server;
 pub mod store;
@@ -15,24 +16,144 @@ pub mod image;
 pub mod container;
 pub mod image_builder;
 pub mod networking;
-
-use crate::jail::{Jail, JailError, JailState};
+pub mod devfs;
+pub mod orphans;
+pub mod logs;
+pub mod syslog;
+pub mod upgrade;
+pub mod cmdtrace;
+pub mod system;
+pub mod doctor;
+pub mod boot;
+pub mod vars;
+pub mod top;
+pub mod stream;
+pub mod build_jail;
+pub mod names;
+pub mod build_log;
+pub mod paths;
+pub mod mount_policy;
+pub mod read_only;
+pub mod artifact;
+pub mod task_queue;
+pub mod hosts;
+pub mod version;
+pub mod operation;
+pub mod timestamp;
+pub mod kernel_jails;
+pub mod ownership;
+pub mod build_dirs;
+pub mod persist_queue;
+pub mod layer_cache;
+pub mod dataset_health;
+pub mod log_level;
+pub mod upload;
+pub mod http_gateway;
+pub mod dataset_attach;
+pub mod pool_health;
+pub mod error_codes;
+pub mod exec_sessions;
+pub mod placement;
+pub mod snapshot_gc;
+pub mod sysctl;
+pub mod container_lock;
+pub mod filter;
+
+use crate::jail::{Jail, JailError, JailState, StopMode};
 use crate::store::{JailStore, StoreError};
-use crate::bootstrap::{BootstrapProgress, BootstrapStatus};
+use crate::persist_queue::{Resource, RetryQueue, Write, WriteTarget};
+use crate::bootstrap::{BaseBootstrapper, BootstrapProgress, BootstrapStatus, MirrorBootstrapper};
 use crate::image::{Image, ImageId};
-use crate::container::{Container, ContainerId};
-use crate::zfs::Zfs;
-use crate::config::KawakazeConfig;
-use crate::image_builder::ImageBuildProgress;
+use crate::orphans::{Orphan, OrphanError, OrphanKind};
+use crate::container::{Container, ContainerId, VolumeMode};
+use crate::zfs::{DatasetInfo, PoolStatus, Zfs};
+use crate::config::{ConfigSource, KawakazeConfig};
+use crate::image_builder::{BuildStatus, ImageBuildProgress};
 use crate::networking::NetworkManager;
-use std::collections::HashMap;
+use crate::task_queue::{QueueError, TaskQueue, Ticket};
+use crate::timestamp::Timestamp;
+use crate::upgrade::{UpgradeError, UpgradeOutcome, UpgradePlan, UpgradeProgress, UpgradeStatus, UpgradeTarget};
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
-use tokio::sync::mpsc;
+use std::sync::Arc;
+use tokio::sync::{mpsc, watch};
 use tracing::{debug, error, info, warn};
 
 /// Type for bootstrap progress sender
 pub type BootstrapProgressSender = mpsc::Sender<BootstrapProgress>;
 
+/// Options controlling container and image removal
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RemovalOptions {
+    /// Remove even if the resource is in use
+    pub force: bool,
+    /// Remove even if the resource is protected (only honoured with `force`)
+    pub override_protection: bool,
+}
+
+impl RemovalOptions {
+    /// Refuse removal of a protected resource unless protection is overridden
+    fn check_protection(&self, kind: &str, id: &str, protected: bool) -> Result<(), StoreError> {
+        if !protected {
+            return Ok(());
+        }
+
+        if !(self.override_protection && self.force) {
+            return Err(StoreError::ResourceProtected(format!(
+                "{} {} is protected; use force with override_protection to remove it",
+                kind, id
+            )));
+        }
+
+        warn!(target: "kawakaze::audit", "Removing protected {} {} (protection overridden)", kind, id);
+        Ok(())
+    }
+}
+
+/// A build (by build ID), bootstrap (by jail name) or container operation
+/// (by operation ID) whose progress is tracked
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ProgressKey {
+    Build(ImageId),
+    Bootstrap(String),
+    Operation(String),
+}
+
+/// A container checked and given an ID, whose datasets are yet to be made
+/// (see [`JailManager::plan_container`])
+#[derive(Debug, Clone)]
+pub struct PendingContainer {
+    pub id: ContainerId,
+    pub jail_name: String,
+    pub dataset: String,
+    /// Where the dataset is mounted
+    pub mountpoint: PathBuf,
+    /// Image snapshot it is cloned from
+    pub snapshot: String,
+    /// Options of an encrypted dataset
+    pub encryption: Option<crate::zfs::DatasetOptions>,
+    /// Datasets for the image's VOLUMEs the request didn't mount
+    pub anonymous: Vec<crate::container::Mount>,
+    /// Port mappings with their host ports assigned
+    pub ports: Vec<crate::container::PortMapping>,
+    pub devfs_ruleset: u16,
+    pub config: crate::container::ContainerConfig,
+}
+
+/// A container taken out of the manager whose datasets are yet to be
+/// destroyed (see [`JailManager::begin_container_removal`])
+#[derive(Debug)]
+pub struct ContainerTeardown {
+    pub id: ContainerId,
+    pub dataset: String,
+    /// The dataset was already destroyed outside kawakaze
+    pub dataset_missing: bool,
+    /// Anonymous volume datasets, destroyed with the container
+    pub volumes: Vec<String>,
+    /// Name resolution before the container left, to republish after
+    hosts_before: crate::hosts::Registry,
+}
+
 /// Jail manager - handles jail lifecycle
 pub struct JailManager {
     pub(crate) socket_path: PathBuf,
@@ -43,6 +164,10 @@ pub struct JailManager {
     pub bootstrap_tracker: HashMap<String, BootstrapProgressSender>,
     /// Bootstrap progress state (jail name -> latest progress)
     pub bootstrap_progress: HashMap<String, BootstrapProgress>,
+    /// Slots for running image builds (see `task_queue`)
+    pub(crate) build_queue: Arc<TaskQueue>,
+    /// Slots for running jail bootstraps
+    pub(crate) bootstrap_queue: Arc<TaskQueue>,
     /// Image storage (image ID -> Image)
     pub(crate) images: HashMap<ImageId, Image>,
     /// Container storage (container ID -> Container)
@@ -51,6 +176,10 @@ pub struct JailManager {
     pub(crate) zfs: Option<Zfs>,
     /// Configuration
     pub(crate) config: KawakazeConfig,
+    /// Where `config` was loaded from
+    pub(crate) config_source: ConfigSource,
+    /// The daemon's log filter (see `log_level`)
+    log_level: crate::log_level::LogLevel,
     /// Image build progress trackers (image ID -> progress sender)
     pub image_build_tracker: HashMap<ImageId, mpsc::Sender<ImageBuildProgress>>,
     /// Image build progress state (image ID -> latest progress)
@@ -59,6 +188,48 @@ pub struct JailManager {
     pub(crate) network_manager: Option<NetworkManager>,
     /// Network configurations for containers (container ID -> network config)
     pub(crate) container_networks: HashMap<ContainerId, crate::networking::ContainerNetwork>,
+    /// Cached base system snapshots ("<version>-<arch>" -> snapshot name)
+    pub(crate) base_images: HashMap<String, String>,
+    /// In-flight image builds (image name -> completion signal). Dropping the
+    /// sender wakes builds queued behind it.
+    pub(crate) active_builds: HashMap<String, watch::Sender<()>>,
+    /// Upgrade progress, kept after the upgrade finishes so it can be polled
+    pub(crate) upgrades: HashMap<UpgradeTarget, UpgradeProgress>,
+    /// When finished builds and bootstraps finished; their progress is
+    /// dropped `progress_retention_secs` later
+    pub(crate) finished_progress: HashMap<ProgressKey, std::time::Instant>,
+    /// When the manager was created, for the daemon's uptime
+    pub(crate) started_at: std::time::Instant,
+    /// Outcome of the `--boot` start, if the daemon was launched with it
+    pub(crate) boot_report: Option<crate::boot::BootReport>,
+    /// Host ports published by running containers, persisted in the store
+    pub(crate) published_ports: crate::container::PublishedPorts,
+    /// Take over records a newer kawakaze wrote instead of loading them
+    /// read-only (`--force-downgrade-writes`)
+    force_downgrade_writes: bool,
+    /// Containers being created (ID -> host ports they will publish)
+    pub(crate) creating: HashMap<ContainerId, Vec<crate::container::PortMapping>>,
+    /// Async container creates and removes, kept after they finish so they
+    /// can be polled
+    pub(crate) operations: HashMap<String, crate::operation::OperationProgress>,
+    /// Jails in the kernel, snapshotted for a short TTL
+    kernel: crate::kernel_jails::KernelView,
+    /// Whether container datasets still exist, cached for a short TTL
+    pub(crate) datasets: crate::dataset_health::DatasetView,
+    /// Health of the pool, cached for a short TTL (see `pool_health`)
+    pub(crate) pool_health: Arc<crate::pool_health::PoolHealth>,
+    /// Store writes that failed and are retried (see `persist_queue`)
+    pending_writes: RetryQueue,
+    /// What the daemon can't do when it runs inside a jail
+    confinement: crate::system::Confinement,
+    /// Chunked uploads being received or waiting for their consumer
+    pub(crate) uploads: crate::upload::Uploads,
+    /// Commands running in containers through `exec` (see `exec_sessions`)
+    pub(crate) exec_sessions: Arc<crate::exec_sessions::ExecSessions>,
+    /// Serialize start, stop and remove per container (see `container_lock`)
+    pub(crate) container_locks: crate::container_lock::ContainerLocks,
+    /// This node's labels, read from the host on first use (see `placement`)
+    pub(crate) node_labels: std::sync::OnceLock<std::collections::BTreeMap<String, String>>,
 }
 
 impl JailManager {
@@ -71,20 +242,43 @@ impl JailManager {
             store: None,
             bootstrap_tracker: HashMap::new(),
             bootstrap_progress: HashMap::new(),
+            build_queue: TaskQueue::new(KawakazeConfig::default().max_concurrent_builds),
+            bootstrap_queue: TaskQueue::new(KawakazeConfig::default().max_concurrent_bootstraps),
             images: HashMap::new(),
             containers: HashMap::new(),
             zfs: None,
             config: KawakazeConfig::default(),
+            config_source: ConfigSource::default(),
+            log_level: crate::log_level::LogLevel::default(),
             image_build_tracker: HashMap::new(),
             image_build_progress: HashMap::new(),
             network_manager: None,
             container_networks: HashMap::new(),
+            base_images: HashMap::new(),
+            active_builds: HashMap::new(),
+            upgrades: HashMap::new(),
+            finished_progress: HashMap::new(),
+            started_at: std::time::Instant::now(),
+            boot_report: None,
+            published_ports: crate::container::PublishedPorts::new(),
+            force_downgrade_writes: false,
+            creating: HashMap::new(),
+            operations: HashMap::new(),
+            kernel: crate::kernel_jails::KernelView::default(),
+            datasets: crate::dataset_health::DatasetView::default(),
+            pool_health: Arc::default(),
+            exec_sessions: Arc::default(),
+            container_locks: Default::default(),
+            node_labels: std::sync::OnceLock::new(),
+            pending_writes: RetryQueue::default(),
+            confinement: crate::system::Confinement::default(),
+            uploads: crate::upload::Uploads::from_config(&KawakazeConfig::default().uploads),
         }
     }
 
     /// Create a jail manager with default socket path
     pub fn with_default_socket() -> Self {
-        Self::new("/var/run/kawakaze.sock")
+        Self::new(crate::paths::Paths::default().socket)
     }
 
     /// Create a jail manager with database persistence
@@ -92,26 +286,49 @@ impl JailManager {
         let store = JailStore::new(db_path)?;
 
         Ok(Self {
-            socket_path: PathBuf::from("/var/run/kawakaze.sock"),
+            socket_path: crate::paths::Paths::default().socket,
             jails: HashMap::new(),
             running: false,
             store: Some(store),
             bootstrap_tracker: HashMap::new(),
             bootstrap_progress: HashMap::new(),
+            build_queue: TaskQueue::new(KawakazeConfig::default().max_concurrent_builds),
+            bootstrap_queue: TaskQueue::new(KawakazeConfig::default().max_concurrent_bootstraps),
             images: HashMap::new(),
             containers: HashMap::new(),
             zfs: None,
             config: KawakazeConfig::default(),
+            config_source: ConfigSource::default(),
+            log_level: crate::log_level::LogLevel::default(),
             image_build_tracker: HashMap::new(),
             image_build_progress: HashMap::new(),
             network_manager: None,
             container_networks: HashMap::new(),
+            base_images: HashMap::new(),
+            active_builds: HashMap::new(),
+            upgrades: HashMap::new(),
+            finished_progress: HashMap::new(),
+            started_at: std::time::Instant::now(),
+            boot_report: None,
+            published_ports: crate::container::PublishedPorts::new(),
+            force_downgrade_writes: false,
+            creating: HashMap::new(),
+            operations: HashMap::new(),
+            kernel: crate::kernel_jails::KernelView::default(),
+            datasets: crate::dataset_health::DatasetView::default(),
+            pool_health: Arc::default(),
+            exec_sessions: Arc::default(),
+            container_locks: Default::default(),
+            node_labels: std::sync::OnceLock::new(),
+            pending_writes: RetryQueue::default(),
+            confinement: crate::system::Confinement::default(),
+            uploads: crate::upload::Uploads::from_config(&KawakazeConfig::default().uploads),
         })
     }
 
     /// Create a jail manager with database persistence at default location
     pub fn with_default_database() -> Result<Self, StoreError> {
-        Self::with_database("/var/db/kawakaze.db")
+        Self::with_database(crate::paths::Paths::default().database)
     }
 
     /// Create a jail manager with custom socket and database paths
@@ -125,14 +342,37 @@ impl JailManager {
             store: Some(store),
             bootstrap_tracker: HashMap::new(),
             bootstrap_progress: HashMap::new(),
+            build_queue: TaskQueue::new(KawakazeConfig::default().max_concurrent_builds),
+            bootstrap_queue: TaskQueue::new(KawakazeConfig::default().max_concurrent_bootstraps),
             images: HashMap::new(),
             containers: HashMap::new(),
             zfs: None,
             config: KawakazeConfig::default(),
+            config_source: ConfigSource::default(),
+            log_level: crate::log_level::LogLevel::default(),
             image_build_tracker: HashMap::new(),
             image_build_progress: HashMap::new(),
             network_manager: None,
             container_networks: HashMap::new(),
+            base_images: HashMap::new(),
+            active_builds: HashMap::new(),
+            upgrades: HashMap::new(),
+            finished_progress: HashMap::new(),
+            started_at: std::time::Instant::now(),
+            boot_report: None,
+            published_ports: crate::container::PublishedPorts::new(),
+            force_downgrade_writes: false,
+            creating: HashMap::new(),
+            operations: HashMap::new(),
+            kernel: crate::kernel_jails::KernelView::default(),
+            datasets: crate::dataset_health::DatasetView::default(),
+            pool_health: Arc::default(),
+            exec_sessions: Arc::default(),
+            container_locks: Default::default(),
+            node_labels: std::sync::OnceLock::new(),
+            pending_writes: RetryQueue::default(),
+            confinement: crate::system::Confinement::default(),
+            uploads: crate::upload::Uploads::from_config(&KawakazeConfig::default().uploads),
         })
     }
 
@@ -142,21 +382,45 @@ impl JailManager {
 
         // Ensure required ZFS datasets exist
         if let Some(ref zfs) = zfs {
-            // Create containers dataset if it doesn't exist (ignoring errors if it already exists)
-            let containers_dataset = format!("{}/containers", config.zfs_pool);
-            if !zfs.dataset_exists(&containers_dataset) {
-                let _ = zfs.create_dataset(&containers_dataset);
+            for root in ["containers", "images"] {
+                let dataset = format!("{}/{}", config.zfs_pool, root);
+                if let Err(e) = zfs.ensure_dataset(&dataset, &[]) {
+                    warn!("Failed to create dataset {}: {}", dataset, e);
+                }
             }
         }
 
+        crate::cmdtrace::set_slow_threshold(std::time::Duration::from_secs(config.diagnostics.slow_command_secs));
+
+        let paths = crate::paths::Paths::from_config(&config);
+        let uploads = crate::upload::Uploads::from_config(&config.uploads);
+
         // Initialize database with new tables
-        let store = JailStore::new(&config.storage.database_path)?;
+        let store = JailStore::new(&paths.database)?;
+
+        let confinement = crate::system::Confinement::detect(&crate::system::CommandProbe);
+        let datasets = match &zfs {
+            Some(zfs) => crate::dataset_health::DatasetView::new(zfs.clone(), crate::dataset_health::DEFAULT_TTL),
+            None => crate::dataset_health::DatasetView::default(),
+        };
+        let pool_health = Arc::new(match &zfs {
+            Some(zfs) => crate::pool_health::PoolHealth::new(zfs.clone(), crate::pool_health::DEFAULT_TTL),
+            None => crate::pool_health::PoolHealth::default(),
+        });
 
         // Create and initialize network manager
-        let mut network_manager = NetworkManager::new();
+        let mut network_manager = NetworkManager::with_state_file(paths.ip_allocations());
+        if let Some(subnet6) = config.network.container_cidr6.as_deref().and_then(|cidr| crate::networking::Subnet6::parse(cidr).ok()) {
+            network_manager = network_manager.with_ipv6(subnet6, paths.ip6_allocations());
+        }
+        if !config.network.ipv4_enabled {
+            network_manager = network_manager.without_ipv4();
+        }
         #[cfg(target_os = "freebsd")]
         {
-            if let Err(e) = network_manager.initialize() {
+            if !confinement.vnet_available() {
+                warn!("The daemon's jail doesn't allow VNET; containers will have no networking");
+            } else if let Err(e) = network_manager.initialize() {
                 warn!("Failed to initialize network manager: {}. Container networking will not be available.", e);
                 // Continue without networking - container creation will work but without network
             } else {
@@ -165,20 +429,43 @@ impl JailManager {
         }
 
         Ok(Self {
-            socket_path: PathBuf::from(&config.storage.socket_path),
+            socket_path: paths.socket,
             jails: HashMap::new(),
             running: false,
             store: Some(store),
             bootstrap_tracker: HashMap::new(),
             bootstrap_progress: HashMap::new(),
+            build_queue: TaskQueue::new(config.max_concurrent_builds),
+            bootstrap_queue: TaskQueue::new(config.max_concurrent_bootstraps),
             images: HashMap::new(),
             containers: HashMap::new(),
             zfs,
             config,
+            config_source: ConfigSource::default(),
+            log_level: crate::log_level::LogLevel::default(),
             image_build_tracker: HashMap::new(),
             image_build_progress: HashMap::new(),
-            network_manager: Some(network_manager),
+            network_manager: confinement.vnet_available().then_some(network_manager),
             container_networks: HashMap::new(),
+            base_images: HashMap::new(),
+            active_builds: HashMap::new(),
+            upgrades: HashMap::new(),
+            finished_progress: HashMap::new(),
+            started_at: std::time::Instant::now(),
+            boot_report: None,
+            published_ports: crate::container::PublishedPorts::new(),
+            force_downgrade_writes: false,
+            creating: HashMap::new(),
+            operations: HashMap::new(),
+            kernel: crate::kernel_jails::KernelView::default(),
+            datasets,
+            pool_health,
+            exec_sessions: Arc::default(),
+            container_locks: Default::default(),
+            node_labels: std::sync::OnceLock::new(),
+            pending_writes: RetryQueue::default(),
+            confinement,
+            uploads,
         })
     }
 
@@ -188,11 +475,67 @@ impl JailManager {
             return Err("JailManager is already running".into());
         }
 
+        if self.confinement.jailed {
+            warn!("=======================================================");
+            warn!("The daemon is running inside a jail. VNET: {}, devfs mounts: {}.",
+                if self.confinement.allow_vnet { "allowed" } else { "not allowed" },
+                if self.confinement.allow_devfs { "allowed" } else { "not allowed" });
+            warn!("Operations the parent jail doesn't allow fail with 503.");
+            warn!("=======================================================");
+        }
+
+        // Jails mount /dev with these rulesets, so refuse to run without them
+        #[cfg(target_os = "freebsd")]
+        if self.config.devfs.manage_rules {
+            for (number, action) in crate::devfs::ensure_rulesets(&self.config.devfs)? {
+                debug!("devfs ruleset {}: {:?}", number, action);
+            }
+        }
+
         // Load jails from database if configured
         if let Some(ref store) = self.store.clone() {
             self.load_jails_from_db(store)?;
             self.load_images_from_db(store)?;
             self.load_containers_from_db(store)?;
+            self.load_published_ports_from_db(store)?;
+            self.take_over_newer_records(store);
+        }
+
+        // Containers whose keys can't be loaded are locked, not fatal
+        if let Some(ref zfs) = self.zfs {
+            match zfs.key_status(&self.config.zfs_pool) {
+                Ok(statuses) => {
+                    let locked = crate::zfs::load_missing_keys(&statuses, |root| {
+                        info!("Loading encryption key for {}", root);
+                        zfs.load_key(root)
+                    });
+                    self.lock_containers(&locked);
+                }
+                Err(e) => warn!("Encryption key check failed: {}", e),
+            }
+        }
+
+        // Report (never remove) datasets the database doesn't know about
+        if self.zfs.is_some() {
+            match self.find_orphans() {
+                Ok(orphans) => {
+                    for orphan in &orphans {
+                        warn!(
+                            "Orphaned {} dataset {} ({} bytes); see `kawakaze system orphans`",
+                            orphan.kind.as_str(), orphan.dataset, orphan.size_bytes
+                        );
+                    }
+                }
+                Err(e) => warn!("Orphan dataset scan failed: {}", e),
+            }
+        }
+
+        self.sweep_logs();
+        self.sweep_build_dirs();
+        self.clear_upload_spool();
+
+        if self.config.container.restart_on_boot {
+            self.restart_on_boot(&crate::boot::ManagerStarter);
         }
 
         // Mark as running
@@ -200,6 +543,72 @@ impl JailManager {
         Ok(())
     }
 
+    /// Let start take over records a newer kawakaze wrote, restamping them
+    /// with this version, instead of loading them read-only
+    pub fn set_force_downgrade_writes(&mut self, force: bool) {
+        self.force_downgrade_writes = force;
+    }
+
+    /// Report the records a newer kawakaze wrote, or with
+    /// `--force-downgrade-writes` restamp them so they can be written again
+    fn take_over_newer_records(&mut self, store: &JailStore) {
+        use crate::store::VersionedTable;
+
+        let mut newer: Vec<(VersionedTable, String, String)> = Vec::new();
+        for (name, jail) in &self.jails {
+            if let Some(version) = jail.written_by_newer() {
+                newer.push((VersionedTable::Jails, name.clone(), version.to_string()));
+            }
+        }
+        for (id, image) in &self.images {
+            if let Some(version) = &image.written_by_newer {
+                newer.push((VersionedTable::Images, id.clone(), version.clone()));
+            }
+        }
+        for (id, container) in &self.containers {
+            if let Some(version) = &container.written_by_newer {
+                newer.push((VersionedTable::Containers, id.clone(), version.clone()));
+            }
+        }
+
+        for (table, key, version) in newer {
+            if !self.force_downgrade_writes {
+                warn!(
+                    "{} {} was last written by kawakaze {} (this is {}); loaded read-only",
+                    table.kind(), key, version, crate::version::CURRENT
+                );
+                continue;
+            }
+
+            warn!(
+                target: "kawakaze::audit",
+                "Downgrading {} {} from kawakaze {} to {} (--force-downgrade-writes); fields it doesn't know are lost",
+                table.kind(), key, version, crate::version::CURRENT
+            );
+            if let Err(e) = store.restamp(table, &key) {
+                error!("Failed to restamp {} {}: {}", table.kind(), key, e);
+                continue;
+            }
+            match table {
+                VersionedTable::Jails => {
+                    if let Some(jail) = self.jails.get_mut(&key) {
+                        jail.clear_written_by_newer();
+                    }
+                }
+                VersionedTable::Images => {
+                    if let Some(image) = self.images.get_mut(&key) {
+                        image.written_by_newer = None;
+                    }
+                }
+                VersionedTable::Containers => {
+                    if let Some(container) = self.containers.get_mut(&key) {
+                        container.written_by_newer = None;
+                    }
+                }
+            }
+        }
+    }
+
     /// Load jails from database and sync JIDs with FreeBSD kernel
     fn load_jails_from_db(&mut self, store: &JailStore) -> Result<(), Box<dyn std::error::Error>> {
         info!("Loading jails from database: {:?}", store.db_path());
@@ -207,28 +616,27 @@ impl JailManager {
         let jail_rows = store.get_all_jails()?;
         let mut loaded_count = 0;
 
+        // One walk of the kernel's jails for all of them
+        let kernel = match self.kernel.current() {
+            Ok(kernel) => Some(kernel),
+            Err(e) => {
+                warn!("Failed to list kernel jails, not syncing jail states: {}", e);
+                None
+            }
+        };
+
         for row in jail_rows {
             let name = row.name.clone();
-            match Jail::from_db_row(row) {
+            match Jail::from_db_row(row).map(|j| j.with_devfs_ruleset(self.config.devfs.ruleset)) {
                 Ok(mut jail) => {
-                    // Reset JID to -1 before syncing with kernel
-                    jail.set_jid(-1);
-
-                    // Sync with FreeBSD kernel - check if jail is actually running
-                    #[cfg(target_os = "freebsd")]
-                    {
-                        let actual_jid = self.get_jid_from_kernel(jail.name());
-                        if let Some(jid) = actual_jid {
-                            jail.set_jid(jid);
-                            jail.set_state(JailState::Running);
-                            debug!("Jail '{}' is running with JID {}", jail.name(), jid);
-                        } else {
-                            // Jail is not running, ensure state is Stopped or Created
-                            if jail.state() == JailState::Running {
-                                jail.set_state(JailState::Stopped);
-                            }
-                            debug!("Jail '{}' is not running", jail.name());
+                    if let Some(ref kernel) = kernel {
+                        jail.sync_with_kernel(kernel);
+                        match jail.jid() {
+                            -1 => debug!("Jail '{}' is not running", jail.name()),
+                            jid => debug!("Jail '{}' is {} with JID {}", jail.name(), if jail.is_frozen() { "frozen" } else { "running" }, jid),
                         }
+                    } else {
+                        jail.set_jid(-1);
                     }
 
                     self.jails.insert(name.clone(), jail);
@@ -280,6 +688,7 @@ impl JailManager {
             match self.load_container_from_store_row(store_container) {
                 Ok(container) => {
                     self.containers.insert(id.clone(), container);
+                    self.restore_container_jail(&id);
                     loaded_count += 1;
                 }
                 Err(e) => {
@@ -292,6 +701,69 @@ impl JailManager {
         Ok(())
     }
 
+    /// Give container `id` back the jail it was created with
+    ///
+    /// A container's jail only gets a row once it has started, and that row
+    /// keeps neither the VNET interface nor the container's own devfs
+    /// ruleset, so the definition is always taken from the container. A
+    /// loaded row of the same name still supplies the JID and state.
+    fn restore_container_jail(&mut self, id: &ContainerId) {
+        let Some(container) = self.containers.get(id) else { return };
+        let jail = match self.jails.remove(&container.jail_name) {
+            Some(loaded) => Ok(loaded),
+            None => Jail::create(&container.jail_name),
+        };
+        match jail.and_then(|jail| self.define_container_jail(container, jail)) {
+            Ok(jail) => {
+                self.jails.insert(container.jail_name.clone(), jail);
+            }
+            Err(e) => warn!("Failed to restore jail '{}' of container {}: {}", container.jail_name, id, e),
+        }
+    }
+
+    /// Set up `jail` as `container`'s: rooted at its dataset, with its
+    /// address, network interface, devfs ruleset and mount permissions
+    fn define_container_jail(&self, container: &Container, jail: Jail) -> Result<Jail, JailError> {
+        let devfs_ruleset = self.config.devfs.resolve(container.devfs_ruleset).unwrap_or(self.config.devfs.ruleset);
+        let mut jail = jail
+            .with_devfs_ruleset(devfs_ruleset)
+            .with_mount_permissions(container.mount_permissions)
+            .with_path(self.paths().container_root(&container.id))?;
+        // Setting an IP enables VNET; an IPv6-only container is VNET just the same
+        if let Some(ip) = container.ip.as_ref().or(container.ip6.as_ref()) {
+            jail = jail.with_ip(ip)?;
+        }
+        // The epair end that goes into the jail
+        if let Some(network) = self.container_networks.get(&container.id) {
+            jail = jail.with_vnet_interface(&network.epair_jail)?;
+        }
+        Ok(jail)
+    }
+
+    /// Load the host ports of running containers; rows of other containers
+    /// are left from a crash and released
+    fn load_published_ports_from_db(&mut self, store: &JailStore) -> Result<(), Box<dyn std::error::Error>> {
+        let mut stale = std::collections::BTreeSet::new();
+        for row in store.list_published_ports()? {
+            let running = self.containers.get(&row.container_id).is_some_and(|c| c.is_running());
+            match row.protocol.parse() {
+                Ok(protocol) if running => {
+                    let mapping = crate::container::PortMapping::new(row.host_port, 0, protocol);
+                    self.published_ports.claim(&row.container_id, &[mapping]);
+                }
+                _ => {
+                    stale.insert(row.container_id);
+                }
+            }
+        }
+
+        for id in stale {
+            debug!("Releasing published ports of container {}", id);
+            store.set_published_ports(&id, &[])?;
+        }
+        Ok(())
+    }
+
     /// Convert a store::Image to an image::Image
     fn load_image_from_store_row(&self, store_image: crate::store::Image) -> Result<crate::image::Image, Box<dyn std::error::Error>> {
         use crate::image::{Image, ImageConfig};
@@ -317,7 +789,14 @@ impl JailManager {
                 crate::store::ImageState::Available => crate::image::ImageState::Available,
                 crate::store::ImageState::Deleted => crate::image::ImageState::Deleted,
             },
-            created_at: store_image.created_at,
+            created_at: Timestamp::from_secs(store_image.created_at),
+            protected: store_image.protected,
+            kind: store_image.kind.parse().unwrap_or_default(),
+            os_version: store_image.os_version,
+            content_digest: store_image.content_digest,
+            build_log: store_image.build_log,
+            digest: store_image.digest,
+            written_by_newer: store_image.provenance.newer_than(crate::version::CURRENT),
         })
     }
 
@@ -343,6 +822,28 @@ impl JailManager {
         let restart_policy = store_container.restart_policy.parse::<RestartPolicy>()
             .map_err(|e| format!("Failed to parse restart_policy: {}", e))?;
 
+        let network_aliases: Vec<String> = serde_json::from_str(&store_container.network_aliases)
+            .map_err(|e| format!("Failed to parse network_aliases: {}", e))?;
+        let ip_aliases: Vec<String> = serde_json::from_str(&store_container.ip_aliases)
+            .map_err(|e| format!("Failed to parse ip_aliases: {}", e))?;
+        let tmpfs: Vec<crate::container::TmpfsMount> = serde_json::from_str(&store_container.tmpfs)
+            .map_err(|e| format!("Failed to parse tmpfs: {}", e))?;
+        let env: std::collections::BTreeMap<String, String> = serde_json::from_str(&store_container.env)
+            .map_err(|e| format!("Failed to parse env: {}", e))?;
+        let placement: Option<crate::placement::Placement> = store_container.placement.as_deref()
+            .map(serde_json::from_str)
+            .transpose()
+            .map_err(|e| format!("Failed to parse placement: {}", e))?;
+        let sysctls: std::collections::BTreeMap<String, String> = serde_json::from_str(&store_container.sysctls)
+            .map_err(|e| format!("Failed to parse sysctls: {}", e))?;
+        let mount_permissions: crate::jail::MountPermissions = store_container.mount_permissions.parse()?;
+        // A cause only a newer version knows is dropped, not fatal
+        let last_stop_cause = store_container.last_stop_cause.as_deref().and_then(|json| {
+            serde_json::from_str::<crate::container::StopCause>(json)
+                .inspect_err(|e| warn!("Ignoring stop cause {} of container {}: {}", json, store_container.id, e))
+                .ok()
+        });
+
         let state = match store_container.state {
             crate::store::ContainerState::Created => ContainerState::Created,
             crate::store::ContainerState::Running => ContainerState::Running,
@@ -352,7 +853,7 @@ impl JailManager {
         };
 
         // Create container with the loaded data including command
-        Ok(Container::new_with_existing_data(
+        let container = Container::new_with_existing_data(
             store_container.id,
             store_container.name,
             store_container.image_id,
@@ -364,51 +865,30 @@ impl JailManager {
             port_mappings,
             store_container.ip,
             command,
-            store_container.created_at,
-            store_container.started_at,
-        ))
-    }
-
-    /// Query FreeBSD kernel for JID by jail name
-    #[cfg(target_os = "freebsd")]
-    fn get_jid_from_kernel(&self, name: &str) -> Option<i32> {
-        use std::ffi::CString;
-        use std::mem;
-
-        let name_param = CString::new("name").unwrap();
-        let name_value = CString::new(name).ok()?;
-        let jid_param = CString::new("jid").unwrap();
-
-        let mut jid_out: libc::c_int = 0;
-
-        let iovs = [
-            libc::iovec {
-                iov_base: name_param.as_ptr() as *mut libc::c_void,
-                iov_len: name_param.as_bytes().len() + 1,
-            },
-            libc::iovec {
-                iov_base: name_value.as_ptr() as *mut libc::c_void,
-                iov_len: name_value.as_bytes().len() + 1,
-            },
-            libc::iovec {
-                iov_base: jid_param.as_ptr() as *mut libc::c_void,
-                iov_len: jid_param.as_bytes().len() + 1,
-            },
-            libc::iovec {
-                iov_base: &mut jid_out as *mut libc::c_int as *mut libc::c_void,
-                iov_len: mem::size_of::<libc::c_int>(),
-            },
-        ];
-
-        let result = unsafe {
-            libc::jail_get(iovs.as_ptr() as *mut libc::iovec, iovs.len() as libc::c_uint, 0)
-        };
-
-        if result > 0 {
-            Some(result)
-        } else {
-            None
-        }
+            Timestamp::from_secs(store_container.created_at),
+            store_container.started_at.map(Timestamp::from_secs),
+        );
+
+        let mut container = container
+            .with_protected(store_container.protected)
+            .with_devfs_ruleset(store_container.devfs_ruleset)
+            .with_healthcheck_disabled(store_container.healthcheck_disabled)
+            .with_boot(store_container.boot)
+            .with_read_only_root(store_container.read_only_root)
+            .with_tmpfs(tmpfs)
+            .with_env(env)
+            .with_placement(placement)
+            .with_sysctls(sysctls)
+            .with_description(store_container.description)
+            .with_network_aliases(network_aliases)
+            .with_hostname(store_container.hostname)
+            .with_ip_aliases(ip_aliases)
+            .with_mount_permissions(mount_permissions)
+            .with_dataset_copy(store_container.encrypted, store_container.full_copy)
+            .with_last_stop(store_container.stopped_at.map(Timestamp::from_secs), last_stop_cause);
+        container.ip6 = store_container.ip6;
+        container.written_by_newer = store_container.provenance.newer_than(crate::version::CURRENT);
+        Ok(container)
     }
 
     /// Stop the jail manager service
@@ -417,11 +897,21 @@ impl JailManager {
             return Err("JailManager is not running".into());
         }
 
-        // Stop all running jails
-        for jail in self.jails.values_mut() {
-            if jail.is_running() {
-                let _ = jail.stop();
-            }
+        // Stop all running jails; the containers among them stop for the
+        // shutdown
+        let stopped_jails: std::collections::HashSet<String> = self.jails.values_mut()
+            .filter(|jail| jail.is_running())
+            .filter_map(|jail| jail.stop().ok().map(|()| jail.name().to_string()))
+            .collect();
+        self.kernel.invalidate();
+
+        let stopped: Vec<ContainerId> = self.containers.values()
+            .filter(|c| c.is_running() && stopped_jails.contains(&c.jail_name))
+            .map(|c| c.id.clone())
+            .collect();
+        for id in stopped {
+            let stopped = self.record_container_stop(&id, crate::container::StopCause::DaemonShutdown);
+            self.persist(Resource::Container(id), stopped);
         }
 
         self.running = false;
@@ -442,21 +932,30 @@ impl JailManager {
             )));
         }
 
-        let jail = Jail::create(name)?;
+        let jail = Jail::create(name)?.with_devfs_ruleset(self.config.devfs.ruleset);
 
-        // Persist to database if configured
-        if let Some(ref store) = self.store {
-            let row = jail.to_db_row();
-            if let Err(e) = store.insert_jail(&row) {
-                error!("Failed to persist jail '{}' to database: {}", name, e);
-                // Continue anyway - persistence failures shouldn't block jail operations
-            }
-        }
+        // Persistence failures shouldn't block jail operations; a failed
+        // write is retried
+        self.persist(Resource::Jail(name.to_string()), Write::SaveJail(jail.to_db_row()));
 
         self.jails.insert(name.to_string(), jail);
         Ok(())
     }
 
+    /// Set or clear a jail's description
+    pub fn set_jail_description(&mut self, name: &str, description: Option<String>) -> Result<(), JailError> {
+        self.check_jail_writable(name)?;
+        let jail = self
+            .jails
+            .get_mut(name)
+            .ok_or_else(|| JailError::UpdateFailed(format!("Jail '{}' not found", name)))?;
+
+        jail.set_description(description);
+        let row = jail.to_db_row();
+        self.persist(Resource::Jail(name.to_string()), Write::SaveJail(row));
+        Ok(())
+    }
+
     /// Get a jail by name
     pub fn get_jail(&self, name: &str) -> Option<&Jail> {
         self.jails.get(name)
@@ -467,62 +966,164 @@ impl JailManager {
         self.jails.get_mut(name)
     }
 
+    /// What the daemon can't do in the jail it runs in, if any
+    pub fn confinement(&self) -> crate::system::Confinement {
+        self.confinement
+    }
+
+    /// Override the detected confinement (for tests)
+    pub fn set_confinement(&mut self, confinement: crate::system::Confinement) {
+        self.confinement = confinement;
+    }
+
+    /// Refuse to start a jail that needs what a jailed daemon lacks
+    fn check_confinement(confinement: &crate::system::Confinement, jail: &Jail) -> Result<(), String> {
+        if jail.uses_vnet() {
+            confinement.require_vnet()?;
+        }
+        confinement.require_devfs()
+    }
+
+    /// Refuse to change a jail a newer kawakaze last wrote
+    fn check_jail_writable(&self, name: &str) -> Result<(), JailError> {
+        let newer = self.jails.get(name).and_then(|jail| jail.written_by_newer());
+        crate::version::check_writable("Jail", name, newer).map_err(JailError::VersionSkew)
+    }
+
+    /// Refuse to change an image a newer kawakaze last wrote
+    fn check_image_writable(&self, id: &ImageId) -> Result<(), StoreError> {
+        let newer = self.images.get(id).and_then(|image| image.written_by_newer.as_deref());
+        crate::version::check_writable("Image", id, newer).map_err(StoreError::VersionSkew)
+    }
+
+    /// Whether the container's dataset was destroyed outside kawakaze
+    /// (see `dataset_health`)
+    pub fn dataset_missing(&self, container: &Container) -> bool {
+        self.datasets.is_missing(&container.dataset)
+    }
+
+    /// Refuse an operation that needs the container's dataset once it is
+    /// gone; removing the container is all that is left to do
+    pub fn check_container_dataset(&self, id: &ContainerId) -> Result<(), StoreError> {
+        match self.containers.get(id) {
+            Some(container) if self.dataset_missing(container) => Err(StoreError::DatasetMissing(format!(
+                "Container '{}' has no dataset: {} was destroyed outside kawakaze. Remove the container.",
+                container.display_name(),
+                container.dataset
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// The ZFS properties inspect shows for `dataset`; `None` without ZFS or
+    /// if they can't be read
+    pub fn inspect_properties(&self, dataset: &str) -> Option<std::collections::BTreeMap<String, String>> {
+        let properties = self.zfs.as_ref()?.all_properties(dataset)
+            .inspect_err(|e| warn!("Failed to read properties of {}: {}", dataset, e))
+            .ok()?;
+        Some(crate::zfs::select_properties(&properties, crate::zfs::INSPECT_PROPERTIES))
+    }
+
+    /// The container's `/var/log/messages`, below its dataset's mountpoint
+    pub fn container_messages_file(&self, container: &Container) -> PathBuf {
+        let mountpoint = self.zfs.as_ref().and_then(|zfs| zfs.get_mountpoint(&container.dataset).ok());
+        crate::syslog::messages_file(&mountpoint.unwrap_or_else(|| self.paths().container_root(&container.id)))
+    }
+
+    /// List item for a container, marked if its dataset is gone
+    pub fn container_list_item(&self, container: &Container) -> crate::api::ContainerListItem {
+        crate::api::ContainerListItem { dataset_missing: self.dataset_missing(container), ..container.into() }
+    }
+
+    /// Refuse to change a container a newer kawakaze last wrote
+    fn check_container_writable(&self, id: &ContainerId) -> Result<(), StoreError> {
+        let newer = self.containers.get(id).and_then(|container| container.written_by_newer.as_deref());
+        crate::version::check_writable("Container", id, newer).map_err(StoreError::VersionSkew)
+    }
+
     /// Start a jail by name
     pub fn start_jail(&mut self, name: &str) -> Result<(), JailError> {
+        self.check_jail_writable(name)?;
         let jail = self
             .jails
             .get_mut(name)
             .ok_or_else(|| JailError::StartFailed(format!("Jail '{}' not found", name)))?;
 
-        jail.start()?;
+        if self.upgrades.get(&UpgradeTarget::Jail(name.to_string())).is_some_and(|p| !p.status.is_finished()) {
+            return Err(JailError::StartFailed(format!("Jail '{}' is being upgraded", name)));
+        }
+        Self::check_confinement(&self.confinement, jail).map_err(JailError::Unavailable)?;
 
-        // Persist state change to database if configured
-        if let Some(ref store) = self.store {
-            let row = jail.to_db_row();
-            if let Err(e) = store.update_jail(&row) {
-                error!("Failed to persist jail '{}' state to database: {}", name, e);
-            }
+        // A frozen jail may have been removed behind our back
+        if jail.is_frozen() {
+            let kernel = self.kernel.current().map_err(|e| JailError::StartFailed(format!("Failed to list kernel jails: {}", e)))?;
+            jail.sync_with_kernel(&kernel);
         }
 
+        let started = jail.start();
+        self.kernel.invalidate();
+        started?;
+
+        let row = jail.to_db_row();
+        self.persist(Resource::Jail(name.to_string()), Write::SaveJail(row));
         Ok(())
     }
 
     /// Stop a jail by name
     pub fn stop_jail(&mut self, name: &str) -> Result<(), JailError> {
+        self.stop_jail_with(name, StopMode::Remove)
+    }
+
+    /// Stop a jail by name, removing or freezing it (see [`StopMode`])
+    pub fn stop_jail_with(&mut self, name: &str, mode: StopMode) -> Result<(), JailError> {
+        self.check_jail_writable(name)?;
         let jail = self
             .jails
             .get_mut(name)
             .ok_or_else(|| JailError::StopFailed(format!("Jail '{}' not found", name)))?;
 
-        jail.stop()?;
+        // A frozen jail may have been removed behind our back, which leaves
+        // it where removing it would
+        let was_frozen = jail.is_frozen();
+        if was_frozen {
+            let kernel = self.kernel.current().map_err(|e| JailError::StopFailed(format!("Failed to list kernel jails: {}", e)))?;
+            jail.sync_with_kernel(&kernel);
+        }
 
-        // Persist state change to database if configured
-        if let Some(ref store) = self.store {
-            let row = jail.to_db_row();
-            if let Err(e) = store.update_jail(&row) {
-                error!("Failed to persist jail '{}' state to database: {}", name, e);
-            }
+        if !(was_frozen && !jail.is_frozen() && mode == StopMode::Remove) {
+            let stopped = jail.stop_with(mode);
+            self.kernel.invalidate();
+            stopped?;
         }
 
+        let row = jail.to_db_row();
+        self.persist(Resource::Jail(name.to_string()), Write::SaveJail(row));
         Ok(())
     }
 
     /// Remove a jail by name
     pub fn remove_jail(&mut self, name: &str) -> Result<(), JailError> {
-        let jail = self
+        self.check_jail_writable(name)?;
+        let mut jail = self
             .jails
             .remove(name)
             .ok_or_else(|| JailError::DestroyFailed(format!("Jail '{}' not found", name)))?;
 
-        jail.destroy()?;
+        // Destroying removes a frozen jail, unless it is already gone
+        if jail.is_frozen() && let Ok(kernel) = self.kernel.current() {
+            jail.sync_with_kernel(&kernel);
+        }
 
-        // Remove from database if configured
-        if let Some(ref store) = self.store {
-            if let Err(e) = store.delete_jail(name) {
-                error!("Failed to delete jail '{}' from database: {}", name, e);
-            }
+        let destroyed = jail.destroy();
+        self.kernel.invalidate();
+        destroyed?;
+
+        let log = crate::build_log::bootstrap_log_file(&self.bootstrap_log_dir(), name);
+        if let Err(e) = crate::build_log::remove(&log) {
+            warn!("Failed to remove bootstrap log {}: {}", log.display(), e);
         }
 
+        self.persist(Resource::Jail(name.to_string()), Write::DeleteJail);
         Ok(())
     }
 
@@ -541,10 +1142,80 @@ impl JailManager {
         &self.socket_path
     }
 
+    /// Locations of the daemon's state under the running configuration
+    pub fn paths(&self) -> crate::paths::Paths {
+        crate::paths::Paths::from_config(&self.config)
+    }
+
+    /// Get the running configuration
+    pub fn config(&self) -> &KawakazeConfig {
+        &self.config
+    }
+
+    /// Health and capacity of the ZFS pool, if ZFS is available; read at
+    /// most every `pool_health::DEFAULT_TTL`
+    pub fn pool_status(&self) -> Option<Result<PoolStatus, String>> {
+        self.pool_health.status()
+    }
+
+    /// The pool's health cache, for the periodic check to refresh without
+    /// holding the manager
+    pub fn pool_health(&self) -> Arc<crate::pool_health::PoolHealth> {
+        Arc::clone(&self.pool_health)
+    }
+
+    /// The exec sessions of every container, for the sweeper and for kills
+    /// that wait out the grace period without holding the manager
+    pub fn exec_sessions(&self) -> Arc<crate::exec_sessions::ExecSessions> {
+        Arc::clone(&self.exec_sessions)
+    }
+
+    /// This node's labels for placement constraints
+    pub fn node_labels(&self) -> &std::collections::BTreeMap<String, String> {
+        self.node_labels.get_or_init(|| {
+            let host = crate::system::HostInfo::collect(&crate::system::CommandProbe);
+            crate::placement::node_labels(&self.config.node_labels, &host, &self.config.zfs_pool)
+        })
+    }
+
+    /// Why `action` should not write to the pool right now, if the pool is
+    /// known to be in a state other than ONLINE (or DEGRADED with
+    /// `storage.allow_degraded_pool`)
+    ///
+    /// A pool whose status can't be read doesn't block anything.
+    pub fn pool_write_refusal(&self, action: &str) -> Option<String> {
+        self.pool_health.refusal(action, self.config.storage.allow_degraded_pool)
+    }
+
+    /// Get where the running configuration was loaded from
+    pub fn config_source(&self) -> &ConfigSource {
+        &self.config_source
+    }
+
+    /// Record where the running configuration was loaded from
+    pub fn set_config_source(&mut self, source: ConfigSource) {
+        self.config_source = source;
+    }
+
+    /// Hand over the handle to the daemon's log filter, so the level can be
+    /// changed while it runs
+    pub fn set_log_level_handle(&mut self, log_level: crate::log_level::LogLevel) {
+        self.log_level = log_level;
+    }
+
+    /// Change the daemon's log filter, returning the one it replaced
+    pub fn set_log_level(&mut self, level: &str) -> std::result::Result<String, crate::log_level::LogLevelError> {
+        let previous = self.log_level.set(level)?;
+        info!("Log level changed from '{}' to '{}'", previous, self.log_level.current());
+        Ok(previous)
+    }
+
     /// Register a bootstrap progress tracker for a jail
     pub async fn register_bootstrap_tracker(&mut self, name: String, sender: BootstrapProgressSender) {
-        // Store the sender for later use
+        // Store the sender for later use; a new bootstrap replaces the
+        // finished one's progress, which then isn't pruned
         self.bootstrap_tracker.insert(name.clone(), sender);
+        self.finished_progress.remove(&ProgressKey::Bootstrap(name.clone()));
 
         // Initialize progress with defaults
         self.bootstrap_progress.insert(name, BootstrapProgress {
@@ -556,9 +1227,14 @@ impl JailManager {
         });
     }
 
-    /// Get the current bootstrap progress for a jail
+    /// Get the current bootstrap progress for a jail, with its place in
+    /// line while queued
     pub async fn get_bootstrap_progress(&self, name: &str) -> Option<BootstrapProgress> {
-        self.bootstrap_progress.get(name).cloned()
+        let mut progress = self.bootstrap_progress.get(name)?.clone();
+        if progress.status == BootstrapStatus::Queued {
+            progress.current_step = crate::task_queue::queued_status(self.bootstrap_queue.position(name));
+        }
+        Some(progress)
     }
 
     /// Send a bootstrap progress update
@@ -597,462 +1273,2116 @@ impl JailManager {
     pub async fn remove_bootstrap_tracker(&mut self, name: &str) {
         self.bootstrap_tracker.remove(name);
         self.bootstrap_progress.remove(name);
+        self.finished_progress.remove(&ProgressKey::Bootstrap(name.to_string()));
     }
 
-    // Image management methods
-
-    /// Add an image to the manager
-    pub fn add_image(&mut self, image: Image) -> Result<(), StoreError> {
-        if let Some(ref store) = self.store {
-            // Convert to store Image format
-            let store_image = crate::store::Image {
-                id: image.id.clone(),
-                name: image.name.clone(),
-                parent_id: image.parent_id.clone(),
-                snapshot: image.snapshot.clone(),
-                dockerfile: serde_json::to_string(&image.dockerfile)
-                    .map_err(|e| StoreError::SerializationError(e.to_string()))?,
-                config: serde_json::to_string(&image.config)
-                    .map_err(|e| StoreError::SerializationError(e.to_string()))?,
-                size_bytes: image.size_bytes as i64,
-                state: crate::store::ImageState::Available, // Since it's being added
-                created_at: image.created_at,
-            };
-            store.insert_image(&store_image)?;
+    /// Record that a build or bootstrap reached its final status. The
+    /// tracker goes now, ending its progress forwarder; the progress stays
+    /// for `progress_retention_secs` so clients can still fetch the final
+    /// status. Returns that period, for [`Self::schedule_progress_prune`].
+    pub fn finish_progress(&mut self, key: ProgressKey) -> std::time::Duration {
+        match &key {
+            ProgressKey::Build(image_id) => {
+                self.image_build_tracker.remove(image_id);
+            }
+            ProgressKey::Bootstrap(name) => {
+                self.bootstrap_tracker.remove(name);
+            }
+            ProgressKey::Operation(_) => {}
         }
-
-        self.images.insert(image.id.clone(), image);
-        Ok(())
+        self.finished_progress.insert(key, std::time::Instant::now());
+        std::time::Duration::from_secs(self.config.progress_retention_secs)
     }
 
-    /// Get an image by ID
-    pub fn get_image(&self, id: &ImageId) -> Option<&Image> {
-        self.images.get(id).or_else(|| {
-            // Try to load from database if not in memory
-            if let Some(ref store) = self.store {
-                if let Ok(Some(store_image)) = store.get_image(id) {
-                    // Note: In a real implementation, you'd want to cache this
-                    // For now, we'll just return None since we can't convert back easily here
-                    return None;
+    /// Drop the progress of builds and bootstraps that finished at least
+    /// `progress_retention_secs` before `now`
+    pub fn prune_finished_progress(&mut self, now: std::time::Instant) {
+        let retention = std::time::Duration::from_secs(self.config.progress_retention_secs);
+        let expired: Vec<ProgressKey> = self
+            .finished_progress
+            .iter()
+            .filter(|(_, finished)| now.saturating_duration_since(**finished) >= retention)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in expired {
+            self.finished_progress.remove(&key);
+            match key {
+                ProgressKey::Build(image_id) => {
+                    self.image_build_progress.remove(&image_id);
+                }
+                ProgressKey::Bootstrap(name) => {
+                    self.bootstrap_progress.remove(&name);
+                }
+                ProgressKey::Operation(id) => {
+                    self.operations.remove(&id);
                 }
             }
-            None
-        })
+        }
     }
 
-    /// Get an image by name
-    pub fn get_image_by_name(&self, name: &str) -> Option<&Image> {
-        self.images.values().find(|i| i.name == name).or_else(|| {
-            // Try to load from database if not in memory
-            if let Some(ref store) = self.store {
-                if let Ok(Some(store_image)) = store.get_image_by_name(name) {
-                    // Note: Same limitation as above
-                    return None;
-                }
-            }
-            None
-        })
+    /// Prune finished progress once `after` has passed. Whatever finished
+    /// since, or started again under the same key, is judged by its own time.
+    pub fn schedule_progress_prune(manager: &Arc<tokio::sync::Mutex<Self>>, after: std::time::Duration) {
+        let manager = manager.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(after).await;
+            manager.lock().await.prune_finished_progress(std::time::Instant::now());
+        });
     }
 
-    /// Get an image by ID prefix (supports short IDs like "6f5d541c-5cc")
-    /// Returns the first image whose ID starts with the given prefix.
-    /// Returns None if multiple images match the prefix (ambiguous).
-    pub fn get_image_by_prefix(&self, prefix: &str) -> Option<&Image> {
-        let matches: Vec<&Image> = self.images.values()
-            .filter(|i| i.id.starts_with(prefix))
-            .collect();
+    // Operation methods
 
-        match matches.len() {
-            0 => None,
-            1 => Some(matches[0]),
-            _ => {
-                // Multiple matches - ambiguous
-                warn!("Ambiguous image prefix '{}': matches {} images", prefix, matches.len());
-                None
-            }
-        }
+    /// Register an async create or remove of a container
+    pub fn begin_operation(&mut self, kind: crate::operation::OperationKind, container_id: &str, name: &str) -> String {
+        let id = uuid::Uuid::new_v4().to_string();
+        let progress = crate::operation::OperationProgress::new(id.clone(), kind, container_id.to_string(), name.to_string());
+        self.operations.insert(id.clone(), progress);
+        id
     }
 
-    /// List all images
-    pub fn list_images(&self) -> Vec<&Image> {
-        let mut images: Vec<&Image> = self.images.values().collect();
+    /// Record progress of a running operation
+    pub fn update_operation(&mut self, id: &str, event: crate::operation::OperationEvent) {
+        if let Some(progress) = self.operations.get_mut(id)
+            && let Err(e) = progress.apply(event)
+        {
+            warn!("{}", e);
+        }
+    }
 
-        // Also include images from database
-        if let Some(ref store) = self.store {
-            if let Ok(db_images) = store.list_images() {
-                // Note: In a real implementation, you'd want to convert and deduplicate
-                // For now, we'll just return the in-memory images
-                debug!("Found {} images in database (not yet loaded)", db_images.len());
-            }
+    /// Record how an operation ended. Returns how long its progress is kept.
+    pub fn finish_operation(&mut self, id: &str, result: Result<(), String>) -> std::time::Duration {
+        if let Some(progress) = self.operations.get_mut(id) {
+            progress.finish(result);
         }
+        self.finish_progress(ProgressKey::Operation(id.to_string()))
+    }
 
-        images
+    pub fn operation(&self, id: &str) -> Option<&crate::operation::OperationProgress> {
+        self.operations.get(id)
     }
 
-    /// Remove an image
-    pub fn remove_image(&mut self, id: &ImageId) -> Result<(), StoreError> {
-        if let Some(image) = self.get_image(id) {
-            // Clean up ZFS snapshot
-            if let Some(ref zfs) = self.zfs {
-                let _ = zfs.destroy(&image.snapshot);
-            }
-        }
+    // Upgrade methods
 
-        if let Some(ref store) = self.store {
-            store.delete_image(id)?;
+    /// Plan an upgrade of a stopped jail's root dataset
+    pub fn plan_jail_upgrade(&self, name: &str, to: Option<String>) -> Result<UpgradePlan, UpgradeError> {
+        let jail = self.jails.get(name).ok_or_else(|| UpgradeError::NotFound(format!("Jail '{}'", name)))?;
+        if jail.state() == JailState::Running {
+            return Err(UpgradeError::Conflict(format!("Jail '{}' is running; stop it first", name)));
+        }
+
+        let root = jail
+            .info()
+            .path
+            .map(PathBuf::from)
+            .ok_or_else(|| UpgradeError::NotStarted(format!("Jail '{}' has no root path", name)))?;
+        let zfs = self.zfs.as_ref().ok_or_else(|| UpgradeError::NotStarted("ZFS is not available".to_string()))?;
+        let dataset = zfs.dataset_mounted_at(&root).map_err(|e| UpgradeError::NotStarted(e.to_string()))?;
+
+        Ok(UpgradePlan::new(dataset, root, to))
+    }
+
+    /// Plan an upgrade of an image's dataset
+    ///
+    /// The image dataset is upgraded in place and snapshotted again; the
+    /// image then points at the new snapshot. Containers already cloned
+    /// from the old snapshot are unaffected.
+    pub fn plan_image_upgrade(&self, id: &ImageId, to: Option<String>) -> Result<UpgradePlan, UpgradeError> {
+        let image = self.images.get(id).ok_or_else(|| UpgradeError::NotFound(format!("Image '{}'", id)))?;
+        if self.is_building(&image.name) {
+            return Err(UpgradeError::Conflict(format!("Image '{}' is being built", image.name)));
+        }
+
+        let (dataset, _) = image
+            .snapshot
+            .split_once('@')
+            .ok_or_else(|| UpgradeError::NotStarted(format!("Image '{}' has no snapshot", image.name)))?;
+        let zfs = self.zfs.as_ref().ok_or_else(|| UpgradeError::NotStarted("ZFS is not available".to_string()))?;
+        let root = zfs.get_mountpoint(dataset).map_err(|e| UpgradeError::NotStarted(e.to_string()))?;
+
+        let plan = UpgradePlan::new(dataset.to_string(), root, to);
+        let commit = format!("{}-upgraded-{}", image.name, chrono::Utc::now().timestamp());
+        Ok(plan.with_commit_snapshot(commit))
+    }
+
+    /// Mark an upgrade as started, refusing a second concurrent one
+    pub fn begin_upgrade(&mut self, target: &UpgradeTarget) -> Result<(), UpgradeError> {
+        if self.upgrades.get(target).is_some_and(|p| !p.status.is_finished()) {
+            return Err(UpgradeError::Conflict("An upgrade is already in progress".to_string()));
+        }
+
+        self.upgrades.insert(target.clone(), UpgradeProgress::new());
+        Ok(())
+    }
+
+    /// Latest progress of an upgrade
+    pub fn upgrade_progress(&self, target: &UpgradeTarget) -> Option<&UpgradeProgress> {
+        self.upgrades.get(target)
+    }
+
+    /// Store a progress update from a running upgrade
+    pub fn set_upgrade_progress(&mut self, target: &UpgradeTarget, progress: UpgradeProgress) {
+        self.upgrades.insert(target.clone(), progress);
+    }
+
+    /// Record the result of an upgrade
+    ///
+    /// On success the new userland version is stored on the jail or image
+    /// (and an upgraded image is switched to its new snapshot).
+    pub fn finish_upgrade(&mut self, target: &UpgradeTarget, result: Result<UpgradeOutcome, UpgradeError>) {
+        let mut progress = self.upgrades.remove(target).unwrap_or_default();
+
+        match result {
+            Ok(outcome) => {
+                self.record_upgrade(target, &outcome);
+                progress.status = UpgradeStatus::Complete;
+                progress.current_step = format!("Upgraded {} -> {}", outcome.previous_version, outcome.version);
+                progress.previous_version = Some(outcome.previous_version);
+                progress.version = Some(outcome.version);
+                progress.snapshot = Some(outcome.snapshot);
+            }
+            Err(UpgradeError::RolledBack { reason, output }) => {
+                warn!("Upgrade of {:?} failed and was rolled back: {}", target, reason);
+                progress.current_step = format!("Upgrade failed and was rolled back: {}", reason);
+                progress.status = UpgradeStatus::RolledBack(reason);
+                progress.output = output;
+            }
+            Err(UpgradeError::RollbackFailed { reason, snapshot, rollback_error, output }) => {
+                error!("Upgrade of {:?} failed and rollback to {} failed: {}", target, snapshot, rollback_error);
+                let message = format!("{}; rollback to {} failed: {}", reason, snapshot, rollback_error);
+                progress.current_step = format!("Upgrade failed: {}", message);
+                progress.status = UpgradeStatus::Failed(message);
+                progress.output = output;
+            }
+            Err(e) => {
+                progress.current_step = e.to_string();
+                progress.status = UpgradeStatus::Failed(e.to_string());
+            }
+        }
+
+        self.upgrades.insert(target.clone(), progress);
+    }
+
+    fn record_upgrade(&mut self, target: &UpgradeTarget, outcome: &UpgradeOutcome) {
+        match target {
+            UpgradeTarget::Jail(name) => {
+                let Some(jail) = self.jails.get_mut(name) else {
+                    return;
+                };
+                jail.set_os_version(outcome.version.clone());
+                let row = jail.to_db_row();
+                self.persist(Resource::Jail(name.clone()), Write::SaveJail(row));
+            }
+            UpgradeTarget::Image(id) => {
+                let Some(image) = self.images.get_mut(id) else {
+                    return;
+                };
+                if let Some(ref snapshot) = outcome.committed_snapshot {
+                    image.snapshot = snapshot.clone();
+                }
+                image.os_version = Some(outcome.version.clone());
+                let write = Write::ImageUpgraded { snapshot: image.snapshot.clone(), os_version: outcome.version.clone() };
+                self.persist(Resource::Image(id.clone()), write);
+            }
+        }
+    }
+
+    // Base image methods
+
+    /// Ensure a base system snapshot exists for the given version and architecture
+    ///
+    /// The first call bootstraps the base system into `<pool>/base/<version>-<arch>`
+    /// and snapshots it; later calls return the cached snapshot so new jails and
+    /// images can be cloned from it instead of re-extracting base.txz.
+    pub async fn ensure_base_image(&mut self, version: &str, architecture: &str) -> Result<String, StoreError> {
+        self.ensure_base_image_with(version, architecture, &MirrorBootstrapper::default()).await
+    }
+
+    /// Ensure a base system snapshot exists, using a custom bootstrapper
+    pub async fn ensure_base_image_with<B: BaseBootstrapper>(
+        &mut self,
+        version: &str,
+        architecture: &str,
+        bootstrapper: &B,
+    ) -> Result<String, StoreError> {
+        let key = format!("{}-{}", version, architecture);
+
+        if let Some(snapshot) = self.cached_base_image(&key) {
+            return Ok(snapshot);
+        }
+
+        let zfs = self.zfs.as_ref()
+            .ok_or_else(|| StoreError::SerializationError("ZFS not configured".to_string()))?;
+
+        let snapshot = crate::bootstrap::ensure_base_snapshot(
+            zfs,
+            &self.config.zfs_pool,
+            version,
+            architecture,
+            &self.paths().base_root(version, architecture),
+            bootstrapper,
+        )
+        .await
+        .map_err(|e| StoreError::SerializationError(format!("Failed to prepare base image: {}", e)))?;
+
+        self.base_images.insert(key, snapshot.clone());
+        Ok(snapshot)
+    }
+
+    /// Like `ensure_base_image_with`, but only holds the manager lock to consult
+    /// and update the cache, so a first-time bootstrap doesn't stall the daemon
+    pub async fn ensure_base_image_shared<B: BaseBootstrapper>(
+        manager: &std::sync::Arc<tokio::sync::Mutex<JailManager>>,
+        version: &str,
+        architecture: &str,
+        bootstrapper: &B,
+    ) -> Result<String, StoreError> {
+        let key = format!("{}-{}", version, architecture);
+
+        let (zfs_pool, mountpoint) = {
+            let mgr = manager.lock().await;
+            if let Some(snapshot) = mgr.cached_base_image(&key) {
+                return Ok(snapshot);
+            }
+            if mgr.zfs.is_none() {
+                return Err(StoreError::SerializationError("ZFS not configured".to_string()));
+            }
+            (mgr.config.zfs_pool.clone(), mgr.paths().base_root(version, architecture))
+        };
+
+        let zfs = Zfs::new(&zfs_pool)
+            .map_err(|e| StoreError::SerializationError(e.to_string()))?;
+
+        let snapshot = crate::bootstrap::ensure_base_snapshot(&zfs, &zfs_pool, version, architecture, &mountpoint, bootstrapper)
+            .await
+            .map_err(|e| StoreError::SerializationError(format!("Failed to prepare base image: {}", e)))?;
+
+        manager.lock().await.base_images.insert(key, snapshot.clone());
+        Ok(snapshot)
+    }
+
+    /// Cached base snapshot for "<version>-<arch>", unless ZFS says it has gone away
+    fn cached_base_image(&self, key: &str) -> Option<String> {
+        let snapshot = self.base_images.get(key)?;
+
+        match self.zfs {
+            Some(ref zfs) if !zfs.snapshot_exists(snapshot) => {
+                warn!("Cached base image {} no longer exists, recreating", snapshot);
+                None
+            }
+            _ => Some(snapshot.clone()),
+        }
+    }
+
+    // Image management methods
+
+    /// Convert an image to its database row
+    fn image_row(image: &Image) -> Result<crate::store::Image, StoreError> {
+        Ok(crate::store::Image {
+            id: image.id.clone(),
+            name: image.name.clone(),
+            parent_id: image.parent_id.clone(),
+            snapshot: image.snapshot.clone(),
+            dockerfile: serde_json::to_string(&image.dockerfile)
+                .map_err(|e| StoreError::SerializationError(e.to_string()))?,
+            config: serde_json::to_string(&image.config)
+                .map_err(|e| StoreError::SerializationError(e.to_string()))?,
+            size_bytes: image.size_bytes as i64,
+            state: crate::store::ImageState::Available, // Since it's being added
+            created_at: image.created_at.as_secs(),
+            protected: image.protected,
+            kind: image.kind.as_str().to_string(),
+            os_version: image.os_version.clone(),
+            content_digest: image.content_digest.clone(),
+            build_log: image.build_log.clone(),
+            digest: image.digest.clone(),
+            provenance: Default::default(),
+        })
+    }
+
+    /// Add an image to the manager
+    pub fn add_image(&mut self, image: Image) -> Result<(), StoreError> {
+        if let Some(ref store) = self.store {
+            store.insert_image(&Self::image_row(&image)?)?;
+        }
+
+        self.images.insert(image.id.clone(), image);
+        Ok(())
+    }
+
+    /// Add a freshly built image, moving its name off any image that had it
+    ///
+    /// Earlier images with the name stay, so containers cloned from them
+    /// keep working, but become untagged. Untagging and inserting are one
+    /// store transaction. Returns the IDs of the images that were untagged.
+    pub fn add_built_image(&mut self, image: Image) -> Result<Vec<ImageId>, StoreError> {
+        let untag: Vec<ImageId> = self.images
+            .values()
+            .filter(|other| other.name == image.name && other.id != image.id && !other.is_untagged())
+            .map(|other| other.id.clone())
+            .collect();
+
+        if let Some(ref store) = self.store {
+            store.retag_image(&Self::image_row(&image)?, &untag)?;
+        }
+
+        for id in &untag {
+            if let Some(old) = self.images.get_mut(id) {
+                old.untag();
+                info!("Image {} untagged; '{}' now points at {}", id, image.name, image.id);
+            }
+        }
+        self.images.insert(image.id.clone(), image);
+        Ok(untag)
+    }
+
+    /// Get an image by ID
+    pub fn get_image(&self, id: &ImageId) -> Option<&Image> {
+        self.images.get(id).or_else(|| {
+            // Try to load from database if not in memory
+            if let Some(ref store) = self.store {
+                if let Ok(Some(store_image)) = store.get_image(id) {
+                    // Note: In a real implementation, you'd want to cache this
+                    // For now, we'll just return None since we can't convert back easily here
+                    return None;
+                }
+            }
+            None
+        })
+    }
+
+    /// Get an image by name
+    pub fn get_image_by_name(&self, name: &str) -> Option<&Image> {
+        self.images.values().find(|i| i.name == name && !i.is_untagged()).or_else(|| {
+            // Try to load from database if not in memory
+            if let Some(ref store) = self.store {
+                if let Ok(Some(store_image)) = store.get_image_by_name(name) {
+                    // Note: Same limitation as above
+                    return None;
+                }
+            }
+            None
+        })
+    }
+
+    /// Resolve an image ID or ID prefix (see [`names::resolve_by_prefix`])
+    pub fn resolve_image_prefix(&self, prefix: &str) -> Result<&Image, names::ResolveError> {
+        names::resolve_by_prefix(self.images.values(), |i| &i.id, prefix)
+    }
+
+    /// Get an image by ID prefix (supports short IDs like "6f5d541c-5cc")
+    /// Returns None if no image matches, the prefix is ambiguous or it is
+    /// shorter than [`names::MIN_PREFIX_LEN`].
+    pub fn get_image_by_prefix(&self, prefix: &str) -> Option<&Image> {
+        match self.resolve_image_prefix(prefix) {
+            Ok(image) => Some(image),
+            Err(e @ names::ResolveError::Ambiguous { .. }) => {
+                warn!("Image lookup: {}", e);
+                None
+            }
+            Err(_) => None,
+        }
+    }
+
+    /// List all images
+    pub fn list_images(&self) -> Vec<&Image> {
+        let mut images: Vec<&Image> = self.images.values().collect();
+
+        // Also include images from database
+        if let Some(ref store) = self.store {
+            if let Ok(db_images) = store.list_images() {
+                // Note: In a real implementation, you'd want to convert and deduplicate
+                // For now, we'll just return the in-memory images
+                debug!("Found {} images in database (not yet loaded)", db_images.len());
+            }
+        }
+
+        images
+    }
+
+    /// Register an in-flight build for an image name
+    pub fn begin_build(&mut self, name: &str) {
+        let (tx, _) = watch::channel(());
+        self.active_builds.insert(name.to_string(), tx);
+    }
+
+    /// Mark a build as finished, releasing any builds waiting on it
+    pub fn finish_build(&mut self, name: &str) {
+        self.active_builds.remove(name);
+    }
+
+    /// Whether an image with this name is currently being built
+    pub fn is_building(&self, name: &str) -> bool {
+        self.active_builds.contains_key(name)
+    }
+
+    /// Subscribe to the completion of an in-flight build. The receiver's
+    /// `changed()` resolves once the build finishes.
+    pub fn build_waiter(&self, name: &str) -> Option<watch::Receiver<()>> {
+        self.active_builds.get(name).map(|tx| tx.subscribe())
+    }
+
+    /// Directory of image build logs
+    pub fn build_log_dir(&self) -> PathBuf {
+        PathBuf::from(&self.config.storage.build_log_path)
+    }
+
+    /// Directory of bootstrap logs
+    pub fn bootstrap_log_dir(&self) -> PathBuf {
+        PathBuf::from(&self.config.storage.bootstrap_log_path)
+    }
+
+    /// Whether the build producing `image_id` is still running
+    pub fn is_build_active(&self, image_id: &str) -> bool {
+        self.image_build_progress
+            .get(image_id)
+            .is_some_and(|p| matches!(p.status, BuildStatus::Queued | BuildStatus::Building))
+    }
+
+    /// Whether the bootstrap of jail `name` is still running
+    pub fn is_bootstrap_active(&self, name: &str) -> bool {
+        self.bootstrap_progress
+            .get(name)
+            .is_some_and(|p| !matches!(p.status, BootstrapStatus::Complete | BootstrapStatus::Failed(_)))
+    }
+
+    /// Progress of build `image_id`, with its place in line while queued
+    pub fn build_progress(&self, image_id: &str) -> Option<ImageBuildProgress> {
+        let mut progress = self.image_build_progress.get(image_id)?.clone();
+        if progress.status == BuildStatus::Queued {
+            progress.current_instruction = crate::task_queue::queued_status(self.build_queue.position(image_id));
+        }
+        Some(progress)
+    }
+
+    /// Queue the registered build `image_id` of image `name` for a slot
+    pub fn queue_build(&mut self, image_id: &str, name: &str) -> Ticket {
+        let ticket = self.build_queue.enqueue(image_id, name);
+        if let Some(position) = self.build_queue.position(image_id)
+            && let Some(progress) = self.image_build_progress.get_mut(image_id)
+        {
+            progress.status = BuildStatus::Queued;
+            progress.current_instruction = crate::task_queue::queued_status(Some(position));
+        }
+        ticket
+    }
+
+    /// Mark a queued build as running once it has its slot
+    pub fn build_admitted(&mut self, image_id: &str) {
+        if let Some(progress) = self.image_build_progress.get_mut(image_id)
+            && progress.status == BuildStatus::Queued
+        {
+            progress.status = BuildStatus::Building;
+            progress.current_instruction = "Initializing...".to_string();
+        }
+    }
+
+    /// Queue the registered bootstrap of jail `name` for a slot
+    pub fn queue_bootstrap(&mut self, name: &str) -> Ticket {
+        let ticket = self.bootstrap_queue.enqueue(name, name);
+        if let Some(position) = self.bootstrap_queue.position(name)
+            && let Some(progress) = self.bootstrap_progress.get_mut(name)
+        {
+            progress.status = BootstrapStatus::Queued;
+            progress.current_step = crate::task_queue::queued_status(Some(position));
+        }
+        ticket
+    }
+
+    /// Mark a queued bootstrap as running once it has its slot
+    pub fn bootstrap_admitted(&mut self, name: &str) {
+        if let Some(progress) = self.bootstrap_progress.get_mut(name)
+            && progress.status == BootstrapStatus::Queued
+        {
+            progress.status = BootstrapStatus::Initializing;
+            progress.current_step = "Bootstrap starting...".to_string();
+        }
+    }
+
+    /// Builds and bootstraps holding or waiting for a slot, and container
+    /// operations still running
+    pub fn tasks(&self) -> Vec<crate::api::TaskInfo> {
+        let tasks = |kind: &'static str, queue: &TaskQueue| {
+            queue.tasks().into_iter().map(move |task| crate::api::TaskInfo {
+                kind: kind.to_string(),
+                id: task.id,
+                name: task.name,
+                position: task.position,
+            })
+        };
+        let operations = self.operations.values().filter(|op| !op.is_finished()).map(|op| crate::api::TaskInfo {
+            kind: op.kind.as_str().to_string(),
+            id: op.id.clone(),
+            name: op.name.clone(),
+            position: None,
+        });
+        tasks("build", &self.build_queue).chain(tasks("bootstrap", &self.bootstrap_queue)).chain(operations).collect()
+    }
+
+    /// Cancel a queued build (by build ID) or bootstrap (by jail name)
+    pub fn cancel_task(&mut self, id: &str) -> Result<(), QueueError> {
+        let result = match self.build_queue.cancel(id) {
+            Err(QueueError::NotFound(_)) => self.bootstrap_queue.cancel(id),
+            result => result,
+        };
+        if result.is_ok() {
+            self.fail_queued(id, &QueueError::Cancelled);
+        }
+        result
+    }
+
+    /// Refuse new builds and bootstraps and fail the queued ones; running
+    /// tasks are left to finish or die with the daemon
+    pub fn drain_task_queues(&mut self) {
+        let drained: Vec<String> = self.build_queue.close().into_iter().chain(self.bootstrap_queue.close()).collect();
+        for id in drained {
+            info!("Failing queued task '{}': daemon shutdown", id);
+            self.fail_queued(&id, &QueueError::Shutdown);
+        }
+    }
+
+    /// Record a task that never left the queue as failed. Its own task
+    /// records the same once it wakes; this makes it visible right away.
+    fn fail_queued(&mut self, id: &str, error: &QueueError) {
+        if let Some(progress) = self.image_build_progress.get_mut(id)
+            && progress.status == BuildStatus::Queued
+        {
+            progress.status = BuildStatus::Failed;
+            progress.current_instruction = format!("Build failed: {}", error);
+        }
+        if let Some(progress) = self.bootstrap_progress.get_mut(id)
+            && progress.status == BootstrapStatus::Queued
+        {
+            progress.status = BootstrapStatus::Failed(error.to_string());
+            progress.current_step = format!("Bootstrap failed: {}", error);
+        }
+    }
+
+    /// Apply the log retention caps to the build and bootstrap log
+    /// directories, sparing logs still being written
+    pub fn sweep_logs(&self) {
+        let retention = crate::build_log::Retention::from_config(&self.config.storage);
+        let (build_dir, bootstrap_dir) = (self.build_log_dir(), self.bootstrap_log_dir());
+        let keep: std::collections::HashSet<PathBuf> = self.image_build_progress
+            .keys()
+            .filter(|id| self.is_build_active(id))
+            .map(|id| crate::build_log::build_log_file(&build_dir, id))
+            .chain(
+                self.bootstrap_progress
+                    .keys()
+                    .filter(|name| self.is_bootstrap_active(name))
+                    .map(|name| crate::build_log::bootstrap_log_file(&bootstrap_dir, name)),
+            )
+            .collect();
+
+        for dir in [&build_dir, &bootstrap_dir] {
+            match crate::build_log::sweep(dir, retention, &keep, std::time::SystemTime::now()) {
+                Ok(removed) if !removed.is_empty() => info!("Removed {} old logs from {}", removed.len(), dir.display()),
+                Ok(_) => {}
+                Err(e) => warn!("Failed to sweep logs in {}: {}", dir.display(), e),
+            }
+        }
+    }
+
+    /// Spool uploads in `uploads` instead of the configured directory
+    pub fn set_uploads(&mut self, uploads: crate::upload::Uploads) {
+        self.uploads = uploads;
+    }
+
+    /// Remove the uploads a previous daemon left in the spool; they can't
+    /// be resumed (see `upload`)
+    pub fn clear_upload_spool(&mut self) {
+        match self.uploads.clear_spool() {
+            Ok(0) => {}
+            Ok(removed) => info!("Removed {} stale uploads from {}", removed, self.uploads.dir().display()),
+            Err(e) => warn!("Failed to clear upload spool {}: {}", self.uploads.dir().display(), e),
+        }
+    }
+
+    /// Drop uploads no request touched for `uploads.idle_timeout_secs`
+    pub fn expire_idle_uploads(&mut self) {
+        for id in self.uploads.expire_idle(std::time::Instant::now()) {
+            info!("Dropped idle upload {}", id);
+        }
+    }
+
+    /// Remove build mount points a previous daemon left behind, sparing
+    /// mounted ones and those of builds still running
+    pub fn sweep_build_dirs(&self) {
+        let mounts = match crate::build_dirs::mount_points() {
+            Ok(mounts) => mounts,
+            Err(e) => {
+                warn!("Skipping build directory sweep, mount table unreadable: {}", e);
+                return;
+            }
+        };
+        let active: Vec<String> = self.image_build_progress.keys().filter(|id| self.is_build_active(id)).cloned().collect();
+        let paths = self.paths();
+        let builds = &paths.builds;
+        match crate::build_dirs::sweep(builds, &mounts, &active) {
+            Ok(removed) if !removed.is_empty() => info!("Removed {} leftover build directories from {}", removed.len(), builds.display()),
+            Ok(_) => {}
+            Err(e) => warn!("Failed to sweep build directories in {}: {}", builds.display(), e),
+        }
+    }
+
+    /// Remove an image
+    pub fn remove_image(&mut self, id: &ImageId) -> Result<(), StoreError> {
+        self.remove_image_with(id, &RemovalOptions::default())
+    }
+
+    /// Remove an image, enforcing its protection policy
+    pub fn remove_image_with(&mut self, id: &ImageId, options: &RemovalOptions) -> Result<(), StoreError> {
+        self.check_image_writable(id)?;
+        if let Some(image) = self.get_image(id) {
+            options.check_protection("image", id, image.protected)?;
+        }
+
+        if let Some(image) = self.get_image(id) {
+            // Clean up ZFS snapshot
+            if let Some(ref zfs) = self.zfs {
+                let _ = zfs.destroy(&image.snapshot);
+            }
+            if let Some(ref log) = image.build_log
+                && let Err(e) = crate::build_log::remove(Path::new(log))
+            {
+                warn!("Failed to remove build log {}: {}", log, e);
+            }
+        }
+
+        if let Some(ref store) = self.store {
+            store.delete_image(id)?;
         }
 
         self.images.remove(id);
         Ok(())
     }
 
-    // Container management methods
+    /// Set or clear the protected flag on an image
+    pub fn set_image_protected(&mut self, id: &ImageId, protected: bool) -> Result<(), StoreError> {
+        self.check_image_writable(id)?;
+        let image = self.images.get_mut(id)
+            .ok_or_else(|| StoreError::SerializationError(format!("Image {} not found", id)))?;
+
+        if let Some(ref store) = self.store {
+            store.set_image_protected(id, protected)?;
+        }
+
+        image.protected = protected;
+        Ok(())
+    }
+
+    /// Remove dangling images, returning what was removed
+    ///
+    /// Without `all` only intermediate images nothing depends on are removed;
+    /// with `all`, user images that no container uses go too. Images are
+    /// removed children first, so a failure part-way leaves the graph intact.
+    pub fn prune_images(&mut self, all: bool) -> Result<Vec<Image>, StoreError> {
+        let in_use: std::collections::HashSet<ImageId> = self.containers.values()
+            .map(|container| container.image_id.clone())
+            .collect();
+        let candidates = crate::image::prune_candidates(self.images.values(), &in_use, all);
+
+        let mut removed = Vec::new();
+        for id in candidates {
+            let Some(image) = self.images.get(&id).cloned() else { continue };
+            self.remove_image(&id)?;
+            info!("Pruned {} image {} ({})", image.kind, image.name, id);
+            removed.push(image);
+        }
+
+        Ok(removed)
+    }
+
+    // Container management methods
+
+    /// Create a container from an image
+    pub fn create_container(&mut self, config: crate::container::ContainerConfig) -> Result<Container, StoreError> {
+        let pending = self.plan_container(config)?;
+        let zfs = self.zfs.clone();
+        match Self::provision_container(zfs.as_ref(), &pending, &mut |_, _| {}) {
+            Ok(plan) => self.finish_container(pending, plan),
+            Err(e) => {
+                self.abandon_container(&pending.id);
+                Err(e)
+            }
+        }
+    }
+
+    /// Check a new container and pick its ID, dataset and host ports. Until
+    /// `finish_container` or `abandon_container`, the ID and ports count as
+    /// taken.
+    pub fn plan_container(&mut self, config: crate::container::ContainerConfig) -> Result<PendingContainer, StoreError> {
+        // Validate image exists
+        let image = self.get_image(&config.image_id)
+            .ok_or_else(|| StoreError::SerializationError(format!("Image {} not found", config.image_id)))?;
+
+        // Validate the devfs ruleset before creating anything
+        let devfs_ruleset = self.config.devfs.resolve(config.devfs_ruleset)
+            .map_err(|e| StoreError::SerializationError(e.to_string()))?;
+
+        // Pick host ports for mappings that left them to us
+        let mut ports = config.ports.clone();
+        let in_use: Vec<crate::container::PortMapping> = self.containers
+            .values()
+            .flat_map(|c| c.port_mappings.iter().cloned())
+            .chain(self.creating.values().flatten().cloned())
+            .collect();
+        crate::container::assign_host_ports(&mut ports, &in_use)
+            .map_err(StoreError::SerializationError)?;
+
+        if let Some(ref name) = config.name {
+            self.check_new_name(name).map_err(|e| StoreError::SerializationError(e.to_string()))?;
+        }
+
+        // Generate container ID
+        let container_id = self.unique_id(Container::generate_id)
+            .map_err(|e| StoreError::SerializationError(e.to_string()))?;
+        let jail_name = format!("kawakaze-{}", &container_id[..8]);
+        let dataset = format!("{}/containers/{}", self.config.zfs_pool, &container_id[..8]);
+
+        // Clone the image snapshot, or receive a full copy when an encrypted
+        // dataset is wanted from an unencrypted image
+        let encryption = (config.encrypted || self.config.encryption.enabled)
+            .then(|| self.config.encryption.dataset_options());
+
+        // Image VOLUMEs the request didn't mount get a dataset of their own,
+        // mounted inside the container's root
+        let anonymous = crate::container::plan_anonymous_volumes(
+            &image.config.volumes,
+            &config.volumes,
+            &config.tmpfs,
+            &format!("{}/volumes", self.config.zfs_pool),
+            &container_id,
+        );
+
+        let pending = PendingContainer {
+            snapshot: image.snapshot.clone(),
+            mountpoint: self.paths().container_root(&container_id),
+            id: container_id,
+            jail_name,
+            dataset,
+            encryption,
+            anonymous,
+            ports,
+            devfs_ruleset,
+            config,
+        };
+        self.creating.insert(pending.id.clone(), pending.ports.clone());
+        Ok(pending)
+    }
+
+    /// Make and mount the datasets of a planned container. Needs no manager,
+    /// so an async create runs it without holding the lock.
+    pub fn provision_container<D: crate::operation::DatasetOps>(
+        zfs: Option<&D>,
+        pending: &PendingContainer,
+        report: &mut dyn FnMut(crate::operation::Phase, &str),
+    ) -> Result<crate::zfs::CopyPlan, StoreError> {
+        use crate::operation::Phase;
+
+        let Some(zfs) = zfs else {
+            return Ok(crate::zfs::plan_copy(false, pending.encryption.is_some()));
+        };
+
+        report(Phase::Cloning, &format!("Cloning {}", pending.snapshot));
+        let plan = zfs.copy_snapshot(&pending.snapshot, &pending.dataset, pending.encryption.as_ref())?;
+        if plan.method == crate::zfs::CopyMethod::SendReceive {
+            info!("Image {} is not encrypted; copying it into encrypted dataset {}", pending.config.image_id, pending.dataset);
+        }
+
+        // Mount the container dataset to a directory so the jail can access the files
+        report(Phase::Mounting, &format!("Mounting {}", pending.dataset));
+        zfs.mount(&pending.dataset, &pending.mountpoint)
+            .map_err(|e| StoreError::SerializationError(format!("Failed to mount container dataset: {}", e)))?;
+
+        if !pending.anonymous.is_empty() {
+            report(Phase::Volumes, &format!("Creating {} volume(s)", pending.anonymous.len()));
+        }
+        let options = pending.encryption.clone().unwrap_or_default();
+        for volume in &pending.anonymous {
+            let target = pending.mountpoint.join(volume.destination.trim_start_matches('/'));
+            zfs.create_volume(&volume.source, &options, &target)
+                .map_err(|e| StoreError::SerializationError(format!("Failed to create volume for {}: {}", volume.destination, e)))?;
+        }
+
+        Ok(plan)
+    }
+
+    /// Forget a planned container whose datasets couldn't be made
+    pub fn abandon_container(&mut self, id: &ContainerId) {
+        self.creating.remove(id);
+    }
+
+    /// Give a provisioned container its network and jail and record it
+    pub fn finish_container(&mut self, pending: PendingContainer, plan: crate::zfs::CopyPlan) -> Result<Container, StoreError> {
+        self.creating.remove(&pending.id);
+        let PendingContainer { id: container_id, jail_name, dataset, anonymous, ports, config, .. } = pending;
+
+        // Allocate network resources if network manager is available
+        let (container_ip, container_ip6) = if let Some(ref mut network_manager) = self.network_manager {
+            match network_manager.allocate_network(&jail_name) {
+                Ok(network) => {
+                    let (ip, ip6) = (network.ip.clone(), network.ip6.clone());
+                    info!("Allocated IP {:?}, IPv6 {:?} for container {} (epair: {})", ip, ip6, container_id, network.epair_jail);
+                    self.container_networks.insert(container_id.clone(), network);
+                    (ip, ip6)
+                }
+                Err(e) => {
+                    warn!("Failed to allocate network for container {}: {}. Container will have no networking.", container_id, e);
+                    (None, None)
+                }
+            }
+        } else {
+            info!("No network manager available, container {} will have no networking", container_id);
+            (None, None)
+        };
+
+        // Create container with the pre-generated ID
+        let mut container = Container::new_with_id(container_id.clone(), config.image_id.clone(), jail_name, dataset)
+            .with_name(config.name.unwrap_or_else(|| container_id.clone()))
+            .with_restart_policy(config.restart_policy)
+            .with_protected(config.protected)
+            .with_devfs_ruleset(config.devfs_ruleset)
+            .with_healthcheck_disabled(config.healthcheck_disabled)
+            .with_boot(config.boot)
+            .with_read_only_root(config.read_only_root)
+            .with_tmpfs(config.tmpfs)
+            .with_env(config.env)
+            .with_placement(config.placement)
+            .with_sysctls(config.sysctls)
+            .with_description(config.description)
+            .with_network_aliases(config.network_aliases)
+            .with_hostname(config.hostname)
+            .with_mount_permissions(config.mount_permissions)
+            .with_dataset_copy(plan.encrypted, plan.method == crate::zfs::CopyMethod::SendReceive);
+
+        // Set IPs if allocated
+        if let Some(ip) = container_ip {
+            container = container.with_ip(ip);
+        }
+        if let Some(ip6) = container_ip6 {
+            container = container.with_ip6(ip6);
+        }
+
+        // Add port mappings
+        for port_mapping in &ports {
+            container = container.with_port_mapping(port_mapping.clone());
+        }
+
+        for mount in config.volumes.into_iter().chain(anonymous) {
+            container = container.with_mount(mount);
+        }
+
+        // Create the FreeBSD jail with the mounted path; the same definition
+        // is rebuilt from the record when the container is loaded again
+        let jail = Jail::create(&container.jail_name)
+            .and_then(|jail| self.define_container_jail(&container, jail))
+            .map_err(|e| StoreError::SerializationError(format!("Failed to create jail: {}", e)))?;
+        self.jails.insert(container.jail_name.clone(), jail);
+
+        // Store in database
+        if let Some(ref store) = self.store {
+            // Serialize command to JSON for storage (before we move it to the container)
+            let command_json = config.command
+                .as_ref()
+                .map(|cmd| serde_json::to_string(&cmd)
+                    .map_err(|e| StoreError::SerializationError(e.to_string())))
+                .transpose()?;
+
+            let store_container = Self::container_row(&container, command_json)?;
+            store.insert_container(&store_container)?;
+        }
+
+        // Set command if provided (after database storage since we move the value)
+        if let Some(command) = config.command {
+            container = container.with_command(command);
+        }
+
+        self.containers.insert(container_id.clone(), container.clone());
+        Ok(container)
+    }
+
+    /// Mark containers on the given datasets (dataset -> reason) as locked
+    pub(crate) fn lock_containers(&mut self, locked: &HashMap<String, String>) -> Vec<ContainerId> {
+        let mut ids = Vec::new();
+        for container in self.containers.values_mut() {
+            if let Some(reason) = locked.get(&container.dataset) {
+                warn!("Container {} is locked: {}", container.display_name(), reason);
+                container.set_state(crate::container::ContainerState::Locked);
+                ids.push(container.id.clone());
+            }
+        }
+        ids
+    }
+
+    /// Database row for a newly created container
+    fn container_row(container: &Container, command_json: Option<String>) -> Result<crate::store::Container, StoreError> {
+        Ok(crate::store::Container {
+            id: container.id.clone(),
+            name: container.name.clone(),
+            image_id: container.image_id.clone(),
+            jail_name: container.jail_name.clone(),
+            dataset: container.dataset.clone(),
+            state: crate::store::ContainerState::Created,
+            restart_policy: container.restart_policy.as_str().to_string(),
+            mounts: serde_json::to_string(&container.mounts)
+                .map_err(|e| StoreError::SerializationError(e.to_string()))?,
+            port_mappings: serde_json::to_string(&container.port_mappings)
+                .map_err(|e| StoreError::SerializationError(e.to_string()))?,
+            ip: container.ip.clone(),
+            command: command_json,
+            created_at: container.created_at.as_secs(),
+            started_at: container.started_at.map(Timestamp::as_secs),
+            protected: container.protected,
+            devfs_ruleset: container.devfs_ruleset,
+            healthcheck_disabled: container.healthcheck_disabled,
+            boot: container.boot,
+            encrypted: container.encrypted,
+            full_copy: container.full_copy,
+            read_only_root: container.read_only_root,
+            network_aliases: serde_json::to_string(&container.network_aliases)
+                .map_err(|e| StoreError::SerializationError(e.to_string()))?,
+            hostname: container.hostname.clone(),
+            ip_aliases: serde_json::to_string(&container.ip_aliases)
+                .map_err(|e| StoreError::SerializationError(e.to_string()))?,
+            ip6: container.ip6.clone(),
+            tmpfs: serde_json::to_string(&container.tmpfs)
+                .map_err(|e| StoreError::SerializationError(e.to_string()))?,
+            mount_permissions: container.mount_permissions.as_str().to_string(),
+            stopped_at: container.stopped_at.map(Timestamp::as_secs),
+            last_stop_cause: container.last_stop_cause.map(|cause| serde_json::to_string(&cause))
+                .transpose()
+                .map_err(|e| StoreError::SerializationError(e.to_string()))?,
+            env: serde_json::to_string(&container.env)
+                .map_err(|e| StoreError::SerializationError(e.to_string()))?,
+            placement: container.placement.as_ref().map(serde_json::to_string)
+                .transpose()
+                .map_err(|e| StoreError::SerializationError(e.to_string()))?,
+            sysctls: serde_json::to_string(&container.sysctls)
+                .map_err(|e| StoreError::SerializationError(e.to_string()))?,
+            description: container.description.clone(),
+            provenance: Default::default(),
+        })
+    }
+
+    /// Start a container
+    pub fn start_container(&mut self, id: &ContainerId) -> Result<(), StoreError> {
+        // Try to load from database if not in memory
+        if !self.containers.contains_key(id) {
+            if let Some(ref store) = self.store {
+                if let Ok(Some(store_container)) = store.get_container(id) {
+                    match self.load_container_from_store_row(store_container) {
+                        Ok(container) => {
+                            self.containers.insert(id.clone(), container);
+                            self.restore_container_jail(id);
+                        }
+                        Err(e) => {
+                            return Err(StoreError::SerializationError(format!("Failed to load container: {}", e)));
+                        }
+                    }
+                }
+            }
+        }
+
+        self.check_container_writable(id)?;
+        self.check_container_dataset(id)?;
+
+        // Clone the data we need before starting the jail
+        let (jail_name, hostname, command, env, sysctls, port_mappings, (ip, ip6), ip_aliases, (read_only_dataset, tmpfs)) = {
+            let container = self.containers.get(id)
+                .ok_or_else(|| StoreError::SerializationError(format!("Container {} not found", id)))?;
+            if container.state == crate::container::ContainerState::Locked {
+                return Err(StoreError::SerializationError(format!(
+                    "Container {} is locked: the encryption key for {} is not loaded",
+                    id, container.dataset
+                )));
+            }
+            (
+                container.jail_name.clone(),
+                container.hostname().to_string(),
+                container.command.clone(),
+                self.container_env(container),
+                container.sysctls.clone(),
+                container.port_mappings.clone(),
+                (container.ip.clone(), container.ip6.clone()),
+                container.ip_aliases.clone(),
+                self.root_mounts(container),
+            )
+        };
+
+        // A rename while stopped only changed the record
+        if let Some(jail) = self.jails.get_mut(&jail_name) {
+            jail.set_hostname(&hostname).map_err(|e| StoreError::SerializationError(e.to_string()))?;
+        }
+
+        if let Some(jail) = self.jails.get(&jail_name) {
+            Self::check_confinement(&self.confinement, jail).map_err(StoreError::Unavailable)?;
+        }
+
+        // Claim the host ports before anything can forward them
+        self.reserve_ports(id, &port_mappings)?;
+        let hosts_before = self.hosts_registry();
+
+        // External datasets are attached before the jail starts, and the
+        // delegated ones handed to it once it runs
+        let (mut datasets, attachments) = match self.containers.get(id) {
+            Some(container) => self.attached_datasets(container),
+            None => return Err(StoreError::SerializationError(format!("Container {} not found", id))),
+        };
+        if let Err(e) = crate::dataset_attach::attach(&mut datasets, id, &self.paths().container_root(id), &attachments) {
+            self.release_ports(id);
+            return Err(StoreError::SerializationError(e));
+        }
+
+        // Start the jail, on a read-only root if the container asks for one
+        // and with its tmpfs mounted
+        let started = if read_only_dataset.is_some() || !tmpfs.is_empty() {
+            let root = self.paths().container_root(id);
+            if read_only_dataset.is_some() {
+                // The root can't be written once it is read-only, so it gets
+                // the network's hosts as of now, itself included
+                let mut registry = hosts_before.clone();
+                if let Some(entry) = self.containers.get(id).and_then(Self::host_entry) {
+                    registry.insert(id.clone(), entry);
+                }
+                self.publish_hosts_to(&registry, std::slice::from_ref(&root));
+            }
+            let mut fs = crate::read_only::HostRootFs { zfs: self.zfs.clone() };
+            crate::read_only::start(&mut fs, read_only_dataset.as_deref(), &root, &tmpfs, || {
+                self.start_jail(&jail_name).map_err(|e| e.to_string())
+            })
+        } else {
+            self.start_jail(&jail_name).map_err(|e| e.to_string())
+        };
+        let started = started.and_then(|()| {
+            let jid = self.jails.get(&jail_name).map_or(-1, |jail| jail.jid());
+            crate::dataset_attach::delegate(&mut datasets, jid, &attachments).inspect_err(|_| {
+                // Not left running without the data it was given
+                if let Err(e) = self.stop_jail_with(&jail_name, StopMode::Remove) {
+                    warn!("Failed to stop container {} after its datasets failed: {}", id, e);
+                }
+                self.release_root_mounts(id, read_only_dataset.as_deref(), &tmpfs);
+            })
+        });
+        // A tunable that didn't apply fails the start (see `sysctl`)
+        let mut applied_sysctls = std::collections::BTreeMap::new();
+        let started = started.and_then(|()| {
+            let applied = match self.jails.get(&jail_name) {
+                Some(jail) => crate::sysctl::apply(&sysctls, |program, args| {
+                    jail.exec(program, args).map(|(stdout, _)| stdout).map_err(|e| e.to_string())
+                }),
+                None => Ok(std::collections::BTreeMap::new()),
+            };
+            applied_sysctls = applied.inspect_err(|e| {
+                warn!("Removing container {} again: {}", id, e);
+                if let Err(e) = self.stop_jail_with(&jail_name, StopMode::Remove) {
+                    warn!("Failed to stop container {} after its sysctls failed: {}", id, e);
+                }
+                self.release_root_mounts(id, read_only_dataset.as_deref(), &tmpfs);
+            })?;
+            Ok(())
+        });
+        if let Err(e) = started {
+            crate::dataset_attach::release(&mut datasets, id, &attachments);
+            self.release_ports(id);
+            return Err(StoreError::SerializationError(e));
+        }
+
+        // Addresses added with `network connect`
+        let alias_params = crate::container::ip_alias_params(&[], &ip_aliases);
+        if !alias_params.is_empty()
+            && let Some(jail) = self.jails.get(&jail_name)
+            && let Err(e) = jail.update_params(&alias_params.iter().map(|(param, value)| (*param, value.as_str())).collect::<Vec<_>>())
+        {
+            warn!("Failed to add IP aliases to container {}: {}", id, e);
+        }
+
+        // Configure network if we have a network configuration for this container
+        if let Some(ref network_manager) = self.network_manager {
+            if let Some(network) = self.container_networks.get(id) {
+                info!("Configuring network for container {}", id);
+                if let Err(e) = network_manager.configure_jail_network(&jail_name, network) {
+                    warn!("Failed to configure network for container {}: {}", id, e);
+                }
+            }
+        }
+
+        // Set up port forwarding if we have port mappings
+        if let Some(ref network_manager) = self.network_manager {
+            for port_mapping in &port_mappings {
+                if ip.is_some() || ip6.is_some() {
+                    info!("Setting up port forwarding for container {}: {} -> {}/{} port {} ({})",
+                          id, port_mapping.host_port, ip.as_deref().unwrap_or("-"), ip6.as_deref().unwrap_or("-"),
+                          port_mapping.container_port, port_mapping.protocol);
+                    if let Err(e) = network_manager.setup_port_forwarding(ip.as_deref(), ip6.as_deref(), port_mapping) {
+                        warn!("Failed to set up port forwarding for container {}: {}", id, e);
+                    }
+                }
+            }
+        }
+
+        // Execute command if specified
+        if let Some(cmd) = command {
+            if !cmd.is_empty() {
+                let jail = self.jails.get(&jail_name)
+                    .ok_or_else(|| StoreError::SerializationError(format!("Jail {} not found", jail_name)))?;
+
+                let (program, args) = cmd.split_first().unwrap_or((&cmd[0], &[]));
+                let args: Vec<String> = args.to_vec();
+
+                info!("Executing command in container {}: {} {:?}", id, program, args);
+
+                // Execute the command in the jail, keeping its output for `kawakaze logs`
+                let (stdout, stderr) = jail.exec_with_env(program, &args, &env)
+                    .map_err(|e| StoreError::SerializationError(format!("Failed to execute command: {}", e)))?;
+
+                let log_file = crate::logs::container_log_file(Path::new(&self.config.storage.log_path), id);
+                let now = chrono::Utc::now().timestamp();
+                if let Err(e) = crate::logs::append_output(&log_file, "stdout", &stdout, now)
+                    .and_then(|_| crate::logs::append_output(&log_file, "stderr", &stderr, now))
+                {
+                    warn!("Failed to write logs for container {}: {}", id, e);
+                }
+            }
+        }
+
+        // Update state
+        if let Some(container) = self.containers.get_mut(id) {
+            container.set_state(crate::container::ContainerState::Running);
+            container.applied_sysctls = applied_sysctls;
+        }
+        self.publish_hosts(&hosts_before);
+
+        self.write_through(Resource::Container(id.clone()), Write::ContainerState(crate::store::ContainerState::Running))
+    }
+
+    /// The environment of `container`'s processes: its image's ENV with its
+    /// own variables over it (see [`crate::container::effective_env`])
+    pub fn container_env(&self, container: &Container) -> std::collections::BTreeMap<String, String> {
+        let image_env = self.images.get(&container.image_id).map(|image| &image.config.env);
+        crate::container::effective_env(image_env.unwrap_or(&HashMap::new()), &container.env)
+    }
+
+    /// Stop a container for `cause`
+    pub fn stop_container(&mut self, id: &ContainerId, cause: crate::container::StopCause) -> Result<(), StoreError> {
+        self.stop_container_with(id, StopMode::Remove, cause)
+    }
+
+    /// Stop a container, removing or freezing its jail (see [`StopMode`]),
+    /// and record `cause` as why it stopped
+    pub fn stop_container_with(&mut self, id: &ContainerId, mode: StopMode, cause: crate::container::StopCause) -> Result<(), StoreError> {
+        // Try to load from database if not in memory
+        if !self.containers.contains_key(id) {
+            if let Some(ref store) = self.store {
+                if let Ok(Some(store_container)) = store.get_container(id) {
+                    match self.load_container_from_store_row(store_container) {
+                        Ok(container) => {
+                            self.containers.insert(id.clone(), container);
+                            self.restore_container_jail(id);
+                        }
+                        Err(e) => {
+                            return Err(StoreError::SerializationError(format!("Failed to load container: {}", e)));
+                        }
+                    }
+                }
+            }
+        }
+
+        self.check_container_writable(id)?;
+
+        // Get the jail name first
+        let container = self.containers.get(id)
+            .ok_or_else(|| StoreError::SerializationError(format!("Container {} not found", id)))?;
+        let jail_name = container.jail_name.clone();
+
+        // Exec sessions would keep the jail busy through the stop
+        let killed = self.exec_sessions.kill_container(id);
+        if !killed.is_empty() {
+            info!(
+                target: "kawakaze::audit",
+                "Killed {} exec session(s) of container {} before stopping it: {}",
+                killed.len(),
+                container.display_name(),
+                killed.iter().map(|s| s.id.as_str()).collect::<Vec<_>>().join(", ")
+            );
+        }
+
+        let (read_only_dataset, tmpfs) = self.root_mounts(container);
+        let hosts_before = self.hosts_registry();
+
+        // Delegated datasets come back while the jail still exists
+        let (mut datasets, attachments) = self.attached_datasets(container);
+        if let Some(jail) = self.jails.get(&jail_name).filter(|jail| jail.is_running()) {
+            crate::dataset_attach::take_back(&mut datasets, jail.jid(), &attachments);
+        }
+
+        // Stop the jail
+        self.stop_jail_with(&jail_name, mode)
+            .map_err(|e| StoreError::SerializationError(e.to_string()))?;
+        self.release_ports(id);
+        self.release_root_mounts(id, read_only_dataset.as_deref(), &tmpfs);
+        crate::dataset_attach::release(&mut datasets, id, &attachments);
+
+        let stopped = self.record_container_stop(id, cause);
+        if let Some(container) = self.containers.get_mut(id) {
+            container.last_stop_exec_sessions = killed.len();
+            container.applied_sysctls.clear();
+        }
+        self.publish_hosts(&hosts_before);
+
+        self.write_through(Resource::Container(id.clone()), stopped)
+    }
+
+    /// Mark a container stopped for `cause`, with an audit line saying why;
+    /// returns the write that persists it
+    fn record_container_stop(&mut self, id: &ContainerId, cause: crate::container::StopCause) -> Write {
+        let mut at = Timestamp::now();
+        if let Some(container) = self.containers.get_mut(id) {
+            container.record_stop(cause);
+            at = container.stopped_at.unwrap_or(at);
+            info!(target: "kawakaze::audit", "Container {} stopped: {}", container.display_name(), cause);
+        }
+        Write::ContainerStopped { cause, at: at.as_secs() }
+    }
+
+    /// Publish `ports` for container `id`
+    ///
+    /// Fails with [`StoreError::PortInUse`] when another container publishes
+    /// one of them or a host service listens on it; nothing is claimed then.
+    pub fn reserve_ports(&mut self, id: &ContainerId, ports: &[crate::container::PortMapping]) -> Result<(), StoreError> {
+        for mapping in ports {
+            let (port, protocol) = (mapping.host_port, mapping.protocol);
+            match self.published_ports.owner(port, protocol) {
+                Some(owner) if owner == id => continue,
+                Some(owner) => {
+                    let owner = self.containers.get(owner).map_or(owner.as_str(), |c| c.display_name());
+                    return Err(StoreError::PortInUse(format!(
+                        "host port {}/{} already in use by container {}",
+                        port, protocol, owner
+                    )));
+                }
+                None if crate::container::host_port_bound(port, protocol) => {
+                    return Err(StoreError::PortInUse(format!("host port {}/{} already in use on the host", port, protocol)));
+                }
+                None => {}
+            }
+        }
+
+        self.published_ports.claim(id, ports);
+        if let Err(e) = self.persist_published_ports(id) {
+            self.published_ports.release(id);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Stop publishing the host ports of container `id`
+    pub fn release_ports(&mut self, id: &ContainerId) {
+        self.published_ports.release(id);
+        let write = self.published_ports_write(id);
+        self.persist(Resource::PublishedPorts(id.clone()), write);
+    }
+
+    fn persist_published_ports(&mut self, id: &ContainerId) -> Result<(), StoreError> {
+        let write = self.published_ports_write(id);
+        self.write_through(Resource::PublishedPorts(id.clone()), write)
+    }
+
+    fn published_ports_write(&self, id: &ContainerId) -> Write {
+        let ports = self.published_ports.ports_of(id);
+        Write::PublishedPorts(ports.iter().map(|(port, protocol)| (*port, protocol.to_string())).collect())
+    }
+
+    /// Apply a store write; a queued retry for the same resource is dropped
+    /// once a newer write goes through
+    fn write_through(&mut self, resource: Resource, write: Write) -> Result<(), StoreError> {
+        let Some(ref store) = self.store else {
+            return Ok(());
+        };
+        store.apply(&resource, &write)?;
+        self.pending_writes.resolve(&resource);
+        Ok(())
+    }
+
+    /// Apply a store write for a change that goes ahead regardless, queueing
+    /// it for retry if it fails
+    fn persist(&mut self, resource: Resource, write: Write) {
+        if let Err(e) = self.write_through(resource.clone(), write.clone()) {
+            warn!("Failed to {} {} in the database, will retry: {}", write.operation(), resource, e);
+            self.pending_writes.push(resource, write, &e, std::time::Instant::now());
+        }
+    }
+
+    /// Retry the failed store writes that are due; returns how many went
+    /// through
+    pub fn retry_pending_writes(&mut self) -> usize {
+        let Some(ref store) = self.store else {
+            return 0;
+        };
+        self.pending_writes.retry_due(store, std::time::Instant::now())
+    }
+
+    /// Retry every failed store write until they all went through or
+    /// `grace` has passed, for shutdown
+    pub async fn flush_pending_writes(&mut self, grace: std::time::Duration) {
+        let Some(ref store) = self.store else {
+            return;
+        };
+        if self.pending_writes.is_empty() {
+            return;
+        }
+        let lost = self.pending_writes.flush(store, grace).await;
+        if lost > 0 {
+            error!("{} database write(s) still failing at shutdown; those changes are lost", lost);
+        }
+    }
+
+    /// Length and age of the store write retry queue
+    pub fn pending_writes_info(&self) -> crate::persist_queue::QueueInfo {
+        self.pending_writes.info(std::time::Instant::now())
+    }
+
+    /// Remove a container
+    pub fn remove_container(&mut self, id: &ContainerId) -> Result<(), StoreError> {
+        self.remove_container_with(id, &RemovalOptions::default())
+    }
+
+    /// Remove a container, enforcing its protection policy
+    pub fn remove_container_with(&mut self, id: &ContainerId, options: &RemovalOptions) -> Result<(), StoreError> {
+        let teardown = self.begin_container_removal(id, options)?;
+        let zfs = self.zfs.clone();
+        Self::destroy_container_datasets(zfs.as_ref(), &teardown, &mut |_, _| {}, &mut |_, _| {});
+        self.finish_container_removal(teardown)
+    }
+
+    /// Take a container out of the manager: stop its jail and release its
+    /// network and ports. Its datasets and record are left for
+    /// `destroy_container_datasets` and `finish_container_removal`.
+    pub fn begin_container_removal(&mut self, id: &ContainerId, options: &RemovalOptions) -> Result<ContainerTeardown, StoreError> {
+        self.check_container_writable(id)?;
+        if let Some(container) = self.containers.get(id) {
+            options.check_protection("container", id, container.protected)?;
+        }
+
+        let hosts_before = self.hosts_registry();
+        let container = self.containers.remove(id)
+            .ok_or_else(|| StoreError::SerializationError(format!("Container {} not found", id)))?;
+        let dataset_missing = self.dataset_missing(&container);
+        self.datasets.forget(&container.dataset);
+
+        // Release network resources if we have a network configuration
+        if let Some(ref mut network_manager) = self.network_manager {
+            if let Some(network) = self.container_networks.remove(id) {
+                info!("Releasing network resources for container {}", id);
+                if let Err(e) = network_manager.release_network(&network) {
+                    warn!("Failed to release network for container {}: {}", id, e);
+                }
+            }
+        }
+
+        // Remove port forwarding if configured
+        if let Some(ref network_manager) = self.network_manager {
+            if let Some(ip) = container.ip.as_ref().or(container.ip6.as_ref()) {
+                info!("Removing port forwarding for container {}", id);
+                let _ = network_manager.remove_port_forwarding(ip);
+            }
+        }
+
+        // Stop if running
+        let (mut datasets, attachments) = self.attached_datasets(&container);
+        if container.is_running() {
+            if let Some(jail) = self.jails.get(&container.jail_name).filter(|jail| jail.is_running()) {
+                crate::dataset_attach::take_back(&mut datasets, jail.jid(), &attachments);
+            }
+            let _ = self.stop_jail(&container.jail_name);
+            // The tmpfs mounts would keep the dataset busy
+            let (read_only_dataset, tmpfs) = self.root_mounts(&container);
+            self.release_root_mounts(id, read_only_dataset.as_deref(), &tmpfs);
+        }
+        // Also what a crash left attached: external datasets go back to the
+        // host and are never destroyed with the container
+        crate::dataset_attach::release(&mut datasets, id, &attachments);
+        self.release_ports(id);
+
+        // Destroy jail
+        let _ = self.remove_jail(&container.jail_name);
+
+        Ok(ContainerTeardown {
+            id: id.clone(),
+            volumes: container.mounts.iter().filter(|m| m.mode() == VolumeMode::Anonymous).map(|m| m.source.clone()).collect(),
+            dataset: container.dataset,
+            dataset_missing,
+            hosts_before,
+        })
+    }
+
+    /// Unmount and destroy a removed container's datasets. Needs no manager,
+    /// so an async remove runs it without holding the lock. Failures are
+    /// logged, as the container is already gone.
+    pub fn destroy_container_datasets<D: crate::operation::DatasetOps>(
+        zfs: Option<&D>,
+        teardown: &ContainerTeardown,
+        report: &mut dyn FnMut(crate::operation::Phase, &str),
+        sample: &mut dyn FnMut(u64, u64),
+    ) {
+        use crate::operation::Phase;
+
+        let Some(zfs) = zfs else { return };
+
+        // Anonymous volumes are mounted inside the container's root
+        report(Phase::Unmounting, &format!("Unmounting {}", teardown.dataset));
+        for volume in &teardown.volumes {
+            let _ = zfs.unmount(volume);
+        }
+        if !teardown.dataset_missing {
+            let _ = zfs.unmount(&teardown.dataset);
+        }
 
-    /// Create a container from an image
-    pub fn create_container(&mut self, config: crate::container::ContainerConfig) -> Result<Container, StoreError> {
-        // Validate image exists
-        let image = self.get_image(&config.image_id)
-            .ok_or_else(|| StoreError::SerializationError(format!("Image {} not found", config.image_id)))?;
+        report(Phase::Destroying, &format!("Destroying {}", teardown.dataset));
+        for volume in &teardown.volumes {
+            if let Err(e) = zfs.destroy(volume) {
+                warn!("Failed to destroy volume {}: {}", volume, e);
+            }
+        }
+        if teardown.dataset_missing {
+            info!("Dataset {} of container {} is already gone", teardown.dataset, teardown.id);
+            return;
+        }
+        let destroyed = crate::operation::destroy_with_progress(zfs, &teardown.dataset, crate::operation::SAMPLE_INTERVAL, sample);
+        if let Err(e) = destroyed {
+            warn!("Failed to destroy dataset {} of container {}: {}", teardown.dataset, teardown.id, e);
+        }
+    }
 
-        // Generate container ID
-        let container_id = Container::generate_id();
-        let jail_name = format!("kawakaze-{}", &container_id[..8]);
-        let dataset = format!("{}/containers/{}", self.config.zfs_pool, &container_id[..8]);
+    /// Republish name resolution without a removed container and delete its
+    /// record
+    pub fn finish_container_removal(&mut self, teardown: ContainerTeardown) -> Result<(), StoreError> {
+        self.publish_hosts(&teardown.hosts_before);
 
-        // Create ZFS clone from image snapshot
-        if let Some(ref zfs) = self.zfs {
-            zfs.clone_snapshot(&image.snapshot, &dataset)?;
+        // Remove from database
+        if let Some(ref store) = self.store {
+            store.delete_container(&teardown.id)?;
         }
+        // Nothing left to bring up to date
+        self.pending_writes.resolve(&Resource::Container(teardown.id.clone()));
+        self.pending_writes.resolve(&Resource::PublishedPorts(teardown.id));
 
-        // Mount the container dataset to a directory so the jail can access the files
-        let container_mountpoint = std::path::PathBuf::from(format!("/var/db/kawakaze/containers/{}", &container_id[..8]));
-        if let Some(ref zfs) = self.zfs {
-            zfs.mount_dataset(&dataset, &container_mountpoint)
-                .map_err(|e| StoreError::SerializationError(format!("Failed to mount container dataset: {}", e)))?;
-        }
+        Ok(())
+    }
 
-        // Allocate network resources if network manager is available
-        let (container_ip, epair_jail) = if let Some(ref mut network_manager) = self.network_manager {
-            match network_manager.allocate_network(&jail_name) {
-                Ok(network) => {
-                    let ip = network.ip.clone();
-                    let epair_jail = network.epair_jail.clone();
-                    self.container_networks.insert(container_id.clone(), network);
-                    info!("Allocated IP {} for container {} (epair: {})", ip, container_id, epair_jail);
-                    (Some(ip), Some(epair_jail))
-                }
-                Err(e) => {
-                    warn!("Failed to allocate network for container {}: {}. Container will have no networking.", container_id, e);
-                    (None, None)
-                }
-            }
-        } else {
-            info!("No network manager available, container {} will have no networking", container_id);
-            (None, None)
-        };
+    /// Running containers on the network, by the names they answer to
+    /// (see [`crate::hosts`])
+    pub fn hosts_registry(&self) -> crate::hosts::Registry {
+        self.containers
+            .values()
+            .filter(|container| container.is_running())
+            .filter_map(|container| Some((container.id.clone(), Self::host_entry(container)?)))
+            .collect()
+    }
 
-        // Create the FreeBSD jail with the mounted path
-        // Set IP if allocated (VNET is automatically enabled when IP is set)
-        let jail = crate::jail::Jail::create(&jail_name)
-            .and_then(|j| j.with_path(&container_mountpoint))
-            .and_then(|j| {
-                // Set IP if allocated (this automatically enables VNET)
-                if let Some(ref ip) = container_ip {
-                    j.with_ip(ip)
-                } else {
-                    Ok(j)
-                }
-            })
-            .and_then(|j| {
-                // Set VNET interface if allocated (epair that goes into the jail)
-                if let Some(ref epair) = epair_jail {
-                    j.with_vnet_interface(epair)
-                } else {
-                    Ok(j)
-                }
-            })
-            .map_err(|e| StoreError::SerializationError(format!("Failed to create jail: {}", e)))?;
+    fn host_entry(container: &crate::container::Container) -> Option<crate::hosts::HostEntry> {
+        let ips = container.addresses();
+        if ips.is_empty() {
+            return None;
+        }
+        Some(crate::hosts::HostEntry {
+            ips,
+            name: container.display_name().to_string(),
+            aliases: container.network_aliases.clone(),
+        })
+    }
 
-        // Add to jails HashMap
-        self.jails.insert(jail_name.clone(), jail);
+    /// Rewrite the hosts of every running member if the registry changed
+    /// since `before`. Read-only roots keep what they got at start.
+    fn publish_hosts(&self, before: &crate::hosts::Registry) {
+        let after = self.hosts_registry();
+        let paths = self.paths();
+        let roots: Vec<PathBuf> = crate::hosts::rewrites(before, &after)
+            .iter()
+            .filter(|id| self.containers.get(*id).is_some_and(|c| !c.read_only_root))
+            .map(|id| paths.container_root(id))
+            .collect();
+        if !roots.is_empty() {
+            self.publish_hosts_to(&after, &roots);
+        }
+    }
 
-        // Create container with the pre-generated ID
-        let mut container = Container::new_with_id(container_id.clone(), config.image_id.clone(), jail_name, dataset)
-            .with_name(config.name.unwrap_or_else(|| container_id.clone()))
-            .with_restart_policy(config.restart_policy);
+    fn publish_hosts_to(&self, registry: &crate::hosts::Registry, roots: &[PathBuf]) {
+        use crate::hosts::NameResolver;
+        if let Err(e) = crate::hosts::HostsFile.publish(&self.config.network.bridge_name, registry, roots) {
+            warn!("Failed to update container hosts files: {}", e);
+        }
+    }
 
-        // Set IP if allocated
-        if let Some(ref ip) = container_ip {
-            container = container.with_ip(ip.clone());
+    /// Set or clear the protected flag on a container
+    pub fn set_container_protected(&mut self, id: &ContainerId, protected: bool) -> Result<(), StoreError> {
+        self.check_container_writable(id)?;
+        let container = self.containers.get_mut(id)
+            .ok_or_else(|| StoreError::SerializationError(format!("Container {} not found", id)))?;
+
+        if let Some(ref store) = self.store {
+            store.set_container_protected(id, protected)?;
         }
 
-        // Add port mappings
-        for port_mapping in &config.ports {
-            container = container.with_port_mapping(port_mapping.clone());
+        container.protected = protected;
+        Ok(())
+    }
+
+    /// The dataset made read-only (only with ZFS) and the tmpfs mounted
+    /// while `container` runs (see [`read_only`])
+    fn root_mounts(&self, container: &Container) -> (Option<String>, Vec<crate::read_only::TmpfsTarget>) {
+        let read_only_dataset = (container.read_only_root && self.zfs.is_some()).then(|| container.dataset.clone());
+        let configured = if read_only_dataset.is_some() { self.config.read_only.tmpfs.as_slice() } else { &[] };
+        let root = self.paths().container_root(&container.id);
+        (read_only_dataset, crate::read_only::tmpfs_targets(&root, configured, &container.tmpfs))
+    }
+
+    /// A container's external datasets (see [`dataset_attach`]) and the ZFS
+    /// operations on them
+    fn attached_datasets(&self, container: &Container) -> (crate::dataset_attach::HostDatasets, Vec<crate::dataset_attach::Attachment>) {
+        let root = self.paths().container_root(&container.id);
+        let attachments = crate::dataset_attach::attachments(&root, &container.mounts);
+        (crate::dataset_attach::HostDatasets { zfs: self.zfs.clone() }, attachments)
+    }
+
+    /// Unmount a stopped container's tmpfs and make its root writable again
+    fn release_root_mounts(&self, id: &ContainerId, read_only_dataset: Option<&str>, tmpfs: &[crate::read_only::TmpfsTarget]) {
+        if read_only_dataset.is_none() && tmpfs.is_empty() {
+            return;
+        }
+        let mut fs = crate::read_only::HostRootFs { zfs: self.zfs.clone() };
+        if let Err(e) = crate::read_only::stop(&mut fs, read_only_dataset, tmpfs) {
+            warn!("Container {}: {}", id, e);
         }
+    }
 
-        // Store in database
-        if let Some(ref store) = self.store {
-            // Serialize command to JSON for storage (before we move it to the container)
-            let command_json = config.command
-                .as_ref()
-                .map(|cmd| serde_json::to_string(&cmd)
-                    .map_err(|e| StoreError::SerializationError(e.to_string())))
-                .transpose()?;
+    /// Set or clear the read-only root flag on a container; it applies from
+    /// the next start
+    pub fn set_container_read_only_root(&mut self, id: &ContainerId, read_only_root: bool) -> Result<(), StoreError> {
+        self.check_container_writable(id)?;
+        let container = self.containers.get_mut(id)
+            .ok_or_else(|| StoreError::SerializationError(format!("Container {} not found", id)))?;
 
-            let store_container = crate::store::Container {
-                id: container.id.clone(),
-                name: container.name.clone(),
-                image_id: container.image_id.clone(),
-                jail_name: container.jail_name.clone(),
-                dataset: container.dataset.clone(),
-                state: crate::store::ContainerState::Created,
-                restart_policy: container.restart_policy.as_str().to_string(),
-                mounts: serde_json::to_string(&container.mounts)
-                    .map_err(|e| StoreError::SerializationError(e.to_string()))?,
-                port_mappings: serde_json::to_string(&container.port_mappings)
-                    .map_err(|e| StoreError::SerializationError(e.to_string()))?,
-                ip: container.ip.clone(),
-                command: command_json,
-                created_at: container.created_at,
-                started_at: container.started_at,
-            };
-            store.insert_container(&store_container)?;
+        if let Some(ref store) = self.store {
+            store.set_container_read_only_root(id, read_only_root)?;
         }
 
-        // Set command if provided (after database storage since we move the value)
-        if let Some(command) = config.command {
-            container = container.with_command(command);
+        container.read_only_root = read_only_root;
+        Ok(())
+    }
+
+    /// Set or clear a container's description
+    pub fn set_container_description(&mut self, id: &ContainerId, description: Option<String>) -> Result<(), StoreError> {
+        self.check_container_writable(id)?;
+        let container = self.containers.get_mut(id)
+            .ok_or_else(|| StoreError::SerializationError(format!("Container {} not found", id)))?;
+
+        if let Some(ref store) = self.store {
+            store.set_container_description(id, description.as_deref())?;
         }
 
-        self.containers.insert(container_id.clone(), container.clone());
-        Ok(container)
+        container.description = description;
+        Ok(())
     }
 
-    /// Start a container
-    pub fn start_container(&mut self, id: &ContainerId) -> Result<(), StoreError> {
-        // Try to load from database if not in memory
-        if !self.containers.contains_key(id) {
-            if let Some(ref store) = self.store {
-                if let Ok(Some(store_container)) = store.get_container(id) {
-                    match self.load_container_from_store_row(store_container) {
-                        Ok(container) => {
-                            self.containers.insert(id.clone(), container);
-                        }
-                        Err(e) => {
-                            return Err(StoreError::SerializationError(format!("Failed to load container: {}", e)));
-                        }
-                    }
-                }
-            }
+    /// Set or clear the start-at-boot flag on a container
+    pub fn set_container_boot(&mut self, id: &ContainerId, boot: bool) -> Result<(), StoreError> {
+        self.check_container_writable(id)?;
+        let container = self.containers.get_mut(id)
+            .ok_or_else(|| StoreError::SerializationError(format!("Container {} not found", id)))?;
+
+        if let Some(ref store) = self.store {
+            store.set_container_boot(id, boot)?;
         }
 
-        // Clone the data we need before starting the jail
-        let (jail_name, command, port_mappings, ip) = {
-            let container = self.containers.get(id)
-                .ok_or_else(|| StoreError::SerializationError(format!("Container {} not found", id)))?;
-            (
-                container.jail_name.clone(),
-                container.command.clone(),
-                container.port_mappings.clone(),
-                container.ip.clone(),
-            )
-        };
+        container.boot = boot;
+        Ok(())
+    }
 
-        // Start the jail
-        self.start_jail(&jail_name)
-            .map_err(|e| StoreError::SerializationError(e.to_string()))?;
+    /// Rename a container. A host name that followed the old name follows
+    /// the new one: a running jail is updated in place (see
+    /// [`Jail::set_hostname`]), a stopped one gets it at its next start.
+    /// An explicit host name is left alone.
+    pub fn rename_container(&mut self, id: &ContainerId, name: String) -> Result<(), StoreError> {
+        self.check_container_writable(id)?;
+        let hosts_before = self.hosts_registry();
+        let container = self.containers.get_mut(id)
+            .ok_or_else(|| StoreError::SerializationError(format!("Container {} not found", id)))?;
 
-        // Configure network if we have a network configuration for this container
-        if let Some(ref network_manager) = self.network_manager {
-            if let Some(network) = self.container_networks.get(id) {
-                info!("Configuring network for container {}", id);
-                if let Err(e) = network_manager.configure_jail_network(&jail_name, network) {
-                    warn!("Failed to configure network for container {}: {}", id, e);
-                }
-            }
+        if let Some(ref store) = self.store {
+            store.set_container_name(id, &name)?;
         }
 
-        // Set up port forwarding if we have port mappings
-        if let Some(ref network_manager) = self.network_manager {
-            for port_mapping in &port_mappings {
-                if let Some(ref container_ip) = ip {
-                    info!("Setting up port forwarding for container {}: {} -> {}:{} ({})",
-                          id, port_mapping.host_port, container_ip, port_mapping.container_port, port_mapping.protocol);
-                    if let Err(e) = network_manager.setup_port_forwarding(
-                        container_ip,
-                        port_mapping.host_port,
-                        port_mapping.container_port,
-                        port_mapping.protocol.as_str(),
-                    ) {
-                        warn!("Failed to set up port forwarding for container {}: {}", id, e);
-                    }
-                }
-            }
+        let hostname = container.rename(name);
+        let jail_name = container.jail_name.clone();
+        if let Some(hostname) = hostname
+            && let Some(jail) = self.jails.get_mut(&jail_name)
+            && let Err(e) = jail.set_hostname(&hostname)
+        {
+            warn!("Container {} keeps its old host name until restarted: {}", id, e);
         }
 
-        // Execute command if specified
-        if let Some(cmd) = command {
-            if !cmd.is_empty() {
-                let jail = self.jails.get(&jail_name)
-                    .ok_or_else(|| StoreError::SerializationError(format!("Jail {} not found", jail_name)))?;
+        // The name is how other containers reach it
+        self.publish_hosts(&hosts_before);
+        Ok(())
+    }
 
-                let (program, args) = cmd.split_first().unwrap_or((&cmd[0], &[]));
-                let args: Vec<String> = args.to_vec();
+    /// Add an `ip4.addr` or `ip6.addr` alias to a container's jail
+    ///
+    /// Only for jails without VNET, which share the host's stack and use
+    /// whatever addresses `ip4.addr` and `ip6.addr` list. A jail in the
+    /// kernel gets the address in place (see [`Jail::update_params`]); a
+    /// stopped container at its next start. The address must be configured
+    /// on a host interface to carry traffic.
+    pub fn add_container_ip(&mut self, id: &ContainerId, ip: std::net::IpAddr) -> Result<(), StoreError> {
+        let container = self.containers.get(id)
+            .ok_or_else(|| StoreError::SerializationError(format!("Container {} not found", id)))?;
+        if container.ip.is_some() || container.ip6.is_some() {
+            return Err(StoreError::SerializationError(format!("Container {} has its own network stack (VNET)", id)));
+        }
+        let ip = ip.to_string();
+        if container.ip_aliases.contains(&ip) {
+            return Err(StoreError::SerializationError(format!("Container {} already has {}", id, ip)));
+        }
 
-                info!("Executing command in container {}: {} {:?}", id, program, args);
+        let mut ip_aliases = container.ip_aliases.clone();
+        ip_aliases.push(ip);
+        self.set_container_ip_aliases(id, ip_aliases)
+    }
 
-                // Execute the command in the jail
-                jail.exec(program, &args)
-                    .map_err(|e| StoreError::SerializationError(format!("Failed to execute command: {}", e)))?;
-            }
+    /// Remove an `ip4.addr` alias added with [`JailManager::add_container_ip`]
+    pub fn remove_container_ip(&mut self, id: &ContainerId, ip: std::net::IpAddr) -> Result<(), StoreError> {
+        let container = self.containers.get(id)
+            .ok_or_else(|| StoreError::SerializationError(format!("Container {} not found", id)))?;
+        let ip = ip.to_string();
+        if !container.ip_aliases.contains(&ip) {
+            return Err(StoreError::SerializationError(format!("Container {} has no address {}", id, ip)));
         }
 
-        // Update state
-        if let Some(container) = self.containers.get_mut(id) {
-            container.set_state(crate::container::ContainerState::Running);
+        let ip_aliases = container.ip_aliases.iter().filter(|alias| **alias != ip).cloned().collect();
+        self.set_container_ip_aliases(id, ip_aliases)
+    }
+
+    /// Replace a container's `ip4.addr` and `ip6.addr` lists: live first,
+    /// so a change the kernel refuses isn't recorded, then in the store
+    fn set_container_ip_aliases(&mut self, id: &ContainerId, ip_aliases: Vec<String>) -> Result<(), StoreError> {
+        self.check_container_writable(id)?;
+        let container = self.containers.get_mut(id)
+            .ok_or_else(|| StoreError::SerializationError(format!("Container {} not found", id)))?;
+
+        if let Some(jail) = self.jails.get(&container.jail_name)
+            && jail.jid() >= 0
+        {
+            let params = crate::container::ip_alias_params(&container.ip_aliases, &ip_aliases);
+            jail.update_params(&params.iter().map(|(param, value)| (*param, value.as_str())).collect::<Vec<_>>())
+                .map_err(|e| StoreError::SerializationError(e.to_string()))?;
         }
 
-        // Persist to database
         if let Some(ref store) = self.store {
-            store.update_container(id, crate::store::ContainerState::Running)?;
+            let json = serde_json::to_string(&ip_aliases).map_err(|e| StoreError::SerializationError(e.to_string()))?;
+            store.set_container_ip_aliases(id, &json)?;
         }
-
+        container.ip_aliases = ip_aliases;
         Ok(())
     }
 
-    /// Stop a container
-    pub fn stop_container(&mut self, id: &ContainerId) -> Result<(), StoreError> {
-        // Try to load from database if not in memory
-        if !self.containers.contains_key(id) {
+    /// Containers flagged to start at boot, in the order they are started
+    pub fn boot_containers(&self) -> Vec<&Container> {
+        crate::boot::boot_order(self.containers.values())
+    }
+
+    /// Outcome of the `--boot` start, if there was one
+    pub fn boot_report(&self) -> Option<&crate::boot::BootReport> {
+        self.boot_report.as_ref()
+    }
+
+    /// Start again the containers that were running when the daemon went
+    /// down and whose policy asks for it (see
+    /// [`crate::boot::needs_restart_on_boot`]), in boot order. Returns the
+    /// ones started; the others are recorded as stopped.
+    pub fn restart_on_boot(&mut self, starter: &impl crate::boot::ContainerStarter) -> Vec<ContainerId> {
+        let candidates: Vec<&Container> = self.containers.values()
+            .filter(|c| {
+                // Restarting would write a record a newer version owns
+                if c.written_by_newer.is_some() {
+                    warn!("Not restarting container {}: {}", c.id, crate::version::READONLY_REASON);
+                    return false;
+                }
+                let jail_running = self.jails.get(&c.jail_name).is_some_and(|j| j.state() == JailState::Running);
+                crate::boot::needs_restart_on_boot(c, jail_running)
+            })
+            .collect();
+        let candidates: Vec<ContainerId> = crate::boot::start_order(candidates).into_iter().map(|c| c.id.clone()).collect();
+        info!("Restarting {} container(s) that were running before", candidates.len());
+
+        let mut started = Vec::new();
+        for id in candidates {
+            // Its jail is gone; it is stopped until the start succeeds
+            if let Some(container) = self.containers.get_mut(&id) {
+                container.set_state(crate::container::ContainerState::Stopped);
+            }
+            match starter.start(self, &id) {
+                Ok(()) => started.push(id),
+                Err(e) => {
+                    error!("Failed to restart container {}: {}", id, e);
+                    let stopped = self.record_container_stop(&id, crate::container::StopCause::RestartPolicyGaveUp);
+                    self.persist(Resource::Container(id.clone()), stopped);
+                }
+            }
+        }
+        started
+    }
+
+    /// Processes running in a container, from the host's `ps -J`
+    pub fn container_top(&self, id: &ContainerId) -> Result<Vec<crate::top::ProcessInfo>, StoreError> {
+        let container = self.containers.get(id)
+            .ok_or_else(|| StoreError::SerializationError(format!("Container {} not found", id)))?;
+        if !container.is_running() {
+            return Err(StoreError::InvalidState(format!("Container {} is not running", container.display_name())));
+        }
+
+        crate::top::jail_processes(&container.jail_name).map_err(StoreError::SerializationError)
+    }
+
+    /// Get a container by ID
+    pub fn get_container(&self, id: &ContainerId) -> Option<&Container> {
+        self.containers.get(id).or_else(|| {
+            // Try to load from database if not in memory
             if let Some(ref store) = self.store {
                 if let Ok(Some(store_container)) = store.get_container(id) {
-                    match self.load_container_from_store_row(store_container) {
-                        Ok(container) => {
-                            self.containers.insert(id.clone(), container);
-                        }
-                        Err(e) => {
-                            return Err(StoreError::SerializationError(format!("Failed to load container: {}", e)));
-                        }
-                    }
+                    // Note: Same limitation as with images
+                    return None;
                 }
             }
-        }
+            None
+        })
+    }
 
-        // Get the jail name first
-        let jail_name = self.containers.get(id)
-            .ok_or_else(|| StoreError::SerializationError(format!("Container {} not found", id)))?
-            .jail_name.clone();
+    /// Resolve a container ID or ID prefix (see [`names::resolve_by_prefix`])
+    pub fn resolve_container_prefix(&self, prefix: &str) -> Result<&Container, names::ResolveError> {
+        names::resolve_by_prefix(self.containers.values(), |c| &c.id, prefix)
+    }
 
-        // Stop the jail
-        self.stop_jail(&jail_name)
-            .map_err(|e| StoreError::SerializationError(e.to_string()))?;
+    /// Resolve a container reference by exact ID, exact name, then unique ID
+    /// prefix (see [`names::resolve_reference`])
+    pub fn resolve_container(&self, reference: &str) -> Result<&Container, names::ResolveError> {
+        names::resolve_reference(self.containers.values(), |c| &c.id, |c| c.name.as_deref(), reference)
+    }
 
-        // Update state
-        if let Some(container) = self.containers.get_mut(id) {
-            container.set_state(crate::container::ContainerState::Stopped);
+    /// Get a container by ID prefix (supports short IDs like "faeb9f1b-b05")
+    /// Returns None if no container matches, the prefix is ambiguous or it
+    /// is shorter than [`names::MIN_PREFIX_LEN`].
+    pub fn get_container_by_prefix(&self, prefix: &str) -> Option<&Container> {
+        match self.resolve_container_prefix(prefix) {
+            Ok(container) => Some(container),
+            Err(e @ names::ResolveError::Ambiguous { .. }) => {
+                warn!("Container lookup: {}", e);
+                None
+            }
+            Err(_) => None,
+        }
+    }
+
+    /// Check a name for a new container or image
+    ///
+    /// Names may not look like an ID or be a prefix of a container or image
+    /// ID, so resolving a reference by name never competes with its ID forms
+    /// (see [`names`]).
+    pub fn check_new_name(&self, name: &str) -> Result<(), names::NameError> {
+        let ids = self.containers.keys().chain(self.creating.keys()).chain(self.images.keys()).map(String::as_str);
+        names::check_name(name, &self.config.names, ids)
+    }
+
+    /// Check a name for a new image: its form ([`names::check_image_name`]),
+    /// the rules of [`check_new_name`](Self::check_new_name), and that no
+    /// other spelling of the same `name:tag` (`web` and `web:latest`) is
+    /// taken. An image of exactly this name is untagged by the new one.
+    pub fn check_new_image_name(&self, name: &str) -> Result<(), names::NameError> {
+        names::check_image_name(name)?;
+        self.check_new_name(name)?;
+
+        // Builds in flight count too: they tag their name when they finish
+        let canonical = names::canonical_image_name(name);
+        let taken = self.images.values()
+            .filter(|i| !i.is_untagged())
+            .map(|i| i.name.as_str())
+            .chain(self.active_builds.keys().map(String::as_str))
+            .find(|other| *other != name && names::canonical_image_name(other) == canonical);
+        match taken {
+            Some(existing) => Err(names::NameError::TagTaken { name: name.to_string(), existing: existing.to_string() }),
+            None => Ok(()),
         }
+    }
+
+    /// Draw a container or image ID that no existing name is a prefix of
+    pub fn unique_id(&self, generate: impl FnMut() -> String) -> Result<String, names::NameError> {
+        let taken: Vec<&str> = self.containers.values()
+            .filter_map(|c| c.name.as_deref())
+            .chain(self.images.values().map(|i| i.name.as_str()))
+            .collect();
+        names::unique_id(generate, &taken)
+    }
+
+    /// List all containers
+    pub fn list_containers(&self) -> Vec<&Container> {
+        let mut containers: Vec<&Container> = self.containers.values().collect();
 
-        // Persist to database
+        // Also include containers from database
         if let Some(ref store) = self.store {
-            store.update_container(id, crate::store::ContainerState::Stopped)?;
+            if let Ok(db_containers) = store.list_containers() {
+                debug!("Found {} containers in database (not yet loaded)", db_containers.len());
+            }
         }
 
-        Ok(())
+        containers
     }
 
-    /// Remove a container
-    pub fn remove_container(&mut self, id: &ContainerId) -> Result<(), StoreError> {
-        let container = self.containers.remove(id)
-            .ok_or_else(|| StoreError::SerializationError(format!("Container {} not found", id)))?;
+    /// Names, then short IDs, of the containers or images `kind` selects,
+    /// each group sorted; read from memory only, as shell completion asks
+    /// on every TAB
+    pub fn completion_names(&self, kind: crate::api::NameKind) -> Vec<String> {
+        use crate::api::NameKind;
+        use crate::container::ContainerState;
+
+        let (mut names, mut ids): (Vec<String>, Vec<String>) = match kind {
+            NameKind::Images => (
+                self.images.values().map(|image| image.name.clone()).filter(|name| !name.is_empty()).collect(),
+                self.images.values().map(|image| crate::names::short_id(&image.id).to_string()).collect(),
+            ),
+            _ => {
+                let selected: Vec<&Container> = self
+                    .containers
+                    .values()
+                    .filter(|container| match kind {
+                        NameKind::Running => matches!(container.state, ContainerState::Running | ContainerState::Paused),
+                        NameKind::Stopped => matches!(container.state, ContainerState::Created | ContainerState::Stopped),
+                        _ => container.state != ContainerState::Removing,
+                    })
+                    .collect();
+                // A container created without a name is named by its ID
+                (
+                    selected.iter().filter_map(|container| container.name.clone().filter(|name| *name != container.id)).collect(),
+                    selected.iter().map(|container| crate::names::short_id(&container.id).to_string()).collect(),
+                )
+            }
+        };
+        names.sort();
+        ids.sort();
+        names.extend(ids);
+        names
+    }
 
-        // Release network resources if we have a network configuration
-        if let Some(ref mut network_manager) = self.network_manager {
-            if let Some(network) = self.container_networks.remove(id) {
-                info!("Releasing network resources for container {}", id);
-                if let Err(e) = network_manager.release_network(&network) {
-                    warn!("Failed to release network for container {}: {}", id, e);
+    /// Count containers by state
+    ///
+    /// Covers the loaded containers and any rows in the database that
+    /// haven't been loaded, so the counts match what a restart would show.
+    pub fn summarize_containers(&self) -> crate::container::ContainerSummary {
+        let mut summary = crate::container::ContainerSummary::default();
+        for container in self.containers.values() {
+            summary.add(container.state);
+        }
+
+        if let Some(ref store) = self.store {
+            match store.list_containers() {
+                Ok(rows) => {
+                    for row in rows.iter().filter(|row| !self.containers.contains_key(&row.id)) {
+                        if let Ok(state) = row.state.as_str().parse() {
+                            summary.add(state);
+                        }
+                    }
                 }
+                Err(e) => warn!("Failed to read containers for summary: {}", e),
             }
         }
 
-        // Remove port forwarding if configured
-        if let Some(ref network_manager) = self.network_manager {
-            if let Some(ref ip) = container.ip {
-                info!("Removing port forwarding for container {}", id);
-                let _ = network_manager.remove_port_forwarding(ip);
+        summary
+    }
+
+    /// Containers created from an image, oldest first: the loaded ones
+    /// plus database rows that aren't loaded
+    pub fn containers_for_image(&self, image_id: &str) -> Vec<Container> {
+        let mut containers: Vec<Container> = self.containers.values()
+            .filter(|container| container.image_id == image_id)
+            .cloned()
+            .collect();
+
+        if let Some(ref store) = self.store {
+            match store.containers_for_image(image_id) {
+                Ok(rows) => {
+                    for row in rows.into_iter().filter(|row| !self.containers.contains_key(&row.id)) {
+                        match self.load_container_from_store_row(row) {
+                            Ok(container) => containers.push(container),
+                            Err(e) => warn!("Skipping unreadable container row of image {}: {}", image_id, e),
+                        }
+                    }
+                }
+                Err(e) => warn!("Failed to read containers of image {}: {}", image_id, e),
             }
         }
 
-        // Stop if running
-        if container.is_running() {
-            let _ = self.stop_jail(&container.jail_name);
-        }
+        containers.sort_by(|a, b| a.created_at.cmp(&b.created_at).then_with(|| a.id.cmp(&b.id)));
+        containers
+    }
 
-        // Destroy jail
-        let _ = self.remove_jail(&container.jail_name);
+    // Orphaned dataset methods
 
-        // Unmount and destroy ZFS dataset
-        if let Some(ref zfs) = self.zfs {
-            // Unmount the dataset first
-            let _ = zfs.unmount_dataset(&container.dataset);
-            // Then destroy it
-            let _ = zfs.destroy(&container.dataset);
-        }
+    fn containers_root(&self) -> String {
+        format!("{}/containers", self.config.zfs_pool)
+    }
 
-        // Remove from database
-        if let Some(ref store) = self.store {
-            store.delete_container(id)?;
-        }
+    fn images_root(&self) -> String {
+        format!("{}/images", self.config.zfs_pool)
+    }
 
-        Ok(())
+    /// Datasets that records (or in-flight builds) account for
+    fn known_datasets(&self) -> HashSet<String> {
+        let images_root = self.images_root();
+
+        self.containers.values()
+            .map(|container| container.dataset.clone())
+            .chain(self.images.values().map(|image| {
+                image.snapshot.split_once('@').map_or(image.snapshot.as_str(), |(dataset, _)| dataset).to_string()
+            }))
+            .chain(self.active_builds.keys().map(|name| format!("{}/build-{}", images_root, name)))
+            .collect()
     }
 
-    /// Get a container by ID
-    pub fn get_container(&self, id: &ContainerId) -> Option<&Container> {
-        self.containers.get(id).or_else(|| {
-            // Try to load from database if not in memory
-            if let Some(ref store) = self.store {
-                if let Ok(Some(store_container)) = store.get_container(id) {
-                    // Note: Same limitation as with images
-                    return None;
-                }
-            }
-            None
+    /// Diff `zfs list` trees of the containers and images roots against the
+    /// known records
+    pub fn orphans_in(&self, container_tree: &[DatasetInfo], image_tree: &[DatasetInfo]) -> Vec<Orphan> {
+        let known = self.known_datasets();
+        let mut orphans = crate::orphans::find_orphans(&self.containers_root(), OrphanKind::Container, container_tree, &known);
+        orphans.extend(crate::orphans::find_orphans(&self.images_root(), OrphanKind::Image, image_tree, &known));
+        orphans
+    }
+
+    /// List datasets under the containers/images roots that no record uses
+    pub fn find_orphans(&self) -> Result<Vec<Orphan>, OrphanError> {
+        let zfs = self.zfs.as_ref().ok_or_else(|| OrphanError::Zfs("ZFS is not available".to_string()))?;
+
+        let list = |root: &str| match zfs.list_tree(root, 2) {
+            Ok(entries) => Ok(entries),
+            Err(crate::zfs::ZfsError::DatasetNotFound(_)) => Ok(Vec::new()),
+            Err(e) => Err(OrphanError::Zfs(e.to_string())),
+        };
+
+        Ok(self.orphans_in(&list(&self.containers_root())?, &list(&self.images_root())?))
+    }
+
+    /// A snapshot collection of the containers/images roots against the
+    /// current records, to run without the lock; `None` without ZFS
+    pub fn snapshot_gc(&self) -> Option<crate::snapshot_gc::SnapshotGc<Zfs>> {
+        let zfs = self.zfs.clone()?;
+        let retention_days = self.config.snapshot_gc.pre_upgrade_retention_days;
+        Some(crate::snapshot_gc::SnapshotGc {
+            store: zfs,
+            roots: vec![self.containers_root(), self.images_root()],
+            images_root: self.images_root(),
+            references: crate::snapshot_gc::references(self.images.values()),
+            policy: crate::snapshot_gc::Policy {
+                keep_last: self.config.storage.keep_snapshots,
+                pre_upgrade_retention_secs: i64::try_from(retention_days.saturating_mul(86_400)).unwrap_or(i64::MAX),
+            },
         })
     }
 
-    /// Get a container by ID prefix (supports short IDs like "faeb9f1b-b05")
-    /// Returns the first container whose ID starts with the given prefix.
-    /// Returns None if multiple containers match the prefix (ambiguous).
-    pub fn get_container_by_prefix(&self, prefix: &str) -> Option<&Container> {
-        let matches: Vec<&Container> = self.containers.values()
-            .filter(|c| c.id.starts_with(prefix))
-            .collect();
+    /// Register an orphan from a listing as a Created container (needs
+    /// `image`) or an Available image (needs `name` and a snapshot)
+    ///
+    /// Returns the new container or image ID.
+    pub fn adopt_orphan_from(
+        &mut self,
+        orphans: &[Orphan],
+        dataset: &str,
+        name: Option<String>,
+        image: Option<&str>,
+    ) -> Result<String, OrphanError> {
+        let orphan = crate::orphans::find_listed(orphans, dataset)?;
+        let invalid = |reason: &str| OrphanError::Invalid(dataset.to_string(), reason.to_string());
+
+        match orphan.kind {
+            OrphanKind::Container => {
+                let image = image.ok_or_else(|| invalid("an image is required to adopt a container"))?;
+                let image_id = self.get_image(&image.to_string())
+                    .or_else(|| self.get_image_by_name(image))
+                    .or_else(|| self.get_image_by_prefix(image))
+                    .map(|image| image.id.clone())
+                    .ok_or_else(|| invalid(&format!("image '{}' not found", image)))?;
+
+                if let Some(ref name) = name
+                    && self.containers.values().any(|c| c.name.as_deref() == Some(name.as_str()))
+                {
+                    return Err(OrphanError::Conflict(dataset.to_string(), format!("container name '{}' is taken", name)));
+                }
 
-        match matches.len() {
-            0 => None,
-            1 => Some(matches[0]),
-            _ => {
-                // Multiple matches - ambiguous
-                warn!("Ambiguous container prefix '{}': matches {} containers", prefix, matches.len());
-                None
+                let id = crate::orphans::adopted_container_id(orphan)?;
+                let jail_name = format!("kawakaze-{}", &id[..8]);
+                let mut container = Container::new_with_id(id.clone(), image_id, jail_name, orphan.dataset.clone())
+                    .with_name(name.unwrap_or_else(|| id.clone()));
+                container.created_at = orphan.created_at;
+
+                if let Some(ref store) = self.store {
+                    let row = Self::container_row(&container, None).map_err(|e| OrphanError::Store(e.to_string()))?;
+                    store.insert_container(&row).map_err(|e| OrphanError::Store(e.to_string()))?;
+                }
+
+                warn!(target: "kawakaze::audit", "Adopted orphaned dataset {} as container {}", dataset, id);
+                self.containers.insert(id.clone(), container);
+                self.restore_container_jail(&id);
+                Ok(id)
             }
-        }
-    }
+            OrphanKind::Image => {
+                let name = name.ok_or_else(|| invalid("a name is required to adopt an image"))?;
+                let snapshot = orphan.latest_snapshot()
+                    .ok_or_else(|| invalid("dataset has no snapshot to use as the image"))?;
 
-    /// List all containers
-    pub fn list_containers(&self) -> Vec<&Container> {
-        let mut containers: Vec<&Container> = self.containers.values().collect();
+                if self.get_image_by_name(&name).is_some() {
+                    return Err(OrphanError::Conflict(dataset.to_string(), format!("image name '{}' is taken", name)));
+                }
+                match self.check_new_image_name(&name) {
+                    Ok(()) => {}
+                    Err(e @ names::NameError::TagTaken { .. }) => return Err(OrphanError::Conflict(dataset.to_string(), e.to_string())),
+                    Err(e) => return Err(invalid(&e.to_string())),
+                }
 
-        // Also include containers from database
-        if let Some(ref store) = self.store {
-            if let Ok(db_containers) = store.list_containers() {
-                debug!("Found {} containers in database (not yet loaded)", db_containers.len());
+                let mut image = Image::new(name, Vec::new())
+                    .with_snapshot(snapshot)
+                    .with_size(orphan.size_bytes)
+                    .with_state(crate::image::ImageState::Available);
+                image.created_at = orphan.created_at;
+                let id = image.id.clone();
+
+                self.add_image(image).map_err(|e| OrphanError::Store(e.to_string()))?;
+                warn!(target: "kawakaze::audit", "Adopted orphaned dataset {} as image {}", dataset, id);
+                Ok(id)
             }
         }
+    }
 
-        containers
+    /// Adopt an orphan from a fresh listing
+    pub fn adopt_orphan(&mut self, dataset: &str, name: Option<String>, image: Option<&str>) -> Result<String, OrphanError> {
+        let orphans = self.find_orphans()?;
+        self.adopt_orphan_from(&orphans, dataset, name, image)
+    }
+
+    /// Destroy an orphan from a listing, refusing anything that isn't listed
+    /// or lies outside the managed roots
+    pub fn destroy_orphan_from(&mut self, orphans: &[Orphan], dataset: &str) -> Result<(), OrphanError> {
+        let orphan = crate::orphans::find_listed(orphans, dataset)?;
+        let (containers_root, images_root) = (self.containers_root(), self.images_root());
+        crate::orphans::check_scope(&orphan.dataset, &[&containers_root, &images_root])?;
+
+        let zfs = self.zfs.as_ref().ok_or_else(|| OrphanError::Zfs("ZFS is not available".to_string()))?;
+        zfs.destroy(&orphan.dataset).map_err(|e| OrphanError::Zfs(e.to_string()))?;
+
+        warn!(target: "kawakaze::audit", "Destroyed orphaned dataset {}", dataset);
+        Ok(())
+    }
+
+    /// Destroy an orphan from a fresh listing
+    pub fn destroy_orphan(&mut self, dataset: &str) -> Result<(), OrphanError> {
+        let orphans = self.find_orphans()?;
+        self.destroy_orphan_from(&orphans, dataset)
     }
 }
 
@@ -1074,10 +3404,66 @@ mod tests {
         assert_eq!(manager.jail_count(), 0);
     }
 
+    #[test]
+    fn test_unique_id_avoids_existing_names() {
+        let mut manager = JailManager::new("/tmp/test.sock");
+        let image = Image::new("cafe01".to_string(), Vec::new());
+        let container = Container::new(image.id.clone(), "kawakaze-web".to_string(), "tank/containers/web".to_string())
+            .with_name("web".to_string());
+        manager.images.insert(image.id.clone(), image);
+        manager.containers.insert(container.id.clone(), container);
+
+        // IDs starting with a container or image name are drawn again
+        let mut drawn = ["web0a1b2-0000", "cafe0123-0000", "0a1b2c3d-0000"].into_iter();
+        let id = manager.unique_id(|| drawn.next().unwrap().to_string()).unwrap();
+        assert_eq!(id, "0a1b2c3d-0000");
+    }
+
+    #[test]
+    fn test_publishing_same_host_port_twice_conflicts() {
+        use crate::container::{PortMapping, PortProtocol};
+
+        let port = std::net::TcpListener::bind((std::net::Ipv4Addr::UNSPECIFIED, 0)).unwrap().local_addr().unwrap().port();
+        let mut manager = JailManager::new("/tmp/test.sock");
+        let mut ids = Vec::new();
+        for name in ["web", "api"] {
+            let container = Container::new("img".to_string(), format!("kawakaze-{}", name), format!("tank/containers/{}", name))
+                .with_name(name.to_string());
+            ids.push(container.id.clone());
+            manager.containers.insert(container.id.clone(), container);
+        }
+        let mapping = [PortMapping::new(port, 80, PortProtocol::Tcp)];
+
+        manager.reserve_ports(&ids[0], &mapping).unwrap();
+        // Claiming again for the same container is fine
+        manager.reserve_ports(&ids[0], &mapping).unwrap();
+        let err = manager.reserve_ports(&ids[1], &mapping).unwrap_err();
+        assert_eq!(err.to_string(), format!("host port {}/tcp already in use by container web", port));
+
+        // Another protocol is another port
+        manager.reserve_ports(&ids[1], &[PortMapping::new(port, 80, PortProtocol::Udp)]).unwrap();
+
+        manager.release_ports(&ids[0]);
+        manager.reserve_ports(&ids[1], &mapping).unwrap();
+    }
+
+    #[test]
+    fn test_reserve_ports_sees_host_listeners() {
+        use crate::container::{PortMapping, PortProtocol};
+
+        let listener = std::net::TcpListener::bind((std::net::Ipv4Addr::UNSPECIFIED, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut manager = JailManager::new("/tmp/test.sock");
+
+        let err = manager.reserve_ports(&"c1".to_string(), &[PortMapping::new(port, 80, PortProtocol::Tcp)]).unwrap_err();
+        assert!(matches!(err, StoreError::PortInUse(ref msg) if msg.ends_with("already in use on the host")));
+        assert!(manager.published_ports.ports_of(&"c1".to_string()).is_empty());
+    }
+
     #[tokio::test]
     async fn test_manager_default() {
         let manager = JailManager::default();
-        assert_eq!(manager.socket_path().to_str().unwrap(), "/var/run/kawakaze.sock");
+        assert_eq!(manager.socket_path().to_str().unwrap(), "/var/lib/kawakaze/kawakaze.sock");
     }
 
     #[tokio::test]
@@ -1320,4 +3706,1001 @@ mod tests {
         assert_eq!(names[0], "alpha");
         assert_eq!(names[1], "beta");
     }
+
+    #[tokio::test]
+    async fn test_set_jail_description() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = JailManager::with_database(dir.path().join("kawakaze.db")).unwrap();
+        manager.add_jail("db").unwrap();
+        assert_eq!(manager.get_jail("db").unwrap().description(), None);
+
+        manager.set_jail_description("db", Some("owned by team-payments".to_string())).unwrap();
+        assert_eq!(manager.get_jail("db").unwrap().description(), Some("owned by team-payments"));
+        let row = manager.store.as_ref().unwrap().get_jail("db").unwrap().unwrap();
+        assert_eq!(row.description.as_deref(), Some("owned by team-payments"));
+        // Survives a reload from the row
+        assert_eq!(Jail::from_db_row(row).unwrap().description(), Some("owned by team-payments"));
+
+        manager.set_jail_description("db", None).unwrap();
+        assert_eq!(manager.store.as_ref().unwrap().get_jail("db").unwrap().unwrap().description, None);
+
+        assert!(matches!(manager.set_jail_description("nope", None), Err(JailError::UpdateFailed(_))));
+    }
+
+    /// Bootstrapper that records calls and writes a fake base system
+    struct MockBootstrapper {
+        calls: std::sync::atomic::AtomicUsize,
+    }
+
+    impl MockBootstrapper {
+        fn new() -> Self {
+            Self { calls: std::sync::atomic::AtomicUsize::new(0) }
+        }
+
+        fn calls(&self) -> usize {
+            self.calls.load(std::sync::atomic::Ordering::SeqCst)
+        }
+    }
+
+    impl BaseBootstrapper for MockBootstrapper {
+        async fn bootstrap(
+            &self,
+            root: &Path,
+            _version: &str,
+            _architecture: &str,
+        ) -> Result<(), crate::bootstrap::BootstrapError> {
+            self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            std::fs::create_dir_all(root.join("bin"))?;
+            std::fs::write(root.join("bin/sh"), b"")?;
+            Ok(())
+        }
+    }
+
+    fn build_progress(image_id: &str, status: crate::image_builder::BuildStatus) -> ImageBuildProgress {
+        ImageBuildProgress {
+            image_id: image_id.to_string(),
+            step: 0,
+            total_steps: 0,
+            current_instruction: String::new(),
+            status,
+            copy: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_finished_build_progress_is_pruned() {
+        let mut manager = JailManager::new("/tmp/test.sock");
+        for id in ["done", "running"] {
+            let (tx, _rx) = mpsc::channel(1);
+            manager.image_build_tracker.insert(id.to_string(), tx);
+        }
+        manager.image_build_progress.insert("done".to_string(), build_progress("done", BuildStatus::Complete));
+        manager.image_build_progress.insert("running".to_string(), build_progress("running", BuildStatus::Building));
+
+        // The tracker goes at once; the final status stays for the retention period
+        let retention = manager.finish_progress(ProgressKey::Build("done".to_string()));
+        assert_eq!(retention, std::time::Duration::from_secs(600));
+        assert!(!manager.image_build_tracker.contains_key("done"));
+        manager.prune_finished_progress(std::time::Instant::now());
+        assert!(manager.build_progress("done").is_some());
+
+        manager.prune_finished_progress(std::time::Instant::now() + retention);
+        assert!(manager.build_progress("done").is_none());
+        assert!(manager.build_progress("running").is_some());
+        assert!(manager.image_build_tracker.contains_key("running"));
+    }
+
+    #[tokio::test]
+    async fn test_scheduled_prune_spares_restarted_bootstrap() {
+        let mut manager = JailManager::new("/tmp/test.sock");
+        manager.config.progress_retention_secs = 0;
+        let (tx, _rx) = mpsc::channel(1);
+        manager.register_bootstrap_tracker("web".to_string(), tx.clone()).await;
+        manager.register_bootstrap_tracker("db".to_string(), tx.clone()).await;
+        manager.finish_progress(ProgressKey::Bootstrap("web".to_string()));
+        let retention = manager.finish_progress(ProgressKey::Bootstrap("db".to_string()));
+
+        // db is bootstrapped again before the prune runs
+        manager.register_bootstrap_tracker("db".to_string(), tx).await;
+        let manager = Arc::new(tokio::sync::Mutex::new(manager));
+        JailManager::schedule_progress_prune(&manager, retention);
+
+        for _ in 0..100 {
+            if manager.lock().await.bootstrap_progress.get("web").is_none() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        }
+        let manager = manager.lock().await;
+        assert!(!manager.bootstrap_progress.contains_key("web"));
+        assert!(manager.bootstrap_progress.contains_key("db"));
+        assert!(manager.finished_progress.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_build_waiter_released_on_finish() {
+        let mut manager = JailManager::new("/tmp/test_build_waiter.sock");
+        assert!(manager.build_waiter("base").is_none());
+
+        manager.begin_build("base");
+        assert!(manager.is_building("base"));
+        let mut waiter = manager.build_waiter("base").unwrap();
+
+        manager.finish_build("base");
+        assert!(!manager.is_building("base"));
+        // The sender is gone, so the waiter resolves immediately
+        assert!(waiter.changed().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_ensure_base_image_uses_cache() {
+        let mut manager = JailManager::new("/tmp/test.sock");
+        manager.base_images.insert(
+            "14.1-RELEASE-amd64".to_string(),
+            "tank/base/14.1-RELEASE-amd64@base".to_string(),
+        );
+
+        let bootstrapper = MockBootstrapper::new();
+        let snapshot = manager
+            .ensure_base_image_with("14.1-RELEASE", "amd64", &bootstrapper)
+            .await
+            .unwrap();
+
+        assert_eq!(snapshot, "tank/base/14.1-RELEASE-amd64@base");
+        assert_eq!(bootstrapper.calls(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_ensure_base_image_without_zfs() {
+        let mut manager = JailManager::new("/tmp/test.sock");
+
+        let bootstrapper = MockBootstrapper::new();
+        let result = manager
+            .ensure_base_image_with("14.1-RELEASE", "amd64", &bootstrapper)
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(bootstrapper.calls(), 0);
+        assert!(manager.base_images.is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires actual ZFS pool
+    async fn test_ensure_base_image_bootstraps_once() {
+        let mut manager = JailManager::new("/tmp/test.sock");
+        manager.zfs = Some(Zfs::new(&manager.config.zfs_pool).unwrap());
+
+        let bootstrapper = MockBootstrapper::new();
+        let first = manager
+            .ensure_base_image_with("0.0-TEST", "amd64", &bootstrapper)
+            .await
+            .unwrap();
+        let second = manager
+            .ensure_base_image_with("0.0-TEST", "amd64", &bootstrapper)
+            .await
+            .unwrap();
+
+        assert_eq!(first, second);
+        assert!(first.ends_with("/base/0.0-TEST-amd64@base"));
+        assert_eq!(bootstrapper.calls(), 1);
+
+        let dataset = first.split('@').next().unwrap().to_string();
+        let _ = manager.zfs.as_ref().unwrap().destroy(&dataset);
+    }
+
+    #[tokio::test]
+    async fn test_ensure_base_image_shared_uses_cache() {
+        let manager = std::sync::Arc::new(tokio::sync::Mutex::new(JailManager::new("/tmp/test.sock")));
+        let bootstrapper = MockBootstrapper::new();
+
+        // Without ZFS there is nothing to bootstrap into
+        let result = JailManager::ensure_base_image_shared(&manager, "14.1-RELEASE", "amd64", &bootstrapper).await;
+        assert!(result.is_err());
+
+        manager.lock().await.base_images.insert(
+            "14.1-RELEASE-amd64".to_string(),
+            "tank/base/14.1-RELEASE-amd64@base".to_string(),
+        );
+        let snapshot = JailManager::ensure_base_image_shared(&manager, "14.1-RELEASE", "amd64", &bootstrapper)
+            .await
+            .unwrap();
+
+        assert_eq!(snapshot, "tank/base/14.1-RELEASE-amd64@base");
+        assert_eq!(bootstrapper.calls(), 0);
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires actual ZFS pool
+    async fn test_build_from_freebsd_without_local_image() {
+        let mut manager = JailManager::new("/tmp/test.sock");
+        manager.zfs = Some(Zfs::new(&manager.config.zfs_pool).unwrap());
+        let pool = manager.config.zfs_pool.clone();
+        let manager = std::sync::Arc::new(tokio::sync::Mutex::new(manager));
+
+        let dockerfile = "FROM freebsd:0.0-TEST\nENV GREETING=hello\n";
+        let config = crate::bootstrap::parse_base_reference("freebsd:0.0-TEST").unwrap();
+        let version = config.resolve_version().unwrap();
+        assert!(manager.lock().await.get_image_by_name("freebsd:0.0-TEST").is_none());
+
+        let bootstrapper = MockBootstrapper::new();
+        let snapshot = JailManager::ensure_base_image_shared(&manager, &version, "amd64", &bootstrapper)
+            .await
+            .unwrap();
+        assert_eq!(bootstrapper.calls(), 1);
+
+        let (builder, _rx) = crate::image_builder::ImageBuilder::new(
+            Zfs::new(&pool).unwrap(),
+            format!("{}/images", pool),
+        );
+        let image = builder
+            .with_base_snapshot(snapshot.clone())
+            .build("from-freebsd-test".to_string(), dockerfile, None)
+            .await
+            .unwrap();
+        assert_eq!(image.config.env.get("GREETING").map(String::as_str), Some("hello"));
+
+        let zfs = Zfs::new(&pool).unwrap();
+        let _ = zfs.destroy(image.snapshot.split('@').next().unwrap());
+        let _ = zfs.destroy(snapshot.split('@').next().unwrap());
+    }
+
+    fn dataset(name: &str, used_bytes: u64, created_at: i64) -> DatasetInfo {
+        DatasetInfo { name: name.to_string(), used_bytes, created_at, clones: Vec::new() }
+    }
+
+    /// A store that knows one of two container datasets and none of the
+    /// image datasets, as after restoring an older database backup
+    fn orphan_fixture(dir: &Path) -> (JailManager, Vec<DatasetInfo>, Vec<DatasetInfo>) {
+        let mut manager = JailManager::with_database(dir.join("kawakaze.db")).unwrap();
+
+        let base = Image::new("base".to_string(), Vec::new())
+            .with_snapshot("zroot/kawakaze/images/base@base-1".to_string());
+        let base_id = base.id.clone();
+        manager.add_image(base).unwrap();
+
+        let known = Container::new_with_id(
+            "aaaa1111-0000-0000-0000-000000000000".to_string(),
+            base_id,
+            "kawakaze-aaaa1111".to_string(),
+            "zroot/kawakaze/containers/aaaa1111".to_string(),
+        );
+        manager.containers.insert(known.id.clone(), known);
+
+        let containers = vec![
+            dataset("zroot/kawakaze/containers", 0, 1),
+            dataset("zroot/kawakaze/containers/aaaa1111", 100, 10),
+            dataset("zroot/kawakaze/containers/bbbb2222", 200, 20),
+        ];
+        let images = vec![
+            dataset("zroot/kawakaze/images", 0, 1),
+            dataset("zroot/kawakaze/images/base", 300, 5),
+            dataset("zroot/kawakaze/images/base@base-1", 0, 6),
+            dataset("zroot/kawakaze/images/web", 400, 30),
+            dataset("zroot/kawakaze/images/web@web-1", 0, 31),
+            dataset("zroot/kawakaze/images/empty", 50, 40),
+        ];
+        (manager, containers, images)
+    }
+
+    #[test]
+    fn test_summarize_containers_merges_memory_and_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = JailManager::with_database(dir.path().join("kawakaze.db")).unwrap();
+        let image = Image::new("base".to_string(), Vec::new());
+        let image_id = image.id.clone();
+        manager.add_image(image).unwrap();
+
+        let container = |id: &str| {
+            Container::new_with_id(id.to_string(), image_id.clone(), format!("kawakaze-{}", &id[..8]), format!("zroot/c/{}", &id[..8]))
+        };
+
+        // Loaded and running; its row still says created and must not count twice
+        let mut loaded = container("aaaa1111-0000-0000-0000-000000000000");
+        let row = JailManager::container_row(&loaded, None).unwrap();
+        manager.store.as_ref().unwrap().insert_container(&row).unwrap();
+        loaded.set_state(crate::container::ContainerState::Running);
+        manager.containers.insert(loaded.id.clone(), loaded);
+
+        // Only in the database
+        for (id, state) in [
+            ("bbbb2222-0000-0000-0000-000000000000", crate::store::ContainerState::Stopped),
+            ("cccc3333-0000-0000-0000-000000000000", crate::store::ContainerState::Paused),
+        ] {
+            let mut row = JailManager::container_row(&container(id), None).unwrap();
+            row.state = state;
+            manager.store.as_ref().unwrap().insert_container(&row).unwrap();
+        }
+
+        let summary = manager.summarize_containers();
+        assert_eq!(summary, crate::container::ContainerSummary {
+            total: 3,
+            running: 1,
+            stopped: 1,
+            created: 0,
+            paused: 1,
+            locked: 0,
+            unhealthy: 0,
+        });
+    }
+
+    /// A database whose jail, image and container a newer kawakaze wrote
+    fn newer_version_db(db_path: &Path) -> (ImageId, ContainerId) {
+        let mut manager = JailManager::with_database(db_path).unwrap();
+        manager.add_jail("legacy").unwrap();
+        let image = Image::new("base".to_string(), Vec::new());
+        let image_id = image.id.clone();
+        manager.add_image(image).unwrap();
+        let container = Container::new_with_id(
+            "dddd4444-0000-0000-0000-000000000000".to_string(), image_id.clone(), "kawakaze-dddd4444".to_string(),
+            "zroot/c/dddd4444".to_string(),
+        );
+        let row = JailManager::container_row(&container, None).unwrap();
+        manager.store.as_ref().unwrap().insert_container(&row).unwrap();
+
+        let conn = rusqlite::Connection::open(db_path).unwrap();
+        for table in ["jails", "images", "containers"] {
+            conn.execute(&format!("UPDATE {} SET last_written_version = '99.0.0'", table), []).unwrap();
+        }
+        (image_id, container.id)
+    }
+
+    #[tokio::test]
+    async fn test_newer_version_records_are_read_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("kawakaze.db");
+        let (image_id, container_id) = newer_version_db(&db_path);
+
+        let mut manager = JailManager::with_database(&db_path).unwrap();
+        manager.start().await.unwrap();
+
+        // Still listed and inspected, with the reason
+        let jail = manager.get_jail("legacy").unwrap();
+        assert_eq!(crate::api::JailInfo::from(jail.info()).readonly_reason.as_deref(), Some("written by newer version"));
+        assert_eq!(manager.get_image(&image_id).unwrap().readonly_reason(), Some("written by newer version"));
+        let container = manager.get_container(&container_id).unwrap();
+        assert_eq!(crate::api::ContainerListItem::from(container).readonly_reason.as_deref(), Some("written by newer version"));
+
+        assert!(matches!(manager.start_jail("legacy"), Err(JailError::VersionSkew(_))));
+        assert!(matches!(manager.stop_jail("legacy"), Err(JailError::VersionSkew(_))));
+        assert!(matches!(manager.remove_jail("legacy"), Err(JailError::VersionSkew(_))));
+        assert!(matches!(manager.set_image_protected(&image_id, true), Err(StoreError::VersionSkew(_))));
+        assert!(matches!(manager.remove_image(&image_id), Err(StoreError::VersionSkew(_))));
+        assert!(matches!(manager.start_container(&container_id), Err(StoreError::VersionSkew(_))));
+        assert!(matches!(manager.stop_container(&container_id, crate::container::StopCause::UserRequest(None)), Err(StoreError::VersionSkew(_))));
+        assert!(matches!(manager.set_container_boot(&container_id, true), Err(StoreError::VersionSkew(_))));
+        assert!(matches!(manager.remove_container(&container_id), Err(StoreError::VersionSkew(_))));
+
+        // Nothing was written over
+        let store = manager.store.as_ref().unwrap();
+        let row = store.get_container(&container_id).unwrap().unwrap();
+        assert_eq!(row.provenance.last_written.as_deref(), Some("99.0.0"));
+        assert!(!row.boot);
+        assert!(store.get_image(&image_id).unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_force_downgrade_writes_takes_over_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("kawakaze.db");
+        let (image_id, container_id) = newer_version_db(&db_path);
+
+        let mut manager = JailManager::with_database(&db_path).unwrap();
+        manager.set_force_downgrade_writes(true);
+        manager.start().await.unwrap();
+
+        assert_eq!(manager.get_jail("legacy").unwrap().readonly_reason(), None);
+        assert_eq!(manager.get_image(&image_id).unwrap().readonly_reason(), None);
+        assert_eq!(manager.get_container(&container_id).unwrap().readonly_reason(), None);
+        let store = manager.store.as_ref().unwrap();
+        let row = store.get_container(&container_id).unwrap().unwrap();
+        assert_eq!(row.provenance.last_written.as_deref(), Some(crate::version::CURRENT));
+        assert_eq!(row.provenance.created_by.as_deref(), Some(crate::version::CURRENT));
+
+        manager.set_container_boot(&container_id, true).unwrap();
+        manager.set_image_protected(&image_id, true).unwrap();
+        manager.remove_jail("legacy").unwrap();
+    }
+
+    #[test]
+    fn test_container_ip_aliases_persist() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = JailManager::with_database(dir.path().join("kawakaze.db")).unwrap();
+        let image = Image::new("base".to_string(), Vec::new());
+        let image_id = image.id.clone();
+        manager.add_image(image).unwrap();
+        let container = Container::new_with_id(
+            "eeee5555-0000-0000-0000-000000000000".to_string(), image_id, "kawakaze-eeee5555".to_string(),
+            "zroot/c/eeee5555".to_string(),
+        );
+        let id = container.id.clone();
+        manager.store.as_ref().unwrap().insert_container(&JailManager::container_row(&container, None).unwrap()).unwrap();
+        manager.containers.insert(id.clone(), container);
+
+        let ip = |s: &str| s.parse::<std::net::IpAddr>().unwrap();
+        manager.add_container_ip(&id, ip("192.0.2.10")).unwrap();
+        manager.add_container_ip(&id, ip("192.0.2.11")).unwrap();
+        assert!(manager.add_container_ip(&id, ip("192.0.2.10")).is_err());
+        manager.remove_container_ip(&id, ip("192.0.2.10")).unwrap();
+        assert!(manager.remove_container_ip(&id, ip("192.0.2.10")).is_err());
+
+        // Stored, and loaded back
+        let row = manager.store.as_ref().unwrap().get_container(&id).unwrap().unwrap();
+        assert_eq!(row.ip_aliases, r#"["192.0.2.11"]"#);
+        let loaded = manager.load_container_from_store_row(row).unwrap();
+        assert_eq!(loaded.ip_aliases, vec!["192.0.2.11".to_string()]);
+
+        // A VNET jail has its own stack
+        manager.containers.get_mut(&id).unwrap().ip = Some("10.11.0.2".to_string());
+        assert!(manager.add_container_ip(&id, ip("192.0.2.12")).is_err());
+    }
+
+    #[test]
+    fn test_container_stop_cause_persists() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = JailManager::with_database(dir.path().join("kawakaze.db")).unwrap();
+        let image = Image::new("base".to_string(), Vec::new());
+        let image_id = image.id.clone();
+        manager.add_image(image).unwrap();
+        let mut container = Container::new_with_id(
+            "abab7777-0000-0000-0000-000000000000".to_string(), image_id, "kawakaze-abab7777".to_string(),
+            "zroot/c/abab7777".to_string(),
+        );
+        container.set_state(crate::container::ContainerState::Running);
+        let id = container.id.clone();
+        manager.store.as_ref().unwrap().insert_container(&JailManager::container_row(&container, None).unwrap()).unwrap();
+        manager.containers.insert(id.clone(), container);
+
+        let cause = crate::container::StopCause::UserRequest(Some(1001));
+        let stopped = manager.record_container_stop(&id, cause);
+        manager.write_through(Resource::Container(id.clone()), stopped).unwrap();
+
+        // Stored, and loaded back
+        let row = manager.store.as_ref().unwrap().get_container(&id).unwrap().unwrap();
+        assert_eq!(row.state, crate::store::ContainerState::Stopped);
+        let loaded = manager.load_container_from_store_row(row).unwrap();
+        assert_eq!(loaded.last_stop_cause, Some(cause));
+        assert_eq!(loaded.stopped_at.map(Timestamp::as_secs), manager.containers[&id].stopped_at.map(Timestamp::as_secs));
+
+        let item = manager.container_list_item(&loaded);
+        assert_eq!(item.last_stop_cause, Some(cause));
+        assert!(item.stopped_at.is_some());
+    }
+
+    #[test]
+    fn test_container_ip6_is_stored_and_resolvable() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = JailManager::with_database(dir.path().join("kawakaze.db")).unwrap();
+        let image = Image::new("base".to_string(), Vec::new());
+        let image_id = image.id.clone();
+        manager.add_image(image).unwrap();
+        let container = Container::new_with_id(
+            "ffff6666-0000-0000-0000-000000000000".to_string(), image_id, "kawakaze-ffff6666".to_string(),
+            "zroot/c/ffff6666".to_string(),
+        )
+        .with_name("web".to_string())
+        .with_ip6("fd00:6b7a::2".to_string());
+        let store = manager.store.as_ref().unwrap();
+        store.insert_container(&JailManager::container_row(&container, None).unwrap()).unwrap();
+
+        let row = store.get_container(&container.id).unwrap().unwrap();
+        assert_eq!(row.ip6.as_deref(), Some("fd00:6b7a::2"));
+        let mut loaded = manager.load_container_from_store_row(row).unwrap();
+        assert_eq!((loaded.ip.as_deref(), loaded.ip6.as_deref()), (None, Some("fd00:6b7a::2")));
+
+        // An IPv6-only container is on the network by its IPv6 address,
+        // a dual-stack one by both
+        let entry = JailManager::host_entry(&loaded).unwrap();
+        assert_eq!(entry.ips, vec!["fd00:6b7a::2".to_string()]);
+        loaded.ip = Some("10.11.0.2".to_string());
+        assert_eq!(JailManager::host_entry(&loaded).unwrap().ips, vec!["10.11.0.2".to_string(), "fd00:6b7a::2".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_reloaded_container_keeps_its_jail() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("kawakaze.db");
+        let container = {
+            let mut manager = JailManager::with_database(&db_path).unwrap();
+            let image = Image::new("base".to_string(), Vec::new());
+            let image_id = image.id.clone();
+            manager.add_image(image).unwrap();
+            let container = Container::new_with_id(
+                "eeee8888-0000-0000-0000-000000000000".to_string(), image_id, "kawakaze-eeee8888".to_string(),
+                "zroot/c/eeee8888".to_string(),
+            )
+            .with_ip("10.11.0.8".to_string())
+            .with_mount_permissions(crate::jail::MountPermissions::Tmpfs);
+            manager.store.as_ref().unwrap().insert_container(&JailManager::container_row(&container, None).unwrap()).unwrap();
+            container
+        };
+
+        // A restart: the container never ran, so there is no jail row
+        let mut manager = JailManager::with_database(&db_path).unwrap();
+        manager.start().await.unwrap();
+        let loaded = &manager.containers[&container.id];
+        assert_eq!((loaded.jail_name.as_str(), loaded.dataset.as_str()), ("kawakaze-eeee8888", "zroot/c/eeee8888"));
+        let jail = &manager.jails["kawakaze-eeee8888"];
+        let row = jail.to_db_row();
+        let root = manager.paths().container_root(&container.id);
+        assert_eq!(row.path.as_deref(), Some(root.to_str().unwrap()));
+        assert_eq!(row.ip.as_deref(), Some("10.11.0.8"));
+        assert_eq!(jail.mount_permissions(), crate::jail::MountPermissions::Tmpfs);
+        assert_eq!(jail.devfs_ruleset(), manager.config.devfs.ruleset);
+
+        // Start and stop go to that jail; here they fail starting it rather
+        // than for want of one
+        for result in [
+            manager.start_container(&container.id),
+            manager.stop_container(&container.id, crate::container::StopCause::UserRequest(None)),
+        ] {
+            if let Err(e) = result {
+                assert!(!e.to_string().contains("not found"), "{}", e);
+            }
+        }
+
+        // A container loaded on demand gets its jail the same way
+        let mut manager = JailManager::with_database(&db_path).unwrap();
+        if let Err(e) = manager.start_container(&container.id) {
+            assert!(!e.to_string().contains("not found"), "{}", e);
+        }
+        assert!(manager.jails.contains_key("kawakaze-eeee8888"));
+    }
+
+    #[tokio::test]
+    async fn test_failed_writes_are_queued_and_flushed() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("kawakaze.db");
+        let mut manager = JailManager::with_database(&db).unwrap();
+
+        // With the database swapped for an empty one, writes fail
+        std::fs::rename(&db, dir.path().join("moved.db")).unwrap();
+        manager.add_jail("web").unwrap();
+        manager.add_jail("db").unwrap();
+        manager.remove_jail("db").unwrap();
+        assert!(manager.get_jail("web").is_some());
+        // The delete superseded the save of "db"
+        let info = manager.pending_writes_info();
+        assert_eq!((info.pending, info.escalated), (2, 0));
+        assert_eq!(info.oldest_secs, Some(0));
+
+        // Not due yet
+        std::fs::rename(dir.path().join("moved.db"), &db).unwrap();
+        assert_eq!(manager.retry_pending_writes(), 0);
+
+        manager.flush_pending_writes(std::time::Duration::from_secs(1)).await;
+        assert_eq!(manager.pending_writes_info().pending, 0);
+        let store = manager.store.as_ref().unwrap();
+        assert!(store.get_jail("web").unwrap().is_some());
+        assert!(store.get_jail("db").unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_kernel_snapshot_syncs_jails() {
+        use crate::kernel_jails::{KernelJail, KernelJails, KernelView};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("kawakaze.db");
+        {
+            let mut manager = JailManager::with_database(&db_path).unwrap();
+            for (name, state, jid) in [
+                ("up", JailState::Stopped, -1),
+                ("crashed", JailState::Running, 4),
+                ("frozen", JailState::Stopped, 5),
+                ("thawed", JailState::Stopped, 6),
+            ] {
+                manager.add_jail(name).unwrap();
+                let jail = manager.jails.get_mut(name).unwrap();
+                jail.set_state(state);
+                jail.set_jid(jid);
+                manager.store.as_ref().unwrap().update_jail(&jail.to_db_row()).unwrap();
+            }
+        }
+
+        // The kernel has "up" and "frozen"; "crashed" and "thawed" are gone
+        let walks = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&walks);
+        let mut manager = JailManager::with_database(&db_path).unwrap();
+        manager.kernel = KernelView::new(
+            move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let jail = |name: &str, jid| KernelJail { jid, name: name.to_string(), path: format!("/jails/{}", name), vnet: false };
+                Ok(KernelJails::from_jails([jail("up", 2), jail("frozen", 5)]))
+            },
+            std::time::Duration::from_secs(3600),
+        );
+        manager.start().await.unwrap();
+
+        // Startup walked the kernel once for all four
+        assert_eq!(walks.load(Ordering::SeqCst), 1);
+        let state = |manager: &JailManager, name: &str| {
+            let jail = manager.get_jail(name).unwrap();
+            (jail.state(), jail.jid())
+        };
+        assert_eq!(state(&manager, "up"), (JailState::Running, 2));
+        assert_eq!(state(&manager, "crashed"), (JailState::Stopped, -1));
+        assert_eq!(state(&manager, "frozen"), (JailState::Stopped, 5));
+        assert!(manager.get_jail("frozen").unwrap().is_frozen());
+        assert_eq!(state(&manager, "thawed"), (JailState::Stopped, -1));
+
+        // Removing a frozen jail the kernel no longer has just records it,
+        // answered from the cached snapshot
+        manager.jails.get_mut("thawed").unwrap().set_jid(6);
+        manager.stop_jail_with("thawed", StopMode::Remove).unwrap();
+        assert_eq!(state(&manager, "thawed"), (JailState::Stopped, -1));
+        assert_eq!(manager.store.as_ref().unwrap().get_jail("thawed").unwrap().unwrap().jid, -1);
+        assert_eq!(walks.load(Ordering::SeqCst), 1);
+
+        // Resuming a frozen jail still in the kernel keeps its JID, and
+        // drops the snapshot since jails changed
+        manager.start_jail("frozen").unwrap();
+        assert_eq!(state(&manager, "frozen"), (JailState::Running, 5));
+        manager.jails.get_mut("thawed").unwrap().set_jid(6);
+        assert!(manager.stop_jail_with("thawed", StopMode::Freeze).is_err());
+        assert_eq!(walks.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_add_built_image_moves_tag_and_keeps_old_image() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("kawakaze.db");
+        let mut manager = JailManager::with_database(&db_path).unwrap();
+
+        // First build of "web", with a container cloned from it
+        let first = Image::new("web".to_string(), Vec::new()).with_state(crate::image::ImageState::Available);
+        let first_id = first.id.clone();
+        assert!(manager.add_built_image(first).unwrap().is_empty());
+
+        let container = Container::new_with_id(
+            "dddd4444-0000-0000-0000-000000000000".to_string(),
+            first_id.clone(),
+            "kawakaze-dddd4444".to_string(),
+            "zroot/c/dddd4444".to_string(),
+        );
+        let row = JailManager::container_row(&container, None).unwrap();
+        manager.store.as_ref().unwrap().insert_container(&row).unwrap();
+        manager.containers.insert(container.id.clone(), container);
+
+        // Second build of "web"
+        let second = Image::new("web".to_string(), Vec::new()).with_state(crate::image::ImageState::Available);
+        let second_id = second.id.clone();
+        assert_eq!(manager.add_built_image(second).unwrap(), vec![first_id.clone()]);
+
+        assert_eq!(manager.get_image_by_name("web").unwrap().id, second_id);
+        let old = manager.get_image(&first_id).unwrap();
+        assert!(old.is_untagged());
+        assert_eq!(old.display_name(), "<none>");
+
+        // The container still resolves its image, and keeps it from being pruned
+        let container = manager.containers.values().next().unwrap();
+        assert!(manager.get_image(&container.image_id).is_some());
+        assert!(manager.prune_images(false).unwrap().is_empty());
+
+        // Both changes were persisted
+        let store = manager.store.as_ref().unwrap();
+        assert_eq!(store.get_image_by_name("web").unwrap().unwrap().id, second_id);
+        assert_eq!(store.get_image(&first_id).unwrap().unwrap().kind, "untagged");
+
+        // A third build only untags the current tag holder
+        let third = Image::new("web".to_string(), Vec::new()).with_state(crate::image::ImageState::Available);
+        assert_eq!(manager.add_built_image(third).unwrap(), vec![second_id]);
+    }
+
+    #[test]
+    fn test_orphans_in_reports_unknown_datasets() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut manager, containers, images) = orphan_fixture(dir.path());
+
+        // An in-flight build's dataset isn't an orphan
+        manager.begin_build("next");
+        let mut images = images;
+        images.push(dataset("zroot/kawakaze/images/build-next", 10, 50));
+
+        let orphans = manager.orphans_in(&containers, &images);
+        let names: Vec<&str> = orphans.iter().map(|o| o.dataset.as_str()).collect();
+        assert_eq!(names, vec![
+            "zroot/kawakaze/containers/bbbb2222",
+            "zroot/kawakaze/images/web",
+            "zroot/kawakaze/images/empty",
+        ]);
+        assert_eq!(orphans[0].kind, OrphanKind::Container);
+        assert_eq!(orphans[1].kind, OrphanKind::Image);
+    }
+
+    fn volume_config(image_id: &str, volumes: Vec<crate::container::Mount>) -> crate::container::ContainerConfig {
+        crate::container::ContainerConfig {
+            image_id: image_id.to_string(),
+            name: None,
+            ports: Vec::new(),
+            volumes,
+            tmpfs: Vec::new(),
+            restart_policy: crate::container::RestartPolicy::default(),
+            command: None,
+            protected: false,
+            devfs_ruleset: None,
+            healthcheck_disabled: false,
+            boot: false,
+            encrypted: false,
+            read_only_root: false,
+            network_aliases: Vec::new(),
+            hostname: None,
+            mount_permissions: crate::jail::MountPermissions::None,
+            env: Default::default(),
+            placement: None,
+            sysctls: Default::default(),
+            description: None,
+        }
+    }
+
+    #[test]
+    fn test_image_volumes_become_anonymous_mounts() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = JailManager::with_database(dir.path().join("kawakaze.db")).unwrap();
+        let mut image = Image::new("pg".to_string(), Vec::new());
+        image.config.volumes = vec!["/var/db/postgres".to_string(), "/var/log".to_string()];
+        let image_id = image.id.clone();
+        manager.add_image(image).unwrap();
+
+        let user = crate::container::Mount::new(
+            "/srv/logs".to_string(),
+            "/var/log".to_string(),
+            crate::container::MountType::Nullfs,
+            false,
+        );
+        let container = manager.create_container(volume_config(&image_id, vec![user])).unwrap();
+
+        let anonymous: Vec<_> = container.mounts.iter().filter(|m| m.anonymous).collect();
+        assert_eq!(anonymous.len(), 1);
+        assert_eq!(anonymous[0].destination, "/var/db/postgres");
+        assert_eq!(anonymous[0].source, format!("{}/volumes/{}-0", manager.config.zfs_pool, &container.id[..8]));
+        assert_eq!(container.mounts.len(), 2);
+
+        // Recorded with the container, so removal knows to destroy it
+        let row = manager.store.as_ref().unwrap().get_container(&container.id).unwrap().unwrap();
+        let stored: Vec<crate::container::Mount> = serde_json::from_str(&row.mounts).unwrap();
+        assert!(stored.iter().any(|m| m.anonymous && m.source == anonymous[0].source));
+    }
+
+    #[test]
+    fn test_completion_names() {
+        use crate::api::NameKind;
+        use crate::container::ContainerState;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = JailManager::with_database(dir.path().join("kawakaze.db")).unwrap();
+        let image = Image::new("pg".to_string(), Vec::new());
+        let image_id = image.id.clone();
+        manager.add_image(image).unwrap();
+
+        let mut create = |name: Option<&str>, state: ContainerState| {
+            let config = crate::container::ContainerConfig { name: name.map(String::from), ..volume_config(&image_id, Vec::new()) };
+            let id = manager.create_container(config).unwrap().id;
+            manager.containers.get_mut(&id).unwrap().state = state;
+            crate::names::short_id(&id).to_string()
+        };
+        let web = create(Some("web"), ContainerState::Running);
+        let db = create(Some("db"), ContainerState::Created);
+        let anonymous = create(None, ContainerState::Stopped);
+        let gone = create(Some("gone"), ContainerState::Removing);
+
+        assert_eq!(manager.completion_names(NameKind::Running), ["web".to_string(), web.clone()]);
+        let mut ids = vec![db.clone(), anonymous.clone()];
+        ids.sort();
+        assert_eq!(manager.completion_names(NameKind::Stopped), [vec!["db".to_string()], ids].concat());
+        let all = manager.completion_names(NameKind::Containers);
+        assert_eq!(all[..2], ["db".to_string(), "web".to_string()]);
+        assert_eq!(all.len(), 5);
+        assert!(!all.contains(&gone) && !all.iter().any(|name| name == "gone"));
+        assert_eq!(manager.completion_names(NameKind::Images), ["pg".to_string(), crate::names::short_id(&image_id).to_string()]);
+    }
+
+    #[test]
+    fn test_external_datasets_never_destroyed() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = JailManager::with_database(dir.path().join("kawakaze.db")).unwrap();
+        let mut image = Image::new("pg".to_string(), Vec::new());
+        image.config.volumes = vec!["/var/db/postgres".to_string()];
+        let image_id = image.id.clone();
+        manager.add_image(image).unwrap();
+
+        let external = |source: &str, destination: &str| {
+            crate::container::Mount::new(source.to_string(), destination.to_string(), crate::container::MountType::Zfs, false)
+        };
+        let volumes = vec![external("data/www", "/var/www"), external("data/jails/db", "/data").with_delegate(true)];
+        let container = manager.create_container(volume_config(&image_id, volumes)).unwrap();
+        let modes: Vec<_> = container.mounts.iter().map(|m| m.mode()).collect();
+        assert_eq!(modes, [VolumeMode::Attached, VolumeMode::Delegated, VolumeMode::Anonymous]);
+
+        // Only the volume kawakaze created goes with the container
+        let teardown = manager.begin_container_removal(&container.id, &RemovalOptions::default()).unwrap();
+        assert_eq!(teardown.volumes, [format!("{}/volumes/{}-0", manager.config.zfs_pool, &container.id[..8])]);
+    }
+
+    #[test]
+    #[ignore] // Requires a ZFS pool named tank
+    fn test_anonymous_volume_destroyed_with_container() {
+        let zfs = crate::zfs::Zfs::new("tank").unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = JailManager::with_database(dir.path().join("kawakaze.db")).unwrap();
+        manager.config.zfs_pool = "tank/kawakaze-test".to_string();
+        manager.config.data_root = dir.path().display().to_string();
+        zfs.create_dataset("tank/kawakaze-test/images/vol").unwrap();
+        zfs.create_snapshot("tank/kawakaze-test/images/vol", "base").unwrap();
+        manager.zfs = Some(zfs.clone());
+
+        let mut image = Image::new("vol".to_string(), Vec::new());
+        image.snapshot = "tank/kawakaze-test/images/vol@base".to_string();
+        image.config.volumes = vec!["/data".to_string()];
+        let image_id = image.id.clone();
+        manager.add_image(image).unwrap();
+
+        let container = manager.create_container(volume_config(&image_id, Vec::new())).unwrap();
+        let volume = container.mounts.iter().find(|m| m.anonymous).unwrap().source.clone();
+        assert!(zfs.dataset_exists(&volume));
+
+        manager.remove_container(&container.id).unwrap();
+        assert!(!zfs.dataset_exists(&volume));
+        let _ = zfs.destroy("tank/kawakaze-test");
+    }
+
+    #[test]
+    fn test_adopt_orphan_container_and_image() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut manager, containers, images) = orphan_fixture(dir.path());
+        let orphans = manager.orphans_in(&containers, &images);
+
+        let id = manager
+            .adopt_orphan_from(&orphans, "zroot/kawakaze/containers/bbbb2222", Some("restored".to_string()), Some("base"))
+            .unwrap();
+        let container = manager.get_container(&id).unwrap();
+        assert!(id.starts_with("bbbb2222"));
+        assert_eq!(container.jail_name, "kawakaze-bbbb2222");
+        assert_eq!(container.state, crate::container::ContainerState::Created);
+        assert_eq!(container.created_at, Timestamp::from_secs(20));
+
+        let image_id = manager
+            .adopt_orphan_from(&orphans, "zroot/kawakaze/images/web", Some("web".to_string()), None)
+            .unwrap();
+        let image = manager.get_image(&image_id).unwrap();
+        assert_eq!(image.snapshot, "zroot/kawakaze/images/web@web-1");
+        assert_eq!(image.size_bytes, 400);
+
+        // Both are persisted and no longer orphans
+        let store = manager.store.as_ref().unwrap();
+        assert!(store.get_container(&id).unwrap().is_some());
+        assert!(store.get_image(&image_id).unwrap().is_some());
+        assert_eq!(manager.orphans_in(&containers, &images).len(), 1);
+    }
+
+    #[test]
+    fn test_adopt_orphan_interlocks() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut manager, containers, images) = orphan_fixture(dir.path());
+        let orphans = manager.orphans_in(&containers, &images);
+
+        // The name has to match the listing exactly
+        assert!(matches!(
+            manager.adopt_orphan_from(&orphans, "bbbb2222", None, Some("base")),
+            Err(OrphanError::NotListed(_))
+        ));
+        // Known datasets are never orphans
+        assert!(matches!(
+            manager.adopt_orphan_from(&orphans, "zroot/kawakaze/containers/aaaa1111", None, Some("base")),
+            Err(OrphanError::NotListed(_))
+        ));
+        assert!(matches!(
+            manager.adopt_orphan_from(&orphans, "zroot/kawakaze/containers/bbbb2222", None, None),
+            Err(OrphanError::Invalid(..))
+        ));
+        assert!(matches!(
+            manager.adopt_orphan_from(&orphans, "zroot/kawakaze/images/empty", Some("empty".to_string()), None),
+            Err(OrphanError::Invalid(..))
+        ));
+        assert!(matches!(
+            manager.adopt_orphan_from(&orphans, "zroot/kawakaze/images/web", Some("base".to_string()), None),
+            Err(OrphanError::Conflict(..))
+        ));
+        assert!(manager.get_container_by_prefix("bbbb2222").is_none());
+    }
+
+    #[test]
+    fn test_destroy_orphan_requires_listing() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut manager, containers, images) = orphan_fixture(dir.path());
+        let orphans = manager.orphans_in(&containers, &images);
+
+        assert!(matches!(
+            manager.destroy_orphan_from(&orphans, "zroot/kawakaze/containers/aaaa1111"),
+            Err(OrphanError::NotListed(_))
+        ));
+        assert!(matches!(
+            manager.destroy_orphan_from(&orphans, "zroot/kawakaze"),
+            Err(OrphanError::NotListed(_))
+        ));
+    }
+
+    fn upgrade_outcome(version: &str) -> UpgradeOutcome {
+        UpgradeOutcome {
+            previous_version: "14.1-RELEASE-p3".to_string(),
+            version: version.to_string(),
+            snapshot: "zroot/jails/web@pre-upgrade-1".to_string(),
+            committed_snapshot: None,
+        }
+    }
+
+    #[test]
+    fn test_plan_jail_upgrade_refuses_running_jail() {
+        let mut manager = JailManager::new("/tmp/test.sock");
+        manager.add_jail("web").unwrap();
+        manager.get_jail_mut("web").unwrap().set_state(JailState::Running);
+
+        assert!(matches!(manager.plan_jail_upgrade("missing", None), Err(UpgradeError::NotFound(_))));
+        assert!(matches!(manager.plan_jail_upgrade("web", None), Err(UpgradeError::Conflict(_))));
+    }
+
+    #[test]
+    fn test_upgrade_records_os_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = JailManager::with_database(dir.path().join("kawakaze.db")).unwrap();
+        manager.add_jail("web").unwrap();
+        let target = UpgradeTarget::Jail("web".to_string());
+
+        manager.begin_upgrade(&target).unwrap();
+        assert!(matches!(manager.begin_upgrade(&target), Err(UpgradeError::Conflict(_))));
+        assert!(manager.start_jail("web").is_err());
+
+        manager.finish_upgrade(&target, Ok(upgrade_outcome("14.1-RELEASE-p5")));
+
+        let progress = manager.upgrade_progress(&target).unwrap();
+        assert_eq!(progress.status, UpgradeStatus::Complete);
+        assert_eq!(progress.version.as_deref(), Some("14.1-RELEASE-p5"));
+        assert_eq!(manager.get_jail("web").unwrap().os_version(), Some("14.1-RELEASE-p5"));
+
+        let row = manager.store.as_ref().unwrap().get_jail("web").unwrap().unwrap();
+        assert_eq!(row.os_version.as_deref(), Some("14.1-RELEASE-p5"));
+        assert!(manager.begin_upgrade(&target).is_ok());
+    }
+
+    #[test]
+    fn test_rolled_back_upgrade_keeps_os_version() {
+        let mut manager = JailManager::new("/tmp/test.sock");
+        manager.add_jail("web").unwrap();
+        let target = UpgradeTarget::Jail("web".to_string());
+
+        manager.begin_upgrade(&target).unwrap();
+        manager.finish_upgrade(&target, Err(UpgradeError::RolledBack {
+            reason: "freebsd-update exited with 1".to_string(),
+            output: vec!["fetch failed".to_string()],
+        }));
+
+        let progress = manager.upgrade_progress(&target).unwrap();
+        assert!(matches!(progress.status, UpgradeStatus::RolledBack(ref r) if r.contains("exited with 1")));
+        assert_eq!(progress.output, vec!["fetch failed"]);
+        assert_eq!(manager.get_jail("web").unwrap().os_version(), None);
+    }
+
+    #[test]
+    fn test_image_upgrade_switches_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = JailManager::with_database(dir.path().join("kawakaze.db")).unwrap();
+        let image = Image::new("web".to_string(), Vec::new())
+            .with_snapshot("zroot/kawakaze/images/web@web-1".to_string());
+        let id = image.id.clone();
+        manager.add_image(image).unwrap();
+        let target = UpgradeTarget::Image(id.clone());
+
+        manager.begin_upgrade(&target).unwrap();
+        manager.finish_upgrade(&target, Ok(UpgradeOutcome {
+            committed_snapshot: Some("zroot/kawakaze/images/web@web-upgraded-2".to_string()),
+            ..upgrade_outcome("14.2-RELEASE")
+        }));
+
+        let image = manager.get_image(&id).unwrap();
+        assert_eq!(image.snapshot, "zroot/kawakaze/images/web@web-upgraded-2");
+        assert_eq!(image.os_version.as_deref(), Some("14.2-RELEASE"));
+
+        let row = manager.store.as_ref().unwrap().get_image(&id).unwrap().unwrap();
+        assert_eq!(row.snapshot, "zroot/kawakaze/images/web@web-upgraded-2");
+        assert_eq!(row.os_version.as_deref(), Some("14.2-RELEASE"));
+    }
 }
@@ -0,0 +1,122 @@
+//! The daemon's log filter, changeable while it runs
+//!
+//! The binary installs its `EnvFilter` behind a `tracing_subscriber::reload`
+//! layer and hands the layer's handle to the manager as a [`LogLevel`].
+//! At startup the filter is `RUST_LOG` if set, else `log_level` from the
+//! config; `POST /system/log-level` replaces it until the next restart. A
+//! level is any `EnvFilter` directive list, so `debug`, `warn` and
+//! `info,kawakaze_backend::zfs=trace` all work.
+
+use tracing_subscriber::{EnvFilter, Registry, reload};
+
+/// Filter used when neither `RUST_LOG` nor the config sets one
+pub const DEFAULT_LEVEL: &str = "info";
+
+/// Handle to the filter installed in front of the registry
+pub type Handle = reload::Handle<EnvFilter, Registry>;
+
+/// Why the log level couldn't be changed
+#[derive(Debug, thiserror::Error)]
+pub enum LogLevelError {
+    #[error("{0}")]
+    Invalid(String),
+    #[error("This daemon's log level can't be changed while it runs")]
+    NotReloadable,
+    #[error("Failed to change the log level: {0}")]
+    Reload(String),
+}
+
+/// Parse a level into the filter it stands for
+pub fn parse(level: &str) -> Result<EnvFilter, String> {
+    let level = level.trim();
+    if level.is_empty() {
+        return Err("Log level cannot be empty".to_string());
+    }
+    EnvFilter::try_new(level).map_err(|e| format!("Invalid log level '{}': {}", level, e))
+}
+
+/// A filter that can be installed in front of the registry, and its handle
+pub fn layer(level: &str) -> Result<(reload::Layer<EnvFilter, Registry>, Handle), String> {
+    Ok(reload::Layer::new(parse(level)?))
+}
+
+/// The active log filter and the handle that changes it
+#[derive(Default)]
+pub struct LogLevel {
+    /// `None` when the daemon's subscriber isn't reloadable (tests, or a
+    /// library user's own subscriber)
+    handle: Option<Handle>,
+    current: String,
+}
+
+impl LogLevel {
+    pub fn new(handle: Handle, current: &str) -> Self {
+        Self { handle: Some(handle), current: current.trim().to_string() }
+    }
+
+    /// The level in effect, as it was last set
+    pub fn current(&self) -> &str {
+        &self.current
+    }
+
+    /// Install `level`, returning the level it replaced
+    pub fn set(&mut self, level: &str) -> Result<String, LogLevelError> {
+        let filter = parse(level).map_err(LogLevelError::Invalid)?;
+        let handle = self.handle.as_ref().ok_or(LogLevelError::NotReloadable)?;
+        handle.reload(filter).map_err(|e| LogLevelError::Reload(e.to_string()))?;
+        Ok(std::mem::replace(&mut self.current, level.trim().to_string()))
+    }
+}
+
+impl std::fmt::Debug for LogLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LogLevel")
+            .field("current", &self.current)
+            .field("reloadable", &self.handle.is_some())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing::Level;
+    use tracing_subscriber::prelude::*;
+
+    #[test]
+    fn test_reload_updates_active_filter() {
+        let (filter, handle) = layer("info").unwrap();
+        let subscriber = tracing_subscriber::registry().with(filter);
+        let mut level = LogLevel::new(handle, "info");
+
+        tracing::subscriber::with_default(subscriber, || {
+            assert!(tracing::enabled!(Level::INFO));
+            assert!(!tracing::enabled!(Level::DEBUG));
+
+            assert_eq!(level.set("debug").unwrap(), "info");
+            assert!(tracing::enabled!(Level::DEBUG));
+            assert_eq!(level.current(), "debug");
+
+            assert_eq!(level.set(" warn ").unwrap(), "debug");
+            assert!(!tracing::enabled!(Level::INFO));
+            assert!(tracing::enabled!(Level::WARN));
+
+            // A bad level leaves the filter as it was
+            assert!(level.set("kawakaze_backend=loud").is_err());
+            assert!(!tracing::enabled!(Level::INFO));
+            assert_eq!(level.current(), "warn");
+        });
+    }
+
+    #[test]
+    fn test_levels() {
+        assert!(parse("debug").is_ok());
+        assert!(parse("info,kawakaze_backend::zfs=trace").is_ok());
+        assert!(parse("").is_err());
+        assert!(parse("   ").is_err());
+
+        let mut unreloadable = LogLevel::default();
+        assert!(matches!(unreloadable.set("debug"), Err(LogLevelError::NotReloadable)));
+        assert!(matches!(unreloadable.set("kawakaze_backend=loud"), Err(LogLevelError::Invalid(_))));
+    }
+}
@@ -0,0 +1,382 @@
+//! Container log files
+//!
+//! Each container has a log file under `storage.log_path`, named after its
+//! ID. Lines written by kawakaze are JSON (`{"time":..,"stream":..,"log":..}`)
+//! and keep the time they were captured; any other line is treated as plain
+//! text and its time estimated from the file's modification time.
+//!
+//! A container's syslog (see [`crate::syslog`]) is a second [`LogSource`];
+//! [`merge`] interleaves the two by time and [`Tail`] follows either.
+
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::timestamp::Timestamp;
+
+/// One log line
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogEntry {
+    /// When the line was written (estimated for plain text lines)
+    pub timestamp: Option<Timestamp>,
+    /// "stdout" or "stderr" ("stdout" for plain text lines)
+    pub stream: String,
+    pub message: String,
+}
+
+/// Which of a container's logs to read
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogSource {
+    /// What the container's processes wrote to stdout and stderr
+    #[default]
+    Stdio,
+    /// The jail's `/var/log/messages`
+    Syslog,
+    /// Both, in time order
+    All,
+}
+
+impl LogSource {
+    pub fn is_stdio(&self) -> bool {
+        *self == LogSource::Stdio
+    }
+
+    pub fn includes_stdio(self) -> bool {
+        self != LogSource::Syslog
+    }
+
+    pub fn includes_syslog(self) -> bool {
+        self != LogSource::Stdio
+    }
+}
+
+/// Parses a log's contents given the file's modification time
+pub type Parser = fn(&str, Option<i64>) -> Vec<LogEntry>;
+
+/// On-disk form of a JSON log line
+#[derive(Debug, Serialize, Deserialize)]
+struct JsonLine {
+    time: i64,
+    stream: String,
+    log: String,
+}
+
+/// Log file for a container
+pub fn container_log_file(log_dir: &Path, container_id: &str) -> PathBuf {
+    log_dir.join(format!("{}.log", container_id))
+}
+
+/// Append captured output to a log file, one JSON line per output line
+pub fn append_output(path: &Path, stream: &str, output: &str, time: i64) -> io::Result<()> {
+    if output.is_empty() {
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut file = fs::OpenOptions::new().create(true).append(true).open(path)?;
+    for line in output.lines() {
+        let json = serde_json::to_string(&JsonLine {
+            time,
+            stream: stream.to_string(),
+            log: line.to_string(),
+        })
+        .map_err(io::Error::other)?;
+        writeln!(file, "{}", json)?;
+    }
+
+    Ok(())
+}
+
+/// Parse a log file's contents, using `mtime` for lines without a time
+pub fn parse_log(contents: &str, mtime: Option<i64>) -> Vec<LogEntry> {
+    contents
+        .lines()
+        .map(|line| match serde_json::from_str::<JsonLine>(line) {
+            Ok(json) => LogEntry {
+                timestamp: Some(Timestamp::from_secs(json.time)),
+                stream: json.stream,
+                message: json.log,
+            },
+            Err(_) => LogEntry {
+                timestamp: mtime.map(Timestamp::from_secs),
+                stream: "stdout".to_string(),
+                message: line.to_string(),
+            },
+        })
+        .collect()
+}
+
+/// Read a container's log; a missing file is an empty log
+pub fn read_log(path: &Path) -> io::Result<Vec<LogEntry>> {
+    read_with(path, parse_log)
+}
+
+/// Read a whole log with `parse`; a missing file is an empty log
+pub fn read_with(path: &Path, parse: Parser) -> io::Result<Vec<LogEntry>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    Ok(parse(&contents, mtime(path)))
+}
+
+/// Read the complete lines written to a log since byte `offset`, returning
+/// them with the offset to continue from. A file shorter than `offset` was
+/// truncated and is read from the start.
+pub fn read_log_from(path: &Path, offset: u64) -> io::Result<(Vec<LogEntry>, u64)> {
+    let mut file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok((Vec::new(), 0)),
+        Err(e) => return Err(e),
+    };
+    read_complete_lines(&mut file, offset, parse_log, mtime(path))
+}
+
+/// Parse the complete lines of `file` after `offset` (from the start if the
+/// file is shorter), returning them with the offset to continue from
+fn read_complete_lines(file: &mut fs::File, offset: u64, parse: Parser, mtime: Option<i64>) -> io::Result<(Vec<LogEntry>, u64)> {
+    let start = if offset > file.metadata()?.len() { 0 } else { offset };
+    let mut bytes = Vec::new();
+    file.seek(SeekFrom::Start(start))?;
+    file.read_to_end(&mut bytes)?;
+
+    // A trailing line without its newline is still being written
+    let end = match bytes.iter().rposition(|&b| b == b'\n') {
+        Some(pos) => pos + 1,
+        None => return Ok((Vec::new(), start)),
+    };
+
+    let contents = String::from_utf8_lossy(&bytes[..end]);
+    Ok((parse(&contents, mtime), start + end as u64))
+}
+
+/// Follows a log as it grows, from its first line. A file that shrinks was
+/// truncated and one with a new inode was rotated; either is read again
+/// from the start, and a file that is briefly gone reads as nothing new.
+#[derive(Debug)]
+pub struct Tail {
+    path: PathBuf,
+    parse: Parser,
+    offset: u64,
+    inode: Option<u64>,
+}
+
+impl Tail {
+    pub fn new(path: PathBuf, parse: Parser) -> Self {
+        Self { path, parse, offset: 0, inode: None }
+    }
+
+    /// The complete lines written since the last poll
+    pub fn poll(&mut self) -> io::Result<Vec<LogEntry>> {
+        let mut file = match fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                self.offset = 0;
+                self.inode = None;
+                return Ok(Vec::new());
+            }
+            Err(e) => return Err(e),
+        };
+
+        // Checked on the open file, so a rotation between the two can't
+        // pair the old offset with the new file
+        let inode = file.metadata()?.ino();
+        if self.inode.is_some_and(|known| known != inode) {
+            self.offset = 0;
+        }
+        self.inode = Some(inode);
+
+        let (entries, next) = read_complete_lines(&mut file, self.offset, self.parse, mtime(&self.path))?;
+        self.offset = next;
+        Ok(entries)
+    }
+}
+
+/// Interleave two logs by time, each kept in its own order. A line without
+/// a time stays with the lines around it in its log; ties go to `first`.
+pub fn merge(first: Vec<LogEntry>, second: Vec<LogEntry>) -> Vec<LogEntry> {
+    let mut merged = Vec::with_capacity(first.len() + second.len());
+    let mut first = first.into_iter().peekable();
+    let mut second = second.into_iter().peekable();
+
+    while let (Some(a), Some(b)) = (first.peek(), second.peek()) {
+        let take_second = match (a.timestamp, b.timestamp) {
+            (Some(a), Some(b)) => b < a,
+            (Some(_), None) => true,
+            (None, _) => false,
+        };
+        let next = if take_second { second.next() } else { first.next() };
+        merged.extend(next);
+    }
+    merged.extend(first);
+    merged.extend(second);
+    merged
+}
+
+/// Read the last `lines` lines of a log without reading all of it
+pub fn read_log_tail(path: &Path, lines: usize) -> io::Result<Vec<LogEntry>> {
+    read_tail_with(path, lines, parse_log)
+}
+
+/// Read the last `lines` lines of a log with `parse`
+pub fn read_tail_with(path: &Path, lines: usize, parse: Parser) -> io::Result<Vec<LogEntry>> {
+    const CHUNK: u64 = 8192;
+
+    let mut file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    if lines == 0 {
+        return Ok(Vec::new());
+    }
+
+    // Read backwards until the buffer holds one newline more than the lines
+    // wanted (the one ending the line before them), or the whole file
+    let mut start = file.metadata()?.len();
+    let mut buf: Vec<u8> = Vec::new();
+    while start > 0 {
+        let body = buf.strip_suffix(b"\n").unwrap_or(&buf);
+        if body.iter().filter(|&&b| b == b'\n').count() >= lines {
+            break;
+        }
+        let len = CHUNK.min(start);
+        start -= len;
+        let mut chunk = vec![0; len as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut chunk)?;
+        chunk.extend_from_slice(&buf);
+        buf = chunk;
+    }
+
+    let contents = String::from_utf8_lossy(&buf);
+    let entries = parse(&contents, mtime(path));
+    // The first line may be cut off unless the file was read from its start
+    let skip = entries.len().saturating_sub(lines);
+    Ok(entries.into_iter().skip(skip).collect())
+}
+
+fn mtime(path: &Path) -> Option<i64> {
+    fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as i64)
+}
+
+/// Line as shown to the user, optionally prefixed with its timestamp
+pub fn render_line(entry: &LogEntry, timestamps: bool) -> String {
+    match (timestamps, entry.timestamp) {
+        (true, Some(ts)) => format!("{} {}", ts, entry.message),
+        _ => entry.message.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_log_json_and_text_lines() {
+        let contents = "{\"time\":1700000000,\"stream\":\"stderr\",\"log\":\"oops\"}\nplain line\n";
+        let entries = parse_log(contents, Some(1700000500));
+
+        assert_eq!(entries[0], LogEntry {
+            timestamp: Some(Timestamp::from_secs(1700000000)),
+            stream: "stderr".to_string(),
+            message: "oops".to_string(),
+        });
+        assert_eq!(entries[1].timestamp, Some(Timestamp::from_secs(1700000500)));
+        assert_eq!(entries[1].message, "plain line");
+    }
+
+    #[test]
+    fn test_render_line_prepends_timestamp_only_when_asked() {
+        let entry = LogEntry {
+            timestamp: Some(Timestamp::from_secs(1700000000)),
+            stream: "stdout".to_string(),
+            message: "listening on :80".to_string(),
+        };
+
+        assert_eq!(render_line(&entry, false), "listening on :80");
+        assert_eq!(render_line(&entry, true), "2023-11-14T22:13:20Z listening on :80");
+
+        let untimed = LogEntry { timestamp: None, ..entry };
+        assert_eq!(render_line(&untimed, true), "listening on :80");
+    }
+
+    #[test]
+    fn test_append_and_read_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = container_log_file(dir.path(), "abc");
+
+        append_output(&path, "stdout", "one\ntwo\n", 1700000000).unwrap();
+        append_output(&path, "stderr", "", 1700000001).unwrap();
+        append_output(&path, "stderr", "three", 1700000002).unwrap();
+
+        let entries = read_log(&path).unwrap();
+        let lines: Vec<String> = entries.iter().map(|e| render_line(e, true)).collect();
+        assert_eq!(lines, vec![
+            "2023-11-14T22:13:20Z one",
+            "2023-11-14T22:13:20Z two",
+            "2023-11-14T22:13:22Z three",
+        ]);
+        assert!(read_log(&dir.path().join("missing.log")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_read_log_from_offset() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = container_log_file(dir.path(), "abc");
+        assert_eq!(read_log_from(&path, 0).unwrap(), (Vec::new(), 0));
+
+        append_output(&path, "stdout", "one\ntwo", 1700000000).unwrap();
+        let (entries, offset) = read_log_from(&path, 0).unwrap();
+        assert_eq!(entries.iter().map(|e| e.message.as_str()).collect::<Vec<_>>(), ["one", "two"]);
+
+        // Nothing new, then only what was appended
+        assert!(read_log_from(&path, offset).unwrap().0.is_empty());
+        append_output(&path, "stderr", "three", 1700000001).unwrap();
+        let (entries, next) = read_log_from(&path, offset).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].message, "three");
+
+        // A partial line waits for its newline
+        fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(b"half").unwrap();
+        assert_eq!(read_log_from(&path, next).unwrap(), (Vec::new(), next));
+
+        // Truncation starts over
+        fs::write(&path, "fresh\n").unwrap();
+        let (entries, _) = read_log_from(&path, next).unwrap();
+        assert_eq!(entries[0].message, "fresh");
+    }
+
+    #[test]
+    fn test_read_log_tail() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = container_log_file(dir.path(), "abc");
+        assert!(read_log_tail(&path, 5).unwrap().is_empty());
+
+        // Spans several read chunks
+        let output: String = (0..2000).map(|i| format!("line {}\n", i)).collect();
+        append_output(&path, "stdout", &output, 1700000000).unwrap();
+        let messages = |entries: Vec<LogEntry>| entries.into_iter().map(|e| e.message).collect::<Vec<_>>();
+
+        assert_eq!(messages(read_log_tail(&path, 3).unwrap()), ["line 1997", "line 1998", "line 1999"]);
+        assert!(read_log_tail(&path, 0).unwrap().is_empty());
+        assert_eq!(read_log_tail(&path, 5000).unwrap().len(), 2000);
+        assert_eq!(read_log_tail(&path, 2000).unwrap()[0].message, "line 0");
+
+        // Plain text lines, the last one without its newline
+        fs::write(&path, "a\nb\nc").unwrap();
+        assert_eq!(messages(read_log_tail(&path, 2).unwrap()), ["b", "c"]);
+    }
+}
@@ -0,0 +1,242 @@
+//! Which host paths containers may bind-mount
+//!
+//! A nullfs mount exposes a host directory to whoever controls the
+//! container, and anyone who can reach the API socket can ask for one, so
+//! each source is checked at container create:
+//!
+//! - it must exist, and is judged by its canonical path (symlinks and `..`
+//!   resolved), which is also what gets recorded and mounted;
+//! - it must lie under one of `allowed_mount_roots`, unless
+//!   `allow_any_mount_source` is set;
+//! - it may never be inside, or contain, the daemon's own state (database,
+//!   socket directory, data root with its build, jail and container mount
+//!   points), whatever the allowlist says;
+//! - with `strict_mounts`, sources outside the home roots ([`HOME_ROOTS`])
+//!   must be mounted read-only.
+//!
+//! [`resolve_within`] is the containment check itself, for anything else
+//! that takes host paths from clients.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::config::KawakazeConfig;
+use crate::paths::Paths;
+
+/// Roots holding users' own files, which `strict_mounts` lets containers
+/// mount writable
+pub const HOME_ROOTS: &[&str] = &["/home", "/usr/home"];
+
+/// Mount source rule violations
+#[derive(Debug, thiserror::Error)]
+pub enum MountError {
+    #[error("Mount source '{0}' is not an absolute path")]
+    Relative(String),
+    #[error("Mount source '{path}' does not exist: {error}")]
+    Missing { path: String, error: io::Error },
+    #[error("Mount source '{path}' resolves to '{resolved}', outside the allowed roots ({})", join(.roots))]
+    NotAllowed { path: String, resolved: PathBuf, roots: Vec<PathBuf> },
+    #[error("Mount source '{path}' resolves to '{resolved}', which overlaps kawakaze state at '{state}'")]
+    StateDir { path: String, resolved: PathBuf, state: PathBuf },
+    #[error("Mount source '{path}' is outside the home roots ({}) and must be mounted read-only (:ro)", HOME_ROOTS.join(", "))]
+    ReadOnlyRequired { path: String },
+}
+
+fn join(roots: &[PathBuf]) -> String {
+    roots.iter().map(|root| root.display().to_string()).collect::<Vec<_>>().join(", ")
+}
+
+/// Canonical form of `path` if it resolves inside one of `roots`
+///
+/// `path` must exist. Roots are canonicalized as well, so a root given
+/// through a symlink (`/home` on FreeBSD) still matches; roots that don't
+/// exist match nothing.
+pub fn resolve_within(path: &Path, roots: &[PathBuf]) -> io::Result<Option<PathBuf>> {
+    let resolved = path.canonicalize()?;
+    let inside = roots
+        .iter()
+        .filter_map(|root| root.canonicalize().ok())
+        .any(|root| resolved.starts_with(root));
+    Ok(inside.then_some(resolved))
+}
+
+/// Mount source checks under a configuration
+#[derive(Debug, Clone)]
+pub struct MountPolicy {
+    allowed_roots: Vec<PathBuf>,
+    allow_any: bool,
+    strict: bool,
+    state: Vec<PathBuf>,
+}
+
+impl MountPolicy {
+    pub fn from_config(config: &KawakazeConfig) -> Self {
+        let paths = Paths::from_config(config);
+        let mut state = vec![paths.data_root.clone(), paths.database.clone(), paths.builds.clone(), paths.jails.clone()];
+        state.extend(paths.socket.parent().map(Path::to_path_buf));
+
+        Self {
+            allowed_roots: config.allowed_mount_roots.clone(),
+            allow_any: config.allow_any_mount_source,
+            strict: config.strict_mounts,
+            state,
+        }
+    }
+
+    /// Check a nullfs source, returning the canonical path to mount
+    pub fn check_source(&self, source: &str, read_only: bool) -> Result<PathBuf, MountError> {
+        let path = Path::new(source);
+        if !path.is_absolute() {
+            return Err(MountError::Relative(source.to_string()));
+        }
+
+        let missing = |error| MountError::Missing { path: source.to_string(), error };
+        let resolved = path.canonicalize().map_err(missing)?;
+        if !self.allow_any && resolve_within(path, &self.allowed_roots).map_err(missing)?.is_none() {
+            return Err(MountError::NotAllowed {
+                path: source.to_string(),
+                resolved,
+                roots: self.allowed_roots.clone(),
+            });
+        }
+
+        // Either direction exposes the state: mounting a parent shows it too
+        for state in &self.state {
+            let state = canonicalize_lenient(state);
+            if resolved.starts_with(&state) || state.starts_with(&resolved) {
+                return Err(MountError::StateDir { path: source.to_string(), resolved, state });
+            }
+        }
+
+        let home_roots: Vec<PathBuf> = HOME_ROOTS.iter().map(PathBuf::from).collect();
+        if self.strict && !read_only && resolve_within(&resolved, &home_roots).map_err(missing)?.is_none() {
+            return Err(MountError::ReadOnlyRequired { path: source.to_string() });
+        }
+
+        Ok(resolved)
+    }
+}
+
+/// Canonical form of a state path, which may not exist yet (a socket before
+/// the daemon binds it)
+fn canonicalize_lenient(path: &Path) -> PathBuf {
+    match (path.canonicalize(), path.parent(), path.file_name()) {
+        (Ok(canonical), _, _) => canonical,
+        (Err(_), Some(parent), Some(name)) => canonicalize_lenient(parent).join(name),
+        _ => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    /// A host layout in a temp dir: an allowed root, somewhere outside it,
+    /// and the daemon's data root inside the allowed root
+    struct Host {
+        dir: tempfile::TempDir,
+    }
+
+    impl Host {
+        fn new() -> Self {
+            let dir = tempfile::tempdir().unwrap();
+            for sub in ["srv/app", "etc", "srv/kawakaze/containers/a1b2c3d4"] {
+                std::fs::create_dir_all(dir.path().join(sub)).unwrap();
+            }
+            std::fs::write(dir.path().join("etc/master.passwd"), "root:*").unwrap();
+            Self { dir }
+        }
+
+        fn path(&self, sub: &str) -> PathBuf {
+            self.dir.path().join(sub)
+        }
+
+        fn policy(&self) -> MountPolicy {
+            let mut config = KawakazeConfig::default();
+            config.data_root = self.path("srv/kawakaze").display().to_string();
+            config.allowed_mount_roots = vec![self.path("srv")];
+            MountPolicy::from_config(&config)
+        }
+
+        fn check(&self, policy: &MountPolicy, sub: &str) -> Result<PathBuf, MountError> {
+            policy.check_source(&self.path(sub).display().to_string(), false)
+        }
+    }
+
+    #[test]
+    fn test_allowed_and_refused_sources() {
+        let host = Host::new();
+        let policy = host.policy();
+
+        assert_eq!(host.check(&policy, "srv/app").unwrap(), host.path("srv/app").canonicalize().unwrap());
+        assert!(matches!(host.check(&policy, "etc"), Err(MountError::NotAllowed { .. })));
+        assert!(matches!(host.check(&policy, "srv/missing"), Err(MountError::Missing { .. })));
+        assert!(matches!(policy.check_source("srv/app", false), Err(MountError::Relative(_))));
+
+        let err = host.check(&policy, "etc").unwrap_err().to_string();
+        assert!(err.contains("etc") && err.contains(&host.path("srv").display().to_string()), "{}", err);
+    }
+
+    #[test]
+    fn test_symlink_tricks() {
+        let host = Host::new();
+        let policy = host.policy();
+
+        // A link inside the allowed root pointing out is judged by its target
+        symlink(host.path("etc"), host.path("srv/app/escape")).unwrap();
+        assert!(matches!(host.check(&policy, "srv/app/escape"), Err(MountError::NotAllowed { .. })));
+        symlink(host.path("etc/master.passwd"), host.path("srv/app/passwd")).unwrap();
+        assert!(matches!(host.check(&policy, "srv/app/passwd"), Err(MountError::NotAllowed { .. })));
+
+        // `..` can't climb out either
+        assert!(matches!(host.check(&policy, "srv/app/../../etc"), Err(MountError::NotAllowed { .. })));
+
+        // A sibling sharing the root's name as a prefix is not inside it
+        std::fs::create_dir(host.path("srvx")).unwrap();
+        assert!(matches!(host.check(&policy, "srvx"), Err(MountError::NotAllowed { .. })));
+
+        // A link outside pointing in resolves to, and records, the target
+        symlink(host.path("srv/app"), host.path("etc/app")).unwrap();
+        assert_eq!(host.check(&policy, "etc/app").unwrap(), host.path("srv/app").canonicalize().unwrap());
+
+        // State reached through a link is still state
+        symlink(host.path("srv/kawakaze/containers"), host.path("srv/app/containers")).unwrap();
+        assert!(matches!(host.check(&policy, "srv/app/containers"), Err(MountError::StateDir { .. })));
+    }
+
+    #[test]
+    fn test_state_dirs_refused_even_when_any_source_allowed() {
+        let host = Host::new();
+        let mut policy = host.policy();
+        policy.allow_any = true;
+
+        assert!(host.check(&policy, "etc").is_ok());
+        for sub in ["srv/kawakaze", "srv/kawakaze/containers/a1b2c3d4", "srv", ""] {
+            assert!(matches!(host.check(&policy, sub), Err(MountError::StateDir { .. })), "{}", sub);
+        }
+        assert!(matches!(policy.check_source("/", false), Err(MountError::StateDir { .. })));
+    }
+
+    #[test]
+    fn test_strict_mounts_require_read_only_outside_home() {
+        let host = Host::new();
+        let mut policy = host.policy();
+        policy.strict = true;
+
+        let source = host.path("srv/app").display().to_string();
+        assert!(matches!(policy.check_source(&source, false), Err(MountError::ReadOnlyRequired { .. })));
+        assert!(policy.check_source(&source, true).is_ok());
+    }
+
+    #[test]
+    fn test_resolve_within() {
+        let host = Host::new();
+        let roots = [host.path("srv")];
+
+        assert!(resolve_within(&host.path("srv/app"), &roots).unwrap().is_some());
+        assert!(resolve_within(&host.path("etc"), &roots).unwrap().is_none());
+        assert!(resolve_within(&host.path("etc"), &[host.path("missing")]).unwrap().is_none());
+        assert!(resolve_within(&host.path("nope"), &roots).is_err());
+    }
+}
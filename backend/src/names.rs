@@ -0,0 +1,410 @@
+//! Rules keeping container and image names apart from IDs
+//!
+//! A container reference resolves by exact ID, then exact name, then unique
+//! ID prefix ([`resolve_reference`]). A name that is also a prefix of another
+//! container's ID would make that order decide which container a command
+//! hits, so such a reference is refused as ambiguous, and names and IDs are
+//! kept disjoint at the source:
+//!
+//! - a new name may not look like an ID (only hex digits, within
+//!   [`NamesConfig`]'s lengths) nor be a prefix of an existing ID
+//!   ([`check_name`]);
+//! - a new ID may not start with an existing name ([`unique_id`]).
+//!
+//! Names the daemon assigns itself (an unnamed container's ID, an untagged
+//! image's ID) aren't user names and don't go through these checks.
+//!
+//! Image names also have a form, `repo[/repo...][:tag]`, checked by
+//! [`check_image_name`]: lowercase repository components as Docker has
+//! them, a tag of letters, digits, `_`, `.` and `-`, and at most
+//! [`MAX_IMAGE_NAME_LEN`] characters. A name without a tag stands for
+//! [`DEFAULT_TAG`], so `web` and `web:latest` name the same tag
+//! ([`canonical_image_name`]); `freebsd[:version]` is reserved for base
+//! systems.
+//!
+//! Prefix lookups for images and containers both go through
+//! [`resolve_by_prefix`]: an exact ID always matches, a shorter prefix needs
+//! [`MIN_PREFIX_LEN`] characters and must match exactly one ID. IDs are
+//! shown shortened to [`SHORT_ID_LEN`] characters ([`short_id`]).
+
+use crate::config::NamesConfig;
+
+/// Attempts at drawing an ID that no existing name is a prefix of
+pub const MAX_ID_ATTEMPTS: usize = 32;
+
+/// Shortest ID prefix resolved; an exact ID may be shorter
+pub const MIN_PREFIX_LEN: usize = 4;
+
+/// Length IDs are shown with in listings
+pub const SHORT_ID_LEN: usize = 12;
+
+/// Longest image name, tag included
+pub const MAX_IMAGE_NAME_LEN: usize = 255;
+
+/// Longest image tag
+pub const MAX_TAG_LEN: usize = 128;
+
+/// Tag of an image name given without one
+pub const DEFAULT_TAG: &str = "latest";
+
+/// Name rule violations
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum NameError {
+    #[error("Name '{name}' looks like an ID: names of {min}-{max} hex digits are not allowed")]
+    IdLike { name: String, min: usize, max: usize },
+    #[error("Name '{name}' is a prefix of the existing ID {id}")]
+    IdPrefix { name: String, id: String },
+    #[error("No ID avoiding existing names found after {0} attempts")]
+    IdsExhausted(usize),
+    #[error("Image name cannot be empty")]
+    EmptyImageName,
+    #[error("Image name '{name}' is too long: at most {max} characters")]
+    ImageNameTooLong { name: String, max: usize },
+    #[error("Invalid image name '{name}': {reason}")]
+    InvalidImageName { name: String, reason: &'static str },
+    #[error("Image name '{name}' is taken: image '{existing}' has the same tag")]
+    TagTaken { name: String, existing: String },
+}
+
+/// Why an ID prefix didn't resolve to one item
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ResolveError {
+    #[error("No ID starts with '{0}'")]
+    NotFound(String),
+    #[error("ID prefix '{prefix}' is ambiguous: it matches {count} IDs")]
+    Ambiguous { prefix: String, count: usize },
+    #[error("ID prefix '{prefix}' is too short: use at least {min} characters")]
+    TooShort { prefix: String, min: usize },
+    #[error("'{reference}' is ambiguous: it is the name of {named} and a prefix of the ID {id}; use a longer ID")]
+    NameOrPrefix { reference: String, named: String, id: String },
+}
+
+/// The one item of `items` whose ID (`id_of`) is `prefix` or starts with it
+///
+/// An exact ID wins over longer IDs it is a prefix of and isn't held to
+/// [`MIN_PREFIX_LEN`].
+pub fn resolve_by_prefix<'a, T>(
+    items: impl IntoIterator<Item = &'a T>,
+    id_of: impl Fn(&T) -> &str,
+    prefix: &str,
+) -> Result<&'a T, ResolveError> {
+    let matches: Vec<&T> = items.into_iter().filter(|item| id_of(item).starts_with(prefix)).collect();
+    if let Some(exact) = matches.iter().find(|item| id_of(item) == prefix) {
+        return Ok(exact);
+    }
+    if prefix.len() < MIN_PREFIX_LEN {
+        return Err(ResolveError::TooShort { prefix: prefix.to_string(), min: MIN_PREFIX_LEN });
+    }
+
+    match matches.as_slice() {
+        [] => Err(ResolveError::NotFound(prefix.to_string())),
+        [item] => Ok(item),
+        _ => Err(ResolveError::Ambiguous { prefix: prefix.to_string(), count: matches.len() }),
+    }
+}
+
+/// The one item of `items` that `reference` refers to: the item with that
+/// exact ID, else the item of that name (`name_of`), else the one item whose
+/// ID starts with it (see [`resolve_by_prefix`])
+///
+/// A name that is also a usable prefix of another item's ID is
+/// [`ResolveError::NameOrPrefix`]: either could be meant, and guessing would
+/// act on the wrong item.
+pub fn resolve_reference<'a, T>(
+    items: impl IntoIterator<Item = &'a T>,
+    id_of: impl Fn(&T) -> &str,
+    name_of: impl Fn(&T) -> Option<&str>,
+    reference: &str,
+) -> Result<&'a T, ResolveError> {
+    let items: Vec<&T> = items.into_iter().collect();
+    if let Some(exact) = items.iter().find(|item| id_of(item) == reference) {
+        return Ok(exact);
+    }
+
+    let Some(named) = items.iter().find(|item| name_of(item) == Some(reference)) else {
+        return resolve_by_prefix(items, id_of, reference);
+    };
+    if reference.len() >= MIN_PREFIX_LEN
+        && let Some(other) = items.iter().find(|item| id_of(item) != id_of(named) && id_of(item).starts_with(reference))
+    {
+        return Err(ResolveError::NameOrPrefix {
+            reference: reference.to_string(),
+            named: short_id(id_of(named)).to_string(),
+            id: id_of(other).to_string(),
+        });
+    }
+    Ok(named)
+}
+
+/// `id` shortened for display
+pub fn short_id(id: &str) -> &str {
+    id.get(..SHORT_ID_LEN).unwrap_or(id)
+}
+
+/// `name` split into repository and tag, if it has one
+pub fn split_image_name(name: &str) -> (&str, Option<&str>) {
+    match name.rsplit_once(':') {
+        Some((repo, tag)) if !tag.contains('/') => (repo, Some(tag)),
+        _ => (name, None),
+    }
+}
+
+/// `repo:tag` with the default tag filled in; two image names name the
+/// same tag when these are equal
+pub fn canonical_image_name(name: &str) -> String {
+    let (repo, tag) = split_image_name(name);
+    format!("{}:{}", repo, tag.unwrap_or(DEFAULT_TAG))
+}
+
+/// Check the form of a new image name (`repo[/repo...][:tag]`)
+pub fn check_image_name(name: &str) -> Result<(), NameError> {
+    let invalid = |reason| Err(NameError::InvalidImageName { name: name.to_string(), reason });
+    if name.is_empty() {
+        return Err(NameError::EmptyImageName);
+    }
+    if name.len() > MAX_IMAGE_NAME_LEN {
+        return Err(NameError::ImageNameTooLong { name: name.to_string(), max: MAX_IMAGE_NAME_LEN });
+    }
+
+    let (repo, tag) = split_image_name(name);
+    if !repo.split('/').all(is_valid_repo_component) {
+        return invalid("repository parts are lowercase letters and digits, separated by '.', '_', '__' or '-'");
+    }
+    if let Some(tag) = tag {
+        let mut chars = tag.chars();
+        let valid = chars.next().is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+            && tag.len() <= MAX_TAG_LEN;
+        if !valid {
+            return invalid("tags are up to 128 letters, digits, '_', '.' and '-', not starting with '.' or '-'");
+        }
+    }
+    if crate::bootstrap::parse_base_reference(name).is_some() {
+        return invalid("'freebsd' names the base systems");
+    }
+    Ok(())
+}
+
+/// `[a-z0-9]+` runs joined by `.`, `_`, `__` or any number of `-`
+fn is_valid_repo_component(component: &str) -> bool {
+    let is_alnum = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit();
+    let mut separator = String::new();
+    let mut started = false;
+    for c in component.chars() {
+        if is_alnum(c) {
+            let joins = separator.is_empty()
+                || matches!(separator.as_str(), "." | "_" | "__")
+                || separator.chars().all(|s| s == '-');
+            if !joins || (!started && !separator.is_empty()) {
+                return false;
+            }
+            separator.clear();
+            started = true;
+        } else if matches!(c, '.' | '_' | '-') {
+            separator.push(c);
+        } else {
+            return false;
+        }
+    }
+    started && separator.is_empty()
+}
+
+/// Whether `name` has the form of a (short) ID under `policy`
+pub fn looks_like_id(name: &str, policy: &NamesConfig) -> bool {
+    policy.reject_hex
+        && (policy.hex_min_len..=policy.hex_max_len).contains(&name.len())
+        && name.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Check a new name against the policy and the IDs in use
+pub fn check_name<'a>(name: &str, policy: &NamesConfig, ids: impl IntoIterator<Item = &'a str>) -> Result<(), NameError> {
+    if looks_like_id(name, policy) {
+        return Err(NameError::IdLike {
+            name: name.to_string(),
+            min: policy.hex_min_len,
+            max: policy.hex_max_len,
+        });
+    }
+
+    match ids.into_iter().find(|id| id.starts_with(name)) {
+        Some(id) => Err(NameError::IdPrefix { name: name.to_string(), id: id.to_string() }),
+        None => Ok(()),
+    }
+}
+
+/// Whether some non-empty name in `names` is a prefix of `id`
+pub fn id_collides<'a>(id: &str, names: impl IntoIterator<Item = &'a str>) -> bool {
+    names.into_iter().any(|name| !name.is_empty() && id.starts_with(name))
+}
+
+/// Draw IDs from `generate` until one doesn't start with any of `names`
+pub fn unique_id(mut generate: impl FnMut() -> String, names: &[&str]) -> Result<String, NameError> {
+    for _ in 0..MAX_ID_ATTEMPTS {
+        let id = generate();
+        if !id_collides(&id, names.iter().copied()) {
+            return Ok(id);
+        }
+    }
+    Err(NameError::IdsExhausted(MAX_ID_ATTEMPTS))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "a1b2c3d4-e5f6-4a7b-8c9d-0e1f2a3b4c5d";
+
+    #[test]
+    fn test_looks_like_id() {
+        let policy = NamesConfig::default();
+        assert!(looks_like_id("a1b2c3", &policy));
+        assert!(looks_like_id("DEADBEEF", &policy));
+        assert!(looks_like_id(&"f".repeat(64), &policy));
+        assert!(!looks_like_id("a1b2c", &policy));
+        assert!(!looks_like_id(&"f".repeat(65), &policy));
+        assert!(!looks_like_id("web-1", &policy));
+        assert!(!looks_like_id("facade", &NamesConfig { reject_hex: false, ..policy }));
+    }
+
+    #[test]
+    fn test_check_name_rejects_id_forms() {
+        let policy = NamesConfig::default();
+        assert!(matches!(check_name("a1b2c3", &policy, []), Err(NameError::IdLike { .. })));
+
+        // Dashed prefixes aren't hex-only, but still collide with an ID
+        let err = check_name("a1b2c3d4-e5", &policy, [ID]).unwrap_err();
+        assert_eq!(err, NameError::IdPrefix { name: "a1b2c3d4-e5".to_string(), id: ID.to_string() });
+        // So do short ones the hex rule lets through
+        assert!(matches!(check_name("a1", &policy, [ID]), Err(NameError::IdPrefix { .. })));
+
+        assert!(check_name("web", &policy, [ID]).is_ok());
+        assert!(check_name("a1", &policy, []).is_ok());
+    }
+
+    #[test]
+    fn test_unique_id_regenerates_on_name_prefix() {
+        let mut drawn = vec![ID.to_string(), "b0000000-0000".to_string()].into_iter();
+        let id = unique_id(|| drawn.next().unwrap(), &["a1b2", "web"]).unwrap();
+        assert_eq!(id, "b0000000-0000");
+
+        assert!(!id_collides(ID, [""]));
+        assert_eq!(unique_id(|| ID.to_string(), &["a1"]), Err(NameError::IdsExhausted(MAX_ID_ATTEMPTS)));
+    }
+
+    #[test]
+    fn test_check_image_name() {
+        for name in ["web", "repo/name:tag", "my-org/web.app:1.2.3", "a__b/c---d", "web:_x", "base", "0f:V1"] {
+            assert_eq!(check_image_name(name), Ok(()), "{}", name);
+        }
+
+        assert_eq!(check_image_name(""), Err(NameError::EmptyImageName));
+        let long = "a".repeat(MAX_IMAGE_NAME_LEN + 1);
+        assert_eq!(check_image_name(&long), Err(NameError::ImageNameTooLong { name: long.clone(), max: MAX_IMAGE_NAME_LEN }));
+        assert_eq!(check_image_name(&format!("web:{}", "1".repeat(MAX_TAG_LEN))), Ok(()));
+
+        let invalid = [
+            " web", "web ", "we b", "Web", "web/", "/web", "a//b", "-web", "web.", "a..b", "a___b", "a.-b", "web:", "web:.x", "web:-x",
+            "web:a b", "web:a/b", "web::1", "w\u{e9}b", "web@sha", "freebsd", "freebsd:14.1",
+        ];
+        for name in invalid {
+            assert!(matches!(check_image_name(name), Err(NameError::InvalidImageName { .. })), "{:?}", name);
+        }
+        assert!(check_image_name(&format!("web:{}", "1".repeat(MAX_TAG_LEN + 1))).is_err());
+    }
+
+    #[test]
+    fn test_canonical_image_name() {
+        assert_eq!(split_image_name("repo/name:tag"), ("repo/name", Some("tag")));
+        assert_eq!(split_image_name("web"), ("web", None));
+        assert_eq!(canonical_image_name("web"), "web:latest");
+        assert_eq!(canonical_image_name("web:latest"), "web:latest");
+        assert_eq!(canonical_image_name("org/web:1.0"), "org/web:1.0");
+    }
+
+    const OTHER: &str = "a1b2ffff-0000-4000-8000-000000000000";
+    const IDS: [&str; 3] = [ID, OTHER, "c0ffee00-0000-4000-8000-000000000000"];
+
+    fn resolve(prefix: &str) -> Result<&'static str, ResolveError> {
+        resolve_by_prefix(IDS.iter(), |id| id, prefix).copied()
+    }
+
+    #[test]
+    fn test_resolve_exact_and_unique_prefix() {
+        assert_eq!(resolve(ID), Ok(ID));
+        assert_eq!(resolve("a1b2c"), Ok(ID));
+        assert_eq!(resolve("c0ff"), Ok(IDS[2]));
+        assert_eq!(resolve("a1b2f"), Ok(OTHER));
+
+        // Items are matched by whatever `id_of` returns
+        let items = [("web", "1234abcd"), ("db", "5678abcd")];
+        assert_eq!(resolve_by_prefix(&items, |item| item.1, "5678").unwrap().0, "db");
+    }
+
+    #[test]
+    fn test_resolve_ambiguous_prefix() {
+        assert_eq!(resolve("a1b2"), Err(ResolveError::Ambiguous { prefix: "a1b2".to_string(), count: 2 }));
+        // An exact ID wins even when it is a prefix of another
+        let ids = ["abcd", "abcdef"];
+        assert_eq!(resolve_by_prefix(ids.iter(), |id| id, "abcd"), Ok(&"abcd"));
+        assert!(matches!(resolve_by_prefix(ids.iter(), |id| id, "abc"), Err(ResolveError::TooShort { .. })));
+    }
+
+    #[test]
+    fn test_resolve_too_short_and_missing() {
+        // Too short even when only one ID would match
+        assert_eq!(resolve("c0f"), Err(ResolveError::TooShort { prefix: "c0f".to_string(), min: MIN_PREFIX_LEN }));
+        assert!(matches!(resolve(""), Err(ResolveError::TooShort { .. })));
+        // Short exact IDs still resolve
+        let ids = ["ab"];
+        assert_eq!(resolve_by_prefix(ids.iter(), |id| id, "ab"), Ok(&"ab"));
+
+        assert_eq!(resolve("deadbeef"), Err(ResolveError::NotFound("deadbeef".to_string())));
+        assert_eq!(resolve_by_prefix(std::iter::empty::<&&str>(), |id| id, "a1b2"), Err(ResolveError::NotFound("a1b2".to_string())));
+    }
+
+    /// (ID, name) of containers; the second is named like a prefix of the first
+    const NAMED: [(&str, Option<&str>); 3] = [
+        (ID, Some("web")),
+        ("c0ffee00-0000-4000-8000-000000000000", Some("a1b2c3")),
+        ("d00d0000-0000-4000-8000-000000000000", Some("d00d")),
+    ];
+
+    fn resolve_named(reference: &str) -> Result<&'static str, ResolveError> {
+        resolve_reference(NAMED.iter(), |item| item.0, |item| item.1, reference).map(|item| item.0)
+    }
+
+    #[test]
+    fn test_resolve_reference_order() {
+        assert_eq!(resolve_named(ID), Ok(ID));
+        assert_eq!(resolve_named("web"), Ok(ID));
+        assert_eq!(resolve_named("c0ff"), Ok(NAMED[1].0));
+        // A name that is a prefix of its own ID only
+        assert_eq!(resolve_named("d00d"), Ok(NAMED[2].0));
+        assert_eq!(resolve_named("nope"), Err(ResolveError::NotFound("nope".to_string())));
+
+        // An exact ID wins over a name
+        let items = [("abcd", Some("x")), ("ffff", Some("abcd"))];
+        assert_eq!(resolve_reference(items.iter(), |i| i.0, |i| i.1, "abcd").unwrap().0, "abcd");
+        // A name too short to be a prefix is just a name
+        let items = [(ID, None), (OTHER, Some("a1"))];
+        assert_eq!(resolve_reference(items.iter(), |i| i.0, |i| i.1, "a1").unwrap().0, OTHER);
+    }
+
+    #[test]
+    fn test_resolve_name_colliding_with_prefix() {
+        // "a1b2c3" names one container and starts another's ID
+        assert_eq!(
+            resolve_named("a1b2c3"),
+            Err(ResolveError::NameOrPrefix { reference: "a1b2c3".to_string(), named: "c0ffee00-000".to_string(), id: ID.to_string() })
+        );
+        // The full ID still reaches the other one
+        assert_eq!(resolve_named(ID), Ok(ID));
+    }
+
+    #[test]
+    fn test_short_id() {
+        assert_eq!(short_id(ID), "a1b2c3d4-e5f");
+        assert_eq!(short_id("abc"), "abc");
+        assert_eq!(short_id(""), "");
+    }
+}
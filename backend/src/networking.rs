@@ -2,25 +2,32 @@
 //!
 //! This module handles network configuration for FreeBSD jails, including:
 //! - Bridge interface management (bridge0)
-//! - IP address allocation from 10.11.0.0/16
+//! - IP address allocation from 10.11.0.0/16 and, when configured, from an
+//!   IPv6 subnet (`network.container_cidr6`); either family can be off
 //! - epair interface creation and attachment
 //! - NAT/pf configuration for internet access
 //! - Port forwarding with pf rules
+//!
+//! pf rules and the commands run in a jail are built by pure functions
+//! ([`nat_rules`], [`rdr_rules`], [`ContainerNetwork::jail_commands`]) so
+//! the address family handling can be tested off FreeBSD.
 
 use std::collections::HashSet;
-use std::net::IpAddr;
-use std::path::Path;
+use std::net::Ipv6Addr;
+use std::path::PathBuf;
 use std::process::Command;
 use std::fs;
 use std::thread;
 use std::time::Duration;
 use tracing::{debug, info, warn, error};
 
+use crate::cmdtrace::TracedCommand;
+
 const BRIDGE_NAME: &str = "bridge0";
 const BRIDGE_IP: &str = "10.11.0.1/16";
 const NETWORK_PREFIX: &str = "10.11.0";
 const NETWORK_CIDR: &str = "10.11.0.0/16";
-const PF_ANCHOR: &str = "kawakaze";
+pub(crate) const PF_ANCHOR: &str = "kawakaze";
 
 /// Network configuration errors
 #[derive(Debug)]
@@ -64,14 +71,21 @@ impl From<std::io::Error> for NetworkError {
 pub struct IpAllocator {
     allocated_ips: HashSet<std::net::Ipv4Addr>,
     next_ip: u32, // Offset from NETWORK_PREFIX
+    state_file: PathBuf,
 }
 
 impl IpAllocator {
     /// Create a new IP allocator for the 10.11.0.0/16 network
     pub fn new() -> Self {
+        Self::with_state_file(crate::paths::Paths::default().ip_allocations())
+    }
+
+    /// Create an allocator keeping its state in `state_file`
+    pub fn with_state_file(state_file: impl Into<PathBuf>) -> Self {
         let mut allocator = Self {
             allocated_ips: HashSet::new(),
             next_ip: 2, // Start at 10.11.0.2 (.1 is the bridge)
+            state_file: state_file.into(),
         };
 
         // Load existing allocations from state file
@@ -173,10 +187,10 @@ impl IpAllocator {
 
     /// Save allocation state to disk
     fn save_state(&self) -> Result<(), NetworkError> {
-        let state_dir = "/var/db/kawakaze";
-        fs::create_dir_all(state_dir)?;
+        if let Some(state_dir) = self.state_file.parent() {
+            fs::create_dir_all(state_dir)?;
+        }
 
-        let state_file = Path::new(state_dir).join("ip_allocations.txt");
         let mut content = String::new();
 
         for ip in &self.allocated_ips {
@@ -184,21 +198,19 @@ impl IpAllocator {
             content.push('\n');
         }
 
-        fs::write(&state_file, content)?;
+        fs::write(&self.state_file, content)?;
         Ok(())
     }
 
     /// Load allocation state from disk
     fn load_state(&mut self) -> Result<(), NetworkError> {
-        let state_file = Path::new("/var/db/kawakaze").join("ip_allocations.txt");
-
-        if !state_file.exists() {
+        if !self.state_file.exists() {
             return Err(NetworkError::IoError(
                 std::io::Error::new(std::io::ErrorKind::NotFound, "State file not found")
             ));
         }
 
-        let content = fs::read_to_string(&state_file)?;
+        let content = fs::read_to_string(&self.state_file)?;
         let mut max_offset = 1;
 
         for line in content.lines() {
@@ -231,19 +243,275 @@ impl Default for IpAllocator {
     }
 }
 
+/// An IPv6 subnet containers get addresses from
+///
+/// The first address after the network one (`::1`) is the bridge and the
+/// containers' gateway.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Subnet6 {
+    network: Ipv6Addr,
+    prefix_len: u8,
+}
+
+impl Subnet6 {
+    /// Longest prefix accepted; it leaves room for 254 containers
+    pub const MAX_PREFIX_LEN: u8 = 120;
+
+    /// Parse `address/prefix`; host bits in the address are cleared
+    pub fn parse(cidr: &str) -> Result<Self, String> {
+        let (addr, prefix) = cidr
+            .split_once('/')
+            .ok_or_else(|| format!("Invalid IPv6 subnet '{}': expected address/prefix", cidr))?;
+        let addr: Ipv6Addr = addr.parse().map_err(|_| format!("Invalid IPv6 subnet '{}': not an IPv6 address", cidr))?;
+        let prefix_len: u8 = prefix
+            .parse()
+            .ok()
+            .filter(|len| (1..=Self::MAX_PREFIX_LEN).contains(len))
+            .ok_or_else(|| format!("Invalid IPv6 subnet '{}': prefix must be 1-{}", cidr, Self::MAX_PREFIX_LEN))?;
+        let mask = u128::MAX << (128 - prefix_len);
+        Ok(Self { network: Ipv6Addr::from(u128::from(addr) & mask), prefix_len })
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    /// Whether `ip` is inside the subnet
+    pub fn contains(&self, ip: Ipv6Addr) -> bool {
+        let mask = u128::MAX << (128 - self.prefix_len);
+        u128::from(ip) & mask == u128::from(self.network)
+    }
+
+    /// The address `offset` hosts into the subnet, if the subnet has it
+    pub fn host(&self, offset: u64) -> Option<Ipv6Addr> {
+        (offset <= self.max_offset()).then(|| Ipv6Addr::from(u128::from(self.network) + offset as u128))
+    }
+
+    /// Offset of `ip` within the subnet
+    fn offset_of(&self, ip: Ipv6Addr) -> Option<u64> {
+        if !self.contains(ip) {
+            return None;
+        }
+        u64::try_from(u128::from(ip) - u128::from(self.network)).ok().filter(|offset| *offset <= self.max_offset())
+    }
+
+    /// Highest offset handed out: the last address of the subnet, capped
+    /// so the search for a free one stays bounded on a /64
+    fn max_offset(&self) -> u64 {
+        let host_bits = (128 - self.prefix_len).min(32);
+        (1u64 << host_bits) - 1
+    }
+
+    /// The bridge's address and the containers' default route
+    pub fn gateway(&self) -> Ipv6Addr {
+        Ipv6Addr::from(u128::from(self.network) + 1)
+    }
+
+    /// Whether the subnet is unique local (fc00::/7): such addresses aren't
+    /// routed on the internet, so traffic out is NATed like IPv4's
+    pub fn is_unique_local(&self) -> bool {
+        self.network.segments()[0] & 0xfe00 == 0xfc00
+    }
+}
+
+impl std::fmt::Display for Subnet6 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+/// IPv6 address allocator, the counterpart of [`IpAllocator`] for a
+/// [`Subnet6`]
+pub struct Ip6Allocator {
+    subnet: Subnet6,
+    allocated_ips: HashSet<Ipv6Addr>,
+    next_offset: u64,
+    state_file: PathBuf,
+}
+
+impl Ip6Allocator {
+    /// Create an allocator for `subnet` keeping its state in `state_file`
+    pub fn with_state_file(subnet: Subnet6, state_file: impl Into<PathBuf>) -> Self {
+        let mut allocator = Self {
+            subnet,
+            allocated_ips: HashSet::new(),
+            next_offset: 2, // ::1 is the bridge
+            state_file: state_file.into(),
+        };
+
+        if let Err(e) = allocator.load_state() {
+            debug!("No existing IPv6 allocation state found: {}", e);
+        }
+
+        allocator
+    }
+
+    pub fn subnet(&self) -> Subnet6 {
+        self.subnet
+    }
+
+    /// Allocate the next free address, wrapping around to fill gaps
+    pub fn allocate(&mut self) -> Result<Ipv6Addr, NetworkError> {
+        let max = self.subnet.max_offset();
+        let candidates = (self.next_offset..=max).chain(2..self.next_offset.min(max + 1));
+        for offset in candidates {
+            let Some(ip) = self.subnet.host(offset) else { continue };
+            if self.allocated_ips.insert(ip) {
+                self.next_offset = offset + 1;
+                self.save_state()?;
+                debug!("Allocated IPv6 address: {}", ip);
+                return Ok(ip);
+            }
+        }
+
+        error!("IPv6 address pool {} exhausted", self.subnet);
+        Err(NetworkError::IpExhausted)
+    }
+
+    /// Allocate a specific address
+    pub fn allocate_specific(&mut self, ip: Ipv6Addr) -> Result<(), NetworkError> {
+        if !self.subnet.offset_of(ip).is_some_and(|offset| offset >= 2) {
+            return Err(NetworkError::IpAllocationFailed(format!("IP {} is not in the {} network", ip, self.subnet)));
+        }
+        if !self.allocated_ips.insert(ip) {
+            return Err(NetworkError::IpAllocationFailed(format!("IP {} is already allocated", ip)));
+        }
+        self.save_state()?;
+        debug!("Allocated specific IPv6 address: {}", ip);
+        Ok(())
+    }
+
+    /// Release an address back to the pool
+    pub fn release(&mut self, ip: Ipv6Addr) -> Result<(), NetworkError> {
+        if self.allocated_ips.remove(&ip) {
+            debug!("Released IPv6 address: {}", ip);
+            self.save_state()?;
+        }
+        Ok(())
+    }
+
+    pub fn allocated_count(&self) -> usize {
+        self.allocated_ips.len()
+    }
+
+    fn save_state(&self) -> Result<(), NetworkError> {
+        if let Some(state_dir) = self.state_file.parent() {
+            fs::create_dir_all(state_dir)?;
+        }
+        let content: String = self.allocated_ips.iter().map(|ip| format!("{}\n", ip)).collect();
+        fs::write(&self.state_file, content)?;
+        Ok(())
+    }
+
+    /// Load allocations, dropping those outside the subnet (it was changed)
+    fn load_state(&mut self) -> Result<(), NetworkError> {
+        let content = fs::read_to_string(&self.state_file)?;
+        let mut max_offset = 1;
+        for ip in content.lines().filter_map(|line| line.parse::<Ipv6Addr>().ok()) {
+            if let Some(offset) = self.subnet.offset_of(ip) {
+                max_offset = max_offset.max(offset);
+                self.allocated_ips.insert(ip);
+            }
+        }
+        self.next_offset = max_offset + 1;
+        debug!("Loaded {} IPv6 allocations, next offset is {}", self.allocated_ips.len(), self.next_offset);
+        Ok(())
+    }
+}
+
+/// NAT rules for traffic leaving through `iface`: the IPv4 network's, and
+/// the IPv6 subnet's when it is unique local (a global one is routed)
+pub fn nat_rules(iface: &str, cidr: Option<&str>, subnet6: Option<&Subnet6>) -> String {
+    let mut rules = String::new();
+    if let Some(cidr) = cidr {
+        rules.push_str(&format!("nat on {} inet from {} to any -> ({})\n", iface, cidr, iface));
+    }
+    if let Some(subnet6) = subnet6.filter(|subnet| subnet.is_unique_local()) {
+        rules.push_str(&format!("nat on {} inet6 from {} to any -> ({})\n", iface, subnet6, iface));
+    }
+    rules
+}
+
+/// Redirect rules publishing `host_port` on `iface` to a container's
+/// addresses, one per family the container has
+///
+/// A mapping bound to a host address only gets the rule of that address's
+/// family, and fails if the container has no address of that family: pf
+/// can't redirect across families.
+pub fn rdr_rules(
+    iface: &str,
+    ip: Option<&str>,
+    ip6: Option<&str>,
+    mapping: &crate::container::PortMapping,
+) -> Result<String, NetworkError> {
+    let to = mapping.host_ip.map_or_else(|| "any".to_string(), |host_ip| host_ip.to_string());
+    let families = [
+        ("inet", ip, mapping.host_ip.is_none_or(|host_ip| host_ip.is_ipv4())),
+        ("inet6", ip6, mapping.host_ip.is_none_or(|host_ip| host_ip.is_ipv6())),
+    ];
+
+    let mut rules = String::new();
+    for (family, addr, wanted) in families {
+        let (Some(addr), true) = (addr, wanted) else { continue };
+        rules.push_str(&format!(
+            "rdr pass on {} {} proto {} from any to {} port {} -> {} port {}\n",
+            iface, family, mapping.protocol, to, mapping.host_port, addr, mapping.container_port
+        ));
+    }
+
+    if rules.is_empty() {
+        return Err(NetworkError::PfError(match mapping.host_ip {
+            Some(host_ip) => format!("host address {} has no container address of the same family to forward to", host_ip),
+            None => "the container has no address to forward to".to_string(),
+        }));
+    }
+    Ok(rules)
+}
+
 /// Network interface manager
+///
+/// Containers get an IPv4 address unless IPv4 is turned off
+/// ([`NetworkManager::without_ipv4`]) and an IPv6 one when a subnet is
+/// given ([`NetworkManager::with_ipv6`]).
 pub struct NetworkManager {
-    ip_allocator: IpAllocator,
+    ip_allocator: Option<IpAllocator>,
+    ip6_allocator: Option<Ip6Allocator>,
 }
 
 impl NetworkManager {
     /// Create a new network manager
     pub fn new() -> Self {
         Self {
-            ip_allocator: IpAllocator::new(),
+            ip_allocator: Some(IpAllocator::new()),
+            ip6_allocator: None,
+        }
+    }
+
+    /// Create a network manager keeping IP allocations in `state_file`
+    pub fn with_state_file(state_file: impl Into<PathBuf>) -> Self {
+        Self {
+            ip_allocator: Some(IpAllocator::with_state_file(state_file)),
+            ip6_allocator: None,
         }
     }
 
+    /// Also give containers addresses from `subnet`, keeping its
+    /// allocations in `state_file`
+    pub fn with_ipv6(mut self, subnet: Subnet6, state_file: impl Into<PathBuf>) -> Self {
+        self.ip6_allocator = Some(Ip6Allocator::with_state_file(subnet, state_file));
+        self
+    }
+
+    /// Give containers no IPv4 address (IPv6-only)
+    pub fn without_ipv4(mut self) -> Self {
+        self.ip_allocator = None;
+        self
+    }
+
+    fn subnet6(&self) -> Option<Subnet6> {
+        self.ip6_allocator.as_ref().map(Ip6Allocator::subnet)
+    }
+
     /// Initialize the bridge interface and NAT
     pub fn initialize(&self) -> Result<(), NetworkError> {
         info!("Initializing network infrastructure");
@@ -284,7 +552,7 @@ impl NetworkManager {
     fn bridge_exists(&self) -> Result<bool, NetworkError> {
         let output = Command::new("ifconfig")
             .arg(BRIDGE_NAME)
-            .output()?;
+            .traced_output()?;
 
         Ok(output.status.success())
     }
@@ -297,7 +565,7 @@ impl NetworkManager {
         let output = Command::new("ifconfig")
             .arg(BRIDGE_NAME)
             .arg("create")
-            .output()?;
+            .traced_output()?;
 
         if !output.status.success() {
             let stderr = String::from_utf8_lossy(&output.stderr);
@@ -306,36 +574,53 @@ impl NetworkManager {
             )));
         }
 
-        // Configure IP address
-        let output = Command::new("ifconfig")
-            .arg(BRIDGE_NAME)
-            .arg("inet")
-            .arg(BRIDGE_IP)
-            .arg("up")
-            .output()?;
+        // Configure the gateway address of each family
+        let mut addresses: Vec<Vec<String>> = Vec::new();
+        if self.ip_allocator.is_some() {
+            addresses.push(vec!["inet".into(), BRIDGE_IP.into(), "up".into()]);
+        }
+        if let Some(subnet6) = self.subnet6() {
+            addresses.push(vec!["inet6".into(), "-ifdisabled".into()]);
+            addresses.push(vec!["inet6".into(), format!("{}/{}", subnet6.gateway(), subnet6.prefix_len()), "up".into()]);
+        }
+        for args in &addresses {
+            let output = Command::new("ifconfig")
+                .arg(BRIDGE_NAME)
+                .args(args)
+                .traced_output()?;
 
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(NetworkError::BridgeCreationFailed(format!(
-                "Failed to configure bridge IP: {}", stderr
-            )));
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                return Err(NetworkError::BridgeCreationFailed(format!(
+                    "Failed to configure bridge IP: {}", stderr
+                )));
+            }
         }
 
-        info!("Bridge {} created with IP {}", BRIDGE_NAME, BRIDGE_IP);
+        info!("Bridge {} created", BRIDGE_NAME);
         Ok(())
     }
 
-    /// Enable IP forwarding
+    /// Enable IP forwarding for the families in use
     fn enable_ip_forwarding(&self) -> Result<(), NetworkError> {
         debug!("Enabling IP forwarding");
 
-        let output = Command::new("sysctl")
-            .arg("net.inet.ip.forwarding=1")
-            .output()?;
+        let mut sysctls = Vec::new();
+        if self.ip_allocator.is_some() {
+            sysctls.push("net.inet.ip.forwarding=1");
+        }
+        if self.ip6_allocator.is_some() {
+            sysctls.push("net.inet6.ip6.forwarding=1");
+        }
+        for sysctl in sysctls {
+            let output = Command::new("sysctl")
+                .arg(sysctl)
+                .traced_output()?;
 
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            warn!("Failed to enable IP forwarding: {}", stderr);
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                warn!("Failed to enable IP forwarding ({}): {}", sysctl, stderr);
+            }
         }
 
         Ok(())
@@ -348,7 +633,7 @@ impl NetworkManager {
         // Enable pf
         let output = Command::new("pfctl")
             .arg("-e")
-            .output();
+            .traced_output();
 
         // pf might already be enabled, ignore error
         if let Ok(output) = output {
@@ -372,13 +657,14 @@ impl NetworkManager {
             .arg(PF_ANCHOR)
             .arg("-F")
             .arg("all")
-            .output();
-
-        // Create NAT rules
-        // nat on $ext_if from 10.11.0.0/16 to any -> ($ext_if)
-        let nat_rules = format!(
-            "nat on {} from {} to any -> ({})\n",
-            default_iface, NETWORK_CIDR, default_iface
+            .traced_output();
+
+        // nat on $ext_if inet from 10.11.0.0/16 to any -> ($ext_if)
+        let subnet6 = self.subnet6();
+        let nat_rules = nat_rules(
+            &default_iface,
+            self.ip_allocator.as_ref().map(|_| NETWORK_CIDR),
+            subnet6.as_ref(),
         );
 
         // Load the rules
@@ -387,17 +673,7 @@ impl NetworkManager {
             .arg(PF_ANCHOR)
             .arg("-f")
             .arg("-")
-            .stdin(std::process::Stdio::piped())
-            .stdout(std::process::Stdio::piped())
-            .stderr(std::process::Stdio::piped())
-            .spawn()
-            .and_then(|mut child| {
-                use std::io::Write;
-                if let Some(mut stdin) = child.stdin.take() {
-                    stdin.write_all(nat_rules.as_bytes())?;
-                }
-                child.wait_with_output()
-            })
+            .traced_output_with_input(nat_rules.as_bytes())
             .map_err(|e| NetworkError::PfError(format!("Failed to execute pfctl: {}", e)))?;
 
         if !output.status.success() {
@@ -411,13 +687,21 @@ impl NetworkManager {
         Ok(())
     }
 
-    /// Get the default network interface
+    /// Get the default network interface: the one of the IPv4 default
+    /// route, or of the IPv6 one on a host without IPv4
     fn get_default_interface(&self) -> Result<Option<String>, NetworkError> {
+        match self.get_default_interface_for("inet")? {
+            Some(iface) => Ok(Some(iface)),
+            None => self.get_default_interface_for("inet6"),
+        }
+    }
+
+    fn get_default_interface_for(&self, family: &str) -> Result<Option<String>, NetworkError> {
         let output = Command::new("netstat")
             .arg("-nr")
             .arg("-f")
-            .arg("inet")
-            .output()?;
+            .arg(family)
+            .traced_output()?;
 
         if !output.status.success() {
             return Ok(None);
@@ -425,9 +709,9 @@ impl NetworkManager {
 
         let stdout = String::from_utf8_lossy(&output.stdout);
 
-        // Find the default route (0.0.0.0 or default)
+        // Find the default route (0.0.0.0, ::/0 or default)
         for line in stdout.lines() {
-            if line.starts_with("default") || line.starts_with("0.0.0.0") {
+            if line.starts_with("default") || line.starts_with("0.0.0.0") || line.starts_with("::/0") {
                 let parts: Vec<&str> = line.split_whitespace().collect();
                 // FreeBSD netstat format: Destination Gateway Flags Netif [Expire]
                 // Interface is typically at index 3 (4th column)
@@ -442,30 +726,54 @@ impl NetworkManager {
 
     /// Allocate network resources for a container
     pub fn allocate_network(&mut self, jail_name: &str) -> Result<ContainerNetwork, NetworkError> {
-        // Allocate IP address
-        let ip = self.ip_allocator.allocate()?;
-
-        // Create epair interface
-        let epair_a = self.create_epair(jail_name)?;
+        // Allocate an address of each family in use
+        let ip = self.ip_allocator.as_mut().map(IpAllocator::allocate).transpose()?;
+        let ip6 = match self.ip6_allocator.as_mut().map(Ip6Allocator::allocate).transpose() {
+            Ok(ip6) => ip6,
+            Err(e) => {
+                self.release_addresses(ip, None);
+                return Err(e);
+            }
+        };
 
-        // Attach epair_a to bridge
-        self.attach_to_bridge(&epair_a)?;
+        // Create epair interface and attach epair_a to the bridge
+        let epair_a = match self.create_epair(jail_name).and_then(|epair| self.attach_to_bridge(&epair).map(|_| epair)) {
+            Ok(epair) => epair,
+            Err(e) => {
+                self.release_addresses(ip, ip6);
+                return Err(e);
+            }
+        };
 
         // The epair_b will be moved into the jail
         // epair interfaces are named epair0a/epair0b, so we need to change just the last char
         let epair_b = format!("{}b", &epair_a[..epair_a.len().saturating_sub(1)]);
 
-        debug!("Allocated network for {}: IP={}, epair={}", jail_name, ip, epair_b);
+        debug!("Allocated network for {}: IP={:?}, IPv6={:?}, epair={}", jail_name, ip, ip6, epair_b);
 
+        let subnet6 = self.subnet6();
         Ok(ContainerNetwork {
-            ip: ip.to_string(),
+            ip: ip.map(|ip| ip.to_string()),
+            ip6: ip6.map(|ip6| ip6.to_string()),
             bridge: BRIDGE_NAME.to_string(),
             epair_host: epair_a,
             epair_jail: epair_b,
-            gateway: BRIDGE_IP.split('/').next().unwrap().to_string(),
+            gateway: ip.map(|_| BRIDGE_IP.split('/').next().unwrap().to_string()),
+            gateway6: subnet6.map(|subnet| subnet.gateway().to_string()),
+            prefix6: subnet6.map_or(0, |subnet| subnet.prefix_len()),
         })
     }
 
+    /// Give addresses back after a failed allocation
+    fn release_addresses(&mut self, ip: Option<std::net::Ipv4Addr>, ip6: Option<Ipv6Addr>) {
+        if let (Some(allocator), Some(ip)) = (self.ip_allocator.as_mut(), ip) {
+            let _ = allocator.release(ip);
+        }
+        if let (Some(allocator), Some(ip6)) = (self.ip6_allocator.as_mut(), ip6) {
+            let _ = allocator.release(ip6);
+        }
+    }
+
     /// Create an epair interface
     fn create_epair(&self, jail_name: &str) -> Result<String, NetworkError> {
         let epair_name = format!("e_{}", &jail_name[jail_name.len().saturating_sub(8)..]);
@@ -473,7 +781,7 @@ impl NetworkManager {
         let output = Command::new("ifconfig")
             .arg("epair")
             .arg("create")
-            .output()?;
+            .traced_output()?;
 
         if !output.status.success() {
             let stderr = String::from_utf8_lossy(&output.stderr);
@@ -496,7 +804,7 @@ impl NetworkManager {
             .arg("addm")
             .arg(interface)
             .arg("up")
-            .output()?;
+            .traced_output()?;
 
         if !output.status.success() {
             let stderr = String::from_utf8_lossy(&output.stderr);
@@ -509,7 +817,7 @@ impl NetworkManager {
         let output = Command::new("ifconfig")
             .arg(interface)
             .arg("up")
-            .output()?;
+            .traced_output()?;
 
         if !output.status.success() {
             let stderr = String::from_utf8_lossy(&output.stderr);
@@ -545,7 +853,7 @@ impl NetworkManager {
                 .arg(epair)
                 .arg("-vnet")
                 .arg(jail_name)
-                .output()?;
+                .traced_output()?;
 
             if output.status.success() {
                 info!("Successfully moved {} to jail {} on attempt {}", epair, jail_name, attempt);
@@ -586,48 +894,36 @@ impl NetworkManager {
 
         // Note: The epair interface is already moved into the jail via vnet.interface
         // parameter during jail creation. We only need to configure the IP and routing.
+        for command in network.jail_commands() {
+            let output = Command::new("jexec")
+                .arg(jail_name)
+                .args(&command.args)
+                .traced_output()?;
 
-        // Configure IP address inside the jail using jexec
-        let output = Command::new("jexec")
-            .arg(jail_name)
-            .arg("ifconfig")
-            .arg(&network.epair_jail)
-            .arg("inet")
-            .arg(&format!("{}/16", network.ip))
-            .arg("up")
-            .output()?;
-
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(NetworkError::EpairAttachmentFailed(format!(
-                "Failed to configure IP in jail {}: {}", jail_name, stderr
-            )));
-        }
-
-        // Set default route
-        let output = Command::new("jexec")
-            .arg(jail_name)
-            .arg("route")
-            .arg("add")
-            .arg("default")
-            .arg(&network.gateway)
-            .output()?;
-
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            warn!("Failed to set default route in jail {}: {}", jail_name, stderr);
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                if command.required {
+                    return Err(NetworkError::EpairAttachmentFailed(format!(
+                        "Failed to configure IP in jail {}: {}", jail_name, stderr
+                    )));
+                }
+                warn!("Failed to set default route in jail {}: {}", jail_name, stderr);
+            }
         }
 
-        info!("Network configured for jail {}: IP={}, gateway={}",
-              jail_name, network.ip, network.gateway);
+        info!("Network configured for jail {}: IP={:?}, IPv6={:?}, gateway={:?}, IPv6 gateway={:?}",
+              jail_name, network.ip, network.ip6, network.gateway, network.gateway6);
         Ok(())
     }
 
     /// Release network resources for a container
     pub fn release_network(&mut self, network: &ContainerNetwork) -> Result<(), NetworkError> {
-        // Release IP address
-        if let Ok(ip) = network.ip.parse::<std::net::Ipv4Addr>() {
-            self.ip_allocator.release(ip)?;
+        // Release IP addresses
+        if let (Some(allocator), Some(ip)) = (self.ip_allocator.as_mut(), network.ip.as_deref().and_then(|ip| ip.parse().ok())) {
+            allocator.release(ip)?;
+        }
+        if let (Some(allocator), Some(ip6)) = (self.ip6_allocator.as_mut(), network.ip6.as_deref().and_then(|ip| ip.parse().ok())) {
+            allocator.release(ip6)?;
         }
 
         // Remove epair interfaces
@@ -637,27 +933,26 @@ impl NetworkManager {
             .arg(BRIDGE_NAME)
             .arg("remm")
             .arg(&network.epair_host)
-            .output();
+            .traced_output();
 
         let _ = Command::new("ifconfig")
             .arg(&network.epair_host)
             .arg("destroy")
-            .output();
+            .traced_output();
 
-        debug!("Released network resources: IP={}, epair={}", network.ip, network.epair_host);
+        debug!("Released network resources: IP={:?}, IPv6={:?}, epair={}", network.ip, network.ip6, network.epair_host);
         Ok(())
     }
 
-    /// Set up port forwarding for a container
+    /// Set up port forwarding to a container's addresses
     pub fn setup_port_forwarding(
         &self,
-        container_ip: &str,
-        host_port: u16,
-        container_port: u16,
-        protocol: &str,
+        ip: Option<&str>,
+        ip6: Option<&str>,
+        mapping: &crate::container::PortMapping,
     ) -> Result<(), NetworkError> {
-        info!("Setting up port forwarding: {} -> {}:{} ({})",
-              host_port, container_ip, container_port, protocol);
+        info!("Setting up port forwarding: {} -> {:?}/{:?}:{} ({})",
+              mapping.host_port, ip, ip6, mapping.container_port, mapping.protocol);
 
         // Get the external interface for rdr
         let external_iface = self.get_default_interface()?
@@ -665,27 +960,14 @@ impl NetworkManager {
 
         // rdr pass on $ext_if inet proto tcp from any to any port $host_port -> $container_ip port $container_port
         // We use the external interface so external traffic can reach the container
-        let rule = format!(
-            "rdr pass on {} inet proto {} from any to any port {} -> {} port {}\n",
-            external_iface, protocol, host_port, container_ip, container_port
-        );
+        let rule = rdr_rules(&external_iface, ip, ip6, mapping)?;
 
         let output = Command::new("pfctl")
             .arg("-a")
             .arg(format!("{}_forwarding", PF_ANCHOR))
             .arg("-f")
             .arg("-")
-            .stdin(std::process::Stdio::piped())
-            .stdout(std::process::Stdio::piped())
-            .stderr(std::process::Stdio::piped())
-            .spawn()
-            .and_then(|mut child| {
-                use std::io::Write;
-                if let Some(mut stdin) = child.stdin.take() {
-                    stdin.write_all(rule.as_bytes())?;
-                }
-                child.wait_with_output()
-            })
+            .traced_output_with_input(rule.as_bytes())
             .map_err(|e| NetworkError::PfError(format!("Failed to execute pfctl: {}", e)))?;
 
         if !output.status.success() {
@@ -707,7 +989,7 @@ impl NetworkManager {
             .arg(format!("{}_forwarding", PF_ANCHOR))
             .arg("-F")
             .arg("all")
-            .output();
+            .traced_output();
 
         Ok(())
     }
@@ -722,11 +1004,51 @@ impl Default for NetworkManager {
 /// Network configuration for a container
 #[derive(Debug, Clone)]
 pub struct ContainerNetwork {
-    pub ip: String,
+    /// IPv4 address, unless IPv4 is off
+    pub ip: Option<String>,
+    /// IPv6 address, when an IPv6 subnet is configured
+    pub ip6: Option<String>,
     pub bridge: String,
     pub epair_host: String,
     pub epair_jail: String,
-    pub gateway: String,
+    pub gateway: Option<String>,
+    pub gateway6: Option<String>,
+    /// Prefix length of the IPv6 subnet
+    pub prefix6: u8,
+}
+
+/// A command run with `jexec` in a VNET jail
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JailCommand {
+    pub args: Vec<String>,
+    /// Whether a failure fails the configuration; routes are only warned about
+    pub required: bool,
+}
+
+impl ContainerNetwork {
+    /// Commands configuring the jail's end of the epair: for each family,
+    /// the address and then the default route through the bridge
+    pub fn jail_commands(&self) -> Vec<JailCommand> {
+        let command = |args: &[&str], required| JailCommand { args: args.iter().map(|arg| arg.to_string()).collect(), required };
+        let epair = self.epair_jail.as_str();
+        let mut commands = Vec::new();
+
+        if let Some(ip) = &self.ip {
+            commands.push(command(&["ifconfig", epair, "inet", &format!("{}/16", ip), "up"], true));
+            if let Some(gateway) = &self.gateway {
+                commands.push(command(&["route", "add", "default", gateway], false));
+            }
+        }
+        if let Some(ip6) = &self.ip6 {
+            // New interfaces start with IPv6 disabled unless rc.conf says otherwise
+            commands.push(command(&["ifconfig", epair, "inet6", "-ifdisabled"], true));
+            commands.push(command(&["ifconfig", epair, "inet6", &format!("{}/{}", ip6, self.prefix6), "up"], true));
+            if let Some(gateway6) = &self.gateway6 {
+                commands.push(command(&["route", "-6", "add", "default", gateway6], false));
+            }
+        }
+        commands
+    }
 }
 
 /// Check if running as root
@@ -833,20 +1155,174 @@ mod tests {
         assert!(!allocator.is_in_network(std::net::Ipv4Addr::new(192, 168, 1, 1)));
     }
 
-    #[test]
-    fn test_container_network() {
-        let network = ContainerNetwork {
-            ip: "10.11.0.2".to_string(),
+    fn network(ip: Option<&str>, ip6: Option<&str>) -> ContainerNetwork {
+        ContainerNetwork {
+            ip: ip.map(String::from),
+            ip6: ip6.map(String::from),
             bridge: "bridge0".to_string(),
             epair_host: "epair0a".to_string(),
             epair_jail: "epair0b".to_string(),
-            gateway: "10.11.0.1".to_string(),
-        };
+            gateway: ip.map(|_| "10.11.0.1".to_string()),
+            gateway6: ip6.map(|_| "fd00:6b7a::1".to_string()),
+            prefix6: 64,
+        }
+    }
+
+    #[test]
+    fn test_container_network() {
+        let network = network(Some("10.11.0.2"), None);
 
-        assert_eq!(network.ip, "10.11.0.2");
+        assert_eq!(network.ip.as_deref(), Some("10.11.0.2"));
         assert_eq!(network.bridge, "bridge0");
         assert_eq!(network.epair_host, "epair0a");
         assert_eq!(network.epair_jail, "epair0b");
-        assert_eq!(network.gateway, "10.11.0.1");
+        assert_eq!(network.gateway.as_deref(), Some("10.11.0.1"));
+    }
+
+    #[test]
+    fn test_subnet6() {
+        let subnet = Subnet6::parse("fd00:6b7a::1234/64").unwrap();
+        // Host bits are dropped
+        assert_eq!(subnet.to_string(), "fd00:6b7a::/64");
+        assert_eq!(subnet.gateway(), "fd00:6b7a::1".parse::<Ipv6Addr>().unwrap());
+        assert_eq!(subnet.host(2), Some("fd00:6b7a::2".parse().unwrap()));
+        assert!(subnet.contains("fd00:6b7a::ffff:1".parse().unwrap()));
+        assert!(!subnet.contains("fd00:6b7b::2".parse().unwrap()));
+        assert!(subnet.is_unique_local());
+        assert!(!Subnet6::parse("2001:db8:1::/64").unwrap().is_unique_local());
+
+        // A /120 has 256 addresses
+        let small = Subnet6::parse("2001:db8::100/120").unwrap();
+        assert_eq!(small.host(255), Some("2001:db8::1ff".parse().unwrap()));
+        assert_eq!(small.host(256), None);
+
+        for invalid in ["fd00::", "fd00::/0", "fd00::/121", "fd00::/x", "10.11.0.0/16"] {
+            assert!(Subnet6::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_ip6_allocator() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = dir.path().join("ip6_allocations.txt");
+        let subnet = Subnet6::parse("2001:db8::/120").unwrap();
+        let mut allocator = Ip6Allocator::with_state_file(subnet, &state);
+
+        // ::1 is the bridge
+        let first = allocator.allocate().unwrap();
+        assert_eq!(first, "2001:db8::2".parse::<Ipv6Addr>().unwrap());
+        assert_eq!(allocator.allocate().unwrap(), "2001:db8::3".parse::<Ipv6Addr>().unwrap());
+
+        assert!(allocator.allocate_specific("2001:db8::3".parse().unwrap()).is_err());
+        assert!(allocator.allocate_specific("2001:db8::1".parse().unwrap()).is_err());
+        assert!(allocator.allocate_specific("2001:db9::10".parse().unwrap()).is_err());
+        allocator.allocate_specific("2001:db8::10".parse().unwrap()).unwrap();
+        assert_eq!(allocator.allocated_count(), 3);
+
+        // State survives a restart; the next address follows the highest
+        allocator.release(first).unwrap();
+        let mut reloaded = Ip6Allocator::with_state_file(subnet, &state);
+        assert_eq!(reloaded.allocated_count(), 2);
+        assert_eq!(reloaded.allocate().unwrap(), "2001:db8::11".parse::<Ipv6Addr>().unwrap());
+
+        // Allocations outside a changed subnet are dropped
+        let moved = Ip6Allocator::with_state_file(Subnet6::parse("2001:db8:1::/64").unwrap(), &state);
+        assert_eq!(moved.allocated_count(), 0);
+    }
+
+    #[test]
+    fn test_ip6_allocator_fills_gaps_then_exhausts() {
+        let dir = tempfile::tempdir().unwrap();
+        let subnet = Subnet6::parse("2001:db8::/120").unwrap();
+        let mut allocator = Ip6Allocator::with_state_file(subnet, dir.path().join("state"));
+
+        // A /120 has ::2 to ::ff for containers
+        let allocated: Vec<Ipv6Addr> = (0..254).map(|_| allocator.allocate().unwrap()).collect();
+        assert_eq!(allocated.last(), Some(&"2001:db8::ff".parse().unwrap()));
+        assert!(matches!(allocator.allocate(), Err(NetworkError::IpExhausted)));
+
+        // A released address in the middle is found again
+        let gap = allocated[10];
+        allocator.release(gap).unwrap();
+        assert_eq!(allocator.allocate().unwrap(), gap);
+    }
+
+    #[test]
+    fn test_nat_rules() {
+        let ula = Subnet6::parse("fd00:6b7a::/64").unwrap();
+        let global = Subnet6::parse("2001:db8::/64").unwrap();
+
+        assert_eq!(nat_rules("vtnet0", Some(NETWORK_CIDR), None), "nat on vtnet0 inet from 10.11.0.0/16 to any -> (vtnet0)\n");
+        assert_eq!(
+            nat_rules("vtnet0", Some(NETWORK_CIDR), Some(&ula)),
+            "nat on vtnet0 inet from 10.11.0.0/16 to any -> (vtnet0)\nnat on vtnet0 inet6 from fd00:6b7a::/64 to any -> (vtnet0)\n"
+        );
+        // A global subnet is routed, not NATed
+        assert_eq!(nat_rules("vtnet0", None, Some(&global)), "");
+        assert_eq!(nat_rules("vtnet0", None, Some(&ula)), "nat on vtnet0 inet6 from fd00:6b7a::/64 to any -> (vtnet0)\n");
+    }
+
+    #[test]
+    fn test_rdr_rules_per_family() {
+        use crate::container::{PortMapping, PortProtocol};
+        let mapping = PortMapping::new(8080, 80, PortProtocol::Tcp);
+
+        assert_eq!(
+            rdr_rules("vtnet0", Some("10.11.0.2"), None, &mapping).unwrap(),
+            "rdr pass on vtnet0 inet proto tcp from any to any port 8080 -> 10.11.0.2 port 80\n"
+        );
+        assert_eq!(
+            rdr_rules("vtnet0", Some("10.11.0.2"), Some("fd00::2"), &mapping).unwrap(),
+            "rdr pass on vtnet0 inet proto tcp from any to any port 8080 -> 10.11.0.2 port 80\n\
+             rdr pass on vtnet0 inet6 proto tcp from any to any port 8080 -> fd00::2 port 80\n"
+        );
+        assert_eq!(
+            rdr_rules("vtnet0", None, Some("fd00::2"), &PortMapping::new(53, 53, PortProtocol::Udp)).unwrap(),
+            "rdr pass on vtnet0 inet6 proto udp from any to any port 53 -> fd00::2 port 53\n"
+        );
+        assert!(rdr_rules("vtnet0", None, None, &mapping).is_err());
+    }
+
+    #[test]
+    fn test_rdr_rules_bound_to_host_address() {
+        use crate::container::{PortMapping, PortProtocol};
+        let v6 = PortMapping::new(8080, 80, PortProtocol::Tcp).with_host_ip("::1".parse().unwrap());
+        let v4 = PortMapping::new(8080, 80, PortProtocol::Tcp).with_host_ip("192.0.2.1".parse().unwrap());
+
+        // Only the host address's family is forwarded
+        assert_eq!(
+            rdr_rules("vtnet0", Some("10.11.0.2"), Some("fd00::2"), &v6).unwrap(),
+            "rdr pass on vtnet0 inet6 proto tcp from any to ::1 port 8080 -> fd00::2 port 80\n"
+        );
+        assert_eq!(
+            rdr_rules("vtnet0", Some("10.11.0.2"), Some("fd00::2"), &v4).unwrap(),
+            "rdr pass on vtnet0 inet proto tcp from any to 192.0.2.1 port 8080 -> 10.11.0.2 port 80\n"
+        );
+        // pf can't redirect across families
+        let err = rdr_rules("vtnet0", Some("10.11.0.2"), None, &v6).unwrap_err();
+        assert!(err.to_string().contains("::1"), "{}", err);
+        assert!(rdr_rules("vtnet0", None, Some("fd00::2"), &v4).is_err());
+    }
+
+    #[test]
+    fn test_jail_commands() {
+        let args = |network: &ContainerNetwork| -> Vec<(String, bool)> {
+            network.jail_commands().into_iter().map(|c| (c.args.join(" "), c.required)).collect()
+        };
+
+        assert_eq!(args(&network(Some("10.11.0.2"), None)), vec![
+            ("ifconfig epair0b inet 10.11.0.2/16 up".to_string(), true),
+            ("route add default 10.11.0.1".to_string(), false),
+        ]);
+        assert_eq!(args(&network(None, Some("fd00:6b7a::2"))), vec![
+            ("ifconfig epair0b inet6 -ifdisabled".to_string(), true),
+            ("ifconfig epair0b inet6 fd00:6b7a::2/64 up".to_string(), true),
+            ("route -6 add default fd00:6b7a::1".to_string(), false),
+        ]);
+        // Dual stack configures both, IPv4 first
+        let dual = args(&network(Some("10.11.0.2"), Some("fd00:6b7a::2")));
+        assert_eq!(dual.len(), 5);
+        assert_eq!(dual[0].0, "ifconfig epair0b inet 10.11.0.2/16 up");
+        assert_eq!(dual[4].0, "route -6 add default fd00:6b7a::1");
     }
 }
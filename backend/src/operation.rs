@@ -0,0 +1,531 @@
+//! Container create and remove in the background
+//!
+//! Cloning a large image is quick, but mounting it and creating its volumes
+//! isn't always, and destroying a dataset of tens of gigabytes takes tens of
+//! seconds. With `async: true` on the create or remove request the handler
+//! answers 202 with an operation ID, does the ZFS work without holding the
+//! manager lock, and publishes an [`OperationProgress`] that
+//! `GET /operations/{id}` reports.
+//!
+//! An operation moves through the [`Phase`]s of its kind in order. Destroying
+//! samples the `used` bytes of the dataset while `zfs destroy` runs
+//! ([`destroy_with_progress`]); the other phases only report that they
+//! started. The ZFS calls go through [`DatasetOps`] so the phases and the
+//! sampling can be tested with a slow fake.
+
+use std::path::Path;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::zfs::{CopyPlan, DatasetOptions, Zfs, ZfsError};
+
+/// How often a destroy samples the bytes the dataset still uses
+pub const SAMPLE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// What an operation does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OperationKind {
+    Create,
+    Remove,
+}
+
+impl OperationKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OperationKind::Create => "create",
+            OperationKind::Remove => "remove",
+        }
+    }
+
+    /// The phases an operation of this kind goes through, in order
+    pub fn phases(self) -> &'static [Phase] {
+        match self {
+            OperationKind::Create => &[Phase::Pending, Phase::Cloning, Phase::Mounting, Phase::Volumes, Phase::Configuring, Phase::Complete],
+            OperationKind::Remove => &[Phase::Pending, Phase::Stopping, Phase::Unmounting, Phase::Destroying, Phase::Recording, Phase::Complete],
+        }
+    }
+}
+
+/// Step of an operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Phase {
+    /// Accepted, not started yet
+    Pending,
+    /// Cloning (or copying) the image snapshot
+    Cloning,
+    /// Mounting the container dataset
+    Mounting,
+    /// Creating and mounting anonymous volumes
+    Volumes,
+    /// Networking, the jail and the database record
+    Configuring,
+    /// Stopping the jail and releasing its network and ports
+    Stopping,
+    /// Unmounting the datasets
+    Unmounting,
+    /// Destroying the datasets
+    Destroying,
+    /// Removing the database record
+    Recording,
+    /// Done
+    Complete,
+    /// Stopped with an error
+    Failed,
+}
+
+impl Phase {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Phase::Pending => "pending",
+            Phase::Cloning => "cloning",
+            Phase::Mounting => "mounting",
+            Phase::Volumes => "volumes",
+            Phase::Configuring => "configuring",
+            Phase::Stopping => "stopping",
+            Phase::Unmounting => "unmounting",
+            Phase::Destroying => "destroying",
+            Phase::Recording => "recording",
+            Phase::Complete => "complete",
+            Phase::Failed => "failed",
+        }
+    }
+}
+
+/// Progress of an operation, as reported by `GET /operations/{id}`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OperationProgress {
+    pub id: String,
+    pub kind: OperationKind,
+    /// Container created or removed
+    pub container_id: String,
+    /// Its name, for display
+    pub name: String,
+    pub phase: Phase,
+    /// Overall progress (0-100)
+    pub progress: u8,
+    /// Human-readable description of the current step
+    pub current_step: String,
+    /// Bytes the dataset used when the destroy started
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes_total: Option<u64>,
+    /// Bytes it still used at the last sample
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes_remaining: Option<u64>,
+    /// Why the operation failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl OperationProgress {
+    pub fn new(id: String, kind: OperationKind, container_id: String, name: String) -> Self {
+        Self {
+            id,
+            kind,
+            container_id,
+            name,
+            phase: Phase::Pending,
+            progress: 0,
+            current_step: "Waiting to start".to_string(),
+            bytes_total: None,
+            bytes_remaining: None,
+            error: None,
+        }
+    }
+
+    /// Whether the operation has finished, successfully or not
+    pub fn is_finished(&self) -> bool {
+        matches!(self.phase, Phase::Complete | Phase::Failed)
+    }
+
+    /// Position of `phase` in this kind's sequence
+    fn index(&self, phase: Phase) -> Option<usize> {
+        self.kind.phases().iter().position(|p| *p == phase)
+    }
+
+    /// Move on to `phase`. Phases only move forward, skipping is allowed
+    /// (a container without volumes), and a finished operation stays finished.
+    pub fn enter(&mut self, phase: Phase, step: impl Into<String>) -> Result<(), String> {
+        if self.is_finished() {
+            return Err(format!("{} operation {} has already finished", self.kind.as_str(), self.id));
+        }
+        let (Some(from), Some(to)) = (self.index(self.phase), self.index(phase)) else {
+            return Err(format!("{:?} is not a phase of a {} operation", phase, self.kind.as_str()));
+        };
+        if to <= from {
+            return Err(format!("{} operation {} can't go from {:?} back to {:?}", self.kind.as_str(), self.id, self.phase, phase));
+        }
+
+        self.phase = phase;
+        self.current_step = step.into();
+        self.progress = self.phase_start(to);
+        Ok(())
+    }
+
+    /// Progress when the `index`th phase starts
+    fn phase_start(&self, index: usize) -> u8 {
+        let last = self.kind.phases().len() - 1;
+        (index * 100 / last) as u8
+    }
+
+    /// Record a sample of a running destroy
+    pub fn sample(&mut self, remaining: u64, total: u64) {
+        self.bytes_total = Some(total);
+        self.bytes_remaining = Some(remaining.min(total));
+        if let Some(index) = self.index(self.phase) {
+            let (start, end) = (self.phase_start(index) as u64, self.phase_start(index + 1) as u64);
+            let freed = total.saturating_sub(remaining);
+            self.progress = (start + ((end - start) * freed).checked_div(total).unwrap_or(0)) as u8;
+        }
+        self.current_step = format!("Destroying datasets ({} of {} left)", bytes_to_human(self.bytes_remaining.unwrap_or(0)), bytes_to_human(total));
+    }
+
+    /// Record how the operation ended
+    pub fn finish(&mut self, result: Result<(), String>) {
+        match result {
+            Ok(()) => {
+                self.phase = Phase::Complete;
+                self.progress = 100;
+                self.current_step = format!("Container {} {}", self.name, match self.kind {
+                    OperationKind::Create => "created",
+                    OperationKind::Remove => "removed",
+                });
+                if self.bytes_total.is_some() {
+                    self.bytes_remaining = Some(0);
+                }
+            }
+            Err(e) => {
+                self.current_step = format!("failed while {}", self.phase.as_str());
+                self.phase = Phase::Failed;
+                self.error = Some(e);
+            }
+        }
+    }
+
+    /// Record something the ZFS work reported
+    pub fn apply(&mut self, event: OperationEvent) -> Result<(), String> {
+        match event {
+            OperationEvent::Phase(phase, step) => self.enter(phase, step),
+            OperationEvent::Sample { remaining, total } => {
+                self.sample(remaining, total);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// What the ZFS work of an operation reports as it goes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OperationEvent {
+    /// A phase started, with a description of its step
+    Phase(Phase, String),
+    /// Bytes the dataset being destroyed still uses
+    Sample { remaining: u64, total: u64 },
+}
+
+fn bytes_to_human(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 { format!("{} B", bytes) } else { format!("{:.1} {}", value, UNITS[unit]) }
+}
+
+/// The ZFS calls a container create or remove makes
+pub trait DatasetOps: Sync {
+    /// Clone `snapshot` to `target`, or receive an encrypted copy
+    fn copy_snapshot(&self, snapshot: &str, target: &str, encryption: Option<&DatasetOptions>) -> Result<CopyPlan, ZfsError>;
+    fn mount(&self, dataset: &str, mountpoint: &Path) -> Result<(), ZfsError>;
+    fn create_volume(&self, dataset: &str, options: &DatasetOptions, mountpoint: &Path) -> Result<(), ZfsError>;
+    fn unmount(&self, dataset: &str) -> Result<(), ZfsError>;
+    fn destroy(&self, dataset: &str) -> Result<(), ZfsError>;
+    /// Bytes the dataset uses, `None` once it's gone
+    fn used_bytes(&self, dataset: &str) -> Option<u64>;
+}
+
+impl DatasetOps for Zfs {
+    fn copy_snapshot(&self, snapshot: &str, target: &str, encryption: Option<&DatasetOptions>) -> Result<CopyPlan, ZfsError> {
+        Zfs::copy_snapshot(self, snapshot, target, encryption)
+    }
+
+    fn mount(&self, dataset: &str, mountpoint: &Path) -> Result<(), ZfsError> {
+        self.mount_dataset(dataset, mountpoint)
+    }
+
+    fn create_volume(&self, dataset: &str, options: &DatasetOptions, mountpoint: &Path) -> Result<(), ZfsError> {
+        self.create_dataset_with_options(dataset, &options.clone().mounted_at(mountpoint))
+            .and_then(|()| self.mount_at_mountpoint(dataset))
+    }
+
+    fn unmount(&self, dataset: &str) -> Result<(), ZfsError> {
+        self.unmount_dataset(dataset)
+    }
+
+    fn destroy(&self, dataset: &str) -> Result<(), ZfsError> {
+        Zfs::destroy(self, dataset)
+    }
+
+    fn used_bytes(&self, dataset: &str) -> Option<u64> {
+        self.get_used_space(dataset).ok()
+    }
+}
+
+/// Destroy `dataset`, reporting `(remaining, total)` bytes every `interval`
+/// until `zfs destroy` returns
+pub fn destroy_with_progress<D: DatasetOps>(
+    ops: &D,
+    dataset: &str,
+    interval: Duration,
+    report: &mut dyn FnMut(u64, u64),
+) -> Result<(), ZfsError> {
+    let total = ops.used_bytes(dataset).unwrap_or(0);
+    report(total, total);
+
+    let (done_tx, done_rx) = std::sync::mpsc::channel();
+    std::thread::scope(|scope| {
+        scope.spawn(move || {
+            let _ = done_tx.send(ops.destroy(dataset));
+        });
+        loop {
+            match done_rx.recv_timeout(interval) {
+                Ok(result) => return result,
+                Err(RecvTimeoutError::Timeout) => {
+                    // Gone between the timeout and the sample: the result is next
+                    if let Some(remaining) = ops.used_bytes(dataset) {
+                        report(remaining.min(total), total);
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(ZfsError::CommandFailed(format!("destroy of {} did not finish", dataset)));
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use std::time::Instant;
+
+    /// Datasets whose destroy takes `destroy_time`, freeing space evenly
+    /// meanwhile; every call is logged
+    struct SlowZfs {
+        size: u64,
+        destroy_time: Duration,
+        fail_destroy: bool,
+        destroying: Mutex<Option<Instant>>,
+        calls: Mutex<Vec<String>>,
+    }
+
+    impl SlowZfs {
+        fn new(size: u64, destroy_time: Duration) -> Self {
+            Self { size, destroy_time, fail_destroy: false, destroying: Mutex::new(None), calls: Mutex::new(Vec::new()) }
+        }
+
+        fn log(&self, call: String) {
+            self.calls.lock().unwrap().push(call);
+        }
+    }
+
+    impl DatasetOps for SlowZfs {
+        fn copy_snapshot(&self, snapshot: &str, target: &str, _: Option<&DatasetOptions>) -> Result<CopyPlan, ZfsError> {
+            self.log(format!("clone {} {}", snapshot, target));
+            Ok(crate::zfs::plan_copy(false, false))
+        }
+
+        fn mount(&self, dataset: &str, _: &Path) -> Result<(), ZfsError> {
+            self.log(format!("mount {}", dataset));
+            Ok(())
+        }
+
+        fn create_volume(&self, dataset: &str, _: &DatasetOptions, _: &Path) -> Result<(), ZfsError> {
+            self.log(format!("volume {}", dataset));
+            Ok(())
+        }
+
+        fn unmount(&self, dataset: &str) -> Result<(), ZfsError> {
+            self.log(format!("unmount {}", dataset));
+            Ok(())
+        }
+
+        fn destroy(&self, dataset: &str) -> Result<(), ZfsError> {
+            self.log(format!("destroy {}", dataset));
+            *self.destroying.lock().unwrap() = Some(Instant::now());
+            std::thread::sleep(self.destroy_time);
+            *self.destroying.lock().unwrap() = None;
+            if self.fail_destroy {
+                return Err(ZfsError::CommandFailed("dataset is busy".to_string()));
+            }
+            Ok(())
+        }
+
+        fn used_bytes(&self, _: &str) -> Option<u64> {
+            let destroying = *self.destroying.lock().unwrap();
+            Some(match destroying {
+                None => self.size,
+                Some(started) => {
+                    let done = (started.elapsed().as_millis() as u64).min(self.destroy_time.as_millis() as u64);
+                    self.size - self.size * done / self.destroy_time.as_millis().max(1) as u64
+                }
+            })
+        }
+    }
+
+    #[test]
+    fn test_phases_move_forward() {
+        let mut op = OperationProgress::new("op1".into(), OperationKind::Remove, "c1".into(), "web".into());
+        assert_eq!(op.progress, 0);
+
+        op.enter(Phase::Stopping, "Stopping").unwrap();
+        assert_eq!(op.progress, 20);
+        // Skipping ahead is fine; going back or into another kind's phase isn't
+        op.enter(Phase::Destroying, "Destroying").unwrap();
+        assert_eq!(op.progress, 60);
+        assert!(op.enter(Phase::Unmounting, "Unmounting").is_err());
+        assert!(op.enter(Phase::Cloning, "Cloning").is_err());
+        assert_eq!(op.phase, Phase::Destroying);
+
+        op.finish(Ok(()));
+        assert_eq!((op.phase, op.progress), (Phase::Complete, 100));
+        assert!(op.is_finished());
+        assert!(op.enter(Phase::Recording, "Recording").is_err());
+    }
+
+    #[test]
+    fn test_failure_keeps_progress() {
+        let mut op = OperationProgress::new("op1".into(), OperationKind::Create, "c1".into(), "web".into());
+        op.enter(Phase::Mounting, "Mounting").unwrap();
+        op.finish(Err("mount failed".to_string()));
+        assert_eq!(op.phase, Phase::Failed);
+        assert_eq!(op.progress, 40);
+        assert_eq!(op.error.as_deref(), Some("mount failed"));
+        assert_eq!(op.current_step, "failed while mounting");
+    }
+
+    #[test]
+    fn test_sample_interpolates_destroy() {
+        let mut op = OperationProgress::new("op1".into(), OperationKind::Remove, "c1".into(), "web".into());
+        op.enter(Phase::Destroying, "Destroying").unwrap();
+        op.sample(10 << 30, 10 << 30);
+        assert_eq!(op.progress, 60);
+        op.sample(5 << 30, 10 << 30);
+        assert_eq!(op.progress, 70);
+        assert_eq!(op.current_step, "Destroying datasets (5.0 GB of 10.0 GB left)");
+        op.sample(0, 10 << 30);
+        assert_eq!(op.progress, 80);
+        // An empty dataset doesn't divide by zero
+        op.sample(0, 0);
+        assert_eq!(op.progress, 60);
+    }
+
+    #[test]
+    fn test_destroy_with_progress_samples_slow_destroy() {
+        let zfs = SlowZfs::new(1000, Duration::from_millis(200));
+        let mut samples = Vec::new();
+        destroy_with_progress(&zfs, "tank/c/web", Duration::from_millis(20), &mut |remaining, total| {
+            samples.push((remaining, total));
+        })
+        .unwrap();
+
+        assert_eq!(samples[0], (1000, 1000));
+        assert!(samples.len() > 3, "only {} samples", samples.len());
+        assert!(samples.iter().all(|(_, total)| *total == 1000));
+        assert!(samples.windows(2).all(|pair| pair[1].0 <= pair[0].0));
+        assert!(samples.last().unwrap().0 < 1000);
+        assert_eq!(*zfs.calls.lock().unwrap(), ["destroy tank/c/web"]);
+    }
+
+    #[test]
+    fn test_destroy_with_progress_returns_error() {
+        let mut zfs = SlowZfs::new(1000, Duration::from_millis(10));
+        zfs.fail_destroy = true;
+        let err = destroy_with_progress(&zfs, "tank/c/web", Duration::from_millis(5), &mut |_, _| {}).unwrap_err();
+        assert!(err.to_string().contains("busy"));
+    }
+
+    #[test]
+    fn test_provision_and_destroy_report_phases() {
+        use crate::JailManager;
+
+        let zfs = SlowZfs::new(1000, Duration::from_millis(50));
+        let config: crate::container::ContainerConfig = serde_json::from_value(serde_json::json!({ "image_id": "base" })).unwrap();
+        let volume = crate::container::Mount::new(
+            "tank/volumes/c1-data".to_string(),
+            "/data".to_string(),
+            crate::container::MountType::Zfs,
+            false,
+        );
+        let pending = crate::PendingContainer {
+            id: "c1".to_string(),
+            jail_name: "kawakaze-c1".to_string(),
+            dataset: "tank/containers/c1".to_string(),
+            mountpoint: "/var/kawakaze/containers/c1".into(),
+            snapshot: "tank/images/base@latest".to_string(),
+            encryption: None,
+            anonymous: vec![volume],
+            ports: Vec::new(),
+            devfs_ruleset: 4,
+            config,
+        };
+
+        let mut op = OperationProgress::new("op1".into(), OperationKind::Create, "c1".into(), "c1".into());
+        JailManager::provision_container(Some(&zfs), &pending, &mut |phase, step| op.enter(phase, step).unwrap()).unwrap();
+        assert_eq!(op.phase, Phase::Volumes);
+        assert_eq!(
+            *zfs.calls.lock().unwrap(),
+            ["clone tank/images/base@latest tank/containers/c1", "mount tank/containers/c1", "volume tank/volumes/c1-data"]
+        );
+
+        zfs.calls.lock().unwrap().clear();
+        let teardown = crate::ContainerTeardown {
+            id: "c1".to_string(),
+            dataset: "tank/containers/c1".to_string(),
+            dataset_missing: false,
+            volumes: vec!["tank/volumes/c1-data".to_string()],
+            hosts_before: Default::default(),
+        };
+        let op = std::cell::RefCell::new(OperationProgress::new("op2".into(), OperationKind::Remove, "c1".into(), "c1".into()));
+        let mut samples = 0;
+        JailManager::destroy_container_datasets(
+            Some(&zfs),
+            &teardown,
+            &mut |phase, step| op.borrow_mut().enter(phase, step).unwrap(),
+            &mut |remaining, total| {
+                samples += 1;
+                op.borrow_mut().sample(remaining, total);
+            },
+        );
+        let op = op.into_inner();
+        assert_eq!(op.phase, Phase::Destroying);
+        assert_eq!(op.bytes_total, Some(1000));
+        // The interval sampling is covered above; the destroy is sampled at least once
+        assert!(samples >= 1);
+        // Volumes are unmounted before the root they're mounted in
+        assert_eq!(
+            *zfs.calls.lock().unwrap(),
+            [
+                "unmount tank/volumes/c1-data",
+                "unmount tank/containers/c1",
+                "destroy tank/volumes/c1-data",
+                "destroy tank/containers/c1",
+            ]
+        );
+
+        // A dataset destroyed out of band is skipped; its volumes are not
+        zfs.calls.lock().unwrap().clear();
+        let teardown = crate::ContainerTeardown { dataset_missing: true, ..teardown };
+        JailManager::destroy_container_datasets(Some(&zfs), &teardown, &mut |_, _| {}, &mut |_, _| {});
+        assert_eq!(*zfs.calls.lock().unwrap(), ["unmount tank/volumes/c1-data", "destroy tank/volumes/c1-data"]);
+
+        // Without ZFS there is nothing to do
+        assert!(JailManager::provision_container(None::<&SlowZfs>, &pending, &mut |_, _| panic!("no phases")).is_ok());
+    }
+}
@@ -0,0 +1,240 @@
+//! Orphaned dataset discovery
+//!
+//! Restoring an older database can leave container and image datasets on
+//! the pool that no record points at. These are found by diffing the
+//! children of the containers/images roots against the known records.
+//! Orphans are only ever reported: adopting or destroying one is an
+//! explicit request naming the dataset exactly as listed.
+
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::timestamp::Timestamp;
+use crate::zfs::DatasetInfo;
+
+/// Orphan handling errors
+#[derive(Debug, thiserror::Error)]
+pub enum OrphanError {
+    #[error("Dataset '{0}' is not a listed orphan")]
+    NotListed(String),
+    #[error("Refusing to touch dataset '{0}': outside the managed containers/images roots")]
+    OutOfScope(String),
+    #[error("Cannot adopt '{0}': {1}")]
+    Invalid(String, String),
+    #[error("Cannot adopt '{0}': {1}")]
+    Conflict(String, String),
+    #[error("ZFS error: {0}")]
+    Zfs(String),
+    #[error("Store error: {0}")]
+    Store(String),
+}
+
+/// What an orphaned dataset looks like it was
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OrphanKind {
+    Container,
+    Image,
+}
+
+impl OrphanKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrphanKind::Container => "container",
+            OrphanKind::Image => "image",
+        }
+    }
+}
+
+/// A dataset under a managed root with no record pointing at it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Orphan {
+    pub dataset: String,
+    pub kind: OrphanKind,
+    pub size_bytes: u64,
+    pub created_at: Timestamp,
+    /// Snapshot names on the dataset, oldest first
+    pub snapshots: Vec<String>,
+}
+
+impl Orphan {
+    /// Snapshot an adopted image would be cloned from
+    pub fn latest_snapshot(&self) -> Option<String> {
+        self.snapshots.last().map(|snap| format!("{}@{}", self.dataset, snap))
+    }
+
+    /// Last path component of the dataset
+    pub fn leaf(&self) -> &str {
+        self.dataset.rsplit('/').next().unwrap_or(&self.dataset)
+    }
+}
+
+/// Direct children of `root` in `entries` that aren't in `known`, with
+/// their snapshots attached
+///
+/// `entries` is a `zfs list` of `root` down to depth 2 (see
+/// `Zfs::list_tree`); grandchildren and the root itself are ignored.
+pub fn find_orphans(root: &str, kind: OrphanKind, entries: &[DatasetInfo], known: &HashSet<String>) -> Vec<Orphan> {
+    let prefix = format!("{}/", root);
+    let mut orphans: Vec<Orphan> = entries
+        .iter()
+        .filter(|entry| {
+            entry.name.strip_prefix(&prefix).is_some_and(|rest| !rest.contains('/') && !rest.contains('@'))
+        })
+        .filter(|entry| !known.contains(&entry.name))
+        .map(|entry| Orphan {
+            dataset: entry.name.clone(),
+            kind,
+            size_bytes: entry.used_bytes,
+            created_at: Timestamp::from_secs(entry.created_at),
+            snapshots: Vec::new(),
+        })
+        .collect();
+
+    for orphan in &mut orphans {
+        let snap_prefix = format!("{}@", orphan.dataset);
+        orphan.snapshots = entries
+            .iter()
+            .filter_map(|entry| entry.name.strip_prefix(&snap_prefix))
+            .map(str::to_string)
+            .collect();
+    }
+
+    orphans
+}
+
+/// Look up a dataset in an orphan listing, requiring an exact name match
+pub fn find_listed<'a>(orphans: &'a [Orphan], dataset: &str) -> Result<&'a Orphan, OrphanError> {
+    orphans
+        .iter()
+        .find(|orphan| orphan.dataset == dataset)
+        .ok_or_else(|| OrphanError::NotListed(dataset.to_string()))
+}
+
+/// Only allow destroying datasets strictly below one of the managed roots
+pub fn check_scope(dataset: &str, roots: &[&str]) -> Result<(), OrphanError> {
+    let in_scope = !dataset.contains('@')
+        && !dataset.split('/').any(|part| part.is_empty() || part == "." || part == "..")
+        && roots.iter().any(|root| {
+            dataset.strip_prefix(root).and_then(|rest| rest.strip_prefix('/')).is_some_and(|rest| !rest.is_empty())
+        });
+
+    if in_scope {
+        Ok(())
+    } else {
+        Err(OrphanError::OutOfScope(dataset.to_string()))
+    }
+}
+
+/// Container ID for an adopted container dataset
+///
+/// Container datasets are named after the first 8 characters of the
+/// container ID, so the adopted ID keeps that prefix to line up with the
+/// jail name and mountpoint derived from it.
+pub fn adopted_container_id(orphan: &Orphan) -> Result<String, OrphanError> {
+    let leaf = orphan.leaf();
+    if leaf.len() != 8 || !leaf.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(OrphanError::Invalid(
+            orphan.dataset.clone(),
+            "container datasets must be named after an 8 character container ID".to_string(),
+        ));
+    }
+
+    let fresh = uuid::Uuid::new_v4().to_string();
+    Ok(format!("{}{}", leaf.to_ascii_lowercase(), &fresh[8..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTAINERS: &str = "tank/k/containers";
+    const IMAGES: &str = "tank/k/images";
+
+    fn entry(name: &str, used_bytes: u64, created_at: i64) -> DatasetInfo {
+        DatasetInfo { name: name.to_string(), used_bytes, created_at, clones: Vec::new() }
+    }
+
+    /// Three container datasets, one with a nested child, of which the
+    /// store only knows the first
+    fn container_tree() -> Vec<DatasetInfo> {
+        vec![
+            entry(CONTAINERS, 4096, 100),
+            entry("tank/k/containers/aaaa1111", 1000, 200),
+            entry("tank/k/containers/bbbb2222", 2000, 300),
+            entry("tank/k/containers/bbbb2222/nested", 10, 301),
+            entry("tank/k/containers/cccc3333", 3000, 400),
+            entry("tank/k/containers/cccc3333@manual", 0, 401),
+        ]
+    }
+
+    fn known(datasets: &[&str]) -> HashSet<String> {
+        datasets.iter().map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn test_find_orphans_diffs_against_known() {
+        let orphans = find_orphans(CONTAINERS, OrphanKind::Container, &container_tree(), &known(&["tank/k/containers/aaaa1111"]));
+
+        let names: Vec<&str> = orphans.iter().map(|o| o.dataset.as_str()).collect();
+        assert_eq!(names, vec!["tank/k/containers/bbbb2222", "tank/k/containers/cccc3333"]);
+        assert_eq!(orphans[0].size_bytes, 2000);
+        assert_eq!(orphans[0].created_at, Timestamp::from_secs(300));
+        assert!(orphans[0].snapshots.is_empty());
+        assert_eq!(orphans[1].snapshots, vec!["manual"]);
+    }
+
+    #[test]
+    fn test_find_orphans_nothing_when_all_known() {
+        let all = known(&["tank/k/containers/aaaa1111", "tank/k/containers/bbbb2222", "tank/k/containers/cccc3333"]);
+        assert!(find_orphans(CONTAINERS, OrphanKind::Container, &container_tree(), &all).is_empty());
+    }
+
+    #[test]
+    fn test_orphan_image_latest_snapshot() {
+        let entries = vec![
+            entry(IMAGES, 0, 1),
+            entry("tank/k/images/web", 500, 10),
+            entry("tank/k/images/web@web-old", 0, 11),
+            entry("tank/k/images/web@web-new", 0, 12),
+            entry("tank/k/images/bare", 500, 20),
+        ];
+        let orphans = find_orphans(IMAGES, OrphanKind::Image, &entries, &HashSet::new());
+
+        assert_eq!(orphans[0].latest_snapshot().as_deref(), Some("tank/k/images/web@web-new"));
+        assert_eq!(orphans[1].latest_snapshot(), None);
+    }
+
+    #[test]
+    fn test_find_listed_requires_exact_name() {
+        let orphans = find_orphans(CONTAINERS, OrphanKind::Container, &container_tree(), &HashSet::new());
+
+        assert!(find_listed(&orphans, "tank/k/containers/bbbb2222").is_ok());
+        assert!(matches!(find_listed(&orphans, "bbbb2222"), Err(OrphanError::NotListed(_))));
+        assert!(matches!(find_listed(&orphans, "tank/k/containers/bbbb2222/"), Err(OrphanError::NotListed(_))));
+        assert!(matches!(find_listed(&orphans, "tank/k/containers"), Err(OrphanError::NotListed(_))));
+    }
+
+    #[test]
+    fn test_check_scope() {
+        let roots = [CONTAINERS, IMAGES];
+        assert!(check_scope("tank/k/containers/bbbb2222", &roots).is_ok());
+        assert!(check_scope("tank/k/containers", &roots).is_err());
+        assert!(check_scope("tank/k/containers-old/x", &roots).is_err());
+        assert!(check_scope("tank/k/containers/../jails", &roots).is_err());
+        assert!(check_scope("tank/k/images/web@snap", &roots).is_err());
+        assert!(check_scope("tank", &roots).is_err());
+    }
+
+    #[test]
+    fn test_adopted_container_id_keeps_prefix() {
+        let orphans = find_orphans(CONTAINERS, OrphanKind::Container, &container_tree(), &HashSet::new());
+        let id = adopted_container_id(&orphans[0]).unwrap();
+        assert!(id.starts_with("aaaa1111"));
+        assert_eq!(id.len(), 36);
+
+        let odd = Orphan { dataset: "tank/k/containers/my-box".to_string(), ..orphans[0].clone() };
+        assert!(matches!(adopted_container_id(&odd), Err(OrphanError::Invalid(..))));
+    }
+}
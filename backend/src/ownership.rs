@@ -0,0 +1,241 @@
+//! File ownership and modes for COPY and ADD
+//!
+//! `COPY --chown=user[:group]` names users of the image being built, so they
+//! are looked up in the build root's `/etc/passwd` and `/etc/group`, never
+//! the host's. Numeric IDs are taken verbatim. As with Docker, a user given
+//! without a group also sets the group to the same numeric ID.
+//!
+//! [`apply`] walks what a copy wrote: the entries of the source tree, found
+//! again under the destination. Symlinks are `lchown`ed and never followed,
+//! and a destination that resolves outside the build root is refused.
+
+use std::fs;
+use std::io;
+use std::os::unix::fs::{PermissionsExt, lchown};
+use std::path::{Path, PathBuf};
+
+/// Numeric owner for copied files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Owner {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// Check a `--chown` value without resolving it: `user` or `user:group`
+pub fn check_chown(spec: &str) -> Result<(), String> {
+    let (user, group) = split_chown(spec);
+    if user.is_empty() || group.is_some_and(str::is_empty) {
+        return Err(format!("Invalid --chown '{}': expected user[:group]", spec));
+    }
+    Ok(())
+}
+
+/// Parse a `--chmod` value: octal permission bits, at most 7777
+pub fn parse_chmod(spec: &str) -> Result<u32, String> {
+    u32::from_str_radix(spec, 8)
+        .ok()
+        .filter(|mode| !spec.is_empty() && *mode <= 0o7777)
+        .ok_or_else(|| format!("Invalid --chmod '{}': expected octal permissions like 755", spec))
+}
+
+fn split_chown(spec: &str) -> (&str, Option<&str>) {
+    match spec.split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (spec, None),
+    }
+}
+
+/// Resolve `user[:group]` against the passwd and group files under `root`
+pub fn resolve_owner(root: &Path, spec: &str) -> Result<Owner, String> {
+    check_chown(spec)?;
+    let (user, group) = split_chown(spec);
+
+    let uid = match user.parse() {
+        Ok(uid) => uid,
+        Err(_) => lookup(&root.join("etc/passwd"), user)?
+            .ok_or_else(|| format!("Unknown user '{}' in the image (no entry in /etc/passwd)", user))?,
+    };
+    let gid = match group {
+        None => uid,
+        Some(group) => match group.parse() {
+            Ok(gid) => gid,
+            Err(_) => lookup(&root.join("etc/group"), group)?
+                .ok_or_else(|| format!("Unknown group '{}' in the image (no entry in /etc/group)", group))?,
+        },
+    };
+    Ok(Owner { uid, gid })
+}
+
+/// The ID (third field) of `name` in a passwd(5) or group(5) file; a
+/// missing file has no entries
+fn lookup(file: &Path, name: &str) -> Result<Option<u32>, String> {
+    let content = match fs::read_to_string(file) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("Failed to read {}: {}", file.display(), e)),
+    };
+
+    Ok(content
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .find(|fields| fields.first() == Some(&name))
+        .and_then(|fields| fields.get(2)?.parse().ok()))
+}
+
+/// Give what a copy from `src` wrote at `dst` its owner and mode
+///
+/// For a directory copy that is `dst` and everything below it that has a
+/// counterpart in `src`; files already in the image are left alone. `dst`
+/// must resolve inside `root`.
+pub fn apply(root: &Path, src: &Path, dst: &Path, owner: Option<Owner>, mode: Option<u32>) -> io::Result<()> {
+    if owner.is_none() && mode.is_none() {
+        return Ok(());
+    }
+    let contained = dst.parent().map(|parent| crate::mount_policy::resolve_within(parent, &[root.to_path_buf()]));
+    if !matches!(contained, Some(Ok(Some(_)))) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("'{}' resolves outside the build root", dst.display()),
+        ));
+    }
+
+    let mut pending: Vec<(PathBuf, PathBuf)> = vec![(src.to_path_buf(), dst.to_path_buf())];
+    while let Some((src, dst)) = pending.pop() {
+        let meta = fs::symlink_metadata(&dst)?;
+        if meta.file_type().is_symlink() {
+            if let Some(owner) = owner {
+                lchown(&dst, Some(owner.uid), Some(owner.gid))?;
+            }
+            continue;
+        }
+
+        if let Some(owner) = owner {
+            lchown(&dst, Some(owner.uid), Some(owner.gid))?;
+        }
+        if let Some(mode) = mode {
+            fs::set_permissions(&dst, fs::Permissions::from_mode(mode))?;
+        }
+
+        if meta.is_dir() && src.is_dir() {
+            for entry in fs::read_dir(&src)? {
+                let name = entry?.file_name();
+                pending.push((src.join(&name), dst.join(&name)));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::{MetadataExt, symlink};
+
+    fn image_root() -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir_all(root.path().join("etc")).unwrap();
+        fs::write(
+            root.path().join("etc/passwd"),
+            "# comment\nroot:*:0:0:Charlie &:/root:/bin/sh\nwww:*:80:80:World Wide Web Owner:/nonexistent:/usr/sbin/nologin\n",
+        )
+        .unwrap();
+        fs::write(root.path().join("etc/group"), "wheel:*:0:root\nwww:*:80:\nstaff:*:20:\n").unwrap();
+        root
+    }
+
+    #[test]
+    fn test_parse_flags() {
+        assert!(check_chown("www").is_ok());
+        assert!(check_chown("www:staff").is_ok());
+        assert!(check_chown("1001:1001").is_ok());
+        assert!(check_chown("").is_err());
+        assert!(check_chown(":staff").is_err());
+        assert!(check_chown("www:").is_err());
+
+        assert_eq!(parse_chmod("755"), Ok(0o755));
+        assert_eq!(parse_chmod("0640"), Ok(0o640));
+        assert_eq!(parse_chmod("4755"), Ok(0o4755));
+        assert!(parse_chmod("").is_err());
+        assert!(parse_chmod("789").is_err());
+        assert!(parse_chmod("17777").is_err());
+        assert!(parse_chmod("u+x").is_err());
+    }
+
+    #[test]
+    fn test_resolve_owner() {
+        let root = image_root();
+        let resolve = |spec: &str| resolve_owner(root.path(), spec);
+
+        assert_eq!(resolve("www"), Ok(Owner { uid: 80, gid: 80 }));
+        assert_eq!(resolve("www:staff"), Ok(Owner { uid: 80, gid: 20 }));
+        assert_eq!(resolve("root:www"), Ok(Owner { uid: 0, gid: 80 }));
+        // Numeric IDs need no entry; the group defaults to the same ID
+        assert_eq!(resolve("1001"), Ok(Owner { uid: 1001, gid: 1001 }));
+        assert_eq!(resolve("www:1002"), Ok(Owner { uid: 80, gid: 1002 }));
+
+        let err = resolve("nginx").unwrap_err();
+        assert!(err.contains("Unknown user 'nginx'"), "{}", err);
+        let err = resolve("www:nogroup").unwrap_err();
+        assert!(err.contains("Unknown group 'nogroup'"), "{}", err);
+
+        // The host's files are never consulted
+        let empty = tempfile::tempdir().unwrap();
+        assert!(resolve_owner(empty.path(), "root").is_err());
+        assert_eq!(resolve_owner(empty.path(), "0:0"), Ok(Owner { uid: 0, gid: 0 }));
+    }
+
+    #[test]
+    fn test_apply_recursively() {
+        let context = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        fs::write(outside.path().join("secret"), "").unwrap();
+
+        // What a directory copy produced, next to a file that was there before
+        fs::create_dir_all(context.path().join("htdocs/css")).unwrap();
+        fs::write(context.path().join("htdocs/index.html"), "").unwrap();
+        fs::write(context.path().join("htdocs/css/site.css"), "").unwrap();
+        fs::write(context.path().join("htdocs/link"), "").unwrap();
+        let www = root.path().join("usr/local/www");
+        fs::create_dir_all(www.join("css")).unwrap();
+        fs::write(www.join("index.html"), "").unwrap();
+        fs::write(www.join("css/site.css"), "").unwrap();
+        fs::write(www.join("existing"), "").unwrap();
+        symlink(outside.path().join("secret"), www.join("link")).unwrap();
+
+        let owner = if unsafe { libc::geteuid() } == 0 {
+            Owner { uid: 1234, gid: 5678 }
+        } else {
+            let meta = fs::metadata(root.path()).unwrap();
+            Owner { uid: meta.uid(), gid: meta.gid() }
+        };
+        apply(root.path(), &context.path().join("htdocs"), &www, Some(owner), Some(0o750)).unwrap();
+
+        for path in [www.clone(), www.join("css"), www.join("index.html"), www.join("css/site.css")] {
+            let meta = fs::metadata(&path).unwrap();
+            assert_eq!((meta.uid(), meta.gid()), (owner.uid, owner.gid), "{}", path.display());
+            assert_eq!(meta.mode() & 0o7777, 0o750, "{}", path.display());
+        }
+        // Not part of the copy
+        assert_ne!(fs::metadata(www.join("existing")).unwrap().mode() & 0o7777, 0o750);
+        // The symlink itself is changed, not what it points to
+        let link = fs::symlink_metadata(www.join("link")).unwrap();
+        assert_eq!((link.uid(), link.gid()), (owner.uid, owner.gid));
+        assert_ne!(fs::metadata(outside.path().join("secret")).unwrap().mode() & 0o7777, 0o750);
+    }
+
+    #[test]
+    fn test_apply_refuses_destination_outside_root() {
+        let context = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        fs::write(context.path().join("file"), "").unwrap();
+        fs::write(outside.path().join("file"), "").unwrap();
+        symlink(outside.path(), root.path().join("escape")).unwrap();
+
+        let err = apply(root.path(), &context.path().join("file"), &root.path().join("escape/file"), None, Some(0o600)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert_ne!(fs::metadata(outside.path().join("file")).unwrap().mode() & 0o7777, 0o600);
+    }
+}
@@ -0,0 +1,189 @@
+//! Filesystem locations of kawakaze state
+//!
+//! Everything the daemon keeps on disk derives from `data_root`
+//! (`/var/lib/kawakaze` by default), so relocating or sandboxing a daemon
+//! means setting one value:
+//!
+//! ```text
+//! <data_root>/kawakaze.db            storage.database_path
+//! <data_root>/kawakaze.sock          storage.socket_path
+//! <data_root>/builds/<image>-<id>    storage.builds_path
+//! <data_root>/jails/<jail>           jail_base_path
+//! <data_root>/base/<version>-<arch>
+//! <data_root>/containers/<short id>
+//! <data_root>/ip_allocations.txt
+//! <data_root>/ip6_allocations.txt
+//! ```
+//!
+//! The paths named on the right can be set individually in the config and
+//! then no longer follow `data_root`. Nothing else should build these paths
+//! itself; go through [`Paths`].
+
+use std::path::PathBuf;
+
+use crate::config::KawakazeConfig;
+
+/// Data root used when the config doesn't set one
+pub const DEFAULT_DATA_ROOT: &str = "/var/lib/kawakaze";
+
+/// Resolved locations of the daemon's state
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Paths {
+    pub data_root: PathBuf,
+    /// SQLite database
+    pub database: PathBuf,
+    /// API socket
+    pub socket: PathBuf,
+    /// Directory build datasets are mounted under while building
+    pub builds: PathBuf,
+    /// Directory jail roots live under; client-supplied jail paths must
+    /// stay inside it
+    pub jails: PathBuf,
+}
+
+impl Paths {
+    /// Every path derived from `data_root`
+    pub fn new(data_root: impl Into<PathBuf>) -> Self {
+        let data_root = data_root.into();
+        Self {
+            database: data_root.join("kawakaze.db"),
+            socket: data_root.join("kawakaze.sock"),
+            builds: data_root.join("builds"),
+            jails: data_root.join("jails"),
+            data_root,
+        }
+    }
+
+    /// Paths of a configuration: derived from its `data_root` except where
+    /// it sets a path itself
+    pub fn from_config(config: &KawakazeConfig) -> Self {
+        let derived = Self::new(&config.data_root);
+        let or_derived = |path: &Option<String>, default: PathBuf| path.as_ref().map_or(default, PathBuf::from);
+        Self {
+            database: or_derived(&config.storage.database_path, derived.database),
+            socket: or_derived(&config.storage.socket_path, derived.socket),
+            builds: or_derived(&config.storage.builds_path, derived.builds),
+            jails: or_derived(&config.jail_base_path, derived.jails),
+            data_root: derived.data_root,
+        }
+    }
+
+    /// Mount point of the dataset building image `name`; `build_id` keeps
+    /// builds of one name apart, including names that sanitize alike
+    pub fn build_root(&self, name: &str, build_id: &str) -> PathBuf {
+        self.builds.join(format!("{}-{}", path_component(name), build_id))
+    }
+
+    /// Root of a jail created without a path
+    pub fn jail_root(&self, name: &str) -> PathBuf {
+        self.jails.join(name)
+    }
+
+    /// Mount point of the base system dataset being bootstrapped
+    pub fn base_root(&self, version: &str, architecture: &str) -> PathBuf {
+        self.data_root.join("base").join(format!("{}-{}", version, architecture))
+    }
+
+    /// Mount point of a container's dataset
+    pub fn container_root(&self, container_id: &str) -> PathBuf {
+        self.data_root.join("containers").join(&container_id[..container_id.len().min(8)])
+    }
+
+    /// IP allocation state of the network manager
+    pub fn ip_allocations(&self) -> PathBuf {
+        self.data_root.join("ip_allocations.txt")
+    }
+
+    /// IPv6 allocation state of the network manager
+    pub fn ip6_allocations(&self) -> PathBuf {
+        self.data_root.join("ip6_allocations.txt")
+    }
+}
+
+/// `name` as a single safe path component: characters other than ASCII
+/// letters, digits, `.`, `_` and `-` become `-`, and leading dots are
+/// dropped so the result is never hidden, `.` or `..`
+pub fn path_component(name: &str) -> String {
+    let safe: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-') { c } else { '-' })
+        .collect();
+    match safe.trim_start_matches('.') {
+        "" => "image".to_string(),
+        trimmed => trimmed.to_string(),
+    }
+}
+
+impl Default for Paths {
+    fn default() -> Self {
+        Self::new(DEFAULT_DATA_ROOT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_data_root_moves_every_derived_path() {
+        let mut config = KawakazeConfig::default();
+        config.data_root = "/srv/kz".to_string();
+        let paths = Paths::from_config(&config);
+
+        assert_eq!(paths.database, Path::new("/srv/kz/kawakaze.db"));
+        assert_eq!(paths.socket, Path::new("/srv/kz/kawakaze.sock"));
+        assert_eq!(paths.builds, Path::new("/srv/kz/builds"));
+        assert_eq!(paths.jails, Path::new("/srv/kz/jails"));
+        assert_eq!(paths.build_root("web/app", "1234"), Path::new("/srv/kz/builds/web-app-1234"));
+        assert_ne!(paths.build_root("web", "1234"), paths.build_root("web", "5678"));
+        assert_eq!(paths.jail_root("db"), Path::new("/srv/kz/jails/db"));
+        assert_eq!(paths.base_root("14.1-RELEASE", "amd64"), Path::new("/srv/kz/base/14.1-RELEASE-amd64"));
+        assert_eq!(paths.container_root("a1b2c3d4-e5f6"), Path::new("/srv/kz/containers/a1b2c3d4"));
+        assert_eq!(paths.ip_allocations(), Path::new("/srv/kz/ip_allocations.txt"));
+        assert_eq!(paths.ip6_allocations(), Path::new("/srv/kz/ip6_allocations.txt"));
+
+        for path in [&paths.database, &paths.socket, &paths.builds, &paths.jails] {
+            assert!(path.starts_with("/srv/kz"), "{} is outside the data root", path.display());
+        }
+        assert_eq!(Paths::from_config(&KawakazeConfig::default()), Paths::default());
+    }
+
+    #[test]
+    fn test_build_root_is_one_safe_component() {
+        let paths = Paths::new("/srv/kz");
+        for (name, component) in [
+            ("web", "web"),
+            ("web/app:1.0", "web-app-1.0"),
+            ("../../etc", "-..-etc"),
+            ("..", "image"),
+            (".hidden", "hidden"),
+            ("café bar\n", "caf--bar-"),
+            ("", "image"),
+        ] {
+            assert_eq!(path_component(name), component, "{:?}", name);
+            let root = paths.build_root(name, "1234");
+            assert_eq!(root.parent(), Some(paths.builds.as_path()), "{:?} escapes builds/", name);
+            assert_eq!(root.file_name().unwrap(), format!("{}-1234", component).as_str());
+        }
+
+        // Names that sanitize alike still get their own mount points
+        assert_eq!(path_component("web/app"), path_component("web:app"));
+        assert_ne!(paths.build_root("web/app", "1234"), paths.build_root("web:app", "5678"));
+    }
+
+    #[test]
+    fn test_overridden_paths_ignore_data_root() {
+        let mut config = KawakazeConfig::default();
+        config.data_root = "/srv/kz".to_string();
+        config.storage.socket_path = Some("/var/run/kawakaze.sock".to_string());
+        config.jail_base_path = Some("/usr/local/jails".to_string());
+        let paths = Paths::from_config(&config);
+
+        assert_eq!(paths.socket, Path::new("/var/run/kawakaze.sock"));
+        assert_eq!(paths.jail_root("db"), Path::new("/usr/local/jails/db"));
+        // The rest still follow the data root
+        assert_eq!(paths.database, Path::new("/srv/kz/kawakaze.db"));
+        assert_eq!(paths.builds, Path::new("/srv/kz/builds"));
+    }
+}
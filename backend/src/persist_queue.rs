@@ -0,0 +1,420 @@
+//! Retrying store writes that failed
+//!
+//! Some state changes go ahead even when the database write behind them
+//! fails (a jail that started is running whatever sqlite says). Instead of
+//! logging and moving on, which leaves memory and the database apart until
+//! a restart loses the change, the manager queues the write as a
+//! [`PendingWrite`]. The daemon retries due writes every
+//! [`RETRY_INTERVAL`] with exponential backoff and flushes the queue on
+//! shutdown.
+//!
+//! The queue holds at most one write per [`Resource`]: a newer write
+//! replaces the queued one (it carries the full state, so the latest wins),
+//! and a newer write that succeeds directly drops it. After
+//! [`RetryPolicy::escalate_after`] failed attempts a write is logged as an
+//! error once; it keeps being retried.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+use crate::store::{ContainerState, JailRow, JailStore, StoreError};
+
+/// How often the daemon looks for due retries
+pub const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The record a write is about; writes to the same one supersede each other
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Resource {
+    Jail(String),
+    Container(String),
+    /// A container's published host ports (their own table)
+    PublishedPorts(String),
+    Image(String),
+}
+
+impl std::fmt::Display for Resource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Resource::Jail(name) => write!(f, "jail '{}'", name),
+            Resource::Container(id) => write!(f, "container {}", id),
+            Resource::PublishedPorts(id) => write!(f, "published ports of container {}", id),
+            Resource::Image(id) => write!(f, "image {}", id),
+        }
+    }
+}
+
+/// A store write and its payload
+#[derive(Debug, Clone)]
+pub enum Write {
+    /// Update a jail row, inserting it if it isn't there
+    SaveJail(JailRow),
+    DeleteJail,
+    ContainerState(ContainerState),
+    /// The container stopped at `at` (Unix seconds), for `cause`
+    ContainerStopped { cause: crate::container::StopCause, at: i64 },
+    PublishedPorts(Vec<(u16, String)>),
+    ImageUpgraded { snapshot: String, os_version: String },
+}
+
+impl Write {
+    pub fn operation(&self) -> &'static str {
+        match self {
+            Write::SaveJail(_) => "save",
+            Write::DeleteJail => "delete",
+            Write::ContainerState(_) => "set state",
+            Write::ContainerStopped { .. } => "record stop",
+            Write::PublishedPorts(_) => "set published ports",
+            Write::ImageUpgraded { .. } => "record upgrade",
+        }
+    }
+}
+
+/// Where writes are applied; the tests swap the store for a stub
+pub trait WriteTarget {
+    fn apply(&self, resource: &Resource, write: &Write) -> Result<(), StoreError>;
+}
+
+impl WriteTarget for JailStore {
+    fn apply(&self, resource: &Resource, write: &Write) -> Result<(), StoreError> {
+        match (resource, write) {
+            (Resource::Jail(name), Write::SaveJail(row)) => match self.get_jail(name)? {
+                Some(_) => self.update_jail(row),
+                None => self.insert_jail(row),
+            },
+            (Resource::Jail(name), Write::DeleteJail) => self.delete_jail(name),
+            (Resource::Container(id), Write::ContainerState(state)) => self.update_container(id, *state),
+            (Resource::Container(id), Write::ContainerStopped { cause, at }) => {
+                let cause = serde_json::to_string(cause).map_err(|e| StoreError::SerializationError(e.to_string()))?;
+                self.record_container_stop(id, *at, &cause)
+            }
+            (Resource::PublishedPorts(id), Write::PublishedPorts(ports)) => {
+                let rows: Vec<(u16, &str)> = ports.iter().map(|(port, protocol)| (*port, protocol.as_str())).collect();
+                self.set_published_ports(id, &rows)
+            }
+            (Resource::Image(id), Write::ImageUpgraded { snapshot, os_version }) => {
+                self.set_image_upgraded(id, snapshot, os_version)
+            }
+            (resource, write) => Err(StoreError::InvalidState(format!("cannot {} {}", write.operation(), resource))),
+        }
+    }
+}
+
+/// When failed writes are tried again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Delay after the first failure; doubled after each further one
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Failed attempts after which a write is logged as an error
+    pub escalate_after: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self { initial_backoff: Duration::from_secs(1), max_backoff: Duration::from_secs(60), escalate_after: 5 }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before the next attempt of a write that failed `attempts` times
+    pub fn backoff(&self, attempts: u32) -> Duration {
+        let doublings = attempts.saturating_sub(1).min(16);
+        self.initial_backoff.saturating_mul(1 << doublings).min(self.max_backoff)
+    }
+}
+
+/// A write waiting to be retried
+#[derive(Debug, Clone)]
+pub struct PendingWrite {
+    pub resource: Resource,
+    pub payload: Write,
+    /// Failed attempts so far
+    pub attempts: u32,
+    pub last_error: String,
+    /// When the resource first fell behind; kept when a write is superseded
+    pub queued_at: Instant,
+    pub next_attempt: Instant,
+}
+
+/// Queue length and age, for `SystemInfo`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueueInfo {
+    /// Writes waiting to be retried
+    pub pending: usize,
+    /// Seconds the oldest one has been waiting
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oldest_secs: Option<u64>,
+    /// Writes that failed often enough to be logged as errors
+    #[serde(default)]
+    pub escalated: usize,
+}
+
+/// Failed writes, one per resource
+#[derive(Debug, Default)]
+pub struct RetryQueue {
+    pending: HashMap<Resource, PendingWrite>,
+    policy: RetryPolicy,
+}
+
+impl RetryQueue {
+    pub fn new(policy: RetryPolicy) -> Self {
+        Self { pending: HashMap::new(), policy }
+    }
+
+    /// Queue a write that just failed, replacing any queued for the resource
+    pub fn push(&mut self, resource: Resource, payload: Write, error: &StoreError, now: Instant) {
+        let queued_at = self.pending.get(&resource).map_or(now, |queued| queued.queued_at);
+        let write = PendingWrite {
+            resource: resource.clone(),
+            payload,
+            attempts: 1,
+            last_error: error.to_string(),
+            queued_at,
+            next_attempt: now + self.policy.backoff(1),
+        };
+        self.pending.insert(resource, write);
+    }
+
+    /// Drop the queued write for a resource whose newer write went through
+    pub fn resolve(&mut self, resource: &Resource) -> Option<PendingWrite> {
+        self.pending.remove(resource)
+    }
+
+    pub fn get(&self, resource: &Resource) -> Option<&PendingWrite> {
+        self.pending.get(resource)
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    pub fn info(&self, now: Instant) -> QueueInfo {
+        QueueInfo {
+            pending: self.pending.len(),
+            oldest_secs: self.pending.values().map(|write| now.saturating_duration_since(write.queued_at).as_secs()).max(),
+            escalated: self.pending.values().filter(|write| write.attempts >= self.policy.escalate_after).count(),
+        }
+    }
+
+    /// Try the writes whose backoff has passed; returns how many went through
+    pub fn retry_due(&mut self, target: &dyn WriteTarget, now: Instant) -> usize {
+        let due: Vec<Resource> =
+            self.pending.values().filter(|write| write.next_attempt <= now).map(|write| write.resource.clone()).collect();
+        due.iter().filter(|resource| self.attempt(target, resource, now)).count()
+    }
+
+    /// Try every queued write once, due or not; returns how many are left
+    pub fn retry_all(&mut self, target: &dyn WriteTarget, now: Instant) -> usize {
+        let all: Vec<Resource> = self.pending.keys().cloned().collect();
+        for resource in &all {
+            self.attempt(target, resource, now);
+        }
+        self.pending.len()
+    }
+
+    /// Retry until the queue is empty or `grace` has passed; returns how
+    /// many writes are lost
+    pub async fn flush(&mut self, target: &dyn WriteTarget, grace: Duration) -> usize {
+        let deadline = Instant::now() + grace;
+        loop {
+            let left = self.retry_all(target, Instant::now());
+            if left == 0 || Instant::now() >= deadline {
+                return left;
+            }
+            tokio::time::sleep(Duration::from_millis(50).min(deadline - Instant::now())).await;
+        }
+    }
+
+    fn attempt(&mut self, target: &dyn WriteTarget, resource: &Resource, now: Instant) -> bool {
+        let Some(write) = self.pending.get_mut(resource) else {
+            return false;
+        };
+        match target.apply(&write.resource, &write.payload) {
+            Ok(()) => {
+                info!("Wrote {} of {} to the database after {} failed attempt(s)", write.payload.operation(), resource, write.attempts);
+                self.pending.remove(resource);
+                true
+            }
+            Err(e) => {
+                write.attempts += 1;
+                write.last_error = e.to_string();
+                write.next_attempt = now + self.policy.backoff(write.attempts);
+                if write.attempts == self.policy.escalate_after {
+                    error!(
+                        "Failed {} times to {} {} in the database, memory and database disagree: {}",
+                        write.attempts, write.payload.operation(), resource, e
+                    );
+                } else {
+                    warn!("Retry {} of {} {} failed: {}", write.attempts, write.payload.operation(), resource, e);
+                }
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    /// Fails the first `failures` writes, then records what it applies
+    struct Flaky {
+        failures: RefCell<u32>,
+        applied: RefCell<Vec<(Resource, String)>>,
+    }
+
+    impl Flaky {
+        fn new(failures: u32) -> Self {
+            Self { failures: RefCell::new(failures), applied: RefCell::new(Vec::new()) }
+        }
+    }
+
+    impl WriteTarget for Flaky {
+        fn apply(&self, resource: &Resource, write: &Write) -> Result<(), StoreError> {
+            let mut failures = self.failures.borrow_mut();
+            if *failures > 0 {
+                *failures -= 1;
+                return Err(StoreError::InvalidState("database is locked".to_string()));
+            }
+            let payload = match write {
+                Write::ContainerState(state) => state.as_str().to_string(),
+                other => other.operation().to_string(),
+            };
+            self.applied.borrow_mut().push((resource.clone(), payload));
+            Ok(())
+        }
+    }
+
+    fn locked() -> StoreError {
+        StoreError::InvalidState("database is locked".to_string())
+    }
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy { initial_backoff: Duration::from_secs(1), max_backoff: Duration::from_secs(8), escalate_after: 3 }
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_the_cap() {
+        let policy = policy();
+        let delays: Vec<u64> = (1..=6).map(|attempts| policy.backoff(attempts).as_secs()).collect();
+        assert_eq!(delays, [1, 2, 4, 8, 8, 8]);
+        assert_eq!(policy.backoff(u32::MAX), Duration::from_secs(8));
+    }
+
+    #[test]
+    fn test_latest_write_supersedes() {
+        let mut queue = RetryQueue::new(policy());
+        let start = Instant::now();
+        let web = Resource::Container("c1".to_string());
+        queue.push(web.clone(), Write::ContainerState(ContainerState::Running), &locked(), start);
+        queue.push(Resource::PublishedPorts("c1".to_string()), Write::PublishedPorts(vec![]), &locked(), start);
+        queue.push(web.clone(), Write::ContainerState(ContainerState::Stopped), &locked(), start + Duration::from_secs(5));
+
+        // One write per resource, the newest payload, the oldest age
+        assert_eq!(queue.len(), 2);
+        let pending = queue.get(&web).unwrap();
+        assert!(matches!(pending.payload, Write::ContainerState(ContainerState::Stopped)));
+        assert_eq!(pending.queued_at, start);
+        assert_eq!(queue.info(start + Duration::from_secs(30)).oldest_secs, Some(30));
+
+        let store = Flaky::new(0);
+        assert_eq!(queue.retry_all(&store, start), 0);
+        assert!(store.applied.borrow().contains(&(web.clone(), "stopped".to_string())));
+        assert!(!store.applied.borrow().contains(&(web, "running".to_string())));
+    }
+
+    #[test]
+    fn test_resolved_writes_are_not_retried() {
+        let mut queue = RetryQueue::new(policy());
+        let jail = Resource::Jail("web".to_string());
+        queue.push(jail.clone(), Write::DeleteJail, &locked(), Instant::now());
+        assert!(queue.resolve(&jail).is_some());
+        assert!(queue.is_empty());
+        assert_eq!(queue.info(Instant::now()), QueueInfo::default());
+    }
+
+    #[test]
+    fn test_retry_follows_backoff_and_escalates() {
+        let mut queue = RetryQueue::new(policy());
+        let start = Instant::now();
+        let jail = Resource::Jail("web".to_string());
+        queue.push(jail.clone(), Write::DeleteJail, &locked(), start);
+        let store = Flaky::new(3);
+
+        // Not due before the first backoff
+        assert_eq!(queue.retry_due(&store, start), 0);
+        assert_eq!(queue.get(&jail).unwrap().attempts, 1);
+
+        // Fails at 1s (attempt 2, next in 2s), then at 3s (attempt 3, next in 4s)
+        assert_eq!(queue.retry_due(&store, start + Duration::from_secs(1)), 0);
+        assert_eq!(queue.get(&jail).unwrap().next_attempt, start + Duration::from_secs(3));
+        assert_eq!(queue.retry_due(&store, start + Duration::from_secs(2)), 0);
+        assert_eq!(queue.get(&jail).unwrap().attempts, 2);
+        assert_eq!(queue.retry_due(&store, start + Duration::from_secs(3)), 0);
+        let pending = queue.get(&jail).unwrap();
+        assert_eq!((pending.attempts, pending.next_attempt), (3, start + Duration::from_secs(7)));
+        assert_eq!(pending.last_error, locked().to_string());
+        assert_eq!(queue.info(start + Duration::from_secs(3)).escalated, 1);
+
+        // Still retried after escalating; the stub fails once more, then works
+        assert_eq!(queue.retry_due(&store, start + Duration::from_secs(7)), 0);
+        assert_eq!(queue.retry_due(&store, start + Duration::from_secs(15)), 1);
+        assert!(queue.is_empty());
+        assert_eq!(*store.applied.borrow(), [(jail, "delete".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn test_flush_retries_until_empty_or_grace() {
+        let mut queue = RetryQueue::new(policy());
+        let now = Instant::now();
+        queue.push(Resource::Jail("web".to_string()), Write::DeleteJail, &locked(), now);
+        queue.push(Resource::Image("i1".to_string()), Write::ImageUpgraded { snapshot: "s".into(), os_version: "14.2".into() }, &locked(), now);
+
+        // Backoff doesn't apply on shutdown: failing twice, then written
+        let store = Flaky::new(2);
+        assert_eq!(queue.flush(&store, Duration::from_secs(5)).await, 0);
+        assert_eq!(store.applied.borrow().len(), 2);
+
+        // A store that never recovers gives up at the deadline
+        queue.push(Resource::Jail("db".to_string()), Write::DeleteJail, &locked(), now);
+        let started = Instant::now();
+        assert_eq!(queue.flush(&Flaky::new(u32::MAX), Duration::from_millis(120)).await, 1);
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_store_applies_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = JailStore::new(dir.path().join("test.db")).unwrap();
+        let jail = Resource::Jail("web".to_string());
+        let row = JailRow {
+            name: "web".to_string(),
+            path: None,
+            ip: None,
+            state: "stopped".to_string(),
+            jid: -1,
+            os_version: None,
+            mount_permissions: "none".to_string(),
+            description: None,
+            provenance: Default::default(),
+        };
+
+        // Saving inserts, then updates
+        store.apply(&jail, &Write::SaveJail(row.clone())).unwrap();
+        store.apply(&jail, &Write::SaveJail(JailRow { state: "running".to_string(), jid: 4, ..row })).unwrap();
+        assert_eq!(store.get_jail("web").unwrap().unwrap().state, "running");
+        store.apply(&jail, &Write::DeleteJail).unwrap();
+        assert!(store.get_jail("web").unwrap().is_none());
+
+        let err = store.apply(&jail, &Write::ContainerState(ContainerState::Running)).unwrap_err();
+        assert!(err.to_string().contains("cannot set state jail 'web'"), "{}", err);
+    }
+}
@@ -0,0 +1,513 @@
+//! Placement constraints on containers, and the labels of this node
+//!
+//! A single daemon runs everything locally, but containers already carry
+//! the placement a scheduler in front of several hosts will need. The
+//! constraints are parsed when a container is created and checked against
+//! this node's labels, so a container that could never run here is
+//! refused instead of being created; preferences are only validated.
+//!
+//! ```text
+//! constraint := field op value
+//! preference := "spread=" field
+//! field      := "node.hostname" | "node.labels." key
+//! op         := "==" | "!="
+//! key        := [A-Za-z0-9] [A-Za-z0-9._-]*
+//! value      := one or more characters other than whitespace, '=' and '!'
+//! ```
+//!
+//! Spaces are allowed around `op`. `node.hostname` is the `hostname` label.
+//! `!=` holds for a node without the label, `==` doesn't.
+//!
+//! The labels are `node_labels` from the config plus [`DERIVED_LABELS`],
+//! which the config may not set.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::system::HostInfo;
+
+/// Labels every node has, read from the host rather than configured
+pub const DERIVED_LABELS: &[&str] = &["hostname", "os_version", "arch", "pool"];
+
+/// Where a container may run, as given at create time
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Placement {
+    /// Constraints a node must satisfy, e.g. `node.labels.zone==eu`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub constraints: Vec<String>,
+    /// Soft preferences, e.g. `spread=node.labels.zone`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub preferences: Vec<String>,
+}
+
+/// What a constraint or preference compares
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Field {
+    Hostname,
+    Label(String),
+}
+
+impl Field {
+    /// The label the field reads
+    pub fn label(&self) -> &str {
+        match self {
+            Field::Hostname => "hostname",
+            Field::Label(key) => key,
+        }
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Field::Hostname => write!(f, "node.hostname"),
+            Field::Label(key) => write!(f, "node.labels.{}", key),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Eq,
+    Ne,
+}
+
+/// A parsed constraint
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Constraint {
+    pub field: Field,
+    pub op: Op,
+    pub value: String,
+}
+
+impl Constraint {
+    /// Whether a node with `labels` satisfies the constraint
+    pub fn matches(&self, labels: &BTreeMap<String, String>) -> bool {
+        let actual = labels.get(self.field.label());
+        match self.op {
+            Op::Eq => actual == Some(&self.value),
+            Op::Ne => actual != Some(&self.value),
+        }
+    }
+}
+
+impl fmt::Display for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self.op {
+            Op::Eq => "==",
+            Op::Ne => "!=",
+        };
+        write!(f, "{}{}{}", self.field, op, self.value)
+    }
+}
+
+/// A syntax error, at `position` (in characters, from 0)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "at position {}: {}", self.position, self.message)
+    }
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    /// Byte offset into `input`
+    offset: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str) -> Self {
+        Self { input, offset: 0 }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.input[self.offset..]
+    }
+
+    fn error(&self, message: impl Into<String>) -> ParseError {
+        ParseError { position: self.input[..self.offset].chars().count(), message: message.into() }
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        let found = self.rest().starts_with(token);
+        if found {
+            self.offset += token.len();
+        }
+        found
+    }
+
+    fn skip_spaces(&mut self) {
+        let rest = self.rest();
+        self.offset += rest.len() - rest.trim_start().len();
+    }
+
+    /// The longest run of characters from here for which `accept` holds
+    fn take_while(&mut self, accept: impl Fn(char) -> bool) -> &'a str {
+        let rest = self.rest();
+        let len = rest.find(|c| !accept(c)).unwrap_or(rest.len());
+        self.offset += len;
+        &rest[..len]
+    }
+
+    fn field(&mut self) -> Result<Field, ParseError> {
+        if !self.eat("node.") {
+            return Err(self.error("expected 'node.hostname' or 'node.labels.<key>'"));
+        }
+        let start = self.offset;
+        match self.take_while(|c| c.is_ascii_alphanumeric() || c == '_') {
+            "hostname" => Ok(Field::Hostname),
+            "labels" if self.eat(".") => {
+                let start = self.offset;
+                let key = self.take_while(is_key_char);
+                check_key(key).map_err(|message| ParseError { position: self.input[..start].chars().count(), message })?;
+                Ok(Field::Label(key.to_string()))
+            }
+            _ => {
+                self.offset = start;
+                Err(self.error("expected 'hostname' or 'labels.<key>'"))
+            }
+        }
+    }
+
+    fn op(&mut self) -> Result<Op, ParseError> {
+        if self.eat("==") {
+            Ok(Op::Eq)
+        } else if self.eat("!=") {
+            Ok(Op::Ne)
+        } else {
+            Err(self.error("expected '==' or '!='"))
+        }
+    }
+
+    fn value(&mut self) -> Result<String, ParseError> {
+        let value = self.take_while(is_value_char);
+        if value.is_empty() {
+            return Err(self.error("expected a value"));
+        }
+        Ok(value.to_string())
+    }
+
+    fn end(&mut self) -> Result<(), ParseError> {
+        self.skip_spaces();
+        match self.rest().chars().next() {
+            None => Ok(()),
+            Some(c) => Err(self.error(format!("unexpected '{}'", c))),
+        }
+    }
+}
+
+fn is_key_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-')
+}
+
+fn is_value_char(c: char) -> bool {
+    !c.is_whitespace() && c != '=' && c != '!'
+}
+
+fn check_key(key: &str) -> Result<(), String> {
+    match key.chars().next() {
+        None => Err("expected a label key".to_string()),
+        Some(c) if !c.is_ascii_alphanumeric() => Err(format!("a label key must start with a letter or digit, not '{}'", c)),
+        Some(_) => Ok(()),
+    }
+}
+
+/// Parse a constraint such as `node.labels.zone==eu`
+pub fn parse_constraint(input: &str) -> Result<Constraint, ParseError> {
+    let mut parser = Parser::new(input);
+    parser.skip_spaces();
+    let field = parser.field()?;
+    parser.skip_spaces();
+    let op = parser.op()?;
+    parser.skip_spaces();
+    let value = parser.value()?;
+    parser.end()?;
+    Ok(Constraint { field, op, value })
+}
+
+/// Parse a preference such as `spread=node.labels.zone`
+pub fn parse_preference(input: &str) -> Result<Field, ParseError> {
+    let mut parser = Parser::new(input);
+    parser.skip_spaces();
+    if !parser.eat("spread=") {
+        return Err(parser.error("expected 'spread='"));
+    }
+    let field = parser.field()?;
+    parser.end()?;
+    Ok(field)
+}
+
+/// A constraint or preference of a [`Placement`] that doesn't parse
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlacementError {
+    /// "constraint" or "preference"
+    pub kind: &'static str,
+    pub input: String,
+    pub error: ParseError,
+}
+
+impl fmt::Display for PlacementError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid placement {} '{}' {}", self.kind, self.input, self.error)
+    }
+}
+
+impl std::error::Error for PlacementError {}
+
+impl Placement {
+    /// Parse the constraints, checking the preferences too
+    pub fn parse(&self) -> Result<Vec<Constraint>, PlacementError> {
+        for preference in &self.preferences {
+            parse_preference(preference).map_err(|error| PlacementError { kind: "preference", input: preference.clone(), error })?;
+        }
+        self.constraints
+            .iter()
+            .map(|constraint| {
+                parse_constraint(constraint).map_err(|error| PlacementError { kind: "constraint", input: constraint.clone(), error })
+            })
+            .collect()
+    }
+}
+
+/// Why a node with `labels` can't run a container with `constraints`, or
+/// `None` if it satisfies them all
+pub fn unsatisfied(constraints: &[Constraint], labels: &BTreeMap<String, String>) -> Option<String> {
+    let failed: Vec<String> = constraints
+        .iter()
+        .filter(|constraint| !constraint.matches(labels))
+        .map(|constraint| match labels.get(constraint.field.label()) {
+            Some(actual) => format!("{} ({} is '{}')", constraint, constraint.field.label(), actual),
+            None => format!("{} (no {} label)", constraint, constraint.field.label()),
+        })
+        .collect();
+    if failed.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "Node '{}' doesn't satisfy the placement: {}",
+        labels.get("hostname").map(String::as_str).unwrap_or("?"),
+        failed.join("; ")
+    ))
+}
+
+/// Check the `node_labels` of the config
+pub fn check_node_labels(labels: &BTreeMap<String, String>) -> Result<(), String> {
+    for (key, value) in labels {
+        if let Err(e) = check_key(key).and_then(|()| match key.chars().find(|c| !is_key_char(*c)) {
+            Some(c) => Err(format!("'{}' isn't allowed in a label key", c)),
+            None => Ok(()),
+        }) {
+            return Err(format!("Invalid node label '{}': {}", key, e));
+        }
+        if DERIVED_LABELS.contains(&key.as_str()) {
+            return Err(format!("Node label '{}' is derived from the host and can't be configured", key));
+        }
+        if value.is_empty() || !value.chars().all(is_value_char) {
+            return Err(format!("Invalid value for node label '{}': use no spaces, '=' or '!'", key));
+        }
+    }
+    Ok(())
+}
+
+/// This node's labels: the configured ones plus [`DERIVED_LABELS`] from
+/// `host` and the pool of `zfs_pool`; a fact the host didn't give is left out
+pub fn node_labels(configured: &BTreeMap<String, String>, host: &HostInfo, zfs_pool: &str) -> BTreeMap<String, String> {
+    let mut labels = configured.clone();
+    let derived = [
+        ("hostname", host.hostname.as_deref()),
+        ("os_version", host.os_version.as_deref()),
+        ("arch", host.arch.as_deref()),
+        ("pool", zfs_pool.split('/').next().filter(|pool| !pool.is_empty())),
+    ];
+    for (key, value) in derived {
+        if let Some(value) = value {
+            labels.insert(key.to_string(), value.to_string());
+        }
+    }
+    labels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels() -> BTreeMap<String, String> {
+        BTreeMap::from([
+            ("hostname".to_string(), "web1".to_string()),
+            ("zone".to_string(), "eu-west".to_string()),
+            ("disk".to_string(), "ssd".to_string()),
+        ])
+    }
+
+    fn constraint(field: Field, op: Op, value: &str) -> Constraint {
+        Constraint { field, op, value: value.to_string() }
+    }
+
+    #[test]
+    fn test_parse_constraint() {
+        let label = |key: &str| Field::Label(key.to_string());
+        let cases = [
+            ("node.hostname==web1", constraint(Field::Hostname, Op::Eq, "web1")),
+            ("node.hostname!=web1", constraint(Field::Hostname, Op::Ne, "web1")),
+            ("node.labels.zone==eu-west", constraint(label("zone"), Op::Eq, "eu-west")),
+            ("node.labels.kawakaze.io==1", constraint(label("kawakaze.io"), Op::Eq, "1")),
+            ("  node.labels.disk != ssd  ", constraint(label("disk"), Op::Ne, "ssd")),
+            ("node.labels.os_version==14.1-RELEASE-p3", constraint(label("os_version"), Op::Eq, "14.1-RELEASE-p3")),
+            ("node.labels.9a==b/c:d", constraint(label("9a"), Op::Eq, "b/c:d")),
+            ("node.labels.labels==x", constraint(label("labels"), Op::Eq, "x")),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parse_constraint(input), Ok(expected), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_parse_constraint_errors() {
+        let cases = [
+            ("", 0, "expected 'node.hostname' or 'node.labels.<key>'"),
+            ("hostname==web1", 0, "expected 'node.hostname' or 'node.labels.<key>'"),
+            ("node.host==web1", 5, "expected 'hostname' or 'labels.<key>'"),
+            ("node.hostnames==web1", 5, "expected 'hostname' or 'labels.<key>'"),
+            ("node.labels==x", 5, "expected 'hostname' or 'labels.<key>'"),
+            ("node.labels.==x", 12, "expected a label key"),
+            ("node.labels._x==y", 12, "a label key must start with a letter or digit, not '_'"),
+            ("node.labels.kawakaze.io/x==1", 23, "expected '==' or '!='"),
+            ("node.hostname.x==1", 13, "expected '==' or '!='"),
+            ("node.labels.zone=eu", 16, "expected '==' or '!='"),
+            ("node.labels.zone eu", 17, "expected '==' or '!='"),
+            ("node.hostname<>web1", 13, "expected '==' or '!='"),
+            ("node.hostname==", 15, "expected a value"),
+            ("node.hostname== ", 16, "expected a value"),
+            ("node.hostname===web1", 15, "expected a value"),
+            ("node.hostname==web1 web2", 20, "unexpected 'w'"),
+            ("node.hostname==web1!", 19, "unexpected '!'"),
+            ("ñode.hostname==web1", 0, "expected 'node.hostname' or 'node.labels.<key>'"),
+            ("node.labels.zone==é x", 20, "unexpected 'x'"),
+        ];
+        for (input, position, message) in cases {
+            let err = parse_constraint(input).unwrap_err();
+            assert_eq!((err.position, err.message.as_str()), (position, message), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_parse_preference() {
+        assert_eq!(parse_preference("spread=node.labels.zone"), Ok(Field::Label("zone".to_string())));
+        assert_eq!(parse_preference("spread=node.hostname"), Ok(Field::Hostname));
+
+        let cases = [
+            ("node.labels.zone", 0, "expected 'spread='"),
+            ("binpack=node.labels.zone", 0, "expected 'spread='"),
+            ("spread=zone", 7, "expected 'node.hostname' or 'node.labels.<key>'"),
+            ("spread=node.labels.zone==eu", 23, "unexpected '='"),
+        ];
+        for (input, position, message) in cases {
+            let err = parse_preference(input).unwrap_err();
+            assert_eq!((err.position, err.message.as_str()), (position, message), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_matches() {
+        let cases = [
+            ("node.hostname==web1", true),
+            ("node.hostname==web2", false),
+            ("node.hostname!=web2", true),
+            ("node.hostname!=web1", false),
+            ("node.labels.zone==eu-west", true),
+            ("node.labels.zone==EU-WEST", false),
+            ("node.labels.disk!=hdd", true),
+            // A missing label equals nothing
+            ("node.labels.gpu==true", false),
+            ("node.labels.gpu!=true", true),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parse_constraint(input).unwrap().matches(&labels()), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_constraint_display() {
+        let constraint = parse_constraint(" node.labels.zone != eu ").unwrap();
+        assert_eq!(constraint.to_string(), "node.labels.zone!=eu");
+        assert_eq!(parse_constraint(&constraint.to_string()), Ok(constraint));
+    }
+
+    #[test]
+    fn test_placement_parse() {
+        let placement = Placement {
+            constraints: vec!["node.labels.zone==eu-west".to_string(), "node.hostname!=web2".to_string()],
+            preferences: vec!["spread=node.labels.zone".to_string()],
+        };
+        assert_eq!(placement.parse().unwrap().len(), 2);
+        assert!(Placement::default().parse().unwrap().is_empty());
+
+        let placement = Placement { constraints: vec!["node.hostname==web1".to_string(), "node.labels.zone=eu".to_string()], ..Default::default() };
+        let err = placement.parse().unwrap_err();
+        assert_eq!(err.to_string(), "Invalid placement constraint 'node.labels.zone=eu' at position 16: expected '==' or '!='");
+
+        let placement = Placement { preferences: vec!["spread=zone".to_string()], ..Default::default() };
+        assert_eq!(placement.parse().unwrap_err().kind, "preference");
+    }
+
+    #[test]
+    fn test_unsatisfied() {
+        let parse = |inputs: &[&str]| inputs.iter().map(|input| parse_constraint(input).unwrap()).collect::<Vec<_>>();
+        assert_eq!(unsatisfied(&[], &labels()), None);
+        assert_eq!(unsatisfied(&parse(&["node.hostname==web1", "node.labels.disk==ssd"]), &labels()), None);
+
+        let reason = unsatisfied(&parse(&["node.hostname==web1", "node.labels.zone==us-east", "node.labels.gpu==true"]), &labels()).unwrap();
+        assert_eq!(
+            reason,
+            "Node 'web1' doesn't satisfy the placement: node.labels.zone==us-east (zone is 'eu-west'); node.labels.gpu==true (no gpu label)"
+        );
+    }
+
+    #[test]
+    fn test_node_labels() {
+        let host = HostInfo {
+            hostname: Some("web1.example.org".to_string()),
+            os_version: Some("14.1-RELEASE-p3".to_string()),
+            arch: Some("amd64".to_string()),
+            ..Default::default()
+        };
+        let configured = BTreeMap::from([("zone".to_string(), "eu-west".to_string())]);
+        let labels = node_labels(&configured, &host, "tank/kawakaze");
+        assert_eq!(
+            labels,
+            BTreeMap::from([
+                ("arch".to_string(), "amd64".to_string()),
+                ("hostname".to_string(), "web1.example.org".to_string()),
+                ("os_version".to_string(), "14.1-RELEASE-p3".to_string()),
+                ("pool".to_string(), "tank".to_string()),
+                ("zone".to_string(), "eu-west".to_string()),
+            ])
+        );
+        // The same facts give the same labels
+        assert_eq!(node_labels(&configured, &host, "tank/kawakaze"), labels);
+
+        // Unknown facts are left out
+        let labels = node_labels(&BTreeMap::new(), &HostInfo::default(), "");
+        assert!(labels.is_empty());
+    }
+
+    #[test]
+    fn test_check_node_labels() {
+        let check = |key: &str, value: &str| check_node_labels(&BTreeMap::from([(key.to_string(), value.to_string())]));
+        assert!(check("zone", "eu-west").is_ok());
+        assert!(check("rack.row-1_a", "12").is_ok());
+        assert!(check("", "x").is_err());
+        assert!(check("-zone", "x").is_err());
+        assert!(check("zo ne", "x").is_err());
+        assert!(check("zone", "").is_err());
+        assert!(check("zone", "eu west").is_err());
+        assert!(check("zone", "a=b").is_err());
+        assert_eq!(check("hostname", "web1").unwrap_err(), "Node label 'hostname' is derived from the host and can't be configured");
+    }
+}
@@ -0,0 +1,282 @@
+//! Pool health gate on operations that write to the pool
+//!
+//! A pool that is DEGRADED, or whose I/O is suspended after a disk was
+//! pulled, lets `zfs` commands hang instead of failing. Requests that would
+//! run them (builds, container creates, bootstraps, upgrades) are refused
+//! up front with 503 `POOL_UNHEALTHY` while the pool isn't ONLINE, so stuck
+//! tasks don't pile up. Reads, stop and remove are never gated: they are
+//! what an operator needs during the incident.
+//!
+//! [`PoolHealth`] reads the pool's state when asked and keeps it for a short
+//! TTL; the daemon also refreshes it periodically, so a state change shows
+//! up in the log even while nothing is created. A failed read is neither
+//! cached nor taken to mean the pool is unhealthy. Which requests are gated
+//! is [`gated_action`].
+
+use crate::api::{Endpoint, Method};
+use crate::zfs::{PoolState, PoolStatus, Zfs};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long a pool status is kept before it is read again
+pub const DEFAULT_TTL: Duration = Duration::from_secs(5);
+
+/// How often the daemon reads the pool status on its own
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Where a [`PoolHealth`] reads the pool status
+pub trait PoolSource: Send + Sync {
+    fn status(&self) -> Result<PoolStatus, String>;
+}
+
+impl<F> PoolSource for F
+where
+    F: Fn() -> Result<PoolStatus, String> + Send + Sync,
+{
+    fn status(&self) -> Result<PoolStatus, String> {
+        self()
+    }
+}
+
+impl PoolSource for Zfs {
+    fn status(&self) -> Result<PoolStatus, String> {
+        self.pool_status().map_err(|e| e.to_string())
+    }
+}
+
+/// Whether writes are refused on a pool in `state`
+///
+/// Only ONLINE is writable, and DEGRADED too when `allow_degraded` is set.
+pub fn refuses(state: PoolState, allow_degraded: bool) -> bool {
+    !(state.is_healthy() || (allow_degraded && state == PoolState::Degraded))
+}
+
+/// The action a request would take on the pool, named in the refusal, if
+/// the request is gated on the pool's health
+pub fn gated_action(method: &Method, endpoint: &Endpoint) -> Option<&'static str> {
+    match (method, endpoint) {
+        (Method::Post, Endpoint::ImageBuild) => Some("build an image"),
+        (Method::Post, Endpoint::ContainerCreate | Endpoint::ContainerBatchCreate) => Some("create a container"),
+        (Method::Post, Endpoint::BootstrapJail(_)) => Some("bootstrap a jail"),
+        (Method::Post, Endpoint::ImageUpgrade(_)) => Some("upgrade an image"),
+        (Method::Post, Endpoint::JailUpgrade(_)) => Some("upgrade a jail"),
+        _ => None,
+    }
+}
+
+/// The pool's status, cached for a short TTL
+pub struct PoolHealth {
+    /// `None` without ZFS: there is no pool, so nothing is refused
+    source: Option<Box<dyn PoolSource>>,
+    ttl: Duration,
+    cached: Mutex<Option<(Instant, PoolStatus)>>,
+}
+
+impl PoolHealth {
+    pub fn new(source: impl PoolSource + 'static, ttl: Duration) -> Self {
+        Self { source: Some(Box::new(source)), ttl, cached: Mutex::new(None) }
+    }
+
+    /// The pool's status, read again once the cached one is older than the
+    /// TTL; `None` without ZFS
+    pub fn status(&self) -> Option<Result<PoolStatus, String>> {
+        {
+            let cached = self.cached.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some((checked, status)) = cached.as_ref()
+                && checked.elapsed() < self.ttl
+            {
+                return Some(Ok(status.clone()));
+            }
+        }
+        self.refresh()
+    }
+
+    /// Read the pool's status now, logging a change of state
+    pub fn refresh(&self) -> Option<Result<PoolStatus, String>> {
+        let source = self.source.as_ref()?;
+        let result = source.status();
+
+        let mut cached = self.cached.lock().unwrap_or_else(|e| e.into_inner());
+        match &result {
+            Ok(status) => {
+                let previous = cached.as_ref().map(|(_, previous)| previous.state);
+                if previous != Some(status.state) {
+                    if status.state.is_healthy() {
+                        if previous.is_some() {
+                            tracing::info!("Pool '{}' is ONLINE again", status.name);
+                        }
+                    } else {
+                        tracing::warn!(
+                            "Pool '{}' is {}{}",
+                            status.name,
+                            status.state,
+                            status.status.as_deref().map(|s| format!(": {}", s)).unwrap_or_default()
+                        );
+                    }
+                }
+                *cached = Some((Instant::now(), status.clone()));
+            }
+            Err(e) => {
+                tracing::warn!("Could not read the pool status: {}", e);
+                *cached = None;
+            }
+        }
+        Some(result)
+    }
+
+    /// Why `action` must not write to the pool right now, if the pool is
+    /// known to be in a state that [`refuses`] writes
+    ///
+    /// A DEGRADED pool that is allowed still logs a warning. A pool whose
+    /// status can't be read doesn't block anything.
+    pub fn refusal(&self, action: &str, allow_degraded: bool) -> Option<String> {
+        let status = self.status()?.ok()?;
+        if status.state.is_healthy() {
+            return None;
+        }
+        if !refuses(status.state, allow_degraded) {
+            tracing::warn!("Pool '{}' is {}; allowed to {} by storage.allow_degraded_pool", status.name, status.state, action);
+            return None;
+        }
+
+        tracing::warn!("Pool '{}' is {}; refusing to {}", status.name, status.state, action);
+        Some(format!(
+            "Pool '{}' is {}{}; refusing to {} until it is repaired (see `zpool status {}`)",
+            status.name,
+            status.state,
+            status.status.map(|s| format!(": {}", s)).unwrap_or_default(),
+            action,
+            status.name
+        ))
+    }
+}
+
+impl Default for PoolHealth {
+    fn default() -> Self {
+        Self { source: None, ttl: DEFAULT_TTL, cached: Mutex::new(None) }
+    }
+}
+
+impl std::fmt::Debug for PoolHealth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PoolHealth").field("ttl", &self.ttl).finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::ROUTES;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn status(state: PoolState) -> PoolStatus {
+        PoolStatus { name: "tank".to_string(), state, free_bytes: 1 << 30, size_bytes: 1 << 31, status: None }
+    }
+
+    /// A pool whose state the test changes, counting the reads
+    fn pool(ttl: Duration) -> (PoolHealth, Arc<Mutex<Result<PoolStatus, String>>>, Arc<AtomicUsize>) {
+        let current = Arc::new(Mutex::new(Ok(status(PoolState::Online))));
+        let reads = Arc::new(AtomicUsize::new(0));
+        let (state, counter) = (Arc::clone(&current), Arc::clone(&reads));
+        let health = PoolHealth::new(
+            move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                state.lock().unwrap().clone()
+            },
+            ttl,
+        );
+        (health, current, reads)
+    }
+
+    #[test]
+    fn test_status_cached_for_ttl() {
+        let (health, current, reads) = pool(Duration::from_secs(3600));
+        assert_eq!(health.status().unwrap().unwrap().state, PoolState::Online);
+        *current.lock().unwrap() = Ok(status(PoolState::Suspended));
+        assert_eq!(health.status().unwrap().unwrap().state, PoolState::Online);
+        assert_eq!(reads.load(Ordering::SeqCst), 1);
+
+        // The periodic check reads it regardless
+        assert_eq!(health.refresh().unwrap().unwrap().state, PoolState::Suspended);
+        assert_eq!(health.status().unwrap().unwrap().state, PoolState::Suspended);
+        assert_eq!(reads.load(Ordering::SeqCst), 2);
+
+        let (health, _, reads) = pool(Duration::ZERO);
+        health.status();
+        health.status();
+        assert_eq!(reads.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_failed_read_not_cached() {
+        let (health, current, reads) = pool(Duration::from_secs(3600));
+        *current.lock().unwrap() = Err("zpool: timed out".to_string());
+        assert!(health.status().unwrap().is_err());
+        assert!(health.refusal("build an image", false).is_none());
+
+        *current.lock().unwrap() = Ok(status(PoolState::Faulted));
+        assert!(health.refusal("build an image", false).is_some());
+        assert_eq!(reads.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_refusal() {
+        let (health, current, _) = pool(Duration::ZERO);
+        assert!(health.refusal("create a container", false).is_none());
+
+        *current.lock().unwrap() = Ok(PoolStatus {
+            status: Some("One or more devices has been removed.".to_string()),
+            ..status(PoolState::Suspended)
+        });
+        let reason = health.refusal("create a container", true).unwrap();
+        assert!(reason.starts_with("Pool 'tank' is SUSPENDED: One or more devices"), "{}", reason);
+        assert!(reason.contains("refusing to create a container"), "{}", reason);
+
+        // DEGRADED only when allowed
+        *current.lock().unwrap() = Ok(status(PoolState::Degraded));
+        assert!(health.refusal("create a container", false).is_some());
+        assert!(health.refusal("create a container", true).is_none());
+
+        assert!(PoolHealth::default().status().is_none());
+        assert!(PoolHealth::default().refusal("build an image", false).is_none());
+    }
+
+    #[test]
+    fn test_refuses() {
+        for state in [PoolState::Faulted, PoolState::Offline, PoolState::Removed, PoolState::Unavail, PoolState::Suspended, PoolState::Unknown] {
+            assert!(refuses(state, true), "{}", state);
+        }
+        assert!(!refuses(PoolState::Online, false));
+        assert!(refuses(PoolState::Degraded, false));
+        assert!(!refuses(PoolState::Degraded, true));
+    }
+
+    #[test]
+    fn test_gated_routes() {
+        let gated: Vec<_> = ROUTES
+            .iter()
+            .filter(|route| gated_action(&route.method, &route.endpoint).is_some())
+            .map(|route| route.path)
+            .collect();
+        assert_eq!(
+            gated,
+            [
+                "jails/{name}/bootstrap",
+                "jails/{name}/upgrade",
+                "images/build",
+                "images/{id}/upgrade",
+                "containers/create",
+                "containers/batch",
+            ]
+        );
+
+        // Reads, stop and remove stay available during an incident
+        for route in ROUTES {
+            let stops = matches!(route.endpoint, Endpoint::StopContainer(_) | Endpoint::StopJail(_));
+            if route.method != Method::Post || stops {
+                assert!(gated_action(&route.method, &route.endpoint).is_none(), "{}", route.path);
+            }
+        }
+    }
+}
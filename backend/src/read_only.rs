@@ -0,0 +1,416 @@
+//! Read-only container roots and tmpfs mounts
+//!
+//! A container created with `read_only_root` runs with its dataset set
+//! `readonly=on`; it can write only to its volumes and to tmpfs mounts: the
+//! ones listed under `read_only.tmpfs` in the config (`/tmp` and `/var/run`
+//! by default) and its own (`--tmpfs`). Containers with a writable root get
+//! only their own tmpfs. A container's tmpfs replaces a configured one at
+//! the same path. The start order is fixed here:
+//!
+//! 1. anything written into the root at start happens before this module
+//!    is called (the managed `/etc/hosts` block, see [`crate::hosts`]);
+//! 2. the tmpfs mount points are created, while the root is still writable;
+//! 3. `readonly=on` on the dataset, for a read-only root;
+//! 4. the tmpfs mounts, parents before the paths below them;
+//! 5. the jail starts.
+//!
+//! A failure at any step undoes the earlier ones. Stop reverses the order
+//! and leaves the dataset writable, so maintenance (writing exec, upgrades)
+//! happens while the container is stopped.
+
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use tracing::warn;
+
+use crate::cmdtrace::TracedCommand;
+use crate::config::TmpfsConfig;
+use crate::container::TmpfsMount;
+use crate::zfs::Zfs;
+
+/// A tmpfs to mount under a container's root
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TmpfsTarget {
+    pub target: PathBuf,
+    /// `mount -o` options, e.g. "size=64m,mode=1777"; may be empty
+    pub options: String,
+}
+
+/// Host operations around a container's root, so the order can be tested
+pub trait RootFs {
+    fn set_readonly(&mut self, dataset: &str, readonly: bool) -> Result<(), String>;
+    fn create_mount_point(&mut self, root: &Path, target: &Path) -> Result<(), String>;
+    fn mount_tmpfs(&mut self, target: &Path, options: &str) -> Result<(), String>;
+    fn unmount(&mut self, target: &Path) -> Result<(), String>;
+}
+
+/// [`RootFs`] on the host: ZFS properties and `mount -t tmpfs`
+///
+/// Without ZFS there is no container dataset to make read-only; tmpfs
+/// mounts still work.
+pub struct HostRootFs {
+    pub zfs: Option<Zfs>,
+}
+
+impl RootFs for HostRootFs {
+    fn set_readonly(&mut self, dataset: &str, readonly: bool) -> Result<(), String> {
+        let zfs = self.zfs.as_ref().ok_or_else(|| "ZFS is not available".to_string())?;
+        zfs.set_property(dataset, "readonly", if readonly { "on" } else { "off" })
+            .map_err(|e| e.to_string())
+    }
+
+    fn create_mount_point(&mut self, root: &Path, target: &Path) -> Result<(), String> {
+        create_mount_point(root, target).map_err(|e| e.to_string())
+    }
+
+    fn mount_tmpfs(&mut self, target: &Path, options: &str) -> Result<(), String> {
+        let target = target.display().to_string();
+        if options.is_empty() {
+            run("mount", &["-t", "tmpfs", "tmpfs", &target])
+        } else {
+            run("mount", &["-t", "tmpfs", "-o", options, "tmpfs", &target])
+        }
+    }
+
+    fn unmount(&mut self, target: &Path) -> Result<(), String> {
+        run("umount", &[&target.display().to_string()])
+    }
+}
+
+fn run(program: &str, args: &[&str]) -> Result<(), String> {
+    let output = std::process::Command::new(program)
+        .args(args)
+        .traced_output()
+        .map_err(|e| format!("{} {}: {}", program, args.join(" "), e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "{} {}: {}",
+            program,
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+/// Create `target` and its missing parents below `root`
+///
+/// The deepest part that exists must resolve inside `root`, so a symlink in
+/// the container can't put the mount point (or the tmpfs) on the host.
+pub fn create_mount_point(root: &Path, target: &Path) -> io::Result<()> {
+    let mut existing = target;
+    while existing.symlink_metadata().is_err() {
+        existing = match existing.parent() {
+            Some(parent) if parent.starts_with(root) => parent,
+            _ => return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} does not exist", root.display()))),
+        };
+    }
+    let Some(resolved) = crate::mount_policy::resolve_within(existing, &[root.to_path_buf()])? else {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("'{}' resolves outside the container root", target.display()),
+        ));
+    };
+    let missing = target.strip_prefix(existing).unwrap_or(Path::new(""));
+    let mount_point = resolved.join(missing);
+    std::fs::create_dir_all(&mount_point)?;
+    if !mount_point.is_dir() {
+        return Err(io::Error::other(format!("'{}' is not a directory", target.display())));
+    }
+    Ok(())
+}
+
+/// Where each tmpfs goes on the host, under the container's root
+///
+/// `configured` are the `read_only.tmpfs` entries (pass none for a writable
+/// root); a container's own tmpfs at the same path replaces one. The result
+/// is in mount order: fewer path components first.
+pub fn tmpfs_targets(root: &Path, configured: &[TmpfsConfig], own: &[TmpfsMount]) -> Vec<TmpfsTarget> {
+    let normalize = |path: &str| path.trim_matches('/').to_string();
+    let own_paths: Vec<String> = own.iter().map(|t| normalize(&t.destination)).collect();
+
+    let mut targets: Vec<(String, String)> = configured
+        .iter()
+        .filter(|t| !own_paths.contains(&normalize(&t.path)))
+        .map(|t| (normalize(&t.path), format!("size={}", t.size)))
+        .chain(own.iter().map(|t| (normalize(&t.destination), t.options())))
+        .collect();
+    targets.sort_by_key(|(path, _)| Path::new(path).components().filter(|c| matches!(c, Component::Normal(_))).count());
+
+    targets
+        .into_iter()
+        .map(|(path, options)| TmpfsTarget { target: root.join(path), options })
+        .collect()
+}
+
+/// Create the mount points, make the root read-only when `readonly_dataset`
+/// is given, mount the tmpfs and start the jail, undoing what was done if a
+/// step fails
+pub fn start(
+    fs: &mut impl RootFs,
+    readonly_dataset: Option<&str>,
+    root: &Path,
+    tmpfs: &[TmpfsTarget],
+    start_jail: impl FnOnce() -> Result<(), String>,
+) -> Result<(), String> {
+    for t in tmpfs {
+        fs.create_mount_point(root, &t.target)
+            .map_err(|e| format!("Failed to create tmpfs mount point {}: {}", t.target.display(), e))?;
+    }
+    if let Some(dataset) = readonly_dataset {
+        fs.set_readonly(dataset, true)
+            .map_err(|e| format!("Failed to make {} read-only: {}", dataset, e))?;
+    }
+
+    let mut mounted = Vec::new();
+    let mut result = Ok(());
+    for t in tmpfs {
+        if let Err(e) = fs.mount_tmpfs(&t.target, &t.options) {
+            result = Err(format!("Failed to mount tmpfs on {}: {}", t.target.display(), e));
+            break;
+        }
+        mounted.push(t.target.clone());
+    }
+    if result.is_ok() {
+        result = start_jail();
+    }
+
+    if result.is_err() {
+        release(fs, readonly_dataset, &mounted);
+    }
+    result
+}
+
+/// Unmount the tmpfs, in reverse mount order, and make the root writable
+/// again after the jail stopped
+pub fn stop(fs: &mut impl RootFs, readonly_dataset: Option<&str>, tmpfs: &[TmpfsTarget]) -> Result<(), String> {
+    for t in tmpfs.iter().rev() {
+        // Not mounted if the daemon restarted since, or an earlier stop failed
+        if let Err(e) = fs.unmount(&t.target) {
+            warn!("Failed to unmount tmpfs on {}: {}", t.target.display(), e);
+        }
+    }
+    match readonly_dataset {
+        Some(dataset) => fs
+            .set_readonly(dataset, false)
+            .map_err(|e| format!("Failed to make {} writable: {}", dataset, e)),
+        None => Ok(()),
+    }
+}
+
+fn release(fs: &mut impl RootFs, readonly_dataset: Option<&str>, mounted: &[PathBuf]) {
+    for target in mounted.iter().rev() {
+        if let Err(e) = fs.unmount(target) {
+            warn!("Failed to unmount tmpfs on {}: {}", target.display(), e);
+        }
+    }
+    if let Some(dataset) = readonly_dataset
+        && let Err(e) = fs.set_readonly(dataset, false)
+    {
+        warn!("Failed to make {} writable again: {}", dataset, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Records each operation in a log shared with the mock jail start,
+    /// failing the ones named in `fail`
+    #[derive(Default)]
+    struct MockFs {
+        ops: Rc<RefCell<Vec<String>>>,
+        fail: Vec<String>,
+    }
+
+    impl MockFs {
+        fn failing(op: &str) -> Self {
+            Self { fail: vec![op.to_string()], ..Default::default() }
+        }
+
+        fn record(&mut self, op: String) -> Result<(), String> {
+            let failed = self.fail.contains(&op);
+            self.ops.borrow_mut().push(op);
+            if failed { Err("mock failure".to_string()) } else { Ok(()) }
+        }
+
+        fn ops(&self) -> Vec<String> {
+            self.ops.borrow().clone()
+        }
+    }
+
+    impl RootFs for MockFs {
+        fn set_readonly(&mut self, dataset: &str, readonly: bool) -> Result<(), String> {
+            self.record(format!("readonly={} {}", if readonly { "on" } else { "off" }, dataset))
+        }
+
+        fn create_mount_point(&mut self, _root: &Path, target: &Path) -> Result<(), String> {
+            self.record(format!("mkdir {}", target.display()))
+        }
+
+        fn mount_tmpfs(&mut self, target: &Path, options: &str) -> Result<(), String> {
+            self.record(format!("mount {} {}", options, target.display()))
+        }
+
+        fn unmount(&mut self, target: &Path) -> Result<(), String> {
+            self.record(format!("umount {}", target.display()))
+        }
+    }
+
+    const ROOT: &str = "/kz/c/a1";
+
+    fn tmpfs() -> Vec<TmpfsTarget> {
+        tmpfs_targets(Path::new(ROOT), &crate::config::ReadOnlyConfig::default().tmpfs, &[])
+    }
+
+    fn start_with(fs: &mut MockFs, readonly: bool, tmpfs: &[TmpfsTarget], jail: Result<(), String>) -> Result<(), String> {
+        let ops = fs.ops.clone();
+        start(fs, readonly.then_some("tank/c/a1"), Path::new(ROOT), tmpfs, move || {
+            ops.borrow_mut().push("start jail".to_string());
+            jail
+        })
+    }
+
+    fn own(destination: &str, size_bytes: Option<u64>, mode: Option<u32>) -> TmpfsMount {
+        TmpfsMount { destination: destination.to_string(), size_bytes, mode }
+    }
+
+    #[test]
+    fn test_start_order() {
+        let mut fs = MockFs::default();
+        start_with(&mut fs, true, &tmpfs(), Ok(())).unwrap();
+        assert_eq!(fs.ops(), [
+            "mkdir /kz/c/a1/tmp",
+            "mkdir /kz/c/a1/var/run",
+            "readonly=on tank/c/a1",
+            "mount size=64m /kz/c/a1/tmp",
+            "mount size=16m /kz/c/a1/var/run",
+            "start jail",
+        ]);
+    }
+
+    #[test]
+    fn test_start_rolls_back_failed_mount() {
+        let mut fs = MockFs::failing("mount size=16m /kz/c/a1/var/run");
+        let err = start_with(&mut fs, true, &tmpfs(), Ok(())).unwrap_err();
+        assert!(err.contains("/var/run"), "{}", err);
+        // The jail never starts; the first tmpfs comes off and the root is writable again
+        assert_eq!(fs.ops()[2..], [
+            "readonly=on tank/c/a1",
+            "mount size=64m /kz/c/a1/tmp",
+            "mount size=16m /kz/c/a1/var/run",
+            "umount /kz/c/a1/tmp",
+            "readonly=off tank/c/a1",
+        ]);
+    }
+
+    #[test]
+    fn test_start_rolls_back_failed_jail() {
+        let mut fs = MockFs::default();
+        assert!(start_with(&mut fs, true, &tmpfs(), Err("jail_set: EPERM".to_string())).is_err());
+        assert_eq!(fs.ops()[2..], [
+            "readonly=on tank/c/a1",
+            "mount size=64m /kz/c/a1/tmp",
+            "mount size=16m /kz/c/a1/var/run",
+            "start jail",
+            "umount /kz/c/a1/var/run",
+            "umount /kz/c/a1/tmp",
+            "readonly=off tank/c/a1",
+        ]);
+    }
+
+    #[test]
+    fn test_start_stops_at_readonly_failure() {
+        let mut fs = MockFs::failing("readonly=on tank/c/a1");
+        assert!(start_with(&mut fs, true, &tmpfs(), Ok(())).is_err());
+        assert_eq!(fs.ops()[2..], ["readonly=on tank/c/a1"]);
+    }
+
+    #[test]
+    fn test_start_stops_at_mount_point_failure() {
+        // Nothing is changed before every mount point is in place
+        let mut fs = MockFs::failing("mkdir /kz/c/a1/var/run");
+        let err = start_with(&mut fs, true, &tmpfs(), Ok(())).unwrap_err();
+        assert!(err.contains("mount point /kz/c/a1/var/run"), "{}", err);
+        assert_eq!(fs.ops(), ["mkdir /kz/c/a1/tmp", "mkdir /kz/c/a1/var/run"]);
+    }
+
+    #[test]
+    fn test_stop_unmounts_then_makes_writable() {
+        // A tmpfs that is already gone doesn't keep the root read-only
+        let mut fs = MockFs::failing("umount /kz/c/a1/var/run");
+        stop(&mut fs, Some("tank/c/a1"), &tmpfs()).unwrap();
+        assert_eq!(fs.ops(), ["umount /kz/c/a1/var/run", "umount /kz/c/a1/tmp", "readonly=off tank/c/a1"]);
+    }
+
+    #[test]
+    fn test_tmpfs_targets() {
+        let configured = crate::config::ReadOnlyConfig::default().tmpfs;
+        let own = [
+            own("/tmp/cache", None, Some(0o700)),
+            own("/run", Some(64 << 20), Some(0o1777)),
+            own("/tmp/", Some(1 << 30), None),
+        ];
+        let targets: Vec<(String, String)> = tmpfs_targets(Path::new(ROOT), &configured, &own)
+            .into_iter()
+            .map(|t| (t.target.display().to_string(), t.options))
+            .collect();
+        // The container's /tmp replaces the configured one, and parents come first
+        assert_eq!(targets, [
+            ("/kz/c/a1/run".to_string(), "size=67108864,mode=1777".to_string()),
+            ("/kz/c/a1/tmp".to_string(), "size=1073741824".to_string()),
+            ("/kz/c/a1/var/run".to_string(), "size=16m".to_string()),
+            ("/kz/c/a1/tmp/cache".to_string(), "mode=700".to_string()),
+        ]);
+
+        // A writable root gets only the container's own
+        let targets = tmpfs_targets(Path::new(ROOT), &[], &own[..1]);
+        assert_eq!(targets, [TmpfsTarget { target: PathBuf::from("/kz/c/a1/tmp/cache"), options: "mode=700".to_string() }]);
+    }
+
+    #[test]
+    fn test_writable_root_only_mounts() {
+        let targets = tmpfs_targets(Path::new(ROOT), &[], &[own("/run", Some(4096), None), own("/run/lock", None, None)]);
+        let mut fs = MockFs::default();
+        start_with(&mut fs, false, &targets, Ok(())).unwrap();
+        assert_eq!(fs.ops(), [
+            "mkdir /kz/c/a1/run",
+            "mkdir /kz/c/a1/run/lock",
+            "mount size=4096 /kz/c/a1/run",
+            "mount  /kz/c/a1/run/lock",
+            "start jail",
+        ]);
+
+        let mut fs = MockFs::failing("mount  /kz/c/a1/run/lock");
+        assert!(start_with(&mut fs, false, &targets, Ok(())).is_err());
+        assert_eq!(fs.ops()[2..], ["mount size=4096 /kz/c/a1/run", "mount  /kz/c/a1/run/lock", "umount /kz/c/a1/run"]);
+
+        let mut fs = MockFs::default();
+        stop(&mut fs, None, &targets).unwrap();
+        assert_eq!(fs.ops(), ["umount /kz/c/a1/run/lock", "umount /kz/c/a1/run"]);
+    }
+
+    #[test]
+    fn test_create_mount_point() {
+        let root = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::create_dir(root.path().join("var")).unwrap();
+        std::os::unix::fs::symlink(outside.path(), root.path().join("escape")).unwrap();
+        std::os::unix::fs::symlink("var", root.path().join("inside")).unwrap();
+        std::fs::write(root.path().join("file"), "").unwrap();
+
+        create_mount_point(root.path(), &root.path().join("var/run/lock")).unwrap();
+        assert!(root.path().join("var/run/lock").is_dir());
+        // A symlink that stays inside the root is fine
+        create_mount_point(root.path(), &root.path().join("inside/cache")).unwrap();
+        assert!(root.path().join("var/cache").is_dir());
+
+        let err = create_mount_point(root.path(), &root.path().join("escape/run")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert!(!outside.path().join("run").exists());
+        assert!(create_mount_point(root.path(), &root.path().join("file")).is_err());
+    }
+}
@@ -1,32 +1,184 @@
 //! Unix socket server for Kawakaze API
 //!
 //! This module provides a JSON-over-Unix-socket server using line-delimited framing.
+//! A connection serves any number of requests, one at a time; streaming
+//! endpoints keep it busy until their stream ends (see [`crate::stream`]).
 
+use std::collections::VecDeque;
+use std::future::Future;
 use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::UnixListener;
 use tokio::sync::Mutex;
-use tokio_util::codec::{Framed, LinesCodec};
+use tokio_util::codec::{Framed, LinesCodec, LinesCodecError};
+use tokio_util::sync::CancellationToken;
 use futures::{SinkExt, StreamExt};
 use tracing::{info, warn, error, debug, instrument};
 
-use crate::api::Request;
-use crate::handler::handle_request;
+use crate::api::{ApiError, Request, Response, status};
+use crate::handler::{handle_request, start_stream};
+use crate::stream::{ActiveStream, StreamRegistry};
 use crate::JailManager;
 
 /// Unix socket server for the Kawakaze API
 pub struct SocketServer {
     socket_path: Arc<String>,
     manager: Arc<Mutex<JailManager>>,
+    streams: Arc<StreamRegistry>,
 }
 
+/// How long shutdown waits for streams to send their terminal frames
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(2);
+
+/// How long a cancelled request may take to stop its work
+const CANCEL_GRACE: Duration = Duration::from_secs(5);
+
 impl SocketServer {
     /// Create a new socket server
     pub fn new(socket_path: Arc<String>, manager: Arc<Mutex<JailManager>>) -> Self {
         Self {
             socket_path,
             manager,
+            streams: Arc::new(StreamRegistry::new()),
+        }
+    }
+
+    /// Streams being served
+    pub fn streams(&self) -> &Arc<StreamRegistry> {
+        &self.streams
+    }
+
+    /// Fail queued builds and bootstraps, then cancel every active stream
+    /// and wait (briefly) for their terminal frames to be sent
+    pub async fn shutdown(&self) {
+        let active = self.streams.active().len();
+        info!(active_streams = active, "Shutting down API server");
+        self.manager.lock().await.drain_task_queues();
+        self.streams.shutdown();
+
+        let deadline = tokio::time::Instant::now() + SHUTDOWN_GRACE;
+        while !self.streams.active().is_empty() && tokio::time::Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(20)).await;
         }
+        // Let connection tasks flush the last frames
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // Failed database writes get one last chance
+        self.manager.lock().await.flush_pending_writes(SHUTDOWN_GRACE).await;
+    }
+
+    /// Retry failed database writes as they come due, until the manager is
+    /// dropped
+    pub fn spawn_write_retries(&self) -> tokio::task::JoinHandle<()> {
+        let manager = Arc::downgrade(&self.manager);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(crate::persist_queue::RETRY_INTERVAL);
+            loop {
+                interval.tick().await;
+                let Some(manager) = manager.upgrade() else {
+                    return;
+                };
+                manager.lock().await.retry_pending_writes();
+            }
+        })
+    }
+
+    /// Drop idle uploads as they time out, until the manager is dropped
+    pub fn spawn_upload_sweeper(&self) -> tokio::task::JoinHandle<()> {
+        let manager = Arc::downgrade(&self.manager);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(crate::upload::SWEEP_INTERVAL);
+            loop {
+                interval.tick().await;
+                let Some(manager) = manager.upgrade() else {
+                    return;
+                };
+                manager.lock().await.expire_idle_uploads();
+            }
+        })
+    }
+
+    /// Drop exec sessions whose process is gone, until the manager is
+    /// dropped
+    pub fn spawn_exec_session_sweeper(&self) -> tokio::task::JoinHandle<()> {
+        let manager = Arc::downgrade(&self.manager);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(crate::exec_sessions::SWEEP_INTERVAL);
+            loop {
+                interval.tick().await;
+                let Some(manager) = manager.upgrade() else {
+                    return;
+                };
+                manager.lock().await.exec_sessions().sweep();
+            }
+        })
+    }
+
+    /// Read the pool's health every `pool_health::CHECK_INTERVAL`, until the
+    /// manager is dropped
+    ///
+    /// `zpool` runs on a blocking thread without the manager lock, so a pool
+    /// that hangs it doesn't hang the API too.
+    pub fn spawn_pool_checks(&self) -> tokio::task::JoinHandle<()> {
+        let manager = Arc::downgrade(&self.manager);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(crate::pool_health::CHECK_INTERVAL);
+            loop {
+                interval.tick().await;
+                let Some(manager) = manager.upgrade() else {
+                    return;
+                };
+                let health = manager.lock().await.pool_health();
+                drop(manager);
+                let _ = tokio::task::spawn_blocking(move || health.refresh()).await;
+            }
+        })
+    }
+
+    /// Collect unreferenced snapshots every `snapshot_gc.interval_secs`,
+    /// until the manager is dropped; nothing is spawned when it is 0
+    ///
+    /// The first run is one interval after startup. A run is skipped while
+    /// the pool refuses writes, and `zfs` runs without the manager lock.
+    pub async fn spawn_snapshot_gc(&self) -> Option<tokio::task::JoinHandle<()>> {
+        let interval_secs = self.manager.lock().await.config.snapshot_gc.interval_secs;
+        if interval_secs == 0 {
+            return None;
+        }
+        let period = Duration::from_secs(interval_secs);
+        let manager = Arc::downgrade(&self.manager);
+        Some(tokio::spawn(async move {
+            let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            loop {
+                interval.tick().await;
+                let Some(manager) = manager.upgrade() else {
+                    return;
+                };
+                let gc = {
+                    let mgr = manager.lock().await;
+                    if let Some(reason) = mgr.pool_write_refusal("collect snapshots") {
+                        warn!("Skipping snapshot collection: {}", reason);
+                        continue;
+                    }
+                    mgr.snapshot_gc()
+                };
+                drop(manager);
+                let Some(gc) = gc else {
+                    return;
+                };
+
+                let now = chrono::Utc::now().timestamp();
+                match tokio::task::spawn_blocking(move || gc.run(false, now)).await {
+                    Ok(Ok(report)) if !report.errors.is_empty() => {
+                        warn!("Snapshot collection left {} snapshot(s) behind", report.errors.len())
+                    }
+                    Ok(Ok(_)) => {}
+                    Ok(Err(e)) => warn!("Snapshot collection failed: {}", e),
+                    Err(e) => warn!("Snapshot collection task failed: {}", e),
+                }
+            }
+        }))
     }
 
     /// Run the socket server
@@ -43,6 +195,10 @@ impl SocketServer {
             std::fs::remove_file(socket_path)?;
         }
 
+        if let Some(parent) = Path::new(socket_path).parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
         // Bind to socket
         let listener = UnixListener::bind(socket_path)?;
         info!("Kawakaze API server listening on {}", socket_path);
@@ -65,13 +221,14 @@ impl SocketServer {
                 Ok((stream, addr)) => {
                     connection_count += 1;
                     let manager = self.manager.clone();
+                    let streams = self.streams.clone();
                     let conn_id = connection_count;
 
                     debug!(connection_id = conn_id, peer_addr = ?addr, "New connection accepted");
 
                     // Spawn a new task for each connection
                     tokio::spawn(async move {
-                        if let Err(e) = handle_connection(stream, manager, conn_id).await {
+                        if let Err(e) = handle_connection(stream, manager, streams, conn_id).await {
                             error!(connection_id = conn_id, error = %e, "Connection error");
                         } else {
                             debug!(connection_id = conn_id, "Connection closed gracefully");
@@ -87,33 +244,67 @@ impl SocketServer {
 }
 
 /// Handle a single client connection
-#[instrument(skip(stream, manager), fields(connection_id = connection_id))]
+///
+/// The connection answers requests in order until the client closes it.
+/// While a stream started on it is active, frames are forwarded as they
+/// come and other requests are refused (see [`crate::stream`]). A request
+/// whose client disconnects before the response is cancelled.
+#[instrument(skip(stream, manager, streams), fields(connection_id = connection_id))]
 async fn handle_connection(
     stream: tokio::net::UnixStream,
     manager: Arc<Mutex<JailManager>>,
+    streams: Arc<StreamRegistry>,
     connection_id: u64,
 ) -> Result<(), Box<dyn std::error::Error>> {
+    // Who is asking, for the records that say so (a stop's cause)
+    let peer_uid = stream.peer_cred().ok().map(|cred| cred.uid());
+
     // Use Framed with LinesCodec for line-delimited JSON messages
     let mut framed = Framed::new(stream, LinesCodec::new());
 
     let mut request_count: u64 = 0;
+    // Dropping it (including on return) cancels the stream's producer
+    let mut active: Option<ActiveStream> = None;
+    // Lines the client sent while a request was being handled
+    let mut pending: VecDeque<String> = VecDeque::new();
 
     loop {
-        // Read next JSON line
-        match framed.next().await {
+        let line = match pending.pop_front() {
+            Some(line) => Some(Ok(line)),
+            None => tokio::select! {
+                line = framed.next() => line,
+                frame = next_frame(&mut active) => {
+                    match frame {
+                        Some(frame) => {
+                            let end = frame.end;
+                            framed.send(serialize_response(&frame, request_count)).await?;
+                            if end {
+                                log_response(&frame, request_count);
+                                active = None;
+                            }
+                        }
+                        // The producer went away without its terminal frame
+                        None => active = None,
+                    }
+                    continue;
+                }
+            },
+        };
+
+        match line {
             Some(Ok(line)) => {
                 request_count += 1;
 
                 // Parse JSON as Request
                 let request = match serde_json::from_str::<Request>(&line) {
-                    Ok(req) => req,
+                    Ok(req) => Request { peer_uid, ..req },
                     Err(e) => {
                         warn!(request_id = request_count, error = %e, "Invalid request format");
                         // Send error response for invalid request
                         let error_response = serde_json::json!({
                             "status": 400,
                             "error": {
-                                "code": "INVALID_REQUEST",
+                                "code": crate::error_codes::INVALID_REQUEST,
                                 "message": format!("Invalid request format: {}", e)
                             }
                         });
@@ -132,48 +323,36 @@ async fn handle_connection(
                     "Incoming request"
                 );
 
-                // Handle the request
-                let response = handle_request(request, manager.clone()).await;
-
-                // Log the response status
-                if response.is_success() {
-                    debug!(
-                        request_id = request_count,
-                        status = response.status,
-                        "Request successful"
-                    );
-                } else {
-                    warn!(
-                        request_id = request_count,
-                        status = response.status,
-                        error = response.error.as_ref().map(|e| e.message.as_str()),
-                        "Request failed"
-                    );
+                if let Some(stream) = &active {
+                    warn!(request_id = request_count, stream_id = stream.id, "Request while streaming");
+                    let busy = Response::error(status::CONFLICT, ApiError::ConnectionBusyStreaming());
+                    framed.send(serialize_response(&busy, request_count)).await?;
+                    continue;
                 }
 
-                // Serialize response to JSON string
-                let response_line = match serde_json::to_string(&response) {
-                    Ok(json) => json,
-                    Err(e) => {
-                        error!(request_id = request_count, error = %e, "Failed to serialize response");
-                        // This should rarely happen, but handle it gracefully
-                        let error_json = serde_json::json!({
-                            "status": 500,
-                            "error": {
-                                "code": "SERIALIZATION_ERROR",
-                                "message": format!("Failed to serialize response: {}", e)
-                            }
-                        });
-                        serde_json::to_string(&error_json)
-                            .map_err(|e| Box::new(e) as Box<dyn std::error::Error>)?
+                if request.parse_endpoint().is_ok_and(|endpoint| endpoint.is_streaming()) {
+                    match start_stream(request, manager.clone(), &streams, connection_id).await {
+                        Ok(stream) => {
+                            debug!(request_id = request_count, stream_id = stream.id, "Streaming");
+                            active = Some(stream);
+                        }
+                        Err(response) => {
+                            log_response(&response, request_count);
+                            framed.send(serialize_response(&response, request_count)).await?;
+                        }
                     }
-                };
-
-                // Send response
-                framed.send(response_line).await?;
+                    continue;
+                }
 
-                // For simple request/response model, close connection after one request
-                break;
+                // Handle the request, cancelling it if the client goes away
+                let cancel = CancellationToken::new();
+                let handler = handle_request(request, manager.clone(), cancel.clone());
+                let Some(response) = handle_or_cancel(&mut framed, &mut pending, handler, &cancel).await else {
+                    info!(request_id = request_count, "Client disconnected; request cancelled");
+                    break;
+                };
+                log_response(&response, request_count);
+                framed.send(serialize_response(&response, request_count)).await?;
             }
             Some(Err(e)) => {
                 // If we've already handled at least one request, the client might have
@@ -196,9 +375,132 @@ async fn handle_connection(
     Ok(())
 }
 
+/// Run `handler` to its response while watching the connection
+///
+/// Lines the client sends meanwhile are queued in `pending`. If the client
+/// closes the connection first, `cancel` fires and the handler gets
+/// [`CANCEL_GRACE`] to stop its work before it is dropped; `None` is
+/// returned since there is nobody to respond to.
+async fn handle_or_cancel<S>(
+    framed: &mut S,
+    pending: &mut VecDeque<String>,
+    handler: impl Future<Output = Response>,
+    cancel: &CancellationToken,
+) -> Option<Response>
+where
+    S: futures::Stream<Item = Result<String, LinesCodecError>> + Unpin,
+{
+    tokio::pin!(handler);
+    loop {
+        tokio::select! {
+            response = &mut handler => return Some(response),
+            line = framed.next() => match line {
+                Some(Ok(line)) => pending.push_back(line),
+                Some(Err(_)) | None => break,
+            },
+        }
+    }
+
+    cancel.cancel();
+    if tokio::time::timeout(CANCEL_GRACE, handler).await.is_err() {
+        warn!("Cancelled request did not finish in time; dropping it");
+    }
+    None
+}
+
+/// Next frame of the active stream; never resolves without one
+async fn next_frame(active: &mut Option<ActiveStream>) -> Option<Response> {
+    match active {
+        Some(stream) => stream.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+fn log_response(response: &Response, request_id: u64) {
+    if response.is_success() {
+        debug!(request_id, status = response.status, "Request successful");
+    } else {
+        warn!(
+            request_id,
+            status = response.status,
+            error = response.error.as_ref().map(|e| e.message.as_str()),
+            "Request failed"
+        );
+    }
+}
+
+/// Serialize a response to its JSON line
+fn serialize_response(response: &Response, request_id: u64) -> String {
+    match serde_json::to_string(response) {
+        Ok(json) => json,
+        Err(e) => {
+            error!(request_id, error = %e, "Failed to serialize response");
+            // This should rarely happen, but handle it gracefully
+            let error_json = serde_json::json!({
+                "status": 500,
+                "error": {
+                    "code": crate::error_codes::SERIALIZATION_ERROR,
+                    "message": format!("Failed to serialize response: {}", e)
+                }
+            });
+            error_json.to_string()
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::api::Endpoint;
+    use crate::container::{Container, ContainerState};
+    use tokio::net::UnixStream;
+
+    type Client = Framed<UnixStream, LinesCodec>;
+
+    /// Serve a manager holding one container, `web`, whose log has two lines
+    async fn serve(dir: &Path, state: ContainerState) -> (Arc<StreamRegistry>, std::path::PathBuf, tokio::task::JoinHandle<()>) {
+        let socket = dir.join("kawakaze.sock");
+        let mut manager = JailManager::new(&socket);
+        manager.config.storage.log_path = dir.join("logs").to_string_lossy().into_owned();
+        let mut container = Container::new("img".to_string(), "kawakaze-web".to_string(), "tank/containers/web".to_string())
+            .with_name("web".to_string());
+        container.set_state(state);
+        let log = crate::logs::container_log_file(&dir.join("logs"), &container.id);
+        crate::logs::append_output(&log, "stdout", "one\ntwo", 1700000000).unwrap();
+        manager.containers.insert(container.id.clone(), container);
+
+        let server = SocketServer::new(Arc::new(socket.to_string_lossy().into_owned()), Arc::new(Mutex::new(manager)));
+        let streams = server.streams().clone();
+        let task = tokio::spawn(async move {
+            let _ = server.run().await.map_err(|e| e.to_string());
+        });
+        for _ in 0..500 {
+            if socket.exists() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        (streams, socket, task)
+    }
+
+    async fn send(client: &mut Client, request: &Request) {
+        client.send(serde_json::to_string(request).unwrap()).await.unwrap();
+    }
+
+    async fn receive(client: &mut Client) -> Response {
+        let line = tokio::time::timeout(Duration::from_secs(5), client.next()).await.unwrap().unwrap().unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    async fn wait_idle(streams: &StreamRegistry) {
+        for _ in 0..500 {
+            if streams.active().is_empty() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("streams still registered: {:?}", streams.active());
+    }
 
     #[test]
     fn test_socket_server_creation() {
@@ -206,4 +508,140 @@ mod tests {
         let server = SocketServer::new(Arc::new("/tmp/test.sock".to_string()), manager);
         assert_eq!(server.socket_path.as_ref(), "/tmp/test.sock");
     }
+
+    #[tokio::test]
+    async fn test_client_drop_cancels_handler() {
+        let (server_end, client_end) = UnixStream::pair().unwrap();
+        let mut framed = Framed::new(server_end, LinesCodec::new());
+        let mut pending = VecDeque::new();
+        let cancel = CancellationToken::new();
+        let stopped = Arc::new(std::sync::atomic::AtomicBool::new(false));
+
+        // Works until it's cancelled, then cleans up
+        let handler = {
+            let (cancel, stopped) = (cancel.clone(), stopped.clone());
+            async move {
+                cancel.cancelled().await;
+                stopped.store(true, std::sync::atomic::Ordering::SeqCst);
+                Response::conflict("cancelled")
+            }
+        };
+
+        let mut client = Framed::new(client_end, LinesCodec::new());
+        client.send("queued".to_string()).await.unwrap();
+        let outcome = tokio::spawn(async move { handle_or_cancel(&mut framed, &mut pending, handler, &cancel).await.map(|_| pending) });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!stopped.load(std::sync::atomic::Ordering::SeqCst));
+
+        drop(client);
+        let outcome = tokio::time::timeout(Duration::from_secs(5), outcome).await.unwrap().unwrap();
+        assert!(outcome.is_none());
+        assert!(stopped.load(std::sync::atomic::Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_handler_response_keeps_queued_lines() {
+        let (server_end, client_end) = UnixStream::pair().unwrap();
+        let mut framed = Framed::new(server_end, LinesCodec::new());
+        let mut client = Framed::new(client_end, LinesCodec::new());
+        let mut pending = VecDeque::new();
+        let cancel = CancellationToken::new();
+
+        client.send("next".to_string()).await.unwrap();
+        let handler = async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Response::success("done").unwrap()
+        };
+        let response = handle_or_cancel(&mut framed, &mut pending, handler, &cancel).await.unwrap();
+
+        assert_eq!(response.data.unwrap(), "done");
+        assert_eq!(pending, ["next"]);
+        assert!(!cancel.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn test_stream_ends_with_terminal_frame() {
+        let dir = tempfile::tempdir().unwrap();
+        let (streams, socket, task) = serve(dir.path(), ContainerState::Stopped).await;
+        let mut client = Framed::new(UnixStream::connect(&socket).await.unwrap(), LinesCodec::new());
+
+        send(&mut client, &Request::get(Endpoint::ContainerLogsFollow("web".into()))).await;
+        let mut frames = vec![receive(&mut client).await];
+        while !frames.last().unwrap().end {
+            frames.push(receive(&mut client).await);
+        }
+
+        // The log of a stopped container, then a clean end
+        let lines: Vec<_> = frames.iter().filter_map(|f| f.data.as_ref()).map(|d| d["message"].clone()).collect();
+        assert_eq!(lines, ["one", "two"]);
+        assert!(frames.iter().all(|f| f.stream));
+        assert_eq!(frames.last().unwrap().status, status::OK);
+        wait_idle(&streams).await;
+
+        // The connection takes requests again
+        send(&mut client, &Request::get(Endpoint::Containers)).await;
+        let response = receive(&mut client).await;
+        assert!(response.is_success() && !response.stream);
+
+        task.abort();
+    }
+
+    #[tokio::test]
+    async fn test_requests_rejected_while_streaming() {
+        let dir = tempfile::tempdir().unwrap();
+        let (streams, socket, task) = serve(dir.path(), ContainerState::Running).await;
+        let mut client = Framed::new(UnixStream::connect(&socket).await.unwrap(), LinesCodec::new());
+
+        send(&mut client, &Request::get(Endpoint::ContainerLogsFollow("web".into()))).await;
+        assert!(receive(&mut client).await.stream);
+
+        send(&mut client, &Request::get(Endpoint::Containers)).await;
+        let busy = loop {
+            let response = receive(&mut client).await;
+            if !response.stream {
+                break response;
+            }
+        };
+        assert_eq!(busy.status, status::CONFLICT);
+        assert_eq!(busy.error.unwrap().code, "CONNECTION_BUSY_STREAMING");
+
+        // Other connections are unaffected
+        let mut other = Framed::new(UnixStream::connect(&socket).await.unwrap(), LinesCodec::new());
+        send(&mut other, &Request::get(Endpoint::Containers)).await;
+        assert!(receive(&mut other).await.is_success());
+        assert_eq!(streams.active().len(), 1);
+
+        task.abort();
+    }
+
+    #[tokio::test]
+    async fn test_client_drop_cancels_stream() {
+        let dir = tempfile::tempdir().unwrap();
+        let (streams, socket, task) = serve(dir.path(), ContainerState::Running).await;
+        let mut client = Framed::new(UnixStream::connect(&socket).await.unwrap(), LinesCodec::new());
+
+        send(&mut client, &Request::get(Endpoint::ContainerLogsFollow("web".into()))).await;
+        assert!(receive(&mut client).await.stream);
+        assert_eq!(streams.active().len(), 1);
+
+        drop(client);
+        wait_idle(&streams).await;
+
+        task.abort();
+    }
+
+    #[tokio::test]
+    async fn test_stream_refused_for_unknown_container() {
+        let dir = tempfile::tempdir().unwrap();
+        let (streams, socket, task) = serve(dir.path(), ContainerState::Running).await;
+        let mut client = Framed::new(UnixStream::connect(&socket).await.unwrap(), LinesCodec::new());
+
+        send(&mut client, &Request::get(Endpoint::ContainerLogsFollow("missing".into()))).await;
+        let response = receive(&mut client).await;
+        assert_eq!(response.status, status::NOT_FOUND);
+        assert!(!response.stream);
+        assert!(streams.active().is_empty());
+
+        task.abort();
+    }
 }
@@ -0,0 +1,357 @@
+//! Collection of snapshots nothing references
+//!
+//! Layer caching, commits and failed retags leave snapshots on image and
+//! container datasets that no record points at. They pin their space, and
+//! the dataset itself can't be destroyed while they exist. A run lists
+//! every snapshot under the managed roots in one `zfs list` pass and
+//! destroys, oldest first, those that aren't kept for one of these reasons:
+//!
+//! - a dataset was cloned from it (never destroyed, whatever else holds)
+//! - an image record points at it
+//! - it is on a build dataset, where it is a layer cache entry
+//! - it is a pre-upgrade snapshot younger than the retention
+//! - it is one of the newest `storage.keep_snapshots` of its dataset, the
+//!   same count a build keeps when it prunes
+//!
+//! Which snapshots go is [`select`], a pure function over the listing; the
+//! destroy loop in [`SnapshotGc::run`] goes through the same scope guard
+//! as orphan destroys, so nothing outside the managed roots is touched.
+
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::image::Image;
+use crate::timestamp::Timestamp;
+use crate::zfs::{DatasetInfo, Zfs, ZfsError};
+
+/// What a run keeps besides referenced and cloned snapshots
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Policy {
+    /// Newest snapshots kept on every dataset
+    pub keep_last: usize,
+    /// Pre-upgrade snapshots younger than this are kept
+    pub pre_upgrade_retention_secs: i64,
+}
+
+/// A snapshot a run destroys, or would destroy in a dry run
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Candidate {
+    /// Full name, `dataset@snapshot`
+    pub name: String,
+    pub used_bytes: u64,
+    pub created_at: Timestamp,
+}
+
+/// Outcome of a run
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotGcReport {
+    pub dry_run: bool,
+    /// Unreferenced snapshots, oldest first
+    pub candidates: Vec<Candidate>,
+    /// Candidates destroyed; 0 in a dry run
+    pub destroyed: usize,
+    /// Space the destroyed candidates used
+    pub reclaimed_bytes: u64,
+    /// Candidates that couldn't be destroyed, with the reason
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+}
+
+/// Snapshots the image records point at
+pub fn references<'a>(images: impl IntoIterator<Item = &'a Image>) -> HashSet<String> {
+    images
+        .into_iter()
+        .map(|image| image.snapshot.clone())
+        .filter(|snapshot| !snapshot.is_empty())
+        .collect()
+}
+
+/// Snapshots in `snapshots` that nothing keeps, oldest first
+///
+/// `snapshots` is a listing of the managed roots with the `clones` column;
+/// build datasets are `<images_root>/build-<name>`. `now` is Unix time.
+pub fn select(
+    snapshots: &[DatasetInfo],
+    references: &HashSet<String>,
+    images_root: &str,
+    policy: Policy,
+    now: i64,
+) -> Vec<DatasetInfo> {
+    let build_prefix = format!("{}/build-", images_root);
+
+    let mut by_dataset: HashMap<&str, Vec<&DatasetInfo>> = HashMap::new();
+    for snapshot in snapshots {
+        if let Some((dataset, _)) = snapshot.name.split_once('@') {
+            by_dataset.entry(dataset).or_default().push(snapshot);
+        }
+    }
+
+    let mut candidates = Vec::new();
+    for (dataset, mut snapshots) in by_dataset {
+        if dataset.starts_with(&build_prefix) {
+            continue;
+        }
+        // Newest first, so the first `keep_last` are the ones kept
+        snapshots.sort_by(|a, b| b.created_at.cmp(&a.created_at).then_with(|| b.name.cmp(&a.name)));
+        for snapshot in snapshots.into_iter().skip(policy.keep_last) {
+            let snap = snapshot.name.split_once('@').map_or("", |(_, snap)| snap);
+            let recent_upgrade = snap.starts_with(crate::upgrade::SNAPSHOT_PREFIX)
+                && now - snapshot.created_at < policy.pre_upgrade_retention_secs;
+            if snapshot.clones.is_empty() && !references.contains(&snapshot.name) && !recent_upgrade {
+                candidates.push(snapshot.clone());
+            }
+        }
+    }
+
+    candidates.sort_by(|a, b| a.created_at.cmp(&b.created_at).then_with(|| a.name.cmp(&b.name)));
+    candidates
+}
+
+/// Where a run lists and destroys snapshots
+pub trait SnapshotStore: Send {
+    /// Snapshots below `root` with their clones; none if `root` doesn't exist
+    fn snapshots(&self, root: &str) -> Result<Vec<DatasetInfo>, String>;
+    fn destroy(&self, snapshot: &str) -> Result<(), String>;
+}
+
+impl SnapshotStore for Zfs {
+    fn snapshots(&self, root: &str) -> Result<Vec<DatasetInfo>, String> {
+        match self.list_snapshots_with_clones(root) {
+            Ok(snapshots) => Ok(snapshots),
+            Err(ZfsError::DatasetNotFound(_)) => Ok(Vec::new()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    fn destroy(&self, snapshot: &str) -> Result<(), String> {
+        Zfs::destroy(self, snapshot).map_err(|e| e.to_string())
+    }
+}
+
+/// A run, planned under the manager lock and carried out without it
+pub struct SnapshotGc<S> {
+    pub store: S,
+    /// Managed roots; only snapshots of datasets below them are listed
+    pub roots: Vec<String>,
+    pub images_root: String,
+    pub references: HashSet<String>,
+    pub policy: Policy,
+}
+
+impl<S: SnapshotStore> SnapshotGc<S> {
+    /// List, select and (unless `dry_run`) destroy the unreferenced
+    /// snapshots; `now` is Unix time
+    ///
+    /// A snapshot that fails to destroy is reported and the run goes on.
+    pub fn run(&self, dry_run: bool, now: i64) -> Result<SnapshotGcReport, String> {
+        let mut snapshots = Vec::new();
+        for root in &self.roots {
+            snapshots.extend(self.store.snapshots(root)?);
+        }
+
+        let selected = select(&snapshots, &self.references, &self.images_root, self.policy, now);
+        let mut report = SnapshotGcReport {
+            dry_run,
+            candidates: selected
+                .iter()
+                .map(|snapshot| Candidate {
+                    name: snapshot.name.clone(),
+                    used_bytes: snapshot.used_bytes,
+                    created_at: Timestamp::from_secs(snapshot.created_at),
+                })
+                .collect(),
+            ..Default::default()
+        };
+        if dry_run {
+            return Ok(report);
+        }
+
+        let roots: Vec<&str> = self.roots.iter().map(String::as_str).collect();
+        for snapshot in &selected {
+            if let Err(e) = check_scope(&snapshot.name, &roots) {
+                report.errors.push(e);
+                continue;
+            }
+            match self.store.destroy(&snapshot.name) {
+                Ok(()) => {
+                    report.destroyed += 1;
+                    report.reclaimed_bytes += snapshot.used_bytes;
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to destroy snapshot {}: {}", snapshot.name, e);
+                    report.errors.push(format!("{}: {}", snapshot.name, e));
+                }
+            }
+        }
+
+        if report.destroyed > 0 {
+            tracing::warn!(
+                target: "kawakaze::audit",
+                destroyed = report.destroyed,
+                reclaimed_bytes = report.reclaimed_bytes,
+                "Collected unreferenced snapshots"
+            );
+        }
+        Ok(report)
+    }
+}
+
+/// Refuse anything but a named snapshot of a dataset below `roots`
+fn check_scope(snapshot: &str, roots: &[&str]) -> Result<(), String> {
+    match snapshot.split_once('@') {
+        Some((dataset, snap)) if !snap.is_empty() && !snap.contains('@') => {
+            crate::orphans::check_scope(dataset, roots).map_err(|e| e.to_string())
+        }
+        _ => Err(format!("Refusing to destroy '{}': not a snapshot", snapshot)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    const DAY: i64 = 86_400;
+    const NOW: i64 = 100 * DAY;
+
+    fn snap(name: &str, created_at: i64, clones: &[&str]) -> DatasetInfo {
+        DatasetInfo {
+            name: name.to_string(),
+            used_bytes: 1024,
+            created_at,
+            clones: clones.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    fn policy(keep_last: usize) -> Policy {
+        Policy { keep_last, pre_upgrade_retention_secs: 7 * DAY }
+    }
+
+    fn fixture() -> Vec<DatasetInfo> {
+        vec![
+            snap("tank/images/web@build-1", 10, &[]),
+            snap("tank/images/web@build-2", 20, &["tank/containers/1a2b3c4d"]),
+            snap("tank/images/web@commit-3", 30, &[]),
+            snap("tank/images/web@build-4", 40, &[]),
+            snap("tank/images/build-api@layer-1-abc", 5, &[]),
+            snap("tank/images/base@pre-upgrade-1", 50, &[]),
+            snap("tank/images/base@pre-upgrade-2", NOW - DAY, &[]),
+            snap("tank/images/base@build-1", 60, &[]),
+            snap("tank/containers/1a2b3c4d@manual", 15, &[]),
+        ]
+    }
+
+    fn names(selected: &[DatasetInfo]) -> Vec<&str> {
+        selected.iter().map(|s| s.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_select() {
+        let references: HashSet<String> =
+            ["tank/images/web@build-4".to_string(), "tank/images/base@build-1".to_string()].into();
+
+        let cases: &[(usize, &[&str])] = &[
+            (
+                0,
+                &["tank/images/web@build-1", "tank/containers/1a2b3c4d@manual", "tank/images/web@commit-3", "tank/images/base@pre-upgrade-1"],
+            ),
+            // The newest of each dataset is kept
+            (1, &["tank/images/web@build-1", "tank/images/web@commit-3", "tank/images/base@pre-upgrade-1"]),
+            (3, &["tank/images/web@build-1"]),
+            (4, &[]),
+        ];
+        for (keep_last, expected) in cases {
+            let selected = select(&fixture(), &references, "tank/images", policy(*keep_last), NOW);
+            assert_eq!(names(&selected), *expected, "keep_last {}", keep_last);
+        }
+    }
+
+    #[test]
+    fn test_select_pre_upgrade_retention() {
+        let references = HashSet::new();
+        let snapshots = [snap("tank/images/base@pre-upgrade-1", NOW - DAY, &[])];
+        assert!(select(&snapshots, &references, "tank/images", policy(0), NOW).is_empty());
+
+        let expired = Policy { keep_last: 0, pre_upgrade_retention_secs: DAY / 2 };
+        assert_eq!(names(&select(&snapshots, &references, "tank/images", expired, NOW)), ["tank/images/base@pre-upgrade-1"]);
+    }
+
+    #[test]
+    fn test_select_never_cloned() {
+        let references = HashSet::new();
+        let snapshots = [snap("tank/images/web@old", 1, &["tank/containers/1a2b3c4d", "tank/containers/5e6f7a8b"])];
+        assert!(select(&snapshots, &references, "tank/images", policy(0), NOW).is_empty());
+    }
+
+    #[test]
+    fn test_references() {
+        let image = |snapshot: &str| {
+            let mut image = Image::new("web".to_string(), Vec::new());
+            image.snapshot = snapshot.to_string();
+            image
+        };
+        let images = [image("tank/images/web@build-4"), image("")];
+        assert_eq!(references(&images), HashSet::from(["tank/images/web@build-4".to_string()]));
+    }
+
+    #[test]
+    fn test_check_scope() {
+        let roots = ["tank/images", "tank/containers"];
+        assert!(check_scope("tank/images/web@build-1", &roots).is_ok());
+        for name in ["tank/images/web", "tank/images/web@", "tank/images@snap", "tank/other/web@snap", "tank/images/../x@s"] {
+            assert!(check_scope(name, &roots).is_err(), "{}", name);
+        }
+    }
+
+    struct FakeStore {
+        snapshots: Vec<DatasetInfo>,
+        failing: Option<&'static str>,
+        destroyed: Mutex<Vec<String>>,
+    }
+
+    impl SnapshotStore for FakeStore {
+        fn snapshots(&self, root: &str) -> Result<Vec<DatasetInfo>, String> {
+            let prefix = format!("{}/", root);
+            Ok(self.snapshots.iter().filter(|s| s.name.starts_with(&prefix)).cloned().collect())
+        }
+
+        fn destroy(&self, snapshot: &str) -> Result<(), String> {
+            if self.failing == Some(snapshot) {
+                return Err("dataset is busy".to_string());
+            }
+            self.destroyed.lock().unwrap().push(snapshot.to_string());
+            Ok(())
+        }
+    }
+
+    fn gc(failing: Option<&'static str>) -> SnapshotGc<FakeStore> {
+        SnapshotGc {
+            store: FakeStore { snapshots: fixture(), failing, destroyed: Mutex::new(Vec::new()) },
+            roots: vec!["tank/containers".to_string(), "tank/images".to_string()],
+            images_root: "tank/images".to_string(),
+            references: HashSet::from(["tank/images/web@build-4".to_string(), "tank/images/base@build-1".to_string()]),
+            policy: policy(1),
+        }
+    }
+
+    #[test]
+    fn test_run_dry_run() {
+        let gc = gc(None);
+        let report = gc.run(true, NOW).unwrap();
+        assert!(report.dry_run);
+        assert_eq!(report.candidates.len(), 3);
+        assert_eq!(report.destroyed, 0);
+        assert!(gc.store.destroyed.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_run_destroys_oldest_first() {
+        let gc = gc(Some("tank/images/web@commit-3"));
+        let report = gc.run(false, NOW).unwrap();
+        assert_eq!(*gc.store.destroyed.lock().unwrap(), ["tank/images/web@build-1", "tank/images/base@pre-upgrade-1"]);
+        assert_eq!(report.destroyed, 2);
+        assert_eq!(report.reclaimed_bytes, 2048);
+        assert_eq!(report.errors, ["tank/images/web@commit-3: dataset is busy"]);
+    }
+}
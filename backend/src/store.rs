@@ -3,6 +3,7 @@
 //! This module provides database storage for jail configurations,
 //! allowing the jail manager to survive restarts and crashes.
 
+use crate::version::{self, Provenance};
 use rusqlite::{params, Connection};
 use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
@@ -16,6 +17,15 @@ pub struct JailRow {
     pub ip: Option<String>,
     pub state: String,
     pub jid: i32,
+    /// Userland version recorded by the last upgrade
+    pub os_version: Option<String>,
+    /// What the jail may mount itself (`jail::MountPermissions`)
+    pub mount_permissions: String,
+    /// Free-form notes from the operator
+    pub description: Option<String>,
+    /// Versions that created and last wrote the row; writes ignore it and
+    /// stamp the running version
+    pub provenance: Provenance,
 }
 
 /// Image state enumeration
@@ -104,6 +114,13 @@ pub struct Image {
     pub size_bytes: i64,
     pub state: ImageState,
     pub created_at: i64,
+    pub protected: bool,
+    pub kind: String,  // "user" or "intermediate"
+    pub os_version: Option<String>,
+    pub content_digest: Option<String>,
+    pub build_log: Option<String>,
+    pub digest: Option<String>,
+    pub provenance: Provenance,
 }
 
 /// Port mapping for containers
@@ -139,6 +156,183 @@ pub struct Container {
     pub command: Option<String>, // JSON serialized array of command strings
     pub created_at: i64,
     pub started_at: Option<i64>,
+    pub protected: bool,
+    pub devfs_ruleset: Option<u16>,
+    pub healthcheck_disabled: bool,
+    pub boot: bool,
+    pub encrypted: bool,
+    pub full_copy: bool,
+    pub read_only_root: bool,
+    pub network_aliases: String, // JSON serialized array of alias strings
+    pub hostname: Option<String>,
+    pub ip_aliases: String, // JSON serialized array of IPv4 and IPv6 addresses
+    pub ip6: Option<String>,
+    pub tmpfs: String, // JSON serialized array of TmpfsMount
+    pub mount_permissions: String,
+    pub stopped_at: Option<i64>,
+    pub last_stop_cause: Option<String>, // JSON serialized StopCause
+    pub env: String, // JSON serialized object of the container's own variables
+    pub placement: Option<String>, // JSON serialized Placement
+    pub sysctls: String, // JSON serialized object of sysctl names to values
+    pub description: Option<String>,
+    pub provenance: Provenance,
+}
+
+/// A host port published by a container
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublishedPort {
+    pub host_port: u16,
+    pub protocol: String,
+    pub container_id: String,
+}
+
+/// Columns selected for image rows, in `image_from_row` order
+const IMAGE_COLUMNS: &str =
+    "id, name, parent_id, snapshot, dockerfile, config, size_bytes, state, created_at, protected, kind, os_version, content_digest, build_log, digest, created_by_version, last_written_version";
+
+/// Columns selected for container rows, in `container_from_row` order
+const CONTAINER_COLUMNS: &str =
+    "id, name, image_id, jail_name, dataset, state, restart_policy, mounts, port_mappings, ip, command, created_at, started_at, protected, devfs_ruleset, healthcheck_disabled, boot, encrypted, full_copy, read_only_root, network_aliases, hostname, ip_aliases, created_by_version, last_written_version, ip6, tmpfs, mount_permissions, stopped_at, last_stop_cause, env, placement, sysctls, description";
+
+/// Columns added after the initial schema (table, column, definition)
+///
+/// Applied in order on startup to databases that predate them.
+const COLUMN_MIGRATIONS: &[(&str, &str, &str)] = &[
+    ("images", "protected", "INTEGER NOT NULL DEFAULT 0"),
+    ("containers", "protected", "INTEGER NOT NULL DEFAULT 0"),
+    ("containers", "devfs_ruleset", "INTEGER"),
+    ("images", "kind", "TEXT NOT NULL DEFAULT 'user'"),
+    ("jails", "os_version", "TEXT"),
+    ("images", "os_version", "TEXT"),
+    ("containers", "healthcheck_disabled", "INTEGER NOT NULL DEFAULT 0"),
+    ("containers", "boot", "INTEGER NOT NULL DEFAULT 0"),
+    ("images", "content_digest", "TEXT"),
+    ("containers", "encrypted", "INTEGER NOT NULL DEFAULT 0"),
+    ("containers", "full_copy", "INTEGER NOT NULL DEFAULT 0"),
+    ("images", "build_log", "TEXT"),
+    ("containers", "read_only_root", "INTEGER NOT NULL DEFAULT 0"),
+    ("images", "digest", "TEXT"),
+    ("containers", "network_aliases", "TEXT NOT NULL DEFAULT '[]'"),
+    ("jails", "created_by_version", "TEXT"),
+    ("jails", "last_written_version", "TEXT"),
+    ("images", "created_by_version", "TEXT"),
+    ("images", "last_written_version", "TEXT"),
+    ("containers", "created_by_version", "TEXT"),
+    ("containers", "last_written_version", "TEXT"),
+    ("containers", "hostname", "TEXT"),
+    ("containers", "ip_aliases", "TEXT NOT NULL DEFAULT '[]'"),
+    ("containers", "ip6", "TEXT"),
+    ("containers", "tmpfs", "TEXT NOT NULL DEFAULT '[]'"),
+    ("jails", "mount_permissions", "TEXT NOT NULL DEFAULT 'none'"),
+    ("containers", "mount_permissions", "TEXT NOT NULL DEFAULT 'none'"),
+    ("containers", "stopped_at", "INTEGER"),
+    ("containers", "last_stop_cause", "TEXT"),
+    ("containers", "env", "TEXT NOT NULL DEFAULT '{}'"),
+    ("containers", "placement", "TEXT"),
+    ("containers", "sysctls", "TEXT NOT NULL DEFAULT '{}'"),
+    ("jails", "description", "TEXT"),
+    ("containers", "description", "TEXT"),
+];
+
+/// Tables whose rows record the kawakaze version that wrote them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VersionedTable {
+    Jails,
+    Images,
+    Containers,
+}
+
+impl VersionedTable {
+    fn table(self) -> &'static str {
+        match self {
+            VersionedTable::Jails => "jails",
+            VersionedTable::Images => "images",
+            VersionedTable::Containers => "containers",
+        }
+    }
+
+    fn key(self) -> &'static str {
+        match self {
+            VersionedTable::Jails => "name",
+            VersionedTable::Images | VersionedTable::Containers => "id",
+        }
+    }
+
+    /// What a row of the table is, for messages
+    pub fn kind(self) -> &'static str {
+        match self {
+            VersionedTable::Jails => "Jail",
+            VersionedTable::Images => "Image",
+            VersionedTable::Containers => "Container",
+        }
+    }
+}
+
+/// Build an image row from a query using `IMAGE_COLUMNS`
+fn image_from_row(row: &rusqlite::Row) -> rusqlite::Result<Image> {
+    let state_str: String = row.get(7)?;
+    let state = ImageState::from_str(&state_str)
+        .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+    Ok(Image {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        parent_id: row.get(2)?,
+        snapshot: row.get(3)?,
+        dockerfile: row.get(4)?,
+        config: row.get(5)?,
+        size_bytes: row.get(6)?,
+        state,
+        created_at: row.get(8)?,
+        protected: row.get(9)?,
+        kind: row.get(10)?,
+        os_version: row.get(11)?,
+        content_digest: row.get(12)?,
+        build_log: row.get(13)?,
+        digest: row.get(14)?,
+        provenance: Provenance { created_by: row.get(15)?, last_written: row.get(16)? },
+    })
+}
+
+/// Build a container row from a query using `CONTAINER_COLUMNS`
+fn container_from_row(row: &rusqlite::Row) -> rusqlite::Result<Container> {
+    let state_str: String = row.get(5)?;
+    let state = ContainerState::from_str(&state_str)
+        .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+    Ok(Container {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        image_id: row.get(2)?,
+        jail_name: row.get(3)?,
+        dataset: row.get(4)?,
+        state,
+        restart_policy: row.get(6)?,
+        mounts: row.get(7)?,
+        port_mappings: row.get(8)?,
+        ip: row.get(9)?,
+        command: row.get(10)?,
+        created_at: row.get(11)?,
+        started_at: row.get(12)?,
+        protected: row.get(13)?,
+        devfs_ruleset: row.get(14)?,
+        healthcheck_disabled: row.get(15)?,
+        boot: row.get(16)?,
+        encrypted: row.get(17)?,
+        full_copy: row.get(18)?,
+        read_only_root: row.get(19)?,
+        network_aliases: row.get(20)?,
+        hostname: row.get(21)?,
+        ip_aliases: row.get(22)?,
+        ip6: row.get(25)?,
+        tmpfs: row.get(26)?,
+        mount_permissions: row.get(27)?,
+        stopped_at: row.get(28)?,
+        last_stop_cause: row.get(29)?,
+        env: row.get(30)?,
+        placement: row.get(31)?,
+        sysctls: row.get(32)?,
+        description: row.get(33)?,
+        provenance: Provenance { created_by: row.get(23)?, last_written: row.get(24)? },
+    })
 }
 
 /// Store error type
@@ -147,6 +341,16 @@ pub enum StoreError {
     DatabaseError(rusqlite::Error),
     InvalidState(String),
     SerializationError(String),
+    /// The resource is protected and the removal did not override it
+    ResourceProtected(String),
+    /// A host port to publish is taken by the host or another container
+    PortInUse(String),
+    /// The record was last written by a newer kawakaze (see `version`)
+    VersionSkew(String),
+    /// The daemon runs inside a jail that doesn't allow this
+    Unavailable(String),
+    /// The container's dataset was destroyed outside kawakaze
+    DatasetMissing(String),
 }
 
 impl std::fmt::Display for StoreError {
@@ -155,6 +359,11 @@ impl std::fmt::Display for StoreError {
             StoreError::DatabaseError(e) => write!(f, "Database error: {}", e),
             StoreError::InvalidState(msg) => write!(f, "Invalid state: {}", msg),
             StoreError::SerializationError(msg) => write!(f, "Serialization error: {}", msg),
+            StoreError::ResourceProtected(msg) => write!(f, "Resource protected: {}", msg),
+            StoreError::PortInUse(msg) => write!(f, "{}", msg),
+            StoreError::VersionSkew(msg) => write!(f, "{}", msg),
+            StoreError::Unavailable(msg) => write!(f, "{}", msg),
+            StoreError::DatasetMissing(msg) => write!(f, "{}", msg),
         }
     }
 }
@@ -287,22 +496,93 @@ impl JailStore {
             [],
         )?;
 
+        // Host ports held by running containers, one owner per port
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS published_ports (
+                host_port INTEGER NOT NULL,
+                protocol TEXT NOT NULL,
+                container_id TEXT NOT NULL,
+                PRIMARY KEY (host_port, protocol)
+            )",
+            [],
+        )?;
+
+        Self::migrate(&conn)?;
+
         debug!("Database initialized at {:?}", self.db_path);
         Ok(())
     }
 
+    /// Add columns introduced after the initial schema to existing databases
+    fn migrate(conn: &Connection) -> Result<(), StoreError> {
+        for (table, column, definition) in COLUMN_MIGRATIONS {
+            if !Self::has_column(conn, table, column)? {
+                conn.execute(
+                    &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+                    [],
+                )?;
+                debug!("Migrated database: added {}.{}", table, column);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn has_column(conn: &Connection, table: &str, column: &str) -> Result<bool, StoreError> {
+        let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+        let exists = stmt
+            .query_map([], |row| row.get::<_, String>(1))?
+            .filter_map(|name| name.ok())
+            .any(|name| name == column);
+        Ok(exists)
+    }
+
+    /// Columns (`table.column`) a database at `db_path` still lacks
+    ///
+    /// Opens the file read-only, so it neither creates nor migrates it.
+    /// Empty when the schema is current.
+    pub fn missing_columns(db_path: impl AsRef<Path>) -> Result<Vec<String>, StoreError> {
+        let conn = Connection::open_with_flags(db_path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+
+        let mut missing = Vec::new();
+        for (table, column, _) in COLUMN_MIGRATIONS {
+            if !Self::has_column(&conn, table, column)? {
+                missing.push(format!("{}.{}", table, column));
+            }
+        }
+        Ok(missing)
+    }
+
+    /// Stamp a row as last written by the running version, taking over a
+    /// row a newer version wrote
+    pub fn restamp(&self, table: VersionedTable, key: &str) -> Result<(), StoreError> {
+        let conn = Connection::open(&self.db_path)?;
+
+        conn.execute(
+            &format!("UPDATE {} SET last_written_version = ?1 WHERE {} = ?2", table.table(), table.key()),
+            params![version::CURRENT, key],
+        )?;
+
+        debug!("Restamped {} row '{}' with version {}", table.table(), key, version::CURRENT);
+        Ok(())
+    }
+
     /// Insert a new jail into the database
     pub fn insert_jail(&self, jail: &JailRow) -> Result<(), StoreError> {
         let conn = Connection::open(&self.db_path)?;
 
         conn.execute(
-            "INSERT INTO jails (name, path, ip, state, jid) VALUES (?1, ?2, ?3, ?4, ?5)",
+            "INSERT INTO jails (name, path, ip, state, jid, os_version, created_by_version, last_written_version, mount_permissions, description) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?7, ?8, ?9)",
             params![
                 &jail.name,
                 &jail.path,
                 &jail.ip,
                 &jail.state,
                 &jail.jid,
+                &jail.os_version,
+                version::CURRENT,
+                &jail.mount_permissions,
+                &jail.description,
             ],
         )?;
 
@@ -315,12 +595,16 @@ impl JailStore {
         let conn = Connection::open(&self.db_path)?;
 
         conn.execute(
-            "UPDATE jails SET path = ?1, ip = ?2, state = ?3, jid = ?4, updated_at = strftime('%s', 'now') WHERE name = ?5",
+            "UPDATE jails SET path = ?1, ip = ?2, state = ?3, jid = ?4, os_version = ?5, last_written_version = ?6, mount_permissions = ?7, description = ?8, updated_at = strftime('%s', 'now') WHERE name = ?9",
             params![
                 &jail.path,
                 &jail.ip,
                 &jail.state,
                 &jail.jid,
+                &jail.os_version,
+                version::CURRENT,
+                &jail.mount_permissions,
+                &jail.description,
                 &jail.name,
             ],
         )?;
@@ -349,7 +633,7 @@ impl JailStore {
         let conn = Connection::open(&self.db_path)?;
 
         let mut stmt = conn.prepare(
-            "SELECT name, path, ip, state, jid FROM jails"
+            "SELECT name, path, ip, state, jid, os_version, created_by_version, last_written_version, mount_permissions, description FROM jails"
         )?;
 
         let jail_iter = stmt.query_map([], |row| {
@@ -359,6 +643,10 @@ impl JailStore {
                 ip: row.get(2)?,
                 state: row.get(3)?,
                 jid: row.get(4)?,
+                os_version: row.get(5)?,
+                mount_permissions: row.get(8)?,
+                description: row.get(9)?,
+                provenance: Provenance { created_by: row.get(6)?, last_written: row.get(7)? },
             })
         })?;
 
@@ -376,7 +664,7 @@ impl JailStore {
         let conn = Connection::open(&self.db_path)?;
 
         let mut stmt = conn.prepare(
-            "SELECT name, path, ip, state, jid FROM jails WHERE name = ?1"
+            "SELECT name, path, ip, state, jid, os_version, created_by_version, last_written_version, mount_permissions, description FROM jails WHERE name = ?1"
         )?;
 
         let jail_iter = stmt.query_map(params![name], |row| {
@@ -386,6 +674,10 @@ impl JailStore {
                 ip: row.get(2)?,
                 state: row.get(3)?,
                 jid: row.get(4)?,
+                os_version: row.get(5)?,
+                mount_permissions: row.get(8)?,
+                description: row.get(9)?,
+                provenance: Provenance { created_by: row.get(6)?, last_written: row.get(7)? },
             })
         })?;
 
@@ -408,8 +700,8 @@ impl JailStore {
         let conn = Connection::open(&self.db_path)?;
 
         conn.execute(
-            "INSERT INTO images (id, name, parent_id, snapshot, dockerfile, config, size_bytes, state, created_at)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            "INSERT INTO images (id, name, parent_id, snapshot, dockerfile, config, size_bytes, state, created_at, protected, kind, os_version, content_digest, build_log, digest, created_by_version, last_written_version)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?16)",
             params![
                 &image.id,
                 &image.name,
@@ -420,6 +712,13 @@ impl JailStore {
                 &image.size_bytes,
                 image.state.as_str(),
                 &image.created_at,
+                &image.protected,
+                &image.kind,
+                &image.os_version,
+                &image.content_digest,
+                &image.build_log,
+                &image.digest,
+                version::CURRENT,
             ],
         )?;
 
@@ -432,26 +731,10 @@ impl JailStore {
         let conn = Connection::open(&self.db_path)?;
 
         let mut stmt = conn.prepare(
-            "SELECT id, name, parent_id, snapshot, dockerfile, config, size_bytes, state, created_at
-             FROM images WHERE id = ?1"
-        )?;
-
-        let image_iter = stmt.query_map(params![id], |row| {
-            let state_str: String = row.get(7)?;
-            let state = ImageState::from_str(&state_str)
-                .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
-            Ok(Image {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                parent_id: row.get(2)?,
-                snapshot: row.get(3)?,
-                dockerfile: row.get(4)?,
-                config: row.get(5)?,
-                size_bytes: row.get(6)?,
-                state,
-                created_at: row.get(8)?,
-            })
-        })?;
+            &format!("SELECT {} FROM images WHERE id = ?1", IMAGE_COLUMNS)
+        )?;
+
+        let image_iter = stmt.query_map(params![id], image_from_row)?;
 
         for image in image_iter {
             return Ok(Some(image?));
@@ -465,26 +748,10 @@ impl JailStore {
         let conn = Connection::open(&self.db_path)?;
 
         let mut stmt = conn.prepare(
-            "SELECT id, name, parent_id, snapshot, dockerfile, config, size_bytes, state, created_at
-             FROM images WHERE name = ?1"
-        )?;
-
-        let image_iter = stmt.query_map(params![name], |row| {
-            let state_str: String = row.get(7)?;
-            let state = ImageState::from_str(&state_str)
-                .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
-            Ok(Image {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                parent_id: row.get(2)?,
-                snapshot: row.get(3)?,
-                dockerfile: row.get(4)?,
-                config: row.get(5)?,
-                size_bytes: row.get(6)?,
-                state,
-                created_at: row.get(8)?,
-            })
-        })?;
+            &format!("SELECT {} FROM images WHERE name = ?1", IMAGE_COLUMNS)
+        )?;
+
+        let image_iter = stmt.query_map(params![name], image_from_row)?;
 
         for image in image_iter {
             return Ok(Some(image?));
@@ -498,26 +765,10 @@ impl JailStore {
         let conn = Connection::open(&self.db_path)?;
 
         let mut stmt = conn.prepare(
-            "SELECT id, name, parent_id, snapshot, dockerfile, config, size_bytes, state, created_at
-             FROM images"
-        )?;
-
-        let image_iter = stmt.query_map([], |row| {
-            let state_str: String = row.get(7)?;
-            let state = ImageState::from_str(&state_str)
-                .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
-            Ok(Image {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                parent_id: row.get(2)?,
-                snapshot: row.get(3)?,
-                dockerfile: row.get(4)?,
-                config: row.get(5)?,
-                size_bytes: row.get(6)?,
-                state,
-                created_at: row.get(8)?,
-            })
-        })?;
+            &format!("SELECT {} FROM images", IMAGE_COLUMNS)
+        )?;
+
+        let image_iter = stmt.query_map([], image_from_row)?;
 
         let mut images = Vec::new();
         for image in image_iter {
@@ -533,8 +784,8 @@ impl JailStore {
         let conn = Connection::open(&self.db_path)?;
 
         let rows_affected = conn.execute(
-            "UPDATE images SET state = ?1 WHERE id = ?2",
-            params![state.as_str(), id],
+            "UPDATE images SET state = ?1, last_written_version = ?2 WHERE id = ?3",
+            params![state.as_str(), version::CURRENT, id],
         )?;
 
         if rows_affected == 0 {
@@ -561,6 +812,85 @@ impl JailStore {
         Ok(())
     }
 
+    /// Set whether an image is protected from removal
+    pub fn set_image_protected(&self, id: &str, protected: bool) -> Result<(), StoreError> {
+        let conn = Connection::open(&self.db_path)?;
+
+        let rows_affected = conn.execute(
+            "UPDATE images SET protected = ?1, last_written_version = ?2 WHERE id = ?3",
+            params![protected, version::CURRENT, id],
+        )?;
+
+        if rows_affected == 0 {
+            warn!("Attempted to update non-existent image '{}' in database", id);
+        } else {
+            debug!("Set image '{}' protected={} in database", id, protected);
+        }
+
+        Ok(())
+    }
+
+    /// Insert a newly built image, first untagging the images in `untag`
+    ///
+    /// Both happen in one transaction, so the name is never on two images
+    /// or on none.
+    pub fn retag_image(&self, image: &Image, untag: &[String]) -> Result<(), StoreError> {
+        let mut conn = Connection::open(&self.db_path)?;
+        let tx = conn.transaction()?;
+
+        for id in untag {
+            tx.execute(
+                "UPDATE images SET name = id, kind = 'untagged', last_written_version = ?1 WHERE id = ?2",
+                params![version::CURRENT, id],
+            )?;
+        }
+
+        tx.execute(
+            "INSERT INTO images (id, name, parent_id, snapshot, dockerfile, config, size_bytes, state, created_at, protected, kind, os_version, content_digest, build_log, digest, created_by_version, last_written_version)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?16)",
+            params![
+                &image.id,
+                &image.name,
+                &image.parent_id,
+                &image.snapshot,
+                &image.dockerfile,
+                &image.config,
+                &image.size_bytes,
+                image.state.as_str(),
+                &image.created_at,
+                &image.protected,
+                &image.kind,
+                &image.os_version,
+                &image.content_digest,
+                &image.build_log,
+                &image.digest,
+                version::CURRENT,
+            ],
+        )?;
+
+        tx.commit()?;
+        debug!("Tagged image '{}' as '{}', untagged {:?}", image.id, image.name, untag);
+        Ok(())
+    }
+
+    /// Record an upgraded image's new snapshot and userland version
+    pub fn set_image_upgraded(&self, id: &str, snapshot: &str, os_version: &str) -> Result<(), StoreError> {
+        let conn = Connection::open(&self.db_path)?;
+
+        let rows_affected = conn.execute(
+            "UPDATE images SET snapshot = ?1, os_version = ?2, last_written_version = ?3 WHERE id = ?4",
+            params![snapshot, os_version, version::CURRENT, id],
+        )?;
+
+        if rows_affected == 0 {
+            warn!("Attempted to update non-existent image '{}' in database", id);
+        } else {
+            debug!("Set image '{}' snapshot={} os_version={} in database", id, snapshot, os_version);
+        }
+
+        Ok(())
+    }
+
     // ========== Container Methods ==========
 
     /// Insert a new container into the database
@@ -568,8 +898,8 @@ impl JailStore {
         let conn = Connection::open(&self.db_path)?;
 
         conn.execute(
-            "INSERT INTO containers (id, name, image_id, jail_name, dataset, state, restart_policy, mounts, port_mappings, ip, command, created_at, started_at)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+            "INSERT INTO containers (id, name, image_id, jail_name, dataset, state, restart_policy, mounts, port_mappings, ip, command, created_at, started_at, protected, devfs_ruleset, healthcheck_disabled, boot, encrypted, full_copy, read_only_root, network_aliases, hostname, ip_aliases, created_by_version, last_written_version, ip6, tmpfs, mount_permissions, stopped_at, last_stop_cause, env, placement, sysctls, description)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?24, ?25, ?26, ?27, ?28, ?29, ?30, ?31, ?32, ?33)",
             params![
                 &container.id,
                 &container.name,
@@ -584,6 +914,26 @@ impl JailStore {
                 &container.command,
                 &container.created_at,
                 &container.started_at,
+                &container.protected,
+                &container.devfs_ruleset,
+                &container.healthcheck_disabled,
+                &container.boot,
+                &container.encrypted,
+                &container.full_copy,
+                &container.read_only_root,
+                &container.network_aliases,
+                &container.hostname,
+                &container.ip_aliases,
+                version::CURRENT,
+                &container.ip6,
+                &container.tmpfs,
+                &container.mount_permissions,
+                &container.stopped_at,
+                &container.last_stop_cause,
+                &container.env,
+                &container.placement,
+                &container.sysctls,
+                &container.description,
             ],
         )?;
 
@@ -596,30 +946,10 @@ impl JailStore {
         let conn = Connection::open(&self.db_path)?;
 
         let mut stmt = conn.prepare(
-            "SELECT id, name, image_id, jail_name, dataset, state, restart_policy, mounts, port_mappings, ip, command, created_at, started_at
-             FROM containers WHERE id = ?1"
-        )?;
-
-        let container_iter = stmt.query_map(params![id], |row| {
-            let state_str: String = row.get(5)?;
-            let state = ContainerState::from_str(&state_str)
-                .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
-            Ok(Container {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                image_id: row.get(2)?,
-                jail_name: row.get(3)?,
-                dataset: row.get(4)?,
-                state,
-                restart_policy: row.get(6)?,
-                mounts: row.get(7)?,
-                port_mappings: row.get(8)?,
-                ip: row.get(9)?,
-                command: row.get(10)?,
-                created_at: row.get(11)?,
-                started_at: row.get(12)?,
-            })
-        })?;
+            &format!("SELECT {} FROM containers WHERE id = ?1", CONTAINER_COLUMNS)
+        )?;
+
+        let container_iter = stmt.query_map(params![id], container_from_row)?;
 
         for container in container_iter {
             return Ok(Some(container?));
@@ -633,30 +963,10 @@ impl JailStore {
         let conn = Connection::open(&self.db_path)?;
 
         let mut stmt = conn.prepare(
-            "SELECT id, name, image_id, jail_name, dataset, state, restart_policy, mounts, port_mappings, ip, command, created_at, started_at
-             FROM containers WHERE name = ?1"
-        )?;
-
-        let container_iter = stmt.query_map(params![name], |row| {
-            let state_str: String = row.get(5)?;
-            let state = ContainerState::from_str(&state_str)
-                .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
-            Ok(Container {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                image_id: row.get(2)?,
-                jail_name: row.get(3)?,
-                dataset: row.get(4)?,
-                state,
-                restart_policy: row.get(6)?,
-                mounts: row.get(7)?,
-                port_mappings: row.get(8)?,
-                ip: row.get(9)?,
-                command: row.get(10)?,
-                created_at: row.get(11)?,
-                started_at: row.get(12)?,
-            })
-        })?;
+            &format!("SELECT {} FROM containers WHERE name = ?1", CONTAINER_COLUMNS)
+        )?;
+
+        let container_iter = stmt.query_map(params![name], container_from_row)?;
 
         for container in container_iter {
             return Ok(Some(container?));
@@ -670,30 +980,10 @@ impl JailStore {
         let conn = Connection::open(&self.db_path)?;
 
         let mut stmt = conn.prepare(
-            "SELECT id, name, image_id, jail_name, dataset, state, restart_policy, mounts, port_mappings, ip, command, created_at, started_at
-             FROM containers"
-        )?;
-
-        let container_iter = stmt.query_map([], |row| {
-            let state_str: String = row.get(5)?;
-            let state = ContainerState::from_str(&state_str)
-                .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
-            Ok(Container {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                image_id: row.get(2)?,
-                jail_name: row.get(3)?,
-                dataset: row.get(4)?,
-                state,
-                restart_policy: row.get(6)?,
-                mounts: row.get(7)?,
-                port_mappings: row.get(8)?,
-                ip: row.get(9)?,
-                command: row.get(10)?,
-                created_at: row.get(11)?,
-                started_at: row.get(12)?,
-            })
-        })?;
+            &format!("SELECT {} FROM containers", CONTAINER_COLUMNS)
+        )?;
+
+        let container_iter = stmt.query_map([], container_from_row)?;
 
         let mut containers = Vec::new();
         for container in container_iter {
@@ -704,13 +994,27 @@ impl JailStore {
         Ok(containers)
     }
 
+    /// Containers created from an image
+    pub fn containers_for_image(&self, image_id: &str) -> Result<Vec<Container>, StoreError> {
+        let conn = Connection::open(&self.db_path)?;
+
+        let mut stmt = conn.prepare(
+            &format!("SELECT {} FROM containers WHERE image_id = ?1 ORDER BY created_at, id", CONTAINER_COLUMNS)
+        )?;
+
+        let containers = stmt
+            .query_map(params![image_id], container_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(containers)
+    }
+
     /// Update a container's state
     pub fn update_container(&self, id: &str, state: ContainerState) -> Result<(), StoreError> {
         let conn = Connection::open(&self.db_path)?;
 
         let rows_affected = conn.execute(
-            "UPDATE containers SET state = ?1 WHERE id = ?2",
-            params![state.as_str(), id],
+            "UPDATE containers SET state = ?1, last_written_version = ?2 WHERE id = ?3",
+            params![state.as_str(), version::CURRENT, id],
         )?;
 
         if rows_affected == 0 {
@@ -722,6 +1026,133 @@ impl JailStore {
         Ok(())
     }
 
+    /// Record that a container stopped at `stopped_at` (Unix seconds) for
+    /// `cause` (a JSON serialized `StopCause`)
+    pub fn record_container_stop(&self, id: &str, stopped_at: i64, cause: &str) -> Result<(), StoreError> {
+        let conn = Connection::open(&self.db_path)?;
+
+        let rows_affected = conn.execute(
+            "UPDATE containers SET state = ?1, stopped_at = ?2, last_stop_cause = ?3, last_written_version = ?4 WHERE id = ?5",
+            params![ContainerState::Stopped.as_str(), stopped_at, cause, version::CURRENT, id],
+        )?;
+
+        if rows_affected == 0 {
+            warn!("Attempted to record the stop of non-existent container '{}' in database", id);
+        } else {
+            debug!("Recorded container '{}' stopped: {}", id, cause);
+        }
+
+        Ok(())
+    }
+
+    /// Rename a container
+    pub fn set_container_name(&self, id: &str, name: &str) -> Result<(), StoreError> {
+        let conn = Connection::open(&self.db_path)?;
+
+        let rows_affected = conn.execute(
+            "UPDATE containers SET name = ?1, last_written_version = ?2 WHERE id = ?3",
+            params![name, version::CURRENT, id],
+        )?;
+
+        if rows_affected == 0 {
+            warn!("Attempted to update non-existent container '{}' in database", id);
+        } else {
+            debug!("Renamed container '{}' to '{}' in database", id, name);
+        }
+
+        Ok(())
+    }
+
+    /// Set a container's `ip4.addr` aliases (JSON array)
+    pub fn set_container_ip_aliases(&self, id: &str, ip_aliases: &str) -> Result<(), StoreError> {
+        let conn = Connection::open(&self.db_path)?;
+
+        let rows_affected = conn.execute(
+            "UPDATE containers SET ip_aliases = ?1, last_written_version = ?2 WHERE id = ?3",
+            params![ip_aliases, version::CURRENT, id],
+        )?;
+
+        if rows_affected == 0 {
+            warn!("Attempted to update non-existent container '{}' in database", id);
+        } else {
+            debug!("Set IP aliases of container '{}' to {} in database", id, ip_aliases);
+        }
+
+        Ok(())
+    }
+
+    /// Set whether a container is protected from removal
+    pub fn set_container_protected(&self, id: &str, protected: bool) -> Result<(), StoreError> {
+        let conn = Connection::open(&self.db_path)?;
+
+        let rows_affected = conn.execute(
+            "UPDATE containers SET protected = ?1, last_written_version = ?2 WHERE id = ?3",
+            params![protected, version::CURRENT, id],
+        )?;
+
+        if rows_affected == 0 {
+            warn!("Attempted to update non-existent container '{}' in database", id);
+        } else {
+            debug!("Set container '{}' protected={} in database", id, protected);
+        }
+
+        Ok(())
+    }
+
+    /// Set whether a container's root is read-only while it runs
+    pub fn set_container_read_only_root(&self, id: &str, read_only_root: bool) -> Result<(), StoreError> {
+        let conn = Connection::open(&self.db_path)?;
+
+        let rows_affected = conn.execute(
+            "UPDATE containers SET read_only_root = ?1, last_written_version = ?2 WHERE id = ?3",
+            params![read_only_root, version::CURRENT, id],
+        )?;
+
+        if rows_affected == 0 {
+            warn!("Attempted to update non-existent container '{}' in database", id);
+        } else {
+            debug!("Set container '{}' read_only_root={} in database", id, read_only_root);
+        }
+
+        Ok(())
+    }
+
+    /// Set whether a container is started at boot
+    pub fn set_container_boot(&self, id: &str, boot: bool) -> Result<(), StoreError> {
+        let conn = Connection::open(&self.db_path)?;
+
+        let rows_affected = conn.execute(
+            "UPDATE containers SET boot = ?1, last_written_version = ?2 WHERE id = ?3",
+            params![boot, version::CURRENT, id],
+        )?;
+
+        if rows_affected == 0 {
+            warn!("Attempted to update non-existent container '{}' in database", id);
+        } else {
+            debug!("Set container '{}' boot={} in database", id, boot);
+        }
+
+        Ok(())
+    }
+
+    /// Set or clear a container's description
+    pub fn set_container_description(&self, id: &str, description: Option<&str>) -> Result<(), StoreError> {
+        let conn = Connection::open(&self.db_path)?;
+
+        let rows_affected = conn.execute(
+            "UPDATE containers SET description = ?1, last_written_version = ?2 WHERE id = ?3",
+            params![description, version::CURRENT, id],
+        )?;
+
+        if rows_affected == 0 {
+            warn!("Attempted to update non-existent container '{}' in database", id);
+        } else {
+            debug!("Set container '{}' description in database", id);
+        }
+
+        Ok(())
+    }
+
     /// Delete a container from the database
     pub fn delete_container(&self, id: &str) -> Result<(), StoreError> {
         let conn = Connection::open(&self.db_path)?;
@@ -736,6 +1167,41 @@ impl JailStore {
 
         Ok(())
     }
+
+    /// Replace the host ports published by a container; empty releases them
+    pub fn set_published_ports(&self, container_id: &str, ports: &[(u16, &str)]) -> Result<(), StoreError> {
+        let mut conn = Connection::open(&self.db_path)?;
+        let tx = conn.transaction()?;
+
+        tx.execute("DELETE FROM published_ports WHERE container_id = ?1", params![container_id])?;
+        for (port, protocol) in ports {
+            tx.execute(
+                "INSERT INTO published_ports (host_port, protocol, container_id) VALUES (?1, ?2, ?3)",
+                params![port, protocol, container_id],
+            )?;
+        }
+
+        tx.commit()?;
+        debug!("Container '{}' publishes {} host ports", container_id, ports.len());
+        Ok(())
+    }
+
+    /// All published host ports
+    pub fn list_published_ports(&self) -> Result<Vec<PublishedPort>, StoreError> {
+        let conn = Connection::open(&self.db_path)?;
+
+        let mut stmt = conn.prepare("SELECT host_port, protocol, container_id FROM published_ports ORDER BY host_port, protocol")?;
+        let ports = stmt
+            .query_map([], |row| {
+                Ok(PublishedPort {
+                    host_port: row.get(0)?,
+                    protocol: row.get(1)?,
+                    container_id: row.get(2)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(ports)
+    }
 }
 
 #[cfg(test)]
@@ -764,6 +1230,10 @@ mod tests {
             ip: Some("192.168.1.1".to_string()),
             state: "created".to_string(),
             jid: -1,
+            os_version: None,
+            mount_permissions: "none".to_string(),
+            description: None,
+            provenance: Provenance::default(),
         };
 
         store.insert_jail(&jail).unwrap();
@@ -786,6 +1256,10 @@ mod tests {
             ip: None,
             state: "created".to_string(),
             jid: -1,
+            os_version: None,
+            mount_permissions: "none".to_string(),
+            description: None,
+            provenance: Provenance::default(),
         };
 
         store.insert_jail(&jail).unwrap();
@@ -799,6 +1273,18 @@ mod tests {
         let retrieved = store.get_jail("test_jail").unwrap().unwrap();
         assert_eq!(retrieved.state, "running");
         assert_eq!(retrieved.jid, 123);
+        assert_eq!(retrieved.description, None);
+
+        // The description is written with the rest of the row, and cleared
+        updated.description = Some("owned by team-payments\ndecommission after Q3".to_string());
+        store.update_jail(&updated).unwrap();
+        let retrieved = store.get_jail("test_jail").unwrap().unwrap();
+        assert_eq!(retrieved.description.as_deref(), Some("owned by team-payments\ndecommission after Q3"));
+        assert_eq!(store.get_all_jails().unwrap()[0].description, retrieved.description);
+
+        updated.description = None;
+        store.update_jail(&updated).unwrap();
+        assert_eq!(store.get_jail("test_jail").unwrap().unwrap().description, None);
     }
 
     #[test]
@@ -811,6 +1297,10 @@ mod tests {
             ip: None,
             state: "created".to_string(),
             jid: -1,
+            os_version: None,
+            mount_permissions: "none".to_string(),
+            description: None,
+            provenance: Provenance::default(),
         };
 
         store.insert_jail(&jail).unwrap();
@@ -830,6 +1320,10 @@ mod tests {
             ip: None,
             state: "created".to_string(),
             jid: -1,
+            os_version: None,
+            mount_permissions: "none".to_string(),
+            description: None,
+            provenance: Provenance::default(),
         };
 
         let jail2 = JailRow {
@@ -838,6 +1332,10 @@ mod tests {
             ip: Some("10.0.0.1".to_string()),
             state: "running".to_string(),
             jid: 100,
+            os_version: None,
+            mount_permissions: "none".to_string(),
+            description: None,
+            provenance: Provenance::default(),
         };
 
         store.insert_jail(&jail1).unwrap();
@@ -861,6 +1359,10 @@ mod tests {
             ip: None,
             state: "created".to_string(),
             jid: -1,
+            os_version: None,
+            mount_permissions: "none".to_string(),
+            description: None,
+            provenance: Provenance::default(),
         };
 
         store.insert_jail(&jail).unwrap();
@@ -892,6 +1394,10 @@ mod tests {
             ip: Some("192.168.1.1".to_string()),
             state: "created".to_string(),
             jid: -1,
+            os_version: None,
+            mount_permissions: "none".to_string(),
+            description: None,
+            provenance: Provenance::default(),
         };
 
         store.insert_jail(&jail).unwrap();
@@ -927,4 +1433,315 @@ mod tests {
         drop(store);
         std::fs::remove_dir_all(&test_dir).ok();
     }
+
+    fn test_image_row(id: &str, name: &str) -> Image {
+        Image {
+            id: id.to_string(),
+            name: name.to_string(),
+            parent_id: None,
+            snapshot: format!("tank/images/{}@snap", name),
+            dockerfile: "[]".to_string(),
+            config: "{}".to_string(),
+            size_bytes: 0,
+            state: ImageState::Available,
+            created_at: 0,
+            protected: false,
+            kind: "user".to_string(),
+            os_version: None,
+            content_digest: None,
+            build_log: None,
+            digest: None,
+            provenance: Provenance::default(),
+        }
+    }
+
+    #[test]
+    fn test_set_image_protected() {
+        let store = create_test_store("image_protected");
+
+        store.insert_image(&test_image_row("img-1", "base")).unwrap();
+        assert!(!store.get_image("img-1").unwrap().unwrap().protected);
+
+        store.set_image_protected("img-1", true).unwrap();
+        assert!(store.get_image("img-1").unwrap().unwrap().protected);
+
+        store.set_image_protected("img-1", false).unwrap();
+        assert!(!store.get_image("img-1").unwrap().unwrap().protected);
+    }
+
+    /// Pretend a newer kawakaze last wrote a row
+    fn set_last_written(store: &JailStore, table: &str, version: &str) {
+        let conn = Connection::open(store.db_path()).unwrap();
+        conn.execute(&format!("UPDATE {} SET last_written_version = ?1", table), params![version]).unwrap();
+    }
+
+    #[test]
+    fn test_writes_stamp_version() {
+        let store = create_test_store("version_stamp");
+        let current = Some(version::CURRENT.to_string());
+
+        store.insert_image(&test_image_row("img-1", "base")).unwrap();
+        store.insert_container(&test_container_row("web", "img-1")).unwrap();
+        let image = store.get_image("img-1").unwrap().unwrap();
+        assert_eq!(image.provenance, Provenance { created_by: current.clone(), last_written: current.clone() });
+
+        set_last_written(&store, "images", "99.0.0");
+        set_last_written(&store, "containers", "99.0.0");
+        assert_eq!(store.get_image("img-1").unwrap().unwrap().provenance.last_written.as_deref(), Some("99.0.0"));
+
+        // Any update takes the row back to the running version
+        store.set_image_protected("img-1", true).unwrap();
+        let image = store.get_image("img-1").unwrap().unwrap();
+        assert_eq!(image.provenance, Provenance { created_by: current.clone(), last_written: current.clone() });
+        store.update_container("id-web", ContainerState::Running).unwrap();
+        assert_eq!(store.get_container("id-web").unwrap().unwrap().provenance.last_written, current);
+
+        set_last_written(&store, "containers", "99.0.0");
+        store.restamp(VersionedTable::Containers, "id-web").unwrap();
+        assert_eq!(store.get_container("id-web").unwrap().unwrap().provenance.last_written, current);
+    }
+
+    #[test]
+    fn test_image_digest_round_trip() {
+        let store = create_test_store("image_digest");
+
+        let mut row = test_image_row("img-1", "web");
+        row.digest = Some(format!("sha256:{}", "ab".repeat(32)));
+        store.insert_image(&row).unwrap();
+        assert_eq!(store.get_image("img-1").unwrap().unwrap().digest, row.digest);
+    }
+
+    fn test_container_row(name: &str, image_id: &str) -> Container {
+        Container {
+            id: format!("id-{}", name),
+            name: Some(name.to_string()),
+            image_id: image_id.to_string(),
+            jail_name: format!("kawakaze-{}", name),
+            dataset: format!("tank/containers/{}", name),
+            state: ContainerState::Created,
+            restart_policy: "no".to_string(),
+            mounts: "[]".to_string(),
+            port_mappings: "[]".to_string(),
+            ip: None,
+            command: None,
+            created_at: 0,
+            started_at: None,
+            protected: false,
+            devfs_ruleset: None,
+            healthcheck_disabled: false,
+            boot: false,
+            encrypted: false,
+            full_copy: false,
+            read_only_root: false,
+            network_aliases: "[]".to_string(),
+            hostname: None,
+            ip_aliases: "[]".to_string(),
+            ip6: None,
+            tmpfs: "[]".to_string(),
+            mount_permissions: "none".to_string(),
+            stopped_at: None,
+            last_stop_cause: None,
+            env: "{}".to_string(),
+            placement: None,
+            sysctls: "{}".to_string(),
+            description: None,
+            provenance: Provenance::default(),
+        }
+    }
+
+    #[test]
+    fn test_record_container_stop() {
+        let store = create_test_store("record_container_stop");
+        store.insert_image(&test_image_row("img-1", "base")).unwrap();
+        let web = Container { state: ContainerState::Running, ..test_container_row("web", "img-1") };
+        store.insert_container(&web).unwrap();
+        let row = store.get_container(&web.id).unwrap().unwrap();
+        assert_eq!((row.stopped_at, row.last_stop_cause), (None, None));
+
+        store.record_container_stop(&web.id, 1700000000, r#"{"user_request":1001}"#).unwrap();
+        let row = store.get_container(&web.id).unwrap().unwrap();
+        assert_eq!(row.state, ContainerState::Stopped);
+        assert_eq!(row.stopped_at, Some(1700000000));
+        assert_eq!(row.last_stop_cause.as_deref(), Some(r#"{"user_request":1001}"#));
+    }
+
+    #[test]
+    fn test_set_container_name() {
+        let store = create_test_store("set_container_name");
+        store.insert_image(&test_image_row("img-1", "base")).unwrap();
+        let web = Container { hostname: Some("www.example.org".to_string()), ..test_container_row("web", "img-1") };
+        store.insert_container(&web).unwrap();
+        store.insert_container(&test_container_row("db", "img-1")).unwrap();
+
+        store.set_container_name(&web.id, "frontend").unwrap();
+        let renamed = store.get_container(&web.id).unwrap().unwrap();
+        assert_eq!(renamed.name.as_deref(), Some("frontend"));
+        assert_eq!(renamed.hostname.as_deref(), Some("www.example.org"));
+
+        // Names stay unique
+        assert!(store.set_container_name(&web.id, "db").is_err());
+    }
+
+    #[test]
+    fn test_set_container_description() {
+        let store = create_test_store("set_container_description");
+        store.insert_image(&test_image_row("img-1", "base")).unwrap();
+        let web = Container { description: Some("team-search".to_string()), ..test_container_row("web", "img-1") };
+        store.insert_container(&web).unwrap();
+        assert_eq!(store.get_container(&web.id).unwrap().unwrap().description.as_deref(), Some("team-search"));
+
+        store.set_container_description(&web.id, Some("team-payments")).unwrap();
+        let row = store.get_container(&web.id).unwrap().unwrap();
+        assert_eq!(row.description.as_deref(), Some("team-payments"));
+        assert_eq!(row.provenance.last_written.as_deref(), Some(version::CURRENT));
+
+        store.set_container_description(&web.id, None).unwrap();
+        assert_eq!(store.get_container(&web.id).unwrap().unwrap().description, None);
+    }
+
+    #[test]
+    fn test_set_container_ip_aliases() {
+        let store = create_test_store("set_container_ip_aliases");
+        store.insert_image(&test_image_row("img-1", "base")).unwrap();
+        let web = test_container_row("web", "img-1");
+        store.insert_container(&web).unwrap();
+        assert_eq!(store.get_container(&web.id).unwrap().unwrap().ip_aliases, "[]");
+
+        store.set_container_ip_aliases(&web.id, r#"["192.0.2.10","192.0.2.11"]"#).unwrap();
+        let row = store.get_container(&web.id).unwrap().unwrap();
+        assert_eq!(row.ip_aliases, r#"["192.0.2.10","192.0.2.11"]"#);
+        assert_eq!(row.provenance.last_written.as_deref(), Some(version::CURRENT));
+    }
+
+    #[test]
+    fn test_containers_for_image() {
+        let store = create_test_store("containers_for_image");
+        store.insert_image(&test_image_row("img-1", "base")).unwrap();
+        store.insert_image(&test_image_row("img-2", "other")).unwrap();
+        for (name, image) in [("web", "img-1"), ("db", "img-2"), ("worker", "img-1")] {
+            store.insert_container(&test_container_row(name, image)).unwrap();
+        }
+
+        let mut names: Vec<_> = store.containers_for_image("img-1").unwrap().into_iter().filter_map(|c| c.name).collect();
+        names.sort();
+        assert_eq!(names, ["web", "worker"]);
+        assert!(store.containers_for_image("img-3").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_published_ports() {
+        let store = create_test_store("published_ports");
+        store.set_published_ports("c1", &[(8080, "tcp"), (53, "udp")]).unwrap();
+
+        // A port has one owner
+        assert!(store.set_published_ports("c2", &[(8080, "tcp")]).is_err());
+        store.set_published_ports("c2", &[(8080, "udp")]).unwrap();
+
+        let ports = store.list_published_ports().unwrap();
+        let listed: Vec<_> = ports.iter().map(|p| (p.host_port, p.protocol.as_str(), p.container_id.as_str())).collect();
+        assert_eq!(listed, [(53, "udp", "c1"), (8080, "tcp", "c1"), (8080, "udp", "c2")]);
+
+        store.set_published_ports("c1", &[]).unwrap();
+        assert_eq!(store.list_published_ports().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_migration_adds_protected_columns() {
+        let test_db = "/tmp/test_kawakaze_migrate_protected.db";
+        let _ = std::fs::remove_file(test_db);
+
+        // Database created before the protected column existed
+        {
+            let conn = Connection::open(test_db).unwrap();
+            conn.execute(
+                "CREATE TABLE images (
+                    id TEXT PRIMARY KEY,
+                    name TEXT UNIQUE NOT NULL,
+                    parent_id TEXT,
+                    snapshot TEXT NOT NULL,
+                    dockerfile TEXT NOT NULL,
+                    config TEXT NOT NULL,
+                    size_bytes INTEGER NOT NULL DEFAULT 0,
+                    state TEXT NOT NULL DEFAULT 'building',
+                    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+                )",
+                [],
+            ).unwrap();
+            conn.execute(
+                "INSERT INTO images (id, name, snapshot, dockerfile, config, state)
+                 VALUES ('old', 'legacy', 'tank/images/legacy@snap', '[]', '{}', 'available')",
+                [],
+            ).unwrap();
+        }
+
+        let store = JailStore::new(test_db).unwrap();
+        let image = store.get_image("old").unwrap().unwrap();
+        assert_eq!(image.name, "legacy");
+        assert!(!image.protected);
+        assert_eq!(image.digest, None);
+        assert_eq!(image.provenance, Provenance::default());
+
+        // Migrating twice is a no-op
+        assert!(JailStore::new(test_db).is_ok());
+    }
+
+    #[test]
+    fn test_migration_adds_description_columns() {
+        let test_db = "/tmp/test_kawakaze_migrate_description.db";
+        let _ = std::fs::remove_file(test_db);
+
+        // Jails and containers from the initial schema
+        {
+            let conn = Connection::open(test_db).unwrap();
+            conn.execute(
+                "CREATE TABLE jails (
+                    name TEXT PRIMARY KEY,
+                    path TEXT,
+                    ip TEXT,
+                    state TEXT NOT NULL CHECK(state IN ('created', 'running', 'stopped')),
+                    jid INTEGER,
+                    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+                    updated_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+                )",
+                [],
+            ).unwrap();
+            conn.execute("INSERT INTO jails (name, state, jid) VALUES ('legacy', 'stopped', -1)", []).unwrap();
+            conn.execute(
+                "CREATE TABLE containers (
+                    id TEXT PRIMARY KEY,
+                    name TEXT UNIQUE,
+                    image_id TEXT NOT NULL,
+                    jail_name TEXT UNIQUE NOT NULL,
+                    dataset TEXT NOT NULL,
+                    state TEXT NOT NULL DEFAULT 'created',
+                    restart_policy TEXT NOT NULL DEFAULT 'no',
+                    mounts TEXT NOT NULL,
+                    port_mappings TEXT NOT NULL,
+                    ip TEXT,
+                    command TEXT,
+                    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+                    started_at INTEGER
+                )",
+                [],
+            ).unwrap();
+            conn.execute(
+                "INSERT INTO containers (id, name, image_id, jail_name, dataset, mounts, port_mappings)
+                 VALUES ('old', 'legacy', 'img-1', 'c_old', 'tank/containers/old', '[]', '[]')",
+                [],
+            ).unwrap();
+        }
+
+        let store = JailStore::new(test_db).unwrap();
+        let jail = store.get_jail("legacy").unwrap().unwrap();
+        assert_eq!(jail.description, None);
+        assert_eq!(jail.mount_permissions, "none");
+        let container = store.get_container("old").unwrap().unwrap();
+        assert_eq!(container.description, None);
+        assert_eq!(container.name.as_deref(), Some("legacy"));
+
+        // The new columns take writes like any other
+        store.set_container_description("old", Some("migrated")).unwrap();
+        assert_eq!(store.get_container("old").unwrap().unwrap().description.as_deref(), Some("migrated"));
+        assert!(JailStore::new(test_db).is_ok());
+    }
 }
@@ -0,0 +1,211 @@
+//! Streaming responses and the connection rules around them
+//!
+//! A request to a streaming endpoint ([`Endpoint::is_streaming`]) is
+//! answered with any number of frames instead of one response. Every frame
+//! carries `"stream": true`; the last one also carries `"end": true` and a
+//! status: 200 when the stream finished normally, an error otherwise (503
+//! `SHUTTING_DOWN` when the daemon stopped). A stream that ends without a
+//! terminal frame was broken.
+//!
+//! While a stream is active its connection is busy: any other request on
+//! it is answered with 409 `CONNECTION_BUSY_STREAMING`. Once the terminal
+//! frame is sent the connection takes requests again.
+//!
+//! Each stream is produced by its own task, registered in the
+//! [`StreamRegistry`]. The task is cancelled when its client goes away
+//! (the [`ActiveStream`] is dropped) or when the registry shuts down.
+//!
+//! [`Endpoint::is_streaming`]: crate::api::Endpoint::is_streaming
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+
+use crate::api::{ApiError, Response, status};
+use crate::timestamp::Timestamp;
+
+/// Frames buffered per stream before its producer waits for the client
+const FRAME_BUFFER: usize = 64;
+
+/// A stream being produced
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StreamInfo {
+    pub id: u64,
+    pub connection_id: u64,
+    /// Endpoint path the stream was requested on
+    pub endpoint: String,
+    pub started_at: Timestamp,
+}
+
+/// The client of a stream is gone
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamClosed;
+
+/// Sends the data frames of one stream
+pub struct StreamSender {
+    frames: mpsc::Sender<Response>,
+}
+
+impl StreamSender {
+    /// Send one data frame, waiting while the client is behind
+    pub async fn send(&self, data: impl Serialize) -> Result<(), StreamClosed> {
+        let frame = Response::stream_frame(data)
+            .unwrap_or_else(|e| Response::internal_error(format!("Failed to serialize frame: {}", e)));
+        self.frames.send(frame).await.map_err(|_| StreamClosed)
+    }
+}
+
+/// The receiving end of a stream; dropping it cancels the producer
+pub struct ActiveStream {
+    pub id: u64,
+    frames: mpsc::Receiver<Response>,
+    cancel: CancellationToken,
+}
+
+impl ActiveStream {
+    /// Next frame; `None` once the terminal frame has been returned
+    pub async fn next(&mut self) -> Option<Response> {
+        self.frames.recv().await
+    }
+}
+
+impl Drop for ActiveStream {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+    }
+}
+
+/// Every stream the daemon is producing
+#[derive(Debug, Default)]
+pub struct StreamRegistry {
+    next_id: AtomicU64,
+    streams: Mutex<BTreeMap<u64, StreamInfo>>,
+    shutdown: CancellationToken,
+}
+
+impl StreamRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run `produce` as a new stream's task and return its receiving end
+    ///
+    /// The producer sends data frames through the [`StreamSender`] and
+    /// returns how the stream ended; the terminal frame is sent for it. It
+    /// is dropped at its next await point once the stream is cancelled.
+    pub fn spawn<F, Fut>(self: &Arc<Self>, connection_id: u64, endpoint: impl Into<String>, produce: F) -> ActiveStream
+    where
+        F: FnOnce(StreamSender) -> Fut,
+        Fut: Future<Output = Result<(), Response>> + Send + 'static,
+    {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let info = StreamInfo {
+            id,
+            connection_id,
+            endpoint: endpoint.into(),
+            started_at: Timestamp::now(),
+        };
+        debug!(stream_id = id, connection_id, endpoint = %info.endpoint, "Stream started");
+        self.streams.lock().unwrap().insert(id, info);
+
+        let (tx, rx) = mpsc::channel(FRAME_BUFFER);
+        let cancel = self.shutdown.child_token();
+        let producer = produce(StreamSender { frames: tx.clone() });
+        let registry = self.clone();
+        let token = cancel.clone();
+        tokio::spawn(async move {
+            let outcome = tokio::select! {
+                outcome = producer => outcome,
+                _ = token.cancelled() => Err(if registry.shutdown.is_cancelled() {
+                    Response::error(status::SERVICE_UNAVAILABLE, ApiError::ShuttingDown())
+                } else {
+                    Response::coded(crate::error_codes::STREAM_CANCELLED, "Stream cancelled")
+                }),
+            };
+            // Nobody receives the terminal frame of a stream whose client left
+            let _ = tx.send(Response::stream_end(outcome)).await;
+            registry.streams.lock().unwrap().remove(&id);
+            debug!(stream_id = id, "Stream finished");
+        });
+
+        ActiveStream { id, frames: rx, cancel }
+    }
+
+    /// Streams still being produced, oldest first
+    pub fn active(&self) -> Vec<StreamInfo> {
+        self.streams.lock().unwrap().values().cloned().collect()
+    }
+
+    /// Cancel every stream; each still gets its terminal frame
+    pub fn shutdown(&self) {
+        self.shutdown.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    async fn wait_idle(registry: &StreamRegistry) {
+        for _ in 0..200 {
+            if registry.active().is_empty() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("streams still registered: {:?}", registry.active());
+    }
+
+    #[tokio::test]
+    async fn test_frames_then_terminal_frame() {
+        let registry = Arc::new(StreamRegistry::new());
+        let mut stream = registry.spawn(1, "containers/web/logs/follow", |sender| async move {
+            for line in ["one", "two"] {
+                sender.send(line).await.map_err(|_| Response::internal_error("gone"))?;
+            }
+            Ok(())
+        });
+
+        let mut frames = Vec::new();
+        while let Some(frame) = stream.next().await {
+            frames.push(frame);
+        }
+        let data: Vec<_> = frames.iter().filter_map(|f| f.data.clone()).collect();
+        assert_eq!(data, ["one", "two"]);
+        assert!(frames.iter().all(|f| f.stream));
+        let last = frames.last().unwrap();
+        assert!(last.end && last.status == status::OK);
+        wait_idle(&registry).await;
+    }
+
+    #[tokio::test]
+    async fn test_drop_cancels_producer() {
+        let registry = Arc::new(StreamRegistry::new());
+        let stream = registry.spawn(7, "containers/web/logs/follow", |_sender| std::future::pending());
+        assert_eq!(registry.active()[0].connection_id, 7);
+
+        drop(stream);
+        wait_idle(&registry).await;
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_ends_streams_with_503() {
+        let registry = Arc::new(StreamRegistry::new());
+        let mut stream = registry.spawn(1, "containers/web/logs/follow", |_sender| std::future::pending());
+
+        registry.shutdown();
+        let end = stream.next().await.unwrap();
+        assert!(end.end);
+        assert_eq!(end.status, status::SERVICE_UNAVAILABLE);
+        assert_eq!(end.error.unwrap().code, "SHUTTING_DOWN");
+        assert!(stream.next().await.is_none());
+        wait_idle(&registry).await;
+    }
+}
@@ -0,0 +1,222 @@
+//! sysctl tunables set inside a container's jail at start
+//!
+//! A container may ask for sysctls (`--sysctl net.inet.ip.forwarding=1`).
+//! Only the ones a jail can set for itself make sense, which in practice is
+//! the network stack of a VNET jail, so a key has to be allowed by
+//! [`Allowlist`]: under one of [`DEFAULT_PREFIXES`] or the configured
+//! `sysctl.allowed_prefixes`, or named in `sysctl.allowed`, and not named in
+//! `sysctl.denied`. Prefixes match whole components, so `net.inet` covers
+//! `net.inet.ip.forwarding` but not `net.inet6.ip6.forwarding`.
+//!
+//! At start every sysctl is set with `sysctl key=value` through `jexec`
+//! once the jail runs ([`apply`]). All of them are tried and the failures
+//! collected; any failure fails the start and the jail is removed again,
+//! since a security-relevant tunable that silently didn't apply is worse
+//! than a container that didn't start.
+
+use std::collections::BTreeMap;
+
+use crate::config::SysctlConfig;
+
+/// Prefixes a container may always set sysctls under
+pub const DEFAULT_PREFIXES: &[&str] = &[
+    "net.inet.ip",
+    "net.inet.tcp",
+    "net.inet.udp",
+    "net.inet.icmp",
+    "net.inet6.ip6",
+    "net.inet6.icmp6",
+];
+
+/// Whether `key` looks like a sysctl name: dot-separated components of
+/// letters, digits, `_` and `-`
+pub fn is_valid_key(key: &str) -> bool {
+    !key.is_empty()
+        && key.split('.').all(|part| {
+            !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        })
+}
+
+/// Whether `prefix` covers `key`, on a component boundary
+fn covers(prefix: &str, key: &str) -> bool {
+    key.strip_prefix(prefix).is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+}
+
+/// Which sysctls a container may set
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Allowlist {
+    prefixes: Vec<String>,
+    allowed: Vec<String>,
+    denied: Vec<String>,
+}
+
+impl Allowlist {
+    /// The built-in prefixes with the configured ones and exact keys
+    pub fn from_config(config: &SysctlConfig) -> Self {
+        let mut prefixes: Vec<String> = DEFAULT_PREFIXES.iter().map(|p| p.to_string()).collect();
+        for prefix in &config.allowed_prefixes {
+            let prefix = prefix.trim_end_matches('.').to_string();
+            if !prefixes.contains(&prefix) {
+                prefixes.push(prefix);
+            }
+        }
+        Self { prefixes, allowed: config.allowed.clone(), denied: config.denied.clone() }
+    }
+
+    /// Whether `key` may be set; exact keys override the prefixes, and a
+    /// denied key wins over everything
+    pub fn allows(&self, key: &str) -> bool {
+        if self.denied.iter().any(|denied| denied == key) {
+            return false;
+        }
+        self.allowed.iter().any(|allowed| allowed == key) || self.prefixes.iter().any(|prefix| covers(prefix, key))
+    }
+
+    /// Check every requested sysctl, naming the permitted prefixes when
+    /// one isn't allowed
+    pub fn check(&self, sysctls: &BTreeMap<String, String>) -> Result<(), String> {
+        for (key, value) in sysctls {
+            if !is_valid_key(key) {
+                return Err(format!("Invalid sysctl name '{}'", key));
+            }
+            if value.is_empty() || value.chars().any(char::is_control) {
+                return Err(format!("Invalid value for sysctl {}: must be non-empty, without control characters", key));
+            }
+            if !self.allows(key) {
+                let mut permitted = self.prefixes.iter().map(|p| format!("{}.*", p)).collect::<Vec<_>>();
+                permitted.extend(self.allowed.iter().cloned());
+                return Err(format!("sysctl {} can't be set in a container; permitted: {}", key, permitted.join(", ")));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The value `sysctl key=value` reports it set, from `key: old -> new`
+pub fn parse_applied(output: &str) -> Option<String> {
+    output
+        .lines()
+        .find_map(|line| line.split_once(" -> ").map(|(_, new)| new.trim().to_string()))
+}
+
+/// Set every sysctl in the jail through `exec`, which runs a program with
+/// its arguments in the jail and returns its stdout
+///
+/// Returns the values the jail reports as set; a sysctl whose output can't
+/// be parsed counts as set to what was asked. On failure every sysctl has
+/// still been tried, and the error lists each one that failed.
+pub fn apply(
+    sysctls: &BTreeMap<String, String>,
+    mut exec: impl FnMut(&str, &[String]) -> Result<String, String>,
+) -> Result<BTreeMap<String, String>, String> {
+    let mut applied = BTreeMap::new();
+    let mut failures = Vec::new();
+    for (key, value) in sysctls {
+        match exec("sysctl", &[format!("{}={}", key, value)]) {
+            Ok(output) => {
+                applied.insert(key.clone(), parse_applied(&output).unwrap_or_else(|| value.clone()));
+            }
+            Err(e) => failures.push(format!("{}={}: {}", key, value, e.trim())),
+        }
+    }
+
+    if failures.is_empty() {
+        Ok(applied)
+    } else {
+        Err(format!("Failed to set sysctl {}", failures.join("; ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    fn config(prefixes: &[&str], allowed: &[&str], denied: &[&str]) -> SysctlConfig {
+        let strings = |list: &[&str]| list.iter().map(|s| s.to_string()).collect();
+        SysctlConfig { allowed_prefixes: strings(prefixes), allowed: strings(allowed), denied: strings(denied) }
+    }
+
+    #[test]
+    fn test_allows() {
+        let list = Allowlist::from_config(&config(&["net.pf."], &["kern.ipc.somaxconn"], &["net.inet.ip.forwarding"]));
+        let cases = [
+            ("net.inet.tcp.keepidle", true),
+            ("net.inet.ip", true),
+            // Prefixes match whole components
+            ("net.inet6.ip6.forwarding", true),
+            ("net.inet.ipx.whatever", false),
+            ("net.pf.request_maxcount", true),
+            ("net.pfx", false),
+            // Exact keys override the prefixes either way
+            ("kern.ipc.somaxconn", true),
+            ("kern.ipc.maxsockbuf", false),
+            ("net.inet.ip.forwarding", false),
+            ("security.jail.param.allow.raw_sockets", false),
+        ];
+        for (key, allowed) in cases {
+            assert_eq!(list.allows(key), allowed, "{}", key);
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let list = Allowlist::from_config(&SysctlConfig::default());
+        assert!(list.check(&map(&[("net.inet.ip.forwarding", "1"), ("net.inet6.ip6.forwarding", "1")])).is_ok());
+
+        let err = list.check(&map(&[("kern.securelevel", "-1")])).unwrap_err();
+        assert!(err.starts_with("sysctl kern.securelevel can't be set in a container; permitted: net.inet.ip.*, net.inet.tcp.*"), "{}", err);
+
+        for (key, value) in [("net..inet", "1"), ("net.inet.ip.forwarding=1", "1"), ("net.inet.ip.ttl", ""), ("net.inet.ip.ttl", "64\n1")] {
+            assert!(list.check(&map(&[(key, value)])).is_err(), "{}={:?}", key, value);
+        }
+    }
+
+    #[test]
+    fn test_parse_applied() {
+        assert_eq!(parse_applied("net.inet.ip.forwarding: 0 -> 1\n").as_deref(), Some("1"));
+        assert_eq!(parse_applied("net.inet.tcp.cc.algorithm: newreno -> cubic").as_deref(), Some("cubic"));
+        assert_eq!(parse_applied(""), None);
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut calls = Vec::new();
+        let sysctls = map(&[("net.inet.tcp.keepidle", "30000"), ("net.inet.ip.forwarding", "1")]);
+        let applied = apply(&sysctls, |program, args| {
+            calls.push(std::iter::once(program.to_string()).chain(args.iter().cloned()).collect::<Vec<_>>());
+            Ok(match args[0].as_str() {
+                "net.inet.ip.forwarding=1" => "net.inet.ip.forwarding: 0 -> 1\n".to_string(),
+                _ => String::new(),
+            })
+        })
+        .unwrap();
+
+        // In key order, one `sysctl` each
+        assert_eq!(calls, [["sysctl", "net.inet.ip.forwarding=1"], ["sysctl", "net.inet.tcp.keepidle=30000"]]);
+        assert_eq!(applied, map(&[("net.inet.ip.forwarding", "1"), ("net.inet.tcp.keepidle", "30000")]));
+    }
+
+    #[test]
+    fn test_apply_collects_failures() {
+        let mut tried = 0;
+        let sysctls = map(&[("net.inet.ip.forwarding", "1"), ("net.inet.ip.ttl", "64"), ("net.inet.tcp.mssdflt", "x")]);
+        let err = apply(&sysctls, |_, args| {
+            tried += 1;
+            match args[0].as_str() {
+                "net.inet.ip.ttl=64" => Ok(String::new()),
+                _ => Err("sysctl: oid not writable\n".to_string()),
+            }
+        })
+        .unwrap_err();
+
+        assert_eq!(tried, 3);
+        assert_eq!(
+            err,
+            "Failed to set sysctl net.inet.ip.forwarding=1: sysctl: oid not writable; net.inet.tcp.mssdflt=x: sysctl: oid not writable"
+        );
+    }
+}
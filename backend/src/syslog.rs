@@ -0,0 +1,266 @@
+//! A container's syslog, read as a second log
+//!
+//! Services started from rc.d log through syslogd inside the jail rather
+//! than to stdout, so what they say ends up in the jail's
+//! `/var/log/messages`. [`parse_messages`] turns that file into the same
+//! [`LogEntry`] shape as the stdio log:
+//!
+//! - BSD lines (`Oct 15 12:34:56 host sshd[42]: message`) carry no year,
+//!   which is taken from the file's modification time (the previous year
+//!   for a date after it, as in December's lines read in January), and are
+//!   in the host's local time
+//! - lines stamped by `syslogd -O rfc5424` start with an RFC 3339 time
+//!
+//! Lines carry their facility only when the jail's syslogd runs with `-vv`
+//! (`<daemon.notice>`) or `-v` (`<30>`); it then becomes the stream, which
+//! is "syslog" otherwise. The message keeps the program tag. A line that
+//! isn't syslog is kept whole, timed by the file's modification time.
+//!
+//! Following the file is [`crate::logs::Tail`], which starts over when
+//! newsyslog rotates it. Rotated files (`messages.0.bz2`) are not read.
+
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Datelike, Duration, FixedOffset, Local, NaiveDateTime, TimeZone};
+
+use crate::logs::LogEntry;
+use crate::timestamp::Timestamp;
+
+/// Stream of syslog lines without a facility
+pub const STREAM: &str = "syslog";
+
+/// Facility names by number, as syslog.h has them
+const FACILITIES: [&str; 24] = [
+    "kern", "user", "mail", "daemon", "auth", "syslog", "lpr", "news", "uucp", "cron", "authpriv", "ftp", "ntp",
+    "security", "console", "", "local0", "local1", "local2", "local3", "local4", "local5", "local6", "local7",
+];
+
+/// `/var/log/messages` below a jail's root
+pub fn messages_file(root: &Path) -> PathBuf {
+    root.join("var/log/messages")
+}
+
+/// Parse a messages file's contents, `mtime` dating the file (now if
+/// unknown)
+pub fn parse_messages(contents: &str, mtime: Option<i64>) -> Vec<LogEntry> {
+    let reference = mtime
+        .and_then(|secs| Local.timestamp_opt(secs, 0).single())
+        .unwrap_or_else(Local::now)
+        .fixed_offset();
+
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            parse_line(line, reference).unwrap_or_else(|| LogEntry {
+                timestamp: mtime.map(Timestamp::from_secs),
+                stream: STREAM.to_string(),
+                message: line.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Parse one syslog line; `reference` is when the file was last written,
+/// for a BSD line's year and offset from UTC
+pub fn parse_line(line: &str, reference: DateTime<FixedOffset>) -> Option<LogEntry> {
+    let (time, rest) = match next_token(line) {
+        Some((first, rest)) if first.contains('T') => (DateTime::parse_from_rfc3339(first).ok()?, rest),
+        _ => parse_bsd_time(line, reference)?,
+    };
+
+    let (facility, rest) = match next_token(rest)? {
+        (priority, rest) if priority.starts_with('<') && priority.ends_with('>') => {
+            (facility(&priority[1..priority.len() - 1]), rest)
+        }
+        _ => (None, rest),
+    };
+    // The host name, then the message
+    let (_host, message) = next_token(rest)?;
+
+    Some(LogEntry {
+        timestamp: Some(Timestamp::from_millis(time.timestamp_millis())),
+        stream: facility.unwrap_or(STREAM).to_string(),
+        message: message.to_string(),
+    })
+}
+
+/// `Mmm dd hh:mm:ss`, in the year that puts it no later than a day past
+/// `reference`
+fn parse_bsd_time(line: &str, reference: DateTime<FixedOffset>) -> Option<(DateTime<FixedOffset>, &str)> {
+    let (month, rest) = next_token(line)?;
+    let (day, rest) = next_token(rest)?;
+    let (time, rest) = next_token(rest)?;
+
+    let at = |year: i32| {
+        let naive = NaiveDateTime::parse_from_str(&format!("{} {} {} {}", year, month, day, time), "%Y %b %d %H:%M:%S").ok()?;
+        reference.offset().from_local_datetime(&naive).single()
+    };
+    let time = match at(reference.year())? {
+        time if time > reference + Duration::days(1) => at(reference.year() - 1)?,
+        time => time,
+    };
+    Some((time, rest))
+}
+
+/// Facility of a `-v` priority number or a `-vv` `facility.level`
+fn facility(priority: &str) -> Option<&'static str> {
+    let name = match priority.parse::<usize>() {
+        Ok(number) => FACILITIES.get(number >> 3).copied()?,
+        Err(_) => {
+            let name = priority.split('.').next()?;
+            FACILITIES.iter().copied().find(|known| *known == name)?
+        }
+    };
+    (!name.is_empty()).then_some(name)
+}
+
+/// The first space-separated word of `s` (skipping the padding syslogd puts
+/// before a one-digit day) and what follows the space after it
+fn next_token(s: &str) -> Option<(&str, &str)> {
+    let s = s.trim_start_matches(' ');
+    let (token, rest) = s.split_once(' ')?;
+    Some((token, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logs::{LogSource, Tail, merge};
+    use std::fs;
+    use std::io::Write;
+
+    const MESSAGES: &str = "\
+Oct 14 23:59:58 web syslogd: kernel boot file is /boot/kernel/kernel
+Oct 15 00:00:01 web nginx[812]: started
+Oct  5 08:00:00 web cron[90]: (root) CMD (adjkerntz -a)
+Oct 15 09:30:00 <daemon.notice> web sshd[77]: Server listening on :: port 22.
+Oct 15 09:30:01 <34> web su[78]: BAD SU to root on /dev/pts/0
+2026-10-15T09:31:00.250+02:00 web named 91 - - zone loaded
+--- this is not syslog ---
+";
+
+    fn reference() -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339("2026-10-15T12:00:00+02:00").unwrap()
+    }
+
+    fn at(rfc3339: &str) -> Option<Timestamp> {
+        Some(Timestamp::parse(rfc3339).unwrap())
+    }
+
+    #[test]
+    fn test_parse_bsd_lines() {
+        let entry = parse_line("Oct 15 00:00:01 web nginx[812]: started", reference()).unwrap();
+        assert_eq!(entry, LogEntry {
+            timestamp: at("2026-10-15T00:00:01+02:00"),
+            stream: "syslog".to_string(),
+            message: "nginx[812]: started".to_string(),
+        });
+
+        // A padded one-digit day, and spacing inside the message kept
+        let entry = parse_line("Oct  5 08:00:00 web cron[90]:  two  spaces", reference()).unwrap();
+        assert_eq!(entry.timestamp, at("2026-10-05T08:00:00+02:00"));
+        assert_eq!(entry.message, "cron[90]:  two  spaces");
+
+        // December, read in January, is last year
+        let january = DateTime::parse_from_rfc3339("2027-01-02T00:00:00+00:00").unwrap();
+        let entry = parse_line("Dec 31 23:00:00 web app: bye", january).unwrap();
+        assert_eq!(entry.timestamp, at("2026-12-31T23:00:00Z"));
+
+        assert!(parse_line("Foo 31 23:00:00 web app: bye", reference()).is_none());
+        assert!(parse_line("Oct 15 09:30:00", reference()).is_none());
+    }
+
+    #[test]
+    fn test_parse_facilities_and_rfc3339() {
+        let entries: Vec<LogEntry> = MESSAGES.lines().filter_map(|line| parse_line(line, reference())).collect();
+        let streams: Vec<&str> = entries.iter().map(|e| e.stream.as_str()).collect();
+        assert_eq!(streams, ["syslog", "syslog", "syslog", "daemon", "auth", "syslog"]);
+        assert_eq!(entries[3].message, "sshd[77]: Server listening on :: port 22.");
+        assert_eq!(entries[5].timestamp, at("2026-10-15T07:31:00.250Z"));
+        assert_eq!(entries[5].message, "named 91 - - zone loaded");
+
+        assert_eq!(facility("local3.info"), Some("local3"));
+        assert_eq!(facility("120"), None);
+        assert_eq!(facility("nope.info"), None);
+    }
+
+    #[test]
+    fn test_parse_messages_keeps_other_lines() {
+        let entries = parse_messages(MESSAGES, Some(1_700_000_000));
+        assert_eq!(entries.len(), 7);
+
+        let last = &entries[6];
+        assert_eq!(last.timestamp, Some(Timestamp::from_secs(1_700_000_000)));
+        assert_eq!(last.stream, "syslog");
+        assert_eq!(last.message, "--- this is not syslog ---");
+    }
+
+    #[test]
+    fn test_merge_with_stdio() {
+        let stdio = crate::logs::parse_log(
+            "{\"time\":1700000000,\"stream\":\"stdout\",\"log\":\"one\"}\n\
+             {\"time\":1700000010,\"stream\":\"stderr\",\"log\":\"three\"}\n\
+             {\"time\":1700000020,\"stream\":\"stdout\",\"log\":\"five\"}\n",
+            None,
+        );
+        let entry = |secs: Option<i64>, message: &str| LogEntry {
+            timestamp: secs.map(Timestamp::from_secs),
+            stream: STREAM.to_string(),
+            message: message.to_string(),
+        };
+        let syslog = vec![
+            entry(Some(1700000000), "two"),
+            entry(None, "two and a half"),
+            entry(Some(1700000015), "four"),
+            entry(Some(1700000030), "six"),
+        ];
+
+        let merged: Vec<String> = merge(stdio, syslog).into_iter().map(|e| e.message).collect();
+        assert_eq!(merged, ["one", "two", "two and a half", "three", "four", "five", "six"]);
+        assert!(merge(Vec::new(), Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn test_tail_survives_rotation_and_truncation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = messages_file(dir.path());
+        let mut tail = Tail::new(path.clone(), parse_messages);
+        let messages = |entries: Vec<LogEntry>| entries.into_iter().map(|e| e.message).collect::<Vec<_>>();
+
+        // Nothing yet
+        assert!(tail.poll().unwrap().is_empty());
+
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "Oct 15 00:00:01 web app: one\nOct 15 00:00:02 web app: tw").unwrap();
+        assert_eq!(messages(tail.poll().unwrap()), ["app: one"]);
+
+        fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(b"o\n").unwrap();
+        assert_eq!(messages(tail.poll().unwrap()), ["app: two"]);
+        assert!(tail.poll().unwrap().is_empty());
+
+        // newsyslog moves the file away and a new one is started, longer
+        // than what was read of the old one by the next poll
+        fs::rename(&path, dir.path().join("var/log/messages.0")).unwrap();
+        assert!(tail.poll().unwrap().is_empty());
+        fs::write(&path, "Oct 15 00:01:00 web newsyslog[5]: logfile turned over\nOct 15 00:01:01 web app: three\nOct 15 00:01:02 web app: four\n").unwrap();
+        assert_eq!(messages(tail.poll().unwrap()), ["newsyslog[5]: logfile turned over", "app: three", "app: four"]);
+
+        // Rotated in place between polls, so never seen missing
+        fs::rename(&path, dir.path().join("var/log/messages.1")).unwrap();
+        fs::write(&path, "Oct 15 00:02:00 web app: five\nOct 15 00:02:01 web app: six\nOct 15 00:02:02 web app: seven\nOct 15 00:02:03 web app: eight\n").unwrap();
+        assert_eq!(messages(tail.poll().unwrap()), ["app: five", "app: six", "app: seven", "app: eight"]);
+
+        // Truncated
+        fs::write(&path, "Oct 15 00:03:00 web app: nine\n").unwrap();
+        assert_eq!(messages(tail.poll().unwrap()), ["app: nine"]);
+    }
+
+    #[test]
+    fn test_sources() {
+        assert!(LogSource::default().is_stdio());
+        assert!(LogSource::All.includes_stdio() && LogSource::All.includes_syslog());
+        assert!(!LogSource::Syslog.includes_stdio());
+        assert_eq!(serde_json::from_str::<LogSource>("\"syslog\"").unwrap(), LogSource::Syslog);
+    }
+}
@@ -0,0 +1,306 @@
+//! Host and daemon information for `GET /system/info`
+//!
+//! Host facts come from `sysctl` and `uname` through the [`HostProbe`]
+//! trait, so the parsing can be tested without a FreeBSD host. The rest is
+//! aggregated from the manager's state.
+
+use std::collections::BTreeMap;
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+
+use crate::JailManager;
+use crate::api::SystemInfo;
+use crate::cmdtrace::TracedCommand;
+
+/// Source of raw host facts
+pub trait HostProbe {
+    /// Value of `sysctl -n NAME`, if it could be read
+    fn sysctl(&self, name: &str) -> Option<String>;
+
+    /// Output of `uname FLAG` (e.g. `-v`), if it could be read
+    fn uname(&self, flag: &str) -> Option<String>;
+
+    /// Run any other command, returning its stdout or why it failed
+    fn command(&self, program: &str, args: &[&str]) -> Result<String, String>;
+}
+
+/// Reads host facts by running `sysctl` and `uname`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CommandProbe;
+
+impl HostProbe for CommandProbe {
+    fn sysctl(&self, name: &str) -> Option<String> {
+        run("sysctl", &["-n", name])
+    }
+
+    fn uname(&self, flag: &str) -> Option<String> {
+        run("uname", &[flag])
+    }
+
+    fn command(&self, program: &str, args: &[&str]) -> Result<String, String> {
+        let output = Command::new(program)
+            .args(args)
+            .traced_output()
+            .map_err(|e| format!("{}: {}", program, e))?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+            return Err(if stderr.is_empty() { format!("{} exited with {}", program, output.status) } else { stderr });
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+fn run(program: &str, args: &[&str]) -> Option<String> {
+    let stdout = CommandProbe.command(program, args).ok()?.trim().to_string();
+    (!stdout.is_empty()).then_some(stdout)
+}
+
+/// Facts about the host the daemon runs on; anything unreadable is `None`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HostInfo {
+    /// Host name, e.g. `web1.example.org`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    /// FreeBSD release, e.g. `14.1-RELEASE-p3`
+    pub os_version: Option<String>,
+    /// Kernel version string
+    pub kernel: Option<String>,
+    /// Machine architecture, e.g. `amd64`
+    pub arch: Option<String>,
+    pub cpu_model: Option<String>,
+    pub cpus: Option<u32>,
+    pub memory_bytes: Option<u64>,
+}
+
+impl HostInfo {
+    /// Collect host facts from `probe`
+    pub fn collect(probe: &impl HostProbe) -> Self {
+        Self {
+            hostname: probe.sysctl("kern.hostname").or_else(|| probe.uname("-n")),
+            os_version: probe.sysctl("kern.osrelease").or_else(|| probe.uname("-r")),
+            kernel: probe
+                .uname("-v")
+                .or_else(|| probe.sysctl("kern.version"))
+                .and_then(|version| version.lines().next().map(|line| line.trim().to_string())),
+            arch: probe.sysctl("hw.machine_arch").or_else(|| probe.uname("-p")),
+            cpu_model: probe.sysctl("hw.model"),
+            cpus: probe.sysctl("hw.ncpu").and_then(|n| n.trim().parse().ok()),
+            memory_bytes: probe.sysctl("hw.physmem").and_then(|n| n.trim().parse().ok()),
+        }
+    }
+}
+
+/// Prefix of every error for an operation a jailed daemon can't perform
+pub const JAILED_UNAVAILABLE: &str = "operation unavailable: daemon is running inside a jail";
+
+/// What running inside a jail takes away from the daemon
+///
+/// A jailed daemon can only create VNET jails if its own jail allows it
+/// (`security.jail.allow.vnet`) and can only mount `/dev` in them with
+/// `allow.mount.devfs` (`security.jail.mount_devfs_allowed`). Without those
+/// the syscalls fail with bare `EPERM`s; the `require_*` checks turn that
+/// into an error that says why.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Confinement {
+    /// `security.jail.jailed`
+    pub jailed: bool,
+    pub allow_vnet: bool,
+    pub allow_devfs: bool,
+}
+
+impl Confinement {
+    /// Read the jail sysctls from `probe`; an unreadable `jailed` means the
+    /// host isn't confined (or isn't FreeBSD)
+    pub fn detect(probe: &impl HostProbe) -> Self {
+        let enabled = |name: &str| probe.sysctl(name).is_some_and(|value| value.trim() == "1");
+        if !enabled("security.jail.jailed") {
+            return Self::default();
+        }
+        Self {
+            jailed: true,
+            allow_vnet: enabled("security.jail.allow.vnet"),
+            allow_devfs: enabled("security.jail.mount_devfs_allowed"),
+        }
+    }
+
+    /// Whether jails with their own network stack can be created
+    pub fn vnet_available(&self) -> bool {
+        !self.jailed || self.allow_vnet
+    }
+
+    pub fn require_vnet(&self) -> Result<(), String> {
+        if self.vnet_available() {
+            return Ok(());
+        }
+        Err(format!("{} without allow.vnet (VNET networking)", JAILED_UNAVAILABLE))
+    }
+
+    pub fn require_devfs(&self) -> Result<(), String> {
+        if !self.jailed || self.allow_devfs {
+            return Ok(());
+        }
+        Err(format!("{} without allow.mount.devfs (mounting /dev in jails)", JAILED_UNAVAILABLE))
+    }
+}
+
+/// Count occurrences of each state name
+pub fn count_states<'a>(states: impl IntoIterator<Item = &'a str>) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for state in states {
+        *counts.entry(state.to_string()).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// Assemble the system info from the manager's state and `host`
+pub fn system_info(manager: &JailManager, host: HostInfo) -> SystemInfo {
+    let (pool, pool_error) = match manager.pool_status() {
+        Some(Ok(status)) => (Some(status), None),
+        Some(Err(err)) => (None, Some(err.to_string())),
+        None => (None, None),
+    };
+    let allow_degraded = manager.config.storage.allow_degraded_pool;
+    let pool_writes_refused = pool.as_ref().is_some_and(|pool| crate::pool_health::refuses(pool.state, allow_degraded));
+
+    SystemInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        host,
+        pool,
+        pool_error,
+        pool_writes_refused,
+        jails: count_states(manager.jails.values().map(|jail| jail.state().as_str())),
+        images: count_states(manager.images.values().map(|image| image.state.as_str())),
+        containers: manager.summarize_containers(),
+        uptime_secs: manager.started_at.elapsed().as_secs(),
+        slowest_commands: crate::cmdtrace::metrics().slowest_recent,
+        pending_writes: manager.pending_writes_info(),
+        node_labels: manager.node_labels().clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::{Image, ImageState};
+    use crate::jail::Jail;
+    use std::collections::HashMap;
+
+    struct MockProbe {
+        sysctls: HashMap<&'static str, &'static str>,
+        uname: HashMap<&'static str, &'static str>,
+    }
+
+    impl HostProbe for MockProbe {
+        fn sysctl(&self, name: &str) -> Option<String> {
+            self.sysctls.get(name).map(|v| v.to_string())
+        }
+
+        fn uname(&self, flag: &str) -> Option<String> {
+            self.uname.get(flag).map(|v| v.to_string())
+        }
+
+        fn command(&self, program: &str, _args: &[&str]) -> Result<String, String> {
+            Err(format!("{} not mocked", program))
+        }
+    }
+
+    fn sysctls(values: &[(&'static str, &'static str)]) -> MockProbe {
+        MockProbe { sysctls: values.iter().copied().collect(), uname: HashMap::new() }
+    }
+
+    #[test]
+    fn test_confinement_detection() {
+        // Not jailed, or the sysctl can't be read: nothing is restricted
+        for probe in [sysctls(&[]), sysctls(&[("security.jail.jailed", "0"), ("security.jail.allow.vnet", "0")])] {
+            let confinement = Confinement::detect(&probe);
+            assert_eq!(confinement, Confinement::default());
+            assert!(confinement.require_vnet().is_ok() && confinement.require_devfs().is_ok());
+        }
+
+        let bare = Confinement::detect(&sysctls(&[("security.jail.jailed", "1")]));
+        assert_eq!(bare, Confinement { jailed: true, allow_vnet: false, allow_devfs: false });
+        assert!(!bare.vnet_available());
+        let err = bare.require_vnet().unwrap_err();
+        assert!(err.starts_with("operation unavailable: daemon is running inside a jail"), "{}", err);
+        assert!(bare.require_devfs().unwrap_err().contains("allow.mount.devfs"));
+
+        let allowed = Confinement::detect(&sysctls(&[
+            ("security.jail.jailed", "1"),
+            ("security.jail.allow.vnet", "1\n"),
+            ("security.jail.mount_devfs_allowed", "1"),
+        ]));
+        assert_eq!(allowed, Confinement { jailed: true, allow_vnet: true, allow_devfs: true });
+        assert!(allowed.require_vnet().is_ok() && allowed.require_devfs().is_ok());
+    }
+
+    #[test]
+    fn test_host_info_from_sysctl() {
+        let probe = MockProbe {
+            sysctls: HashMap::from([
+                ("kern.hostname", "web1.example.org"),
+                ("kern.osrelease", "14.1-RELEASE-p3"),
+                ("kern.version", "FreeBSD 14.1-RELEASE-p3 GENERIC\n"),
+                ("hw.machine_arch", "amd64"),
+                ("hw.model", "AMD EPYC 7402P 24-Core Processor"),
+                ("hw.ncpu", "48"),
+                ("hw.physmem", "137438953472"),
+            ]),
+            uname: HashMap::new(),
+        };
+
+        assert_eq!(HostInfo::collect(&probe), HostInfo {
+            hostname: Some("web1.example.org".to_string()),
+            os_version: Some("14.1-RELEASE-p3".to_string()),
+            kernel: Some("FreeBSD 14.1-RELEASE-p3 GENERIC".to_string()),
+            arch: Some("amd64".to_string()),
+            cpu_model: Some("AMD EPYC 7402P 24-Core Processor".to_string()),
+            cpus: Some(48),
+            memory_bytes: Some(137438953472),
+        });
+    }
+
+    #[test]
+    fn test_host_info_falls_back_to_uname() {
+        let probe = MockProbe {
+            sysctls: HashMap::from([("hw.ncpu", "not a number")]),
+            uname: HashMap::from([("-n", "build2"), ("-r", "15.0-CURRENT"), ("-v", "FreeBSD 15.0-CURRENT main-n1"), ("-p", "aarch64")]),
+        };
+
+        let host = HostInfo::collect(&probe);
+        assert_eq!(host.hostname.as_deref(), Some("build2"));
+        assert_eq!(host.os_version.as_deref(), Some("15.0-CURRENT"));
+        assert_eq!(host.kernel.as_deref(), Some("FreeBSD 15.0-CURRENT main-n1"));
+        assert_eq!(host.arch.as_deref(), Some("aarch64"));
+        assert_eq!(host.cpus, None);
+        assert_eq!(host.memory_bytes, None);
+    }
+
+    #[test]
+    fn test_system_info_aggregates_manager_state() {
+        let mut manager = JailManager::new("/tmp/kawakaze-system-test.sock");
+        manager.jails.insert("web".to_string(), Jail::create("web").unwrap());
+        manager.jails.insert("db".to_string(), Jail::create("db").unwrap());
+        for state in [ImageState::Available, ImageState::Available, ImageState::Building] {
+            let image = Image::new(format!("img-{}", manager.images.len()), Vec::new()).with_state(state);
+            manager.images.insert(image.id.clone(), image);
+        }
+
+        let host = HostInfo { arch: Some("amd64".to_string()), ..Default::default() };
+        let info = system_info(&manager, host.clone());
+
+        assert_eq!(info.host, host);
+        assert_eq!(info.jails, BTreeMap::from([("created".to_string(), 2)]));
+        assert_eq!(info.images, BTreeMap::from([("available".to_string(), 2), ("building".to_string(), 1)]));
+        assert_eq!(info.containers.total, 0);
+        assert!(info.pool.is_none());
+    }
+
+    #[test]
+    fn test_count_states() {
+        let counts = count_states(["running", "stopped", "running"]);
+        assert_eq!(counts["running"], 2);
+        assert_eq!(counts["stopped"], 1);
+        assert!(count_states([]).is_empty());
+    }
+}
@@ -0,0 +1,393 @@
+//! Admission queue for heavy background work
+//!
+//! Image builds and jail bootstraps take a [`Slot`] from their queue before
+//! doing real work, so no more than `max_concurrent_builds` /
+//! `max_concurrent_bootstraps` run at once. Requests beyond the limit wait
+//! in the queue; they are already accepted (202) and report their position
+//! through the progress endpoints until a slot frees up.
+//!
+//! Admission is first come, first served, but fair across names: each task
+//! is given a round when it is queued, one past the name's previous task
+//! (and never before the round being admitted), and tasks go in round
+//! order, then arrival order. One name queueing many tasks thus takes turns
+//! with the others instead of holding them back. Waiting tasks never change
+//! order among themselves, though a newcomer may go ahead of a name's later
+//! turns.
+//!
+//! A slot is released when it is dropped, which includes a task that
+//! panics. Waiting tasks can be cancelled; [`TaskQueue::close`] fails all of
+//! them at daemon shutdown.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use tokio::sync::oneshot;
+
+/// Why a task didn't get (or give up) a slot
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum QueueError {
+    #[error("cancelled while queued")]
+    Cancelled,
+    #[error("daemon shutdown")]
+    Shutdown,
+    #[error("task '{0}' has already started")]
+    AlreadyRunning(String),
+    #[error("no queued or running task '{0}'")]
+    NotFound(String),
+}
+
+/// A task of a queue, as listed by [`TaskQueue::tasks`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueuedTask {
+    pub id: String,
+    pub name: String,
+    /// Place in line (1 is next), or `None` once running
+    pub position: Option<usize>,
+}
+
+/// Limits how many tasks run at once and queues the rest
+pub struct TaskQueue {
+    limit: usize,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// (id, name) of the tasks holding a slot
+    running: Vec<(String, String)>,
+    waiting: Vec<Waiter>,
+    next_seq: u64,
+    /// Round of the task admitted last
+    round: u64,
+    /// Round of each name's latest task, for names at or past `round`
+    last_round: HashMap<String, u64>,
+    closed: bool,
+}
+
+struct Waiter {
+    round: u64,
+    seq: u64,
+    id: String,
+    name: String,
+    admit: oneshot::Sender<Result<Slot, QueueError>>,
+}
+
+/// Admissions and refusals to hand out once the lock is released, since a
+/// slot whose task has gone away releases itself on drop
+type Deliveries = Vec<(oneshot::Sender<Result<Slot, QueueError>>, Result<Slot, QueueError>)>;
+
+impl TaskQueue {
+    /// A queue running at most `limit` tasks at once (at least one)
+    pub fn new(limit: usize) -> Arc<Self> {
+        Arc::new(Self { limit: limit.max(1), state: Mutex::new(State::default()) })
+    }
+
+    /// Maximum number of tasks running at once
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Queue task `id` of resource `name`; the ticket resolves to a slot
+    /// when the task may start
+    pub fn enqueue(self: &Arc<Self>, id: &str, name: &str) -> Ticket {
+        let (admit, rx) = oneshot::channel();
+        let deliveries = {
+            let mut state = self.lock();
+            if state.closed {
+                vec![(admit, Err(QueueError::Shutdown))]
+            } else {
+                let seq = state.next_seq;
+                state.next_seq += 1;
+                let round = match state.last_round.get(name) {
+                    Some(last) => state.round.max(last + 1),
+                    None => state.round,
+                };
+                state.last_round.insert(name.to_string(), round);
+                state.waiting.push(Waiter { round, seq, id: id.to_string(), name: name.to_string(), admit });
+                self.admit(&mut state)
+            }
+        };
+        deliver(deliveries);
+        Ticket { rx }
+    }
+
+    /// Place of a waiting task in line (1 is next); `None` if it isn't waiting
+    pub fn position(&self, id: &str) -> Option<usize> {
+        let state = self.lock();
+        order(&state).iter().position(|&i| state.waiting[i].id == id).map(|p| p + 1)
+    }
+
+    /// Whether task `id` is running or waiting
+    pub fn contains(&self, id: &str) -> bool {
+        let state = self.lock();
+        state.running.iter().any(|(running, _)| running == id) || state.waiting.iter().any(|w| w.id == id)
+    }
+
+    /// Running tasks first, then the waiting ones in admission order
+    pub fn tasks(&self) -> Vec<QueuedTask> {
+        let state = self.lock();
+        let running = state.running.iter().map(|(id, name)| QueuedTask { id: id.clone(), name: name.clone(), position: None });
+        let waiting = order(&state).into_iter().enumerate().map(|(p, i)| {
+            let waiter = &state.waiting[i];
+            QueuedTask { id: waiter.id.clone(), name: waiter.name.clone(), position: Some(p + 1) }
+        });
+        running.chain(waiting).collect()
+    }
+
+    /// Take a waiting task out of the queue; its ticket resolves to
+    /// [`QueueError::Cancelled`]. Running tasks can't be cancelled here.
+    pub fn cancel(&self, id: &str) -> Result<(), QueueError> {
+        let waiter = {
+            let mut state = self.lock();
+            match state.waiting.iter().position(|w| w.id == id) {
+                Some(i) => state.waiting.remove(i),
+                None if state.running.iter().any(|(running, _)| running == id) => {
+                    return Err(QueueError::AlreadyRunning(id.to_string()));
+                }
+                None => return Err(QueueError::NotFound(id.to_string())),
+            }
+        };
+        let _ = waiter.admit.send(Err(QueueError::Cancelled));
+        Ok(())
+    }
+
+    /// Refuse new tasks and fail the waiting ones with
+    /// [`QueueError::Shutdown`], returning their IDs. Running tasks keep
+    /// their slots.
+    pub fn close(&self) -> Vec<String> {
+        let waiters = {
+            let mut state = self.lock();
+            state.closed = true;
+            std::mem::take(&mut state.waiting)
+        };
+        waiters
+            .into_iter()
+            .map(|waiter| {
+                let _ = waiter.admit.send(Err(QueueError::Shutdown));
+                waiter.id
+            })
+            .collect()
+    }
+
+    fn release(self: &Arc<Self>, id: &str) {
+        let deliveries = {
+            let mut state = self.lock();
+            if let Some(i) = state.running.iter().position(|(running, _)| running == id) {
+                state.running.remove(i);
+            }
+            self.admit(&mut state)
+        };
+        deliver(deliveries);
+    }
+
+    /// Move waiting tasks into free slots
+    fn admit(self: &Arc<Self>, state: &mut State) -> Deliveries {
+        let mut deliveries = Vec::new();
+        while state.running.len() < self.limit && !state.waiting.is_empty() {
+            let waiter = state.waiting.remove(order(state)[0]);
+            state.round = waiter.round;
+            let round = state.round;
+            state.last_round.retain(|_, last| *last >= round);
+            state.running.push((waiter.id.clone(), waiter.name));
+            deliveries.push((waiter.admit, Ok(Slot { queue: self.clone(), id: waiter.id })));
+        }
+        deliveries
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // A panic elsewhere never leaves the state half-updated
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Indices of the waiting tasks in admission order
+fn order(state: &State) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..state.waiting.len()).collect();
+    order.sort_by_key(|&i| (state.waiting[i].round, state.waiting[i].seq));
+    order
+}
+
+fn deliver(deliveries: Deliveries) {
+    for (admit, result) in deliveries {
+        // A task that is gone drops the slot here, which admits the next one
+        let _ = admit.send(result);
+    }
+}
+
+/// Progress text of a queued task: its place in line, or just "queued"
+/// between getting a slot and starting
+pub fn queued_status(position: Option<usize>) -> String {
+    match position {
+        Some(position) => format!("queued, position {}", position),
+        None => "queued".to_string(),
+    }
+}
+
+/// A queued task's claim on a slot
+pub struct Ticket {
+    rx: oneshot::Receiver<Result<Slot, QueueError>>,
+}
+
+impl Ticket {
+    /// Wait for the slot
+    pub async fn wait(self) -> Result<Slot, QueueError> {
+        self.rx.await.unwrap_or(Err(QueueError::Shutdown))
+    }
+}
+
+/// A running task's slot, given back on drop
+pub struct Slot {
+    queue: Arc<TaskQueue>,
+    id: String,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.queue.release(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Resolve a ticket that should already be decided
+    fn ready(ticket: Ticket) -> Result<Slot, QueueError> {
+        let mut rx = ticket.rx;
+        rx.try_recv().expect("ticket not decided yet")
+    }
+
+    fn waiting(queue: &TaskQueue) -> Vec<(String, usize)> {
+        queue.tasks().into_iter().filter_map(|t| Some((t.id, t.position?))).collect()
+    }
+
+    #[test]
+    fn test_fifo_admission_and_positions() {
+        let queue = TaskQueue::new(2);
+        let a = ready(queue.enqueue("a", "web")).unwrap();
+        let _b = ready(queue.enqueue("b", "db")).unwrap();
+        let c = queue.enqueue("c", "api");
+        let d = queue.enqueue("d", "cache");
+
+        assert_eq!(queue.position("c"), Some(1));
+        assert_eq!(queue.position("d"), Some(2));
+        assert_eq!(queue.position("a"), None);
+        assert_eq!(waiting(&queue), [("c".to_string(), 1), ("d".to_string(), 2)]);
+
+        drop(a);
+        let _c = ready(c).unwrap();
+        assert_eq!(queue.position("d"), Some(1));
+        let running: Vec<_> = queue.tasks().into_iter().filter(|t| t.position.is_none()).map(|t| t.id).collect();
+        assert_eq!(running, ["b", "c"]);
+        drop(d);
+    }
+
+    #[test]
+    fn test_fair_across_names() {
+        let queue = TaskQueue::new(1);
+        let running = ready(queue.enqueue("web-1", "web")).unwrap();
+        let _tickets = [queue.enqueue("web-2", "web"), queue.enqueue("web-3", "web"), queue.enqueue("db-1", "db")];
+
+        // db hasn't had a turn yet, so it goes before web's second and third
+        let order: Vec<_> = waiting(&queue).into_iter().map(|(id, _)| id).collect();
+        assert_eq!(order, ["db-1", "web-2", "web-3"]);
+
+        drop(running);
+        assert_eq!(queue.tasks()[0], QueuedTask { id: "db-1".to_string(), name: "db".to_string(), position: None });
+
+        // A newcomer gets its first turn before web's second
+        let _late = queue.enqueue("api-1", "api");
+        let order: Vec<_> = waiting(&queue).into_iter().map(|(id, _)| id).collect();
+        assert_eq!(order, ["api-1", "web-2", "web-3"]);
+    }
+
+    #[test]
+    fn test_cancel_while_queued() {
+        let queue = TaskQueue::new(1);
+        let running = ready(queue.enqueue("a", "web")).unwrap();
+        let b = queue.enqueue("b", "db");
+        let c = queue.enqueue("c", "api");
+
+        queue.cancel("b").unwrap();
+        assert_eq!(ready(b).err(), Some(QueueError::Cancelled));
+        assert_eq!(queue.position("c"), Some(1));
+
+        assert_eq!(queue.cancel("a"), Err(QueueError::AlreadyRunning("a".to_string())));
+        assert_eq!(queue.cancel("b"), Err(QueueError::NotFound("b".to_string())));
+
+        // The cancelled task never takes the freed slot
+        drop(running);
+        assert!(ready(c).is_ok());
+    }
+
+    #[test]
+    fn test_close_fails_waiting_tasks() {
+        let queue = TaskQueue::new(1);
+        let running = ready(queue.enqueue("a", "web")).unwrap();
+        let b = queue.enqueue("b", "db");
+
+        assert_eq!(queue.close(), ["b"]);
+        assert_eq!(ready(b).err(), Some(QueueError::Shutdown));
+        assert_eq!(ready(queue.enqueue("c", "api")).err(), Some(QueueError::Shutdown));
+        assert_eq!(QueueError::Shutdown.to_string(), "daemon shutdown");
+
+        // The running task finishes normally
+        drop(running);
+        assert!(queue.tasks().is_empty());
+    }
+
+    #[test]
+    fn test_abandoned_ticket_passes_the_slot_on() {
+        let queue = TaskQueue::new(1);
+        let running = ready(queue.enqueue("a", "web")).unwrap();
+        drop(queue.enqueue("b", "db"));
+        let c = queue.enqueue("c", "api");
+
+        drop(running);
+        assert!(ready(c).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_slot_released_on_panic() {
+        let queue = TaskQueue::new(1);
+        let first = queue.enqueue("a", "web");
+        let second = queue.enqueue("b", "db");
+
+        let task = tokio::spawn(async move {
+            let _slot = first.wait().await.unwrap();
+            panic!("build blew up");
+        });
+        assert!(task.await.unwrap_err().is_panic());
+
+        let slot = tokio::time::timeout(std::time::Duration::from_secs(1), second.wait()).await.unwrap();
+        assert!(slot.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_limit_bounds_concurrent_tasks() {
+        let queue = TaskQueue::new(2);
+        let active = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let peak = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let started = Arc::new(Mutex::new(Vec::new()));
+
+        let mut tasks = Vec::new();
+        for i in 0..6 {
+            let ticket = queue.enqueue(&format!("task-{}", i), &format!("image-{}", i));
+            let (active, peak, started) = (active.clone(), peak.clone(), started.clone());
+            tasks.push(tokio::spawn(async move {
+                let _slot = ticket.wait().await.unwrap();
+                started.lock().unwrap().push(i);
+                let now = active.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+                peak.fetch_max(now, std::sync::atomic::Ordering::SeqCst);
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+                active.fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        assert_eq!(peak.load(std::sync::atomic::Ordering::SeqCst), 2);
+        assert_eq!(*started.lock().unwrap(), [0, 1, 2, 3, 4, 5]);
+    }
+}
@@ -0,0 +1,185 @@
+//! Points in time as the API reports them
+//!
+//! A [`Timestamp`] is milliseconds since the Unix epoch, UTC. On the wire it
+//! is an RFC3339 string (`"2023-11-14T22:13:20Z"`, with a fraction only when
+//! there are milliseconds). Daemons before it sent bare Unix seconds, so an
+//! integer is still accepted when deserializing: a newer client reads an
+//! older daemon, and an older record decodes unchanged. The store keeps its
+//! integer-seconds columns and converts at the edge with
+//! [`Timestamp::from_secs`] and [`Timestamp::as_secs`].
+//!
+//! [`Timestamp::age`] renders "3 hours ago" for the CLI's tables.
+
+use std::fmt;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// A point in time, to the millisecond, UTC
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp(i64);
+
+impl Timestamp {
+    pub const EPOCH: Timestamp = Timestamp(0);
+
+    pub fn now() -> Self {
+        Self(Utc::now().timestamp_millis())
+    }
+
+    pub fn from_secs(secs: i64) -> Self {
+        Self(secs.saturating_mul(1000))
+    }
+
+    pub fn from_millis(millis: i64) -> Self {
+        Self(millis)
+    }
+
+    /// Whole seconds since the epoch, rounded down
+    pub fn as_secs(self) -> i64 {
+        self.0.div_euclid(1000)
+    }
+
+    pub fn as_millis(self) -> i64 {
+        self.0
+    }
+
+    /// Parse an RFC3339 time in any offset
+    pub fn parse(s: &str) -> Result<Self, String> {
+        DateTime::parse_from_rfc3339(s)
+            .map(|time| Self(time.timestamp_millis()))
+            .map_err(|e| format!("invalid timestamp '{}': {}", s, e))
+    }
+
+    /// RFC3339 in UTC; a time chrono can't represent falls back to Unix
+    /// seconds, which still deserializes
+    pub fn to_rfc3339(self) -> String {
+        match DateTime::<Utc>::from_timestamp_millis(self.0) {
+            Some(time) => time.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+            None => self.as_secs().to_string(),
+        }
+    }
+
+    /// How long before `now` this was: "3 hours ago", "less than a minute
+    /// ago", or "in 2 days" for a time after `now`
+    pub fn age(self, now: Timestamp) -> String {
+        let secs = now.0.saturating_sub(self.0) / 1000;
+        let future = secs < 0;
+        let secs = secs.unsigned_abs();
+
+        const UNITS: [(u64, &str); 5] =
+            [(365 * 86_400, "year"), (30 * 86_400, "month"), (86_400, "day"), (3600, "hour"), (60, "minute")];
+        let Some((n, unit)) = UNITS.iter().find(|(size, _)| secs >= *size).map(|(size, unit)| (secs / size, *unit)) else {
+            return if future { "in less than a minute" } else { "less than a minute ago" }.to_string();
+        };
+
+        let span = format!("{} {}{}", n, unit, if n == 1 { "" } else { "s" });
+        if future { format!("in {}", span) } else { format!("{} ago", span) }
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_rfc3339())
+    }
+}
+
+impl Serialize for Timestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_rfc3339())
+    }
+}
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct TimestampVisitor;
+
+        impl Visitor<'_> for TimestampVisitor {
+            type Value = Timestamp;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("an RFC3339 time or Unix seconds")
+            }
+
+            fn visit_i64<E: de::Error>(self, secs: i64) -> Result<Timestamp, E> {
+                Ok(Timestamp::from_secs(secs))
+            }
+
+            fn visit_u64<E: de::Error>(self, secs: u64) -> Result<Timestamp, E> {
+                i64::try_from(secs).map(Timestamp::from_secs).map_err(|_| E::custom("Unix seconds out of range"))
+            }
+
+            fn visit_str<E: de::Error>(self, s: &str) -> Result<Timestamp, E> {
+                Timestamp::parse(s).map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(TimestampVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_serializes_rfc3339() {
+        let ts = Timestamp::from_secs(1_700_000_000);
+        assert_eq!(serde_json::to_value(ts).unwrap(), json!("2023-11-14T22:13:20Z"));
+        assert_eq!(Timestamp::from_millis(1_700_000_000_250).to_string(), "2023-11-14T22:13:20.250Z");
+        assert_eq!(Timestamp::EPOCH.to_string(), "1970-01-01T00:00:00Z");
+    }
+
+    #[test]
+    fn test_deserializes_int_or_string() {
+        let from_int: Timestamp = serde_json::from_value(json!(1_700_000_000)).unwrap();
+        let from_str: Timestamp = serde_json::from_value(json!("2023-11-14T22:13:20Z")).unwrap();
+        assert_eq!(from_int, from_str);
+        // Any offset is taken to UTC
+        let offset: Timestamp = serde_json::from_value(json!("2023-11-15T07:13:20+09:00")).unwrap();
+        assert_eq!(offset, from_int);
+        let negative: Timestamp = serde_json::from_value(json!(-60)).unwrap();
+        assert_eq!(negative.as_secs(), -60);
+
+        assert!(serde_json::from_value::<Timestamp>(json!("yesterday")).is_err());
+        assert!(serde_json::from_value::<Timestamp>(json!(1.5)).is_err());
+        assert!(serde_json::from_value::<Timestamp>(json!(u64::MAX)).is_err());
+
+        // Round trips, milliseconds included
+        let ts = Timestamp::from_millis(1_700_000_000_250);
+        assert_eq!(serde_json::from_value::<Timestamp>(serde_json::to_value(ts).unwrap()).unwrap(), ts);
+        let optional: Option<Timestamp> = serde_json::from_value(json!(null)).unwrap();
+        assert!(optional.is_none());
+    }
+
+    #[test]
+    fn test_ordering_and_equality() {
+        let a = Timestamp::from_secs(100);
+        assert!(a < Timestamp::from_millis(100_001));
+        assert_eq!(a, Timestamp::from_millis(100_000));
+        assert!(Timestamp::from_secs(-1) < Timestamp::EPOCH);
+        // Seconds round down, before the epoch too
+        assert_eq!(Timestamp::from_millis(1_999).as_secs(), 1);
+        assert_eq!(Timestamp::from_millis(-1).as_secs(), -1);
+    }
+
+    #[test]
+    fn test_age() {
+        let now = Timestamp::from_secs(1_700_000_000);
+        let ago = |secs: i64| Timestamp::from_secs(now.as_secs() - secs).age(now);
+        assert_eq!(ago(0), "less than a minute ago");
+        assert_eq!(ago(59), "less than a minute ago");
+        assert_eq!(ago(60), "1 minute ago");
+        assert_eq!(ago(3 * 3600 + 59), "3 hours ago");
+        assert_eq!(ago(86_400), "1 day ago");
+        assert_eq!(ago(45 * 86_400), "1 month ago");
+        assert_eq!(ago(800 * 86_400), "2 years ago");
+        // Sub-second differences don't count
+        assert_eq!(Timestamp::from_millis(now.as_millis() - 999).age(now), "less than a minute ago");
+
+        // A clock that went backwards, or a time still to come
+        assert_eq!(ago(-30), "in less than a minute");
+        assert_eq!(ago(-2 * 3600), "in 2 hours");
+    }
+}
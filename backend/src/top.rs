@@ -0,0 +1,120 @@
+//! Process listing for `kawakaze top`
+//!
+//! The host's `ps -J <jail>` lists the processes of one jail without
+//! needing `ps` inside the container. Its output is parsed into
+//! [`ProcessInfo`] rows.
+
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cmdtrace::TracedCommand;
+
+/// Columns requested from `ps -o`, in [`parse_ps`] order. `command` is
+/// last since it may contain spaces.
+pub const PS_FORMAT: &str = "pid,user,%cpu,%mem,rss,etime,command";
+
+/// One process in a container
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub user: String,
+    pub cpu_percent: f64,
+    pub mem_percent: f64,
+    /// Resident set size in KiB
+    pub rss_kb: u64,
+    /// Time since the process started, as ps prints it (`[[dd-]hh:]mm:ss`)
+    pub elapsed: String,
+    /// Command with its arguments
+    pub command: String,
+}
+
+/// Arguments for `ps` listing the processes of `jail` (a JID or jail name)
+pub fn ps_args(jail: &str) -> Vec<String> {
+    ["-J", jail, "-ww", "-o", PS_FORMAT].iter().map(|s| s.to_string()).collect()
+}
+
+/// Processes of a running jail
+pub fn jail_processes(jail: &str) -> Result<Vec<ProcessInfo>, String> {
+    let output = Command::new("ps")
+        .args(ps_args(jail))
+        .traced_output()
+        .map_err(|e| format!("Failed to run ps: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("ps failed for jail {}: {}", jail, String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    parse_ps(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Parse `ps -o PS_FORMAT` output, skipping the header line
+pub fn parse_ps(output: &str) -> Result<Vec<ProcessInfo>, String> {
+    output
+        .lines()
+        .skip(1)
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let mut fields = line.split_whitespace();
+            let mut next = |name: &str| fields.next().ok_or_else(|| format!("ps line without {}: {}", name, line.trim()));
+            let pid = next("pid")?;
+            let user = next("user")?;
+            let cpu = next("%cpu")?;
+            let mem = next("%mem")?;
+            let rss = next("rss")?;
+            let elapsed = next("etime")?.to_string();
+            let command = fields.collect::<Vec<_>>().join(" ");
+
+            let number = |name: &str, value: &str| format!("invalid {} '{}' in ps line: {}", name, value, line.trim());
+            Ok(ProcessInfo {
+                pid: pid.parse().map_err(|_| number("pid", pid))?,
+                user: user.to_string(),
+                cpu_percent: cpu.parse().map_err(|_| number("%cpu", cpu))?,
+                mem_percent: mem.parse().map_err(|_| number("%mem", mem))?,
+                rss_kb: rss.parse().map_err(|_| number("rss", rss))?,
+                elapsed,
+                command,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ps() {
+        let output = "\
+  PID USER     %CPU %MEM   RSS     ELAPSED COMMAND
+ 4021 root      0.0  0.0  2404  1-02:03:04 /sbin/init --
+ 4102 root      0.0  0.1  9876       12:45 /usr/sbin/syslogd -ss
+ 4230 www      12.5  1.3 52340       00:07 nginx: worker process (nginx)
+";
+        let rows = parse_ps(output).unwrap();
+
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0], ProcessInfo {
+            pid: 4021,
+            user: "root".to_string(),
+            cpu_percent: 0.0,
+            mem_percent: 0.0,
+            rss_kb: 2404,
+            elapsed: "1-02:03:04".to_string(),
+            command: "/sbin/init --".to_string(),
+        });
+        assert_eq!(rows[2].user, "www");
+        assert_eq!(rows[2].cpu_percent, 12.5);
+        assert_eq!(rows[2].command, "nginx: worker process (nginx)");
+    }
+
+    #[test]
+    fn test_parse_ps_empty_and_invalid() {
+        assert!(parse_ps("  PID USER %CPU %MEM RSS ELAPSED COMMAND\n").unwrap().is_empty());
+        assert!(parse_ps("header\n abc root 0.0 0.0 1 00:01 sh\n").is_err());
+        assert!(parse_ps("header\n 12 root\n").is_err());
+    }
+
+    #[test]
+    fn test_ps_args() {
+        assert_eq!(ps_args("kawakaze-1a2b3c4d"), ["-J", "kawakaze-1a2b3c4d", "-ww", "-o", PS_FORMAT]);
+    }
+}
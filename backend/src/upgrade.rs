@@ -0,0 +1,556 @@
+//! Applying `freebsd-update` to a stopped jail or an image
+//!
+//! An upgrade is snapshot → run → verify → commit-or-rollback. The dataset
+//! is snapshotted as `pre-upgrade-<ts>` first; if `freebsd-update` fails or
+//! the userland version afterwards doesn't make sense, the dataset is rolled
+//! back to that snapshot. Each step goes through the `Upgrader` trait so the
+//! orchestration, and the rollback path in particular, can be tested
+//! without ZFS or network access.
+
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::image::ImageId;
+use crate::zfs::Zfs;
+
+/// Captured `freebsd-update` output kept for reporting
+const OUTPUT_LINES: usize = 200;
+
+/// Start of a pre-upgrade snapshot's name; the Unix time follows
+pub const SNAPSHOT_PREFIX: &str = "pre-upgrade-";
+
+/// What is being upgraded
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum UpgradeTarget {
+    Jail(String),
+    Image(ImageId),
+}
+
+/// Upgrade status
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpgradeStatus {
+    /// Taking the pre-upgrade snapshot
+    Snapshotting,
+    /// Running freebsd-update
+    Updating,
+    /// Checking the userland version afterwards
+    Verifying,
+    /// Upgrade applied and recorded
+    Complete,
+    /// Upgrade failed and the dataset was restored from the snapshot
+    RolledBack(String),
+    /// Upgrade failed and could not be rolled back (or failed before snapshotting)
+    Failed(String),
+}
+
+impl UpgradeStatus {
+    /// Whether the upgrade has finished, successfully or not
+    pub fn is_finished(&self) -> bool {
+        matches!(self, UpgradeStatus::Complete | UpgradeStatus::RolledBack(_) | UpgradeStatus::Failed(_))
+    }
+}
+
+/// Progress of an upgrade, as reported by the status endpoints
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct UpgradeProgress {
+    pub status: UpgradeStatus,
+    /// Human-readable description of the current step
+    pub current_step: String,
+    /// Userland version before the upgrade
+    pub previous_version: Option<String>,
+    /// Userland version after a successful upgrade
+    pub version: Option<String>,
+    /// Pre-upgrade snapshot (`dataset@pre-upgrade-<ts>`)
+    pub snapshot: Option<String>,
+    /// Last lines of freebsd-update output
+    #[serde(default)]
+    pub output: Vec<String>,
+}
+
+impl UpgradeProgress {
+    pub fn new() -> Self {
+        Self {
+            status: UpgradeStatus::Snapshotting,
+            current_step: "Upgrade starting...".to_string(),
+            previous_version: None,
+            version: None,
+            snapshot: None,
+            output: Vec::new(),
+        }
+    }
+}
+
+impl Default for UpgradeProgress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// What to upgrade
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpgradePlan {
+    /// Dataset holding the userland
+    pub dataset: String,
+    /// Where the dataset is mounted
+    pub root: PathBuf,
+    /// Release to upgrade to (`None` applies patches for the current release)
+    pub to: Option<String>,
+    /// Name of the pre-upgrade snapshot
+    pub snapshot_name: String,
+    /// Snapshot to take once the upgrade is verified (images are cloned
+    /// from a snapshot, so an upgraded image needs a new one)
+    pub commit_snapshot: Option<String>,
+}
+
+impl UpgradePlan {
+    pub fn new(dataset: String, root: PathBuf, to: Option<String>) -> Self {
+        Self {
+            dataset,
+            root,
+            to,
+            snapshot_name: format!("{}{}", SNAPSHOT_PREFIX, chrono::Utc::now().timestamp()),
+            commit_snapshot: None,
+        }
+    }
+
+    /// Take `name` as a new snapshot once the upgrade is verified
+    pub fn with_commit_snapshot(mut self, name: String) -> Self {
+        self.commit_snapshot = Some(name);
+        self
+    }
+
+    pub fn snapshot(&self) -> String {
+        format!("{}@{}", self.dataset, self.snapshot_name)
+    }
+}
+
+/// Result of a successful upgrade
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpgradeOutcome {
+    pub previous_version: String,
+    pub version: String,
+    pub snapshot: String,
+    /// Snapshot taken after the upgrade (`dataset@name`), if asked for
+    pub committed_snapshot: Option<String>,
+}
+
+/// Upgrade errors
+#[derive(Debug, thiserror::Error)]
+pub enum UpgradeError {
+    #[error("{0} not found")]
+    NotFound(String),
+    #[error("{0}")]
+    Conflict(String),
+    /// Nothing was changed
+    #[error("Upgrade not started: {0}")]
+    NotStarted(String),
+    /// The upgrade failed and the dataset was restored
+    #[error("Upgrade failed and was rolled back: {reason}")]
+    RolledBack { reason: String, output: Vec<String> },
+    /// The upgrade failed and so did the rollback
+    #[error("Upgrade failed ({reason}) and rollback to {snapshot} failed: {rollback_error}")]
+    RollbackFailed { reason: String, snapshot: String, rollback_error: String, output: Vec<String> },
+}
+
+/// The steps of an upgrade
+pub trait Upgrader {
+    /// Snapshot `dataset` as `dataset@name`
+    fn snapshot(&self, dataset: &str, name: &str) -> Result<(), String>;
+    /// Userland version of the system at `root` (e.g. `14.1-RELEASE-p3`)
+    fn userland_version(&self, root: &Path) -> Result<String, String>;
+    /// Run freebsd-update against `root`, passing each output line to `output`
+    fn run_update(&self, root: &Path, current: &str, to: Option<&str>, output: &mut dyn FnMut(&str)) -> Result<(), String>;
+    /// Roll `dataset` back to `dataset@name`
+    fn rollback(&self, dataset: &str, name: &str) -> Result<(), String>;
+}
+
+/// Parse a userland version such as `14.1-RELEASE-p3` into
+/// `(major, minor, patch)`
+pub fn parse_version(version: &str) -> Option<(u32, u32, u32)> {
+    let mut parts = version.trim().split('-');
+    let (major, minor) = parts.next()?.split_once('.')?;
+    let _branch = parts.next()?;
+    let patch = match parts.next() {
+        Some(p) => p.strip_prefix('p')?.parse().ok()?,
+        None => 0,
+    };
+    Some((major.parse().ok()?, minor.parse().ok()?, patch))
+}
+
+/// Pull `USERLAND_VERSION` out of a `freebsd-version` script
+///
+/// The script is read rather than run: it comes from the jail and the
+/// daemon runs as root on the host.
+pub fn parse_freebsd_version_script(contents: &str) -> Option<String> {
+    contents.lines().find_map(|line| {
+        let value = line.trim().strip_prefix("USERLAND_VERSION=")?;
+        let value = value.trim_matches('"');
+        (!value.is_empty()).then(|| value.to_string())
+    })
+}
+
+/// Check the userland version after an upgrade
+pub fn verify_version(before: &str, after: &str, to: Option<&str>) -> Result<(), String> {
+    let old = parse_version(before).ok_or_else(|| format!("unparseable version before upgrade: {}", before))?;
+    let new = parse_version(after).ok_or_else(|| format!("unparseable version after upgrade: {}", after))?;
+
+    if new < old {
+        return Err(format!("version went backwards: {} -> {}", before, after));
+    }
+    if let Some(to) = to
+        && !after.starts_with(to)
+    {
+        return Err(format!("expected {} after upgrade, found {}", to, after));
+    }
+
+    Ok(())
+}
+
+/// Arguments for `freebsd-update` against a jail root
+///
+/// State and config are taken from inside the root so jails don't share
+/// the host's `/var/db/freebsd-update`.
+pub fn freebsd_update_args(root: &Path, current: &str, to: Option<&str>) -> Vec<String> {
+    let root_str = root.display().to_string();
+    let mut args = vec![
+        "-b".to_string(),
+        root_str,
+        "-d".to_string(),
+        root.join("var/db/freebsd-update").display().to_string(),
+        "-f".to_string(),
+        root.join("etc/freebsd-update.conf").display().to_string(),
+        "--currently-running".to_string(),
+        current.to_string(),
+        "--not-running-from-cron".to_string(),
+    ];
+
+    match to {
+        Some(release) => args.extend(["-r".to_string(), release.to_string(), "upgrade".to_string(), "install".to_string()]),
+        None => args.extend(["fetch".to_string(), "install".to_string()]),
+    }
+
+    args
+}
+
+/// Run an upgrade, reporting each step through `report`
+pub fn run_upgrade<U: Upgrader>(
+    upgrader: &U,
+    plan: &UpgradePlan,
+    report: &mut dyn FnMut(&UpgradeProgress),
+) -> Result<UpgradeOutcome, UpgradeError> {
+    let mut progress = UpgradeProgress::new();
+
+    let before = upgrader.userland_version(&plan.root).map_err(UpgradeError::NotStarted)?;
+    progress.previous_version = Some(before.clone());
+
+    progress.current_step = format!("Snapshotting {}", plan.snapshot());
+    report(&progress);
+    upgrader.snapshot(&plan.dataset, &plan.snapshot_name).map_err(UpgradeError::NotStarted)?;
+    progress.snapshot = Some(plan.snapshot());
+
+    progress.status = UpgradeStatus::Updating;
+    progress.current_step = match plan.to {
+        Some(ref to) => format!("Upgrading {} to {}", before, to),
+        None => format!("Fetching and installing updates for {}", before),
+    };
+    report(&progress);
+
+    let updated = upgrader.run_update(&plan.root, &before, plan.to.as_deref(), &mut |line| {
+        if progress.output.len() == OUTPUT_LINES {
+            progress.output.remove(0);
+        }
+        progress.output.push(line.to_string());
+        report(&progress);
+    });
+
+    let result = updated.and_then(|()| {
+        progress.status = UpgradeStatus::Verifying;
+        progress.current_step = "Verifying userland version".to_string();
+        report(&progress);
+
+        let after = upgrader.userland_version(&plan.root)?;
+        verify_version(&before, &after, plan.to.as_deref())?;
+
+        if let Some(ref name) = plan.commit_snapshot {
+            upgrader.snapshot(&plan.dataset, name)?;
+        }
+        Ok(after)
+    });
+    let output = progress.output;
+
+    match result {
+        Ok(after) => Ok(UpgradeOutcome {
+            previous_version: before,
+            version: after,
+            snapshot: plan.snapshot(),
+            committed_snapshot: plan.commit_snapshot.as_ref().map(|name| format!("{}@{}", plan.dataset, name)),
+        }),
+        Err(reason) => match upgrader.rollback(&plan.dataset, &plan.snapshot_name) {
+            Ok(()) => Err(UpgradeError::RolledBack { reason, output }),
+            Err(rollback_error) => Err(UpgradeError::RollbackFailed {
+                reason,
+                snapshot: plan.snapshot(),
+                rollback_error,
+                output,
+            }),
+        },
+    }
+}
+
+/// Upgrader using ZFS and the host's `freebsd-update`
+pub struct ZfsUpgrader {
+    pub zfs: Zfs,
+}
+
+impl Upgrader for ZfsUpgrader {
+    fn snapshot(&self, dataset: &str, name: &str) -> Result<(), String> {
+        self.zfs.create_snapshot(dataset, name).map_err(|e| e.to_string())
+    }
+
+    fn userland_version(&self, root: &Path) -> Result<String, String> {
+        let script = root.join("bin/freebsd-version");
+        let contents = std::fs::read_to_string(&script)
+            .map_err(|e| format!("cannot read {}: {}", script.display(), e))?;
+        parse_freebsd_version_script(&contents)
+            .ok_or_else(|| format!("no USERLAND_VERSION in {}", script.display()))
+    }
+
+    fn run_update(&self, root: &Path, current: &str, to: Option<&str>, output: &mut dyn FnMut(&str)) -> Result<(), String> {
+        let args = freebsd_update_args(root, current, to);
+        let started = std::time::Instant::now();
+        let mut child = Command::new("freebsd-update")
+            .args(&args)
+            .env("PAGER", "cat")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("failed to run freebsd-update: {}", e))?;
+
+        // `upgrade` asks for confirmation before merging; answer yes
+        if let Some(mut stdin) = child.stdin.take() {
+            let _ = stdin.write_all("y\n".repeat(8).as_bytes());
+        }
+
+        if let Some(stdout) = child.stdout.take() {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                output(&line);
+            }
+        }
+
+        let result = child.wait_with_output();
+        crate::cmdtrace::record("freebsd-update", &args, started, result.as_ref().is_ok_and(|r| r.status.success()));
+        let result = result.map_err(|e| format!("freebsd-update: {}", e))?;
+        for line in String::from_utf8_lossy(&result.stderr).lines() {
+            output(line);
+        }
+
+        if result.status.success() {
+            Ok(())
+        } else {
+            Err(format!("freebsd-update exited with {}", result.status))
+        }
+    }
+
+    fn rollback(&self, dataset: &str, name: &str) -> Result<(), String> {
+        self.zfs.rollback(dataset, name).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    /// Upgrader with scripted results that records the steps it was asked to do
+    struct MockUpgrader {
+        versions: RefCell<Vec<Result<String, String>>>,
+        snapshot: Result<(), String>,
+        update: Result<(), String>,
+        rollback: Result<(), String>,
+        calls: RefCell<Vec<String>>,
+    }
+
+    impl MockUpgrader {
+        fn new(before: &str, after: &str) -> Self {
+            Self {
+                versions: RefCell::new(vec![Ok(before.to_string()), Ok(after.to_string())]),
+                snapshot: Ok(()),
+                update: Ok(()),
+                rollback: Ok(()),
+                calls: RefCell::new(Vec::new()),
+            }
+        }
+
+        fn calls(&self) -> Vec<String> {
+            self.calls.borrow().clone()
+        }
+    }
+
+    impl Upgrader for MockUpgrader {
+        fn snapshot(&self, dataset: &str, name: &str) -> Result<(), String> {
+            self.calls.borrow_mut().push(format!("snapshot {}@{}", dataset, name));
+            self.snapshot.clone()
+        }
+
+        fn userland_version(&self, _root: &Path) -> Result<String, String> {
+            self.calls.borrow_mut().push("version".to_string());
+            self.versions.borrow_mut().remove(0)
+        }
+
+        fn run_update(&self, _root: &Path, current: &str, to: Option<&str>, output: &mut dyn FnMut(&str)) -> Result<(), String> {
+            self.calls.borrow_mut().push(format!("update {} -> {:?}", current, to));
+            output("Installing updates...");
+            self.update.clone()
+        }
+
+        fn rollback(&self, dataset: &str, name: &str) -> Result<(), String> {
+            self.calls.borrow_mut().push(format!("rollback {}@{}", dataset, name));
+            self.rollback.clone()
+        }
+    }
+
+    fn plan(to: Option<&str>) -> UpgradePlan {
+        UpgradePlan {
+            dataset: "tank/jails/web".to_string(),
+            root: PathBuf::from("/usr/local/jails/web"),
+            to: to.map(str::to_string),
+            snapshot_name: "pre-upgrade-1700000000".to_string(),
+            commit_snapshot: None,
+        }
+    }
+
+    fn run(upgrader: &MockUpgrader, to: Option<&str>) -> (Result<UpgradeOutcome, UpgradeError>, Vec<UpgradeStatus>) {
+        let mut statuses = Vec::new();
+        let result = run_upgrade(upgrader, &plan(to), &mut |p| {
+            if statuses.last() != Some(&p.status) {
+                statuses.push(p.status.clone());
+            }
+        });
+        (result, statuses)
+    }
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(parse_version("14.1-RELEASE-p3"), Some((14, 1, 3)));
+        assert_eq!(parse_version("14.2-RELEASE"), Some((14, 2, 0)));
+        assert_eq!(parse_version("15.0-CURRENT"), Some((15, 0, 0)));
+        assert_eq!(parse_version("garbage"), None);
+        assert_eq!(parse_version("14.1-RELEASE-x3"), None);
+    }
+
+    #[test]
+    fn test_parse_freebsd_version_script() {
+        let script = "#!/bin/sh\nset -e\n\nUSERLAND_VERSION=\"14.1-RELEASE-p5\"\n\n: ${ROOT:=}\n";
+        assert_eq!(parse_freebsd_version_script(script).as_deref(), Some("14.1-RELEASE-p5"));
+        assert_eq!(parse_freebsd_version_script("#!/bin/sh\n"), None);
+    }
+
+    #[test]
+    fn test_verify_version() {
+        assert!(verify_version("14.1-RELEASE-p3", "14.1-RELEASE-p5", None).is_ok());
+        assert!(verify_version("14.1-RELEASE-p3", "14.1-RELEASE-p3", None).is_ok());
+        assert!(verify_version("14.1-RELEASE-p3", "14.0-RELEASE-p9", None).is_err());
+        assert!(verify_version("14.1-RELEASE", "14.2-RELEASE", Some("14.2-RELEASE")).is_ok());
+        assert!(verify_version("14.1-RELEASE", "14.1-RELEASE-p1", Some("14.2-RELEASE")).is_err());
+        assert!(verify_version("14.1-RELEASE", "???", None).is_err());
+    }
+
+    #[test]
+    fn test_freebsd_update_args() {
+        let root = Path::new("/jails/web");
+        let args = freebsd_update_args(root, "14.1-RELEASE", None);
+        assert_eq!(&args[..2], ["-b", "/jails/web"]);
+        assert!(args.contains(&"/jails/web/var/db/freebsd-update".to_string()));
+        assert_eq!(&args[args.len() - 2..], ["fetch", "install"]);
+
+        let args = freebsd_update_args(root, "14.1-RELEASE", Some("14.2-RELEASE"));
+        assert_eq!(&args[args.len() - 4..], ["-r", "14.2-RELEASE", "upgrade", "install"]);
+    }
+
+    #[test]
+    fn test_upgrade_success_commits() {
+        let upgrader = MockUpgrader::new("14.1-RELEASE-p3", "14.1-RELEASE-p5");
+        let (result, statuses) = run(&upgrader, None);
+
+        let outcome = result.unwrap();
+        assert_eq!(outcome.previous_version, "14.1-RELEASE-p3");
+        assert_eq!(outcome.version, "14.1-RELEASE-p5");
+        assert_eq!(outcome.snapshot, "tank/jails/web@pre-upgrade-1700000000");
+        assert_eq!(statuses, vec![UpgradeStatus::Snapshotting, UpgradeStatus::Updating, UpgradeStatus::Verifying]);
+        assert!(!upgrader.calls().iter().any(|c| c.starts_with("rollback")));
+    }
+
+    #[test]
+    fn test_upgrade_commit_snapshot_taken_after_verify() {
+        let upgrader = MockUpgrader::new("14.1-RELEASE-p3", "14.1-RELEASE-p5");
+        let plan = plan(None).with_commit_snapshot("web-upgraded".to_string());
+        let outcome = run_upgrade(&upgrader, &plan, &mut |_| {}).unwrap();
+
+        assert_eq!(outcome.committed_snapshot.as_deref(), Some("tank/jails/web@web-upgraded"));
+        assert_eq!(upgrader.calls().last().unwrap(), "snapshot tank/jails/web@web-upgraded");
+    }
+
+    #[test]
+    fn test_upgrade_failure_rolls_back() {
+        let mut upgrader = MockUpgrader::new("14.1-RELEASE-p3", "14.1-RELEASE-p3");
+        upgrader.update = Err("freebsd-update exited with 1".to_string());
+        let (result, _) = run(&upgrader, None);
+
+        match result {
+            Err(UpgradeError::RolledBack { reason, output }) => {
+                assert!(reason.contains("exited with 1"));
+                assert_eq!(output, vec!["Installing updates..."]);
+            }
+            other => panic!("expected rollback, got {:?}", other),
+        }
+        assert_eq!(upgrader.calls().last().unwrap(), "rollback tank/jails/web@pre-upgrade-1700000000");
+    }
+
+    #[test]
+    fn test_upgrade_bad_version_rolls_back() {
+        let upgrader = MockUpgrader::new("14.1-RELEASE-p3", "14.0-RELEASE");
+        let (result, _) = run(&upgrader, None);
+
+        assert!(matches!(result, Err(UpgradeError::RolledBack { ref reason, .. }) if reason.contains("backwards")));
+        assert!(upgrader.calls().iter().any(|c| c.starts_with("rollback")));
+    }
+
+    #[test]
+    fn test_upgrade_to_wrong_release_rolls_back() {
+        let upgrader = MockUpgrader::new("14.1-RELEASE", "14.1-RELEASE-p1");
+        let (result, _) = run(&upgrader, Some("14.2-RELEASE"));
+
+        assert!(matches!(result, Err(UpgradeError::RolledBack { .. })));
+        assert!(upgrader.calls().contains(&"update 14.1-RELEASE -> Some(\"14.2-RELEASE\")".to_string()));
+    }
+
+    #[test]
+    fn test_upgrade_rollback_failure_is_reported() {
+        let mut upgrader = MockUpgrader::new("14.1-RELEASE", "14.1-RELEASE");
+        upgrader.update = Err("boom".to_string());
+        upgrader.rollback = Err("dataset is busy".to_string());
+        let (result, _) = run(&upgrader, None);
+
+        match result {
+            Err(UpgradeError::RollbackFailed { reason, snapshot, rollback_error, .. }) => {
+                assert_eq!(reason, "boom");
+                assert_eq!(snapshot, "tank/jails/web@pre-upgrade-1700000000");
+                assert_eq!(rollback_error, "dataset is busy");
+            }
+            other => panic!("expected rollback failure, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_upgrade_snapshot_failure_changes_nothing() {
+        let mut upgrader = MockUpgrader::new("14.1-RELEASE", "14.1-RELEASE");
+        upgrader.snapshot = Err("out of space".to_string());
+        let (result, _) = run(&upgrader, None);
+
+        assert!(matches!(result, Err(UpgradeError::NotStarted(_))));
+        assert!(!upgrader.calls().iter().any(|c| c.starts_with("update") || c.starts_with("rollback")));
+    }
+}
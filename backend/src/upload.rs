@@ -0,0 +1,550 @@
+//! Chunked, resumable uploads of large files from a client
+//!
+//! Build contexts, image loads and copies into a container move hundreds of
+//! megabytes, which must not travel as one JSON field. A client announces
+//! the file with `POST /uploads` (purpose, size, sha256), then sends it in
+//! chunks of at most [`MAX_CHUNK_BYTES`] with `POST /uploads/{id}/chunk`,
+//! each saying at which offset it starts. The socket protocol is
+//! line-delimited JSON with no binary frame type, so a chunk's bytes are
+//! base64 in its request; each line stays around a megabyte and only one
+//! chunk is in memory at a time.
+//!
+//! Chunks are appended to a spool file under `uploads.spool_path` and
+//! hashed as they arrive. A chunk must start where the last one ended: the
+//! daemon answers 409 with the offset it expects otherwise, and
+//! `GET /uploads/{id}` reports it, so a client whose connection dropped
+//! asks and carries on from there. `POST /uploads/{id}/commit` checks that
+//! every byte arrived and that the digest matches, then keeps the file for
+//! the consumer named by the purpose, which claims it with
+//! [`Uploads::take`]. `DELETE /uploads/{id}` aborts.
+//!
+//! Space is reserved for the announced size when an upload is created:
+//! one upload may not exceed `uploads.max_upload_bytes`, and all uploads
+//! held together (open or committed but not yet taken) may not exceed
+//! `uploads.max_spool_bytes`. An upload untouched for
+//! `uploads.idle_timeout_secs` is dropped with its file; so is everything
+//! in the spool directory when the daemon starts, as the digest state of
+//! an unfinished upload lives in memory only.
+//!
+//! No consumer exists yet: builds take the Dockerfile inline, and there is
+//! no image load or `cp`. They will call [`Uploads::take`] with their
+//! purpose.
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::config::UploadConfig;
+
+/// Largest chunk the daemon accepts, before base64
+pub const MAX_CHUNK_BYTES: usize = 1024 * 1024;
+
+/// How often idle uploads are looked for
+pub const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Suffix of a spool file still being written
+const PARTIAL_SUFFIX: &str = ".part";
+
+/// What an upload is for, naming the consumer that takes it once committed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum UploadPurpose {
+    /// A build context for `POST /images/build`
+    BuildContext,
+    /// An image archive to load
+    ImageLoad,
+    /// A file or archive to copy into a container
+    Copy,
+}
+
+/// Where an upload is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UploadState {
+    /// Receiving chunks
+    Open,
+    /// Complete and verified, waiting for its consumer
+    Committed,
+}
+
+/// An upload as `GET /uploads/{id}` reports it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UploadStatus {
+    pub id: String,
+    pub purpose: UploadPurpose,
+    /// Announced size in bytes
+    pub size: u64,
+    /// Bytes received so far: the offset the next chunk must start at
+    pub received: u64,
+    /// Expected sha256, lowercase hex
+    pub sha256: String,
+    pub state: UploadState,
+}
+
+/// Why an upload request was refused
+#[derive(Debug, thiserror::Error)]
+pub enum UploadError {
+    #[error("Upload '{0}' not found")]
+    NotFound(String),
+    #[error("Invalid sha256 '{0}': expected 64 hex digits")]
+    InvalidDigest(String),
+    #[error("Upload of {size} bytes exceeds the limit of {limit} bytes per upload")]
+    TooLarge { size: u64, limit: u64 },
+    #[error("Upload spool is full: {size} bytes don't fit in the {available} bytes left")]
+    SpoolFull { size: u64, available: u64 },
+    #[error("Chunk of {0} bytes exceeds the limit of {MAX_CHUNK_BYTES} bytes")]
+    ChunkTooLarge(usize),
+    #[error("Chunk at offset {offset}, but upload '{id}' continues at offset {expected}")]
+    OffsetMismatch { id: String, offset: u64, expected: u64 },
+    #[error("Chunk ends at byte {end}, past the announced size of {size} bytes")]
+    Overflow { end: u64, size: u64 },
+    #[error("Upload '{id}' is incomplete: {received} of {size} bytes received")]
+    Incomplete { id: String, received: u64, size: u64 },
+    #[error("Upload '{id}' doesn't match its sha256: expected {expected}, got {actual}; it was discarded")]
+    DigestMismatch { id: String, expected: String, actual: String },
+    #[error("Upload '{0}' is already committed")]
+    Committed(String),
+    #[error("Upload '{0}' is not committed")]
+    NotCommitted(String),
+    #[error("Upload '{id}' is a {actual:?} upload, not {expected:?}")]
+    WrongPurpose { id: String, expected: UploadPurpose, actual: UploadPurpose },
+    #[error("Upload spool error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// How much may be spooled, and for how long
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UploadLimits {
+    pub max_upload_bytes: u64,
+    pub max_spool_bytes: u64,
+    pub idle_timeout: Duration,
+}
+
+impl From<&UploadConfig> for UploadLimits {
+    fn from(config: &UploadConfig) -> Self {
+        Self {
+            max_upload_bytes: config.max_upload_bytes,
+            max_spool_bytes: config.max_spool_bytes,
+            idle_timeout: Duration::from_secs(config.idle_timeout_secs),
+        }
+    }
+}
+
+/// One upload being received or waiting for its consumer
+struct Upload {
+    purpose: UploadPurpose,
+    size: u64,
+    sha256: String,
+    received: u64,
+    /// Digest of the `received` bytes
+    hasher: Sha256,
+    state: UploadState,
+    last_active: Instant,
+}
+
+/// A committed upload handed to its consumer; the file is removed when
+/// this is dropped, unless the consumer moved it away first
+#[derive(Debug)]
+pub struct SpooledFile {
+    pub path: PathBuf,
+    pub purpose: UploadPurpose,
+    pub size: u64,
+}
+
+impl Drop for SpooledFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// The uploads the daemon holds, and their spool directory
+pub struct Uploads {
+    dir: PathBuf,
+    limits: UploadLimits,
+    uploads: HashMap<String, Upload>,
+}
+
+impl Uploads {
+    pub fn new(dir: impl Into<PathBuf>, limits: UploadLimits) -> Self {
+        Self { dir: dir.into(), limits, uploads: HashMap::new() }
+    }
+
+    pub fn from_config(config: &UploadConfig) -> Self {
+        Self::new(&config.spool_path, config.into())
+    }
+
+    /// Spool directory
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Bytes reserved by the uploads held
+    pub fn reserved(&self) -> u64 {
+        self.uploads.values().map(|upload| upload.size).sum()
+    }
+
+    /// Remove every file left in the spool directory, as at daemon start
+    /// when no upload can be resumed. Returns how many were removed.
+    pub fn clear_spool(&mut self) -> std::io::Result<usize> {
+        self.uploads.clear();
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e),
+        };
+        let mut removed = 0;
+        for entry in entries {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                fs::remove_file(entry.path())?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Announce an upload of `size` bytes with digest `sha256`, reserving
+    /// its space
+    pub fn create(&mut self, purpose: UploadPurpose, size: u64, sha256: &str, now: Instant) -> Result<UploadStatus, UploadError> {
+        let sha256 = parse_digest(sha256)?;
+        if size > self.limits.max_upload_bytes {
+            return Err(UploadError::TooLarge { size, limit: self.limits.max_upload_bytes });
+        }
+        let available = self.limits.max_spool_bytes.saturating_sub(self.reserved());
+        if size > available {
+            return Err(UploadError::SpoolFull { size, available });
+        }
+
+        fs::create_dir_all(&self.dir)?;
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        File::options().write(true).create_new(true).open(self.partial_path(&id))?;
+
+        let upload = Upload {
+            purpose,
+            size,
+            sha256,
+            received: 0,
+            hasher: Sha256::new(),
+            state: UploadState::Open,
+            last_active: now,
+        };
+        let status = upload.status(&id);
+        self.uploads.insert(id, upload);
+        Ok(status)
+    }
+
+    /// Where upload `id` is, counting the question as activity
+    pub fn status(&mut self, id: &str, now: Instant) -> Result<UploadStatus, UploadError> {
+        let upload = self.uploads.get_mut(id).ok_or_else(|| UploadError::NotFound(id.to_string()))?;
+        upload.last_active = now;
+        Ok(upload.status(id))
+    }
+
+    /// Append `data`, which starts at `offset` of the file
+    pub fn append(&mut self, id: &str, offset: u64, data: &[u8], now: Instant) -> Result<UploadStatus, UploadError> {
+        let path = self.partial_path(id);
+        let upload = self.uploads.get_mut(id).ok_or_else(|| UploadError::NotFound(id.to_string()))?;
+        upload.last_active = now;
+        if upload.state == UploadState::Committed {
+            return Err(UploadError::Committed(id.to_string()));
+        }
+        if data.len() > MAX_CHUNK_BYTES {
+            return Err(UploadError::ChunkTooLarge(data.len()));
+        }
+        if offset != upload.received {
+            return Err(UploadError::OffsetMismatch { id: id.to_string(), offset, expected: upload.received });
+        }
+        let end = offset + data.len() as u64;
+        if end > upload.size {
+            return Err(UploadError::Overflow { end, size: upload.size });
+        }
+
+        // A write that fails halfway leaves the file as it was, so the
+        // chunk can be sent again at the same offset
+        let mut file = OpenOptions::new().write(true).open(&path)?;
+        let written = file.seek(SeekFrom::Start(offset)).and_then(|_| file.write_all(data));
+        if let Err(e) = written {
+            let _ = file.set_len(offset);
+            return Err(e.into());
+        }
+
+        upload.hasher.update(data);
+        upload.received = end;
+        Ok(upload.status(id))
+    }
+
+    /// Check that upload `id` is complete and matches its digest, and keep
+    /// it for its consumer. A mismatch discards it.
+    pub fn commit(&mut self, id: &str, now: Instant) -> Result<UploadStatus, UploadError> {
+        let upload = self.uploads.get_mut(id).ok_or_else(|| UploadError::NotFound(id.to_string()))?;
+        upload.last_active = now;
+        if upload.state == UploadState::Committed {
+            return Err(UploadError::Committed(id.to_string()));
+        }
+        if upload.received != upload.size {
+            return Err(UploadError::Incomplete { id: id.to_string(), received: upload.received, size: upload.size });
+        }
+
+        let actual = hex::encode(upload.hasher.clone().finalize());
+        if actual != upload.sha256 {
+            let expected = upload.sha256.clone();
+            self.discard(id);
+            return Err(UploadError::DigestMismatch { id: id.to_string(), expected, actual });
+        }
+
+        fs::rename(self.partial_path(id), self.dir.join(id))?;
+        let upload = self.uploads.get_mut(id).ok_or_else(|| UploadError::NotFound(id.to_string()))?;
+        upload.state = UploadState::Committed;
+        Ok(upload.status(id))
+    }
+
+    /// Hand committed upload `id` to the consumer for `purpose`, which
+    /// then owns the file and its space
+    pub fn take(&mut self, id: &str, purpose: UploadPurpose) -> Result<SpooledFile, UploadError> {
+        let upload = self.uploads.get(id).ok_or_else(|| UploadError::NotFound(id.to_string()))?;
+        if upload.state != UploadState::Committed {
+            return Err(UploadError::NotCommitted(id.to_string()));
+        }
+        if upload.purpose != purpose {
+            return Err(UploadError::WrongPurpose { id: id.to_string(), expected: purpose, actual: upload.purpose });
+        }
+        let upload = self.uploads.remove(id).ok_or_else(|| UploadError::NotFound(id.to_string()))?;
+        Ok(SpooledFile { path: self.dir.join(id), purpose: upload.purpose, size: upload.size })
+    }
+
+    /// Drop upload `id` and its file
+    pub fn abort(&mut self, id: &str) -> Result<(), UploadError> {
+        if !self.uploads.contains_key(id) {
+            return Err(UploadError::NotFound(id.to_string()));
+        }
+        self.discard(id);
+        Ok(())
+    }
+
+    /// Drop the uploads idle for longer than the timeout at `now`, open or
+    /// committed; returns their IDs
+    pub fn expire_idle(&mut self, now: Instant) -> Vec<String> {
+        let timeout = self.limits.idle_timeout;
+        let mut expired: Vec<String> = self.uploads.iter()
+            .filter(|(_, upload)| now.saturating_duration_since(upload.last_active) > timeout)
+            .map(|(id, _)| id.clone())
+            .collect();
+        expired.sort();
+        for id in &expired {
+            self.discard(id);
+        }
+        expired
+    }
+
+    fn discard(&mut self, id: &str) {
+        if self.uploads.remove(id).is_some() {
+            // Whichever of the two it is by now
+            let _ = fs::remove_file(self.partial_path(id));
+            let _ = fs::remove_file(self.dir.join(id));
+        }
+    }
+
+    fn partial_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}{}", id, PARTIAL_SUFFIX))
+    }
+}
+
+impl std::fmt::Debug for Uploads {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Uploads")
+            .field("dir", &self.dir)
+            .field("uploads", &self.uploads.len())
+            .field("reserved", &self.reserved())
+            .finish()
+    }
+}
+
+impl Upload {
+    fn status(&self, id: &str) -> UploadStatus {
+        UploadStatus {
+            id: id.to_string(),
+            purpose: self.purpose,
+            size: self.size,
+            received: self.received,
+            sha256: self.sha256.clone(),
+            state: self.state,
+        }
+    }
+}
+
+/// A sha256 as lowercase hex, with or without a `sha256:` prefix
+fn parse_digest(digest: &str) -> Result<String, UploadError> {
+    let hex = digest.strip_prefix("sha256:").unwrap_or(digest);
+    if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(UploadError::InvalidDigest(digest.to_string()));
+    }
+    Ok(hex.to_ascii_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MB: u64 = 1024 * 1024;
+
+    fn uploads(dir: &Path, max_upload_bytes: u64, max_spool_bytes: u64) -> Uploads {
+        Uploads::new(dir, UploadLimits { max_upload_bytes, max_spool_bytes, idle_timeout: Duration::from_secs(60) })
+    }
+
+    fn digest(data: &[u8]) -> String {
+        hex::encode(Sha256::digest(data))
+    }
+
+    #[test]
+    fn test_upload_in_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut uploads = uploads(dir.path(), MB, 4 * MB);
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let now = Instant::now();
+
+        let status = uploads.create(UploadPurpose::BuildContext, data.len() as u64, &format!("sha256:{}", digest(&data)), now).unwrap();
+        assert_eq!((status.received, status.state), (0, UploadState::Open));
+        assert_eq!(status.sha256, digest(&data));
+        let id = status.id;
+
+        let mut offset = 0;
+        for chunk in data.chunks(30_000) {
+            offset = uploads.append(&id, offset, chunk, now).unwrap().received;
+        }
+        assert_eq!(offset, data.len() as u64);
+        assert!(dir.path().join(format!("{}.part", id)).exists());
+
+        let status = uploads.commit(&id, now).unwrap();
+        assert_eq!(status.state, UploadState::Committed);
+        assert!(matches!(uploads.append(&id, offset, b"more", now), Err(UploadError::Committed(_))));
+        assert!(matches!(uploads.take(&id, UploadPurpose::ImageLoad), Err(UploadError::WrongPurpose { .. })));
+
+        let spooled = uploads.take(&id, UploadPurpose::BuildContext).unwrap();
+        assert_eq!(fs::read(&spooled.path).unwrap(), data);
+        assert_eq!(uploads.reserved(), 0);
+        let path = spooled.path.clone();
+        drop(spooled);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_resume_from_reported_offset() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut uploads = uploads(dir.path(), MB, MB);
+        let data = b"0123456789abcdefghij";
+        let now = Instant::now();
+        let id = uploads.create(UploadPurpose::Copy, data.len() as u64, &digest(data), now).unwrap().id;
+
+        uploads.append(&id, 0, &data[..8], now).unwrap();
+        // The client lost the answer to this one and sends it again
+        uploads.append(&id, 8, &data[8..12], now).unwrap();
+        let err = uploads.append(&id, 8, &data[8..12], now).unwrap_err();
+        assert!(matches!(err, UploadError::OffsetMismatch { offset: 8, expected: 12, .. }), "{}", err);
+        // A gap is refused the same way
+        assert!(matches!(uploads.append(&id, 16, &data[16..], now), Err(UploadError::OffsetMismatch { expected: 12, .. })));
+
+        // It asks where to carry on
+        let offset = uploads.status(&id, now).unwrap().received;
+        assert_eq!(offset, 12);
+        uploads.append(&id, offset, &data[offset as usize..], now).unwrap();
+        uploads.commit(&id, now).unwrap();
+        assert_eq!(fs::read(uploads.take(&id, UploadPurpose::Copy).unwrap().path.clone()).unwrap(), data);
+    }
+
+    #[test]
+    fn test_commit_checks_size_and_digest() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut uploads = uploads(dir.path(), MB, MB);
+        let now = Instant::now();
+
+        let id = uploads.create(UploadPurpose::ImageLoad, 6, &digest(b"hello!"), now).unwrap().id;
+        uploads.append(&id, 0, b"hello", now).unwrap();
+        assert!(matches!(uploads.commit(&id, now), Err(UploadError::Incomplete { received: 5, size: 6, .. })));
+        assert!(matches!(uploads.append(&id, 5, b"?!", now), Err(UploadError::Overflow { end: 7, size: 6 })));
+        assert!(matches!(uploads.take(&id, UploadPurpose::ImageLoad), Err(UploadError::NotCommitted(_))));
+
+        // Right size, wrong bytes: discarded
+        uploads.append(&id, 5, b"?", now).unwrap();
+        let err = uploads.commit(&id, now).unwrap_err();
+        assert!(matches!(err, UploadError::DigestMismatch { .. }), "{}", err);
+        assert!(matches!(uploads.status(&id, now), Err(UploadError::NotFound(_))));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        // An empty file is an upload like any other
+        let id = uploads.create(UploadPurpose::ImageLoad, 0, &digest(b""), now).unwrap().id;
+        assert_eq!(uploads.commit(&id, now).unwrap().state, UploadState::Committed);
+    }
+
+    #[test]
+    fn test_quotas() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut uploads = uploads(dir.path(), 4 * MB, 6 * MB);
+        let now = Instant::now();
+        let sha = digest(b"");
+
+        assert!(matches!(
+            uploads.create(UploadPurpose::Copy, 4 * MB + 1, &sha, now),
+            Err(UploadError::TooLarge { limit, .. }) if limit == 4 * MB
+        ));
+        let first = uploads.create(UploadPurpose::Copy, 4 * MB, &sha, now).unwrap().id;
+        // Space is reserved up front, before any chunk arrives
+        let err = uploads.create(UploadPurpose::Copy, 3 * MB, &sha, now).unwrap_err();
+        assert!(matches!(err, UploadError::SpoolFull { available, .. } if available == 2 * MB), "{}", err);
+        uploads.create(UploadPurpose::Copy, 2 * MB, &sha, now).unwrap();
+        assert_eq!(uploads.reserved(), 6 * MB);
+
+        // Aborting gives it back
+        uploads.abort(&first).unwrap();
+        assert!(matches!(uploads.abort(&first), Err(UploadError::NotFound(_))));
+        uploads.create(UploadPurpose::Copy, 3 * MB, &sha, now).unwrap();
+
+        assert!(matches!(uploads.create(UploadPurpose::Copy, 1, "abc", now), Err(UploadError::InvalidDigest(_))));
+        let open = uploads.create(UploadPurpose::Copy, MB, &sha, now).unwrap().id;
+        let too_big = vec![0u8; MAX_CHUNK_BYTES + 1];
+        assert!(matches!(uploads.append(&open, 0, &too_big, now), Err(UploadError::ChunkTooLarge(_))));
+    }
+
+    #[test]
+    fn test_idle_uploads_expire() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut uploads = uploads(dir.path(), MB, MB);
+        let start = Instant::now();
+
+        let idle = uploads.create(UploadPurpose::Copy, 4, &digest(b"idle"), start).unwrap().id;
+        let committed = uploads.create(UploadPurpose::Copy, 0, &digest(b""), start).unwrap().id;
+        uploads.commit(&committed, start).unwrap();
+        let busy = uploads.create(UploadPurpose::Copy, 4, &digest(b"busy"), start).unwrap().id;
+
+        // Any request, a status query included, keeps an upload alive
+        uploads.append(&busy, 0, b"bu", start + Duration::from_secs(50)).unwrap();
+        assert!(uploads.expire_idle(start + Duration::from_secs(60)).is_empty());
+        uploads.status(&busy, start + Duration::from_secs(100)).unwrap();
+
+        let mut expected = vec![idle.clone(), committed.clone()];
+        expected.sort();
+        assert_eq!(uploads.expire_idle(start + Duration::from_secs(120)), expected);
+        assert!(matches!(uploads.status(&idle, start), Err(UploadError::NotFound(_))));
+        assert!(!dir.path().join(format!("{}.part", idle)).exists());
+        assert!(!dir.path().join(&committed).exists());
+        assert_eq!(uploads.reserved(), 4);
+    }
+
+    #[test]
+    fn test_clear_spool() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool = dir.path().join("uploads");
+        let mut uploads = uploads(&spool, MB, MB);
+        assert_eq!(uploads.clear_spool().unwrap(), 0);
+
+        uploads.create(UploadPurpose::Copy, 4, &digest(b"left"), Instant::now()).unwrap();
+        fs::write(spool.join("0123abcd"), b"committed before a restart").unwrap();
+        assert_eq!(uploads.clear_spool().unwrap(), 2);
+        assert_eq!(uploads.reserved(), 0);
+        assert_eq!(fs::read_dir(&spool).unwrap().count(), 0);
+    }
+}
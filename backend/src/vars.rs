@@ -0,0 +1,328 @@
+//! Dockerfile variable expansion
+//!
+//! Each instruction's arguments (never the keyword) are expanded against
+//! the variables visible at that point in the Dockerfile, following
+//! Docker's precedence:
+//!
+//! 1. `ENV` values, either inherited from the `FROM` image or set by an
+//!    earlier instruction, win over everything else.
+//! 2. A `--build-arg` value comes next, whether or not the Dockerfile
+//!    declares it with `ARG`.
+//! 3. An `ARG NAME=default` default applies when no build arg was given.
+//!
+//! A single instruction sees the variables as they were before it ran, so
+//! `ENV A=1 B=$A` does not see the new `A`. `RUN` leaves anything it can't
+//! resolve to the shell; `CMD`, `ENTRYPOINT` and `SHELL` are not expanded
+//! at all, as in Docker.
+
+use std::collections::HashMap;
+
+/// Variable expansion errors
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ExpandError {
+    #[error("Undefined variable '{0}'")]
+    Undefined(String),
+    #[error("Unterminated '${{' in '{0}'")]
+    Unterminated(String),
+    #[error("Bad substitution '{0}'")]
+    BadSubstitution(String),
+}
+
+/// What to do with a reference to a variable that isn't set
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Undefined {
+    /// Leave the reference (and any unsupported `${...}` form) as written
+    Literal,
+    /// Fail with `ExpandError::Undefined`
+    Error,
+}
+
+/// Variables visible to a Dockerfile instruction
+#[derive(Debug, Clone, Default)]
+pub struct BuildVars {
+    build_args: HashMap<String, String>,
+    arg_defaults: HashMap<String, String>,
+    env: HashMap<String, String>,
+}
+
+impl BuildVars {
+    /// Variables for a build given `--build-arg` values
+    pub fn new(build_args: HashMap<String, String>) -> Self {
+        Self { build_args, ..Default::default() }
+    }
+
+    /// Start from the ENV of the image being built on
+    pub fn with_env(mut self, env: &HashMap<String, String>) -> Self {
+        self.env.extend(env.iter().map(|(k, v)| (k.clone(), v.clone())));
+        self
+    }
+
+    /// Record an `ARG NAME[=default]`
+    pub fn declare_arg(&mut self, name: &str, default: Option<String>) {
+        if let Some(default) = default {
+            self.arg_defaults.insert(name.to_string(), default);
+        }
+    }
+
+    /// Record values set by an `ENV` instruction
+    pub fn set_env(&mut self, env: &HashMap<String, String>) {
+        self.env.extend(env.iter().map(|(k, v)| (k.clone(), v.clone())));
+    }
+
+    /// Value of a variable, by Docker's precedence
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.env
+            .get(name)
+            .or_else(|| self.build_args.get(name))
+            .or_else(|| self.arg_defaults.get(name))
+            .map(String::as_str)
+    }
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+fn is_name(s: &str) -> bool {
+    s.chars().next().is_some_and(is_name_start) && s.chars().all(is_name_char)
+}
+
+/// Expand `$NAME`, `${NAME}` and `${NAME:-default}` in `input`
+///
+/// `\$` is a literal `$`; any other backslash is kept as written. A `$`
+/// not followed by a name or `{` (`$1`, `$(`, a trailing `$`) is literal.
+/// Names are matched greedily, so `$KEYSUFFIX` never expands `$KEY`.
+pub fn expand(input: &str, vars: &BuildVars, undefined: Undefined) -> Result<String, ExpandError> {
+    let mut out = String::with_capacity(input.len());
+    let mut rest = input;
+
+    while let Some(pos) = rest.find(['$', '\\']) {
+        out.push_str(&rest[..pos]);
+        let tail = &rest[pos..];
+
+        if let Some(after) = tail.strip_prefix("\\$") {
+            out.push('$');
+            rest = after;
+            continue;
+        }
+        if let Some(after) = tail.strip_prefix('\\') {
+            out.push('\\');
+            rest = after;
+            continue;
+        }
+
+        let after_dollar = &tail[1..];
+        if let Some(body_start) = after_dollar.strip_prefix('{') {
+            let end = body_start.find('}').ok_or_else(|| ExpandError::Unterminated(input.to_string()))?;
+            let body = &body_start[..end];
+            let reference = &tail[..end + 3];
+            rest = &body_start[end + 1..];
+
+            let (name, default) = match body.split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (body, None),
+            };
+            if !is_name(name) {
+                match undefined {
+                    Undefined::Literal => out.push_str(reference),
+                    Undefined::Error => return Err(ExpandError::BadSubstitution(reference.to_string())),
+                }
+                continue;
+            }
+
+            // Like the shell, `:-` also replaces a value that is set but empty
+            match (vars.get(name), default) {
+                (Some(value), None) => out.push_str(value),
+                (Some(value), Some(_)) if !value.is_empty() => out.push_str(value),
+                (_, Some(default)) => out.push_str(&expand(default, vars, undefined)?),
+                (None, None) => match undefined {
+                    Undefined::Literal => out.push_str(reference),
+                    Undefined::Error => return Err(ExpandError::Undefined(name.to_string())),
+                },
+            }
+            continue;
+        }
+
+        let name_len = if after_dollar.starts_with(is_name_start) {
+            after_dollar.find(|c| !is_name_char(c)).unwrap_or(after_dollar.len())
+        } else {
+            0
+        };
+        if name_len == 0 {
+            out.push('$');
+            rest = after_dollar;
+            continue;
+        }
+
+        let name = &after_dollar[..name_len];
+        rest = &after_dollar[name_len..];
+        match vars.get(name) {
+            Some(value) => out.push_str(value),
+            None => match undefined {
+                Undefined::Literal => out.push_str(&tail[..name_len + 1]),
+                Undefined::Error => return Err(ExpandError::Undefined(name.to_string())),
+            },
+        }
+    }
+
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Expand the arguments of one Dockerfile line, keeping its keyword
+///
+/// `strict` turns references to undefined variables into errors, except in
+/// `RUN` where they are left for the shell to resolve.
+pub fn expand_instruction(line: &str, vars: &BuildVars, strict: bool) -> Result<String, ExpandError> {
+    let (keyword, args) = match line.split_once(' ') {
+        Some((keyword, args)) => (keyword, args),
+        None => return Ok(line.to_string()),
+    };
+
+    let undefined = match keyword.to_uppercase().as_str() {
+        "CMD" | "ENTRYPOINT" | "SHELL" => return Ok(line.to_string()),
+        "RUN" => Undefined::Literal,
+        _ if strict => Undefined::Error,
+        _ => Undefined::Literal,
+    };
+
+    Ok(format!("{} {}", keyword, expand(args, vars, undefined)?))
+}
+
+/// Split `ARG NAME[=default]` arguments into the name and default
+pub fn parse_arg(args: &str) -> Option<(String, Option<String>)> {
+    let args = args.trim();
+    let (name, default) = match args.split_once('=') {
+        Some((name, default)) => (name, Some(default.trim_matches('"').to_string())),
+        None => (args, None),
+    };
+    is_name(name).then(|| (name.to_string(), default))
+}
+
+/// Parse `ENV` arguments: `KEY=value...` pairs, or the legacy `KEY value`
+pub fn parse_env(args: &str) -> Result<HashMap<String, String>, String> {
+    let args = args.trim();
+    let first = args.split_whitespace().next().unwrap_or_default();
+
+    if !first.contains('=') {
+        let (key, value) = args.split_once(char::is_whitespace).ok_or("ENV requires a value")?;
+        if !is_name(key) {
+            return Err(format!("Invalid ENV name '{}'", key));
+        }
+        return Ok(HashMap::from([(key.to_string(), value.trim().to_string())]));
+    }
+
+    let words = shell_words::split(args).map_err(|e| format!("Invalid ENV syntax: {}", e))?;
+    words
+        .iter()
+        .map(|word| match word.split_once('=') {
+            Some((key, value)) if is_name(key) => Ok((key.to_string(), value.to_string())),
+            _ => Err(format!("Invalid ENV pair '{}'", word)),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> BuildVars {
+        let build_args = HashMap::from([
+            ("KEY".to_string(), "k".to_string()),
+            ("VERSION".to_string(), "1.0.0".to_string()),
+            ("EMPTY".to_string(), String::new()),
+        ]);
+        BuildVars::new(build_args).with_env(&HashMap::from([("PREFIX".to_string(), "/opt".to_string())]))
+    }
+
+    #[test]
+    fn test_expand_table() {
+        let vars = vars();
+        let cases = [
+            // (input, lenient, strict)
+            ("plain text", Ok("plain text"), Ok("plain text")),
+            ("$PREFIX/app", Ok("/opt/app"), Ok("/opt/app")),
+            ("${PREFIX}/app", Ok("/opt/app"), Ok("/opt/app")),
+            ("app-${VERSION}.txz", Ok("app-1.0.0.txz"), Ok("app-1.0.0.txz")),
+            ("$KEY$VERSION", Ok("k1.0.0"), Ok("k1.0.0")),
+            ("${KEY}SUFFIX", Ok("kSUFFIX"), Ok("kSUFFIX")),
+            ("$KEYSUFFIX", Ok("$KEYSUFFIX"), Err(ExpandError::Undefined("KEYSUFFIX".into()))),
+            ("$KEY-suffix", Ok("k-suffix"), Ok("k-suffix")),
+            ("${MISSING:-fallback}", Ok("fallback"), Ok("fallback")),
+            ("${KEY:-fallback}", Ok("k"), Ok("k")),
+            ("${EMPTY:-fallback}", Ok("fallback"), Ok("fallback")),
+            ("${EMPTY}", Ok(""), Ok("")),
+            ("${MISSING:-$PREFIX/lib}", Ok("/opt/lib"), Ok("/opt/lib")),
+            ("${MISSING:-}", Ok(""), Ok("")),
+            ("\\$PREFIX", Ok("$PREFIX"), Ok("$PREFIX")),
+            ("\\${PREFIX}", Ok("${PREFIX}"), Ok("${PREFIX}")),
+            ("C:\\dir", Ok("C:\\dir"), Ok("C:\\dir")),
+            ("$MISSING", Ok("$MISSING"), Err(ExpandError::Undefined("MISSING".into()))),
+            ("a ${MISSING} b", Ok("a ${MISSING} b"), Err(ExpandError::Undefined("MISSING".into()))),
+            ("cost $5", Ok("cost $5"), Ok("cost $5")),
+            ("trailing $", Ok("trailing $"), Ok("trailing $")),
+            ("$(pwd)", Ok("$(pwd)"), Ok("$(pwd)")),
+            ("${1bad}", Ok("${1bad}"), Err(ExpandError::BadSubstitution("${1bad}".into()))),
+            ("${PREFIX", Err(ExpandError::Unterminated("${PREFIX".into())), Err(ExpandError::Unterminated("${PREFIX".into()))),
+        ];
+
+        for (input, lenient, strict) in cases {
+            assert_eq!(expand(input, &vars, Undefined::Literal).as_deref(), lenient.as_ref().copied(), "lenient: {}", input);
+            assert_eq!(expand(input, &vars, Undefined::Error).as_deref(), strict.as_ref().copied(), "strict: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_precedence() {
+        let mut vars = BuildVars::new(HashMap::from([("V".to_string(), "from-build-arg".to_string())]));
+        vars.declare_arg("V", Some("from-default".to_string()));
+        vars.declare_arg("D", Some("default-only".to_string()));
+        assert_eq!(vars.get("V"), Some("from-build-arg"));
+        assert_eq!(vars.get("D"), Some("default-only"));
+
+        vars.set_env(&HashMap::from([("V".to_string(), "from-env".to_string())]));
+        assert_eq!(vars.get("V"), Some("from-env"));
+    }
+
+    #[test]
+    fn test_expand_instruction_by_keyword() {
+        let vars = vars();
+
+        assert_eq!(expand_instruction("WORKDIR $PREFIX/app", &vars, false).unwrap(), "WORKDIR /opt/app");
+        assert_eq!(expand_instruction("RUN echo $PREFIX $HOME", &vars, true).unwrap(), "RUN echo /opt $HOME");
+        assert_eq!(expand_instruction("CMD [\"echo\", \"$PREFIX\"]", &vars, true).unwrap(), "CMD [\"echo\", \"$PREFIX\"]");
+        assert!(matches!(expand_instruction("COPY $SRC /app", &vars, true), Err(ExpandError::Undefined(name)) if name == "SRC"));
+        assert_eq!(expand_instruction("COPY $SRC /app", &vars, false).unwrap(), "COPY $SRC /app");
+        // The keyword itself is never expanded
+        assert_eq!(expand_instruction("$KEY value", &vars, false).unwrap(), "$KEY value");
+    }
+
+    #[test]
+    fn test_parse_env_forms() {
+        let env = parse_env("PATH=/usr/bin DEBUG=true").unwrap();
+        assert_eq!(env.get("PATH").map(String::as_str), Some("/usr/bin"));
+        assert_eq!(env.get("DEBUG").map(String::as_str), Some("true"));
+
+        let env = parse_env("GREETING=\"hello world\" EMPTY=").unwrap();
+        assert_eq!(env.get("GREETING").map(String::as_str), Some("hello world"));
+        assert_eq!(env.get("EMPTY").map(String::as_str), Some(""));
+
+        let env = parse_env("MOTD hello  there").unwrap();
+        assert_eq!(env.get("MOTD").map(String::as_str), Some("hello  there"));
+
+        assert!(parse_env("ALONE").is_err());
+        assert!(parse_env("A=1 junk").is_err());
+    }
+
+    #[test]
+    fn test_parse_arg() {
+        assert_eq!(parse_arg("VERSION"), Some(("VERSION".to_string(), None)));
+        assert_eq!(parse_arg("VERSION=1.2"), Some(("VERSION".to_string(), Some("1.2".to_string()))));
+        assert_eq!(parse_arg("NAME=\"a b\""), Some(("NAME".to_string(), Some("a b".to_string()))));
+        assert_eq!(parse_arg("1BAD"), None);
+    }
+}
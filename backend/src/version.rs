@@ -0,0 +1,162 @@
+//! Which kawakaze version wrote a record
+//!
+//! Jail, image and container rows record the version that created them
+//! (`created_by_version`) and the one that last wrote them
+//! (`last_written_version`), stamped by the store on every insert and
+//! update. After rolling back an upgrade, rows written by the newer
+//! version may be in a format this one only half understands, so they are
+//! loaded read-only: listed and inspected with a `readonly_reason`, but
+//! starting, stopping, removing or updating them is refused with 409
+//! `VERSION_SKEW`. Upgrading kawakaze again lifts that; so does starting
+//! the daemon with `--force-downgrade-writes`, which restamps the rows with
+//! the running version (see `JailManager::set_force_downgrade_writes`).
+//!
+//! Rows from before the columns existed have no version and are treated
+//! as written by an older kawakaze.
+
+use std::cmp::Ordering;
+
+/// Version of the running daemon
+pub const CURRENT: &str = env!("CARGO_PKG_VERSION");
+
+/// `readonly_reason` of a record written by a newer version
+pub const READONLY_REASON: &str = "written by newer version";
+
+/// Versions recorded on a row
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Provenance {
+    pub created_by: Option<String>,
+    pub last_written: Option<String>,
+}
+
+impl Provenance {
+    /// The version that last wrote the row, if it is newer than `running`
+    pub fn newer_than(&self, running: &str) -> Option<String> {
+        self.last_written.clone().filter(|version| is_newer(version, running))
+    }
+}
+
+/// A parsed `MAJOR.MINOR.PATCH[-PRE][+BUILD]` version
+#[derive(Debug, PartialEq, Eq)]
+struct Version<'a> {
+    release: [u64; 3],
+    pre: Option<&'a str>,
+}
+
+fn parse(version: &str) -> Option<Version<'_>> {
+    // Build metadata doesn't take part in precedence
+    let version = version.split_once('+').map_or(version, |(v, _)| v);
+    let (release, pre) = match version.split_once('-') {
+        Some((release, pre)) if !pre.is_empty() => (release, Some(pre)),
+        Some(_) => return None,
+        None => (version, None),
+    };
+
+    let mut parts = release.split('.').map(|part| part.parse::<u64>().ok());
+    let release = [parts.next()??, parts.next()??, parts.next()??];
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(Version { release, pre })
+}
+
+/// Semver precedence of two pre-release tags: numeric identifiers compare
+/// numerically and sort before alphanumeric ones, and a shorter tag sorts
+/// before a longer one it is a prefix of
+fn compare_pre(a: &str, b: &str) -> Ordering {
+    let mut a = a.split('.');
+    let mut b = b.split('.');
+    loop {
+        let ordering = match (a.next(), b.next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) => match (x.parse::<u64>(), y.parse::<u64>()) {
+                (Ok(x), Ok(y)) => x.cmp(&y),
+                (Ok(_), Err(_)) => Ordering::Less,
+                (Err(_), Ok(_)) => Ordering::Greater,
+                (Err(_), Err(_)) => x.cmp(y),
+            },
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+/// Semver precedence of two versions; `None` if either doesn't parse
+pub fn compare(a: &str, b: &str) -> Option<Ordering> {
+    let (a, b) = (parse(a)?, parse(b)?);
+    Some(a.release.cmp(&b.release).then_with(|| match (a.pre, b.pre) {
+        (None, None) => Ordering::Equal,
+        // A pre-release comes before its release
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (Some(a), Some(b)) => compare_pre(a, b),
+    }))
+}
+
+/// Whether `version` is newer than `than`; a version that doesn't parse
+/// isn't
+pub fn is_newer(version: &str, than: &str) -> bool {
+    compare(version, than) == Some(Ordering::Greater)
+}
+
+/// Refuse to write a record last written by the newer version `newer`
+pub fn check_writable(kind: &str, id: &str, newer: Option<&str>) -> Result<(), String> {
+    match newer {
+        None => Ok(()),
+        Some(version) => Err(format!(
+            "{} {} was last written by kawakaze {}, newer than this daemon ({}); upgrade kawakaze again, \
+             or restart the daemon with --force-downgrade-writes to write it anyway",
+            kind, id, version, CURRENT
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare() {
+        assert_eq!(compare("0.2.0", "0.1.9"), Some(Ordering::Greater));
+        assert_eq!(compare("0.10.0", "0.9.0"), Some(Ordering::Greater));
+        assert_eq!(compare("1.0.0", "1.0.0+build.5"), Some(Ordering::Equal));
+        assert_eq!(compare("1.0.0-rc.1", "1.0.0"), Some(Ordering::Less));
+        // The semver spec's own ordering
+        let ordered = [
+            "1.0.0-alpha", "1.0.0-alpha.1", "1.0.0-alpha.beta", "1.0.0-beta", "1.0.0-beta.2", "1.0.0-beta.11",
+            "1.0.0-rc.1", "1.0.0",
+        ];
+        for pair in ordered.windows(2) {
+            assert_eq!(compare(pair[0], pair[1]), Some(Ordering::Less), "{} < {}", pair[0], pair[1]);
+        }
+
+        assert_eq!(compare("1.0", "1.0.0"), None);
+        assert_eq!(compare("1.0.0.0", "1.0.0"), None);
+        assert_eq!(compare("1.0.0-", "1.0.0"), None);
+        assert_eq!(compare("banana", "1.0.0"), None);
+    }
+
+    #[test]
+    fn test_is_newer() {
+        assert!(is_newer("0.2.0", "0.1.0"));
+        assert!(!is_newer("0.1.0", "0.1.0"));
+        assert!(!is_newer("0.1.0", "0.2.0"));
+        assert!(!is_newer("garbage", "0.1.0"));
+
+        let provenance = |last: Option<&str>| Provenance { created_by: None, last_written: last.map(str::to_string) };
+        assert_eq!(provenance(Some("9.0.0")).newer_than("0.1.0").as_deref(), Some("9.0.0"));
+        assert_eq!(provenance(Some("0.1.0")).newer_than("0.1.0"), None);
+        // Rows from before the columns existed
+        assert_eq!(provenance(None).newer_than("0.1.0"), None);
+    }
+
+    #[test]
+    fn test_check_writable() {
+        assert!(check_writable("Container", "web", None).is_ok());
+        let err = check_writable("Container", "web", Some("9.0.0")).unwrap_err();
+        assert!(err.contains("kawakaze 9.0.0") && err.contains("--force-downgrade-writes"));
+    }
+}
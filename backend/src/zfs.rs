@@ -4,11 +4,16 @@
 //! zfs and zpool command-line utilities. It supports creating and managing
 //! datasets, snapshots, and clones which are used for jail images and containers.
 
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, Stdio};
 use std::string::FromUtf8Error;
 use thiserror::Error;
 
+use crate::cmdtrace::TracedCommand;
+
 pub type Result<T> = std::result::Result<T, ZfsError>;
 
 /// Errors that can occur during ZFS operations
@@ -32,6 +37,9 @@ pub enum ZfsError {
     #[error("Invalid snapshot name: {0}")]
     InvalidSnapshot(String),
 
+    #[error("Dataset has dependent clones: {0}")]
+    HasClones(String),
+
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 
@@ -39,6 +47,472 @@ pub enum ZfsError {
     Utf8Error(#[from] FromUtf8Error),
 }
 
+/// A dataset or snapshot from `zfs list`, with its space usage and age
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DatasetInfo {
+    /// Full name, e.g. `tank/kawakaze/containers/1a2b3c4d` or `...@snap`
+    pub name: String,
+    pub used_bytes: u64,
+    /// Unix timestamp of creation
+    pub created_at: i64,
+    /// Datasets cloned from a snapshot, when the listing asked for them
+    pub clones: Vec<String>,
+}
+
+/// Parse `zfs list -H -p -o name,used,creation[,clones]` output
+///
+/// `clones` is comma-separated, and empty or `-` for none.
+pub fn parse_dataset_info(output: &str) -> Vec<DatasetInfo> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            let name = fields.next()?.trim();
+            let used_bytes = fields.next()?.trim().parse().ok()?;
+            let created_at = fields.next()?.trim().parse().ok()?;
+            let clones = fields
+                .next()
+                .map(|clones| clones.split(',').map(str::trim).filter(|c| !c.is_empty() && *c != "-").map(str::to_string).collect())
+                .unwrap_or_default();
+            (!name.is_empty()).then(|| DatasetInfo { name: name.to_string(), used_bytes, created_at, clones })
+        })
+        .collect()
+}
+
+/// Properties shown by container and image inspect
+pub const INSPECT_PROPERTIES: &[&str] = &["compression", "quota", "used", "referenced", "origin"];
+
+/// Parse `zfs get -H -o property,value all` output
+///
+/// Fields are tab-separated, so values keep their spaces (`mountpoint`s,
+/// user property text). A line without a tab is split at its first run of
+/// spaces instead.
+pub fn parse_properties(output: &str) -> HashMap<String, String> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim_end_matches('\r');
+            let (property, value) = line
+                .split_once('\t')
+                .or_else(|| line.trim_start().split_once(' ').map(|(p, v)| (p, v.trim_start())))?;
+            let property = property.trim();
+            (!property.is_empty()).then(|| (property.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+/// The `names` that `properties` has, in name order
+pub fn select_properties(properties: &HashMap<String, String>, names: &[&str]) -> BTreeMap<String, String> {
+    names
+        .iter()
+        .filter_map(|name| Some((name.to_string(), properties.get(*name)?.clone())))
+        .collect()
+}
+
+/// Find the dataset mounted at `path` in `zfs list -H -o name,mountpoint`
+/// output
+pub fn find_mounted_dataset(output: &str, path: &Path) -> Option<String> {
+    output.lines().find_map(|line| {
+        let (name, mountpoint) = line.split_once('\t')?;
+        (Path::new(mountpoint.trim()) == path).then(|| name.trim().to_string())
+    })
+}
+
+/// Parse `zfs list -H -o origin` output into the set of snapshots that
+/// have clones
+pub fn parse_origins(output: &str) -> HashSet<String> {
+    output
+        .lines()
+        .map(str::trim)
+        .filter(|origin| origin.contains('@'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Snapshots of `dataset` to destroy to keep only the newest `keep_last`
+///
+/// `snapshots` are snapshot names oldest first, as returned by
+/// `Zfs::list_snapshots`. Snapshots in `origins` have clones and are always
+/// kept; they still count towards `keep_last` if they are among the newest.
+pub fn snapshots_to_prune(dataset: &str, snapshots: &[String], keep_last: usize, origins: &HashSet<String>) -> Vec<String> {
+    let excess = snapshots.len().saturating_sub(keep_last);
+    snapshots[..excess]
+        .iter()
+        .filter(|snap| !origins.contains(&format!("{}@{}", dataset, snap)))
+        .cloned()
+        .collect()
+}
+
+/// Whether and when a dataset is mounted (`canmount`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CanMount {
+    /// Mounted by `zfs mount -a` at boot, and when created
+    On,
+    /// Never mounted
+    Off,
+    /// Mounted only by an explicit `zfs mount`
+    NoAuto,
+}
+
+impl CanMount {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CanMount::On => "on",
+            CanMount::Off => "off",
+            CanMount::NoAuto => "noauto",
+        }
+    }
+}
+
+/// Properties set on a dataset when it is created or received
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DatasetOptions {
+    properties: Vec<(String, String)>,
+}
+
+impl DatasetOptions {
+    /// Set `name=value` on the new dataset, replacing an earlier value
+    /// (`zfs create` refuses a property given twice)
+    pub fn with_property(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        let (name, value) = (name.into(), value.into());
+        match self.properties.iter_mut().find(|(existing, _)| *existing == name) {
+            Some((_, existing)) => *existing = value,
+            None => self.properties.push((name, value)),
+        }
+        self
+    }
+
+    /// `canmount`; without it a new dataset gets `canmount=off`
+    pub fn with_canmount(self, canmount: CanMount) -> Self {
+        self.with_property("canmount", canmount.as_str())
+    }
+
+    pub fn with_mountpoint(self, mountpoint: &Path) -> Self {
+        self.with_property("mountpoint", mountpoint.to_string_lossy())
+    }
+
+    /// `lz4`, `zstd`, `off`, ...
+    pub fn with_compression(self, compression: &str) -> Self {
+        self.with_property("compression", compression)
+    }
+
+    /// Size limit such as `10G`, or `none`
+    pub fn with_quota(self, quota: &str) -> Self {
+        self.with_property("quota", quota)
+    }
+
+    /// Block size such as `128K`
+    pub fn with_recordsize(self, recordsize: &str) -> Self {
+        self.with_property("recordsize", recordsize)
+    }
+
+    /// What [`Zfs::mount_dataset`] sets to mount a dataset at `mountpoint`.
+    /// Given at create, the dataset only needs
+    /// [`Zfs::mount_at_mountpoint`] afterwards. `noauto` rather than `on`:
+    /// a dataset below a container's root must not be mounted at boot
+    /// before the root is.
+    pub fn mounted_at(self, mountpoint: &Path) -> Self {
+        // atime=off: with atime on, every read during VNET jail
+        // initialization updates the access time, which takes minutes
+        self.with_canmount(CanMount::NoAuto)
+            .with_mountpoint(mountpoint)
+            .with_property("atime", "off")
+    }
+
+    fn has(&self, name: &str) -> bool {
+        self.properties.iter().any(|(existing, _)| existing == name)
+    }
+
+    /// Native encryption with the key read from `keylocation`
+    pub fn with_encryption(self, keyformat: &str, keylocation: &str) -> Self {
+        self.with_property("encryption", "on")
+            .with_property("keyformat", keyformat)
+            .with_property("keylocation", keylocation)
+    }
+
+    /// Whether the options turn encryption on
+    pub fn is_encrypted(&self) -> bool {
+        self.properties.iter().any(|(name, value)| name == "encryption" && value != "off")
+    }
+
+    /// `-o name=value` arguments for `zfs create` or `zfs receive`
+    pub fn to_args(&self) -> Vec<String> {
+        self.properties
+            .iter()
+            .flat_map(|(name, value)| ["-o".to_string(), format!("{}={}", name, value)])
+            .collect()
+    }
+}
+
+/// Arguments of the `zfs create` making `path` with `options`
+///
+/// `-p` creates missing parents too; the `-o` properties apply to `path`
+/// only. New datasets aren't mounted unless `options` set `canmount`.
+pub fn create_args(path: &str, options: &DatasetOptions) -> Vec<String> {
+    let mut args = vec!["create".to_string(), "-p".to_string()];
+    if !options.has("canmount") {
+        args.extend(["-o".to_string(), "canmount=off".to_string()]);
+    }
+    args.extend(options.to_args());
+    args.push(path.to_string());
+    args
+}
+
+/// Arguments of the one `zfs set` giving `dataset` every property in
+/// `options`
+pub fn set_args(dataset: &str, options: &DatasetOptions) -> Vec<String> {
+    let mut args = vec!["set".to_string()];
+    args.extend(options.properties.iter().map(|(name, value)| format!("{}={}", name, value)));
+    args.push(dataset.to_string());
+    args
+}
+
+/// Arguments of the `zfs receive` writing a stream into `target`
+///
+/// The received dataset isn't mounted (`-u`). `force` adds `-F`, which
+/// rolls an existing `target` back to its latest snapshot and destroys the
+/// snapshots the stream doesn't have; see [`check_force_receive`].
+pub fn receive_args(target: &str, options: &DatasetOptions, force: bool) -> Vec<String> {
+    let mut args = vec!["receive".to_string(), "-u".to_string()];
+    if force {
+        args.push("-F".to_string());
+    }
+    args.extend(options.to_args());
+    args.push(target.to_string());
+    args
+}
+
+/// Refuse a forced receive into `target` that would pull a snapshot from
+/// under a clone
+///
+/// `origins` are the snapshots that have clones (see [`parse_origins`]).
+/// A clone of any snapshot of `target`, or of a dataset below it, is a
+/// live container or image, so the receive must not touch them.
+pub fn check_force_receive(target: &str, origins: &HashSet<String>) -> Result<()> {
+    let below = format!("{}/", target);
+    let mut cloned: Vec<&str> = origins
+        .iter()
+        .map(String::as_str)
+        .filter(|origin| {
+            let dataset = origin.split('@').next().unwrap_or(origin);
+            dataset == target || dataset.starts_with(&below)
+        })
+        .collect();
+    if cloned.is_empty() {
+        return Ok(());
+    }
+    cloned.sort_unstable();
+    Err(ZfsError::HasClones(format!(
+        "refusing to force a receive into '{}': {} cloned",
+        target,
+        cloned.join(", ")
+    )))
+}
+
+/// Whether `zfs create` failed because the dataset is already there
+fn is_exists_error(stderr: &str) -> bool {
+    stderr.contains("dataset already exists")
+}
+
+/// Result of a `zfs create` for [`Zfs::ensure_dataset`]: an existing
+/// dataset is as good as a new one
+fn ensure_result(path: &str, success: bool, stderr: &str) -> Result<()> {
+    if success || is_exists_error(stderr) {
+        return Ok(());
+    }
+    Err(ZfsError::CommandFailed(format!("Failed to create dataset '{}': {}", path, stderr)))
+}
+
+/// How a dataset is made from a snapshot
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CopyMethod {
+    /// `zfs clone`: instant and shares blocks with the snapshot
+    Clone,
+    /// `zfs send | zfs receive`: a full, independent copy
+    SendReceive,
+}
+
+/// How a dataset will be made from a snapshot, and whether it ends up
+/// encrypted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CopyPlan {
+    pub method: CopyMethod,
+    pub encrypted: bool,
+}
+
+/// Decide between clone and send/receive
+///
+/// A clone always inherits the encryption of its origin, so an encrypted
+/// copy of an unencrypted snapshot has to be received into a new
+/// encrypted dataset. A clone of an encrypted snapshot is encrypted
+/// whether or not that was asked for.
+pub fn plan_copy(origin_encrypted: bool, encrypt: bool) -> CopyPlan {
+    if encrypt && !origin_encrypted {
+        CopyPlan { method: CopyMethod::SendReceive, encrypted: true }
+    } else {
+        CopyPlan { method: CopyMethod::Clone, encrypted: origin_encrypted }
+    }
+}
+
+/// Key state of one dataset
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyStatus {
+    pub dataset: String,
+    /// Dataset holding the key, `None` if the dataset isn't encrypted
+    pub encryption_root: Option<String>,
+    /// Whether the key is loaded (always true for unencrypted datasets)
+    pub available: bool,
+}
+
+/// Parse `zfs list -H -o name,encryptionroot,keystatus` output
+pub fn parse_key_status(output: &str) -> Vec<KeyStatus> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t').map(str::trim);
+            let dataset = fields.next().filter(|name| !name.is_empty())?;
+            let root = fields.next()?;
+            let status = fields.next()?;
+            Some(KeyStatus {
+                dataset: dataset.to_string(),
+                encryption_root: (root != "-" && !root.is_empty()).then(|| root.to_string()),
+                available: status != "unavailable",
+            })
+        })
+        .collect()
+}
+
+/// Load the key of every encryption root that has a dataset without one
+///
+/// `load` is called once per root. Returns the datasets that remain
+/// locked, each with the reason its root's key couldn't be loaded.
+pub fn load_missing_keys(datasets: &[KeyStatus], mut load: impl FnMut(&str) -> Result<()>) -> HashMap<String, String> {
+    let mut failed: HashMap<&str, String> = HashMap::new();
+    let mut loaded: HashSet<&str> = HashSet::new();
+    for status in datasets.iter().filter(|s| !s.available) {
+        let Some(root) = status.encryption_root.as_deref() else { continue };
+        if loaded.contains(root) || failed.contains_key(root) {
+            continue;
+        }
+        match load(root) {
+            Ok(()) => {
+                loaded.insert(root);
+            }
+            Err(e) => {
+                failed.insert(root, e.to_string());
+            }
+        }
+    }
+
+    datasets
+        .iter()
+        .filter_map(|s| {
+            let reason = failed.get(s.encryption_root.as_deref()?)?;
+            Some((s.dataset.clone(), reason.clone()))
+        })
+        .collect()
+}
+
+/// Pool health as reported by `zpool list -o health`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum PoolState {
+    Online,
+    Degraded,
+    Faulted,
+    Offline,
+    Removed,
+    Unavail,
+    Suspended,
+    #[serde(other)]
+    Unknown,
+}
+
+impl PoolState {
+    /// Whether new datasets may safely be written to the pool
+    pub fn is_healthy(self) -> bool {
+        self == PoolState::Online
+    }
+}
+
+impl std::str::FromStr for PoolState {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(match s.trim().to_ascii_uppercase().as_str() {
+            "ONLINE" => PoolState::Online,
+            "DEGRADED" => PoolState::Degraded,
+            "FAULTED" => PoolState::Faulted,
+            "OFFLINE" => PoolState::Offline,
+            "REMOVED" => PoolState::Removed,
+            "UNAVAIL" => PoolState::Unavail,
+            "SUSPENDED" => PoolState::Suspended,
+            _ => PoolState::Unknown,
+        })
+    }
+}
+
+impl fmt::Display for PoolState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            PoolState::Online => "ONLINE",
+            PoolState::Degraded => "DEGRADED",
+            PoolState::Faulted => "FAULTED",
+            PoolState::Offline => "OFFLINE",
+            PoolState::Removed => "REMOVED",
+            PoolState::Unavail => "UNAVAIL",
+            PoolState::Suspended => "SUSPENDED",
+            PoolState::Unknown => "UNKNOWN",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Health and capacity of a pool
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PoolStatus {
+    pub name: String,
+    pub state: PoolState,
+    pub free_bytes: u64,
+    pub size_bytes: u64,
+    /// The `status:` explanation from `zpool status -x`, when not healthy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+}
+
+/// Parse `zpool list -H -p -o health,free,size POOL` output
+pub fn parse_pool_list(output: &str) -> Option<(PoolState, u64, u64)> {
+    let mut fields = output.lines().next()?.split('\t');
+    let state = fields.next()?.parse().ok()?;
+    let free = fields.next()?.trim().parse().ok()?;
+    let size = fields.next()?.trim().parse().ok()?;
+    Some((state, free, size))
+}
+
+/// Extract the `status:` paragraph from `zpool status -x POOL` output
+///
+/// Returns `None` for a healthy pool ("pool 'tank' is healthy").
+pub fn parse_status_explanation(output: &str) -> Option<String> {
+    let mut lines = output.lines().skip_while(|line| !line.trim_start().starts_with("status:"));
+    let first = lines.next()?.trim_start().trim_start_matches("status:").trim();
+
+    // Continuation lines are indented; the next section starts with `key:`
+    let mut parts = vec![first.to_string()];
+    for line in lines {
+        let trimmed = line.trim();
+        let is_section = trimmed
+            .split_once(':')
+            .is_some_and(|(key, _)| !key.is_empty() && key.chars().all(|c| c.is_ascii_alphabetic()));
+        if trimmed.is_empty() || is_section {
+            break;
+        }
+        parts.push(trimmed.to_string());
+    }
+
+    Some(parts.join(" "))
+}
+
 /// ZFS wrapper for managing datasets, snapshots, and clones
 ///
 /// # Example
@@ -58,7 +532,7 @@ pub enum ZfsError {
 /// // Clone the snapshot to create a new jail
 /// zfs.clone_snapshot("tank/jails/webserver@initial", "tank/jails/webserver-clone").unwrap();
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Zfs {
     pool: String,
 }
@@ -99,7 +573,7 @@ impl Zfs {
             .arg("-o")
             .arg("name")
             .arg("-H")
-            .output()?;
+            .traced_output()?;
 
         if !output.status.success() {
             return Err(ZfsError::CommandFailed(
@@ -123,6 +597,57 @@ impl Zfs {
         &self.pool
     }
 
+    /// Get the health, free and total space of the pool
+    ///
+    /// When the pool isn't ONLINE, `status` carries the explanation from
+    /// `zpool status -x`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use kawakaze_backend::zfs::Zfs;
+    /// # let zfs = Zfs::new("tank").unwrap();
+    /// let status = zfs.pool_status().unwrap();
+    /// println!("{} is {}", status.name, status.state);
+    /// ```
+    pub fn pool_status(&self) -> Result<PoolStatus> {
+        let output = Command::new("zpool")
+            .arg("list")
+            .arg("-H")
+            .arg("-p")
+            .arg("-o")
+            .arg("health,free,size")
+            .arg(&self.pool)
+            .traced_output()?;
+
+        if !output.status.success() {
+            let error_msg = String::from_utf8_lossy(&output.stderr);
+            return Err(ZfsError::CommandFailed(format!(
+                "Failed to get status of pool '{}': {}",
+                self.pool, error_msg
+            )));
+        }
+
+        let stdout = String::from_utf8(output.stdout)?;
+        let (state, free_bytes, size_bytes) = parse_pool_list(&stdout).ok_or_else(|| {
+            ZfsError::CommandFailed(format!("Failed to parse zpool list output: {}", stdout.trim()))
+        })?;
+
+        let status = if state.is_healthy() {
+            None
+        } else {
+            Command::new("zpool")
+                .arg("status")
+                .arg("-x")
+                .arg(&self.pool)
+                .traced_output()
+                .ok()
+                .and_then(|output| parse_status_explanation(&String::from_utf8_lossy(&output.stdout)))
+        };
+
+        Ok(PoolStatus { name: self.pool.clone(), state, free_bytes, size_bytes, status })
+    }
+
     /// Create a new ZFS dataset
     ///
     /// # Arguments
@@ -137,17 +662,25 @@ impl Zfs {
     /// zfs.create_dataset("tank/jails/webserver").unwrap();
     /// ```
     pub fn create_dataset(&self, path: &str) -> Result<()> {
+        self.create_dataset_with_options(path, &DatasetOptions::default())
+    }
+
+    /// Create a new ZFS dataset with extra properties
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use kawakaze_backend::zfs::{DatasetOptions, Zfs};
+    /// # let zfs = Zfs::new("tank").unwrap();
+    /// let options = DatasetOptions::default().with_encryption("raw", "file:///root/tank.key");
+    /// zfs.create_dataset_with_options("tank/jails/secret", &options).unwrap();
+    /// ```
+    pub fn create_dataset_with_options(&self, path: &str, options: &DatasetOptions) -> Result<()> {
         if self.dataset_exists(path) {
             return Err(ZfsError::DatasetExists(path.to_string()));
         }
 
-        let output = Command::new("zfs")
-            .arg("create")
-            .arg("-p")
-            .arg("-o")
-            .arg("canmount=off")
-            .arg(path)
-            .output()?;
+        let output = Command::new("zfs").args(create_args(path, options)).traced_output()?;
 
         if !output.status.success() {
             let error_msg = String::from_utf8_lossy(&output.stderr);
@@ -160,65 +693,51 @@ impl Zfs {
         Ok(())
     }
 
-    /// Mount a dataset to a specific mountpoint
-    pub fn mount_dataset(&self, dataset: &str, mountpoint: &Path) -> Result<()> {
-        let mountpoint_str = mountpoint.to_str()
-            .ok_or_else(|| ZfsError::CommandFailed("Invalid mountpoint path".to_string()))?;
-
-        // Set canmount to noauto so we can control mounting
-        let output = Command::new("zfs")
-            .arg("set")
-            .arg("canmount=noauto")
-            .arg(dataset)
-            .output()?;
-
-        if !output.status.success() {
-            let error_msg = String::from_utf8_lossy(&output.stderr);
-            return Err(ZfsError::CommandFailed(format!(
-                "Failed to set canmount for '{}': {}",
-                dataset, error_msg
-            )));
-        }
-
-        let output = Command::new("zfs")
-            .arg("set")
-            .arg(format!("mountpoint={}", mountpoint_str))
-            .arg(dataset)
-            .output()?;
-
-        if !output.status.success() {
-            let error_msg = String::from_utf8_lossy(&output.stderr);
-            return Err(ZfsError::CommandFailed(format!(
-                "Failed to set mountpoint for '{}': {}",
-                dataset, error_msg
-            )));
-        }
+    /// Create a dataset unless it exists, with `props` set on a new one
+    ///
+    /// Unlike [`Zfs::create_dataset`] there is no separate existence check
+    /// to race with: an existing dataset, whether it was there before or
+    /// another caller just made it, counts as success and is left as it is.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use kawakaze_backend::zfs::Zfs;
+    /// # let zfs = Zfs::new("tank").unwrap();
+    /// zfs.ensure_dataset("tank/containers", &[("compression", "lz4")]).unwrap();
+    /// ```
+    pub fn ensure_dataset(&self, path: &str, props: &[(&str, &str)]) -> Result<()> {
+        let options = props
+            .iter()
+            .fold(DatasetOptions::default(), |options, (name, value)| options.with_property(*name, *value));
 
-        // Set atime=off to improve VNET jail initialization performance
-        // With atime=on, every read access updates the access time, causing
-        // significant slowdown during VNET initialization (3+ minutes vs immediate)
-        let output = Command::new("zfs")
-            .arg("set")
-            .arg("atime=off")
-            .arg(dataset)
-            .output()?;
+        let output = Command::new("zfs").args(create_args(path, &options)).traced_output()?;
+        ensure_result(path, output.status.success(), &String::from_utf8_lossy(&output.stderr))
+    }
 
-        if !output.status.success() {
-            let error_msg = String::from_utf8_lossy(&output.stderr);
-            return Err(ZfsError::CommandFailed(format!(
-                "Failed to set atime for '{}': {}",
-                dataset, error_msg
-            )));
+    /// Mount a dataset to a specific mountpoint
+    ///
+    /// Sets what [`DatasetOptions::mounted_at`] does first, for a dataset
+    /// created without it (one made by [`Zfs::create_dataset`], or a clone).
+    pub fn mount_dataset(&self, dataset: &str, mountpoint: &Path) -> Result<()> {
+        if mountpoint.to_str().is_none() {
+            return Err(ZfsError::CommandFailed("Invalid mountpoint path".to_string()));
         }
+        self.set_properties(dataset, &DatasetOptions::default().mounted_at(mountpoint))?;
 
         // Ensure the mountpoint directory exists
         std::fs::create_dir_all(mountpoint).map_err(|e| ZfsError::CommandFailed(format!("Failed to create mountpoint directory: {}", e)))?;
 
-        // Mount the dataset (ignore error if already mounted)
+        self.mount_at_mountpoint(dataset)
+    }
+
+    /// Mount a dataset at its own `mountpoint`, as one created with
+    /// [`DatasetOptions::mounted_at`] is; already mounted is fine
+    pub fn mount_at_mountpoint(&self, dataset: &str) -> Result<()> {
         let output = Command::new("zfs")
             .arg("mount")
             .arg(dataset)
-            .output()?;
+            .traced_output()?;
 
         if !output.status.success() {
             let error_msg = String::from_utf8_lossy(&output.stderr);
@@ -234,49 +753,73 @@ impl Zfs {
         Ok(())
     }
 
-    /// Unmount a dataset
+    /// Unmount a dataset, leaving it unmountable (`mountpoint=none`,
+    /// `canmount=off`) so nothing mounts it at boot
     pub fn unmount_dataset(&self, dataset: &str) -> Result<()> {
-        // Try force unmount first to handle busy filesystems
-        let output = Command::new("zfs")
-            .arg("unmount")
-            .arg("-f")
-            .arg(dataset)
-            .output()?;
+        self.force_unmount(dataset)?;
+        let reset = DatasetOptions::default().with_property("mountpoint", "none").with_canmount(CanMount::Off);
+        self.set_properties(dataset, &reset)
+    }
+
+    /// Unmount a dataset even if it is busy, leaving its properties alone
+    pub fn force_unmount(&self, dataset: &str) -> Result<()> {
+        self.run(&["unmount", "-f", dataset], &format!("unmount '{}'", dataset))
+    }
 
+    /// Mount a dataset read-only at its own `mountpoint`
+    pub fn mount_read_only(&self, dataset: &str) -> Result<()> {
+        self.run(&["mount", "-o", "ro", dataset], &format!("mount '{}' read-only", dataset))
+    }
+
+    /// Hand a dataset (set `jailed=on` first) to the jail `jid`
+    pub fn jail_dataset(&self, jid: i32, dataset: &str) -> Result<()> {
+        self.run(&["jail", &jid.to_string(), dataset], &format!("attach '{}' to jail {}", dataset, jid))
+    }
+
+    /// Take a dataset back from the jail `jid`
+    pub fn unjail_dataset(&self, jid: i32, dataset: &str) -> Result<()> {
+        self.run(&["unjail", &jid.to_string(), dataset], &format!("detach '{}' from jail {}", dataset, jid))
+    }
+
+    /// Clear a property set on a dataset, so it inherits it again
+    pub fn inherit_property(&self, dataset: &str, prop: &str) -> Result<()> {
+        self.run(&["inherit", prop, dataset], &format!("inherit '{}' on '{}'", prop, dataset))
+    }
+
+    /// A property's value and where it comes from (`local`, `default`,
+    /// `inherited from tank/data`, `-` for a user property never set)
+    pub fn property_with_source(&self, dataset: &str, prop: &str) -> Result<(String, String)> {
+        let output = Command::new("zfs").args(["get", "-H", "-o", "value,source", prop, dataset]).traced_output()?;
         if !output.status.success() {
             let error_msg = String::from_utf8_lossy(&output.stderr);
             return Err(ZfsError::CommandFailed(format!(
-                "Failed to unmount '{}': {}",
-                dataset, error_msg
+                "Failed to get property '{}' from '{}': {}",
+                prop, dataset, error_msg.trim()
             )));
         }
+        let stdout = String::from_utf8(output.stdout)?;
+        let (value, source) = stdout.trim_end_matches('\n').split_once('\t').unwrap_or((stdout.trim(), "-"));
+        Ok((value.to_string(), source.to_string()))
+    }
 
-        // Reset mountpoint to none
-        let output = Command::new("zfs")
-            .arg("set")
-            .arg("mountpoint=none")
-            .arg(dataset)
-            .output()?;
-
+    /// Run `zfs` with `args`; `what` names the action in the error
+    fn run(&self, args: &[&str], what: &str) -> Result<()> {
+        let output = Command::new("zfs").args(args).traced_output()?;
         if !output.status.success() {
             let error_msg = String::from_utf8_lossy(&output.stderr);
-            return Err(ZfsError::CommandFailed(format!(
-                "Failed to reset mountpoint for '{}': {}",
-                dataset, error_msg
-            )));
+            return Err(ZfsError::CommandFailed(format!("Failed to {}: {}", what, error_msg.trim())));
         }
+        Ok(())
+    }
 
-        // Reset canmount to off
-        let output = Command::new("zfs")
-            .arg("set")
-            .arg("canmount=off")
-            .arg(dataset)
-            .output()?;
+    /// Set every property in `options` on `dataset` with one `zfs set`
+    fn set_properties(&self, dataset: &str, options: &DatasetOptions) -> Result<()> {
+        let output = Command::new("zfs").args(set_args(dataset, options)).traced_output()?;
 
         if !output.status.success() {
             let error_msg = String::from_utf8_lossy(&output.stderr);
             return Err(ZfsError::CommandFailed(format!(
-                "Failed to reset canmount for '{}': {}",
+                "Failed to set properties of '{}': {}",
                 dataset, error_msg
             )));
         }
@@ -285,14 +828,14 @@ impl Zfs {
     }
 
     /// Check if a dataset is currently mounted
-    fn is_dataset_mounted(&self, dataset: &str) -> bool {
+    pub fn is_dataset_mounted(&self, dataset: &str) -> bool {
         let output = Command::new("zfs")
             .arg("list")
             .arg("-H")
             .arg("-o")
             .arg("mounted")
             .arg(dataset)
-            .output();
+            .traced_output();
 
         match output {
             Ok(out) => {
@@ -331,7 +874,7 @@ impl Zfs {
         let output = Command::new("zfs")
             .arg("snapshot")
             .arg(&snapshot)
-            .output()?;
+            .traced_output()?;
 
         if !output.status.success() {
             let error_msg = String::from_utf8_lossy(&output.stderr);
@@ -374,7 +917,7 @@ impl Zfs {
             .arg("clone")
             .arg(snapshot)
             .arg(target)
-            .output()?;
+            .traced_output()?;
 
         if !output.status.success() {
             let error_msg = String::from_utf8_lossy(&output.stderr);
@@ -387,6 +930,104 @@ impl Zfs {
         Ok(())
     }
 
+    /// Copy a snapshot into a new dataset with `zfs send | zfs receive`
+    ///
+    /// Unlike a clone, the new dataset shares no blocks with the snapshot
+    /// and takes `options` (e.g. encryption) instead of inheriting them.
+    /// The received dataset isn't mounted.
+    pub fn send_receive(&self, snapshot: &str, target: &str, options: &DatasetOptions) -> Result<()> {
+        if !self.snapshot_exists(snapshot) {
+            return Err(ZfsError::SnapshotNotFound(snapshot.to_string()));
+        }
+
+        if self.dataset_exists(target) {
+            return Err(ZfsError::DatasetExists(target.to_string()));
+        }
+
+        let send_args = vec!["send".to_string(), snapshot.to_string()];
+        let receive_args = receive_args(target, options, false);
+
+        let started = std::time::Instant::now();
+        let mut send = Command::new("zfs")
+            .args(&send_args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let stream = send.stdout.take()
+            .ok_or_else(|| ZfsError::CommandFailed("zfs send has no output".to_string()))?;
+        let receive = Command::new("zfs")
+            .args(&receive_args)
+            .stdin(stream)
+            .traced_output();
+        let sent = send.wait_with_output();
+        crate::cmdtrace::record("zfs", &send_args, started, sent.as_ref().is_ok_and(|o| o.status.success()));
+
+        let sent = sent?;
+        if !sent.status.success() {
+            let _ = self.destroy(target);
+            return Err(ZfsError::CommandFailed(format!(
+                "Failed to send snapshot '{}': {}",
+                snapshot, String::from_utf8_lossy(&sent.stderr)
+            )));
+        }
+
+        let received = receive?;
+        if !received.status.success() {
+            return Err(ZfsError::CommandFailed(format!(
+                "Failed to receive '{}' into '{}': {}",
+                snapshot, target, String::from_utf8_lossy(&received.stderr)
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Receive a `zfs send` stream into `target`, unmounted
+    ///
+    /// Without `force`, `target` must not exist. With it, an existing
+    /// `target` is replaced by the stream (`zfs receive -F`), which is how an
+    /// image or base snapshot imported before is updated; refused while
+    /// anything is cloned from it (see [`check_force_receive`]).
+    pub fn receive(&self, stream: impl Into<Stdio>, target: &str, options: &DatasetOptions, force: bool) -> Result<()> {
+        if self.dataset_exists(target) {
+            if !force {
+                return Err(ZfsError::DatasetExists(target.to_string()));
+            }
+            check_force_receive(target, &self.list_origins()?)?;
+        }
+
+        let output = Command::new("zfs")
+            .args(receive_args(target, options, force))
+            .stdin(stream)
+            .traced_output()?;
+
+        if !output.status.success() {
+            return Err(ZfsError::CommandFailed(format!(
+                "Failed to receive into '{}': {}",
+                target, String::from_utf8_lossy(&output.stderr)
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Make `target` from `snapshot`, by clone where possible
+    ///
+    /// With `encryption`, the new dataset is encrypted: cloned if the
+    /// snapshot already is, received with those options otherwise (see
+    /// [`plan_copy`]).
+    pub fn copy_snapshot(&self, snapshot: &str, target: &str, encryption: Option<&DatasetOptions>) -> Result<CopyPlan> {
+        let origin = snapshot.split('@').next().unwrap_or(snapshot);
+        let plan = plan_copy(self.is_encrypted(origin)?, encryption.is_some());
+
+        match (plan.method, encryption) {
+            (CopyMethod::SendReceive, Some(options)) => self.send_receive(snapshot, target, options)?,
+            _ => self.clone_snapshot(snapshot, target)?,
+        }
+
+        Ok(plan)
+    }
+
     /// Destroy a ZFS dataset or snapshot
     ///
     /// # Arguments
@@ -409,7 +1050,7 @@ impl Zfs {
             .arg("destroy")
             .arg("-r")
             .arg(path)
-            .output()?;
+            .traced_output()?;
 
         if !output.status.success() {
             let error_msg = String::from_utf8_lossy(&output.stderr);
@@ -453,7 +1094,7 @@ impl Zfs {
             .arg("mountpoint")
             .arg("-H")
             .arg(dataset)
-            .output()?;
+            .traced_output()?;
 
         if !output.status.success() {
             let error_msg = String::from_utf8_lossy(&output.stderr);
@@ -505,7 +1146,7 @@ impl Zfs {
             .arg("-s")
             .arg("creation")
             .arg(format!("{}@*", dataset))
-            .output()?;
+            .traced_output()?;
 
         if !output.status.success() {
             let error_msg = String::from_utf8_lossy(&output.stderr);
@@ -556,7 +1197,7 @@ impl Zfs {
             .arg("name")
             .arg("-H")
             .arg(dataset)
-            .output();
+            .traced_output();
 
         match output {
             Ok(result) => result.status.success(),
@@ -564,6 +1205,27 @@ impl Zfs {
         }
     }
 
+    /// Like [`Zfs::dataset_exists`], but tells a dataset that doesn't exist
+    /// (`Ok(false)`) from a `zfs list` that failed for another reason
+    pub fn dataset_presence(&self, dataset: &str) -> Result<bool> {
+        let output = Command::new("zfs")
+            .arg("list")
+            .arg("-o")
+            .arg("name")
+            .arg("-H")
+            .arg(dataset)
+            .traced_output()?;
+
+        if output.status.success() {
+            return Ok(true);
+        }
+        let error_msg = String::from_utf8_lossy(&output.stderr);
+        if error_msg.contains("does not exist") {
+            return Ok(false);
+        }
+        Err(ZfsError::CommandFailed(format!("Failed to list '{}': {}", dataset, error_msg)))
+    }
+
     /// Check if a snapshot exists
     ///
     /// # Arguments
@@ -582,7 +1244,7 @@ impl Zfs {
             .arg("name")
             .arg("-H")
             .arg(snapshot)
-            .output();
+            .traced_output();
 
         match output {
             Ok(result) => result.status.success(),
@@ -621,7 +1283,7 @@ impl Zfs {
             .arg("set")
             .arg(format!("{}={}", prop, value))
             .arg(dataset)
-            .output()?;
+            .traced_output()?;
 
         if !output.status.success() {
             let error_msg = String::from_utf8_lossy(&output.stderr);
@@ -668,7 +1330,7 @@ impl Zfs {
             .arg("value")
             .arg(prop)
             .arg(dataset)
-            .output()?;
+            .traced_output()?;
 
         if !output.status.success() {
             let error_msg = String::from_utf8_lossy(&output.stderr);
@@ -685,6 +1347,32 @@ impl Zfs {
         Ok(value)
     }
 
+    /// Every property of a dataset with its value, as `zfs get all` shows
+    /// them (see [`parse_properties`])
+    pub fn all_properties(&self, dataset: &str) -> Result<HashMap<String, String>> {
+        let output = Command::new("zfs")
+            .arg("get")
+            .arg("-H")
+            .arg("-o")
+            .arg("property,value")
+            .arg("all")
+            .arg(dataset)
+            .traced_output()?;
+
+        if !output.status.success() {
+            let error_msg = String::from_utf8_lossy(&output.stderr);
+            if error_msg.contains("does not exist") {
+                return Err(ZfsError::DatasetNotFound(dataset.to_string()));
+            }
+            return Err(ZfsError::CommandFailed(format!(
+                "Failed to get properties of '{}': {}",
+                dataset, error_msg
+            )));
+        }
+
+        Ok(parse_properties(&String::from_utf8(output.stdout)?))
+    }
+
     /// List all datasets under a path
     ///
     /// # Arguments
@@ -713,7 +1401,7 @@ impl Zfs {
             .arg("-H")
             .arg("-r")
             .arg(path)
-            .output()?;
+            .traced_output()?;
 
         if !output.status.success() {
             let error_msg = String::from_utf8_lossy(&output.stderr);
@@ -762,7 +1450,7 @@ impl Zfs {
             .arg("rollback")
             .arg("-r")
             .arg(&snapshot_path)
-            .output()?;
+            .traced_output()?;
 
         if !output.status.success() {
             let error_msg = String::from_utf8_lossy(&output.stderr);
@@ -805,7 +1493,7 @@ impl Zfs {
             .arg("-H")
             .arg("-p")
             .arg(dataset)
-            .output()?;
+            .traced_output()?;
 
         if !output.status.success() {
             let error_msg = String::from_utf8_lossy(&output.stderr);
@@ -859,7 +1547,7 @@ impl Zfs {
             .arg("-H")
             .arg("-p")
             .arg(dataset)
-            .output()?;
+            .traced_output()?;
 
         if !output.status.success() {
             let error_msg = String::from_utf8_lossy(&output.stderr);
@@ -908,7 +1596,7 @@ impl Zfs {
         let output = Command::new("zfs")
             .arg("promote")
             .arg(dataset)
-            .output()?;
+            .traced_output()?;
 
         if !output.status.success() {
             let error_msg = String::from_utf8_lossy(&output.stderr);
@@ -948,7 +1636,7 @@ impl Zfs {
             .arg("rename")
             .arg(old_name)
             .arg(new_name)
-            .output()?;
+            .traced_output()?;
 
         if !output.status.success() {
             let error_msg = String::from_utf8_lossy(&output.stderr);
@@ -960,6 +1648,213 @@ impl Zfs {
 
         Ok(())
     }
+
+    /// List a dataset's filesystems and snapshots down to `depth` levels,
+    /// with sizes and creation times, oldest first
+    ///
+    /// A snapshot counts as one level below its dataset, so a depth of 2 on
+    /// `tank/kawakaze/images` includes each image dataset and its snapshots.
+    pub fn list_tree(&self, root: &str, depth: u32) -> Result<Vec<DatasetInfo>> {
+        let output = Command::new("zfs")
+            .arg("list")
+            .arg("-H")
+            .arg("-p")
+            .arg("-t")
+            .arg("filesystem,snapshot")
+            .arg("-o")
+            .arg("name,used,creation")
+            .arg("-s")
+            .arg("creation")
+            .arg("-d")
+            .arg(depth.to_string())
+            .arg(root)
+            .traced_output()?;
+
+        if !output.status.success() {
+            let error_msg = String::from_utf8_lossy(&output.stderr);
+            if error_msg.contains("does not exist") {
+                return Err(ZfsError::DatasetNotFound(root.to_string()));
+            }
+            return Err(ZfsError::CommandFailed(format!(
+                "Failed to list datasets under '{}': {}",
+                root, error_msg
+            )));
+        }
+
+        Ok(parse_dataset_info(&String::from_utf8(output.stdout)?))
+    }
+
+    /// Every snapshot below `root`, oldest first, with the datasets cloned
+    /// from it
+    pub fn list_snapshots_with_clones(&self, root: &str) -> Result<Vec<DatasetInfo>> {
+        let output = Command::new("zfs")
+            .args(["list", "-H", "-p", "-t", "snapshot", "-o", "name,used,creation,clones", "-s", "creation", "-r", root])
+            .traced_output()?;
+
+        if !output.status.success() {
+            let error_msg = String::from_utf8_lossy(&output.stderr);
+            if error_msg.contains("does not exist") {
+                return Err(ZfsError::DatasetNotFound(root.to_string()));
+            }
+            return Err(ZfsError::CommandFailed(format!(
+                "Failed to list snapshots under '{}': {}",
+                root, error_msg
+            )));
+        }
+
+        Ok(parse_dataset_info(&String::from_utf8(output.stdout)?))
+    }
+
+    /// Whether a dataset uses native encryption
+    pub fn is_encrypted(&self, dataset: &str) -> Result<bool> {
+        let encryption = self.get_property(dataset, "encryption")?;
+        Ok(encryption != "off" && encryption != "-")
+    }
+
+    /// Key state of `root` and every dataset below it
+    pub fn key_status(&self, root: &str) -> Result<Vec<KeyStatus>> {
+        let output = Command::new("zfs")
+            .arg("list")
+            .arg("-H")
+            .arg("-t")
+            .arg("filesystem,volume")
+            .arg("-o")
+            .arg("name,encryptionroot,keystatus")
+            .arg("-r")
+            .arg(root)
+            .traced_output()?;
+
+        if !output.status.success() {
+            let error_msg = String::from_utf8_lossy(&output.stderr);
+            return Err(ZfsError::CommandFailed(format!(
+                "Failed to list key status under '{}': {}",
+                root, error_msg
+            )));
+        }
+
+        Ok(parse_key_status(&String::from_utf8(output.stdout)?))
+    }
+
+    /// Load the key of an encryption root from its `keylocation`
+    pub fn load_key(&self, dataset: &str) -> Result<()> {
+        let output = Command::new("zfs")
+            .arg("load-key")
+            .arg(dataset)
+            .traced_output()?;
+
+        if !output.status.success() {
+            let error_msg = String::from_utf8_lossy(&output.stderr);
+            if error_msg.contains("Key already loaded") {
+                return Ok(());
+            }
+            return Err(ZfsError::CommandFailed(format!(
+                "Failed to load key for '{}': {}",
+                dataset, error_msg.trim()
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Unload the key of an encryption root; its datasets must be unmounted
+    pub fn unload_key(&self, dataset: &str) -> Result<()> {
+        let output = Command::new("zfs")
+            .arg("unload-key")
+            .arg(dataset)
+            .traced_output()?;
+
+        if !output.status.success() {
+            let error_msg = String::from_utf8_lossy(&output.stderr);
+            return Err(ZfsError::CommandFailed(format!(
+                "Failed to unload key for '{}': {}",
+                dataset, error_msg.trim()
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Get the snapshot a dataset was cloned from, if any
+    pub fn get_origin(&self, dataset: &str) -> Result<Option<String>> {
+        let origin = self.get_property(dataset, "origin")?;
+        Ok((origin != "-" && !origin.is_empty()).then_some(origin))
+    }
+
+    /// Every snapshot in the pool that has a clone
+    pub fn list_origins(&self) -> Result<HashSet<String>> {
+        let output = Command::new("zfs")
+            .arg("list")
+            .arg("-H")
+            .arg("-t")
+            .arg("filesystem,volume")
+            .arg("-o")
+            .arg("origin")
+            .arg("-r")
+            .arg(&self.pool)
+            .traced_output()?;
+
+        if !output.status.success() {
+            let error_msg = String::from_utf8_lossy(&output.stderr);
+            return Err(ZfsError::CommandFailed(format!(
+                "Failed to list origins in '{}': {}",
+                self.pool, error_msg
+            )));
+        }
+
+        Ok(parse_origins(&String::from_utf8(output.stdout)?))
+    }
+
+    /// Destroy all but the newest `keep_last` snapshots of a dataset
+    ///
+    /// Snapshots that have dependent clones anywhere in the pool are never
+    /// destroyed. Returns the names (without the dataset prefix) of the
+    /// snapshots that were destroyed.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use kawakaze_backend::zfs::Zfs;
+    /// # let zfs = Zfs::new("tank").unwrap();
+    /// let destroyed = zfs.prune_snapshots("tank/kawakaze/images/web", 3).unwrap();
+    /// println!("Pruned {} snapshots", destroyed.len());
+    /// ```
+    pub fn prune_snapshots(&self, dataset: &str, keep_last: usize) -> Result<Vec<String>> {
+        let snapshots = self.list_snapshots(dataset)?;
+        let origins = self.list_origins()?;
+
+        let mut destroyed = Vec::new();
+        for snap in snapshots_to_prune(dataset, &snapshots, keep_last, &origins) {
+            self.destroy(&format!("{}@{}", dataset, snap))?;
+            destroyed.push(snap);
+        }
+
+        Ok(destroyed)
+    }
+
+    /// Find the dataset in the pool mounted at `path`
+    pub fn dataset_mounted_at(&self, path: &Path) -> Result<String> {
+        let output = Command::new("zfs")
+            .arg("list")
+            .arg("-H")
+            .arg("-t")
+            .arg("filesystem")
+            .arg("-o")
+            .arg("name,mountpoint")
+            .arg("-r")
+            .arg(&self.pool)
+            .traced_output()?;
+
+        if !output.status.success() {
+            let error_msg = String::from_utf8_lossy(&output.stderr);
+            return Err(ZfsError::CommandFailed(format!(
+                "Failed to list mountpoints in '{}': {}",
+                self.pool, error_msg
+            )));
+        }
+
+        find_mounted_dataset(&String::from_utf8(output.stdout)?, path)
+            .ok_or_else(|| ZfsError::DatasetNotFound(format!("no dataset mounted at {}", path.display())))
+    }
 }
 
 #[cfg(test)]
@@ -969,6 +1864,376 @@ mod tests {
     // Note: These tests require a running ZFS pool
     // Most will be marked as ignored unless a ZFS pool is available
 
+    #[test]
+    fn test_parse_dataset_info() {
+        let output = "tank/k/images\t98304\t1700000000\n\
+                      tank/k/images/web\t1048576\t1700000100\n\
+                      tank/k/images/web@web-1\t0\t1700000200\n\
+                      garbage line\n";
+        let entries = parse_dataset_info(output);
+
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[1], DatasetInfo {
+            name: "tank/k/images/web".to_string(),
+            used_bytes: 1048576,
+            created_at: 1700000100,
+            clones: Vec::new(),
+        });
+        assert_eq!(entries[2].name, "tank/k/images/web@web-1");
+
+        // With the clones column
+        let output = "tank/k/images/web@web-1\t0\t1700000200\t-\n\
+                      tank/k/images/web@web-2\t4096\t1700000300\ttank/k/containers/1a2b3c4d,tank/k/containers/5e6f7a8b\n";
+        let entries = parse_dataset_info(output);
+        assert!(entries[0].clones.is_empty());
+        assert_eq!(entries[1].clones, ["tank/k/containers/1a2b3c4d", "tank/k/containers/5e6f7a8b"]);
+    }
+
+    #[test]
+    fn test_parse_properties() {
+        let output = "type\tfilesystem\n\
+                      creation\tTue Nov 14 22:13 2023\n\
+                      used\t1.21G\n\
+                      referenced\t845M\n\
+                      compression\tlz4\n\
+                      quota\tnone\n\
+                      mountpoint\t/var/lib/kawakaze/containers/my app\n\
+                      origin\ttank/k/images/web@web-1\n\
+                      org.kawakaze:note\tbuilt by CI, do not touch\n\
+                      keylocation\t-\n\
+                      comment\t\n\
+                      \n\
+                      snapdir hidden\n";
+        let properties = parse_properties(output);
+
+        assert_eq!(properties.len(), 12);
+        // Multi-word values are kept whole
+        assert_eq!(properties["creation"], "Tue Nov 14 22:13 2023");
+        assert_eq!(properties["mountpoint"], "/var/lib/kawakaze/containers/my app");
+        assert_eq!(properties["org.kawakaze:note"], "built by CI, do not touch");
+        assert_eq!(properties["keylocation"], "-");
+        assert_eq!(properties["comment"], "");
+        // Without a tab, the first space separates
+        assert_eq!(properties["snapdir"], "hidden");
+
+        let inspect = select_properties(&properties, INSPECT_PROPERTIES);
+        assert_eq!(
+            inspect.into_iter().collect::<Vec<_>>(),
+            [
+                ("compression".to_string(), "lz4".to_string()),
+                ("origin".to_string(), "tank/k/images/web@web-1".to_string()),
+                ("quota".to_string(), "none".to_string()),
+                ("referenced".to_string(), "845M".to_string()),
+                ("used".to_string(), "1.21G".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_snapshots_to_prune_keeps_newest_and_cloned() {
+        let snapshots: Vec<String> = ["s1", "s2", "s3", "s4", "s5"].iter().map(|s| s.to_string()).collect();
+        let origins = parse_origins("-\ntank/k/images/web@s2\ntank/k/images/other@s1\n-\n");
+
+        assert_eq!(origins.len(), 2);
+        assert_eq!(snapshots_to_prune("tank/k/images/web", &snapshots, 2, &origins), vec!["s1", "s3"]);
+        assert_eq!(snapshots_to_prune("tank/k/images/web", &snapshots, 0, &HashSet::new()).len(), 5);
+        assert!(snapshots_to_prune("tank/k/images/web", &snapshots, 5, &HashSet::new()).is_empty());
+        assert!(snapshots_to_prune("tank/k/images/web", &snapshots, 10, &HashSet::new()).is_empty());
+    }
+
+    #[test]
+    fn test_receive_args() {
+        assert_eq!(receive_args("tank/k/images/web", &DatasetOptions::default(), false), ["receive", "-u", "tank/k/images/web"]);
+        let options = DatasetOptions::default().with_compression("lz4");
+        assert_eq!(receive_args("tank/k/images/web", &options, true), [
+            "receive", "-u", "-F", "-o", "compression=lz4", "tank/k/images/web",
+        ]);
+    }
+
+    #[test]
+    fn test_check_force_receive() {
+        let origins = parse_origins("-
+tank/k/images/web@base
+tank/k/images/web-2@base
+tank/k/images/app/sub@s1
+");
+
+        // Clones of the target's snapshots, or of a dataset below it
+        let err = check_force_receive("tank/k/images/web", &origins).unwrap_err();
+        assert!(matches!(err, ZfsError::HasClones(_)));
+        assert!(err.to_string().ends_with("tank/k/images/web@base cloned"), "{}", err);
+        let err = check_force_receive("tank/k/images/app", &origins).unwrap_err();
+        assert!(err.to_string().contains("tank/k/images/app/sub@s1"), "{}", err);
+
+        // A sibling sharing the prefix isn't below it
+        assert!(check_force_receive("tank/k/images/we", &origins).is_ok());
+        assert!(check_force_receive("tank/k/images/db", &origins).is_ok());
+        assert!(check_force_receive("tank/k/images/web", &HashSet::new()).is_ok());
+    }
+
+    #[test]
+    fn test_find_mounted_dataset() {
+        let output = "tank\t/tank\n\
+                      tank/jails\t/usr/local/jails\n\
+                      tank/jails/web\t/usr/local/jails/web\n";
+
+        assert_eq!(find_mounted_dataset(output, Path::new("/usr/local/jails/web")).as_deref(), Some("tank/jails/web"));
+        assert_eq!(find_mounted_dataset(output, Path::new("/usr/local/jails/web/")).as_deref(), Some("tank/jails/web"));
+        assert_eq!(find_mounted_dataset(output, Path::new("/usr/local/jails/db")), None);
+    }
+
+    #[test]
+    fn test_parse_pool_list() {
+        assert_eq!(parse_pool_list("ONLINE\t858993459200\t1099511627776\n"), Some((PoolState::Online, 858993459200, 1099511627776)));
+        assert_eq!(parse_pool_list("DEGRADED\t1024\t4096\n"), Some((PoolState::Degraded, 1024, 4096)));
+        // A pool whose I/O is suspended, after its only disk was pulled
+        assert_eq!(parse_pool_list("SUSPENDED\t1024\t4096\n"), Some((PoolState::Suspended, 1024, 4096)));
+        assert_eq!(parse_pool_list("WEIRD\t1\t2"), Some((PoolState::Unknown, 1, 2)));
+        assert_eq!(parse_pool_list("ONLINE\t-\t4096"), None);
+        assert_eq!(parse_pool_list(""), None);
+    }
+
+    #[test]
+    fn test_parse_status_explanation() {
+        assert_eq!(parse_status_explanation("pool 'tank' is healthy\n"), None);
+
+        let degraded = "  pool: tank
+ state: DEGRADED
+status: One or more devices could not be opened.  Sufficient replicas exist for
+	the pool to continue functioning in a degraded state.
+action: Attach the missing device and online it using 'zpool online'.
+   see: https://openzfs.github.io/openzfs-docs/msg/ZFS-8000-2Q
+  scan: scrub repaired 0B in 00:00:01 with 0 errors on Sun Oct 12 00:00:01 2025
+config:
+
+	NAME        STATE     READ WRITE CKSUM
+	tank        DEGRADED     0     0     0
+	  mirror-0  DEGRADED     0     0     0
+	    ada0    ONLINE       0     0     0
+	    ada1    UNAVAIL      0     0     0  cannot open
+
+errors: No known data errors
+";
+        assert_eq!(
+            parse_status_explanation(degraded).as_deref(),
+            Some("One or more devices could not be opened.  Sufficient replicas exist for the pool to continue functioning in a degraded state.")
+        );
+
+        let suspended = "  pool: tank
+ state: SUSPENDED
+status: One or more devices are faulted in response to IO failures.
+action: Make sure the affected devices are connected, then run 'zpool clear'.
+   see: https://openzfs.github.io/openzfs-docs/msg/ZFS-8000-HC
+config:
+
+	NAME        STATE     READ WRITE CKSUM
+	tank        ONLINE       0     0     0
+	  da0       REMOVED      0     0     0
+
+errors: List of errors unavailable: pool I/O is currently suspended
+";
+        assert_eq!(parse_status_explanation(suspended).as_deref(), Some("One or more devices are faulted in response to IO failures."));
+    }
+
+    #[test]
+    fn test_dataset_options_args() {
+        assert!(DatasetOptions::default().to_args().is_empty());
+
+        let options = DatasetOptions::default()
+            .with_property("compression", "lz4")
+            .with_encryption("raw", "file:///var/db/kawakaze/keys/kawakaze.key");
+        assert!(options.is_encrypted());
+        assert_eq!(options.to_args(), [
+            "-o", "compression=lz4",
+            "-o", "encryption=on",
+            "-o", "keyformat=raw",
+            "-o", "keylocation=file:///var/db/kawakaze/keys/kawakaze.key",
+        ]);
+        assert!(!DatasetOptions::default().with_property("encryption", "off").is_encrypted());
+    }
+
+    #[test]
+    fn test_create_args() {
+        assert_eq!(create_args("tank/containers", &DatasetOptions::default()), [
+            "create", "-p", "-o", "canmount=off", "tank/containers",
+        ]);
+        let options = DatasetOptions::default().with_property("compression", "lz4");
+        assert_eq!(create_args("tank/images", &options), [
+            "create", "-p", "-o", "canmount=off", "-o", "compression=lz4", "tank/images",
+        ]);
+
+        // Options that say how to mount replace the default
+        let options = DatasetOptions::default().with_compression("lz4").mounted_at(Path::new("/jails/web root/data"));
+        assert_eq!(create_args("tank/volumes/v1", &options), [
+            "create", "-p", "-o", "compression=lz4", "-o", "canmount=noauto", "-o", "mountpoint=/jails/web root/data",
+            "-o", "atime=off", "tank/volumes/v1",
+        ]);
+        let options = DatasetOptions::default().with_canmount(CanMount::On);
+        assert_eq!(create_args("tank/data", &options), ["create", "-p", "-o", "canmount=on", "tank/data"]);
+    }
+
+    #[test]
+    fn test_dataset_options_builder() {
+        let options = DatasetOptions::default()
+            .with_compression("lz4")
+            .with_quota("10G")
+            .with_recordsize("16K")
+            .with_property("org.kawakaze:note", "a=b c")
+            .with_compression("zstd");
+        // In the order given, a repeated property replaced where it was
+        // first set, and values with `=` or spaces kept as one argument
+        assert_eq!(options.to_args(), [
+            "-o", "compression=zstd", "-o", "quota=10G", "-o", "recordsize=16K", "-o", "org.kawakaze:note=a=b c",
+        ]);
+        assert_eq!(set_args("tank/data", &options), [
+            "set", "compression=zstd", "quota=10G", "recordsize=16K", "org.kawakaze:note=a=b c", "tank/data",
+        ]);
+    }
+
+    /// A pool where no dataset exists and every other command succeeds
+    fn recorded_zfs() -> (Zfs, crate::cmdtrace::recorder::Recorder) {
+        let recorder = crate::cmdtrace::recorder::Recorder::install(|command| match command.get(1).map(String::as_str) {
+            Some("list") => Err("dataset does not exist".to_string()),
+            _ => Ok(String::new()),
+        });
+        (Zfs { pool: "tank".to_string() }, recorder)
+    }
+
+    #[test]
+    fn test_volume_is_created_mountable() {
+        let (zfs, recorder) = recorded_zfs();
+        let mountpoint = Path::new("/jails/web/data");
+        let options = DatasetOptions::default().with_encryption("raw", "file:///k.key");
+        crate::operation::DatasetOps::create_volume(&zfs, "tank/volumes/v1", &options, mountpoint).unwrap();
+
+        // The existence check, then a create that sets everything the mount
+        // needs: one `zfs mount` instead of three `zfs set`s and a mount
+        assert_eq!(recorder.calls(), [
+            vec!["zfs", "list", "-o", "name", "-H", "tank/volumes/v1"],
+            vec![
+                "zfs", "create", "-p", "-o", "encryption=on", "-o", "keyformat=raw", "-o", "keylocation=file:///k.key",
+                "-o", "canmount=noauto", "-o", "mountpoint=/jails/web/data", "-o", "atime=off", "tank/volumes/v1",
+            ],
+            vec!["zfs", "mount", "tank/volumes/v1"],
+        ]);
+    }
+
+    #[test]
+    fn test_mount_and_unmount_set_properties_at_once() {
+        let (zfs, recorder) = recorded_zfs();
+        let dir = tempfile::tempdir().unwrap();
+        let mountpoint = dir.path().join("root");
+
+        zfs.mount_dataset("tank/containers/web", &mountpoint).unwrap();
+        assert!(mountpoint.is_dir());
+        zfs.unmount_dataset("tank/containers/web").unwrap();
+        let set_mount = format!("mountpoint={}", mountpoint.display());
+        assert_eq!(recorder.calls(), [
+            vec!["zfs", "set", "canmount=noauto", &set_mount, "atime=off", "tank/containers/web"],
+            vec!["zfs", "mount", "tank/containers/web"],
+            vec!["zfs", "unmount", "-f", "tank/containers/web"],
+            vec!["zfs", "set", "mountpoint=none", "canmount=off", "tank/containers/web"],
+        ]);
+    }
+
+    #[test]
+    fn test_passthrough_commands() {
+        let recorder = crate::cmdtrace::recorder::Recorder::install(|command| match command.get(1).map(String::as_str) {
+            Some("get") => Ok("/tank/data/pg\tinherited from tank/data\n".to_string()),
+            _ => Ok(String::new()),
+        });
+        let zfs = Zfs { pool: "tank".to_string() };
+
+        let (value, source) = zfs.property_with_source("tank/data/pg", "mountpoint").unwrap();
+        assert_eq!((value.as_str(), source.as_str()), ("/tank/data/pg", "inherited from tank/data"));
+        zfs.inherit_property("tank/data/pg", "org.kawakaze:attached").unwrap();
+        zfs.mount_read_only("tank/data/pg").unwrap();
+        zfs.jail_dataset(42, "tank/data/pg").unwrap();
+        zfs.unjail_dataset(42, "tank/data/pg").unwrap();
+        assert_eq!(recorder.calls(), [
+            vec!["zfs", "get", "-H", "-o", "value,source", "mountpoint", "tank/data/pg"],
+            vec!["zfs", "inherit", "org.kawakaze:attached", "tank/data/pg"],
+            vec!["zfs", "mount", "-o", "ro", "tank/data/pg"],
+            vec!["zfs", "jail", "42", "tank/data/pg"],
+            vec!["zfs", "unjail", "42", "tank/data/pg"],
+        ]);
+    }
+
+    #[test]
+    fn test_ensure_result() {
+        assert!(ensure_result("tank/images", true, "").is_ok());
+        // Someone else created it first
+        assert!(ensure_result("tank/images", false, "cannot create 'tank/images': dataset already exists\n").is_ok());
+        let err = ensure_result("tank/images", false, "cannot create 'tank/images': out of space").unwrap_err();
+        assert!(matches!(err, ZfsError::CommandFailed(msg) if msg.contains("out of space")));
+    }
+
+    #[test]
+    fn test_plan_copy() {
+        assert_eq!(plan_copy(false, false), CopyPlan { method: CopyMethod::Clone, encrypted: false });
+        assert_eq!(plan_copy(true, true), CopyPlan { method: CopyMethod::Clone, encrypted: true });
+        // A clone can't drop its origin's encryption...
+        assert_eq!(plan_copy(true, false), CopyPlan { method: CopyMethod::Clone, encrypted: true });
+        // ...nor add it
+        assert_eq!(plan_copy(false, true), CopyPlan { method: CopyMethod::SendReceive, encrypted: true });
+    }
+
+    #[test]
+    fn test_parse_key_status() {
+        let output = "tank/kawakaze\t-\t-\n\
+                      tank/kawakaze/containers/aaaa\ttank/kawakaze/containers/aaaa\tavailable\n\
+                      tank/kawakaze/containers/bbbb\ttank/kawakaze/containers/bbbb\tunavailable\n";
+        let statuses = parse_key_status(output);
+
+        assert_eq!(statuses.len(), 3);
+        assert_eq!(statuses[0].encryption_root, None);
+        assert!(statuses[0].available);
+        assert!(statuses[1].available);
+        assert_eq!(statuses[2].encryption_root.as_deref(), Some("tank/kawakaze/containers/bbbb"));
+        assert!(!statuses[2].available);
+    }
+
+    #[test]
+    fn test_load_missing_keys() {
+        let status = |dataset: &str, root: Option<&str>, available: bool| KeyStatus {
+            dataset: dataset.to_string(),
+            encryption_root: root.map(str::to_string),
+            available,
+        };
+        let statuses = [
+            status("tank/k", None, true),
+            status("tank/k/secure", Some("tank/k/secure"), false),
+            status("tank/k/secure/a", Some("tank/k/secure"), false),
+            status("tank/k/c/open", Some("tank/k/c/open"), true),
+            status("tank/k/c/lost", Some("tank/k/c/lost"), false),
+        ];
+
+        let mut attempts = Vec::new();
+        let locked = load_missing_keys(&statuses, |root| {
+            attempts.push(root.to_string());
+            if root == "tank/k/c/lost" {
+                Err(ZfsError::CommandFailed("Key load error: Failed to open key material file".to_string()))
+            } else {
+                Ok(())
+            }
+        });
+
+        // One attempt per root, none for loaded or unencrypted datasets
+        assert_eq!(attempts, ["tank/k/secure", "tank/k/c/lost"]);
+        assert_eq!(locked.len(), 1);
+        assert!(locked["tank/k/c/lost"].contains("key material"));
+    }
+
+    #[test]
+    fn test_pool_state() {
+        assert_eq!("ONLINE".parse::<PoolState>().unwrap(), PoolState::Online);
+        assert_eq!("degraded".parse::<PoolState>().unwrap(), PoolState::Degraded);
+        assert!(PoolState::Online.is_healthy());
+        assert!(!PoolState::Degraded.is_healthy());
+        assert_eq!(PoolState::Degraded.to_string(), "DEGRADED");
+        assert_eq!(serde_json::to_value(PoolState::Unavail).unwrap(), "UNAVAIL");
+    }
+
     #[test]
     fn test_zfs_new_invalid_pool() {
         let result = Zfs::new("nonexistent_pool_test_12345");
@@ -1022,6 +2287,9 @@ mod tests {
             _ => panic!("Expected DatasetExists error"),
         }
 
+        // Unless it's fine that it exists
+        assert!(zfs.ensure_dataset(test_dataset, &[("compression", "lz4")]).is_ok());
+
         // Destroy dataset
         let result = zfs.destroy(test_dataset);
         assert!(result.is_ok());
@@ -1059,6 +2327,38 @@ mod tests {
         zfs.destroy(test_dataset).unwrap();
     }
 
+    #[test]
+    #[ignore]
+    fn test_prune_snapshots_spares_cloned() {
+        let zfs = match Zfs::new("tank") {
+            Ok(z) => z,
+            Err(_) => {
+                eprintln!("Skipping test: no 'tank' pool available");
+                return;
+            }
+        };
+
+        let test_dataset = "tank/kawakaze_test_prune";
+        let test_clone = "tank/kawakaze_test_prune_clone";
+        let _ = zfs.destroy(test_clone);
+        let _ = zfs.destroy(test_dataset);
+        zfs.create_dataset(test_dataset).unwrap();
+
+        for snap in ["snap1", "snap2", "snap3", "snap4"] {
+            zfs.create_snapshot(test_dataset, snap).unwrap();
+        }
+        zfs.clone_snapshot(&format!("{}@snap1", test_dataset), test_clone).unwrap();
+        assert_eq!(zfs.get_origin(test_clone).unwrap().as_deref(), Some("tank/kawakaze_test_prune@snap1"));
+
+        let destroyed = zfs.prune_snapshots(test_dataset, 1).unwrap();
+        assert_eq!(destroyed, vec!["snap2", "snap3"]);
+        assert_eq!(zfs.list_snapshots(test_dataset).unwrap(), vec!["snap1", "snap4"]);
+
+        // Cleanup
+        zfs.destroy(test_clone).unwrap();
+        zfs.destroy(test_dataset).unwrap();
+    }
+
     #[test]
     #[ignore]
     fn test_clone_snapshot() {
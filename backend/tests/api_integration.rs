@@ -102,6 +102,10 @@ async fn test_create_and_list_jails() {
         path: Some("/tmp/test_jail_path".into()),
         ip: None,
         bootstrap: None,
+        devfs_ruleset: None,
+        cleanup_path: None,
+        mount_permissions: Default::default(),
+        description: None,
     };
     let request = Request::post(Endpoint::Jails, create_req).unwrap();
     let response = send_request(socket_path, request).await.unwrap();
@@ -134,6 +138,10 @@ async fn test_get_jail() {
         path: None,
         ip: None,
         bootstrap: None,
+        devfs_ruleset: None,
+        cleanup_path: None,
+        mount_permissions: Default::default(),
+        description: None,
     };
     let request = Request::post(Endpoint::Jails, create_req).unwrap();
     send_request(socket_path, request).await.unwrap();
@@ -180,6 +188,10 @@ async fn test_create_invalid_jail_name() {
         path: None,
         ip: None,
         bootstrap: None,
+        devfs_ruleset: None,
+        cleanup_path: None,
+        mount_permissions: Default::default(),
+        description: None,
     };
     let request = Request::post(Endpoint::Jails, create_req).unwrap();
     let response = send_request(socket_path, request).await.unwrap();
@@ -204,6 +216,10 @@ async fn test_create_duplicate_jail() {
         path: None,
         ip: None,
         bootstrap: None,
+        devfs_ruleset: None,
+        cleanup_path: None,
+        mount_permissions: Default::default(),
+        description: None,
     };
     let request = Request::post(Endpoint::Jails, create_req.clone()).unwrap();
     send_request(socket_path, request).await.unwrap();
@@ -232,6 +248,10 @@ async fn test_delete_jail() {
         path: None,
         ip: None,
         bootstrap: None,
+        devfs_ruleset: None,
+        cleanup_path: None,
+        mount_permissions: Default::default(),
+        description: None,
     };
     let request = Request::post(Endpoint::Jails, create_req).unwrap();
     send_request(socket_path, request).await.unwrap();
@@ -285,6 +305,10 @@ async fn test_concurrent_requests() {
                 path: None,
                 ip: None,
                 bootstrap: None,
+                devfs_ruleset: None,
+                cleanup_path: None,
+                mount_permissions: Default::default(),
+                description: None,
             };
             let request = Request::post(Endpoint::Jails, &create_req).unwrap();
             send_request(&socket_path, request).await
@@ -367,6 +391,10 @@ async fn test_create_jail_with_path_and_ip() {
         path: Some("/jails/configured".into()),
         ip: Some("192.168.1.100".into()),
         bootstrap: None,
+        devfs_ruleset: None,
+        cleanup_path: None,
+        mount_permissions: Default::default(),
+        description: None,
     };
     let request = Request::post(Endpoint::Jails, create_req).unwrap();
     let response = send_request(socket_path, request).await.unwrap();
@@ -399,6 +427,10 @@ async fn test_multiple_operations_same_jail() {
         path: None,
         ip: None,
         bootstrap: None,
+        devfs_ruleset: None,
+        cleanup_path: None,
+        mount_permissions: Default::default(),
+        description: None,
     };
     let request = Request::post(Endpoint::Jails, create_req).unwrap();
     let response = send_request(socket_path, request).await.unwrap();
@@ -0,0 +1,38 @@
+//! Network isolation of `network: none` RUN steps on a real FreeBSD host
+//!
+//! These run as root and create jails rooted at `/` (which has a `/bin/sh`),
+//! so they are ignored by default: `cargo test --test build_isolation_tests -- --ignored`.
+#![cfg(target_os = "freebsd")]
+
+use std::collections::HashMap;
+use std::process::Command;
+
+use kawakaze_backend::build_jail::{CommandJailBackend, TempJail, run_in_temp_jail};
+
+fn jail_exists(name: &str) -> bool {
+    Command::new("jls").args(["-j", name]).output().is_ok_and(|o| o.status.success())
+}
+
+#[test]
+#[ignore] // Needs root
+fn test_none_network_has_no_addresses() {
+    let jail = TempJail::new("/");
+
+    // The step sees no inet addresses and can't open an IPv4 socket
+    run_in_temp_jail(&CommandJailBackend, &jail, "! /sbin/ifconfig | grep -q 'inet'", &HashMap::new()).unwrap();
+    let reached = run_in_temp_jail(&CommandJailBackend, &jail, "/usr/bin/nc -z -w 1 127.0.0.1 22", &HashMap::new());
+    assert!(reached.is_err());
+
+    assert!(!jail_exists(&jail.name));
+}
+
+#[test]
+#[ignore] // Needs root
+fn test_jail_removed_after_timeout() {
+    let mut jail = TempJail::new("/");
+    jail.timeout = Some(std::time::Duration::from_secs(1));
+
+    let err = run_in_temp_jail(&CommandJailBackend, &jail, "sleep 30", &HashMap::new()).unwrap_err();
+    assert!(err.contains("timed out"));
+    assert!(!jail_exists(&jail.name));
+}
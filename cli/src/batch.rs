@@ -0,0 +1,615 @@
+//! Batch image builds (`kawakaze build --recursive DIR`)
+//!
+//! Discovers Dockerfiles under a directory, orders them by their FROM
+//! dependencies and submits them to the backend with bounded parallelism.
+
+use crate::exit::CliError;
+use kawakaze_backend::handler::parse_from_instruction;
+use kawakaze_client::types::{BuildImageRequest, BuildNetwork, BuildStatus};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Default pattern used to discover Dockerfiles
+pub const DEFAULT_GLOB: &str = "**/Dockerfile";
+
+/// Optional manifest at the root of the build directory
+pub const MANIFEST_FILE: &str = "kawakaze-build.toml";
+
+/// How often running builds are polled for progress
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Request options shared by every image of a batch
+#[derive(Debug, Clone, Default)]
+pub struct BuildOptions {
+    pub build_args: HashMap<String, String>,
+    pub secrets: HashMap<String, String>,
+    pub protect: bool,
+    pub strict_vars: bool,
+    pub no_cache: bool,
+    pub network: BuildNetwork,
+}
+
+/// Contents of `kawakaze-build.toml`
+///
+/// ```toml
+/// [[image]]
+/// path = "services/api"   # directory containing the Dockerfile
+/// name = "api-server"     # overrides the directory-derived name
+/// build_args = { VERSION = "1.2" }
+/// ```
+#[derive(Debug, Default, Deserialize)]
+pub struct Manifest {
+    #[serde(default, rename = "image")]
+    pub images: Vec<ManifestEntry>,
+}
+
+/// Per-directory overrides in the manifest
+#[derive(Debug, Deserialize)]
+pub struct ManifestEntry {
+    /// Directory containing the Dockerfile, relative to the build directory
+    pub path: String,
+    /// Image name (defaults to the directory name)
+    pub name: Option<String>,
+    /// Build stage to stop at. Multi-stage builds are not supported yet, so
+    /// setting this is rejected rather than silently ignored.
+    pub target: Option<String>,
+    /// Build arguments, merged over those given on the command line
+    #[serde(default)]
+    pub build_args: HashMap<String, String>,
+}
+
+/// A single image build in the batch
+#[derive(Debug, Clone)]
+pub struct BuildJob {
+    pub name: String,
+    pub dockerfile: PathBuf,
+    pub contents: String,
+    pub build_args: HashMap<String, String>,
+    /// Base image named by the FROM instruction, if any
+    pub base: Option<String>,
+}
+
+/// Lifecycle of a job within the batch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JobState {
+    Pending,
+    /// Submitted to the backend (holds the build ID and latest progress line)
+    Running { build_id: String, detail: String },
+    Complete,
+    Failed(String),
+    /// Not built because a dependency failed or the batch was aborted
+    Skipped,
+}
+
+/// Match a `/`-separated path against a glob supporting `**`, `*` and `?`
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<&str> = pattern.split('/').filter(|s| !s.is_empty()).collect();
+    let path: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    match_segments(&pattern, &path)
+}
+
+fn match_segments(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|skip| match_segments(rest, &path[skip..])),
+        Some((segment, rest)) => match path.split_first() {
+            Some((name, path_rest)) => {
+                match_segment(segment.as_bytes(), name.as_bytes()) && match_segments(rest, path_rest)
+            }
+            None => false,
+        },
+    }
+}
+
+fn match_segment(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((b'*', rest)) => (0..=text.len()).any(|skip| match_segment(rest, &text[skip..])),
+        Some((b'?', rest)) => !text.is_empty() && match_segment(rest, &text[1..]),
+        Some((c, rest)) => text.first() == Some(c) && match_segment(rest, &text[1..]),
+    }
+}
+
+/// Find files under `dir` whose relative path matches `pattern`
+pub fn discover(dir: &Path, pattern: &str) -> Result<Vec<PathBuf>, String> {
+    let mut found = Vec::new();
+    let mut stack = vec![PathBuf::new()];
+
+    while let Some(rel_dir) = stack.pop() {
+        let entries = std::fs::read_dir(dir.join(&rel_dir))
+            .map_err(|e| format!("Failed to read {}: {}", dir.join(&rel_dir).display(), e))?;
+
+        for entry in entries {
+            let entry = entry.map_err(|e| format!("Failed to read directory entry: {}", e))?;
+            let rel = rel_dir.join(entry.file_name());
+            let file_type = entry.file_type().map_err(|e| e.to_string())?;
+
+            if file_type.is_dir() {
+                stack.push(rel);
+            } else if file_type.is_file() && glob_match(pattern, &rel.to_string_lossy()) {
+                found.push(rel);
+            }
+        }
+    }
+
+    found.sort();
+    Ok(found)
+}
+
+/// Derive an image name from the directory containing a Dockerfile
+pub fn derive_name(dir: &Path, dockerfile: &Path) -> String {
+    let parent = dockerfile.parent().filter(|p| !p.as_os_str().is_empty());
+    let name = match parent {
+        Some(p) => p.file_name().map(|n| n.to_string_lossy().to_string()),
+        None => dir
+            .canonicalize()
+            .ok()
+            .and_then(|d| d.file_name().map(|n| n.to_string_lossy().to_string())),
+    };
+
+    name.unwrap_or_else(|| "image".to_string()).to_lowercase()
+}
+
+/// Load the manifest from the build directory, if present
+pub fn load_manifest(dir: &Path) -> Result<Manifest, String> {
+    let path = dir.join(MANIFEST_FILE);
+    if !path.exists() {
+        return Ok(Manifest::default());
+    }
+
+    let contents = std::fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    toml::from_str(&contents).map_err(|e| format!("Invalid {}: {}", MANIFEST_FILE, e))
+}
+
+/// Discover Dockerfiles and apply manifest overrides, producing the jobs to build
+pub fn plan(
+    dir: &Path,
+    pattern: &str,
+    build_args: &HashMap<String, String>,
+) -> Result<Vec<BuildJob>, String> {
+    let manifest = load_manifest(dir)?;
+    let mut dockerfiles = discover(dir, pattern)?;
+
+    let entries: HashMap<PathBuf, &ManifestEntry> = manifest
+        .images
+        .iter()
+        .map(|entry| (PathBuf::from(entry.path.trim_start_matches("./").trim_end_matches('/')), entry))
+        .collect();
+
+    // Manifest entries outside the glob are still built
+    for path in entries.keys() {
+        let dockerfile = path.join("Dockerfile");
+        if !dockerfiles.iter().any(|d| d.parent() == Some(path.as_path())) {
+            if !dir.join(&dockerfile).is_file() {
+                return Err(format!("{}: no Dockerfile in '{}'", MANIFEST_FILE, path.display()));
+            }
+            dockerfiles.push(dockerfile);
+        }
+    }
+
+    let mut jobs = Vec::new();
+    for dockerfile in dockerfiles {
+        let entry = dockerfile.parent().and_then(|p| entries.get(p));
+
+        if let Some(target) = entry.and_then(|e| e.target.as_ref()) {
+            return Err(format!(
+                "{}: build target '{}' for '{}' is not supported",
+                MANIFEST_FILE,
+                target,
+                dockerfile.display()
+            ));
+        }
+
+        let contents = std::fs::read_to_string(dir.join(&dockerfile))
+            .map_err(|e| format!("Failed to read {}: {}", dockerfile.display(), e))?;
+
+        let mut args = build_args.clone();
+        if let Some(entry) = entry {
+            args.extend(entry.build_args.clone());
+        }
+
+        jobs.push(BuildJob {
+            name: entry
+                .and_then(|e| e.name.clone())
+                .unwrap_or_else(|| derive_name(dir, &dockerfile)),
+            base: parse_from_instruction(&contents).ok(),
+            dockerfile,
+            contents,
+            build_args: args,
+        });
+    }
+
+    Ok(jobs)
+}
+
+/// Build the dependency graph: `deps[i]` lists the jobs that job `i` is built FROM
+pub fn dependency_graph(jobs: &[BuildJob]) -> Result<Vec<Vec<usize>>, String> {
+    let mut by_name = HashMap::new();
+    for (idx, job) in jobs.iter().enumerate() {
+        if let Some(prev) = by_name.insert(job.name.as_str(), idx) {
+            return Err(format!(
+                "Image name '{}' is used by both {} and {}; set distinct names in {}",
+                job.name,
+                jobs[prev].dockerfile.display(),
+                job.dockerfile.display(),
+                MANIFEST_FILE
+            ));
+        }
+    }
+
+    Ok(jobs
+        .iter()
+        .map(|job| {
+            job.base
+                .as_deref()
+                .and_then(|base| by_name.get(base).copied())
+                .into_iter()
+                .collect()
+        })
+        .collect())
+}
+
+/// Topologically order jobs so every job comes after its dependencies.
+/// Ties keep discovery order.
+pub fn build_order(deps: &[Vec<usize>]) -> Result<Vec<usize>, String> {
+    let mut remaining: Vec<usize> = deps.iter().map(|d| d.len()).collect();
+    let mut order = Vec::with_capacity(deps.len());
+    let mut placed = vec![false; deps.len()];
+
+    while order.len() < deps.len() {
+        let next = (0..deps.len()).find(|&i| !placed[i] && remaining[i] == 0);
+        let Some(next) = next else {
+            return Err("FROM dependencies between the discovered images form a cycle".to_string());
+        };
+
+        placed[next] = true;
+        order.push(next);
+        for (i, d) in deps.iter().enumerate() {
+            remaining[i] -= d.iter().filter(|&&dep| dep == next).count();
+        }
+    }
+
+    Ok(order)
+}
+
+/// Tracks job states and decides which job may be submitted next
+pub struct Scheduler {
+    deps: Vec<Vec<usize>>,
+    order: Vec<usize>,
+    pub states: Vec<JobState>,
+    keep_going: bool,
+    aborted: bool,
+}
+
+impl Scheduler {
+    pub fn new(deps: Vec<Vec<usize>>, keep_going: bool) -> Result<Self, String> {
+        let order = build_order(&deps)?;
+        let states = vec![JobState::Pending; deps.len()];
+        Ok(Self { deps, order, states, keep_going, aborted: false })
+    }
+
+    /// Next pending job whose dependencies have at least been submitted.
+    /// Dependents are submitted early and queue on the backend behind their base.
+    pub fn next_ready(&self) -> Option<usize> {
+        if self.aborted {
+            return None;
+        }
+
+        self.order.iter().copied().find(|&i| {
+            self.states[i] == JobState::Pending
+                && self.deps[i].iter().all(|&d| {
+                    matches!(self.states[d], JobState::Running { .. } | JobState::Complete)
+                })
+        })
+    }
+
+    pub fn started(&mut self, idx: usize, build_id: String) {
+        self.states[idx] = JobState::Running { build_id, detail: String::new() };
+    }
+
+    pub fn completed(&mut self, idx: usize) {
+        self.states[idx] = JobState::Complete;
+    }
+
+    /// Record a failure, skipping everything that depends on the job and,
+    /// unless keep-going, everything not yet submitted
+    pub fn failed(&mut self, idx: usize, reason: String) {
+        self.states[idx] = JobState::Failed(reason);
+
+        if !self.keep_going {
+            self.aborted = true;
+            for state in self.states.iter_mut() {
+                if *state == JobState::Pending {
+                    *state = JobState::Skipped;
+                }
+            }
+        }
+
+        // Dependents come later in the order, so one pass skips transitively
+        for &i in &self.order {
+            let blocked = self.deps[i]
+                .iter()
+                .any(|&d| matches!(self.states[d], JobState::Failed(_) | JobState::Skipped));
+            if blocked && self.states[i] == JobState::Pending {
+                self.states[i] = JobState::Skipped;
+            }
+        }
+    }
+
+    /// Jobs submitted to the backend that have not finished yet
+    pub fn running(&self) -> Vec<(usize, String)> {
+        self.states
+            .iter()
+            .enumerate()
+            .filter_map(|(i, s)| match s {
+                JobState::Running { build_id, .. } => Some((i, build_id.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.states
+            .iter()
+            .all(|s| !matches!(s, JobState::Pending | JobState::Running { .. }))
+    }
+
+    pub fn failures(&self) -> usize {
+        self.states
+            .iter()
+            .filter(|s| matches!(s, JobState::Failed(_) | JobState::Skipped))
+            .count()
+    }
+}
+
+/// Combined progress view: one line per build, redrawn in place on a TTY
+/// and printed only on change otherwise
+pub struct ProgressView {
+    tty: bool,
+    last: Vec<String>,
+}
+
+impl ProgressView {
+    pub fn new(tty: bool) -> Self {
+        Self { tty, last: Vec::new() }
+    }
+
+    pub fn render(&mut self, out: &mut impl Write, names: &[&str], states: &[JobState]) {
+        let width = names.iter().map(|n| n.len()).max().unwrap_or(0);
+        let lines: Vec<String> = names
+            .iter()
+            .zip(states)
+            .map(|(name, state)| format!("{:<width$}  {}", name, describe(state), width = width))
+            .collect();
+
+        if self.tty {
+            if !self.last.is_empty() {
+                let _ = write!(out, "\x1b[{}A", self.last.len());
+            }
+            for line in &lines {
+                let _ = writeln!(out, "\x1b[2K{}", line);
+            }
+        } else {
+            for (i, line) in lines.iter().enumerate() {
+                if self.last.get(i) != Some(line) {
+                    let _ = writeln!(out, "{}", line);
+                }
+            }
+        }
+
+        let _ = out.flush();
+        self.last = lines;
+    }
+}
+
+fn describe(state: &JobState) -> String {
+    match state {
+        JobState::Pending => "pending".to_string(),
+        JobState::Running { detail, .. } if detail.is_empty() => "building".to_string(),
+        JobState::Running { detail, .. } => format!("building: {}", detail),
+        JobState::Complete => "done".to_string(),
+        JobState::Failed(reason) => format!("failed: {}", reason),
+        JobState::Skipped => "skipped".to_string(),
+    }
+}
+
+/// Build every Dockerfile under `dir`
+pub async fn run(
+    dir: &Path,
+    pattern: &str,
+    max_jobs: usize,
+    keep_going: bool,
+    options: BuildOptions,
+) -> Result<(), CliError> {
+    let jobs = plan(dir, pattern, &options.build_args)?;
+    if jobs.is_empty() {
+        return Err(format!("No Dockerfiles matching '{}' under {}", pattern, dir.display()).into());
+    }
+
+    let mut scheduler = Scheduler::new(dependency_graph(&jobs)?, keep_going)?;
+    let client = crate::client().await?;
+    let names: Vec<&str> = jobs.iter().map(|j| j.name.as_str()).collect();
+    let mut view = ProgressView::new(crate::output::get().tty);
+    let mut stderr = std::io::stderr();
+
+    crate::output::status(format!("Building {} images...", jobs.len()));
+
+    while !scheduler.is_done() {
+        // Submit as many ready jobs as the parallelism limit allows
+        while scheduler.running().len() < max_jobs.max(1) {
+            let Some(idx) = scheduler.next_ready() else { break };
+            let job = &jobs[idx];
+
+            let request = BuildImageRequest {
+                name: job.name.clone(),
+                dockerfile: job.contents.clone(),
+                build_args: job.build_args.clone(),
+                protected: options.protect,
+                wait_for_base: true,
+                strict_vars: options.strict_vars,
+                secrets: options.secrets.clone(),
+                no_cache: options.no_cache,
+                network: options.network,
+            };
+
+            match client.build_image(&request).await {
+                Ok(started) if started.cached => scheduler.completed(idx),
+                Ok(started) => scheduler.started(idx, started.id),
+                Err(e) => scheduler.failed(idx, e.to_string()),
+            }
+        }
+
+        view.render(&mut stderr, &names, &scheduler.states);
+        if scheduler.is_done() {
+            break;
+        }
+
+        tokio::time::sleep(POLL_INTERVAL).await;
+
+        for (idx, build_id) in scheduler.running() {
+            match client.build_status(&build_id).await {
+                Ok(p) if p.status == BuildStatus::Complete => scheduler.completed(idx),
+                Ok(p) if p.status == BuildStatus::Failed => scheduler.failed(idx, p.current_instruction),
+                Ok(p) => scheduler.states[idx] = JobState::Running { build_id, detail: p.current_instruction },
+                Err(e) => scheduler.failed(idx, e.to_string()),
+            }
+        }
+    }
+
+    view.render(&mut stderr, &names, &scheduler.states);
+
+    match scheduler.failures() {
+        0 => Ok(()),
+        n => Err(format!("{} of {} images were not built", n, jobs.len()).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(name: &str, base: Option<&str>) -> BuildJob {
+        BuildJob {
+            name: name.to_string(),
+            dockerfile: PathBuf::from(format!("{}/Dockerfile", name)),
+            contents: String::new(),
+            build_args: HashMap::new(),
+            base: base.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("**/Dockerfile", "Dockerfile"));
+        assert!(glob_match("**/Dockerfile", "services/api/Dockerfile"));
+        assert!(!glob_match("**/Dockerfile", "services/api/Dockerfile.dev"));
+        assert!(glob_match("services/*/Dockerfile*", "services/api/Dockerfile.dev"));
+        assert!(!glob_match("services/*/Dockerfile", "services/a/b/Dockerfile"));
+        assert!(glob_match("svc-?/Dockerfile", "svc-1/Dockerfile"));
+    }
+
+    #[test]
+    fn test_dependency_graph_and_order() {
+        // web FROM api FROM base; worker FROM base; base FROM freebsd (external)
+        let jobs = vec![
+            job("web", Some("api")),
+            job("worker", Some("base")),
+            job("api", Some("base")),
+            job("base", Some("freebsd:14.1")),
+        ];
+
+        let deps = dependency_graph(&jobs).unwrap();
+        assert_eq!(deps, vec![vec![2], vec![3], vec![3], vec![]]);
+
+        let order = build_order(&deps).unwrap();
+        assert_eq!(order, vec![3, 1, 2, 0]);
+    }
+
+    #[test]
+    fn test_dependency_cycle() {
+        let jobs = vec![job("a", Some("b")), job("b", Some("a"))];
+        let deps = dependency_graph(&jobs).unwrap();
+        assert!(build_order(&deps).is_err());
+    }
+
+    #[test]
+    fn test_duplicate_names() {
+        let jobs = vec![job("api", None), job("api", None)];
+        assert!(dependency_graph(&jobs).is_err());
+    }
+
+    #[test]
+    fn test_scheduler_submits_after_dependencies() {
+        let mut scheduler = Scheduler::new(vec![vec![1], vec![], vec![]], true).unwrap();
+
+        assert_eq!(scheduler.next_ready(), Some(1));
+        scheduler.started(1, "b1".into());
+        // 0 depends on 1, which is now submitted and queues on the backend
+        assert_eq!(scheduler.next_ready(), Some(0));
+        scheduler.started(0, "b0".into());
+        assert_eq!(scheduler.next_ready(), Some(2));
+    }
+
+    #[test]
+    fn test_scheduler_keep_going() {
+        let mut scheduler = Scheduler::new(vec![vec![], vec![0], vec![]], true).unwrap();
+        scheduler.started(0, "b0".into());
+        scheduler.failed(0, "boom".into());
+
+        assert_eq!(scheduler.states[1], JobState::Skipped);
+        assert_eq!(scheduler.next_ready(), Some(2));
+    }
+
+    #[test]
+    fn test_scheduler_fail_fast() {
+        let mut scheduler = Scheduler::new(vec![vec![], vec![], vec![]], false).unwrap();
+        scheduler.started(0, "b0".into());
+        scheduler.started(1, "b1".into());
+        scheduler.failed(0, "boom".into());
+
+        assert_eq!(scheduler.next_ready(), None);
+        assert_eq!(scheduler.states[2], JobState::Skipped);
+        // Builds already submitted are left to finish
+        assert!(!scheduler.is_done());
+        scheduler.completed(1);
+        assert!(scheduler.is_done());
+        assert_eq!(scheduler.failures(), 2);
+    }
+
+    #[test]
+    fn test_progress_plain_prints_changes_only() {
+        let mut view = ProgressView::new(false);
+        let mut out = Vec::new();
+        let names = ["api", "web"];
+
+        view.render(&mut out, &names, &[JobState::Complete, JobState::Pending]);
+        view.render(&mut out, &names, &[JobState::Complete, JobState::Skipped]);
+
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(text, "api  done\nweb  pending\nweb  skipped\n");
+    }
+
+    #[test]
+    fn test_plan_with_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("services/api")).unwrap();
+        std::fs::create_dir_all(dir.path().join("services/web")).unwrap();
+        std::fs::write(dir.path().join("services/api/Dockerfile"), "FROM scratch\n").unwrap();
+        std::fs::write(dir.path().join("services/web/Dockerfile"), "FROM api-server\n").unwrap();
+        std::fs::write(
+            dir.path().join(MANIFEST_FILE),
+            "[[image]]\npath = \"services/api\"\nname = \"api-server\"\nbuild_args = { V = \"2\" }\n",
+        )
+        .unwrap();
+
+        let jobs = plan(dir.path(), DEFAULT_GLOB, &HashMap::new()).unwrap();
+        let names: Vec<&str> = jobs.iter().map(|j| j.name.as_str()).collect();
+        assert_eq!(names, vec!["api-server", "web"]);
+        assert_eq!(jobs[0].build_args.get("V"), Some(&"2".to_string()));
+        assert_eq!(dependency_graph(&jobs).unwrap(), vec![vec![], vec![0]]);
+    }
+}
@@ -0,0 +1,79 @@
+//! Container ID files (`run --cidfile`)
+//!
+//! The file is claimed before the container is created, so an existing file
+//! fails the run before anything happens on the daemon. It stays empty until
+//! the create succeeds and is removed again if it doesn't.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// A claimed ID file, removed on drop unless the ID was written
+pub struct CidFile {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+impl CidFile {
+    /// Create `path`, failing if it already exists
+    pub fn claim(path: impl Into<PathBuf>) -> Result<Self, String> {
+        let path = path.into();
+        let file = OpenOptions::new().write(true).create_new(true).open(&path).map_err(|e| match e.kind() {
+            std::io::ErrorKind::AlreadyExists => format!("Container ID file {} already exists", path.display()),
+            _ => format!("Failed to create container ID file {}: {}", path.display(), e),
+        })?;
+        Ok(Self { path, file: Some(file) })
+    }
+
+    /// Write the created container's ID and keep the file
+    pub fn write(mut self, id: &str) -> Result<(), String> {
+        let mut file = self.file.take().expect("ID file written twice");
+        if let Err(e) = file.write_all(id.as_bytes()) {
+            remove(&self.path);
+            return Err(format!("Failed to write container ID file {}: {}", self.path.display(), e));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for CidFile {
+    fn drop(&mut self) {
+        if self.file.is_some() {
+            remove(&self.path);
+        }
+    }
+}
+
+fn remove(path: &Path) {
+    let _ = std::fs::remove_file(path);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cidfile_holds_the_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("web.cid");
+
+        CidFile::claim(&path).unwrap().write("a1b2c3d4e5f6").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "a1b2c3d4e5f6");
+
+        // An existing file is never overwritten
+        let err = CidFile::claim(&path).err().unwrap();
+        assert!(err.contains("already exists"), "{}", err);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "a1b2c3d4e5f6");
+    }
+
+    #[test]
+    fn test_cidfile_removed_when_create_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("web.cid");
+
+        let cidfile = CidFile::claim(&path).unwrap();
+        assert!(path.exists());
+        drop(cidfile);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,131 @@
+//! Detach key sequences for interactive sessions (`--detach-keys`)
+
+/// Default detach sequence: Ctrl-P followed by Ctrl-Q
+pub const DEFAULT_DETACH_KEYS: &str = "ctrl-p,ctrl-q";
+
+/// Parse a comma-separated key list such as `ctrl-p,ctrl-q` or `ctrl-a,d`
+///
+/// An empty string disables detaching.
+pub fn parse_detach_keys(spec: &str) -> Result<Vec<u8>, String> {
+    if spec.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    spec.split(',').map(parse_key).collect()
+}
+
+fn parse_key(key: &str) -> Result<u8, String> {
+    let lower = key.to_ascii_lowercase();
+
+    if let Some(rest) = lower.strip_prefix("ctrl-") {
+        return match rest.as_bytes() {
+            [c @ b'a'..=b'z'] => Ok(c - b'a' + 1),
+            [b'@'] => Ok(0),
+            [b'['] => Ok(27),
+            [b'\\'] => Ok(28),
+            [b']'] => Ok(29),
+            [b'^'] => Ok(30),
+            [b'_'] => Ok(31),
+            _ => Err(format!("Invalid detach key '{}'", key)),
+        };
+    }
+
+    match key.as_bytes() {
+        [c] if c.is_ascii() => Ok(*c),
+        _ => Err(format!("Invalid detach key '{}'", key)),
+    }
+}
+
+/// Scans terminal input for the detach sequence
+///
+/// Bytes that could be the start of the sequence are held back until it is
+/// either completed (detach) or broken (the held bytes are forwarded).
+#[cfg_attr(not(target_os = "freebsd"), allow(dead_code))]
+#[derive(Debug)]
+pub struct DetachDetector {
+    sequence: Vec<u8>,
+    matched: usize,
+}
+
+#[cfg_attr(not(target_os = "freebsd"), allow(dead_code))]
+impl DetachDetector {
+    pub fn new(sequence: Vec<u8>) -> Self {
+        Self { sequence, matched: 0 }
+    }
+
+    /// Feed input, returning the bytes to forward to the session and whether
+    /// the detach sequence was completed. Input after the sequence is dropped.
+    pub fn scan(&mut self, input: &[u8]) -> (Vec<u8>, bool) {
+        let mut forward = Vec::with_capacity(input.len());
+
+        if self.sequence.is_empty() {
+            forward.extend_from_slice(input);
+            return (forward, false);
+        }
+
+        for &byte in input {
+            if byte == self.sequence[self.matched] {
+                self.matched += 1;
+                if self.matched == self.sequence.len() {
+                    self.matched = 0;
+                    return (forward, true);
+                }
+                continue;
+            }
+
+            // Sequence broken: release what was held back, then see whether
+            // this byte starts a new attempt
+            forward.extend_from_slice(&self.sequence[..self.matched]);
+            self.matched = 0;
+
+            if byte == self.sequence[0] {
+                self.matched = 1;
+            } else {
+                forward.push(byte);
+            }
+        }
+
+        (forward, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_detach_keys() {
+        assert_eq!(parse_detach_keys(DEFAULT_DETACH_KEYS).unwrap(), vec![0x10, 0x11]);
+        assert_eq!(parse_detach_keys("ctrl-a,d").unwrap(), vec![0x01, b'd']);
+        assert_eq!(parse_detach_keys("Ctrl-@,ctrl-_").unwrap(), vec![0, 31]);
+        assert!(parse_detach_keys("").unwrap().is_empty());
+        assert!(parse_detach_keys("ctrl-1").is_err());
+        assert!(parse_detach_keys("ab").is_err());
+    }
+
+    #[test]
+    fn test_detach_sequence_detection() {
+        let mut detector = DetachDetector::new(vec![0x10, 0x11]);
+
+        assert_eq!(detector.scan(b"ls\r"), (b"ls\r".to_vec(), false));
+        // Split across reads, with trailing input discarded
+        assert_eq!(detector.scan(b"x\x10"), (b"x".to_vec(), false));
+        assert_eq!(detector.scan(b"\x11junk"), (Vec::new(), true));
+    }
+
+    #[test]
+    fn test_detach_partial_match_is_forwarded() {
+        let mut detector = DetachDetector::new(vec![0x10, 0x11]);
+
+        // Ctrl-P alone (e.g. shell history) still reaches the session
+        assert_eq!(detector.scan(b"\x10a"), (b"\x10a".to_vec(), false));
+        // A repeated first key restarts the match
+        assert_eq!(detector.scan(b"\x10\x10\x11"), (b"\x10".to_vec(), true));
+    }
+
+    #[test]
+    fn test_detach_disabled() {
+        let mut detector = DetachDetector::new(Vec::new());
+        assert_eq!(detector.scan(b"\x10\x11"), (b"\x10\x11".to_vec(), false));
+    }
+}
@@ -0,0 +1,205 @@
+//! Environment files (`run --env-file`, `build --build-arg-file`)
+//!
+//! The format is Docker's: one `KEY=VALUE` per line, blank lines and lines
+//! starting with `#` ignored, an optional `export ` in front tolerated. The
+//! value is everything after the first `=`, taken literally: no quotes are
+//! removed, nothing is expanded and trailing spaces stay. A line with only a
+//! `KEY` takes the value from the CLI's own environment and is skipped when
+//! that isn't set. CRLF line endings and a leading byte order mark are
+//! accepted.
+//!
+//! Files are read before the daemon is contacted, so a bad line fails the
+//! command naming the file and line. Later files override earlier ones and
+//! the explicit `-e`/`--build-arg` flags override every file ([`merge`]).
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// The variables a file sets, in file order, with `lookup` answering for
+/// lines without a value
+///
+/// Errors read `<source>:<line>: <problem>`.
+pub fn parse(content: &str, source: &Path, lookup: impl Fn(&str) -> Option<String>) -> Result<Vec<(String, String)>, String> {
+    let content = content.strip_prefix('\u{feff}').unwrap_or(content);
+    let mut vars = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let fail = |problem: String| Err(format!("{}:{}: {}", source.display(), index + 1, problem));
+        let line = line.strip_suffix('\r').unwrap_or(line).trim_start();
+        if line.trim_end().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = strip_export(line);
+
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key, Some(value)),
+            None => (line.trim_end(), None),
+        };
+        if key.is_empty() {
+            return fail("missing variable name before '='".to_string());
+        }
+        if key.chars().any(|c| c.is_whitespace() || c.is_control() || c == '\u{feff}') {
+            return fail(format!("invalid variable name '{}': no whitespace or control characters", key.escape_debug()));
+        }
+        match value {
+            Some(value) if value.contains('\0') => return fail(format!("value of {} contains a NUL byte", key)),
+            Some(value) => vars.push((key.to_string(), value.to_string())),
+            None => {
+                if let Some(value) = lookup(key) {
+                    vars.push((key.to_string(), value));
+                }
+            }
+        }
+    }
+    Ok(vars)
+}
+
+/// `line` without a leading `export` and the whitespace after it
+fn strip_export(line: &str) -> &str {
+    match line.strip_prefix("export") {
+        Some(rest) if rest.starts_with([' ', '\t']) => rest.trim_start(),
+        _ => line,
+    }
+}
+
+/// Read and parse the file at `path`
+pub fn read(path: &Path) -> Result<Vec<(String, String)>, String> {
+    let content = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    parse(&content, path, |key| std::env::var(key).ok())
+}
+
+/// The variables of `files` in order, then `explicit` on top
+pub fn merge(files: &[PathBuf], explicit: HashMap<String, String>) -> Result<HashMap<String, String>, String> {
+    let mut vars = HashMap::new();
+    for path in files {
+        vars.extend(read(path)?);
+    }
+    vars.extend(explicit);
+    Ok(vars)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(content: &str) -> Vec<(String, String)> {
+        parse(content, Path::new("app.env"), |_| None).unwrap()
+    }
+
+    fn pairs(list: &[(&str, &str)]) -> Vec<(String, String)> {
+        list.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    fn error(content: &str) -> String {
+        parse(content, Path::new("app.env"), |_| None).unwrap_err()
+    }
+
+    #[test]
+    fn test_parse_basic() {
+        let content = "# database\nDB_HOST=db.internal\n\n   \nDB_PORT=5432\n  # indented comment\n";
+        assert_eq!(vars(content), pairs(&[("DB_HOST", "db.internal"), ("DB_PORT", "5432")]));
+        assert_eq!(vars(""), pairs(&[]));
+        // The last line needs no newline
+        assert_eq!(vars("A=1"), pairs(&[("A", "1")]));
+    }
+
+    #[test]
+    fn test_parse_values_are_literal() {
+        let cases = [
+            // Everything after the first '='
+            ("URL=postgres://u:p@h/db?sslmode=require", "postgres://u:p@h/db?sslmode=require"),
+            ("TOKEN=abc==", "abc=="),
+            ("A==", "="),
+            // Empty
+            ("EMPTY=", ""),
+            // Quotes, '#', '$' and spaces stay as written
+            ("Q=\"quoted\"", "\"quoted\""),
+            ("S='single'", "'single'"),
+            ("HASH=a#b", "a#b"),
+            ("HASH2=a # not a comment", "a # not a comment"),
+            ("HOME_REF=$HOME/${USER}", "$HOME/${USER}"),
+            ("SPACES=  padded  ", "  padded  "),
+            ("BACKSLASH=a\\nb", "a\\nb"),
+            ("TAB=a\tb", "a\tb"),
+            // Unicode on either side
+            ("GREETING=héllo wörld ✓", "héllo wörld ✓"),
+            ("ÜBER=1", "1"),
+        ];
+        for (line, value) in cases {
+            let (key, _) = line.split_once('=').unwrap();
+            assert_eq!(vars(line), pairs(&[(key, value)]), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn test_parse_line_endings_and_bom() {
+        assert_eq!(vars("A=1\r\nB=2\r\n"), pairs(&[("A", "1"), ("B", "2")]));
+        // A file ending in a bare CR
+        assert_eq!(vars("A=1\r\nB=\r"), pairs(&[("A", "1"), ("B", "")]));
+        // Blank and comment lines in CRLF
+        assert_eq!(vars("\r\n# c\r\nA=1\r\n"), pairs(&[("A", "1")]));
+        assert_eq!(vars("\u{feff}A=1\nB=2"), pairs(&[("A", "1"), ("B", "2")]));
+        // Only a leading BOM is one
+        assert!(error("A=1\n\u{feff}B=2").starts_with("app.env:2: invalid variable name"));
+    }
+
+    #[test]
+    fn test_parse_export_prefix() {
+        assert_eq!(vars("export A=1\nexport\tB=2\n  export   C=3"), pairs(&[("A", "1"), ("B", "2"), ("C", "3")]));
+        // Without whitespace after it, "export" is part of the name
+        assert_eq!(vars("export=1\nexported=2"), pairs(&[("export", "1"), ("exported", "2")]));
+        assert_eq!(vars("EXPORT_DIR=/srv"), pairs(&[("EXPORT_DIR", "/srv")]));
+    }
+
+    #[test]
+    fn test_parse_without_value() {
+        let lookup = |key: &str| (key == "SET").then(|| "from env".to_string());
+        let parsed = parse("SET\nUNSET\nexport SET  \nA=1", Path::new("app.env"), lookup).unwrap();
+        assert_eq!(parsed, pairs(&[("SET", "from env"), ("SET", "from env"), ("A", "1")]));
+    }
+
+    #[test]
+    fn test_parse_errors_name_file_and_line() {
+        assert_eq!(error("A=1\n\nB C=2"), "app.env:3: invalid variable name 'B C': no whitespace or control characters");
+        assert_eq!(error("=value"), "app.env:1: missing variable name before '='");
+        assert_eq!(error("A =1"), "app.env:1: invalid variable name 'A ': no whitespace or control characters");
+        assert_eq!(error("# ok\nA=1\0"), "app.env:2: value of A contains a NUL byte");
+        assert!(error("export =1").starts_with("app.env:1: missing variable name"));
+    }
+
+    #[test]
+    fn test_merge_precedence() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("base.env");
+        let prod = dir.path().join("prod.env");
+        std::fs::write(&base, "A=base\nB=base\nC=base\n").unwrap();
+        std::fs::write(&prod, "B=prod\nC=prod\nC=prod-again\n").unwrap();
+
+        // Later lines, later files, then the flags win
+        let explicit = HashMap::from([("C".to_string(), "flag".to_string())]);
+        let merged = merge(&[base.clone(), prod.clone()], explicit).unwrap();
+        let expected = HashMap::from([("A", "base"), ("B", "prod"), ("C", "flag")]);
+        assert_eq!(merged, expected.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect());
+
+        assert_eq!(merge(&[prod, base], HashMap::new()).unwrap()["B"], "base");
+        assert!(merge(&[], HashMap::new()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_merge_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing.env");
+        let err = merge(std::slice::from_ref(&missing), HashMap::new()).unwrap_err();
+        assert!(err.starts_with(&format!("Failed to read {}", missing.display())), "{}", err);
+
+        let bad = dir.path().join("bad.env");
+        std::fs::write(&bad, "A=1\nnot valid=2\n").unwrap();
+        assert_eq!(
+            merge(std::slice::from_ref(&bad), HashMap::new()).unwrap_err(),
+            format!("{}:2: invalid variable name 'not valid': no whitespace or control characters", bad.display())
+        );
+
+        let binary = dir.path().join("binary.env");
+        std::fs::write(&binary, b"A=\xff\xfe\n").unwrap();
+        assert!(merge(std::slice::from_ref(&binary), HashMap::new()).unwrap_err().contains(&binary.display().to_string()));
+    }
+}
@@ -0,0 +1,178 @@
+//! How a command run by `exec` or `run` ended, and the CLI's exit code for it
+//!
+//! A kawakaze command that fails exits with a [`code`] for what went wrong,
+//! so scripts can tell a missing container from a daemon that isn't
+//! running without parsing the message.
+
+use std::os::unix::process::ExitStatusExt;
+
+use kawakaze_client::ClientError;
+use kawakaze_client::types::Family;
+
+/// Exit codes of a failed kawakaze command; 2 is clap's for bad usage
+pub mod code {
+    /// Any other failure, such as a file that couldn't be read
+    pub const FAILURE: i32 = 1;
+    /// The daemon refused the request as invalid or not allowed
+    pub const INVALID: i32 = 3;
+    /// The container, image, jail or other resource doesn't exist
+    pub const NOT_FOUND: i32 = 4;
+    /// The resource is in the wrong state, in use, protected or the name taken
+    pub const CONFLICT: i32 = 5;
+    /// The daemon couldn't be reached, or the connection broke
+    pub const CONNECTION: i32 = 6;
+    /// The daemon failed, or can't do it right now
+    pub const DAEMON: i32 = 7;
+}
+
+/// Why a kawakaze command failed: the message printed, and the exit code
+#[derive(Debug)]
+pub struct CliError {
+    pub message: String,
+    pub code: i32,
+}
+
+impl CliError {
+    pub fn new(code: i32, message: impl Into<String>) -> Self {
+        Self { message: message.into(), code }
+    }
+}
+
+impl std::fmt::Display for CliError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl From<String> for CliError {
+    fn from(message: String) -> Self {
+        Self { message, code: code::FAILURE }
+    }
+}
+
+impl From<&str> for CliError {
+    fn from(message: &str) -> Self {
+        Self::from(message.to_string())
+    }
+}
+
+impl From<ClientError> for CliError {
+    fn from(err: ClientError) -> Self {
+        let code = match (err.family(), &err) {
+            (Some(Family::Invalid), _) => code::INVALID,
+            (Some(Family::NotFound), _) => code::NOT_FOUND,
+            (Some(Family::Conflict), _) => code::CONFLICT,
+            (Some(Family::Unavailable | Family::Internal), _) => code::DAEMON,
+            (None, ClientError::Connect { .. } | ClientError::Io(_)) => code::CONNECTION,
+            (None, ClientError::Protocol(_)) => code::DAEMON,
+            (None, _) => code::FAILURE,
+        };
+        Self { message: err.to_string(), code }
+    }
+}
+
+/// End of an attached command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// Exited with this code
+    Exited(i32),
+    /// Killed by this signal
+    Signaled(i32),
+    /// The session was detached; the command is still running
+    #[cfg_attr(not(target_os = "freebsd"), allow(dead_code))]
+    Detached,
+}
+
+impl ExitStatus {
+    pub const SUCCESS: Self = Self::Exited(0);
+
+    /// From a status filled in by `waitpid`
+    #[cfg_attr(not(target_os = "freebsd"), allow(dead_code))]
+    pub fn from_wait_status(status: libc::c_int) -> Self {
+        if libc::WIFSIGNALED(status) {
+            Self::Signaled(libc::WTERMSIG(status))
+        } else {
+            Self::Exited(libc::WEXITSTATUS(status))
+        }
+    }
+
+    /// The CLI's exit code: the command's own, or 128 plus the signal as a
+    /// shell reports it
+    pub fn code(self) -> i32 {
+        match self {
+            Self::Exited(code) => code,
+            Self::Signaled(signal) => 128 + signal,
+            Self::Detached => 0,
+        }
+    }
+}
+
+impl From<std::process::ExitStatus> for ExitStatus {
+    fn from(status: std::process::ExitStatus) -> Self {
+        match (status.code(), status.signal()) {
+            (Some(code), _) => Self::Exited(code),
+            (None, Some(signal)) => Self::Signaled(signal),
+            (None, None) => Self::Exited(-1),
+        }
+    }
+}
+
+/// Leave with `status`'s code unless it is 0, as `docker exec` does; the
+/// command already said why it failed, so nothing more is printed
+pub fn finish(status: ExitStatus) -> Result<(), CliError> {
+    match status.code() {
+        0 => Ok(()),
+        code => std::process::exit(code),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_error_codes() {
+        use kawakaze_backend::api::{ApiError, Response, status};
+
+        let code = |response| CliError::from(ClientError::from_response(response)).code;
+        assert_eq!(code(Response::invalid_body("missing field `image_id`")), code::INVALID);
+        assert_eq!(code(Response::container_not_found("web")), code::NOT_FOUND);
+        assert_eq!(code(Response::error(status::CONFLICT, ApiError::ResourceProtected("web".to_string()))), code::CONFLICT);
+        assert_eq!(code(Response::error(status::SERVICE_UNAVAILABLE, ApiError::ShuttingDown())), code::DAEMON);
+        assert_eq!(code(Response::internal_error("boom")), code::DAEMON);
+
+        let refused = std::io::Error::from(std::io::ErrorKind::ConnectionRefused);
+        let err = CliError::from(ClientError::Connect { path: "/var/run/kawakaze.sock".into(), source: refused });
+        assert_eq!(err.code, code::CONNECTION);
+        assert!(err.message.starts_with("Failed to connect to /var/run/kawakaze.sock"), "{}", err);
+        assert_eq!(CliError::from("no such file".to_string()).code, code::FAILURE);
+    }
+
+    #[test]
+    fn test_from_wait_status() {
+        assert_eq!(ExitStatus::from_wait_status(0), ExitStatus::SUCCESS);
+        assert_eq!(ExitStatus::from_wait_status(3 << 8), ExitStatus::Exited(3));
+        assert_eq!(ExitStatus::from_wait_status(libc::SIGKILL), ExitStatus::Signaled(libc::SIGKILL));
+
+        let status = std::process::ExitStatus::from_raw(42 << 8);
+        assert_eq!(ExitStatus::from(status), ExitStatus::Exited(42));
+        let status = std::process::ExitStatus::from_raw(libc::SIGTERM);
+        assert_eq!(ExitStatus::from(status), ExitStatus::Signaled(libc::SIGTERM));
+    }
+
+    #[test]
+    fn test_child_status() {
+        let status = |script: &str| ExitStatus::from(std::process::Command::new("/bin/sh").args(["-c", script]).status().unwrap());
+        assert_eq!(status("exit 5"), ExitStatus::Exited(5));
+        assert_eq!(status("kill -9 $$"), ExitStatus::Signaled(libc::SIGKILL));
+        assert_eq!(status("kill -9 $$").code(), 137);
+    }
+
+    #[test]
+    fn test_codes() {
+        assert_eq!(ExitStatus::Exited(7).code(), 7);
+        assert_eq!(ExitStatus::Signaled(libc::SIGINT).code(), 130);
+        assert_eq!(ExitStatus::Detached.code(), 0);
+        assert!(finish(ExitStatus::SUCCESS).is_ok());
+    }
+}
@@ -1,77 +1,220 @@
-use clap::{Parser, Subcommand};
-use futures::{SinkExt, StreamExt};
-use kawakaze_backend::api::{
-    BuildImageRequest, CreateContainerRequest, Endpoint, ExecRequest, PortMapping,
-    Request,
+mod batch;
+mod cidfile;
+mod detach;
+mod envfile;
+mod exit;
+mod output;
+
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use futures::StreamExt;
+use kawakaze_client::types::{
+    AdoptOrphanRequest, BootOutcome, BuildImageRequest, BuildNetwork, BuildStatus, CheckStatus, ContainerListItem, ContainerSummary,
+    CreateContainerRequest, DestroyOrphanRequest, ExecKind, ExecRequest, ExecSessionRequest, ImageListItem, JailUpdateRequest, LogEntry, LogSource, LogsRequest, Mount, NameKind, OperationProgress,
+    MountPermissions, Phase, Placement, PortMapping, TmpfsMount,
+    PruneImagesRequest, PruneSnapshotsRequest, RemoveRequest, StopMode, UpdateRequest, UpgradeProgress, UpgradeRequest,
+    Termination, Timestamp, UpgradeStarted, UpgradeStatus,
 };
+use exit::{CliError, ExitStatus};
+use kawakaze_backend::names::short_id;
+use kawakaze_client::{Client, LogStream};
 use serde_json::Value;
-use std::collections::HashMap;
-use tokio::net::UnixStream;
-use tokio_util::codec::{Framed, LinesCodec};
-
-const SOCKET_PATH: &str = "/var/run/kawakaze.sock";
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
 
 #[derive(Parser)]
 #[command(name = "kawakaze")]
 #[command(about = "Kawakaze - FreeBSD jail manager", long_about = None)]
 #[command(version)]
 struct Cli {
+    /// Print status messages to stderr
+    #[arg(long, global = true)]
+    verbose: bool,
+    /// Print progress as plain timestamped lines even on a terminal
+    #[arg(long, global = true)]
+    no_progress: bool,
     #[command(subcommand)]
     command: Commands,
 }
 
+/// What `run` and `create` make a container from
+#[derive(Args)]
+struct ContainerArgs {
+    /// Image ID to run
+    image: String,
+    /// Container name
+    #[arg(short, long)]
+    name: Option<String>,
+    /// Write the container ID to this file, which must not exist yet
+    #[arg(long, value_name = "PATH")]
+    cidfile: Option<PathBuf>,
+    /// Publish port ([hostIp:][hostPort:]containerPort[/protocol], IPv6 hostIp in brackets; without hostPort a free one is picked)
+    #[arg(short = 'p', long)]
+    publish: Vec<String>,
+    /// Volume mount (source:destination[:ro|delegate]); host paths must be under the daemon's allowed mount roots, other sources attach an existing ZFS dataset
+    #[arg(short = 'v', long)]
+    volume: Vec<String>,
+    /// tmpfs mount (/path[:size=64m,mode=1777]; size takes k, m and g), writable even on a read-only root
+    #[arg(long, value_name = "PATH[:OPTIONS]")]
+    tmpfs: Vec<String>,
+    /// Environment variable (key=value); overrides --env-file
+    #[arg(short, long)]
+    env: Vec<String>,
+    /// Read environment variables from a file of KEY=VALUE lines
+    /// (repeatable; later files override earlier ones)
+    #[arg(long, value_name = "PATH")]
+    env_file: Vec<PathBuf>,
+    /// Restart policy (no, on-restart, on-fail)
+    #[arg(long, default_value = "no")]
+    restart: String,
+    /// Working directory
+    #[arg(long)]
+    workdir: Option<String>,
+    /// User to run as
+    #[arg(long)]
+    user: Option<String>,
+    /// Protect the container against accidental removal
+    #[arg(long)]
+    protect: bool,
+    /// Don't run the image's HEALTHCHECK for this container
+    #[arg(long)]
+    no_healthcheck: bool,
+    /// Start the container whenever the daemon boots
+    #[arg(long)]
+    boot: bool,
+    /// Put the container on an encrypted ZFS dataset
+    #[arg(long)]
+    encrypted: bool,
+    /// Mount the root read-only; only volumes and the configured tmpfs
+    /// paths (/tmp, /var/run) are writable
+    #[arg(long)]
+    read_only: bool,
+    /// Extra name other containers on the network can reach this one by (repeatable)
+    #[arg(long = "network-alias", value_name = "NAME")]
+    network_alias: Vec<String>,
+    /// Host name inside the container (default: its name, following
+    /// renames)
+    #[arg(long)]
+    hostname: Option<String>,
+    /// File systems the container may mount itself (none, nullfs,
+    /// tmpfs, zfs, all); the daemon must allow in-jail mounts
+    #[arg(long, value_name = "FSTYPE", default_value = "none")]
+    mount_permissions: MountPermissions,
+    /// Create the container in the background and show each step
+    #[arg(long = "async")]
+    run_async: bool,
+    /// Placement constraint, e.g. node.labels.zone==eu (repeatable)
+    #[arg(long = "constraint", value_name = "EXPR")]
+    constraint: Vec<String>,
+    /// Placement preference, e.g. spread=node.labels.zone (repeatable)
+    #[arg(long = "placement-pref", value_name = "PREF")]
+    placement_pref: Vec<String>,
+    /// sysctl set in the jail at start, e.g. net.inet.ip.forwarding=1
+    /// (repeatable); the daemon only allows the ones a jail can set
+    #[arg(long, value_name = "KEY=VALUE")]
+    sysctl: Vec<String>,
+    /// Free-form notes shown by inspect, e.g. the owning team (at most
+    /// 4096 bytes)
+    #[arg(long, value_name = "TEXT")]
+    description: Option<String>,
+    /// Command to run
+    #[arg(trailing_var_arg = true)]
+    command: Vec<String>,
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// Build an image from a Dockerfile
     Build {
         /// Path to the Dockerfile
-        path: String,
+        #[arg(required_unless_present = "recursive", conflicts_with = "recursive")]
+        path: Option<String>,
         /// Name for the image
-        #[arg(short, long)]
-        name: String,
-        /// Build arguments (key=value)
+        #[arg(short, long, required_unless_present = "recursive", conflicts_with = "recursive")]
+        name: Option<String>,
+        /// Build every Dockerfile under DIR, ordered by FROM dependencies
+        #[arg(long, value_name = "DIR")]
+        recursive: Option<String>,
+        /// Pattern used to discover Dockerfiles with --recursive
+        #[arg(long, default_value = batch::DEFAULT_GLOB, requires = "recursive")]
+        glob: String,
+        /// Maximum number of concurrent builds with --recursive
+        #[arg(short, long, default_value = "4", requires = "recursive")]
+        jobs: usize,
+        /// Continue building independent images after a failure
+        #[arg(long, requires = "recursive")]
+        keep_going: bool,
+        /// Build arguments (key=value); override --build-arg-file
         #[arg(short, long)]
         build_args: Vec<String>,
+        /// Read build arguments from a file of KEY=VALUE lines (repeatable;
+        /// later files override earlier ones)
+        #[arg(long, value_name = "PATH")]
+        build_arg_file: Vec<PathBuf>,
+        /// Secret for RUN steps: id=NAME[,src=FILE|,env=VAR]. Read from the
+        /// environment variable NAME unless a source is given; never stored
+        /// with the image
+        #[arg(long, value_name = "SPEC")]
+        secret: Vec<String>,
+        /// Protect the image against accidental removal
+        #[arg(long)]
+        protect: bool,
+        /// Fail the build on references to undefined variables
+        #[arg(long)]
+        strict_vars: bool,
+        /// Build even if the image is up to date with the Dockerfile and context
+        #[arg(long, alias = "pull")]
+        no_cache: bool,
+        /// Network for RUN steps: "default" shares the host's, "none" runs
+        /// each step in a jail without network
+        #[arg(long, default_value = "default", value_name = "MODE")]
+        network: BuildNetwork,
+        /// Print the build ID and return without waiting for the build
+        #[arg(short, long, conflicts_with = "recursive")]
+        detach: bool,
     },
 
-    /// Run a container
+    /// Run a container: create it, start it, and attach with -i/-t
     Run {
-        /// Image ID to run
-        image: String,
-        /// Container name
-        #[arg(short, long)]
-        name: Option<String>,
+        #[command(flatten)]
+        container: ContainerArgs,
         /// Interactive mode (keep STDIN open)
         #[arg(short = 'i', long)]
         interactive: bool,
         /// Pseudo-TTY (allocate a terminal)
         #[arg(short = 't', long)]
         tty: bool,
-        /// Publish port (hostPort:containerPort or hostPort:containerPort/protocol)
-        #[arg(short = 'p', long)]
-        publish: Vec<String>,
-        /// Volume mount (source:destination)
-        #[arg(short = 'v', long)]
-        volume: Vec<String>,
-        /// Environment variable (key=value)
-        #[arg(short, long)]
-        env: Vec<String>,
-        /// Restart policy (no, on-restart, on-fail)
-        #[arg(long, default_value = "no")]
-        restart: String,
-        /// Working directory
-        #[arg(long)]
-        workdir: Option<String>,
-        /// User to run as
-        #[arg(long)]
-        user: Option<String>,
-        /// Command to run
-        #[arg(trailing_var_arg = true)]
-        command: Vec<String>,
+        /// Run in the background and print the container ID (the default
+        /// without -i/-t)
+        #[arg(short, long, conflicts_with_all = ["interactive", "tty"])]
+        detach: bool,
+        /// Key sequence for detaching from a TTY session (e.g. ctrl-p,ctrl-q)
+        #[arg(long, default_value = detach::DEFAULT_DETACH_KEYS)]
+        detach_keys: String,
+    },
+
+    /// Create a container without starting it, and print its ID
+    Create {
+        #[command(flatten)]
+        container: ContainerArgs,
     },
 
     /// List containers
-    Ps,
+    Ps {
+        /// Don't print the state counts after the table
+        #[arg(long, conflicts_with = "summary")]
+        no_summary: bool,
+        /// Only containers matching KEY=VALUE exactly or KEY~=VALUE as a
+        /// substring, ignoring case; keys are name, state and description
+        /// (repeatable, all must match)
+        #[arg(long, value_name = "FILTER", conflicts_with = "summary")]
+        filter: Vec<String>,
+        /// Print only the state counts
+        #[arg(long)]
+        summary: bool,
+        /// Output format
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+    },
 
     /// Start container
     Start {
@@ -83,6 +226,10 @@ enum Commands {
     Stop {
         /// Container ID or name
         container: String,
+        /// Kill the container's processes but keep its jail (and JID) for
+        /// the next start instead of removing it
+        #[arg(long)]
+        freeze: bool,
     },
 
     /// Remove container
@@ -92,10 +239,79 @@ enum Commands {
         /// Force removal
         #[arg(short, long)]
         force: bool,
+        /// Remove even if the container is protected (requires --force)
+        #[arg(long, requires = "force")]
+        override_protection: bool,
+        /// Destroy the datasets in the background and show how much is
+        /// left (the default for containers over 1 GiB)
+        #[arg(long = "async")]
+        run_async: bool,
+    },
+
+    /// Update container, image or jail settings
+    Update {
+        /// Container (or image, with --image, or jail, with --jail) ID or name
+        target: String,
+        /// Target an image instead of a container
+        #[arg(long, conflicts_with = "jail")]
+        image: bool,
+        /// Target a jail instead of a container; only --description applies
+        #[arg(long, conflicts_with_all = ["protect", "unprotect", "boot", "no_boot", "read_only"])]
+        jail: bool,
+        /// Protect against accidental removal
+        #[arg(long, conflicts_with = "unprotect")]
+        protect: bool,
+        /// Remove protection
+        #[arg(long)]
+        unprotect: bool,
+        /// Start the container whenever the daemon boots
+        #[arg(long, conflicts_with = "no_boot")]
+        boot: bool,
+        /// Stop starting the container at boot
+        #[arg(long)]
+        no_boot: bool,
+        /// Mount the container's root read-only (`--read-only=false` to
+        /// clear); the container must be stopped
+        #[arg(long, value_name = "BOOL", num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+        read_only: Option<bool>,
+        /// Set the free-form notes of a container or jail; "" clears them
+        #[arg(long, value_name = "TEXT")]
+        description: Option<String>,
+    },
+
+    /// Rename a container; its host name follows unless set with
+    /// `run --hostname`
+    Rename {
+        /// Container ID or name
+        container: String,
+        /// New name
+        name: String,
     },
 
     /// List images
-    Images,
+    Images {
+        /// Also show intermediate images (build cache layers and leftovers)
+        #[arg(short, long)]
+        all: bool,
+    },
+
+    /// Manage images
+    Image {
+        #[command(subcommand)]
+        command: ImageCommands,
+    },
+
+    /// Manage jails
+    Jail {
+        #[command(subcommand)]
+        command: JailCommands,
+    },
+
+    /// Manage container addresses
+    Network {
+        #[command(subcommand)]
+        command: NetworkCommands,
+    },
 
     /// Remove image
     Rmi {
@@ -104,6 +320,9 @@ enum Commands {
         /// Force removal
         #[arg(short, long)]
         force: bool,
+        /// Remove even if the image is protected (requires --force)
+        #[arg(long, requires = "force")]
+        override_protection: bool,
     },
 
     /// View container logs
@@ -116,6 +335,19 @@ enum Commands {
         /// Number of lines to show from the end
         #[arg(short = 'n', long, default_value = "100")]
         tail: usize,
+        /// Prefix each line with its RFC 3339 timestamp
+        #[arg(short, long)]
+        timestamps: bool,
+        /// Which logs: the processes' output, the jail's /var/log/messages,
+        /// or both
+        #[arg(long, value_enum, default_value_t = LogSourceArg::Stdio)]
+        source: LogSourceArg,
+    },
+
+    /// Show the processes running in a container
+    Top {
+        /// Container ID or name
+        container: String,
     },
 
     /// Execute command in container
@@ -128,121 +360,440 @@ enum Commands {
         /// Pseudo-TTY (allocate a terminal)
         #[arg(short = 't', long)]
         tty: bool,
+        /// Key sequence for detaching from a TTY session (e.g. ctrl-p,ctrl-q)
+        #[arg(long, default_value = detach::DEFAULT_DETACH_KEYS)]
+        detach_keys: String,
         /// Command to execute
         #[arg(trailing_var_arg = true)]
         command: Vec<String>,
     },
 
+    /// List the commands running in containers through exec
+    ExecSessions {
+        /// Only this container's sessions (ID or name)
+        container: Option<String>,
+    },
+
+    /// Kill an exec session's processes: SIGTERM, then SIGKILL
+    ExecKill {
+        /// Session ID, as exec-sessions lists it
+        session: String,
+    },
+
     /// Inspect image or container
     Inspect {
         /// Image or container ID
         id: String,
     },
+
+    /// List image builds and jail bootstraps running or waiting for a slot
+    Tasks {
+        /// Cancel this queued task (build ID or jail name)
+        #[arg(long, value_name = "ID")]
+        cancel: Option<String>,
+    },
+
+    /// Show host and daemon information
+    Info,
+
+    /// Check that the host is set up for kawakaze
+    Doctor,
+
+    /// Inspect the daemon configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
+
+    /// System maintenance
+    System {
+        #[command(subcommand)]
+        command: SystemCommands,
+    },
+
+    /// Administrative operations on the daemon and its storage
+    Admin {
+        #[command(subcommand)]
+        command: AdminCommands,
+    },
+
+    /// Print a shell completion script (`kawakaze completions bash > /usr/local/share/bash-completion/completions/kawakaze`)
+    Completions {
+        /// Shell to complete for
+        shell: clap_complete::Shell,
+    },
+
+    /// Print the container or image names and IDs the completion scripts offer
+    #[command(name = "__complete", hide = true)]
+    Complete {
+        /// containers, running, stopped or images
+        #[arg(required = true)]
+        kinds: Vec<NameKind>,
+    },
+}
+
+#[derive(Subcommand)]
+enum SystemCommands {
+    /// List container/image datasets the database has no record of
+    Orphans {
+        /// Re-register this dataset (name exactly as listed)
+        #[arg(long, value_name = "DATASET", conflicts_with = "destroy")]
+        adopt: Option<String>,
+        /// Destroy this dataset (name exactly as listed)
+        #[arg(long, value_name = "DATASET")]
+        destroy: Option<String>,
+        /// Name for the adopted container or image (required for images)
+        #[arg(long, requires = "adopt", conflicts_with = "destroy")]
+        name: Option<String>,
+        /// Image an adopted container was created from
+        #[arg(long, requires = "adopt", conflicts_with = "destroy")]
+        image: Option<String>,
+    },
+    /// Show how long external commands (zfs, jail, ...) have been taking
+    Commands,
+    /// List the containers started at boot and how the last boot went
+    Boot,
+    /// Print the JSON Schema of every API route (`system api-schema > api.json`)
+    ApiSchema,
+}
+
+#[derive(Subcommand)]
+enum AdminCommands {
+    /// Destroy all but the newest snapshots of a dataset (cloned snapshots are kept)
+    PruneSnapshots {
+        /// Dataset below the configured pool root
+        dataset: String,
+        /// Number of newest snapshots to keep
+        #[arg(short, long)]
+        keep: usize,
+    },
+    /// Destroy the image and container snapshots nothing references
+    SnapshotGc {
+        /// List the snapshots that would be destroyed
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Change the daemon's log level until it restarts
+    LogLevel {
+        /// A level (error, warn, info, debug, trace) or filter directives
+        /// such as "info,kawakaze_backend::zfs=trace"
+        level: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum ImageCommands {
+    /// List the containers created from an image
+    Containers {
+        /// Image ID or name
+        image: String,
+    },
+    /// Remove dangling intermediate images
+    Prune {
+        /// Also remove user images that no container uses
+        #[arg(short, long)]
+        all: bool,
+    },
+    /// Apply freebsd-update to an image (rolled back on failure)
+    Upgrade {
+        /// Image ID or name
+        image: String,
+        /// Release to upgrade to (default: patch the current release)
+        #[arg(long, value_name = "RELEASE")]
+        to: Option<String>,
+        /// Wait for the upgrade to finish, showing its progress
+        #[arg(short, long)]
+        follow: bool,
+    },
+    /// Show the log of an image's build (or of a running or failed build by its ID)
+    BuildLog {
+        /// Image ID or name, or build ID
+        image: String,
+        /// Follow a running build's log until it finishes
+        #[arg(short, long)]
+        follow: bool,
+        /// Number of lines to show from the end
+        #[arg(short = 'n', long)]
+        tail: Option<usize>,
+        /// Prefix each line with its RFC 3339 timestamp
+        #[arg(short, long)]
+        timestamps: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum JailCommands {
+    /// Apply freebsd-update to a stopped jail (rolled back on failure)
+    Upgrade {
+        /// Jail name
+        name: String,
+        /// Release to upgrade to (default: patch the current release)
+        #[arg(long, value_name = "RELEASE")]
+        to: Option<String>,
+        /// Wait for the upgrade to finish, showing its progress
+        #[arg(short, long)]
+        follow: bool,
+    },
+    /// Show the log of a jail's last bootstrap
+    BootstrapLog {
+        /// Jail name
+        name: String,
+        /// Number of lines to show from the end
+        #[arg(short = 'n', long)]
+        tail: Option<usize>,
+        /// Prefix each line with its RFC 3339 timestamp
+        #[arg(short, long)]
+        timestamps: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum NetworkCommands {
+    /// Give a container without VNET an additional IPv4 or IPv6 address,
+    /// live if it is running (the address must be on a host interface)
+    Connect {
+        /// Container ID or name
+        container: String,
+        /// IPv4 or IPv6 address
+        ip: String,
+    },
+    /// Take an address added with `network connect` away again
+    Disconnect {
+        /// Container ID or name
+        container: String,
+        /// IPv4 or IPv6 address
+        ip: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommands {
+    /// Show the running configuration and any drift from the config file
+    Show,
+}
+
+/// How list commands print their results
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum OutputFormat {
+    Table,
+    Json,
+}
+
+/// `kawakaze logs --source`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum LogSourceArg {
+    Stdio,
+    Syslog,
+    All,
+}
+
+impl From<LogSourceArg> for LogSource {
+    fn from(source: LogSourceArg) -> Self {
+        match source {
+            LogSourceArg::Stdio => LogSource::Stdio,
+            LogSourceArg::Syslog => LogSource::Syslog,
+            LogSourceArg::All => LogSource::All,
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() {
     let cli = Cli::parse();
+    output::init(output::Output::new(cli.verbose, cli.no_progress));
 
     let result = match cli.command {
         Commands::Build {
             path,
             name,
+            recursive,
+            glob,
+            jobs,
+            keep_going,
             build_args,
-        } => build_image(path, name, build_args).await,
+            build_arg_file,
+            secret,
+            protect,
+            strict_vars,
+            no_cache,
+            network,
+            detach,
+        } => match (read_secrets(&secret), envfile::merge(&build_arg_file, parse_build_args(build_args))) {
+            (Err(e), _) | (_, Err(e)) => Err(e.into()),
+            (Ok(secrets), Ok(build_args)) => {
+                let options = batch::BuildOptions {
+                    build_args,
+                    secrets,
+                    protect,
+                    strict_vars,
+                    no_cache,
+                    network,
+                };
+                match recursive {
+                    Some(dir) => batch::run(std::path::Path::new(&dir), &glob, jobs, keep_going, options).await,
+                    // clap guarantees both are present without --recursive
+                    None => build_image(path.unwrap_or_default(), name.unwrap_or_default(), options, detach).await,
+                }
+            }
+        },
+
+        Commands::Run { container, interactive, tty, detach: _, detach_keys } => {
+            run_container(container, interactive, tty, detach_keys).await.and_then(exit::finish)
+        }
 
-        Commands::Run {
+        Commands::Create { container } => create_container(container).await,
+
+        Commands::Ps { no_summary, filter, summary, format } => list_containers(no_summary, summary, filter, format).await,
+
+        Commands::Start { container } => start_container(container).await,
+
+        Commands::Stop { container, freeze } => stop_container(container, freeze).await,
+
+        Commands::Rm {
+            container,
+            force,
+            override_protection,
+            run_async,
+        } => remove_container(container, force, override_protection, run_async).await,
+
+        Commands::Update {
+            target,
             image,
-            name,
-            interactive,
-            tty,
-            publish,
-            volume,
-            env,
-            restart,
-            workdir: _,
-            user: _,
-            command,
+            protect,
+            unprotect,
+            boot,
+            no_boot,
+            read_only,
+            jail,
+            description,
         } => {
-            run_container(image, name, interactive, tty, publish, volume, env, restart, command).await
+            let kind = if jail { UpdateTarget::Jail } else if image { UpdateTarget::Image } else { UpdateTarget::Container };
+            update(target, kind, flag(protect, unprotect), flag(boot, no_boot), read_only, description).await
         }
 
-        Commands::Ps => list_containers().await,
+        Commands::Rename { container, name } => rename(container, name).await,
 
-        Commands::Start { container } => start_container(container).await,
+        Commands::Images { all } => list_images(all).await,
+
+        Commands::Image {
+            command: ImageCommands::Containers { image },
+        } => image_containers(image).await,
+
+        Commands::Image {
+            command: ImageCommands::Prune { all },
+        } => prune_images(all).await,
+
+        Commands::Image {
+            command: ImageCommands::Upgrade { image, to, follow },
+        } => upgrade(image, true, to, follow).await,
+
+        Commands::Image {
+            command: ImageCommands::BuildLog { image, follow, tail, timestamps },
+        } => build_log(image, follow, tail, timestamps).await,
 
-        Commands::Stop { container } => stop_container(container).await,
+        Commands::Jail {
+            command: JailCommands::Upgrade { name, to, follow },
+        } => upgrade(name, false, to, follow).await,
 
-        Commands::Rm { container, force } => remove_container(container, force).await,
+        Commands::Jail {
+            command: JailCommands::BootstrapLog { name, tail, timestamps },
+        } => bootstrap_log(name, tail, timestamps).await,
 
-        Commands::Images => list_images().await,
+        Commands::Network {
+            command: NetworkCommands::Connect { container, ip },
+        } => network_connect(container, ip, true).await,
 
-        Commands::Rmi { image, force } => remove_image(image, force).await,
+        Commands::Network {
+            command: NetworkCommands::Disconnect { container, ip },
+        } => network_connect(container, ip, false).await,
+
+        Commands::Rmi {
+            image,
+            force,
+            override_protection,
+        } => remove_image(image, force, override_protection).await,
 
         Commands::Logs {
             container,
             follow,
             tail,
-        } => container_logs(container, follow, tail).await,
+            timestamps,
+            source,
+        } => container_logs(container, follow, tail, timestamps, source.into()).await,
 
         Commands::Exec {
             container,
             interactive,
             tty,
+            detach_keys,
             command,
-        } => exec_container(container, interactive, tty, command).await,
+        } => exec_container(container, interactive, tty, &detach_keys, command).await.and_then(exit::finish),
+
+        Commands::Top { container } => top(container).await,
+
+        Commands::ExecSessions { container } => exec_sessions(container).await,
+
+        Commands::ExecKill { session } => exec_kill(session).await,
 
         Commands::Inspect { id } => inspect(id).await,
-    };
 
-    if let Err(e) = result {
-        eprintln!("Error: {}", e);
-        std::process::exit(1);
-    }
-}
+        Commands::Tasks { cancel } => tasks(cancel).await,
 
-/// Connect to the Unix socket
-async fn connect_to_socket() -> Result<Framed<UnixStream, LinesCodec>, String> {
-    let stream = UnixStream::connect(SOCKET_PATH)
-        .await
-        .map_err(|e| format!("Failed to connect to backend: {}", e))?;
+        Commands::Info => info().await,
 
-    Ok(Framed::new(stream, LinesCodec::new()))
-}
+        Commands::Doctor => doctor().await,
 
-/// Send a JSON request and get the response
-async fn send_request(request: Request) -> Result<Value, String> {
-    let mut socket = connect_to_socket().await?;
+        Commands::Config {
+            command: ConfigCommands::Show,
+        } => show_config().await,
 
-    // Convert Request to JSON string
-    let request_json = serde_json::to_string(&request)
-        .map_err(|e| format!("Failed to serialize request: {}", e))?;
+        Commands::System {
+            command: SystemCommands::Orphans { adopt, destroy, name, image },
+        } => orphans(adopt, destroy, name, image).await,
 
-    socket
-        .send(request_json)
-        .await
-        .map_err(|e| format!("Failed to send request: {}", e))?;
+        Commands::System {
+            command: SystemCommands::Commands,
+        } => command_metrics().await,
 
-    let response_line = socket
-        .next()
-        .await
-        .ok_or("No response from backend")?
-        .map_err(|e| format!("Failed to read response: {}", e))?;
+        Commands::System {
+            command: SystemCommands::Boot,
+        } => boot_list().await,
 
-    let response: kawakaze_backend::api::Response = serde_json::from_str(&response_line)
-        .map_err(|e| format!("Failed to parse response: {}", e))?;
+        Commands::System {
+            command: SystemCommands::ApiSchema,
+        } => api_schema().await,
 
-    if response.is_success() {
-        Ok(response.data.unwrap_or(Value::Null))
-    } else {
-        let error = response.error.unwrap_or(kawakaze_backend::api::ApiError {
-            code: "UNKNOWN".to_string(),
-            message: "Unknown error".to_string(),
-        });
-        Err(format!("{}: {}", error.code, error.message))
+        Commands::Admin {
+            command: AdminCommands::PruneSnapshots { dataset, keep },
+        } => prune_snapshots(dataset, keep).await,
+
+        Commands::Admin {
+            command: AdminCommands::SnapshotGc { dry_run },
+        } => snapshot_gc(dry_run).await,
+
+        Commands::Admin {
+            command: AdminCommands::LogLevel { level },
+        } => set_log_level(level).await,
+
+        Commands::Completions { shell } => completions(shell, &mut std::io::stdout()).map_err(CliError::from),
+
+        Commands::Complete { kinds } => {
+            complete_names(kinds).await;
+            Ok(())
+        }
+    };
+
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(e.code);
     }
 }
 
+/// Connect to the daemon
+async fn client() -> Result<Client, CliError> {
+    Client::connect(kawakaze_client::default_socket_path()).await.map_err(CliError::from)
+}
+
 /// Format a JSON value for display
 fn format_response(value: &Value) -> String {
     if value.is_null() {
@@ -256,59 +807,178 @@ fn format_response(value: &Value) -> String {
 // Command Implementations
 // ============================================================================
 
-/// Build an image from a Dockerfile
-async fn build_image(
-    path: String,
-    name: String,
-    build_args: Vec<String>,
-) -> Result<(), String> {
+/// Build an image from a Dockerfile, printing the image ID once built (or
+/// the build ID right away with `detach`)
+async fn build_image(path: String, name: String, options: batch::BuildOptions, detach: bool) -> Result<(), CliError> {
     // Read the Dockerfile
     let dockerfile_content =
         std::fs::read_to_string(&path).map_err(|e| format!("Failed to read Dockerfile: {}", e))?;
 
-    // Parse build arguments
-    let mut args_map = HashMap::new();
-    for arg in build_args {
-        let parts: Vec<&str> = arg.splitn(2, '=').collect();
-        if parts.len() == 2 {
-            args_map.insert(parts[0].to_string(), parts[1].to_string());
-        }
-    }
-
     let build_request = BuildImageRequest {
-        name,
+        name: name.clone(),
         dockerfile: dockerfile_content,
-        build_args: args_map,
+        build_args: options.build_args,
+        protected: options.protect,
+        wait_for_base: false,
+        strict_vars: options.strict_vars,
+        secrets: options.secrets,
+        no_cache: options.no_cache,
+        network: options.network,
     };
 
-    let request =
-        Request::post(Endpoint::ImageBuild, build_request).map_err(|e| e.to_string())?;
+    let client = client().await?;
+    output::status(format!("Building {}...", name));
+
+    let started = client.build_image(&build_request).await?;
+    if started.cached {
+        output::status("Image is up to date (use --no-cache to rebuild)");
+        println!("{}", started.id);
+        return Ok(());
+    }
+    if detach {
+        println!("{}", started.id);
+        return Ok(());
+    }
+
+    let image_id = wait_for_build(&client, &started.id, &mut output::Progress::stderr()).await?;
+    println!("{}", image_id);
+    Ok(())
+}
+
+/// Show a build's steps until it finishes, returning the image ID
+async fn wait_for_build(
+    client: &Client,
+    build_id: &str,
+    progress: &mut output::Progress<impl std::io::Write>,
+) -> Result<String, CliError> {
+    loop {
+        let status = client.build_status(build_id).await?;
+
+        progress.update(status.step, status.total_steps, &status.current_instruction);
+        match status.status {
+            BuildStatus::Complete => {
+                progress.finish("Build complete");
+                return Ok(status.image_id.to_string());
+            }
+            BuildStatus::Failed => {
+                progress.finish("Build failed");
+                return Err(format!("Build failed: {} (see `kawakaze image build-log {}`)", status.current_instruction, build_id).into());
+            }
+            _ => tokio::time::sleep(std::time::Duration::from_secs(1)).await,
+        }
+    }
+}
 
-    println!("Building image...");
+/// Show an async create or remove until it finishes
+async fn wait_for_operation(
+    client: &Client,
+    operation_id: &str,
+    progress: &mut output::Progress<impl std::io::Write>,
+) -> Result<OperationProgress, CliError> {
+    loop {
+        let status = client.operation(operation_id).await?;
+
+        progress.update(0, 0, &format!("[{}] {}", status.phase.as_str(), status.current_step));
+        match status.phase {
+            Phase::Complete => {
+                progress.finish(&status.current_step);
+                return Ok(status);
+            }
+            Phase::Failed => {
+                progress.finish(&status.current_step);
+                return Err(CliError::new(exit::code::DAEMON, status.error.unwrap_or(status.current_step)));
+            }
+            _ => tokio::time::sleep(std::time::Duration::from_millis(500)).await,
+        }
+    }
+}
+
+/// Run a container
+async fn run_container(args: ContainerArgs, interactive: bool, tty: bool, detach_keys: String) -> Result<ExitStatus, CliError> {
+    // Reject a bad sequence before anything is created
+    detach::parse_detach_keys(&detach_keys)?;
+    let command = args.command.clone();
+    let env = container_env(&args)?;
 
-    let response = send_request(request).await?;
+    let client = client().await?;
+    let container_id = create_from(&client, args, env).await?;
+    let container_id = container_id.as_str();
 
-    if let Some(image_id) = response.get("id") {
-        println!("Built image: {}", image_id);
-    } else {
-        println!("Build complete");
+    // Auto-start the container
+    client.start_container(container_id).await?;
+
+    output::status(format!("Started container: {}", container_id));
+
+    // If interactive or tty mode, attach to the container
+    if interactive || tty {
+        // Default command is /bin/sh if no command was specified
+        let attach_command = if command.is_empty() {
+            vec!["/bin/sh".to_string()]
+        } else {
+            command
+        };
+
+        // Reuse the exec logic to attach
+        return exec_container(container_id.to_string(), interactive, tty, &detach_keys, attach_command).await;
     }
 
+    println!("{}", container_id);
+    Ok(ExitStatus::SUCCESS)
+}
+
+/// Create a container without starting it
+async fn create_container(args: ContainerArgs) -> Result<(), CliError> {
+    let env = container_env(&args)?;
+    let client = client().await?;
+    let container_id = create_from(&client, args, env).await?;
+    println!("{}", container_id);
     Ok(())
 }
 
-/// Run a container
-async fn run_container(
-    image: String,
-    name: Option<String>,
-    interactive: bool,
-    tty: bool,
-    publish: Vec<String>,
-    volume: Vec<String>,
-    env: Vec<String>,
-    restart: String,
-    command: Vec<String>,
-) -> Result<(), String> {
+/// The container's environment: the `--env-file`s in order, then `-e`
+///
+/// Read before the daemon is contacted, so a bad file fails the command
+/// without creating anything.
+fn container_env(args: &ContainerArgs) -> Result<HashMap<String, String>, String> {
+    let explicit = args.env.iter().filter_map(|e| e.split_once('=')).map(|(k, v)| (k.to_string(), v.to_string()));
+    envfile::merge(&args.env_file, explicit.collect())
+}
+
+/// Create a container with environment `env` (see [`container_env`]),
+/// reporting what was allocated for it, and return its ID; it is left in
+/// the created state
+async fn create_from(client: &Client, args: ContainerArgs, env: HashMap<String, String>) -> Result<String, CliError> {
+    let ContainerArgs {
+        image,
+        name,
+        cidfile,
+        publish,
+        volume,
+        tmpfs,
+        env: _,
+        env_file: _,
+        restart,
+        workdir: _,
+        user: _,
+        protect,
+        no_healthcheck,
+        boot,
+        encrypted,
+        read_only,
+        network_alias: network_aliases,
+        hostname,
+        mount_permissions,
+        run_async,
+        constraint,
+        placement_pref,
+        sysctl,
+        description,
+        command,
+    } = args;
+    // Claimed first so an existing file fails the create; dropped (and
+    // removed) if the create fails
+    let cidfile = cidfile.map(cidfile::CidFile::claim).transpose()?;
+
     // Parse port mappings
     let ports: Vec<PortMapping> = publish
         .iter()
@@ -320,326 +990,626 @@ async fn run_container(
         .iter()
         .filter_map(|v| parse_volume_mount(v))
         .collect();
-
-    // Parse environment variables
-    let env_map: HashMap<String, String> = env
-        .iter()
-        .filter_map(|e| {
-            let parts: Vec<&str> = e.splitn(2, '=').collect();
-            if parts.len() == 2 {
-                Some((parts[0].to_string(), parts[1].to_string()))
-            } else {
-                None
-            }
-        })
-        .collect();
+    let tmpfs = tmpfs.iter().map(|t| parse_tmpfs_mount(t)).collect::<Result<Vec<_>, _>>()?;
+    let sysctls = sysctl.iter().map(|s| parse_sysctl(s)).collect::<Result<HashMap<_, _>, _>>()?;
 
     let container_request = CreateContainerRequest {
         image_id: image,
         name,
         ports,
         volumes,
-        env: env_map,
+        tmpfs,
+        env,
         restart_policy: restart,
         command: if command.is_empty() {
             None
         } else {
-            Some(command.clone())
+            Some(command)
         },
+        protected: protect,
+        devfs_ruleset: None,
+        disable_healthcheck: no_healthcheck,
+        boot,
+        encrypted,
+        read_only_root: read_only,
+        network_aliases,
+        hostname,
+        mount_permissions,
+        run_async,
+        depends_on: Vec::new(),
+        placement: (!constraint.is_empty() || !placement_pref.is_empty())
+            .then_some(Placement { constraints: constraint, preferences: placement_pref }),
+        sysctls,
+        description,
     };
 
-    let request = Request::post(Endpoint::ContainerCreate, container_request)
-        .map_err(|e| e.to_string())?;
+    let (container_id, created) = if run_async {
+        let started = client.create_container_async(&container_request).await?;
+        wait_for_operation(client, &started.id, &mut output::Progress::stderr()).await?;
+        (started.container_id, None)
+    } else {
+        let created = client.create_container(&container_request).await?;
+        (created.container.id.clone(), Some(created))
+    };
+    if let Some(cidfile) = cidfile {
+        cidfile.write(&container_id)?;
+    }
 
-    let response = send_request(request).await?;
+    output::status(format!("Created container: {}", container_id));
+    // An async create answers before there is anything to report
+    if let Some(created) = created {
+        if let Some(ip) = &created.container.ip.v4 {
+            output::status(format!("  IP: {}", ip));
+        }
+        if let Some(ip6) = &created.container.ip.v6 {
+            output::status(format!("  IPv6: {}", ip6));
+        }
+        for port in &created.ports {
+            output::status(format!("  Port: {} -> {}/{}", port.host_port, port.container_port, port.protocol));
+        }
+        if created.container.encrypted {
+            output::status("  Dataset: encrypted");
+        }
+        for warning in &created.warnings {
+            output::warn(warning);
+        }
+    }
 
-    let container_id = response
-        .get("id")
-        .and_then(|v| v.as_str())
-        .ok_or("No container ID in response")?;
+    Ok(container_id)
+}
 
-    println!("Created container: {}", container_id);
+/// List all containers
+async fn list_containers(no_summary: bool, summary_only: bool, filter: Vec<String>, format: OutputFormat) -> Result<(), CliError> {
+    let client = client().await?;
+    // The daemon's counts cover every container, not just the matching ones
+    let (containers, summary) = if !filter.is_empty() {
+        (client.list_containers_matching(&filter).await?, None)
+    } else if !no_summary || summary_only {
+        let list = client.list_containers_with_summary().await?;
+        (list.containers, Some(list.summary))
+    } else {
+        (client.list_containers().await?, None)
+    };
 
-    // Auto-start the container
-    let start_request = Request::post(Endpoint::StartContainer(container_id.to_string()), ())
-        .map_err(|e| e.to_string())?;
-
-    send_request(start_request).await?;
+    if summary_only {
+        let summary = summary.unwrap_or_default();
+        match format {
+            OutputFormat::Json => println!("{}", format_response(&serde_json::to_value(summary).unwrap_or_default())),
+            OutputFormat::Table => println!("{}", summary_line(&summary)),
+        }
+        return Ok(());
+    }
+    if format == OutputFormat::Json {
+        // Same shape as the daemon's response: wrapped when it has a summary
+        let value = match &summary {
+            Some(summary) => serde_json::json!({ "containers": containers, "summary": summary }),
+            None => serde_json::to_value(&containers).unwrap_or_default(),
+        };
+        println!("{}", format_response(&value));
+        return Ok(());
+    }
 
-    println!("Started container: {}", container_id);
+    if containers.is_empty() {
+        eprintln!("No containers found");
+        return Ok(());
+    }
 
-    // If interactive or tty mode, attach to the container
-    if interactive || tty {
-        // Default command is /bin/sh if no command was specified
-        let attach_command = if command.is_empty() {
-            vec!["/bin/sh".to_string()]
-        } else {
-            command
-        };
+    let statuses: Vec<String> = containers.iter().map(container_status).collect();
+    let status_width = statuses.iter().map(String::len).max().unwrap_or(0).max(10);
+    println!(
+        "{:<12} {:<20} {:<20} {:<status_width$} {:<24} {:<15}",
+        "CONTAINER ID", "NAME", "IMAGE", "STATUS", "CREATED", "IP"
+    );
+
+    let mut any_protected = false;
+    let mut any_missing = false;
+    for (container, status) in containers.iter().zip(&statuses) {
+        any_protected |= container.protected;
+        any_missing |= container.dataset_missing;
+        let name = mark_protected(container.name.as_deref().unwrap_or(""), container.protected);
+
+        // Shorten IDs for display (first 12 chars)
+        let short_id = short_id(&container.id);
+
+        println!(
+            "{:<12} {:<20} {:<20} {:<status_width$} {:<24} {:<15}",
+            short_id,
+            name,
+            container.image_id,
+            status,
+            container.created_at.map(format_age).unwrap_or_default(),
+            container.ip.primary().unwrap_or("")
+        );
+    }
 
-        // Reuse the exec logic to attach
-        exec_container(container_id.to_string(), interactive, tty, attach_command).await?;
+    if let Some(summary) = summary.filter(|_| !no_summary) {
+        println!("\n{}", summary_line(&summary));
+    }
+    if any_protected {
+        println!("\n* protected");
+    }
+    if any_missing {
+        println!("{}! dataset destroyed outside kawakaze; only `rm` works", if any_protected { "" } else { "\n" });
     }
 
     Ok(())
 }
 
-/// List all containers
-async fn list_containers() -> Result<(), String> {
-    let request = Request::get(Endpoint::Containers);
-    let response = send_request(request).await?;
-
-    if let Some(containers) = response.as_array() {
-        if containers.is_empty() {
-            println!("No containers found");
-            return Ok(());
-        }
+/// Start a container
+async fn start_container(container: String) -> Result<(), CliError> {
+    let client = client().await?;
+    output::status(format!("Starting container {}...", container));
+    client.start_container(&container).await?;
+    println!("{}", container);
+
+    Ok(())
+}
+
+/// Stop a container
+async fn stop_container(container: String, freeze: bool) -> Result<(), CliError> {
+    let client = client().await?;
+    send_stop(&client, &container, if freeze { StopMode::Freeze } else { StopMode::Remove }).await?;
+    println!("{}", container);
+    Ok(())
+}
 
-        println!("{:<12} {:<20} {:<20} {:<10} {:<15}", "CONTAINER ID", "NAME", "IMAGE", "STATUS", "IP");
+async fn send_stop(client: &Client, container: &str, stop_mode: StopMode) -> Result<(), CliError> {
+    output::status(format!("Stopping container {}...", container));
+    client.stop_container(container, stop_mode).await?;
+    Ok(())
+}
 
-        for container in containers {
-            let id = container.get("id").and_then(|v| v.as_str()).unwrap_or("N/A");
-            let name = container.get("name").and_then(|v| v.as_str()).unwrap_or("");
-            let image = container.get("image_id").and_then(|v| v.as_str()).unwrap_or("N/A");
-            let state = container.get("state").and_then(|v| v.as_str()).unwrap_or("unknown");
-            let ip = container.get("ip").and_then(|v| v.as_str()).unwrap_or("");
+/// Containers using more than this are removed asynchronously unless
+/// asked otherwise
+const ASYNC_REMOVE_BYTES: u64 = 1 << 30;
 
-            // Shorten IDs for display (first 12 chars)
-            let short_id = if id.len() > 12 { &id[..12] } else { id };
+/// Whether to remove a container asynchronously: when asked to, or when
+/// its dataset is large enough for the destroy to take a while
+fn remove_async(requested: bool, size_bytes: Option<u64>) -> bool {
+    requested || size_bytes.is_some_and(|size| size > ASYNC_REMOVE_BYTES)
+}
 
-            println!("{:<12} {:<20} {:<20} {:<10} {:<15}", short_id, name, image, state, ip);
-        }
+/// Remove a container
+async fn remove_container(
+    container: String,
+    force: bool,
+    override_protection: bool,
+    run_async: bool,
+) -> Result<(), CliError> {
+    let client = client().await?;
+    if force {
+        // Force stop first, then remove
+        let _ = send_stop(&client, &container, StopMode::Remove).await;
+    }
+
+    let options = RemoveRequest {
+        force,
+        override_protection,
+        run_async: false,
+    };
+
+    // A container that can't be inspected is left to the remove to report
+    let size_bytes = client.container(&container).await.ok().and_then(|info| info.size_bytes);
+    if remove_async(run_async, size_bytes) {
+        let started = client.remove_container_async(&container, &options).await?;
+        wait_for_operation(&client, &started.id, &mut output::Progress::stderr()).await?;
     } else {
-        println!("No containers found");
+        output::status(format!("Removing container {}...", container));
+        client.remove_container(&container, &options).await?;
     }
+    println!("{}", container);
 
     Ok(())
 }
 
-/// Start a container
-async fn start_container(container: String) -> Result<(), String> {
-    let request = Request::post(Endpoint::StartContainer(container.clone()), ())
-        .map_err(|e| e.to_string())?;
+/// List images, hiding intermediate ones unless `all` is set
+async fn list_images(all: bool) -> Result<(), CliError> {
+    let images = client().await?.list_images().await?;
+    let images: Vec<&ImageListItem> = images
+        .iter()
+        .filter(|image| all || !is_intermediate(image))
+        .collect();
+
+    if images.is_empty() {
+        eprintln!("No images found");
+        return Ok(());
+    }
+
+    println!("{:<12} {:<30} {:<15} {:<20}", "IMAGE ID", "NAME", "SIZE", "CREATED");
 
-    println!("Starting container {}...", container);
+    let mut any_protected = false;
+    for image in images {
+        any_protected |= image.protected;
+        let name = mark_protected(&image.name, image.protected);
 
-    send_request(request).await?;
+        // Shorten IDs for display
+        let short_id = short_id(&image.id);
 
-    println!("Container {} started", container);
+        println!("{:<12} {:<30} {:<15} {:<20}", short_id, name, format_size(image.size_bytes), format_age(image.created_at));
+    }
+
+    if any_protected {
+        println!("\n* protected");
+    }
 
     Ok(())
 }
 
-/// Stop a container
-async fn stop_container(container: String) -> Result<(), String> {
-    let request = Request::post(Endpoint::StopContainer(container.clone()), ())
-        .map_err(|e| e.to_string())?;
-
-    println!("Stopping container {}...", container);
+fn is_intermediate(image: &ImageListItem) -> bool {
+    image.kind == "intermediate"
+}
 
-    send_request(request).await?;
+/// Remove dangling images (and, with `all`, unused user images)
+async fn image_containers(image: String) -> Result<(), CliError> {
+    let containers = client().await?.image_containers(&image).await?;
 
-    println!("Container {} stopped", container);
+    if containers.is_empty() {
+        eprintln!("No containers use image {}", image);
+        return Ok(());
+    }
 
+    println!("{:<12} {:<20} {:<10}", "CONTAINER ID", "NAME", "STATUS");
+    for container in &containers {
+        let short_id = short_id(&container.id);
+        println!("{:<12} {:<20} {:<10}", short_id, container.name.as_deref().unwrap_or(""), container.state);
+    }
     Ok(())
 }
 
-/// Remove a container
-async fn remove_container(container: String, force: bool) -> Result<(), String> {
-    if force {
-        // Force stop first, then remove
-        let _ = stop_container(container.clone()).await;
+async fn prune_images(all: bool) -> Result<(), CliError> {
+    let result = client().await?.prune_images(&PruneImagesRequest { all }).await?;
+
+    for image in &result.removed {
+        println!("Deleted: {} ({})", image.name, image.id);
     }
 
-    let request = Request::delete(Endpoint::RemoveContainer(container.clone()));
+    eprintln!("Removed {} image(s), reclaimed {}", result.removed.len(), format_size(result.reclaimed_bytes));
 
-    println!("Removing container {}...", container);
+    Ok(())
+}
 
-    send_request(request).await?;
+/// Start an upgrade of a jail (or, with `image`, an image) and, with
+/// `follow`, poll its status until it finishes
+async fn upgrade(target: String, image: bool, to: Option<String>, follow: bool) -> Result<(), CliError> {
+    let client = client().await?;
+    let request = UpgradeRequest { to };
+    let started: UpgradeStarted = if image {
+        client.upgrade_image(&target, &request).await
+    } else {
+        client.upgrade_jail(&target, &request).await
+    }
+    ?;
 
-    println!("Container {} removed", container);
+    // The rollback snapshot is the result worth keeping
+    println!("{}", started.snapshot);
+    if !follow {
+        output::status("Upgrade started; use --follow to wait for it to finish.");
+        return Ok(());
+    }
 
-    Ok(())
-}
+    let verbose = output::get().verbose;
+    let mut bar = output::Progress::stderr();
+    let mut shown_lines = 0;
+    loop {
+        let progress: UpgradeProgress = if image {
+            client.image_upgrade_status(&target).await
+        } else {
+            client.jail_upgrade_status(&target).await
+        }
+        ?;
 
-/// List all images
-async fn list_images() -> Result<(), String> {
-    let request = Request::get(Endpoint::Images);
-    let response = send_request(request).await?;
+        if verbose {
+            for line in progress.output.iter().skip(shown_lines) {
+                bar.output(&format!("  {}", line));
+            }
+        }
+        shown_lines = shown_lines.max(progress.output.len());
+        bar.update(0, 0, &progress.current_step);
 
-    if let Some(images) = response.as_array() {
-        if images.is_empty() {
-            println!("No images found");
-            return Ok(());
+        match progress.status {
+            UpgradeStatus::Complete => {
+                bar.finish("Upgrade complete");
+                return Ok(());
+            }
+            UpgradeStatus::RolledBack(reason) => {
+                bar.finish("Upgrade rolled back");
+                return Err(CliError::new(exit::code::DAEMON, format!("Upgrade rolled back: {}", reason)));
+            }
+            UpgradeStatus::Failed(reason) => {
+                bar.finish("Upgrade failed");
+                return Err(CliError::new(exit::code::DAEMON, format!("Upgrade failed: {}", reason)));
+            }
+            _ => tokio::time::sleep(std::time::Duration::from_secs(2)).await,
         }
+    }
+}
 
-        println!("{:<12} {:<30} {:<15} {:<20}", "IMAGE ID", "NAME", "SIZE", "CREATED");
+/// Remove an image
+async fn remove_image(image: String, force: bool, override_protection: bool) -> Result<(), CliError> {
+    let client = client().await?;
+    let options = RemoveRequest {
+        force,
+        override_protection,
+        run_async: false,
+    };
 
-        for image in images {
-            let id = image.get("id").and_then(|v| v.as_str()).unwrap_or("N/A");
-            let name = image.get("name").and_then(|v| v.as_str()).unwrap_or("N/A");
-            let size = image.get("size_bytes").and_then(|v| v.as_u64()).unwrap_or(0);
-            let created = image.get("created_at").and_then(|v| v.as_i64()).unwrap_or(0);
+    output::status(format!("{} image {}...", if force { "Force removing" } else { "Removing" }, image));
+    client.delete_image(&image, &options).await?;
+    println!("{}", image);
 
-            // Shorten IDs for display
-            let short_id = if id.len() > 12 { &id[..12] } else { id };
+    Ok(())
+}
 
-            // Format size
-            let size_str = format_size(size);
+/// Update container or image settings
+async fn update(
+    target: String,
+    kind: UpdateTarget,
+    protected: Option<bool>,
+    boot: Option<bool>,
+    read_only: Option<bool>,
+    description: Option<String>,
+) -> Result<(), CliError> {
+    if protected.is_none() && boot.is_none() && read_only.is_none() && description.is_none() {
+        return Err("Nothing to update: pass --protect/--unprotect, --boot/--no-boot, --read-only[=false] or --description".into());
+    }
 
-            // Format timestamp (simple conversion)
-            let created_str = if created > 0 {
-                format_timestamp(created)
-            } else {
-                "unknown".to_string()
-            };
+    let client = client().await?;
+    let cleared = description.as_deref() == Some("");
+    let described = description.is_some();
+    match kind {
+        UpdateTarget::Jail => client.update_jail(&target, &JailUpdateRequest { description }).await.map(drop),
+        UpdateTarget::Image => client.update_image(&target, &UpdateRequest { protected, boot, read_only, description }).await.map(drop),
+        UpdateTarget::Container => client.update_container(&target, &UpdateRequest { protected, boot, read_only, description }).await.map(drop),
+    }?;
+
+    let kind = kind.as_str();
+    match protected {
+        Some(true) => output::status(format!("{} {} protected", kind, target)),
+        Some(false) => output::status(format!("{} {} unprotected", kind, target)),
+        None => {}
+    }
+    match boot {
+        Some(true) => output::status(format!("{} {} starts at boot", kind, target)),
+        Some(false) => output::status(format!("{} {} no longer starts at boot", kind, target)),
+        None => {}
+    }
+    match read_only {
+        Some(true) => output::status(format!("{} {} has a read-only root", kind, target)),
+        Some(false) => output::status(format!("{} {} has a writable root", kind, target)),
+        None => {}
+    }
+    if cleared {
+        output::status(format!("{} {} has no description", kind, target));
+    } else if described {
+        output::status(format!("{} {} description set", kind, target));
+    }
+
+    Ok(())
+}
 
-            println!("{:<12} {:<30} {:<15} {:<20}", short_id, name, size_str, created_str);
+/// What `update` changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UpdateTarget {
+    Container,
+    Image,
+    Jail,
+}
+
+impl UpdateTarget {
+    fn as_str(self) -> &'static str {
+        match self {
+            UpdateTarget::Container => "Container",
+            UpdateTarget::Image => "Image",
+            UpdateTarget::Jail => "Jail",
         }
-    } else {
-        println!("No images found");
     }
+}
 
+/// Rename a container
+async fn rename(container: String, name: String) -> Result<(), CliError> {
+    let info = client().await?.rename_container(&container, &name).await?;
+    println!("{}", info.name.as_deref().unwrap_or(&info.id));
+    if let Some(hostname) = info.hostname.filter(|hostname| *hostname != name) {
+        output::status(format!("Host name stays {}", hostname));
+    }
     Ok(())
 }
 
-/// Remove an image
-async fn remove_image(image: String, force: bool) -> Result<(), String> {
-    let request = Request::delete(Endpoint::DeleteImage(image));
+/// Add (`connect`) or remove an IP alias of a container
+async fn network_connect(container: String, ip: String, connect: bool) -> Result<(), CliError> {
+    let client = client().await?;
+    let info = if connect {
+        client.add_container_ip(&container, &ip).await
+    } else {
+        client.remove_container_ip(&container, &ip).await
+    }
+    ?;
+    println!("{}", container);
+    output::status(format!("Addresses: {}", if info.ip_aliases.is_empty() { "none".to_string() } else { info.ip_aliases.join(", ") }));
+    Ok(())
+}
 
-    if force {
-        println!("Force removing image...");
+/// `Some(true)` for `--x`, `Some(false)` for `--no-x`, `None` for neither
+fn flag(set: bool, unset: bool) -> Option<bool> {
+    match (set, unset) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
     }
+}
 
-    println!("Removing image...");
+/// View container logs
+/// Show a container's processes
+async fn top(container: String) -> Result<(), CliError> {
+    let processes = client().await?.top(&container).await?;
+
+    println!("{:>7} {:<10} {:>5} {:>5} {:>8} {:>12} COMMAND", "PID", "USER", "%CPU", "%MEM", "RSS", "ELAPSED");
+    for p in &processes {
+        println!(
+            "{:>7} {:<10} {:>5.1} {:>5.1} {:>8} {:>12} {}",
+            p.pid,
+            p.user,
+            p.cpu_percent,
+            p.mem_percent,
+            format_size(p.rss_kb * 1024),
+            p.elapsed,
+            p.command
+        );
+    }
 
-    send_request(request).await?;
+    Ok(())
+}
+
+/// List exec sessions, of every container or of one
+async fn exec_sessions(container: Option<String>) -> Result<(), CliError> {
+    let client = client().await?;
+    let sessions = match &container {
+        Some(container) => client.container_exec_sessions(container).await?,
+        None => client.exec_sessions().await?,
+    };
 
-    println!("Image removed");
+    if sessions.is_empty() {
+        println!("No exec sessions");
+        return Ok(());
+    }
+    println!("{:<12} {:<20} {:<11} {:>7} {:<10} {:<16} COMMAND", "SESSION", "CONTAINER", "KIND", "PID", "USER", "STARTED");
+    for session in &sessions {
+        let user = session.user.clone().or_else(|| session.peer_uid.map(|uid| uid.to_string()));
+        println!(
+            "{:<12} {:<20} {:<11} {:>7} {:<10} {:<16} {}",
+            session.id,
+            session.container,
+            session.kind.as_str(),
+            session.pid.map(|pid| pid.to_string()).unwrap_or_else(|| "-".to_string()),
+            user.as_deref().unwrap_or("-"),
+            format_age(session.started_at),
+            shell_words::join(&session.command)
+        );
+    }
 
     Ok(())
 }
 
-/// View container logs
-async fn container_logs(container: String, follow: bool, tail: usize) -> Result<(), String> {
-    let mut socket = connect_to_socket().await?;
+/// Kill an exec session
+async fn exec_kill(session: String) -> Result<(), CliError> {
+    let killed = client().await?.kill_exec_session(&session).await?;
+    let how = match killed.termination {
+        Termination::Terminated => "terminated",
+        Termination::Killed => "killed after the grace period",
+        Termination::Gone => "had already exited",
+    };
+    println!("{} ({})", killed.session.id, how);
+    Ok(())
+}
 
-    let request = Request::get(Endpoint::ContainerLogs(container));
-    let request_json = serde_json::to_string(&request)
-        .map_err(|e| format!("Failed to serialize request: {}", e))?;
+/// Print a container, build or bootstrap log
+fn print_log(logs: kawakaze_client::Result<Vec<LogEntry>>) -> Result<(), CliError> {
+    for log in logs? {
+        println!("{}", log.message);
+    }
+    Ok(())
+}
+
+/// Print a log, then its new lines as they are written
+async fn follow_log(stream: kawakaze_client::Result<LogStream>) -> Result<(), CliError> {
+    let mut stream = stream?;
 
-    socket
-        .send(request_json)
-        .await
-        .map_err(|e| format!("Failed to send request: {}", e))?;
+    output::status("Following logs (Ctrl+C to stop)...");
+    while let Some(log) = stream.next().await {
+        println!("{}", log?.message);
+    }
+    Ok(())
+}
 
+async fn build_log(image: String, follow: bool, tail: Option<usize>, timestamps: bool) -> Result<(), CliError> {
+    let client = client().await?;
     if follow {
-        println!("Following logs (Ctrl+C to stop)...");
-        while let Some(result) = socket.next().await {
-            match result {
-                Ok(line) => {
-                    if let Ok(response) = serde_json::from_str::<kawakaze_backend::api::Response>(
-                        &line,
-                    ) {
-                        if response.is_success() {
-                            if let Some(data) = response.data {
-                                if let Some(logs) = data.as_array() {
-                                    for log in logs {
-                                        if let Some(msg) = log.get("message").and_then(|v| v.as_str())
-                                        {
-                                            println!("{}", msg);
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-                Err(e) => {
-                    eprintln!("Error reading logs: {}", e);
-                    break;
-                }
-            }
-        }
-    } else {
-        let response_line = socket
-            .next()
-            .await
-            .ok_or("No response from backend")?
-            .map_err(|e| format!("Failed to read response: {}", e))?;
-
-        let response: kawakaze_backend::api::Response =
-            serde_json::from_str(&response_line).map_err(|e| format!("Failed to parse response: {}", e))?;
-
-        if response.is_success() {
-            if let Some(data) = response.data {
-                if let Some(logs) = data.as_array() {
-                    // Apply tail
-                    let start = if logs.len() > tail { logs.len() - tail } else { 0 };
-                    for log in logs.iter().skip(start) {
-                        if let Some(msg) = log.get("message").and_then(|v| v.as_str()) {
-                            println!("{}", msg);
-                        }
-                    }
-                }
-            }
-        }
+        return follow_log(client.follow_build_log(&image, &LogsRequest { timestamps, ..Default::default() }).await).await;
     }
+    print_log(client.build_log(&image, &LogsRequest { timestamps, tail, ..Default::default() }).await)
+}
 
-    Ok(())
+async fn bootstrap_log(name: String, tail: Option<usize>, timestamps: bool) -> Result<(), CliError> {
+    print_log(client().await?.bootstrap_log(&name, &LogsRequest { timestamps, tail, ..Default::default() }).await)
+}
+
+async fn container_logs(container: String, follow: bool, tail: usize, timestamps: bool, source: LogSource) -> Result<(), CliError> {
+    let client = client().await?;
+    if follow {
+        return follow_log(client.follow_logs(&container, &LogsRequest { timestamps, tail: None, source }).await).await;
+    }
+    print_log(client.logs(&container, &LogsRequest { timestamps, tail: Some(tail), source }).await)
+}
+
+/// `command` as a shell command that exports `env` first
+///
+/// `jexec -l` starts from a clean login environment, so the container's
+/// environment has to be set inside the jail.
+fn with_env(env: &BTreeMap<String, String>, command: &[String]) -> String {
+    let command = shell_words::join(command);
+    if env.is_empty() {
+        return command;
+    }
+    let exports: Vec<String> = env.iter().map(|(k, v)| format!("{}={}", k, shell_words::quote(v))).collect();
+    format!("export {}; {}", exports.join(" "), command)
 }
 
 /// Execute a command in a container
-async fn exec_container(container: String, interactive: bool, tty: bool, command: Vec<String>) -> Result<(), String> {
+async fn exec_container(
+    container: String,
+    interactive: bool,
+    tty: bool,
+    detach_keys: &str,
+    command: Vec<String>,
+) -> Result<ExitStatus, CliError> {
     if command.is_empty() {
-        return Err("No command specified".to_string());
+        return Err("No command specified".into());
     }
 
     // TTY mode: use forkpty to allocate a pseudo-terminal
     if tty {
-        // First, we need to get the jail name by querying the container
-        let request = Request::get(Endpoint::Container(container.clone()));
-        let response = send_request(request).await?;
+        let detach_sequence = detach::parse_detach_keys(detach_keys)?;
 
-        let jail_name = response.get("jail_name")
-            .and_then(|v| v.as_str())
-            .ok_or("Failed to get jail name from container")?;
+        // First, we need to get the jail name by querying the container
+        let client = client().await?;
+        let info = client.container(&container).await?;
 
         // Build the command string
-        let cmd_str = shell_words::join(&command);
-
-        exec_with_pty(jail_name, &cmd_str)?;
-        Ok(())
+        let cmd_str = with_env(&info.env, &command);
+
+        let mut session = None;
+        let status = exec_with_pty(&info.jail_name, &cmd_str, detach_sequence, |pid| {
+            session = register_session(&client, &container, &command, ExecKind::Pty, pid);
+        })?;
+        // A detached session stays listed until its command exits
+        if status != ExitStatus::Detached {
+            end_session(&client, session).await;
+        }
+        Ok(status)
     } else if interactive {
         // Interactive mode without PTY: run jexec directly with stdin/stdout connected
         // First, we need to get the jail name by querying the container
-        let request = Request::get(Endpoint::Container(container.clone()));
-        let response = send_request(request).await?;
-
-        let jail_name = response.get("jail_name")
-            .and_then(|v| v.as_str())
-            .ok_or("Failed to get jail name from container")?;
+        let client = client().await?;
+        let info = client.container(&container).await?;
 
         // Build the command string
-        let cmd_str = shell_words::join(&command);
+        let cmd_str = with_env(&info.env, &command);
 
         // Run jexec interactively, connecting stdin/stdout/stderr directly
         // This gives the user an interactive shell
-        let status = std::process::Command::new("jexec")
+        let mut child = std::process::Command::new("jexec")
             .arg("-l")  // Login shell to load profiles
-            .arg(jail_name)
+            .arg(&info.jail_name)
             .arg("/bin/sh")
             .arg("-c")
             .arg(&cmd_str)
-            .status()
+            .spawn()
             .map_err(|e| format!("Failed to execute command: {}", e))?;
+        let session = register_session(&client, &container, &command, ExecKind::Interactive, child.id());
+        let status = child.wait().map_err(|e| format!("Failed to execute command: {}", e));
+        end_session(&client, session).await;
 
-        let exit_code = status.code().unwrap_or(-1);
-        if exit_code != 0 {
-            return Err(format!("Command exited with code {}", exit_code));
-        }
-
-        Ok(())
+        Ok(status?.into())
     } else {
         // Non-interactive mode: use backend API
         let exec_request = ExecRequest {
@@ -648,36 +1618,50 @@ async fn exec_container(container: String, interactive: bool, tty: bool, command
             workdir: None,
         };
 
-        let request =
-            Request::post(Endpoint::ContainerExec(container), exec_request).map_err(|e| e.to_string())?;
+        let client = client().await?;
+        output::status(format!("Executing: {}", command.join(" ")));
 
-        println!("Executing: {}", command.join(" "));
-
-        let response = send_request(request).await?;
+        let result = client.exec(&container, &exec_request).await?;
 
         // Print output
-        if let Some(stdout) = response.get("stdout").and_then(|v| v.as_str()) {
-            print!("{}", stdout);
-        }
+        print!("{}", result.stdout);
+        eprint!("{}", result.stderr);
 
-        if let Some(stderr) = response.get("stderr").and_then(|v| v.as_str()) {
-            eprint!("{}", stderr);
-        }
+        Ok(ExitStatus::Exited(result.exit_code))
+    }
+}
 
-        // Check exit code
-        if let Some(exit_code) = response.get("exit_code").and_then(|v| v.as_i64()) {
-            if exit_code != 0 {
-                return Err(format!("Command exited with code {}", exit_code));
-            }
+/// Register a session the CLI runs itself, so `exec-sessions` lists it and
+/// `exec-kill` reaches it; returns its ID. Without it (an older daemon) the
+/// command still runs, untracked.
+fn register_session(client: &Client, container: &str, command: &[String], kind: ExecKind, pid: u32) -> Option<String> {
+    let request = ExecSessionRequest { container: container.to_string(), command: command.to_vec(), kind, pid };
+    let registered = tokio::task::block_in_place(|| tokio::runtime::Handle::current().block_on(client.register_exec_session(&request)));
+    match registered {
+        Ok(session) => Some(session.id),
+        Err(e) => {
+            output::warn(format!("Exec session not registered with the daemon: {}", e));
+            None
         }
+    }
+}
 
-        Ok(())
+/// End a registered session once its command exited
+async fn end_session(client: &Client, session: Option<String>) {
+    if let Some(id) = session
+        && let Err(e) = client.end_exec_session(&id).await
+        && !e.is_not_found()
+    {
+        output::warn(format!("Failed to end exec session {}: {}", id, e));
     }
 }
 
 /// Execute command in a jail with a pseudo-TTY
+///
+/// Typing `detach_sequence` ends the session without stopping the command.
+/// `on_spawn` gets the pid of `jexec`, which leads its own session.
 #[cfg(target_os = "freebsd")]
-fn exec_with_pty(jail_name: &str, command: &str) -> Result<(), String> {
+fn exec_with_pty(jail_name: &str, command: &str, detach_sequence: Vec<u8>, on_spawn: impl FnOnce(u32)) -> Result<ExitStatus, String> {
     use std::fs::File;
     use std::io::Read;
     use std::os::fd::FromRawFd;
@@ -792,6 +1776,8 @@ fn exec_with_pty(jail_name: &str, command: &str) -> Result<(), String> {
             libc::_exit(1);
         }
     } else {
+        on_spawn(pid as u32);
+
         // Parent process: forward I/O between stdin/stdout and the PTY master
         unsafe {
             // Set master_fd to non-blocking
@@ -857,7 +1843,8 @@ fn exec_with_pty(jail_name: &str, command: &str) -> Result<(), String> {
             });
 
             // Main I/O loop
-            let _exit_status: Option<i32> = None;
+            let mut detector = detach::DetachDetector::new(detach_sequence);
+            let mut detached = false;
 
             while running.load(Ordering::Relaxed) {
                 let mut activity = false;
@@ -907,9 +1894,11 @@ fn exec_with_pty(jail_name: &str, command: &str) -> Result<(), String> {
                         let _ = libc::shutdown(master_fd, libc::SHUT_WR);
                     }
                     Ok(n) => {
+                        let (input, detach) = detector.scan(&buffer[..n]);
+                        let n = input.len();
                         let mut written = 0;
                         while written < n {
-                            match libc::write(master_fd, buffer[written..].as_ptr() as *const libc::c_void, n - written) {
+                            match libc::write(master_fd, input[written..].as_ptr() as *const libc::c_void, n - written) {
                                 -1 => {
                                     let err = std::io::Error::last_os_error();
                                     if err.kind() == std::io::ErrorKind::WouldBlock {
@@ -927,6 +1916,7 @@ fn exec_with_pty(jail_name: &str, command: &str) -> Result<(), String> {
                                 }
                             }
                         }
+                        detached = detach;
                     }
                     Err(e) => {
                         if e.kind() != std::io::ErrorKind::WouldBlock {
@@ -936,6 +1926,10 @@ fn exec_with_pty(jail_name: &str, command: &str) -> Result<(), String> {
                     }
                 }
 
+                if detached {
+                    break;
+                }
+
                 // Small sleep to avoid busy waiting
                 if !activity {
                     thread::sleep(std::time::Duration::from_millis(1));
@@ -944,6 +1938,31 @@ fn exec_with_pty(jail_name: &str, command: &str) -> Result<(), String> {
 
             running.store(false, Ordering::Relaxed);
 
+            if detached {
+                libc::tcsetattr(0, libc::TCSANOW, &orig_term_settings);
+
+                // Closing the master would hang up the session, so hand it to
+                // a background process that drains output until the command exits
+                let drainer = libc::fork();
+                if drainer < 0 {
+                    return Err(format!("Failed to detach: {}", std::io::Error::last_os_error()));
+                }
+                if drainer == 0 {
+                    libc::setsid();
+                    libc::close(0);
+                    libc::close(1);
+                    libc::close(2);
+                    let flags = libc::fcntl(master_fd, libc::F_GETFL, 0);
+                    libc::fcntl(master_fd, libc::F_SETFL, flags & !libc::O_NONBLOCK);
+                    let mut sink = [0u8; 4096];
+                    while libc::read(master_fd, sink.as_mut_ptr() as *mut libc::c_void, sink.len()) > 0 {}
+                    libc::_exit(0);
+                }
+
+                eprintln!("\r\nDetached from session (pid {})", pid);
+                return Ok(ExitStatus::Detached);
+            }
+
             // Wait for child process
             let mut status: libc::c_int = 0;
             if libc::waitpid(pid, &mut status, 0) < 0 {
@@ -955,126 +1974,670 @@ fn exec_with_pty(jail_name: &str, command: &str) -> Result<(), String> {
             // Restore terminal settings
             libc::tcsetattr(0, libc::TCSANOW, &orig_term_settings);
 
-            if libc::WIFEXITED(status) {
-                let exit_code = libc::WEXITSTATUS(status);
-                if exit_code != 0 {
-                    return Err(format!("Command exited with code {}", exit_code));
-                }
-            } else if libc::WIFSIGNALED(status) {
-                let signal = libc::WTERMSIG(status);
-                return Err(format!("Command terminated by signal {}", signal));
-            }
-
-            Ok(())
+            Ok(ExitStatus::from_wait_status(status))
         }
     }
 }
 
 /// Stub for non-FreeBSD platforms (compile error)
 #[cfg(not(target_os = "freebsd"))]
-fn exec_with_pty(_jail_name: &str, _command: &str) -> Result<(), String> {
+fn exec_with_pty(_jail_name: &str, _command: &str, _detach_sequence: Vec<u8>, _on_spawn: impl FnOnce(u32)) -> Result<ExitStatus, String> {
     Err("PTY mode is only supported on FreeBSD".to_string())
 }
 
 /// Inspect an image or container
-async fn inspect(id: String) -> Result<(), String> {
+async fn inspect(id: String) -> Result<(), CliError> {
+    let client = client().await?;
+
     // Try as container first, then image
-    let request = Request::get(Endpoint::Container(id.clone()));
+    let value = match client.container(&id).await {
+        Ok(container) => serde_json::to_value(container),
+        Err(e) if e.is_not_found() => match client.image(&id).await {
+            Ok(image) => serde_json::to_value(image),
+            Err(e) if e.is_not_found() => {
+                return Err(CliError::new(exit::code::NOT_FOUND, format!("No image or container found with ID: {}", id)));
+            }
+            Err(e) => return Err(e.into()),
+        },
+        Err(e) => return Err(e.into()),
+    };
+    println!("{}", format_response(&value.map_err(|e| e.to_string())?));
+    Ok(())
+}
+
+// ============================================================================
+// Helper Functions
+// ============================================================================
 
-    let mut socket = connect_to_socket().await?;
-    let request_json = serde_json::to_string(&request)
-        .map_err(|e| format!("Failed to serialize request: {}", e))?;
+/// Show the daemon's running configuration, highlighting fields changed on disk
+async fn show_config() -> Result<(), CliError> {
+    let info = client().await?.system_config().await?;
 
-    socket
-        .send(request_json)
-        .await
-        .map_err(|e| format!("Failed to send request: {}", e))?;
+    match &info.path {
+        Some(path) => println!("Config file: {}", path),
+        None => println!("Config file: none (built-in defaults)"),
+    }
+    if let Some(mtime) = info.loaded_mtime {
+        println!("Loaded:      {} (file mtime)", mtime);
+    }
+    println!();
 
-    let response_line = socket
-        .next()
-        .await
-        .ok_or("No response from backend")?
-        .map_err(|e| format!("Failed to read response: {}", e))?;
+    let mut fields = Vec::new();
+    flatten_config(&info.config, String::new(), &mut fields);
+    for (path, value) in fields {
+        let marker = if info.drifted_fields.contains(&path) { "*" } else { " " };
+        println!("{} {} = {}", marker, path, value);
+    }
 
-    let response: kawakaze_backend::api::Response =
-        serde_json::from_str(&response_line).map_err(|e| format!("Failed to parse response: {}", e))?;
+    if let Some(err) = &info.file_error {
+        println!();
+        println!("Config file can no longer be read: {}", err);
+    }
+    if info.drift {
+        if !info.drifted_fields.is_empty() {
+            println!();
+            println!("* changed in the config file since the daemon loaded it");
+        }
+        println!("Restart kawakaze-backend to apply the file on disk.");
+    }
 
-    if response.is_success() {
-        if let Some(data) = response.data {
-            println!("{}", format_response(&data));
+    Ok(())
+}
+
+/// Show host and daemon state
+async fn info() -> Result<(), CliError> {
+    let info = client().await?.system_info().await?;
+    let unknown = || "unknown".to_string();
+    let host = &info.host;
+
+    println!("Version:     {}", info.version);
+    println!("Uptime:      {}", format_uptime(info.uptime_secs));
+    println!(
+        "OS:          {} ({})",
+        host.os_version.clone().unwrap_or_else(unknown),
+        host.arch.clone().unwrap_or_else(unknown)
+    );
+    println!("Kernel:      {}", host.kernel.clone().unwrap_or_else(unknown));
+    match (&host.cpu_model, host.cpus) {
+        (Some(model), Some(cpus)) => println!("CPU:         {} ({} cpus)", model, cpus),
+        (model, cpus) => println!(
+            "CPU:         {}",
+            model.clone().or(cpus.map(|n| format!("{} cpus", n))).unwrap_or_else(unknown)
+        ),
+    }
+    println!("Memory:      {}", host.memory_bytes.map(format_size).unwrap_or_else(unknown));
+
+    match (&info.pool, &info.pool_error) {
+        (Some(pool), _) => {
+            println!("Pool:        {} ({})", pool.name, pool.state);
+            println!("Space:       {} free of {}", format_size(pool.free_bytes), format_size(pool.size_bytes));
+            if let Some(status) = &pool.status {
+                println!("Status:      {}", status);
+            }
         }
+        (None, Some(err)) => println!("Pool:        unavailable ({})", err),
+        (None, None) => println!("Pool:        ZFS not configured"),
+    }
+
+    println!("Jails:       {}", state_counts_line(&info.jails));
+    println!("Images:      {}", state_counts_line(&info.images));
+    println!("Containers:  {}", summary_line(&info.containers));
+    if info.pending_writes.pending > 0 {
+        let oldest = info.pending_writes.oldest_secs.unwrap_or_default();
+        println!("DB writes:   {} pending retry, oldest {}s", info.pending_writes.pending, oldest);
+    }
+
+    if !info.slowest_commands.is_empty() {
+        println!();
+        println!("Slowest commands (last hour):");
+        for record in info.slowest_commands.iter().take(5) {
+            println!("  {:>8}ms  {} {}", record.duration_ms, record.program, record.args.join(" "));
+        }
+    }
+
+    if info.pool_writes_refused {
+        println!();
+        println!("Builds, container creation, bootstraps and upgrades are refused until the pool is ONLINE.");
+    } else if info.pool.as_ref().is_some_and(|pool| !pool.state.is_healthy()) {
+        println!();
+        println!("Builds and container creation are still allowed on the DEGRADED pool (storage.allow_degraded_pool).");
+    }
+
+    Ok(())
+}
+
+/// Subcommands whose first operand completes to names from the daemon, and
+/// the `__complete` kinds offered for it
+const NAME_COMPLETIONS: &[(&str, &str)] = &[
+    ("run", "images"),
+    ("create", "images"),
+    ("start", "stopped"),
+    ("stop", "running"),
+    ("rm", "containers"),
+    ("update", "containers images"),
+    ("rename", "containers"),
+    ("rmi", "images"),
+    ("logs", "containers"),
+    ("top", "running"),
+    ("exec-sessions", "containers"),
+    ("exec", "running"),
+    ("inspect", "containers images"),
+];
+
+/// How long `__complete` waits for the daemon before offering nothing
+const COMPLETION_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Write the completion script for `shell`; needs no daemon
+///
+/// clap generates the static part. For bash and zsh its `_kawakaze` is
+/// renamed `_kawakaze_static` and wrapped by one that offers the names
+/// `kawakaze __complete` prints for the first operand of the subcommands in
+/// `NAME_COMPLETIONS`; fish gets extra `complete` lines.
+fn completions(shell: clap_complete::Shell, out: &mut dyn std::io::Write) -> Result<(), String> {
+    use clap_complete::Shell;
+
+    let mut script = Vec::new();
+    clap_complete::generate(shell, &mut Cli::command(), "kawakaze", &mut script);
+    let script = String::from_utf8(script).map_err(|e| e.to_string())?;
+    let cases: String = NAME_COMPLETIONS.iter().map(|(command, kinds)| format!("        {}) kinds=\"{}\" ;;\n", command, kinds)).collect();
+
+    let script = match shell {
+        Shell::Bash => format!(
+            "{}\n_kawakaze() {{\n    \
+             local cur=\"${{COMP_WORDS[COMP_CWORD]}}\" kinds=\"\" i operands=0\n    \
+             case \"${{COMP_WORDS[1]}}\" in\n{}    esac\n    \
+             if [[ -n \"$kinds\" && \"$cur\" != -* ]]; then\n        \
+             for (( i = 2; i < COMP_CWORD; i++ )); do\n            \
+             [[ \"${{COMP_WORDS[i]}}\" == -* ]] || operands=$((operands + 1))\n        \
+             done\n        \
+             if (( operands == 0 )); then\n            \
+             COMPREPLY=( $(compgen -W \"$(kawakaze __complete $kinds 2>/dev/null)\" -- \"$cur\") )\n            \
+             return 0\n        \
+             fi\n    \
+             fi\n    \
+             _kawakaze_static \"$@\"\n}}\n",
+            script.replacen("_kawakaze() {\n", "_kawakaze_static() {\n", 1),
+            cases
+        ),
+        Shell::Zsh => {
+            let wrapper = format!(
+                "_kawakaze() {{\n    \
+                 local kinds\n    \
+                 case $words[2] in\n{}    esac\n    \
+                 if [[ -n $kinds && $CURRENT -eq 3 && $PREFIX != -* ]]; then\n        \
+                 local -a names\n        \
+                 names=(${{(f)\"$(kawakaze __complete ${{=kinds}} 2>/dev/null)\"}})\n        \
+                 compadd -a names\n        \
+                 return\n    \
+                 fi\n    \
+                 _kawakaze_static \"$@\"\n}}\n\n",
+                cases
+            );
+            // Defined before the trailer that runs or registers `_kawakaze`
+            let script = script.replacen("_kawakaze() {\n", "_kawakaze_static() {\n", 1);
+            match script.rfind("if [ \"$funcstack[1]\"") {
+                Some(trailer) => format!("{}{}{}", &script[..trailer], wrapper, &script[trailer..]),
+                None => format!("{}\n{}compdef _kawakaze kawakaze\n", script, wrapper),
+            }
+        }
+        Shell::Fish => {
+            let lines: String = NAME_COMPLETIONS
+                .iter()
+                .map(|(command, kinds)| {
+                    format!("complete -c kawakaze -n \"__fish_kawakaze_using_subcommand {}\" -f -a \"(kawakaze __complete {})\"\n", command, kinds)
+                })
+                .collect();
+            format!("{}{}", script, lines)
+        }
+        _ => script,
+    };
+    out.write_all(script.as_bytes()).map_err(|e| e.to_string())
+}
+
+/// Print the names and IDs of `kinds`, one per line; prints nothing when
+/// the daemon can't be reached, so a TAB never shows an error
+async fn complete_names(kinds: Vec<NameKind>) {
+    let names = async {
+        let client = client().await.ok()?;
+        let mut names = Vec::new();
+        for kind in kinds {
+            names.extend(client.names(kind).await.ok()?);
+        }
+        Some(names)
+    };
+    if let Ok(Some(names)) = tokio::time::timeout(COMPLETION_TIMEOUT, names).await {
+        for name in names {
+            println!("{}", name);
+        }
+    }
+}
+
+/// Run the daemon's host checks; fails if any check failed
+async fn doctor() -> Result<(), CliError> {
+    let report = client().await?.doctor().await?;
+    let color = std::io::IsTerminal::is_terminal(&std::io::stdout());
+
+    println!("{:<18} {:<8} DETAIL", "CHECK", "STATUS");
+    for check in &report.checks {
+        println!("{:<18} {} {}", check.name, status_cell(check.status, color), check.detail);
+        if let Some(hint) = &check.hint {
+            println!("{:<27} -> {}", "", hint);
+        }
+    }
+
+    let failed = report.count(CheckStatus::Fail);
+    if failed > 0 {
+        return Err(format!("{} check(s) failed", failed).into());
+    }
+    Ok(())
+}
+
+/// A check status padded to the STATUS column, colored on a terminal
+fn status_cell(status: CheckStatus, color: bool) -> String {
+    let text = format!("{:<8}", status.as_str());
+    let code = match status {
+        CheckStatus::Ok => "32",
+        CheckStatus::Warn => "33",
+        CheckStatus::Fail => "31",
+        CheckStatus::Skipped => "90",
+    };
+    if color { format!("\x1b[{}m{}\x1b[0m", code, text) } else { text }
+}
+
+/// "2 running, 1 stopped (3 total)" from per-state counts
+fn state_counts_line(counts: &std::collections::BTreeMap<String, usize>) -> String {
+    let total: usize = counts.values().sum();
+    if total == 0 {
+        return "0 total".to_string();
+    }
+    let parts: Vec<String> = counts.iter().map(|(state, n)| format!("{} {}", n, state)).collect();
+    format!("{} ({} total)", parts.join(", "), total)
+}
+
+/// Seconds as "3d 4h 5m", "4h 5m" or "5m 6s"
+fn format_uptime(secs: u64) -> String {
+    let (days, hours, mins) = (secs / 86_400, secs % 86_400 / 3600, secs % 3600 / 60);
+    if days > 0 {
+        format!("{}d {}h {}m", days, hours, mins)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, mins)
+    } else {
+        format!("{}m {}s", mins, secs % 60)
+    }
+}
+
+/// List orphaned datasets, or adopt/destroy one of them
+async fn orphans(
+    adopt: Option<String>,
+    destroy: Option<String>,
+    name: Option<String>,
+    image: Option<String>,
+) -> Result<(), CliError> {
+    let client = client().await?;
+    if let Some(dataset) = adopt {
+        let request = AdoptOrphanRequest { dataset: dataset.clone(), name, image };
+        let adopted = client.adopt_orphan(&request).await?;
+        println!("Adopted {} as {}", dataset, adopted.id);
+        return Ok(());
+    }
+
+    if let Some(dataset) = destroy {
+        let request = DestroyOrphanRequest { dataset: dataset.clone() };
+        client.destroy_orphan(&request).await?;
+        println!("Destroyed {}", dataset);
         return Ok(());
     }
 
-    // Try as image
-    let request = Request::get(Endpoint::Image(id.clone()));
+    let orphans = client.orphans().await?;
 
-    let mut socket = connect_to_socket().await?;
-    let request_json = serde_json::to_string(&request)
-        .map_err(|e| format!("Failed to serialize request: {}", e))?;
+    if orphans.is_empty() {
+        eprintln!("No orphaned datasets");
+        return Ok(());
+    }
+
+    println!("{:<10} {:<50} {:<10} {:<20}", "KIND", "DATASET", "SIZE", "CREATED");
+    for orphan in &orphans {
+        println!(
+            "{:<10} {:<50} {:<10} {:<20}",
+            orphan.kind,
+            orphan.dataset,
+            format_size(orphan.size_bytes),
+            format_age(orphan.created_at)
+        );
+    }
+    output::status("\nUse --adopt DATASET (with --image for containers, --name for images) or --destroy DATASET.");
+
+    Ok(())
+}
+
+/// Show per-program command timing and the slowest recent commands
+async fn command_metrics() -> Result<(), CliError> {
+    let metrics = client().await?.command_metrics().await?;
+
+    println!("{:<16} {:>8} {:>10} {:>10} {:>8}", "PROGRAM", "COUNT", "TOTAL", "AVG", "FAILED");
+    for (program, stats) in &metrics.commands {
+        println!(
+            "{:<16} {:>8} {:>8}ms {:>8}ms {:>8}",
+            program,
+            stats.count,
+            stats.total_ms,
+            stats.total_ms / stats.count.max(1),
+            stats.failures
+        );
+    }
+
+    if !metrics.slowest_recent.is_empty() {
+        println!();
+        println!("Slowest in the last hour (slow threshold {}ms):", metrics.slow_threshold_ms);
+        for record in &metrics.slowest_recent {
+            println!(
+                "  {:>8}ms  {} {}{}",
+                record.duration_ms,
+                record.program,
+                record.args.join(" "),
+                if record.success { "" } else { "  (failed)" }
+            );
+        }
+    }
+
+    Ok(())
+}
 
-    socket
-        .send(request_json)
-        .await
-        .map_err(|e| format!("Failed to send request: {}", e))?;
+/// List the boot containers and the last boot's results
+async fn api_schema() -> Result<(), CliError> {
+    let description = client().await?.api_schema().await?;
+    let json = serde_json::to_string_pretty(&description).map_err(|e| e.to_string())?;
+    println!("{}", json);
+    Ok(())
+}
 
-    let response_line = socket
-        .next()
-        .await
-        .ok_or("No response from backend")?
-        .map_err(|e| format!("Failed to read response: {}", e))?;
+async fn boot_list() -> Result<(), CliError> {
+    let list = client().await?.boot_list().await?;
 
-    let response: kawakaze_backend::api::Response =
-        serde_json::from_str(&response_line).map_err(|e| format!("Failed to parse response: {}", e))?;
+    if list.containers.is_empty() {
+        println!("No containers start at boot (kawakaze update --boot CONTAINER)");
+    } else {
+        println!("{:<4} {:<14} {:<24} {:<10}", "#", "CONTAINER ID", "NAME", "STATE");
+        for (idx, container) in list.containers.iter().enumerate() {
+            println!(
+                "{:<4} {:<14} {:<24} {:<10}",
+                idx + 1,
+                short_id(&container.id),
+                container.name.as_deref().unwrap_or("-"),
+                container.state
+            );
+        }
+    }
 
-    if response.is_success() {
-        if let Some(data) = response.data {
-            println!("{}", format_response(&data));
+    let Some(report) = list.last_boot else {
+        return Ok(());
+    };
+    println!();
+    println!("Last boot ({} failed):", report.failed());
+    for result in &report.results {
+        let outcome = match result.outcome {
+            BootOutcome::Started => "started",
+            BootOutcome::AlreadyRunning => "already running",
+            BootOutcome::Failed => "FAILED",
+        };
+        let name = result.name.as_deref().unwrap_or(&result.id);
+        match &result.error {
+            Some(error) => println!("  {:<24} {}: {}", name, outcome, error),
+            None => println!("  {:<24} {}", name, outcome),
         }
+    }
+
+    Ok(())
+}
+
+/// List queued and running builds and bootstraps, or cancel a queued one
+async fn tasks(cancel: Option<String>) -> Result<(), CliError> {
+    let client = client().await?;
+    if let Some(id) = cancel {
+        client.cancel_task(&id).await?;
+        println!("{}", id);
         return Ok(());
     }
 
-    Err(format!("No image or container found with ID: {}", id))
+    let tasks = client.tasks().await?;
+
+    if tasks.is_empty() {
+        println!("No builds or bootstraps running or queued");
+        return Ok(());
+    }
+    println!("{:<10} {:<36} {:<24} STATE", "KIND", "ID", "NAME");
+    for task in &tasks {
+        let state = match task.position {
+            Some(position) => format!("queued, position {}", position),
+            None => "running".to_string(),
+        };
+        println!("{:<10} {:<36} {:<24} {}", task.kind, task.id, task.name, state);
+    }
+
+    Ok(())
 }
 
-// ============================================================================
-// Helper Functions
-// ============================================================================
+/// Destroy old snapshots of a dataset
+async fn prune_snapshots(dataset: String, keep: usize) -> Result<(), CliError> {
+    let request = PruneSnapshotsRequest { dataset, keep_last: keep };
+    let result = client().await?.prune_snapshots(&request).await?;
+
+    for snapshot in &result.destroyed {
+        println!("Destroyed: {}@{}", result.dataset, snapshot);
+    }
+    println!("Pruned {} snapshot(s) of {}", result.destroyed.len(), result.dataset);
+
+    Ok(())
+}
 
-/// Parse a port mapping string (hostPort:containerPort or hostPort:containerPort/protocol)
+/// Destroy (or list) the snapshots nothing references
+async fn snapshot_gc(dry_run: bool) -> Result<(), CliError> {
+    let report = client().await?.snapshot_gc(dry_run).await?;
+
+    if report.dry_run {
+        for candidate in &report.candidates {
+            println!("Would destroy: {} ({})", candidate.name, format_size(candidate.used_bytes));
+        }
+        let total = report.candidates.iter().map(|c| c.used_bytes).sum();
+        println!("{} unreferenced snapshot(s), {}", report.candidates.len(), format_size(total));
+        return Ok(());
+    }
+
+    for error in &report.errors {
+        eprintln!("Failed: {}", error);
+    }
+    println!("Destroyed {} snapshot(s), reclaimed {}", report.destroyed, format_size(report.reclaimed_bytes));
+
+    Ok(())
+}
+
+/// Change the daemon's log level
+async fn set_log_level(level: String) -> Result<(), CliError> {
+    let result = client().await?.set_log_level(&level).await?;
+    println!("Log level: {} (was {})", result.level, result.previous);
+    Ok(())
+}
+
+/// Flatten a JSON object into `(dotted.path, value)` pairs
+fn flatten_config(value: &Value, prefix: String, out: &mut Vec<(String, String)>) {
+    match value {
+        Value::Object(map) => {
+            for (key, field) in map {
+                let path = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+                flatten_config(field, path, out);
+            }
+        }
+        _ => out.push((prefix, value.to_string())),
+    }
+}
+
+/// Parse a port mapping string: `[hostIp:][hostPort:]containerPort[/protocol]`,
+/// with an IPv6 host address in brackets (`[::1]:8080:80`)
 fn parse_port_mapping(s: &str) -> Option<PortMapping> {
     let parts: Vec<&str> = s.split('/').collect();
     let protocol = if parts.len() > 1 { parts[1] } else { "tcp" };
 
-    let port_parts: Vec<&str> = parts[0].split(':').collect();
-    if port_parts.len() != 2 {
-        return None;
-    }
+    let (host_ip, ports) = match parts[0].strip_prefix('[') {
+        Some(rest) => {
+            let (ip, ports) = rest.split_once("]:")?;
+            (Some(ip.parse::<std::net::Ipv6Addr>().ok()?.to_string()), ports)
+        }
+        None if parts[0].matches(':').count() == 2 => {
+            let (ip, ports) = parts[0].split_once(':')?;
+            (Some(ip.parse::<std::net::Ipv4Addr>().ok()?.to_string()), ports)
+        }
+        None => (None, parts[0]),
+    };
 
-    let host_port: u16 = port_parts[0].parse().ok()?;
-    let container_port: u16 = port_parts[1].parse().ok()?;
+    // A bare container port, or an empty host port after a host address,
+    // leaves the host port (0) to the daemon
+    let (host_port, container_port) = match ports.split_once(':') {
+        Some(("", container)) if host_ip.is_some() => (0, container.parse().ok()?),
+        Some((host, container)) => (host.parse().ok()?, container.parse().ok()?),
+        None if host_ip.is_none() => (0, ports.parse().ok()?),
+        None => return None,
+    };
 
     Some(PortMapping {
         host_port,
         container_port,
         protocol: protocol.to_string(),
+        host_ip,
     })
 }
 
 /// Parse a volume mount string (source:destination)
-fn parse_volume_mount(s: &str) -> Option<kawakaze_backend::api::Mount> {
-    let parts: Vec<&str> = s.splitn(2, ':').collect();
-    if parts.len() != 2 {
+fn parse_volume_mount(s: &str) -> Option<Mount> {
+    let parts: Vec<&str> = s.splitn(3, ':').collect();
+    if parts.len() < 2 {
         return None;
     }
+    let (mut read_only, mut delegate) = (false, false);
+    if let Some(options) = parts.get(2) {
+        for option in options.split(',') {
+            match option {
+                "rw" => read_only = false,
+                "ro" => read_only = true,
+                "delegate" => delegate = true,
+                _ => return None,
+            }
+        }
+    }
+    // A source that isn't a host path names an existing ZFS dataset
+    let mount_type = if parts[0].starts_with('/') { "nullfs" } else { "zfs" };
 
-    Some(kawakaze_backend::api::Mount {
+    Some(Mount {
         source: parts[0].to_string(),
         destination: parts[1].to_string(),
-        mount_type: "nullfs".to_string(), // Default to nullfs for now
+        mount_type: mount_type.to_string(),
+        read_only,
+        delegate,
     })
 }
 
+/// Parse `--sysctl key=value`
+fn parse_sysctl(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("Invalid --sysctl '{}': expected KEY=VALUE", s)),
+    }
+}
+
+/// Parse a tmpfs mount (/path[:size=64m,mode=1777])
+fn parse_tmpfs_mount(s: &str) -> Result<TmpfsMount, String> {
+    let (destination, options) = s.split_once(':').unwrap_or((s, ""));
+    if !destination.starts_with('/') {
+        return Err(format!("Invalid --tmpfs '{}': the path must be absolute", s));
+    }
+
+    let mut mount = TmpfsMount::new(destination.to_string());
+    for option in options.split(',').filter(|o| !o.is_empty()) {
+        match option.split_once('=') {
+            Some(("size", size)) => {
+                mount.size_bytes = Some(parse_size(size).ok_or_else(|| {
+                    format!("Invalid --tmpfs size '{}': expected a number with an optional k, m or g suffix", size)
+                })?)
+            }
+            Some(("mode", mode)) => {
+                mount.mode = Some(
+                    u32::from_str_radix(mode, 8)
+                        .ok()
+                        .filter(|mode| *mode <= 0o7777)
+                        .ok_or_else(|| format!("Invalid --tmpfs mode '{}': expected octal permissions like 1777", mode))?,
+                )
+            }
+            _ => return Err(format!("Invalid --tmpfs option '{}': expected size=... or mode=...", option)),
+        }
+    }
+    Ok(mount)
+}
+
+/// Parse a size in bytes with an optional k, m or g suffix (powers of 1024)
+fn parse_size(s: &str) -> Option<u64> {
+    let (digits, shift) = match s.chars().last()?.to_ascii_lowercase() {
+        'k' => (&s[..s.len() - 1], 10),
+        'm' => (&s[..s.len() - 1], 20),
+        'g' => (&s[..s.len() - 1], 30),
+        _ => (s, 0),
+    };
+    let size: u64 = digits.parse().ok().filter(|size| *size > 0)?;
+    size.checked_mul(1 << shift)
+}
+
+/// Parse key=value build arguments, ignoring malformed entries
+fn parse_build_args(build_args: Vec<String>) -> HashMap<String, String> {
+    let mut args_map = HashMap::new();
+    for arg in build_args {
+        let parts: Vec<&str> = arg.splitn(2, '=').collect();
+        if parts.len() == 2 {
+            args_map.insert(parts[0].to_string(), parts[1].to_string());
+        }
+    }
+    args_map
+}
+
+/// Where the value of a `--secret` comes from
+#[derive(Debug, PartialEq)]
+enum SecretSource {
+    Env(String),
+    File(String),
+}
+
+/// Parse `id=NAME[,src=FILE|,env=VAR]`; without a source the value is read
+/// from the environment variable NAME
+fn parse_secret_spec(spec: &str) -> Result<(String, SecretSource), String> {
+    let mut id = None;
+    let mut source = None;
+    for part in spec.split(',') {
+        match part.split_once('=') {
+            Some(("id", value)) => id = Some(value.to_string()),
+            Some(("src" | "source", value)) => source = Some(SecretSource::File(value.to_string())),
+            Some(("env", value)) => source = Some(SecretSource::Env(value.to_string())),
+            _ => return Err(format!("Invalid secret '{}': expected id=NAME[,src=FILE|,env=VAR]", spec)),
+        }
+    }
+
+    let id = id.filter(|id| !id.is_empty()).ok_or_else(|| format!("Secret '{}' has no id", spec))?;
+    let source = source.unwrap_or_else(|| SecretSource::Env(id.clone()));
+    Ok((id, source))
+}
+
+/// Resolve every `--secret` to its value
+fn read_secrets(specs: &[String]) -> Result<HashMap<String, String>, String> {
+    let mut secrets = HashMap::new();
+    for spec in specs {
+        let (id, source) = parse_secret_spec(spec)?;
+        let value = match source {
+            SecretSource::Env(var) => std::env::var(&var)
+                .map_err(|_| format!("Secret '{}': environment variable {} is not set", id, var))?,
+            SecretSource::File(path) => std::fs::read_to_string(&path)
+                .map_err(|e| format!("Secret '{}': failed to read {}: {}", id, path, e))?,
+        };
+        secrets.insert(id, value);
+    }
+    Ok(secrets)
+}
+
 /// Format bytes to human-readable size
 fn format_size(bytes: u64) -> String {
     const KB: u64 = 1024;
@@ -1092,14 +2655,60 @@ fn format_size(bytes: u64) -> String {
     }
 }
 
-/// Format Unix timestamp to human-readable date
-fn format_timestamp(ts: i64) -> String {
-    use std::time::{Duration, UNIX_EPOCH};
+/// One-line container counts, e.g. "12 running, 3 stopped (15 total)"
+fn summary_line(summary: &ContainerSummary) -> String {
+    let parts: Vec<String> = [
+        (summary.running, "running"),
+        (summary.stopped, "stopped"),
+        (summary.created, "created"),
+        (summary.paused, "paused"),
+        (summary.locked, "locked"),
+        (summary.unhealthy, "unhealthy"),
+    ]
+    .iter()
+    .filter(|(count, _)| *count > 0)
+    .map(|(count, label)| format!("{} {}", count, label))
+    .collect();
+
+    if parts.is_empty() {
+        format!("{} total", summary.total)
+    } else {
+        format!("{} ({} total)", parts.join(", "), summary.total)
+    }
+}
+
+/// Append a marker to the display name of protected resources
+fn mark_protected(name: &str, protected: bool) -> String {
+    if protected {
+        format!("{}*", name)
+    } else {
+        name.to_string()
+    }
+}
+
+/// A container's STATUS in `ps`: its state, and for a stopped container
+/// when and why it stopped ("stopped 2 hours ago (stopped by uid 1001)")
+fn container_status(container: &ContainerListItem) -> String {
+    let mut status = container.state.clone();
+    if container.state == "stopped" {
+        if let Some(stopped_at) = container.stopped_at {
+            status = format!("{} {}", status, format_age(stopped_at));
+        }
+        if let Some(cause) = &container.last_stop_cause {
+            status = format!("{} ({})", status, cause);
+        }
+    }
+    if container.dataset_missing {
+        status.push('!');
+    }
+    status
+}
 
-    if let Some(d) = UNIX_EPOCH.checked_add(Duration::from_secs(ts as u64)) {
-        // Simple date formatting
-        let datetime = chrono::DateTime::<chrono::Utc>::from(d);
-        datetime.format("%Y-%m-%d %H:%M").to_string()
+/// How long ago `ts` was, e.g. "3 hours ago"; "unknown" for records
+/// without a creation time
+fn format_age(ts: Timestamp) -> String {
+    if ts > Timestamp::EPOCH {
+        ts.age(Timestamp::now())
     } else {
         "unknown".to_string()
     }
@@ -1109,6 +2718,168 @@ fn format_timestamp(ts: i64) -> String {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_flag() {
+        assert_eq!(flag(true, false), Some(true));
+        assert_eq!(flag(false, true), Some(false));
+        assert_eq!(flag(false, false), None);
+    }
+
+    #[test]
+    fn test_with_env() {
+        let command = vec!["echo".to_string(), "$GREETING".to_string()];
+        assert_eq!(with_env(&BTreeMap::new(), &command), "echo '$GREETING'");
+
+        let env = BTreeMap::from([("GREETING".to_string(), "hello world".to_string()), ("A".to_string(), "1".to_string())]);
+        assert_eq!(with_env(&env, &command), "export A=1 GREETING='hello world'; echo '$GREETING'");
+    }
+
+    #[test]
+    fn test_parse_secret_spec() {
+        assert_eq!(parse_secret_spec("id=NPM_TOKEN").unwrap(), ("NPM_TOKEN".to_string(), SecretSource::Env("NPM_TOKEN".to_string())));
+        assert_eq!(
+            parse_secret_spec("id=key,src=/run/key.pem").unwrap(),
+            ("key".to_string(), SecretSource::File("/run/key.pem".to_string()))
+        );
+        assert_eq!(parse_secret_spec("id=TOKEN,env=CI_TOKEN").unwrap().1, SecretSource::Env("CI_TOKEN".to_string()));
+        assert!(parse_secret_spec("src=/run/key.pem").is_err());
+        assert!(parse_secret_spec("id=").is_err());
+        assert!(parse_secret_spec("TOKEN").is_err());
+    }
+
+
+    #[test]
+    fn test_summary_line() {
+        let summary = ContainerSummary { total: 16, running: 12, stopped: 3, created: 1, paused: 0, locked: 0, unhealthy: 1 };
+        assert_eq!(summary_line(&summary), "12 running, 3 stopped, 1 created, 1 unhealthy (16 total)");
+        let summary = ContainerSummary { total: 2, running: 1, locked: 1, ..Default::default() };
+        assert_eq!(summary_line(&summary), "1 running, 1 locked (2 total)");
+        assert_eq!(summary_line(&ContainerSummary::default()), "0 total");
+    }
+
+    #[test]
+    fn test_state_counts_line() {
+        let counts = std::collections::BTreeMap::from([("running".to_string(), 2), ("stopped".to_string(), 1)]);
+        assert_eq!(state_counts_line(&counts), "2 running, 1 stopped (3 total)");
+        assert_eq!(state_counts_line(&Default::default()), "0 total");
+    }
+
+    #[test]
+    fn test_status_cell() {
+        assert_eq!(status_cell(CheckStatus::Warn, false), "warn    ");
+        assert_eq!(status_cell(CheckStatus::Fail, true), "\x1b[31mfail    \x1b[0m");
+    }
+
+    #[test]
+    fn test_format_uptime() {
+        assert_eq!(format_uptime(42), "0m 42s");
+        assert_eq!(format_uptime(3 * 3600 + 120), "3h 2m");
+        assert_eq!(format_uptime(2 * 86_400 + 3600 + 60), "2d 1h 1m");
+    }
+
+    #[test]
+    fn test_completions() {
+        for shell in [clap_complete::Shell::Bash, clap_complete::Shell::Zsh, clap_complete::Shell::Fish] {
+            let mut script = Vec::new();
+            completions(shell, &mut script).unwrap();
+            let script = String::from_utf8(script).unwrap();
+            assert!(script.contains("kawakaze") && script.contains("inspect"), "{}", shell);
+            // Names come from the daemon, through the hidden subcommand
+            assert!(script.contains("kawakaze __complete"), "{}", shell);
+        }
+        for shell in [clap_complete::Shell::Bash, clap_complete::Shell::Zsh] {
+            let mut script = Vec::new();
+            completions(shell, &mut script).unwrap();
+            let script = String::from_utf8(script).unwrap();
+            assert_eq!(script.matches("_kawakaze_static() {").count(), 1, "{}", shell);
+            assert_eq!(script.matches("\n_kawakaze() {").count(), 1, "{}", shell);
+            assert!(script.contains("        stop) kinds=\"running\" ;;\n"), "{}", shell);
+        }
+        let cli = Cli::try_parse_from(["kawakaze", "__complete", "running", "images"]).unwrap();
+        assert!(matches!(cli.command, Commands::Complete { kinds } if kinds == [NameKind::Running, NameKind::Images]));
+        assert!(Cli::try_parse_from(["kawakaze", "__complete", "networks"]).is_err());
+        let cli = Cli::try_parse_from(["kawakaze", "completions", "zsh"]).unwrap();
+        assert!(matches!(cli.command, Commands::Completions { shell: clap_complete::Shell::Zsh }));
+        assert!(Cli::try_parse_from(["kawakaze", "completions", "tcsh"]).is_err());
+    }
+
+    #[test]
+    fn test_ps_summary_args() {
+        let cli = Cli::try_parse_from(["kawakaze", "ps", "--summary", "--format", "json"]).unwrap();
+        assert!(matches!(cli.command, Commands::Ps { summary: true, no_summary: false, format: OutputFormat::Json, .. }));
+        assert!(Cli::try_parse_from(["kawakaze", "ps", "--summary", "--no-summary"]).is_err());
+    }
+
+    #[test]
+    fn test_description_args() {
+        let cli = Cli::try_parse_from(["kawakaze", "ps", "--filter", "description~=payments", "--filter", "state=running"]).unwrap();
+        assert!(matches!(cli.command, Commands::Ps { filter, .. } if filter == ["description~=payments", "state=running"]));
+        assert!(Cli::try_parse_from(["kawakaze", "ps", "--filter", "state=running", "--summary"]).is_err());
+
+        let cli = Cli::try_parse_from(["kawakaze", "run", "--description", "owned by team-payments", "base"]).unwrap();
+        assert!(matches!(cli.command, Commands::Run { container, .. } if container.description.as_deref() == Some("owned by team-payments")));
+
+        let cli = Cli::try_parse_from(["kawakaze", "update", "--jail", "db", "--description", ""]).unwrap();
+        assert!(matches!(cli.command, Commands::Update { jail: true, description: Some(d), .. } if d.is_empty()));
+        // A jail has nothing else to update, and isn't an image
+        assert!(Cli::try_parse_from(["kawakaze", "update", "--jail", "db", "--protect"]).is_err());
+        assert!(Cli::try_parse_from(["kawakaze", "update", "--jail", "--image", "db", "--description", "x"]).is_err());
+    }
+
+    #[test]
+    fn test_async_args() {
+        let cli = Cli::try_parse_from(["kawakaze", "rm", "web", "--async"]).unwrap();
+        assert!(matches!(cli.command, Commands::Rm { run_async: true, force: false, .. }));
+        let cli = Cli::try_parse_from(["kawakaze", "run", "--async", "base", "/bin/sh"]).unwrap();
+        assert!(matches!(cli.command, Commands::Run { container: ContainerArgs { run_async: true, .. }, .. }));
+    }
+
+    #[test]
+    fn test_env_file_args() {
+        let dir = tempfile::tempdir().unwrap();
+        let (base, prod) = (dir.path().join("base.env"), dir.path().join("prod.env"));
+        std::fs::write(&base, "A=base\nB=base\n").unwrap();
+        std::fs::write(&prod, "B=prod\nC=prod\n").unwrap();
+        let (base_arg, prod_arg) = (base.to_str().unwrap(), prod.to_str().unwrap());
+
+        // Files in order, then -e, wherever the flags are given
+        let cli = Cli::try_parse_from(["kawakaze", "run", "-e", "C=flag", "--env-file", base_arg, "--env-file", prod_arg, "base"]).unwrap();
+        let Commands::Run { container, .. } = cli.command else { panic!("not a run") };
+        let env = container_env(&container).unwrap();
+        let expected = [("A", "base"), ("B", "prod"), ("C", "flag")];
+        assert_eq!(env, expected.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect());
+
+        let missing = dir.path().join("missing.env");
+        let cli = Cli::try_parse_from(["kawakaze", "create", "--env-file", missing.to_str().unwrap(), "base"]).unwrap();
+        let Commands::Create { container } = cli.command else { panic!("not a create") };
+        assert!(container_env(&container).unwrap_err().contains(missing.to_str().unwrap()));
+
+        let cli = Cli::try_parse_from(["kawakaze", "build", "f", "-n", "web", "--build-arg-file", base_arg, "--build-arg-file", prod_arg]).unwrap();
+        assert!(matches!(cli.command, Commands::Build { build_arg_file, .. } if build_arg_file == [base.clone(), prod.clone()]));
+    }
+
+    #[test]
+    fn test_create_args() {
+        // Everything run creates with, nothing it only does to start
+        let cli = Cli::try_parse_from(["kawakaze", "create", "--name", "web", "-p", "8080:80", "--read-only", "base", "/usr/local/bin/httpd", "-f"]).unwrap();
+        let Commands::Create { container } = cli.command else { panic!("not a create") };
+        assert_eq!(container.image, "base");
+        assert_eq!(container.name.as_deref(), Some("web"));
+        assert_eq!(container.publish, ["8080:80"]);
+        assert!(container.read_only);
+        assert_eq!(container.command, ["/usr/local/bin/httpd", "-f"]);
+        assert!(Cli::try_parse_from(["kawakaze", "create", "-it", "base"]).is_err());
+        assert!(Cli::try_parse_from(["kawakaze", "create", "--detach-keys", "ctrl-a", "base"]).is_err());
+    }
+
+    #[test]
+    fn test_remove_async() {
+        assert!(remove_async(true, None));
+        assert!(!remove_async(false, None));
+        assert!(!remove_async(false, Some(ASYNC_REMOVE_BYTES)));
+        assert!(remove_async(false, Some(ASYNC_REMOVE_BYTES + 1)));
+    }
+
     #[test]
     fn test_parse_port_mapping() {
         let mapping = parse_port_mapping("8080:80").unwrap();
@@ -1120,6 +2891,37 @@ mod tests {
         assert_eq!(mapping.host_port, 8080);
         assert_eq!(mapping.container_port, 80);
         assert_eq!(mapping.protocol, "udp");
+
+        let mapping = parse_port_mapping("53/udp").unwrap();
+        assert_eq!(mapping.host_port, 0);
+        assert_eq!(mapping.container_port, 53);
+        assert_eq!(mapping.protocol, "udp");
+
+        assert!(parse_port_mapping("a:80").is_none());
+    }
+
+    #[test]
+    fn test_parse_port_mapping_host_ip() {
+        let mapping = parse_port_mapping("[::1]:8080:80").unwrap();
+        assert_eq!(mapping.host_ip.as_deref(), Some("::1"));
+        assert_eq!((mapping.host_port, mapping.container_port), (8080, 80));
+
+        let mapping = parse_port_mapping("[2001:DB8::10]:53:53/udp").unwrap();
+        assert_eq!(mapping.host_ip.as_deref(), Some("2001:db8::10"));
+        assert_eq!(mapping.protocol, "udp");
+
+        let mapping = parse_port_mapping("127.0.0.1:8080:80").unwrap();
+        assert_eq!(mapping.host_ip.as_deref(), Some("127.0.0.1"));
+        assert_eq!((mapping.host_port, mapping.container_port), (8080, 80));
+
+        // A host address with an empty host port picks one
+        let mapping = parse_port_mapping("[::1]::80").unwrap();
+        assert_eq!((mapping.host_port, mapping.container_port), (0, 80));
+        assert_eq!(parse_port_mapping("8080:80").unwrap().host_ip, None);
+
+        for bad in ["::1:8080:80", "[::1]8080:80", "[::1:8080:80", "[10.0.0.1]:8080:80", "[::1]:80", "localhost:8080:80", "10.0.0.1:80"] {
+            assert!(parse_port_mapping(bad).is_none(), "{}", bad);
+        }
     }
 
     #[test]
@@ -1128,6 +2930,58 @@ mod tests {
         assert_eq!(mount.source, "/host/path");
         assert_eq!(mount.destination, "/container/path");
         assert_eq!(mount.mount_type, "nullfs");
+        assert!(!mount.read_only);
+
+        assert!(parse_volume_mount("/srv/www:/var/www:ro").unwrap().read_only);
+        assert!(!parse_volume_mount("/srv/www:/var/www:rw").unwrap().read_only);
+        assert!(parse_volume_mount("/srv/www:/var/www:rx").is_none());
+
+        let mount = parse_volume_mount("tank/data/pg:/var/db/postgres:delegate").unwrap();
+        assert_eq!(mount.source, "tank/data/pg");
+        assert_eq!(mount.mount_type, "zfs");
+        assert!(mount.delegate && !mount.read_only);
+        let mount = parse_volume_mount("tank/data/www:/var/www:ro").unwrap();
+        assert!(mount.read_only && !mount.delegate);
+        assert!(!parse_volume_mount("/srv/www:/var/www").unwrap().delegate);
+    }
+
+    #[test]
+    fn test_parse_sysctl() {
+        assert_eq!(parse_sysctl("net.inet.ip.forwarding=1").unwrap(), ("net.inet.ip.forwarding".to_string(), "1".to_string()));
+        assert_eq!(parse_sysctl("net.inet.tcp.cc.algorithm=cubic=x").unwrap().1, "cubic=x");
+        assert!(parse_sysctl("net.inet.ip.forwarding").is_err());
+        assert!(parse_sysctl("=1").is_err());
+    }
+
+    #[test]
+    fn test_parse_tmpfs_mount() {
+        let mount = parse_tmpfs_mount("/run:size=64m,mode=1777").unwrap();
+        assert_eq!(mount.destination, "/run");
+        assert_eq!(mount.size_bytes, Some(64 << 20));
+        assert_eq!(mount.mode, Some(0o1777));
+
+        assert_eq!(parse_tmpfs_mount("/tmp").unwrap(), TmpfsMount::new("/tmp".to_string()));
+        assert_eq!(parse_tmpfs_mount("/tmp:mode=700").unwrap().mode, Some(0o700));
+        assert_eq!(parse_tmpfs_mount("/tmp:size=4096").unwrap().size_bytes, Some(4096));
+
+        assert!(parse_tmpfs_mount("run").is_err());
+        assert!(parse_tmpfs_mount("/run:size=64x").is_err());
+        assert!(parse_tmpfs_mount("/run:mode=999").is_err());
+        assert!(parse_tmpfs_mount("/run:mode=17777").is_err());
+        assert!(parse_tmpfs_mount("/run:uid=0").unwrap_err().contains("uid=0"));
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512"), Some(512));
+        assert_eq!(parse_size("8k"), Some(8 << 10));
+        assert_eq!(parse_size("64M"), Some(64 << 20));
+        assert_eq!(parse_size("2g"), Some(2 << 30));
+        assert_eq!(parse_size(""), None);
+        assert_eq!(parse_size("m"), None);
+        assert_eq!(parse_size("0m"), None);
+        assert_eq!(parse_size("1.5g"), None);
+        assert_eq!(parse_size("99999999999999g"), None);
     }
 
     #[test]
@@ -1137,4 +2991,111 @@ mod tests {
         assert_eq!(format_size(5_242_880), "5.0MB");
         assert_eq!(format_size(1_073_741_824), "1.0GB");
     }
+
+    #[test]
+    fn test_mark_protected() {
+        assert_eq!(mark_protected("web", true), "web*");
+        assert_eq!(mark_protected("web", false), "web");
+    }
+
+    #[test]
+    fn test_container_status() {
+        let item = |value: serde_json::Value| -> ContainerListItem {
+            let mut item = serde_json::json!({ "id": "0f8e", "image_id": "9a1c", "state": "stopped", "ip": null });
+            item.as_object_mut().unwrap().extend(value.as_object().unwrap().clone());
+            serde_json::from_value(item).unwrap()
+        };
+
+        // Older daemons don't say why
+        assert_eq!(container_status(&item(serde_json::json!({}))), "stopped");
+        assert_eq!(
+            container_status(&item(serde_json::json!({ "stopped_at": "1970-01-01T00:00:00Z", "last_stop_cause": { "user_request": 1001 } }))),
+            "stopped unknown (stopped by uid 1001)"
+        );
+        assert_eq!(
+            container_status(&item(serde_json::json!({ "last_stop_cause": "restart_policy_gave_up", "dataset_missing": true }))),
+            "stopped (restart policy gave up)!"
+        );
+        // A running container's last stop is history
+        assert_eq!(container_status(&item(serde_json::json!({ "state": "running", "last_stop_cause": "daemon_shutdown" }))), "running");
+    }
+
+    #[test]
+    fn test_flatten_config() {
+        let config = serde_json::json!({
+            "zfs_pool": "zroot/kawakaze",
+            "network": {"nat_enabled": true},
+        });
+
+        let mut fields = Vec::new();
+        flatten_config(&config, String::new(), &mut fields);
+        assert!(fields.contains(&("zfs_pool".to_string(), "\"zroot/kawakaze\"".to_string())));
+        assert!(fields.contains(&("network.nat_enabled".to_string(), "true".to_string())));
+    }
+
+    #[test]
+    fn test_override_protection_requires_force() {
+        assert!(Cli::try_parse_from(["kawakaze", "rm", "web", "--override-protection"]).is_err());
+        assert!(Cli::try_parse_from(["kawakaze", "rm", "web", "--force", "--override-protection"]).is_ok());
+        assert!(Cli::try_parse_from(["kawakaze", "update", "web", "--protect", "--unprotect"]).is_err());
+    }
+
+    #[test]
+    fn test_update_read_only_values() {
+        let read_only = |args: &[&str]| match Cli::try_parse_from([&["kawakaze", "update", "web"], args].concat()) {
+            Ok(Cli { command: Commands::Update { read_only, .. }, .. }) => Ok(read_only),
+            Ok(_) => unreachable!(),
+            Err(e) => Err(e.kind()),
+        };
+
+        assert_eq!(read_only(&[]), Ok(None));
+        assert_eq!(read_only(&["--read-only"]), Ok(Some(true)));
+        assert_eq!(read_only(&["--read-only=false"]), Ok(Some(false)));
+        assert!(read_only(&["--read-only=maybe"]).is_err());
+    }
+
+    #[test]
+    fn test_is_intermediate() {
+        let image = |value| serde_json::from_value::<ImageListItem>(value).unwrap();
+        let base = serde_json::json!({ "id": "9a1c", "name": "base", "size_bytes": 0, "created_at": 0 });
+        let mut intermediate = base.clone();
+        intermediate["kind"] = "intermediate".into();
+        assert!(is_intermediate(&image(intermediate)));
+        // Older daemons don't report a kind
+        assert!(!is_intermediate(&image(base)));
+    }
+
+    #[test]
+    fn test_system_orphans_args() {
+        assert!(Cli::try_parse_from(["kawakaze", "system", "orphans"]).is_ok());
+        assert!(Cli::try_parse_from(["kawakaze", "system", "orphans", "--adopt", "tank/k/images/web", "--name", "web"]).is_ok());
+        assert!(Cli::try_parse_from(["kawakaze", "system", "orphans", "--adopt", "a", "--destroy", "a"]).is_err());
+        assert!(Cli::try_parse_from(["kawakaze", "system", "orphans", "--destroy", "a", "--name", "x"]).is_err());
+    }
+
+    #[test]
+    fn test_admin_prune_snapshots_args() {
+        assert!(Cli::try_parse_from(["kawakaze", "admin", "prune-snapshots", "zroot/kawakaze/images/web", "--keep", "2"]).is_ok());
+        // The keep count is required
+        assert!(Cli::try_parse_from(["kawakaze", "admin", "prune-snapshots", "zroot/kawakaze/images/web"]).is_err());
+        assert!(Cli::try_parse_from(["kawakaze", "admin", "snapshot-gc"]).is_ok());
+        assert!(Cli::try_parse_from(["kawakaze", "admin", "snapshot-gc", "--dry-run"]).is_ok());
+        assert!(Cli::try_parse_from(["kawakaze", "admin", "log-level", "debug"]).is_ok());
+        assert!(Cli::try_parse_from(["kawakaze", "admin", "log-level"]).is_err());
+    }
+
+    #[test]
+    fn test_upgrade_args() {
+        assert!(Cli::try_parse_from(["kawakaze", "jail", "upgrade", "web"]).is_ok());
+        assert!(Cli::try_parse_from(["kawakaze", "jail", "upgrade", "web", "--to", "14.2-RELEASE", "--follow"]).is_ok());
+        assert!(Cli::try_parse_from(["kawakaze", "image", "upgrade", "base", "-f"]).is_ok());
+        assert!(Cli::try_parse_from(["kawakaze", "jail", "upgrade"]).is_err());
+    }
+
+    #[test]
+    fn test_image_prune_args() {
+        assert!(Cli::try_parse_from(["kawakaze", "image", "prune"]).is_ok());
+        assert!(Cli::try_parse_from(["kawakaze", "image", "prune", "--all"]).is_ok());
+        assert!(Cli::try_parse_from(["kawakaze", "images", "-a"]).is_ok());
+    }
 }
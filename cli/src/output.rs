@@ -0,0 +1,231 @@
+//! Where command output goes
+//!
+//! Stdout carries results only (IDs, tables, JSON), so `ID=$(kawakaze run
+//! -d img)` works; everything else goes to stderr. Status chatter ("Stopping
+//! container ...") is printed only with `--verbose`. Long operations report
+//! through [`Progress`], which redraws one line on a terminal and prints
+//! timestamped lines otherwise.
+
+use std::fmt::Display;
+use std::io::{IsTerminal, Write};
+use std::sync::OnceLock;
+
+/// Output settings from the global flags
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Output {
+    pub verbose: bool,
+    /// Whether progress is drawn in place rather than printed line by line
+    pub tty: bool,
+}
+
+impl Output {
+    pub fn new(verbose: bool, no_progress: bool) -> Self {
+        Self { verbose, tty: !no_progress && std::io::stderr().is_terminal() }
+    }
+
+    /// Write a status line, if verbose
+    pub fn status(&self, out: &mut impl Write, message: impl Display) {
+        if self.verbose {
+            let _ = writeln!(out, "{}", message);
+        }
+    }
+}
+
+static OUTPUT: OnceLock<Output> = OnceLock::new();
+
+/// Set the settings for the rest of the process; only the first call counts
+pub fn init(output: Output) {
+    let _ = OUTPUT.set(output);
+}
+
+/// Current settings
+pub fn get() -> Output {
+    OUTPUT.get().copied().unwrap_or_default()
+}
+
+/// Print a status line to stderr, if verbose
+pub fn status(message: impl Display) {
+    get().status(&mut std::io::stderr(), message);
+}
+
+/// Print a warning to stderr
+pub fn warn(message: impl Display) {
+    eprintln!("Warning: {}", message);
+}
+
+const SPINNER: &[char] = &['|', '/', '-', '\\'];
+const BAR_WIDTH: usize = 20;
+
+/// Progress of one long operation
+///
+/// On a terminal each update redraws a single line with a spinner, and a
+/// bar when the step count is known; otherwise each change is printed once
+/// with a timestamp. Output lines of the operation itself (RUN output,
+/// freebsd-update) are passed through with [`Progress::output`].
+pub struct Progress<W: Write> {
+    out: W,
+    tty: bool,
+    clock: fn() -> String,
+    /// Last step shown, to print plain lines only on change
+    last: String,
+    /// Progress line as drawn on a terminal, without the spinner
+    line: String,
+    ticks: usize,
+    drawn: bool,
+}
+
+impl Progress<std::io::Stderr> {
+    /// Progress on stderr under the global settings
+    pub fn stderr() -> Self {
+        Self::new(std::io::stderr(), get().tty)
+    }
+}
+
+impl<W: Write> Progress<W> {
+    pub fn new(out: W, tty: bool) -> Self {
+        Self { out, tty, clock: now, last: String::new(), line: String::new(), ticks: 0, drawn: false }
+    }
+
+    /// Use `clock` for the timestamps of plain lines
+    #[cfg(test)]
+    fn with_clock(mut self, clock: fn() -> String) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Show the current step; `step`/`total` draw a bar when `total` is known
+    pub fn update(&mut self, step: usize, total: usize, message: &str) {
+        let line = if total > 0 { format!("{}/{} {}", step, total, message) } else { message.to_string() };
+
+        if self.tty {
+            self.line = if total > 0 { format!("{} {}", bar(step, total), line) } else { line.clone() };
+            self.draw();
+        } else if line != self.last {
+            let _ = writeln!(self.out, "[{}] {}", (self.clock)(), line);
+        }
+
+        let _ = self.out.flush();
+        self.last = line;
+    }
+
+    /// Print a line of the operation's own output above the progress line
+    pub fn output(&mut self, line: &str) {
+        self.clear();
+        let _ = writeln!(self.out, "{}", line);
+        if self.tty && !self.line.is_empty() {
+            self.draw();
+        }
+        let _ = self.out.flush();
+    }
+
+    /// Replace the progress line with a final message
+    pub fn finish(&mut self, message: &str) {
+        self.clear();
+        if self.tty {
+            let _ = writeln!(self.out, "{}", message);
+        } else {
+            let _ = writeln!(self.out, "[{}] {}", (self.clock)(), message);
+        }
+        let _ = self.out.flush();
+    }
+
+    fn draw(&mut self) {
+        self.ticks += 1;
+        let _ = write!(self.out, "\r\x1b[2K{} {}", SPINNER[self.ticks % SPINNER.len()], self.line);
+        self.drawn = true;
+    }
+
+    fn clear(&mut self) {
+        if self.drawn {
+            let _ = write!(self.out, "\r\x1b[2K");
+            self.drawn = false;
+        }
+    }
+}
+
+/// `[=======>            ]` for `step` of `total`
+fn bar(step: usize, total: usize) -> String {
+    let filled = (step.min(total) * BAR_WIDTH) / total.max(1);
+    let head = if filled < BAR_WIDTH && filled > 0 { ">" } else { "" };
+    let filled = if head.is_empty() { filled } else { filled - 1 };
+    format!("[{}{}{}]", "=".repeat(filled), head, " ".repeat(BAR_WIDTH - filled - head.len()))
+}
+
+fn now() -> String {
+    chrono::Local::now().format("%H:%M:%S").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixed_clock() -> String {
+        "12:00:00".to_string()
+    }
+
+    fn rendered(tty: bool, run: impl FnOnce(&mut Progress<&mut Vec<u8>>)) -> String {
+        let mut out = Vec::new();
+        let mut progress = Progress::new(&mut out, tty).with_clock(fixed_clock);
+        run(&mut progress);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_plain_progress_prints_changes_with_timestamps() {
+        let text = rendered(false, |p| {
+            p.update(1, 3, "FROM freebsd");
+            p.update(1, 3, "FROM freebsd");
+            p.output("cc -o app app.c");
+            p.update(2, 3, "RUN make");
+            p.finish("Built web (a1b2c3d4)");
+        });
+
+        assert_eq!(
+            text,
+            "[12:00:00] 1/3 FROM freebsd\ncc -o app app.c\n[12:00:00] 2/3 RUN make\n[12:00:00] Built web (a1b2c3d4)\n"
+        );
+        assert!(!text.contains('\x1b'));
+    }
+
+    #[test]
+    fn test_tty_progress_redraws_one_line() {
+        let text = rendered(true, |p| {
+            p.update(1, 2, "FROM freebsd");
+            p.update(2, 2, "RUN make");
+            p.finish("done");
+        });
+
+        assert_eq!(text.matches('\n').count(), 1, "{:?}", text);
+        assert!(text.contains("\r\x1b[2K/ [=========>          ] 1/2 FROM freebsd"), "{:?}", text);
+        assert!(text.contains("[====================] 2/2 RUN make"), "{:?}", text);
+        assert!(text.ends_with("\r\x1b[2Kdone\n"), "{:?}", text);
+    }
+
+    #[test]
+    fn test_tty_output_lines_go_above_the_progress_line() {
+        let text = rendered(true, |p| {
+            p.update(0, 0, "Fetching updates");
+            p.output("10 patches");
+        });
+
+        // Cleared, printed, then the step redrawn
+        assert!(text.ends_with("\r\x1b[2K10 patches\n\r\x1b[2K- Fetching updates"), "{:?}", text);
+    }
+
+    #[test]
+    fn test_bar() {
+        assert_eq!(bar(0, 4), format!("[{}]", " ".repeat(20)));
+        assert_eq!(bar(1, 4), "[====>               ]");
+        assert_eq!(bar(4, 4), format!("[{}]", "=".repeat(20)));
+        assert_eq!(bar(9, 4), format!("[{}]", "=".repeat(20)));
+    }
+
+    #[test]
+    fn test_status_only_when_verbose() {
+        let mut out = Vec::new();
+        Output { verbose: false, tty: false }.status(&mut out, "Stopping container web...");
+        assert!(out.is_empty());
+        Output { verbose: true, tty: false }.status(&mut out, "Stopping container web...");
+        assert_eq!(out, b"Stopping container web...\n");
+    }
+}
@@ -0,0 +1,155 @@
+//! Errors returned by the client
+
+use std::path::PathBuf;
+
+use kawakaze_backend::api::{ApiError, Response, StatusCode, status};
+use kawakaze_backend::error_codes::{self, Family};
+
+/// Why a request failed
+///
+/// The daemon's refusals are sorted by what a caller usually does about
+/// them; the [`ApiError`] is kept for its code and message.
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    /// The daemon's socket couldn't be reached
+    #[error("Failed to connect to {}: {source}", path.display())]
+    Connect { path: PathBuf, source: std::io::Error },
+
+    /// The connection failed mid-request
+    #[error("Connection to the daemon failed: {0}")]
+    Io(#[from] std::io::Error),
+
+    /// A file to upload couldn't be read
+    #[error("Failed to read {}: {source}", path.display())]
+    File { path: PathBuf, source: std::io::Error },
+
+    /// The daemon sent something that isn't a valid response
+    #[error("Invalid response from the daemon: {0}")]
+    Protocol(String),
+
+    /// 400: the request was malformed or invalid
+    #[error("{0}")]
+    BadRequest(ApiError),
+
+    /// 404: the jail, image, container, build or task doesn't exist
+    #[error("{0}")]
+    NotFound(ApiError),
+
+    /// 409 `RESOURCE_PROTECTED`: the resource is protected against removal
+    #[error("{0}")]
+    Protected(ApiError),
+
+    /// 409: the resource is in the wrong state or the name is taken
+    #[error("{0}")]
+    Conflict(ApiError),
+
+    /// 503: the daemon is shutting down
+    #[error("{0}")]
+    ShuttingDown(ApiError),
+
+    /// Any other failure status
+    #[error("{error} (status {status})")]
+    Server { status: StatusCode, error: ApiError },
+}
+
+impl ClientError {
+    /// Error for a failed response
+    pub fn from_response(response: Response) -> Self {
+        let error = response.error.unwrap_or_else(|| ApiError::new(error_codes::INTERNAL_ERROR, "Unknown error"));
+        match response.status {
+            status::BAD_REQUEST => Self::BadRequest(error),
+            status::NOT_FOUND => Self::NotFound(error),
+            status::CONFLICT if error.code == error_codes::RESOURCE_PROTECTED => Self::Protected(error),
+            status::CONFLICT => Self::Conflict(error),
+            status::SERVICE_UNAVAILABLE => Self::ShuttingDown(error),
+            status => Self::Server { status, error },
+        }
+    }
+
+    /// The daemon's error, if it answered with one
+    pub fn api_error(&self) -> Option<&ApiError> {
+        match self {
+            Self::BadRequest(error)
+            | Self::NotFound(error)
+            | Self::Protected(error)
+            | Self::Conflict(error)
+            | Self::ShuttingDown(error)
+            | Self::Server { error, .. } => Some(error),
+            Self::Connect { .. } | Self::Io(_) | Self::File { .. } | Self::Protocol(_) => None,
+        }
+    }
+
+    /// Whether the daemon answered 404
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, Self::NotFound(_))
+    }
+
+    /// What kind of refusal or failure the daemon answered with, by its
+    /// code and, for a code this client doesn't know, by its status; `None`
+    /// when the daemon didn't answer
+    pub fn family(&self) -> Option<Family> {
+        let status = match self {
+            Self::BadRequest(_) => status::BAD_REQUEST,
+            Self::NotFound(_) => status::NOT_FOUND,
+            Self::Protected(_) | Self::Conflict(_) => status::CONFLICT,
+            Self::ShuttingDown(_) => status::SERVICE_UNAVAILABLE,
+            Self::Server { status, .. } => *status,
+            Self::Connect { .. } | Self::Io(_) | Self::File { .. } | Self::Protocol(_) => return None,
+        };
+        self.api_error()
+            .and_then(|error| error_codes::family(&error.code))
+            .or_else(|| Family::of_status(status))
+    }
+}
+
+impl From<serde_json::Error> for ClientError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Protocol(err.to_string())
+    }
+}
+
+impl From<tokio_util::codec::LinesCodecError> for ClientError {
+    fn from(err: tokio_util::codec::LinesCodecError) -> Self {
+        match err {
+            tokio_util::codec::LinesCodecError::Io(err) => Self::Io(err),
+            err => Self::Protocol(err.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_response() {
+        assert!(ClientError::from_response(Response::not_found("Container 'web'")).is_not_found());
+        assert!(matches!(ClientError::from_response(Response::bad_request("bad")), ClientError::BadRequest(_)));
+        assert!(matches!(ClientError::from_response(Response::conflict("busy")), ClientError::Conflict(_)));
+
+        let protected = Response::error(status::CONFLICT, ApiError::ResourceProtected("web is protected".to_string()));
+        assert!(matches!(ClientError::from_response(protected), ClientError::Protected(_)));
+        let shutdown = Response::error(status::SERVICE_UNAVAILABLE, ApiError::ShuttingDown());
+        assert!(matches!(ClientError::from_response(shutdown), ClientError::ShuttingDown(_)));
+
+        let err = ClientError::from_response(Response::internal_error("boom"));
+        assert!(matches!(err, ClientError::Server { status: 500, .. }));
+        assert_eq!(err.api_error().unwrap().code, "INTERNAL_ERROR");
+        assert_eq!(err.to_string(), "[INTERNAL_ERROR] boom (status 500)");
+    }
+
+    #[test]
+    fn test_family() {
+        let family = |response| ClientError::from_response(response).family();
+        assert_eq!(family(Response::container_not_found("web")), Some(Family::NotFound));
+        assert_eq!(family(Response::invalid_body("missing field")), Some(Family::Invalid));
+        assert_eq!(family(Response::forbidden("no")), Some(Family::Invalid));
+        assert_eq!(family(Response::coded(error_codes::NAME_IN_USE, "taken")), Some(Family::Conflict));
+        assert_eq!(family(Response::error(status::SERVICE_UNAVAILABLE, ApiError::ShuttingDown())), Some(Family::Unavailable));
+        assert_eq!(family(Response::coded(error_codes::START_FAILED, "boom")), Some(Family::Internal));
+
+        // A code from a newer daemon falls back on the status
+        assert_eq!(family(Response::error(status::CONFLICT, ApiError::new("SOMETHING_NEW", "?"))), Some(Family::Conflict));
+        assert_eq!(ClientError::Protocol("eof".to_string()).family(), None);
+    }
+}
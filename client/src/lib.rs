@@ -0,0 +1,856 @@
+//! Client library for the kawakaze daemon
+//!
+//! [`Client`] talks to `kawakaze-backend` over its Unix socket with one
+//! typed method per API endpoint. Requests and responses are the types in
+//! [`types`]; failures are [`ClientError`]s sorted by the daemon's status
+//! and error code.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), kawakaze_client::ClientError> {
+//! let client = kawakaze_client::Client::connect(kawakaze_client::default_socket_path()).await?;
+//! for container in client.list_containers().await? {
+//!     println!("{} {}", container.id, container.state);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The daemon serves one request at a time per connection, so the client
+//! keeps a small pool of idle connections and opens another when all are
+//! busy; concurrent calls on one `Client` don't wait for each other. A
+//! pooled connection the daemon closed (it restarted, say) is noticed on
+//! the next request, which is retried once on a new connection. Streams
+//! ([`Client::follow_logs`], [`Client::follow_build_log`]) get a connection
+//! of their own, closed when the stream is dropped.
+
+mod error;
+pub mod types;
+
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+
+use futures::{SinkExt, Stream, StreamExt};
+use kawakaze_backend::api::{Endpoint, Request, Response};
+use kawakaze_backend::upload::MAX_CHUNK_BYTES;
+use serde::de::DeserializeOwned;
+use tokio::net::UnixStream;
+use tokio_util::codec::{Framed, LinesCodec};
+
+pub use error::ClientError;
+use types::*;
+
+/// Result of a client call
+pub type Result<T> = std::result::Result<T, ClientError>;
+
+/// Idle connections kept for reuse unless [`Client::with_max_idle`] says otherwise
+const DEFAULT_MAX_IDLE: usize = 4;
+
+/// Failed chunks in a row after which [`Client::resume_upload`] gives up
+pub const UPLOAD_RETRIES: usize = 5;
+
+type Connection = Framed<UnixStream, LinesCodec>;
+
+/// Socket of the local daemon, from the same config file it reads
+pub fn default_socket_path() -> PathBuf {
+    let config = kawakaze_backend::config::KawakazeConfig::load_defaults().unwrap_or_default();
+    kawakaze_backend::paths::Paths::from_config(&config).socket
+}
+
+/// Connection to a kawakaze daemon
+pub struct Client {
+    path: PathBuf,
+    idle: Mutex<Vec<Connection>>,
+    max_idle: usize,
+}
+
+/// How one exchange on a connection went wrong
+enum ExchangeError {
+    /// The connection was closed before the daemon answered
+    Closed,
+    Failed(ClientError),
+}
+
+impl Client {
+    /// Connect to the daemon listening on `path`
+    ///
+    /// The first connection is opened right away, so a daemon that isn't
+    /// running is reported here rather than on the first call.
+    pub async fn connect(path: impl Into<PathBuf>) -> Result<Self> {
+        let client = Self { path: path.into(), idle: Mutex::new(Vec::new()), max_idle: DEFAULT_MAX_IDLE };
+        let connection = client.open().await?;
+        client.checkin(connection);
+        Ok(client)
+    }
+
+    /// Keep at most `max_idle` idle connections (0 closes each after use)
+    pub fn with_max_idle(mut self, max_idle: usize) -> Self {
+        self.max_idle = max_idle;
+        self.idle.get_mut().unwrap_or_else(|e| e.into_inner()).truncate(max_idle);
+        self
+    }
+
+    /// Socket the client connects to
+    pub fn socket_path(&self) -> &Path {
+        &self.path
+    }
+
+    async fn open(&self) -> Result<Connection> {
+        let stream = UnixStream::connect(&self.path)
+            .await
+            .map_err(|source| ClientError::Connect { path: self.path.clone(), source })?;
+        Ok(Framed::new(stream, LinesCodec::new()))
+    }
+
+    fn checkout(&self) -> Option<Connection> {
+        self.idle.lock().unwrap_or_else(|e| e.into_inner()).pop()
+    }
+
+    fn checkin(&self, connection: Connection) {
+        let mut idle = self.idle.lock().unwrap_or_else(|e| e.into_inner());
+        if idle.len() < self.max_idle {
+            idle.push(connection);
+        }
+    }
+
+    /// Send a request and read its response, whatever its status
+    ///
+    /// This is what the typed methods are built on; it also reaches
+    /// endpoints newer than this client.
+    pub async fn send(&self, request: &Request) -> Result<Response> {
+        let line = serde_json::to_string(request)?;
+        if let Some(mut connection) = self.checkout() {
+            match exchange(&mut connection, &line).await {
+                Ok(response) => {
+                    self.checkin(connection);
+                    return Ok(response);
+                }
+                // Closed while idle: try again on a new connection
+                Err(ExchangeError::Closed) => {}
+                Err(ExchangeError::Failed(err)) => return Err(err),
+            }
+        }
+
+        let mut connection = self.open().await?;
+        match exchange(&mut connection, &line).await {
+            Ok(response) => {
+                self.checkin(connection);
+                Ok(response)
+            }
+            Err(ExchangeError::Closed) => Err(ClientError::Protocol("connection closed without a response".to_string())),
+            Err(ExchangeError::Failed(err)) => Err(err),
+        }
+    }
+
+    /// Send a request and decode the data of a successful response
+    async fn call<T: DeserializeOwned>(&self, request: Request) -> Result<T> {
+        let response = self.send(&request).await?;
+        if !response.is_success() {
+            return Err(ClientError::from_response(response));
+        }
+        Ok(serde_json::from_value(response.data.unwrap_or_default())?)
+    }
+
+    /// Start a streaming request on a connection of its own
+    async fn stream<T: DeserializeOwned + Send + 'static>(&self, request: Request) -> Result<ResponseStream<T>> {
+        let mut connection = self.open().await?;
+        connection.send(serde_json::to_string(&request)?).await?;
+        Ok(ResponseStream::new(connection))
+    }
+
+    // ------------------------------------------------------------------
+    // Jails
+    // ------------------------------------------------------------------
+
+    /// `GET /jails`
+    pub async fn list_jails(&self) -> Result<Vec<JailListItem>> {
+        self.call(Request::get(Endpoint::Jails)).await
+    }
+
+    /// `GET /jails`, only those matching every `key=value` or
+    /// `key~=value` filter
+    pub async fn list_jails_matching(&self, filter: &[String]) -> Result<Vec<JailListItem>> {
+        self.call(Request::get_with(Endpoint::Jails, ListJailsRequest { filter: filter.to_vec() })?).await
+    }
+
+    /// `GET /jails/{name}`
+    pub async fn jail(&self, name: &str) -> Result<JailInfo> {
+        self.call(Request::get(Endpoint::Jail(name.to_string()))).await
+    }
+
+    /// `POST /jails`
+    pub async fn create_jail(&self, request: &CreateJailRequest) -> Result<JailInfo> {
+        self.call(Request::post(Endpoint::Jails, request)?).await
+    }
+
+    /// `POST /jails/{name}/start`
+    pub async fn start_jail(&self, name: &str) -> Result<JailInfo> {
+        self.call(Request::post(Endpoint::StartJail(name.to_string()), ())?).await
+    }
+
+    /// `POST /jails/{name}/stop`
+    pub async fn stop_jail(&self, name: &str, stop_mode: StopMode) -> Result<JailInfo> {
+        self.call(Request::post(Endpoint::StopJail(name.to_string()), StopRequest { stop_mode })?).await
+    }
+
+    /// `DELETE /jails/{name}`
+    pub async fn delete_jail(&self, name: &str) -> Result<MessageResult> {
+        self.call(Request::delete(Endpoint::Jail(name.to_string()))).await
+    }
+
+    /// `POST /jails/{name}/bootstrap`; follow it with [`Client::bootstrap_status`]
+    pub async fn bootstrap_jail(&self, name: &str, request: &BootstrapRequest) -> Result<()> {
+        self.call::<serde_json::Value>(Request::post(Endpoint::BootstrapJail(name.to_string()), request)?).await?;
+        Ok(())
+    }
+
+    /// `GET /jails/{name}/bootstrap/status`
+    pub async fn bootstrap_status(&self, name: &str) -> Result<BootstrapProgress> {
+        self.call(Request::get(Endpoint::BootstrapStatus(name.to_string()))).await
+    }
+
+    /// `GET /jails/{name}/bootstrap/log`
+    pub async fn bootstrap_log(&self, name: &str, options: &LogsRequest) -> Result<Vec<LogEntry>> {
+        self.call(Request::get_with(Endpoint::BootstrapLog(name.to_string()), options)?).await
+    }
+
+    /// `POST /jails/{name}/upgrade`
+    pub async fn upgrade_jail(&self, name: &str, request: &UpgradeRequest) -> Result<UpgradeStarted> {
+        self.call(Request::post(Endpoint::JailUpgrade(name.to_string()), request)?).await
+    }
+
+    /// `GET /jails/{name}/upgrade/status`
+    pub async fn jail_upgrade_status(&self, name: &str) -> Result<UpgradeProgress> {
+        self.call(Request::get(Endpoint::JailUpgradeStatus(name.to_string()))).await
+    }
+
+    /// `POST /jails/{name}/update`
+    pub async fn update_jail(&self, name: &str, request: &JailUpdateRequest) -> Result<JailInfo> {
+        self.call(Request::post(Endpoint::JailUpdate(name.to_string()), request)?).await
+    }
+
+    // ------------------------------------------------------------------
+    // Images
+    // ------------------------------------------------------------------
+
+    /// `GET /images`
+    pub async fn list_images(&self) -> Result<Vec<ImageListItem>> {
+        self.call(Request::get(Endpoint::Images)).await
+    }
+
+    /// `GET /images/{id}`
+    pub async fn image(&self, id_or_name: &str) -> Result<ImageInfo> {
+        self.call(Request::get(Endpoint::Image(id_or_name.to_string()))).await
+    }
+
+    /// `POST /images/build`; unless `cached`, follow it with [`Client::build_status`]
+    pub async fn build_image(&self, request: &BuildImageRequest) -> Result<BuildStarted> {
+        self.call(Request::post(Endpoint::ImageBuild, request)?).await
+    }
+
+    /// `GET /images/build/{id}`
+    pub async fn build_status(&self, build_id: &str) -> Result<ImageBuildProgress> {
+        self.call(Request::get(Endpoint::ImageBuildStatus(build_id.to_string()))).await
+    }
+
+    /// `DELETE /images/{id}`
+    pub async fn delete_image(&self, id_or_name: &str, options: &RemoveRequest) -> Result<MessageResult> {
+        self.call(Request::delete_with(Endpoint::DeleteImage(id_or_name.to_string()), options)?).await
+    }
+
+    /// `GET /images/{id}/history`
+    pub async fn image_history(&self, id_or_name: &str) -> Result<Vec<ImageHistoryItem>> {
+        self.call(Request::get(Endpoint::ImageHistory(id_or_name.to_string()))).await
+    }
+
+    /// `GET /images/{id}/containers`
+    pub async fn image_containers(&self, id_or_name: &str) -> Result<Vec<ContainerListItem>> {
+        self.call(Request::get(Endpoint::ImageContainers(id_or_name.to_string()))).await
+    }
+
+    /// `GET /images/{id}/build-log`
+    pub async fn build_log(&self, id_or_name: &str, options: &LogsRequest) -> Result<Vec<LogEntry>> {
+        self.call(Request::get_with(Endpoint::ImageBuildLog(id_or_name.to_string()), options)?).await
+    }
+
+    /// `GET /images/{id}/build-log/follow`: the log, then new lines until
+    /// the build ends
+    pub async fn follow_build_log(&self, id_or_name: &str, options: &LogsRequest) -> Result<LogStream> {
+        self.stream(Request::get_with(Endpoint::ImageBuildLogFollow(id_or_name.to_string()), options)?).await
+    }
+
+    /// `POST /images/{id}/update`
+    pub async fn update_image(&self, id_or_name: &str, request: &UpdateRequest) -> Result<ImageInfo> {
+        self.call(Request::post(Endpoint::UpdateImage(id_or_name.to_string()), request)?).await
+    }
+
+    /// `POST /images/prune`
+    pub async fn prune_images(&self, request: &PruneImagesRequest) -> Result<PruneImagesResult> {
+        self.call(Request::post(Endpoint::PruneImages, request)?).await
+    }
+
+    /// `POST /images/{id}/upgrade`
+    pub async fn upgrade_image(&self, id_or_name: &str, request: &UpgradeRequest) -> Result<UpgradeStarted> {
+        self.call(Request::post(Endpoint::ImageUpgrade(id_or_name.to_string()), request)?).await
+    }
+
+    /// `GET /images/{id}/upgrade/status`
+    pub async fn image_upgrade_status(&self, id_or_name: &str) -> Result<UpgradeProgress> {
+        self.call(Request::get(Endpoint::ImageUpgradeStatus(id_or_name.to_string()))).await
+    }
+
+    // ------------------------------------------------------------------
+    // Containers
+    // ------------------------------------------------------------------
+
+    /// `GET /containers`
+    pub async fn list_containers(&self) -> Result<Vec<ContainerListItem>> {
+        self.call(Request::get(Endpoint::Containers)).await
+    }
+
+    /// `GET /containers` with per-state counts
+    pub async fn list_containers_with_summary(&self) -> Result<ContainerListWithSummary> {
+        self.call(Request::get_with(Endpoint::Containers, ListContainersRequest { include_summary: true, filter: Vec::new() })?).await
+    }
+
+    /// `GET /containers`, only those matching every `key=value` or
+    /// `key~=value` filter
+    pub async fn list_containers_matching(&self, filter: &[String]) -> Result<Vec<ContainerListItem>> {
+        let request = ListContainersRequest { include_summary: false, filter: filter.to_vec() };
+        self.call(Request::get_with(Endpoint::Containers, request)?).await
+    }
+
+    /// `GET /containers/{id}`
+    pub async fn container(&self, id_or_name: &str) -> Result<ContainerInfo> {
+        self.call(Request::get(Endpoint::Container(id_or_name.to_string()))).await
+    }
+
+    /// `POST /containers/create`
+    pub async fn create_container(&self, request: &CreateContainerRequest) -> Result<CreatedContainer> {
+        self.call(Request::post(Endpoint::ContainerCreate, request)?).await
+    }
+
+    /// `POST /containers/batch`
+    pub async fn create_containers(&self, request: &BatchCreateRequest) -> Result<BatchCreated> {
+        self.call(Request::post(Endpoint::ContainerBatchCreate, request)?).await
+    }
+
+    /// `POST /containers/create` with `async` set, whatever the request
+    /// says; follow it with [`Client::operation`]
+    pub async fn create_container_async(&self, request: &CreateContainerRequest) -> Result<OperationStarted> {
+        let mut body = serde_json::to_value(request)?;
+        body["async"] = true.into();
+        self.call(Request::post(Endpoint::ContainerCreate, body)?).await
+    }
+
+    /// `POST /containers/{id}/start`
+    pub async fn start_container(&self, id_or_name: &str) -> Result<ContainerInfo> {
+        self.call(Request::post(Endpoint::StartContainer(id_or_name.to_string()), ())?).await
+    }
+
+    /// `POST /containers/{id}/stop`
+    pub async fn stop_container(&self, id_or_name: &str, stop_mode: StopMode) -> Result<ContainerInfo> {
+        self.call(Request::post(Endpoint::StopContainer(id_or_name.to_string()), StopRequest { stop_mode })?).await
+    }
+
+    /// `DELETE /containers/{id}`
+    pub async fn remove_container(&self, id_or_name: &str, options: &RemoveRequest) -> Result<MessageResult> {
+        self.call(Request::delete_with(Endpoint::RemoveContainer(id_or_name.to_string()), options)?).await
+    }
+
+    /// `DELETE /containers/{id}` with `async` set; follow it with
+    /// [`Client::operation`]
+    pub async fn remove_container_async(&self, id_or_name: &str, options: &RemoveRequest) -> Result<OperationStarted> {
+        let options = RemoveRequest { run_async: true, ..*options };
+        self.call(Request::delete_with(Endpoint::RemoveContainer(id_or_name.to_string()), options)?).await
+    }
+
+    /// `GET /operations/{id}`
+    pub async fn operation(&self, id: &str) -> Result<OperationProgress> {
+        self.call(Request::get(Endpoint::Operation(id.to_string()))).await
+    }
+
+    /// `GET /containers/{id}/logs`
+    pub async fn logs(&self, id_or_name: &str, options: &LogsRequest) -> Result<Vec<LogEntry>> {
+        self.call(Request::get_with(Endpoint::ContainerLogs(id_or_name.to_string()), options)?).await
+    }
+
+    /// `GET /containers/{id}/logs/follow`: the log, then new lines while the
+    /// container runs
+    pub async fn follow_logs(&self, id_or_name: &str, options: &LogsRequest) -> Result<LogStream> {
+        self.stream(Request::get_with(Endpoint::ContainerLogsFollow(id_or_name.to_string()), options)?).await
+    }
+
+    /// `POST /containers/{id}/exec`
+    pub async fn exec(&self, id_or_name: &str, request: &ExecRequest) -> Result<ExecResult> {
+        self.call(Request::post(Endpoint::ContainerExec(id_or_name.to_string()), request)?).await
+    }
+
+    /// `GET /containers/{id}/top`
+    pub async fn top(&self, id_or_name: &str) -> Result<Vec<ProcessInfo>> {
+        self.call(Request::get(Endpoint::ContainerTop(id_or_name.to_string()))).await
+    }
+
+    /// `GET /containers/{id}/exec-sessions`
+    pub async fn container_exec_sessions(&self, id_or_name: &str) -> Result<Vec<ExecSession>> {
+        self.call(Request::get(Endpoint::ContainerExecSessions(id_or_name.to_string()))).await
+    }
+
+    /// `GET /exec-sessions`
+    pub async fn exec_sessions(&self) -> Result<Vec<ExecSession>> {
+        self.call(Request::get(Endpoint::ExecSessions)).await
+    }
+
+    /// `POST /exec-sessions`: register a session run on this side, such as
+    /// an interactive shell
+    pub async fn register_exec_session(&self, request: &ExecSessionRequest) -> Result<ExecSession> {
+        self.call(Request::post(Endpoint::ExecSessions, request)?).await
+    }
+
+    /// `DELETE /exec-sessions/{id}`: the registered session's command exited
+    pub async fn end_exec_session(&self, id: &str) -> Result<MessageResult> {
+        self.call(Request::delete(Endpoint::EndExecSession(id.to_string()))).await
+    }
+
+    /// `POST /exec-sessions/{id}/kill`
+    pub async fn kill_exec_session(&self, id: &str) -> Result<ExecSessionKilled> {
+        self.call(Request::post(Endpoint::ExecSessionKill(id.to_string()), ())?).await
+    }
+
+    /// `POST /containers/{id}/update`
+    pub async fn update_container(&self, id_or_name: &str, request: &UpdateRequest) -> Result<ContainerInfo> {
+        self.call(Request::post(Endpoint::UpdateContainer(id_or_name.to_string()), request)?).await
+    }
+
+    /// `POST /containers/{id}/rename`
+    pub async fn rename_container(&self, id_or_name: &str, name: &str) -> Result<ContainerInfo> {
+        let request = RenameRequest { name: name.to_string() };
+        self.call(Request::post(Endpoint::RenameContainer(id_or_name.to_string()), &request)?).await
+    }
+
+    /// `POST /containers/{id}/ips`
+    pub async fn add_container_ip(&self, id_or_name: &str, ip: &str) -> Result<ContainerInfo> {
+        let request = ContainerIpRequest { ip: ip.to_string() };
+        self.call(Request::post(Endpoint::AddContainerIp(id_or_name.to_string()), &request)?).await
+    }
+
+    /// `DELETE /containers/{id}/ips`
+    pub async fn remove_container_ip(&self, id_or_name: &str, ip: &str) -> Result<ContainerInfo> {
+        let request = ContainerIpRequest { ip: ip.to_string() };
+        self.call(Request::delete_with(Endpoint::RemoveContainerIp(id_or_name.to_string()), &request)?).await
+    }
+
+    // ------------------------------------------------------------------
+    // Uploads
+    // ------------------------------------------------------------------
+
+    /// `POST /uploads`
+    pub async fn create_upload(&self, request: &UploadRequest) -> Result<UploadStatus> {
+        self.call(Request::post(Endpoint::UploadCreate, request)?).await
+    }
+
+    /// `GET /uploads/{id}`
+    pub async fn upload_status(&self, id: &str) -> Result<UploadStatus> {
+        self.call(Request::get(Endpoint::UploadStatus(id.to_string()))).await
+    }
+
+    /// `POST /uploads/{id}/chunk`
+    pub async fn upload_chunk(&self, id: &str, chunk: &UploadChunk) -> Result<UploadStatus> {
+        self.call(Request::post(Endpoint::UploadChunk(id.to_string()), chunk)?).await
+    }
+
+    /// `POST /uploads/{id}/commit`
+    pub async fn commit_upload(&self, id: &str) -> Result<UploadStatus> {
+        self.call(Request::post(Endpoint::UploadCommit(id.to_string()), ())?).await
+    }
+
+    /// `DELETE /uploads/{id}`
+    pub async fn abort_upload(&self, id: &str) -> Result<MessageResult> {
+        self.call(Request::delete(Endpoint::UploadAbort(id.to_string()))).await
+    }
+
+    /// Upload the file at `path` in chunks and commit it, calling
+    /// `progress` with the bytes received and the total after each chunk
+    ///
+    /// See [`Client::resume_upload`] for what happens when a chunk fails.
+    /// The returned upload is committed; its ID is what the consumer for
+    /// `purpose` takes.
+    pub async fn upload_file(&self, path: &Path, purpose: UploadPurpose, progress: impl FnMut(u64, u64)) -> Result<UploadStatus> {
+        let (size, sha256) = hash_file(path).await?;
+        let upload = self.create_upload(&UploadRequest { purpose, size, sha256 }).await?;
+        self.resume_upload(path, &upload.id, progress).await
+    }
+
+    /// Send the rest of upload `id` from the file at `path`, starting where
+    /// the daemon says it is, and commit it
+    ///
+    /// A chunk that is refused (409, another client moved the upload on)
+    /// or lost with the connection is not an error: the daemon is asked
+    /// where the upload is and sending carries on from there. Only
+    /// [`UPLOAD_RETRIES`] such failures in a row give up.
+    pub async fn resume_upload(&self, path: &Path, id: &str, mut progress: impl FnMut(u64, u64)) -> Result<UploadStatus> {
+        use base64::Engine;
+        use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+        let file_error = |source| ClientError::File { path: path.to_path_buf(), source };
+        let mut file = tokio::fs::File::open(path).await.map_err(file_error)?;
+        let mut upload = self.upload_status(id).await?;
+        let mut buf = vec![0; MAX_CHUNK_BYTES];
+        let mut failures = 0;
+
+        while upload.received < upload.size {
+            let len = (upload.size - upload.received).min(MAX_CHUNK_BYTES as u64) as usize;
+            file.seek(std::io::SeekFrom::Start(upload.received)).await.map_err(file_error)?;
+            file.read_exact(&mut buf[..len]).await.map_err(file_error)?;
+            let chunk = UploadChunk { offset: upload.received, data: base64::engine::general_purpose::STANDARD.encode(&buf[..len]) };
+
+            match self.upload_chunk(id, &chunk).await {
+                Ok(status) => {
+                    upload = status;
+                    failures = 0;
+                }
+                Err(ClientError::Conflict(_) | ClientError::Io(_) | ClientError::Protocol(_)) if failures < UPLOAD_RETRIES => {
+                    failures += 1;
+                    upload = self.upload_status(id).await?;
+                }
+                Err(err) => return Err(err),
+            }
+            progress(upload.received, upload.size);
+        }
+
+        self.commit_upload(id).await
+    }
+
+    // ------------------------------------------------------------------
+    // System
+    // ------------------------------------------------------------------
+
+    /// `GET /system/config`
+    pub async fn system_config(&self) -> Result<SystemConfigInfo> {
+        self.call(Request::get(Endpoint::SystemConfig)).await
+    }
+
+    /// `GET /system/info`
+    pub async fn system_info(&self) -> Result<SystemInfo> {
+        self.call(Request::get(Endpoint::SystemInfo)).await
+    }
+
+    /// `GET /system/doctor`
+    pub async fn doctor(&self) -> Result<DoctorReport> {
+        self.call(Request::get(Endpoint::SystemDoctor)).await
+    }
+
+    /// `GET /system/boot`
+    pub async fn boot_list(&self) -> Result<BootList> {
+        self.call(Request::get(Endpoint::SystemBootList)).await
+    }
+
+    /// `GET /system/tasks`
+    pub async fn tasks(&self) -> Result<Vec<TaskInfo>> {
+        self.call(Request::get(Endpoint::SystemTasks)).await
+    }
+
+    /// `GET /system/names`: names, then short IDs, for shell completion
+    pub async fn names(&self, kind: NameKind) -> Result<Vec<String>> {
+        self.call(Request::get_with(Endpoint::SystemNames, NamesRequest { kind })?).await
+    }
+
+    /// `POST /system/tasks/{id}/cancel`
+    pub async fn cancel_task(&self, id: &str) -> Result<MessageResult> {
+        self.call(Request::post(Endpoint::CancelTask(id.to_string()), ())?).await
+    }
+
+    /// `GET /system/orphans`
+    pub async fn orphans(&self) -> Result<Vec<OrphanInfo>> {
+        self.call(Request::get(Endpoint::SystemOrphans)).await
+    }
+
+    /// `POST /system/orphans/adopt`
+    pub async fn adopt_orphan(&self, request: &AdoptOrphanRequest) -> Result<AdoptedOrphan> {
+        self.call(Request::post(Endpoint::OrphanAdopt, request)?).await
+    }
+
+    /// `POST /system/orphans/destroy`
+    pub async fn destroy_orphan(&self, request: &DestroyOrphanRequest) -> Result<MessageResult> {
+        self.call(Request::post(Endpoint::OrphanDestroy, request)?).await
+    }
+
+    /// `POST /system/snapshots/prune`
+    pub async fn prune_snapshots(&self, request: &PruneSnapshotsRequest) -> Result<PruneSnapshotsResult> {
+        self.call(Request::post(Endpoint::PruneSnapshots, request)?).await
+    }
+
+    /// `POST /system/snapshots/gc`
+    pub async fn snapshot_gc(&self, dry_run: bool) -> Result<SnapshotGcReport> {
+        self.call(Request::post(Endpoint::SystemSnapshotGc, SnapshotGcRequest { dry_run })?).await
+    }
+
+    /// `POST /system/log-level`
+    pub async fn set_log_level(&self, level: &str) -> Result<LogLevelResult> {
+        self.call(Request::post(Endpoint::SetLogLevel, LogLevelRequest { level: level.to_string() })?).await
+    }
+
+    /// `GET /system/commands`
+    pub async fn command_metrics(&self) -> Result<CommandMetricsInfo> {
+        self.call(Request::get(Endpoint::CommandMetrics)).await
+    }
+
+    /// `GET /system/api-schema`
+    pub async fn api_schema(&self) -> Result<ApiDescription> {
+        self.call(Request::get(Endpoint::ApiSchema)).await
+    }
+}
+
+/// Size and hex SHA-256 of the file at `path`
+async fn hash_file(path: &Path) -> Result<(u64, String)> {
+    use sha2::{Digest, Sha256};
+    use tokio::io::AsyncReadExt;
+
+    let file_error = |source| ClientError::File { path: path.to_path_buf(), source };
+    let mut file = tokio::fs::File::open(path).await.map_err(file_error)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; MAX_CHUNK_BYTES];
+    let mut size = 0;
+    loop {
+        let read = file.read(&mut buf).await.map_err(file_error)?;
+        if read == 0 {
+            return Ok((size, hex::encode(hasher.finalize())));
+        }
+        hasher.update(&buf[..read]);
+        size += read as u64;
+    }
+}
+
+/// Send `line` on `connection` and read the response to it
+async fn exchange(connection: &mut Connection, line: &str) -> std::result::Result<Response, ExchangeError> {
+    if connection.send(line).await.is_err() {
+        return Err(ExchangeError::Closed);
+    }
+    match connection.next().await {
+        None => Err(ExchangeError::Closed),
+        Some(Err(err)) => Err(ExchangeError::Failed(err.into())),
+        Some(Ok(reply)) => serde_json::from_str(&reply).map_err(|err| ExchangeError::Failed(err.into())),
+    }
+}
+
+/// Frames of a streaming response, ending with the stream
+///
+/// A stream the daemon refuses (an unknown container, say) yields that
+/// error as its only item; one that ends with an error (daemon shutdown)
+/// yields it last.
+pub struct ResponseStream<T> {
+    inner: Pin<Box<dyn Stream<Item = Result<T>> + Send>>,
+}
+
+/// Log lines of a followed container or build log
+pub type LogStream = ResponseStream<LogEntry>;
+
+impl<T: DeserializeOwned + Send + 'static> ResponseStream<T> {
+    fn new(connection: Connection) -> Self {
+        let frames = futures::stream::unfold(Some(connection), |connection| async move {
+            let mut connection = connection?;
+            let item = match connection.next().await {
+                None => Err(ClientError::Protocol("stream ended without a terminal frame".to_string())),
+                Some(line) => line
+                    .map_err(ClientError::from)
+                    .and_then(|line| Ok(serde_json::from_str::<Response>(&line)?))
+                    .and_then(stream_frame),
+            };
+            match item {
+                Ok(Some(data)) => Some((Ok(data), Some(connection))),
+                Ok(None) => None,
+                Err(err) => Some((Err(err), None)),
+            }
+        });
+        Self { inner: Box::pin(frames) }
+    }
+}
+
+impl<T> Stream for ResponseStream<T> {
+    type Item = Result<T>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.as_mut().poll_next(cx)
+    }
+}
+
+/// Data of one frame of a stream, `None` once it ended normally. A refused
+/// stream is answered with a plain error response instead.
+fn stream_frame<T: DeserializeOwned>(response: Response) -> Result<Option<T>> {
+    if !response.is_success() {
+        return Err(ClientError::from_response(response));
+    }
+    if response.end || !response.stream {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_value(response.data.unwrap_or_default())?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use kawakaze_backend::JailManager;
+    use kawakaze_backend::api::{ApiError, status};
+    use kawakaze_backend::server::SocketServer;
+    use serde_json::Value;
+
+    #[test]
+    fn test_stream_frame() {
+        assert_eq!(stream_frame::<String>(Response::stream_frame("one").unwrap()).unwrap(), Some("one".to_string()));
+        assert_eq!(stream_frame::<String>(Response::stream_end(Ok(()))).unwrap(), None);
+        let shutdown = Response::stream_end(Err(Response::error(status::SERVICE_UNAVAILABLE, ApiError::ShuttingDown())));
+        assert!(matches!(stream_frame::<Value>(shutdown), Err(ClientError::ShuttingDown(_))));
+        assert!(stream_frame::<Value>(Response::not_found("Container 'web'")).unwrap_err().is_not_found());
+    }
+
+    /// A daemon on a temporary socket, with a database but no ZFS or jails
+    async fn serve(dir: &Path) -> (PathBuf, tokio::task::JoinHandle<()>) {
+        let socket = dir.join("kawakaze.sock");
+        let mut manager = JailManager::with_database(dir.join("kawakaze.db")).unwrap();
+        let limits = kawakaze_backend::upload::UploadLimits {
+            max_upload_bytes: 16 * 1024 * 1024,
+            max_spool_bytes: 16 * 1024 * 1024,
+            idle_timeout: std::time::Duration::from_secs(60),
+        };
+        manager.set_uploads(kawakaze_backend::upload::Uploads::new(dir.join("uploads"), limits));
+        let manager = Arc::new(tokio::sync::Mutex::new(manager));
+        let server = SocketServer::new(Arc::new(socket.to_string_lossy().into_owned()), manager);
+        let task = tokio::spawn(async move {
+            let _ = server.run().await.map_err(|e| e.to_string());
+        });
+        while !socket.exists() {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        (socket, task)
+    }
+
+    #[tokio::test]
+    async fn test_client_against_daemon() {
+        let dir = tempfile::tempdir().unwrap();
+        let (socket, task) = serve(dir.path()).await;
+        let client = Client::connect(&socket).await.unwrap();
+
+        assert!(client.list_containers().await.unwrap().is_empty());
+        let listed = client.list_containers_with_summary().await.unwrap();
+        assert_eq!(listed.summary.total, 0);
+        assert!(client.list_images().await.unwrap().is_empty());
+        assert!(client.tasks().await.unwrap().is_empty());
+
+        // Refusals keep their kind
+        let err = client.container("nope").await.unwrap_err();
+        assert!(err.is_not_found(), "{:?}", err);
+        let request = CreateContainerRequest {
+            image_id: "missing".to_string(),
+            name: None,
+            ports: Vec::new(),
+            volumes: Vec::new(),
+            tmpfs: Vec::new(),
+            env: Default::default(),
+            restart_policy: "no".to_string(),
+            command: None,
+            protected: false,
+            devfs_ruleset: None,
+            disable_healthcheck: false,
+            boot: false,
+            encrypted: false,
+            read_only_root: false,
+            network_aliases: Vec::new(),
+            hostname: None,
+            mount_permissions: Default::default(),
+            run_async: false,
+            depends_on: Vec::new(),
+            placement: None,
+            sysctls: Default::default(),
+            description: None,
+        };
+        assert!(client.create_container(&request).await.unwrap_err().is_not_found());
+
+        // Concurrent calls each get a connection; they go back to the pool
+        let (a, b, c) = tokio::join!(client.list_jails(), client.list_images(), client.tasks());
+        assert!(a.is_ok() && b.is_ok() && c.is_ok());
+        assert!(client.idle.lock().unwrap().len() >= 2);
+
+        // A refused stream reports why as its only item
+        let mut logs = client.follow_logs("nope", &LogsRequest::default()).await.unwrap();
+        assert!(logs.next().await.unwrap().unwrap_err().is_not_found());
+        assert!(logs.next().await.is_none());
+
+        task.abort();
+    }
+
+    #[tokio::test]
+    async fn test_upload_file_resumes() {
+        let dir = tempfile::tempdir().unwrap();
+        let (socket, task) = serve(dir.path()).await;
+        let client = Client::connect(&socket).await.unwrap();
+
+        let path = dir.path().join("context.tar");
+        let contents: Vec<u8> = (0..MAX_CHUNK_BYTES * 2 + 100).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&path, &contents).unwrap();
+
+        let mut reported = Vec::new();
+        let upload = client.upload_file(&path, UploadPurpose::BuildContext, |received, _| reported.push(received)).await.unwrap();
+        assert_eq!(upload.state, UploadState::Committed);
+        assert_eq!(reported, [MAX_CHUNK_BYTES as u64, MAX_CHUNK_BYTES as u64 * 2, contents.len() as u64]);
+        assert_eq!(std::fs::read(dir.path().join("uploads").join(&upload.id)).unwrap(), contents);
+
+        // A client that lost track of an upload picks it up where the
+        // daemon is, even when it resends a chunk the daemon already has
+        let (size, sha256) = hash_file(&path).await.unwrap();
+        let upload = client.create_upload(&UploadRequest { purpose: UploadPurpose::Copy, size, sha256 }).await.unwrap();
+        let first = UploadChunk { offset: 0, data: base64::Engine::encode(&base64::engine::general_purpose::STANDARD, &contents[..10]) };
+        client.upload_chunk(&upload.id, &first).await.unwrap();
+        assert!(matches!(client.upload_chunk(&upload.id, &first).await, Err(ClientError::Conflict(_))));
+        let upload = client.resume_upload(&path, &upload.id, |_, _| {}).await.unwrap();
+        assert_eq!(upload.state, UploadState::Committed);
+
+        client.abort_upload(&upload.id).await.unwrap();
+        assert!(client.upload_status(&upload.id).await.unwrap_err().is_not_found());
+
+        let err = client.upload_file(&dir.path().join("missing"), UploadPurpose::Copy, |_, _| {}).await.unwrap_err();
+        assert!(matches!(err, ClientError::File { .. }));
+        task.abort();
+    }
+
+    #[tokio::test]
+    async fn test_reconnects_when_pooled_connection_closed() {
+        // A daemon that answers one request per connection, then hangs up
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("kawakaze.sock");
+        let listener = tokio::net::UnixListener::bind(&socket).unwrap();
+        let accepted = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let task = {
+            let accepted = accepted.clone();
+            tokio::spawn(async move {
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    accepted.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    let mut framed = Framed::new(stream, LinesCodec::new());
+                    if framed.next().await.is_some() {
+                        let reply = serde_json::to_string(&Response::success(Vec::<JailListItem>::new()).unwrap()).unwrap();
+                        framed.send(reply).await.unwrap();
+                    }
+                }
+            })
+        };
+
+        let client = Client::connect(&socket).await.unwrap();
+        for _ in 0..3 {
+            assert!(client.list_jails().await.unwrap().is_empty());
+        }
+        // The connection from `connect` served the first call; each later
+        // call found its pooled connection closed and opened another
+        assert_eq!(accepted.load(std::sync::atomic::Ordering::SeqCst), 3);
+        task.abort();
+    }
+
+    #[tokio::test]
+    async fn test_connect_without_daemon() {
+        let dir = tempfile::tempdir().unwrap();
+        let err = Client::connect(dir.path().join("missing.sock")).await.err().unwrap();
+        assert!(matches!(err, ClientError::Connect { .. }));
+        assert!(err.to_string().contains("missing.sock"));
+    }
+}
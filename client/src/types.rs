@@ -0,0 +1,173 @@
+//! Request and response types of the kawakaze API
+//!
+//! Everything a [`Client`](crate::Client) sends or returns is re-exported
+//! here, so a dashboard depends on this module rather than on the daemon's
+//! internals. The types are the daemon's own, so both sides serialize them
+//! the same way; the tests below pin the wire format so a daemon update
+//! can't silently break deserialization in older client builds.
+
+pub use kawakaze_backend::api::{
+    AdoptOrphanRequest, AdoptedOrphan, ApiError, BatchCreateRequest, BatchCreated, BootList, BootstrapConfig, BootstrapProgress, BootstrapRequest, BuildImageRequest,
+    BuildStarted, ContainerAddresses, ContainerInfo, ContainerListItem, ContainerListWithSummary, CreateContainerRequest, CreateJailRequest,
+    CreatedContainer, DestroyOrphanRequest, ExecRequest, ExecResult, ExecSessionKilled, ExecSessionRequest, ImageHistoryItem, ImageInfo, ImageListItem, JailInfo,
+    JailListItem, JailUpdateRequest, ListContainersRequest, ListJailsRequest, LogLevelRequest, LogLevelResult, LogsRequest, MessageResult, Mount, NameKind, NamesRequest, OperationStarted, OrphanInfo, PortMapping, PruneImagesRequest,
+    PruneImagesResult, PruneSnapshotsRequest, PruneSnapshotsResult, RemoveRequest, RenameRequest, ContainerIpRequest, SnapshotGcRequest, StopRequest, SystemConfigInfo, SystemInfo,
+    TaskInfo, UpdateRequest, UpgradeRequest, UpgradeStarted, UploadChunk, UploadRequest,
+};
+pub use kawakaze_backend::api_schema::{API_VERSION, ApiDescription, EndpointDescription};
+pub use kawakaze_backend::boot::{BootOutcome, BootReport, BootResult};
+pub use kawakaze_backend::bootstrap::BootstrapStatus;
+pub use kawakaze_backend::build_jail::BuildNetwork;
+pub use kawakaze_backend::cmdtrace::{CommandMetricsInfo, CommandRecord};
+pub use kawakaze_backend::container::{ContainerSummary, StopCause, TmpfsMount};
+pub use kawakaze_backend::doctor::{CheckStatus, DoctorReport};
+pub use kawakaze_backend::error_codes::Family;
+pub use kawakaze_backend::exec_sessions::{ExecKind, ExecSession, Termination};
+pub use kawakaze_backend::image_builder::{BuildStatus, ImageBuildProgress};
+pub use kawakaze_backend::jail::{MountPermissions, StopMode};
+pub use kawakaze_backend::persist_queue::QueueInfo;
+pub use kawakaze_backend::logs::{LogEntry, LogSource};
+pub use kawakaze_backend::operation::{OperationKind, OperationProgress, Phase};
+pub use kawakaze_backend::placement::Placement;
+pub use kawakaze_backend::snapshot_gc::{Candidate, SnapshotGcReport};
+pub use kawakaze_backend::timestamp::Timestamp;
+pub use kawakaze_backend::top::ProcessInfo;
+pub use kawakaze_backend::upgrade::{UpgradeProgress, UpgradeStatus};
+pub use kawakaze_backend::upload::{UploadPurpose, UploadState, UploadStatus};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// A container as the daemon describes it today
+    fn container_info() -> serde_json::Value {
+        json!({
+            "id": "0f8e", "name": "web", "image_id": "9a1c", "jail_name": "kawakaze-0f8e", "state": "running",
+            "ip": "10.11.0.2", "ip4": "10.11.0.2", "ip6": "fd00:6b7a::2", "restart_policy": "always", "created_at": "2023-11-14T22:13:20Z",
+            "started_at": "2023-11-14T22:15:00Z", "protected": true, "boot": true, "encrypted": false, "full_copy": false, "read_only_root": false,
+            "network_aliases": ["www"]
+        })
+    }
+
+    #[test]
+    fn test_container_info_round_trips() {
+        let info: ContainerInfo = serde_json::from_value(container_info()).unwrap();
+        assert_eq!(info.network_aliases, ["www"]);
+        assert_eq!(info.ip.v6.as_deref(), Some("fd00:6b7a::2"));
+        assert_eq!(serde_json::to_value(&info).unwrap(), container_info());
+    }
+
+    #[test]
+    fn test_older_daemons_still_deserialize() {
+        // Fields added over time are optional on the wire
+        let old = json!({
+            "id": "0f8e", "name": null, "image_id": "9a1c", "jail_name": "kawakaze-0f8e", "state": "stopped",
+            "ip": null, "restart_policy": "no", "created_at": 1700000000, "started_at": null
+        });
+        let info: ContainerInfo = serde_json::from_value(old).unwrap();
+        assert!(!info.protected && !info.read_only_root && info.network_aliases.is_empty());
+        assert!(info.ip.is_empty());
+        // Before IPv6, `ip` was the only address
+        let mut v4 = container_info();
+        v4.as_object_mut().unwrap().retain(|key, _| key != "ip4" && key != "ip6");
+        let info: ContainerInfo = serde_json::from_value(v4).unwrap();
+        assert_eq!(info.ip, ContainerAddresses { v4: Some("10.11.0.2".to_string()), v6: None });
+        // Times were Unix seconds before they were RFC3339
+        assert_eq!(info.created_at.to_string(), "2023-11-14T22:13:20Z");
+
+        let image: ImageListItem =
+            serde_json::from_value(json!({ "id": "9a1c", "name": "base", "size_bytes": 10, "created_at": 1 })).unwrap();
+        assert_eq!(image.kind, "user");
+    }
+
+    #[test]
+    fn test_newer_daemons_still_deserialize() {
+        // Fields a client doesn't know yet are ignored
+        let mut newer = container_info();
+        newer["added_later"] = json!({ "nested": true });
+        assert!(serde_json::from_value::<ContainerInfo>(newer).is_ok());
+    }
+
+    #[test]
+    fn test_created_container_flattens_info() {
+        let mut created = container_info();
+        created["ports"] = json!([{ "host_port": 8080, "container_port": 80, "protocol": "tcp" }]);
+        created["command"] = json!(["/usr/local/bin/nginx"]);
+        let created: CreatedContainer = serde_json::from_value(created).unwrap();
+        assert_eq!(created.container.name.as_deref(), Some("web"));
+        assert_eq!(created.ports[0].host_port, 8080);
+        assert!(created.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_small_responses() {
+        let started: BuildStarted = serde_json::from_value(json!({ "id": "b1", "name": "app" })).unwrap();
+        assert!(!started.cached);
+        assert_eq!(serde_json::to_value(&started).unwrap(), json!({ "id": "b1", "name": "app" }));
+        let cached = json!({ "id": "i1", "name": "app", "cached": true });
+        assert!(serde_json::from_value::<BuildStarted>(cached).unwrap().cached);
+
+        let message: MessageResult = serde_json::from_value(json!({ "message": "Container 'web' removed" })).unwrap();
+        assert_eq!(message.message, "Container 'web' removed");
+        let upgrade: UpgradeStarted = serde_json::from_value(json!({ "snapshot": "tank/jails/a@pre-upgrade" })).unwrap();
+        assert_eq!(upgrade.snapshot, "tank/jails/a@pre-upgrade");
+        let adopted: AdoptedOrphan = serde_json::from_value(json!({ "id": "c1", "dataset": "tank/containers/c1" })).unwrap();
+        assert_eq!(adopted.id, "c1");
+        let started: OperationStarted = serde_json::from_value(json!({ "id": "op1", "container_id": "c1" })).unwrap();
+        assert_eq!(started.container_id, "c1");
+
+        let progress = json!({
+            "id": "op1", "kind": "remove", "container_id": "c1", "name": "web", "phase": "destroying", "progress": 70,
+            "current_step": "Destroying datasets (5.0 GB of 10.0 GB left)", "bytes_total": 10737418240u64, "bytes_remaining": 5368709120u64
+        });
+        let progress: OperationProgress = serde_json::from_value(progress).unwrap();
+        assert_eq!((progress.kind, progress.phase), (OperationKind::Remove, Phase::Destroying));
+        assert!(!progress.is_finished() && progress.error.is_none());
+    }
+
+    #[test]
+    fn test_list_with_summary() {
+        let list = json!({
+            "containers": [{ "id": "0f8e", "name": "web", "image_id": "9a1c", "state": "running", "ip": null }],
+            "summary": { "total": 1, "running": 1, "stopped": 0, "created": 0, "paused": 0, "unhealthy": 0 }
+        });
+        let list: ContainerListWithSummary = serde_json::from_value(list).unwrap();
+        assert_eq!(list.containers.len(), 1);
+        assert!(!list.containers[0].protected);
+        assert!(list.containers[0].last_stop_cause.is_none());
+        assert_eq!(list.summary.locked, 0);
+
+        let stopped = json!({
+            "id": "0f8e", "image_id": "9a1c", "state": "stopped", "ip": null,
+            "stopped_at": "2023-11-14T22:20:00Z", "last_stop_cause": { "user_request": 1001 }
+        });
+        let stopped: ContainerListItem = serde_json::from_value(stopped).unwrap();
+        assert_eq!(stopped.last_stop_cause, Some(StopCause::UserRequest(Some(1001))));
+        let cause = json!("daemon_shutdown");
+        assert_eq!(serde_json::from_value::<StopCause>(cause).unwrap(), StopCause::DaemonShutdown);
+    }
+
+    #[test]
+    fn test_request_bodies() {
+        // What a client sends must stay what the daemon parses
+        let request: CreateContainerRequest = serde_json::from_value(json!({ "image_id": "base" })).unwrap();
+        assert!(request.name.is_none() && request.ports.is_empty() && request.network_aliases.is_empty());
+        assert_eq!(serde_json::to_value(StopRequest { stop_mode: StopMode::Freeze }).unwrap(), json!({ "stop_mode": "freeze" }));
+        assert_eq!(
+            serde_json::to_value(LogsRequest { timestamps: true, tail: Some(5), ..Default::default() }).unwrap(),
+            json!({ "timestamps": true, "tail": 5 })
+        );
+        assert_eq!(
+            serde_json::to_value(LogsRequest { source: LogSource::All, ..Default::default() }).unwrap(),
+            json!({ "timestamps": false, "source": "all" })
+        );
+        assert_eq!(
+            serde_json::to_value(UploadRequest { purpose: UploadPurpose::BuildContext, size: 3, sha256: "ab".repeat(32) }).unwrap(),
+            json!({ "purpose": "build-context", "size": 3, "sha256": "ab".repeat(32) })
+        );
+        let upload = json!({ "id": "u1", "purpose": "image-load", "size": 3, "received": 1, "sha256": "ab".repeat(32), "state": "open" });
+        let upload: UploadStatus = serde_json::from_value(upload).unwrap();
+        assert_eq!((upload.purpose, upload.state), (UploadPurpose::ImageLoad, UploadState::Open));
+    }
+}